@@ -0,0 +1,475 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use tokio::task::JoinHandle;
+use tracing::error;
+
+use waddle_core::error::WaddleError;
+use waddle_core::event::{BroadcastEventBus, EventBus, RosterItem, Subscription};
+use waddle_core::jid::Jid;
+use waddle_core::shutdown::{Manager, ShutdownFuture};
+use waddle_mam::MamManager;
+use waddle_messaging::{ConversationManager, MessageManager, MucManager, RuleEngine};
+use waddle_presence::{CapabilitiesManager, PresenceInfo, PresenceManager, ServerHealthMonitor};
+use waddle_roster::{ProfileManager, RosterManager};
+use waddle_storage::{
+    Database, FromRow, MaintenanceReport, NativeDatabase, StorageError, ToSql, Transaction,
+};
+
+use crate::ApiError;
+
+pub type RosterHandle = Arc<RosterManager<Store>>;
+pub type ProfileHandle = Arc<ProfileManager<Store>>;
+pub type MessagesHandle = Arc<MessageManager<Store>>;
+pub type MucHandle = Arc<MucManager<Store>>;
+pub type ConversationsHandle = Arc<ConversationManager<Store>>;
+pub type PresenceHandle = Arc<PresenceManager>;
+pub type CapabilitiesHandle = Arc<CapabilitiesManager>;
+pub type HealthHandle = Arc<ServerHealthMonitor<Store>>;
+pub type MamHandle = Arc<MamManager<Store>>;
+pub type RulesHandle = Arc<RuleEngine<Store>>;
+
+/// The database the facade's managers share. Opaque, so the storage backend
+/// behind the handles can change without breaking frontends.
+#[derive(Debug)]
+pub struct Store(NativeDatabase);
+
+impl Database for Store {
+    async fn execute(&self, sql: &str, params: &[&dyn ToSql]) -> Result<u64, StorageError> {
+        self.0.execute(sql, params).await
+    }
+
+    async fn query<T: FromRow>(
+        &self,
+        sql: &str,
+        params: &[&dyn ToSql],
+    ) -> Result<Vec<T>, StorageError> {
+        self.0.query(sql, params).await
+    }
+
+    async fn query_one<T: FromRow>(
+        &self,
+        sql: &str,
+        params: &[&dyn ToSql],
+    ) -> Result<T, StorageError> {
+        self.0.query_one(sql, params).await
+    }
+
+    async fn transaction<F, R>(&self, f: F) -> Result<R, StorageError>
+    where
+        F: FnOnce(&Transaction) -> Result<R, StorageError> + Send,
+    {
+        self.0.transaction(f).await
+    }
+
+    async fn maintenance(&self) -> Result<MaintenanceReport, StorageError> {
+        self.0.maintenance().await
+    }
+}
+
+/// Checkpoints the write-ahead log on shutdown so the offline queue is in
+/// the database file itself before the process exits.
+impl Manager for Store {
+    fn name(&self) -> &'static str {
+        "storage"
+    }
+
+    fn shutdown(&self) -> ShutdownFuture<'_> {
+        Box::pin(async move {
+            self.0
+                .query::<waddle_storage::Row>("PRAGMA wal_checkpoint(TRUNCATE)", &[])
+                .await
+                .map_err(|e| WaddleError::Storage(e.to_string()))?;
+            Ok(())
+        })
+    }
+}
+
+/// Everything known about one contact, for a profile view. Composed by
+/// [`Waddle::contact_details`] from the roster, presence and rooms.
+#[derive(Debug, Clone)]
+pub struct ContactDetails {
+    pub jid: String,
+    /// The roster entry, or `None` for someone not on the roster.
+    pub item: Option<RosterItem>,
+    /// Our subscription state with the contact; [`Subscription::None`] off
+    /// the roster.
+    pub subscription: Subscription,
+    /// The contact asked to see our presence and awaits an answer.
+    pub subscription_pending: bool,
+    /// Presence of each online resource, by resource name.
+    pub resources: Vec<(String, PresenceInfo)>,
+    /// Hash of the contact's XEP-0084 avatar, as last announced.
+    pub avatar_hash: Option<String>,
+    /// When a message was last exchanged with the contact.
+    pub last_message_at: Option<DateTime<Utc>>,
+    /// Joined rooms where the contact is an occupant under their real JID.
+    pub shared_rooms: Vec<String>,
+}
+
+/// Entry point for frontends: owns the storage, event bus and domain
+/// managers, and hands out cheap cloneable handles to each of them.
+#[derive(Clone)]
+pub struct Waddle {
+    store: Arc<Store>,
+    event_bus: Arc<dyn EventBus>,
+    roster: RosterHandle,
+    profile: ProfileHandle,
+    messages: MessagesHandle,
+    muc: MucHandle,
+    conversations: ConversationsHandle,
+    presence: PresenceHandle,
+    capabilities: CapabilitiesHandle,
+    health: HealthHandle,
+    mam: MamHandle,
+    rules: RulesHandle,
+}
+
+impl Waddle {
+    /// Open the database at `storage_path` and wire up all managers on a new
+    /// event bus with the given per-domain channel capacity. Managers are not
+    /// running until [`Waddle::start`] is called.
+    pub async fn open(storage_path: &Path, channel_capacity: usize) -> Result<Self, ApiError> {
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::new(channel_capacity));
+        Self::open_with_event_bus(storage_path, event_bus).await
+    }
+
+    /// Like [`Waddle::open`], on a bus of the caller's choosing, such as one
+    /// that records the session's events.
+    pub async fn open_with_event_bus(
+        storage_path: &Path,
+        event_bus: Arc<dyn EventBus>,
+    ) -> Result<Self, ApiError> {
+        let database = Arc::new(Store(
+            waddle_storage::open_native_database(storage_path).await?,
+        ));
+
+        let messages = Arc::new(MessageManager::new(database.clone(), event_bus.clone()));
+        let muc = Arc::new(MucManager::new(database.clone(), event_bus.clone()));
+        let presence = Arc::new(PresenceManager::new(event_bus.clone()));
+
+        Ok(Self {
+            roster: Arc::new(RosterManager::new(database.clone(), event_bus.clone())),
+            profile: Arc::new(ProfileManager::new(database.clone(), event_bus.clone())),
+            rules: Arc::new(RuleEngine::new(
+                database.clone(),
+                messages.clone(),
+                event_bus.clone(),
+            )),
+            messages,
+            muc,
+            conversations: Arc::new(ConversationManager::new(
+                database.clone(),
+                event_bus.clone(),
+            )),
+            presence,
+            capabilities: Arc::new(CapabilitiesManager::new(event_bus.clone())),
+            health: Arc::new(ServerHealthMonitor::new(
+                database.clone(),
+                event_bus.clone(),
+            )),
+            mam: Arc::new(MamManager::new(database.clone(), event_bus.clone())),
+            store: database,
+            event_bus,
+        })
+    }
+
+    /// Spawn the event loops of every manager on the current Tokio runtime.
+    /// A loop that terminates with an error is logged and not restarted.
+    pub fn start(&self) -> Vec<JoinHandle<()>> {
+        vec![
+            spawn_loop("roster", {
+                let manager = self.roster.clone();
+                async move { manager.run().await.map_err(|e| e.to_string()) }
+            }),
+            spawn_loop("profile", {
+                let manager = self.profile.clone();
+                async move { manager.run().await.map_err(|e| e.to_string()) }
+            }),
+            spawn_loop("messaging", {
+                let manager = self.messages.clone();
+                async move { manager.run().await.map_err(|e| e.to_string()) }
+            }),
+            spawn_loop("muc", {
+                let manager = self.muc.clone();
+                async move { manager.run().await.map_err(|e| e.to_string()) }
+            }),
+            spawn_loop("conversations", {
+                let manager = self.conversations.clone();
+                async move { manager.run().await.map_err(|e| e.to_string()) }
+            }),
+            spawn_loop("presence", {
+                let manager = self.presence.clone();
+                async move { manager.run().await.map_err(|e| e.to_string()) }
+            }),
+            spawn_loop("capabilities", {
+                let manager = self.capabilities.clone();
+                async move { manager.run().await.map_err(|e| e.to_string()) }
+            }),
+            spawn_loop("health", {
+                let manager = self.health.clone();
+                async move { manager.run().await.map_err(|e| e.to_string()) }
+            }),
+            spawn_loop("mam", {
+                let manager = self.mam.clone();
+                async move { manager.run().await.map_err(|e| e.to_string()) }
+            }),
+            spawn_loop("rules", {
+                let engine = self.rules.clone();
+                async move { engine.run().await.map_err(|e| e.to_string()) }
+            }),
+        ]
+    }
+
+    /// The components to register with a
+    /// [`ShutdownCoordinator`](waddle_core::shutdown::ShutdownCoordinator),
+    /// so presence, archive syncs and storage settle before the process
+    /// exits.
+    pub fn shutdown_managers(&self) -> Vec<Arc<dyn Manager>> {
+        vec![self.presence.clone(), self.mam.clone(), self.store.clone()]
+    }
+
+    pub fn event_bus(&self) -> Arc<dyn EventBus> {
+        self.event_bus.clone()
+    }
+
+    pub fn roster(&self) -> RosterHandle {
+        self.roster.clone()
+    }
+
+    pub fn profile(&self) -> ProfileHandle {
+        self.profile.clone()
+    }
+
+    pub fn messages(&self) -> MessagesHandle {
+        self.messages.clone()
+    }
+
+    pub fn muc(&self) -> MucHandle {
+        self.muc.clone()
+    }
+
+    pub fn conversations(&self) -> ConversationsHandle {
+        self.conversations.clone()
+    }
+
+    pub fn presence(&self) -> PresenceHandle {
+        self.presence.clone()
+    }
+
+    pub fn capabilities(&self) -> CapabilitiesHandle {
+        self.capabilities.clone()
+    }
+
+    pub fn health(&self) -> HealthHandle {
+        self.health.clone()
+    }
+
+    pub fn mam(&self) -> MamHandle {
+        self.mam.clone()
+    }
+
+    pub fn rules(&self) -> RulesHandle {
+        self.rules.clone()
+    }
+
+    /// Compose what is known about `jid` for a profile view: the roster's
+    /// [summary](RosterManager::get_contact_summary), presence per resource
+    /// and the joined rooms the contact is in.
+    pub async fn contact_details(&self, jid: &str) -> Result<ContactDetails, ApiError> {
+        let summary = self.roster.get_contact_summary(jid).await?;
+        let key = Jid::new(&summary.jid);
+
+        let mut shared_rooms = Vec::new();
+        for room in self.muc.get_joined_rooms().await? {
+            let present = self
+                .muc
+                .get_occupants(&room.room_jid)
+                .iter()
+                .any(|occupant| {
+                    occupant
+                        .jid
+                        .as_deref()
+                        .is_some_and(|real| Jid::new(real).bare() == key)
+                });
+            if present {
+                shared_rooms.push(room.room_jid);
+            }
+        }
+
+        Ok(ContactDetails {
+            resources: self.presence.get_resources(&summary.jid),
+            jid: summary.jid,
+            item: summary.item,
+            subscription: summary.subscription,
+            subscription_pending: summary.subscription_pending,
+            avatar_hash: summary.avatar_hash,
+            last_message_at: summary.last_message_at,
+            shared_rooms,
+        })
+    }
+}
+
+fn spawn_loop<F>(component: &'static str, task: F) -> JoinHandle<()>
+where
+    F: Future<Output = Result<(), String>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        if let Err(reason) = task.await {
+            error!(component, %reason, "component task terminated");
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    use crate::events::{
+        Channel, Event, EventPayload, EventSource, MucAffiliation, MucOccupant, MucRole,
+        PresenceShow,
+    };
+
+    #[tokio::test]
+    async fn open_wires_managers_to_shared_bus() {
+        let dir = TempDir::new().unwrap();
+        let waddle = Waddle::open(&dir.path().join("waddle.db"), 64)
+            .await
+            .expect("facade should open");
+
+        assert!(waddle.roster().get_roster().await.unwrap().is_empty());
+        assert!(
+            waddle
+                .conversations()
+                .list_conversations()
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            !waddle
+                .capabilities()
+                .capabilities("alice@example.com")
+                .receipts
+        );
+        assert!(matches!(
+            waddle.presence().own_presence().show,
+            PresenceShow::Unavailable
+        ));
+
+        let mut sub = waddle.event_bus().subscribe("ui.presence.set").unwrap();
+        waddle
+            .presence()
+            .set_own_presence(PresenceShow::Away, None, None)
+            .unwrap();
+        let event = tokio::time::timeout(Duration::from_millis(100), sub.recv())
+            .await
+            .expect("timed out")
+            .unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::PresenceSetRequested { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn contact_details_add_presence_and_shared_rooms_to_the_roster_summary() {
+        let dir = TempDir::new().unwrap();
+        let waddle = Waddle::open(&dir.path().join("waddle.db"), 64)
+            .await
+            .unwrap();
+        let event = |channel: &str, payload: EventPayload| {
+            Event::new(Channel::new(channel).unwrap(), EventSource::Xmpp, payload)
+        };
+
+        waddle
+            .roster()
+            .handle_event(&event(
+                "xmpp.roster.received",
+                EventPayload::RosterReceived {
+                    items: vec![RosterItem {
+                        jid: "bob@example.com".to_string(),
+                        name: Some("Bob".to_string()),
+                        subscription: Subscription::Both,
+                        groups: Vec::new(),
+                    }],
+                },
+            ))
+            .await;
+        waddle
+            .presence()
+            .handle_event(&event(
+                "xmpp.presence.changed",
+                EventPayload::PresenceChanged {
+                    jid: "bob@example.com/phone".to_string(),
+                    show: PresenceShow::Away,
+                    status: None,
+                    priority: 0,
+                    idle_since: None,
+                },
+            ))
+            .await;
+        for room in [
+            "lounge@conference.example.com",
+            "work@conference.example.com",
+        ] {
+            waddle
+                .store
+                .execute(
+                    "INSERT INTO muc_rooms (room_jid, nick, joined, subject) \
+                     VALUES (?1, 'alice', 1, NULL)",
+                    &[&room.to_string()],
+                )
+                .await
+                .unwrap();
+        }
+        waddle
+            .muc()
+            .handle_event(&event(
+                "xmpp.muc.occupant.changed",
+                EventPayload::MucOccupantChanged {
+                    room: "lounge@conference.example.com".to_string(),
+                    occupant: MucOccupant {
+                        nick: "bobby".to_string(),
+                        jid: Some("bob@example.com/laptop".to_string()),
+                        affiliation: MucAffiliation::Member,
+                        role: MucRole::Participant,
+                    },
+                },
+            ))
+            .await;
+
+        let details = waddle.contact_details("Bob@Example.com").await.unwrap();
+        assert_eq!(details.jid, "bob@example.com");
+        assert_eq!(details.subscription, Subscription::Both);
+        assert_eq!(details.resources.len(), 1);
+        assert_eq!(details.resources[0].0, "phone");
+        assert!(matches!(details.resources[0].1.show, PresenceShow::Away));
+        assert_eq!(details.shared_rooms, vec!["lounge@conference.example.com"]);
+
+        let details = waddle.contact_details("carol@example.com").await.unwrap();
+        assert!(details.item.is_none());
+        assert!(details.resources.is_empty());
+        assert!(details.shared_rooms.is_empty());
+    }
+
+    #[tokio::test]
+    async fn start_spawns_a_loop_per_manager() {
+        let dir = TempDir::new().unwrap();
+        let waddle = Waddle::open(&dir.path().join("waddle.db"), 64)
+            .await
+            .unwrap();
+        let handles = waddle.start();
+        assert_eq!(handles.len(), 10);
+
+        tokio::task::yield_now().await;
+        assert!(handles.iter().all(|handle| !handle.is_finished()));
+
+        for handle in handles {
+            handle.abort();
+        }
+    }
+}
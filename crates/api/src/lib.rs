@@ -0,0 +1,268 @@
+//! Stable public API for Waddle frontends.
+//!
+//! External frontends (TUI, GUI, bots, third-party clients) should depend on
+//! this crate rather than on the individual domain crates. Only the types
+//! re-exported here are covered by semver guarantees; storage schema, stanza
+//! pipeline and event bus internals may change between minor releases.
+//!
+//! The surface, down to every signature, field and variant, is recorded in
+//! `public-api.txt` by cargo-public-api, and a test diffs it against this
+//! crate on every run; it needs a nightly toolchain for rustdoc JSON. When it
+//! changes, review the diff, bump [`API_VERSION`] if it breaks frontends, and
+//! regenerate the snapshot with
+//! `WADDLE_UPDATE_API_SNAPSHOT=1 cargo test -p waddle-api`. The growing event
+//! enums are `#[non_exhaustive]`, so new variants are not breaking. The
+//! snapshot also lists the definitions of the items re-exported from the
+//! domain crates and of the managers behind the facade handles. Those
+//! managers run on an opaque [`Store`], so the storage backend stays out of
+//! the surface.
+
+/// Version of the stable API surface. Incremented on breaking changes only.
+pub const API_VERSION: u32 = 1;
+
+/// Event envelope, payloads and the bus abstraction.
+pub mod events {
+    pub use waddle_core::event::{
+        ArchiveGap, Channel, ChatMessage, ChatState, DiscoItem, Event, EventPayload, EventSource,
+        MessageEmbed, MessageTranslation, MessageType, MucAffiliation, MucInvite, MucOccupant,
+        MucRole, OmemoBundle, OmemoEnvelope, OmemoKeyElement, OmemoPreKey, OmemoTrust,
+        PresenceShow, RosterItem, Rule, RuleAction, RuleTrigger, ScrollDirection, ServerFeature,
+        Subscription, SyncTask, UiTarget,
+    };
+
+    #[cfg(feature = "native")]
+    pub use waddle_core::event::{EventBus, EventSubscription};
+
+    // Reaching a bus in another process, as the web build does.
+    #[cfg(any(feature = "native", feature = "web"))]
+    pub use waddle_core::bridge::{
+        BridgeConnector, BridgeSocket, EventBusProxy, ProxySubscription,
+    };
+
+    #[cfg(all(feature = "web", target_arch = "wasm32"))]
+    pub use waddle_core::bridge::BrowserConnector;
+}
+
+/// XEP-0004 data forms carried by `FormRequested` / `FormSubmitted`.
+pub mod forms {
+    pub use waddle_core::form::{
+        DataForm, FORM_TYPE_VAR, FieldOption, FieldType, FormError, FormField, FormMedia, FormType,
+        MediaUri,
+    };
+}
+
+/// XEP-0059 result set paging carried by paged queries such as
+/// `MamQueryRequested` and `DiscoItemsRequested`.
+pub mod paging {
+    pub use waddle_core::rsm::{RsmCursor, RsmQuery, RsmResult};
+}
+
+/// Error types returned by the facade and its handles.
+pub mod errors {
+    pub use waddle_core::bridge::BridgeError;
+    pub use waddle_core::config::ConfigError;
+    pub use waddle_core::error::{EventBusError, WaddleError};
+    pub use waddle_mam::MamError;
+    pub use waddle_messaging::MessagingError;
+    pub use waddle_notifications::NotificationError;
+    pub use waddle_omemo::OmemoError;
+    pub use waddle_presence::PresenceError;
+    pub use waddle_roster::RosterError;
+    pub use waddle_storage::StorageError;
+
+    pub use crate::ApiError;
+}
+
+pub use waddle_core::config::Config;
+pub use waddle_mam::{MamRetryPolicy, MamSyncResult};
+pub use waddle_messaging::{
+    Conversation, ConversationKind, MergeReport, MergedConversation, MucRoom, MucSubjectChange,
+};
+pub use waddle_omemo::OmemoDevice;
+pub use waddle_presence::{ContactCapabilities, PresenceInfo};
+#[cfg(feature = "native")]
+pub use waddle_presence::{FeatureStatus, HealthReport};
+pub use waddle_roster::{ContactSummary, PendingSubscription, Profile};
+
+#[cfg(feature = "native")]
+mod facade;
+
+#[cfg(feature = "native")]
+pub use facade::{
+    CapabilitiesHandle, ContactDetails, ConversationsHandle, HealthHandle, MamHandle,
+    MessagesHandle, MucHandle, PresenceHandle, ProfileHandle, RosterHandle, RulesHandle, Store,
+    Waddle,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("storage error: {0}")]
+    Storage(#[from] waddle_storage::StorageError),
+
+    #[error("event bus error: {0}")]
+    EventBus(#[from] waddle_core::error::EventBusError),
+
+    #[error("roster error: {0}")]
+    Roster(#[from] waddle_roster::RosterError),
+
+    #[error("messaging error: {0}")]
+    Messaging(#[from] waddle_messaging::MessagingError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_exported<T>() {}
+
+    /// Names the core items of the stable surface so that removing or
+    /// renaming one fails here before it reaches downstream frontends.
+    #[test]
+    fn stable_surface_is_exported() {
+        assert_exported::<events::Event>();
+        assert_exported::<events::EventPayload>();
+        assert_exported::<events::Channel>();
+        assert_exported::<events::EventSource>();
+        assert_exported::<events::ChatMessage>();
+        assert_exported::<events::RosterItem>();
+        assert_exported::<events::PresenceShow>();
+        assert_exported::<events::Rule>();
+        assert_exported::<forms::DataForm>();
+        assert_exported::<errors::WaddleError>();
+        assert_exported::<errors::RosterError>();
+        assert_exported::<errors::MessagingError>();
+        assert_exported::<errors::PresenceError>();
+        assert_exported::<errors::MamError>();
+        assert_exported::<errors::OmemoError>();
+        assert_exported::<errors::ApiError>();
+        assert_exported::<PresenceInfo>();
+        assert_exported::<ContactCapabilities>();
+        assert_exported::<MucRoom>();
+        assert_exported::<Conversation>();
+        assert_exported::<MamSyncResult>();
+        assert_exported::<OmemoDevice>();
+        assert_exported::<events::OmemoTrust>();
+        assert_exported::<Config>();
+        assert_exported::<events::ProxySubscription>();
+        assert_exported::<errors::BridgeError>();
+        assert_eq!(API_VERSION, 1);
+    }
+
+    const SNAPSHOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/public-api.txt");
+
+    /// Public API of the package at `manifest`, as cargo-public-api lists
+    /// it, together with the rustdoc JSON it was read from.
+    fn public_items(manifest: &str) -> (Vec<String>, std::path::PathBuf) {
+        let rustdoc_json = rustdoc_json::Builder::default()
+            .toolchain("nightly")
+            .manifest_path(manifest)
+            .build()
+            .expect("failed to build rustdoc JSON; is a nightly toolchain installed?");
+        let items = public_api::Builder::from_rustdoc_json(&rustdoc_json)
+            .omit_blanket_impls(true)
+            .build()
+            .expect("failed to read the public API from rustdoc JSON")
+            .items()
+            .map(ToString::to_string)
+            .collect();
+        (items, rustdoc_json)
+    }
+
+    /// Path of the item a cargo-public-api line describes: the implementing
+    /// type for impl blocks, otherwise the first crate path on the line.
+    fn item_path(line: &str) -> &str {
+        let rest = match line.strip_prefix("impl") {
+            Some(rest) => rest.split_once(" for ").map_or(rest, |(_, ty)| ty),
+            None => line,
+        };
+        let start = rest.find("waddle_").unwrap_or(rest.len());
+        let rest = &rest[start..];
+        let end = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+            .unwrap_or(rest.len());
+        &rest[..end]
+    }
+
+    /// The facade's own surface followed by the full definition of every
+    /// item it re-exports or hands out from the domain crates, so a new
+    /// field, variant or method on one shows up in the snapshot too. rustdoc
+    /// JSON keeps cross-crate re-exports as bare `pub use` lines.
+    fn render_surface() -> String {
+        let (mut surface, rustdoc_json) =
+            public_items(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"));
+        let json: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(rustdoc_json).expect("failed to read rustdoc JSON"),
+        )
+        .expect("rustdoc JSON is not valid JSON");
+        let mut sources: Vec<String> = json["index"]
+            .as_object()
+            .expect("rustdoc JSON has no index")
+            .values()
+            .filter_map(|item| item["inner"]["use"]["source"].as_str())
+            .filter(|source| source.starts_with("waddle_"))
+            .map(str::to_owned)
+            .collect();
+        // The facade handles alias the domain managers, so their methods
+        // belong to the surface as well.
+        for line in surface
+            .iter()
+            .filter(|line| line.starts_with("pub type waddle_api::"))
+        {
+            let target = line.split_once(" = ").map_or("", |(_, target)| target);
+            sources.extend(
+                target
+                    .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+                    .filter(|path| path.starts_with("waddle_") && !path.starts_with("waddle_api"))
+                    .map(str::to_owned),
+            );
+        }
+        sources.sort();
+        sources.dedup();
+
+        let mut crates: Vec<&str> = sources
+            .iter()
+            .map(|source| source.split("::").next().unwrap_or_default())
+            .collect();
+        crates.dedup();
+        for krate in crates {
+            let manifest = format!(
+                "{}/../{}/Cargo.toml",
+                env!("CARGO_MANIFEST_DIR"),
+                krate.trim_start_matches("waddle_")
+            );
+            let (items, _) = public_items(&manifest);
+            surface.extend(items.into_iter().filter(|line| {
+                // Impls for one storage backend do not apply to the handles,
+                // which run on `Store`.
+                if line.contains("<waddle_storage::") {
+                    return false;
+                }
+                let path = item_path(line);
+                sources.iter().any(|source| {
+                    path.strip_prefix(source.as_str())
+                        .is_some_and(|tail| tail.is_empty() || tail.starts_with("::"))
+                })
+            }));
+        }
+        surface.iter().map(|line| format!("{line}\n")).collect()
+    }
+
+    /// Fails when the stable surface drifts from `public-api.txt`, so every
+    /// change to it shows up in review alongside the snapshot diff.
+    #[test]
+    fn public_api_matches_snapshot() {
+        let rendered = render_surface();
+        if std::env::var_os("WADDLE_UPDATE_API_SNAPSHOT").is_some() {
+            std::fs::write(SNAPSHOT, &rendered).expect("failed to write API snapshot");
+            return;
+        }
+        let recorded = std::fs::read_to_string(SNAPSHOT).unwrap_or_default();
+        assert!(
+            recorded == rendered,
+            "the public API no longer matches public-api.txt. Review the change, \
+             bump API_VERSION if it breaks frontends, and regenerate the snapshot \
+             with WADDLE_UPDATE_API_SNAPSHOT=1 cargo test -p waddle-api.\n\n\
+             --- recorded\n{recorded}\n+++ current\n{rendered}"
+        );
+    }
+}
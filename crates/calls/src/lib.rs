@@ -0,0 +1,31 @@
+//! One-to-one voice calls signaled over Jingle (XEP-0166/0167).
+//!
+//! [`CallManager`] runs the call state machine and the Jingle exchange with
+//! the peer; media is left to an external engine, typically WebRTC in the
+//! frontend. The engine hands its local description and ICE candidates to
+//! the manager and applies what arrives as `system.call.media` events.
+
+#[cfg(feature = "native")]
+mod manager;
+
+#[cfg(feature = "native")]
+pub use manager::{CallManager, RING_TIMEOUT};
+
+#[derive(Debug, thiserror::Error)]
+pub enum CallError {
+    #[error("calls need a full JID with a resource: {0}")]
+    InvalidPeer(String),
+
+    #[error("no call with session ID {0}")]
+    UnknownCall(String),
+
+    #[error("call {sid} cannot {action} while {state}")]
+    InvalidState {
+        sid: String,
+        action: &'static str,
+        state: &'static str,
+    },
+
+    #[error("event bus error: {0}")]
+    EventBus(String),
+}
@@ -0,0 +1,686 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use waddle_core::event::{
+    Call, CallContent, CallDirection, CallEndReason, CallMediaKind, CallState, Channel, Event,
+    EventBus, EventPayload, EventSource, JingleAction, channels,
+};
+use waddle_core::jid::{Jid, JidParts};
+use waddle_xmpp::JINGLE_IQ_ID_PREFIX;
+
+use crate::CallError;
+
+/// How long a call may ring before it ends with a `timeout` reason.
+pub const RING_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often ringing calls are checked against [`RING_TIMEOUT`].
+const RING_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Tracks calls in progress and speaks Jingle to their peers. Calls leave
+/// the manager once they end or fail, after their final state is
+/// published.
+pub struct CallManager {
+    calls: RwLock<HashMap<String, Call>>,
+    /// Ids of sent session-initiate and session-accept requests, mapped to
+    /// the session they belong to. The call fails if the peer refuses one.
+    pending: Mutex<HashMap<String, String>>,
+    /// When each call that started ringing stops ringing unanswered.
+    ring_deadlines: Mutex<HashMap<String, Instant>>,
+    event_bus: Arc<dyn EventBus>,
+}
+
+impl CallManager {
+    pub fn new(event_bus: Arc<dyn EventBus>) -> Self {
+        Self {
+            calls: RwLock::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+            ring_deadlines: Mutex::new(HashMap::new()),
+            event_bus,
+        }
+    }
+
+    /// Calls that are ringing or active.
+    pub fn calls(&self) -> Vec<Call> {
+        let mut calls: Vec<Call> = self.calls.read().unwrap().values().cloned().collect();
+        calls.sort_by_key(|call| call.started_at);
+        calls
+    }
+
+    /// Offer a call to `peer`, a full JID, with the media engine's local
+    /// description. The call rings until the peer accepts or declines it,
+    /// or for at most [`RING_TIMEOUT`].
+    pub fn start_call(&self, peer: &str, contents: Vec<CallContent>) -> Result<Call, CallError> {
+        let peer = JidParts::parse(peer)
+            .ok()
+            .filter(|parts| !parts.is_bare())
+            .ok_or_else(|| CallError::InvalidPeer(peer.to_string()))?
+            .to_string();
+        let call = Call {
+            sid: Uuid::new_v4().to_string(),
+            peer,
+            direction: CallDirection::Outgoing,
+            state: CallState::Ringing,
+            reason: None,
+            started_at: Utc::now(),
+            answered_at: None,
+        };
+        info!(sid = %call.sid, peer = %call.peer, "starting call");
+        self.calls
+            .write()
+            .unwrap()
+            .insert(call.sid.clone(), call.clone());
+        let iq_id = self.send(
+            &call.peer,
+            &call.sid,
+            JingleAction::SessionInitiate { contents },
+        );
+        self.pending.lock().unwrap().insert(iq_id, call.sid.clone());
+        self.start_ringing(&call.sid);
+        self.emit_state(&call);
+        Ok(call)
+    }
+
+    /// Answer a ringing incoming call with the media engine's local
+    /// description.
+    pub fn accept_call(&self, sid: &str, contents: Vec<CallContent>) -> Result<(), CallError> {
+        let call = {
+            let mut calls = self.calls.write().unwrap();
+            let call = calls
+                .get_mut(sid)
+                .ok_or_else(|| CallError::UnknownCall(sid.to_string()))?;
+            if call.direction != CallDirection::Incoming || call.state != CallState::Ringing {
+                return Err(invalid_state(call, "be accepted"));
+            }
+            call.state = CallState::Active;
+            call.answered_at = Some(Utc::now());
+            call.clone()
+        };
+        let iq_id = self.send(&call.peer, sid, JingleAction::SessionAccept { contents });
+        self.pending.lock().unwrap().insert(iq_id, sid.to_string());
+        self.emit_state(&call);
+        Ok(())
+    }
+
+    /// Trickle local ICE candidates to the peer.
+    pub fn send_candidates(&self, sid: &str, contents: Vec<CallContent>) -> Result<(), CallError> {
+        let peer = self.call(sid)?.peer;
+        self.send(&peer, sid, JingleAction::TransportInfo { contents });
+        Ok(())
+    }
+
+    /// End a call: declines a ringing incoming call, cancels a ringing
+    /// outgoing one, and hangs up an active one.
+    pub fn hang_up(&self, sid: &str) -> Result<(), CallError> {
+        let call = self.call(sid)?;
+        let reason = match (call.state, call.direction) {
+            (CallState::Ringing, CallDirection::Incoming) => CallEndReason::Decline,
+            (CallState::Ringing, CallDirection::Outgoing) => CallEndReason::Cancel,
+            _ => CallEndReason::Success,
+        };
+        self.terminate(sid, reason)
+    }
+
+    /// End a call because the media engine could not set it up or lost it,
+    /// telling the peer why.
+    pub fn fail_call(&self, sid: &str, reason: CallEndReason) -> Result<(), CallError> {
+        self.call(sid)?;
+        self.terminate(sid, reason)
+    }
+
+    pub async fn handle_event(&self, event: &Event) {
+        match &event.payload {
+            EventPayload::JingleReceived {
+                from,
+                iq_id,
+                sid,
+                action,
+            } => self.handle_request(from, iq_id, sid, action),
+            EventPayload::JingleAnswered {
+                iq_id,
+                error: Some(error),
+            } => {
+                let Some(sid) = self.pending.lock().unwrap().remove(iq_id) else {
+                    return;
+                };
+                warn!(sid = %sid, error = %error, "peer refused call request");
+                self.finish(&sid, CallEndReason::GeneralError);
+            }
+            EventPayload::JingleAnswered { iq_id, error: None } => {
+                self.pending.lock().unwrap().remove(iq_id);
+            }
+            // Signaling needs the connection; calls do not survive it.
+            EventPayload::ConnectionLost { .. } | EventPayload::GoingOffline => {
+                let sids: Vec<String> = self.calls.read().unwrap().keys().cloned().collect();
+                for sid in sids {
+                    self.finish(&sid, CallEndReason::ConnectivityError);
+                }
+                self.pending.lock().unwrap().clear();
+            }
+            _ => {}
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) -> Result<(), CallError> {
+        let mut sub = self
+            .event_bus
+            .subscribe("{system,xmpp}.**")
+            .map_err(|e| CallError::EventBus(e.to_string()))?;
+        let mut ticker = tokio::time::interval(RING_CHECK_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => self.end_unanswered_calls(),
+                received = sub.recv() => match received {
+                    Ok(event) => {
+                        self.handle_event(&event).await;
+                    }
+                    Err(waddle_core::error::EventBusError::ChannelClosed) => {
+                        debug!("event bus closed, call manager stopping");
+                        return Ok(());
+                    }
+                    Err(waddle_core::error::EventBusError::Lagged(count)) => {
+                        warn!(count, "call manager lagged, some events dropped");
+                    }
+                    Err(e) => {
+                        error!(error = %e, "call manager subscription error");
+                        return Err(CallError::EventBus(e.to_string()));
+                    }
+                },
+            }
+        }
+    }
+
+    fn start_ringing(&self, sid: &str) {
+        self.ring_deadlines
+            .lock()
+            .unwrap()
+            .insert(sid.to_string(), Instant::now() + RING_TIMEOUT);
+    }
+
+    /// End the calls that rang for [`RING_TIMEOUT`] without being answered,
+    /// telling their peers the call timed out.
+    fn end_unanswered_calls(&self) {
+        let now = Instant::now();
+        let expired: Vec<String> = self
+            .ring_deadlines
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(sid, _)| sid.clone())
+            .collect();
+        for sid in expired {
+            let ringing = self
+                .calls
+                .read()
+                .unwrap()
+                .get(&sid)
+                .is_some_and(|call| call.state == CallState::Ringing);
+            if ringing {
+                info!(sid = %sid, "call was not answered in time");
+                let _ = self.terminate(&sid, CallEndReason::Timeout);
+            } else {
+                self.ring_deadlines.lock().unwrap().remove(&sid);
+            }
+        }
+    }
+
+    fn handle_request(&self, from: &str, iq_id: &str, sid: &str, action: &JingleAction) {
+        if let JingleAction::SessionInitiate { contents } = action {
+            self.answer(from, iq_id, None);
+            self.handle_offer(from, sid, contents);
+            return;
+        }
+
+        let call = self.calls.read().unwrap().get(sid).cloned();
+        let Some(call) = call.filter(|call| Jid::new(&call.peer) == Jid::new(from)) else {
+            debug!(from, sid, "jingle request for an unknown session");
+            self.answer(from, iq_id, Some("item-not-found"));
+            return;
+        };
+
+        match action {
+            JingleAction::SessionAccept { contents } => {
+                if call.direction != CallDirection::Outgoing || call.state != CallState::Ringing {
+                    self.answer(from, iq_id, Some("unexpected-request"));
+                    return;
+                }
+                self.answer(from, iq_id, None);
+                let call = self.update(sid, |call| {
+                    call.state = CallState::Active;
+                    call.answered_at = Some(Utc::now());
+                });
+                self.emit_media(sid, CallMediaKind::Answer, contents.clone());
+                if let Some(call) = call {
+                    info!(sid, peer = %call.peer, "call answered");
+                    self.emit_state(&call);
+                }
+            }
+            JingleAction::TransportInfo { contents } => {
+                self.answer(from, iq_id, None);
+                self.emit_media(sid, CallMediaKind::Candidates, contents.clone());
+            }
+            JingleAction::Ringing => self.answer(from, iq_id, None),
+            JingleAction::SessionTerminate { reason } => {
+                self.answer(from, iq_id, None);
+                self.finish(sid, *reason);
+            }
+            JingleAction::Unsupported { name } => {
+                debug!(sid, action = %name, "refusing unsupported jingle action");
+                self.answer(from, iq_id, Some("feature-not-implemented"));
+            }
+            JingleAction::SessionInitiate { .. } => unreachable!("handled above"),
+        }
+    }
+
+    /// A peer calls us. Only one call is taken at a time; a second caller
+    /// is told we are busy.
+    fn handle_offer(&self, from: &str, sid: &str, contents: &[CallContent]) {
+        let busy = !self.calls.read().unwrap().is_empty();
+        if busy {
+            info!(from, sid, "declining call while in another");
+            self.send(
+                from,
+                sid,
+                JingleAction::SessionTerminate {
+                    reason: CallEndReason::Busy,
+                },
+            );
+            return;
+        }
+
+        let call = Call {
+            sid: sid.to_string(),
+            peer: from.to_string(),
+            direction: CallDirection::Incoming,
+            state: CallState::Ringing,
+            reason: None,
+            started_at: Utc::now(),
+            answered_at: None,
+        };
+        info!(sid, peer = from, "incoming call");
+        self.calls
+            .write()
+            .unwrap()
+            .insert(sid.to_string(), call.clone());
+        self.start_ringing(sid);
+        self.emit_state(&call);
+        self.emit_media(sid, CallMediaKind::Offer, contents.to_vec());
+        self.send(from, sid, JingleAction::Ringing);
+    }
+
+    fn terminate(&self, sid: &str, reason: CallEndReason) -> Result<(), CallError> {
+        let call = self.call(sid)?;
+        self.send(&call.peer, sid, JingleAction::SessionTerminate { reason });
+        self.finish(sid, reason);
+        Ok(())
+    }
+
+    /// Publish the final state of a call and forget it.
+    fn finish(&self, sid: &str, reason: CallEndReason) {
+        let Some(mut call) = self.calls.write().unwrap().remove(sid) else {
+            return;
+        };
+        self.pending
+            .lock()
+            .unwrap()
+            .retain(|_, pending_sid| pending_sid != sid);
+        self.ring_deadlines.lock().unwrap().remove(sid);
+        call.state = if reason.is_failure() {
+            CallState::Failed
+        } else {
+            CallState::Ended
+        };
+        call.reason = Some(reason);
+        info!(sid, reason = reason.as_str(), "call ended");
+        self.emit_state(&call);
+    }
+
+    fn call(&self, sid: &str) -> Result<Call, CallError> {
+        self.calls
+            .read()
+            .unwrap()
+            .get(sid)
+            .cloned()
+            .ok_or_else(|| CallError::UnknownCall(sid.to_string()))
+    }
+
+    fn update(&self, sid: &str, change: impl FnOnce(&mut Call)) -> Option<Call> {
+        let mut calls = self.calls.write().unwrap();
+        let call = calls.get_mut(sid)?;
+        change(call);
+        Some(call.clone())
+    }
+
+    /// Send `action` to `to`, returning the id of the request.
+    fn send(&self, to: &str, sid: &str, action: JingleAction) -> String {
+        let iq_id = format!("{JINGLE_IQ_ID_PREFIX}{}", Uuid::new_v4());
+        self.emit(
+            channels::UI_JINGLE_SEND,
+            EventPayload::JingleSendRequested {
+                to: to.to_string(),
+                iq_id: iq_id.clone(),
+                sid: sid.to_string(),
+                action,
+            },
+        );
+        iq_id
+    }
+
+    fn answer(&self, to: &str, iq_id: &str, error: Option<&str>) {
+        self.emit(
+            channels::UI_JINGLE_ANSWER,
+            EventPayload::JingleAnswerRequested {
+                to: to.to_string(),
+                iq_id: iq_id.to_string(),
+                error: error.map(String::from),
+            },
+        );
+    }
+
+    fn emit_state(&self, call: &Call) {
+        self.emit(
+            channels::SYSTEM_CALL_STATE_CHANGED,
+            EventPayload::CallStateChanged { call: call.clone() },
+        );
+    }
+
+    fn emit_media(&self, sid: &str, kind: CallMediaKind, contents: Vec<CallContent>) {
+        self.emit(
+            channels::SYSTEM_CALL_MEDIA,
+            EventPayload::CallMediaReceived {
+                sid: sid.to_string(),
+                kind,
+                contents,
+            },
+        );
+    }
+
+    fn emit(&self, channel: &str, payload: EventPayload) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channel).unwrap(),
+            EventSource::System("calls".into()),
+            payload,
+        ));
+    }
+}
+
+fn invalid_state(call: &Call, action: &'static str) -> CallError {
+    CallError::InvalidState {
+        sid: call.sid.clone(),
+        action,
+        state: match call.state {
+            CallState::Ringing => "ringing",
+            CallState::Active => "active",
+            CallState::Ended => "ended",
+            CallState::Failed => "failed",
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use waddle_core::event::{BroadcastEventBus, EventSubscription, IceTransport};
+
+    const PEER: &str = "romeo@montague.lit/orchard";
+
+    fn make_manager() -> (CallManager, Arc<dyn EventBus>) {
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        (CallManager::new(event_bus.clone()), event_bus)
+    }
+
+    fn content() -> Vec<CallContent> {
+        vec![CallContent {
+            name: "0".to_string(),
+            description: None,
+            transport: IceTransport::default(),
+        }]
+    }
+
+    fn request(sid: &str, action: JingleAction) -> Event {
+        Event::new(
+            Channel::new("xmpp.jingle.received").unwrap(),
+            EventSource::Xmpp,
+            EventPayload::JingleReceived {
+                from: PEER.to_string(),
+                iq_id: "peer-1".to_string(),
+                sid: sid.to_string(),
+                action,
+            },
+        )
+    }
+
+    async fn next_state(states: &mut EventSubscription) -> Call {
+        match states.recv().await.unwrap().payload {
+            EventPayload::CallStateChanged { call } => call,
+            other => panic!("unexpected payload: {other:?}"),
+        }
+    }
+
+    async fn next_sent(sent: &mut EventSubscription) -> (String, JingleAction) {
+        match sent.recv().await.unwrap().payload {
+            EventPayload::JingleSendRequested { iq_id, action, .. } => (iq_id, action),
+            other => panic!("unexpected payload: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn outgoing_call_rings_then_becomes_active() {
+        let (manager, event_bus) = make_manager();
+        let mut states = event_bus.subscribe("system.call.state_changed").unwrap();
+        let mut media = event_bus.subscribe("system.call.media").unwrap();
+        let mut sent = event_bus.subscribe("ui.jingle.send").unwrap();
+
+        let call = manager.start_call(PEER, content()).unwrap();
+        assert!(matches!(
+            next_sent(&mut sent).await.1,
+            JingleAction::SessionInitiate { .. }
+        ));
+        assert_eq!(next_state(&mut states).await.state, CallState::Ringing);
+
+        manager
+            .handle_event(&request(
+                &call.sid,
+                JingleAction::SessionAccept {
+                    contents: content(),
+                },
+            ))
+            .await;
+
+        let active = next_state(&mut states).await;
+        assert_eq!(active.state, CallState::Active);
+        assert!(active.answered_at.is_some());
+        assert!(matches!(
+            media.recv().await.unwrap().payload,
+            EventPayload::CallMediaReceived {
+                kind: CallMediaKind::Answer,
+                ..
+            }
+        ));
+
+        manager.hang_up(&call.sid).unwrap();
+        assert_eq!(
+            next_sent(&mut sent).await.1,
+            JingleAction::SessionTerminate {
+                reason: CallEndReason::Success
+            }
+        );
+        let ended = next_state(&mut states).await;
+        assert_eq!(ended.state, CallState::Ended);
+        assert!(manager.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn incoming_call_rings_and_can_be_declined() {
+        let (manager, event_bus) = make_manager();
+        let mut states = event_bus.subscribe("system.call.state_changed").unwrap();
+        let mut media = event_bus.subscribe("system.call.media").unwrap();
+        let mut sent = event_bus.subscribe("ui.jingle.send").unwrap();
+
+        manager
+            .handle_event(&request(
+                "s1",
+                JingleAction::SessionInitiate {
+                    contents: content(),
+                },
+            ))
+            .await;
+
+        let ringing = next_state(&mut states).await;
+        assert_eq!(ringing.direction, CallDirection::Incoming);
+        assert_eq!(ringing.state, CallState::Ringing);
+        assert!(matches!(
+            media.recv().await.unwrap().payload,
+            EventPayload::CallMediaReceived {
+                kind: CallMediaKind::Offer,
+                ..
+            }
+        ));
+        assert_eq!(next_sent(&mut sent).await.1, JingleAction::Ringing);
+
+        manager.hang_up("s1").unwrap();
+        assert_eq!(
+            next_sent(&mut sent).await.1,
+            JingleAction::SessionTerminate {
+                reason: CallEndReason::Decline
+            }
+        );
+        assert_eq!(next_state(&mut states).await.state, CallState::Ended);
+    }
+
+    #[tokio::test]
+    async fn second_caller_is_told_we_are_busy() {
+        let (manager, event_bus) = make_manager();
+        manager.start_call(PEER, content()).unwrap();
+        let mut sent = event_bus.subscribe("ui.jingle.send").unwrap();
+
+        manager
+            .handle_event(&request(
+                "s2",
+                JingleAction::SessionInitiate {
+                    contents: content(),
+                },
+            ))
+            .await;
+
+        assert_eq!(
+            next_sent(&mut sent).await.1,
+            JingleAction::SessionTerminate {
+                reason: CallEndReason::Busy
+            }
+        );
+        assert_eq!(manager.calls().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn refused_offer_fails_the_call() {
+        let (manager, event_bus) = make_manager();
+        let mut sent = event_bus.subscribe("ui.jingle.send").unwrap();
+        let call = manager.start_call(PEER, content()).unwrap();
+        let (iq_id, _) = next_sent(&mut sent).await;
+        let mut states = event_bus.subscribe("system.call.state_changed").unwrap();
+
+        manager
+            .handle_event(&Event::new(
+                Channel::new("xmpp.jingle.answered").unwrap(),
+                EventSource::Xmpp,
+                EventPayload::JingleAnswered {
+                    iq_id,
+                    error: Some("service-unavailable".to_string()),
+                },
+            ))
+            .await;
+
+        let failed = next_state(&mut states).await;
+        assert_eq!(failed.sid, call.sid);
+        assert_eq!(failed.state, CallState::Failed);
+        assert_eq!(failed.reason, Some(CallEndReason::GeneralError));
+    }
+
+    #[tokio::test]
+    async fn refuses_requests_for_unknown_sessions() {
+        let (manager, event_bus) = make_manager();
+        let mut answers = event_bus.subscribe("ui.jingle.answer").unwrap();
+
+        manager
+            .handle_event(&request(
+                "nope",
+                JingleAction::TransportInfo {
+                    contents: content(),
+                },
+            ))
+            .await;
+
+        assert!(matches!(
+            answers.recv().await.unwrap().payload,
+            EventPayload::JingleAnswerRequested { error: Some(error), .. }
+                if error == "item-not-found"
+        ));
+        assert!(matches!(
+            manager.start_call("romeo@montague.lit", content()),
+            Err(CallError::InvalidPeer(_))
+        ));
+        assert!(matches!(
+            manager.accept_call("nope", content()),
+            Err(CallError::UnknownCall(_))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unanswered_call_times_out() {
+        let (manager, event_bus) = make_manager();
+        let mut states = event_bus.subscribe("system.call.state_changed").unwrap();
+        let mut sent = event_bus.subscribe("ui.jingle.send").unwrap();
+
+        let call = manager.start_call(PEER, content()).unwrap();
+        next_sent(&mut sent).await;
+        assert_eq!(next_state(&mut states).await.state, CallState::Ringing);
+
+        tokio::time::advance(RING_TIMEOUT / 2).await;
+        manager.end_unanswered_calls();
+        assert_eq!(manager.calls().len(), 1);
+
+        tokio::time::advance(RING_TIMEOUT / 2).await;
+        manager.end_unanswered_calls();
+        assert_eq!(
+            next_sent(&mut sent).await.1,
+            JingleAction::SessionTerminate {
+                reason: CallEndReason::Timeout
+            }
+        );
+        let ended = next_state(&mut states).await;
+        assert_eq!(ended.sid, call.sid);
+        assert_eq!(ended.state, CallState::Ended);
+        assert_eq!(ended.reason, Some(CallEndReason::Timeout));
+        assert!(manager.calls().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn answered_call_does_not_time_out() {
+        let (manager, event_bus) = make_manager();
+        let mut states = event_bus.subscribe("system.call.state_changed").unwrap();
+
+        manager
+            .handle_event(&request(
+                "sid-1",
+                JingleAction::SessionInitiate {
+                    contents: content(),
+                },
+            ))
+            .await;
+        assert_eq!(next_state(&mut states).await.state, CallState::Ringing);
+        manager.accept_call("sid-1", content()).unwrap();
+        assert_eq!(next_state(&mut states).await.state, CallState::Active);
+
+        tokio::time::advance(RING_TIMEOUT).await;
+        manager.end_unanswered_calls();
+        assert_eq!(manager.calls()[0].state, CallState::Active);
+    }
+}
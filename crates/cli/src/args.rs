@@ -0,0 +1,228 @@
+use waddle_core::event::PresenceShow;
+
+use crate::error::CliError;
+
+pub const USAGE: &str = "\
+usage: waddle [--json] <command>
+
+commands:
+  status                              show the session's account and connection
+  send <jid> <body>...                send a chat message
+  presence <show> [status]...         set presence: available, chat, away, xa or dnd
+  roster list                         list contacts
+  conversations list                  list conversations
+  muc join <room> [--nick <nick>]     join a group chat
+  plugin install <reference>          install a plugin from a registry or directory
+
+options:
+  --json    print results as JSON
+";
+
+#[derive(Debug)]
+pub struct Cli {
+    pub json: bool,
+    pub command: Command,
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Help,
+    Status,
+    Send {
+        to: String,
+        body: String,
+    },
+    Presence {
+        show: PresenceShow,
+        status: Option<String>,
+    },
+    RosterList,
+    ConversationsList,
+    MucJoin {
+        room: String,
+        nick: Option<String>,
+    },
+    PluginInstall {
+        reference: String,
+    },
+}
+
+impl Command {
+    /// Whether the command only makes sense once the session is connected.
+    pub fn needs_connection(&self) -> bool {
+        matches!(
+            self,
+            Command::Send { .. } | Command::Presence { .. } | Command::MucJoin { .. }
+        )
+    }
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, CliError> {
+    let mut args = args.into_iter().peekable();
+    let mut json = false;
+    while let Some(flag) = args.next_if(|arg| arg.starts_with('-')) {
+        match flag.as_str() {
+            "--json" => json = true,
+            "-h" | "--help" => {
+                return Ok(Cli {
+                    json,
+                    command: Command::Help,
+                });
+            }
+            _ => return Err(usage(format!("unknown option `{flag}`"))),
+        }
+    }
+
+    let Some(name) = args.next() else {
+        return Err(usage("missing command"));
+    };
+    let mut args = args.collect::<Vec<_>>().into_iter();
+    let command = match name.as_str() {
+        "help" => Command::Help,
+        "status" => Command::Status,
+        "send" => {
+            let to = required(&mut args, "recipient JID")?;
+            let body = rest(args.by_ref());
+            if body.is_empty() {
+                return Err(usage("missing message body"));
+            }
+            Command::Send { to, body }
+        }
+        "presence" => {
+            let show = required(&mut args, "presence")?;
+            let show = parse_show(&show)?;
+            let status = Some(rest(args.by_ref())).filter(|status| !status.is_empty());
+            Command::Presence { show, status }
+        }
+        "roster" => {
+            subcommand(&mut args, "roster", &["list"])?;
+            Command::RosterList
+        }
+        "conversations" => {
+            subcommand(&mut args, "conversations", &["list"])?;
+            Command::ConversationsList
+        }
+        "muc" => {
+            subcommand(&mut args, "muc", &["join"])?;
+            let room = required(&mut args, "room JID")?;
+            let nick = match args.next().as_deref() {
+                None => None,
+                Some("--nick") => Some(required(&mut args, "nick")?),
+                Some(other) => return Err(usage(format!("unexpected argument `{other}`"))),
+            };
+            Command::MucJoin { room, nick }
+        }
+        "plugin" => {
+            subcommand(&mut args, "plugin", &["install"])?;
+            let reference = required(&mut args, "plugin reference")?;
+            Command::PluginInstall { reference }
+        }
+        other => return Err(usage(format!("unknown command `{other}`"))),
+    };
+    if let Some(extra) = args.next() {
+        return Err(usage(format!("unexpected argument `{extra}`")));
+    }
+
+    Ok(Cli { json, command })
+}
+
+fn usage(message: impl Into<String>) -> CliError {
+    CliError::Usage(message.into())
+}
+
+fn required(args: &mut impl Iterator<Item = String>, what: &str) -> Result<String, CliError> {
+    args.next().ok_or_else(|| usage(format!("missing {what}")))
+}
+
+fn subcommand(
+    args: &mut impl Iterator<Item = String>,
+    command: &str,
+    known: &[&str],
+) -> Result<(), CliError> {
+    let name = args
+        .next()
+        .ok_or_else(|| usage(format!("missing `{command}` subcommand")))?;
+    if known.contains(&name.as_str()) {
+        Ok(())
+    } else {
+        Err(usage(format!("unknown `{command}` subcommand `{name}`")))
+    }
+}
+
+/// The remaining words, as the shell split them.
+fn rest(args: impl Iterator<Item = String>) -> String {
+    args.collect::<Vec<_>>().join(" ")
+}
+
+fn parse_show(show: &str) -> Result<PresenceShow, CliError> {
+    match show {
+        "available" => Ok(PresenceShow::Available),
+        "chat" => Ok(PresenceShow::Chat),
+        "away" => Ok(PresenceShow::Away),
+        "xa" => Ok(PresenceShow::Xa),
+        "dnd" => Ok(PresenceShow::Dnd),
+        other => Err(usage(format!("unknown presence `{other}`"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_words(line: &str) -> Result<Cli, CliError> {
+        parse(line.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn parses_commands() {
+        let cli = parse_words("--json send bob@example.com hi there").unwrap();
+        assert!(cli.json);
+        assert!(matches!(
+            cli.command,
+            Command::Send { ref to, ref body } if to == "bob@example.com" && body == "hi there"
+        ));
+
+        let cli = parse_words("muc join room@conference.example.com --nick alice").unwrap();
+        assert!(!cli.json);
+        assert!(matches!(
+            cli.command,
+            Command::MucJoin { ref room, nick: Some(ref nick) }
+                if room == "room@conference.example.com" && nick == "alice"
+        ));
+
+        assert!(matches!(
+            parse_words("roster list").unwrap().command,
+            Command::RosterList
+        ));
+        assert!(matches!(
+            parse_words("presence dnd").unwrap().command,
+            Command::Presence {
+                show: PresenceShow::Dnd,
+                status: None
+            }
+        ));
+        assert!(matches!(
+            parse_words("plugin install ghcr.io/waddle-social/echo:1.0").unwrap().command,
+            Command::PluginInstall { ref reference } if reference == "ghcr.io/waddle-social/echo:1.0"
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_commands() {
+        for line in [
+            "",
+            "--verbose status",
+            "send bob@example.com",
+            "roster add bob@example.com",
+            "muc join",
+            "muc join room@conference.example.com alice",
+            "presence busy",
+            "status now",
+        ] {
+            assert!(
+                matches!(parse_words(line), Err(CliError::Usage(_))),
+                "accepted `{line}`"
+            );
+        }
+    }
+}
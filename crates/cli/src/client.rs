@@ -0,0 +1,226 @@
+//! Talks to `waddled` over its control socket, starting one for the
+//! duration of the command when none is running.
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf,
+};
+use tokio::net::UnixStream;
+use tokio::process::{Child, Command};
+use tokio::time::{Instant, sleep, timeout};
+
+use waddle_daemon::protocol::{
+    ConnectionStatus, ControlEnvelope, ControlRequest, ControlResponse, DaemonStatus,
+};
+
+use crate::error::CliError;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct ControlClient<S> {
+    lines: Lines<BufReader<ReadHalf<S>>>,
+    writer: WriteHalf<S>,
+    next_id: u64,
+}
+
+impl ControlClient<UnixStream> {
+    pub async fn connect(path: &Path) -> std::io::Result<Self> {
+        Ok(Self::new(UnixStream::connect(path).await?))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> ControlClient<S> {
+    pub fn new(stream: S) -> Self {
+        let (reader, writer) = tokio::io::split(stream);
+        Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+            next_id: 1,
+        }
+    }
+
+    pub async fn request(&mut self, request: ControlRequest) -> Result<Value, CliError> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut line = serde_json::to_vec(&ControlEnvelope {
+            id: Some(id),
+            request,
+        })?;
+        line.push(b'\n');
+        self.writer.write_all(&line).await?;
+        self.writer.flush().await?;
+
+        let line = self
+            .lines
+            .next_line()
+            .await?
+            .ok_or(CliError::Disconnected)?;
+        let response: ControlResponse = serde_json::from_str(&line)?;
+        if response.id != Some(id) {
+            return Err(CliError::Daemon(format!(
+                "response to request {:?} while waiting for {id}",
+                response.id
+            )));
+        }
+        response.into_result().map_err(CliError::Daemon)
+    }
+
+    pub async fn status(&mut self) -> Result<DaemonStatus, CliError> {
+        Ok(serde_json::from_value(
+            self.request(ControlRequest::Status).await?,
+        )?)
+    }
+}
+
+/// A control connection, to a running daemon or to one started for this
+/// command and stopped by [`Session::close`].
+pub struct Session {
+    pub client: ControlClient<UnixStream>,
+    daemon: Option<Child>,
+}
+
+impl Session {
+    /// Connect to the daemon listening on `socket`, or start one. With
+    /// `online`, wait until the session is connected to the server.
+    pub async fn open(socket: &Path, online: bool) -> Result<Self, CliError> {
+        let mut session = match ControlClient::connect(socket).await {
+            Ok(client) => Self {
+                client,
+                daemon: None,
+            },
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    ErrorKind::NotFound | ErrorKind::ConnectionRefused
+                ) =>
+            {
+                Self::start(socket).await?
+            }
+            Err(error) => return Err(error.into()),
+        };
+        if online {
+            session.wait_until_connected().await?;
+        }
+        Ok(session)
+    }
+
+    async fn start(socket: &Path) -> Result<Self, CliError> {
+        let mut daemon = Command::new(daemon_executable())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|error| CliError::Session(format!("cannot run waddled: {error}")))?;
+
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        loop {
+            if let Some(status) = daemon.try_wait()? {
+                return Err(CliError::Session(format!("waddled exited with {status}")));
+            }
+            match ControlClient::connect(socket).await {
+                Ok(client) => {
+                    return Ok(Self {
+                        client,
+                        daemon: Some(daemon),
+                    });
+                }
+                Err(_) if Instant::now() < deadline => sleep(POLL_INTERVAL).await,
+                Err(error) => {
+                    return Err(CliError::Session(format!(
+                        "waddled did not open {}: {error}",
+                        socket.display()
+                    )));
+                }
+            }
+        }
+    }
+
+    async fn wait_until_connected(&mut self) -> Result<(), CliError> {
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+        loop {
+            if self.client.status().await?.connection == ConnectionStatus::Connected {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(CliError::Session("not connected to the server".to_string()));
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Stop the daemon if this session started it.
+    pub async fn close(mut self) -> Result<(), CliError> {
+        let Some(mut daemon) = self.daemon.take() else {
+            return Ok(());
+        };
+        self.client.request(ControlRequest::Shutdown).await?;
+        if timeout(SHUTDOWN_TIMEOUT, daemon.wait()).await.is_err() {
+            daemon.kill().await?;
+        }
+        Ok(())
+    }
+}
+
+/// `waddled` from next to this executable when it is installed there, or
+/// from `PATH`.
+fn daemon_executable() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .map(|executable| executable.with_file_name("waddled"))
+        .filter(|daemon| daemon.is_file())
+        .unwrap_or_else(|| PathBuf::from("waddled"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answer each request with its own command name, and fail `shutdown`.
+    async fn fake_daemon(stream: tokio::io::DuplexStream) {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            let request: Value = serde_json::from_str(&line).unwrap();
+            let id = request["id"].as_u64();
+            let result = match request["command"].as_str() {
+                Some("shutdown") => Err("not now".to_string()),
+                Some(command) => Ok(Value::from(command)),
+                None => Err("no command".to_string()),
+            };
+            let mut line = serde_json::to_vec(&ControlResponse::from_result(id, result)).unwrap();
+            line.push(b'\n');
+            writer.write_all(&line).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn numbers_requests_and_reports_failures() {
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(fake_daemon(server));
+        let mut client = ControlClient::new(client);
+
+        let roster = client.request(ControlRequest::ListRoster).await.unwrap();
+        assert_eq!(roster, "listRoster");
+        let join = client
+            .request(ControlRequest::JoinRoom {
+                room: "room@conference.example.com".to_string(),
+                nick: "alice".to_string(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(join, "joinRoom");
+        assert_eq!(client.next_id, 3);
+
+        let error = client.request(ControlRequest::Shutdown).await.unwrap_err();
+        assert!(matches!(error, CliError::Daemon(message) if message == "not now"));
+    }
+}
@@ -0,0 +1,29 @@
+use waddle_core::config::ConfigError;
+use waddle_plugins::RegistryError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CliError {
+    #[error("{0}")]
+    Usage(String),
+
+    #[error("configuration error: {0}")]
+    Config(#[from] ConfigError),
+
+    #[error("plugin registry error: {0}")]
+    Registry(#[from] RegistryError),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("malformed response from waddled: {0}")]
+    Protocol(#[from] serde_json::Error),
+
+    #[error("waddled closed the control connection")]
+    Disconnected,
+
+    #[error("{0}")]
+    Daemon(String),
+
+    #[error("could not start a session: {0}")]
+    Session(String),
+}
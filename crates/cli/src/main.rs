@@ -0,0 +1,203 @@
+//! `waddle`: scripted access to a Waddle session.
+//!
+//! Commands go to `waddled` over its control socket. When no daemon is
+//! running, one is started for the command and stopped afterwards.
+//! Plugins are installed into the plugin directory directly and load the
+//! next time a frontend starts.
+
+mod args;
+mod client;
+mod error;
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use serde_json::Value;
+
+use waddle_core::config::{self, Config, RegistryAuthConfig};
+use waddle_core::event::RosterItem;
+use waddle_daemon::protocol::{ControlRequest, DaemonStatus};
+use waddle_daemon::{control_socket_path, data_dir, expand_home_path};
+use waddle_plugins::{PluginRegistry, RegistryConfig, RegistryCredentials};
+
+use crate::args::{Cli, Command, USAGE};
+use crate::client::Session;
+use crate::error::CliError;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let cli = match args::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(error) => {
+            eprintln!("waddle: {error}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("waddle: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), CliError> {
+    if let Command::Help = cli.command {
+        print!("{USAGE}");
+        return Ok(());
+    }
+
+    let config = config::load_config()?;
+    if let Command::PluginInstall { reference } = &cli.command {
+        let registry = PluginRegistry::new(registry_config(&config), plugin_data_dir(&config))?;
+        let installed = registry.install(reference).await?;
+        if cli.json {
+            println!("{}", serde_json::to_string(&installed)?);
+        } else {
+            println!("installed {} {}", installed.id, installed.version);
+        }
+        return Ok(());
+    }
+
+    let mut session = Session::open(
+        &control_socket_path(&config),
+        cli.command.needs_connection(),
+    )
+    .await?;
+    let result = match request_for(&cli.command, &config) {
+        Some(request) => session.client.request(request).await,
+        None => Ok(Value::Null),
+    };
+    session.close().await?;
+
+    print_result(&cli, result?)
+}
+
+fn request_for(command: &Command, config: &Config) -> Option<ControlRequest> {
+    let request = match command {
+        Command::Help | Command::PluginInstall { .. } => return None,
+        Command::Status => ControlRequest::Status,
+        Command::Send { to, body } => ControlRequest::SendMessage {
+            to: to.clone(),
+            body: body.clone(),
+        },
+        Command::Presence { show, status } => ControlRequest::SetPresence {
+            show: show.clone(),
+            status: status.clone(),
+        },
+        Command::RosterList => ControlRequest::ListRoster,
+        Command::ConversationsList => ControlRequest::ListConversations,
+        Command::MucJoin { room, nick } => ControlRequest::JoinRoom {
+            room: room.clone(),
+            // The account's local part, as other clients default to.
+            nick: nick.clone().unwrap_or_else(|| {
+                let jid = &config.account.jid;
+                jid.split_once('@')
+                    .map_or(jid.as_str(), |(local, _)| local)
+                    .to_string()
+            }),
+        },
+    };
+    Some(request)
+}
+
+/// Print `result` as JSON, or as text for the commands that return
+/// something worth reading.
+fn print_result(cli: &Cli, result: Value) -> Result<(), CliError> {
+    if cli.json {
+        println!("{}", serde_json::to_string(&result)?);
+        return Ok(());
+    }
+
+    match cli.command {
+        Command::Status => print_status(serde_json::from_value(result)?),
+        Command::RosterList => print_roster(serde_json::from_value(result)?),
+        Command::ConversationsList => print_conversations(result),
+        _ => {}
+    }
+    Ok(())
+}
+
+fn print_status(status: DaemonStatus) {
+    println!("{}", status.jid);
+    println!("connection: {:?}", status.connection);
+    match status.status_message {
+        Some(message) => println!("presence: {:?} ({message})", status.presence),
+        None => println!("presence: {:?}", status.presence),
+    }
+    println!("uptime: {}s", status.uptime_secs);
+}
+
+/// One contact per line: JID, name, subscription and groups, tab-separated.
+fn print_roster(roster: Vec<RosterItem>) {
+    for item in roster {
+        println!(
+            "{}\t{}\t{}\t{}",
+            item.jid,
+            item.name.unwrap_or_default(),
+            item.subscription.as_str(),
+            item.groups.join(",")
+        );
+    }
+}
+
+/// One conversation per line: JID, unread count and last message,
+/// tab-separated.
+fn print_conversations(conversations: Value) {
+    for conversation in conversations.as_array().into_iter().flatten() {
+        println!(
+            "{}\t{}\t{}",
+            conversation["jid"].as_str().unwrap_or_default(),
+            conversation["unreadCount"].as_u64().unwrap_or_default(),
+            conversation["lastMessagePreview"]
+                .as_str()
+                .unwrap_or_default()
+        );
+    }
+}
+
+fn registry_config(config: &Config) -> RegistryConfig {
+    let plugins = &config.plugins;
+    RegistryConfig {
+        signature_policy: plugins.signature_policy.clone(),
+        trusted_keys: plugins.trusted_keys.clone(),
+        credentials: plugins
+            .registries
+            .iter()
+            .map(|(registry, auth)| {
+                let credentials = match auth {
+                    RegistryAuthConfig::Basic { username, password } => {
+                        RegistryCredentials::Basic {
+                            username: username.clone(),
+                            password: password.clone(),
+                        }
+                    }
+                    RegistryAuthConfig::Token { token } => {
+                        RegistryCredentials::Token(token.clone())
+                    }
+                    RegistryAuthConfig::Docker => RegistryCredentials::Docker,
+                };
+                (registry.clone(), credentials)
+            })
+            .collect(),
+        ..RegistryConfig::default()
+    }
+}
+
+/// The directory holding `plugins/`, as the frontends resolve it.
+fn plugin_data_dir(config: &Config) -> PathBuf {
+    let Some(configured_path) = config.plugins.directory.as_deref() else {
+        return data_dir();
+    };
+    let plugin_path = expand_home_path(configured_path);
+    if plugin_path
+        .file_name()
+        .is_some_and(|file_name| file_name == "plugins")
+        && let Some(parent) = plugin_path.parent()
+    {
+        return parent.to_path_buf();
+    }
+    plugin_path
+}
@@ -8,7 +8,7 @@ cmd-leave = Leave the current room
 cmd-theme = Switch theme
 cmd-roster = Manage contacts
 command-presence-updated = Presence updated:
-command-presence-usage = status <available|away|dnd|xa|chat> [message]
+command-presence-usage = status <available|away|dnd|xa|chat|invisible> [message]
 command-join-usage = join <room> [nick]
 command-joining-room = Joining room:
 command-leave-usage = leave [room]
@@ -9,6 +9,7 @@ status-available = Available
 status-away = Away
 status-dnd = Do Not Disturb
 status-xa = Extended Away
+status-invisible = Invisible
 status-unavailable = Unavailable
 roster-title = Contacts
 rooms-title = Rooms
@@ -22,6 +23,12 @@ conversation-placeholder = Type a message…
 conversation-send = Send
 message-delivered = delivered
 chatstate-typing = typing...
+conversation-loading-history = Loading older messages…
+conversation-history-start = Start of conversation
+occupants-title = Occupants
+palette-title = Command palette
+palette-empty = No matching commands
 mode-normal = Normal
 mode-insert = Insert
 mode-command = Command
+mode-palette = Palette
@@ -0,0 +1,109 @@
+//! Carries the event bus between processes.
+//!
+//! A client sends [`ClientFrame`]s and receives [`BridgeFrame`]s, one JSON
+//! document per line on a local socket or per text message on a WebSocket.
+//! The bridge greets every client with a `hello` naming its instance and
+//! latest sequence number. Every request carries an `id` that its `ack` or
+//! `error` answer repeats; a subscription is named by the id of the request
+//! that opened it. Events are numbered as the bridge records them, so a
+//! client that reconnects to the same instance can subscribe `since` the
+//! last number it saw and receive what it missed.
+//!
+//! The native build serves the bus with [`EventBridge`]; the web build,
+//! which has no bus of its own, reaches one through [`EventBusProxy`].
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::event::{Event, EventPayload};
+
+#[cfg(any(feature = "native", feature = "web"))]
+mod proxy;
+#[cfg(feature = "native")]
+mod server;
+
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+pub use proxy::BrowserConnector;
+#[cfg(any(feature = "native", feature = "web"))]
+pub use proxy::{BridgeConnector, BridgeSocket, EventBusProxy, ProxySubscription};
+#[cfg(all(feature = "native", unix))]
+pub use server::bind_private_socket;
+#[cfg(feature = "native")]
+pub use server::{BRIDGE_SOURCE, BridgeAcl, EventBridge, TOKEN_FILE, write_token_file};
+
+#[derive(Debug, thiserror::Error)]
+pub enum BridgeError {
+    #[error("invalid channel pattern: {0}")]
+    InvalidPattern(String),
+
+    #[error("bridge I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("bridge transport error: {0}")]
+    Transport(String),
+
+    #[error("bridge TLS setup failed: {0}")]
+    Tls(String),
+
+    #[error("unexpected bridge frame: {0}")]
+    Protocol(String),
+
+    #[error("event bus proxy is already running")]
+    AlreadyRunning,
+}
+
+/// What a client asks of the bridge.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+pub enum ClientFrame {
+    /// Receive events matching `pattern`, starting after sequence number
+    /// `since` when it is still held, or with the next event otherwise.
+    Subscribe {
+        id: u64,
+        pattern: String,
+        #[serde(default)]
+        since: Option<u64>,
+    },
+    Unsubscribe {
+        id: u64,
+    },
+    #[serde(rename_all = "camelCase")]
+    Publish {
+        id: u64,
+        channel: String,
+        payload: Box<EventPayload>,
+        #[serde(default)]
+        correlation_id: Option<Uuid>,
+    },
+}
+
+/// What the bridge writes back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum BridgeFrame {
+    /// First frame on every connection. Sequence numbers only carry over
+    /// between connections to the same `instance`.
+    Hello {
+        instance: Uuid,
+        seq: u64,
+    },
+    Ack {
+        id: u64,
+    },
+    /// A request failed; `id` is `None` when the frame could not be read.
+    Error {
+        id: Option<u64>,
+        message: String,
+    },
+    Event {
+        subscription: u64,
+        seq: u64,
+        event: Box<Event>,
+    },
+    /// `missed` events of `subscription` were dropped before the client
+    /// could read them.
+    Lagged {
+        subscription: u64,
+        missed: u64,
+    },
+}
@@ -0,0 +1,655 @@
+use std::collections::{HashMap, HashSet};
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::StreamExt;
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender, unbounded};
+use futures::future::{Either, select};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::{BridgeError, BridgeFrame, ClientFrame};
+use crate::error::EventBusError;
+use crate::event::{Channel, Event, EventPayload};
+
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Opens connections to an event bridge.
+pub trait BridgeConnector {
+    type Socket: BridgeSocket;
+
+    fn connect(&self) -> impl Future<Output = Result<Self::Socket, BridgeError>>;
+}
+
+/// One connection to an event bridge, carrying one JSON frame per message.
+pub trait BridgeSocket {
+    fn send(&mut self, frame: String) -> impl Future<Output = Result<(), BridgeError>>;
+
+    /// The next frame, or `None` once the connection has closed. Dropping
+    /// the future before it completes must not lose a frame.
+    fn recv(&mut self) -> impl Future<Output = Result<Option<String>, BridgeError>>;
+}
+
+enum Request {
+    Subscribe(u64),
+    Unsubscribe(u64),
+    Publish(ClientFrame),
+}
+
+struct ProxyState {
+    next_id: u64,
+    /// The bridge the sequence numbers below came from.
+    instance: Option<Uuid>,
+    subscriptions: HashMap<u64, Subscriber>,
+}
+
+struct Subscriber {
+    pattern: String,
+    last_seq: Option<u64>,
+    events: UnboundedSender<Result<Event, EventBusError>>,
+}
+
+impl ProxyState {
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+}
+
+/// Subscribes and publishes on another process's event bus through its
+/// bridge, for builds that have no bus of their own.
+///
+/// [`run`](Self::run) keeps a connection open, reconnecting with
+/// backoff when it drops. Subscriptions survive reconnects: each resumes
+/// after the last event it received, provided the bridge still holds what
+/// came since. Publishes made while disconnected are sent once connected.
+pub struct EventBusProxy<C> {
+    connector: C,
+    state: Arc<Mutex<ProxyState>>,
+    requests: UnboundedSender<Request>,
+    pending: Mutex<Option<UnboundedReceiver<Request>>>,
+    initial_delay: Duration,
+    max_delay: Duration,
+}
+
+impl<C: BridgeConnector> EventBusProxy<C> {
+    pub fn new(connector: C) -> Self {
+        let (requests, pending) = unbounded();
+        Self {
+            connector,
+            state: Arc::new(Mutex::new(ProxyState {
+                next_id: 0,
+                instance: None,
+                subscriptions: HashMap::new(),
+            })),
+            requests,
+            pending: Mutex::new(Some(pending)),
+            initial_delay: INITIAL_RECONNECT_DELAY,
+            max_delay: MAX_RECONNECT_DELAY,
+        }
+    }
+
+    /// Wait `initial` before the first reconnect, doubling up to `max`.
+    pub fn with_reconnect_delay(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_delay = initial;
+        self.max_delay = max.max(initial);
+        self
+    }
+
+    pub fn subscribe(&self, pattern: &str) -> Result<ProxySubscription, EventBusError> {
+        globset::Glob::new(pattern)
+            .map_err(|_| EventBusError::InvalidPattern(pattern.to_string()))?;
+
+        let (sender, events) = unbounded();
+        let id = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            let id = state.next_id();
+            state.subscriptions.insert(
+                id,
+                Subscriber {
+                    pattern: pattern.to_string(),
+                    last_seq: None,
+                    events: sender,
+                },
+            );
+            id
+        };
+        let _ = self.requests.unbounded_send(Request::Subscribe(id));
+        Ok(ProxySubscription {
+            id,
+            events,
+            requests: self.requests.clone(),
+            state: self.state.clone(),
+        })
+    }
+
+    pub fn publish(&self, channel: &str, payload: EventPayload) -> Result<(), EventBusError> {
+        let channel = Channel::new(channel)?;
+        let id = self
+            .state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .next_id();
+        self.requests
+            .unbounded_send(Request::Publish(ClientFrame::Publish {
+                id,
+                channel: channel.to_string(),
+                payload: Box::new(payload),
+                correlation_id: None,
+            }))
+            .map_err(|_| EventBusError::ChannelClosed)
+    }
+
+    /// Stay connected to the bridge. Only returns if called twice.
+    pub async fn run(&self) -> Result<(), BridgeError> {
+        let mut requests = self
+            .pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+            .ok_or(BridgeError::AlreadyRunning)?;
+
+        let mut delay = self.initial_delay;
+        loop {
+            match self.connector.connect().await {
+                Ok(socket) => {
+                    delay = self.initial_delay;
+                    match self.session(socket, &mut requests).await {
+                        Ok(()) => debug!("event bridge connection closed"),
+                        Err(error) => warn!(%error, "event bridge connection failed"),
+                    }
+                }
+                Err(error) => debug!(%error, "cannot reach event bridge"),
+            }
+            sleep(delay).await;
+            delay = (delay * 2).min(self.max_delay);
+        }
+    }
+
+    async fn session(
+        &self,
+        mut socket: C::Socket,
+        requests: &mut UnboundedReceiver<Request>,
+    ) -> Result<(), BridgeError> {
+        let instance = match socket.recv().await? {
+            Some(frame) => match serde_json::from_str(&frame) {
+                Ok(BridgeFrame::Hello { instance, .. }) => instance,
+                _ => return Err(BridgeError::Protocol(frame)),
+            },
+            None => return Ok(()),
+        };
+
+        let resubscribe: Vec<ClientFrame> = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            if state.instance.replace(instance) != Some(instance) {
+                // A different bridge numbers its events afresh.
+                for subscriber in state.subscriptions.values_mut() {
+                    subscriber.last_seq = None;
+                }
+            }
+            state
+                .subscriptions
+                .iter()
+                .map(|(id, subscriber)| subscribe_frame(*id, subscriber))
+                .collect()
+        };
+        let mut active = HashSet::new();
+        for frame in resubscribe {
+            if let ClientFrame::Subscribe { id, .. } = frame {
+                active.insert(id);
+            }
+            send(&mut socket, &frame).await?;
+        }
+
+        loop {
+            let next = {
+                let incoming = pin!(socket.recv());
+                match select(incoming, requests.next()).await {
+                    Either::Left((frame, _)) => Either::Left(frame?),
+                    Either::Right((request, _)) => Either::Right(request),
+                }
+            };
+            let frame = match next {
+                Either::Left(Some(frame)) => {
+                    self.receive(&frame);
+                    continue;
+                }
+                Either::Left(None) | Either::Right(None) => return Ok(()),
+                Either::Right(Some(Request::Subscribe(id))) => {
+                    let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+                    match state.subscriptions.get(&id) {
+                        Some(subscriber) if active.insert(id) => subscribe_frame(id, subscriber),
+                        _ => continue,
+                    }
+                }
+                Either::Right(Some(Request::Unsubscribe(id))) => {
+                    if !active.remove(&id) {
+                        continue;
+                    }
+                    ClientFrame::Unsubscribe { id }
+                }
+                Either::Right(Some(Request::Publish(frame))) => frame,
+            };
+            send(&mut socket, &frame).await?;
+        }
+    }
+
+    fn receive(&self, frame: &str) {
+        let (subscription, event) = match serde_json::from_str(frame) {
+            Ok(BridgeFrame::Event {
+                subscription,
+                seq,
+                event,
+            }) => {
+                let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+                if let Some(subscriber) = state.subscriptions.get_mut(&subscription) {
+                    subscriber.last_seq = Some(seq);
+                }
+                (subscription, Ok(*event))
+            }
+            Ok(BridgeFrame::Lagged {
+                subscription,
+                missed,
+            }) => (subscription, Err(EventBusError::Lagged(missed))),
+            Ok(BridgeFrame::Error { id, message }) => {
+                warn!(?id, %message, "event bridge refused a request");
+                return;
+            }
+            Ok(BridgeFrame::Ack { .. } | BridgeFrame::Hello { .. }) => return,
+            Err(error) => {
+                warn!(%error, "unreadable event bridge frame");
+                return;
+            }
+        };
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(subscriber) = state.subscriptions.get(&subscription) {
+            let _ = subscriber.events.unbounded_send(event);
+        }
+    }
+}
+
+fn subscribe_frame(id: u64, subscriber: &Subscriber) -> ClientFrame {
+    ClientFrame::Subscribe {
+        id,
+        pattern: subscriber.pattern.clone(),
+        since: subscriber.last_seq,
+    }
+}
+
+async fn send<S: BridgeSocket>(socket: &mut S, frame: &ClientFrame) -> Result<(), BridgeError> {
+    // Frames only hold serializable data.
+    let text = serde_json::to_string(frame).unwrap_or_default();
+    socket.send(text).await
+}
+
+/// Events from one [`EventBusProxy::subscribe`] call. Dropping it
+/// unsubscribes.
+pub struct ProxySubscription {
+    id: u64,
+    events: UnboundedReceiver<Result<Event, EventBusError>>,
+    requests: UnboundedSender<Request>,
+    state: Arc<Mutex<ProxyState>>,
+}
+
+impl ProxySubscription {
+    /// The next event, or `Lagged` when the bridge dropped some before
+    /// this client could read them.
+    pub async fn recv(&mut self) -> Result<Event, EventBusError> {
+        self.events
+            .next()
+            .await
+            .unwrap_or(Err(EventBusError::ChannelClosed))
+    }
+}
+
+impl Drop for ProxySubscription {
+    fn drop(&mut self) {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .subscriptions
+            .remove(&self.id);
+        let _ = self.requests.unbounded_send(Request::Unsubscribe(self.id));
+    }
+}
+
+#[cfg(feature = "native")]
+async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await;
+}
+
+#[cfg(not(feature = "native"))]
+async fn sleep(duration: Duration) {
+    let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+        let scheduled = web_sys::window().is_some_and(|window| {
+            window
+                .set_timeout_with_callback_and_timeout_and_arguments_0(
+                    &resolve,
+                    duration.as_millis().min(i32::MAX as u128) as i32,
+                )
+                .is_ok()
+        });
+        if !scheduled {
+            let _ = resolve.call0(&wasm_bindgen::JsValue::NULL);
+        }
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+pub use browser::BrowserConnector;
+
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+mod browser {
+    use futures::StreamExt;
+    use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender, unbounded};
+    use wasm_bindgen::{JsCast, closure::Closure};
+    use web_sys::{CloseEvent, ErrorEvent, Event, MessageEvent, WebSocket};
+
+    use super::{BridgeConnector, BridgeSocket};
+    use crate::bridge::BridgeError;
+
+    /// Reaches a bridge's WebSocket from the browser. Browsers cannot set
+    /// headers on a WebSocket, so `url` carries the bridge token as a
+    /// `token` query parameter.
+    pub struct BrowserConnector {
+        url: String,
+    }
+
+    impl BrowserConnector {
+        pub fn new(url: impl Into<String>) -> Self {
+            Self { url: url.into() }
+        }
+    }
+
+    enum SocketEvent {
+        Open,
+        Text(String),
+        Failed(String),
+        Closed,
+    }
+
+    /// `web_sys::WebSocket` is not `Send`, so it lives in a local task and
+    /// is driven through channels. Dropping this closes it.
+    pub struct BrowserSocket {
+        commands: UnboundedSender<String>,
+        events: UnboundedReceiver<SocketEvent>,
+    }
+
+    async fn run_browser_websocket(
+        socket: WebSocket,
+        mut commands: UnboundedReceiver<String>,
+        events: UnboundedSender<SocketEvent>,
+    ) {
+        let events_for_open = events.clone();
+        let onopen = Closure::wrap(Box::new(move |_event: Event| {
+            let _ = events_for_open.unbounded_send(SocketEvent::Open);
+        }) as Box<dyn FnMut(Event)>);
+        socket.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+
+        let events_for_message = events.clone();
+        let onmessage = Closure::wrap(Box::new(move |event: MessageEvent| {
+            let event = match event.data().as_string() {
+                Some(text) => SocketEvent::Text(text),
+                None => SocketEvent::Failed("binary WebSocket message".to_string()),
+            };
+            let _ = events_for_message.unbounded_send(event);
+        }) as Box<dyn FnMut(MessageEvent)>);
+        socket.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+
+        let events_for_error = events.clone();
+        let onerror = Closure::wrap(Box::new(move |event: ErrorEvent| {
+            let _ = events_for_error.unbounded_send(SocketEvent::Failed(format!(
+                "websocket error: {}",
+                event.message()
+            )));
+        }) as Box<dyn FnMut(ErrorEvent)>);
+        socket.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+
+        let events_for_close = events.clone();
+        let onclose = Closure::wrap(Box::new(move |_event: CloseEvent| {
+            let _ = events_for_close.unbounded_send(SocketEvent::Closed);
+        }) as Box<dyn FnMut(CloseEvent)>);
+        socket.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+
+        while let Some(text) = commands.next().await {
+            if let Err(error) = socket.send_with_str(&text) {
+                let _ = events.unbounded_send(SocketEvent::Failed(format!(
+                    "failed to send websocket message: {error:?}"
+                )));
+            }
+        }
+
+        // The proxy let go of the connection.
+        socket.set_onopen(None);
+        socket.set_onmessage(None);
+        socket.set_onerror(None);
+        socket.set_onclose(None);
+        let _ = socket.close();
+    }
+
+    impl BridgeConnector for BrowserConnector {
+        type Socket = BrowserSocket;
+
+        async fn connect(&self) -> Result<BrowserSocket, BridgeError> {
+            let socket = WebSocket::new(&self.url).map_err(|error| {
+                BridgeError::Transport(format!("failed to open websocket: {error:?}"))
+            })?;
+            let (commands, commands_rx) = unbounded();
+            let (events_tx, events) = unbounded();
+            wasm_bindgen_futures::spawn_local(run_browser_websocket(
+                socket,
+                commands_rx,
+                events_tx,
+            ));
+
+            let mut socket = BrowserSocket { commands, events };
+            match socket.events.next().await {
+                Some(SocketEvent::Open) => Ok(socket),
+                Some(SocketEvent::Failed(message)) => Err(BridgeError::Transport(message)),
+                _ => Err(BridgeError::Transport(format!(
+                    "websocket to '{}' closed before opening",
+                    self.url
+                ))),
+            }
+        }
+    }
+
+    impl BridgeSocket for BrowserSocket {
+        async fn send(&mut self, frame: String) -> Result<(), BridgeError> {
+            self.commands
+                .unbounded_send(frame)
+                .map_err(|_| BridgeError::Transport("websocket is closed".to_string()))
+        }
+
+        async fn recv(&mut self) -> Result<Option<String>, BridgeError> {
+            loop {
+                match self.events.next().await {
+                    Some(SocketEvent::Text(text)) => return Ok(Some(text)),
+                    Some(SocketEvent::Failed(message)) => {
+                        return Err(BridgeError::Transport(message));
+                    }
+                    Some(SocketEvent::Closed) | None => return Ok(None),
+                    Some(SocketEvent::Open) => {}
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use tokio::io::{
+        AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines, ReadHalf, WriteHalf,
+    };
+    use tokio::task::JoinHandle;
+    use tokio::time::timeout;
+
+    use crate::bridge::{BRIDGE_SOURCE, BridgeAcl, EventBridge};
+    use crate::event::{BroadcastEventBus, EventBus, EventSource, channels};
+
+    /// Connects straight to an in-process bridge, and can be taken offline.
+    struct TestConnector {
+        bridge: Arc<EventBridge>,
+        offline: Arc<AtomicBool>,
+        connects: Arc<AtomicUsize>,
+        servers: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    }
+
+    struct LineSocket {
+        lines: Lines<BufReader<ReadHalf<DuplexStream>>>,
+        writer: WriteHalf<DuplexStream>,
+    }
+
+    impl BridgeConnector for TestConnector {
+        type Socket = LineSocket;
+
+        async fn connect(&self) -> Result<LineSocket, BridgeError> {
+            if self.offline.load(Ordering::SeqCst) {
+                return Err(BridgeError::Transport("offline".to_string()));
+            }
+            self.connects.fetch_add(1, Ordering::SeqCst);
+            let (client, server) = tokio::io::duplex(4096);
+            let bridge = self.bridge.clone();
+            let task = tokio::spawn(async move {
+                let _ = bridge.serve_connection(server).await;
+            });
+            self.servers.lock().unwrap().push(task);
+            let (reader, writer) = tokio::io::split(client);
+            Ok(LineSocket {
+                lines: BufReader::new(reader).lines(),
+                writer,
+            })
+        }
+    }
+
+    impl BridgeSocket for LineSocket {
+        async fn send(&mut self, frame: String) -> Result<(), BridgeError> {
+            self.writer.write_all(frame.as_bytes()).await?;
+            self.writer.write_all(b"\n").await?;
+            Ok(())
+        }
+
+        async fn recv(&mut self) -> Result<Option<String>, BridgeError> {
+            Ok(self.lines.next_line().await?)
+        }
+    }
+
+    struct Fixture {
+        event_bus: Arc<dyn EventBus>,
+        proxy: Arc<EventBusProxy<TestConnector>>,
+        offline: Arc<AtomicBool>,
+        connects: Arc<AtomicUsize>,
+        servers: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    }
+
+    fn start() -> Fixture {
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let acl = BridgeAcl::new(&["xmpp.**".to_string()], &["ui.**".to_string()]).unwrap();
+        let bridge = Arc::new(EventBridge::new(event_bus.clone(), acl));
+        let recorder = bridge.clone();
+        tokio::spawn(async move { recorder.run().await });
+
+        let offline = Arc::new(AtomicBool::new(false));
+        let connects = Arc::new(AtomicUsize::new(0));
+        let servers = Arc::new(Mutex::new(Vec::new()));
+        let proxy = Arc::new(
+            EventBusProxy::new(TestConnector {
+                bridge,
+                offline: offline.clone(),
+                connects: connects.clone(),
+                servers: servers.clone(),
+            })
+            .with_reconnect_delay(Duration::from_millis(10), Duration::from_millis(10)),
+        );
+        let runner = proxy.clone();
+        tokio::spawn(async move { runner.run().await });
+        Fixture {
+            event_bus,
+            proxy,
+            offline,
+            connects,
+            servers,
+        }
+    }
+
+    fn delivered(event_bus: &Arc<dyn EventBus>, id: &str) {
+        event_bus
+            .publish(Event::new(
+                Channel::new(channels::XMPP_MESSAGE_DELIVERED).unwrap(),
+                EventSource::Xmpp,
+                EventPayload::MessageDelivered {
+                    id: id.to_string(),
+                    to: "bob@example.com".to_string(),
+                },
+            ))
+            .unwrap();
+    }
+
+    async fn next_delivered(subscription: &mut ProxySubscription) -> String {
+        let event = timeout(Duration::from_secs(1), subscription.recv())
+            .await
+            .expect("timed out")
+            .unwrap();
+        match event.payload {
+            EventPayload::MessageDelivered { id, .. } => id,
+            other => panic!("unexpected payload: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn subscriptions_resume_after_a_reconnect() {
+        let fixture = start();
+        let mut subscription = fixture.proxy.subscribe("xmpp.**").unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        delivered(&fixture.event_bus, "m1");
+        assert_eq!(next_delivered(&mut subscription).await, "m1");
+
+        fixture.offline.store(true, Ordering::SeqCst);
+        for server in fixture.servers.lock().unwrap().drain(..) {
+            server.abort();
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        delivered(&fixture.event_bus, "m2");
+        delivered(&fixture.event_bus, "m3");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        fixture.offline.store(false, Ordering::SeqCst);
+
+        assert_eq!(next_delivered(&mut subscription).await, "m2");
+        assert_eq!(next_delivered(&mut subscription).await, "m3");
+        assert_eq!(fixture.connects.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn publishes_reach_the_bus_behind_the_bridge() {
+        let fixture = start();
+        let mut activity = fixture.event_bus.subscribe("ui.**").unwrap();
+
+        fixture
+            .proxy
+            .publish(
+                channels::UI_CLIENT_ACTIVITY,
+                EventPayload::ClientActivityChanged { active: false },
+            )
+            .unwrap();
+        let event = timeout(Duration::from_secs(1), activity.recv())
+            .await
+            .expect("timed out")
+            .unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::ClientActivityChanged { active: false }
+        ));
+        assert!(matches!(event.source, EventSource::System(ref s) if s == BRIDGE_SOURCE));
+
+        assert!(matches!(
+            fixture.proxy.subscribe("xmpp.[").err(),
+            Some(EventBusError::InvalidPattern(_))
+        ));
+    }
+}
@@ -0,0 +1,284 @@
+//! Consistent color generation (XEP-0392): the color a contact or nickname
+//! is drawn in, derived from the identifier alone.
+//!
+//! Every client following the XEP picks the same hue for the same JID or
+//! nick, so people are recognisable across frontends and devices. The hue
+//! comes from a SHA-1 of the identifier; saturation and lightness come from
+//! a [`ColorPalette`], which frontends adapt to their theme so names stay
+//! readable on light and dark backgrounds alike.
+
+use sha1::{Digest, Sha1};
+
+use crate::theme::Theme;
+
+/// An sRGB color with 8 bits per channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Parse `#rgb`, `#rrggbb` or `#rrggbbaa`, as themes write colors. Any
+    /// alpha is dropped.
+    pub fn from_hex(value: &str) -> Option<Self> {
+        let hex = value.trim().strip_prefix('#')?;
+        if !hex.is_ascii() {
+            return None;
+        }
+        let channel = |digits: &str| u8::from_str_radix(digits, 16).ok();
+        match hex.len() {
+            3 => {
+                let mut channels = hex.chars().map(|c| channel(&c.to_string()).map(|v| v * 17));
+                Some(Self::new(
+                    channels.next()??,
+                    channels.next()??,
+                    channels.next()??,
+                ))
+            }
+            6 | 8 => Some(Self::new(
+                channel(&hex[0..2])?,
+                channel(&hex[2..4])?,
+                channel(&hex[4..6])?,
+            )),
+            _ => None,
+        }
+    }
+
+    /// `#rrggbb`, as CSS and themes take it.
+    pub fn to_hex(self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+
+    /// Relative luminance from 0 (black) to 1 (white), per WCAG.
+    pub fn luminance(self) -> f64 {
+        let linear = |c: u8| to_linear(f64::from(c) / 255.0);
+        0.2126 * linear(self.r) + 0.7152 * linear(self.g) + 0.0722 * linear(self.b)
+    }
+}
+
+/// Saturation and lightness, in HSLuv percent, applied to every generated
+/// hue.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorPalette {
+    pub saturation: f64,
+    pub lightness: f64,
+}
+
+impl ColorPalette {
+    /// The values the XEP recommends when nothing is known about the
+    /// background.
+    pub const DEFAULT: Self = Self {
+        saturation: 100.0,
+        lightness: 50.0,
+    };
+
+    /// Darker colors, for text on a light background.
+    pub const LIGHT_BACKGROUND: Self = Self {
+        saturation: 100.0,
+        lightness: 40.0,
+    };
+
+    /// Lighter colors, for text on a dark background.
+    pub const DARK_BACKGROUND: Self = Self {
+        saturation: 90.0,
+        lightness: 70.0,
+    };
+
+    /// The palette that reads best on `background`.
+    pub fn for_background(background: Rgb) -> Self {
+        if background.luminance() > 0.5 {
+            Self::LIGHT_BACKGROUND
+        } else {
+            Self::DARK_BACKGROUND
+        }
+    }
+
+    /// The palette for `theme`'s background, or [`ColorPalette::DEFAULT`]
+    /// when the background is not a hex color.
+    pub fn for_theme(theme: &Theme) -> Self {
+        Rgb::from_hex(&theme.colors.background).map_or(Self::DEFAULT, Self::for_background)
+    }
+}
+
+impl Default for ColorPalette {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The hue angle, in degrees, the XEP assigns to `identifier`: the first
+/// two bytes of its SHA-1, little-endian, scaled to a full turn.
+pub fn hue_angle(identifier: &str) -> f64 {
+    let digest = Sha1::digest(identifier.as_bytes());
+    let value = u16::from_le_bytes([digest[0], digest[1]]);
+    f64::from(value) / 65536.0 * 360.0
+}
+
+/// The color for `identifier`, a bare JID or a room nickname, with the
+/// XEP's recommended saturation and lightness.
+pub fn consistent_color(identifier: &str) -> Rgb {
+    consistent_color_with(identifier, &ColorPalette::DEFAULT)
+}
+
+/// The color for `identifier`, with `palette`'s saturation and lightness.
+pub fn consistent_color_with(identifier: &str, palette: &ColorPalette) -> Rgb {
+    hsluv_to_rgb(hue_angle(identifier), palette.saturation, palette.lightness)
+}
+
+// HSLuv (https://www.hsluv.org/), reduced to the HSLuv to sRGB direction.
+
+const M: [[f64; 3]; 3] = [
+    [3.240969941904521, -1.537383177570093, -0.498610760293],
+    [-0.96924363628087, 1.87596750150772, 0.041555057407175],
+    [0.055630079696993, -0.20397695888897, 1.056971514242878],
+];
+const REF_U: f64 = 0.19783000664283;
+const REF_V: f64 = 0.46831999493879;
+const KAPPA: f64 = 903.2962962;
+const EPSILON: f64 = 0.0088564516;
+
+fn hsluv_to_rgb(hue: f64, saturation: f64, lightness: f64) -> Rgb {
+    let (l, c, h) = hsluv_to_lch(hue, saturation, lightness);
+    let (l, u, v) = lch_to_luv(l, c, h);
+    let [x, y, z] = luv_to_xyz(l, u, v);
+    let channel = |row: [f64; 3]| {
+        let linear = row[0] * x + row[1] * y + row[2] * z;
+        (from_linear(linear).clamp(0.0, 1.0) * 255.0).round() as u8
+    };
+    Rgb::new(channel(M[0]), channel(M[1]), channel(M[2]))
+}
+
+fn hsluv_to_lch(hue: f64, saturation: f64, lightness: f64) -> (f64, f64, f64) {
+    if lightness > 99.9999999 {
+        return (100.0, 0.0, hue);
+    }
+    if lightness < 0.00000001 {
+        return (0.0, 0.0, hue);
+    }
+    let chroma = max_chroma_for(lightness, hue) / 100.0 * saturation;
+    (lightness, chroma, hue)
+}
+
+/// The largest chroma at lightness `l` and hue `h` that stays inside sRGB.
+fn max_chroma_for(l: f64, h: f64) -> f64 {
+    let hrad = h.to_radians();
+    let sub1 = (l + 16.0).powi(3) / 1560896.0;
+    let sub2 = if sub1 > EPSILON { sub1 } else { l / KAPPA };
+
+    let mut min = f64::MAX;
+    for [m1, m2, m3] in M {
+        for t in [0.0, 1.0] {
+            let top1 = (284517.0 * m1 - 94839.0 * m3) * sub2;
+            let top2 =
+                (838422.0 * m3 + 769860.0 * m2 + 731718.0 * m1) * l * sub2 - 769860.0 * t * l;
+            let bottom = (632260.0 * m3 - 126452.0 * m2) * sub2 + 126452.0 * t;
+            let slope = top1 / bottom;
+            let intercept = top2 / bottom;
+            let length = intercept / (hrad.sin() - slope * hrad.cos());
+            if length >= 0.0 {
+                min = min.min(length);
+            }
+        }
+    }
+    min
+}
+
+fn lch_to_luv(l: f64, c: f64, h: f64) -> (f64, f64, f64) {
+    let hrad = h.to_radians();
+    (l, hrad.cos() * c, hrad.sin() * c)
+}
+
+fn luv_to_xyz(l: f64, u: f64, v: f64) -> [f64; 3] {
+    if l == 0.0 {
+        return [0.0; 3];
+    }
+    let var_u = u / (13.0 * l) + REF_U;
+    let var_v = v / (13.0 * l) + REF_V;
+    let y = if l <= 8.0 {
+        l / KAPPA
+    } else {
+        ((l + 16.0) / 116.0).powi(3)
+    };
+    let x = -(9.0 * y * var_u) / ((var_u - 4.0) * var_v - var_u * var_v);
+    let z = (9.0 * y - 15.0 * var_v * y - var_v * x) / (3.0 * var_v);
+    [x, y, z]
+}
+
+fn from_linear(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn to_linear(c: f64) -> f64 {
+    if c > 0.04045 {
+        ((c + 0.055) / 1.055).powf(2.4)
+    } else {
+        c / 12.92
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Rgb, expected: (f64, f64, f64)) {
+        let channel = |value: f64| (value * 255.0).round() as i16;
+        let (r, g, b) = expected;
+        for (actual, expected) in [(actual.r, r), (actual.g, g), (actual.b, b)] {
+            assert!(
+                (i16::from(actual) - channel(expected)).abs() <= 1,
+                "{actual:?} is not {expected:?}"
+            );
+        }
+    }
+
+    /// The examples from the XEP.
+    #[test]
+    fn matches_the_xep_test_vectors() {
+        for (identifier, angle, rgb) in [
+            ("Romeo", 327.255249, (0.865, 0.000, 0.686)),
+            ("juliet@capulet.lit", 209.410400, (0.000, 0.515, 0.573)),
+            ("😺", 331.199341, (0.872, 0.000, 0.659)),
+            ("council", 359.994507, (0.918, 0.000, 0.394)),
+        ] {
+            assert!(
+                (hue_angle(identifier) - angle).abs() < 0.0001,
+                "hue of {identifier}"
+            );
+            assert_close(consistent_color(identifier), rgb);
+        }
+    }
+
+    #[test]
+    fn palettes_follow_the_background() {
+        let black = Rgb::from_hex("#000").unwrap();
+        let white = Rgb::from_hex("#ffffffcc").unwrap();
+        assert_eq!(black, Rgb::new(0, 0, 0));
+        assert_eq!(white.to_hex(), "#ffffff");
+        assert_eq!(Rgb::from_hex("white"), None);
+        assert_eq!(Rgb::from_hex("#12345"), None);
+
+        assert_eq!(
+            ColorPalette::for_background(black),
+            ColorPalette::DARK_BACKGROUND
+        );
+        assert_eq!(
+            ColorPalette::for_background(white),
+            ColorPalette::LIGHT_BACKGROUND
+        );
+
+        let on_dark = consistent_color_with("Romeo", &ColorPalette::DARK_BACKGROUND);
+        let on_light = consistent_color_with("Romeo", &ColorPalette::LIGHT_BACKGROUND);
+        assert!(on_dark.luminance() > consistent_color("Romeo").luminance());
+        assert!(on_light.luminance() < consistent_color("Romeo").luminance());
+    }
+}
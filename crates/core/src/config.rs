@@ -1,4 +1,5 @@
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 #[derive(Debug, thiserror::Error)]
@@ -38,14 +39,67 @@ pub struct Config {
     pub event_bus: EventBusConfig,
     #[serde(default)]
     pub storage: StorageConfig,
+    #[serde(default)]
+    pub supervisor: SupervisorConfig,
+    #[serde(default)]
+    pub messaging: MessagingConfig,
+    #[serde(default)]
+    pub roster: RosterConfig,
+    #[serde(default)]
+    pub presence: PresenceConfig,
+    #[serde(default)]
+    pub debug: DebugConfig,
+    #[serde(default)]
+    pub daemon: DaemonConfig,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct AccountConfig {
     pub jid: String,
+    /// Plaintext password. Optional: when empty the password is read from the
+    /// OS keyring or the encrypted credential file at connect time.
+    #[serde(default)]
     pub password: String,
     pub server: Option<String>,
     pub port: Option<u16>,
+    /// Transports to try, in order. Empty uses the platform default order.
+    #[serde(default)]
+    pub transports: Vec<TransportKind>,
+    /// `wss://` endpoint for the WebSocket transport.
+    pub websocket_url: Option<String>,
+    /// `https://` endpoint for the BOSH transport.
+    pub bosh_url: Option<String>,
+}
+
+/// How the client reaches the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    /// TCP upgraded with STARTTLS (RFC 6120).
+    Tcp,
+    /// TLS from the first byte (XEP-0368).
+    Tls,
+    /// XMPP over WebSocket (RFC 7395).
+    WebSocket,
+    /// XMPP over BOSH long-polling (XEP-0124, XEP-0206).
+    Bosh,
+}
+
+impl TransportKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransportKind::Tcp => "tcp",
+            TransportKind::Tls => "tls",
+            TransportKind::WebSocket => "websocket",
+            TransportKind::Bosh => "bosh",
+        }
+    }
+}
+
+impl std::fmt::Display for TransportKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -88,6 +142,30 @@ pub struct PluginsConfig {
     #[serde(default = "default_true")]
     pub enabled: bool,
     pub directory: Option<String>,
+    /// How plugin artifact signatures are checked on install: `enforce`,
+    /// `warn` or `disabled`.
+    #[serde(default = "default_signature_policy")]
+    pub signature_policy: String,
+    /// PEM public keys trusted to sign plugin artifacts.
+    #[serde(default)]
+    pub trusted_keys: Vec<String>,
+    /// How often per-plugin resource usage is published; zero never does.
+    #[serde(default = "default_usage_interval_secs")]
+    pub usage_interval_secs: u64,
+    /// Sandbox limits for individual plugins keyed by plugin ID. Fields left
+    /// out keep the runtime's defaults.
+    #[serde(default)]
+    pub limits: HashMap<String, PluginLimitsConfig>,
+    /// Failures within `error_window_secs` after which a plugin stops
+    /// receiving hooks until it is re-enabled.
+    #[serde(default = "default_plugin_error_threshold")]
+    pub error_threshold: u32,
+    #[serde(default = "default_plugin_error_window_secs")]
+    pub error_window_secs: u64,
+    /// Sign-in details for private plugin registries, keyed by registry
+    /// host such as `ghcr.io`.
+    #[serde(default)]
+    pub registries: HashMap<String, RegistryAuthConfig>,
 }
 
 impl Default for PluginsConfig {
@@ -95,20 +173,55 @@ impl Default for PluginsConfig {
         Self {
             enabled: true,
             directory: None,
+            signature_policy: default_signature_policy(),
+            trusted_keys: Vec::new(),
+            usage_interval_secs: default_usage_interval_secs(),
+            limits: HashMap::new(),
+            error_threshold: default_plugin_error_threshold(),
+            error_window_secs: default_plugin_error_window_secs(),
+            registries: HashMap::new(),
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct PluginLimitsConfig {
+    pub fuel_per_invocation: Option<u64>,
+    pub fuel_per_render: Option<u64>,
+    pub max_memory_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "auth", rename_all = "snake_case")]
+pub enum RegistryAuthConfig {
+    Basic {
+        username: String,
+        password: String,
+    },
+    Token {
+        token: String,
+    },
+    /// Whatever `docker login` stored, including through a credential helper.
+    Docker,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct LoggingConfig {
     #[serde(default = "default_log_level")]
     pub level: String,
+    /// Where log files go. Defaults to `logs` in the platform data directory.
+    pub directory: Option<String>,
+    /// Daily log files to keep before the oldest is deleted.
+    #[serde(default = "default_log_max_files")]
+    pub max_files: usize,
 }
 
 impl Default for LoggingConfig {
     fn default() -> Self {
         Self {
             level: "info".to_string(),
+            directory: None,
+            max_files: default_log_max_files(),
         }
     }
 }
@@ -117,12 +230,259 @@ impl Default for LoggingConfig {
 pub struct EventBusConfig {
     #[serde(default = "default_channel_capacity")]
     pub channel_capacity: usize,
+    #[serde(default)]
+    pub bridge: BridgeConfig,
 }
 
 impl Default for EventBusConfig {
     fn default() -> Self {
         Self {
             channel_capacity: 1024,
+            bridge: BridgeConfig::default(),
+        }
+    }
+}
+
+/// Serves the event bus on a local socket, so frontends and tools running
+/// in another process can subscribe and publish.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BridgeConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Unix socket path, or named pipe name on Windows. Defaults to
+    /// `bridge.sock` next to the database.
+    pub path: Option<String>,
+    /// Channel patterns clients may receive events from.
+    #[serde(default = "default_bridge_subscribe")]
+    pub subscribe: Vec<String>,
+    /// Channel patterns clients may publish to.
+    #[serde(default = "default_bridge_publish")]
+    pub publish: Vec<String>,
+    /// Loopback address such as `127.0.0.1:7878` to also accept WebSocket
+    /// clients on, for the web build running against this process. Clients
+    /// must present the token written to `bridge.token` beside the socket.
+    pub websocket: Option<String>,
+    /// Let `websocket` be an address other machines can reach. Such an
+    /// address must be served over TLS.
+    #[serde(default)]
+    pub websocket_allow_remote: bool,
+    /// PEM certificate chain to serve `websocket` over TLS (`wss://`) with.
+    pub websocket_tls_cert: Option<String>,
+    /// PEM private key for `websocket_tls_cert`.
+    pub websocket_tls_key: Option<String>,
+    /// `Origin` headers WebSocket clients may connect from. Clients that
+    /// send none, such as command-line tools, only need the token.
+    #[serde(default)]
+    pub origins: Vec<String>,
+    /// Recent events kept so reconnecting clients can catch up.
+    #[serde(default = "default_bridge_replay")]
+    pub replay: usize,
+}
+
+impl Default for BridgeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            subscribe: default_bridge_subscribe(),
+            publish: default_bridge_publish(),
+            websocket: None,
+            websocket_allow_remote: false,
+            websocket_tls_cert: None,
+            websocket_tls_key: None,
+            origins: Vec::new(),
+            replay: default_bridge_replay(),
+        }
+    }
+}
+
+/// When crashed manager tasks are restarted.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SupervisorConfig {
+    /// Restarts allowed within `restart_window_secs` before a manager is
+    /// left stopped.
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: u32,
+    /// A manager that stays up this long before failing starts over with a
+    /// clean record.
+    #[serde(default = "default_restart_window_secs")]
+    pub restart_window_secs: u64,
+    /// Delay before the first restart, doubled for each further restart.
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            max_restarts: default_max_restarts(),
+            restart_window_secs: default_restart_window_secs(),
+            initial_backoff_ms: default_initial_backoff_ms(),
+            max_backoff_secs: default_max_backoff_secs(),
+        }
+    }
+}
+
+/// Delivery receipts (XEP-0184) and room mention detection.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MessagingConfig {
+    /// Ask recipients to acknowledge each outgoing message.
+    #[serde(default = "default_true")]
+    pub request_receipts: bool,
+    /// Acknowledge incoming messages that ask for a receipt.
+    #[serde(default = "default_true")]
+    pub send_receipts: bool,
+    /// Words that count as a mention in rooms, besides the user's nick.
+    #[serde(default)]
+    pub mention_keywords: Vec<String>,
+    #[serde(default)]
+    pub link_previews: LinkPreviewConfig,
+    #[serde(default)]
+    pub translation: TranslationConfig,
+}
+
+impl Default for MessagingConfig {
+    fn default() -> Self {
+        Self {
+            request_receipts: true,
+            send_receipts: true,
+            mention_keywords: Vec::new(),
+            link_previews: LinkPreviewConfig::default(),
+            translation: TranslationConfig::default(),
+        }
+    }
+}
+
+/// Translating received messages written in another language than the
+/// user's, to show beside the original.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TranslationConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Language tag to translate into; the UI locale when unset.
+    pub target_language: Option<String>,
+}
+
+impl TranslationConfig {
+    /// The language to translate into: `target_language`, else `ui_locale`,
+    /// else the system's, else English.
+    pub fn resolve_target_language(&self, ui_locale: Option<&str>) -> String {
+        self.target_language
+            .clone()
+            .or_else(|| ui_locale.map(str::to_string))
+            .or_else(sys_locale::get_locale)
+            .unwrap_or_else(|| "en".to_string())
+    }
+}
+
+/// Cards for the links in message bodies, built from the metadata each page
+/// declares. Fetching a page tells its server someone read the link, so
+/// this is off unless the user turns it on.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LinkPreviewConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Only fetch previews for links in messages the user sends. Previews
+    /// fetched earlier are still shown.
+    #[serde(default)]
+    pub privacy_mode: bool,
+    /// Also fetch previews for links from senders outside the roster,
+    /// including room occupants.
+    #[serde(default)]
+    pub fetch_from_strangers: bool,
+    /// When not empty, only these domains (and their subdomains) are fetched.
+    #[serde(default)]
+    pub allow_domains: Vec<String>,
+    /// Never fetched, even when also allowed.
+    #[serde(default)]
+    pub deny_domains: Vec<String>,
+}
+
+/// How inbound presence subscription requests are answered.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RosterConfig {
+    /// Subscribe back to a contact when approving their request.
+    #[serde(default = "default_true")]
+    pub mutual_subscription: bool,
+    /// Approve requests from these domains without asking.
+    #[serde(default)]
+    pub auto_accept_domains: Vec<String>,
+    /// Approve requests from contacts the user is already subscribed to.
+    #[serde(default = "default_true")]
+    pub auto_accept_subscribed: bool,
+}
+
+impl Default for RosterConfig {
+    fn default() -> Self {
+        Self {
+            mutual_subscription: true,
+            auto_accept_domains: Vec::new(),
+            auto_accept_subscribed: true,
+        }
+    }
+}
+
+/// Automatic away based on how long the system has seen no input.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PresenceConfig {
+    #[serde(default = "default_true")]
+    pub auto_away: bool,
+    /// Minutes without input before showing as away.
+    #[serde(default = "default_away_after_mins")]
+    pub away_after_mins: u64,
+    /// Minutes without input before showing as extended away; zero stays
+    /// at away.
+    #[serde(default = "default_xa_after_mins")]
+    pub xa_after_mins: u64,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        Self {
+            auto_away: true,
+            away_after_mins: default_away_after_mins(),
+            xa_after_mins: default_xa_after_mins(),
+        }
+    }
+}
+
+/// The headless `waddled` session.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DaemonConfig {
+    /// Unix socket taking control commands. Defaults to `waddled.sock`
+    /// next to the database.
+    pub control_socket: Option<String>,
+}
+
+/// The raw XML stream capture behind the protocol debugging view.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DebugConfig {
+    /// Start capturing at launch rather than when the view turns it on.
+    #[serde(default)]
+    pub capture_stanzas: bool,
+    /// Stanzas kept in memory, oldest dropped first.
+    #[serde(default = "default_stanza_buffer_size")]
+    pub buffer_size: usize,
+    /// Also append captured stanzas to this file.
+    pub capture_file: Option<String>,
+    /// Hide SASL exchanges and passwords.
+    #[serde(default = "default_true")]
+    pub redact_auth: bool,
+    /// Hide message bodies.
+    #[serde(default = "default_true")]
+    pub redact_bodies: bool,
+}
+
+impl Default for DebugConfig {
+    fn default() -> Self {
+        Self {
+            capture_stanzas: false,
+            buffer_size: default_stanza_buffer_size(),
+            capture_file: None,
+            redact_auth: true,
+            redact_bodies: true,
         }
     }
 }
@@ -130,6 +490,138 @@ impl Default for EventBusConfig {
 #[derive(Debug, Clone, Default, Deserialize)]
 pub struct StorageConfig {
     pub path: Option<String>,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub backup: BackupConfig,
+    #[serde(default)]
+    pub maintenance: MaintenanceConfig,
+    #[serde(default)]
+    pub downloads: DownloadConfig,
+}
+
+/// Limits on how much message history is kept locally. Messages are only
+/// pruned from the local store; server archives are left untouched.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RetentionConfig {
+    /// Delete messages older than this many days.
+    pub max_age_days: Option<u32>,
+    /// Keep at most this many messages per conversation.
+    pub max_count: Option<u32>,
+    #[serde(default = "default_prune_interval_secs")]
+    pub prune_interval_secs: u64,
+    /// Per-conversation policies keyed by bare JID. An entry replaces the
+    /// global limits for that conversation; an empty entry keeps everything.
+    #[serde(default)]
+    pub conversations: HashMap<String, RetentionPolicy>,
+}
+
+impl RetentionConfig {
+    /// The policy that applies to the conversation with `jid`.
+    pub fn policy_for(&self, jid: &str) -> RetentionPolicy {
+        self.conversations
+            .get(jid)
+            .copied()
+            .unwrap_or(RetentionPolicy {
+                max_age_days: self.max_age_days,
+                max_count: self.max_count,
+            })
+    }
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            max_age_days: None,
+            max_count: None,
+            prune_interval_secs: default_prune_interval_secs(),
+            conversations: HashMap::new(),
+        }
+    }
+}
+
+/// Periodic snapshots of the database file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BackupConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Defaults to a `backups` directory next to the database.
+    pub directory: Option<String>,
+    #[serde(default = "default_backup_interval_secs")]
+    pub interval_secs: u64,
+    /// Number of snapshots kept; older ones are deleted after each backup.
+    #[serde(default = "default_backup_keep")]
+    pub keep: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: None,
+            interval_secs: default_backup_interval_secs(),
+            keep: default_backup_keep(),
+        }
+    }
+}
+
+/// Integrity checks, vacuuming and WAL checkpoints, run while the app is
+/// inactive.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MaintenanceConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Least time between two runs.
+    #[serde(default = "default_maintenance_interval_secs")]
+    pub interval_secs: u64,
+    /// How long the app must stay inactive before a due run starts.
+    #[serde(default = "default_maintenance_idle_delay_secs")]
+    pub idle_delay_secs: u64,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: default_maintenance_interval_secs(),
+            idle_delay_secs: default_maintenance_idle_delay_secs(),
+        }
+    }
+}
+
+/// Cache of files downloaded from links shared in messages (XEP-0363).
+#[derive(Debug, Clone, Deserialize)]
+pub struct DownloadConfig {
+    /// Defaults to a `downloads` directory next to the database.
+    pub directory: Option<String>,
+    /// Least recently used files are deleted once the cache grows past this.
+    #[serde(default = "default_download_cache_mb")]
+    pub max_cache_mb: u64,
+    /// Download shared files as soon as the message arrives.
+    #[serde(default = "default_true")]
+    pub auto_download: bool,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            max_cache_mb: default_download_cache_mb(),
+            auto_download: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct RetentionPolicy {
+    pub max_age_days: Option<u32>,
+    pub max_count: Option<u32>,
+}
+
+impl RetentionPolicy {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_age_days.is_none() && self.max_count.is_none()
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -148,42 +640,219 @@ fn default_theme_name() -> String {
     "default".to_string()
 }
 
+fn default_signature_policy() -> String {
+    "warn".to_string()
+}
+
+fn default_usage_interval_secs() -> u64 {
+    60
+}
+
+fn default_plugin_error_threshold() -> u32 {
+    5
+}
+
+fn default_plugin_error_window_secs() -> u64 {
+    60
+}
+
 fn default_log_level() -> String {
     "info".to_string()
 }
 
+fn default_log_max_files() -> usize {
+    7
+}
+
+fn default_stanza_buffer_size() -> usize {
+    1000
+}
+
 fn default_channel_capacity() -> usize {
     1024
 }
 
+fn default_bridge_subscribe() -> Vec<String> {
+    vec![
+        "system.**".to_string(),
+        "xmpp.**".to_string(),
+        "ui.**".to_string(),
+    ]
+}
+
+fn default_bridge_publish() -> Vec<String> {
+    vec!["ui.**".to_string()]
+}
+
+fn default_bridge_replay() -> usize {
+    1024
+}
+
+fn default_prune_interval_secs() -> u64 {
+    3600
+}
+
+fn default_backup_interval_secs() -> u64 {
+    86_400
+}
+
+fn default_backup_keep() -> usize {
+    7
+}
+
+fn default_maintenance_interval_secs() -> u64 {
+    86_400
+}
+
+fn default_maintenance_idle_delay_secs() -> u64 {
+    60
+}
+
+fn default_download_cache_mb() -> u64 {
+    1024
+}
+
+fn default_max_restarts() -> u32 {
+    5
+}
+
+fn default_restart_window_secs() -> u64 {
+    300
+}
+
+fn default_initial_backoff_ms() -> u64 {
+    500
+}
+
+fn default_max_backoff_secs() -> u64 {
+    30
+}
+
+fn default_away_after_mins() -> u64 {
+    5
+}
+
+fn default_xa_after_mins() -> u64 {
+    30
+}
+
 const VALID_LOG_LEVELS: &[&str] = &["trace", "debug", "info", "warn", "error"];
 
 const DEFAULT_CONFIG_TOML: &str = r#"[account]
 jid = ""
-password = ""
+# Leave unset to keep the password in the OS keyring instead of this file.
+# password = ""
 # server = "xmpp.example.com"
 # port = 5222
+# Tried in order until one connects: "tcp" (STARTTLS), "tls" (direct TLS),
+# "websocket", "bosh".
+# transports = ["tcp", "tls", "websocket", "bosh"]
+# websocket_url = "wss://xmpp.example.com/xmpp-websocket"
+# bosh_url = "https://xmpp.example.com/http-bind"
 
 [ui]
 notifications = true
 theme = "default"
 
 [theme]
+# Built in: "default" (light), "dark" and "high-contrast". Themes in the
+# data directory's themes/ folder are available by their [meta] name. A
+# theme chosen at runtime is remembered and takes precedence over this.
 name = "default"
 # custom_path = "/path/to/custom/theme.toml"
 
 [plugins]
 enabled = true
 # directory = "~/.local/share/waddle/plugins"
+# signature_policy = "warn"
+# trusted_keys = []
+# usage_interval_secs = 60
+# limits = { "com.example.plugin" = { fuel_per_invocation = 5000000 } }
+# error_threshold = 5
+# error_window_secs = 60
+# registries = { "harbor.example.com" = { auth = "basic", username = "robot", password = "" } }
+# registries = { "ghcr.io" = { auth = "token", token = "" } }
+# registries = { "registry.example.com" = { auth = "docker" } }
 
 [logging]
 level = "info"
+# directory = "~/.local/share/waddle/logs"
+# max_files = 7
 
 [event_bus]
 channel_capacity = 1024
 
+[event_bus.bridge]
+enabled = false
+# path = "~/.local/share/waddle/bridge.sock"
+# subscribe = ["system.**", "xmpp.**", "ui.**"]
+# publish = ["ui.**"]
+# websocket = "127.0.0.1:7878"
+# websocket_allow_remote = false
+# websocket_tls_cert = "~/.config/waddle/bridge.crt"
+# websocket_tls_key = "~/.config/waddle/bridge.key"
+# origins = ["http://localhost:8080"]
+# replay = 1024
+
 [storage]
 # path = "~/.local/share/waddle/waddle.db"
+
+[storage.retention]
+# max_age_days = 365
+# max_count = 10000
+# prune_interval_secs = 3600
+# conversations = { "friend@example.com" = { max_age_days = 30 } }
+
+[storage.backup]
+enabled = false
+# directory = "~/.local/share/waddle/backups"
+# interval_secs = 86400
+# keep = 7
+
+[storage.maintenance]
+# enabled = true
+# interval_secs = 86400
+# idle_delay_secs = 60
+
+[storage.downloads]
+# directory = "~/.local/share/waddle/downloads"
+# max_cache_mb = 1024
+# auto_download = true
+
+[supervisor]
+# max_restarts = 5
+# restart_window_secs = 300
+# initial_backoff_ms = 500
+# max_backoff_secs = 30
+
+[messaging]
+# request_receipts = true
+# send_receipts = true
+# mention_keywords = ["waddle"]
+
+# [messaging.translation]
+# enabled = false
+# target_language = "en"
+
+[roster]
+# mutual_subscription = true
+# auto_accept_domains = ["example.com"]
+# auto_accept_subscribed = true
+
+[presence]
+# auto_away = true
+# away_after_mins = 5
+# xa_after_mins = 30
+
+[debug]
+# capture_stanzas = false
+# buffer_size = 1000
+# capture_file = "~/waddle-stanzas.xml"
+# redact_auth = true
+# redact_bodies = true
+
+[daemon]
+# control_socket = "~/.local/share/waddle/waddled.sock"
 "#;
 
 /// Return the resolved platform-appropriate configuration file path.
@@ -196,6 +865,17 @@ pub fn config_path() -> PathBuf {
     }
 }
 
+/// Return the platform-appropriate directory for data such as the database
+/// and user themes.
+#[cfg(feature = "native")]
+pub fn data_dir() -> PathBuf {
+    if let Some(proj_dirs) = directories::ProjectDirs::from("com", "waddle", "waddle") {
+        proj_dirs.data_dir().to_path_buf()
+    } else {
+        PathBuf::from(".")
+    }
+}
+
 /// Load configuration from the platform config path, merging environment
 /// variable overrides. Returns a validated Config or a descriptive error.
 #[cfg(feature = "native")]
@@ -222,7 +902,7 @@ fn load_config_from_with_overrides(
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
             create_default_config(&path)?;
             return Err(ConfigError::MissingRequiredFields {
-                fields: vec!["account.jid".to_string(), "account.password".to_string()],
+                fields: vec!["account.jid".to_string()],
             });
         }
         Err(e) => return Err(ConfigError::Io(e)),
@@ -287,9 +967,6 @@ fn validate(config: &Config) -> Result<(), ConfigError> {
     if config.account.jid.is_empty() {
         missing.push("account.jid".to_string());
     }
-    if config.account.password.is_empty() {
-        missing.push("account.password".to_string());
-    }
 
     if !missing.is_empty() {
         return Err(ConfigError::MissingRequiredFields { fields: missing });
@@ -302,6 +979,142 @@ fn validate(config: &Config) -> Result<(), ConfigError> {
         });
     }
 
+    if config.logging.max_files == 0 {
+        return Err(ConfigError::InvalidValue {
+            field: "logging.max_files".to_string(),
+            message: "must be greater than zero".to_string(),
+        });
+    }
+
+    if config.debug.buffer_size == 0 {
+        return Err(ConfigError::InvalidValue {
+            field: "debug.buffer_size".to_string(),
+            message: "must be greater than zero".to_string(),
+        });
+    }
+
+    if let Some(url) = &config.account.websocket_url
+        && !(url.starts_with("wss://") || url.starts_with("ws://"))
+    {
+        return Err(ConfigError::InvalidValue {
+            field: "account.websocket_url".to_string(),
+            message: "must be a ws:// or wss:// URL".to_string(),
+        });
+    }
+
+    if let Some(url) = &config.account.bosh_url
+        && !(url.starts_with("https://") || url.starts_with("http://"))
+    {
+        return Err(ConfigError::InvalidValue {
+            field: "account.bosh_url".to_string(),
+            message: "must be an http:// or https:// URL".to_string(),
+        });
+    }
+
+    let plugins = &config.plugins;
+    if plugins.error_threshold == 0 || plugins.error_window_secs == 0 {
+        return Err(ConfigError::InvalidValue {
+            field: "plugins".to_string(),
+            message: "error_threshold and error_window_secs must be greater than zero".to_string(),
+        });
+    }
+
+    if config.storage.retention.prune_interval_secs == 0 {
+        return Err(ConfigError::InvalidValue {
+            field: "storage.retention.prune_interval_secs".to_string(),
+            message: "must be greater than zero".to_string(),
+        });
+    }
+
+    let backup = &config.storage.backup;
+    if backup.enabled && (backup.interval_secs == 0 || backup.keep == 0) {
+        return Err(ConfigError::InvalidValue {
+            field: "storage.backup".to_string(),
+            message: "interval_secs and keep must be greater than zero".to_string(),
+        });
+    }
+
+    let maintenance = &config.storage.maintenance;
+    if maintenance.enabled && maintenance.interval_secs == 0 {
+        return Err(ConfigError::InvalidValue {
+            field: "storage.maintenance.interval_secs".to_string(),
+            message: "must be greater than zero".to_string(),
+        });
+    }
+
+    let bridge = &config.event_bus.bridge;
+    if let Some(pattern) = bridge
+        .subscribe
+        .iter()
+        .chain(&bridge.publish)
+        .find(|pattern| globset::Glob::new(pattern).is_err())
+    {
+        return Err(ConfigError::InvalidValue {
+            field: "event_bus.bridge".to_string(),
+            message: format!("invalid channel pattern: {pattern}"),
+        });
+    }
+    if let Some(address) = bridge.websocket.as_deref() {
+        match address.parse::<std::net::SocketAddr>() {
+            Err(_) => {
+                return Err(ConfigError::InvalidValue {
+                    field: "event_bus.bridge.websocket".to_string(),
+                    message: format!("not a socket address: {address}"),
+                });
+            }
+            Ok(parsed) if !parsed.ip().is_loopback() && !bridge.websocket_allow_remote => {
+                return Err(ConfigError::InvalidValue {
+                    field: "event_bus.bridge.websocket".to_string(),
+                    message: format!(
+                        "{address} is reachable from other machines; \
+                         set websocket_allow_remote to allow it"
+                    ),
+                });
+            }
+            // The token would cross the network in the clear.
+            Ok(parsed) if !parsed.ip().is_loopback() && bridge.websocket_tls_cert.is_none() => {
+                return Err(ConfigError::InvalidValue {
+                    field: "event_bus.bridge.websocket".to_string(),
+                    message: format!(
+                        "{address} is reachable from other machines; \
+                         set websocket_tls_cert and websocket_tls_key to serve it over TLS"
+                    ),
+                });
+            }
+            Ok(_) => {}
+        }
+    }
+    if bridge.websocket_tls_cert.is_some() != bridge.websocket_tls_key.is_some() {
+        return Err(ConfigError::InvalidValue {
+            field: "event_bus.bridge".to_string(),
+            message: "websocket_tls_cert and websocket_tls_key must be set together".to_string(),
+        });
+    }
+
+    if config.supervisor.restart_window_secs == 0 {
+        return Err(ConfigError::InvalidValue {
+            field: "supervisor.restart_window_secs".to_string(),
+            message: "must be greater than zero".to_string(),
+        });
+    }
+
+    let presence = &config.presence;
+    if presence.auto_away && presence.away_after_mins == 0 {
+        return Err(ConfigError::InvalidValue {
+            field: "presence.away_after_mins".to_string(),
+            message: "must be greater than zero".to_string(),
+        });
+    }
+    if presence.auto_away
+        && presence.xa_after_mins != 0
+        && presence.xa_after_mins <= presence.away_after_mins
+    {
+        return Err(ConfigError::InvalidValue {
+            field: "presence.xa_after_mins".to_string(),
+            message: "must be greater than away_after_mins, or zero".to_string(),
+        });
+    }
+
     Ok(())
 }
 
@@ -370,6 +1183,8 @@ password = "secret"
         assert!(config.theme.custom_path.is_none());
         assert!(config.plugins.enabled);
         assert!(config.plugins.directory.is_none());
+        assert_eq!(config.plugins.signature_policy, "warn");
+        assert!(config.plugins.trusted_keys.is_empty());
         assert_eq!(config.logging.level, "info");
         assert_eq!(config.event_bus.channel_capacity, 1024);
         assert!(config.storage.path.is_none());
@@ -402,6 +1217,64 @@ port = 5222
         assert_eq!(config.account.port, Some(5222));
     }
 
+    #[test]
+    fn parses_transport_order_and_websocket_url() {
+        let toml = r#"
+[account]
+jid = "user@example.com"
+transports = ["websocket", "tls", "bosh"]
+websocket_url = "wss://xmpp.example.com/ws"
+bosh_url = "https://xmpp.example.com/http-bind"
+"#;
+        let config = parse_without_env(toml).unwrap();
+        assert_eq!(
+            config.account.transports,
+            vec![
+                TransportKind::WebSocket,
+                TransportKind::Tls,
+                TransportKind::Bosh
+            ]
+        );
+        assert_eq!(
+            config.account.bosh_url.as_deref(),
+            Some("https://xmpp.example.com/http-bind")
+        );
+        assert_eq!(
+            config.account.websocket_url.as_deref(),
+            Some("wss://xmpp.example.com/ws")
+        );
+        assert!(
+            parse_without_env(minimal_toml())
+                .unwrap()
+                .account
+                .transports
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_transport_and_non_websocket_url() {
+        let unknown = r#"
+[account]
+jid = "user@example.com"
+transports = ["carrier-pigeon"]
+"#;
+        assert!(matches!(
+            parse_without_env(unknown),
+            Err(ConfigError::InvalidToml { .. })
+        ));
+
+        let https = r#"
+[account]
+jid = "user@example.com"
+websocket_url = "https://xmpp.example.com/ws"
+"#;
+        match parse_without_env(https).unwrap_err() {
+            ConfigError::InvalidValue { field, .. } => assert_eq!(field, "account.websocket_url"),
+            other => panic!("expected InvalidValue, got {other:?}"),
+        }
+    }
+
     #[test]
     fn parses_custom_theme_path() {
         let toml = r#"
@@ -431,6 +1304,18 @@ password = "secret"
 [plugins]
 enabled = false
 directory = "/opt/waddle/plugins"
+signature_policy = "enforce"
+trusted_keys = ["-----BEGIN PUBLIC KEY-----"]
+usage_interval_secs = 0
+error_threshold = 3
+
+[plugins.limits."com.example.heavy"]
+fuel_per_invocation = 5000000
+max_memory_bytes = 33554432
+
+[plugins.registries]
+"ghcr.io" = { auth = "token", token = "ghp_secret" }
+"harbor.example.com" = { auth = "docker" }
 "#;
         let config = parse_without_env(toml).unwrap();
         assert!(!config.plugins.enabled);
@@ -438,6 +1323,29 @@ directory = "/opt/waddle/plugins"
             config.plugins.directory.as_deref(),
             Some("/opt/waddle/plugins")
         );
+        assert_eq!(config.plugins.signature_policy, "enforce");
+        assert_eq!(config.plugins.trusted_keys.len(), 1);
+        assert_eq!(config.plugins.usage_interval_secs, 0);
+        assert_eq!(config.plugins.error_threshold, 3);
+        assert_eq!(config.plugins.error_window_secs, 60);
+        assert_eq!(
+            config.plugins.limits["com.example.heavy"],
+            PluginLimitsConfig {
+                fuel_per_invocation: Some(5_000_000),
+                fuel_per_render: None,
+                max_memory_bytes: Some(33_554_432),
+            }
+        );
+        assert_eq!(
+            config.plugins.registries["ghcr.io"],
+            RegistryAuthConfig::Token {
+                token: "ghp_secret".to_string()
+            }
+        );
+        assert_eq!(
+            config.plugins.registries["harbor.example.com"],
+            RegistryAuthConfig::Docker
+        );
     }
 
     #[test]
@@ -454,6 +1362,184 @@ path = "/data/waddle.db"
         assert_eq!(config.storage.path.as_deref(), Some("/data/waddle.db"));
     }
 
+    #[test]
+    fn parses_retention_with_per_conversation_overrides() {
+        let toml = r#"
+[account]
+jid = "user@example.com"
+
+[storage.retention]
+max_age_days = 90
+max_count = 500
+
+[storage.retention.conversations."friend@example.com"]
+max_count = 50
+"#;
+        let retention = parse_without_env(toml).unwrap().storage.retention;
+        assert_eq!(retention.prune_interval_secs, 3600);
+        assert_eq!(
+            retention.policy_for("other@example.com"),
+            RetentionPolicy {
+                max_age_days: Some(90),
+                max_count: Some(500),
+            }
+        );
+        assert_eq!(
+            retention.policy_for("friend@example.com"),
+            RetentionPolicy {
+                max_age_days: None,
+                max_count: Some(50),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_backup_settings() {
+        let toml = r#"
+[account]
+jid = "user@example.com"
+
+[storage.backup]
+enabled = true
+directory = "/data/backups"
+keep = 3
+"#;
+        let backup = parse_without_env(toml).unwrap().storage.backup;
+        assert!(backup.enabled);
+        assert_eq!(backup.directory.as_deref(), Some("/data/backups"));
+        assert_eq!(backup.interval_secs, 86_400);
+        assert_eq!(backup.keep, 3);
+        assert!(
+            !parse_without_env(valid_toml())
+                .unwrap()
+                .storage
+                .backup
+                .enabled
+        );
+    }
+
+    #[test]
+    fn parses_download_settings() {
+        let toml = r#"
+[account]
+jid = "user@example.com"
+
+[storage.downloads]
+max_cache_mb = 64
+auto_download = false
+"#;
+        let downloads = parse_without_env(toml).unwrap().storage.downloads;
+        assert_eq!(downloads.max_cache_mb, 64);
+        assert!(!downloads.auto_download);
+        assert!(downloads.directory.is_none());
+        let defaults = parse_without_env(valid_toml()).unwrap().storage.downloads;
+        assert_eq!(defaults.max_cache_mb, 1024);
+        assert!(defaults.auto_download);
+    }
+
+    #[test]
+    fn parses_supervisor_settings() {
+        let toml = r#"
+[account]
+jid = "user@example.com"
+
+[supervisor]
+max_restarts = 2
+initial_backoff_ms = 100
+"#;
+        let supervisor = parse_without_env(toml).unwrap().supervisor;
+        assert_eq!(supervisor.max_restarts, 2);
+        assert_eq!(supervisor.restart_window_secs, 300);
+        assert_eq!(supervisor.initial_backoff_ms, 100);
+        assert_eq!(supervisor.max_backoff_secs, 30);
+    }
+
+    #[test]
+    fn receipts_are_on_unless_disabled() {
+        let toml = r#"
+[account]
+jid = "user@example.com"
+
+[messaging]
+send_receipts = false
+"#;
+        let messaging = parse_without_env(toml).unwrap().messaging;
+        assert!(messaging.request_receipts);
+        assert!(!messaging.send_receipts);
+        assert!(messaging.mention_keywords.is_empty());
+    }
+
+    #[test]
+    fn auto_away_thresholds_are_validated() {
+        let config = |presence: &str| {
+            parse_without_env(&format!(
+                "[account]\njid = \"user@example.com\"\n\n[presence]\n{presence}\n"
+            ))
+        };
+
+        let defaults = config("").unwrap().presence;
+        assert!(defaults.auto_away);
+        assert_eq!(defaults.away_after_mins, 5);
+        assert_eq!(defaults.xa_after_mins, 30);
+
+        assert!(matches!(
+            config("away_after_mins = 10\nxa_after_mins = 10"),
+            Err(ConfigError::InvalidValue { ref field, .. }) if field == "presence.xa_after_mins"
+        ));
+        assert!(config("away_after_mins = 10\nxa_after_mins = 0").is_ok());
+        assert!(config("auto_away = false\naway_after_mins = 0").is_ok());
+    }
+
+    #[test]
+    fn rejects_zero_log_files() {
+        let toml = r#"
+[account]
+jid = "user@example.com"
+
+[logging]
+max_files = 0
+"#;
+        assert!(matches!(
+            parse_without_env(toml),
+            Err(ConfigError::InvalidValue { ref field, .. }) if field == "logging.max_files"
+        ));
+    }
+
+    #[test]
+    fn stanza_capture_is_off_and_redacted_by_default() {
+        let debug = parse_without_env(minimal_toml()).unwrap().debug;
+        assert!(!debug.capture_stanzas);
+        assert!(debug.redact_auth);
+        assert!(debug.redact_bodies);
+
+        let toml = r#"
+[account]
+jid = "user@example.com"
+
+[debug]
+buffer_size = 0
+"#;
+        assert!(matches!(
+            parse_without_env(toml),
+            Err(ConfigError::InvalidValue { ref field, .. }) if field == "debug.buffer_size"
+        ));
+    }
+
+    #[test]
+    fn rejects_zero_prune_interval() {
+        let toml = r#"
+[account]
+jid = "user@example.com"
+
+[storage.retention]
+prune_interval_secs = 0
+"#;
+        assert!(matches!(
+            parse_without_env(toml),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+    }
+
     // ── Validation ────────────────────────────────────────────────
 
     #[test]
@@ -474,24 +1560,17 @@ password = "secret"
     }
 
     #[test]
-    fn rejects_missing_password() {
+    fn accepts_missing_password() {
         let toml = r#"
 [account]
 jid = "user@example.com"
-password = ""
 "#;
-        let err = parse_without_env(toml).unwrap_err();
-        match err {
-            ConfigError::MissingRequiredFields { fields } => {
-                assert!(fields.contains(&"account.password".to_string()));
-                assert!(!fields.contains(&"account.jid".to_string()));
-            }
-            other => panic!("expected MissingRequiredFields, got: {other}"),
-        }
+        let config = parse_without_env(toml).unwrap();
+        assert!(config.account.password.is_empty());
     }
 
     #[test]
-    fn rejects_both_missing() {
+    fn rejects_missing_jid_without_password() {
         let toml = r#"
 [account]
 jid = ""
@@ -500,9 +1579,7 @@ password = ""
         let err = parse_without_env(toml).unwrap_err();
         match err {
             ConfigError::MissingRequiredFields { fields } => {
-                assert_eq!(fields.len(), 2);
-                assert!(fields.contains(&"account.jid".to_string()));
-                assert!(fields.contains(&"account.password".to_string()));
+                assert_eq!(fields, vec!["account.jid".to_string()]);
             }
             other => panic!("expected MissingRequiredFields, got: {other}"),
         }
@@ -628,6 +1705,94 @@ bad_line ===
         assert!(matches!(err, ConfigError::InvalidValue { .. }));
     }
 
+    #[test]
+    fn bridge_is_off_by_default_and_checks_its_patterns() {
+        let config = load_config_from_str(minimal_toml()).unwrap();
+        assert!(!config.event_bus.bridge.enabled);
+        assert_eq!(config.event_bus.bridge.publish, vec!["ui.**"]);
+
+        let toml = r#"
+[account]
+jid = "user@example.com"
+
+[event_bus.bridge]
+enabled = true
+publish = ["ui.{message,muc}.send", "ui.[roster"]
+"#;
+        let err = load_config_from_str(toml).unwrap_err();
+        assert!(
+            matches!(err, ConfigError::InvalidValue { ref field, .. } if field == "event_bus.bridge")
+        );
+
+        let toml = r#"
+[account]
+jid = "user@example.com"
+
+[event_bus.bridge]
+websocket = "localhost"
+"#;
+        let err = load_config_from_str(toml).unwrap_err();
+        assert!(matches!(
+            err,
+            ConfigError::InvalidValue { ref field, .. } if field == "event_bus.bridge.websocket"
+        ));
+
+        let toml = r#"
+[account]
+jid = "user@example.com"
+
+[event_bus.bridge]
+websocket = "0.0.0.0:7878"
+"#;
+        let err = load_config_from_str(toml).unwrap_err();
+        assert!(matches!(
+            err,
+            ConfigError::InvalidValue { ref field, .. } if field == "event_bus.bridge.websocket"
+        ));
+
+        let toml = r#"
+[account]
+jid = "user@example.com"
+
+[event_bus.bridge]
+websocket = "0.0.0.0:7878"
+websocket_allow_remote = true
+"#;
+        let err = load_config_from_str(toml).unwrap_err();
+        assert!(matches!(
+            err,
+            ConfigError::InvalidValue { ref field, ref message }
+                if field == "event_bus.bridge.websocket" && message.contains("TLS")
+        ));
+
+        let toml = r#"
+[account]
+jid = "user@example.com"
+
+[event_bus.bridge]
+websocket = "0.0.0.0:7878"
+websocket_allow_remote = true
+websocket_tls_cert = "bridge.crt"
+"#;
+        let err = load_config_from_str(toml).unwrap_err();
+        assert!(matches!(
+            err,
+            ConfigError::InvalidValue { ref field, .. } if field == "event_bus.bridge"
+        ));
+
+        let toml = r#"
+[account]
+jid = "user@example.com"
+
+[event_bus.bridge]
+websocket = "0.0.0.0:7878"
+websocket_allow_remote = true
+websocket_tls_cert = "bridge.crt"
+websocket_tls_key = "bridge.key"
+"#;
+        assert!(load_config_from_str(toml).is_ok());
+    }
+
     #[test]
     fn env_overrides_take_precedence() {
         let toml = r#"
@@ -677,8 +1842,7 @@ level = "warn"
             load_config_from_with_overrides(path.clone(), ConfigOverrides::default()).unwrap_err();
         match err {
             ConfigError::MissingRequiredFields { fields } => {
-                assert!(fields.contains(&"account.jid".to_string()));
-                assert!(fields.contains(&"account.password".to_string()));
+                assert_eq!(fields, vec!["account.jid".to_string()]);
             }
             other => panic!("expected MissingRequiredFields, got: {other}"),
         }
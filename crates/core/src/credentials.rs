@@ -0,0 +1,486 @@
+//! Account secrets kept out of the configuration file.
+//!
+//! The connection layer asks a [`CredentialStore`] for the password each time
+//! it connects instead of holding it for the lifetime of the process. Native
+//! builds prefer the OS keyring (Secret Service, Keychain, Credential Manager)
+//! and fall back to a passphrase-encrypted file when no keyring is reachable.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+#[cfg(feature = "native")]
+pub use native::{EncryptedFileCredentialStore, KEYRING_SERVICE, KeyringCredentialStore};
+
+#[derive(Debug, thiserror::Error)]
+pub enum CredentialError {
+    #[error("no credential stored for {account}")]
+    NotFound { account: String },
+
+    #[error("credential store unavailable: {0}")]
+    Unavailable(String),
+
+    #[error("credential file passphrase is incorrect")]
+    InvalidPassphrase,
+
+    #[error("credential file is corrupted: {0}")]
+    Corrupted(String),
+
+    #[error("I/O error accessing credential store: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Storage for per-account secrets, keyed by bare JID.
+///
+/// Calls may block: keyring backends talk to a system service, so async
+/// callers should run them on a blocking thread.
+pub trait CredentialStore: Send + Sync {
+    fn get(&self, account: &str) -> Result<String, CredentialError>;
+
+    fn set(&self, account: &str, secret: &str) -> Result<(), CredentialError>;
+
+    /// Remove the secret for `account`. Removing a missing entry is not an error.
+    fn delete(&self, account: &str) -> Result<(), CredentialError>;
+}
+
+/// Process-local store, used for passwords supplied through the config file or
+/// `WADDLE_PASSWORD`, and in tests.
+#[derive(Debug, Default)]
+pub struct InMemoryCredentialStore {
+    secrets: RwLock<HashMap<String, String>>,
+}
+
+impl InMemoryCredentialStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_secret(account: &str, secret: &str) -> Self {
+        let store = Self::new();
+        store
+            .secrets
+            .write()
+            .expect("credential lock poisoned")
+            .insert(account.to_string(), secret.to_string());
+        store
+    }
+}
+
+impl CredentialStore for InMemoryCredentialStore {
+    fn get(&self, account: &str) -> Result<String, CredentialError> {
+        self.secrets
+            .read()
+            .expect("credential lock poisoned")
+            .get(account)
+            .cloned()
+            .ok_or_else(|| CredentialError::NotFound {
+                account: account.to_string(),
+            })
+    }
+
+    fn set(&self, account: &str, secret: &str) -> Result<(), CredentialError> {
+        self.secrets
+            .write()
+            .expect("credential lock poisoned")
+            .insert(account.to_string(), secret.to_string());
+        Ok(())
+    }
+
+    fn delete(&self, account: &str) -> Result<(), CredentialError> {
+        self.secrets
+            .write()
+            .expect("credential lock poisoned")
+            .remove(account);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "native")]
+mod native {
+    use std::collections::BTreeMap;
+    use std::num::NonZeroU32;
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+
+    use ring::aead::{Aad, CHACHA20_POLY1305, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+    use ring::pbkdf2;
+    use ring::rand::{SecureRandom, SystemRandom};
+    use serde::{Deserialize, Serialize};
+
+    use super::{CredentialError, CredentialStore};
+
+    /// Service name under which account passwords are filed in the OS keyring.
+    pub const KEYRING_SERVICE: &str = "com.waddle.waddle";
+
+    /// Passwords stored in the platform keyring: Secret Service on Linux and
+    /// the BSDs, Keychain on macOS, Credential Manager on Windows.
+    #[derive(Debug, Clone)]
+    pub struct KeyringCredentialStore {
+        service: String,
+    }
+
+    impl KeyringCredentialStore {
+        pub fn new(service: &str) -> Self {
+            Self {
+                service: service.to_string(),
+            }
+        }
+
+        /// Whether a keyring backend answers on this machine. Headless Linux
+        /// sessions commonly have no Secret Service provider running.
+        pub fn is_available(&self) -> bool {
+            match self.entry("waddle-availability-probe") {
+                Ok(entry) => !matches!(
+                    entry.get_password(),
+                    Err(keyring::Error::NoStorageAccess(_) | keyring::Error::PlatformFailure(_))
+                ),
+                Err(_) => false,
+            }
+        }
+
+        fn entry(&self, account: &str) -> Result<keyring::Entry, CredentialError> {
+            keyring::Entry::new(&self.service, account)
+                .map_err(|error| map_keyring_error(error, account))
+        }
+    }
+
+    impl Default for KeyringCredentialStore {
+        fn default() -> Self {
+            Self::new(KEYRING_SERVICE)
+        }
+    }
+
+    impl CredentialStore for KeyringCredentialStore {
+        fn get(&self, account: &str) -> Result<String, CredentialError> {
+            self.entry(account)?
+                .get_password()
+                .map_err(|error| map_keyring_error(error, account))
+        }
+
+        fn set(&self, account: &str, secret: &str) -> Result<(), CredentialError> {
+            self.entry(account)?
+                .set_password(secret)
+                .map_err(|error| map_keyring_error(error, account))
+        }
+
+        fn delete(&self, account: &str) -> Result<(), CredentialError> {
+            match self.entry(account)?.delete_credential() {
+                Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+                Err(error) => Err(map_keyring_error(error, account)),
+            }
+        }
+    }
+
+    fn map_keyring_error(error: keyring::Error, account: &str) -> CredentialError {
+        match error {
+            keyring::Error::NoEntry => CredentialError::NotFound {
+                account: account.to_string(),
+            },
+            keyring::Error::BadEncoding(_) => {
+                CredentialError::Corrupted(format!("keyring entry for {account} is not UTF-8"))
+            }
+            other => CredentialError::Unavailable(other.to_string()),
+        }
+    }
+
+    const FILE_FORMAT_VERSION: u32 = 1;
+    const SALT_LEN: usize = 16;
+    const VERIFIER_AAD: &[u8] = b"waddle-credentials-verifier";
+
+    /// OWASP's 2023 recommendation for PBKDF2-HMAC-SHA256; tests use a cheap
+    /// count so they stay fast in debug builds.
+    const PBKDF2_ITERATIONS: u32 = if cfg!(test) { 1_000 } else { 600_000 };
+
+    #[derive(Serialize, Deserialize)]
+    struct CredentialFile {
+        version: u32,
+        salt: String,
+        iterations: u32,
+        verifier: SealedValue,
+        entries: BTreeMap<String, SealedValue>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct SealedValue {
+        nonce: String,
+        ciphertext: String,
+    }
+
+    /// Fallback for machines without a keyring: secrets sealed with
+    /// ChaCha20-Poly1305 under a key derived from a user passphrase.
+    ///
+    /// Each entry is bound to its account name as associated data, so entries
+    /// cannot be swapped between accounts by editing the file.
+    pub struct EncryptedFileCredentialStore {
+        path: PathBuf,
+        key: LessSafeKey,
+        rng: SystemRandom,
+        file: Mutex<CredentialFile>,
+    }
+
+    impl EncryptedFileCredentialStore {
+        /// Open the store at `path`, creating it if missing. Fails with
+        /// [`CredentialError::InvalidPassphrase`] if the file was created
+        /// with a different passphrase.
+        pub fn open(path: &Path, passphrase: &str) -> Result<Self, CredentialError> {
+            let rng = SystemRandom::new();
+
+            let (key, file) = match std::fs::read(path) {
+                Ok(contents) => {
+                    let file: CredentialFile = serde_json::from_slice(&contents)
+                        .map_err(|error| CredentialError::Corrupted(error.to_string()))?;
+                    if file.version != FILE_FORMAT_VERSION {
+                        return Err(CredentialError::Corrupted(format!(
+                            "unsupported credential file version {}",
+                            file.version
+                        )));
+                    }
+                    let salt = decode_hex(&file.salt)?;
+                    let key = derive_key(passphrase, &salt, file.iterations)?;
+                    open_sealed(&key, &file.verifier, VERIFIER_AAD)
+                        .map_err(|_| CredentialError::InvalidPassphrase)?;
+                    (key, file)
+                }
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                    let mut salt = [0u8; SALT_LEN];
+                    fill_random(&rng, &mut salt)?;
+                    let key = derive_key(passphrase, &salt, PBKDF2_ITERATIONS)?;
+                    let verifier = seal(&key, &rng, VERIFIER_AAD, VERIFIER_AAD)?;
+                    let file = CredentialFile {
+                        version: FILE_FORMAT_VERSION,
+                        salt: encode_hex(&salt),
+                        iterations: PBKDF2_ITERATIONS,
+                        verifier,
+                        entries: BTreeMap::new(),
+                    };
+                    write_file(path, &file)?;
+                    (key, file)
+                }
+                Err(error) => return Err(error.into()),
+            };
+
+            Ok(Self {
+                path: path.to_path_buf(),
+                key,
+                rng,
+                file: Mutex::new(file),
+            })
+        }
+    }
+
+    impl CredentialStore for EncryptedFileCredentialStore {
+        fn get(&self, account: &str) -> Result<String, CredentialError> {
+            let file = self.file.lock().expect("credential lock poisoned");
+            let sealed = file
+                .entries
+                .get(account)
+                .ok_or_else(|| CredentialError::NotFound {
+                    account: account.to_string(),
+                })?;
+            let plaintext = open_sealed(&self.key, sealed, account.as_bytes())?;
+            String::from_utf8(plaintext).map_err(|_| {
+                CredentialError::Corrupted(format!("entry for {account} is not UTF-8"))
+            })
+        }
+
+        fn set(&self, account: &str, secret: &str) -> Result<(), CredentialError> {
+            let sealed = seal(&self.key, &self.rng, account.as_bytes(), secret.as_bytes())?;
+            let mut file = self.file.lock().expect("credential lock poisoned");
+            file.entries.insert(account.to_string(), sealed);
+            write_file(&self.path, &file)
+        }
+
+        fn delete(&self, account: &str) -> Result<(), CredentialError> {
+            let mut file = self.file.lock().expect("credential lock poisoned");
+            if file.entries.remove(account).is_some() {
+                write_file(&self.path, &file)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn derive_key(
+        passphrase: &str,
+        salt: &[u8],
+        iterations: u32,
+    ) -> Result<LessSafeKey, CredentialError> {
+        let iterations = NonZeroU32::new(iterations)
+            .ok_or_else(|| CredentialError::Corrupted("iteration count is zero".to_string()))?;
+        let mut key_bytes = [0u8; 32];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            iterations,
+            salt,
+            passphrase.as_bytes(),
+            &mut key_bytes,
+        );
+        let key = UnboundKey::new(&CHACHA20_POLY1305, &key_bytes)
+            .map_err(|_| CredentialError::Unavailable("failed to initialise cipher".to_string()))?;
+        Ok(LessSafeKey::new(key))
+    }
+
+    fn seal(
+        key: &LessSafeKey,
+        rng: &SystemRandom,
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> Result<SealedValue, CredentialError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        fill_random(rng, &mut nonce)?;
+        let mut in_out = plaintext.to_vec();
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(aad),
+            &mut in_out,
+        )
+        .map_err(|_| CredentialError::Unavailable("failed to encrypt credential".to_string()))?;
+        Ok(SealedValue {
+            nonce: encode_hex(&nonce),
+            ciphertext: encode_hex(&in_out),
+        })
+    }
+
+    fn open_sealed(
+        key: &LessSafeKey,
+        sealed: &SealedValue,
+        aad: &[u8],
+    ) -> Result<Vec<u8>, CredentialError> {
+        let nonce: [u8; NONCE_LEN] = decode_hex(&sealed.nonce)?
+            .try_into()
+            .map_err(|_| CredentialError::Corrupted("nonce has the wrong length".to_string()))?;
+        let mut in_out = decode_hex(&sealed.ciphertext)?;
+        let plaintext = key
+            .open_in_place(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(aad),
+                &mut in_out,
+            )
+            .map_err(|_| CredentialError::Corrupted("authentication tag mismatch".to_string()))?;
+        Ok(plaintext.to_vec())
+    }
+
+    fn fill_random(rng: &SystemRandom, buffer: &mut [u8]) -> Result<(), CredentialError> {
+        rng.fill(buffer)
+            .map_err(|_| CredentialError::Unavailable("system RNG failed".to_string()))
+    }
+
+    /// Replace the file atomically so a crash never leaves a truncated store.
+    fn write_file(path: &Path, file: &CredentialFile) -> Result<(), CredentialError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let contents = serde_json::to_vec_pretty(file)
+            .map_err(|error| CredentialError::Corrupted(error.to_string()))?;
+
+        let mut staging = path.as_os_str().to_owned();
+        staging.push(".tmp");
+        let staging = PathBuf::from(staging);
+        std::fs::write(&staging, contents)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&staging, std::fs::Permissions::from_mode(0o600))?;
+        }
+        std::fs::rename(&staging, path)?;
+        Ok(())
+    }
+
+    fn encode_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    fn decode_hex(hex: &str) -> Result<Vec<u8>, CredentialError> {
+        if !hex.len().is_multiple_of(2) {
+            return Err(CredentialError::Corrupted(
+                "odd-length hex string".to_string(),
+            ));
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|index| {
+                hex.get(index..index + 2)
+                    .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                    .ok_or_else(|| CredentialError::Corrupted("invalid hex string".to_string()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn in_memory_store_round_trips_and_deletes() {
+        let store = InMemoryCredentialStore::with_secret("alice@example.com", "secret");
+        assert_eq!(store.get("alice@example.com").unwrap(), "secret");
+
+        store.set("alice@example.com", "rotated").unwrap();
+        assert_eq!(store.get("alice@example.com").unwrap(), "rotated");
+
+        store.delete("alice@example.com").unwrap();
+        store.delete("alice@example.com").unwrap();
+        assert!(matches!(
+            store.get("alice@example.com"),
+            Err(CredentialError::NotFound { .. })
+        ));
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn secrets_persist_across_reopen_without_plaintext_on_disk() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("credentials.json");
+
+        let store = EncryptedFileCredentialStore::open(&path, "hunter2").unwrap();
+        store.set("alice@example.com", "s3cret-password").unwrap();
+        drop(store);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("s3cret-password"));
+
+        let reopened = EncryptedFileCredentialStore::open(&path, "hunter2").unwrap();
+        assert_eq!(
+            reopened.get("alice@example.com").unwrap(),
+            "s3cret-password"
+        );
+        assert!(matches!(
+            reopened.get("bob@example.com"),
+            Err(CredentialError::NotFound { .. })
+        ));
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("credentials.json");
+        drop(EncryptedFileCredentialStore::open(&path, "hunter2").unwrap());
+
+        assert!(matches!(
+            EncryptedFileCredentialStore::open(&path, "hunter3"),
+            Err(CredentialError::InvalidPassphrase)
+        ));
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn entries_are_bound_to_their_account() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("credentials.json");
+        let store = EncryptedFileCredentialStore::open(&path, "hunter2").unwrap();
+        store.set("alice@example.com", "alice-password").unwrap();
+        drop(store);
+
+        let mut json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let entry = json["entries"]["alice@example.com"].clone();
+        json["entries"]["mallory@example.com"] = entry;
+        std::fs::write(&path, json.to_string()).unwrap();
+
+        let store = EncryptedFileCredentialStore::open(&path, "hunter2").unwrap();
+        assert!(matches!(
+            store.get("mallory@example.com"),
+            Err(CredentialError::Corrupted(_))
+        ));
+    }
+}
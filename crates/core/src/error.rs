@@ -53,4 +53,9 @@ pub enum EventBusError {
 
     #[error("Subscriber lagged: {0} events missed")]
     Lagged(u64),
+    #[error("Spill failed: {0}")]
+    Spill(String),
+
+    #[error("Payload {payload} does not belong on channel {channel}")]
+    PayloadMismatch { channel: String, payload: String },
 }
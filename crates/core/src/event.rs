@@ -3,9 +3,25 @@ use chrono::{DateTime, Utc};
 use globset::{Glob, GlobMatcher};
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "native")]
-use tokio::sync::broadcast;
+use std::sync::{Arc, Mutex, Weak};
+#[cfg(feature = "native")]
+use tokio::sync::{broadcast, watch};
 use uuid::Uuid;
 
+use crate::form::DataForm;
+use crate::rsm::{RsmQuery, RsmResult};
+
+pub mod channels;
+#[cfg(feature = "native")]
+mod overflow;
+#[cfg(feature = "debug")]
+pub mod replay;
+
+#[cfg(feature = "native")]
+pub use overflow::{OverflowPolicy, SpillStore};
+#[cfg(feature = "native")]
+use overflow::{QueueMode, SubscriberQueue};
+
 /// Hierarchical channel name validation and parsing.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Channel(String);
@@ -129,6 +145,7 @@ impl Event {
 /// Identifies the source of an event
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "id", rename_all = "camelCase")]
+#[non_exhaustive]
 pub enum EventSource {
     /// Core system component
     System(String),
@@ -142,6 +159,7 @@ pub enum EventSource {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
+#[non_exhaustive]
 pub enum UiTarget {
     Tui,
     Gui,
@@ -149,6 +167,7 @@ pub enum UiTarget {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data", rename_all = "camelCase")]
+#[non_exhaustive]
 pub enum EventPayload {
     // ── System events ──────────────────────────────────────────────
     StartupComplete,
@@ -165,18 +184,224 @@ pub enum EventPayload {
     ConnectionReconnecting {
         attempt: u32,
     },
+    /// Periodic connection-quality snapshot while connected.
+    ConnectionHealth {
+        health: ConnectionHealth,
+    },
     GoingOffline,
     ComingOnline,
     SyncStarted,
     SyncCompleted {
         messages_synced: u64,
     },
+    /// Archive messages written so far by a running sync, with the number
+    /// the sync expects in total when the server reports the archive size.
+    SyncProgress {
+        done: u64,
+        estimated_total: Option<u64>,
+    },
+    /// The archive sync gave up after exhausting retries. Progress up to
+    /// `last_stanza_id` has been persisted and the next sync resumes there.
+    SyncFailed {
+        messages_synced: u64,
+        last_stanza_id: Option<String>,
+        reason: String,
+    },
+    /// Part of an archive was never stored locally, after a lossy sync or
+    /// an absence longer than the server keeps its archive. `jid` is the
+    /// room or contact whose archive has the gap, empty for the user's own.
+    SyncGapDetected {
+        jid: String,
+        gap: ArchiveGap,
+    },
+    /// One part of the archive backfill after connecting has started.
+    SyncTaskStarted {
+        task: SyncTask,
+    },
+    /// One part of the archive backfill has finished, with the reason it
+    /// stopped short in `error`.
+    SyncTaskFinished {
+        task: SyncTask,
+        messages_synced: u64,
+        error: Option<String>,
+    },
     ConfigReloaded,
+    /// A conversation's preview, unread count or flags changed.
+    ConversationUpdated {
+        jid: String,
+    },
+    /// Another device read `conversation` up to a newer message; only
+    /// `unread_count` messages are still unread here.
+    ReadStateSynced {
+        conversation: String,
+        unread_count: u32,
+    },
+    /// `jid` was muted or unmuted, here or on another of our devices. A
+    /// mute with `muted_until` lifts itself at that time.
+    ConversationMuteChanged {
+        jid: String,
+        muted: bool,
+        muted_until: Option<DateTime<Utc>>,
+    },
+    /// Progress of rejoining a room automatically after a reconnect.
+    MucRejoinStatusChanged {
+        room: String,
+        status: MucRejoinStatus,
+    },
+    /// The retention policy deleted old messages. `conversations` lists the
+    /// conversations that lost messages; the total also counts messages
+    /// outside any known conversation.
+    StoragePruned {
+        messages_deleted: u64,
+        conversations: Vec<PrunedConversation>,
+    },
+    /// A database snapshot was written to `path`. `removed` lists older
+    /// snapshots deleted to stay within the configured rotation.
+    BackupCompleted {
+        path: String,
+        size_bytes: u64,
+        removed: Vec<String>,
+    },
+    /// Database upkeep finished. `problems` holds what the integrity check
+    /// reported and is empty for a sound database; `size_bytes` is the size
+    /// on disk afterwards.
+    StorageMaintenanceCompleted {
+        problems: Vec<String>,
+        reclaimed_bytes: u64,
+        size_bytes: u64,
+    },
+    /// Space used by the local database, largest tables first.
+    /// `total_bytes` also counts free pages not yet vacuumed.
+    StorageStats {
+        tables: Vec<TableUsage>,
+        total_bytes: u64,
+    },
     ErrorOccurred {
         component: String,
         message: String,
         recoverable: bool,
     },
+    /// A warning or error was logged.
+    LogEntryRecorded {
+        entry: LogEntry,
+    },
+    /// A supervised manager's task failed and was started again.
+    /// `attempt` counts restarts within the current restart window.
+    ManagerRestarted {
+        manager: String,
+        attempt: u32,
+        reason: String,
+    },
+    /// A server feature present on an earlier check is no longer advertised,
+    /// typically after a server upgrade or reconfiguration.
+    ServerFeatureLost {
+        server: String,
+        feature: ServerFeature,
+    },
+    /// The server's XEP-0363 upload limit dropped since the last check.
+    UploadQuotaReduced {
+        server: String,
+        previous: u64,
+        current: u64,
+    },
+    /// A feature needs the user to fill in a data form sent by `from`.
+    /// Frontends answer with [`EventPayload::FormSubmitted`] carrying the
+    /// same `form_id`.
+    FormRequested {
+        form_id: String,
+        from: String,
+        form: DataForm,
+    },
+    /// An account was created through in-band registration (XEP-0077) and
+    /// its password saved to the credential store.
+    AccountRegistered {
+        jid: String,
+    },
+    /// Registering an account on `server` failed or the server does not
+    /// allow it. Any form sent for it is no longer valid.
+    AccountRegistrationFailed {
+        server: String,
+        reason: String,
+    },
+    /// The password of the logged-in account was changed on the server and
+    /// in the credential store.
+    AccountPasswordChanged {
+        jid: String,
+    },
+    /// The logged-in account was deleted from the server. The session is
+    /// closed and the password removed from the credential store.
+    AccountDeleted {
+        jid: String,
+    },
+    /// Bytes of a shared file downloaded so far. `total` is known when the
+    /// server sent the file's length.
+    TransferProgress {
+        url: String,
+        received: u64,
+        total: Option<u64>,
+    },
+    /// A shared file is in the download cache at `path`.
+    TransferCompleted {
+        url: String,
+        path: String,
+        size: u64,
+    },
+    /// Downloading a shared file failed. A later attempt resumes from the
+    /// bytes already received.
+    TransferFailed {
+        url: String,
+        reason: String,
+    },
+    /// A preview card is ready for a link in message `message_id`.
+    LinkPreviewReady {
+        message_id: String,
+        preview: LinkPreview,
+    },
+    /// Message `message_id`, written in `from`, should be translated into
+    /// `to` and no translator is configured. Whoever can translate it
+    /// answers with [`EventPayload::MessageTranslationProvided`].
+    MessageTranslationRequested {
+        message_id: String,
+        body: String,
+        from: String,
+        to: String,
+    },
+    /// A translation of message `message_id`, from a plugin or frontend.
+    MessageTranslationProvided {
+        message_id: String,
+        translation: MessageTranslation,
+    },
+    /// Message `message_id` has a translation to show beside its body.
+    MessageTranslated {
+        message_id: String,
+        translation: MessageTranslation,
+    },
+    /// A voice call started, was answered, or ended.
+    CallStateChanged {
+        call: Call,
+    },
+    /// Media parameters from the peer of call `sid`, for the media engine
+    /// to apply.
+    CallMediaReceived {
+        sid: String,
+        kind: CallMediaKind,
+        contents: Vec<CallContent>,
+    },
+    /// An OMEMO message could not be decrypted. Frontends show a placeholder
+    /// in the conversation with `from` instead of the message body.
+    OmemoMessageUndecryptable {
+        id: String,
+        from: String,
+        sender_device_id: u32,
+        reason: String,
+    },
+    /// The trust level of an OMEMO device changed, either by the user or
+    /// because the device presented a different identity key.
+    OmemoTrustChanged {
+        jid: String,
+        device_id: u32,
+        trust: OmemoTrust,
+    },
 
     // ── XMPP Roster events ────────────────────────────────────────
     RosterReceived {
@@ -188,9 +413,48 @@ pub enum EventPayload {
     RosterRemoved {
         jid: String,
     },
+    /// The server answered a versioned roster fetch without items: the
+    /// cached roster is current as of the version sent, and any changes
+    /// since then follow as roster pushes (RFC 6121 §2.6).
+    RosterUnchanged,
+    /// The roster version reached by the preceding full roster or push.
+    /// `None` after a full roster without a version, from a server that does
+    /// not support roster versioning.
+    RosterVersionChanged {
+        ver: Option<String>,
+    },
+    /// The server answered a password change or account deletion.
+    /// `error` is the stanza error condition, followed by its text when the
+    /// server gave one, if the request was refused.
+    AccountRequestAnswered {
+        request_id: String,
+        error: Option<String>,
+    },
+    /// The server rejected a roster set or remove for `jid`.
+    RosterSetFailed {
+        jid: String,
+        reason: String,
+    },
     SubscriptionRequest {
         from: String,
     },
+    SubscriptionPending {
+        jid: String,
+        received_at: DateTime<Utc>,
+    },
+    /// The server rejected adding `jid` to the roster; the contact added
+    /// locally has been removed again.
+    RosterAddFailed {
+        jid: String,
+        reason: String,
+    },
+    /// Our own profile changed. `avatar_hash` is the SHA-1 of the avatar,
+    /// named as in XEP-0084.
+    ProfileUpdated {
+        nickname: Option<String>,
+        about: Option<String>,
+        avatar_hash: Option<String>,
+    },
     SubscriptionApproved {
         jid: String,
     },
@@ -204,11 +468,63 @@ pub enum EventPayload {
         show: PresenceShow,
         status: Option<String>,
         priority: i8,
+        /// XEP-0319 last user interaction time, if the resource advertised one.
+        #[serde(default)]
+        idle_since: Option<DateTime<Utc>>,
     },
     OwnPresenceChanged {
         show: PresenceShow,
         status: Option<String>,
     },
+    ContactIdleChanged {
+        jid: String,
+        idle_since: Option<DateTime<Utc>>,
+    },
+    /// XEP-0115 entity capabilities advertised in a resource's presence.
+    EntityCapsReceived {
+        jid: String,
+        node: String,
+        ver: String,
+    },
+    /// Result of a XEP-0030 disco#info query.
+    DiscoInfoReceived {
+        jid: String,
+        node: Option<String>,
+        features: Vec<String>,
+        /// XEP-0363 `max-file-size` if the entity is an upload service.
+        upload_max_size: Option<u64>,
+        /// XEP-0115 SHA-1 verification string computed from this result, for
+        /// queries on a caps node. Matches the `ver` the node ends in unless
+        /// the entity misreported its capabilities.
+        #[serde(default)]
+        verification: Option<String>,
+    },
+    /// One page of a XEP-0030 disco#items query, such as a MUC service's
+    /// rooms.
+    DiscoItemsReceived {
+        jid: String,
+        node: Option<String>,
+        items: Vec<DiscoItem>,
+        /// Absent when the entity returned every item at once.
+        page: Option<RsmResult>,
+    },
+    /// `jid` answered a disco#info query with an error. `error` is the
+    /// stanza error condition, followed by its text when the entity gave one.
+    DiscoInfoFailed {
+        jid: String,
+        node: Option<String>,
+        error: String,
+    },
+    /// `jid` answered a disco#items query with an error.
+    DiscoItemsFailed {
+        jid: String,
+        node: Option<String>,
+        error: String,
+    },
+    /// The known capabilities of a contact changed.
+    CapabilitiesChanged {
+        jid: String,
+    },
 
     // ── XMPP Message events ──────────────────────────────────────
     MessageReceived {
@@ -221,6 +537,83 @@ pub enum EventPayload {
         id: String,
         to: String,
     },
+    /// XEP-0333: the recipient displayed a message we sent.
+    MessageDisplayed {
+        id: String,
+        to: String,
+    },
+    /// XEP-0333: another of our devices displayed message `id` in the chat
+    /// with `conversation`, as seen in a sent carbon or our archive.
+    /// `timestamp` is when the marker was sent.
+    MessageDisplayedElsewhere {
+        conversation: String,
+        id: String,
+        timestamp: DateTime<Utc>,
+    },
+    /// XEP-0424: `from` retracted their message `id` in `conversation`, or
+    /// (XEP-0425) moderator `moderated_by` removed it from room
+    /// `conversation`. `id` is the origin id of a one-to-one message or the
+    /// room's stanza id of a groupchat message.
+    MessageRetractionReceived {
+        conversation: String,
+        from: String,
+        id: String,
+        moderated_by: Option<String>,
+        reason: Option<String>,
+    },
+    /// Stored message `id` is now a tombstone: its body is gone and only
+    /// the fact that it was retracted remains.
+    MessageRetracted {
+        id: String,
+        conversation: String,
+        retracted_at: DateTime<Utc>,
+        moderated_by: Option<String>,
+        reason: Option<String>,
+    },
+    /// XEP-0184: the sender of message `id` asked for a delivery receipt.
+    ReceiptRequested {
+        id: String,
+        from: String,
+    },
+    /// A message was scheduled to be sent later.
+    MessageScheduled {
+        message: ScheduledMessage,
+    },
+    ScheduledMessageCancelled {
+        id: String,
+    },
+    /// A scheduled message fell due and was handed to the send path as
+    /// `message_id`.
+    ScheduledMessageSent {
+        id: String,
+        message_id: String,
+    },
+    /// An automation rule was created or changed.
+    RuleSaved {
+        rule: Rule,
+    },
+    RuleDeleted {
+        id: String,
+    },
+    /// Rule `rule_id` matched something `jid` did and its actions ran.
+    RuleFired {
+        rule_id: String,
+        jid: String,
+    },
+    /// A rule's [`RuleAction::PluginHook`] fired. The plugin runtime runs
+    /// `hook` on the plugin `plugin_id`.
+    RulePluginHookRequested {
+        rule_id: String,
+        plugin_id: String,
+        hook: String,
+        data: serde_json::Value,
+    },
+    /// The theme `theme_id` was selected; `colors` is its resolved
+    /// palette, so frontends can restyle without a registry of their own.
+    ThemeApplied {
+        theme_id: String,
+        colors: crate::theme::ThemeColors,
+    },
     ChatStateReceived {
         from: String,
         state: ChatState,
@@ -229,6 +622,11 @@ pub enum EventPayload {
         room: String,
         message: ChatMessage,
     },
+    /// A room message mentioned the local nick or a configured keyword.
+    MucMentionReceived {
+        room: String,
+        message: ChatMessage,
+    },
     MucJoined {
         room: String,
         nick: String,
@@ -239,11 +637,28 @@ pub enum EventPayload {
     MucSubjectChanged {
         room: String,
         subject: String,
+        /// Nick of the occupant who set the subject, if the room reported one.
+        #[serde(default)]
+        set_by: Option<String>,
     },
     MucOccupantChanged {
         room: String,
         occupant: MucOccupant,
     },
+    /// An occupant changed nick (XEP-0045 status 303). They stay in the
+    /// room throughout; their next presence comes from `new_nick`.
+    MucOccupantRenamed {
+        room: String,
+        old_nick: String,
+        new_nick: String,
+        /// We were the occupant renamed.
+        own: bool,
+    },
+    /// An invitation to a room, sent directly by a contact (XEP-0249) or
+    /// relayed by the room (XEP-0045).
+    MucInviteReceived {
+        invite: MucInvite,
+    },
 
     // ── XMPP MAM events ──────────────────────────────────────────
     MamResultReceived {
@@ -254,7 +669,84 @@ pub enum EventPayload {
     MamFinReceived {
         iq_id: String,
         complete: bool,
-        last_id: Option<String>,
+        page: RsmResult,
+    },
+    /// The server refused MAM query `iq_id`. `error` is the stanza error
+    /// condition, followed by its text when the server gave one.
+    MamQueryFailed {
+        iq_id: String,
+        error: String,
+    },
+
+    // ── XMPP OMEMO events ────────────────────────────────────────
+    /// The XEP-0384 device list of `jid`, ours included, from PEP.
+    OmemoDeviceListReceived {
+        jid: String,
+        devices: Vec<u32>,
+    },
+    OmemoBundleReceived {
+        jid: String,
+        device_id: u32,
+        bundle: OmemoBundle,
+    },
+    /// An encrypted message before decryption. Its fallback body is not
+    /// reported as a [`EventPayload::MessageReceived`].
+    OmemoMessageReceived {
+        id: String,
+        from: String,
+        to: String,
+        message_type: MessageType,
+        envelope: OmemoEnvelope,
+    },
+
+    // ── XMPP Jingle events ───────────────────────────────────────
+    /// A Jingle (XEP-0166) request from `from`. Whoever handles it answers
+    /// the iq with [`EventPayload::JingleAnswerRequested`].
+    JingleReceived {
+        from: String,
+        iq_id: String,
+        sid: String,
+        action: JingleAction,
+    },
+    /// The peer's answer to a request sent with
+    /// [`EventPayload::JingleSendRequested`]; `error` is the stanza error
+    /// condition if it refused it.
+    JingleAnswered {
+        iq_id: String,
+        error: Option<String>,
+    },
+
+    // ── XMPP PubSub events ───────────────────────────────────────
+    /// The service's answer to a `PubSubManager` request. `items` holds the
+    /// retrieved items, or the published item with the id the service gave
+    /// it; `config` holds a node configuration form that was asked for.
+    PubSubRequestAnswered {
+        request_id: String,
+        error: Option<String>,
+        items: Vec<PubSubItem>,
+        config: Option<DataForm>,
+    },
+    /// Items published to `node` of `from` (XEP-0060 notifications, PEP
+    /// included). `from` is empty for our own PEP nodes when the server
+    /// omits it.
+    PubSubItemsReceived {
+        from: String,
+        node: String,
+        items: Vec<PubSubItem>,
+    },
+    PubSubItemsRetracted {
+        from: String,
+        node: String,
+        ids: Vec<String>,
+    },
+    PubSubNodeDeleted {
+        from: String,
+        node: String,
+    },
+    /// Every item of `node` was removed at once.
+    PubSubNodePurged {
+        from: String,
+        node: String,
     },
 
     // ── XMPP Debug events ────────────────────────────────────────
@@ -276,6 +768,19 @@ pub enum EventPayload {
         jid: String,
         direction: ScrollDirection,
     },
+    /// Older history fetched for a conversation after an upward
+    /// [`EventPayload::ScrollRequested`]. `messages` are oldest first and
+    /// have already been stored; `before` is the cursor for the next page.
+    HistoryPageLoaded {
+        jid: String,
+        messages: Vec<ChatMessage>,
+        before: Option<String>,
+        /// `false` once the start of the archive has been reached.
+        has_more: bool,
+    },
+    /// The user typed in the compose box of the conversation with `jid`.
+    /// Published per keystroke; the typing tracker turns these into chat
+    /// states.
     ComposeStarted {
         jid: String,
     },
@@ -288,16 +793,69 @@ pub enum EventPayload {
     NotificationClicked {
         event_id: String,
     },
+    /// The app was hidden or brought back. While inactive the server is
+    /// asked to hold back unimportant traffic (XEP-0352) and background
+    /// upkeep may run.
+    ClientActivityChanged {
+        active: bool,
+    },
+    /// A notification the user's preferences allow; shown by whichever
+    /// frontend or OS integration is listening.
+    NotificationShowRequested {
+        title: String,
+        body: String,
+        /// The message that triggered it, for click-through.
+        event_id: Option<String>,
+        conversation_jid: Option<String>,
+    },
+    /// `None` restores the default for the conversation.
+    NotificationPreferenceChanged {
+        jid: String,
+        preference: Option<NotificationPreference>,
+    },
+    /// Start creating an account on `server`. Its registration form comes
+    /// back as a [`EventPayload::FormRequested`].
+    AccountRegistrationRequested {
+        server: String,
+    },
+    /// The user submitted or cancelled a form from [`EventPayload::FormRequested`].
+    /// `form` is of type `submit` or `cancel`.
+    FormSubmitted {
+        form_id: String,
+        form: DataForm,
+    },
 
     // ── UI command events (consumed by XMPP outbound router) ────
     MessageSendRequested {
         to: String,
         body: String,
         message_type: MessageType,
+        /// Ask the recipient for a XEP-0184 delivery receipt.
+        #[serde(default)]
+        request_receipt: bool,
     },
     PresenceSetRequested {
         show: PresenceShow,
         status: Option<String>,
+        /// Resource priority (RFC 6121 §4.7.2.3); the server routes bare-JID
+        /// messages to the highest-priority resource.
+        #[serde(default)]
+        priority: i8,
+        /// When set, an XEP-0319 `<idle/>` element is attached to the presence.
+        #[serde(default)]
+        idle_since: Option<DateTime<Utc>>,
+    },
+    /// Presence addressed to one entity instead of broadcast to the roster
+    /// (RFC 6121 §4.6), such as our status inside a single MUC room.
+    DirectedPresenceRequested {
+        to: String,
+        show: PresenceShow,
+        status: Option<String>,
+    },
+    /// XEP-0186 invisibility command, only sent to servers that advertise
+    /// `urn:xmpp:invisible:0`.
+    InvisibilitySetRequested {
+        invisible: bool,
     },
     RosterAddRequested {
         jid: String,
@@ -307,6 +865,16 @@ pub enum EventPayload {
     RosterRemoveRequested {
         jid: String,
     },
+    /// XEP-0191: block `jid` on the server. With `report`, the block also
+    /// files a XEP-0377 report of `jid` with the server's operators.
+    BlockRequested {
+        jid: String,
+        #[serde(default)]
+        report: Option<SpamReason>,
+    },
+    UnblockRequested {
+        jid: String,
+    },
     SubscriptionRespondRequested {
         jid: String,
         accept: bool,
@@ -314,10 +882,21 @@ pub enum EventPayload {
     SubscriptionSendRequested {
         jid: String,
         subscribe: bool,
+        /// XEP-0379 token from the contact, letting their server approve
+        /// the request without asking them.
+        #[serde(default)]
+        preauth: Option<String>,
     },
     MucJoinRequested {
         room: String,
         nick: String,
+        /// Ask the room to replay history sent after this time (XEP-0045
+        /// `<history since/>`). `None` leaves the amount to the room.
+        #[serde(default)]
+        history_since: Option<DateTime<Utc>>,
+        /// Password for a protected room, such as one an invite carried.
+        #[serde(default)]
+        password: Option<String>,
     },
     MucLeaveRequested {
         room: String,
@@ -327,21 +906,110 @@ pub enum EventPayload {
         name: Option<String>,
         groups: Vec<String>,
     },
-    RosterFetchRequested,
+    /// Fetch the roster. With a `ver`, the server may answer with only the
+    /// changes since that version; an empty `ver` asks for the full roster
+    /// and its version.
+    RosterFetchRequested {
+        ver: Option<String>,
+    },
+    /// Create `rule`, or replace the rule with its id.
+    RuleSaveRequested {
+        rule: Rule,
+    },
+    RuleDeleteRequested {
+        id: String,
+    },
     MucSendRequested {
         room: String,
         body: String,
     },
+    /// Invite `to` into `room` with a XEP-0249 direct invitation.
+    MucInviteSendRequested {
+        room: String,
+        to: String,
+        #[serde(default)]
+        reason: Option<String>,
+    },
+    /// Turn down a XEP-0045 invitation to `room`, telling `to`, who sent it,
+    /// through the room.
+    MucInviteDeclineRequested {
+        room: String,
+        to: String,
+        #[serde(default)]
+        reason: Option<String>,
+    },
+    /// XEP-0424: retract our message `id` to `to`. `id` is the message's
+    /// origin id, or the room's stanza id in groupchat.
+    MessageRetractRequested {
+        to: String,
+        id: String,
+        message_type: MessageType,
+    },
+    /// XEP-0425: as a moderator of `room`, remove message `id` (the room's
+    /// stanza id) for everyone.
+    MessageModerateRequested {
+        room: String,
+        id: String,
+        reason: Option<String>,
+    },
     ChatStateSendRequested {
         to: String,
         state: ChatState,
     },
+    /// Acknowledge message `id` from `to` with a XEP-0184 receipt.
+    ReceiptSendRequested {
+        to: String,
+        id: String,
+    },
     MamQueryRequested {
         query_id: String,
         with_jid: Option<String>,
-        after: Option<String>,
-        before: Option<String>,
-        max: u32,
+        page: RsmQuery,
+        /// Archive to query: a MUC room for its XEP-0313 room archive, or
+        /// `None` for the user's own archive.
+        #[serde(default)]
+        archive: Option<String>,
+    },
+    DiscoInfoRequested {
+        jid: String,
+        node: Option<String>,
+    },
+    DiscoItemsRequested {
+        jid: String,
+        node: Option<String>,
+        page: RsmQuery,
+    },
+    OmemoDeviceListPublishRequested {
+        devices: Vec<u32>,
+    },
+    OmemoBundlePublishRequested {
+        device_id: u32,
+        bundle: OmemoBundle,
+    },
+    OmemoDeviceListFetchRequested {
+        jid: String,
+    },
+    OmemoBundleFetchRequested {
+        jid: String,
+        device_id: u32,
+    },
+    OmemoMessageSendRequested {
+        to: String,
+        id: String,
+        envelope: OmemoEnvelope,
+    },
+    JingleSendRequested {
+        to: String,
+        iq_id: String,
+        sid: String,
+        action: JingleAction,
+    },
+    /// Acknowledge Jingle request `iq_id`, or refuse it with the stanza
+    /// error condition `error`.
+    JingleAnswerRequested {
+        to: String,
+        iq_id: String,
+        error: Option<String>,
     },
 
     // ── Plugin events ────────────────────────────────────────────
@@ -356,6 +1024,12 @@ pub enum EventPayload {
         plugin_id: String,
         error: String,
     },
+    /// A plugin tried to use a permission its manifest does not declare.
+    PluginPermissionDenied {
+        plugin_id: String,
+        permission: String,
+        detail: String,
+    },
     PluginCustomEvent {
         plugin_id: String,
         event_type: String,
@@ -367,6 +1041,35 @@ pub enum EventPayload {
     PluginInstallCompleted {
         plugin_id: String,
     },
+    PluginUsageSnapshot {
+        plugins: Vec<PluginUsage>,
+    },
+    /// A plugin KV entry was written or deleted.
+    PluginKvChanged {
+        plugin_id: String,
+        key: String,
+        deleted: bool,
+    },
+}
+
+/// Why a sender was reported (XEP-0377).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SpamReason {
+    /// Unsolicited bulk messages.
+    Spam,
+    /// Harassment or other abuse aimed at the user.
+    Abuse,
+}
+
+impl SpamReason {
+    /// The reason URI reports carry on the wire.
+    pub fn uri(self) -> &'static str {
+        match self {
+            SpamReason::Spam => "urn:xmpp:reporting:spam",
+            SpamReason::Abuse => "urn:xmpp:reporting:abuse",
+        }
+    }
 }
 
 /// A single entry in the XMPP roster.
@@ -422,6 +1125,50 @@ impl std::str::FromStr for Subscription {
     }
 }
 
+/// Server-side features the client relies on and monitors for regressions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ServerFeature {
+    /// XEP-0313 Message Archive Management on the account.
+    MessageArchive,
+    /// XEP-0280 Message Carbons.
+    Carbons,
+    /// XEP-0363 HTTP File Upload offered by the server itself.
+    FileUpload,
+}
+
+impl ServerFeature {
+    pub const ALL: [ServerFeature; 3] = [Self::MessageArchive, Self::Carbons, Self::FileUpload];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::MessageArchive => "messageArchive",
+            Self::Carbons => "carbons",
+            Self::FileUpload => "fileUpload",
+        }
+    }
+
+    /// Human-readable name for reports and alerts.
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::MessageArchive => "message archiving (XEP-0313)",
+            Self::Carbons => "message carbons (XEP-0280)",
+            Self::FileUpload => "file upload (XEP-0363)",
+        }
+    }
+}
+
+impl std::str::FromStr for ServerFeature {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|feature| feature.as_str() == s)
+            .ok_or_else(|| format!("unknown server feature: {s}"))
+    }
+}
+
 /// Structured embed attached to a message by a plugin (e.g. GitHub repo card).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -432,6 +1179,28 @@ pub struct MessageEmbed {
     pub data: serde_json::Value,
 }
 
+/// What a linked page says about itself in its OpenGraph or HTML metadata.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkPreview {
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Absolute URL of the page's preview image.
+    pub image_url: Option<String>,
+    pub site_name: Option<String>,
+    pub fetched_at: DateTime<Utc>,
+}
+
+/// A message body in another language than the one it was written in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageTranslation {
+    /// Language tag of `body`.
+    pub lang: String,
+    pub body: String,
+}
+
 /// A chat message (1:1 or MUC).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -442,24 +1211,168 @@ pub struct ChatMessage {
     /// Bare JID of the sender
     pub from: String,
 
-    /// Bare JID of the recipient (or room JID for MUC)
-    pub to: String,
+    /// Bare JID of the recipient (or room JID for MUC)
+    pub to: String,
+
+    /// Plain-text message body
+    pub body: String,
+
+    /// When the message was sent (UTC)
+    pub timestamp: DateTime<Utc>,
+
+    /// Message type
+    pub message_type: MessageType,
+
+    /// Thread ID for conversation threading, if present
+    pub thread: Option<String>,
+
+    /// Plugin-generated rich embeds (e.g. GitHub repo cards).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub embeds: Vec<MessageEmbed>,
+
+    /// XEP-0359 origin ID assigned by the sending client, if present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin_id: Option<String>,
+
+    /// XEP-0359 stanza ID assigned by the archiving entity: our server for
+    /// one-to-one messages, the room for groupchat.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stanza_id: Option<String>,
+
+    /// When the message was retracted (XEP-0424) or removed by a room
+    /// moderator (XEP-0425). Its body is empty from then on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retracted_at: Option<DateTime<Utc>>,
+
+    /// Language tag of the body, from its `xml:lang` or the stanza's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+
+    /// The body in the user's language, shown beside the original.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub translation: Option<MessageTranslation>,
+}
+
+impl ChatMessage {
+    /// The body parsed for message styling (XEP-0393).
+    pub fn styled_body(&self) -> Vec<crate::styling::Block> {
+        crate::styling::parse(&self.body)
+    }
+}
+
+/// An outgoing one-to-one message waiting for its send time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduledMessage {
+    pub id: String,
+    pub to: String,
+    pub body: String,
+    pub send_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A user-defined automation: when `trigger` matches, run `actions` in
+/// order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rule {
+    /// Left empty when creating a rule; one is assigned on save.
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    #[serde(default = "default_rule_enabled")]
+    pub enabled: bool,
+    pub trigger: RuleTrigger,
+    pub actions: Vec<RuleAction>,
+    /// After firing for a contact, the rule ignores that contact for this
+    /// many seconds. Zero fires every time.
+    #[serde(default)]
+    pub cooldown_secs: u64,
+}
+
+fn default_rule_enabled() -> bool {
+    true
+}
+
+/// What a [`Rule`] waits for. Sender patterns match bare JIDs, with `*`
+/// standing for any run of characters, as in `*@example.com`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum RuleTrigger {
+    /// A one-to-one message, optionally only from senders matching `from`
+    /// and containing `keyword` (ignoring case).
+    Message {
+        #[serde(default)]
+        from: Option<String>,
+        #[serde(default)]
+        keyword: Option<String>,
+    },
+    /// A contact's presence changed, optionally only for contacts matching
+    /// `from` and to `show`.
+    PresenceChanged {
+        #[serde(default)]
+        from: Option<String>,
+        #[serde(default)]
+        show: Option<PresenceShow>,
+    },
+}
 
-    /// Plain-text message body
-    pub body: String,
+/// What a [`Rule`] does. Templates may use `{from}`, `{body}`, `{show}` and
+/// `{status}`, which are empty when the trigger has no such value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum RuleAction {
+    /// Message the contact that triggered the rule.
+    Reply { template: String },
+    /// Pass what happened on to `to`.
+    Forward { to: String },
+    /// Run `hook` on the plugin `plugin_id`, through a
+    /// [`EventPayload::RulePluginHookRequested`].
+    #[serde(rename_all = "camelCase")]
+    PluginHook { plugin_id: String, hook: String },
+}
 
-    /// When the message was sent (UTC)
-    pub timestamp: DateTime<Utc>,
+/// How much a conversation is allowed to notify.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum NotificationLevel {
+    All,
+    /// Rooms notify only for mentions; direct messages always count as one.
+    MentionsOnly,
+    Muted,
+}
 
-    /// Message type
-    pub message_type: MessageType,
+/// The flags of a conversation that follow the user across devices,
+/// through a private PEP node.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationFlags {
+    pub archived: bool,
+    pub muted: bool,
+    /// When the mute lifts; `None` mutes until unmuted.
+    pub muted_until: Option<DateTime<Utc>>,
+}
 
-    /// Thread ID for conversation threading, if present
-    pub thread: Option<String>,
+/// A conversation's notification setting. While `muted_until` is in the
+/// future the conversation is muted whatever its level.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationPreference {
+    pub level: NotificationLevel,
+    pub muted_until: Option<DateTime<Utc>>,
+}
 
-    /// Plugin-generated rich embeds (e.g. GitHub repo cards).
-    #[serde(default, skip_serializing_if = "Vec::is_empty")]
-    pub embeds: Vec<MessageEmbed>,
+/// One line of the application log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogEntry {
+    pub timestamp: DateTime<Utc>,
+    /// `ERROR`, `WARN`, `INFO`, `DEBUG` or `TRACE`.
+    pub level: String,
+    /// The module that logged it.
+    pub target: String,
+    /// The message followed by any fields, as `key=value`.
+    pub message: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -473,7 +1386,7 @@ pub enum MessageType {
 }
 
 /// XMPP presence "show" values (RFC 6121 section 4.7.2.1).
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum PresenceShow {
     /// Available (no <show/> element -- the default)
@@ -486,6 +1399,8 @@ pub enum PresenceShow {
     Xa,
     /// Do not disturb
     Dnd,
+    /// Connected, but shown to contacts as offline ("appear offline")
+    Invisible,
     /// Unavailable (offline)
     Unavailable,
 }
@@ -518,6 +1433,65 @@ pub struct MucOccupant {
     pub role: MucRole,
 }
 
+/// A part of the archive backfill run after connecting. The open
+/// conversation is backfilled first, then recent conversations alongside the
+/// rest of the archive.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+#[non_exhaustive]
+pub enum SyncTask {
+    /// The latest history of the conversation open in the UI.
+    OpenConversation { jid: String },
+    /// The latest history of a recently active conversation.
+    RecentConversation { jid: String },
+    /// Everything the user's archive received since the last sync.
+    Archive,
+}
+
+/// Archive messages missing between two spans stored without holes. Both
+/// ends are messages we have.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveGap {
+    /// Archive id of the last stored message before the gap.
+    pub after_id: String,
+    pub after_at: DateTime<Utc>,
+    /// Archive id of the first stored message after the gap.
+    pub before_id: String,
+    pub before_at: DateTime<Utc>,
+}
+
+/// An entry of a disco#items result: a room, when listing a MUC service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiscoItem {
+    pub jid: String,
+    pub node: Option<String>,
+    pub name: Option<String>,
+}
+
+/// An invitation to a MUC room, kept until it is accepted or declined.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MucInvite {
+    /// The room's bare JID.
+    pub room: String,
+
+    /// Bare JID of whoever sent the invitation.
+    pub from: String,
+
+    pub reason: Option<String>,
+
+    /// The room password the invitation carried, if any.
+    pub password: Option<String>,
+
+    /// Relayed by the room (XEP-0045) rather than sent directly (XEP-0249).
+    /// Only these can be declined back to the inviter.
+    pub mediated: bool,
+
+    pub received_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum MucAffiliation {
@@ -537,6 +1511,369 @@ pub enum MucRole {
     None,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MucRejoinStatus {
+    /// The join presence has been sent.
+    Rejoining,
+    Rejoined,
+    /// The room did not confirm the join. If the connection dropped first,
+    /// the room is retried on the next reconnect.
+    Failed,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionHealth {
+    /// Round trip of the last answered XEP-0199 ping.
+    pub rtt_ms: Option<u64>,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Time since anything was last received; `None` before the first frame.
+    pub since_last_inbound_ms: Option<u64>,
+    /// Reconnect attempts since the connection manager was created.
+    pub reconnect_count: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrunedConversation {
+    pub jid: String,
+    pub messages_deleted: u64,
+}
+
+/// One table of the local database. `bytes` includes the table's indexes
+/// and is approximate where the backend cannot measure pages.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TableUsage {
+    pub table: String,
+    pub rows: u64,
+    pub bytes: u64,
+}
+
+/// What a loaded plugin has consumed since it was first loaded. Counters
+/// carry over when the plugin is reloaded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginUsage {
+    pub plugin_id: String,
+    /// Calls into the plugin, including init.
+    pub invocations: u64,
+    pub fuel_consumed: u64,
+    pub wall_time_micros: u64,
+    /// Largest the plugin's linear memory has grown.
+    pub memory_high_water_bytes: u64,
+    pub kv_keys: u64,
+    /// Summed length of the plugin's KV values.
+    pub kv_bytes: u64,
+}
+
+/// Public key material a device publishes so others can start sessions
+/// with it (XEP-0384 v0.3 bundle). Keys are serialized Curve25519 keys.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OmemoBundle {
+    pub identity_key: Vec<u8>,
+    pub signed_pre_key_id: u32,
+    pub signed_pre_key: Vec<u8>,
+    pub signed_pre_key_signature: Vec<u8>,
+    pub pre_keys: Vec<OmemoPreKey>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OmemoPreKey {
+    pub id: u32,
+    pub public_key: Vec<u8>,
+}
+
+/// The `<encrypted/>` element of an OMEMO message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OmemoEnvelope {
+    pub sender_device_id: u32,
+    /// The payload key, encrypted once for every recipient device.
+    pub keys: Vec<OmemoKeyElement>,
+    pub iv: Vec<u8>,
+    /// Absent on key transport messages, which only set up a session.
+    pub payload: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OmemoKeyElement {
+    pub recipient_device_id: u32,
+    /// Whether `data` is a pre-key message that starts a new session.
+    pub prekey: bool,
+    pub data: Vec<u8>,
+}
+
+/// The user's decision about an OMEMO device. Undecided devices are
+/// encrypted to until the user distrusts them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OmemoTrust {
+    #[default]
+    Undecided,
+    Trusted,
+    Untrusted,
+}
+
+impl OmemoTrust {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OmemoTrust::Undecided => "undecided",
+            OmemoTrust::Trusted => "trusted",
+            OmemoTrust::Untrusted => "untrusted",
+        }
+    }
+}
+
+impl std::str::FromStr for OmemoTrust {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "trusted" => OmemoTrust::Trusted,
+            "untrusted" => OmemoTrust::Untrusted,
+            _ => OmemoTrust::Undecided,
+        })
+    }
+}
+
+/// An item of a publish-subscribe node (XEP-0060).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PubSubItem {
+    pub id: Option<String>,
+    pub publisher: Option<String>,
+    /// The item's payload element, serialized as XML.
+    pub payload: Option<String>,
+}
+
+/// A one-to-one voice call negotiated over Jingle (XEP-0166/0167).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Call {
+    /// The Jingle session ID.
+    pub sid: String,
+    /// Full JID of the other party.
+    pub peer: String,
+    pub direction: CallDirection,
+    pub state: CallState,
+    /// Why the call ended or failed.
+    pub reason: Option<CallEndReason>,
+    pub started_at: DateTime<Utc>,
+    pub answered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CallDirection {
+    Incoming,
+    Outgoing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CallState {
+    /// Offered and not answered yet, in either direction.
+    Ringing,
+    Active,
+    Ended,
+    Failed,
+}
+
+/// A Jingle `<reason/>` condition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CallEndReason {
+    Success,
+    Decline,
+    Busy,
+    Cancel,
+    Timeout,
+    Gone,
+    Expired,
+    ConnectivityError,
+    FailedTransport,
+    FailedApplication,
+    MediaError,
+    SecurityError,
+    IncompatibleParameters,
+    UnsupportedApplications,
+    UnsupportedTransports,
+    GeneralError,
+}
+
+impl CallEndReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CallEndReason::Success => "success",
+            CallEndReason::Decline => "decline",
+            CallEndReason::Busy => "busy",
+            CallEndReason::Cancel => "cancel",
+            CallEndReason::Timeout => "timeout",
+            CallEndReason::Gone => "gone",
+            CallEndReason::Expired => "expired",
+            CallEndReason::ConnectivityError => "connectivity-error",
+            CallEndReason::FailedTransport => "failed-transport",
+            CallEndReason::FailedApplication => "failed-application",
+            CallEndReason::MediaError => "media-error",
+            CallEndReason::SecurityError => "security-error",
+            CallEndReason::IncompatibleParameters => "incompatible-parameters",
+            CallEndReason::UnsupportedApplications => "unsupported-applications",
+            CallEndReason::UnsupportedTransports => "unsupported-transports",
+            CallEndReason::GeneralError => "general-error",
+        }
+    }
+
+    /// Conditions this client does not know, such as
+    /// `alternative-session`, read as `general-error`.
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "success" => CallEndReason::Success,
+            "decline" => CallEndReason::Decline,
+            "busy" => CallEndReason::Busy,
+            "cancel" => CallEndReason::Cancel,
+            "timeout" => CallEndReason::Timeout,
+            "gone" => CallEndReason::Gone,
+            "expired" => CallEndReason::Expired,
+            "connectivity-error" => CallEndReason::ConnectivityError,
+            "failed-transport" => CallEndReason::FailedTransport,
+            "failed-application" => CallEndReason::FailedApplication,
+            "media-error" => CallEndReason::MediaError,
+            "security-error" => CallEndReason::SecurityError,
+            "incompatible-parameters" => CallEndReason::IncompatibleParameters,
+            "unsupported-applications" => CallEndReason::UnsupportedApplications,
+            "unsupported-transports" => CallEndReason::UnsupportedTransports,
+            _ => CallEndReason::GeneralError,
+        }
+    }
+
+    /// Whether a call ending for this reason failed rather than ended
+    /// normally.
+    pub fn is_failure(&self) -> bool {
+        !matches!(
+            self,
+            CallEndReason::Success
+                | CallEndReason::Decline
+                | CallEndReason::Busy
+                | CallEndReason::Cancel
+                | CallEndReason::Timeout
+                | CallEndReason::Gone
+                | CallEndReason::Expired
+        )
+    }
+}
+
+/// The Jingle actions a voice call uses. Media is described the way the
+/// media engine reports it, so it can be mapped to and from WebRTC SDP.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum JingleAction {
+    SessionInitiate {
+        contents: Vec<CallContent>,
+    },
+    SessionAccept {
+        contents: Vec<CallContent>,
+    },
+    /// XEP-0167 `<ringing/>` session-info: the callee is being alerted.
+    Ringing,
+    /// Trickled ICE candidates (XEP-0176).
+    TransportInfo {
+        contents: Vec<CallContent>,
+    },
+    SessionTerminate {
+        reason: CallEndReason,
+    },
+    /// Any other action, which this client refuses.
+    Unsupported {
+        name: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CallMediaKind {
+    Offer,
+    Answer,
+    Candidates,
+}
+
+/// One `<content/>` of a Jingle session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallContent {
+    pub name: String,
+    /// Absent on transport-info, which only carries candidates.
+    pub description: Option<RtpDescription>,
+    pub transport: IceTransport,
+}
+
+/// XEP-0167 RTP description.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RtpDescription {
+    /// `audio` for voice calls.
+    pub media: String,
+    pub payload_types: Vec<RtpPayloadType>,
+    /// RTP and RTCP share one port, as WebRTC requires.
+    pub rtcp_mux: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RtpPayloadType {
+    pub id: u8,
+    pub name: Option<String>,
+    pub clockrate: Option<u32>,
+    pub channels: u8,
+    /// Format parameters, such as Opus `useinbandfec`.
+    #[serde(default)]
+    pub parameters: Vec<(String, String)>,
+}
+
+/// XEP-0176 ICE-UDP transport, with the XEP-0320 DTLS fingerprint.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IceTransport {
+    pub ufrag: Option<String>,
+    pub pwd: Option<String>,
+    pub fingerprint: Option<DtlsFingerprint>,
+    pub candidates: Vec<IceCandidate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DtlsFingerprint {
+    /// Hash function, such as `sha-256`.
+    pub hash: String,
+    /// DTLS role: `actpass`, `active` or `passive`.
+    pub setup: String,
+    /// Colon-separated hex, as in SDP.
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IceCandidate {
+    pub id: String,
+    pub foundation: String,
+    pub component: u8,
+    pub generation: u8,
+    pub protocol: String,
+    pub priority: u32,
+    pub ip: String,
+    pub port: u16,
+    /// `host`, `srflx`, `prflx` or `relay`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub rel_addr: Option<String>,
+    pub rel_port: Option<u16>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum ScrollDirection {
@@ -552,9 +1889,35 @@ pub trait EventBus: Send + Sync + 'static {
     fn subscribe(
         &self,
         pattern: &str,
+    ) -> std::result::Result<EventSubscription, crate::error::EventBusError> {
+        self.subscribe_with(pattern, OverflowPolicy::DropOldest)
+    }
+    /// Subscribe with a chosen behaviour for when this subscriber falls
+    /// behind; `subscribe()` drops the oldest events.
+    fn subscribe_with(
+        &self,
+        pattern: &str,
+        policy: OverflowPolicy,
     ) -> std::result::Result<EventSubscription, crate::error::EventBusError>;
+    /// Publish, waiting for room in any full [`OverflowPolicy::Block`]
+    /// queue instead of queueing past it as `publish()` does.
+    fn publish_async(&self, event: Event) -> PublishFuture<'_> {
+        Box::pin(std::future::ready(self.publish(event)))
+    }
+    /// Stop accepting events. Subscribers receive what was already published
+    /// and then `ChannelClosed`.
+    fn close(&self);
 }
 
+#[cfg(feature = "native")]
+pub type PublishFuture<'a> = std::pin::Pin<
+    Box<
+        dyn std::future::Future<Output = std::result::Result<(), crate::error::EventBusError>>
+            + Send
+            + 'a,
+    >,
+>;
+
 #[cfg(feature = "native")]
 #[derive(Clone)]
 pub struct BroadcastEventBus {
@@ -562,6 +1925,10 @@ pub struct BroadcastEventBus {
     xmpp_sender: broadcast::Sender<Event>,
     ui_sender: broadcast::Sender<Event>,
     plugin_sender: broadcast::Sender<Event>,
+    /// Subscribers with their own queue; dropped subscriptions are pruned
+    /// on publish.
+    queues: Arc<Mutex<Vec<Weak<SubscriberQueue>>>>,
+    closed: Arc<watch::Sender<bool>>,
 }
 
 #[cfg(feature = "native")]
@@ -574,15 +1941,48 @@ impl BroadcastEventBus {
         let (xmpp_sender, _) = broadcast::channel(capacity);
         let (ui_sender, _) = broadcast::channel(capacity);
         let (plugin_sender, _) = broadcast::channel(capacity);
+        let (closed, _) = watch::channel(false);
 
         Self {
             system_sender,
             xmpp_sender,
             ui_sender,
             plugin_sender,
+            queues: Arc::new(Mutex::new(Vec::new())),
+            closed: Arc::new(closed),
         }
     }
 
+    /// The domain sender for a publishable `event`.
+    fn sender_for_event(
+        &self,
+        event: &Event,
+    ) -> std::result::Result<&broadcast::Sender<Event>, crate::error::EventBusError> {
+        if *self.closed.borrow() {
+            return Err(crate::error::EventBusError::ChannelClosed);
+        }
+        // A payload on the wrong channel is a bug in whoever published it.
+        #[cfg(debug_assertions)]
+        if let Err(error) = channels::check(event) {
+            panic!("{error}");
+        }
+
+        self.sender_for_domain(event.channel.domain())
+            .ok_or_else(|| crate::error::EventBusError::InvalidChannel(event.channel.to_string()))
+    }
+
+    /// Live subscriber queues whose pattern matches `event`; dropped
+    /// subscriptions are pruned on the way.
+    fn matching_queues(&self, event: &Event) -> Vec<Arc<SubscriberQueue>> {
+        let mut queues = self.queues.lock().unwrap();
+        queues.retain(|queue| queue.strong_count() > 0);
+        queues
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|queue| queue.matches(event.channel.as_str()))
+            .collect()
+    }
+
     fn sender_for_domain(&self, domain: &str) -> Option<&broadcast::Sender<Event>> {
         match domain {
             "system" => Some(&self.system_sender),
@@ -656,26 +2056,70 @@ impl Default for BroadcastEventBus {
 #[cfg(feature = "native")]
 impl EventBus for BroadcastEventBus {
     fn publish(&self, event: Event) -> std::result::Result<(), crate::error::EventBusError> {
-        let sender = self
-            .sender_for_domain(event.channel.domain())
-            .ok_or_else(|| {
-                crate::error::EventBusError::InvalidChannel(event.channel.to_string())
-            })?;
+        let sender = self.sender_for_event(&event)?;
+        for queue in self.matching_queues(&event) {
+            queue.push(event.clone());
+        }
 
         let _ = sender.send(event);
         Ok(())
     }
 
-    fn subscribe(
+    fn publish_async(&self, event: Event) -> PublishFuture<'_> {
+        Box::pin(async move {
+            let sender = self.sender_for_event(&event)?;
+            for queue in self.matching_queues(&event) {
+                queue.push_async(event.clone()).await;
+            }
+
+            let _ = sender.send(event);
+            Ok(())
+        })
+    }
+
+    fn subscribe_with(
         &self,
         pattern: &str,
+        policy: OverflowPolicy,
     ) -> std::result::Result<EventSubscription, crate::error::EventBusError> {
         let matcher = Glob::new(pattern)
             .map_err(|_| crate::error::EventBusError::InvalidPattern(pattern.to_string()))?
             .compile_matcher();
         let receivers = self.receivers_for_pattern(pattern)?;
 
-        Ok(EventSubscription { matcher, receivers })
+        let (capacity, mode) = match policy {
+            OverflowPolicy::DropOldest => {
+                return Ok(EventSubscription {
+                    inner: SubscriptionInner::Broadcast(BroadcastSubscription {
+                        matcher,
+                        receivers,
+                        closed: self.closed.subscribe(),
+                    }),
+                });
+            }
+            OverflowPolicy::Block { capacity } => (capacity, QueueMode::Block),
+            OverflowPolicy::Spill { capacity, store } => (capacity, QueueMode::Spill(store)),
+        };
+
+        let queue = Arc::new(SubscriberQueue::new(
+            matcher,
+            capacity,
+            mode,
+            *self.closed.borrow(),
+        ));
+        self.queues.lock().unwrap().push(Arc::downgrade(&queue));
+        Ok(EventSubscription {
+            inner: SubscriptionInner::Queued(queue),
+        })
+    }
+
+    fn close(&self) {
+        self.closed.send_replace(true);
+        for queue in self.queues.lock().unwrap().iter() {
+            if let Some(queue) = queue.upgrade() {
+                queue.close();
+            }
+        }
     }
 }
 
@@ -687,15 +2131,58 @@ struct DomainReceivers {
     plugin: Option<broadcast::Receiver<Event>>,
 }
 
+#[cfg(feature = "native")]
+impl DomainReceivers {
+    fn try_recv(&mut self) -> Option<std::result::Result<Event, broadcast::error::RecvError>> {
+        [
+            self.system.as_mut(),
+            self.xmpp.as_mut(),
+            self.ui.as_mut(),
+            self.plugin.as_mut(),
+        ]
+        .into_iter()
+        .flatten()
+        .find_map(|receiver| match receiver.try_recv() {
+            Ok(event) => Some(Ok(event)),
+            Err(broadcast::error::TryRecvError::Lagged(count)) => {
+                Some(Err(broadcast::error::RecvError::Lagged(count)))
+            }
+            Err(_) => None,
+        })
+    }
+}
+
 #[cfg(feature = "native")]
 pub struct EventSubscription {
-    matcher: GlobMatcher,
-    receivers: DomainReceivers,
+    inner: SubscriptionInner,
+}
+
+#[cfg(feature = "native")]
+enum SubscriptionInner {
+    Broadcast(BroadcastSubscription),
+    Queued(Arc<SubscriberQueue>),
 }
 
 #[cfg(feature = "native")]
 impl EventSubscription {
     pub async fn recv(&mut self) -> std::result::Result<Event, crate::error::EventBusError> {
+        match &mut self.inner {
+            SubscriptionInner::Broadcast(subscription) => subscription.recv().await,
+            SubscriptionInner::Queued(queue) => queue.recv().await,
+        }
+    }
+}
+
+#[cfg(feature = "native")]
+struct BroadcastSubscription {
+    matcher: GlobMatcher,
+    receivers: DomainReceivers,
+    closed: watch::Receiver<bool>,
+}
+
+#[cfg(feature = "native")]
+impl BroadcastSubscription {
+    async fn recv(&mut self) -> std::result::Result<Event, crate::error::EventBusError> {
         loop {
             let system_receiver = self.receivers.system.as_mut();
             let xmpp_receiver = self.receivers.xmpp.as_mut();
@@ -707,6 +2194,13 @@ impl EventSubscription {
                 result = recv_from_domain(xmpp_receiver) => result,
                 result = recv_from_domain(ui_receiver) => result,
                 result = recv_from_domain(plugin_receiver) => result,
+                _ = self.closed.wait_for(|closed| *closed) => {
+                    // Events published before the close are still delivered.
+                    match self.receivers.try_recv() {
+                        Some(result) => result,
+                        None => return Err(crate::error::EventBusError::ChannelClosed),
+                    }
+                }
             };
 
             match received {
@@ -846,6 +2340,11 @@ mod tests {
                     message_type: MessageType::Chat,
                     thread: None,
                     embeds: vec![],
+                    origin_id: None,
+                    stanza_id: None,
+                    retracted_at: None,
+                    lang: None,
+                    translation: None,
                 },
             },
             corr_id,
@@ -922,6 +2421,11 @@ mod event_bus_tests {
                     message_type: MessageType::Chat,
                     thread: None,
                     embeds: vec![],
+                    origin_id: None,
+                    stanza_id: None,
+                    retracted_at: None,
+                    lang: None,
+                    translation: None,
                 },
             },
         ))
@@ -992,6 +2496,11 @@ mod event_bus_tests {
                     message_type: MessageType::Chat,
                     thread: None,
                     embeds: vec![],
+                    origin_id: None,
+                    stanza_id: None,
+                    retracted_at: None,
+                    lang: None,
+                    translation: None,
                 },
             },
         ))
@@ -1014,6 +2523,42 @@ mod event_bus_tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn close_delivers_queued_events_then_reports_closed() {
+        let bus = BroadcastEventBus::default();
+        let mut sub = bus.subscribe("system.**").unwrap();
+
+        bus.publish(make_event(
+            "system.startup.complete",
+            EventPayload::StartupComplete,
+        ))
+        .unwrap();
+        bus.close();
+
+        let result = bus.publish(make_event(
+            "system.startup.complete",
+            EventPayload::StartupComplete,
+        ));
+        assert!(matches!(
+            result,
+            Err(crate::error::EventBusError::ChannelClosed)
+        ));
+
+        let event = timeout(Duration::from_millis(100), sub.recv())
+            .await
+            .expect("timed out")
+            .unwrap();
+        assert_eq!(event.channel.as_str(), "system.startup.complete");
+
+        let result = timeout(Duration::from_millis(100), sub.recv())
+            .await
+            .expect("timed out");
+        assert!(matches!(
+            result,
+            Err(crate::error::EventBusError::ChannelClosed)
+        ));
+    }
+
     #[tokio::test]
     async fn multiple_subscribers_same_domain_each_get_event() {
         let bus = BroadcastEventBus::default();
@@ -1066,6 +2611,11 @@ mod event_bus_tests {
                     message_type: MessageType::Chat,
                     thread: None,
                     embeds: vec![],
+                    origin_id: None,
+                    stanza_id: None,
+                    retracted_at: None,
+                    lang: None,
+                    translation: None,
                 },
             },
         ))
@@ -1095,6 +2645,11 @@ mod event_bus_tests {
                     message_type: MessageType::Chat,
                     thread: None,
                     embeds: vec![],
+                    origin_id: None,
+                    stanza_id: None,
+                    retracted_at: None,
+                    lang: None,
+                    translation: None,
                 },
             },
         ))
@@ -1143,6 +2698,11 @@ mod event_bus_tests {
                     message_type: MessageType::Chat,
                     thread: None,
                     embeds: vec![],
+                    origin_id: None,
+                    stanza_id: None,
+                    retracted_at: None,
+                    lang: None,
+                    translation: None,
                 },
             },
         ))
@@ -1186,6 +2746,11 @@ mod event_bus_tests {
                     message_type: MessageType::Chat,
                     thread: None,
                     embeds: vec![],
+                    origin_id: None,
+                    stanza_id: None,
+                    retracted_at: None,
+                    lang: None,
+                    translation: None,
                 },
             },
         ))
@@ -1220,6 +2785,11 @@ mod event_bus_tests {
                     message_type: MessageType::Chat,
                     thread: None,
                     embeds: vec![],
+                    origin_id: None,
+                    stanza_id: None,
+                    retracted_at: None,
+                    lang: None,
+                    translation: None,
                 },
             },
         ))
@@ -1307,6 +2877,11 @@ mod event_bus_tests {
                         message_type: MessageType::Chat,
                         thread: None,
                         embeds: vec![],
+                        origin_id: None,
+                        stanza_id: None,
+                        retracted_at: None,
+                        lang: None,
+                        translation: None,
                     },
                 },
             ))
@@ -1397,6 +2972,11 @@ mod event_bus_tests {
                     message_type: MessageType::Chat,
                     thread: None,
                     embeds: vec![],
+                    origin_id: None,
+                    stanza_id: None,
+                    retracted_at: None,
+                    lang: None,
+                    translation: None,
                 },
             },
             corr_id,
@@ -1467,6 +3047,11 @@ mod event_bus_tests {
                     message_type: MessageType::Chat,
                     thread: None,
                     embeds: vec![],
+                    origin_id: None,
+                    stanza_id: None,
+                    retracted_at: None,
+                    lang: None,
+                    translation: None,
                 },
             },
             target_corr,
@@ -1486,6 +3071,11 @@ mod event_bus_tests {
                     message_type: MessageType::Chat,
                     thread: None,
                     embeds: vec![],
+                    origin_id: None,
+                    stanza_id: None,
+                    retracted_at: None,
+                    lang: None,
+                    translation: None,
                 },
             },
             other_corr,
@@ -1529,7 +3119,7 @@ mod event_bus_tests {
         // Overflow the small buffer
         for i in 0..10 {
             bus.publish(make_event(
-                "system.startup.complete",
+                "system.error.occurred",
                 EventPayload::ErrorOccurred {
                     component: "test".into(),
                     message: format!("event {i}"),
@@ -1554,7 +3144,7 @@ mod event_bus_tests {
         // Overflow to cause lag
         for i in 0..5 {
             bus.publish(make_event(
-                "system.startup.complete",
+                "system.error.occurred",
                 EventPayload::ErrorOccurred {
                     component: "test".into(),
                     message: format!("old {i}"),
@@ -1699,6 +3289,11 @@ mod event_bus_tests {
                 namespace: "urn:waddle:github:0".into(),
                 data: serde_json::json!({"owner": "cuenv", "name": "cuenv", "stars": 42}),
             }],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
         };
         let json = serde_json::to_string(&msg).unwrap();
         let parsed: ChatMessage = serde_json::from_str(&json).unwrap();
@@ -1719,6 +3314,11 @@ mod event_bus_tests {
             message_type: MessageType::Chat,
             thread: None,
             embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
         };
         let json = serde_json::to_string(&msg).unwrap();
         // The embeds field should be skipped when empty
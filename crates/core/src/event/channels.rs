@@ -0,0 +1,302 @@
+//! Every channel the application publishes on, with the payloads each
+//! carries.
+//!
+//! Publish and subscribe with these constants rather than string literals.
+//! The registry also records which [`EventPayload`] variants belong on each
+//! channel: debug builds assert it on every publish, and [`validate`] checks
+//! the registry itself at startup. Channels missing from the registry, such
+//! as those plugins publish under `plugin.<id>.`, are not checked.
+
+use std::collections::HashSet;
+
+use super::{Channel, Event, EventPayload};
+use crate::error::EventBusError;
+
+/// Declares each channel constant together with the payload variants it
+/// carries. A channel declared twice is an unreachable match arm, which the
+/// build rejects.
+macro_rules! channels {
+    ($(
+        $(#[$meta:meta])*
+        $name:ident = $channel:literal => [$($variant:ident),+ $(,)?];
+    )*) => {
+        $(
+            $(#[$meta])*
+            pub const $name: &str = $channel;
+        )*
+
+        /// Every declared channel.
+        pub const ALL: &[&str] = &[$($channel),*];
+
+        /// Whether `payload` belongs on `channel`, or `None` if the channel
+        /// is not declared.
+        #[deny(unreachable_patterns)]
+        pub fn allows(channel: &str, payload: &EventPayload) -> Option<bool> {
+            match channel {
+                $($channel => Some(matches!(payload, $(EventPayload::$variant { .. })|+)),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+channels! {
+    // ── system ──────────────────────────────────────────────────────
+    // Lifecycle, connection and manager state.
+    SYSTEM_ACCOUNT_DELETED = "system.account.deleted" => [AccountDeleted];
+    SYSTEM_ACCOUNT_PASSWORD_CHANGED = "system.account.password_changed" => [AccountPasswordChanged];
+    SYSTEM_ACCOUNT_REGISTERED = "system.account.registered" => [AccountRegistered];
+    SYSTEM_ACCOUNT_REGISTRATION_FAILED = "system.account.registration_failed" => [
+        AccountRegistrationFailed,
+    ];
+    SYSTEM_BACKUP_COMPLETED = "system.backup.completed" => [BackupCompleted];
+    SYSTEM_CALL_MEDIA = "system.call.media" => [CallMediaReceived];
+    SYSTEM_CALL_STATE_CHANGED = "system.call.state_changed" => [CallStateChanged];
+    SYSTEM_CAPABILITIES_CHANGED = "system.capabilities.changed" => [CapabilitiesChanged];
+    SYSTEM_COMING_ONLINE = "system.coming_online" => [ComingOnline];
+    SYSTEM_CONFIG_LOADED = "system.config.loaded" => [ConfigReloaded];
+    SYSTEM_CONFIG_RELOADED = "system.config.reloaded" => [ConfigReloaded];
+    SYSTEM_CONNECTION_ESTABLISHED = "system.connection.established" => [ConnectionEstablished];
+    SYSTEM_CONNECTION_HEALTH = "system.connection.health" => [ConnectionHealth];
+    SYSTEM_CONNECTION_LOST = "system.connection.lost" => [ConnectionLost];
+    SYSTEM_CONNECTION_RECONNECTING = "system.connection.reconnecting" => [ConnectionReconnecting];
+    SYSTEM_CONVERSATION_MUTE_CHANGED = "system.conversation.mute_changed" => [
+        ConversationMuteChanged,
+    ];
+    SYSTEM_CONVERSATION_READ_SYNCED = "system.conversation.read_synced" => [ReadStateSynced];
+    SYSTEM_CONVERSATION_UPDATED = "system.conversation.updated" => [ConversationUpdated];
+    SYSTEM_ERROR_OCCURRED = "system.error.occurred" => [ErrorOccurred];
+    SYSTEM_GOING_OFFLINE = "system.going_offline" => [GoingOffline];
+    SYSTEM_HEALTH_FEATURE_LOST = "system.health.feature_lost" => [ServerFeatureLost];
+    SYSTEM_HEALTH_UPLOAD_QUOTA_REDUCED = "system.health.upload_quota_reduced" => [
+        UploadQuotaReduced,
+    ];
+    SYSTEM_LOG_ENTRY = "system.log.entry" => [LogEntryRecorded];
+    SYSTEM_MANAGER_RESTARTED = "system.manager.restarted" => [ManagerRestarted];
+    SYSTEM_MESSAGE_RETRACTED = "system.message.retracted" => [MessageRetracted];
+    SYSTEM_MESSAGE_SCHEDULE_CANCELLED = "system.message.schedule_cancelled" => [
+        ScheduledMessageCancelled,
+    ];
+    SYSTEM_MESSAGE_SCHEDULE_SENT = "system.message.schedule_sent" => [ScheduledMessageSent];
+    SYSTEM_MESSAGE_SCHEDULED = "system.message.scheduled" => [MessageScheduled];
+    SYSTEM_MUC_REJOIN_STATUS = "system.muc.rejoin_status" => [MucRejoinStatusChanged];
+    SYSTEM_OMEMO_MESSAGE_UNDECRYPTABLE = "system.omemo.message.undecryptable" => [
+        OmemoMessageUndecryptable,
+    ];
+    SYSTEM_OMEMO_TRUST_CHANGED = "system.omemo.trust.changed" => [OmemoTrustChanged];
+    SYSTEM_PRESENCE_IDLE_CHANGED = "system.presence.idle_changed" => [ContactIdleChanged];
+    SYSTEM_RULE_DELETED = "system.rule.deleted" => [RuleDeleted];
+    SYSTEM_RULE_FIRED = "system.rule.fired" => [RuleFired];
+    SYSTEM_RULE_PLUGIN_HOOK = "system.rule.plugin_hook" => [RulePluginHookRequested];
+    SYSTEM_RULE_SAVED = "system.rule.saved" => [RuleSaved];
+    SYSTEM_SHUTDOWN_REQUESTED = "system.shutdown.requested" => [ShutdownRequested];
+    SYSTEM_STARTUP_COMPLETE = "system.startup.complete" => [StartupComplete];
+    SYSTEM_STORAGE_MAINTENANCE_COMPLETED = "system.storage.maintenance_completed" => [
+        StorageMaintenanceCompleted,
+    ];
+    SYSTEM_STORAGE_PRUNED = "system.storage.pruned" => [StoragePruned];
+    SYSTEM_STORAGE_READY = "system.storage.ready" => [StartupComplete];
+    SYSTEM_STORAGE_STATS = "system.storage.stats" => [StorageStats];
+    SYSTEM_SYNC_COMPLETED = "system.sync.completed" => [SyncCompleted];
+    SYSTEM_SYNC_FAILED = "system.sync.failed" => [SyncFailed];
+    SYSTEM_SYNC_GAP_DETECTED = "system.sync.gap_detected" => [SyncGapDetected];
+    SYSTEM_SYNC_PROGRESS = "system.sync.progress" => [SyncProgress];
+    SYSTEM_SYNC_STARTED = "system.sync.started" => [SyncStarted];
+    SYSTEM_SYNC_TASK_FINISHED = "system.sync.task.finished" => [SyncTaskFinished];
+    SYSTEM_SYNC_TASK_STARTED = "system.sync.task.started" => [SyncTaskStarted];
+    SYSTEM_THEME_APPLIED = "system.theme.applied" => [ThemeApplied];
+    SYSTEM_TRANSFER_COMPLETED = "system.transfer.completed" => [TransferCompleted];
+    SYSTEM_TRANSFER_FAILED = "system.transfer.failed" => [TransferFailed];
+
+    // ── xmpp ────────────────────────────────────────────────────────
+    // What arrived from, or was sent to, the server.
+    XMPP_ACCOUNT_ANSWERED = "xmpp.account.answered" => [AccountRequestAnswered];
+    XMPP_CAPS_RECEIVED = "xmpp.caps.received" => [EntityCapsReceived];
+    XMPP_CHATSTATE_RECEIVED = "xmpp.chatstate.received" => [ChatStateReceived];
+    XMPP_DEBUG_STANZA_RECEIVED = "xmpp.debug.stanza.received" => [RawStanzaReceived];
+    XMPP_DEBUG_STANZA_SENT = "xmpp.debug.stanza.sent" => [RawStanzaSent];
+    XMPP_DISCO_INFO_FAILED = "xmpp.disco.info.failed" => [DiscoInfoFailed];
+    XMPP_DISCO_INFO_RECEIVED = "xmpp.disco.info.received" => [DiscoInfoReceived];
+    XMPP_DISCO_ITEMS_FAILED = "xmpp.disco.items.failed" => [DiscoItemsFailed];
+    XMPP_DISCO_ITEMS_RECEIVED = "xmpp.disco.items.received" => [DiscoItemsReceived];
+    XMPP_FORM_REQUESTED = "xmpp.form.requested" => [FormRequested];
+    XMPP_JINGLE_ANSWERED = "xmpp.jingle.answered" => [JingleAnswered];
+    XMPP_JINGLE_RECEIVED = "xmpp.jingle.received" => [JingleReceived];
+    XMPP_MAM_FIN_RECEIVED = "xmpp.mam.fin.received" => [MamFinReceived];
+    XMPP_MAM_QUERY_FAILED = "xmpp.mam.query.failed" => [MamQueryFailed];
+    XMPP_MAM_RESULT_RECEIVED = "xmpp.mam.result.received" => [MamResultReceived];
+    XMPP_MESSAGE_DELIVERED = "xmpp.message.delivered" => [MessageDelivered];
+    XMPP_MESSAGE_DISPLAYED = "xmpp.message.displayed" => [MessageDisplayed];
+    XMPP_MESSAGE_DISPLAYED_ELSEWHERE = "xmpp.message.displayed_elsewhere" => [
+        MessageDisplayedElsewhere,
+    ];
+    XMPP_MESSAGE_RECEIPT_REQUESTED = "xmpp.message.receipt_requested" => [ReceiptRequested];
+    XMPP_MESSAGE_RECEIVED = "xmpp.message.received" => [MessageReceived];
+    XMPP_MESSAGE_RETRACTED = "xmpp.message.retracted" => [MessageRetractionReceived];
+    XMPP_MESSAGE_SENT = "xmpp.message.sent" => [MessageSent];
+    XMPP_MUC_INVITE_RECEIVED = "xmpp.muc.invite.received" => [MucInviteReceived];
+    XMPP_MUC_JOINED = "xmpp.muc.joined" => [MucJoined];
+    XMPP_MUC_LEFT = "xmpp.muc.left" => [MucLeft];
+    XMPP_MUC_MESSAGE_RECEIVED = "xmpp.muc.message.received" => [MucMessageReceived];
+    XMPP_MUC_MESSAGE_RETRACTED = "xmpp.muc.message.retracted" => [MessageRetractionReceived];
+    XMPP_MUC_OCCUPANT_CHANGED = "xmpp.muc.occupant.changed" => [MucOccupantChanged];
+    XMPP_MUC_OCCUPANT_RENAMED = "xmpp.muc.occupant.renamed" => [MucOccupantRenamed];
+    XMPP_MUC_SUBJECT_CHANGED = "xmpp.muc.subject.changed" => [MucSubjectChanged];
+    XMPP_OMEMO_BUNDLE_RECEIVED = "xmpp.omemo.bundle.received" => [OmemoBundleReceived];
+    XMPP_OMEMO_DEVICELIST_RECEIVED = "xmpp.omemo.devicelist.received" => [OmemoDeviceListReceived];
+    XMPP_OMEMO_MESSAGE_RECEIVED = "xmpp.omemo.message.received" => [OmemoMessageReceived];
+    XMPP_PRESENCE_CHANGED = "xmpp.presence.changed" => [PresenceChanged];
+    XMPP_PRESENCE_OWN_CHANGED = "xmpp.presence.own_changed" => [OwnPresenceChanged];
+    XMPP_PUBSUB_ANSWERED = "xmpp.pubsub.answered" => [PubSubRequestAnswered];
+    XMPP_PUBSUB_ITEMS = "xmpp.pubsub.items" => [PubSubItemsReceived];
+    XMPP_PUBSUB_NODE_DELETED = "xmpp.pubsub.node_deleted" => [PubSubNodeDeleted];
+    XMPP_PUBSUB_NODE_PURGED = "xmpp.pubsub.node_purged" => [PubSubNodePurged];
+    XMPP_PUBSUB_RETRACTED = "xmpp.pubsub.retracted" => [PubSubItemsRetracted];
+    XMPP_ROSTER_RECEIVED = "xmpp.roster.received" => [RosterReceived];
+    XMPP_ROSTER_REMOVED = "xmpp.roster.removed" => [RosterRemoved];
+    XMPP_ROSTER_SET_FAILED = "xmpp.roster.set_failed" => [RosterSetFailed];
+    XMPP_ROSTER_UNCHANGED = "xmpp.roster.unchanged" => [RosterUnchanged];
+    XMPP_ROSTER_UPDATED = "xmpp.roster.updated" => [RosterUpdated];
+    XMPP_ROSTER_VERSION_CHANGED = "xmpp.roster.version_changed" => [RosterVersionChanged];
+    XMPP_SUBSCRIPTION_APPROVED = "xmpp.subscription.approved" => [SubscriptionApproved];
+    XMPP_SUBSCRIPTION_REQUEST = "xmpp.subscription.request" => [SubscriptionRequest];
+    XMPP_SUBSCRIPTION_REVOKED = "xmpp.subscription.revoked" => [SubscriptionRevoked];
+
+    // ── ui ──────────────────────────────────────────────────────────
+    // Requests from frontends, and what they render.
+    UI_ACCOUNT_REGISTER = "ui.account.register" => [AccountRegistrationRequested];
+    UI_BLOCKING_BLOCK = "ui.blocking.block" => [BlockRequested];
+    UI_BLOCKING_UNBLOCK = "ui.blocking.unblock" => [UnblockRequested];
+    UI_CHATSTATE_SEND = "ui.chatstate.send" => [ChatStateSendRequested];
+    UI_CLIENT_ACTIVITY = "ui.client.activity" => [ClientActivityChanged];
+    UI_COMPOSE_STARTED = "ui.compose.started" => [ComposeStarted];
+    UI_CONVERSATION_OPENED = "ui.conversation.opened" => [ConversationOpened];
+    UI_DISCO_INFO = "ui.disco.info" => [DiscoInfoRequested];
+    UI_DISCO_ITEMS = "ui.disco.items" => [DiscoItemsRequested];
+    UI_FORM_SUBMITTED = "ui.form.submitted" => [FormSubmitted];
+    UI_HISTORY_PAGE_LOADED = "ui.history.page_loaded" => [HistoryPageLoaded];
+    UI_JINGLE_ANSWER = "ui.jingle.answer" => [JingleAnswerRequested];
+    UI_JINGLE_SEND = "ui.jingle.send" => [JingleSendRequested];
+    UI_MAM_QUERY = "ui.mam.query" => [MamQueryRequested];
+    UI_MESSAGE_PREVIEW_READY = "ui.message.preview_ready" => [LinkPreviewReady];
+    UI_MESSAGE_RETRACT = "ui.message.retract" => [MessageRetractRequested];
+    UI_MESSAGE_SEND = "ui.message.send" => [MessageSendRequested];
+    UI_MESSAGE_TRANSLATION_PROVIDED = "ui.message.translation_provided" => [
+        MessageTranslationProvided,
+    ];
+    UI_MESSAGE_TRANSLATION_READY = "ui.message.translation_ready" => [MessageTranslated];
+    UI_MESSAGE_TRANSLATION_REQUESTED = "ui.message.translation_requested" => [
+        MessageTranslationRequested,
+    ];
+    UI_MUC_INVITE = "ui.muc.invite" => [MucInviteSendRequested];
+    UI_MUC_INVITE_DECLINE = "ui.muc.invite.decline" => [MucInviteDeclineRequested];
+    UI_MUC_JOIN = "ui.muc.join" => [MucJoinRequested];
+    UI_MUC_LEAVE = "ui.muc.leave" => [MucLeaveRequested];
+    UI_MUC_MODERATE = "ui.muc.moderate" => [MessageModerateRequested];
+    UI_MUC_SEND = "ui.muc.send" => [MucSendRequested];
+    UI_NOTIFICATION_CLICKED = "ui.notification.clicked" => [NotificationClicked];
+    UI_NOTIFICATION_MENTION = "ui.notification.mention" => [MucMentionReceived];
+    UI_NOTIFICATION_PREFERENCE = "ui.notification.preference" => [NotificationPreferenceChanged];
+    UI_NOTIFICATION_SHOW = "ui.notification.show" => [NotificationShowRequested];
+    UI_OMEMO_BUNDLE_FETCH = "ui.omemo.bundle.fetch" => [OmemoBundleFetchRequested];
+    UI_OMEMO_BUNDLE_PUBLISH = "ui.omemo.bundle.publish" => [OmemoBundlePublishRequested];
+    UI_OMEMO_DEVICELIST_FETCH = "ui.omemo.devicelist.fetch" => [OmemoDeviceListFetchRequested];
+    UI_OMEMO_DEVICELIST_PUBLISH = "ui.omemo.devicelist.publish" => [
+        OmemoDeviceListPublishRequested,
+    ];
+    UI_OMEMO_MESSAGE_SEND = "ui.omemo.message.send" => [OmemoMessageSendRequested];
+    UI_PRESENCE_DIRECTED = "ui.presence.directed" => [DirectedPresenceRequested];
+    UI_PRESENCE_INVISIBILITY = "ui.presence.invisibility" => [InvisibilitySetRequested];
+    UI_PRESENCE_SET = "ui.presence.set" => [PresenceSetRequested];
+    UI_PROFILE_UPDATED = "ui.profile.updated" => [ProfileUpdated];
+    UI_RECEIPT_SEND = "ui.receipt.send" => [ReceiptSendRequested];
+    UI_ROSTER_ADD = "ui.roster.add" => [RosterAddRequested];
+    UI_ROSTER_ADD_FAILED = "ui.roster.add_failed" => [RosterAddFailed];
+    UI_ROSTER_FETCH = "ui.roster.fetch" => [RosterFetchRequested];
+    UI_ROSTER_REMOVE = "ui.roster.remove" => [RosterRemoveRequested];
+    UI_ROSTER_SUBSCRIPTION_PENDING = "ui.roster.subscription_pending" => [SubscriptionPending];
+    UI_ROSTER_UPDATE = "ui.roster.update" => [RosterUpdateRequested];
+    UI_RULE_DELETE = "ui.rule.delete" => [RuleDeleteRequested];
+    UI_RULE_SAVE = "ui.rule.save" => [RuleSaveRequested];
+    UI_SCROLL_REQUESTED = "ui.scroll.requested" => [ScrollRequested];
+    UI_SUBSCRIPTION_RESPOND = "ui.subscription.respond" => [SubscriptionRespondRequested];
+    UI_SUBSCRIPTION_SEND = "ui.subscription.send" => [SubscriptionSendRequested];
+    UI_THEME_CHANGED = "ui.theme.changed" => [ThemeChanged];
+    UI_TRANSFER_PROGRESS = "ui.transfer.progress" => [TransferProgress];
+
+    // ── plugin ──────────────────────────────────────────────────────
+    // Plugin runtime. Channels under `plugin.<id>.` belong to each plugin and are not declared.
+    PLUGIN_INSTALL_COMPLETED = "plugin.install.completed" => [PluginInstallCompleted];
+    PLUGIN_INSTALL_STARTED = "plugin.install.started" => [PluginInstallStarted];
+    PLUGIN_PERMISSION_DENIED = "plugin.permission.denied" => [PluginPermissionDenied];
+    PLUGIN_USAGE_SNAPSHOT = "plugin.usage.snapshot" => [PluginUsageSnapshot];
+}
+
+/// Check the registry: every declared name must be a valid channel, and
+/// declared once. Call at startup, before anything publishes.
+pub fn validate() -> Result<(), EventBusError> {
+    let mut seen = HashSet::new();
+    for name in ALL {
+        if !Channel::is_valid(name) || !seen.insert(*name) {
+            return Err(EventBusError::InvalidChannel((*name).to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Check that `event`'s payload belongs on its channel. Events on channels
+/// that are not declared pass.
+pub fn check(event: &Event) -> Result<(), EventBusError> {
+    match allows(event.channel.as_str(), &event.payload) {
+        Some(false) => Err(EventBusError::PayloadMismatch {
+            channel: event.channel.to_string(),
+            payload: payload_type(&event.payload),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// The payload's variant as it is tagged on the wire, e.g. `messageReceived`.
+fn payload_type(payload: &EventPayload) -> String {
+    serde_json::to_value(payload)
+        .ok()
+        .and_then(|value| value.get("type")?.as_str().map(String::from))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::EventSource;
+
+    #[test]
+    fn registry_is_valid() {
+        validate().unwrap();
+    }
+
+    #[test]
+    fn payloads_are_checked_against_declared_channels() {
+        let event = |channel: &str, payload| {
+            Event::new(Channel::new(channel).unwrap(), EventSource::Xmpp, payload)
+        };
+
+        assert!(
+            check(&event(
+                SYSTEM_STARTUP_COMPLETE,
+                EventPayload::StartupComplete
+            ))
+            .is_ok()
+        );
+        assert!(matches!(
+            check(&event(SYSTEM_STARTUP_COMPLETE, EventPayload::GoingOffline)),
+            Err(EventBusError::PayloadMismatch { ref payload, .. }) if payload == "goingOffline"
+        ));
+        assert!(
+            check(&event(
+                "plugin.example.anything",
+                EventPayload::GoingOffline
+            ))
+            .is_ok()
+        );
+    }
+}
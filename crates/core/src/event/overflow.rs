@@ -0,0 +1,418 @@
+//! Bounded per-subscriber queues for subscribers that must not lose events.
+//!
+//! Broadcast subscriptions share one ring buffer per domain and drop the
+//! oldest events when a subscriber falls behind. A subscription made with
+//! [`OverflowPolicy::Block`] or [`OverflowPolicy::Spill`] gets its own queue
+//! instead, filled by `publish()` in publish order.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use globset::GlobMatcher;
+use tokio::sync::{Notify, Semaphore};
+use tracing::{error, warn};
+
+use super::Event;
+use crate::error::EventBusError;
+
+/// What happens to events published faster than a subscriber reads them.
+#[derive(Clone)]
+pub enum OverflowPolicy {
+    /// Drop the oldest undelivered events; the subscriber sees `Lagged`.
+    DropOldest,
+    /// Queue up to `capacity` events, then make `publish_async()` wait for
+    /// room. `publish()` cannot wait, so it queues past the capacity
+    /// instead. A subscriber must not wait to publish to channels it
+    /// subscribes to, or it can end up waiting on itself.
+    Block { capacity: usize },
+    /// Keep up to `capacity` events in memory and write later ones to
+    /// `store` until the subscriber catches up.
+    Spill {
+        capacity: usize,
+        store: Arc<dyn SpillStore>,
+    },
+}
+
+/// Durable overflow for [`OverflowPolicy::Spill`]. Events come back out in
+/// the order they went in.
+pub trait SpillStore: Send + Sync + 'static {
+    fn push(&self, event: &Event) -> Result<(), EventBusError>;
+
+    /// Return up to `max` of the oldest spilled events without removing
+    /// them.
+    fn peek_batch(&self, max: usize) -> Result<Vec<Event>, EventBusError>;
+
+    /// Remove the `count` oldest events, which the subscriber has now been
+    /// through.
+    fn ack(&self, count: usize) -> Result<(), EventBusError>;
+}
+
+pub(super) enum QueueMode {
+    Block,
+    Spill(Arc<dyn SpillStore>),
+}
+
+struct QueueState {
+    events: VecDeque<Event>,
+    /// Events queued by `publish()` past a full Block queue; they hold no
+    /// permit, so reading them frees no room.
+    overrun: usize,
+    /// Whether `store` may hold events older than new arrivals, which must
+    /// then be spilled behind them.
+    spilled: bool,
+    /// Events read from `store` that it still holds. They are acknowledged
+    /// once the subscriber comes back for more, so a crash while handling
+    /// them delivers them again.
+    unacked: usize,
+    closed: bool,
+}
+
+pub(super) struct SubscriberQueue {
+    matcher: GlobMatcher,
+    capacity: usize,
+    mode: QueueMode,
+    state: Mutex<QueueState>,
+    /// One permit per free slot in a Block queue.
+    room: Semaphore,
+    ready: Notify,
+}
+
+impl SubscriberQueue {
+    pub(super) fn new(
+        matcher: GlobMatcher,
+        capacity: usize,
+        mode: QueueMode,
+        closed: bool,
+    ) -> Self {
+        let capacity = capacity.max(1);
+        // A store reused after a restart may still hold undelivered events.
+        let (events, spilled) = match &mode {
+            QueueMode::Block => (VecDeque::new(), false),
+            QueueMode::Spill(store) => match store.peek_batch(capacity) {
+                Ok(batch) => {
+                    let spilled = !batch.is_empty();
+                    (batch.into(), spilled)
+                }
+                Err(e) => {
+                    error!(error = %e, "failed to read spilled events");
+                    (VecDeque::new(), true)
+                }
+            },
+        };
+        let room = Semaphore::new(capacity);
+        if closed {
+            room.close();
+        }
+        Self {
+            matcher,
+            capacity,
+            mode,
+            state: Mutex::new(QueueState {
+                unacked: events.len(),
+                events,
+                overrun: 0,
+                spilled,
+                closed,
+            }),
+            room,
+            ready: Notify::new(),
+        }
+    }
+
+    pub(super) fn matches(&self, channel: &str) -> bool {
+        self.matcher.is_match(channel)
+    }
+
+    pub(super) fn push(&self, event: Event) {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return;
+        }
+        match &self.mode {
+            QueueMode::Block => match self.room.try_acquire() {
+                Ok(permit) => permit.forget(),
+                Err(_) => {
+                    if state.overrun == 0 {
+                        warn!(
+                            capacity = self.capacity,
+                            "subscriber queue full, publish() is queueing past it"
+                        );
+                    }
+                    state.overrun += 1;
+                }
+            },
+            QueueMode::Spill(store) => {
+                if state.spilled || state.events.len() >= self.capacity {
+                    match store.push(&event) {
+                        Ok(()) => {
+                            state.spilled = true;
+                            drop(state);
+                            self.ready.notify_one();
+                            return;
+                        }
+                        Err(e) => error!(error = %e, "failed to spill event, keeping it in memory"),
+                    }
+                }
+            }
+        }
+        state.events.push_back(event);
+        drop(state);
+        self.ready.notify_one();
+    }
+
+    /// Like `push`, but waits for room in a full Block queue.
+    pub(super) async fn push_async(&self, event: Event) {
+        if !matches!(self.mode, QueueMode::Block) {
+            return self.push(event);
+        }
+        match self.room.acquire().await {
+            Ok(permit) => permit.forget(),
+            // Closed while waiting; nobody will read the event.
+            Err(_) => return,
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return;
+        }
+        state.events.push_back(event);
+        drop(state);
+        self.ready.notify_one();
+    }
+
+    pub(super) fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.room.close();
+        self.ready.notify_one();
+    }
+
+    pub(super) async fn recv(&self) -> Result<Event, EventBusError> {
+        loop {
+            let ready = self.ready.notified();
+            if let Some(result) = self.try_recv() {
+                return result;
+            }
+            ready.await;
+        }
+    }
+
+    fn try_recv(&self) -> Option<Result<Event, EventBusError>> {
+        let mut state = self.state.lock().unwrap();
+        if state.events.is_empty()
+            && state.spilled
+            && let QueueMode::Spill(store) = &self.mode
+        {
+            if state.unacked > 0 {
+                if let Err(e) = store.ack(state.unacked) {
+                    return Some(Err(e));
+                }
+                state.unacked = 0;
+            }
+            match store.peek_batch(self.capacity) {
+                Ok(batch) if batch.is_empty() => state.spilled = false,
+                Ok(batch) => {
+                    state.unacked = batch.len();
+                    state.events.extend(batch);
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        if let Some(event) = state.events.pop_front() {
+            if matches!(self.mode, QueueMode::Block) {
+                if state.overrun > 0 {
+                    state.overrun -= 1;
+                } else {
+                    self.room.add_permits(1);
+                }
+            }
+            return Some(Ok(event));
+        }
+        state.closed.then_some(Err(EventBusError::ChannelClosed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::event::{BroadcastEventBus, Channel, EventBus, EventPayload, EventSource};
+
+    #[derive(Default)]
+    struct MemorySpill {
+        events: Mutex<VecDeque<Event>>,
+    }
+
+    impl SpillStore for MemorySpill {
+        fn push(&self, event: &Event) -> Result<(), EventBusError> {
+            self.events.lock().unwrap().push_back(event.clone());
+            Ok(())
+        }
+
+        fn peek_batch(&self, max: usize) -> Result<Vec<Event>, EventBusError> {
+            let events = self.events.lock().unwrap();
+            Ok(events.iter().take(max).cloned().collect())
+        }
+
+        fn ack(&self, count: usize) -> Result<(), EventBusError> {
+            self.events.lock().unwrap().drain(..count);
+            Ok(())
+        }
+    }
+
+    fn sync_completed(count: u64) -> Event {
+        Event::new(
+            Channel::new("system.sync.completed").unwrap(),
+            EventSource::System("test".into()),
+            EventPayload::SyncCompleted {
+                messages_synced: count,
+            },
+        )
+    }
+
+    fn synced(event: &Event) -> u64 {
+        match event.payload {
+            EventPayload::SyncCompleted { messages_synced } => messages_synced,
+            _ => panic!("unexpected event {event:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn spill_keeps_every_event_in_order() {
+        let bus = BroadcastEventBus::default();
+        let store = Arc::new(MemorySpill::default());
+        let mut sub = bus
+            .subscribe_with(
+                "system.**",
+                OverflowPolicy::Spill {
+                    capacity: 2,
+                    store: store.clone(),
+                },
+            )
+            .unwrap();
+
+        for count in 0..10 {
+            bus.publish(sync_completed(count)).unwrap();
+        }
+        assert_eq!(store.events.lock().unwrap().len(), 8);
+
+        bus.publish(sync_completed(10)).unwrap();
+        for count in 0..=10 {
+            assert_eq!(synced(&sub.recv().await.unwrap()), count);
+        }
+        // Only the last batch is left, until the subscriber asks for more.
+        assert_eq!(store.events.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn spilled_events_stay_stored_until_the_subscriber_is_through_them() {
+        let bus = BroadcastEventBus::default();
+        let store = Arc::new(MemorySpill::default());
+        let mut sub = bus
+            .subscribe_with(
+                "system.**",
+                OverflowPolicy::Spill {
+                    capacity: 1,
+                    store: store.clone(),
+                },
+            )
+            .unwrap();
+
+        for count in 0..3 {
+            bus.publish(sync_completed(count)).unwrap();
+        }
+        assert_eq!(synced(&sub.recv().await.unwrap()), 0);
+        assert_eq!(synced(&sub.recv().await.unwrap()), 1);
+        assert_eq!(store.events.lock().unwrap().len(), 2);
+
+        assert_eq!(synced(&sub.recv().await.unwrap()), 2);
+        assert_eq!(store.events.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn block_makes_publisher_wait_for_room() {
+        let bus = Arc::new(BroadcastEventBus::default());
+        let mut sub = bus
+            .subscribe_with("system.**", OverflowPolicy::Block { capacity: 2 })
+            .unwrap();
+
+        let publisher = tokio::spawn({
+            let bus = bus.clone();
+            async move {
+                for count in 0..5 {
+                    bus.publish_async(sync_completed(count)).await.unwrap();
+                }
+            }
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!publisher.is_finished());
+
+        for count in 0..5 {
+            assert_eq!(synced(&sub.recv().await.unwrap()), count);
+        }
+        publisher.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn publish_queues_past_a_full_block_queue() {
+        let bus = BroadcastEventBus::default();
+        let mut sub = bus
+            .subscribe_with("system.**", OverflowPolicy::Block { capacity: 1 })
+            .unwrap();
+
+        for count in 0..3 {
+            bus.publish(sync_completed(count)).unwrap();
+        }
+        for count in 0..3 {
+            assert_eq!(synced(&sub.recv().await.unwrap()), count);
+        }
+
+        // Reading the overrun freed no room beyond the capacity.
+        bus.publish_async(sync_completed(3)).await.unwrap();
+        let waiting = bus.publish_async(sync_completed(4));
+        tokio::pin!(waiting);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), &mut waiting)
+                .await
+                .is_err()
+        );
+        assert_eq!(synced(&sub.recv().await.unwrap()), 3);
+        waiting.await.unwrap();
+        assert_eq!(synced(&sub.recv().await.unwrap()), 4);
+    }
+
+    #[tokio::test]
+    async fn close_releases_waiting_publisher() {
+        let bus = Arc::new(BroadcastEventBus::default());
+        let _sub = bus
+            .subscribe_with("system.**", OverflowPolicy::Block { capacity: 1 })
+            .unwrap();
+        bus.publish(sync_completed(0)).unwrap();
+
+        let publisher = tokio::spawn({
+            let bus = bus.clone();
+            async move { bus.publish_async(sync_completed(1)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        bus.close();
+        publisher.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn close_releases_queued_subscriber() {
+        let bus = BroadcastEventBus::default();
+        let mut sub = bus
+            .subscribe_with("system.**", OverflowPolicy::Block { capacity: 4 })
+            .unwrap();
+
+        bus.publish(sync_completed(1)).unwrap();
+        bus.close();
+
+        assert_eq!(synced(&sub.recv().await.unwrap()), 1);
+        assert!(matches!(
+            sub.recv().await,
+            Err(EventBusError::ChannelClosed)
+        ));
+    }
+}
@@ -0,0 +1,215 @@
+//! Event recording and replay for development builds (the `debug` feature).
+//!
+//! [`RecordingEventBus`] wraps the session's bus and appends every event it
+//! publishes to a file, one JSON object per line, numbered in publish order.
+//! [`Replayer`] reads such a recording back and publishes its events on
+//! another bus, usually a fresh one with fresh managers subscribed, at the
+//! recorded pace or faster. Replay only the events managers react to, such
+//! as `xmpp.**`: the fresh managers publish their own responses again.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use globset::{Glob, GlobMatcher};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::{Event, EventBus, EventSubscription, OverflowPolicy, PublishFuture};
+use crate::error::EventBusError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    #[error("recording I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("invalid recording at line {line}: {source}")]
+    Parse {
+        line: usize,
+        source: serde_json::Error,
+    },
+
+    #[error("event bus error: {0}")]
+    EventBus(#[from] EventBusError),
+}
+
+/// One line of a recording.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    /// Position in publish order, starting at 1.
+    pub seq: u64,
+    pub event: Event,
+}
+
+struct Recorder {
+    next_seq: u64,
+    file: BufWriter<File>,
+}
+
+/// An [`EventBus`] that records every published event before passing it on
+/// to `inner`. Events are numbered and forwarded under one lock, so the
+/// recorded order is the order subscribers see.
+pub struct RecordingEventBus {
+    inner: Arc<dyn EventBus>,
+    recorder: Mutex<Recorder>,
+}
+
+impl RecordingEventBus {
+    /// Record to `path`, replacing any earlier recording there.
+    pub fn create(inner: Arc<dyn EventBus>, path: &Path) -> Result<Self, ReplayError> {
+        Ok(Self {
+            inner,
+            recorder: Mutex::new(Recorder {
+                next_seq: 1,
+                file: BufWriter::new(File::create(path)?),
+            }),
+        })
+    }
+}
+
+impl RecordingEventBus {
+    fn record(&self, event: Event) -> Event {
+        let mut recorder = self.recorder.lock().unwrap();
+        let recorded = RecordedEvent {
+            seq: recorder.next_seq,
+            event,
+        };
+        recorder.next_seq += 1;
+        // Flushed per event, so a recording survives the crash it is meant
+        // to explain. A failed write never keeps the event from subscribers.
+        let written = serde_json::to_writer(&mut recorder.file, &recorded)
+            .map_err(std::io::Error::from)
+            .and_then(|()| recorder.file.write_all(b"\n"))
+            .and_then(|()| recorder.file.flush());
+        if let Err(error) = written {
+            warn!(%error, seq = recorded.seq, "failed to record event");
+        }
+        recorded.event
+    }
+}
+
+impl EventBus for RecordingEventBus {
+    fn publish(&self, event: Event) -> Result<(), EventBusError> {
+        self.inner.publish(self.record(event))
+    }
+
+    fn publish_async(&self, event: Event) -> PublishFuture<'_> {
+        self.inner.publish_async(self.record(event))
+    }
+
+    fn subscribe_with(
+        &self,
+        pattern: &str,
+        policy: OverflowPolicy,
+    ) -> Result<EventSubscription, EventBusError> {
+        self.inner.subscribe_with(pattern, policy)
+    }
+
+    fn close(&self) {
+        self.inner.close();
+    }
+}
+
+/// Read the recording at `path`, in sequence order.
+pub fn read_recording(path: &Path) -> Result<Vec<RecordedEvent>, ReplayError> {
+    let mut events = Vec::new();
+    for (index, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str(&line).map_err(|source| ReplayError::Parse {
+            line: index + 1,
+            source,
+        })?;
+        events.push(event);
+    }
+    events.sort_by_key(|recorded: &RecordedEvent| recorded.seq);
+    Ok(events)
+}
+
+/// How fast [`Replayer::replay`] publishes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Keep the recorded gaps between events.
+    Original,
+    /// Divide the recorded gaps by this factor.
+    Accelerated(f64),
+    /// No gaps, only a yield between events so subscribers keep up.
+    Instant,
+}
+
+/// Publishes recorded events again, for reproducing a session against
+/// fresh managers.
+pub struct Replayer {
+    events: Vec<RecordedEvent>,
+    speed: ReplaySpeed,
+    filter: Option<GlobMatcher>,
+}
+
+impl Replayer {
+    pub fn new(events: Vec<RecordedEvent>) -> Self {
+        Self {
+            events,
+            speed: ReplaySpeed::Original,
+            filter: None,
+        }
+    }
+
+    /// Replay the recording at `path`.
+    pub fn open(path: &Path) -> Result<Self, ReplayError> {
+        Ok(Self::new(read_recording(path)?))
+    }
+
+    pub fn with_speed(mut self, speed: ReplaySpeed) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Only replay events on channels matching `pattern`, in the syntax of
+    /// [`EventBus::subscribe`].
+    pub fn with_filter(mut self, pattern: &str) -> Result<Self, ReplayError> {
+        let matcher = Glob::new(pattern)
+            .map_err(|_| EventBusError::InvalidPattern(pattern.to_string()))?
+            .compile_matcher();
+        self.filter = Some(matcher);
+        Ok(self)
+    }
+
+    /// Publish the selected events on `event_bus` in sequence order, and
+    /// return how many were published.
+    pub async fn replay(&self, event_bus: &dyn EventBus) -> Result<usize, ReplayError> {
+        let mut published = 0;
+        let mut previous: Option<DateTime<Utc>> = None;
+        for recorded in &self.events {
+            let event = &recorded.event;
+            if let Some(filter) = &self.filter
+                && !filter.is_match(event.channel.as_str())
+            {
+                continue;
+            }
+            if let Some(previous) = previous {
+                // Clocks can step backwards; such gaps count as none.
+                let gap = (event.timestamp - previous)
+                    .to_std()
+                    .unwrap_or(Duration::ZERO);
+                match self.speed {
+                    ReplaySpeed::Original => tokio::time::sleep(gap).await,
+                    ReplaySpeed::Accelerated(factor) if factor > 0.0 => {
+                        tokio::time::sleep(gap.div_f64(factor)).await
+                    }
+                    ReplaySpeed::Accelerated(_) | ReplaySpeed::Instant => {
+                        tokio::task::yield_now().await
+                    }
+                }
+            }
+            previous = Some(event.timestamp);
+            event_bus.publish(event.clone())?;
+            published += 1;
+        }
+        Ok(published)
+    }
+}
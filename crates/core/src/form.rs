@@ -0,0 +1,377 @@
+//! XEP-0004 data forms shared by every feature that asks the user for
+//! structured input (ad-hoc commands, MUC configuration, registration,
+//! CAPTCHA challenges). XML conversion lives in `waddle-xmpp`.
+
+use serde::{Deserialize, Serialize};
+
+/// Name of the hidden field that identifies a form's schema (XEP-0068).
+pub const FORM_TYPE_VAR: &str = "FORM_TYPE";
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum FormError {
+    #[error("required field '{0}' has no value")]
+    MissingRequired(String),
+
+    #[error("field '{0}' accepts a single value")]
+    TooManyValues(String),
+
+    #[error("field '{var}' expects a boolean, got '{value}'")]
+    InvalidBoolean { var: String, value: String },
+
+    #[error("field '{var}' expects a JID, got '{value}'")]
+    InvalidJid { var: String, value: String },
+
+    #[error("field '{var}' does not offer option '{value}'")]
+    InvalidOption { var: String, value: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FormType {
+    /// Asks the recipient to fill in the form.
+    Form,
+    /// A completed form sent back to the requester.
+    Submit,
+    /// The recipient declined to fill in the form.
+    Cancel,
+    /// Data returned by the requester, e.g. search results.
+    Result,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FieldType {
+    Boolean,
+    Fixed,
+    Hidden,
+    JidMulti,
+    JidSingle,
+    ListMulti,
+    ListSingle,
+    TextMulti,
+    TextPrivate,
+    #[default]
+    TextSingle,
+}
+
+impl FieldType {
+    pub fn is_multi(self) -> bool {
+        matches!(self, Self::JidMulti | Self::ListMulti | Self::TextMulti)
+    }
+
+    pub fn is_list(self) -> bool {
+        matches!(self, Self::ListMulti | Self::ListSingle)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldOption {
+    pub label: Option<String>,
+    pub value: String,
+}
+
+/// XEP-0221 media attached to a field, typically a CAPTCHA image or audio.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FormMedia {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub uris: Vec<MediaUri>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaUri {
+    pub mime_type: String,
+    pub uri: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FormField {
+    /// Absent only on `fixed` fields.
+    pub var: Option<String>,
+    pub field_type: FieldType,
+    pub label: Option<String>,
+    pub desc: Option<String>,
+    pub required: bool,
+    pub values: Vec<String>,
+    pub options: Vec<FieldOption>,
+    pub media: Vec<FormMedia>,
+}
+
+impl FormField {
+    pub fn new(var: &str, field_type: FieldType) -> Self {
+        Self {
+            var: Some(var.to_string()),
+            field_type,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    pub fn with_value(mut self, value: &str) -> Self {
+        self.values.push(value.to_string());
+        self
+    }
+
+    pub fn with_option(mut self, value: &str, label: Option<&str>) -> Self {
+        self.options.push(FieldOption {
+            label: label.map(String::from),
+            value: value.to_string(),
+        });
+        self
+    }
+
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    /// Boolean fields accept `1`/`true` and `0`/`false`; anything else,
+    /// including no value, reads as false.
+    pub fn as_bool(&self) -> bool {
+        matches!(self.values.first().map(String::as_str), Some("1" | "true"))
+    }
+
+    fn validate(&self) -> Result<(), FormError> {
+        let var = self.var.clone().unwrap_or_default();
+        let values: Vec<&str> = self
+            .values
+            .iter()
+            .map(String::as_str)
+            .filter(|value| !value.is_empty())
+            .collect();
+
+        if self.required && values.is_empty() {
+            return Err(FormError::MissingRequired(var));
+        }
+        if !self.field_type.is_multi() && self.values.len() > 1 {
+            return Err(FormError::TooManyValues(var));
+        }
+
+        for value in values {
+            let value = value.to_string();
+            match self.field_type {
+                FieldType::Boolean if !matches!(value.as_str(), "0" | "1" | "false" | "true") => {
+                    return Err(FormError::InvalidBoolean { var, value });
+                }
+                FieldType::JidSingle | FieldType::JidMulti if !crate::jid::is_valid(&value) => {
+                    return Err(FormError::InvalidJid { var, value });
+                }
+                FieldType::ListSingle | FieldType::ListMulti
+                    if !self.options.is_empty()
+                        && !self.options.iter().any(|option| option.value == value) =>
+                {
+                    return Err(FormError::InvalidOption { var, value });
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DataForm {
+    pub form_type: FormType,
+    pub title: Option<String>,
+    pub instructions: Option<String>,
+    pub fields: Vec<FormField>,
+}
+
+impl DataForm {
+    pub fn new(form_type: FormType, fields: Vec<FormField>) -> Self {
+        Self {
+            form_type,
+            title: None,
+            instructions: None,
+            fields,
+        }
+    }
+
+    /// The XEP-0068 `FORM_TYPE`, identifying what the form is for.
+    pub fn schema(&self) -> Option<&str> {
+        self.value(FORM_TYPE_VAR)
+    }
+
+    pub fn field(&self, var: &str) -> Option<&FormField> {
+        self.fields
+            .iter()
+            .find(|field| field.var.as_deref() == Some(var))
+    }
+
+    /// First value of the field named `var`.
+    pub fn value(&self, var: &str) -> Option<&str> {
+        self.field(var)
+            .and_then(|field| field.values.first())
+            .map(String::as_str)
+    }
+
+    /// Replace the values of an existing field. Returns false if the form
+    /// has no such field.
+    pub fn set_values(&mut self, var: &str, values: Vec<String>) -> bool {
+        match self
+            .fields
+            .iter_mut()
+            .find(|field| field.var.as_deref() == Some(var))
+        {
+            Some(field) => {
+                field.values = values;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Check every field against its type, options and `required` flag.
+    pub fn validate(&self) -> Result<(), Vec<FormError>> {
+        let errors: Vec<FormError> = self
+            .fields
+            .iter()
+            .filter_map(|field| field.validate().err())
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// The `submit` reply to this form: only named, non-fixed fields with
+    /// their current values, as XEP-0004 §3.4 requires.
+    pub fn to_submission(&self) -> Self {
+        let fields = self
+            .fields
+            .iter()
+            .filter(|field| field.var.is_some() && field.field_type != FieldType::Fixed)
+            .map(|field| FormField {
+                var: field.var.clone(),
+                field_type: field.field_type,
+                values: field.values.clone(),
+                ..FormField::default()
+            })
+            .collect();
+        Self::new(FormType::Submit, fields)
+    }
+
+    pub fn cancel() -> Self {
+        Self::new(FormType::Cancel, Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room_config() -> DataForm {
+        DataForm::new(
+            FormType::Form,
+            vec![
+                FormField::new(FORM_TYPE_VAR, FieldType::Hidden)
+                    .with_value("http://jabber.org/protocol/muc#roomconfig"),
+                FormField {
+                    field_type: FieldType::Fixed,
+                    values: vec!["Room settings".to_string()],
+                    ..FormField::default()
+                },
+                FormField::new("muc#roomconfig_roomname", FieldType::TextSingle).required(),
+                FormField::new("muc#roomconfig_persistentroom", FieldType::Boolean).with_value("0"),
+                FormField::new("muc#roomconfig_whois", FieldType::ListSingle)
+                    .with_option("moderators", Some("Moderators only"))
+                    .with_option("anyone", None)
+                    .with_value("moderators"),
+                FormField::new("muc#roomconfig_roomadmins", FieldType::JidMulti),
+            ],
+        )
+    }
+
+    #[test]
+    fn reads_schema_and_values() {
+        let form = room_config();
+        assert_eq!(
+            form.schema(),
+            Some("http://jabber.org/protocol/muc#roomconfig")
+        );
+        assert_eq!(form.value("muc#roomconfig_whois"), Some("moderators"));
+        assert!(
+            !form
+                .field("muc#roomconfig_persistentroom")
+                .unwrap()
+                .as_bool()
+        );
+    }
+
+    #[test]
+    fn validation_reports_every_invalid_field() {
+        let mut form = room_config();
+        form.set_values("muc#roomconfig_persistentroom", vec!["yes".into()]);
+        form.set_values("muc#roomconfig_whois", vec!["nobody".into()]);
+        form.set_values(
+            "muc#roomconfig_roomadmins",
+            vec!["alice@example.com".into(), "not a jid".into()],
+        );
+
+        let errors = form.validate().unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                FormError::MissingRequired("muc#roomconfig_roomname".into()),
+                FormError::InvalidBoolean {
+                    var: "muc#roomconfig_persistentroom".into(),
+                    value: "yes".into(),
+                },
+                FormError::InvalidOption {
+                    var: "muc#roomconfig_whois".into(),
+                    value: "nobody".into(),
+                },
+                FormError::InvalidJid {
+                    var: "muc#roomconfig_roomadmins".into(),
+                    value: "not a jid".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn single_valued_fields_reject_multiple_values() {
+        let mut form = room_config();
+        form.set_values("muc#roomconfig_roomname", vec!["One".into(), "Two".into()]);
+        assert!(
+            form.validate()
+                .unwrap_err()
+                .contains(&FormError::TooManyValues("muc#roomconfig_roomname".into()))
+        );
+    }
+
+    #[test]
+    fn submission_drops_fixed_fields_and_metadata() {
+        let mut form = room_config();
+        form.set_values("muc#roomconfig_roomname", vec!["Penguins".into()]);
+        assert!(form.validate().is_ok());
+
+        let submission = form.to_submission();
+        assert_eq!(submission.form_type, FormType::Submit);
+        assert_eq!(submission.fields.len(), 5);
+        assert_eq!(
+            submission.schema(),
+            Some("http://jabber.org/protocol/muc#roomconfig")
+        );
+        let whois = submission.field("muc#roomconfig_whois").unwrap();
+        assert!(whois.options.is_empty());
+        assert_eq!(whois.values, vec!["moderators"]);
+    }
+
+    #[test]
+    fn serializes_with_xep_type_names() {
+        let json = serde_json::to_value(room_config()).unwrap();
+        assert_eq!(json["formType"], "form");
+        assert_eq!(json["fields"][5]["fieldType"], "jid-multi");
+    }
+}
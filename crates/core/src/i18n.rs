@@ -58,10 +58,10 @@ impl I18n {
             }
         }
 
-        if !bundles.contains_key("en-US") {
-            if let Some(bundle) = Self::build_bundle("en-US") {
-                bundles.insert("en-US".to_string(), bundle);
-            }
+        if !bundles.contains_key("en-US")
+            && let Some(bundle) = Self::build_bundle("en-US")
+        {
+            bundles.insert("en-US".to_string(), bundle);
         }
 
         let mut negotiated_available: Vec<&str> = effective_available
@@ -93,14 +93,13 @@ impl I18n {
     }
 
     pub fn t(&self, message_id: &str, args: Option<&FluentArgs>) -> String {
-        if let Some(bundle) = self.bundles.get(&self.current_locale) {
-            if let Some(msg) = bundle.get_message(message_id) {
-                if let Some(pattern) = msg.value() {
-                    let mut errors = vec![];
-                    let value = bundle.format_pattern(pattern, args, &mut errors);
-                    return value.into_owned();
-                }
-            }
+        if let Some(bundle) = self.bundles.get(&self.current_locale)
+            && let Some(msg) = bundle.get_message(message_id)
+            && let Some(pattern) = msg.value()
+        {
+            let mut errors = vec![];
+            let value = bundle.format_pattern(pattern, args, &mut errors);
+            return value.into_owned();
         }
         message_id.to_string()
     }
@@ -0,0 +1,394 @@
+//! JID parsing and validation (RFC 7622). Splits an address into its
+//! localpart, domainpart and resourcepart and applies the case folding
+//! that makes two spellings of the same bare JID compare equal. Full
+//! PRECIS profiles need Unicode tables we do not ship, so normalization is
+//! limited to lowercasing, which covers the addresses users actually type.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::ops::Deref;
+
+use serde::{Deserialize, Serialize};
+
+/// Each part of a JID is limited to 1023 bytes (RFC 7622 §3).
+const MAX_PART_LEN: usize = 1023;
+
+/// Characters RFC 7622 §3.3.1 forbids in a localpart.
+const FORBIDDEN_LOCAL_CHARS: &[char] = &['"', '&', '\'', '/', ':', '<', '>', '@'];
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum JidError {
+    #[error("JID is empty")]
+    Empty,
+
+    #[error("JID has an empty localpart")]
+    EmptyLocalpart,
+
+    #[error("JID has an empty domainpart")]
+    EmptyDomain,
+
+    #[error("JID has an empty resourcepart")]
+    EmptyResource,
+
+    #[error("JID {part} is longer than 1023 bytes")]
+    TooLong { part: &'static str },
+
+    #[error("JID localpart contains '{0}'")]
+    InvalidLocalpartChar(char),
+
+    #[error("JID domainpart '{0}' is not a valid domain")]
+    InvalidDomain(String),
+}
+
+/// A JID split into its parts, each already normalized.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct JidParts {
+    pub localpart: Option<String>,
+    pub domain: String,
+    pub resource: Option<String>,
+}
+
+impl JidParts {
+    /// Parse and normalize `input`. Localpart and domainpart are case
+    /// folded and a trailing dot is dropped from the domain; the
+    /// resourcepart is case sensitive and kept as is.
+    pub fn parse(input: &str) -> Result<Self, JidError> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Err(JidError::Empty);
+        }
+
+        let (bare, resource) = match input.split_once('/') {
+            Some((bare, resource)) => (bare, Some(resource)),
+            None => (input, None),
+        };
+        let (localpart, domain) = match bare.split_once('@') {
+            Some((local, domain)) => (Some(local), domain),
+            None => (None, bare),
+        };
+
+        let localpart = localpart.map(normalize_localpart).transpose()?;
+        let domain = normalize_domain(domain)?;
+        let resource = resource.map(validate_resource).transpose()?;
+
+        Ok(Self {
+            localpart,
+            domain,
+            resource,
+        })
+    }
+
+    /// The bare JID, `localpart@domain` or just `domain`.
+    pub fn bare(&self) -> String {
+        match &self.localpart {
+            Some(local) => format!("{local}@{}", self.domain),
+            None => self.domain.clone(),
+        }
+    }
+
+    pub fn is_bare(&self) -> bool {
+        self.resource.is_none()
+    }
+}
+
+impl fmt::Display for JidParts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(local) = &self.localpart {
+            write!(f, "{local}@")?;
+        }
+        f.write_str(&self.domain)?;
+        if let Some(resource) = &self.resource {
+            write!(f, "/{resource}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A normalized JID, used wherever JIDs are compared, stored or used as map
+/// keys so that `Bob@Example.COM` and `bob@example.com` are the same contact.
+/// Input that does not parse is kept verbatim rather than rejected, since
+/// the server has the final word on what it routes.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct Jid(String);
+
+impl Jid {
+    pub fn new(input: &str) -> Self {
+        Self(normalize(input))
+    }
+
+    /// Like [`Jid::new`], but rejects malformed input.
+    pub fn parse(input: &str) -> Result<Self, JidError> {
+        JidParts::parse(input).map(|parts| Self(parts.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The JID without its resourcepart.
+    pub fn bare(&self) -> Self {
+        match self.0.split_once('/') {
+            Some((bare, _)) => Self(bare.to_string()),
+            None => self.clone(),
+        }
+    }
+
+    pub fn resource(&self) -> Option<&str> {
+        self.0.split_once('/').map(|(_, resource)| resource)
+    }
+
+    pub fn is_bare(&self) -> bool {
+        !self.0.contains('/')
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl From<&str> for Jid {
+    fn from(input: &str) -> Self {
+        Self::new(input)
+    }
+}
+
+impl From<String> for Jid {
+    fn from(input: String) -> Self {
+        Self::new(&input)
+    }
+}
+
+impl From<Jid> for String {
+    fn from(jid: Jid) -> Self {
+        jid.0
+    }
+}
+
+impl Deref for Jid {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Jid {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Jid {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for Jid {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Jid {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl fmt::Display for Jid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Whether `input` is a well-formed JID.
+pub fn is_valid(input: &str) -> bool {
+    JidParts::parse(input).is_ok()
+}
+
+/// The normalized form of `input`, or `input` unchanged when it does not
+/// parse, so that callers keying maps by JID never drop an entry.
+pub fn normalize(input: &str) -> String {
+    JidParts::parse(input)
+        .map(|jid| jid.to_string())
+        .unwrap_or_else(|_| input.to_string())
+}
+
+/// Everything before the resourcepart. Malformed input falls back to
+/// cutting at the first `/`.
+pub fn bare_jid(input: &str) -> String {
+    match JidParts::parse(input) {
+        Ok(jid) => jid.bare(),
+        Err(_) => input.split('/').next().unwrap_or(input).to_string(),
+    }
+}
+
+/// The resourcepart, or an empty string for a bare JID.
+pub fn resource_part(input: &str) -> String {
+    input
+        .split_once('/')
+        .map(|(_, resource)| resource.to_string())
+        .unwrap_or_default()
+}
+
+fn normalize_localpart(local: &str) -> Result<String, JidError> {
+    if local.is_empty() {
+        return Err(JidError::EmptyLocalpart);
+    }
+    if local.len() > MAX_PART_LEN {
+        return Err(JidError::TooLong { part: "localpart" });
+    }
+    if let Some(c) = local
+        .chars()
+        .find(|c| FORBIDDEN_LOCAL_CHARS.contains(c) || c.is_whitespace() || c.is_control())
+    {
+        return Err(JidError::InvalidLocalpartChar(c));
+    }
+    Ok(local.to_lowercase())
+}
+
+fn normalize_domain(domain: &str) -> Result<String, JidError> {
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+    if domain.is_empty() {
+        return Err(JidError::EmptyDomain);
+    }
+    if domain.len() > MAX_PART_LEN {
+        return Err(JidError::TooLong { part: "domainpart" });
+    }
+
+    // IPv6 literals are bracketed; everything else is a dot-separated
+    // hostname or IPv4 address.
+    let valid = if let Some(inner) = domain.strip_prefix('[') {
+        inner
+            .strip_suffix(']')
+            .is_some_and(|ip| ip.parse::<std::net::Ipv6Addr>().is_ok())
+    } else {
+        domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && !label
+                    .chars()
+                    .any(|c| c.is_whitespace() || c.is_control() || "@/:[]".contains(c))
+        })
+    };
+    if !valid {
+        return Err(JidError::InvalidDomain(domain.to_string()));
+    }
+    Ok(domain.to_lowercase())
+}
+
+fn validate_resource(resource: &str) -> Result<String, JidError> {
+    if resource.is_empty() {
+        return Err(JidError::EmptyResource);
+    }
+    if resource.len() > MAX_PART_LEN {
+        return Err(JidError::TooLong {
+            part: "resourcepart",
+        });
+    }
+    Ok(resource.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_all_three_parts() {
+        let jid = JidParts::parse("alice@example.com/phone").unwrap();
+        assert_eq!(jid.localpart.as_deref(), Some("alice"));
+        assert_eq!(jid.domain, "example.com");
+        assert_eq!(jid.resource.as_deref(), Some("phone"));
+        assert_eq!(jid.bare(), "alice@example.com");
+        assert!(!jid.is_bare());
+    }
+
+    #[test]
+    fn domain_only_jids_are_valid() {
+        let jid = JidParts::parse("conference.example.com").unwrap();
+        assert_eq!(jid.localpart, None);
+        assert_eq!(jid.bare(), "conference.example.com");
+        assert!(jid.is_bare());
+    }
+
+    #[test]
+    fn resource_may_contain_slashes_and_at_signs() {
+        let jid = JidParts::parse("room@muc.example.com/nick/with@odd").unwrap();
+        assert_eq!(jid.resource.as_deref(), Some("nick/with@odd"));
+    }
+
+    #[test]
+    fn folds_case_except_in_the_resource() {
+        let jid = JidParts::parse("Bob@Example.COM./Laptop").unwrap();
+        assert_eq!(jid.to_string(), "bob@example.com/Laptop");
+        assert_eq!(normalize("Bob@Example.COM"), "bob@example.com");
+    }
+
+    #[test]
+    fn rejects_malformed_jids() {
+        assert_eq!(JidParts::parse("  "), Err(JidError::Empty));
+        assert_eq!(
+            JidParts::parse("@example.com"),
+            Err(JidError::EmptyLocalpart)
+        );
+        assert_eq!(JidParts::parse("alice@"), Err(JidError::EmptyDomain));
+        assert_eq!(
+            JidParts::parse("alice@example.com/"),
+            Err(JidError::EmptyResource)
+        );
+        assert_eq!(
+            JidParts::parse("a:b@example.com"),
+            Err(JidError::InvalidLocalpartChar(':'))
+        );
+        assert!(matches!(
+            JidParts::parse("alice@bob@example.com"),
+            Err(JidError::InvalidDomain(_))
+        ));
+        assert!(!is_valid("not a jid"));
+        assert!(!is_valid("alice@exa..mple.com"));
+        assert!(matches!(
+            JidParts::parse(&format!("{}@example.com", "a".repeat(1024))),
+            Err(JidError::TooLong { part: "localpart" })
+        ));
+    }
+
+    #[test]
+    fn accepts_ip_literal_domains() {
+        assert!(is_valid("alice@192.168.1.10"));
+        assert!(is_valid("alice@[::1]/res"));
+        assert!(!is_valid("alice@[not-an-ip]"));
+    }
+
+    #[test]
+    fn bare_jid_falls_back_for_malformed_input() {
+        assert_eq!(bare_jid("Alice@Example.com/res"), "alice@example.com");
+        assert_eq!(bare_jid("not a jid/res"), "not a jid");
+        assert_eq!(resource_part("user@example.com/res/extra"), "res/extra");
+        assert_eq!(resource_part("user@example.com"), "");
+    }
+
+    #[test]
+    fn jid_folds_case_and_keeps_unparseable_input() {
+        let jid = Jid::new("Bob@Example.COM/Res");
+        assert_eq!(jid, "bob@example.com/Res");
+        assert_eq!(jid.bare(), Jid::new("bob@example.com"));
+        assert_eq!(jid.resource(), Some("Res"));
+        assert!(jid.bare().is_bare());
+        assert_eq!(Jid::new("not a jid").as_str(), "not a jid");
+        assert!(Jid::parse("not a jid").is_err());
+    }
+
+    #[test]
+    fn jid_normalizes_when_deserialized() {
+        let jid: Jid = serde_json::from_str("\"Alice@Example.com\"").unwrap();
+        assert_eq!(jid, "alice@example.com");
+        assert_eq!(
+            serde_json::to_string(&jid).unwrap(),
+            "\"alice@example.com\""
+        );
+    }
+}
@@ -1,7 +1,20 @@
+pub mod bridge;
+pub mod color;
 pub mod config;
+pub mod credentials;
 pub mod error;
 pub mod event;
+pub mod form;
 pub mod i18n;
+pub mod jid;
+#[cfg(feature = "native")]
+pub mod logging;
+pub mod rsm;
+#[cfg(feature = "native")]
+pub mod shutdown;
+pub mod styling;
+#[cfg(feature = "native")]
+pub mod supervisor;
 pub mod theme;
 
 pub use error::{EventBusError, Result, WaddleError};
@@ -0,0 +1,276 @@
+//! Application logging: stderr, a daily-rotated file, and a feed of recent
+//! warnings and errors for in-app diagnostics.
+//!
+//! Warnings and errors are kept in a [`LogBuffer`] from the start and, once
+//! [`Logging::attach_event_bus`] is called, also published on
+//! `system.log.entry` so a debug pane can follow them live.
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fmt::{self, Write as _};
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use chrono::Utc;
+use tracing::field::{Field, Visit};
+use tracing::{Level, Subscriber, warn};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, fmt as tracing_fmt};
+
+use crate::config::LoggingConfig;
+use crate::event::{Channel, Event, EventBus, EventPayload, EventSource, LogEntry, channels};
+
+/// Warnings and errors kept for [`LogBuffer::recent`].
+pub const LOG_BUFFER_CAPACITY: usize = 500;
+
+const LOG_FILE_PREFIX: &str = "waddle";
+const LOG_FILE_SUFFIX: &str = "log";
+
+#[derive(Debug, thiserror::Error)]
+pub enum LoggingError {
+    #[error("a global logger is already installed: {0}")]
+    AlreadyInitialized(String),
+}
+
+/// The most recent warnings and errors, oldest dropped first.
+pub struct LogBuffer {
+    entries: Mutex<VecDeque<LogEntry>>,
+    capacity: usize,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    fn push(&self, entry: LogEntry) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Up to `n` of the latest entries, oldest first.
+    pub fn recent(&self, n: usize) -> Vec<LogEntry> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .skip(entries.len().saturating_sub(n))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Collects the message and fields of a tracing event into one line.
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={value}", field.name());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{value:?}");
+        } else {
+            let _ = write!(self.fields, " {}={value:?}", field.name());
+        }
+    }
+}
+
+thread_local! {
+    /// Set while a log entry is being published, so anything logged by the
+    /// event bus itself is not fed back into it.
+    static PUBLISHING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Records warnings and errors into the buffer and onto the event bus.
+struct FeedLayer {
+    buffer: Arc<LogBuffer>,
+    event_bus: Arc<OnceLock<Arc<dyn EventBus>>>,
+}
+
+impl<S: Subscriber> Layer<S> for FeedLayer {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        // More verbose levels compare greater.
+        if *metadata.level() > Level::WARN {
+            return;
+        }
+
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let entry = LogEntry {
+            timestamp: Utc::now(),
+            level: metadata.level().to_string(),
+            target: metadata.target().to_string(),
+            message: visitor.message + &visitor.fields,
+        };
+        self.buffer.push(entry.clone());
+
+        let Some(event_bus) = self.event_bus.get() else {
+            return;
+        };
+        if PUBLISHING.replace(true) {
+            return;
+        }
+        let _ = event_bus.publish(Event::new(
+            Channel::new(channels::SYSTEM_LOG_ENTRY).unwrap(),
+            EventSource::System("logging".into()),
+            EventPayload::LogEntryRecorded { entry },
+        ));
+        PUBLISHING.set(false);
+    }
+}
+
+/// The installed logger. Keep it alive for the life of the process: the
+/// file writer flushes its last lines when this is dropped.
+pub struct Logging {
+    buffer: Arc<LogBuffer>,
+    event_bus: Arc<OnceLock<Arc<dyn EventBus>>>,
+    _file_guard: Option<WorkerGuard>,
+}
+
+impl Logging {
+    /// Install the global logger at `config.level`, or as `RUST_LOG` says.
+    /// Logs go to stderr and to daily files in `directory`. If `directory`
+    /// can't be used, logging carries on without the file.
+    pub fn init(config: &LoggingConfig, directory: &Path) -> Result<Self, LoggingError> {
+        let filter =
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&config.level));
+
+        let appender = RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix(LOG_FILE_PREFIX)
+            .filename_suffix(LOG_FILE_SUFFIX)
+            .max_log_files(config.max_files)
+            .build(directory);
+        let (file_layer, file_guard, file_error) = match appender {
+            Ok(appender) => {
+                let (writer, guard) = tracing_appender::non_blocking(appender);
+                let layer = tracing_fmt::layer().with_ansi(false).with_writer(writer);
+                (Some(layer), Some(guard), None)
+            }
+            Err(error) => (None, None, Some(error)),
+        };
+
+        let buffer = Arc::new(LogBuffer::new(LOG_BUFFER_CAPACITY));
+        let event_bus = Arc::new(OnceLock::new());
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(tracing_fmt::layer().with_writer(std::io::stderr))
+            .with(file_layer)
+            .with(FeedLayer {
+                buffer: buffer.clone(),
+                event_bus: event_bus.clone(),
+            })
+            .try_init()
+            .map_err(|error| LoggingError::AlreadyInitialized(error.to_string()))?;
+
+        if let Some(error) = file_error {
+            warn!(
+                directory = %directory.display(),
+                %error,
+                "cannot write log files, logging to stderr only"
+            );
+        }
+
+        Ok(Self {
+            buffer,
+            event_bus,
+            _file_guard: file_guard,
+        })
+    }
+
+    pub fn buffer(&self) -> Arc<LogBuffer> {
+        self.buffer.clone()
+    }
+
+    /// Publish warnings and errors on `system.log.entry` from now on. Only
+    /// the first bus attached is used.
+    pub fn attach_event_bus(&self, event_bus: Arc<dyn EventBus>) {
+        let _ = self.event_bus.set(event_bus);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::BroadcastEventBus;
+    use tracing::{error, info};
+
+    fn entry(message: &str) -> LogEntry {
+        LogEntry {
+            timestamp: Utc::now(),
+            level: "WARN".to_string(),
+            target: "test".to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn buffer_keeps_the_latest_entries_in_order() {
+        let buffer = LogBuffer::new(3);
+        for message in ["a", "b", "c", "d"] {
+            buffer.push(entry(message));
+        }
+
+        let messages = |entries: Vec<LogEntry>| {
+            entries
+                .into_iter()
+                .map(|entry| entry.message)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(messages(buffer.recent(10)), ["b", "c", "d"]);
+        assert_eq!(messages(buffer.recent(2)), ["c", "d"]);
+    }
+
+    #[tokio::test]
+    async fn warnings_and_errors_are_buffered_and_published() {
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let mut entries = event_bus.subscribe("system.log.entry").unwrap();
+        let buffer = Arc::new(LogBuffer::new(10));
+        let attached = Arc::new(OnceLock::new());
+        let subscriber = tracing_subscriber::registry().with(FeedLayer {
+            buffer: buffer.clone(),
+            event_bus: attached.clone(),
+        });
+
+        tracing::subscriber::with_default(subscriber, || {
+            warn!("before the bus is attached");
+            let _ = attached.set(event_bus.clone());
+            info!("too quiet to keep");
+            error!(room = "dev@conference.example.com", "join failed");
+        });
+
+        let recent = buffer.recent(10);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].level, "WARN");
+        assert_eq!(recent[1].level, "ERROR");
+        assert_eq!(
+            recent[1].message,
+            "join failed room=dev@conference.example.com"
+        );
+
+        let event = entries.recv().await.unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::LogEntryRecorded { ref entry } if *entry == recent[1]
+        ));
+    }
+}
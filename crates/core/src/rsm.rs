@@ -0,0 +1,100 @@
+//! XEP-0059 result set paging shared by every paged query (MAM archives,
+//! disco#items room lists). XML conversion lives in `waddle-xmpp`.
+//!
+//! A query asks for at most `max` items from a [`RsmCursor`]; the reply
+//! reports the ids of the page's first and last items, which become the
+//! cursors for the pages on either side.
+
+use serde::{Deserialize, Serialize};
+
+/// Where a requested page starts.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "from", content = "id", rename_all = "camelCase")]
+pub enum RsmCursor {
+    /// The first page of the set.
+    #[default]
+    Start,
+    /// The page following the item with this id.
+    After(String),
+    /// The page preceding the item with this id.
+    Before(String),
+    /// The last page of the set.
+    End,
+}
+
+/// A request for one page of a result set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RsmQuery {
+    /// Most items to return; `None` leaves the page size to the server.
+    pub max: Option<u32>,
+    #[serde(default)]
+    pub cursor: RsmCursor,
+}
+
+impl RsmQuery {
+    pub fn first(max: u32) -> Self {
+        Self {
+            max: Some(max),
+            cursor: RsmCursor::Start,
+        }
+    }
+
+    pub fn last(max: u32) -> Self {
+        Self {
+            max: Some(max),
+            cursor: RsmCursor::End,
+        }
+    }
+
+    pub fn after(id: impl Into<String>, max: u32) -> Self {
+        Self {
+            max: Some(max),
+            cursor: RsmCursor::After(id.into()),
+        }
+    }
+
+    pub fn before(id: impl Into<String>, max: u32) -> Self {
+        Self {
+            max: Some(max),
+            cursor: RsmCursor::Before(id.into()),
+        }
+    }
+
+    /// The page after `after` when there is a cursor, else the first page.
+    pub fn after_or_first(after: Option<&str>, max: u32) -> Self {
+        after.map_or_else(|| Self::first(max), |id| Self::after(id, max))
+    }
+}
+
+/// What the server reported about the page it returned.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RsmResult {
+    /// Id of the page's first item.
+    pub first: Option<String>,
+    /// Position of the page's first item in the full set.
+    pub first_index: Option<u64>,
+    /// Id of the page's last item.
+    pub last: Option<String>,
+    /// Size of the full set. Servers may only approximate it.
+    pub count: Option<u64>,
+}
+
+impl RsmResult {
+    /// The page after this one, or `None` for an empty page.
+    pub fn next(&self, max: u32) -> Option<RsmQuery> {
+        self.last.as_ref().map(|last| RsmQuery::after(last, max))
+    }
+
+    /// The page before this one, or `None` for an empty page.
+    pub fn previous(&self, max: u32) -> Option<RsmQuery> {
+        self.first.as_ref().map(|first| RsmQuery::before(first, max))
+    }
+
+    /// Items from the start of this page to the end of the set, when the
+    /// server reported both its size and the page's position.
+    pub fn remaining(&self) -> Option<u64> {
+        Some(self.count?.saturating_sub(self.first_index?))
+    }
+}
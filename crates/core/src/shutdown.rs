@@ -0,0 +1,285 @@
+//! Coordinated teardown of the long-running managers.
+//!
+//! On shutdown the coordinator announces `system.shutdown.requested`, lets
+//! each registered [`Manager`] flush its state in registration order under a
+//! shared deadline, and then closes the event bus so every `run()` loop
+//! stops.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::OnceCell;
+use tokio::time::{Instant, timeout_at};
+use tracing::{debug, error, info, warn};
+
+use crate::error::{EventBusError, WaddleError};
+use crate::event::{Channel, Event, EventBus, EventPayload, EventSource, channels};
+
+/// How long managers get, in total, to flush before the bus is closed.
+pub const DEFAULT_SHUTDOWN_DEADLINE: Duration = Duration::from_secs(5);
+
+const SHUTDOWN_CHANNEL: &str = channels::SYSTEM_SHUTDOWN_REQUESTED;
+const SHUTDOWN_SOURCE: &str = "shutdown";
+
+pub type ShutdownFuture<'a> = Pin<Box<dyn Future<Output = Result<(), WaddleError>> + Send + 'a>>;
+
+/// A component with state to flush before the application exits.
+pub trait Manager: Send + Sync + 'static {
+    /// Name used in logs and in the [`ShutdownReport`].
+    fn name(&self) -> &'static str;
+
+    /// Flush pending work. Runs while the event bus is still open, so a
+    /// manager may publish events and wait for their outcome.
+    fn shutdown(&self) -> ShutdownFuture<'_>;
+}
+
+/// How each registered manager fared during shutdown.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    pub completed: Vec<String>,
+    /// Manager name and the error it returned.
+    pub failed: Vec<(String, String)>,
+    /// Managers cut off by the deadline, or not started because it had
+    /// already passed.
+    pub timed_out: Vec<String>,
+}
+
+pub struct ShutdownCoordinator {
+    event_bus: Arc<dyn EventBus>,
+    managers: Mutex<Vec<Arc<dyn Manager>>>,
+    deadline: Duration,
+    report: OnceCell<ShutdownReport>,
+}
+
+impl ShutdownCoordinator {
+    pub fn new(event_bus: Arc<dyn EventBus>) -> Self {
+        Self::with_deadline(event_bus, DEFAULT_SHUTDOWN_DEADLINE)
+    }
+
+    pub fn with_deadline(event_bus: Arc<dyn EventBus>, deadline: Duration) -> Self {
+        Self {
+            event_bus,
+            managers: Mutex::new(Vec::new()),
+            deadline,
+            report: OnceCell::new(),
+        }
+    }
+
+    /// Managers are shut down in the order they were registered.
+    pub fn register(&self, manager: Arc<dyn Manager>) {
+        self.managers.lock().unwrap().push(manager);
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.report.initialized()
+    }
+
+    /// Shut down once. Later and concurrent calls wait for the first to
+    /// finish and return its report.
+    pub async fn shutdown(&self, reason: &str) -> ShutdownReport {
+        self.report
+            .get_or_init(|| self.run_shutdown(reason))
+            .await
+            .clone()
+    }
+
+    /// Wait for a `ShutdownRequested` published by another component and
+    /// shut down in response.
+    pub async fn run(self: Arc<Self>) -> Result<(), WaddleError> {
+        let mut sub = self.event_bus.subscribe(SHUTDOWN_CHANNEL)?;
+
+        loop {
+            match sub.recv().await {
+                Ok(Event {
+                    payload: EventPayload::ShutdownRequested { reason },
+                    ..
+                }) => {
+                    self.shutdown(&reason).await;
+                    return Ok(());
+                }
+                Ok(_) => {}
+                Err(EventBusError::ChannelClosed) => {
+                    debug!("event bus closed, shutdown coordinator stopping");
+                    return Ok(());
+                }
+                Err(EventBusError::Lagged(count)) => {
+                    warn!(count, "shutdown coordinator lagged, some events dropped");
+                }
+                Err(e) => {
+                    error!(error = %e, "shutdown coordinator subscription error");
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+
+    async fn run_shutdown(&self, reason: &str) -> ShutdownReport {
+        info!(reason, "shutting down");
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(SHUTDOWN_CHANNEL).unwrap(),
+            EventSource::System(SHUTDOWN_SOURCE.into()),
+            EventPayload::ShutdownRequested {
+                reason: reason.to_string(),
+            },
+        ));
+
+        let managers = self.managers.lock().unwrap().clone();
+        let deadline = Instant::now() + self.deadline;
+        let mut report = ShutdownReport::default();
+
+        for manager in managers {
+            let name = manager.name().to_string();
+            if Instant::now() >= deadline {
+                warn!(manager = %name, "shutdown deadline passed, skipping manager");
+                report.timed_out.push(name);
+                continue;
+            }
+            match timeout_at(deadline, manager.shutdown()).await {
+                Ok(Ok(())) => {
+                    debug!(manager = %name, "manager shut down");
+                    report.completed.push(name);
+                }
+                Ok(Err(e)) => {
+                    warn!(manager = %name, error = %e, "manager failed to shut down cleanly");
+                    report.failed.push((name, e.to_string()));
+                }
+                Err(_) => {
+                    warn!(manager = %name, "manager did not shut down before the deadline");
+                    report.timed_out.push(name);
+                }
+            }
+        }
+
+        self.event_bus.close();
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::BroadcastEventBus;
+
+    struct TestManager {
+        name: &'static str,
+        delay: Duration,
+        fail: bool,
+        log: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl Manager for TestManager {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn shutdown(&self) -> ShutdownFuture<'_> {
+            Box::pin(async move {
+                tokio::time::sleep(self.delay).await;
+                self.log.lock().unwrap().push(self.name);
+                if self.fail {
+                    return Err(WaddleError::Internal("flush failed".into()));
+                }
+                Ok(())
+            })
+        }
+    }
+
+    fn manager(
+        name: &'static str,
+        delay_ms: u64,
+        fail: bool,
+        log: &Arc<Mutex<Vec<&'static str>>>,
+    ) -> Arc<dyn Manager> {
+        Arc::new(TestManager {
+            name,
+            delay: Duration::from_millis(delay_ms),
+            fail,
+            log: log.clone(),
+        })
+    }
+
+    #[tokio::test]
+    async fn managers_shut_down_in_registration_order() {
+        let bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let coordinator = ShutdownCoordinator::new(bus);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        coordinator.register(manager("presence", 20, false, &log));
+        coordinator.register(manager("messaging", 0, true, &log));
+        coordinator.register(manager("mam", 0, false, &log));
+
+        let report = coordinator.shutdown("test").await;
+
+        assert_eq!(*log.lock().unwrap(), vec!["presence", "messaging", "mam"]);
+        assert_eq!(report.completed, vec!["presence", "mam"]);
+        assert_eq!(
+            report.failed,
+            vec![(
+                "messaging".to_string(),
+                "Internal error: flush failed".to_string()
+            )]
+        );
+        assert!(report.timed_out.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn deadline_is_shared_across_managers() {
+        let bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let coordinator = ShutdownCoordinator::with_deadline(bus, Duration::from_secs(1));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        coordinator.register(manager("fast", 600, false, &log));
+        coordinator.register(manager("slow", 600, false, &log));
+        coordinator.register(manager("late", 0, false, &log));
+
+        let report = coordinator.shutdown("test").await;
+
+        assert_eq!(report.completed, vec!["fast"]);
+        assert_eq!(report.timed_out, vec!["slow", "late"]);
+    }
+
+    #[tokio::test]
+    async fn shutdown_announces_then_closes_bus() {
+        let bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let mut sub = bus.subscribe("system.**").unwrap();
+        let coordinator = ShutdownCoordinator::new(bus.clone());
+
+        coordinator.shutdown("user quit").await;
+        assert!(coordinator.is_shut_down());
+
+        let event = sub.recv().await.unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::ShutdownRequested { ref reason } if reason == "user quit"
+        ));
+        assert!(matches!(
+            sub.recv().await,
+            Err(EventBusError::ChannelClosed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn external_request_triggers_single_shutdown() {
+        let bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let coordinator = Arc::new(ShutdownCoordinator::new(bus.clone()));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        coordinator.register(manager("presence", 0, false, &log));
+        let task = tokio::spawn(coordinator.clone().run());
+        tokio::task::yield_now().await;
+
+        bus.publish(Event::new(
+            Channel::new(SHUTDOWN_CHANNEL).unwrap(),
+            EventSource::System("test".into()),
+            EventPayload::ShutdownRequested {
+                reason: "auth failed".into(),
+            },
+        ))
+        .unwrap();
+
+        task.await.unwrap().unwrap();
+        let report = coordinator.shutdown("again").await;
+
+        assert_eq!(report.completed, vec!["presence"]);
+        assert_eq!(*log.lock().unwrap(), vec!["presence"]);
+    }
+}
@@ -0,0 +1,298 @@
+//! Message styling (XEP-0393): the `*strong*`, `_emphasis_`, `~strike~`
+//! and `` `preformatted` `` spans, ```` ``` ```` blocks and `>` quotes
+//! senders type into plain-text bodies.
+//!
+//! Frontends render the [`Block`] tree from [`parse`] so styling looks the
+//! same everywhere; [`plain_text`] drops the markup for search and
+//! notifications. Bodies are parsed when shown rather than stored parsed,
+//! since the rules only ever look at the text.
+
+use serde::{Deserialize, Serialize};
+
+/// A line-level part of a styled body.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Block {
+    /// One line of text.
+    Line { spans: Vec<Span> },
+    /// The lines between ```` ``` ```` fences, shown as typed. Anything
+    /// after the opening fence is a hint the sender may have added, such as
+    /// a language name.
+    Preformatted { text: String },
+    /// Consecutive lines starting with `>`, without the prefix.
+    Quote { blocks: Vec<Block> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Span {
+    Text {
+        text: String,
+    },
+    /// Text between a pair of styling directives, which are not part of
+    /// `children`. Preformatted spans hold a single unstyled text span.
+    Styled {
+        style: InlineStyle,
+        children: Vec<Span>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InlineStyle {
+    Strong,
+    Emphasis,
+    Strike,
+    Preformatted,
+}
+
+impl InlineStyle {
+    /// The character that opens and closes spans of this style.
+    pub fn directive(self) -> char {
+        match self {
+            InlineStyle::Strong => '*',
+            InlineStyle::Emphasis => '_',
+            InlineStyle::Strike => '~',
+            InlineStyle::Preformatted => '`',
+        }
+    }
+
+    fn from_directive(c: char) -> Option<Self> {
+        match c {
+            '*' => Some(InlineStyle::Strong),
+            '_' => Some(InlineStyle::Emphasis),
+            '~' => Some(InlineStyle::Strike),
+            '`' => Some(InlineStyle::Preformatted),
+            _ => None,
+        }
+    }
+}
+
+impl Span {
+    /// The span's text without styling directives.
+    pub fn text(&self) -> String {
+        match self {
+            Span::Text { text } => text.clone(),
+            Span::Styled { children, .. } => children.iter().map(Span::text).collect(),
+        }
+    }
+}
+
+const FENCE: &str = "```";
+
+/// Parse a message body into styled blocks.
+pub fn parse(body: &str) -> Vec<Block> {
+    let lines: Vec<&str> = body.split('\n').collect();
+    parse_blocks(&lines)
+}
+
+/// `body` with the styling markup removed: directives, quote prefixes and
+/// code fences.
+pub fn plain_text(body: &str) -> String {
+    let mut lines = Vec::new();
+    push_plain_lines(&parse(body), &mut lines);
+    lines.join("\n")
+}
+
+fn push_plain_lines(blocks: &[Block], lines: &mut Vec<String>) {
+    for block in blocks {
+        match block {
+            Block::Line { spans } => lines.push(spans.iter().map(Span::text).collect()),
+            Block::Preformatted { text } => lines.push(text.clone()),
+            Block::Quote { blocks } => push_plain_lines(blocks, lines),
+        }
+    }
+}
+
+fn parse_blocks(lines: &[&str]) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if line.starts_with(FENCE) {
+            // An unclosed block runs to the end of the message or quote.
+            let close = lines[i + 1..]
+                .iter()
+                .position(|line| *line == FENCE)
+                .map(|offset| i + 1 + offset);
+            let end = close.unwrap_or(lines.len());
+            blocks.push(Block::Preformatted {
+                text: lines[i + 1..end].join("\n"),
+            });
+            i = close.map_or(lines.len(), |close| close + 1);
+        } else if line.starts_with('>') {
+            let quoted: Vec<&str> = lines[i..]
+                .iter()
+                .take_while(|line| line.starts_with('>'))
+                .map(|line| {
+                    let line = &line[1..];
+                    line.strip_prefix(' ').unwrap_or(line)
+                })
+                .collect();
+            i += quoted.len();
+            blocks.push(Block::Quote {
+                blocks: parse_blocks(&quoted),
+            });
+        } else {
+            blocks.push(Block::Line {
+                spans: parse_spans(&line.chars().collect::<Vec<_>>()),
+            });
+            i += 1;
+        }
+    }
+    blocks
+}
+
+fn parse_spans(chars: &[char]) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut text = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if let Some(style) = InlineStyle::from_directive(chars[i])
+            && opens_at(chars, i)
+            && let Some(close) = closing_directive(chars, i)
+        {
+            if !text.is_empty() {
+                spans.push(Span::Text {
+                    text: std::mem::take(&mut text),
+                });
+            }
+            let inner = &chars[i + 1..close];
+            let children = if style == InlineStyle::Preformatted {
+                vec![Span::Text {
+                    text: inner.iter().collect(),
+                }]
+            } else {
+                parse_spans(inner)
+            };
+            spans.push(Span::Styled { style, children });
+            i = close + 1;
+        } else {
+            text.push(chars[i]);
+            i += 1;
+        }
+    }
+    if !text.is_empty() {
+        spans.push(Span::Text { text });
+    }
+    spans
+}
+
+/// An opening directive starts the line (or the enclosing span) or follows
+/// whitespace, and is not followed by whitespace.
+fn opens_at(chars: &[char], i: usize) -> bool {
+    (i == 0 || chars[i - 1].is_whitespace())
+        && chars.get(i + 1).is_some_and(|next| !next.is_whitespace())
+}
+
+/// The first matching directive after `open` that is not preceded by
+/// whitespace and leaves the span non-empty.
+fn closing_directive(chars: &[char], open: usize) -> Option<usize> {
+    let directive = chars[open];
+    (open + 2..chars.len()).find(|&j| chars[j] == directive && !chars[j - 1].is_whitespace())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Span {
+        Span::Text {
+            text: text.to_string(),
+        }
+    }
+
+    fn styled(style: InlineStyle, children: Vec<Span>) -> Span {
+        Span::Styled { style, children }
+    }
+
+    fn line(body: &str) -> Vec<Span> {
+        match parse(body).remove(0) {
+            Block::Line { spans } => spans,
+            other => panic!("expected a line, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_inline_spans() {
+        assert_eq!(
+            line("a *strong* and _emphasis_ ~gone~ `*code*`"),
+            vec![
+                text("a "),
+                styled(InlineStyle::Strong, vec![text("strong")]),
+                text(" and "),
+                styled(InlineStyle::Emphasis, vec![text("emphasis")]),
+                text(" "),
+                styled(InlineStyle::Strike, vec![text("gone")]),
+                text(" "),
+                styled(InlineStyle::Preformatted, vec![text("*code*")]),
+            ]
+        );
+        assert_eq!(
+            line("*_both_*"),
+            vec![styled(
+                InlineStyle::Strong,
+                vec![styled(InlineStyle::Emphasis, vec![text("both")])]
+            )]
+        );
+    }
+
+    #[test]
+    fn ignores_directives_that_cannot_open_or_close() {
+        for body in [
+            "not*strong*",
+            "* not strong*",
+            "*not strong *",
+            "**",
+            "2 * 3 * 4",
+            "*across\nlines*",
+        ] {
+            assert_eq!(plain_text(body), body, "{body}");
+        }
+    }
+
+    #[test]
+    fn parses_blocks_and_quotes() {
+        let blocks = parse("> quoted *text*\n>> nested\n```rust\nlet *x* = 1;\n```\nafter");
+        assert_eq!(
+            blocks,
+            vec![
+                Block::Quote {
+                    blocks: vec![
+                        Block::Line {
+                            spans: vec![
+                                text("quoted "),
+                                styled(InlineStyle::Strong, vec![text("text")]),
+                            ],
+                        },
+                        Block::Quote {
+                            blocks: vec![Block::Line {
+                                spans: vec![text("nested")],
+                            }],
+                        },
+                    ],
+                },
+                Block::Preformatted {
+                    text: "let *x* = 1;".to_string(),
+                },
+                Block::Line {
+                    spans: vec![text("after")],
+                },
+            ]
+        );
+        assert_eq!(
+            parse("```\nnever closed\n*still code*"),
+            vec![Block::Preformatted {
+                text: "never closed\n*still code*".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn plain_text_strips_markup() {
+        assert_eq!(
+            plain_text("> see *this* _now_\n```\ncode\n```"),
+            "see this now\ncode"
+        );
+    }
+}
@@ -0,0 +1,248 @@
+//! Restarts manager tasks whose `run()` loop fails or panics.
+//!
+//! Each supervised task is spawned from a factory so it can be started again
+//! after a failure. Restarts are spaced with exponential backoff and capped
+//! per restart window; a task that exits cleanly, as `run()` loops do once
+//! the event bus closes, is not restarted.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+use crate::config::SupervisorConfig;
+use crate::event::{Channel, Event, EventBus, EventPayload, EventSource, channels};
+
+const SUPERVISOR_SOURCE: &str = "supervisor";
+
+/// How a supervised task ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskExit {
+    /// `run()` returned `Ok`; the manager stopped on purpose.
+    Stopped,
+    /// The restart limit was reached; the last failure is attached.
+    GaveUp(String),
+}
+
+pub struct Supervisor {
+    event_bus: Arc<dyn EventBus>,
+    config: SupervisorConfig,
+}
+
+impl Supervisor {
+    pub fn new(event_bus: Arc<dyn EventBus>, config: SupervisorConfig) -> Self {
+        Self { event_bus, config }
+    }
+
+    /// Run `factory()` as a task named `manager`, starting a fresh one each
+    /// time it fails. The returned handle resolves once the manager stops
+    /// for good.
+    pub fn spawn<F, Fut>(&self, manager: &'static str, factory: F) -> JoinHandle<TaskExit>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let event_bus = self.event_bus.clone();
+        let config = self.config.clone();
+        tokio::spawn(supervise(manager, factory, event_bus, config))
+    }
+}
+
+async fn supervise<F, Fut>(
+    manager: &'static str,
+    factory: F,
+    event_bus: Arc<dyn EventBus>,
+    config: SupervisorConfig,
+) -> TaskExit
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), String>> + Send + 'static,
+{
+    let window = Duration::from_secs(config.restart_window_secs);
+    let mut restarts: u32 = 0;
+
+    loop {
+        let started = Instant::now();
+        let reason = match tokio::spawn(factory()).await {
+            Ok(Ok(())) => {
+                info!(manager, "manager stopped");
+                return TaskExit::Stopped;
+            }
+            Ok(Err(reason)) => reason,
+            Err(join_error) if join_error.is_panic() => format!("panicked: {join_error}"),
+            Err(join_error) => join_error.to_string(),
+        };
+
+        if started.elapsed() >= window {
+            restarts = 0;
+        }
+
+        if restarts >= config.max_restarts {
+            error!(manager, %reason, restarts, "manager keeps failing, not restarting");
+            publish(
+                &event_bus,
+                channels::SYSTEM_ERROR_OCCURRED,
+                EventPayload::ErrorOccurred {
+                    component: manager.to_string(),
+                    message: format!("stopped after {restarts} restarts: {reason}"),
+                    recoverable: false,
+                },
+            );
+            return TaskExit::GaveUp(reason);
+        }
+
+        restarts += 1;
+        let delay = backoff(&config, restarts);
+        warn!(manager, %reason, attempt = restarts, ?delay, "manager failed, restarting");
+        tokio::time::sleep(delay).await;
+
+        publish(
+            &event_bus,
+            channels::SYSTEM_MANAGER_RESTARTED,
+            EventPayload::ManagerRestarted {
+                manager: manager.to_string(),
+                attempt: restarts,
+                reason,
+            },
+        );
+    }
+}
+
+/// Delay before restart number `attempt`, counting from 1.
+fn backoff(config: &SupervisorConfig, attempt: u32) -> Duration {
+    Duration::from_millis(config.initial_backoff_ms)
+        .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(Duration::from_secs(config.max_backoff_secs))
+}
+
+fn publish(event_bus: &Arc<dyn EventBus>, channel: &str, payload: EventPayload) {
+    let _ = event_bus.publish(Event::new(
+        Channel::new(channel).unwrap(),
+        EventSource::System(SUPERVISOR_SOURCE.into()),
+        payload,
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use crate::event::BroadcastEventBus;
+
+    fn config(max_restarts: u32) -> SupervisorConfig {
+        SupervisorConfig {
+            max_restarts,
+            restart_window_secs: 60,
+            initial_backoff_ms: 100,
+            max_backoff_secs: 1,
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let config = config(10);
+        assert_eq!(backoff(&config, 1), Duration::from_millis(100));
+        assert_eq!(backoff(&config, 2), Duration::from_millis(200));
+        assert_eq!(backoff(&config, 4), Duration::from_millis(800));
+        assert_eq!(backoff(&config, 5), Duration::from_secs(1));
+        assert_eq!(backoff(&config, 40), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_task_is_restarted_until_it_stops() {
+        let bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let mut sub = bus.subscribe("system.manager.restarted").unwrap();
+        let supervisor = Supervisor::new(bus, config(5));
+        let runs = Arc::new(AtomicU32::new(0));
+
+        let handle = supervisor.spawn("presence", {
+            let runs = runs.clone();
+            move || {
+                let run = runs.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    match run {
+                        0 => Err("subscription error".to_string()),
+                        1 => panic!("boom"),
+                        _ => Ok(()),
+                    }
+                }
+            }
+        });
+
+        assert_eq!(handle.await.unwrap(), TaskExit::Stopped);
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+
+        let first = sub.recv().await.unwrap();
+        assert!(matches!(
+            first.payload,
+            EventPayload::ManagerRestarted { ref manager, attempt: 1, ref reason }
+                if manager == "presence" && reason == "subscription error"
+        ));
+        let second = sub.recv().await.unwrap();
+        assert!(matches!(
+            second.payload,
+            EventPayload::ManagerRestarted { attempt: 2, ref reason, .. }
+                if reason.starts_with("panicked")
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_max_restarts() {
+        let bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let mut errors = bus.subscribe("system.error.occurred").unwrap();
+        let supervisor = Supervisor::new(bus, config(2));
+        let runs = Arc::new(AtomicU32::new(0));
+
+        let handle = supervisor.spawn("mam", {
+            let runs = runs.clone();
+            move || {
+                runs.fetch_add(1, Ordering::SeqCst);
+                async { Err("still broken".to_string()) }
+            }
+        });
+
+        assert_eq!(
+            handle.await.unwrap(),
+            TaskExit::GaveUp("still broken".to_string())
+        );
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+
+        let error = errors.recv().await.unwrap();
+        assert!(matches!(
+            error.payload,
+            EventPayload::ErrorOccurred { ref component, recoverable: false, .. }
+                if component == "mam"
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restart_count_resets_after_a_quiet_window() {
+        let bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let supervisor = Supervisor::new(bus, config(1));
+        let runs = Arc::new(AtomicU32::new(0));
+
+        let handle = supervisor.spawn("messaging", {
+            let runs = runs.clone();
+            move || {
+                let run = runs.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    if run == 1 {
+                        // Healthy for longer than the restart window.
+                        tokio::time::sleep(Duration::from_secs(120)).await;
+                    }
+                    if run < 2 {
+                        return Err("crashed".to_string());
+                    }
+                    Ok(())
+                }
+            }
+        });
+
+        assert_eq!(handle.await.unwrap(), TaskExit::Stopped);
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+}
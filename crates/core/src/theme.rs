@@ -1,9 +1,20 @@
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "native")]
+use std::sync::Arc;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::config::ThemeConfig;
+#[cfg(feature = "native")]
+use crate::event::{Channel, Event, EventBus, EventPayload, EventSource, channels};
+
+/// Directory under the data directory that user themes are loaded from,
+/// one TOML file each.
+pub const THEMES_DIR: &str = "themes";
+
+/// File under the data directory holding the id of the selected theme.
+const SELECTION_FILE: &str = "selected-theme";
 
 #[derive(Debug, thiserror::Error)]
 pub enum ThemeError {
@@ -15,9 +26,12 @@ pub enum ThemeError {
 
     #[error("invalid color value: {0}")]
     InvalidColor(String),
+
+    #[error("failed to save theme selection: {0}")]
+    Io(#[from] std::io::Error),
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ThemeColors {
     pub background: String,
     pub foreground: String,
@@ -124,12 +138,11 @@ impl Theme {
 
 fn validate_color(value: &str) -> Result<(), ThemeError> {
     let trimmed = value.trim();
-    if let Some(hex) = trimmed.strip_prefix('#') {
-        if (hex.len() == 3 || hex.len() == 6 || hex.len() == 8)
-            && hex.chars().all(|c| c.is_ascii_hexdigit())
-        {
-            return Ok(());
-        }
+    if let Some(hex) = trimmed.strip_prefix('#')
+        && (hex.len() == 3 || hex.len() == 6 || hex.len() == 8)
+        && hex.chars().all(|c| c.is_ascii_hexdigit())
+    {
+        return Ok(());
     }
     Err(ThemeError::InvalidColor(value.to_string()))
 }
@@ -211,6 +224,12 @@ const BUILTIN_NAMES: &[&str] = &["default", "dark", "high-contrast"];
 
 pub struct ThemeManager {
     custom_themes: HashMap<String, Theme>,
+    /// Where user themes are found and the selection is saved. Without
+    /// one, selections last until the process exits.
+    data_dir: Option<PathBuf>,
+    current: Theme,
+    #[cfg(feature = "native")]
+    event_bus: Option<Arc<dyn EventBus>>,
 }
 
 impl ThemeManager {
@@ -249,7 +268,107 @@ impl ThemeManager {
     pub fn new() -> Self {
         Self {
             custom_themes: HashMap::new(),
+            data_dir: None,
+            current: builtin_default(),
+            #[cfg(feature = "native")]
+            event_bus: None,
+        }
+    }
+
+    /// The registry for `data_dir`: the built-in themes, every theme in its
+    /// `themes/` directory and the one `config` points at. The theme last
+    /// passed to [`ThemeManager::set`] is current, or the configured one if
+    /// none was.
+    pub fn open(data_dir: &Path, config: &ThemeConfig) -> Self {
+        let mut manager = Self {
+            data_dir: Some(data_dir.to_path_buf()),
+            ..Self::new()
+        };
+        manager.load_user_themes(&data_dir.join(THEMES_DIR));
+
+        let configured = Self::load(config).unwrap_or_else(|_| builtin_default());
+        if Self::builtin(&configured.name).is_none() {
+            manager.register_custom(configured.clone());
         }
+        manager.current = manager
+            .saved_selection()
+            .and_then(|theme_id| manager.get(&theme_id))
+            .unwrap_or(configured);
+        manager
+    }
+
+    /// Publish [`EventPayload::ThemeApplied`] whenever the theme is set.
+    #[cfg(feature = "native")]
+    pub fn with_event_bus(mut self, event_bus: Arc<dyn EventBus>) -> Self {
+        self.event_bus = Some(event_bus);
+        self
+    }
+
+    /// Register every `*.toml` theme in `dir`. Files that fail to load are
+    /// logged and skipped; a missing directory has no themes.
+    pub fn load_user_themes(&mut self, dir: &Path) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            match Self::load_custom(&path.to_string_lossy()) {
+                Ok(theme) if Self::builtin(&theme.name).is_some() => {
+                    tracing::warn!(
+                        "theme '{}' in '{}' shadows a built-in theme; skipping",
+                        theme.name,
+                        path.display()
+                    );
+                }
+                Ok(theme) => self.register_custom(theme),
+                Err(err) => {
+                    tracing::warn!("skipping theme '{}': {}", path.display(), err);
+                }
+            }
+        }
+    }
+
+    pub fn current(&self) -> &Theme {
+        &self.current
+    }
+
+    /// Make `theme_id` the current theme, save the choice and announce its
+    /// palette on `system.theme.applied`.
+    pub fn set(&mut self, theme_id: &str) -> Result<Theme, ThemeError> {
+        let theme = self
+            .get(theme_id)
+            .ok_or_else(|| ThemeError::NotFound(theme_id.to_string()))?;
+        validate_theme_colors(&theme.colors)?;
+
+        if let Some(data_dir) = &self.data_dir {
+            std::fs::create_dir_all(data_dir)?;
+            std::fs::write(data_dir.join(SELECTION_FILE), format!("{theme_id}\n"))?;
+        }
+        self.current = theme.clone();
+
+        #[cfg(feature = "native")]
+        if let Some(event_bus) = &self.event_bus {
+            let _ = event_bus.publish(Event::new(
+                Channel::new(channels::SYSTEM_THEME_APPLIED).unwrap(),
+                EventSource::System("theme".into()),
+                EventPayload::ThemeApplied {
+                    theme_id: theme_id.to_string(),
+                    colors: theme.colors.clone(),
+                },
+            ));
+        }
+        Ok(theme)
+    }
+
+    fn saved_selection(&self) -> Option<String> {
+        let path = self.data_dir.as_ref()?.join(SELECTION_FILE);
+        let theme_id = std::fs::read_to_string(path).ok()?;
+        Some(theme_id.trim().to_string()).filter(|theme_id| !theme_id.is_empty())
     }
 
     pub fn register_custom(&mut self, theme: Theme) {
@@ -678,4 +797,72 @@ muted = "#999999"
         let manager = ThemeManager::default();
         assert!(manager.get("default").is_some());
     }
+
+    const SOLARIZED: &str = r##"
+[meta]
+name = "solarized"
+
+[colors]
+background = "#002b36"
+foreground = "#839496"
+surface = "#073642"
+accent = "#268bd2"
+border = "#586e75"
+success = "#859900"
+warning = "#b58900"
+error = "#dc322f"
+muted = "#657b83"
+"##;
+
+    #[test]
+    fn open_loads_user_themes_and_remembers_the_selection() {
+        let dir = tempfile::tempdir().unwrap();
+        let themes = dir.path().join(THEMES_DIR);
+        std::fs::create_dir_all(&themes).unwrap();
+        std::fs::write(themes.join("solarized.toml"), SOLARIZED).unwrap();
+        std::fs::write(themes.join("broken.toml"), "not a theme").unwrap();
+        std::fs::write(themes.join("notes.txt"), "ignored").unwrap();
+
+        let config = ThemeConfig {
+            name: "dark".to_string(),
+            custom_path: None,
+        };
+        let mut manager = ThemeManager::open(dir.path(), &config);
+        assert_eq!(manager.current().name, "dark");
+        assert_eq!(
+            manager.available_themes(),
+            vec!["dark", "default", "high-contrast", "solarized"]
+        );
+
+        let theme = manager.set("solarized").unwrap();
+        assert_eq!(theme.colors.background, "#002b36");
+        assert_eq!(manager.current().name, "solarized");
+        assert!(matches!(
+            manager.set("missing"),
+            Err(ThemeError::NotFound(_))
+        ));
+        assert_eq!(manager.current().name, "solarized");
+
+        let reopened = ThemeManager::open(dir.path(), &config);
+        assert_eq!(reopened.current().name, "solarized");
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn set_announces_the_palette() {
+        use crate::event::BroadcastEventBus;
+
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let mut sub = event_bus.subscribe("system.theme.applied").unwrap();
+        let mut manager = ThemeManager::new().with_event_bus(event_bus.clone());
+
+        manager.set("high-contrast").unwrap();
+
+        let event = sub.recv().await.unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::ThemeApplied { ref theme_id, ref colors }
+                if theme_id == "high-contrast" && colors.accent == "#ffff00"
+        ));
+    }
 }
@@ -0,0 +1,332 @@
+//! Serves the [control protocol](waddle_daemon::protocol) on a local
+//! socket.
+
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tracing::{debug, warn};
+
+use waddle_api::Waddle;
+use waddle_core::error::EventBusError;
+use waddle_core::event::{Channel, Event, EventPayload, EventSource, channels};
+use waddle_daemon::protocol::{
+    ConnectionStatus, ControlEnvelope, ControlRequest, ControlResponse, DaemonStatus,
+};
+
+use crate::SYSTEM_COMPONENT;
+
+pub struct ControlServer {
+    waddle: Waddle,
+    own_jid: String,
+    connection: RwLock<ConnectionStatus>,
+    started: Instant,
+}
+
+impl ControlServer {
+    pub fn new(waddle: Waddle, own_jid: impl Into<String>) -> Self {
+        Self {
+            waddle,
+            own_jid: own_jid.into(),
+            connection: RwLock::new(ConnectionStatus::Disconnected),
+            started: Instant::now(),
+        }
+    }
+
+    /// Follow the connection state reported in `status`.
+    pub async fn run(&self) -> Result<(), EventBusError> {
+        let mut subscription = self.waddle.event_bus().subscribe("system.connection.*")?;
+        loop {
+            let status = match subscription.recv().await {
+                Ok(event) => match event.payload {
+                    EventPayload::ConnectionEstablished { .. } => ConnectionStatus::Connected,
+                    EventPayload::ConnectionLost {
+                        will_retry: true, ..
+                    }
+                    | EventPayload::ConnectionReconnecting { .. } => ConnectionStatus::Reconnecting,
+                    EventPayload::ConnectionLost { .. } => ConnectionStatus::Disconnected,
+                    _ => continue,
+                },
+                Err(EventBusError::Lagged(_)) => continue,
+                Err(EventBusError::ChannelClosed) => return Ok(()),
+                Err(error) => return Err(error),
+            };
+            *self.connection.write().unwrap() = status;
+        }
+    }
+
+    pub fn status(&self) -> DaemonStatus {
+        let presence = self.waddle.presence().own_presence();
+        DaemonStatus {
+            jid: self.own_jid.clone(),
+            connection: *self.connection.read().unwrap(),
+            presence: presence.show,
+            status_message: presence.status,
+            uptime_secs: self.started.elapsed().as_secs(),
+        }
+    }
+
+    /// Accept clients on `listener` until it fails. Bind it with
+    /// [`waddle_core::bridge::bind_private_socket`], which keeps it to this
+    /// user and fails if another daemon is answering on the same path.
+    #[cfg(unix)]
+    pub async fn serve_unix(
+        self: Arc<Self>,
+        listener: Arc<tokio::net::UnixListener>,
+    ) -> std::io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Err(error) = server.serve_connection(stream).await {
+                    debug!(%error, "control client disconnected");
+                }
+            });
+        }
+    }
+
+    /// Answer requests on `stream` until the client disconnects.
+    pub async fn serve_connection<S>(&self, stream: S) -> std::io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite,
+    {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match serde_json::from_str::<ControlEnvelope>(&line) {
+                Ok(envelope) => {
+                    ControlResponse::from_result(envelope.id, self.handle(envelope.request).await)
+                }
+                Err(error) => {
+                    ControlResponse::from_result(None, Err(format!("invalid request: {error}")))
+                }
+            };
+            // Responses only hold serializable data.
+            let mut line = serde_json::to_vec(&response).unwrap_or_default();
+            line.push(b'\n');
+            writer.write_all(&line).await?;
+            writer.flush().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn handle(&self, request: ControlRequest) -> Result<Value, String> {
+        let result = match request {
+            ControlRequest::Status => serde_json::to_value(self.status()),
+            ControlRequest::SendMessage { to, body } => {
+                let message = self
+                    .waddle
+                    .messages()
+                    .send_message(&to, &body)
+                    .await
+                    .map_err(|error| error.to_string())?;
+                serde_json::to_value(message)
+            }
+            ControlRequest::SetPresence { show, status } => {
+                self.waddle
+                    .presence()
+                    .set_own_presence(show, status.as_deref(), None)
+                    .map_err(|error| error.to_string())?;
+                serde_json::to_value(self.status())
+            }
+            ControlRequest::ListConversations => {
+                let conversations = self
+                    .waddle
+                    .conversations()
+                    .list_conversations()
+                    .await
+                    .map_err(|error| error.to_string())?;
+                serde_json::to_value(conversations)
+            }
+            ControlRequest::ListRoster => {
+                let roster = self
+                    .waddle
+                    .roster()
+                    .get_roster()
+                    .await
+                    .map_err(|error| error.to_string())?;
+                serde_json::to_value(roster)
+            }
+            ControlRequest::JoinRoom { room, nick } => {
+                self.waddle
+                    .muc()
+                    .join_room(&room, &nick)
+                    .await
+                    .map_err(|error| error.to_string())?;
+                Ok(Value::Null)
+            }
+            ControlRequest::Shutdown => {
+                // The coordinator picks this up once the response is out.
+                let event = Channel::new(channels::SYSTEM_SHUTDOWN_REQUESTED).map(|channel| {
+                    Event::new(
+                        channel,
+                        EventSource::System(SYSTEM_COMPONENT.to_string()),
+                        EventPayload::ShutdownRequested {
+                            reason: "requested over the control socket".to_string(),
+                        },
+                    )
+                });
+                event
+                    .and_then(|event| self.waddle.event_bus().publish(event))
+                    .map_err(|error| error.to_string())?;
+                Ok(Value::Null)
+            }
+        };
+        result.map_err(|error| {
+            warn!(%error, "cannot encode control response");
+            error.to_string()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+    use tokio::time::timeout;
+
+    use waddle_core::event::{Channel, Event, EventSource, channels};
+
+    async fn server(dir: &TempDir) -> Arc<ControlServer> {
+        let waddle = Waddle::open(&dir.path().join("waddle.db"), 64)
+            .await
+            .unwrap();
+        Arc::new(ControlServer::new(waddle, "alice@example.com"))
+    }
+
+    async fn exchange(server: &Arc<ControlServer>, requests: &[&str]) -> Vec<ControlResponse> {
+        let (client, stream) = tokio::io::duplex(64 * 1024);
+        let task = {
+            let server = server.clone();
+            tokio::spawn(async move { server.serve_connection(stream).await })
+        };
+        let (reader, mut writer) = tokio::io::split(client);
+        for request in requests {
+            writer.write_all(request.as_bytes()).await.unwrap();
+            writer.write_all(b"\n").await.unwrap();
+        }
+        let mut lines = BufReader::new(reader).lines();
+        let mut responses = Vec::new();
+        for _ in requests {
+            let line = timeout(Duration::from_secs(1), lines.next_line())
+                .await
+                .expect("timed out")
+                .unwrap()
+                .unwrap();
+            responses.push(serde_json::from_str(&line).unwrap());
+        }
+        // The server sees end of input once both halves are gone.
+        drop((lines, writer));
+        timeout(Duration::from_secs(1), task)
+            .await
+            .expect("server kept the connection open")
+            .unwrap()
+            .unwrap();
+        responses
+    }
+
+    #[tokio::test]
+    async fn answers_requests_in_order() {
+        let dir = TempDir::new().unwrap();
+        let server = server(&dir).await;
+
+        let responses = exchange(
+            &server,
+            &[
+                r#"{"id":1,"command":"status"}"#,
+                r#"{"id":2,"command":"setPresence","show":"dnd","status":"focus"}"#,
+                r#"{"id":3,"command":"sendMessage","to":"bob@example.com","body":"hi"}"#,
+                r#"{"id":4,"command":"listConversations"}"#,
+                r#"{"id":5,"command":"reboot"}"#,
+                r#"{"id":6,"command":"joinRoom","room":"room@conference.example.com","nick":"alice"}"#,
+                r#"{"id":7,"command":"listRoster"}"#,
+            ],
+        )
+        .await;
+
+        let ids: Vec<_> = responses.iter().map(|response| response.id).collect();
+        assert_eq!(
+            ids,
+            [Some(1), Some(2), Some(3), Some(4), None, Some(6), Some(7)]
+        );
+
+        let status = responses[0].result.as_ref().unwrap();
+        assert_eq!(status["jid"], "alice@example.com");
+        assert_eq!(status["connection"], "disconnected");
+
+        let status = responses[1].result.as_ref().unwrap();
+        assert_eq!(status["presence"], "dnd");
+        assert_eq!(status["statusMessage"], "focus");
+
+        assert!(responses[2].ok, "{:?}", responses[2].error);
+        assert_eq!(responses[2].result.as_ref().unwrap()["body"], "hi");
+        assert!(responses[3].result.as_ref().unwrap().is_array());
+
+        assert!(!responses[4].ok);
+        assert!(
+            responses[4]
+                .error
+                .as_ref()
+                .unwrap()
+                .contains("invalid request")
+        );
+
+        assert!(responses[5].ok, "{:?}", responses[5].error);
+        assert_eq!(responses[6].result, Some(Value::Array(Vec::new())));
+    }
+
+    #[tokio::test]
+    async fn shutdown_is_requested_on_the_bus() {
+        let dir = TempDir::new().unwrap();
+        let server = server(&dir).await;
+        let mut requests = server
+            .waddle
+            .event_bus()
+            .subscribe(channels::SYSTEM_SHUTDOWN_REQUESTED)
+            .unwrap();
+
+        let responses = exchange(&server, &[r#"{"command":"shutdown"}"#]).await;
+        assert!(responses[0].ok);
+        let event = timeout(Duration::from_secs(1), requests.recv())
+            .await
+            .expect("no shutdown request")
+            .unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::ShutdownRequested { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn status_follows_the_connection() {
+        let dir = TempDir::new().unwrap();
+        let server = server(&dir).await;
+        let tracker = server.clone();
+        tokio::spawn(async move { tracker.run().await });
+        tokio::task::yield_now().await;
+
+        server
+            .waddle
+            .event_bus()
+            .publish(Event::new(
+                Channel::new(channels::SYSTEM_CONNECTION_ESTABLISHED).unwrap(),
+                EventSource::Xmpp,
+                EventPayload::ConnectionEstablished {
+                    jid: "alice@example.com".to_string(),
+                },
+            ))
+            .unwrap();
+        timeout(Duration::from_secs(1), async {
+            while server.status().connection != ConnectionStatus::Connected {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("status never changed");
+    }
+}
@@ -0,0 +1,32 @@
+use waddle_api::ApiError;
+use waddle_core::bridge::BridgeError;
+use waddle_core::config::ConfigError;
+use waddle_core::credentials::CredentialError;
+use waddle_core::error::EventBusError;
+#[cfg(feature = "debug")]
+use waddle_core::event::replay::ReplayError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DaemonError {
+    #[error("configuration error: {0}")]
+    Config(#[from] ConfigError),
+
+    #[error("{0}")]
+    Api(#[from] ApiError),
+
+    #[error("event bus error: {0}")]
+    EventBus(#[from] EventBusError),
+
+    #[error("credential store error: {0}")]
+    Credentials(#[from] CredentialError),
+
+    #[error("event bridge error: {0}")]
+    Bridge(#[from] BridgeError),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[cfg(feature = "debug")]
+    #[error("event recording error: {0}")]
+    Recording(#[from] ReplayError),
+}
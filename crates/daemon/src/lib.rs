@@ -0,0 +1,47 @@
+//! What clients of `waddled` share with it: the control protocol and where
+//! the daemon keeps its files.
+
+pub mod protocol;
+
+use std::path::PathBuf;
+
+use directories::{BaseDirs, ProjectDirs};
+use waddle_core::config::Config;
+
+/// The control socket's name when `daemon.control_socket` is unset. It sits
+/// next to the database.
+pub const DEFAULT_CONTROL_SOCKET: &str = "waddled.sock";
+
+pub fn data_dir() -> PathBuf {
+    ProjectDirs::from("com", "waddle", "waddle")
+        .map(|project_dirs| project_dirs.data_dir().to_path_buf())
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+pub fn storage_path(config: &Config) -> PathBuf {
+    config
+        .storage
+        .path
+        .as_deref()
+        .map(expand_home_path)
+        .unwrap_or_else(|| data_dir().join("waddle.db"))
+}
+
+pub fn control_socket_path(config: &Config) -> PathBuf {
+    config
+        .daemon
+        .control_socket
+        .as_deref()
+        .map(expand_home_path)
+        .unwrap_or_else(|| storage_path(config).with_file_name(DEFAULT_CONTROL_SOCKET))
+}
+
+pub fn expand_home_path(path: &str) -> PathBuf {
+    if let Some(stripped) = path.strip_prefix("~/")
+        && let Some(base_dirs) = BaseDirs::new()
+    {
+        return base_dirs.home_dir().join(stripped);
+    }
+
+    PathBuf::from(path)
+}
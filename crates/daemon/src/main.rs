@@ -0,0 +1,253 @@
+//! `waddled`: a Waddle session without a user interface.
+//!
+//! Runs the managers and the XMPP connection for the configured account and
+//! takes commands on a control socket (see [`control`]). With the event
+//! bridge enabled, frontends and tools can also attach to the session's
+//! event bus.
+
+mod control;
+mod error;
+mod session;
+
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use tracing::{info, warn};
+
+use waddle_api::Waddle;
+use waddle_core::bridge::{BridgeAcl, EventBridge, TOKEN_FILE, write_token_file};
+use waddle_core::config::{self, Config};
+use waddle_core::credentials::{
+    CredentialError, CredentialStore, EncryptedFileCredentialStore, InMemoryCredentialStore,
+    KeyringCredentialStore,
+};
+use waddle_core::event::channels;
+#[cfg(feature = "debug")]
+use waddle_core::event::replay::RecordingEventBus;
+#[cfg(feature = "debug")]
+use waddle_core::event::{BroadcastEventBus, EventBus};
+use waddle_core::shutdown::ShutdownCoordinator;
+use waddle_core::supervisor::Supervisor;
+use waddle_daemon::{control_socket_path, data_dir, expand_home_path, storage_path};
+
+use crate::control::ControlServer;
+use crate::error::DaemonError;
+
+const SYSTEM_COMPONENT: &str = "daemon";
+const CREDENTIALS_PASSPHRASE_ENV: &str = "WADDLE_CREDENTIALS_PASSPHRASE";
+/// File to record the session's events to, in builds with the `debug`
+/// feature.
+#[cfg(feature = "debug")]
+const EVENT_RECORDING_ENV: &str = "WADDLE_EVENT_RECORDING";
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt::init();
+
+    // The XMPP connection runs on local tasks; see `session`.
+    let local = tokio::task::LocalSet::new();
+    match local.run_until(run()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("waddled: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run() -> Result<(), DaemonError> {
+    let config = config::load_config()?;
+    channels::validate()?;
+
+    // Bound first, so a second daemon for the same socket stops here.
+    #[cfg(unix)]
+    let control_listener = {
+        let path = control_socket_path(&config);
+        let listener = waddle_core::bridge::bind_private_socket(&path)?;
+        info!(path = %path.display(), "control socket enabled");
+        Arc::new(listener)
+    };
+
+    let storage_path = storage_path(&config);
+    let waddle = open_waddle(&storage_path, &config).await?;
+    let event_bus = waddle.event_bus();
+    info!(path = %storage_path.display(), "storage initialized");
+
+    // The facade's manager loops end when the bus closes on shutdown.
+    let _managers = waddle.start();
+    let supervisor = Supervisor::new(event_bus.clone(), config.supervisor.clone());
+
+    let credentials = credential_store_from(&config).await?;
+    let connection = session::start(&config, &waddle, credentials, &supervisor);
+
+    let shutdown = Arc::new(ShutdownCoordinator::new(event_bus.clone()));
+    for manager in waddle.shutdown_managers() {
+        shutdown.register(manager);
+    }
+    shutdown.register(Arc::new(connection));
+    // Ends once a component requested shutdown and it completed.
+    let requested = tokio::spawn(shutdown.clone().run());
+
+    let control = Arc::new(ControlServer::new(waddle.clone(), &config.account.jid));
+    {
+        let control = control.clone();
+        supervisor.spawn("control", move || {
+            let control = control.clone();
+            async move { control.run().await.map_err(|error| error.to_string()) }
+        });
+    }
+    #[cfg(unix)]
+    supervisor.spawn("control.unix", move || {
+        let control = control.clone();
+        let listener = control_listener.clone();
+        async move {
+            control
+                .serve_unix(listener)
+                .await
+                .map_err(|error| error.to_string())
+        }
+    });
+
+    if config.event_bus.bridge.enabled {
+        start_bridge(&config, &waddle, &supervisor, &storage_path)?;
+    }
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => {
+            if let Err(error) = result {
+                warn!(%error, "cannot listen for Ctrl-C");
+            }
+            shutdown.shutdown("interrupted").await;
+        }
+        result = requested => {
+            if let Ok(Err(error)) = result {
+                warn!(%error, "shutdown coordinator stopped");
+            }
+        }
+    }
+    info!("session shut down");
+    Ok(())
+}
+
+/// Open the session. Builds with the `debug` feature record its events
+/// when `WADDLE_EVENT_RECORDING` names a file.
+async fn open_waddle(storage_path: &Path, config: &Config) -> Result<Waddle, DaemonError> {
+    #[cfg(feature = "debug")]
+    if let Ok(recording) = std::env::var(EVENT_RECORDING_ENV) {
+        let event_bus: Arc<dyn EventBus> = Arc::new(RecordingEventBus::create(
+            Arc::new(BroadcastEventBus::new(config.event_bus.channel_capacity)),
+            Path::new(&recording),
+        )?);
+        info!(path = %recording, "recording events");
+        return Ok(Waddle::open_with_event_bus(storage_path, event_bus).await?);
+    }
+    Ok(Waddle::open(storage_path, config.event_bus.channel_capacity).await?)
+}
+
+/// Let frontends attach to the session's event bus.
+fn start_bridge(
+    config: &Config,
+    waddle: &Waddle,
+    supervisor: &Supervisor,
+    storage_path: &std::path::Path,
+) -> Result<(), DaemonError> {
+    let bridge_config = &config.event_bus.bridge;
+    let mut bridge = EventBridge::new(waddle.event_bus(), BridgeAcl::from_config(bridge_config)?)
+        .with_config(bridge_config);
+    if bridge_config.websocket.is_some() {
+        // Beside the Unix socket, or the database where there is none.
+        let token_path = bridge_config
+            .path
+            .as_deref()
+            .filter(|_| cfg!(unix))
+            .map(expand_home_path)
+            .unwrap_or_else(|| storage_path.to_path_buf())
+            .with_file_name(TOKEN_FILE);
+        bridge = bridge.with_token(write_token_file(&token_path)?);
+        info!(path = %token_path.display(), "event bridge WebSocket token written");
+    }
+    if let (Some(cert), Some(key)) = (
+        bridge_config.websocket_tls_cert.as_deref(),
+        bridge_config.websocket_tls_key.as_deref(),
+    ) {
+        bridge = bridge.with_tls(&expand_home_path(cert), &expand_home_path(key))?;
+    }
+    let bridge = Arc::new(bridge);
+    {
+        let bridge = bridge.clone();
+        supervisor.spawn("event-bridge", move || {
+            let bridge = bridge.clone();
+            async move { bridge.run().await.map_err(|error| error.to_string()) }
+        });
+    }
+    if let Some(address) = bridge_config.websocket.as_deref() {
+        // Checked when the configuration was loaded.
+        if let Ok(address) = address.parse::<std::net::SocketAddr>() {
+            info!(%address, "event bridge accepting WebSocket clients");
+            let bridge = bridge.clone();
+            supervisor.spawn("event-bridge.websocket", move || {
+                let bridge = bridge.clone();
+                async move {
+                    bridge
+                        .serve_websocket(address)
+                        .await
+                        .map_err(|error| error.to_string())
+                }
+            });
+        }
+    }
+    #[cfg(unix)]
+    {
+        let path = bridge_config
+            .path
+            .as_deref()
+            .map(expand_home_path)
+            .unwrap_or_else(|| storage_path.with_file_name("bridge.sock"));
+        info!(path = %path.display(), "event bridge enabled");
+        supervisor.spawn("event-bridge.unix", move || {
+            let bridge = bridge.clone();
+            let path = path.clone();
+            async move {
+                bridge
+                    .serve_unix(&path)
+                    .await
+                    .map_err(|error| error.to_string())
+            }
+        });
+    }
+    Ok(())
+}
+
+/// A password in the configuration wins, then the OS keyring, then an
+/// encrypted file unlocked by `WADDLE_CREDENTIALS_PASSPHRASE`.
+async fn credential_store_from(config: &Config) -> Result<Arc<dyn CredentialStore>, DaemonError> {
+    if !config.account.password.is_empty() {
+        warn!("account password is set in plaintext configuration; prefer the OS keyring");
+        return Ok(Arc::new(InMemoryCredentialStore::with_secret(
+            &config.account.jid,
+            &config.account.password,
+        )));
+    }
+
+    let credentials_path = data_dir().join("credentials.json");
+    let store = tokio::task::spawn_blocking(move || {
+        let keyring = KeyringCredentialStore::default();
+        if keyring.is_available() {
+            return Ok::<Arc<dyn CredentialStore>, CredentialError>(Arc::new(keyring));
+        }
+        let Ok(passphrase) = std::env::var(CREDENTIALS_PASSPHRASE_ENV) else {
+            return Err(CredentialError::Unavailable(format!(
+                "no OS keyring found; set {CREDENTIALS_PASSPHRASE_ENV} to use an encrypted credential file"
+            )));
+        };
+        Ok(Arc::new(EncryptedFileCredentialStore::open(
+            &credentials_path,
+            &passphrase,
+        )?))
+    })
+    .await
+    .map_err(|error| CredentialError::Unavailable(error.to_string()))??;
+
+    Ok(store)
+}
@@ -0,0 +1,106 @@
+//! The control socket: one JSON request per line in, one JSON response per
+//! line out, in order.
+//!
+//! ```text
+//! {"id":1,"command":"status"}
+//! {"id":1,"ok":true,"result":{"jid":"alice@example.com","connection":"connected",...}}
+//! {"command":"sendMessage","to":"bob@example.com","body":"hi"}
+//! {"command":"setPresence","show":"away","status":"lunch"}
+//! {"command":"listConversations"}
+//! {"command":"listRoster"}
+//! {"command":"joinRoom","room":"room@conference.example.com","nick":"alice"}
+//! {"command":"shutdown"}
+//! ```
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use waddle_core::event::PresenceShow;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "camelCase")]
+pub enum ControlRequest {
+    Status,
+    SendMessage {
+        to: String,
+        body: String,
+    },
+    SetPresence {
+        show: PresenceShow,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        status: Option<String>,
+    },
+    ListConversations,
+    ListRoster,
+    JoinRoom {
+        room: String,
+        nick: String,
+    },
+    /// End the session; the response is sent before it shuts down.
+    Shutdown,
+}
+
+/// A request with the optional `id` its response repeats.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ControlEnvelope {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub request: ControlRequest,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ControlResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ControlResponse {
+    pub fn from_result(id: Option<u64>, result: Result<Value, String>) -> Self {
+        match result {
+            Ok(result) => Self {
+                id,
+                ok: true,
+                result: Some(result),
+                error: None,
+            },
+            Err(error) => Self {
+                id,
+                ok: false,
+                result: None,
+                error: Some(error),
+            },
+        }
+    }
+
+    pub fn into_result(self) -> Result<Value, String> {
+        if self.ok {
+            Ok(self.result.unwrap_or(Value::Null))
+        } else {
+            Err(self.error.unwrap_or_else(|| "request failed".to_string()))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConnectionStatus {
+    Disconnected,
+    Connected,
+    Reconnecting,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DaemonStatus {
+    pub jid: String,
+    pub connection: ConnectionStatus,
+    pub presence: PresenceShow,
+    pub status_message: Option<String>,
+    pub uptime_secs: u64,
+}
@@ -0,0 +1,346 @@
+//! The XMPP connection behind a headless session: the stanza pipeline, the
+//! outbound router and the tasks moving stanzas between them and the
+//! server.
+//!
+//! The connection's futures are not `Send`, so its tasks are local and
+//! [`start`] must be called inside a [`tokio::task::LocalSet`].
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{Mutex, mpsc, oneshot};
+use tokio::task::spawn_local;
+use tracing::{debug, error, warn};
+
+use waddle_api::Waddle;
+use waddle_core::WaddleError;
+use waddle_core::config::Config;
+use waddle_core::credentials::CredentialStore;
+use waddle_core::error::EventBusError;
+use waddle_core::event::{Channel, Event, EventBus, EventPayload, EventSource, channels};
+use waddle_core::shutdown::{Manager as ShutdownManager, ShutdownFuture};
+use waddle_core::supervisor::Supervisor;
+use waddle_xmpp::{
+    ChatStateProcessor, ConnectionConfig, ConnectionError, ConnectionManager, DiscoProcessor,
+    HEALTH_INTERVAL, MamProcessor, MessageProcessor, MucProcessor, OutboundRouter,
+    PresenceProcessor, PubSubManager, PubSubProcessor, RosterProcessor, StanzaPipeline,
+    StanzaReceiver, stanza_channel,
+};
+
+use crate::SYSTEM_COMPONENT;
+
+const CONNECTION_TIMEOUT_SECONDS: u32 = 30;
+const CONNECTION_MAX_RECONNECT_ATTEMPTS: u32 = 5;
+const WIRE_CHANNEL_CAPACITY: usize = 256;
+
+type SharedConnection = Arc<Mutex<ConnectionManager>>;
+
+/// Wire the account's connection to the session's managers and start
+/// connecting. The returned manager disconnects it on shutdown.
+pub fn start(
+    config: &Config,
+    waddle: &Waddle,
+    credentials: Arc<dyn CredentialStore>,
+    supervisor: &Supervisor,
+) -> ConnectionShutdown {
+    let event_bus = waddle.event_bus();
+    let pipeline = Arc::new(build_stanza_pipeline(event_bus.clone()));
+    let (wire_sender, wire_receiver) = stanza_channel(WIRE_CHANNEL_CAPACITY);
+
+    let router = Arc::new(OutboundRouter::new(
+        event_bus.clone(),
+        pipeline.clone(),
+        wire_sender.clone(),
+    ));
+    supervisor.spawn("xmpp.outbound", move || {
+        let router = router.clone();
+        async move { router.run().await.map_err(|error| error.to_string()) }
+    });
+
+    // Archive and mute flags follow the account to its other devices via PEP.
+    waddle
+        .conversations()
+        .set_pubsub(Arc::new(PubSubManager::new(
+            config.account.jid.clone(),
+            event_bus.clone(),
+            pipeline.clone(),
+            wire_sender.clone(),
+        )));
+    // Our own nickname, avatar and vCard are published there too.
+    waddle.profile().set_pubsub(Arc::new(PubSubManager::new(
+        config.account.jid.clone(),
+        event_bus.clone(),
+        pipeline.clone(),
+        wire_sender,
+    )));
+
+    let connection = Arc::new(Mutex::new(ConnectionManager::with_event_bus(
+        connection_config_from(config),
+        credentials,
+        event_bus.clone(),
+    )));
+    spawn_wire_pump(connection.clone(), wire_receiver, event_bus.clone());
+    spawn_inbound_pump(connection.clone(), pipeline, event_bus.clone());
+    spawn_health_monitor(connection.clone(), event_bus.clone());
+    spawn_connection_control(connection.clone(), event_bus.clone());
+    spawn_initial_connection(connection.clone(), event_bus);
+    spawn_disconnector(connection)
+}
+
+fn connection_config_from(config: &Config) -> ConnectionConfig {
+    ConnectionConfig {
+        jid: config.account.jid.clone(),
+        server: config.account.server.clone(),
+        port: config.account.port,
+        timeout_seconds: CONNECTION_TIMEOUT_SECONDS,
+        max_reconnect_attempts: CONNECTION_MAX_RECONNECT_ATTEMPTS,
+        transports: config.account.transports.clone(),
+        websocket_url: config.account.websocket_url.clone(),
+        bosh_url: config.account.bosh_url.clone(),
+    }
+}
+
+/// The processors of every manager the session runs. Plugins, OMEMO and
+/// calls need a frontend, so their processors are left out.
+fn build_stanza_pipeline(event_bus: Arc<dyn EventBus>) -> StanzaPipeline {
+    let mut pipeline = StanzaPipeline::new();
+    pipeline.register(Box::new(RosterProcessor::new(event_bus.clone())));
+    pipeline.register(Box::new(MessageProcessor::new(event_bus.clone())));
+    pipeline.register(Box::new(PresenceProcessor::new(event_bus.clone())));
+    pipeline.register(Box::new(MamProcessor::new(event_bus.clone())));
+    pipeline.register(Box::new(MucProcessor::new(event_bus.clone())));
+    pipeline.register(Box::new(ChatStateProcessor::new(event_bus.clone())));
+    pipeline.register(Box::new(DiscoProcessor::new(event_bus.clone())));
+    pipeline.register(Box::new(PubSubProcessor::new(event_bus)));
+    pipeline
+}
+
+type DisconnectRequest = oneshot::Sender<Result<(), ConnectionError>>;
+
+/// Closes the XMPP stream once the other managers have flushed, so the
+/// unavailable presence goes out before the disconnect.
+pub struct ConnectionShutdown {
+    requests: mpsc::Sender<DisconnectRequest>,
+}
+
+impl ShutdownManager for ConnectionShutdown {
+    fn name(&self) -> &'static str {
+        "xmpp"
+    }
+
+    fn shutdown(&self) -> ShutdownFuture<'_> {
+        Box::pin(async move {
+            let (reply, result) = oneshot::channel();
+            if self.requests.send(reply).await.is_err() {
+                return Ok(());
+            }
+            match result.await {
+                Ok(result) => result.map_err(|error| WaddleError::Xmpp(error.to_string())),
+                Err(_) => Ok(()),
+            }
+        })
+    }
+}
+
+/// Disconnect on the local task set on behalf of [`ConnectionShutdown`].
+fn spawn_disconnector(connection: SharedConnection) -> ConnectionShutdown {
+    let (requests, mut receiver) = mpsc::channel::<DisconnectRequest>(1);
+    spawn_local(async move {
+        while let Some(reply) = receiver.recv().await {
+            let result = connection.lock().await.disconnect().await;
+            let _ = reply.send(result);
+        }
+    });
+    ConnectionShutdown { requests }
+}
+
+/// Report `error` and try to bring the stream back.
+async fn recover(
+    connection: &SharedConnection,
+    event_bus: &Arc<dyn EventBus>,
+    error: ConnectionError,
+) {
+    let reason = error.to_string();
+    emit_component_error(event_bus, reason.clone(), error.is_retryable());
+    let result = connection
+        .lock()
+        .await
+        .recover_after_network_interruption(reason)
+        .await;
+    if let Err(error) = result {
+        emit_component_error(event_bus, error.to_string(), error.is_retryable());
+    }
+}
+
+fn spawn_wire_pump(
+    connection: SharedConnection,
+    mut wire_receiver: StanzaReceiver,
+    event_bus: Arc<dyn EventBus>,
+) {
+    spawn_local(async move {
+        while let Some(stanza) = wire_receiver.recv().await {
+            let result = connection.lock().await.send_stanza(&stanza).await;
+            if let Err(error) = result {
+                warn!(%error, "failed to send stanza to XMPP transport");
+                recover(&connection, &event_bus, error).await;
+            }
+        }
+        debug!("wire pump stopped");
+    });
+}
+
+fn spawn_inbound_pump(
+    connection: SharedConnection,
+    pipeline: Arc<StanzaPipeline>,
+    event_bus: Arc<dyn EventBus>,
+) {
+    spawn_local(async move {
+        loop {
+            let frame = {
+                let mut manager = connection.lock().await;
+                manager
+                    .recv_frame_with_timeout(Duration::from_millis(50))
+                    .await
+            };
+            let frame = match frame {
+                Ok(Some(frame)) => frame,
+                Ok(None) => {
+                    tokio::task::yield_now().await;
+                    continue;
+                }
+                Err(error) => {
+                    warn!(%error, "failed to receive stanza from XMPP transport");
+                    recover(&connection, &event_bus, error).await;
+                    continue;
+                }
+            };
+
+            let handled = {
+                let mut manager = connection.lock().await;
+                match manager.handle_stream_management_frame(&frame).await {
+                    Ok(true) => true,
+                    Ok(false) => {
+                        let handled = manager.handle_carbons_iq_response(&frame)
+                            || manager.handle_ping_response(&frame);
+                        if handled {
+                            manager.mark_inbound_stanza_handled();
+                        }
+                        handled
+                    }
+                    Err(error) => {
+                        drop(manager);
+                        warn!(%error, "failed to handle stream-management frame");
+                        recover(&connection, &event_bus, error).await;
+                        continue;
+                    }
+                }
+            };
+            if handled {
+                continue;
+            }
+
+            if let Err(error) = pipeline.process_inbound(&frame).await {
+                warn!(%error, "failed to process inbound stanza");
+                continue;
+            }
+            connection.lock().await.mark_inbound_stanza_handled();
+        }
+    });
+}
+
+fn spawn_health_monitor(connection: SharedConnection, event_bus: Arc<dyn EventBus>) {
+    spawn_local(async move {
+        let mut interval = tokio::time::interval(HEALTH_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            // A failed ping surfaces again through the inbound pump, which
+            // owns reconnecting.
+            if let Err(error) = connection.lock().await.check_health().await {
+                debug!(%error, "connection health check failed");
+                emit_component_error(&event_bus, error.to_string(), error.is_retryable());
+            }
+        }
+    });
+}
+
+fn spawn_initial_connection(connection: SharedConnection, event_bus: Arc<dyn EventBus>) {
+    spawn_local(async move {
+        let result = connection.lock().await.connect().await;
+        if let Err(error) = result {
+            emit_component_error(&event_bus, error.to_string(), error.is_retryable());
+            if !error.is_retryable() {
+                publish(
+                    &event_bus,
+                    channels::SYSTEM_SHUTDOWN_REQUESTED,
+                    EventPayload::ShutdownRequested {
+                        reason: "non-recoverable authentication failure".to_string(),
+                    },
+                );
+            }
+        }
+    });
+}
+
+fn spawn_connection_control(connection: SharedConnection, event_bus: Arc<dyn EventBus>) {
+    spawn_local(async move {
+        let mut subscription = match event_bus.subscribe("{system,ui.client}.**") {
+            Ok(subscription) => subscription,
+            Err(error) => {
+                emit_component_error(&event_bus, error.to_string(), false);
+                return;
+            }
+        };
+
+        loop {
+            let event = match subscription.recv().await {
+                Ok(event) => event,
+                Err(EventBusError::Lagged(count)) => {
+                    warn!(count, "connection control lagged");
+                    continue;
+                }
+                Err(_) => return,
+            };
+            let result = match event.payload {
+                EventPayload::ComingOnline => connection.lock().await.connect().await,
+                EventPayload::GoingOffline => connection.lock().await.disconnect().await,
+                // XEP-0352: nothing is sent when the server lacks support.
+                EventPayload::ClientActivityChanged { active: true } => {
+                    connection.lock().await.set_csi_active().await
+                }
+                EventPayload::ClientActivityChanged { active: false } => {
+                    connection.lock().await.set_csi_inactive().await
+                }
+                _ => Ok(()),
+            };
+            if let Err(error) = result {
+                emit_component_error(&event_bus, error.to_string(), error.is_retryable());
+            }
+        }
+    });
+}
+
+fn publish(event_bus: &Arc<dyn EventBus>, channel: &str, payload: EventPayload) {
+    let result = Channel::new(channel).and_then(|channel| {
+        event_bus.publish(Event::new(
+            channel,
+            EventSource::System(SYSTEM_COMPONENT.to_string()),
+            payload,
+        ))
+    });
+    if let Err(error) = result {
+        error!(%error, channel, "failed to publish event");
+    }
+}
+
+fn emit_component_error(event_bus: &Arc<dyn EventBus>, message: String, recoverable: bool) {
+    publish(
+        event_bus,
+        channels::SYSTEM_ERROR_OCCURRED,
+        EventPayload::ErrorOccurred {
+            component: "xmpp".to_string(),
+            message,
+            recoverable,
+        },
+    );
+}
@@ -1,44 +1,76 @@
+use std::collections::HashMap;
 use std::future::Future;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 
+use chrono::{DateTime, Utc};
 use directories::{BaseDirs, ProjectDirs};
 use serde::{Deserialize, Serialize};
 use tauri::{AppHandle, Emitter, Manager, State};
 use tokio::sync::Mutex;
 use tracing::{debug, error, info, warn};
 
+use waddle_calls::CallManager;
+use waddle_core::WaddleError;
+use waddle_core::bridge::{BridgeAcl, BridgeError, EventBridge, TOKEN_FILE, write_token_file};
+use waddle_core::color::{self, ColorPalette, Rgb};
 use waddle_core::config::{self, Config};
+use waddle_core::credentials::{
+    CredentialError, CredentialStore, EncryptedFileCredentialStore, InMemoryCredentialStore,
+    KeyringCredentialStore,
+};
 use waddle_core::event::{
-    BroadcastEventBus, Channel, ChatMessage, Event, EventBus, EventPayload, EventSource,
-    PresenceShow, RosterItem, ScrollDirection, UiTarget,
+    BroadcastEventBus, Call, CallContent, CallEndReason, Channel, ChatMessage, ConnectionHealth,
+    Event, EventBus, EventPayload, EventSource, LinkPreview, LogEntry, MessageTranslation,
+    MucInvite, NotificationPreference, OmemoTrust, OverflowPolicy, PresenceShow, RosterItem, Rule,
+    ScheduledMessage, ScrollDirection, SpamReason, UiTarget, channels,
 };
+use waddle_core::jid::Jid;
+use waddle_core::logging::{Logging, LoggingError};
+use waddle_core::rsm::RsmQuery;
+use waddle_core::shutdown::{Manager as ShutdownManager, ShutdownCoordinator, ShutdownFuture};
+use waddle_core::styling;
+use waddle_core::supervisor::Supervisor;
+use waddle_core::theme::ThemeManager;
+use waddle_gui_bridge::{BridgeCommand, GuiBridge};
 use waddle_mam::MamManager;
-use waddle_messaging::{MessageManager, MucManager};
-use waddle_notifications::NotificationManager;
+use waddle_messaging::{
+    CachedFile, Conversation, ConversationManager, DeliveryStatus, DownloadManager,
+    LinkPreviewManager, MergeReport, MessageManager, MucManager, PruneReport, RetentionManager,
+    RuleEngine, TranslationManager, TypingTracker,
+};
+use waddle_notifications::{NotificationManager, NotificationSettings};
+use waddle_omemo::{OmemoDevice, OmemoManager};
 use waddle_plugins::{
-    InstalledPlugin, PluginCapability, PluginError, PluginInfo as RuntimePluginInfo,
-    PluginRegistry, PluginRuntime, PluginRuntimeConfig, PluginStatus as RuntimePluginStatus,
-    RegistryConfig, RegistryError,
+    DevWatcher, HostPresence, InstalledPlugin, KvExport, PluginCapability, PluginError, PluginHook,
+    PluginHostApi, PluginInfo as RuntimePluginInfo, PluginLimits, PluginRegistry, PluginRuntime,
+    PluginRuntimeConfig, PluginStatus as RuntimePluginStatus, PluginUiContribution, PluginUsage,
+    RegistryConfig, RegistryCredentials, RegistryError, StanzaVerdict,
+};
+use waddle_presence::{
+    AutoAwayMonitor, CapabilitiesManager, ContactCapabilities, HealthReport, PresenceManager,
+    ServerHealthMonitor,
+};
+use waddle_roster::{Profile, ProfileManager, RosterManager};
+use waddle_storage::{
+    self, BackupManager, Database, MaintenanceReport, MaintenanceScheduler, NativeDatabase,
+    StorageError, StorageStats,
 };
-use waddle_presence::PresenceManager;
-use waddle_roster::RosterManager;
-use waddle_storage::{self, NativeDatabase, StorageError};
 use waddle_xmpp::{
-    ChatStateProcessor, ConnectionConfig, ConnectionManager, ConnectionState, MamProcessor,
-    MessageProcessor, MucProcessor, OutboundRouter, PresenceProcessor, RosterProcessor,
-    StanzaPipeline, stanza_channel,
+    AccountManager, AccountProcessor, CapturedStanza, ChatStateProcessor, ConnectionConfig,
+    ConnectionManager, ConnectionState, DebugProcessor, DiscoProcessor, HEALTH_INTERVAL,
+    JingleProcessor, MamProcessor, MessageProcessor, MucProcessor, OmemoProcessor, OutboundRouter,
+    PipelineError, PluginHookFuture, PluginStanzaHost, PluginStanzaProcessor, PresenceProcessor,
+    PubSubManager, PubSubProcessor, RegistrationManager, RosterProcessor, StanzaDebugger,
+    StanzaDirection, StanzaFilter, StanzaHookOutcome, StanzaPipeline, stanza_channel,
 };
 
-#[cfg(debug_assertions)]
-use waddle_xmpp::DebugProcessor;
-
 const SYSTEM_COMPONENT: &str = "gui-backend";
 const CONNECTION_TIMEOUT_SECONDS: u32 = 30;
 const CONNECTION_MAX_RECONNECT_ATTEMPTS: u32 = 5;
 const WIRE_CHANNEL_CAPACITY: usize = 256;
-const SHUTDOWN_CLEANUP_TIMEOUT_SECONDS: u64 = 5;
+const CREDENTIALS_PASSPHRASE_ENV: &str = "WADDLE_CREDENTIALS_PASSPHRASE";
 
 #[derive(Debug, thiserror::Error)]
 enum GuiBackendError {
@@ -57,6 +89,15 @@ enum GuiBackendError {
     #[error("plugin runtime error: {0}")]
     PluginRuntime(#[from] PluginError),
 
+    #[error("credential store error: {0}")]
+    Credentials(#[from] CredentialError),
+
+    #[error("logging error: {0}")]
+    Logging(#[from] LoggingError),
+
+    #[error("event bridge error: {0}")]
+    Bridge(#[from] BridgeError),
+
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -178,20 +219,58 @@ enum PluginAction {
     Install { reference: String },
     Uninstall { plugin_id: String },
     Update { plugin_id: String },
+    Reload { plugin_id: String },
+    ReEnable { plugin_id: String },
     Get { plugin_id: String },
 }
 
 struct AppState {
     own_jid: String,
     ui_config: UiConfigResponse,
+    bridge: GuiBridge<NativeDatabase>,
+    theme_manager: Mutex<ThemeManager>,
     event_bus: Arc<dyn EventBus>,
+    credentials: Arc<dyn CredentialStore>,
+    account_manager: Arc<AccountManager>,
+    database: Arc<NativeDatabase>,
     connection_manager: Arc<Mutex<ConnectionManager>>,
     roster_manager: Arc<RosterManager<NativeDatabase>>,
+    profile_manager: Arc<ProfileManager<NativeDatabase>>,
     message_manager: Arc<MessageManager<NativeDatabase>>,
     muc_manager: Arc<MucManager<NativeDatabase>>,
+    conversation_manager: Arc<ConversationManager<NativeDatabase>>,
+    retention_manager: Arc<RetentionManager<NativeDatabase>>,
+    download_manager: Arc<DownloadManager>,
+    link_preview_manager: Arc<LinkPreviewManager<NativeDatabase>>,
+    translation_manager: Arc<TranslationManager<NativeDatabase>>,
+    rule_engine: Arc<RuleEngine<NativeDatabase>>,
+    call_manager: Arc<CallManager>,
+    typing_tracker: Arc<TypingTracker>,
+    notification_settings: Arc<NotificationSettings<NativeDatabase>>,
+    backup_manager: Arc<BackupManager>,
+    maintenance_scheduler: Arc<MaintenanceScheduler>,
     presence_manager: Arc<PresenceManager>,
+    capabilities_manager: Arc<CapabilitiesManager>,
+    health_monitor: Arc<ServerHealthMonitor<NativeDatabase>>,
+    omemo_manager: Arc<OmemoManager<NativeDatabase>>,
     plugin_registry: Arc<PluginRegistry>,
     plugin_runtime: Arc<Mutex<PluginRuntime<NativeDatabase>>>,
+    shutdown_coordinator: Arc<ShutdownCoordinator>,
+    stanza_debugger: Arc<StanzaDebugger>,
+    logging: Logging,
+}
+
+/// Run a host API command from the webview or a plugin UI.
+#[tauri::command]
+async fn invoke_bridge(
+    command: BridgeCommand,
+    state: State<'_, AppState>,
+) -> Result<serde_json::Value, String> {
+    state
+        .bridge
+        .invoke(command)
+        .await
+        .map_err(|error| error.to_string())
 }
 
 #[tauri::command]
@@ -207,6 +286,120 @@ async fn send_message(
         .map_err(|error| error.to_string())
 }
 
+#[tauri::command]
+async fn get_message_status(
+    id: String,
+    state: State<'_, AppState>,
+) -> Result<Option<DeliveryStatus>, String> {
+    state
+        .message_manager
+        .get_message_status(&id)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+/// Retract one of our own messages (XEP-0424), in a chat or a room.
+#[tauri::command]
+async fn retract_message(id: String, state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .message_manager
+        .retract_message(&id)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn schedule_message(
+    to: String,
+    body: String,
+    send_at: DateTime<Utc>,
+    state: State<'_, AppState>,
+) -> Result<ScheduledMessage, String> {
+    state
+        .message_manager
+        .schedule_message(&to, &body, send_at)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn cancel_scheduled_message(id: String, state: State<'_, AppState>) -> Result<bool, String> {
+    state
+        .message_manager
+        .cancel_scheduled_message(&id)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn list_scheduled_messages(
+    state: State<'_, AppState>,
+) -> Result<Vec<ScheduledMessage>, String> {
+    state
+        .message_manager
+        .list_scheduled_messages()
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn list_rules(state: State<'_, AppState>) -> Result<Vec<Rule>, String> {
+    state
+        .rule_engine
+        .list_rules()
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn save_rule(rule: Rule, state: State<'_, AppState>) -> Result<Rule, String> {
+    state
+        .rule_engine
+        .save_rule(rule)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn delete_rule(id: String, state: State<'_, AppState>) -> Result<bool, String> {
+    state
+        .rule_engine
+        .delete_rule(&id)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn get_notification_preference(
+    jid: String,
+    state: State<'_, AppState>,
+) -> Result<Option<NotificationPreference>, String> {
+    state
+        .notification_settings
+        .get(&jid)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn set_notification_preference(
+    jid: String,
+    preference: Option<NotificationPreference>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .notification_settings
+        .set(&jid, preference)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn notify_typing(to: String, state: State<'_, AppState>) -> Result<(), String> {
+    state.typing_tracker.keystroke(&to);
+    Ok(())
+}
+
 #[tauri::command]
 async fn get_roster(state: State<'_, AppState>) -> Result<Vec<RosterItem>, String> {
     let mut items = state
@@ -216,7 +409,8 @@ async fn get_roster(state: State<'_, AppState>) -> Result<Vec<RosterItem>, Strin
         .map_err(|error| error.to_string())?;
 
     // Inject a synthetic entry for the connected user so they always see themselves
-    let bare_jid = state.own_jid.split('/').next().unwrap_or(&state.own_jid);
+    let own_jid = Jid::new(&state.own_jid).bare();
+    let bare_jid = own_jid.as_str();
     let self_already_present = items.iter().any(|item| item.jid == bare_jid);
     if !self_already_present && !bare_jid.is_empty() {
         let localpart = bare_jid.split('@').next().unwrap_or(bare_jid);
@@ -235,24 +429,104 @@ async fn get_roster(state: State<'_, AppState>) -> Result<Vec<RosterItem>, Strin
 }
 
 #[tauri::command]
-async fn add_contact(jid: String, state: State<'_, AppState>) -> Result<(), String> {
-    // Add to local roster storage and send roster-set IQ to the server
+async fn search_contacts(
+    query: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<RosterItem>, String> {
     state
         .roster_manager
-        .add_contact(&jid, None, &[])
+        .search(&query)
         .await
-        .map_err(|error| error.to_string())?;
+        .map_err(|error| error.to_string())
+}
 
-    // Request a presence subscription so both sides can see each other
+#[tauri::command]
+async fn add_contact(
+    jid: String,
+    preauth: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    // Add to the roster, pre-approve the contact's subscription to us and
+    // subscribe to them, so both sides can see each other after one prompt
     state
         .roster_manager
-        .request_subscription(&jid)
+        .add_contact_preapproved(&jid, None, &[], preauth.as_deref())
         .await
         .map_err(|error| error.to_string())?;
 
     Ok(())
 }
 
+/// Block `jid` on the server (XEP-0191).
+#[tauri::command]
+async fn block_contact(jid: String, state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .roster_manager
+        .block(&jid)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn unblock_contact(jid: String, state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .roster_manager
+        .unblock(&jid)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+/// Block `jid` and report them for spam or abuse (XEP-0377).
+#[tauri::command]
+async fn report_spam(
+    jid: String,
+    reason: SpamReason,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .roster_manager
+        .report_spam(&jid, reason)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+/// Bare JIDs of the senders the user reported, so their messages and
+/// requests can be hidden.
+#[tauri::command]
+async fn get_reported_contacts(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    let reported = state
+        .roster_manager
+        .reported_contacts()
+        .await
+        .map_err(|error| error.to_string())?;
+    Ok(reported.into_iter().map(|contact| contact.jid).collect())
+}
+
+/// Our own profile as last set, or `None` if it never was.
+#[tauri::command]
+async fn get_profile(state: State<'_, AppState>) -> Result<Option<Profile>, String> {
+    state
+        .profile_manager
+        .get_profile()
+        .await
+        .map_err(|error| error.to_string())
+}
+
+/// Replace our nickname, avatar and about text, and publish them to PEP.
+#[tauri::command]
+async fn set_profile(
+    nickname: Option<String>,
+    avatar: Option<Vec<u8>>,
+    about: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Profile, String> {
+    state
+        .profile_manager
+        .set_profile(nickname.as_deref(), avatar.as_deref(), about.as_deref())
+        .await
+        .map_err(|error| error.to_string())
+}
+
 #[tauri::command]
 async fn get_connection_state(
     state: State<'_, AppState>,
@@ -264,6 +538,12 @@ async fn get_connection_state(
     ))
 }
 
+#[tauri::command]
+async fn get_connection_health(state: State<'_, AppState>) -> Result<ConnectionHealth, String> {
+    let connection = state.connection_manager.lock().await;
+    Ok(connection.health())
+}
+
 #[tauri::command]
 async fn set_presence(
     show: String,
@@ -272,7 +552,7 @@ async fn set_presence(
 ) -> Result<(), String> {
     let show = parse_presence_show(&show).ok_or_else(|| {
         format!(
-            "invalid presence show '{show}'; expected one of: available, chat, away, xa, dnd, unavailable"
+            "invalid presence show '{show}'; expected one of: available, chat, away, xa, dnd, invisible, unavailable"
         )
     })?;
 
@@ -304,112 +584,787 @@ async fn leave_room(room_jid: String, state: State<'_, AppState>) -> Result<(),
         .map_err(|error| error.to_string())
 }
 
+/// Ask `room_jid` to call us `nick` from now on.
 #[tauri::command]
-async fn get_history(
-    jid: String,
-    limit: u32,
-    before: Option<String>,
+async fn change_room_nick(
+    room_jid: String,
+    nick: String,
     state: State<'_, AppState>,
-) -> Result<Vec<ChatMessage>, String> {
-    let direction = if before.is_some() {
-        ScrollDirection::Up
-    } else {
-        ScrollDirection::Bottom
-    };
-
-    publish_event(
-        &state.event_bus,
-        "ui.scroll.requested",
-        EventSource::Ui(UiTarget::Gui),
-        EventPayload::ScrollRequested {
-            jid: jid.clone(),
-            direction,
-        },
-    )
-    .map_err(|error| error.to_string())?;
+) -> Result<(), String> {
+    state
+        .muc_manager
+        .change_nick(&room_jid, &nick)
+        .await
+        .map_err(|error| error.to_string())
+}
 
-    let normalized_limit = limit.max(1);
+/// Room invitations waiting for an answer, newest first.
+#[tauri::command]
+async fn get_muc_invites(state: State<'_, AppState>) -> Result<Vec<MucInvite>, String> {
+    state
+        .muc_manager
+        .pending_invites()
+        .await
+        .map_err(|error| error.to_string())
+}
 
-    let messages = state
-        .message_manager
-        .get_messages(&jid, normalized_limit, before.as_deref())
+#[tauri::command]
+async fn accept_muc_invite(
+    room_jid: String,
+    nick: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .muc_manager
+        .accept_invite(&room_jid, &nick)
         .await
-        .map_err(|error| error.to_string())?;
+        .map_err(|error| error.to_string())
+}
 
-    if !messages.is_empty() {
-        return Ok(messages);
-    }
+#[tauri::command]
+async fn decline_muc_invite(
+    room_jid: String,
+    reason: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .muc_manager
+        .decline_invite(&room_jid, reason.as_deref())
+        .await
+        .map_err(|error| error.to_string())
+}
 
+/// Invite `jid` into the joined room `room_jid` (XEP-0249).
+#[tauri::command]
+async fn send_muc_invite(
+    room_jid: String,
+    jid: String,
+    reason: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
     state
         .muc_manager
-        .get_room_messages(&jid, normalized_limit, before.as_deref())
+        .send_invite(&room_jid, &jid, reason.as_deref())
         .await
         .map_err(|error| error.to_string())
 }
 
+/// Request one page of the public rooms hosted by the MUC `service`. The
+/// page is forwarded as `xmpp.disco.items.received`.
 #[tauri::command]
-async fn manage_plugins(
-    action: PluginAction,
+async fn browse_muc_rooms(
+    service: String,
+    page: RsmQuery,
     state: State<'_, AppState>,
-) -> Result<PluginInfoResponse, String> {
-    let app_state = state.inner();
-    let result = match action {
-        PluginAction::Install { reference } => install_plugin(app_state, &reference).await,
-        PluginAction::Uninstall { plugin_id } => uninstall_plugin(app_state, &plugin_id).await,
-        PluginAction::Update { plugin_id } => update_plugin(app_state, &plugin_id).await,
-        PluginAction::Get { plugin_id } => get_plugin(app_state, &plugin_id).await,
-    };
+) -> Result<(), String> {
+    state.muc_manager.browse_rooms(&service, page);
+    Ok(())
+}
 
-    result.map_err(|error| error.to_string())
+/// As a moderator, remove message `id` from `room_jid` for everyone
+/// (XEP-0425).
+#[tauri::command]
+async fn moderate_message(
+    room_jid: String,
+    id: String,
+    reason: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .muc_manager
+        .moderate_message(&room_jid, &id, reason.as_deref())
+        .await
+        .map_err(|error| error.to_string())
 }
 
 #[tauri::command]
-async fn get_config(state: State<'_, AppState>) -> Result<UiConfigResponse, String> {
-    Ok(state.ui_config.clone())
+async fn get_room_mentions(
+    room_jid: String,
+    limit: u32,
+    state: State<'_, AppState>,
+) -> Result<Vec<ChatMessage>, String> {
+    state
+        .muc_manager
+        .get_room_mentions(&room_jid, limit)
+        .await
+        .map_err(|error| error.to_string())
 }
 
-fn main() {
-    init_tracing();
+#[tauri::command]
+async fn get_conversations(state: State<'_, AppState>) -> Result<Vec<Conversation>, String> {
+    state
+        .conversation_manager
+        .list_conversations()
+        .await
+        .map_err(|error| error.to_string())
+}
 
-    let app = tauri::Builder::default()
-        .setup(|app| {
+#[tauri::command]
+async fn get_archived_conversations(
+    state: State<'_, AppState>,
+) -> Result<Vec<Conversation>, String> {
+    state
+        .conversation_manager
+        .list_archived_conversations()
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn pin_conversation(
+    jid: String,
+    pinned: bool,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .conversation_manager
+        .set_pinned(&jid, pinned)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn archive_conversation(
+    jid: String,
+    archived: bool,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .conversation_manager
+        .set_archived(&jid, archived)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn mute_conversation(
+    jid: String,
+    until: Option<DateTime<Utc>>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .conversation_manager
+        .mute_conversation(&jid, until)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn unmute_conversation(jid: String, state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .conversation_manager
+        .unmute_conversation(&jid)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn merge_duplicate_conversations(state: State<'_, AppState>) -> Result<MergeReport, String> {
+    state
+        .conversation_manager
+        .merge_duplicates()
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn backup_database(state: State<'_, AppState>) -> Result<String, String> {
+    state
+        .backup_manager
+        .backup_now()
+        .await
+        .map(|path| path.display().to_string())
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn list_backups(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    state
+        .backup_manager
+        .list_backups()
+        .map(|paths| {
+            paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect()
+        })
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn restore_from_backup(path: String, state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .backup_manager
+        .restore_from_backup(Path::new(&path))
+        .await
+        .map_err(|error| error.to_string())
+}
+
+/// A message body parsed for styling (XEP-0393), so the GUI renders it the
+/// same way the TUI does.
+#[tauri::command]
+async fn style_message_body(body: String) -> Result<Vec<styling::Block>, String> {
+    Ok(styling::parse(&body))
+}
+
+/// The XEP-0392 color of a bare JID or room nick as `#rrggbb`, adapted to
+/// the theme's `background` when given, so names match the TUI.
+#[tauri::command]
+async fn consistent_color(
+    identifier: String,
+    background: Option<String>,
+) -> Result<String, String> {
+    let palette = background
+        .as_deref()
+        .and_then(Rgb::from_hex)
+        .map_or(ColorPalette::DEFAULT, ColorPalette::for_background);
+    Ok(color::consistent_color_with(&identifier, &palette).to_hex())
+}
+
+/// Download a file shared in a message, or return the cached copy.
+#[tauri::command]
+async fn download_attachment(
+    url: String,
+    state: State<'_, AppState>,
+) -> Result<CachedFile, String> {
+    state
+        .download_manager
+        .download(&url)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+/// The stored preview card for a link, for messages loaded from history.
+/// Never fetches the page.
+#[tauri::command]
+async fn get_link_preview(
+    url: String,
+    state: State<'_, AppState>,
+) -> Result<Option<LinkPreview>, String> {
+    state
+        .link_preview_manager
+        .stored(&url)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+/// Attach a translation made by the frontend or a plugin to message
+/// `message_id`, answering a `ui.message.translation_requested` event.
+#[tauri::command]
+async fn provide_translation(
+    message_id: String,
+    translation: MessageTranslation,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .translation_manager
+        .attach(&message_id, translation)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+/// Ring `peer` (a full JID) with the local media description.
+#[tauri::command]
+async fn start_call(
+    peer: String,
+    contents: Vec<CallContent>,
+    state: State<'_, AppState>,
+) -> Result<Call, String> {
+    state
+        .call_manager
+        .start_call(&peer, contents)
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn accept_call(
+    sid: String,
+    contents: Vec<CallContent>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .call_manager
+        .accept_call(&sid, contents)
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn send_call_candidates(
+    sid: String,
+    contents: Vec<CallContent>,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .call_manager
+        .send_candidates(&sid, contents)
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn hang_up_call(sid: String, state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .call_manager
+        .hang_up(&sid)
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn fail_call(
+    sid: String,
+    reason: CallEndReason,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .call_manager
+        .fail_call(&sid, reason)
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn get_calls(state: State<'_, AppState>) -> Result<Vec<Call>, String> {
+    Ok(state.call_manager.calls())
+}
+
+#[tauri::command]
+async fn run_storage_maintenance(state: State<'_, AppState>) -> Result<MaintenanceReport, String> {
+    state
+        .maintenance_scheduler
+        .maintain_now()
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn get_storage_stats(state: State<'_, AppState>) -> Result<StorageStats, String> {
+    state
+        .maintenance_scheduler
+        .report_stats()
+        .await
+        .map_err(|error| error.to_string())
+}
+
+/// Called by the frontend when the window is hidden or shown again.
+#[tauri::command]
+async fn set_client_active(active: bool, state: State<'_, AppState>) -> Result<(), String> {
+    publish_event(
+        &state.event_bus,
+        channels::UI_CLIENT_ACTIVITY,
+        EventSource::Ui(UiTarget::Gui),
+        EventPayload::ClientActivityChanged { active },
+    )
+    .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn prune_message_history(state: State<'_, AppState>) -> Result<PruneReport, String> {
+    state
+        .retention_manager
+        .prune_now()
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn get_capabilities(
+    jid: String,
+    state: State<'_, AppState>,
+) -> Result<ContactCapabilities, String> {
+    Ok(state.capabilities_manager.capabilities(&jid))
+}
+
+#[tauri::command]
+async fn get_health_report(state: State<'_, AppState>) -> Result<HealthReport, String> {
+    state
+        .health_monitor
+        .report(&state.own_jid)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn save_account_password(password: String, state: State<'_, AppState>) -> Result<(), String> {
+    let credentials = state.credentials.clone();
+    let account = state.own_jid.clone();
+    tokio::task::spawn_blocking(move || credentials.set(&account, &password))
+        .await
+        .map_err(|error| error.to_string())?
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn change_password(new_password: String, state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .account_manager
+        .change_password(&new_password)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+/// Delete the account from the server. With `wipe_local_data`, everything
+/// stored for it locally except plugin data is deleted as well.
+#[tauri::command]
+async fn delete_account(wipe_local_data: bool, state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .account_manager
+        .delete_account()
+        .await
+        .map_err(|error| error.to_string())?;
+    if wipe_local_data {
+        state
+            .database
+            .wipe_account_data()
+            .await
+            .map_err(|error| error.to_string())?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn omemo_send_message(
+    to: String,
+    body: String,
+    state: State<'_, AppState>,
+) -> Result<ChatMessage, String> {
+    state
+        .omemo_manager
+        .send_message(&to, &body)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn omemo_list_devices(
+    jid: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<OmemoDevice>, String> {
+    state
+        .omemo_manager
+        .list_devices(&jid)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn omemo_trust_device(
+    jid: String,
+    device_id: u32,
+    trust: OmemoTrust,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .omemo_manager
+        .trust_device(&jid, device_id, trust)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn omemo_own_fingerprint(state: State<'_, AppState>) -> Result<String, String> {
+    state
+        .omemo_manager
+        .own_fingerprint()
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn get_history(
+    jid: String,
+    limit: u32,
+    before: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<ChatMessage>, String> {
+    let direction = if before.is_some() {
+        ScrollDirection::Up
+    } else {
+        ScrollDirection::Bottom
+    };
+
+    publish_event(
+        &state.event_bus,
+        channels::UI_SCROLL_REQUESTED,
+        EventSource::Ui(UiTarget::Gui),
+        EventPayload::ScrollRequested {
+            jid: jid.clone(),
+            direction,
+        },
+    )
+    .map_err(|error| error.to_string())?;
+
+    let normalized_limit = limit.max(1);
+
+    let messages = state
+        .message_manager
+        .get_messages(&jid, normalized_limit, before.as_deref())
+        .await
+        .map_err(|error| error.to_string())?;
+
+    if !messages.is_empty() {
+        return Ok(messages);
+    }
+
+    state
+        .muc_manager
+        .get_room_messages(&jid, normalized_limit, before.as_deref())
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn manage_plugins(
+    action: PluginAction,
+    state: State<'_, AppState>,
+) -> Result<PluginInfoResponse, String> {
+    let app_state = state.inner();
+    let result = match action {
+        PluginAction::Install { reference } => install_plugin(app_state, &reference).await,
+        PluginAction::Uninstall { plugin_id } => uninstall_plugin(app_state, &plugin_id).await,
+        PluginAction::Update { plugin_id } => update_plugin(app_state, &plugin_id).await,
+        PluginAction::Reload { plugin_id } => {
+            load_plugin_into_runtime(
+                app_state.plugin_registry.as_ref(),
+                &app_state.plugin_runtime,
+                &plugin_id,
+            )
+            .await
+        }
+        PluginAction::ReEnable { plugin_id } => re_enable_plugin(app_state, &plugin_id).await,
+        PluginAction::Get { plugin_id } => get_plugin(app_state, &plugin_id).await,
+    };
+
+    result.map_err(|error| error.to_string())
+}
+
+/// Routes, panels and settings pages added by installed plugins.
+#[tauri::command]
+async fn get_plugin_ui_contributions(
+    state: State<'_, AppState>,
+) -> Result<Vec<PluginUiContribution>, String> {
+    state
+        .plugin_runtime
+        .lock()
+        .await
+        .ui_contributions(&state.plugin_registry)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn get_plugin_usage(state: State<'_, AppState>) -> Result<Vec<PluginUsage>, String> {
+    Ok(state.plugin_runtime.lock().await.usage_snapshot())
+}
+
+#[tauri::command]
+async fn export_plugin_data(
+    plugin_id: String,
+    state: State<'_, AppState>,
+) -> Result<KvExport, String> {
+    state
+        .plugin_runtime
+        .lock()
+        .await
+        .export_plugin_data(&plugin_id)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn import_plugin_data(
+    plugin_id: String,
+    data: KvExport,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .plugin_runtime
+        .lock()
+        .await
+        .import_plugin_data(&plugin_id, &data)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn reset_plugin_data(plugin_id: String, state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .plugin_runtime
+        .lock()
+        .await
+        .reset_plugin_data(&plugin_id)
+        .await
+        .map_err(|error| error.to_string())
+}
+
+#[tauri::command]
+async fn set_stanza_capture(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    state.stanza_debugger.set_enabled(enabled);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_captured_stanzas(
+    filter: Option<StanzaFilter>,
+    state: State<'_, AppState>,
+) -> Result<Vec<CapturedStanza>, String> {
+    Ok(state.stanza_debugger.entries(&filter.unwrap_or_default()))
+}
+
+#[tauri::command]
+async fn clear_captured_stanzas(state: State<'_, AppState>) -> Result<(), String> {
+    state.stanza_debugger.clear();
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_recent_logs(
+    limit: usize,
+    state: State<'_, AppState>,
+) -> Result<Vec<LogEntry>, String> {
+    Ok(state.logging.buffer().recent(limit))
+}
+
+#[tauri::command]
+async fn get_config(state: State<'_, AppState>) -> Result<UiConfigResponse, String> {
+    let mut ui_config = state.ui_config.clone();
+    ui_config.theme_name = state.theme_manager.lock().await.current().name.clone();
+    Ok(ui_config)
+}
+
+#[tauri::command]
+async fn list_themes(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    Ok(state.theme_manager.lock().await.available_themes())
+}
+
+/// Switch to `theme_id` and return its CSS custom properties. The choice is
+/// saved for the next start.
+#[tauri::command]
+async fn set_theme(
+    theme_id: String,
+    state: State<'_, AppState>,
+) -> Result<HashMap<String, String>, String> {
+    state
+        .theme_manager
+        .lock()
+        .await
+        .set(&theme_id)
+        .map(|theme| theme.css_custom_properties())
+        .map_err(|error| error.to_string())
+}
+
+fn main() {
+    let app = tauri::Builder::default()
+        .setup(|app| {
             let state = tauri::async_runtime::block_on(initialize_backend(app.handle().clone()))?;
             app.manage(state);
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
+            invoke_bridge,
             send_message,
+            get_message_status,
+            retract_message,
+            schedule_message,
+            cancel_scheduled_message,
+            list_scheduled_messages,
+            list_rules,
+            save_rule,
+            delete_rule,
+            notify_typing,
+            get_notification_preference,
+            set_notification_preference,
             get_roster,
+            search_contacts,
             add_contact,
+            block_contact,
+            unblock_contact,
+            report_spam,
+            get_reported_contacts,
+            get_profile,
+            set_profile,
             get_connection_state,
+            get_connection_health,
             set_presence,
             join_room,
             leave_room,
+            change_room_nick,
+            get_muc_invites,
+            accept_muc_invite,
+            decline_muc_invite,
+            send_muc_invite,
+            browse_muc_rooms,
+            moderate_message,
+            get_room_mentions,
             get_history,
+            get_conversations,
+            get_archived_conversations,
+            pin_conversation,
+            archive_conversation,
+            mute_conversation,
+            unmute_conversation,
+            merge_duplicate_conversations,
+            prune_message_history,
+            backup_database,
+            list_backups,
+            restore_from_backup,
+            style_message_body,
+            consistent_color,
+            download_attachment,
+            get_link_preview,
+            provide_translation,
+            start_call,
+            accept_call,
+            send_call_candidates,
+            hang_up_call,
+            fail_call,
+            get_calls,
+            run_storage_maintenance,
+            get_storage_stats,
+            set_client_active,
+            get_capabilities,
+            get_health_report,
+            save_account_password,
+            change_password,
+            delete_account,
+            omemo_send_message,
+            omemo_list_devices,
+            omemo_trust_device,
+            omemo_own_fingerprint,
             manage_plugins,
-            get_config
+            get_plugin_usage,
+            get_plugin_ui_contributions,
+            export_plugin_data,
+            import_plugin_data,
+            reset_plugin_data,
+            set_stanza_capture,
+            get_captured_stanzas,
+            clear_captured_stanzas,
+            get_recent_logs,
+            get_config,
+            list_themes,
+            set_theme
         ])
         .build(tauri::generate_context!())
         .expect("failed to build Tauri application");
 
     app.run(move |app_handle, event| {
-        if let tauri::RunEvent::ExitRequested { .. } = event {
-            if let Some(state) = app_handle.try_state::<AppState>() {
-                let _ = publish_shutdown_requested(&state.event_bus, "application exit requested");
-            }
+        // Hold the exit until the managers have flushed, then exit for real.
+        if let tauri::RunEvent::ExitRequested { api, .. } = &event
+            && let Some(state) = app_handle.try_state::<AppState>()
+            && !state.shutdown_coordinator.is_shut_down()
+        {
+            api.prevent_exit();
+            let coordinator = state.shutdown_coordinator.clone();
+            let app_handle = app_handle.clone();
+            tauri::async_runtime::spawn(async move {
+                coordinator.shutdown("application exit requested").await;
+                app_handle.exit(0);
+            });
         }
     });
 }
 
-fn init_tracing() {
-    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
-
-    tracing_subscriber::fmt().with_env_filter(filter).init();
-}
-
 async fn initialize_backend(app_handle: AppHandle) -> Result<AppState, GuiBackendError> {
     let config = config::load_config()?;
+    let logging = Logging::init(&config.logging, &resolve_log_dir(&config))?;
     let ui_config = UiConfigResponse::from_config(&config);
 
     let storage_path = resolve_storage_path(&config);
@@ -418,97 +1373,540 @@ async fn initialize_backend(app_handle: AppHandle) -> Result<AppState, GuiBacken
 
     info!(path = %storage_path.display(), "storage initialized");
 
+    channels::validate()?;
     let event_bus: Arc<dyn EventBus> =
         Arc::new(BroadcastEventBus::new(config.event_bus.channel_capacity));
+    logging.attach_event_bus(event_bus.clone());
+    let theme_manager =
+        ThemeManager::open(&config::data_dir(), &config.theme).with_event_bus(event_bus.clone());
 
     publish_event(
         &event_bus,
-        "system.config.loaded",
+        channels::SYSTEM_CONFIG_LOADED,
         EventSource::System(SYSTEM_COMPONENT.to_string()),
         EventPayload::ConfigReloaded,
     )?;
 
     publish_event(
         &event_bus,
-        "system.storage.ready",
+        channels::SYSTEM_STORAGE_READY,
         EventSource::System(SYSTEM_COMPONENT.to_string()),
         EventPayload::StartupComplete,
     )?;
 
     let plugin_registry = Arc::new(PluginRegistry::new(
-        RegistryConfig::default(),
+        plugin_registry_config(&config.plugins),
         resolve_plugin_data_dir(&config),
     )?);
 
-    let plugin_runtime = Arc::new(Mutex::new(PluginRuntime::new(
-        PluginRuntimeConfig::default(),
-        event_bus.clone(),
-        database.clone(),
-    )));
+    let plugin_runtime = Arc::new(Mutex::new(PluginRuntime::new(
+        plugin_runtime_config(&config.plugins),
+        event_bus.clone(),
+        database.clone(),
+    )));
+
+    if config.plugins.enabled {
+        load_installed_plugins(&plugin_registry, &plugin_runtime, &event_bus).await;
+    }
+
+    let roster_manager = Arc::new(
+        RosterManager::new(database.clone(), event_bus.clone()).with_config(&config.roster),
+    );
+    // Messages must not be lost when the manager falls behind, so its
+    // backlog spills to a file next to the database.
+    let message_spill = Arc::new(waddle_storage::SpillFile::open(
+        storage_path.with_file_name("message-events.spill"),
+    )?);
+    let message_manager = Arc::new(
+        MessageManager::new(database.clone(), event_bus.clone())
+            .with_config(config.messaging.clone())
+            .with_overflow_policy(OverflowPolicy::Spill {
+                capacity: config.event_bus.channel_capacity,
+                store: message_spill,
+            }),
+    );
+    let muc_manager = Arc::new(
+        MucManager::new(database.clone(), event_bus.clone())
+            .with_mention_keywords(&config.messaging.mention_keywords),
+    );
+    let conversation_manager = Arc::new(ConversationManager::new(
+        database.clone(),
+        event_bus.clone(),
+    ));
+    let profile_manager = Arc::new(ProfileManager::new(database.clone(), event_bus.clone()));
+    let retention_manager = Arc::new(RetentionManager::new(
+        database.clone(),
+        event_bus.clone(),
+        config.storage.retention.clone(),
+    ));
+    let download_manager = Arc::new(DownloadManager::new(
+        resolve_download_dir(&config, &storage_path),
+        config.storage.downloads.max_cache_mb * 1024 * 1024,
+        event_bus.clone(),
+    ));
+    let link_preview_manager = Arc::new(LinkPreviewManager::new(
+        database.clone(),
+        config.messaging.link_previews.clone(),
+        event_bus.clone(),
+    ));
+    let translation_manager = Arc::new(TranslationManager::new(
+        database.clone(),
+        config
+            .messaging
+            .translation
+            .resolve_target_language(config.ui.locale.as_deref()),
+        event_bus.clone(),
+    ));
+    let rule_engine = Arc::new(RuleEngine::new(
+        database.clone(),
+        message_manager.clone(),
+        event_bus.clone(),
+    ));
+    let call_manager = Arc::new(CallManager::new(event_bus.clone()));
+    let typing_tracker = Arc::new(TypingTracker::new(event_bus.clone()));
+    let notification_settings = Arc::new(NotificationSettings::new(
+        database.clone(),
+        event_bus.clone(),
+    ));
+    let backup_manager = Arc::new(BackupManager::new(
+        database.clone(),
+        event_bus.clone(),
+        resolve_backup_dir(&config, &storage_path),
+        &config.storage.backup,
+    ));
+    let maintenance_scheduler = Arc::new(MaintenanceScheduler::new(
+        database.clone(),
+        event_bus.clone(),
+        &config.storage.maintenance,
+    ));
+    let presence_manager = Arc::new(PresenceManager::new(event_bus.clone()));
+    let capabilities_manager = Arc::new(CapabilitiesManager::new(event_bus.clone()));
+    let health_monitor = Arc::new(ServerHealthMonitor::new(
+        database.clone(),
+        event_bus.clone(),
+    ));
+    let mam_manager = Arc::new(MamManager::new(database.clone(), event_bus.clone()));
+
+    let plugin_host = Arc::new(PluginHostBridge {
+        roster: std::sync::RwLock::new(Vec::new()),
+        presence_manager: presence_manager.clone(),
+    });
+    plugin_runtime
+        .lock()
+        .await
+        .set_host_api(plugin_host.clone());
+    spawn_plugin_roster_cache(plugin_host, roster_manager.clone(), event_bus.clone());
+
+    let supervisor = Supervisor::new(event_bus.clone(), config.supervisor.clone());
+
+    spawn_component_task(
+        &supervisor,
+        "roster",
+        roster_manager.clone(),
+        |manager| async move { manager.run().await.map_err(|error| error.to_string()) },
+    );
+
+    spawn_component_task(
+        &supervisor,
+        "messaging",
+        message_manager.clone(),
+        |manager| async move { manager.run().await.map_err(|error| error.to_string()) },
+    );
+
+    spawn_component_task(
+        &supervisor,
+        "calls",
+        call_manager.clone(),
+        |manager| async move { manager.run().await.map_err(|error| error.to_string()) },
+    );
+
+    spawn_component_task(
+        &supervisor,
+        "muc",
+        muc_manager.clone(),
+        |manager| async move { manager.run().await.map_err(|error| error.to_string()) },
+    );
+
+    spawn_component_task(
+        &supervisor,
+        "conversations",
+        conversation_manager.clone(),
+        |manager| async move { manager.run().await.map_err(|error| error.to_string()) },
+    );
+
+    spawn_component_task(
+        &supervisor,
+        "profile",
+        profile_manager.clone(),
+        |manager| async move { manager.run().await.map_err(|error| error.to_string()) },
+    );
+
+    spawn_component_task(
+        &supervisor,
+        "retention",
+        retention_manager.clone(),
+        |manager| async move { manager.run().await.map_err(|error| error.to_string()) },
+    );
+
+    spawn_component_task(
+        &supervisor,
+        "scheduler",
+        message_manager.clone(),
+        |manager| async move {
+            manager
+                .run_scheduler()
+                .await
+                .map_err(|error| error.to_string())
+        },
+    );
+
+    spawn_component_task(
+        &supervisor,
+        "typing",
+        typing_tracker.clone(),
+        |tracker| async move { tracker.run().await.map_err(|error| error.to_string()) },
+    );
+
+    if config.storage.downloads.auto_download {
+        spawn_component_task(
+            &supervisor,
+            "downloads",
+            download_manager.clone(),
+            |manager| async move { manager.run().await.map_err(|error| error.to_string()) },
+        );
+    }
+
+    if config.messaging.link_previews.enabled {
+        spawn_component_task(
+            &supervisor,
+            "link_previews",
+            link_preview_manager.clone(),
+            |manager| async move { manager.run().await.map_err(|error| error.to_string()) },
+        );
+    }
+
+    if config.messaging.translation.enabled {
+        spawn_component_task(
+            &supervisor,
+            "translations",
+            translation_manager.clone(),
+            |manager| async move { manager.run().await.map_err(|error| error.to_string()) },
+        );
+    }
+
+    spawn_component_task(
+        &supervisor,
+        "rules",
+        rule_engine.clone(),
+        |engine| async move { engine.run().await.map_err(|error| error.to_string()) },
+    );
+
+    if config.storage.backup.enabled {
+        spawn_component_task(
+            &supervisor,
+            "backup",
+            backup_manager.clone(),
+            |manager| async move { manager.run().await.map_err(|error| error.to_string()) },
+        );
+    }
+
+    // `--dev <dir>` reinstalls and reloads the plugin in `dir` on each build.
+    let dev_plugin_dirs = dev_plugin_dirs(std::env::args().skip(1));
+    if config.plugins.enabled || !dev_plugin_dirs.is_empty() {
+        spawn_component_task(
+            &supervisor,
+            "plugins",
+            plugin_runtime.clone(),
+            |runtime| async move {
+                PluginRuntime::dispatch_events(runtime)
+                    .await
+                    .map_err(|error| error.to_string())
+            },
+        );
+        if config.plugins.usage_interval_secs > 0 {
+            let interval = Duration::from_secs(config.plugins.usage_interval_secs);
+            spawn_component_task(
+                &supervisor,
+                "plugin-usage",
+                plugin_runtime.clone(),
+                move |runtime| async move {
+                    PluginRuntime::report_usage(runtime, interval)
+                        .await
+                        .map_err(|error| error.to_string())
+                },
+            );
+        }
+    }
+    for source_dir in dev_plugin_dirs {
+        info!(source = %source_dir.display(), "watching development plugin");
+        spawn_component_task(
+            &supervisor,
+            "plugin-dev",
+            Arc::new(DevWatcher::new(
+                source_dir,
+                plugin_registry.clone(),
+                plugin_runtime.clone(),
+            )),
+            |watcher| async move { watcher.run().await.map_err(|error| error.to_string()) },
+        );
+    }
 
-    if config.plugins.enabled {
-        load_installed_plugins(&plugin_registry, &plugin_runtime, &event_bus).await;
+    if config.storage.maintenance.enabled {
+        spawn_component_task(
+            &supervisor,
+            "maintenance",
+            maintenance_scheduler.clone(),
+            |scheduler| async move { scheduler.run().await.map_err(|error| error.to_string()) },
+        );
     }
 
-    let roster_manager = Arc::new(RosterManager::new(database.clone(), event_bus.clone()));
-    let message_manager = Arc::new(MessageManager::new(database.clone(), event_bus.clone()));
-    let muc_manager = Arc::new(MucManager::new(database.clone(), event_bus.clone()));
-    let presence_manager = Arc::new(PresenceManager::new(event_bus.clone()));
-    let mam_manager = Arc::new(MamManager::new(database.clone(), event_bus.clone()));
+    spawn_component_task(
+        &supervisor,
+        "presence",
+        presence_manager.clone(),
+        |manager| async move { manager.run().await.map_err(|error| error.to_string()) },
+    );
 
-    spawn_component_task("roster", event_bus.clone(), {
-        let manager = roster_manager.clone();
-        async move { manager.run().await.map_err(|error| error.to_string()) }
-    });
+    if config.presence.auto_away {
+        spawn_component_task(
+            &supervisor,
+            "auto-away",
+            Arc::new(AutoAwayMonitor::new(
+                presence_manager.clone(),
+                &config.presence,
+            )),
+            |monitor| async move { monitor.run().await.map_err(|error| error.to_string()) },
+        );
+    }
 
-    spawn_component_task("messaging", event_bus.clone(), {
-        let manager = message_manager.clone();
-        async move { manager.run().await.map_err(|error| error.to_string()) }
-    });
+    spawn_component_task(
+        &supervisor,
+        "capabilities",
+        capabilities_manager.clone(),
+        |manager| async move { manager.run().await.map_err(|error| error.to_string()) },
+    );
 
-    spawn_component_task("muc", event_bus.clone(), {
-        let manager = muc_manager.clone();
-        async move { manager.run().await.map_err(|error| error.to_string()) }
-    });
+    spawn_component_task(
+        &supervisor,
+        "health",
+        health_monitor.clone(),
+        |manager| async move { manager.run().await.map_err(|error| error.to_string()) },
+    );
 
-    spawn_component_task("presence", event_bus.clone(), {
-        let manager = presence_manager.clone();
-        async move { manager.run().await.map_err(|error| error.to_string()) }
-    });
+    spawn_component_task(
+        &supervisor,
+        "mam",
+        mam_manager.clone(),
+        |manager| async move { manager.run().await.map_err(|error| error.to_string()) },
+    );
 
-    spawn_component_task("mam", event_bus.clone(), {
-        let manager = mam_manager.clone();
-        async move { manager.run().await.map_err(|error| error.to_string()) }
-    });
+    // Lets out-of-process frontends and tools use the event bus.
+    if config.event_bus.bridge.enabled {
+        let bridge_config = &config.event_bus.bridge;
+        let mut bridge =
+            EventBridge::new(event_bus.clone(), BridgeAcl::from_config(bridge_config)?)
+                .with_config(bridge_config);
+        if bridge_config.websocket.is_some() {
+            // Beside the Unix socket, or the database where there is none.
+            let token_path = bridge_config
+                .path
+                .as_deref()
+                .filter(|_| cfg!(unix))
+                .map(expand_home_path)
+                .unwrap_or_else(|| storage_path.clone())
+                .with_file_name(TOKEN_FILE);
+            bridge = bridge.with_token(write_token_file(&token_path)?);
+            info!(path = %token_path.display(), "event bridge WebSocket token written");
+        }
+        if let (Some(cert), Some(key)) = (
+            bridge_config.websocket_tls_cert.as_deref(),
+            bridge_config.websocket_tls_key.as_deref(),
+        ) {
+            bridge = bridge.with_tls(&expand_home_path(cert), &expand_home_path(key))?;
+        }
+        let bridge = Arc::new(bridge);
+        spawn_component_task(
+            &supervisor,
+            "event-bridge",
+            bridge.clone(),
+            |bridge| async move { bridge.run().await.map_err(|error| error.to_string()) },
+        );
+        if let Some(address) = bridge_config.websocket.as_deref() {
+            // Checked when the configuration was loaded.
+            if let Ok(address) = address.parse::<std::net::SocketAddr>() {
+                info!(%address, "event bridge accepting WebSocket clients");
+                spawn_component_task(
+                    &supervisor,
+                    "event-bridge.websocket",
+                    bridge.clone(),
+                    move |bridge| async move {
+                        bridge
+                            .serve_websocket(address)
+                            .await
+                            .map_err(|error| error.to_string())
+                    },
+                );
+            }
+        }
+        #[cfg(unix)]
+        {
+            let path = config
+                .event_bus
+                .bridge
+                .path
+                .as_deref()
+                .map(expand_home_path)
+                .unwrap_or_else(|| storage_path.with_file_name("bridge.sock"));
+            info!(path = %path.display(), "event bridge enabled");
+            spawn_component_task(&supervisor, "event-bridge.unix", bridge, move |bridge| {
+                let path = path.clone();
+                async move {
+                    bridge
+                        .serve_unix(&path)
+                        .await
+                        .map_err(|error| error.to_string())
+                }
+            });
+        }
+        #[cfg(windows)]
+        {
+            let name = config
+                .event_bus
+                .bridge
+                .path
+                .clone()
+                .unwrap_or_else(|| r"\\.\pipe\waddle-bridge".to_string());
+            info!(%name, "event bridge enabled");
+            spawn_component_task(&supervisor, "event-bridge.pipe", bridge, move |bridge| {
+                let name = name.clone();
+                async move {
+                    bridge
+                        .serve_named_pipe(&name)
+                        .await
+                        .map_err(|error| error.to_string())
+                }
+            });
+        }
+    }
 
-    let pipeline = Arc::new(build_stanza_pipeline(event_bus.clone()));
+    let stanza_debugger = Arc::new(StanzaDebugger::new(&config.debug));
+    if let Some(path) = config.debug.capture_file.as_deref() {
+        let path = expand_home_path(path);
+        if let Err(error) = stanza_debugger.set_capture_file(Some(&path)) {
+            warn!(path = %path.display(), %error, "cannot open stanza capture file");
+        }
+    }
+    let pipeline = Arc::new(build_stanza_pipeline(
+        event_bus.clone(),
+        plugin_runtime.clone(),
+        stanza_debugger.clone(),
+    ));
     let (wire_sender, wire_receiver) = stanza_channel(WIRE_CHANNEL_CAPACITY);
     let outbound_router = Arc::new(OutboundRouter::new(
         event_bus.clone(),
         pipeline.clone(),
-        wire_sender,
+        wire_sender.clone(),
     ));
 
-    spawn_component_task("xmpp.outbound", event_bus.clone(), {
-        let router = outbound_router.clone();
-        async move { router.run().await.map_err(|error| error.to_string()) }
-    });
+    spawn_component_task(
+        &supervisor,
+        "xmpp.outbound",
+        outbound_router.clone(),
+        |router| async move { router.run().await.map_err(|error| error.to_string()) },
+    );
+
+    // Archive and mute flags follow the account to its other devices via PEP.
+    conversation_manager.set_pubsub(Arc::new(PubSubManager::new(
+        config.account.jid.clone(),
+        event_bus.clone(),
+        pipeline.clone(),
+        wire_sender.clone(),
+    )));
+    // Our own nickname, avatar and vCard are published there too.
+    profile_manager.set_pubsub(Arc::new(PubSubManager::new(
+        config.account.jid.clone(),
+        event_bus.clone(),
+        pipeline.clone(),
+        wire_sender.clone(),
+    )));
+
+    let credentials = credential_store_from(&config).await?;
+    let omemo_key = omemo_storage_key(&credentials, &config.account.jid).await?;
+    let omemo_manager = Arc::new(OmemoManager::new(
+        database.clone(),
+        event_bus.clone(),
+        omemo_key,
+    ));
+    spawn_component_task(
+        &supervisor,
+        "omemo",
+        omemo_manager.clone(),
+        |manager| async move { manager.run().await.map_err(|error| error.to_string()) },
+    );
+
+    // Account sign-up from the onboarding screen (XEP-0077).
+    let registration_manager: Arc<RegistrationManager> = Arc::new(RegistrationManager::new(
+        credentials.clone(),
+        event_bus.clone(),
+    ));
+    spawn_component_task(
+        &supervisor,
+        "registration",
+        registration_manager,
+        |manager| async move { manager.run().await.map_err(|error| error.to_string()) },
+    );
+
+    // Password changes and deletion of the logged-in account (XEP-0077).
+    let account_manager = Arc::new(AccountManager::new(
+        config.account.jid.clone(),
+        credentials.clone(),
+        event_bus.clone(),
+        wire_sender,
+    ));
 
     let connection = Arc::new(Mutex::new(ConnectionManager::with_event_bus(
         connection_config_from(&config),
+        credentials.clone(),
         event_bus.clone(),
     )));
 
     spawn_wire_pump(connection.clone(), wire_receiver, event_bus.clone());
     spawn_inbound_pump(connection.clone(), pipeline, event_bus.clone());
+    spawn_health_monitor(connection.clone(), event_bus.clone());
     spawn_connection_control(connection.clone(), event_bus.clone());
 
-    spawn_notifications(event_bus.clone(), config.clone());
-    spawn_event_forwarder(event_bus.clone(), app_handle);
+    let shutdown_coordinator = Arc::new(ShutdownCoordinator::new(event_bus.clone()));
+    shutdown_coordinator.register(presence_manager.clone());
+    shutdown_coordinator.register(message_manager.clone());
+    shutdown_coordinator.register(mam_manager.clone());
+    shutdown_coordinator.register(Arc::new(ConnectionShutdown {
+        connection: connection.clone(),
+    }));
+    spawn_component_task(
+        &supervisor,
+        "shutdown",
+        shutdown_coordinator.clone(),
+        |coordinator| async move { coordinator.run().await.map_err(|error| error.to_string()) },
+    );
+
+    spawn_notifications(
+        event_bus.clone(),
+        config.clone(),
+        notification_settings.clone(),
+    );
+    let bridge = GuiBridge::new(
+        event_bus.clone(),
+        roster_manager.clone(),
+        message_manager.clone(),
+        muc_manager.clone(),
+        conversation_manager.clone(),
+        presence_manager.clone(),
+    );
+    spawn_event_forwarder(&bridge, &event_bus, app_handle);
 
     publish_event(
         &event_bus,
-        "system.startup.complete",
+        channels::SYSTEM_STARTUP_COMPLETE,
         EventSource::System(SYSTEM_COMPONENT.to_string()),
         EventPayload::StartupComplete,
     )?;
@@ -518,47 +1916,217 @@ async fn initialize_backend(app_handle: AppHandle) -> Result<AppState, GuiBacken
     Ok(AppState {
         own_jid: config.account.jid.clone(),
         ui_config,
+        bridge,
+        theme_manager: Mutex::new(theme_manager),
         event_bus,
+        credentials,
+        account_manager,
+        database,
         connection_manager: connection,
         roster_manager,
+        profile_manager,
         message_manager,
         muc_manager,
+        conversation_manager,
+        retention_manager,
+        download_manager,
+        link_preview_manager,
+        translation_manager,
+        rule_engine,
+        call_manager,
+        typing_tracker,
+        notification_settings,
+        backup_manager,
+        maintenance_scheduler,
         presence_manager,
+        capabilities_manager,
+        health_monitor,
+        omemo_manager,
         plugin_registry,
         plugin_runtime,
+        shutdown_coordinator,
+        stanza_debugger,
+        logging,
     })
 }
 
-fn build_stanza_pipeline(event_bus: Arc<dyn EventBus>) -> StanzaPipeline {
+/// Closes the XMPP stream once the other managers have flushed, so the
+/// unavailable presence goes out before the disconnect.
+struct ConnectionShutdown {
+    connection: Arc<Mutex<ConnectionManager>>,
+}
+
+impl ShutdownManager for ConnectionShutdown {
+    fn name(&self) -> &'static str {
+        "xmpp"
+    }
+
+    fn shutdown(&self) -> ShutdownFuture<'_> {
+        Box::pin(async move {
+            self.connection
+                .lock()
+                .await
+                .disconnect()
+                .await
+                .map_err(|error| WaddleError::Xmpp(error.to_string()))
+        })
+    }
+}
+
+fn build_stanza_pipeline(
+    event_bus: Arc<dyn EventBus>,
+    plugin_runtime: Arc<Mutex<PluginRuntime<NativeDatabase>>>,
+    stanza_debugger: Arc<StanzaDebugger>,
+) -> StanzaPipeline {
     let mut pipeline = StanzaPipeline::new();
+    pipeline.set_plugin_host(Arc::new(PluginStanzaHooks {
+        runtime: plugin_runtime,
+    }));
     pipeline.register(Box::new(RosterProcessor::new(event_bus.clone())));
     pipeline.register(Box::new(MessageProcessor::new(event_bus.clone())));
     pipeline.register(Box::new(PresenceProcessor::new(event_bus.clone())));
     pipeline.register(Box::new(MamProcessor::new(event_bus.clone())));
     pipeline.register(Box::new(MucProcessor::new(event_bus.clone())));
     pipeline.register(Box::new(ChatStateProcessor::new(event_bus.clone())));
-
-    #[cfg(debug_assertions)]
-    pipeline.register(Box::new(DebugProcessor::new(event_bus)));
+    pipeline.register(Box::new(DiscoProcessor::new(event_bus.clone())));
+    pipeline.register(Box::new(AccountProcessor::new(event_bus.clone())));
+    pipeline.register(Box::new(OmemoProcessor::new(event_bus.clone())));
+    pipeline.register(Box::new(JingleProcessor::new(event_bus.clone())));
+    pipeline.register(Box::new(PubSubProcessor::new(event_bus.clone())));
+    pipeline.register(Box::new(DebugProcessor::new(event_bus, stanza_debugger)));
 
     pipeline
 }
 
-fn spawn_component_task<F>(component: &'static str, event_bus: Arc<dyn EventBus>, task: F)
-where
-    F: Future<Output = Result<(), String>> + Send + 'static,
-{
+/// Hands stanzas to the plugins that registered as stanza processors.
+struct PluginStanzaHooks {
+    runtime: Arc<Mutex<PluginRuntime<NativeDatabase>>>,
+}
+
+impl PluginStanzaHost for PluginStanzaHooks {
+    fn stanza_processors(&self) -> PluginHookFuture<'_, Vec<PluginStanzaProcessor>> {
+        Box::pin(async move {
+            self.runtime
+                .lock()
+                .await
+                .stanza_processors()
+                .into_iter()
+                .map(|(plugin_id, priority)| PluginStanzaProcessor {
+                    plugin_id,
+                    priority,
+                })
+                .collect()
+        })
+    }
+
+    fn process_stanza<'a>(
+        &'a self,
+        plugin_id: &'a str,
+        direction: StanzaDirection,
+        xml: String,
+    ) -> PluginHookFuture<'a, Result<StanzaHookOutcome, PipelineError>> {
+        Box::pin(async move {
+            let hook = match direction {
+                StanzaDirection::Inbound => PluginHook::InboundStanza(xml),
+                StanzaDirection::Outbound => PluginHook::OutboundStanza(xml),
+            };
+            let verdict = self
+                .runtime
+                .lock()
+                .await
+                .invoke_stanza_processor(plugin_id, &hook)
+                .await
+                .map_err(|error| PipelineError::ProcessorFailed(error.to_string()))?;
+            Ok(match verdict {
+                StanzaVerdict::PassThrough => StanzaHookOutcome::PassThrough,
+                StanzaVerdict::Modify(xml) => StanzaHookOutcome::Modify(xml),
+                StanzaVerdict::Consume => StanzaHookOutcome::Consume,
+            })
+        })
+    }
+}
+
+/// Serves roster and presence reads to plugins. Plugins read synchronously
+/// from inside an invocation, so the roster is cached here.
+struct PluginHostBridge {
+    roster: std::sync::RwLock<Vec<RosterItem>>,
+    presence_manager: Arc<PresenceManager>,
+}
+
+impl PluginHostApi for PluginHostBridge {
+    fn roster(&self) -> Vec<RosterItem> {
+        self.roster
+            .read()
+            .map(|roster| roster.clone())
+            .unwrap_or_default()
+    }
+
+    fn presence(&self, jid: &str) -> Option<HostPresence> {
+        let presence = self.presence_manager.get_presence(jid);
+        Some(HostPresence {
+            jid: presence.jid,
+            show: presence.show,
+            status: presence.status,
+            priority: presence.priority,
+        })
+    }
+}
+
+/// Keep the plugin roster cache in step with the stored roster.
+fn spawn_plugin_roster_cache(
+    bridge: Arc<PluginHostBridge>,
+    roster_manager: Arc<RosterManager<NativeDatabase>>,
+    event_bus: Arc<dyn EventBus>,
+) {
     tauri::async_runtime::spawn(async move {
-        if let Err(reason) = task.await {
-            error!(component, %reason, "component task terminated");
-            emit_component_error(&event_bus, component, reason, true);
+        let mut subscription = match event_bus.subscribe("xmpp.roster.**") {
+            Ok(subscription) => subscription,
+            Err(error) => {
+                emit_component_error(&event_bus, "plugins", error.to_string(), false);
+                return;
+            }
+        };
+
+        loop {
+            match roster_manager.get_roster().await {
+                Ok(roster) => {
+                    if let Ok(mut cached) = bridge.roster.write() {
+                        *cached = roster;
+                    }
+                }
+                Err(error) => warn!(%error, "failed to refresh plugin roster cache"),
+            }
+
+            match subscription.recv().await {
+                Ok(_) | Err(waddle_core::error::EventBusError::Lagged(_)) => {}
+                Err(_) => return,
+            }
         }
     });
 }
 
-fn spawn_notifications(event_bus: Arc<dyn EventBus>, config: Config) {
+/// Run a component's event loop under the supervisor so it is restarted if
+/// it fails.
+fn spawn_component_task<T, F, Fut>(
+    supervisor: &Supervisor,
+    component: &'static str,
+    target: Arc<T>,
+    run: F,
+) where
+    T: Send + Sync + 'static,
+    F: Fn(Arc<T>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), String>> + Send + 'static,
+{
+    supervisor.spawn(component, move || run(target.clone()));
+}
+
+fn spawn_notifications(
+    event_bus: Arc<dyn EventBus>,
+    config: Config,
+    settings: Arc<NotificationSettings<NativeDatabase>>,
+) {
     tauri::async_runtime::spawn(async move {
-        if let Err(error) = NotificationManager::run(event_bus.clone(), &config).await {
+        if let Err(error) = NotificationManager::run(event_bus.clone(), &config, &settings).await {
             let reason = error.to_string();
             warn!(%reason, "notification manager terminated");
             emit_component_error(&event_bus, "notifications", reason, true);
@@ -680,12 +2248,12 @@ fn spawn_inbound_pump(
                 continue;
             }
 
-            let carbons_handled = {
+            let iq_response_handled = {
                 let mut manager = connection.lock().await;
-                manager.handle_carbons_iq_response(&frame)
+                manager.handle_carbons_iq_response(&frame) || manager.handle_ping_response(&frame)
             };
 
-            if carbons_handled {
+            if iq_response_handled {
                 let mut manager = connection.lock().await;
                 manager.mark_inbound_stanza_handled();
                 continue;
@@ -702,6 +2270,28 @@ fn spawn_inbound_pump(
     });
 }
 
+fn spawn_health_monitor(connection: Arc<Mutex<ConnectionManager>>, event_bus: Arc<dyn EventBus>) {
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(HEALTH_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            let result = {
+                let mut manager = connection.lock().await;
+                manager.check_health().await
+            };
+
+            // A failed ping surfaces again through the inbound pump, which
+            // owns reconnecting.
+            if let Err(error) = result {
+                debug!(%error, "connection health check failed");
+                emit_component_error(&event_bus, "xmpp", error.to_string(), error.is_retryable());
+            }
+        }
+    });
+}
+
 fn spawn_initial_connection(
     connection: Arc<Mutex<ConnectionManager>>,
     event_bus: Arc<dyn EventBus>,
@@ -730,7 +2320,7 @@ fn spawn_connection_control(
     event_bus: Arc<dyn EventBus>,
 ) {
     tauri::async_runtime::spawn(async move {
-        let mut subscription = match event_bus.subscribe("system.**") {
+        let mut subscription = match event_bus.subscribe("{system,ui.client}.**") {
             Ok(subscription) => subscription,
             Err(error) => {
                 emit_component_error(&event_bus, "xmpp", error.to_string(), false);
@@ -771,91 +2361,18 @@ fn spawn_connection_control(
                             );
                         }
                     }
-                    EventPayload::ShutdownRequested { .. } => {
-                        let mut unavailable_subscription =
-                            match event_bus.subscribe("xmpp.presence.own_changed") {
-                                Ok(subscription) => Some(subscription),
-                                Err(error) => {
-                                    emit_component_error(
-                                        &event_bus,
-                                        "presence",
-                                        error.to_string(),
-                                        true,
-                                    );
-                                    None
-                                }
-                            };
-
-                        if let Err(error) = publish_event(
-                            &event_bus,
-                            "ui.presence.set",
-                            EventSource::System(SYSTEM_COMPONENT.to_string()),
-                            EventPayload::PresenceSetRequested {
-                                show: PresenceShow::Unavailable,
-                                status: None,
-                            },
-                        ) {
-                            emit_component_error(&event_bus, "presence", error.to_string(), true);
-                        }
-
-                        let unavailable_seen = if let Some(mut unavailable_subscription) =
-                            unavailable_subscription.take()
-                        {
-                            tokio::time::timeout(
-                                Duration::from_secs(SHUTDOWN_CLEANUP_TIMEOUT_SECONDS),
-                                async {
-                                    loop {
-                                        match unavailable_subscription.recv().await {
-                                            Ok(event) => {
-                                                if matches!(
-                                                    event.payload,
-                                                    EventPayload::OwnPresenceChanged {
-                                                        show: PresenceShow::Unavailable,
-                                                        ..
-                                                    }
-                                                ) {
-                                                    return true;
-                                                }
-                                            }
-                                            Err(waddle_core::error::EventBusError::Lagged(
-                                                count,
-                                            )) => {
-                                                warn!(count, "shutdown presence wait lagged");
-                                            }
-                                            Err(
-                                                waddle_core::error::EventBusError::ChannelClosed,
-                                            ) => {
-                                                return false;
-                                            }
-                                            Err(error) => {
-                                                emit_component_error(
-                                                    &event_bus,
-                                                    "presence",
-                                                    error.to_string(),
-                                                    true,
-                                                );
-                                                return false;
-                                            }
-                                        }
-                                    }
-                                },
-                            )
-                            .await
-                            .unwrap_or_default()
-                        } else {
-                            false
-                        };
-
-                        if !unavailable_seen {
-                            warn!("timed out waiting for unavailable presence during shutdown");
-                        }
-
-                        let disconnect_result = {
+                    // XEP-0352: nothing is sent when the server lacks support.
+                    EventPayload::ClientActivityChanged { active } => {
+                        let csi_result = {
                             let mut manager = connection.lock().await;
-                            manager.disconnect().await
+                            if active {
+                                manager.set_csi_active().await
+                            } else {
+                                manager.set_csi_inactive().await
+                            }
                         };
 
-                        if let Err(error) = disconnect_result {
+                        if let Err(error) = csi_result {
                             emit_component_error(
                                 &event_bus,
                                 "xmpp",
@@ -863,8 +2380,6 @@ fn spawn_connection_control(
                                 error.is_retryable(),
                             );
                         }
-
-                        return;
                     }
                     _ => {}
                 },
@@ -883,39 +2398,26 @@ fn spawn_connection_control(
     });
 }
 
-fn frontend_event_name(channel: &str) -> String {
-    channel.replace('.', ":")
-}
+/// How many events the webview may fall behind by before some are dropped.
+const FORWARDED_EVENT_BUFFER: usize = 1024;
 
-fn spawn_event_forwarder(event_bus: Arc<dyn EventBus>, app_handle: AppHandle) {
-    tauri::async_runtime::spawn(async move {
-        let mut subscription = match event_bus.subscribe("{xmpp,system,plugin}.**") {
-            Ok(subscription) => subscription,
-            Err(error) => {
-                emit_component_error(&event_bus, "event-forwarder", error.to_string(), false);
-                return;
-            }
-        };
+fn spawn_event_forwarder(
+    bridge: &GuiBridge<NativeDatabase>,
+    event_bus: &Arc<dyn EventBus>,
+    app_handle: AppHandle,
+) {
+    let mut events = match bridge.forward_events(FORWARDED_EVENT_BUFFER) {
+        Ok(events) => events,
+        Err(error) => {
+            emit_component_error(event_bus, "event-forwarder", error.to_string(), false);
+            return;
+        }
+    };
 
-        loop {
-            match subscription.recv().await {
-                Ok(event) => {
-                    let channel = event.channel.as_str().to_string();
-                    let frontend_channel = frontend_event_name(channel.as_str());
-                    if let Err(error) = app_handle.emit(frontend_channel.as_str(), &event) {
-                        warn!(channel, frontend_channel, %error, "failed to forward event to frontend");
-                    }
-                }
-                Err(waddle_core::error::EventBusError::Lagged(count)) => {
-                    warn!(count, "event forwarder lagged");
-                }
-                Err(waddle_core::error::EventBusError::ChannelClosed) => {
-                    return;
-                }
-                Err(error) => {
-                    emit_component_error(&event_bus, "event-forwarder", error.to_string(), false);
-                    return;
-                }
+    tauri::async_runtime::spawn(async move {
+        while let Some(forwarded) = events.recv().await {
+            if let Err(error) = app_handle.emit(forwarded.name.as_str(), &forwarded.event) {
+                warn!(name = forwarded.name, %error, "failed to forward event to frontend");
             }
         }
     });
@@ -951,7 +2453,7 @@ async fn install_plugin(
 
     publish_event(
         &state.event_bus,
-        "plugin.install.started",
+        channels::PLUGIN_INSTALL_STARTED,
         EventSource::System(SYSTEM_COMPONENT.to_string()),
         EventPayload::PluginInstallStarted {
             plugin_id: installed.id.clone(),
@@ -967,7 +2469,7 @@ async fn install_plugin(
 
     publish_event(
         &state.event_bus,
-        "plugin.install.completed",
+        channels::PLUGIN_INSTALL_COMPLETED,
         EventSource::System(SYSTEM_COMPONENT.to_string()),
         EventPayload::PluginInstallCompleted {
             plugin_id: installed.id,
@@ -1051,6 +2553,33 @@ async fn get_plugin(
     Ok(PluginInfoResponse::from_installed(installed, "installed"))
 }
 
+/// Resume a plugin disabled for failing too often. One that was disabled
+/// while loading is loaded again from its installed files.
+async fn re_enable_plugin(
+    state: &AppState,
+    plugin_id: &str,
+) -> Result<PluginInfoResponse, GuiBackendError> {
+    let loaded = {
+        let mut runtime = state.plugin_runtime.lock().await;
+        let loaded = runtime.get_plugin(plugin_id).is_some();
+        if loaded || runtime.is_disabled(plugin_id) {
+            runtime.re_enable(plugin_id)?;
+        }
+        loaded
+    };
+
+    if loaded {
+        get_plugin(state, plugin_id).await
+    } else {
+        load_plugin_into_runtime(
+            state.plugin_registry.as_ref(),
+            &state.plugin_runtime,
+            plugin_id,
+        )
+        .await
+    }
+}
+
 async fn load_plugin_into_runtime(
     plugin_registry: &PluginRegistry,
     plugin_runtime: &Arc<Mutex<PluginRuntime<NativeDatabase>>>,
@@ -1062,11 +2591,13 @@ async fn load_plugin_into_runtime(
     let mut runtime = plugin_runtime.lock().await;
 
     if runtime.get_plugin(plugin_id).is_some() {
-        runtime.unload_plugin(plugin_id).await?;
+        runtime
+            .reload_plugin(plugin_id, files.manifest, &wasm_bytes)
+            .await?;
+    } else {
+        runtime.load_plugin(files.manifest, &wasm_bytes).await?;
     }
 
-    runtime.load_plugin(files.manifest, &wasm_bytes).await?;
-
     let plugin =
         runtime
             .get_plugin(plugin_id)
@@ -1101,6 +2632,7 @@ fn parse_presence_show(value: &str) -> Option<PresenceShow> {
         "away" => Some(PresenceShow::Away),
         "xa" => Some(PresenceShow::Xa),
         "dnd" => Some(PresenceShow::Dnd),
+        "invisible" => Some(PresenceShow::Invisible),
         "unavailable" => Some(PresenceShow::Unavailable),
         _ => None,
     }
@@ -1109,11 +2641,102 @@ fn parse_presence_show(value: &str) -> Option<PresenceShow> {
 fn connection_config_from(config: &Config) -> ConnectionConfig {
     ConnectionConfig {
         jid: config.account.jid.clone(),
-        password: config.account.password.clone(),
         server: config.account.server.clone(),
         port: config.account.port,
         timeout_seconds: CONNECTION_TIMEOUT_SECONDS,
         max_reconnect_attempts: CONNECTION_MAX_RECONNECT_ATTEMPTS,
+        transports: config.account.transports.clone(),
+        websocket_url: config.account.websocket_url.clone(),
+        bosh_url: config.account.bosh_url.clone(),
+    }
+}
+
+/// Pick where the account password lives: a password still present in the
+/// config file or environment wins, then the OS keyring, then an encrypted
+/// file unlocked by `WADDLE_CREDENTIALS_PASSPHRASE`.
+async fn credential_store_from(
+    config: &Config,
+) -> Result<Arc<dyn CredentialStore>, GuiBackendError> {
+    if !config.account.password.is_empty() {
+        warn!("account password is set in plaintext configuration; prefer the OS keyring");
+        return Ok(Arc::new(InMemoryCredentialStore::with_secret(
+            &config.account.jid,
+            &config.account.password,
+        )));
+    }
+
+    let credentials_path = default_credentials_path();
+    let store = tokio::task::spawn_blocking(move || {
+        let keyring = KeyringCredentialStore::default();
+        if keyring.is_available() {
+            return Ok::<Arc<dyn CredentialStore>, CredentialError>(Arc::new(keyring));
+        }
+
+        let Ok(passphrase) = std::env::var(CREDENTIALS_PASSPHRASE_ENV) else {
+            return Err(CredentialError::Unavailable(format!(
+                "no OS keyring found; set {CREDENTIALS_PASSPHRASE_ENV} to use an encrypted credential file"
+            )));
+        };
+        info!(
+            path = %credentials_path.display(),
+            "OS keyring unavailable; using encrypted credential file"
+        );
+        Ok(Arc::new(EncryptedFileCredentialStore::open(
+            &credentials_path,
+            &passphrase,
+        )?))
+    })
+    .await
+    .map_err(|error| CredentialError::Unavailable(error.to_string()))??;
+
+    Ok(store)
+}
+
+/// Load the key sealing OMEMO key material in the database, creating it on
+/// first run. It lives in the credential store so a copy of the database
+/// alone does not expose private keys.
+async fn omemo_storage_key(
+    credentials: &Arc<dyn CredentialStore>,
+    jid: &str,
+) -> Result<[u8; 32], GuiBackendError> {
+    let credentials = credentials.clone();
+    let jid = jid.to_string();
+    let account = format!("omemo-storage-key:{jid}");
+    tokio::task::spawn_blocking(move || match credentials.get(&account) {
+        Ok(stored) => {
+            let bytes: Option<Vec<u8>> = (0..stored.len())
+                .step_by(2)
+                .map(|i| {
+                    stored
+                        .get(i..i + 2)
+                        .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                })
+                .collect();
+            if let Some(key) = bytes.and_then(|bytes| <[u8; 32]>::try_from(bytes).ok()) {
+                return Ok(key);
+            }
+            Err(CredentialError::Corrupted(format!(
+                "invalid OMEMO storage key for {jid}"
+            )))
+        }
+        Err(CredentialError::NotFound { .. }) => {
+            let key = waddle_omemo::generate_storage_key();
+            let encoded: String = key.iter().map(|byte| format!("{byte:02x}")).collect();
+            credentials.set(&account, &encoded)?;
+            Ok(key)
+        }
+        Err(error) => Err(error),
+    })
+    .await
+    .map_err(|error| CredentialError::Unavailable(error.to_string()))?
+    .map_err(GuiBackendError::from)
+}
+
+fn default_credentials_path() -> PathBuf {
+    if let Some(project_dirs) = ProjectDirs::from("com", "waddle", "waddle") {
+        project_dirs.data_dir().join("credentials.json")
+    } else {
+        PathBuf::from("credentials.json")
     }
 }
 
@@ -1134,6 +2757,103 @@ fn default_storage_path() -> PathBuf {
     }
 }
 
+fn resolve_backup_dir(config: &Config, storage_path: &Path) -> PathBuf {
+    if let Some(directory) = config.storage.backup.directory.as_deref() {
+        return expand_home_path(directory);
+    }
+
+    storage_path
+        .parent()
+        .map(|parent| parent.join("backups"))
+        .unwrap_or_else(|| PathBuf::from("backups"))
+}
+
+fn resolve_download_dir(config: &Config, storage_path: &Path) -> PathBuf {
+    if let Some(directory) = config.storage.downloads.directory.as_deref() {
+        return expand_home_path(directory);
+    }
+
+    storage_path
+        .parent()
+        .map(|parent| parent.join("downloads"))
+        .unwrap_or_else(|| PathBuf::from("downloads"))
+}
+
+fn resolve_log_dir(config: &Config) -> PathBuf {
+    if let Some(directory) = config.logging.directory.as_deref() {
+        return expand_home_path(directory);
+    }
+
+    if let Some(project_dirs) = ProjectDirs::from("com", "waddle", "waddle") {
+        project_dirs.data_dir().join("logs")
+    } else {
+        PathBuf::from("logs")
+    }
+}
+
+/// Directories given with `--dev <dir>` on the command line.
+fn dev_plugin_dirs(args: impl Iterator<Item = String>) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        if let Some(dir) = arg.strip_prefix("--dev=") {
+            dirs.push(PathBuf::from(dir));
+        } else if arg == "--dev"
+            && let Some(dir) = args.next_if(|next| !next.starts_with("--"))
+        {
+            dirs.push(PathBuf::from(dir));
+        }
+    }
+    dirs
+}
+
+fn plugin_registry_config(config: &config::PluginsConfig) -> RegistryConfig {
+    RegistryConfig {
+        signature_policy: config.signature_policy.clone(),
+        trusted_keys: config.trusted_keys.clone(),
+        credentials: config
+            .registries
+            .iter()
+            .map(|(registry, auth)| {
+                let credentials = match auth {
+                    config::RegistryAuthConfig::Basic { username, password } => {
+                        RegistryCredentials::Basic {
+                            username: username.clone(),
+                            password: password.clone(),
+                        }
+                    }
+                    config::RegistryAuthConfig::Token { token } => {
+                        RegistryCredentials::Token(token.clone())
+                    }
+                    config::RegistryAuthConfig::Docker => RegistryCredentials::Docker,
+                };
+                (registry.clone(), credentials)
+            })
+            .collect(),
+        ..RegistryConfig::default()
+    }
+}
+
+fn plugin_runtime_config(config: &config::PluginsConfig) -> PluginRuntimeConfig {
+    PluginRuntimeConfig {
+        plugin_limits: config
+            .limits
+            .iter()
+            .map(|(plugin_id, limits)| {
+                let limits = PluginLimits {
+                    fuel_per_invocation: limits.fuel_per_invocation,
+                    fuel_per_render: limits.fuel_per_render,
+                    max_memory_bytes: limits.max_memory_bytes,
+                };
+                (plugin_id.clone(), limits)
+            })
+            .collect(),
+        error_threshold: config.error_threshold,
+        error_window_secs: config.error_window_secs,
+        ..PluginRuntimeConfig::default()
+    }
+}
+
 fn resolve_plugin_data_dir(config: &Config) -> PathBuf {
     if let Some(configured_path) = config.plugins.directory.as_deref() {
         let plugin_path = expand_home_path(configured_path);
@@ -1183,7 +2903,7 @@ fn publish_shutdown_requested(
 ) -> Result<(), GuiBackendError> {
     publish_event(
         event_bus,
-        "system.shutdown.requested",
+        channels::SYSTEM_SHUTDOWN_REQUESTED,
         EventSource::System(SYSTEM_COMPONENT.to_string()),
         EventPayload::ShutdownRequested {
             reason: reason.to_string(),
@@ -1199,7 +2919,7 @@ fn emit_component_error(
 ) {
     let result = publish_event(
         event_bus,
-        "system.error.occurred",
+        channels::SYSTEM_ERROR_OCCURRED,
         EventSource::System(SYSTEM_COMPONENT.to_string()),
         EventPayload::ErrorOccurred {
             component: component.to_string(),
@@ -1217,6 +2937,22 @@ fn emit_component_error(
 mod tests {
     use super::*;
 
+    #[test]
+    fn collects_dev_plugin_dirs() {
+        let args = [
+            "--dev",
+            "/src/a",
+            "--verbose",
+            "--dev=/src/b",
+            "--dev",
+            "--other",
+        ];
+        assert_eq!(
+            dev_plugin_dirs(args.into_iter().map(String::from)),
+            [PathBuf::from("/src/a"), PathBuf::from("/src/b")]
+        );
+    }
+
     #[test]
     fn parses_presence_show_values() {
         assert!(matches!(
@@ -1236,6 +2972,10 @@ mod tests {
             parse_presence_show("dnd"),
             Some(PresenceShow::Dnd)
         ));
+        assert!(matches!(
+            parse_presence_show("invisible"),
+            Some(PresenceShow::Invisible)
+        ));
         assert!(matches!(
             parse_presence_show("unavailable"),
             Some(PresenceShow::Unavailable)
@@ -0,0 +1,108 @@
+//! The commands the webview sends, and the events it receives back.
+//!
+//! Commands are JSON objects tagged by `command`, with camelCase fields:
+//!
+//! ```text
+//! {"command":"sendMessage","to":"bob@example.com","body":"hi"}
+//! {"command":"listConversations"}
+//! {"command":"getRoster"}
+//! {"command":"joinRoom","room":"room@conference.example.com","nick":"alice"}
+//! {"command":"setPresence","show":"away","status":"lunch"}
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use waddle_core::event::{Event, PresenceShow};
+
+/// Bus channels forwarded to the webview. UI requests stay on the host side,
+/// apart from the results the frontend renders.
+pub const FORWARDED_CHANNELS: &str = "{xmpp.**,system.**,plugin.**,ui.history.**,\
+                                      ui.notification.**,ui.transfer.**,ui.message.preview_ready,\
+                                      ui.message.translation_ready,ui.message.translation_requested}";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "camelCase")]
+pub enum BridgeCommand {
+    /// Replies with the stored [`ChatMessage`](waddle_core::event::ChatMessage).
+    SendMessage {
+        to: String,
+        body: String,
+    },
+    /// Replies with the conversations, most recent first.
+    ListConversations,
+    /// Replies with every roster item.
+    GetRoster,
+    JoinRoom {
+        room: String,
+        nick: String,
+    },
+    SetPresence {
+        show: PresenceShow,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        status: Option<String>,
+    },
+}
+
+impl BridgeCommand {
+    /// The `command` tag, for logs.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::SendMessage { .. } => "sendMessage",
+            Self::ListConversations => "listConversations",
+            Self::GetRoster => "getRoster",
+            Self::JoinRoom { .. } => "joinRoom",
+            Self::SetPresence { .. } => "setPresence",
+        }
+    }
+}
+
+/// A bus event as the webview listens for it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebviewEvent {
+    /// The channel with `:` for `.`, as webview event names cannot contain
+    /// dots: `xmpp:message:received`.
+    pub name: String,
+    pub event: Event,
+}
+
+impl From<Event> for WebviewEvent {
+    fn from(event: Event) -> Self {
+        Self {
+            name: webview_event_name(event.channel.as_str()),
+            event,
+        }
+    }
+}
+
+pub fn webview_event_name(channel: &str) -> String {
+    channel.replace('.', ":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_use_the_documented_shape() {
+        let command: BridgeCommand = serde_json::from_str(
+            r#"{"command":"joinRoom","room":"room@conference.example.com","nick":"alice"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            &command,
+            BridgeCommand::JoinRoom { room, nick }
+                if room == "room@conference.example.com" && nick == "alice"
+        ));
+        assert_eq!(command.name(), "joinRoom");
+
+        let command = BridgeCommand::SetPresence {
+            show: PresenceShow::Away,
+            status: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&command).unwrap(),
+            r#"{"command":"setPresence","show":"away"}"#
+        );
+        assert!(serde_json::from_str::<BridgeCommand>(r#"{"command":"reboot"}"#).is_err());
+    }
+}
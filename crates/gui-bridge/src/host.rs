@@ -0,0 +1,235 @@
+use std::sync::Arc;
+
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use waddle_api::{PresenceHandle, Store, Waddle};
+use waddle_core::error::EventBusError;
+use waddle_core::event::EventBus;
+use waddle_messaging::{ConversationManager, MessageManager, MucManager};
+use waddle_roster::RosterManager;
+use waddle_storage::Database;
+
+use crate::GuiBridgeError;
+use crate::command::{BridgeCommand, FORWARDED_CHANNELS, WebviewEvent};
+
+/// Runs [`BridgeCommand`]s against the managers and forwards bus events to
+/// the webview. Cheap to clone. Generic over the database so that a host
+/// wiring up its own managers need not go through the [`Waddle`] facade.
+pub struct GuiBridge<D: Database = Store> {
+    event_bus: Arc<dyn EventBus>,
+    roster: Arc<RosterManager<D>>,
+    messages: Arc<MessageManager<D>>,
+    muc: Arc<MucManager<D>>,
+    conversations: Arc<ConversationManager<D>>,
+    presence: PresenceHandle,
+}
+
+impl<D: Database> Clone for GuiBridge<D> {
+    fn clone(&self) -> Self {
+        Self {
+            event_bus: self.event_bus.clone(),
+            roster: self.roster.clone(),
+            messages: self.messages.clone(),
+            muc: self.muc.clone(),
+            conversations: self.conversations.clone(),
+            presence: self.presence.clone(),
+        }
+    }
+}
+
+impl GuiBridge {
+    pub fn from_waddle(waddle: &Waddle) -> Self {
+        Self::new(
+            waddle.event_bus(),
+            waddle.roster(),
+            waddle.messages(),
+            waddle.muc(),
+            waddle.conversations(),
+            waddle.presence(),
+        )
+    }
+}
+
+impl<D: Database> GuiBridge<D> {
+    /// A bridge over managers the host wired up itself.
+    pub fn new(
+        event_bus: Arc<dyn EventBus>,
+        roster: Arc<RosterManager<D>>,
+        messages: Arc<MessageManager<D>>,
+        muc: Arc<MucManager<D>>,
+        conversations: Arc<ConversationManager<D>>,
+        presence: PresenceHandle,
+    ) -> Self {
+        Self {
+            event_bus,
+            roster,
+            messages,
+            muc,
+            conversations,
+            presence,
+        }
+    }
+
+    /// Run `command` and encode its reply; commands without one reply with
+    /// `null`.
+    pub async fn invoke(&self, command: BridgeCommand) -> Result<Value, GuiBridgeError> {
+        debug!(command = command.name(), "bridge command");
+        let reply = match command {
+            BridgeCommand::SendMessage { to, body } => {
+                serde_json::to_value(self.messages.send_message(&to, &body).await?)?
+            }
+            BridgeCommand::ListConversations => {
+                serde_json::to_value(self.conversations.list_conversations().await?)?
+            }
+            BridgeCommand::GetRoster => serde_json::to_value(self.roster.get_roster().await?)?,
+            BridgeCommand::JoinRoom { room, nick } => {
+                self.muc.join_room(&room, &nick).await?;
+                Value::Null
+            }
+            BridgeCommand::SetPresence { show, status } => {
+                self.presence
+                    .set_own_presence(show, status.as_deref(), None)?;
+                Value::Null
+            }
+        };
+        Ok(reply)
+    }
+
+    /// Subscribe to [`FORWARDED_CHANNELS`] and pass each event on until the
+    /// bus closes or the receiver is dropped. Events that arrive while the
+    /// receiver is `capacity` behind are dropped with a warning rather than
+    /// stalling the bus.
+    pub fn forward_events(
+        &self,
+        capacity: usize,
+    ) -> Result<mpsc::Receiver<WebviewEvent>, GuiBridgeError> {
+        let mut subscription = self.event_bus.subscribe(FORWARDED_CHANNELS)?;
+        let (sender, receiver) = mpsc::channel(capacity);
+        tokio::spawn(async move {
+            loop {
+                match subscription.recv().await {
+                    Ok(event) => match sender.try_send(WebviewEvent::from(event)) {
+                        Ok(()) => {}
+                        Err(mpsc::error::TrySendError::Full(event)) => {
+                            warn!(name = event.name, "webview is behind, dropping event");
+                        }
+                        Err(mpsc::error::TrySendError::Closed(_)) => return,
+                    },
+                    Err(EventBusError::Lagged(count)) => {
+                        warn!(count, "event forwarder lagged");
+                    }
+                    Err(EventBusError::ChannelClosed) => return,
+                    Err(error) => {
+                        warn!(%error, "event forwarder stopped");
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(receiver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+    use tokio::time::timeout;
+
+    use waddle_core::event::{Channel, Event, EventPayload, EventSource, PresenceShow, channels};
+
+    async fn bridge(dir: &TempDir) -> GuiBridge {
+        let waddle = Waddle::open(&dir.path().join("waddle.db"), 64)
+            .await
+            .unwrap();
+        GuiBridge::from_waddle(&waddle)
+    }
+
+    #[tokio::test]
+    async fn commands_reach_the_managers() {
+        let dir = TempDir::new().unwrap();
+        let bridge = bridge(&dir).await;
+        let mut presence = bridge.event_bus.subscribe("ui.presence.set").unwrap();
+
+        let message = bridge
+            .invoke(BridgeCommand::SendMessage {
+                to: "bob@example.com".to_string(),
+                body: "hi".to_string(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(message["body"], "hi");
+
+        let conversations = bridge
+            .invoke(BridgeCommand::ListConversations)
+            .await
+            .unwrap();
+        assert!(conversations.is_array());
+        assert_eq!(
+            bridge.invoke(BridgeCommand::GetRoster).await.unwrap(),
+            Value::Array(Vec::new())
+        );
+
+        let reply = bridge
+            .invoke(BridgeCommand::SetPresence {
+                show: PresenceShow::Dnd,
+                status: Some("focus".to_string()),
+            })
+            .await
+            .unwrap();
+        assert_eq!(reply, Value::Null);
+        let event = timeout(Duration::from_secs(1), presence.recv())
+            .await
+            .expect("timed out")
+            .unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::PresenceSetRequested {
+                show: PresenceShow::Dnd,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn forwards_events_under_webview_names() {
+        let dir = TempDir::new().unwrap();
+        let bridge = bridge(&dir).await;
+        let mut events = bridge.forward_events(8).unwrap();
+
+        // Outside the forwarded channels, so never seen by the webview.
+        bridge
+            .event_bus
+            .publish(Event::new(
+                Channel::new(channels::UI_COMPOSE_STARTED).unwrap(),
+                EventSource::System("test".into()),
+                EventPayload::ComposeStarted {
+                    jid: "bob@example.com".to_string(),
+                },
+            ))
+            .unwrap();
+        bridge
+            .event_bus
+            .publish(Event::new(
+                Channel::new(channels::SYSTEM_CONNECTION_ESTABLISHED).unwrap(),
+                EventSource::Xmpp,
+                EventPayload::ConnectionEstablished {
+                    jid: "alice@example.com".to_string(),
+                },
+            ))
+            .unwrap();
+
+        let forwarded = timeout(Duration::from_secs(1), events.recv())
+            .await
+            .expect("timed out")
+            .unwrap();
+        assert_eq!(forwarded.name, "system:connection:established");
+        assert!(matches!(
+            forwarded.event.payload,
+            EventPayload::ConnectionEstablished { .. }
+        ));
+    }
+}
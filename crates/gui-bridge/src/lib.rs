@@ -0,0 +1,36 @@
+//! Host API for the webview frontend.
+//!
+//! The Vue frontend, and the plugin UIs it hosts, talk to the Rust side
+//! through two things only: [`BridgeCommand`]s, which run a manager operation
+//! and reply with JSON, and [`WebviewEvent`]s forwarded from the event bus.
+//! The desktop shell maps these onto its IPC; the frontend never sees the
+//! managers or the bus themselves, so either can change underneath it.
+
+pub mod command;
+
+#[cfg(feature = "native")]
+mod host;
+
+pub use command::{BridgeCommand, FORWARDED_CHANNELS, WebviewEvent, webview_event_name};
+#[cfg(feature = "native")]
+pub use host::GuiBridge;
+
+use waddle_api::errors::{EventBusError, MessagingError, PresenceError, RosterError};
+
+#[derive(Debug, thiserror::Error)]
+pub enum GuiBridgeError {
+    #[error("roster error: {0}")]
+    Roster(#[from] RosterError),
+
+    #[error("messaging error: {0}")]
+    Messaging(#[from] MessagingError),
+
+    #[error("presence error: {0}")]
+    Presence(#[from] PresenceError),
+
+    #[error("event bus error: {0}")]
+    EventBus(#[from] EventBusError),
+
+    #[error("cannot encode reply: {0}")]
+    Encode(#[from] serde_json::Error),
+}
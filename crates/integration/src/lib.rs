@@ -1,3 +1,5 @@
+#[cfg(feature = "native")]
+pub mod sim;
 
 #[cfg(all(test, feature = "native"))]
 mod tests {
@@ -10,17 +12,22 @@ mod tests {
     use tempfile::TempDir;
     use tokio::time::timeout;
 
+    use waddle_core::event::replay::{RecordingEventBus, ReplaySpeed, Replayer, read_recording};
     use waddle_core::event::{
         BroadcastEventBus, Channel, ChatMessage, ChatState, Event, EventBus, EventPayload,
         EventSource, MessageType, MucAffiliation, MucOccupant, MucRole, PresenceShow, RosterItem,
-        Subscription,
+        Rule, RuleAction, RuleTrigger, Subscription,
     };
+    use waddle_core::rsm::{RsmCursor, RsmQuery, RsmResult};
     use waddle_mam::MamManager;
-    use waddle_messaging::{MessageManager, MucManager};
+    use waddle_messaging::{MessageManager, MucManager, RuleEngine};
+    use waddle_plugins::{PluginManifest, PluginRuntime, PluginRuntimeConfig};
     use waddle_presence::PresenceManager;
     use waddle_roster::RosterManager;
     use waddle_storage::{Database, Row, SqlValue};
 
+    use crate::sim::{MamFaults, Script, SimServer};
+
     const TIMEOUT: Duration = Duration::from_millis(500);
 
     async fn setup_db(dir: &TempDir) -> Arc<impl Database + use<>> {
@@ -53,6 +60,11 @@ mod tests {
             message_type: MessageType::Chat,
             thread: None,
             embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
         }
     }
 
@@ -85,7 +97,10 @@ mod tests {
             .await
             .expect("timed out")
             .unwrap();
-        assert!(matches!(event.payload, EventPayload::RosterFetchRequested));
+        assert!(matches!(
+            event.payload,
+            EventPayload::RosterFetchRequested { .. }
+        ));
 
         // Presence should still be Unavailable (waiting for roster)
         let own = presence.own_presence();
@@ -215,6 +230,8 @@ mod tests {
             EventPayload::PresenceSetRequested {
                 show: PresenceShow::Available,
                 status: None,
+                priority: 0,
+                idle_since: None,
             }
         ));
     }
@@ -504,6 +521,11 @@ mod tests {
             message_type: MessageType::Groupchat,
             thread: None,
             embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
         };
         let msg_event = make_xmpp_event(
             "xmpp.muc.message.received",
@@ -527,6 +549,7 @@ mod tests {
             EventPayload::MucSubjectChanged {
                 room: "room@conference.example.com".to_string(),
                 subject: "Sprint Planning".to_string(),
+                set_by: Some("Bob".to_string()),
             },
         );
         muc.handle_event(&subject).await;
@@ -680,7 +703,10 @@ mod tests {
                     EventPayload::MamFinReceived {
                         iq_id: query_id,
                         complete: true,
-                        last_id: Some("arch-1".to_string()),
+                        page: RsmResult {
+                            last: Some("arch-1".to_string()),
+                            ..RsmResult::default()
+                        },
                     },
                 ))
                 .unwrap();
@@ -864,7 +890,7 @@ mod tests {
             Some(&waddle_storage::SqlValue::Text("confirmed".to_string()))
         );
 
-        // MAM reconciliation for second message
+        // MAM reconciliation for second message, matched by its origin id
         let mam_msg = ChatMessage {
             id: "archive-id-99".to_string(),
             from: "alice@example.com".to_string(),
@@ -874,6 +900,11 @@ mod tests {
             message_type: MessageType::Chat,
             thread: None,
             embeds: vec![],
+            origin_id: Some(msg2.id.clone()),
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
         };
 
         // First mark second as sent
@@ -1013,6 +1044,7 @@ mod tests {
                 show: PresenceShow::Available,
                 status: Some("online".to_string()),
                 priority: 5,
+                idle_since: None,
             },
         );
         presence.handle_event(&bob_available).await;
@@ -1029,6 +1061,7 @@ mod tests {
                 show: PresenceShow::Away,
                 status: Some("on phone".to_string()),
                 priority: 10,
+                idle_since: None,
             },
         );
         presence.handle_event(&bob_mobile).await;
@@ -1087,7 +1120,10 @@ mod tests {
                     .await
                     .expect("timed out")
                     .unwrap();
-                assert!(matches!(fetch.payload, EventPayload::RosterFetchRequested));
+                assert!(matches!(
+                    fetch.payload,
+                    EventPayload::RosterFetchRequested { .. }
+                ));
 
                 // Drain ComingOnline from messaging
                 let coming = timeout(TIMEOUT, sys_sub.recv())
@@ -1143,45 +1179,67 @@ mod tests {
                     mam_clone.handle_event(&own_presence).await;
                 });
 
-                // 5. MAM queries for catch-up
-                let query_event = timeout(TIMEOUT, ui_sub.recv())
-                    .await
-                    .expect("timed out waiting for MAM query")
+                // 5. The backfill fetches the roster contact's latest page
+                // while MAM queries for catch-up. Respond to both with an
+                // empty archive.
+                let mut queried_jids = Vec::new();
+                for _ in 0..2 {
+                    let query_event = timeout(TIMEOUT, ui_sub.recv())
+                        .await
+                        .expect("timed out waiting for MAM query")
+                        .unwrap();
+
+                    let (query_id, with_jid) = match &query_event.payload {
+                        EventPayload::MamQueryRequested {
+                            query_id, with_jid, ..
+                        } => (query_id.clone(), with_jid.clone()),
+                        other => panic!("expected MamQueryRequested, got {other:?}"),
+                    };
+
+                    bus.publish(Event::new(
+                        Channel::new("xmpp.mam.fin.received").unwrap(),
+                        EventSource::Xmpp,
+                        EventPayload::MamFinReceived {
+                            iq_id: query_id,
+                            complete: true,
+                            page: RsmResult::default(),
+                        },
+                    ))
                     .unwrap();
 
-                let query_id = match &query_event.payload {
-                    EventPayload::MamQueryRequested { query_id, .. } => query_id.clone(),
-                    other => panic!("expected MamQueryRequested, got {other:?}"),
-                };
-
-                // Respond with empty archive
-                bus.publish(Event::new(
-                    Channel::new("xmpp.mam.fin.received").unwrap(),
-                    EventSource::Xmpp,
-                    EventPayload::MamFinReceived {
-                        iq_id: query_id,
-                        complete: true,
-                        last_id: None,
-                    },
-                ))
-                .unwrap();
+                    queried_jids.push(with_jid);
+                }
+                queried_jids.sort();
+                assert_eq!(
+                    queried_jids,
+                    vec![None, Some("bob@example.com".to_string())]
+                );
 
                 timeout(Duration::from_secs(5), mam_handle)
                     .await
                     .expect("MAM handle timed out")
                     .expect("MAM handle should not panic");
 
-                // 6. SyncStarted and SyncCompleted events
-                let started = timeout(TIMEOUT, sys_sub.recv())
-                    .await
-                    .expect("timed out waiting for SyncStarted")
-                    .unwrap();
+                // 6. SyncStarted and SyncCompleted events, among the
+                // backfill's per-task events
+                let mut sync_events = Vec::new();
+                while sync_events.len() < 2 {
+                    let event = timeout(TIMEOUT, sys_sub.recv())
+                        .await
+                        .expect("timed out waiting for sync events")
+                        .unwrap();
+                    if !matches!(
+                        event.payload,
+                        EventPayload::SyncTaskStarted { .. }
+                            | EventPayload::SyncTaskFinished { .. }
+                    ) {
+                        sync_events.push(event);
+                    }
+                }
+                let completed = sync_events.pop().unwrap();
+                let started = sync_events.pop().unwrap();
                 assert!(matches!(started.payload, EventPayload::SyncStarted));
 
-                let completed = timeout(TIMEOUT, sys_sub.recv())
-                    .await
-                    .expect("timed out waiting for SyncCompleted")
-                    .unwrap();
                 assert!(matches!(
                     completed.payload,
                     EventPayload::SyncCompleted { messages_synced: 0 }
@@ -1244,6 +1302,7 @@ mod tests {
                 show: PresenceShow::Available,
                 status: None,
                 priority: 0,
+                idle_since: None,
             },
         );
         presence.handle_event(&bob_online).await;
@@ -1301,7 +1360,7 @@ mod tests {
                 .expect("timed out waiting for reconnect events")
                 .unwrap();
             match &event.payload {
-                EventPayload::RosterFetchRequested => saw_roster_fetch = true,
+                EventPayload::RosterFetchRequested { .. } => saw_roster_fetch = true,
                 EventPayload::MessageSendRequested { body, .. } if body == "missed you" => {
                     saw_drained_msg = true
                 }
@@ -1353,11 +1412,24 @@ mod tests {
         );
         roster.handle_event(&sub_request).await;
 
+        // The request is held as pending until the user acts on it
+        let event = timeout(TIMEOUT, ui_sub.recv())
+            .await
+            .expect("timed out")
+            .unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::SubscriptionPending { ref jid, .. } if jid == "carol@example.com"
+        ));
+        let pending = roster.pending_subscriptions().await.unwrap();
+        assert_eq!(pending.len(), 1);
+
         // Approve the subscription
         roster
             .approve_subscription("carol@example.com")
             .await
             .unwrap();
+        assert!(roster.pending_subscriptions().await.unwrap().is_empty());
 
         let event = timeout(TIMEOUT, ui_sub.recv())
             .await
@@ -1371,12 +1443,7 @@ mod tests {
             } if jid == "carol@example.com"
         ));
 
-        // Request our own subscription to carol
-        roster
-            .request_subscription("carol@example.com")
-            .await
-            .unwrap();
-
+        // Mutual subscription: approving also subscribes us to carol
         let event = timeout(TIMEOUT, ui_sub.recv())
             .await
             .expect("timed out")
@@ -1386,6 +1453,7 @@ mod tests {
             EventPayload::SubscriptionSendRequested {
                 ref jid,
                 subscribe: true,
+                ..
             } if jid == "carol@example.com"
         ));
 
@@ -1464,6 +1532,7 @@ mod tests {
             EventPayload::SubscriptionSendRequested {
                 ref jid,
                 subscribe: false,
+                ..
             } if jid == "dave@example.com"
         ));
     }
@@ -1526,6 +1595,11 @@ mod tests {
             message_type: MessageType::Groupchat,
             thread: None,
             embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
         };
         let muc_recv = make_xmpp_event(
             "xmpp.muc.message.received",
@@ -1665,7 +1739,10 @@ mod tests {
                     EventPayload::MamFinReceived {
                         iq_id: q1_id,
                         complete: false,
-                        last_id: Some("msg-2".to_string()),
+                        page: RsmResult {
+                            last: Some("msg-2".to_string()),
+                            ..RsmResult::default()
+                        },
                     },
                 ))
                 .unwrap();
@@ -1702,7 +1779,10 @@ mod tests {
                     EventPayload::MamFinReceived {
                         iq_id: q2_id,
                         complete: true,
-                        last_id: Some("msg-3".to_string()),
+                        page: RsmResult {
+                            last: Some("msg-3".to_string()),
+                            ..RsmResult::default()
+                        },
                     },
                 ))
                 .unwrap();
@@ -1960,7 +2040,7 @@ mod tests {
                 let mam_clone = mam.clone();
                 let handle = tokio::task::spawn_local(async move {
                     mam_clone
-                        .fetch_history("bob@example.com", None, 10)
+                        .fetch_history("bob@example.com", RsmQuery::first(10))
                         .await
                         .unwrap()
                 });
@@ -1974,11 +2054,11 @@ mod tests {
                     EventPayload::MamQueryRequested {
                         query_id,
                         with_jid,
-                        max,
+                        page,
                         ..
                     } => {
                         assert_eq!(with_jid.as_deref(), Some("bob@example.com"));
-                        assert_eq!(*max, 10);
+                        assert_eq!(*page, RsmQuery::first(10));
                         query_id.clone()
                     }
                     other => panic!("expected MamQueryRequested, got {other:?}"),
@@ -2015,7 +2095,10 @@ mod tests {
                     EventPayload::MamFinReceived {
                         iq_id: query_id,
                         complete: true,
-                        last_id: Some("hist-2".to_string()),
+                        page: RsmResult {
+                            last: Some("hist-2".to_string()),
+                            ..RsmResult::default()
+                        },
                     },
                 ))
                 .unwrap();
@@ -2197,4 +2280,323 @@ mod tests {
         presence.handle_event(&error).await;
         mam.handle_event(&error).await;
     }
+
+    // ── 17. Simulated Server: Carbons, Duplicates, MAM Faults ────
+    // Live copies, carbons, redeliveries and an archive that repeats
+    // itself must still leave exactly one stored copy of each message
+
+    #[tokio::test]
+    async fn simulated_sync_stores_each_message_once() {
+        let dir = TempDir::new().unwrap();
+        let db = setup_db(&dir).await;
+        let bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+
+        let messaging = Arc::new(MessageManager::new(db.clone(), bus.clone()));
+        let mam = Arc::new(MamManager::new(db.clone(), bus.clone()));
+        let sim = Arc::new(SimServer::new(bus.clone()).with_mam_faults(MamFaults {
+            page_overlap: 3,
+            reverse_pages: true,
+            duplicate_results: true,
+        }));
+        let _server = sim.serve_mam().unwrap();
+        let mut live = bus.subscribe("xmpp.message.**").unwrap();
+
+        // More than one MAM page, the first 40 of them also seen live.
+        let mut script = Script::new();
+        for i in 0..40 {
+            let id = format!("sim-msg-{i:02}");
+            script = match i % 3 {
+                0 => script.carbon_sent(make_chat_message(
+                    &id,
+                    "alice@example.com/phone",
+                    "bob@example.com",
+                    "from my phone",
+                )),
+                1 => script.carbon_received(make_chat_message(
+                    &id,
+                    "bob@example.com/laptop",
+                    "alice@example.com/phone",
+                    "to my phone",
+                )),
+                _ => script
+                    .deliver(make_chat_message(
+                        &id,
+                        "bob@example.com/laptop",
+                        "alice@example.com/desktop",
+                        "live",
+                    ))
+                    .duplicate(),
+            };
+        }
+        let mut script = script.shuffle(7);
+        let live_count = script.len();
+        for i in 40..60 {
+            script = script.archive_only(make_chat_message(
+                &format!("sim-msg-{i:02}"),
+                "bob@example.com/laptop",
+                "alice@example.com",
+                "while offline",
+            ));
+        }
+        sim.play(script).unwrap();
+
+        for _ in 0..live_count {
+            let event = timeout(TIMEOUT, live.recv())
+                .await
+                .expect("timed out waiting for live message")
+                .unwrap();
+            messaging.handle_event(&event).await;
+        }
+
+        mam.handle_event(&make_event(
+            "system.connection.established",
+            EventPayload::ConnectionEstablished {
+                jid: "alice@example.com".to_string(),
+            },
+        ))
+        .await;
+        mam.handle_event(&make_xmpp_event(
+            "xmpp.presence.own_changed",
+            EventPayload::OwnPresenceChanged {
+                show: PresenceShow::Available,
+                status: None,
+            },
+        ))
+        .await;
+
+        assert!(
+            sim.queries()
+                .iter()
+                .any(|query| matches!(query.page.cursor, RsmCursor::After(_))),
+            "the archive was never paged through"
+        );
+        let rows: Vec<Row> = db
+            .query(
+                "SELECT COUNT(*), COUNT(DISTINCT id), COUNT(stanza_id) FROM messages",
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows[0].get(0), Some(&SqlValue::Integer(60)));
+        assert_eq!(rows[0].get(1), Some(&SqlValue::Integer(60)));
+        assert_eq!(rows[0].get(2), Some(&SqlValue::Integer(60)));
+        assert_eq!(sim.archive().len(), 60);
+    }
+
+    // ── 18. Event Recording and Replay ───────────────────────────
+    // A recorded session fed back through fresh managers leaves them in
+    // the state the recorded ones reached
+
+    async fn wait_for_state<D: Database>(
+        roster: &RosterManager<D>,
+        messaging: &MessageManager<D>,
+    ) -> (usize, Vec<String>) {
+        timeout(TIMEOUT, async {
+            loop {
+                let contacts = roster.get_roster().await.unwrap().len();
+                let bodies: Vec<String> = messaging
+                    .get_messages("bob@example.com", 50, None)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|message| message.body)
+                    .collect();
+                if contacts > 0 && !bodies.is_empty() {
+                    return (contacts, bodies);
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("managers never caught up")
+    }
+
+    #[tokio::test]
+    async fn recorded_session_replays_through_fresh_managers() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let recorded_dir = TempDir::new().unwrap();
+                let recording = recorded_dir.path().join("events.jsonl");
+                let db = setup_db(&recorded_dir).await;
+                let bus: Arc<dyn EventBus> = Arc::new(
+                    RecordingEventBus::create(Arc::new(BroadcastEventBus::default()), &recording)
+                        .unwrap(),
+                );
+                let roster = Arc::new(RosterManager::new(db.clone(), bus.clone()));
+                let messaging = Arc::new(MessageManager::new(db.clone(), bus.clone()));
+                tokio::task::spawn_local(roster.clone().run());
+                tokio::task::spawn_local(messaging.clone().run());
+                tokio::time::sleep(Duration::from_millis(10)).await;
+
+                bus.publish(make_event(
+                    "system.connection.established",
+                    EventPayload::ConnectionEstablished {
+                        jid: "alice@example.com".to_string(),
+                    },
+                ))
+                .unwrap();
+                bus.publish(make_xmpp_event(
+                    "xmpp.roster.received",
+                    EventPayload::RosterReceived {
+                        items: vec![RosterItem {
+                            jid: "bob@example.com".to_string(),
+                            name: Some("Bob".to_string()),
+                            subscription: Subscription::Both,
+                            groups: vec![],
+                        }],
+                    },
+                ))
+                .unwrap();
+                bus.publish(make_xmpp_event(
+                    "xmpp.message.received",
+                    EventPayload::MessageReceived {
+                        message: make_chat_message(
+                            "msg-1",
+                            "bob@example.com",
+                            "alice@example.com",
+                            "Hi Alice",
+                        ),
+                    },
+                ))
+                .unwrap();
+                let recorded_state = wait_for_state(&roster, &messaging).await;
+
+                let events = read_recording(&recording).unwrap();
+                let seqs: Vec<u64> = events.iter().map(|recorded| recorded.seq).collect();
+                assert_eq!(seqs, (1..=events.len() as u64).collect::<Vec<_>>());
+                assert!(events.len() >= 3);
+
+                // Only what came from outside the managers: they publish
+                // the rest again themselves.
+                let replay_dir = TempDir::new().unwrap();
+                let replay_db = setup_db(&replay_dir).await;
+                let replay_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+                let replay_roster =
+                    Arc::new(RosterManager::new(replay_db.clone(), replay_bus.clone()));
+                let replay_messaging =
+                    Arc::new(MessageManager::new(replay_db.clone(), replay_bus.clone()));
+                tokio::task::spawn_local(replay_roster.clone().run());
+                tokio::task::spawn_local(replay_messaging.clone().run());
+                tokio::time::sleep(Duration::from_millis(10)).await;
+
+                let replayed = Replayer::new(events)
+                    .with_speed(ReplaySpeed::Accelerated(100.0))
+                    .with_filter("{system.connection,xmpp}.**")
+                    .unwrap()
+                    .replay(replay_bus.as_ref())
+                    .await
+                    .unwrap();
+                assert_eq!(replayed, 3);
+                assert_eq!(
+                    wait_for_state(&replay_roster, &replay_messaging).await,
+                    recorded_state
+                );
+            })
+            .await;
+    }
+
+    // ── 19. Rule Plugin Hooks ────────────────────────────────────
+    // A rule's plugin-hook action runs the named plugin's hook
+
+    #[tokio::test]
+    async fn rule_plugin_hook_runs_the_plugin() {
+        let dir = TempDir::new().unwrap();
+        let db = setup_db(&dir).await;
+        let bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+
+        let messaging = Arc::new(MessageManager::new(db.clone(), bus.clone()));
+        let rules = Arc::new(RuleEngine::new(db.clone(), messaging, bus.clone()));
+        rules
+            .save_rule(Rule {
+                id: String::new(),
+                name: "page me".to_string(),
+                enabled: true,
+                trigger: RuleTrigger::Message {
+                    from: None,
+                    keyword: Some("urgent".to_string()),
+                },
+                actions: vec![RuleAction::PluginHook {
+                    plugin_id: "com.waddle.pager".to_string(),
+                    hook: "page".to_string(),
+                }],
+                cooldown_secs: 0,
+            })
+            .await
+            .unwrap();
+
+        let manifest = PluginManifest::from_toml_str(
+            r#"
+[plugin]
+id = "com.waddle.pager"
+name = "Pager"
+version = "1.0.0"
+description = "Pages on urgent messages"
+license = "MIT"
+
+[permissions]
+
+[hooks]
+rule_hook = true
+"#,
+        )
+        .unwrap();
+        // Publishes what it was asked to run as its own event.
+        let wasm = r#"
+            (module
+              (import "host-events" "publish-event" (func $publish_event (param i32 i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "plugin.com.waddle.pager.paged")
+              (func (export "plugin_init") (result i32)
+                i32.const 0)
+              (func (export "guest_alloc") (param i32) (result i32)
+                i32.const 1024)
+              (func (export "plugin_rule_hook") (param i32 i32) (result i32)
+                i32.const 0
+                i32.const 29
+                local.get 0
+                local.get 1
+                call $publish_event)
+              (func (export "plugin_shutdown")))
+        "#;
+        let mut runtime = PluginRuntime::new(PluginRuntimeConfig::default(), bus.clone(), db);
+        runtime
+            .load_plugin(manifest, wasm.as_bytes())
+            .await
+            .unwrap();
+        let runtime = Arc::new(tokio::sync::Mutex::new(runtime));
+
+        let mut paged = bus.subscribe("plugin.com.waddle.pager.paged").unwrap();
+        let local = tokio::task::LocalSet::new();
+        local.spawn_local(rules.run());
+        local.spawn_local(PluginRuntime::dispatch_events(runtime));
+        let event = local
+            .run_until(async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                bus.publish(make_xmpp_event(
+                    "xmpp.message.received",
+                    EventPayload::MessageReceived {
+                        message: make_chat_message(
+                            "msg-1",
+                            "bob@example.com",
+                            "alice@example.com",
+                            "urgent: call me",
+                        ),
+                    },
+                ))
+                .unwrap();
+
+                timeout(TIMEOUT, paged.recv())
+                    .await
+                    .expect("timed out waiting for the plugin hook")
+                    .unwrap()
+            })
+            .await;
+        let EventPayload::PluginCustomEvent { data, .. } = event.payload else {
+            panic!("unexpected event {event:?}");
+        };
+        assert_eq!(data["hook"], "page");
+        assert_eq!(data["data"]["from"], "bob@example.com");
+        assert_eq!(data["data"]["body"], "urgent: call me");
+    }
 }
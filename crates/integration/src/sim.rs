@@ -0,0 +1,354 @@
+//! `waddle-sim`: a scriptable stand-in for the XMPP server, working at the
+//! event level. It publishes the events the connection layer would publish
+//! for live messages and carbons, archives whatever it delivers, and answers
+//! MAM queries from that archive, so sync and deduplication can be tested
+//! deterministically without a real server.
+//!
+//! Faults are injected explicitly: a [`Script`] can repeat and reorder what
+//! it delivers, and [`MamFaults`] make archive pages overlap, arrive newest
+//! first or repeat their results.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::task::JoinHandle;
+use waddle_core::error::EventBusError;
+use waddle_core::event::{
+    Channel, ChatMessage, Event, EventBus, EventPayload, EventSource, channels,
+};
+use waddle_core::jid::bare_jid;
+use waddle_core::rsm::{RsmCursor, RsmQuery, RsmResult};
+
+#[derive(Debug, Clone)]
+enum Step {
+    Deliver(ChatMessage),
+    CarbonReceived(ChatMessage),
+    CarbonSent(ChatMessage),
+    /// Archived without being delivered, as if it arrived while offline.
+    ArchiveOnly(ChatMessage),
+}
+
+impl Step {
+    fn message(&self) -> &ChatMessage {
+        match self {
+            Self::Deliver(message)
+            | Self::CarbonReceived(message)
+            | Self::CarbonSent(message)
+            | Self::ArchiveOnly(message) => message,
+        }
+    }
+}
+
+/// What the server does, in order, when [`SimServer::play`] runs it.
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A message delivered live to this client.
+    pub fn deliver(mut self, message: ChatMessage) -> Self {
+        self.steps.push(Step::Deliver(message));
+        self
+    }
+
+    /// A XEP-0280 copy of a message another of our resources received. The
+    /// connection layer reports it as an ordinary received message.
+    pub fn carbon_received(mut self, message: ChatMessage) -> Self {
+        self.steps.push(Step::CarbonReceived(message));
+        self
+    }
+
+    /// A XEP-0280 copy of a message another of our resources sent, reported
+    /// as a sent message.
+    pub fn carbon_sent(mut self, message: ChatMessage) -> Self {
+        self.steps.push(Step::CarbonSent(message));
+        self
+    }
+
+    /// A message only MAM will return.
+    pub fn archive_only(mut self, message: ChatMessage) -> Self {
+        self.steps.push(Step::ArchiveOnly(message));
+        self
+    }
+
+    /// Repeat the previous step, as a server does when it resends a stanza
+    /// after a stream resumption.
+    pub fn duplicate(mut self) -> Self {
+        if let Some(last) = self.steps.last().cloned() {
+            self.steps.push(last);
+        }
+        self
+    }
+
+    /// Swap steps `a` and `b`. Indexes out of range are ignored.
+    pub fn swap(mut self, a: usize, b: usize) -> Self {
+        if a < self.steps.len() && b < self.steps.len() {
+            self.steps.swap(a, b);
+        }
+        self
+    }
+
+    /// Put the steps so far in an order chosen by `seed`; the same seed
+    /// always gives the same order.
+    pub fn shuffle(mut self, seed: u64) -> Self {
+        let mut state = seed | 1;
+        for i in (1..self.steps.len()).rev() {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            self.steps.swap(i, (state % (i as u64 + 1)) as usize);
+        }
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+/// Misbehaviour of the simulated archive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MamFaults {
+    /// Pages after the first also repeat this many messages from before the
+    /// cursor.
+    pub page_overlap: usize,
+    /// Each page's messages arrive newest first. The cursor stays correct.
+    pub reverse_pages: bool,
+    /// Each page's messages are sent twice.
+    pub duplicate_results: bool,
+}
+
+#[derive(Default)]
+struct SimState {
+    /// The user's archive, oldest first.
+    archive: Vec<ChatMessage>,
+    /// Room archives, oldest first.
+    rooms: BTreeMap<String, Vec<ChatMessage>>,
+    next_stanza_id: u64,
+    queries: Vec<MamQuery>,
+}
+
+/// A MAM query the server answered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MamQuery {
+    pub query_id: String,
+    pub with_jid: Option<String>,
+    pub page: RsmQuery,
+    pub archive: Option<String>,
+}
+
+pub struct SimServer {
+    event_bus: Arc<dyn EventBus>,
+    faults: MamFaults,
+    state: Mutex<SimState>,
+}
+
+impl SimServer {
+    pub fn new(event_bus: Arc<dyn EventBus>) -> Self {
+        Self {
+            event_bus,
+            faults: MamFaults::default(),
+            state: Mutex::new(SimState::default()),
+        }
+    }
+
+    pub fn with_mam_faults(mut self, faults: MamFaults) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    /// Add `message` to the archive of `room`.
+    pub fn archive_room_message(&self, room: &str, message: ChatMessage) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .rooms
+            .entry(room.to_string())
+            .or_default()
+            .push(message);
+    }
+
+    /// Everything in the user's archive, oldest first.
+    pub fn archive(&self) -> Vec<ChatMessage> {
+        self.state.lock().unwrap().archive.clone()
+    }
+
+    /// The MAM queries answered so far.
+    pub fn queries(&self) -> Vec<MamQuery> {
+        self.state.lock().unwrap().queries.clone()
+    }
+
+    /// Run `script`. Each message is archived the first time it is seen and
+    /// given a XEP-0359 stanza id unless it has one; every copy delivered
+    /// carries that id, as copies from a real server do.
+    pub fn play(&self, script: Script) -> Result<(), EventBusError> {
+        for step in script.steps {
+            let message = self.archive_message(step.message());
+            let (channel, payload) = match step {
+                Step::Deliver(_) | Step::CarbonReceived(_) => (
+                    channels::XMPP_MESSAGE_RECEIVED,
+                    EventPayload::MessageReceived { message },
+                ),
+                Step::CarbonSent(_) => (
+                    channels::XMPP_MESSAGE_SENT,
+                    EventPayload::MessageSent { message },
+                ),
+                Step::ArchiveOnly(_) => continue,
+            };
+            self.publish(channel, payload)?;
+        }
+        Ok(())
+    }
+
+    /// Answer MAM queries from the archive until the event bus closes.
+    pub fn serve_mam(self: &Arc<Self>) -> Result<JoinHandle<()>, EventBusError> {
+        // Subscribed before returning so no query published afterwards is
+        // missed.
+        let mut queries = self.event_bus.subscribe(channels::UI_MAM_QUERY)?;
+        let server = Arc::clone(self);
+        Ok(tokio::spawn(async move {
+            loop {
+                match queries.recv().await {
+                    Ok(event) => {
+                        if let EventPayload::MamQueryRequested {
+                            query_id,
+                            with_jid,
+                            page,
+                            archive,
+                        } = event.payload
+                        {
+                            let query = MamQuery {
+                                query_id,
+                                with_jid,
+                                page,
+                                archive,
+                            };
+                            if server.answer(query).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Err(EventBusError::ChannelClosed) => return,
+                    Err(_) => {}
+                }
+            }
+        }))
+    }
+
+    fn archive_message(&self, message: &ChatMessage) -> ChatMessage {
+        let mut state = self.state.lock().unwrap();
+        if let Some(archived) = state
+            .archive
+            .iter()
+            .find(|archived| archived.id == message.id)
+        {
+            return archived.clone();
+        }
+
+        let mut message = message.clone();
+        if message.stanza_id.is_none() {
+            state.next_stanza_id += 1;
+            message.stanza_id = Some(format!("sim-{}", state.next_stanza_id));
+        }
+        state.archive.push(message.clone());
+        message
+    }
+
+    fn answer(&self, query: MamQuery) -> Result<(), EventBusError> {
+        let (mut page, complete, count, first_index) = {
+            let mut state = self.state.lock().unwrap();
+            state.queries.push(query.clone());
+            let archive = match &query.archive {
+                Some(room) => state.rooms.get(room).cloned().unwrap_or_default(),
+                None => state.archive.clone(),
+            };
+            let matching: Vec<ChatMessage> = archive
+                .into_iter()
+                .filter(|message| match &query.with_jid {
+                    Some(with) => [&message.from, &message.to]
+                        .iter()
+                        .any(|jid| bare_jid(jid) == bare_jid(with)),
+                    None => true,
+                })
+                .collect();
+            let position = |id: &str| matching.iter().position(|message| message.id == id);
+
+            let max = query.page.max.unwrap_or(u32::MAX).max(1) as usize;
+            let (start, end) = match &query.page.cursor {
+                RsmCursor::Start => (0, max.min(matching.len())),
+                RsmCursor::After(after) => {
+                    let start = position(after).map_or(0, |index| index + 1);
+                    (start, (start + max).min(matching.len()))
+                }
+                RsmCursor::Before(before) => {
+                    let end = position(before).unwrap_or(matching.len());
+                    (end.saturating_sub(max), end)
+                }
+                RsmCursor::End => (matching.len().saturating_sub(max), matching.len()),
+            };
+            let complete = match query.page.cursor {
+                RsmCursor::Before(_) | RsmCursor::End => start == 0,
+                RsmCursor::Start | RsmCursor::After(_) => end == matching.len(),
+            };
+            let overlap = if matches!(query.page.cursor, RsmCursor::After(_)) {
+                self.faults.page_overlap
+            } else {
+                0
+            };
+            (
+                matching[start.saturating_sub(overlap)..end].to_vec(),
+                complete,
+                matching.len() as u64,
+                start as u64,
+            )
+        };
+
+        let first_id = page.first().map(|message| message.id.clone());
+        let last_id = page.last().map(|message| message.id.clone());
+        if self.faults.reverse_pages {
+            page.reverse();
+        }
+        if self.faults.duplicate_results {
+            page.extend(page.clone());
+        }
+
+        self.publish(
+            channels::XMPP_MAM_RESULT_RECEIVED,
+            EventPayload::MamResultReceived {
+                query_id: query.query_id.clone(),
+                messages: page,
+                complete: false,
+            },
+        )?;
+        self.publish(
+            channels::XMPP_MAM_FIN_RECEIVED,
+            EventPayload::MamFinReceived {
+                iq_id: query.query_id,
+                complete,
+                page: RsmResult {
+                    first: first_id,
+                    first_index: Some(first_index),
+                    last: last_id,
+                    count: Some(count),
+                },
+            },
+        )
+    }
+
+    fn publish(&self, channel: &str, payload: EventPayload) -> Result<(), EventBusError> {
+        self.event_bus.publish(Event::new(
+            Channel::new(channel).unwrap(),
+            EventSource::Xmpp,
+            payload,
+        ))
+    }
+}
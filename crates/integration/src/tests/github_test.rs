@@ -1,7 +1,6 @@
 #[cfg(all(test, feature = "native"))]
 mod tests {
     use std::sync::Arc;
-    use std::time::Duration;
     use tempfile::TempDir;
     use waddle_core::event::{
         BroadcastEventBus, Channel, ChatMessage, Event, EventBus, EventPayload, EventSource,
@@ -11,8 +10,6 @@ mod tests {
     use waddle_messaging::MessageManager;
     use waddle_storage::Database;
 
-    const TIMEOUT: Duration = Duration::from_millis(500);
-
     async fn setup_db(dir: &TempDir) -> Arc<impl Database + use<>> {
         let db_path = dir.path().join("test.db");
         let db = waddle_storage::open_database(&db_path)
@@ -31,6 +28,11 @@ mod tests {
             message_type: MessageType::Chat,
             thread: None,
             embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
         }
     }
 
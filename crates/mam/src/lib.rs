@@ -1,26 +1,50 @@
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
-use waddle_core::event::ChatMessage;
-use waddle_storage::{Database, FromRow, Row, SqlValue, StorageError};
+use waddle_core::event::{ArchiveGap, ChatMessage};
+use waddle_core::jid::Jid;
+use waddle_core::rsm::{RsmCursor, RsmQuery, RsmResult};
+use waddle_storage::{Database, FromRow, Query, Row, SqlValue, StorageError};
 
 #[cfg(feature = "native")]
+use waddle_core::shutdown::{Manager, ShutdownFuture};
+
 use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(feature = "native")]
+use futures::stream::{self, StreamExt};
 #[cfg(feature = "native")]
 use tracing::{debug, error, info, warn};
 
 #[cfg(feature = "native")]
 use waddle_core::event::{
     Channel, Event, EventBus, EventPayload, EventSource, EventSubscription, PresenceShow,
-    ScrollDirection,
+    ScrollDirection, SyncTask, channels,
 };
 
 const MAM_PAGE_SIZE: u32 = 50;
-#[cfg(feature = "native")]
 const MAM_QUERY_TIMEOUT_SECS: u64 = 30;
+const MAM_PAGE_RETRIES: u32 = 3;
+const MAM_RETRY_BACKOFF_MS: u64 = 1_000;
 const GLOBAL_SYNC_KEY: &str = "__global__";
+/// Number of recent conversations whose latest page is fetched alongside the
+/// global backfill after connecting.
+#[cfg(feature = "native")]
+const PREFETCH_CONVERSATIONS: usize = 10;
+/// Maximum number of MAM queries in flight during the backfill, the global
+/// catch-up included.
+#[cfg(feature = "native")]
+const PREFETCH_CONCURRENCY: usize = 4;
+/// Most archive messages the catch-up writer commits in one transaction.
+#[cfg(feature = "native")]
+const INGEST_BATCH_ROWS: usize = 500;
+/// Pages fetched ahead of the catch-up writer before fetching waits for it.
+#[cfg(feature = "native")]
+const INGEST_QUEUE_PAGES: usize = 20;
 
 #[derive(Debug, thiserror::Error)]
 pub enum MamError {
@@ -33,6 +57,9 @@ pub enum MamError {
     #[error("MAM query timed out after {0}s")]
     Timeout(u64),
 
+    #[error("the archive no longer holds the paging cursor")]
+    CursorNotFound,
+
     #[error("storage error: {0}")]
     Storage(#[from] StorageError),
 
@@ -40,12 +67,90 @@ pub enum MamError {
     EventBus(String),
 }
 
+/// How long to wait for a MAM page and how often to retry it before the
+/// sync is abandoned.
+#[derive(Debug, Clone)]
+pub struct MamRetryPolicy {
+    pub query_timeout: Duration,
+    /// Retries of a timed-out page after the first attempt.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each further retry.
+    pub initial_backoff: Duration,
+}
+
+impl Default for MamRetryPolicy {
+    fn default() -> Self {
+        Self {
+            query_timeout: Duration::from_secs(MAM_QUERY_TIMEOUT_SECS),
+            max_retries: MAM_PAGE_RETRIES,
+            initial_backoff: Duration::from_millis(MAM_RETRY_BACKOFF_MS),
+        }
+    }
+}
+
+impl MamRetryPolicy {
+    #[cfg(feature = "native")]
+    fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2_u32.saturating_pow(retry.saturating_sub(1)))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MamSyncResult {
     pub messages_synced: u64,
     pub complete: bool,
 }
 
+/// A page of older history for one conversation.
+#[derive(Debug, Clone)]
+pub struct MamHistoryPage {
+    /// Oldest first.
+    pub messages: Vec<ChatMessage>,
+    /// Archive id of the oldest message, to request the page before this one.
+    pub before: Option<String>,
+    /// `false` once the page reaches the start of the archive.
+    pub has_more: bool,
+}
+
+/// One page of archive results.
+#[cfg_attr(not(feature = "native"), allow(dead_code))]
+struct ArchivePage {
+    messages: Vec<ChatMessage>,
+    /// The server reported the end of the result set.
+    complete: bool,
+    /// Where the page sits in the full result set. `last` falls back to the
+    /// last message received when the server leaves it out.
+    page: RsmResult,
+    /// Where the page was requested from.
+    cursor: RsmCursor,
+    /// Results may have been dropped while the page was collected.
+    lossy: bool,
+}
+
+impl ArchivePage {
+    /// How many messages a sync starting with this page will read. Without
+    /// the page's position only a sync from the start of the archive can
+    /// tell.
+    #[cfg(feature = "native")]
+    fn remaining(&self, resumed: bool) -> Option<u64> {
+        match self.page.remaining() {
+            Some(remaining) => Some(remaining),
+            None if !resumed && self.page.first_index.is_none() => self.page.count,
+            None => None,
+        }
+    }
+}
+
+/// What the catch-up writer has committed so far.
+#[cfg_attr(not(feature = "native"), allow(dead_code))]
+struct IngestProgress {
+    done: u64,
+    /// Archive id of the last committed page; the next sync resumes here.
+    cursor: Option<String>,
+    estimated_total: Option<u64>,
+}
+
 struct SyncState {
     last_stanza_id: String,
 }
@@ -64,13 +169,72 @@ impl FromRow for SyncState {
     }
 }
 
-fn message_type_to_str(mt: &waddle_core::event::MessageType) -> &'static str {
-    match mt {
-        waddle_core::event::MessageType::Chat => "chat",
-        waddle_core::event::MessageType::Groupchat => "groupchat",
-        waddle_core::event::MessageType::Normal => "normal",
-        waddle_core::event::MessageType::Headline => "headline",
-        waddle_core::event::MessageType::Error => "error",
+/// A span of an archive stored locally without holes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveRange {
+    pub first_id: String,
+    pub first_at: DateTime<Utc>,
+    pub last_id: String,
+    pub last_at: DateTime<Utc>,
+}
+
+impl ArchiveRange {
+    /// The span of a page of results, which may arrive in any order.
+    fn spanning(messages: &[ChatMessage]) -> Option<Self> {
+        let first = messages.iter().min_by_key(|msg| msg.timestamp)?;
+        let last = messages.iter().max_by_key(|msg| msg.timestamp)?;
+        Some(Self {
+            first_id: first.id.clone(),
+            first_at: first.timestamp,
+            last_id: last.id.clone(),
+            last_at: last.timestamp,
+        })
+    }
+
+    /// Whether a page requested from `cursor` picks up where this range
+    /// stops.
+    fn continued_by(&self, cursor: &RsmCursor) -> bool {
+        match cursor {
+            RsmCursor::After(id) => *id == self.last_id,
+            RsmCursor::Before(id) => *id == self.first_id,
+            RsmCursor::Start | RsmCursor::End => false,
+        }
+    }
+
+    fn overlaps(&self, other: &Self) -> bool {
+        self.first_at <= other.last_at && other.first_at <= self.last_at
+    }
+
+    fn union(mut self, other: &Self) -> Self {
+        if other.first_at < self.first_at {
+            self.first_id.clone_from(&other.first_id);
+            self.first_at = other.first_at;
+        }
+        if other.last_at > self.last_at {
+            self.last_id.clone_from(&other.last_id);
+            self.last_at = other.last_at;
+        }
+        self
+    }
+}
+
+impl FromRow for ArchiveRange {
+    fn from_row(row: &Row) -> Result<Self, StorageError> {
+        let text = |index: usize, name: &str| match row.get(index) {
+            Some(SqlValue::Text(value)) => Ok(value.clone()),
+            _ => Err(StorageError::QueryFailed(format!("missing {name} column"))),
+        };
+        let time = |index: usize, name: &str| {
+            DateTime::parse_from_rfc3339(&text(index, name)?)
+                .map(|at| at.with_timezone(&Utc))
+                .map_err(|e| StorageError::QueryFailed(format!("invalid {name}: {e}")))
+        };
+        Ok(ArchiveRange {
+            first_id: text(0, "first_id")?,
+            first_at: time(1, "first_at")?,
+            last_id: text(2, "last_id")?,
+            last_at: time(3, "last_at")?,
+        })
     }
 }
 
@@ -78,15 +242,29 @@ fn sync_key(jid: &str) -> String {
     if jid.is_empty() {
         GLOBAL_SYNC_KEY.to_string()
     } else {
-        jid.to_string()
+        Jid::new(jid).into_string()
     }
 }
 
 pub struct MamManager<D: Database> {
     db: Arc<D>,
+    retry_policy: RwLock<MamRetryPolicy>,
+    /// Conversations whose archive has been paged back to its first message.
+    archive_start_reached: RwLock<HashSet<String>>,
+    /// Set on shutdown; syncs stop before requesting another page.
+    shutting_down: AtomicBool,
+    /// Held for reading by each sync so shutdown can wait for the page in
+    /// flight and its cursor to be stored.
+    #[cfg(feature = "native")]
+    sync_gate: tokio::sync::RwLock<()>,
     #[cfg(feature = "native")]
     startup_sync_pending: AtomicBool,
     #[cfg(feature = "native")]
+    own_jid: RwLock<Option<String>>,
+    /// Bare JID of the conversation open in the UI, backfilled first.
+    #[cfg(feature = "native")]
+    open_conversation: RwLock<Option<String>>,
+    #[cfg(feature = "native")]
     event_bus: Arc<dyn EventBus>,
 }
 
@@ -95,11 +273,21 @@ impl<D: Database> MamManager<D> {
     pub fn new(db: Arc<D>, event_bus: Arc<dyn EventBus>) -> Self {
         Self {
             db,
+            retry_policy: RwLock::new(MamRetryPolicy::default()),
+            archive_start_reached: RwLock::new(HashSet::new()),
+            shutting_down: AtomicBool::new(false),
+            sync_gate: tokio::sync::RwLock::new(()),
             startup_sync_pending: AtomicBool::new(false),
+            own_jid: RwLock::new(None),
+            open_conversation: RwLock::new(None),
             event_bus,
         }
     }
 
+    pub fn set_retry_policy(&self, policy: MamRetryPolicy) {
+        *self.retry_policy.write().unwrap() = policy;
+    }
+
     pub async fn sync_since(&self, _timestamp: DateTime<Utc>) -> Result<MamSyncResult, MamError> {
         if !self.is_supported().await {
             return Ok(MamSyncResult {
@@ -108,39 +296,375 @@ impl<D: Database> MamManager<D> {
             });
         }
 
-        let last_stanza_id = self.get_last_stanza_id("").await?;
+        #[cfg(feature = "native")]
+        let _sync = self.sync_gate.read().await;
+        let cursor = self.get_last_stanza_id("").await?;
 
         let correlation_id = Uuid::new_v4();
 
         self.emit_sync_started(correlation_id)?;
 
-        let mut total_synced: u64 = 0;
-        let mut complete = false;
-        let mut after = last_stanza_id;
+        let (fetched, written) = self.catch_up(cursor, correlation_id).await;
+        let progress = written?;
+        let complete = match fetched {
+            Ok(complete) => complete,
+            Err(e) => {
+                self.emit_sync_failed(
+                    progress.done,
+                    progress.cursor.as_deref(),
+                    &e,
+                    correlation_id,
+                );
+                return Err(e);
+            }
+        };
 
-        while !complete {
-            let query_id = Uuid::new_v4().to_string();
-            let (messages, fin_complete, last_id) = self
-                .query_page(&query_id, None, after.as_deref(), None, MAM_PAGE_SIZE)
-                .await?;
+        if complete {
+            self.emit_sync_completed(progress.done, correlation_id)?;
+        }
 
-            let page_count = messages.len() as u64;
+        Ok(MamSyncResult {
+            messages_synced: progress.done,
+            complete,
+        })
+    }
+
+    /// Page through the user's archive after `cursor` while a writer commits
+    /// the fetched pages in batches, so a long catch-up is not held up by a
+    /// transaction per page. Returns whether the archive was read to its
+    /// end, and what the writer stored.
+    #[cfg(feature = "native")]
+    async fn catch_up(
+        &self,
+        cursor: Option<String>,
+        correlation_id: Uuid,
+    ) -> (Result<bool, MamError>, Result<IngestProgress, MamError>) {
+        let (pages_tx, pages_rx) = tokio::sync::mpsc::channel(INGEST_QUEUE_PAGES);
+        futures::join!(
+            self.fetch_archive_pages(cursor.clone(), pages_tx),
+            self.write_archive_pages(pages_rx, cursor, correlation_id),
+        )
+    }
+
+    #[cfg(not(feature = "native"))]
+    async fn catch_up(
+        &self,
+        cursor: Option<String>,
+        _correlation_id: Uuid,
+    ) -> (Result<bool, MamError>, Result<IngestProgress, MamError>) {
+        let progress = IngestProgress {
+            done: 0,
+            cursor,
+            estimated_total: None,
+        };
+        (Err(MamError::NotSupported), Ok(progress))
+    }
+
+    /// Queue archive pages after `after` for the writer until the archive is
+    /// exhausted, a page fails or shutdown begins. Fetching pauses while the
+    /// queue is full.
+    #[cfg(feature = "native")]
+    async fn fetch_archive_pages(
+        &self,
+        after: Option<String>,
+        pages: tokio::sync::mpsc::Sender<ArchivePage>,
+    ) -> Result<bool, MamError> {
+        let mut query = RsmQuery::after_or_first(after.as_deref(), MAM_PAGE_SIZE);
+        let mut restarted = false;
+        loop {
+            if self.shutting_down.load(Ordering::Relaxed) {
+                return Ok(false);
+            }
+
+            let page = match self.query_page_with_retry(None, None, &query).await {
+                // The server expired everything up to our cursor; carry on
+                // from the oldest message it still keeps. The messages in
+                // between are reported as a gap.
+                Err(MamError::CursorNotFound) if !restarted => {
+                    warn!("MAM cursor expired from the archive, syncing from its start");
+                    restarted = true;
+                    query = RsmQuery::first(MAM_PAGE_SIZE);
+                    continue;
+                }
+                result => result?,
+            };
+            let complete = page.complete || page.messages.is_empty();
+            if let Some(next) = page.page.next(MAM_PAGE_SIZE) {
+                query = next;
+            }
+
+            // The writer only hangs up after a storage error, which it
+            // reports itself.
+            if pages.send(page).await.is_err() {
+                return Ok(false);
+            }
+            if complete {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Commit queued pages until the fetcher is done. Whatever has queued up
+    /// while the previous batch was written goes into the next transaction,
+    /// up to [`INGEST_BATCH_ROWS`] messages, and the cursor only moves past
+    /// messages once they are stored.
+    #[cfg(feature = "native")]
+    async fn write_archive_pages(
+        &self,
+        mut pages: tokio::sync::mpsc::Receiver<ArchivePage>,
+        cursor: Option<String>,
+        correlation_id: Uuid,
+    ) -> Result<IngestProgress, MamError> {
+        let mut progress = IngestProgress {
+            done: 0,
+            estimated_total: None,
+            cursor,
+        };
+        let resumed = progress.cursor.is_some();
+        let mut first_page = true;
+
+        while let Some(mut page) = pages.recv().await {
+            if first_page {
+                progress.estimated_total = page.remaining(resumed);
+                first_page = false;
+            }
+
+            let mut batch = Vec::new();
+            let mut batch_cursor = None;
+            let mut spans = Vec::new();
+            loop {
+                if !page.lossy
+                    && let Some(span) = ArchiveRange::spanning(&page.messages)
+                {
+                    spans.push((page.cursor, span));
+                }
+                batch.append(&mut page.messages);
+                if page.page.last.is_some() {
+                    batch_cursor = page.page.last;
+                }
+                if batch.len() >= INGEST_BATCH_ROWS {
+                    break;
+                }
+                match pages.try_recv() {
+                    Ok(next) => page = next,
+                    Err(_) => break,
+                }
+            }
+
+            self.persist_page(&batch).await?;
+            for (cursor, span) in spans {
+                self.record_range("", &cursor, span).await?;
+            }
+            progress.done += batch.len() as u64;
+            if let Some(id) = batch_cursor {
+                self.update_sync_state("", &id).await?;
+                progress.cursor = Some(id);
+            }
+            if !batch.is_empty() {
+                debug!(
+                    done = progress.done,
+                    estimated_total = ?progress.estimated_total,
+                    "MAM catch-up batch committed"
+                );
+                self.emit_sync_progress(&progress, correlation_id);
+            }
+        }
+
+        Ok(progress)
+    }
+
+    pub async fn fetch_history(
+        &self,
+        jid: &str,
+        page: RsmQuery,
+    ) -> Result<Vec<ChatMessage>, MamError> {
+        Ok(self.fetch_history_page(jid, page).await?.messages)
+    }
+
+    /// Fetch and store one page of `jid`'s history. Rooms are paged through
+    /// their own archive, other conversations through the user's archive
+    /// filtered by `jid`. Pages hold at most [`MAM_PAGE_SIZE`] messages.
+    pub async fn fetch_history_page(
+        &self,
+        jid: &str,
+        mut page: RsmQuery,
+    ) -> Result<MamHistoryPage, MamError> {
+        if !self.is_supported().await {
+            return Ok(MamHistoryPage {
+                messages: Vec::new(),
+                before: None,
+                has_more: false,
+            });
+        }
+
+        let query_id = Uuid::new_v4().to_string();
+        page.max = Some(page.max.unwrap_or(MAM_PAGE_SIZE).clamp(1, MAM_PAGE_SIZE));
+        let is_room = self.is_room(jid).await?;
+
+        let ArchivePage {
+            messages,
+            complete,
+            cursor,
+            lossy,
+            ..
+        } = if is_room {
+            self.query_page(&query_id, None, &page, Some(jid)).await?
+        } else {
+            self.query_page(&query_id, Some(jid), &page, None).await?
+        };
 
-            for msg in &messages {
-                self.persist_message(msg).await?;
+        if is_room {
+            self.persist_room_page(jid, &messages).await?;
+        } else {
+            self.persist_page(&messages).await?;
+        }
+        if !lossy && let Some(span) = ArchiveRange::spanning(&messages) {
+            self.record_range(jid, &cursor, span).await?;
+        }
+
+        Ok(MamHistoryPage {
+            before: messages.first().map(|msg| msg.id.clone()),
+            has_more: !complete && !messages.is_empty(),
+            messages,
+        })
+    }
+
+    /// Whether paging back through `jid`'s history has reached the start of
+    /// its archive during this session.
+    pub fn reached_archive_start(&self, jid: &str) -> bool {
+        self.archive_start_reached.read().unwrap().contains(jid)
+    }
+
+    /// Sync a room's own archive (XEP-0313 served by the MUC service) into
+    /// the local message store so its scrollback is available offline. Each
+    /// room keeps its own cursor. The first sync only fetches the latest
+    /// page; later syncs page forward from the cursor.
+    pub async fn sync_room(&self, room: &str) -> Result<MamSyncResult, MamError> {
+        if !self.is_supported().await {
+            return Ok(MamSyncResult {
+                messages_synced: 0,
+                complete: true,
+            });
+        }
+
+        #[cfg(feature = "native")]
+        let _sync = self.sync_gate.read().await;
+        let cursor = self.get_last_stanza_id(room).await?;
+        let mut first_sync = cursor.is_none();
+        let mut total_synced: u64 = 0;
+        let mut query = match cursor {
+            Some(id) => RsmQuery::after(id, MAM_PAGE_SIZE),
+            None => RsmQuery::last(MAM_PAGE_SIZE),
+        };
+
+        loop {
+            if self.shutting_down.load(Ordering::Relaxed) {
+                return Ok(MamSyncResult {
+                    messages_synced: total_synced,
+                    complete: false,
+                });
             }
 
+            let ArchivePage {
+                messages,
+                complete: fin_complete,
+                page,
+                cursor,
+                lossy,
+            } = match self.query_page_with_retry(None, Some(room), &query).await {
+                // The room expired everything up to our cursor; start over
+                // from its latest page as on a first sync.
+                Err(MamError::CursorNotFound) if !first_sync => {
+                    warn!(room = %room, "MAM cursor expired from the room archive");
+                    first_sync = true;
+                    query = RsmQuery::last(MAM_PAGE_SIZE);
+                    continue;
+                }
+                result => result?,
+            };
+
+            let page_count = messages.len() as u64;
+            self.persist_room_page(room, &messages).await?;
+            if !lossy && let Some(span) = ArchiveRange::spanning(&messages) {
+                self.record_range(room, &cursor, span).await?;
+            }
             total_synced += page_count;
 
-            if let Some(ref id) = last_id {
-                self.update_sync_state("", id).await?;
-                after = Some(id.clone());
+            if let Some(ref id) = page.last {
+                self.update_sync_state(room, id).await?;
+                query = RsmQuery::after(id, MAM_PAGE_SIZE);
             }
 
-            complete = fin_complete || page_count == 0;
+            if first_sync || fin_complete || page_count == 0 {
+                break;
+            }
         }
 
-        self.emit_sync_completed(total_synced, correlation_id)?;
+        Ok(MamSyncResult {
+            messages_synced: total_synced,
+            complete: true,
+        })
+    }
+
+    /// Fetch the messages missing from `gap` in `jid`'s archive (the user's
+    /// own when `jid` is empty), paging forward from its start until the
+    /// stored range after it is reached.
+    pub async fn fill_gap(&self, jid: &str, gap: &ArchiveGap) -> Result<MamSyncResult, MamError> {
+        if !self.is_supported().await {
+            return Ok(MamSyncResult {
+                messages_synced: 0,
+                complete: true,
+            });
+        }
+
+        #[cfg(feature = "native")]
+        let _sync = self.sync_gate.read().await;
+        let is_room = !jid.is_empty() && self.is_room(jid).await?;
+        let (with_jid, archive) = match (jid.is_empty(), is_room) {
+            (true, _) => (None, None),
+            (false, true) => (None, Some(jid)),
+            (false, false) => (Some(jid), None),
+        };
+        let mut query = RsmQuery::after(&gap.after_id, MAM_PAGE_SIZE);
+        let mut total_synced: u64 = 0;
+
+        loop {
+            if self.shutting_down.load(Ordering::Relaxed) {
+                return Ok(MamSyncResult {
+                    messages_synced: total_synced,
+                    complete: false,
+                });
+            }
+
+            let ArchivePage {
+                messages,
+                complete,
+                page,
+                cursor,
+                lossy,
+            } = self
+                .query_page_with_retry(with_jid, archive, &query)
+                .await?;
+
+            if is_room {
+                self.persist_room_page(jid, &messages).await?;
+            } else {
+                self.persist_page(&messages).await?;
+            }
+            total_synced += messages.len() as u64;
+
+            let closed = messages
+                .iter()
+                .any(|msg| msg.id == gap.before_id || msg.timestamp >= gap.before_at);
+            if !lossy && let Some(span) = ArchiveRange::spanning(&messages) {
+                self.record_range(jid, &cursor, span).await?;
+            }
+
+            match page.next(MAM_PAGE_SIZE) {
+                Some(next) if !closed && !complete && !messages.is_empty() => query = next,
+                _ => break,
+            }
+        }
 
         Ok(MamSyncResult {
             messages_synced: total_synced,
@@ -148,79 +672,316 @@ impl<D: Database> MamManager<D> {
         })
     }
 
-    pub async fn fetch_history(
+    /// Catch up on the archive after connecting, most visible history first.
+    /// The open conversation's latest page is fetched before anything else;
+    /// then the latest pages of the `recent` most recent conversations are
+    /// fetched while the global catch-up runs, with at most `concurrency`
+    /// queries in flight. Each part is reported with `SyncTaskStarted` and
+    /// `SyncTaskFinished` events sharing one correlation id. Returns the
+    /// number of messages fetched.
+    #[cfg(feature = "native")]
+    pub async fn backfill(&self, recent: usize, concurrency: usize) -> u64 {
+        if !self.is_supported().await {
+            return 0;
+        }
+
+        let correlation_id = Uuid::new_v4();
+        let open = self.open_conversation.read().unwrap().clone();
+        let mut fetched = 0;
+
+        if let Some(jid) = &open {
+            let task = SyncTask::OpenConversation { jid: jid.clone() };
+            fetched += self.latest_page_task(task, jid, correlation_id).await;
+        }
+
+        let jids = match self.recent_conversation_jids(recent).await {
+            Ok(jids) => jids,
+            Err(e) => {
+                error!(error = %e, "failed to list recent conversations");
+                Vec::new()
+            }
+        };
+        // The global catch-up holds one of the query slots.
+        let recent = stream::iter(jids.into_iter().filter(|jid| open.as_ref() != Some(jid)))
+            .map(|jid| async move {
+                let task = SyncTask::RecentConversation { jid: jid.clone() };
+                self.latest_page_task(task, &jid, correlation_id).await
+            })
+            .buffer_unordered(concurrency.saturating_sub(1).max(1))
+            .fold(0, |total, count| async move { total + count });
+
+        let (recent, archive) = futures::join!(recent, self.archive_task(correlation_id));
+        fetched + recent + archive
+    }
+
+    /// Fetch the latest page of `jid`'s history as one backfill task.
+    #[cfg(feature = "native")]
+    async fn latest_page_task(&self, task: SyncTask, jid: &str, correlation_id: Uuid) -> u64 {
+        self.emit_sync_task_started(&task, correlation_id);
+        match self.fetch_history(jid, RsmQuery::last(MAM_PAGE_SIZE)).await {
+            Ok(messages) => {
+                let fetched = messages.len() as u64;
+                self.emit_sync_task_finished(task, fetched, None, correlation_id);
+                fetched
+            }
+            Err(e) => {
+                warn!(error = %e, jid = %jid, "MAM backfill failed for conversation");
+                self.emit_sync_task_finished(task, 0, Some(&e), correlation_id);
+                0
+            }
+        }
+    }
+
+    /// Run the global catch-up as one backfill task.
+    #[cfg(feature = "native")]
+    async fn archive_task(&self, correlation_id: Uuid) -> u64 {
+        self.emit_sync_task_started(&SyncTask::Archive, correlation_id);
+        info!("starting MAM catch-up sync");
+        let fetched = match self.sync_since(Utc::now()).await {
+            Ok(result) => {
+                info!(
+                    messages_synced = result.messages_synced,
+                    "MAM catch-up sync complete"
+                );
+                self.emit_sync_task_finished(
+                    SyncTask::Archive,
+                    result.messages_synced,
+                    None,
+                    correlation_id,
+                );
+                result.messages_synced
+            }
+            Err(e) => {
+                if matches!(e, MamError::Timeout(_)) {
+                    warn!("MAM catch-up sync timed out after retries, will resume on next sync");
+                } else {
+                    error!(error = %e, "MAM catch-up sync failed");
+                }
+                self.emit_sync_task_finished(SyncTask::Archive, 0, Some(&e), correlation_id);
+                0
+            }
+        };
+        self.report_gaps("").await;
+        fetched
+    }
+
+    /// Fetch the latest archive page for the `limit` most recent
+    /// conversations, running at most `concurrency` queries at once. Failures for individual
+    /// conversations are logged and skipped. Returns the number of messages
+    /// fetched.
+    #[cfg(feature = "native")]
+    pub async fn prefetch_recent_conversations(
         &self,
-        jid: &str,
-        before: Option<&str>,
-        limit: u32,
-    ) -> Result<Vec<ChatMessage>, MamError> {
+        limit: usize,
+        concurrency: usize,
+    ) -> Result<u64, MamError> {
         if !self.is_supported().await {
-            return Ok(Vec::new());
+            return Ok(0);
         }
 
-        let query_id = Uuid::new_v4().to_string();
-        let page_size = limit.clamp(1, MAM_PAGE_SIZE);
+        let jids = self.recent_conversation_jids(limit).await?;
+        if jids.is_empty() {
+            return Ok(0);
+        }
+
+        debug!(
+            count = jids.len(),
+            concurrency, "prefetching recent conversations"
+        );
+
+        let fetched: u64 = stream::iter(jids)
+            .map(|jid| async move {
+                match self
+                    .fetch_history(&jid, RsmQuery::last(MAM_PAGE_SIZE))
+                    .await
+                {
+                    Ok(messages) => messages.len() as u64,
+                    Err(e) => {
+                        warn!(error = %e, jid = %jid, "MAM prefetch failed for conversation");
+                        0
+                    }
+                }
+            })
+            .buffer_unordered(concurrency.max(1))
+            .fold(0, |total, count| async move { total + count })
+            .await;
+
+        Ok(fetched)
+    }
 
-        let (messages, _complete, _last_id) = self
-            .query_page(&query_id, Some(jid), None, before, page_size)
+    /// Bare JIDs of the most recently active conversations, newest first.
+    /// Conversations with local history come first; roster contacts fill the
+    /// remainder so a fresh install still has something to prefetch.
+    #[cfg(feature = "native")]
+    async fn recent_conversation_jids(&self, limit: usize) -> Result<Vec<String>, MamError> {
+        let own_jid = self.own_jid.read().unwrap().clone().unwrap_or_default();
+        let limit_i = limit as i64;
+
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT jid FROM ( \
+                     SELECT from_jid AS jid, timestamp FROM messages WHERE message_type = 'chat' \
+                     UNION ALL \
+                     SELECT to_jid AS jid, timestamp FROM messages WHERE message_type = 'chat' \
+                 ) WHERE jid != ?1 AND jid != '' \
+                 GROUP BY jid \
+                 ORDER BY MAX(timestamp) DESC \
+                 LIMIT ?2",
+                &[&own_jid, &limit_i],
+            )
             .await?;
 
-        for msg in &messages {
-            self.persist_message(msg).await?;
+        let mut jids: Vec<String> = rows
+            .iter()
+            .filter_map(|row| match row.get(0) {
+                Some(SqlValue::Text(jid)) => Some(jid.clone()),
+                _ => None,
+            })
+            .collect();
+
+        if jids.len() < limit {
+            let roster_rows: Vec<Row> = self
+                .db
+                .query("SELECT jid FROM roster ORDER BY jid", &[])
+                .await?;
+            for row in &roster_rows {
+                if jids.len() >= limit {
+                    break;
+                }
+                if let Some(SqlValue::Text(jid)) = row.get(0)
+                    && *jid != own_jid
+                    && !jids.contains(jid)
+                {
+                    jids.push(jid.clone());
+                }
+            }
         }
 
-        Ok(messages)
+        Ok(jids)
     }
 
     pub async fn is_supported(&self) -> bool {
         cfg!(feature = "native")
     }
 
-    async fn get_last_stanza_id(&self, jid: &str) -> Result<Option<String>, MamError> {
-        let jid_s = sync_key(jid);
-
-        let rows: Vec<SyncState> = self
+    async fn is_room(&self, jid: &str) -> Result<bool, MamError> {
+        let jid_s = Jid::new(jid);
+        let rows: Vec<Row> = self
             .db
-            .query(
-                "SELECT last_stanza_id FROM mam_sync_state WHERE jid = ?1",
-                &[&jid_s],
-            )
+            .query("SELECT 1 FROM muc_rooms WHERE room_jid = ?1", &[&jid_s])
             .await?;
+        Ok(!rows.is_empty())
+    }
 
-        Ok(rows.into_iter().next().map(|s| s.last_stanza_id))
+    async fn get_last_stanza_id(&self, jid: &str) -> Result<Option<String>, MamError> {
+        let state: Option<SyncState> =
+            Query::new("SELECT last_stanza_id FROM mam_sync_state WHERE jid = :jid")
+                .bind("jid", &sync_key(jid))
+                .fetch_optional(self.db.as_ref())
+                .await?;
+
+        Ok(state.map(|s| s.last_stanza_id))
     }
 
     async fn update_sync_state(&self, jid: &str, stanza_id: &str) -> Result<(), MamError> {
-        let jid_s = sync_key(jid);
-        let stanza_id_s = stanza_id.to_string();
-        let now = Utc::now().to_rfc3339();
+        Query::new(
+            "INSERT OR REPLACE INTO mam_sync_state (jid, last_stanza_id, last_sync_at) \
+             VALUES (:jid, :stanza_id, :synced_at)",
+        )
+        .bind("jid", &sync_key(jid))
+        .bind("stanza_id", stanza_id)
+        .bind("synced_at", &Utc::now().to_rfc3339())
+        .execute(self.db.as_ref())
+        .await?;
+
+        Ok(())
+    }
+
+    /// The spans of `jid`'s archive stored without holes, oldest first. An
+    /// empty `jid` is the user's own archive.
+    pub async fn synced_ranges(&self, jid: &str) -> Result<Vec<ArchiveRange>, MamError> {
+        Ok(Query::new(
+            "SELECT first_id, first_at, last_id, last_at FROM mam_sync_ranges \
+             WHERE jid = :jid ORDER BY first_at",
+        )
+        .bind("jid", &sync_key(jid))
+        .fetch_all(self.db.as_ref())
+        .await?)
+    }
 
+    /// The stretches of `jid`'s archive missing between its synced ranges,
+    /// oldest first.
+    pub async fn gaps(&self, jid: &str) -> Result<Vec<ArchiveGap>, MamError> {
+        let ranges = self.synced_ranges(jid).await?;
+        Ok(ranges
+            .windows(2)
+            .map(|pair| ArchiveGap {
+                after_id: pair[0].last_id.clone(),
+                after_at: pair[0].last_at,
+                before_id: pair[1].first_id.clone(),
+                before_at: pair[1].first_at,
+            })
+            .collect())
+    }
+
+    /// Note that `span` of `jid`'s archive, requested from `cursor`, is
+    /// stored. It joins the range it continues and those it overlaps, or
+    /// starts a range of its own.
+    async fn record_range(
+        &self,
+        jid: &str,
+        cursor: &RsmCursor,
+        span: ArchiveRange,
+    ) -> Result<(), MamError> {
+        let joined: Vec<ArchiveRange> = self
+            .synced_ranges(jid)
+            .await?
+            .into_iter()
+            .filter(|range| range.continued_by(cursor) || range.overlaps(&span))
+            .collect();
+        let merged = joined.iter().fold(span, ArchiveRange::union);
+
+        let key = sync_key(jid);
         self.db
-            .execute(
-                "INSERT OR REPLACE INTO mam_sync_state (jid, last_stanza_id, last_sync_at) \
-                 VALUES (?1, ?2, ?3)",
-                &[&jid_s, &stanza_id_s, &now],
-            )
+            .transaction(|tx| {
+                for range in &joined {
+                    tx.execute(
+                        "DELETE FROM mam_sync_ranges WHERE jid = ?1 AND first_id = ?2",
+                        &[&key, &range.first_id],
+                    );
+                }
+                tx.execute(
+                    "INSERT OR REPLACE INTO mam_sync_ranges \
+                     (jid, first_id, first_at, last_id, last_at) \
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    &[
+                        &key,
+                        &merged.first_id,
+                        &merged.first_at.to_rfc3339(),
+                        &merged.last_id,
+                        &merged.last_at.to_rfc3339(),
+                    ],
+                );
+                Ok(())
+            })
             .await?;
-
         Ok(())
     }
 
     #[cfg(feature = "native")]
     async fn oldest_local_message_id(&self, jid: &str) -> Result<Option<String>, MamError> {
-        let jid_s = jid.to_string();
-        let rows: Vec<Row> = self
-            .db
-            .query(
-                "SELECT id FROM messages \
-                 WHERE from_jid = ?1 OR to_jid = ?1 \
-                 ORDER BY timestamp ASC \
-                 LIMIT 1",
-                &[&jid_s],
-            )
-            .await?;
-
-        let Some(row) = rows.first() else {
+        let row: Option<Row> = Query::new(
+            "SELECT id FROM messages \
+             WHERE from_jid = :jid OR to_jid = :jid \
+             ORDER BY timestamp ASC \
+             LIMIT 1",
+        )
+        .bind("jid", &Jid::new(jid))
+        .fetch_optional(self.db.as_ref())
+        .await?;
+
+        let Some(row) = row else {
             return Ok(None);
         };
 
@@ -233,25 +994,71 @@ impl<D: Database> MamManager<D> {
         }
     }
 
-    async fn persist_message(&self, message: &ChatMessage) -> Result<(), MamError> {
-        let id = message.id.clone();
-        let from = message.from.clone();
-        let to = message.to.clone();
-        let body = message.body.clone();
-        let ts = message.timestamp.to_rfc3339();
-        let mt = message_type_to_str(&message.message_type).to_string();
-        let thread = message.thread.clone();
-        let read = 0_i64;
+    /// Store a page of results in one transaction.
+    async fn persist_page(&self, messages: &[ChatMessage]) -> Result<(), MamError> {
+        waddle_storage::store_messages(self.db.as_ref(), messages).await?;
+        Ok(())
+    }
 
-        self.db
-            .execute(
-                "INSERT OR IGNORE INTO messages (id, from_jid, to_jid, body, timestamp, message_type, thread, read) \
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-                &[&id, &from, &to, &body, &ts, &mt, &thread, &read],
-            )
-            .await?;
+    /// Room archives store messages as the room reflected them; they are
+    /// kept alongside live groupchat messages, addressed to the room.
+    async fn persist_room_page(
+        &self,
+        room: &str,
+        messages: &[ChatMessage],
+    ) -> Result<(), MamError> {
+        let normalized: Vec<ChatMessage> = messages
+            .iter()
+            .map(|message| ChatMessage {
+                to: room.to_string(),
+                message_type: waddle_core::event::MessageType::Groupchat,
+                ..message.clone()
+            })
+            .collect();
+        self.persist_page(&normalized).await
+    }
 
-        Ok(())
+    /// Query one page of an archive (the user's own when `archive` is
+    /// `None`), retrying timeouts with exponential backoff. Each attempt
+    /// uses a fresh query id so late results from an abandoned attempt are
+    /// ignored.
+    #[cfg(feature = "native")]
+    async fn query_page_with_retry(
+        &self,
+        with_jid: Option<&str>,
+        archive: Option<&str>,
+        page: &RsmQuery,
+    ) -> Result<ArchivePage, MamError> {
+        let policy = self.retry_policy.read().unwrap().clone();
+        let mut retry = 0;
+        loop {
+            let query_id = Uuid::new_v4().to_string();
+            match self.query_page(&query_id, with_jid, page, archive).await {
+                Err(MamError::Timeout(secs)) if retry < policy.max_retries => {
+                    retry += 1;
+                    let backoff = policy.backoff(retry);
+                    warn!(
+                        retry,
+                        timeout_secs = secs,
+                        backoff_ms = backoff.as_millis() as u64,
+                        "MAM page timed out, retrying"
+                    );
+                    tokio::time::sleep(backoff).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    #[cfg(not(feature = "native"))]
+    async fn query_page_with_retry(
+        &self,
+        with_jid: Option<&str>,
+        archive: Option<&str>,
+        page: &RsmQuery,
+    ) -> Result<ArchivePage, MamError> {
+        let query_id = Uuid::new_v4().to_string();
+        self.query_page(&query_id, with_jid, page, archive).await
     }
 
     #[cfg(feature = "native")]
@@ -259,10 +1066,9 @@ impl<D: Database> MamManager<D> {
         &self,
         query_id: &str,
         with_jid: Option<&str>,
-        after: Option<&str>,
-        before: Option<&str>,
-        max: u32,
-    ) -> Result<(Vec<ChatMessage>, bool, Option<String>), MamError> {
+        page: &RsmQuery,
+        archive: Option<&str>,
+    ) -> Result<ArchivePage, MamError> {
         let mut sub = self
             .event_bus
             .subscribe("xmpp.mam.**")
@@ -270,19 +1076,22 @@ impl<D: Database> MamManager<D> {
 
         self.event_bus
             .publish(Event::new(
-                Channel::new("ui.mam.query").unwrap(),
+                Channel::new(channels::UI_MAM_QUERY).unwrap(),
                 EventSource::System("mam".into()),
                 EventPayload::MamQueryRequested {
                     query_id: query_id.to_string(),
                     with_jid: with_jid.map(String::from),
-                    after: after.map(String::from),
-                    before: before.map(String::from),
-                    max,
+                    page: page.clone(),
+                    archive: archive.map(String::from),
                 },
             ))
             .map_err(|e| MamError::EventBus(e.to_string()))?;
 
-        self.collect_query_results(&mut sub, query_id).await
+        let archived = self.collect_query_results(&mut sub, query_id).await?;
+        Ok(ArchivePage {
+            cursor: page.cursor.clone(),
+            ..archived
+        })
     }
 
     #[cfg(not(feature = "native"))]
@@ -290,10 +1099,9 @@ impl<D: Database> MamManager<D> {
         &self,
         _query_id: &str,
         _with_jid: Option<&str>,
-        _after: Option<&str>,
-        _before: Option<&str>,
-        _max: u32,
-    ) -> Result<(Vec<ChatMessage>, bool, Option<String>), MamError> {
+        _page: &RsmQuery,
+        _archive: Option<&str>,
+    ) -> Result<ArchivePage, MamError> {
         Err(MamError::NotSupported)
     }
 
@@ -302,10 +1110,11 @@ impl<D: Database> MamManager<D> {
         &self,
         sub: &mut EventSubscription,
         query_id: &str,
-    ) -> Result<(Vec<ChatMessage>, bool, Option<String>), MamError> {
+    ) -> Result<ArchivePage, MamError> {
         let mut messages = Vec::new();
         let mut last_id = None;
-        let timeout_duration = tokio::time::Duration::from_secs(MAM_QUERY_TIMEOUT_SECS);
+        let mut lossy = false;
+        let timeout_duration = self.retry_policy.read().unwrap().query_timeout;
 
         loop {
             match tokio::time::timeout(timeout_duration, sub.recv()).await {
@@ -321,27 +1130,52 @@ impl<D: Database> MamManager<D> {
                         }
 
                         if *complete {
-                            return Ok((messages, true, last_id));
+                            return Ok(ArchivePage {
+                                messages,
+                                complete: true,
+                                page: RsmResult {
+                                    last: last_id,
+                                    ..RsmResult::default()
+                                },
+                                cursor: RsmCursor::default(),
+                                lossy,
+                            });
                         }
                     }
                     EventPayload::MamFinReceived {
                         iq_id,
                         complete,
-                        last_id: fin_last,
+                        page,
                     } if iq_id == query_id => {
-                        let resolved_last = fin_last.clone().or(last_id);
-                        return Ok((messages, *complete, resolved_last));
+                        return Ok(ArchivePage {
+                            messages,
+                            complete: *complete,
+                            page: RsmResult {
+                                last: page.last.clone().or(last_id),
+                                ..page.clone()
+                            },
+                            cursor: RsmCursor::default(),
+                            lossy,
+                        });
+                    }
+                    EventPayload::MamQueryFailed { iq_id, error } if iq_id == query_id => {
+                        return Err(if error.split(':').next() == Some("item-not-found") {
+                            MamError::CursorNotFound
+                        } else {
+                            MamError::QueryFailed(error.clone())
+                        });
                     }
                     _ => {}
                 },
                 Ok(Err(waddle_core::error::EventBusError::Lagged(count))) => {
                     warn!(count, "MAM result collector lagged");
+                    lossy = true;
                 }
                 Ok(Err(e)) => {
                     return Err(MamError::QueryFailed(format!("event bus error: {e}")));
                 }
                 Err(_) => {
-                    return Err(MamError::Timeout(MAM_QUERY_TIMEOUT_SECS));
+                    return Err(MamError::Timeout(timeout_duration.as_secs()));
                 }
             }
         }
@@ -351,7 +1185,7 @@ impl<D: Database> MamManager<D> {
     fn emit_sync_started(&self, correlation_id: Uuid) -> Result<(), MamError> {
         self.event_bus
             .publish(Event::with_correlation(
-                Channel::new("system.sync.started").unwrap(),
+                Channel::new(channels::SYSTEM_SYNC_STARTED).unwrap(),
                 EventSource::System("mam".into()),
                 EventPayload::SyncStarted,
                 correlation_id,
@@ -372,27 +1206,153 @@ impl<D: Database> MamManager<D> {
     ) -> Result<(), MamError> {
         self.event_bus
             .publish(Event::with_correlation(
-                Channel::new("system.sync.completed").unwrap(),
+                Channel::new(channels::SYSTEM_SYNC_COMPLETED).unwrap(),
+                EventSource::System("mam".into()),
+                EventPayload::SyncCompleted { messages_synced },
+                correlation_id,
+            ))
+            .map_err(|e| MamError::EventBus(e.to_string()))
+    }
+
+    #[cfg(feature = "native")]
+    fn emit_sync_progress(&self, progress: &IngestProgress, correlation_id: Uuid) {
+        let _ = self.event_bus.publish(Event::with_correlation(
+            Channel::new(channels::SYSTEM_SYNC_PROGRESS).unwrap(),
+            EventSource::System("mam".into()),
+            EventPayload::SyncProgress {
+                done: progress.done,
+                // The server's count is approximate.
+                estimated_total: progress
+                    .estimated_total
+                    .map(|total| total.max(progress.done)),
+            },
+            correlation_id,
+        ));
+    }
+
+    #[cfg(not(feature = "native"))]
+    fn emit_sync_completed(
+        &self,
+        _messages_synced: u64,
+        _correlation_id: Uuid,
+    ) -> Result<(), MamError> {
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    fn emit_sync_failed(
+        &self,
+        messages_synced: u64,
+        last_stanza_id: Option<&str>,
+        error: &MamError,
+        correlation_id: Uuid,
+    ) {
+        let _ = self.event_bus.publish(Event::with_correlation(
+            Channel::new(channels::SYSTEM_SYNC_FAILED).unwrap(),
+            EventSource::System("mam".into()),
+            EventPayload::SyncFailed {
+                messages_synced,
+                last_stanza_id: last_stanza_id.map(String::from),
+                reason: error.to_string(),
+            },
+            correlation_id,
+        ));
+    }
+
+    #[cfg(not(feature = "native"))]
+    fn emit_sync_failed(
+        &self,
+        _messages_synced: u64,
+        _last_stanza_id: Option<&str>,
+        _error: &MamError,
+        _correlation_id: Uuid,
+    ) {
+    }
+
+    #[cfg(feature = "native")]
+    fn emit_sync_task_started(&self, task: &SyncTask, correlation_id: Uuid) {
+        let _ = self.event_bus.publish(Event::with_correlation(
+            Channel::new(channels::SYSTEM_SYNC_TASK_STARTED).unwrap(),
+            EventSource::System("mam".into()),
+            EventPayload::SyncTaskStarted { task: task.clone() },
+            correlation_id,
+        ));
+    }
+
+    #[cfg(feature = "native")]
+    fn emit_sync_task_finished(
+        &self,
+        task: SyncTask,
+        messages_synced: u64,
+        error: Option<&MamError>,
+        correlation_id: Uuid,
+    ) {
+        let _ = self.event_bus.publish(Event::with_correlation(
+            Channel::new(channels::SYSTEM_SYNC_TASK_FINISHED).unwrap(),
+            EventSource::System("mam".into()),
+            EventPayload::SyncTaskFinished {
+                task,
+                messages_synced,
+                error: error.map(ToString::to_string),
+            },
+            correlation_id,
+        ));
+    }
+
+    /// Announce every gap left in `jid`'s archive.
+    #[cfg(feature = "native")]
+    async fn report_gaps(&self, jid: &str) {
+        let gaps = match self.gaps(jid).await {
+            Ok(gaps) => gaps,
+            Err(e) => {
+                error!(error = %e, jid = %jid, "failed to read MAM sync ranges");
+                return;
+            }
+        };
+        for gap in gaps {
+            warn!(
+                jid = %jid,
+                after = %gap.after_id,
+                before = %gap.before_id,
+                "gap detected in the message archive"
+            );
+            let _ = self.event_bus.publish(Event::new(
+                Channel::new(channels::SYSTEM_SYNC_GAP_DETECTED).unwrap(),
                 EventSource::System("mam".into()),
-                EventPayload::SyncCompleted { messages_synced },
-                correlation_id,
-            ))
-            .map_err(|e| MamError::EventBus(e.to_string()))
+                EventPayload::SyncGapDetected {
+                    jid: jid.to_string(),
+                    gap,
+                },
+            ));
+        }
     }
 
-    #[cfg(not(feature = "native"))]
-    fn emit_sync_completed(
+    #[cfg(feature = "native")]
+    fn emit_history_page(
         &self,
-        _messages_synced: u64,
-        _correlation_id: Uuid,
-    ) -> Result<(), MamError> {
-        Ok(())
+        jid: &str,
+        messages: Vec<ChatMessage>,
+        before: Option<String>,
+        has_more: bool,
+    ) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channels::UI_HISTORY_PAGE_LOADED).unwrap(),
+            EventSource::System("mam".into()),
+            EventPayload::HistoryPageLoaded {
+                jid: jid.to_string(),
+                messages,
+                before,
+                has_more,
+            },
+        ));
     }
 
     #[cfg(feature = "native")]
     pub async fn handle_event(&self, event: &Event) {
         match &event.payload {
             EventPayload::ConnectionEstablished { jid } => {
+                let bare = Jid::new(jid).bare().into_string();
+                *self.own_jid.write().unwrap() = Some(bare);
                 self.startup_sync_pending.store(true, Ordering::Relaxed);
                 info!(jid = %jid, "connection established, waiting for own presence before MAM catch-up sync");
             }
@@ -408,26 +1368,48 @@ impl<D: Database> MamManager<D> {
                     return;
                 }
 
-                info!("initial own presence published, starting MAM catch-up sync");
-                match self.sync_since(Utc::now()).await {
+                info!("initial own presence published, starting MAM backfill");
+                let fetched = self
+                    .backfill(PREFETCH_CONVERSATIONS, PREFETCH_CONCURRENCY)
+                    .await;
+                info!(fetched, "MAM backfill complete");
+            }
+            EventPayload::ConversationOpened { jid } => {
+                *self.open_conversation.write().unwrap() = Some(Jid::new(jid).bare().into_string());
+            }
+            EventPayload::ConversationClosed { jid } => {
+                let bare = Jid::new(jid).bare().into_string();
+                let mut open = self.open_conversation.write().unwrap();
+                if open.as_ref() == Some(&bare) {
+                    *open = None;
+                }
+            }
+            EventPayload::MucJoined { room, .. } => {
+                debug!(room = %room, "room joined, syncing room archive");
+                match self.sync_room(room).await {
                     Ok(result) => {
-                        info!(
+                        debug!(
+                            room = %room,
                             messages_synced = result.messages_synced,
-                            "MAM catch-up sync complete"
+                            "room archive sync complete"
                         );
                     }
-                    Err(MamError::Timeout(_)) => {
-                        warn!("MAM catch-up sync timed out");
-                    }
                     Err(e) => {
-                        error!(error = %e, "MAM catch-up sync failed");
+                        warn!(error = %e, room = %room, "room archive sync failed");
                     }
                 }
+                self.report_gaps(room).await;
             }
             EventPayload::ScrollRequested {
                 jid,
                 direction: ScrollDirection::Up,
             } => {
+                if self.reached_archive_start(jid) {
+                    debug!(jid = %jid, "scroll up requested, archive start already reached");
+                    self.emit_history_page(jid, Vec::new(), None, false);
+                    return;
+                }
+
                 debug!(jid = %jid, "scroll up requested, fetching MAM history");
                 let before = match self.oldest_local_message_id(jid).await {
                     Ok(oldest) => oldest,
@@ -437,12 +1419,26 @@ impl<D: Database> MamManager<D> {
                     }
                 };
 
-                match self
-                    .fetch_history(jid, before.as_deref(), MAM_PAGE_SIZE)
-                    .await
-                {
-                    Ok(messages) => {
-                        debug!(count = messages.len(), jid = %jid, "fetched MAM history");
+                // With nothing stored locally, start from the latest page.
+                let page = before.map_or_else(
+                    || RsmQuery::last(MAM_PAGE_SIZE),
+                    |id| RsmQuery::before(id, MAM_PAGE_SIZE),
+                );
+                match self.fetch_history_page(jid, page).await {
+                    Ok(page) => {
+                        debug!(
+                            count = page.messages.len(),
+                            has_more = page.has_more,
+                            jid = %jid,
+                            "fetched MAM history"
+                        );
+                        if !page.has_more {
+                            self.archive_start_reached
+                                .write()
+                                .unwrap()
+                                .insert(jid.clone());
+                        }
+                        self.emit_history_page(jid, page.messages, page.before, page.has_more);
                     }
                     Err(e) => {
                         error!(error = %e, jid = %jid, "MAM history fetch failed");
@@ -481,12 +1477,30 @@ impl<D: Database> MamManager<D> {
     }
 }
 
+/// Stops syncs at a page boundary on shutdown, once the cursor for the page
+/// in flight has been stored.
+#[cfg(feature = "native")]
+impl<D: Database> Manager for MamManager<D> {
+    fn name(&self) -> &'static str {
+        "mam"
+    }
+
+    fn shutdown(&self) -> ShutdownFuture<'_> {
+        Box::pin(async move {
+            self.shutting_down.store(true, Ordering::Relaxed);
+            let _ = self.sync_gate.write().await;
+            debug!("MAM syncs stopped for shutdown");
+            Ok(())
+        })
+    }
+}
+
 #[cfg(all(test, feature = "native"))]
 mod tests {
     use super::*;
     use std::sync::Arc;
     use tempfile::TempDir;
-    use waddle_core::event::{BroadcastEventBus, EventBus, MessageType, PresenceShow};
+    use waddle_core::event::{BroadcastEventBus, EventBus, MessageType, PresenceShow, UiTarget};
 
     async fn setup() -> (Arc<MamManager<impl Database>>, Arc<dyn EventBus>, TempDir) {
         let dir = TempDir::new().expect("failed to create temp dir");
@@ -510,17 +1524,25 @@ mod tests {
             message_type: MessageType::Chat,
             thread: None,
             embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
         }
     }
 
     #[tokio::test]
-    async fn persist_message_deduplicates() {
+    async fn persist_page_deduplicates() {
         let (manager, _, _dir) = setup().await;
 
         let msg = make_chat_message("mam-1", "alice@example.com", "bob@example.com", "Hello");
 
-        manager.persist_message(&msg).await.unwrap();
-        manager.persist_message(&msg).await.unwrap();
+        manager
+            .persist_page(&[msg.clone(), msg.clone()])
+            .await
+            .unwrap();
+        manager.persist_page(&[msg]).await.unwrap();
 
         let rows: Vec<Row> = manager
             .db
@@ -534,6 +1556,30 @@ mod tests {
         assert_eq!(rows.len(), 1);
     }
 
+    #[tokio::test]
+    async fn sync_after_shutdown_keeps_cursor_and_requests_nothing() {
+        let dir = TempDir::new().expect("failed to create temp dir");
+        let db = waddle_storage::open_native_database(&dir.path().join("test.db"))
+            .await
+            .expect("failed to open database");
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let manager = MamManager::new(Arc::new(db), event_bus.clone());
+        manager.update_sync_state("", "archive-id-7").await.unwrap();
+        let mut queries = event_bus.subscribe("ui.mam.**").unwrap();
+
+        manager.shutdown().await.unwrap();
+        let result = manager.sync_since(Utc::now()).await.unwrap();
+
+        assert!(!result.complete);
+        assert_eq!(result.messages_synced, 0);
+        assert_eq!(
+            manager.get_last_stanza_id("").await.unwrap(),
+            Some("archive-id-7".to_string())
+        );
+        let query = tokio::time::timeout(Duration::from_millis(50), queries.recv()).await;
+        assert!(query.is_err(), "no archive page should be requested");
+    }
+
     #[tokio::test]
     async fn sync_state_round_trip() {
         let (manager, _, _dir) = setup().await;
@@ -632,74 +1678,477 @@ mod tests {
                     ))
                     .unwrap();
 
-                let msg2 =
-                    make_chat_message("arch-2", "bob@example.com", "alice@example.com", "Hey");
+                let msg2 =
+                    make_chat_message("arch-2", "bob@example.com", "alice@example.com", "Hey");
+                event_bus
+                    .publish(Event::new(
+                        Channel::new("xmpp.mam.result.received").unwrap(),
+                        EventSource::Xmpp,
+                        EventPayload::MamResultReceived {
+                            query_id: query_id.clone(),
+                            messages: vec![msg2],
+                            complete: false,
+                        },
+                    ))
+                    .unwrap();
+
+                // Simulate MAM fin
+                event_bus
+                    .publish(Event::new(
+                        Channel::new("xmpp.mam.fin.received").unwrap(),
+                        EventSource::Xmpp,
+                        EventPayload::MamFinReceived {
+                            iq_id: query_id,
+                            complete: true,
+                            page: RsmResult {
+                                last: Some("arch-2".to_string()),
+                                ..RsmResult::default()
+                            },
+                        },
+                    ))
+                    .unwrap();
+
+                let result = tokio::time::timeout(std::time::Duration::from_secs(5), sync_handle)
+                    .await
+                    .expect("sync timed out")
+                    .expect("sync task should not panic")
+                    .expect("sync should succeed");
+
+                assert_eq!(result.messages_synced, 2);
+                assert!(result.complete);
+
+                // Verify messages persisted
+                let rows: Vec<Row> = manager
+                    .db
+                    .query("SELECT COUNT(*) FROM messages", &[])
+                    .await
+                    .unwrap();
+                assert_eq!(rows[0].get(0), Some(&SqlValue::Integer(2)));
+
+                // Verify sync state updated
+                let last = manager.get_last_stanza_id("").await.unwrap();
+                assert_eq!(last, Some("arch-2".to_string()));
+
+                // Verify SyncStarted event
+                let started =
+                    tokio::time::timeout(std::time::Duration::from_millis(100), sys_sub.recv())
+                        .await
+                        .expect("timed out waiting for SyncStarted")
+                        .expect("should receive SyncStarted");
+                assert!(matches!(started.payload, EventPayload::SyncStarted));
+                let corr_id = started.correlation_id.expect("should have correlation ID");
+
+                // The page is committed as one batch
+                let progress =
+                    tokio::time::timeout(std::time::Duration::from_millis(100), sys_sub.recv())
+                        .await
+                        .expect("timed out waiting for SyncProgress")
+                        .expect("should receive SyncProgress");
+                assert!(matches!(
+                    progress.payload,
+                    EventPayload::SyncProgress {
+                        done: 2,
+                        estimated_total: None
+                    }
+                ));
+                assert_eq!(progress.correlation_id, Some(corr_id));
+
+                // Verify SyncCompleted event with matching correlation ID
+                let completed =
+                    tokio::time::timeout(std::time::Duration::from_millis(100), sys_sub.recv())
+                        .await
+                        .expect("timed out waiting for SyncCompleted")
+                        .expect("should receive SyncCompleted");
+                assert!(matches!(
+                    completed.payload,
+                    EventPayload::SyncCompleted { messages_synced: 2 }
+                ));
+                assert_eq!(completed.correlation_id, Some(corr_id));
+            })
+            .await;
+    }
+
+    fn fast_retry_policy(max_retries: u32) -> MamRetryPolicy {
+        MamRetryPolicy {
+            query_timeout: std::time::Duration::from_millis(50),
+            max_retries,
+            initial_backoff: std::time::Duration::from_millis(5),
+        }
+    }
+
+    async fn next_query(ui_sub: &mut EventSubscription) -> (String, RsmQuery) {
+        loop {
+            let event = tokio::time::timeout(std::time::Duration::from_secs(1), ui_sub.recv())
+                .await
+                .expect("timed out waiting for MAM query")
+                .expect("should receive query event");
+            if let EventPayload::MamQueryRequested { query_id, page, .. } = event.payload {
+                return (query_id, page);
+            }
+        }
+    }
+
+    fn publish_fin(event_bus: &Arc<dyn EventBus>, query_id: &str, id: &str, complete: bool) {
+        event_bus
+            .publish(Event::new(
+                Channel::new("xmpp.mam.result.received").unwrap(),
+                EventSource::Xmpp,
+                EventPayload::MamResultReceived {
+                    query_id: query_id.to_string(),
+                    messages: vec![make_chat_message(
+                        id,
+                        "alice@example.com",
+                        "bob@example.com",
+                        "Hi",
+                    )],
+                    complete: false,
+                },
+            ))
+            .unwrap();
+        event_bus
+            .publish(Event::new(
+                Channel::new("xmpp.mam.fin.received").unwrap(),
+                EventSource::Xmpp,
+                EventPayload::MamFinReceived {
+                    iq_id: query_id.to_string(),
+                    complete,
+                    page: RsmResult {
+                        last: Some(id.to_string()),
+                        ..RsmResult::default()
+                    },
+                },
+            ))
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn sync_retries_timed_out_page_with_new_query_id() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let (manager, event_bus, _dir) = setup().await;
+                manager.set_retry_policy(fast_retry_policy(2));
+                let mut ui_sub = event_bus.subscribe("ui.**").unwrap();
+
+                let manager_clone = manager.clone();
+                let sync_handle =
+                    tokio::task::spawn_local(
+                        async move { manager_clone.sync_since(Utc::now()).await },
+                    );
+
+                // First attempt goes unanswered and times out.
+                let (first_id, first_page) = next_query(&mut ui_sub).await;
+                let (retry_id, retry_page) = next_query(&mut ui_sub).await;
+                assert_ne!(first_id, retry_id);
+                assert_eq!(first_page, retry_page);
+
+                publish_fin(&event_bus, &retry_id, "arch-1", true);
+
+                let result = sync_handle
+                    .await
+                    .expect("sync task should not panic")
+                    .expect("sync should succeed after retry");
+                assert_eq!(result.messages_synced, 1);
+                assert_eq!(
+                    manager.get_last_stanza_id("").await.unwrap(),
+                    Some("arch-1".to_string())
+                );
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn sync_reports_progress_when_retries_are_exhausted() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let (manager, event_bus, _dir) = setup().await;
+                manager.set_retry_policy(fast_retry_policy(1));
+                let mut ui_sub = event_bus.subscribe("ui.**").unwrap();
+                let mut failed_sub = event_bus.subscribe("system.sync.failed").unwrap();
+
+                let manager_clone = manager.clone();
+                let sync_handle =
+                    tokio::task::spawn_local(
+                        async move { manager_clone.sync_since(Utc::now()).await },
+                    );
+
+                let (first_page, _) = next_query(&mut ui_sub).await;
+                publish_fin(&event_bus, &first_page, "arch-1", false);
+
+                // Second page: the first attempt and its single retry time out.
+                let (_, page) = next_query(&mut ui_sub).await;
+                assert_eq!(page, RsmQuery::after("arch-1", MAM_PAGE_SIZE));
+                let (_, retry_page) = next_query(&mut ui_sub).await;
+                assert_eq!(retry_page, page);
+
+                let result = sync_handle.await.expect("sync task should not panic");
+                assert!(matches!(result, Err(MamError::Timeout(_))));
+
+                let failed =
+                    tokio::time::timeout(std::time::Duration::from_millis(100), failed_sub.recv())
+                        .await
+                        .expect("timed out waiting for SyncFailed")
+                        .unwrap();
+                match failed.payload {
+                    EventPayload::SyncFailed {
+                        messages_synced,
+                        last_stanza_id,
+                        ..
+                    } => {
+                        assert_eq!(messages_synced, 1);
+                        assert_eq!(last_stanza_id.as_deref(), Some("arch-1"));
+                    }
+                    other => panic!("expected SyncFailed, got {other:?}"),
+                }
+
+                // The completed page's cursor survives for the next sync.
+                assert_eq!(
+                    manager.get_last_stanza_id("").await.unwrap(),
+                    Some("arch-1".to_string())
+                );
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn sync_commits_queued_pages_and_reports_progress() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let (manager, event_bus, _dir) = setup().await;
+                manager.update_sync_state("", "arch-0").await.unwrap();
+                let mut ui_sub = event_bus.subscribe("ui.**").unwrap();
+                let mut progress_sub = event_bus.subscribe("system.sync.progress").unwrap();
+
+                let manager_clone = manager.clone();
+                let sync_handle =
+                    tokio::task::spawn_local(
+                        async move { manager_clone.sync_since(Utc::now()).await },
+                    );
+
+                // Ten messages precede the cursor in an archive of fifteen.
+                let pages = [vec!["arch-1", "arch-2"], vec!["arch-3", "arch-4", "arch-5"]];
+                for (page, ids) in pages.iter().enumerate() {
+                    let (query_id, query) = next_query(&mut ui_sub).await;
+                    let expected_after = if page == 0 { "arch-0" } else { "arch-2" };
+                    assert_eq!(query, RsmQuery::after(expected_after, MAM_PAGE_SIZE));
+
+                    let messages = ids
+                        .iter()
+                        .map(|id| {
+                            make_chat_message(id, "alice@example.com", "bob@example.com", "Hi")
+                        })
+                        .collect();
+                    event_bus
+                        .publish(Event::new(
+                            Channel::new("xmpp.mam.result.received").unwrap(),
+                            EventSource::Xmpp,
+                            EventPayload::MamResultReceived {
+                                query_id: query_id.clone(),
+                                messages,
+                                complete: false,
+                            },
+                        ))
+                        .unwrap();
+                    event_bus
+                        .publish(Event::new(
+                            Channel::new("xmpp.mam.fin.received").unwrap(),
+                            EventSource::Xmpp,
+                            EventPayload::MamFinReceived {
+                                iq_id: query_id,
+                                complete: page == 1,
+                                page: RsmResult {
+                                    last: ids.last().map(|id| id.to_string()),
+                                    count: Some(15),
+                                    first_index: Some(10 + 2 * page as u64),
+                                    ..RsmResult::default()
+                                },
+                            },
+                        ))
+                        .unwrap();
+                }
+
+                let result = sync_handle
+                    .await
+                    .expect("sync task should not panic")
+                    .expect("sync should succeed");
+                assert_eq!(result.messages_synced, 5);
+                assert_eq!(
+                    manager.get_last_stanza_id("").await.unwrap(),
+                    Some("arch-5".to_string())
+                );
+
+                let mut last_done = 0;
+                while last_done < 5 {
+                    let event = tokio::time::timeout(
+                        std::time::Duration::from_millis(100),
+                        progress_sub.recv(),
+                    )
+                    .await
+                    .expect("timed out waiting for SyncProgress")
+                    .unwrap();
+                    match event.payload {
+                        EventPayload::SyncProgress {
+                            done,
+                            estimated_total,
+                        } => {
+                            assert!(done > last_done);
+                            assert_eq!(estimated_total, Some(5));
+                            last_done = done;
+                        }
+                        other => panic!("expected SyncProgress, got {other:?}"),
+                    }
+                }
+                assert_eq!(last_done, 5);
+            })
+            .await;
+    }
+
+    #[test]
+    fn remaining_needs_a_position_once_resumed() {
+        let page = |count, first_index| ArchivePage {
+            messages: Vec::new(),
+            complete: false,
+            page: RsmResult {
+                count,
+                first_index,
+                ..RsmResult::default()
+            },
+            cursor: RsmCursor::Start,
+            lossy: false,
+        };
+        assert_eq!(page(Some(15), Some(10)).remaining(true), Some(5));
+        assert_eq!(page(Some(15), None).remaining(false), Some(15));
+        assert_eq!(page(Some(15), None).remaining(true), None);
+        assert_eq!(page(None, Some(3)).remaining(false), None);
+    }
+
+    #[tokio::test]
+    async fn expired_cursor_restarts_from_the_archive_start_and_leaves_a_gap() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let (manager, event_bus, _dir) = setup().await;
+                manager.set_retry_policy(fast_retry_policy(0));
+                let stored = [
+                    make_chat_message("old-1", "alice@example.com", "bob@example.com", "a"),
+                    make_chat_message("old-2", "bob@example.com", "alice@example.com", "b"),
+                ];
+                let span = ArchiveRange::spanning(&stored).unwrap();
+                manager
+                    .record_range("", &RsmCursor::Start, span)
+                    .await
+                    .unwrap();
+                manager.update_sync_state("", "old-2").await.unwrap();
+                let mut ui_sub = event_bus.subscribe("ui.**").unwrap();
+                let mut gap_sub = event_bus.subscribe("system.sync.gap_detected").unwrap();
+
+                let manager_clone = manager.clone();
+                let sync_handle =
+                    tokio::task::spawn_local(
+                        async move { manager_clone.sync_since(Utc::now()).await },
+                    );
+
+                let (query_id, page) = next_query(&mut ui_sub).await;
+                assert_eq!(page, RsmQuery::after("old-2", MAM_PAGE_SIZE));
+                event_bus
+                    .publish(Event::new(
+                        Channel::new("xmpp.mam.query.failed").unwrap(),
+                        EventSource::Xmpp,
+                        EventPayload::MamQueryFailed {
+                            iq_id: query_id,
+                            error: "item-not-found".to_string(),
+                        },
+                    ))
+                    .unwrap();
+
+                let (query_id, page) = next_query(&mut ui_sub).await;
+                assert_eq!(page, RsmQuery::first(MAM_PAGE_SIZE));
+                publish_fin(&event_bus, &query_id, "new-1", true);
+
+                let result = sync_handle.await.unwrap().unwrap();
+                assert_eq!(result.messages_synced, 1);
+
+                let gaps = manager.gaps("").await.unwrap();
+                assert_eq!(gaps.len(), 1);
+                assert_eq!(gaps[0].after_id, "old-2");
+                assert_eq!(gaps[0].before_id, "new-1");
+
+                manager.report_gaps("").await;
+                let event =
+                    tokio::time::timeout(std::time::Duration::from_millis(500), gap_sub.recv())
+                        .await
+                        .expect("timed out waiting for gap")
+                        .expect("should receive gap event");
+                assert!(matches!(
+                    event.payload,
+                    EventPayload::SyncGapDetected { ref jid, ref gap }
+                        if jid.is_empty() && *gap == gaps[0]
+                ));
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn fill_gap_fetches_the_missing_messages_and_joins_the_ranges() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let (manager, event_bus, _dir) = setup().await;
+                let [a1, a2, missing, c1] = ["a-1", "a-2", "b-1", "c-1"]
+                    .map(|id| make_chat_message(id, "alice@example.com", "bob@example.com", id));
+                for (stored, cursor) in [
+                    (vec![a1, a2], RsmCursor::Start),
+                    (vec![c1.clone()], RsmCursor::After("lagged".to_string())),
+                ] {
+                    let span = ArchiveRange::spanning(&stored).unwrap();
+                    manager.record_range("", &cursor, span).await.unwrap();
+                }
+                let gap = manager.gaps("").await.unwrap().remove(0);
+                let mut ui_sub = event_bus.subscribe("ui.**").unwrap();
+
+                let manager_clone = manager.clone();
+                let fill_handle =
+                    tokio::task::spawn_local(async move { manager_clone.fill_gap("", &gap).await });
+
+                let (query_id, page) = next_query(&mut ui_sub).await;
+                assert_eq!(page, RsmQuery::after("a-2", MAM_PAGE_SIZE));
                 event_bus
                     .publish(Event::new(
                         Channel::new("xmpp.mam.result.received").unwrap(),
                         EventSource::Xmpp,
                         EventPayload::MamResultReceived {
                             query_id: query_id.clone(),
-                            messages: vec![msg2],
+                            messages: vec![missing, c1],
                             complete: false,
                         },
                     ))
                     .unwrap();
-
-                // Simulate MAM fin
                 event_bus
                     .publish(Event::new(
                         Channel::new("xmpp.mam.fin.received").unwrap(),
                         EventSource::Xmpp,
                         EventPayload::MamFinReceived {
                             iq_id: query_id,
-                            complete: true,
-                            last_id: Some("arch-2".to_string()),
+                            complete: false,
+                            page: RsmResult {
+                                last: Some("c-1".to_string()),
+                                ..RsmResult::default()
+                            },
                         },
                     ))
                     .unwrap();
 
-                let result = tokio::time::timeout(std::time::Duration::from_secs(5), sync_handle)
-                    .await
-                    .expect("sync timed out")
-                    .expect("sync task should not panic")
-                    .expect("sync should succeed");
-
+                let result = fill_handle.await.unwrap().unwrap();
                 assert_eq!(result.messages_synced, 2);
-                assert!(result.complete);
-
-                // Verify messages persisted
-                let rows: Vec<Row> = manager
-                    .db
-                    .query("SELECT COUNT(*) FROM messages", &[])
-                    .await
-                    .unwrap();
-                assert_eq!(rows[0].get(0), Some(&SqlValue::Integer(2)));
-
-                // Verify sync state updated
-                let last = manager.get_last_stanza_id("").await.unwrap();
-                assert_eq!(last, Some("arch-2".to_string()));
-
-                // Verify SyncStarted event
-                let started =
-                    tokio::time::timeout(std::time::Duration::from_millis(100), sys_sub.recv())
-                        .await
-                        .expect("timed out waiting for SyncStarted")
-                        .expect("should receive SyncStarted");
-                assert!(matches!(started.payload, EventPayload::SyncStarted));
-                let corr_id = started.correlation_id.expect("should have correlation ID");
-
-                // Verify SyncCompleted event with matching correlation ID
-                let completed =
-                    tokio::time::timeout(std::time::Duration::from_millis(100), sys_sub.recv())
-                        .await
-                        .expect("timed out waiting for SyncCompleted")
-                        .expect("should receive SyncCompleted");
-                assert!(matches!(
-                    completed.payload,
-                    EventPayload::SyncCompleted { messages_synced: 2 }
-                ));
-                assert_eq!(completed.correlation_id, Some(corr_id));
+                let ranges = manager.synced_ranges("").await.unwrap();
+                assert_eq!(ranges.len(), 1);
+                assert_eq!(
+                    (ranges[0].first_id.as_str(), ranges[0].last_id.as_str()),
+                    ("a-1", "c-1")
+                );
+                assert!(manager.gaps("").await.unwrap().is_empty());
             })
             .await;
     }
@@ -759,7 +2208,7 @@ mod tests {
                         EventPayload::MamFinReceived {
                             iq_id: query_id,
                             complete: true,
-                            last_id: None,
+                            page: RsmResult::default(),
                         },
                     ))
                     .unwrap();
@@ -803,10 +2252,8 @@ mod tests {
                         .expect("should receive query event");
 
                 let query_id = match &query_event.payload {
-                    EventPayload::MamQueryRequested {
-                        query_id, after, ..
-                    } => {
-                        assert_eq!(after.as_deref(), Some("existing-id-99"));
+                    EventPayload::MamQueryRequested { query_id, page, .. } => {
+                        assert_eq!(*page, RsmQuery::after("existing-id-99", MAM_PAGE_SIZE));
                         query_id.clone()
                     }
                     _ => panic!("expected MamQueryRequested"),
@@ -820,7 +2267,7 @@ mod tests {
                         EventPayload::MamFinReceived {
                             iq_id: query_id,
                             complete: true,
-                            last_id: None,
+                            page: RsmResult::default(),
                         },
                     ))
                     .unwrap();
@@ -834,6 +2281,117 @@ mod tests {
             .await;
     }
 
+    #[tokio::test]
+    async fn sync_room_queries_room_archive_and_keeps_per_room_cursor() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let (manager, event_bus, _dir) = setup().await;
+                let room = "lobby@conference.example.com";
+                let mut ui_sub = event_bus.subscribe("ui.**").unwrap();
+
+                let manager_clone = manager.clone();
+                let first_sync =
+                    tokio::task::spawn_local(async move { manager_clone.sync_room(room).await });
+
+                let query_event =
+                    tokio::time::timeout(std::time::Duration::from_millis(500), ui_sub.recv())
+                        .await
+                        .expect("timed out waiting for MAM query")
+                        .expect("should receive query event");
+                let EventPayload::MamQueryRequested {
+                    query_id,
+                    page,
+                    archive,
+                    ..
+                } = query_event.payload
+                else {
+                    panic!("expected MamQueryRequested event");
+                };
+                assert_eq!(archive.as_deref(), Some(room));
+                assert_eq!(page, RsmQuery::last(MAM_PAGE_SIZE));
+
+                let mut archived = make_chat_message(
+                    "room-stanza-2",
+                    &format!("{room}/bob"),
+                    "alice@example.com",
+                    "from the room archive",
+                );
+                archived.message_type = MessageType::Groupchat;
+                event_bus
+                    .publish(Event::new(
+                        Channel::new("xmpp.mam.result.received").unwrap(),
+                        EventSource::Xmpp,
+                        EventPayload::MamResultReceived {
+                            query_id: query_id.clone(),
+                            messages: vec![archived],
+                            complete: false,
+                        },
+                    ))
+                    .unwrap();
+                event_bus
+                    .publish(Event::new(
+                        Channel::new("xmpp.mam.fin.received").unwrap(),
+                        EventSource::Xmpp,
+                        EventPayload::MamFinReceived {
+                            iq_id: query_id,
+                            complete: false,
+                            page: RsmResult {
+                                last: Some("room-stanza-2".to_string()),
+                                ..RsmResult::default()
+                            },
+                        },
+                    ))
+                    .unwrap();
+
+                let result = tokio::time::timeout(std::time::Duration::from_secs(5), first_sync)
+                    .await
+                    .expect("timed out")
+                    .expect("should not panic")
+                    .expect("room sync should succeed");
+                assert_eq!(result.messages_synced, 1);
+
+                let row: Row = manager
+                    .db
+                    .query_one(
+                        "SELECT to_jid, message_type FROM messages WHERE id = 'room-stanza-2'",
+                        &[],
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(row.get(0), Some(&SqlValue::Text(room.to_string())));
+                assert_eq!(row.get(1), Some(&SqlValue::Text("groupchat".to_string())));
+                assert_eq!(
+                    manager.get_last_stanza_id(room).await.unwrap().as_deref(),
+                    Some("room-stanza-2")
+                );
+                assert_eq!(manager.get_last_stanza_id("").await.unwrap(), None);
+
+                let manager_clone = manager.clone();
+                let second_sync =
+                    tokio::task::spawn_local(async move { manager_clone.sync_room(room).await });
+                let (query_id, page) = next_query(&mut ui_sub).await;
+                assert_eq!(page, RsmQuery::after("room-stanza-2", MAM_PAGE_SIZE));
+                event_bus
+                    .publish(Event::new(
+                        Channel::new("xmpp.mam.fin.received").unwrap(),
+                        EventSource::Xmpp,
+                        EventPayload::MamFinReceived {
+                            iq_id: query_id,
+                            complete: true,
+                            page: RsmResult::default(),
+                        },
+                    ))
+                    .unwrap();
+                tokio::time::timeout(std::time::Duration::from_secs(5), second_sync)
+                    .await
+                    .expect("timed out")
+                    .expect("should not panic")
+                    .expect("room sync should succeed");
+            })
+            .await;
+    }
+
     #[tokio::test]
     async fn fetch_history_uses_jid_filter_and_limit() {
         let local = tokio::task::LocalSet::new();
@@ -846,7 +2404,7 @@ mod tests {
                 let manager_clone = manager.clone();
                 let fetch_handle = tokio::task::spawn_local(async move {
                     manager_clone
-                        .fetch_history("bob@example.com", Some("oldest-1"), 10)
+                        .fetch_history("bob@example.com", RsmQuery::before("oldest-1", 10))
                         .await
                 });
 
@@ -863,13 +2421,11 @@ mod tests {
                     EventPayload::MamQueryRequested {
                         query_id,
                         with_jid,
-                        before,
-                        max,
+                        page,
                         ..
                     } => {
                         assert_eq!(with_jid.as_deref(), Some("bob@example.com"));
-                        assert_eq!(before.as_deref(), Some("oldest-1"));
-                        assert_eq!(max, 10);
+                        assert_eq!(page, RsmQuery::before("oldest-1", 10));
                         query_id
                     }
                     other => panic!("expected MamQueryRequested event, got {other:?}"),
@@ -882,7 +2438,7 @@ mod tests {
                         EventPayload::MamFinReceived {
                             iq_id: query_id,
                             complete: true,
-                            last_id: None,
+                            page: RsmResult::default(),
                         },
                     ))
                     .unwrap();
@@ -899,6 +2455,94 @@ mod tests {
             .await;
     }
 
+    #[tokio::test]
+    async fn scroll_up_emits_loaded_page_and_remembers_archive_start() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let (manager, event_bus, _dir) = setup().await;
+                manager
+                    .persist_page(&[make_chat_message(
+                        "local-oldest",
+                        "alice@example.com",
+                        "bob@example.com",
+                        "already stored",
+                    )])
+                    .await
+                    .unwrap();
+
+                let mut ui_sub = event_bus.subscribe("ui.mam.**").unwrap();
+                let mut pages = event_bus.subscribe("ui.history.**").unwrap();
+                let scroll_up = Event::new(
+                    Channel::new("ui.scroll.requested").unwrap(),
+                    EventSource::System("test".into()),
+                    EventPayload::ScrollRequested {
+                        jid: "bob@example.com".to_string(),
+                        direction: ScrollDirection::Up,
+                    },
+                );
+
+                let manager_clone = manager.clone();
+                let event = scroll_up.clone();
+                let handle = tokio::task::spawn_local(async move {
+                    manager_clone.handle_event(&event).await;
+                });
+
+                let query_event =
+                    tokio::time::timeout(std::time::Duration::from_millis(500), ui_sub.recv())
+                        .await
+                        .expect("timed out waiting for MAM query")
+                        .expect("should receive query event");
+                let EventPayload::MamQueryRequested { query_id, page, .. } = query_event.payload
+                else {
+                    panic!("expected MamQueryRequested event");
+                };
+                assert_eq!(page, RsmQuery::before("local-oldest", MAM_PAGE_SIZE));
+                publish_fin(&event_bus, &query_id, "archived-1", true);
+                handle.await.unwrap();
+
+                let page =
+                    tokio::time::timeout(std::time::Duration::from_millis(500), pages.recv())
+                        .await
+                        .expect("timed out waiting for history page")
+                        .expect("should receive history page");
+                let EventPayload::HistoryPageLoaded {
+                    jid,
+                    messages,
+                    before,
+                    has_more,
+                } = page.payload
+                else {
+                    panic!("expected HistoryPageLoaded event");
+                };
+                assert_eq!(jid, "bob@example.com");
+                assert_eq!(messages.len(), 1);
+                assert_eq!(messages[0].id, "archived-1");
+                assert_eq!(before.as_deref(), Some("archived-1"));
+                assert!(!has_more);
+                assert!(manager.reached_archive_start("bob@example.com"));
+
+                manager.handle_event(&scroll_up).await;
+                let page =
+                    tokio::time::timeout(std::time::Duration::from_millis(500), pages.recv())
+                        .await
+                        .expect("timed out waiting for history page")
+                        .expect("should receive history page");
+                assert!(matches!(
+                    page.payload,
+                    EventPayload::HistoryPageLoaded { ref messages, has_more: false, .. }
+                        if messages.is_empty()
+                ));
+                assert!(
+                    tokio::time::timeout(std::time::Duration::from_millis(50), ui_sub.recv())
+                        .await
+                        .is_err(),
+                    "the archive is not queried again once its start was reached"
+                );
+            })
+            .await;
+    }
+
     #[tokio::test]
     async fn sync_since_ignores_other_query_results() {
         let local = tokio::task::LocalSet::new();
@@ -948,7 +2592,10 @@ mod tests {
                         EventPayload::MamFinReceived {
                             iq_id: "other-query".to_string(),
                             complete: true,
-                            last_id: Some("other-1".to_string()),
+                            page: RsmResult {
+                                last: Some("other-1".to_string()),
+                                ..RsmResult::default()
+                            },
                         },
                     ))
                     .unwrap();
@@ -973,7 +2620,10 @@ mod tests {
                         EventPayload::MamFinReceived {
                             iq_id: query_id,
                             complete: true,
-                            last_id: Some("arch-10".to_string()),
+                            page: RsmResult {
+                                last: Some("arch-10".to_string()),
+                                ..RsmResult::default()
+                            },
                         },
                     ))
                     .unwrap();
@@ -995,4 +2645,294 @@ mod tests {
             })
             .await;
     }
+
+    #[tokio::test]
+    async fn recent_conversation_jids_orders_by_latest_message_and_pads_with_roster() {
+        let (manager, _, _dir) = setup().await;
+
+        let mut old = make_chat_message("m-1", "alice@example.com", "me@example.com", "old");
+        old.timestamp = Utc::now() - chrono::Duration::hours(2);
+        let recent = make_chat_message("m-2", "me@example.com", "bob@example.com", "new");
+        manager.persist_page(&[old, recent]).await.unwrap();
+
+        for jid in ["alice@example.com", "carol@example.com", "dave@example.com"] {
+            manager
+                .db
+                .execute(
+                    "INSERT INTO roster (jid, name, subscription, groups) VALUES (?1, NULL, 'both', '[]')",
+                    &[&jid.to_string()],
+                )
+                .await
+                .unwrap();
+        }
+
+        let connected = Event::new(
+            Channel::new("system.connection.established").unwrap(),
+            EventSource::Xmpp,
+            EventPayload::ConnectionEstablished {
+                jid: "me@example.com/waddle".to_string(),
+            },
+        );
+        manager.handle_event(&connected).await;
+
+        let jids = manager.recent_conversation_jids(3).await.unwrap();
+        assert_eq!(
+            jids,
+            vec![
+                "bob@example.com".to_string(),
+                "alice@example.com".to_string(),
+                "carol@example.com".to_string(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn prefetch_queries_latest_page_per_conversation_in_parallel() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let (manager, event_bus, _dir) = setup().await;
+
+                for jid in ["alice@example.com", "bob@example.com", "carol@example.com"] {
+                    manager
+                        .db
+                        .execute(
+                            "INSERT INTO roster (jid, name, subscription, groups) VALUES (?1, NULL, 'both', '[]')",
+                            &[&jid.to_string()],
+                        )
+                        .await
+                        .unwrap();
+                }
+
+                let mut ui_sub = event_bus.subscribe("ui.**").unwrap();
+
+                let manager_clone = manager.clone();
+                let prefetch_handle = tokio::task::spawn_local(async move {
+                    manager_clone.prefetch_recent_conversations(3, 2).await
+                });
+
+                // With a concurrency of two, two queries are in flight before
+                // any of them completes.
+                let mut pending = Vec::new();
+                for _ in 0..2 {
+                    let query_event =
+                        tokio::time::timeout(std::time::Duration::from_millis(500), ui_sub.recv())
+                            .await
+                            .expect("timed out waiting for MAM query")
+                            .expect("should receive query event");
+                    match query_event.payload {
+                        EventPayload::MamQueryRequested {
+                            query_id,
+                            with_jid,
+                            page,
+                            ..
+                        } => {
+                            assert!(with_jid.is_some());
+                            assert_eq!(page, RsmQuery::last(MAM_PAGE_SIZE));
+                            pending.push((query_id, with_jid.unwrap()));
+                        }
+                        other => panic!("expected MamQueryRequested event, got {other:?}"),
+                    }
+                }
+
+                let no_third_yet =
+                    tokio::time::timeout(std::time::Duration::from_millis(50), ui_sub.recv())
+                        .await;
+                assert!(no_third_yet.is_err(), "concurrency limit should hold");
+
+                let (first_id, first_jid) = pending.remove(0);
+                event_bus
+                    .publish(Event::new(
+                        Channel::new("xmpp.mam.result.received").unwrap(),
+                        EventSource::Xmpp,
+                        EventPayload::MamResultReceived {
+                            query_id: first_id.clone(),
+                            messages: vec![make_chat_message(
+                                "pf-1",
+                                &first_jid,
+                                "me@example.com",
+                                "hello",
+                            )],
+                            complete: false,
+                        },
+                    ))
+                    .unwrap();
+                event_bus
+                    .publish(Event::new(
+                        Channel::new("xmpp.mam.fin.received").unwrap(),
+                        EventSource::Xmpp,
+                        EventPayload::MamFinReceived {
+                            iq_id: first_id,
+                            complete: true,
+                            page: RsmResult::default(),
+                        },
+                    ))
+                    .unwrap();
+
+                let third =
+                    tokio::time::timeout(std::time::Duration::from_millis(500), ui_sub.recv())
+                        .await
+                        .expect("timed out waiting for third query")
+                        .expect("should receive third query");
+                let EventPayload::MamQueryRequested { query_id, .. } = third.payload else {
+                    panic!("expected MamQueryRequested");
+                };
+                pending.push((query_id, String::new()));
+
+                for (query_id, _) in pending {
+                    event_bus
+                        .publish(Event::new(
+                            Channel::new("xmpp.mam.fin.received").unwrap(),
+                            EventSource::Xmpp,
+                            EventPayload::MamFinReceived {
+                                iq_id: query_id,
+                                complete: true,
+                                page: RsmResult::default(),
+                            },
+                        ))
+                        .unwrap();
+                }
+
+                let fetched =
+                    tokio::time::timeout(std::time::Duration::from_secs(5), prefetch_handle)
+                        .await
+                        .expect("prefetch timed out")
+                        .expect("prefetch should not panic")
+                        .expect("prefetch should succeed");
+                assert_eq!(fetched, 1);
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn backfill_fetches_the_open_conversation_before_the_rest() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let (manager, event_bus, _dir) = setup().await;
+
+                for jid in ["bob@example.com", "carol@example.com"] {
+                    manager
+                        .db
+                        .execute(
+                            "INSERT INTO roster (jid, name, subscription, groups) VALUES (?1, NULL, 'both', '[]')",
+                            &[&jid.to_string()],
+                        )
+                        .await
+                        .unwrap();
+                }
+                manager
+                    .handle_event(&Event::new(
+                        Channel::new("ui.conversation.opened").unwrap(),
+                        EventSource::Ui(UiTarget::Tui),
+                        EventPayload::ConversationOpened {
+                            jid: "carol@example.com/phone".to_string(),
+                        },
+                    ))
+                    .await;
+
+                let mut ui_sub = event_bus.subscribe("ui.**").unwrap();
+                let mut task_sub = event_bus.subscribe("system.sync.task.**").unwrap();
+
+                let manager_clone = manager.clone();
+                let backfill_handle =
+                    tokio::task::spawn_local(async move { manager_clone.backfill(10, 4).await });
+
+                async fn query_with_jid(ui_sub: &mut EventSubscription) -> (String, Option<String>, RsmQuery) {
+                    let event =
+                        tokio::time::timeout(std::time::Duration::from_millis(500), ui_sub.recv())
+                            .await
+                            .expect("timed out waiting for MAM query")
+                            .expect("should receive query event");
+                    match event.payload {
+                        EventPayload::MamQueryRequested {
+                            query_id,
+                            with_jid,
+                            page,
+                            ..
+                        } => (query_id, with_jid, page),
+                        other => panic!("expected MamQueryRequested event, got {other:?}"),
+                    }
+                }
+                let publish_empty_fin = |query_id: String| {
+                    event_bus
+                        .publish(Event::new(
+                            Channel::new("xmpp.mam.fin.received").unwrap(),
+                            EventSource::Xmpp,
+                            EventPayload::MamFinReceived {
+                                iq_id: query_id,
+                                complete: true,
+                                page: RsmResult::default(),
+                            },
+                        ))
+                        .unwrap();
+                };
+
+                let (open_id, open_jid, open_page) = query_with_jid(&mut ui_sub).await;
+                assert_eq!(open_jid.as_deref(), Some("carol@example.com"));
+                assert_eq!(open_page, RsmQuery::last(MAM_PAGE_SIZE));
+                let nothing_else_yet =
+                    tokio::time::timeout(std::time::Duration::from_millis(50), ui_sub.recv())
+                        .await;
+                assert!(
+                    nothing_else_yet.is_err(),
+                    "the open conversation should be fetched alone"
+                );
+                publish_empty_fin(open_id);
+
+                // The other recent conversation and the global catch-up run
+                // side by side.
+                let (first_id, first_jid, _) = query_with_jid(&mut ui_sub).await;
+                let (second_id, second_jid, _) = query_with_jid(&mut ui_sub).await;
+                let mut jids = vec![first_jid, second_jid];
+                jids.sort();
+                assert_eq!(jids, vec![None, Some("bob@example.com".to_string())]);
+                publish_empty_fin(first_id);
+                publish_empty_fin(second_id);
+
+                let fetched =
+                    tokio::time::timeout(std::time::Duration::from_secs(5), backfill_handle)
+                        .await
+                        .expect("backfill timed out")
+                        .expect("backfill should not panic");
+                assert_eq!(fetched, 0);
+
+                let mut task_events = Vec::new();
+                while let Ok(Ok(event)) =
+                    tokio::time::timeout(std::time::Duration::from_millis(50), task_sub.recv())
+                        .await
+                {
+                    task_events.push(event);
+                }
+                assert_eq!(task_events.len(), 6);
+                let open_task = SyncTask::OpenConversation {
+                    jid: "carol@example.com".to_string(),
+                };
+                assert!(
+                    matches!(&task_events[0].payload, EventPayload::SyncTaskStarted { task } if *task == open_task)
+                );
+                assert!(matches!(
+                    &task_events[1].payload,
+                    EventPayload::SyncTaskFinished { task, messages_synced: 0, error: None } if *task == open_task
+                ));
+                for task in [
+                    SyncTask::RecentConversation {
+                        jid: "bob@example.com".to_string(),
+                    },
+                    SyncTask::Archive,
+                ] {
+                    assert!(task_events.iter().any(|event| matches!(
+                        &event.payload,
+                        EventPayload::SyncTaskFinished { task: finished, error: None, .. } if *finished == task
+                    )));
+                }
+                let correlation_id = task_events[0].correlation_id;
+                assert!(
+                    task_events
+                        .iter()
+                        .all(|event| event.correlation_id == correlation_id)
+                );
+            })
+            .await;
+    }
 }
@@ -0,0 +1,1409 @@
+#[cfg(feature = "native")]
+use std::collections::HashSet;
+use std::sync::Arc;
+#[cfg(feature = "native")]
+use std::sync::{Mutex, RwLock};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{debug, error, warn};
+
+use waddle_core::event::{ChatMessage, Event, EventPayload, MessageType};
+use waddle_core::jid::Jid;
+use waddle_storage::{Database, FromRow, Row, SqlValue, StorageError};
+
+#[cfg(feature = "native")]
+use waddle_core::event::{Channel, ConversationFlags, EventBus, EventSource, PubSubItem, channels};
+#[cfg(feature = "native")]
+use waddle_xmpp::{
+    CONVERSATION_FLAGS_NODE, CONVERSATION_FLAGS_OPTIONS, PubSubManager, flags_from_element,
+    flags_to_element, item_payload,
+};
+
+use crate::MessagingError;
+use crate::merge::{MergeReport, merge_duplicate_conversations};
+
+/// Longest message preview kept per conversation, in characters.
+const PREVIEW_MAX_CHARS: usize = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConversationKind {
+    Chat,
+    Groupchat,
+}
+
+impl ConversationKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            ConversationKind::Chat => "chat",
+            ConversationKind::Groupchat => "groupchat",
+        }
+    }
+}
+
+/// One entry of the conversation list: a 1:1 chat or a MUC room.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Conversation {
+    pub jid: String,
+    pub kind: ConversationKind,
+    pub last_message_preview: Option<String>,
+    /// Sender of the last message: a bare JID for chats, a nick for rooms.
+    pub last_message_from: Option<String>,
+    pub last_activity: Option<DateTime<Utc>>,
+    pub unread_count: u32,
+    pub pinned: bool,
+    pub archived: bool,
+    /// Notifications are muted now: the conversation was muted and
+    /// `muted_until`, if any, has not passed yet.
+    pub muted: bool,
+    pub muted_until: Option<DateTime<Utc>>,
+}
+
+impl FromRow for Conversation {
+    fn from_row(row: &Row) -> Result<Self, StorageError> {
+        let text = |idx: usize| match row.get(idx) {
+            Some(SqlValue::Text(s)) => Some(s.clone()),
+            _ => None,
+        };
+        let integer = |idx: usize| match row.get(idx) {
+            Some(SqlValue::Integer(i)) => *i,
+            _ => 0,
+        };
+
+        let jid =
+            text(0).ok_or_else(|| StorageError::QueryFailed("missing jid column".to_string()))?;
+        let kind = match text(1).as_deref() {
+            Some("groupchat") => ConversationKind::Groupchat,
+            _ => ConversationKind::Chat,
+        };
+        let timestamp = |idx: usize, column: &str| {
+            text(idx)
+                .map(|s| {
+                    DateTime::parse_from_rfc3339(&s)
+                        .map(|dt| dt.with_timezone(&Utc))
+                        .map_err(|e| StorageError::QueryFailed(format!("invalid {column}: {e}")))
+                })
+                .transpose()
+        };
+        let last_activity = timestamp(4, "last_activity")?;
+        let muted_until = timestamp(9, "muted_until")?;
+        let muted = integer(8) != 0 && muted_until.is_none_or(|until| until > Utc::now());
+
+        Ok(Conversation {
+            jid,
+            kind,
+            last_message_preview: text(2),
+            last_message_from: text(3),
+            last_activity,
+            unread_count: u32::try_from(integer(5)).unwrap_or(0),
+            pinned: integer(6) != 0,
+            archived: integer(7) != 0,
+            muted,
+            muted_until,
+        })
+    }
+}
+
+/// Maintains the conversation list from message and MUC events so every
+/// frontend renders the same sidebar.
+pub struct ConversationManager<D: Database> {
+    db: Arc<D>,
+    #[cfg(feature = "native")]
+    event_bus: Arc<dyn EventBus>,
+    /// Publishes archive and mute flags to our PEP service, once set.
+    #[cfg(feature = "native")]
+    pubsub: RwLock<Option<Arc<PubSubManager>>>,
+    /// Our bare JID while connected.
+    #[cfg(feature = "native")]
+    own_jid: RwLock<Option<String>>,
+    /// Conversations whose flags changed without reaching the PEP node,
+    /// published again on the next connection.
+    #[cfg(feature = "native")]
+    unsynced_flags: Arc<Mutex<HashSet<String>>>,
+}
+
+impl<D: Database> ConversationManager<D> {
+    #[cfg(feature = "native")]
+    pub fn new(db: Arc<D>, event_bus: Arc<dyn EventBus>) -> Self {
+        Self {
+            db,
+            event_bus,
+            pubsub: RwLock::new(None),
+            own_jid: RwLock::new(None),
+            unsynced_flags: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Sync archive and mute flags with our other devices through `pubsub`.
+    #[cfg(feature = "native")]
+    pub fn set_pubsub(&self, pubsub: Arc<PubSubManager>) {
+        *self.pubsub.write().unwrap() = Some(pubsub);
+    }
+
+    /// Conversations that are not archived, pinned first, then by most
+    /// recent activity.
+    pub async fn list_conversations(&self) -> Result<Vec<Conversation>, MessagingError> {
+        self.list_by_archived(false).await
+    }
+
+    /// Archived conversations, in the same order as [`list_conversations`](Self::list_conversations).
+    pub async fn list_archived_conversations(&self) -> Result<Vec<Conversation>, MessagingError> {
+        self.list_by_archived(true).await
+    }
+
+    async fn list_by_archived(&self, archived: bool) -> Result<Vec<Conversation>, MessagingError> {
+        let archived = i64::from(archived);
+        let rows: Vec<Conversation> = self
+            .db
+            .query(
+                "SELECT jid, kind, last_message_preview, last_message_from, last_activity, \
+                 unread_count, pinned, archived, muted, muted_until FROM conversations \
+                 WHERE archived = ?1 \
+                 ORDER BY pinned DESC, last_activity IS NULL, last_activity DESC, jid",
+                &[&archived],
+            )
+            .await?;
+        Ok(rows)
+    }
+
+    pub async fn get_conversation(
+        &self,
+        jid: &str,
+    ) -> Result<Option<Conversation>, MessagingError> {
+        let jid = Jid::new(jid);
+        let result: Result<Conversation, StorageError> = self
+            .db
+            .query_one(
+                "SELECT jid, kind, last_message_preview, last_message_from, last_activity, \
+                 unread_count, pinned, archived, muted, muted_until FROM conversations \
+                 WHERE jid = ?1",
+                &[&jid],
+            )
+            .await;
+        match result {
+            Ok(conversation) => Ok(Some(conversation)),
+            Err(StorageError::NotFound) => Ok(None),
+            Err(e) => Err(MessagingError::Storage(e)),
+        }
+    }
+
+    pub async fn set_pinned(&self, jid: &str, pinned: bool) -> Result<(), MessagingError> {
+        self.set_flag(jid, "pinned", pinned).await
+    }
+
+    /// Archive or unarchive a conversation. A new inbound message
+    /// unarchives it again.
+    pub async fn set_archived(&self, jid: &str, archived: bool) -> Result<(), MessagingError> {
+        self.set_flag(jid, "archived", archived).await?;
+        self.publish_flags(&Jid::new(jid)).await
+    }
+
+    /// Mute notifications for a conversation, until `until` if given.
+    pub async fn mute_conversation(
+        &self,
+        jid: &str,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<(), MessagingError> {
+        self.set_muted(jid, true, until).await
+    }
+
+    pub async fn unmute_conversation(&self, jid: &str) -> Result<(), MessagingError> {
+        self.set_muted(jid, false, None).await
+    }
+
+    async fn set_muted(
+        &self,
+        jid: &str,
+        muted: bool,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<(), MessagingError> {
+        let jid = Jid::new(jid);
+        let muted_i = i64::from(muted);
+        let until_s = until.map(|until| until.to_rfc3339());
+        let affected = self
+            .db
+            .execute(
+                "UPDATE conversations SET muted = ?1, muted_until = ?2 WHERE jid = ?3",
+                &[&muted_i, &until_s, &jid],
+            )
+            .await?;
+        if affected == 0 {
+            return Err(MessagingError::ConversationNotFound(jid.into_string()));
+        }
+        self.emit_updated(&jid);
+        self.emit_mute_changed(&jid, muted, until);
+        self.publish_flags(&jid).await
+    }
+
+    pub async fn mark_read(&self, jid: &str) -> Result<(), MessagingError> {
+        let jid = Jid::new(jid);
+        let affected = self
+            .db
+            .execute(
+                "UPDATE conversations SET unread_count = 0 WHERE jid = ?1 AND unread_count > 0",
+                &[&jid],
+            )
+            .await?;
+        if affected > 0 {
+            self.emit_updated(&jid);
+        }
+        Ok(())
+    }
+
+    /// Lower the unread count of `jid` to what another device left unread.
+    /// Counts are never raised, as this device may have read further.
+    async fn sync_unread(&self, jid: &str, unread_count: u32) -> Result<(), MessagingError> {
+        let jid = Jid::new(jid);
+        let unread = i64::from(unread_count);
+        let affected = self
+            .db
+            .execute(
+                "UPDATE conversations SET unread_count = ?1 WHERE jid = ?2 AND unread_count > ?1",
+                &[&unread, &jid],
+            )
+            .await?;
+        if affected > 0 {
+            self.emit_updated(&jid);
+        }
+        Ok(())
+    }
+
+    /// Fold conversations whose JIDs differ only by case or resource into
+    /// their [`canonical_jid`](crate::canonical_jid), moving history and
+    /// related rows across. Safe to run repeatedly.
+    pub async fn merge_duplicates(&self) -> Result<MergeReport, MessagingError> {
+        let report = merge_duplicate_conversations(self.db.as_ref()).await?;
+        for merged in &report.merged {
+            self.emit_updated(&merged.jid);
+            for variant in &merged.merged_from {
+                self.emit_updated(variant);
+            }
+        }
+        Ok(report)
+    }
+
+    async fn set_flag(&self, jid: &str, column: &str, value: bool) -> Result<(), MessagingError> {
+        let jid = Jid::new(jid);
+        let value = i64::from(value);
+        let affected = self
+            .db
+            .execute(
+                &format!("UPDATE conversations SET {column} = ?1 WHERE jid = ?2"),
+                &[&value, &jid],
+            )
+            .await?;
+        if affected == 0 {
+            return Err(MessagingError::ConversationNotFound(jid.into_string()));
+        }
+        self.emit_updated(&jid);
+        Ok(())
+    }
+
+    async fn ensure_room(&self, room: &str) -> Result<(), MessagingError> {
+        let room = Jid::new(room);
+        let kind = ConversationKind::Groupchat.as_str().to_string();
+        let affected = self
+            .db
+            .execute(
+                "INSERT OR IGNORE INTO conversations (jid, kind) VALUES (?1, ?2)",
+                &[&room, &kind],
+            )
+            .await?;
+        if affected > 0 {
+            self.emit_updated(&room);
+        }
+        Ok(())
+    }
+
+    /// Record `message` as activity in the conversation with `jid`. The
+    /// preview only moves forward in time, so late archive results do not
+    /// replace a newer live message.
+    async fn record_message(
+        &self,
+        jid: &Jid,
+        kind: ConversationKind,
+        from: &str,
+        message: &ChatMessage,
+        unread: bool,
+    ) -> Result<(), MessagingError> {
+        let kind_s = kind.as_str().to_string();
+        let preview = preview(&message.body);
+        let activity = message.timestamp.to_rfc3339();
+        let unread_i = i64::from(unread);
+
+        self.db
+            .execute(
+                "INSERT INTO conversations (jid, kind, last_message_id, last_message_preview, \
+                 last_message_from, last_activity, unread_count) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) \
+                 ON CONFLICT (jid) DO UPDATE SET \
+                 last_message_id = IIF(excluded.last_activity >= IFNULL(last_activity, ''), \
+                     excluded.last_message_id, last_message_id), \
+                 last_message_preview = IIF(excluded.last_activity >= IFNULL(last_activity, ''), \
+                     excluded.last_message_preview, last_message_preview), \
+                 last_message_from = IIF(excluded.last_activity >= IFNULL(last_activity, ''), \
+                     excluded.last_message_from, last_message_from), \
+                 last_activity = MAX(excluded.last_activity, IFNULL(last_activity, '')), \
+                 unread_count = IIF(last_message_id IS excluded.last_message_id, \
+                     unread_count, unread_count + excluded.unread_count), \
+                 archived = IIF(excluded.unread_count > 0, 0, archived)",
+                &[
+                    jid,
+                    &kind_s,
+                    &message.id,
+                    &preview,
+                    &from.to_string(),
+                    &activity,
+                    &unread_i,
+                ],
+            )
+            .await?;
+
+        self.emit_updated(jid);
+        Ok(())
+    }
+
+    async fn own_room_nick(&self, room: &str) -> Result<Option<String>, MessagingError> {
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT nick FROM muc_rooms WHERE room_jid = ?1",
+                &[&Jid::new(room)],
+            )
+            .await?;
+        Ok(rows.first().and_then(|row| match row.get(0) {
+            Some(SqlValue::Text(nick)) => Some(nick.clone()),
+            _ => None,
+        }))
+    }
+
+    #[cfg(feature = "native")]
+    fn emit_updated(&self, jid: &str) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channels::SYSTEM_CONVERSATION_UPDATED).unwrap(),
+            EventSource::System("conversations".into()),
+            EventPayload::ConversationUpdated {
+                jid: jid.to_string(),
+            },
+        ));
+    }
+
+    #[cfg(not(feature = "native"))]
+    fn emit_updated(&self, _jid: &str) {}
+
+    #[cfg(feature = "native")]
+    fn emit_mute_changed(&self, jid: &str, muted: bool, muted_until: Option<DateTime<Utc>>) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channels::SYSTEM_CONVERSATION_MUTE_CHANGED).unwrap(),
+            EventSource::System("conversations".into()),
+            EventPayload::ConversationMuteChanged {
+                jid: jid.to_string(),
+                muted,
+                muted_until,
+            },
+        ));
+    }
+
+    #[cfg(not(feature = "native"))]
+    fn emit_mute_changed(&self, _jid: &str, _muted: bool, _muted_until: Option<DateTime<Utc>>) {}
+
+    /// Publish the archive and mute flags of `jid` to our PEP node, or keep
+    /// them for the next connection while offline.
+    #[cfg(feature = "native")]
+    async fn publish_flags(&self, jid: &Jid) -> Result<(), MessagingError> {
+        let pubsub = self.pubsub.read().unwrap().clone();
+        let Some(pubsub) = pubsub else {
+            return Ok(());
+        };
+        if self.own_jid.read().unwrap().is_none() {
+            self.unsynced_flags
+                .lock()
+                .unwrap()
+                .insert(jid.as_str().to_string());
+            return Ok(());
+        }
+        let flags = self.stored_flags(jid).await?;
+        let jid = jid.as_str().to_string();
+        let unsynced = self.unsynced_flags.clone();
+        // Only the PubSubManager moves into the task: database futures are
+        // not Send.
+        tokio::spawn(async move {
+            let payload = flags_to_element(&flags);
+            if let Err(e) = pubsub
+                .publish(
+                    None,
+                    CONVERSATION_FLAGS_NODE,
+                    Some(&jid),
+                    payload,
+                    CONVERSATION_FLAGS_OPTIONS,
+                )
+                .await
+            {
+                warn!(error = %e, jid, "failed to publish conversation flags");
+                unsynced.lock().unwrap().insert(jid);
+            }
+        });
+        Ok(())
+    }
+
+    #[cfg(not(feature = "native"))]
+    async fn publish_flags(&self, _jid: &Jid) -> Result<(), MessagingError> {
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    async fn stored_flags(&self, jid: &Jid) -> Result<ConversationFlags, MessagingError> {
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT archived, muted, muted_until FROM conversations WHERE jid = ?1",
+                &[jid],
+            )
+            .await?;
+        let Some(row) = rows.first() else {
+            return Err(MessagingError::ConversationNotFound(
+                jid.as_str().to_string(),
+            ));
+        };
+        let flag = |idx: usize| matches!(row.get(idx), Some(SqlValue::Integer(i)) if *i != 0);
+        let muted_until = match row.get(2) {
+            Some(SqlValue::Text(until)) => DateTime::parse_from_rfc3339(until)
+                .ok()
+                .map(|until| until.with_timezone(&Utc)),
+            _ => None,
+        };
+        Ok(ConversationFlags {
+            archived: flag(0),
+            muted: flag(1),
+            muted_until,
+        })
+    }
+
+    /// Store flags published by another device. Conversations this device
+    /// does not know yet are left alone.
+    #[cfg(feature = "native")]
+    async fn apply_flags(
+        &self,
+        jid: &str,
+        flags: &ConversationFlags,
+    ) -> Result<(), MessagingError> {
+        let jid = Jid::new(jid);
+        let archived = i64::from(flags.archived);
+        let muted = i64::from(flags.muted);
+        let muted_until = flags.muted_until.map(|until| until.to_rfc3339());
+        let affected = self
+            .db
+            .execute(
+                "UPDATE conversations SET archived = ?1, muted = ?2, muted_until = ?3 \
+                 WHERE jid = ?4 AND (archived != ?1 OR muted != ?2 OR muted_until IS NOT ?3)",
+                &[&archived, &muted, &muted_until, &jid],
+            )
+            .await?;
+        if affected > 0 {
+            self.emit_updated(&jid);
+            self.emit_mute_changed(&jid, flags.muted, flags.muted_until);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    async fn apply_flag_items(&self, items: &[PubSubItem]) -> Result<(), MessagingError> {
+        for item in items {
+            let Some(jid) = item.id.as_deref() else {
+                continue;
+            };
+            if self.unsynced_flags.lock().unwrap().contains(jid) {
+                continue;
+            }
+            match item_payload(item).map(|payload| flags_from_element(&payload)) {
+                Some(Ok(flags)) => self.apply_flags(jid, &flags).await?,
+                Some(Err(e)) => debug!(error = %e, jid, "ignoring invalid conversation flags"),
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// On connecting, take the flags other devices published, then publish
+    /// the ones changed here while offline.
+    #[cfg(feature = "native")]
+    async fn sync_flags(&self) -> Result<(), MessagingError> {
+        let pubsub = self.pubsub.read().unwrap().clone();
+        let Some(pubsub) = pubsub else {
+            return Ok(());
+        };
+        match pubsub.retrieve(None, CONVERSATION_FLAGS_NODE, None).await {
+            Ok(items) => self.apply_flag_items(&items).await?,
+            Err(e) => debug!(error = %e, "no conversation flags to sync"),
+        }
+        let unsynced: Vec<String> = self.unsynced_flags.lock().unwrap().drain().collect();
+        for jid in unsynced {
+            match self.publish_flags(&Jid::new(&jid)).await {
+                Ok(()) | Err(MessagingError::ConversationNotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Flags notifications come from our own PEP service, whose address the
+    /// server may leave out.
+    #[cfg(feature = "native")]
+    fn is_own_pep(&self, from: &str) -> bool {
+        from.is_empty()
+            || self
+                .own_jid
+                .read()
+                .unwrap()
+                .as_deref()
+                .is_some_and(|own| Jid::new(from).bare().as_str() == own)
+    }
+
+    #[cfg(feature = "native")]
+    pub async fn handle_event(&self, event: &Event) {
+        let result = match &event.payload {
+            EventPayload::MessageReceived { message } if is_chat(message) => {
+                let peer = Jid::new(&message.from).bare();
+                self.record_message(&peer, ConversationKind::Chat, &peer, message, true)
+                    .await
+            }
+            EventPayload::MessageSent { message } if is_chat(message) => {
+                let peer = Jid::new(&message.to).bare();
+                self.record_message(&peer, ConversationKind::Chat, "", message, false)
+                    .await
+            }
+            EventPayload::ReadStateSynced {
+                conversation,
+                unread_count,
+            } => self.sync_unread(conversation, *unread_count).await,
+            EventPayload::ConnectionEstablished { jid } => {
+                *self.own_jid.write().unwrap() = Some(Jid::new(jid).bare().into_string());
+                self.sync_flags().await
+            }
+            EventPayload::ConnectionLost { .. } => {
+                *self.own_jid.write().unwrap() = None;
+                Ok(())
+            }
+            EventPayload::PubSubItemsReceived { from, node, items }
+                if node == CONVERSATION_FLAGS_NODE && self.is_own_pep(from) =>
+            {
+                self.apply_flag_items(items).await
+            }
+            EventPayload::PubSubItemsRetracted { from, node, ids }
+                if node == CONVERSATION_FLAGS_NODE && self.is_own_pep(from) =>
+            {
+                let mut result = Ok(());
+                for jid in ids {
+                    result = self.apply_flags(jid, &ConversationFlags::default()).await;
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                result
+            }
+            EventPayload::MucJoined { room, .. } => self.ensure_room(room).await,
+            EventPayload::MucMessageReceived { room, message } => {
+                let nick = message.from.split_once('/').map(|(_, n)| n).unwrap_or("");
+                match self.own_room_nick(room).await {
+                    Ok(own_nick) => {
+                        let unread = own_nick.as_deref() != Some(nick);
+                        self.record_message(
+                            &Jid::new(room),
+                            ConversationKind::Groupchat,
+                            nick,
+                            message,
+                            unread,
+                        )
+                        .await
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            _ => Ok(()),
+        };
+
+        if let Err(e) = result {
+            error!(error = %e, channel = %event.channel, "failed to update conversation list");
+        }
+    }
+
+    #[cfg(feature = "native")]
+    pub async fn run(self: Arc<Self>) -> Result<(), MessagingError> {
+        let mut sub = self
+            .event_bus
+            .subscribe("{system,xmpp}.**")
+            .map_err(|e| MessagingError::EventBus(e.to_string()))?;
+
+        loop {
+            match sub.recv().await {
+                Ok(event) => {
+                    self.handle_event(&event).await;
+                }
+                Err(waddle_core::error::EventBusError::ChannelClosed) => {
+                    debug!("event bus closed, conversation manager stopping");
+                    return Ok(());
+                }
+                Err(waddle_core::error::EventBusError::Lagged(count)) => {
+                    warn!(count, "conversation manager lagged, some events dropped");
+                }
+                Err(e) => {
+                    error!(error = %e, "conversation manager subscription error");
+                    return Err(MessagingError::EventBus(e.to_string()));
+                }
+            }
+        }
+    }
+}
+
+fn is_chat(message: &ChatMessage) -> bool {
+    matches!(
+        message.message_type,
+        MessageType::Chat | MessageType::Normal
+    ) && !message.body.is_empty()
+}
+
+fn preview(body: &str) -> String {
+    let line = body.lines().next().unwrap_or("");
+    match line.char_indices().nth(PREVIEW_MAX_CHARS) {
+        Some((idx, _)) => format!("{}…", &line[..idx]),
+        None => line.to_string(),
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+    use waddle_core::event::BroadcastEventBus;
+
+    async fn setup() -> (
+        Arc<ConversationManager<impl Database>>,
+        Arc<dyn EventBus>,
+        TempDir,
+    ) {
+        let dir = TempDir::new().expect("failed to create temp dir");
+        let db = waddle_storage::open_database(&dir.path().join("test.db"))
+            .await
+            .expect("failed to open database");
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let manager = Arc::new(ConversationManager::new(Arc::new(db), event_bus.clone()));
+        (manager, event_bus, dir)
+    }
+
+    fn make_event(channel: &str, payload: EventPayload) -> Event {
+        Event::new(Channel::new(channel).unwrap(), EventSource::Xmpp, payload)
+    }
+
+    fn message(id: &str, from: &str, to: &str, body: &str, seconds_ago: i64) -> ChatMessage {
+        ChatMessage {
+            id: id.to_string(),
+            from: from.to_string(),
+            to: to.to_string(),
+            body: body.to_string(),
+            timestamp: Utc::now() - chrono::Duration::seconds(seconds_ago),
+            message_type: MessageType::Chat,
+            thread: None,
+            embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
+        }
+    }
+
+    fn received(message: ChatMessage) -> Event {
+        make_event(
+            "xmpp.message.received",
+            EventPayload::MessageReceived { message },
+        )
+    }
+
+    #[tokio::test]
+    async fn read_state_from_another_device_only_lowers_unread_count() {
+        let (manager, _, _dir) = setup().await;
+        for (id, seconds_ago) in [("m1", 30), ("m2", 20), ("m3", 10)] {
+            manager
+                .handle_event(&received(message(
+                    id,
+                    "bob@example.com",
+                    "me@example.com",
+                    id,
+                    seconds_ago,
+                )))
+                .await;
+        }
+
+        let synced = |unread_count| {
+            make_event(
+                "system.conversation.read_synced",
+                EventPayload::ReadStateSynced {
+                    conversation: "bob@example.com".to_string(),
+                    unread_count,
+                },
+            )
+        };
+        manager.handle_event(&synced(1)).await;
+        let bob = manager
+            .get_conversation("bob@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(bob.unread_count, 1);
+
+        manager.handle_event(&synced(2)).await;
+        let bob = manager
+            .get_conversation("bob@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(bob.unread_count, 1);
+    }
+
+    #[tokio::test]
+    async fn case_variants_of_a_jid_share_one_conversation() {
+        let (manager, _, _dir) = setup().await;
+
+        manager
+            .handle_event(&received(message(
+                "m1",
+                "Bob@Example.COM/Laptop",
+                "me@example.com",
+                "hi",
+                20,
+            )))
+            .await;
+        manager
+            .handle_event(&received(message(
+                "m2",
+                "bob@example.com/phone",
+                "me@example.com",
+                "again",
+                10,
+            )))
+            .await;
+
+        let list = manager.list_conversations().await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].jid, "bob@example.com");
+        assert_eq!(list[0].unread_count, 2);
+
+        manager.mark_read("BOB@example.com").await.unwrap();
+        let conversation = manager
+            .get_conversation("Bob@Example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(conversation.unread_count, 0);
+    }
+
+    #[tokio::test]
+    async fn chat_messages_drive_preview_and_unread() {
+        let (manager, event_bus, _dir) = setup().await;
+        let mut sub = event_bus.subscribe("system.conversation.updated").unwrap();
+
+        manager
+            .handle_event(&received(message(
+                "m1",
+                "alice@example.com/phone",
+                "me@example.com",
+                "hi",
+                20,
+            )))
+            .await;
+        manager
+            .handle_event(&received(message(
+                "m2",
+                "alice@example.com/phone",
+                "me@example.com",
+                "there?",
+                10,
+            )))
+            .await;
+        // Duplicate delivery of the latest message is not counted twice.
+        manager
+            .handle_event(&received(message(
+                "m2",
+                "alice@example.com/phone",
+                "me@example.com",
+                "there?",
+                10,
+            )))
+            .await;
+        manager
+            .handle_event(&make_event(
+                "xmpp.message.sent",
+                EventPayload::MessageSent {
+                    message: message("m3", "", "bob@example.com", "line one\nline two", 5),
+                },
+            ))
+            .await;
+
+        let event = tokio::time::timeout(Duration::from_millis(100), sub.recv())
+            .await
+            .expect("timed out")
+            .unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::ConversationUpdated { ref jid } if jid == "alice@example.com"
+        ));
+
+        let list = manager.list_conversations().await.unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].jid, "bob@example.com");
+        assert_eq!(list[0].last_message_preview.as_deref(), Some("line one"));
+        assert_eq!(list[0].unread_count, 0);
+        assert_eq!(list[1].jid, "alice@example.com");
+        assert_eq!(list[1].last_message_preview.as_deref(), Some("there?"));
+        assert_eq!(
+            list[1].last_message_from.as_deref(),
+            Some("alice@example.com")
+        );
+        assert_eq!(list[1].unread_count, 2);
+
+        manager.mark_read("alice@example.com").await.unwrap();
+        let alice = manager
+            .get_conversation("alice@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(alice.unread_count, 0);
+    }
+
+    #[tokio::test]
+    async fn older_message_does_not_replace_preview() {
+        let (manager, _, _dir) = setup().await;
+        manager
+            .handle_event(&received(message(
+                "new",
+                "alice@example.com",
+                "me@example.com",
+                "latest",
+                1,
+            )))
+            .await;
+        manager
+            .handle_event(&received(message(
+                "old",
+                "alice@example.com",
+                "me@example.com",
+                "stale",
+                60,
+            )))
+            .await;
+
+        let alice = manager
+            .get_conversation("alice@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(alice.last_message_preview.as_deref(), Some("latest"));
+        assert_eq!(alice.unread_count, 2);
+    }
+
+    #[tokio::test]
+    async fn pinned_first_and_inbound_message_unarchives() {
+        let (manager, _, _dir) = setup().await;
+        manager
+            .handle_event(&received(message(
+                "a1",
+                "alice@example.com",
+                "me@example.com",
+                "a",
+                30,
+            )))
+            .await;
+        manager
+            .handle_event(&received(message(
+                "b1",
+                "bob@example.com",
+                "me@example.com",
+                "b",
+                10,
+            )))
+            .await;
+
+        manager.set_pinned("alice@example.com", true).await.unwrap();
+        manager.set_archived("bob@example.com", true).await.unwrap();
+
+        let list = manager.list_conversations().await.unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].jid, "alice@example.com");
+        assert!(list[0].pinned);
+        let archived = manager.list_archived_conversations().await.unwrap();
+        assert_eq!(archived.len(), 1);
+        assert_eq!(archived[0].jid, "bob@example.com");
+        assert!(archived[0].archived);
+
+        manager
+            .handle_event(&received(message(
+                "b2",
+                "bob@example.com",
+                "me@example.com",
+                "ping",
+                0,
+            )))
+            .await;
+        let bob = manager
+            .get_conversation("bob@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!bob.archived);
+
+        let result = manager.set_pinned("nobody@example.com", true).await;
+        assert!(matches!(
+            result,
+            Err(MessagingError::ConversationNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn muting_expires_and_announces_changes() {
+        let (manager, event_bus, _dir) = setup().await;
+        let mut sub = event_bus
+            .subscribe("system.conversation.mute_changed")
+            .unwrap();
+        manager
+            .handle_event(&received(message(
+                "m1",
+                "alice@example.com",
+                "me@example.com",
+                "hi",
+                10,
+            )))
+            .await;
+
+        let until = Utc::now() + chrono::Duration::hours(1);
+        manager
+            .mute_conversation("Alice@example.com", Some(until))
+            .await
+            .unwrap();
+        let alice = manager
+            .get_conversation("alice@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(alice.muted);
+        let event = tokio::time::timeout(Duration::from_millis(100), sub.recv())
+            .await
+            .expect("timed out")
+            .unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::ConversationMuteChanged { ref jid, muted: true, muted_until: Some(_) }
+                if jid == "alice@example.com"
+        ));
+
+        manager
+            .mute_conversation(
+                "alice@example.com",
+                Some(Utc::now() - chrono::Duration::minutes(1)),
+            )
+            .await
+            .unwrap();
+        let alice = manager
+            .get_conversation("alice@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!alice.muted);
+
+        manager
+            .mute_conversation("alice@example.com", None)
+            .await
+            .unwrap();
+        manager
+            .unmute_conversation("alice@example.com")
+            .await
+            .unwrap();
+        let alice = manager
+            .get_conversation("alice@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!alice.muted);
+        assert!(alice.muted_until.is_none());
+
+        let result = manager.mute_conversation("nobody@example.com", None).await;
+        assert!(matches!(
+            result,
+            Err(MessagingError::ConversationNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn flags_from_other_devices_apply_to_known_conversations() {
+        let (manager, _, _dir) = setup().await;
+        manager
+            .handle_event(&received(message(
+                "m1",
+                "alice@example.com",
+                "me@example.com",
+                "hi",
+                10,
+            )))
+            .await;
+        manager
+            .handle_event(&make_event(
+                "system.connection.established",
+                EventPayload::ConnectionEstablished {
+                    jid: "me@example.com/desktop".to_string(),
+                },
+            ))
+            .await;
+
+        let item = |jid: &str, payload: &str| PubSubItem {
+            id: Some(jid.to_string()),
+            publisher: None,
+            payload: Some(payload.to_string()),
+        };
+        let notification = |from: &str| {
+            make_event(
+                "xmpp.pubsub.items",
+                EventPayload::PubSubItemsReceived {
+                    from: from.to_string(),
+                    node: CONVERSATION_FLAGS_NODE.to_string(),
+                    items: vec![
+                        item(
+                            "alice@example.com",
+                            "<conversation xmlns='urn:waddle:conversation-flags:0' \
+                             archived='true' muted='true'/>",
+                        ),
+                        item(
+                            "unknown@example.com",
+                            "<conversation xmlns='urn:waddle:conversation-flags:0' \
+                             archived='true'/>",
+                        ),
+                    ],
+                },
+            )
+        };
+
+        // Someone else's node of the same name is not ours to follow.
+        manager
+            .handle_event(&notification("mallory@example.com"))
+            .await;
+        assert_eq!(manager.list_conversations().await.unwrap().len(), 1);
+
+        manager.handle_event(&notification("me@example.com")).await;
+        assert!(manager.list_conversations().await.unwrap().is_empty());
+        let archived = manager.list_archived_conversations().await.unwrap();
+        assert_eq!(archived.len(), 1);
+        assert!(archived[0].muted);
+        assert!(
+            manager
+                .get_conversation("unknown@example.com")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        manager
+            .handle_event(&make_event(
+                "xmpp.pubsub.retracted",
+                EventPayload::PubSubItemsRetracted {
+                    from: String::new(),
+                    node: CONVERSATION_FLAGS_NODE.to_string(),
+                    ids: vec!["alice@example.com".to_string()],
+                },
+            ))
+            .await;
+        let alice = manager
+            .get_conversation("alice@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!alice.archived);
+        assert!(!alice.muted);
+    }
+
+    #[tokio::test]
+    async fn muc_rooms_listed_on_join_and_own_messages_stay_read() {
+        let (manager, _, _dir) = setup().await;
+        let room = "room@conference.example.com";
+        manager
+            .db
+            .execute(
+                "INSERT INTO muc_rooms (room_jid, nick, joined) VALUES (?1, ?2, 1)",
+                &[&room.to_string(), &"me".to_string()],
+            )
+            .await
+            .unwrap();
+
+        manager
+            .handle_event(&make_event(
+                "xmpp.muc.joined",
+                EventPayload::MucJoined {
+                    room: room.to_string(),
+                    nick: "me".to_string(),
+                },
+            ))
+            .await;
+        let joined = manager.get_conversation(room).await.unwrap().unwrap();
+        assert_eq!(joined.kind, ConversationKind::Groupchat);
+        assert!(joined.last_activity.is_none());
+
+        for (id, nick) in [("r1", "alice"), ("r2", "me")] {
+            let mut msg = message(id, &format!("{room}/{nick}"), room, "hello", 0);
+            msg.message_type = MessageType::Groupchat;
+            manager
+                .handle_event(&make_event(
+                    "xmpp.muc.message.received",
+                    EventPayload::MucMessageReceived {
+                        room: room.to_string(),
+                        message: msg,
+                    },
+                ))
+                .await;
+        }
+
+        let room_conv = manager.get_conversation(room).await.unwrap().unwrap();
+        assert_eq!(room_conv.unread_count, 1);
+        assert_eq!(room_conv.last_message_from.as_deref(), Some("me"));
+    }
+
+    #[tokio::test]
+    async fn merge_duplicates_folds_case_and_resource_variants() {
+        let (manager, event_bus, _dir) = setup().await;
+        let mut sub = event_bus.subscribe("system.conversation.updated").unwrap();
+
+        for (jid, preview, activity, unread, pinned, archived) in [
+            (
+                "Alice@Example.com",
+                "older",
+                "2024-01-01T10:00:00+00:00",
+                2,
+                1,
+                0,
+            ),
+            (
+                "alice@example.com/phone",
+                "newest",
+                "2024-01-03T10:00:00+00:00",
+                1,
+                0,
+                1,
+            ),
+            (
+                "alice@example.com",
+                "middle",
+                "2024-01-02T10:00:00+00:00",
+                0,
+                0,
+                1,
+            ),
+            (
+                "bob@example.com",
+                "untouched",
+                "2024-01-02T10:00:00+00:00",
+                4,
+                0,
+                0,
+            ),
+        ] {
+            manager
+                .db
+                .execute(
+                    "INSERT INTO conversations (jid, kind, last_message_preview, last_activity, \
+                     unread_count, pinned, archived) VALUES (?1, 'chat', ?2, ?3, ?4, ?5, ?6)",
+                    &[
+                        &jid.to_string(),
+                        &preview.to_string(),
+                        &activity.to_string(),
+                        &(unread as i64),
+                        &(pinned as i64),
+                        &(archived as i64),
+                    ],
+                )
+                .await
+                .unwrap();
+        }
+        for (id, from, to) in [
+            ("m1", "Alice@Example.com", "me@example.com"),
+            ("m2", "me@example.com", "alice@example.com/phone"),
+            ("m3", "Alice@Example.com/Laptop", "me@example.com"),
+        ] {
+            manager
+                .db
+                .execute(
+                    "INSERT INTO messages (id, from_jid, to_jid, body, timestamp, message_type) \
+                     VALUES (?1, ?2, ?3, 'hi', '2024-01-01T10:00:00+00:00', 'chat')",
+                    &[&id.to_string(), &from.to_string(), &to.to_string()],
+                )
+                .await
+                .unwrap();
+        }
+        manager
+            .db
+            .execute(
+                "INSERT INTO roster (jid, subscription) VALUES ('Alice@Example.com', 'both')",
+                &[],
+            )
+            .await
+            .unwrap();
+
+        let report = manager.merge_duplicates().await.unwrap();
+        assert_eq!(report.merged.len(), 1);
+        let merged = &report.merged[0];
+        assert_eq!(merged.jid, "alice@example.com");
+        assert_eq!(
+            merged.merged_from,
+            vec!["Alice@Example.com", "alice@example.com/phone"]
+        );
+        assert_eq!(merged.messages_rewritten, 3);
+
+        let list = manager.list_conversations().await.unwrap();
+        assert_eq!(list.len(), 2);
+        let alice = manager
+            .get_conversation("alice@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(alice.last_message_preview.as_deref(), Some("newest"));
+        assert_eq!(alice.unread_count, 3);
+        assert!(alice.pinned);
+        assert!(!alice.archived);
+
+        let rows: Vec<Row> = manager
+            .db
+            .query("SELECT from_jid, to_jid FROM messages ORDER BY id", &[])
+            .await
+            .unwrap();
+        let jids: Vec<(SqlValue, SqlValue)> = rows
+            .iter()
+            .map(|row| (row.get(0).unwrap().clone(), row.get(1).unwrap().clone()))
+            .collect();
+        assert_eq!(
+            jids,
+            vec![
+                (
+                    SqlValue::Text("alice@example.com".into()),
+                    SqlValue::Text("me@example.com".into())
+                ),
+                (
+                    SqlValue::Text("me@example.com".into()),
+                    SqlValue::Text("alice@example.com".into())
+                ),
+                (
+                    SqlValue::Text("alice@example.com/Laptop".into()),
+                    SqlValue::Text("me@example.com".into())
+                ),
+            ]
+        );
+        let roster: Vec<Row> = manager
+            .db
+            .query("SELECT jid FROM roster", &[])
+            .await
+            .unwrap();
+        assert_eq!(
+            roster[0].get(0),
+            Some(&SqlValue::Text("alice@example.com".into()))
+        );
+
+        let event = tokio::time::timeout(Duration::from_millis(100), sub.recv())
+            .await
+            .expect("timed out")
+            .unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::ConversationUpdated { ref jid } if jid == "alice@example.com"
+        ));
+
+        assert!(manager.merge_duplicates().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn merge_duplicates_folds_history_without_a_conversation_row() {
+        let (manager, _, _dir) = setup().await;
+
+        manager
+            .db
+            .execute(
+                "INSERT INTO conversations (jid, kind, unread_count) VALUES \
+                 ('bob@example.com', 'chat', 1)",
+                &[],
+            )
+            .await
+            .unwrap();
+        for (id, from, to) in [
+            ("m1", "Bob@Example.com/desk", "me@example.com"),
+            ("m2", "me@example.com", "CAROL@example.com"),
+        ] {
+            manager
+                .db
+                .execute(
+                    "INSERT INTO messages (id, from_jid, to_jid, body, timestamp, message_type) \
+                     VALUES (?1, ?2, ?3, 'hi', '2024-01-01T10:00:00+00:00', 'chat')",
+                    &[&id.to_string(), &from.to_string(), &to.to_string()],
+                )
+                .await
+                .unwrap();
+        }
+
+        let report = manager.merge_duplicates().await.unwrap();
+        let merged: Vec<(&str, Vec<String>, u64)> = report
+            .merged
+            .iter()
+            .map(|merged| {
+                (
+                    merged.jid.as_str(),
+                    merged.merged_from.clone(),
+                    merged.messages_rewritten,
+                )
+            })
+            .collect();
+        assert_eq!(
+            merged,
+            vec![
+                ("bob@example.com", vec!["Bob@Example.com".to_string()], 1),
+                (
+                    "carol@example.com",
+                    vec!["CAROL@example.com".to_string()],
+                    1
+                ),
+            ]
+        );
+
+        let rows: Vec<Row> = manager
+            .db
+            .query("SELECT from_jid, to_jid FROM messages ORDER BY id", &[])
+            .await
+            .unwrap();
+        assert_eq!(
+            rows[0].get(0),
+            Some(&SqlValue::Text("bob@example.com/desk".into()))
+        );
+        assert_eq!(
+            rows[1].get(1),
+            Some(&SqlValue::Text("carol@example.com".into()))
+        );
+        let bob = manager
+            .get_conversation("bob@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(bob.unread_count, 1);
+        assert!(
+            manager
+                .get_conversation("carol@example.com")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        assert!(manager.merge_duplicates().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn preview_truncates_long_first_line() {
+        let long = "x".repeat(PREVIEW_MAX_CHARS + 10);
+        let shortened = preview(&long);
+        assert_eq!(shortened.chars().count(), PREVIEW_MAX_CHARS + 1);
+        assert!(shortened.ends_with('…'));
+        assert_eq!(preview("short\nsecond"), "short");
+    }
+}
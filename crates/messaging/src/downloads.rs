@@ -0,0 +1,583 @@
+//! Downloads of files shared in messages, usually links to XEP-0363 HTTP
+//! File Upload slots. Files are kept in a content-addressed cache, named by
+//! the SHA-256 of their contents, and the least recently used are deleted
+//! once the cache grows past its limit.
+//!
+//! An interrupted download keeps what it received under `partial/`, and
+//! the next attempt asks the server for the rest with an HTTP range
+//! request. Upload slots never change their content, so the bytes already
+//! received are not revalidated.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use reqwest::header::RANGE;
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::{debug, info, warn};
+
+use waddle_core::error::EventBusError;
+use waddle_core::event::{
+    Channel, ChatMessage, Event, EventBus, EventPayload, EventSource, channels,
+};
+
+use crate::MessagingError;
+
+const INDEX_FILE: &str = "index.json";
+const PARTIAL_DIR: &str = "partial";
+/// Progress is reported at most once per this many bytes.
+const PROGRESS_STEP: u64 = 256 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DownloadError {
+    #[error("not an http(s) URL: {0}")]
+    InvalidUrl(String),
+
+    #[error("already downloading {0}")]
+    InProgress(String),
+
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("server answered with status {0}")]
+    Status(u16),
+
+    #[error("file is larger than the download cache ({limit} bytes)")]
+    TooLarge { limit: u64 },
+
+    #[error("download cache error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// A file in the download cache.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedFile {
+    pub url: String,
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexEntry {
+    /// Name of the file in the cache directory.
+    file: String,
+    size: u64,
+    last_used: DateTime<Utc>,
+}
+
+/// Cached files keyed by URL. URLs with the same content share a file.
+type Index = BTreeMap<String, IndexEntry>;
+
+/// The URL a message shares, if it shares a file. XEP-0363 clients send
+/// the URL as the whole body, along with a XEP-0066 reference to it.
+pub fn attachment_url(message: &ChatMessage) -> Option<&str> {
+    let body = message.body.trim();
+    http_url(body).map(|_| body)
+}
+
+pub struct DownloadManager {
+    client: reqwest::Client,
+    cache_dir: PathBuf,
+    max_cache_bytes: u64,
+    event_bus: Arc<dyn EventBus>,
+    index: tokio::sync::Mutex<Index>,
+    in_flight: Mutex<HashSet<String>>,
+}
+
+impl DownloadManager {
+    pub fn new(
+        cache_dir: impl Into<PathBuf>,
+        max_cache_bytes: u64,
+        event_bus: Arc<dyn EventBus>,
+    ) -> Self {
+        let cache_dir = cache_dir.into();
+        let index = load_index(&cache_dir);
+        Self {
+            client: reqwest::Client::new(),
+            cache_dir,
+            max_cache_bytes,
+            event_bus,
+            index: tokio::sync::Mutex::new(index),
+            in_flight: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// The cached copy of `url`, if there is one.
+    pub async fn cached(&self, url: &str) -> Option<CachedFile> {
+        let mut index = self.index.lock().await;
+        let entry = index.get_mut(url)?;
+        let path = self.cache_dir.join(&entry.file);
+        if !path.is_file() {
+            return None;
+        }
+        entry.last_used = Utc::now();
+        Some(CachedFile {
+            url: url.to_string(),
+            path,
+            size: entry.size,
+        })
+    }
+
+    /// Download `url` into the cache, or return the cached copy. Publishes
+    /// `ui.transfer.progress` while downloading, then
+    /// `system.transfer.completed` or `system.transfer.failed`.
+    pub async fn download(&self, url: &str) -> Result<CachedFile, DownloadError> {
+        let parsed = http_url(url).ok_or_else(|| DownloadError::InvalidUrl(url.to_string()))?;
+        if let Some(cached) = self.cached(url).await {
+            return Ok(cached);
+        }
+        if !self.in_flight.lock().unwrap().insert(url.to_string()) {
+            return Err(DownloadError::InProgress(url.to_string()));
+        }
+
+        let result = self.fetch(url, &parsed).await;
+        self.in_flight.lock().unwrap().remove(url);
+
+        match &result {
+            Ok(file) => {
+                info!(url, size = file.size, "downloaded shared file");
+                self.emit(
+                    channels::SYSTEM_TRANSFER_COMPLETED,
+                    EventPayload::TransferCompleted {
+                        url: url.to_string(),
+                        path: file.path.display().to_string(),
+                        size: file.size,
+                    },
+                );
+            }
+            Err(error) => {
+                warn!(url, %error, "failed to download shared file");
+                self.emit(
+                    channels::SYSTEM_TRANSFER_FAILED,
+                    EventPayload::TransferFailed {
+                        url: url.to_string(),
+                        reason: error.to_string(),
+                    },
+                );
+            }
+        }
+        result
+    }
+
+    /// Download the files shared in incoming messages until the event bus
+    /// closes.
+    pub async fn run(self: Arc<Self>) -> Result<(), MessagingError> {
+        let mut sub = self
+            .event_bus
+            .subscribe(channels::XMPP_MESSAGE_RECEIVED)
+            .map_err(|e| MessagingError::EventBus(e.to_string()))?;
+
+        loop {
+            match sub.recv().await {
+                Ok(event) => {
+                    let EventPayload::MessageReceived { message } = event.payload else {
+                        continue;
+                    };
+                    let Some(url) = attachment_url(&message) else {
+                        continue;
+                    };
+                    let manager = self.clone();
+                    let url = url.to_string();
+                    // Failures are reported on the event bus.
+                    tokio::spawn(async move {
+                        let _ = manager.download(&url).await;
+                    });
+                }
+                Err(EventBusError::ChannelClosed) => {
+                    debug!("event bus closed, download manager stopping");
+                    return Ok(());
+                }
+                Err(EventBusError::Lagged(count)) => {
+                    warn!(count, "download manager lagged, some messages missed");
+                }
+                Err(e) => return Err(MessagingError::EventBus(e.to_string())),
+            }
+        }
+    }
+
+    async fn fetch(&self, url: &str, parsed: &Url) -> Result<CachedFile, DownloadError> {
+        let partial_dir = self.cache_dir.join(PARTIAL_DIR);
+        tokio::fs::create_dir_all(&partial_dir).await?;
+        let partial_path = partial_dir.join(format!("{:x}", Sha256::digest(url.as_bytes())));
+        let mut received = tokio::fs::metadata(&partial_path)
+            .await
+            .map_or(0, |metadata| metadata.len());
+
+        let mut request = self.client.get(url);
+        if received > 0 {
+            request = request.header(RANGE, format!("bytes={received}-"));
+        }
+        let mut response = request.send().await?;
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            // The partial file does not fit the resource; start over.
+            received = 0;
+            response = self.client.get(url).send().await?;
+        }
+        let status = response.status();
+        if !status.is_success() {
+            return Err(DownloadError::Status(status.as_u16()));
+        }
+
+        let mut hasher = Sha256::new();
+        let mut file = if status == StatusCode::PARTIAL_CONTENT && received > 0 {
+            hash_file(&partial_path, &mut hasher).await?;
+            tokio::fs::OpenOptions::new()
+                .append(true)
+                .open(&partial_path)
+                .await?
+        } else {
+            // The server sent the whole file.
+            received = 0;
+            tokio::fs::File::create(&partial_path).await?
+        };
+
+        let total = response.content_length().map(|length| received + length);
+        if total.is_some_and(|total| total > self.max_cache_bytes) {
+            drop(file);
+            let _ = tokio::fs::remove_file(&partial_path).await;
+            return Err(DownloadError::TooLarge {
+                limit: self.max_cache_bytes,
+            });
+        }
+
+        self.emit_progress(url, received, total);
+        let mut reported = received;
+        while let Some(chunk) = response.chunk().await? {
+            received += chunk.len() as u64;
+            if received > self.max_cache_bytes {
+                drop(file);
+                let _ = tokio::fs::remove_file(&partial_path).await;
+                return Err(DownloadError::TooLarge {
+                    limit: self.max_cache_bytes,
+                });
+            }
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+            if received - reported >= PROGRESS_STEP {
+                self.emit_progress(url, received, total);
+                reported = received;
+            }
+        }
+        file.flush().await?;
+        drop(file);
+        if reported != received {
+            self.emit_progress(url, received, total);
+        }
+
+        let hash = format!("{:x}", hasher.finalize());
+        let file_name = match extension(parsed) {
+            Some(extension) => format!("{hash}.{extension}"),
+            None => hash,
+        };
+        let path = self.cache_dir.join(&file_name);
+        tokio::fs::rename(&partial_path, &path).await?;
+
+        let mut index = self.index.lock().await;
+        index.insert(
+            url.to_string(),
+            IndexEntry {
+                file: file_name.clone(),
+                size: received,
+                last_used: Utc::now(),
+            },
+        );
+        self.evict(&mut index, &file_name).await;
+        save_index(&self.cache_dir, &index).await?;
+
+        Ok(CachedFile {
+            url: url.to_string(),
+            path,
+            size: received,
+        })
+    }
+
+    /// Delete least recently used files until the cache fits its limit.
+    /// `keep`, the file just downloaded, is never deleted.
+    async fn evict(&self, index: &mut Index, keep: &str) {
+        let mut files: HashMap<&str, (u64, DateTime<Utc>)> = HashMap::new();
+        for entry in index.values() {
+            let file = files
+                .entry(entry.file.as_str())
+                .or_insert((entry.size, entry.last_used));
+            file.1 = file.1.max(entry.last_used);
+        }
+        let mut total: u64 = files.values().map(|(size, _)| size).sum();
+        let mut candidates: Vec<(String, u64, DateTime<Utc>)> = files
+            .into_iter()
+            .filter(|(file, _)| *file != keep)
+            .map(|(file, (size, last_used))| (file.to_string(), size, last_used))
+            .collect();
+        candidates.sort_by_key(|(_, _, last_used)| *last_used);
+
+        for (file, size, _) in candidates {
+            if total <= self.max_cache_bytes {
+                break;
+            }
+            if let Err(error) = tokio::fs::remove_file(self.cache_dir.join(&file)).await
+                && error.kind() != std::io::ErrorKind::NotFound
+            {
+                warn!(file, %error, "failed to evict cached download");
+                continue;
+            }
+            debug!(file, size, "evicted cached download");
+            index.retain(|_, entry| entry.file != file);
+            total -= size;
+        }
+    }
+
+    fn emit_progress(&self, url: &str, received: u64, total: Option<u64>) {
+        self.emit(
+            channels::UI_TRANSFER_PROGRESS,
+            EventPayload::TransferProgress {
+                url: url.to_string(),
+                received,
+                total,
+            },
+        );
+    }
+
+    fn emit(&self, channel: &str, payload: EventPayload) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channel).unwrap(),
+            EventSource::System("downloads".into()),
+            payload,
+        ));
+    }
+}
+
+pub(crate) fn http_url(url: &str) -> Option<Url> {
+    Url::parse(url)
+        .ok()
+        .filter(|parsed| matches!(parsed.scheme(), "http" | "https") && parsed.has_host())
+}
+
+/// The extension of the file the URL names, kept so cached files open in
+/// the right application.
+fn extension(url: &Url) -> Option<String> {
+    let name = url.path_segments()?.next_back()?;
+    let (_, extension) = name.rsplit_once('.')?;
+    (!extension.is_empty()
+        && extension.len() <= 8
+        && extension.chars().all(|c| c.is_ascii_alphanumeric()))
+    .then(|| extension.to_ascii_lowercase())
+}
+
+async fn hash_file(path: &Path, hasher: &mut Sha256) -> std::io::Result<()> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
+
+fn load_index(cache_dir: &Path) -> Index {
+    let index_path = cache_dir.join(INDEX_FILE);
+    match std::fs::read_to_string(&index_path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Index::new(),
+        Err(error) => {
+            warn!(path = %index_path.display(), %error, "failed to read download index, starting fresh");
+            Index::new()
+        }
+    }
+}
+
+async fn save_index(cache_dir: &Path, index: &Index) -> std::io::Result<()> {
+    let contents = serde_json::to_vec_pretty(index).map_err(std::io::Error::other)?;
+    let temporary = cache_dir.join(format!("{INDEX_FILE}.tmp"));
+    tokio::fs::write(&temporary, contents).await?;
+    tokio::fs::rename(temporary, cache_dir.join(INDEX_FILE)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use waddle_core::event::{BroadcastEventBus, MessageType};
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const CONTENT: &[u8] = b"0123456789abcdefghij";
+
+    fn message(body: &str) -> ChatMessage {
+        ChatMessage {
+            id: "m1".to_string(),
+            from: "alice@example.com".to_string(),
+            to: "bob@example.com".to_string(),
+            body: body.to_string(),
+            timestamp: Utc::now(),
+            message_type: MessageType::Chat,
+            thread: None,
+            embeds: Vec::new(),
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
+        }
+    }
+
+    fn manager(dir: &Path, max_cache_bytes: u64) -> (DownloadManager, Arc<dyn EventBus>) {
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::new(256));
+        (
+            DownloadManager::new(dir, max_cache_bytes, event_bus.clone()),
+            event_bus,
+        )
+    }
+
+    async fn serve(server: &MockServer, name: &str, content: &'static [u8]) {
+        Mock::given(method("GET"))
+            .and(path(format!("/{name}")))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(content))
+            .mount(server)
+            .await;
+    }
+
+    #[test]
+    fn recognises_shared_file_links() {
+        assert_eq!(
+            attachment_url(&message(" https://upload.example.com/a/cat.png ")),
+            Some("https://upload.example.com/a/cat.png")
+        );
+        assert_eq!(
+            attachment_url(&message("see https://example.com/cat.png")),
+            None
+        );
+        assert_eq!(attachment_url(&message("ftp://example.com/cat.png")), None);
+
+        let url = Url::parse("https://example.com/a/Cat.PNG?token=1").unwrap();
+        assert_eq!(extension(&url).as_deref(), Some("png"));
+        assert_eq!(
+            extension(&Url::parse("https://example.com/a/").unwrap()),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn downloads_into_content_addressed_cache() {
+        let server = MockServer::start().await;
+        serve(&server, "file.txt", CONTENT).await;
+        let dir = tempfile::tempdir().unwrap();
+        let (manager, event_bus) = manager(dir.path(), 1024);
+        let mut progress = event_bus.subscribe("ui.transfer.progress").unwrap();
+        let mut outcome = event_bus.subscribe("system.transfer.*").unwrap();
+
+        let url = format!("{}/file.txt", server.uri());
+        let file = manager.download(&url).await.unwrap();
+
+        assert_eq!(file.size, CONTENT.len() as u64);
+        assert_eq!(
+            file.path.file_name().unwrap().to_str().unwrap(),
+            format!("{:x}.txt", Sha256::digest(CONTENT))
+        );
+        assert_eq!(std::fs::read(&file.path).unwrap(), CONTENT);
+        assert!(matches!(
+            progress.recv().await.unwrap().payload,
+            EventPayload::TransferProgress {
+                received: 0,
+                total: Some(20),
+                ..
+            }
+        ));
+        assert!(matches!(
+            progress.recv().await.unwrap().payload,
+            EventPayload::TransferProgress { received: 20, .. }
+        ));
+        assert!(matches!(
+            outcome.recv().await.unwrap().payload,
+            EventPayload::TransferCompleted { size: 20, .. }
+        ));
+
+        // Served from the cache, including after a restart.
+        drop(manager);
+        let (manager, _) = self::manager(dir.path(), 1024);
+        assert_eq!(manager.download(&url).await.unwrap(), file);
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn resumes_partial_downloads_with_a_range_request() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/file.bin"))
+            .and(header("range", "bytes=5-"))
+            .respond_with(ResponseTemplate::new(206).set_body_bytes(&CONTENT[5..]))
+            .mount(&server)
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("{}/file.bin", server.uri());
+        let partial_dir = dir.path().join(PARTIAL_DIR);
+        std::fs::create_dir_all(&partial_dir).unwrap();
+        std::fs::write(
+            partial_dir.join(format!("{:x}", Sha256::digest(url.as_bytes()))),
+            &CONTENT[..5],
+        )
+        .unwrap();
+
+        let (manager, _) = manager(dir.path(), 1024);
+        let file = manager.download(&url).await.unwrap();
+
+        assert_eq!(std::fs::read(&file.path).unwrap(), CONTENT);
+        assert_eq!(
+            file.path.file_name().unwrap().to_str().unwrap(),
+            format!("{:x}.bin", Sha256::digest(CONTENT))
+        );
+        assert!(std::fs::read_dir(&partial_dir).unwrap().next().is_none());
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used_files() {
+        let server = MockServer::start().await;
+        serve(&server, "a", b"aaaaaaaaaa").await;
+        serve(&server, "b", b"bbbbbbbbbb").await;
+        serve(&server, "c", b"cccccccccc").await;
+        let dir = tempfile::tempdir().unwrap();
+        let (manager, _) = manager(dir.path(), 25);
+        let url = |name: &str| format!("{}/{name}", server.uri());
+
+        let a = manager.download(&url("a")).await.unwrap();
+        let b = manager.download(&url("b")).await.unwrap();
+        // Using `a` makes `b` the least recently used.
+        manager.cached(&url("a")).await.unwrap();
+        let c = manager.download(&url("c")).await.unwrap();
+
+        assert!(a.path.exists());
+        assert!(!b.path.exists());
+        assert!(c.path.exists());
+        assert!(manager.cached(&url("b")).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn refuses_files_larger_than_the_cache() {
+        let server = MockServer::start().await;
+        serve(&server, "big", CONTENT).await;
+        Mock::given(method("GET"))
+            .and(path("/missing"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let (manager, _) = manager(dir.path(), 10);
+
+        assert!(matches!(
+            manager.download(&format!("{}/big", server.uri())).await,
+            Err(DownloadError::TooLarge { limit: 10 })
+        ));
+        assert!(matches!(
+            manager.download(&format!("{}/missing", server.uri())).await,
+            Err(DownloadError::Status(404))
+        ));
+        assert!(matches!(
+            manager.download("file:///etc/passwd").await,
+            Err(DownloadError::InvalidUrl(_))
+        ));
+    }
+}
@@ -1,21 +1,65 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 use chrono::{DateTime, Utc};
-#[cfg(feature = "native")]
 use serde::{Deserialize, Serialize};
 use std::sync::RwLock;
 use tracing::{debug, error, warn};
 use uuid::Uuid;
 
 use waddle_core::event::{
-    ChatMessage, ChatState, Event, EventPayload, MessageType, MucOccupant, MucRole, MessageEmbed,
+    ChatMessage, ChatState, Event, EventPayload, MessageTranslation, MessageType, MucInvite,
+    MucOccupant, MucRole,
 };
+use waddle_core::jid::Jid;
+use waddle_core::rsm::RsmQuery;
 use waddle_storage::{Database, FromRow, Row, SqlValue, StorageError};
 use waddle_xmpp::Stanza;
 
 #[cfg(feature = "native")]
-use waddle_core::event::{Channel, EventBus, EventSource};
+use waddle_core::WaddleError;
+#[cfg(feature = "native")]
+use waddle_core::config::MessagingConfig;
+#[cfg(feature = "native")]
+use waddle_core::event::{
+    Channel, EventBus, EventSource, MucRejoinStatus, OverflowPolicy, PresenceShow, channels,
+};
+#[cfg(feature = "native")]
+use waddle_core::shutdown::{Manager, ShutdownFuture};
+#[cfg(feature = "native")]
+use waddle_storage::NativeDatabase;
+
+mod conversations;
+#[cfg(feature = "native")]
+mod downloads;
+mod mentions;
+mod merge;
+#[cfg(feature = "native")]
+mod previews;
+mod retention;
+#[cfg(feature = "native")]
+mod rules;
+mod scheduled;
+#[cfg(feature = "native")]
+mod translations;
+#[cfg(feature = "native")]
+mod typing;
+
+pub use conversations::{Conversation, ConversationKind, ConversationManager};
+#[cfg(feature = "native")]
+pub use downloads::{CachedFile, DownloadError, DownloadManager, attachment_url};
+pub use merge::{MergeReport, MergedConversation, canonical_jid};
+#[cfg(feature = "native")]
+pub use previews::{LinkPreviewError, LinkPreviewManager, link_urls};
+pub use retention::{PruneReport, RetentionManager};
+#[cfg(feature = "native")]
+pub use rules::RuleEngine;
+#[cfg(feature = "native")]
+pub use translations::{
+    TranslationError, TranslationFuture, TranslationManager, Translator, same_language,
+};
+#[cfg(feature = "native")]
+pub use typing::{MIN_NOTIFICATION_INTERVAL, PAUSED_AFTER, TypingTracker};
 
 #[derive(Debug, thiserror::Error)]
 pub enum MessagingError {
@@ -30,6 +74,58 @@ pub enum MessagingError {
 
     #[error("invalid JID: {0}")]
     InvalidJid(String),
+
+    #[error("conversation not found: {0}")]
+    ConversationNotFound(String),
+
+    #[error("message not found: {0}")]
+    MessageNotFound(String),
+
+    #[error("message cannot be retracted: {0}")]
+    NotRetractable(String),
+
+    #[error("invalid rule: {0}")]
+    InvalidRule(String),
+
+    #[error("no pending invite to room: {0}")]
+    InviteNotFound(String),
+
+    #[error("not joined to room: {0}")]
+    RoomNotJoined(String),
+}
+
+/// How far an outgoing message has got. Incoming messages have no status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DeliveryStatus {
+    /// Stored locally, not yet handed to the server.
+    Pending,
+    Sent,
+    /// The recipient's client acknowledged it (XEP-0184).
+    Delivered,
+    /// The recipient's client showed it to the user (XEP-0333).
+    Displayed,
+}
+
+impl DeliveryStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Sent => "sent",
+            Self::Delivered => "delivered",
+            Self::Displayed => "displayed",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(Self::Pending),
+            "sent" => Some(Self::Sent),
+            "delivered" => Some(Self::Delivered),
+            "displayed" => Some(Self::Displayed),
+            _ => None,
+        }
+    }
 }
 
 struct StoredMessage {
@@ -41,6 +137,9 @@ struct StoredMessage {
     message_type: String,
     thread: Option<String>,
     embeds: Option<String>,
+    retracted_at: Option<String>,
+    lang: Option<String>,
+    translation: Option<MessageTranslation>,
 }
 
 impl FromRow for StoredMessage {
@@ -95,6 +194,23 @@ impl FromRow for StoredMessage {
             Some(SqlValue::Null) | None => None,
             _ => None,
         };
+        let retracted_at = match row.get(8) {
+            Some(SqlValue::Text(s)) => Some(s.clone()),
+            Some(SqlValue::Null) | None => None,
+            _ => None,
+        };
+        let lang = match row.get(9) {
+            Some(SqlValue::Text(s)) => Some(s.clone()),
+            Some(SqlValue::Null) | None => None,
+            _ => None,
+        };
+        let translation = match (row.get(10), row.get(11)) {
+            (Some(SqlValue::Text(lang)), Some(SqlValue::Text(body))) => Some(MessageTranslation {
+                lang: lang.clone(),
+                body: body.clone(),
+            }),
+            _ => None,
+        };
         Ok(StoredMessage {
             id,
             from_jid,
@@ -104,6 +220,9 @@ impl FromRow for StoredMessage {
             message_type,
             thread,
             embeds,
+            retracted_at,
+            lang,
+            translation,
         })
     }
 }
@@ -122,13 +241,13 @@ impl StoredMessage {
             .timestamp
             .parse::<DateTime<Utc>>()
             .unwrap_or_else(|_| Utc::now());
-            
+
         let embeds = if let Some(json) = self.embeds {
             serde_json::from_str(&json).unwrap_or_default()
         } else {
             Vec::new()
         };
-        
+
         ChatMessage {
             id: self.id,
             from: self.from_jid,
@@ -138,20 +257,17 @@ impl StoredMessage {
             message_type,
             thread: self.thread,
             embeds,
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: self
+                .retracted_at
+                .and_then(|at| at.parse::<DateTime<Utc>>().ok()),
+            lang: self.lang,
+            translation: self.translation,
         }
     }
 }
 
-fn message_type_to_str(mt: &MessageType) -> &'static str {
-    match mt {
-        MessageType::Chat => "chat",
-        MessageType::Groupchat => "groupchat",
-        MessageType::Normal => "normal",
-        MessageType::Headline => "headline",
-        MessageType::Error => "error",
-    }
-}
-
 #[cfg(feature = "native")]
 const OFFLINE_STATUS_PENDING: &str = "pending";
 #[cfg(feature = "native")]
@@ -160,9 +276,16 @@ const OFFLINE_STATUS_SENT: &str = "sent";
 const OFFLINE_STATUS_CONFIRMED: &str = "confirmed";
 #[cfg(feature = "native")]
 const OFFLINE_STATUS_FAILED: &str = "failed";
+/// A sent message that the archive or room may have echoed without an
+/// origin id. It is neither confirmed nor resent until the user decides.
+#[cfg(feature = "native")]
+const OFFLINE_STATUS_AMBIGUOUS: &str = "ambiguous";
 #[cfg(feature = "native")]
 const OFFLINE_SOURCE: &str = "offline";
 
+/// A command held back while offline. For message sends the correlation id
+/// doubles as the stanza id and XEP-0359 origin id of the eventual stanza,
+/// and is what copies echoed back by the server are matched on.
 #[cfg(feature = "native")]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct QueuedOutboundEvent {
@@ -171,6 +294,14 @@ struct QueuedOutboundEvent {
     correlation_id: Option<Uuid>,
 }
 
+#[cfg(feature = "native")]
+impl QueuedOutboundEvent {
+    fn has_id(&self, id: &str) -> bool {
+        self.correlation_id
+            .is_some_and(|correlation_id| correlation_id.to_string() == id)
+    }
+}
+
 #[cfg(feature = "native")]
 struct StoredOfflineQueueItem {
     id: i64,
@@ -224,8 +355,12 @@ fn command_stanza_type(payload: &EventPayload) -> Option<&'static str> {
     match payload {
         EventPayload::MessageSendRequested { .. }
         | EventPayload::MucSendRequested { .. }
+        | EventPayload::MucInviteSendRequested { .. }
+        | EventPayload::MucInviteDeclineRequested { .. }
+        | EventPayload::MessageRetractRequested { .. }
         | EventPayload::ChatStateSendRequested { .. } => Some("message"),
         EventPayload::PresenceSetRequested { .. }
+        | EventPayload::DirectedPresenceRequested { .. }
         | EventPayload::SubscriptionRespondRequested { .. }
         | EventPayload::SubscriptionSendRequested { .. }
         | EventPayload::MucJoinRequested { .. }
@@ -233,7 +368,24 @@ fn command_stanza_type(payload: &EventPayload) -> Option<&'static str> {
         EventPayload::RosterAddRequested { .. }
         | EventPayload::RosterUpdateRequested { .. }
         | EventPayload::RosterRemoveRequested { .. }
-        | EventPayload::RosterFetchRequested => Some("iq"),
+        | EventPayload::RosterFetchRequested { .. }
+        | EventPayload::MessageModerateRequested { .. }
+        | EventPayload::InvisibilitySetRequested { .. } => Some("iq"),
+        _ => None,
+    }
+}
+
+/// The room a queued command is addressed to. These only work once we are
+/// back in the room, so they wait for it to be rejoined.
+fn command_room(payload: &EventPayload) -> Option<&str> {
+    match payload {
+        EventPayload::MucSendRequested { room, .. }
+        | EventPayload::MessageModerateRequested { room, .. } => Some(room),
+        EventPayload::MessageRetractRequested {
+            to,
+            message_type: MessageType::Groupchat,
+            ..
+        } => Some(to),
         _ => None,
     }
 }
@@ -244,6 +396,10 @@ pub struct MessageManager<D: Database> {
     event_bus: Arc<dyn EventBus>,
     #[cfg(feature = "native")]
     is_online: RwLock<bool>,
+    #[cfg(feature = "native")]
+    overflow: OverflowPolicy,
+    #[cfg(feature = "native")]
+    config: MessagingConfig,
 }
 
 impl<D: Database> MessageManager<D> {
@@ -253,9 +409,27 @@ impl<D: Database> MessageManager<D> {
             db,
             event_bus,
             is_online: RwLock::new(false),
+            overflow: OverflowPolicy::DropOldest,
+            config: MessagingConfig::default(),
         }
     }
 
+    /// Set whether receipts are requested on outgoing messages and sent for
+    /// incoming ones. Both are on by default.
+    #[cfg(feature = "native")]
+    pub fn with_config(mut self, config: MessagingConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Choose what happens to events that arrive while `run()` is behind.
+    /// The default drops the oldest, which can lose a `MessageReceived`.
+    #[cfg(feature = "native")]
+    pub fn with_overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow = policy;
+        self
+    }
+
     pub async fn send_message(&self, to: &str, body: &str) -> Result<ChatMessage, MessagingError> {
         let id = Uuid::new_v4();
         let now = Utc::now();
@@ -268,9 +442,16 @@ impl<D: Database> MessageManager<D> {
             message_type: MessageType::Chat,
             thread: None,
             embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
         };
 
         self.persist_message(&message).await?;
+        self.advance_delivery_status(&message.id, None, DeliveryStatus::Pending)
+            .await?;
 
         #[cfg(feature = "native")]
         {
@@ -278,17 +459,18 @@ impl<D: Database> MessageManager<D> {
                 to: to.to_string(),
                 body: body.to_string(),
                 message_type: MessageType::Chat,
+                request_receipt: self.config.request_receipts,
             };
 
             if self.is_online() {
                 let _ = self.event_bus.publish(Event::with_correlation(
-                    Channel::new("ui.message.send").unwrap(),
+                    Channel::new(channels::UI_MESSAGE_SEND).unwrap(),
                     EventSource::System("messaging".into()),
                     payload,
                     id,
                 ));
             } else {
-                self.enqueue_command_event("ui.message.send", payload, Some(id))
+                self.enqueue_command_event(channels::UI_MESSAGE_SEND, payload, Some(id))
                     .await?;
             }
         }
@@ -306,12 +488,12 @@ impl<D: Database> MessageManager<D> {
 
             if self.is_online() {
                 let _ = self.event_bus.publish(Event::new(
-                    Channel::new("ui.chatstate.send").unwrap(),
+                    Channel::new(channels::UI_CHATSTATE_SEND).unwrap(),
                     EventSource::System("messaging".into()),
                     payload,
                 ));
             } else {
-                self.enqueue_command_event("ui.chatstate.send", payload, None)
+                self.enqueue_command_event(channels::UI_CHATSTATE_SEND, payload, None)
                     .await?;
             }
         }
@@ -324,17 +506,18 @@ impl<D: Database> MessageManager<D> {
         limit: u32,
         before: Option<&str>,
     ) -> Result<Vec<ChatMessage>, MessagingError> {
-        let jid_s = jid.to_string();
+        let jid_s = Jid::new(jid);
         let limit_i = i64::from(limit);
 
         let rows: Vec<StoredMessage> = if let Some(before_ts) = before {
             let before_s = before_ts.to_string();
             self.db
                 .query(
-                    "SELECT id, from_jid, to_jid, body, timestamp, message_type, thread, embeds \
-                     FROM messages \
+                    "SELECT id, from_jid, to_jid, messages.body, timestamp, message_type, thread, embeds, \
+                     retracted_at, messages.lang, t.lang, t.body \
+                     FROM messages LEFT JOIN message_translations t ON t.message_id = messages.id \
                      WHERE (from_jid = ?1 OR to_jid = ?1) AND message_type = 'chat' AND timestamp < ?2 \
-                     ORDER BY timestamp DESC \
+                     ORDER BY timestamp DESC, id DESC \
                      LIMIT ?3",
                     &[&jid_s, &before_s, &limit_i],
                 )
@@ -342,10 +525,11 @@ impl<D: Database> MessageManager<D> {
         } else {
             self.db
                 .query(
-                    "SELECT id, from_jid, to_jid, body, timestamp, message_type, thread, embeds \
-                     FROM messages \
+                    "SELECT id, from_jid, to_jid, messages.body, timestamp, message_type, thread, embeds, \
+                     retracted_at, messages.lang, t.lang, t.body \
+                     FROM messages LEFT JOIN message_translations t ON t.message_id = messages.id \
                      WHERE (from_jid = ?1 OR to_jid = ?1) AND message_type = 'chat' \
-                     ORDER BY timestamp DESC \
+                     ORDER BY timestamp DESC, id DESC \
                      LIMIT ?2",
                     &[&jid_s, &limit_i],
                 )
@@ -356,7 +540,7 @@ impl<D: Database> MessageManager<D> {
     }
 
     pub async fn mark_read(&self, jid: &str) -> Result<(), MessagingError> {
-        let jid_s = jid.to_string();
+        let jid_s = Jid::new(jid);
         let read_val = 1_i64;
         self.db
             .execute(
@@ -367,29 +551,293 @@ impl<D: Database> MessageManager<D> {
         Ok(())
     }
 
-    async fn persist_message(&self, message: &ChatMessage) -> Result<(), MessagingError> {
-        let id = message.id.clone();
-        let from = message.from.clone();
-        let to = message.to.clone();
-        let body = message.body.clone();
-        let ts = message.timestamp.to_rfc3339();
-        let mt = message_type_to_str(&message.message_type).to_string();
-        let thread = message.thread.clone();
-        let read = 0_i64;
-        
-        let embeds = if message.embeds.is_empty() {
-            None
+    /// Catch up with a displayed marker another of our devices sent: every
+    /// message from `conversation` up to the marked one counts as read.
+    /// Only the newest marker per conversation is kept, so a late archive
+    /// copy of an older marker changes nothing. A marker for a message we
+    /// do not have yet reads up to when the marker was sent. Returns how
+    /// many messages remain unread, or `None` when the marker was not newer.
+    async fn sync_read_marker(
+        &self,
+        conversation: &str,
+        id: &str,
+        marked_at: DateTime<Utc>,
+    ) -> Result<Option<u32>, MessagingError> {
+        let conversation = Jid::new(conversation).bare();
+        let id_s = id.to_string();
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT timestamp FROM messages \
+                 WHERE (id = ?1 OR origin_id = ?1 OR stanza_id = ?1) AND from_jid = ?2 \
+                 LIMIT 1",
+                &[&id_s, &conversation],
+            )
+            .await?;
+        let read_up_to = match rows.first().and_then(|row| row.get(0)) {
+            Some(SqlValue::Text(timestamp)) => timestamp.clone(),
+            _ => marked_at.to_rfc3339(),
+        };
+
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT read_up_to FROM read_markers WHERE conversation = ?1",
+                &[&conversation],
+            )
+            .await?;
+        if let Some(SqlValue::Text(current)) = rows.first().and_then(|row| row.get(0))
+            && *current >= read_up_to
+        {
+            return Ok(None);
+        }
+
+        self.db
+            .execute(
+                "INSERT INTO read_markers (conversation, message_id, read_up_to, marked_at) \
+                 VALUES (?1, ?2, ?3, ?4) \
+                 ON CONFLICT(conversation) DO UPDATE SET \
+                 message_id = excluded.message_id, \
+                 read_up_to = excluded.read_up_to, \
+                 marked_at = excluded.marked_at",
+                &[&conversation, &id_s, &read_up_to, &marked_at.to_rfc3339()],
+            )
+            .await?;
+        self.db
+            .execute(
+                "UPDATE messages SET read = 1 \
+                 WHERE from_jid = ?1 AND read = 0 AND timestamp <= ?2",
+                &[&conversation, &read_up_to],
+            )
+            .await?;
+
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT COUNT(*) FROM messages WHERE from_jid = ?1 AND read = 0",
+                &[&conversation],
+            )
+            .await?;
+        let unread = match rows.first().and_then(|row| row.get(0)) {
+            Some(SqlValue::Integer(count)) => u32::try_from(*count).unwrap_or(0),
+            _ => 0,
+        };
+        Ok(Some(unread))
+    }
+
+    /// Retract a message we sent (XEP-0424), in a chat or a room. The stored
+    /// copy becomes a tombstone straight away; the retraction itself waits in
+    /// the offline queue while we are offline.
+    pub async fn retract_message(&self, id: &str) -> Result<(), MessagingError> {
+        let id_s = id.to_string();
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT from_jid, to_jid, message_type, delivery_status, origin_id, stanza_id \
+                 FROM messages WHERE id = ?1 AND retracted_at IS NULL",
+                &[&id_s],
+            )
+            .await?;
+        let Some(row) = rows.first() else {
+            return Err(MessagingError::MessageNotFound(id.to_string()));
+        };
+        let text = |index| match row.get(index) {
+            Some(SqlValue::Text(value)) => Some(value.clone()),
+            _ => None,
+        };
+        let from_jid = text(0).unwrap_or_default();
+        let to_jid = text(1).unwrap_or_default();
+
+        let (conversation, reference, message_type) = if text(2).as_deref() == Some("groupchat") {
+            let room = Jid::new(&to_jid).bare();
+            #[cfg(feature = "native")]
+            let own = self
+                .own_room_nick(room.as_str())
+                .await?
+                .map(|nick| Jid::new(&format!("{room}/{nick}")));
+            #[cfg(not(feature = "native"))]
+            let own: Option<Jid> = None;
+            if own != Some(Jid::new(&from_jid)) {
+                return Err(MessagingError::NotRetractable(
+                    "sent by another occupant".to_string(),
+                ));
+            }
+            // Rooms refer to their messages by the stanza id they assigned.
+            let Some(stanza_id) = text(5) else {
+                return Err(MessagingError::NotRetractable(
+                    "not yet confirmed by the room".to_string(),
+                ));
+            };
+            (room.into_string(), stanza_id, MessageType::Groupchat)
+        } else {
+            if text(3).is_none() {
+                return Err(MessagingError::NotRetractable(
+                    "sent by someone else".to_string(),
+                ));
+            }
+            let reference = text(4).unwrap_or_else(|| id.to_string());
+            (
+                Jid::new(&to_jid).bare().into_string(),
+                reference,
+                MessageType::Chat,
+            )
+        };
+
+        let now = Utc::now();
+        waddle_storage::retract_message(self.db.as_ref(), id, None, now).await?;
+
+        #[cfg(feature = "native")]
+        {
+            let payload = EventPayload::MessageRetractRequested {
+                to: conversation.clone(),
+                id: reference,
+                message_type,
+            };
+            if self.is_online() {
+                let _ = self.event_bus.publish(Event::new(
+                    Channel::new(channels::UI_MESSAGE_RETRACT).unwrap(),
+                    EventSource::System("messaging".into()),
+                    payload,
+                ));
+            } else {
+                self.enqueue_command_event(channels::UI_MESSAGE_RETRACT, payload, None)
+                    .await?;
+            }
+            self.emit_retracted(id, &conversation, now, None, None);
+        }
+        #[cfg(not(feature = "native"))]
+        let _ = (conversation, reference, message_type);
+
+        Ok(())
+    }
+
+    /// Tombstone the message a received retraction refers to. Only its
+    /// sender may retract a message, or the room when a moderator removed
+    /// it.
+    async fn apply_retraction(
+        &self,
+        conversation: &str,
+        from: &str,
+        id: &str,
+        moderated_by: Option<&str>,
+        reason: Option<&str>,
+    ) -> Result<(), MessagingError> {
+        let sender = if moderated_by.is_some() {
+            conversation
         } else {
-            Some(serde_json::to_string(&message.embeds).unwrap_or_default())
+            from
+        };
+        let now = Utc::now();
+        let Some(stored_id) =
+            waddle_storage::retract_message(self.db.as_ref(), id, Some(sender), now).await?
+        else {
+            debug!(id = %id, from = %from, "ignoring retraction of unknown message");
+            return Ok(());
+        };
+        #[cfg(feature = "native")]
+        self.emit_retracted(&stored_id, conversation, now, moderated_by, reason);
+        #[cfg(not(feature = "native"))]
+        let _ = (stored_id, reason);
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    fn emit_retracted(
+        &self,
+        id: &str,
+        conversation: &str,
+        retracted_at: DateTime<Utc>,
+        moderated_by: Option<&str>,
+        reason: Option<&str>,
+    ) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channels::SYSTEM_MESSAGE_RETRACTED).unwrap(),
+            EventSource::System("messaging".into()),
+            EventPayload::MessageRetracted {
+                id: id.to_string(),
+                conversation: Jid::new(conversation).bare().into_string(),
+                retracted_at,
+                moderated_by: moderated_by.map(str::to_string),
+                reason: reason.map(str::to_string),
+            },
+        ));
+    }
+
+    /// Delivery state of the outgoing message `id`, or `None` for unknown
+    /// and incoming messages.
+    pub async fn get_message_status(
+        &self,
+        id: &str,
+    ) -> Result<Option<DeliveryStatus>, MessagingError> {
+        Ok(self
+            .load_delivery_status(id)
+            .await?
+            .and_then(|(status, _)| status))
+    }
+
+    async fn load_delivery_status(
+        &self,
+        id: &str,
+    ) -> Result<Option<(Option<DeliveryStatus>, String)>, MessagingError> {
+        let id_s = id.to_string();
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT delivery_status, to_jid FROM messages WHERE id = ?1",
+                &[&id_s],
+            )
+            .await?;
+        Ok(rows.first().map(|row| {
+            let status = match row.get(0) {
+                Some(SqlValue::Text(status)) => DeliveryStatus::parse(status),
+                _ => None,
+            };
+            let to_jid = match row.get(1) {
+                Some(SqlValue::Text(jid)) => jid.clone(),
+                _ => String::new(),
+            };
+            (status, to_jid)
+        }))
+    }
+
+    /// Move message `id` forward to `status`. States never go backwards, so
+    /// a late `sent` cannot undo `delivered`. Acknowledgements only count
+    /// when `recipient` is who the message was sent to, and only for
+    /// messages we sent.
+    async fn advance_delivery_status(
+        &self,
+        id: &str,
+        recipient: Option<&str>,
+        status: DeliveryStatus,
+    ) -> Result<bool, MessagingError> {
+        let Some((current, to_jid)) = self.load_delivery_status(id).await? else {
+            return Ok(false);
         };
+        let bare_to = to_jid.split('/').next().unwrap_or_default();
+        if recipient.is_some_and(|recipient| recipient != bare_to) {
+            return Ok(false);
+        }
+        let advances = match current {
+            Some(current) => status > current,
+            None => status <= DeliveryStatus::Sent,
+        };
+        if !advances {
+            return Ok(false);
+        }
 
+        let status_s = status.as_str().to_string();
+        let id_s = id.to_string();
         self.db
             .execute(
-                "INSERT OR IGNORE INTO messages (id, from_jid, to_jid, body, timestamp, message_type, thread, read, embeds) \
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-                &[&id, &from, &to, &body, &ts, &mt, &thread, &read, &embeds],
+                "UPDATE messages SET delivery_status = ?1 WHERE id = ?2",
+                &[&status_s, &id_s],
             )
             .await?;
+        Ok(true)
+    }
+
+    async fn persist_message(&self, message: &ChatMessage) -> Result<(), MessagingError> {
+        waddle_storage::store_message(self.db.as_ref(), message).await?;
         Ok(())
     }
 
@@ -417,24 +865,31 @@ impl<D: Database> MessageManager<D> {
             return Ok(());
         };
 
-        let resolved_correlation = if matches!(&payload, EventPayload::MessageSendRequested { .. })
-            && correlation_id.is_none()
+        let resolved_correlation = if matches!(
+            &payload,
+            EventPayload::MessageSendRequested { .. } | EventPayload::MucSendRequested { .. }
+        ) && correlation_id.is_none()
         {
             Some(Uuid::new_v4())
         } else {
             correlation_id
         };
 
+        // Placeholders are stored under the id the stanza will carry as its
+        // origin id, so echoed copies are recognized as the same message.
+        let message_id = resolved_correlation
+            .unwrap_or_else(Uuid::new_v4)
+            .to_string();
+
         if let EventPayload::MessageSendRequested {
             to,
             body,
             message_type,
+            ..
         } = &payload
         {
             let message = ChatMessage {
-                id: resolved_correlation
-                    .unwrap_or_else(Uuid::new_v4)
-                    .to_string(),
+                id: message_id.clone(),
                 from: String::new(),
                 to: to.clone(),
                 body: body.clone(),
@@ -442,6 +897,37 @@ impl<D: Database> MessageManager<D> {
                 message_type: message_type.clone(),
                 thread: None,
                 embeds: vec![],
+                origin_id: Some(message_id.clone()),
+                stanza_id: None,
+                retracted_at: None,
+                lang: None,
+                translation: None,
+            };
+            self.persist_message(&message).await?;
+            self.advance_delivery_status(&message.id, None, DeliveryStatus::Pending)
+                .await?;
+        }
+
+        if let EventPayload::MucSendRequested { room, body } = &payload {
+            let from = self
+                .own_room_nick(room)
+                .await?
+                .map(|nick| format!("{room}/{nick}"))
+                .unwrap_or_default();
+            let message = ChatMessage {
+                id: message_id.clone(),
+                from,
+                to: room.clone(),
+                body: body.clone(),
+                timestamp: Utc::now(),
+                message_type: MessageType::Groupchat,
+                thread: None,
+                embeds: vec![],
+                origin_id: Some(message_id),
+                stanza_id: None,
+                retracted_at: None,
+                lang: None,
+                translation: None,
             };
             self.persist_message(&message).await?;
         }
@@ -468,6 +954,19 @@ impl<D: Database> MessageManager<D> {
         Ok(())
     }
 
+    #[cfg(feature = "native")]
+    async fn own_room_nick(&self, room: &str) -> Result<Option<String>, MessagingError> {
+        let room_s = Jid::new(room);
+        let rows: Vec<Row> = self
+            .db
+            .query("SELECT nick FROM muc_rooms WHERE room_jid = ?1", &[&room_s])
+            .await?;
+        Ok(rows.first().and_then(|row| match row.get(0) {
+            Some(SqlValue::Text(nick)) => Some(nick.clone()),
+            _ => None,
+        }))
+    }
+
     #[cfg(feature = "native")]
     async fn load_offline_queue_by_status(
         &self,
@@ -514,12 +1013,19 @@ impl<D: Database> MessageManager<D> {
         Ok(())
     }
 
+    /// Publishes pending queue items accepted by `should_drain`, in FIFO
+    /// order. Items that are skipped stay pending for a later drain. Each
+    /// item is marked sent as soon as it is published, so one interrupted
+    /// drain does not publish it again on the next; messages are confirmed
+    /// later, by the server or the room, and other commands right away.
     #[cfg(feature = "native")]
-    async fn drain_offline_queue(&self) -> Result<(), MessagingError> {
+    async fn drain_offline_queue(
+        &self,
+        should_drain: impl Fn(&QueuedOutboundEvent) -> bool,
+    ) -> Result<(), MessagingError> {
         let pending_items = self
             .load_offline_queue_by_status(OFFLINE_STATUS_PENDING)
             .await?;
-
         for item in pending_items {
             let queued: QueuedOutboundEvent = match serde_json::from_str(&item.payload) {
                 Ok(parsed) => parsed,
@@ -529,13 +1035,18 @@ impl<D: Database> MessageManager<D> {
                         error = %error,
                         "failed to deserialize offline queue item"
                     );
-                    let _ = self
-                        .update_queue_status(item.id, OFFLINE_STATUS_FAILED)
-                        .await;
+                    self.update_queue_status(item.id, OFFLINE_STATUS_FAILED)
+                        .await?;
                     continue;
                 }
             };
 
+            if !should_drain(&queued) {
+                continue;
+            }
+            let is_retraction =
+                matches!(queued.payload, EventPayload::MessageRetractRequested { .. });
+
             let channel = match Channel::new(&queued.channel) {
                 Ok(channel) => channel,
                 Err(error) => {
@@ -545,9 +1056,8 @@ impl<D: Database> MessageManager<D> {
                         error = %error,
                         "invalid queued channel"
                     );
-                    let _ = self
-                        .update_queue_status(item.id, OFFLINE_STATUS_FAILED)
-                        .await;
+                    self.update_queue_status(item.id, OFFLINE_STATUS_FAILED)
+                        .await?;
                     continue;
                 }
             };
@@ -565,32 +1075,20 @@ impl<D: Database> MessageManager<D> {
                     error = %error,
                     "failed to publish queued offline command"
                 );
-                let _ = self
-                    .update_queue_status(item.id, OFFLINE_STATUS_FAILED)
-                    .await;
+                self.update_queue_status(item.id, OFFLINE_STATUS_FAILED)
+                    .await?;
                 continue;
             }
+            self.update_queue_status(item.id, OFFLINE_STATUS_SENT)
+                .await?;
 
-            if item.stanza_type != "message" {
-                if let Err(error) = self.update_queue_status(item.id, OFFLINE_STATUS_SENT).await {
-                    error!(
-                        queue_id = item.id,
-                        error = %error,
-                        "failed to update queued command status to sent"
-                    );
-                } else if let Err(error) = self
-                    .update_queue_status(item.id, OFFLINE_STATUS_CONFIRMED)
-                    .await
-                {
-                    error!(
-                        queue_id = item.id,
-                        error = %error,
-                        "failed to update queued command status to confirmed"
-                    );
-                }
+            // Messages wait for a receipt, their archived copy or the room's
+            // reflection; nothing comes back to confirm other commands.
+            if item.stanza_type != "message" || is_retraction {
+                self.update_queue_status(item.id, OFFLINE_STATUS_CONFIRMED)
+                    .await?;
             }
         }
-
         Ok(())
     }
 
@@ -612,8 +1110,7 @@ impl<D: Database> MessageManager<D> {
                 continue;
             };
 
-            let queued_id = queued.correlation_id.map(|id| id.to_string());
-            if queued_id.as_deref() == Some(message_id) {
+            if queued.has_id(message_id) {
                 self.update_queue_status(item.id, to_status).await?;
                 return Ok(true);
             }
@@ -622,18 +1119,21 @@ impl<D: Database> MessageManager<D> {
         Ok(false)
     }
 
+    /// Marks sent items to `to` with the same body as ambiguous. Used when
+    /// an echoed copy carries no origin id: it may be one of ours, or an
+    /// identical message sent from elsewhere, and guessing either way risks
+    /// a lost or duplicated message.
     #[cfg(feature = "native")]
-    async fn update_message_queue_status_by_content(
+    async fn mark_ambiguous_by_content(
         &self,
         to: &str,
         body: &str,
-        from_statuses: &[&str],
-        to_status: &str,
-    ) -> Result<bool, MessagingError> {
+    ) -> Result<usize, MessagingError> {
         let candidates = self.load_message_queue_candidates().await?;
+        let mut marked = 0;
 
         for item in candidates {
-            if !from_statuses.contains(&item.status.as_str()) {
+            if item.status != OFFLINE_STATUS_SENT {
                 continue;
             }
 
@@ -641,63 +1141,190 @@ impl<D: Database> MessageManager<D> {
                 continue;
             };
 
-            if let EventPayload::MessageSendRequested {
-                to: queued_to,
-                body: queued_body,
-                ..
-            } = queued.payload
-            {
-                if queued_to == to && queued_body == body {
-                    self.update_queue_status(item.id, to_status).await?;
-                    return Ok(true);
-                }
+            let (queued_to, queued_body) = match &queued.payload {
+                EventPayload::MessageSendRequested { to, body, .. } => (to, body),
+                EventPayload::MucSendRequested { room, body } => (room, body),
+                _ => continue,
+            };
+            if Jid::new(queued_to).bare() == Jid::new(to).bare() && queued_body == body {
+                warn!(
+                    queue_id = item.id,
+                    to = %to,
+                    "echoed message has no origin id, queued send needs manual retry"
+                );
+                self.update_queue_status(item.id, OFFLINE_STATUS_AMBIGUOUS)
+                    .await?;
+                marked += 1;
             }
         }
 
-        Ok(false)
+        Ok(marked)
     }
 
+    /// Confirms a queued send from its archived copy. The archive replaces
+    /// the stanza id, so only the origin id ties the copy to the send; a copy
+    /// without one that looks like a queued send marks it ambiguous instead.
     #[cfg(feature = "native")]
-    fn emit_system_transition(&self, channel: &str, payload: EventPayload) {
-        let Ok(channel) = Channel::new(channel) else {
-            return;
-        };
-        let _ = self.event_bus.publish(Event::new(
-            channel,
-            EventSource::System(OFFLINE_SOURCE.to_string()),
-            payload,
-        ));
+    async fn reconcile_archived_message(
+        &self,
+        message: &ChatMessage,
+    ) -> Result<(), MessagingError> {
+        match message.origin_id.as_deref() {
+            Some(origin_id) => {
+                self.update_message_queue_status_by_id(
+                    origin_id,
+                    &[OFFLINE_STATUS_PENDING, OFFLINE_STATUS_SENT],
+                    OFFLINE_STATUS_CONFIRMED,
+                )
+                .await?;
+            }
+            None => {
+                self.mark_ambiguous_by_content(&message.to, &message.body)
+                    .await?;
+            }
+        }
+        Ok(())
     }
 
+    /// Confirms a queued groupchat send once the room reflects it back.
+    /// Rooms normally keep our stanza id, and those that rewrite it still
+    /// keep our origin id. A reflection from our own occupant JID that has
+    /// neither may be a send whose ids the room dropped, so a queued send
+    /// with the same body is marked ambiguous.
     #[cfg(feature = "native")]
-    pub async fn handle_event(&self, event: &Event) {
-        match &event.payload {
-            EventPayload::ConnectionEstablished { .. } => {
-                let was_online = self.set_online(true);
-                if !was_online {
-                    self.emit_system_transition("system.coming_online", EventPayload::ComingOnline);
-                }
-                if let Err(error) = self.drain_offline_queue().await {
-                    error!(error = %error, "failed to drain offline queue");
-                }
-            }
-            EventPayload::ConnectionLost { .. } => {
+    async fn reconcile_room_message(
+        &self,
+        room: &str,
+        message: &ChatMessage,
+    ) -> Result<(), MessagingError> {
+        let sent_id = message.origin_id.as_deref().unwrap_or(&message.id);
+        if self
+            .update_message_queue_status_by_id(
+                sent_id,
+                &[OFFLINE_STATUS_PENDING, OFFLINE_STATUS_SENT],
+                OFFLINE_STATUS_CONFIRMED,
+            )
+            .await?
+            || message.origin_id.is_some()
+        {
+            return Ok(());
+        }
+
+        let Some(nick) = self.own_room_nick(room).await? else {
+            return Ok(());
+        };
+        if message.from == format!("{room}/{nick}") {
+            self.mark_ambiguous_by_content(room, &message.body).await?;
+        }
+        Ok(())
+    }
+
+    /// Ids of queued messages that were sent but could not be matched to
+    /// their echo with certainty. Each stays unconfirmed until passed to
+    /// [`MessageManager::retry_queued_message`] or confirmed by a receipt.
+    #[cfg(feature = "native")]
+    pub async fn ambiguous_queued_messages(&self) -> Result<Vec<String>, MessagingError> {
+        let items = self
+            .load_offline_queue_by_status(OFFLINE_STATUS_AMBIGUOUS)
+            .await?;
+        Ok(items
+            .iter()
+            .filter_map(|item| serde_json::from_str::<QueuedOutboundEvent>(&item.payload).ok())
+            .filter_map(|queued| queued.correlation_id.map(|id| id.to_string()))
+            .collect())
+    }
+
+    /// Sends an ambiguous queued message again, under its original id so a
+    /// copy that did arrive is deduplicated by the recipient. Returns
+    /// `false` when no ambiguous item has that id.
+    #[cfg(feature = "native")]
+    pub async fn retry_queued_message(&self, message_id: &str) -> Result<bool, MessagingError> {
+        let items = self
+            .load_offline_queue_by_status(OFFLINE_STATUS_AMBIGUOUS)
+            .await?;
+        let Some(item) = items.iter().find(|item| {
+            serde_json::from_str::<QueuedOutboundEvent>(&item.payload)
+                .is_ok_and(|queued| queued.has_id(message_id))
+        }) else {
+            return Ok(false);
+        };
+
+        self.update_queue_status(item.id, OFFLINE_STATUS_PENDING)
+            .await?;
+        if self.is_online() {
+            self.drain_offline_queue(|queued| queued.has_id(message_id))
+                .await?;
+        }
+        Ok(true)
+    }
+
+    #[cfg(feature = "native")]
+    fn emit_system_transition(&self, channel: &str, payload: EventPayload) {
+        let Ok(channel) = Channel::new(channel) else {
+            return;
+        };
+        let _ = self.event_bus.publish(Event::new(
+            channel,
+            EventSource::System(OFFLINE_SOURCE.to_string()),
+            payload,
+        ));
+    }
+
+    #[cfg(feature = "native")]
+    pub async fn handle_event(&self, event: &Event) {
+        match &event.payload {
+            EventPayload::ConnectionEstablished { .. } => {
+                let was_online = self.set_online(true);
+                if !was_online {
+                    self.emit_system_transition(
+                        channels::SYSTEM_COMING_ONLINE,
+                        EventPayload::ComingOnline,
+                    );
+                }
+                // Room commands wait for the room to be rejoined.
+                if let Err(error) = self
+                    .drain_offline_queue(|queued| command_room(&queued.payload).is_none())
+                    .await
+                {
+                    error!(error = %error, "failed to drain offline queue");
+                }
+            }
+            EventPayload::MucJoined { room, .. } => {
+                if let Err(error) = self
+                    .drain_offline_queue(|queued| {
+                        command_room(&queued.payload) == Some(room.as_str())
+                    })
+                    .await
+                {
+                    error!(error = %error, room = %room, "failed to drain queued room messages");
+                }
+            }
+            EventPayload::ConnectionLost { .. } => {
                 let was_online = self.set_online(false);
                 if was_online {
-                    self.emit_system_transition("system.going_offline", EventPayload::GoingOffline);
+                    self.emit_system_transition(
+                        channels::SYSTEM_GOING_OFFLINE,
+                        EventPayload::GoingOffline,
+                    );
                 }
             }
             EventPayload::MessageSendRequested { .. }
             | EventPayload::PresenceSetRequested { .. }
+            | EventPayload::DirectedPresenceRequested { .. }
+            | EventPayload::InvisibilitySetRequested { .. }
             | EventPayload::RosterAddRequested { .. }
             | EventPayload::RosterUpdateRequested { .. }
             | EventPayload::RosterRemoveRequested { .. }
-            | EventPayload::RosterFetchRequested
+            | EventPayload::RosterFetchRequested { .. }
             | EventPayload::SubscriptionRespondRequested { .. }
             | EventPayload::SubscriptionSendRequested { .. }
             | EventPayload::MucJoinRequested { .. }
             | EventPayload::MucLeaveRequested { .. }
             | EventPayload::MucSendRequested { .. }
+            | EventPayload::MucInviteSendRequested { .. }
+            | EventPayload::MucInviteDeclineRequested { .. }
+            | EventPayload::MessageRetractRequested { .. }
+            | EventPayload::MessageModerateRequested { .. }
             | EventPayload::ChatStateSendRequested { .. } => {
                 if self.is_online() {
                     return;
@@ -738,6 +1365,12 @@ impl<D: Database> MessageManager<D> {
                 if let Err(e) = self.persist_message(message).await {
                     error!(error = %e, "failed to persist sent message");
                 }
+                if let Err(e) = self
+                    .advance_delivery_status(&message.id, None, DeliveryStatus::Sent)
+                    .await
+                {
+                    error!(error = %e, "failed to mark message sent");
+                }
                 if let Err(error) = self
                     .update_message_queue_status_by_id(
                         &message.id,
@@ -751,6 +1384,12 @@ impl<D: Database> MessageManager<D> {
             }
             EventPayload::MessageDelivered { id, to } => {
                 debug!(id = %id, to = %to, "delivery receipt received");
+                if let Err(e) = self
+                    .advance_delivery_status(id, Some(to), DeliveryStatus::Delivered)
+                    .await
+                {
+                    error!(error = %e, "failed to mark message delivered");
+                }
                 if let Err(error) = self
                     .update_message_queue_status_by_id(
                         id,
@@ -762,48 +1401,84 @@ impl<D: Database> MessageManager<D> {
                     error!(error = %error, "failed to update queued message to confirmed");
                 }
             }
+            EventPayload::MessageDisplayed { id, to } => {
+                if let Err(e) = self
+                    .advance_delivery_status(id, Some(to), DeliveryStatus::Displayed)
+                    .await
+                {
+                    error!(error = %e, "failed to mark message displayed");
+                }
+            }
+            EventPayload::MessageDisplayedElsewhere {
+                conversation,
+                id,
+                timestamp,
+            } => match self.sync_read_marker(conversation, id, *timestamp).await {
+                Ok(Some(unread_count)) => {
+                    debug!(conversation = %conversation, unread_count, "read on another device");
+                    let _ = self.event_bus.publish(Event::new(
+                        Channel::new(channels::SYSTEM_CONVERSATION_READ_SYNCED).unwrap(),
+                        EventSource::System("messaging".into()),
+                        EventPayload::ReadStateSynced {
+                            conversation: Jid::new(conversation).bare().into_string(),
+                            unread_count,
+                        },
+                    ));
+                }
+                Ok(None) => {}
+                Err(e) => error!(error = %e, "failed to sync read state"),
+            },
+            EventPayload::ReceiptRequested { id, from } if self.config.send_receipts => {
+                debug!(id = %id, to = %from, "acknowledging message");
+                let _ = self.event_bus.publish(Event::new(
+                    Channel::new(channels::UI_RECEIPT_SEND).unwrap(),
+                    EventSource::System("messaging".into()),
+                    EventPayload::ReceiptSendRequested {
+                        to: from.clone(),
+                        id: id.clone(),
+                    },
+                ));
+            }
             EventPayload::MamResultReceived { messages, .. } => {
                 for message in messages {
-                    let confirmed_by_id = match self
-                        .update_message_queue_status_by_id(
-                            &message.id,
-                            &[OFFLINE_STATUS_PENDING, OFFLINE_STATUS_SENT],
-                            OFFLINE_STATUS_CONFIRMED,
-                        )
-                        .await
-                    {
-                        Ok(updated) => updated,
-                        Err(error) => {
-                            error!(
-                                error = %error,
-                                message_id = %message.id,
-                                "failed to reconcile queued message by id"
-                            );
-                            false
-                        }
-                    };
-
-                    if confirmed_by_id {
-                        continue;
-                    }
-
-                    if let Err(error) = self
-                        .update_message_queue_status_by_content(
-                            &message.to,
-                            &message.body,
-                            &[OFFLINE_STATUS_SENT],
-                            OFFLINE_STATUS_CONFIRMED,
-                        )
-                        .await
-                    {
+                    if let Err(error) = self.reconcile_archived_message(message).await {
                         error!(
                             error = %error,
-                            to = %message.to,
-                            "failed to reconcile queued message with MAM content"
+                            message_id = %message.id,
+                            "failed to reconcile queued message with MAM result"
                         );
                     }
                 }
             }
+            EventPayload::MucMessageReceived { room, message } => {
+                if let Err(error) = self.reconcile_room_message(room, message).await {
+                    error!(
+                        error = %error,
+                        room = %room,
+                        "failed to reconcile queued room message"
+                    );
+                }
+            }
+            EventPayload::MessageRetractionReceived {
+                conversation,
+                from,
+                id,
+                moderated_by,
+                reason,
+            } => {
+                if let Err(error) = self
+                    .apply_retraction(
+                        conversation,
+                        from,
+                        id,
+                        moderated_by.as_deref(),
+                        reason.as_deref(),
+                    )
+                    .await
+                {
+                    error!(error = %error, id = %id, "failed to apply message retraction");
+                }
+            }
             EventPayload::ChatStateReceived { from, state } => {
                 debug!(from = %from, ?state, "chat state received");
             }
@@ -815,7 +1490,7 @@ impl<D: Database> MessageManager<D> {
     pub async fn run(self: Arc<Self>) -> Result<(), MessagingError> {
         let mut sub = self
             .event_bus
-            .subscribe("{system,xmpp,ui}.**")
+            .subscribe_with("{system,xmpp,ui}.**", self.overflow.clone())
             .map_err(|e| MessagingError::EventBus(e.to_string()))?;
 
         loop {
@@ -841,6 +1516,25 @@ impl<D: Database> MessageManager<D> {
     pub fn handle_stanza(&self, _stanza: &Stanza) {}
 }
 
+/// Checkpoints the write-ahead log on shutdown so the offline queue is in
+/// the database file itself before the process exits.
+#[cfg(feature = "native")]
+impl Manager for MessageManager<NativeDatabase> {
+    fn name(&self) -> &'static str {
+        "messaging"
+    }
+
+    fn shutdown(&self) -> ShutdownFuture<'_> {
+        Box::pin(async move {
+            self.db
+                .query::<Row>("PRAGMA wal_checkpoint(TRUNCATE)", &[])
+                .await
+                .map_err(|e| WaddleError::Storage(e.to_string()))?;
+            Ok(())
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MucRoom {
     pub room_jid: String,
@@ -903,12 +1597,127 @@ impl StoredRoom {
     }
 }
 
+/// One entry in a room's subject history.
+#[derive(Debug, Clone)]
+pub struct MucSubjectChange {
+    pub room_jid: String,
+    pub subject: String,
+    /// Nick of the occupant who set the subject, if known.
+    pub set_by: Option<String>,
+    pub changed_at: DateTime<Utc>,
+}
+
+impl FromRow for MucSubjectChange {
+    fn from_row(row: &Row) -> Result<Self, StorageError> {
+        let room_jid = match row.get(0) {
+            Some(SqlValue::Text(s)) => s.clone(),
+            _ => {
+                return Err(StorageError::QueryFailed(
+                    "missing room_jid column".to_string(),
+                ));
+            }
+        };
+        let subject = match row.get(1) {
+            Some(SqlValue::Text(s)) => s.clone(),
+            _ => {
+                return Err(StorageError::QueryFailed(
+                    "missing subject column".to_string(),
+                ));
+            }
+        };
+        let set_by = match row.get(2) {
+            Some(SqlValue::Text(s)) => Some(s.clone()),
+            _ => None,
+        };
+        let changed_at = match row.get(3) {
+            Some(SqlValue::Text(s)) => DateTime::parse_from_rfc3339(s)
+                .map(|dt| dt.with_timezone(&Utc))
+                .map_err(|e| StorageError::QueryFailed(format!("invalid changed_at: {e}")))?,
+            _ => {
+                return Err(StorageError::QueryFailed(
+                    "missing changed_at column".to_string(),
+                ));
+            }
+        };
+        Ok(MucSubjectChange {
+            room_jid,
+            subject,
+            set_by,
+            changed_at,
+        })
+    }
+}
+
+struct StoredInvite {
+    room_jid: String,
+    from_jid: String,
+    reason: Option<String>,
+    password: Option<String>,
+    mediated: i64,
+    received_at: DateTime<Utc>,
+}
+
+impl FromRow for StoredInvite {
+    fn from_row(row: &Row) -> Result<Self, StorageError> {
+        let text = |index: usize, column: &str| match row.get(index) {
+            Some(SqlValue::Text(s)) => Ok(s.clone()),
+            _ => Err(StorageError::QueryFailed(format!(
+                "missing {column} column"
+            ))),
+        };
+        let optional_text = |index: usize| match row.get(index) {
+            Some(SqlValue::Text(s)) => Some(s.clone()),
+            _ => None,
+        };
+        let mediated = match row.get(4) {
+            Some(SqlValue::Integer(i)) => *i,
+            _ => {
+                return Err(StorageError::QueryFailed(
+                    "missing mediated column".to_string(),
+                ));
+            }
+        };
+        let received_at = DateTime::parse_from_rfc3339(&text(5, "received_at")?)
+            .map(|dt| dt.with_timezone(&Utc))
+            .map_err(|e| StorageError::QueryFailed(format!("invalid received_at: {e}")))?;
+        Ok(StoredInvite {
+            room_jid: text(0, "room_jid")?,
+            from_jid: text(1, "from_jid")?,
+            reason: optional_text(2),
+            password: optional_text(3),
+            mediated,
+            received_at,
+        })
+    }
+}
+
+impl StoredInvite {
+    fn into_muc_invite(self) -> MucInvite {
+        MucInvite {
+            room: self.room_jid,
+            from: self.from_jid,
+            reason: self.reason,
+            password: self.password,
+            mediated: self.mediated != 0,
+            received_at: self.received_at,
+        }
+    }
+}
+
 /// Per-room occupant map: nick -> MucOccupant
 type OccupantMap = HashMap<String, MucOccupant>;
 
 pub struct MucManager<D: Database> {
     db: Arc<D>,
-    occupants: RwLock<HashMap<String, OccupantMap>>,
+    occupants: RwLock<HashMap<Jid, OccupantMap>>,
+    /// Lowercased words that count as a mention besides the room nick.
+    mention_keywords: Vec<String>,
+    /// Rooms with an automatic rejoin awaiting the room's confirmation.
+    #[cfg(feature = "native")]
+    rejoining: RwLock<HashSet<Jid>>,
+    /// Our last broadcast show and status, repeated to rooms as we join them.
+    #[cfg(feature = "native")]
+    own_presence: RwLock<(PresenceShow, Option<String>)>,
     #[cfg(feature = "native")]
     event_bus: Arc<dyn EventBus>,
 }
@@ -919,12 +1728,34 @@ impl<D: Database> MucManager<D> {
         Self {
             db,
             occupants: RwLock::new(HashMap::new()),
+            mention_keywords: Vec::new(),
+            rejoining: RwLock::new(HashSet::new()),
+            own_presence: RwLock::new((PresenceShow::Available, None)),
             event_bus,
         }
     }
 
+    /// Treat `keywords` as mentions too, in addition to the room nick.
+    pub fn with_mention_keywords(mut self, keywords: &[String]) -> Self {
+        self.mention_keywords = keywords
+            .iter()
+            .map(|keyword| keyword.trim().to_lowercase())
+            .filter(|keyword| !keyword.is_empty())
+            .collect();
+        self
+    }
+
     pub async fn join_room(&self, room: &str, nick: &str) -> Result<(), MessagingError> {
-        let room_s = room.to_string();
+        self.request_join(room, nick, None).await
+    }
+
+    async fn request_join(
+        &self,
+        room: &str,
+        nick: &str,
+        password: Option<String>,
+    ) -> Result<(), MessagingError> {
+        let room_s = Jid::new(room);
         let nick_s = nick.to_string();
         let joined = 0_i64;
         let subject: Option<String> = None;
@@ -940,23 +1771,147 @@ impl<D: Database> MucManager<D> {
         #[cfg(feature = "native")]
         {
             let _ = self.event_bus.publish(Event::new(
-                Channel::new("ui.muc.join").unwrap(),
+                Channel::new(channels::UI_MUC_JOIN).unwrap(),
                 EventSource::System("muc".into()),
                 EventPayload::MucJoinRequested {
                     room: room.to_string(),
                     nick: nick.to_string(),
+                    history_since: None,
+                    password,
                 },
             ));
         }
+        #[cfg(not(feature = "native"))]
+        let _ = password;
+
+        Ok(())
+    }
+
+    /// Rejoins every room still marked joined, asking each for the history
+    /// missed since its newest stored message.
+    #[cfg(feature = "native")]
+    pub async fn rejoin_rooms(&self) -> Result<(), MessagingError> {
+        for room in self.get_joined_rooms().await? {
+            let history_since = self.last_room_message_at(&room.room_jid).await?;
+            debug!(
+                room = %room.room_jid,
+                since = ?history_since,
+                "rejoining MUC room after reconnect"
+            );
+
+            self.rejoining
+                .write()
+                .unwrap()
+                .insert(Jid::new(&room.room_jid));
+            let _ = self.event_bus.publish(Event::new(
+                Channel::new(channels::UI_MUC_JOIN).unwrap(),
+                EventSource::System("muc".into()),
+                EventPayload::MucJoinRequested {
+                    room: room.room_jid.clone(),
+                    nick: room.nick,
+                    history_since,
+                    password: None,
+                },
+            ));
+            self.emit_rejoin_status(&room.room_jid, MucRejoinStatus::Rejoining);
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    async fn last_room_message_at(
+        &self,
+        room: &str,
+    ) -> Result<Option<DateTime<Utc>>, MessagingError> {
+        let room_s = Jid::new(room);
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT MAX(timestamp) FROM messages \
+                 WHERE to_jid = ?1 AND message_type = 'groupchat'",
+                &[&room_s],
+            )
+            .await?;
+
+        Ok(rows.first().and_then(|row| match row.get(0) {
+            Some(SqlValue::Text(ts)) => DateTime::parse_from_rfc3339(ts)
+                .ok()
+                .map(|dt| dt.with_timezone(&Utc)),
+            _ => None,
+        }))
+    }
+
+    /// Settles a pending rejoin of `room`, if there is one.
+    #[cfg(feature = "native")]
+    fn finish_rejoin(&self, room: &str, status: MucRejoinStatus) {
+        if self.rejoining.write().unwrap().remove(&Jid::new(room)) {
+            self.emit_rejoin_status(room, status);
+        }
+    }
+
+    /// Servers only broadcast presence to the roster, so a change of our
+    /// show or status is sent to every joined room directly. Rooms still
+    /// being rejoined pick it up once they confirm the join.
+    #[cfg(feature = "native")]
+    async fn update_room_presence(
+        &self,
+        show: &PresenceShow,
+        status: Option<&str>,
+    ) -> Result<(), MessagingError> {
+        *self.own_presence.write().unwrap() = (show.clone(), status.map(String::from));
+
+        // Going unavailable ends the session and the server tells the rooms;
+        // while appearing offline, rooms keep our last visible status.
+        if matches!(show, PresenceShow::Unavailable | PresenceShow::Invisible) {
+            return Ok(());
+        }
 
+        for room in self.get_joined_rooms().await? {
+            if self
+                .rejoining
+                .read()
+                .unwrap()
+                .contains(room.room_jid.as_str())
+            {
+                continue;
+            }
+            self.send_room_presence(&room.room_jid, &room.nick, show.clone(), status);
+        }
         Ok(())
     }
 
+    #[cfg(feature = "native")]
+    fn send_room_presence(&self, room: &str, nick: &str, show: PresenceShow, status: Option<&str>) {
+        debug!(room = %room, ?show, "updating own occupant presence");
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channels::UI_PRESENCE_DIRECTED).unwrap(),
+            EventSource::System("muc".into()),
+            EventPayload::DirectedPresenceRequested {
+                to: format!("{room}/{nick}"),
+                show,
+                status: status.map(String::from),
+            },
+        ));
+    }
+
+    #[cfg(feature = "native")]
+    fn emit_rejoin_status(&self, room: &str, status: MucRejoinStatus) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channels::SYSTEM_MUC_REJOIN_STATUS).unwrap(),
+            EventSource::System("muc".into()),
+            EventPayload::MucRejoinStatusChanged {
+                room: room.to_string(),
+                status,
+            },
+        ));
+    }
+
     pub async fn leave_room(&self, room: &str) -> Result<(), MessagingError> {
         #[cfg(feature = "native")]
         {
             let _ = self.event_bus.publish(Event::new(
-                Channel::new("ui.muc.leave").unwrap(),
+                Channel::new(channels::UI_MUC_LEAVE).unwrap(),
                 EventSource::System("muc".into()),
                 EventPayload::MucLeaveRequested {
                     room: room.to_string(),
@@ -967,18 +1922,263 @@ impl<D: Database> MucManager<D> {
         Ok(())
     }
 
-    pub async fn send_message(&self, room: &str, body: &str) -> Result<(), MessagingError> {
+    pub async fn send_message(&self, room: &str, body: &str) -> Result<(), MessagingError> {
+        #[cfg(feature = "native")]
+        {
+            let _ = self.event_bus.publish(Event::with_correlation(
+                Channel::new(channels::UI_MUC_SEND).unwrap(),
+                EventSource::System("muc".into()),
+                EventPayload::MucSendRequested {
+                    room: room.to_string(),
+                    body: body.to_string(),
+                },
+                Uuid::new_v4(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Ask `room` to call us `new_nick`. The stored nick follows once the
+    /// room confirms the rename; a room that refuses it leaves the old nick
+    /// in place.
+    pub async fn change_nick(&self, room: &str, new_nick: &str) -> Result<(), MessagingError> {
+        let room_s = Jid::new(room);
+        let joined = self
+            .get_joined_rooms()
+            .await?
+            .into_iter()
+            .find(|joined| joined.room_jid == room_s.as_str());
+        let Some(joined) = joined else {
+            return Err(MessagingError::RoomNotJoined(room.to_string()));
+        };
+        if joined.nick == new_nick {
+            return Ok(());
+        }
+
+        #[cfg(feature = "native")]
+        {
+            // A room presence cannot make us invisible; while appearing
+            // offline we show up as plainly available.
+            let (show, status) = match self.own_presence.read().unwrap().clone() {
+                (PresenceShow::Unavailable | PresenceShow::Invisible, _) => {
+                    (PresenceShow::Available, None)
+                }
+                presence => presence,
+            };
+            self.send_room_presence(room_s.as_str(), new_nick, show, status.as_deref());
+        }
+
+        Ok(())
+    }
+
+    /// Invite `jid` into `room` with a direct invitation. Only rooms we
+    /// have joined can be shared.
+    pub async fn send_invite(
+        &self,
+        room: &str,
+        jid: &str,
+        reason: Option<&str>,
+    ) -> Result<(), MessagingError> {
+        let room_s = Jid::new(room);
+        let joined = self
+            .get_joined_rooms()
+            .await?
+            .iter()
+            .any(|joined| joined.room_jid == room_s.as_str());
+        if !joined {
+            return Err(MessagingError::RoomNotJoined(room.to_string()));
+        }
+
+        #[cfg(feature = "native")]
+        {
+            let _ = self.event_bus.publish(Event::new(
+                Channel::new(channels::UI_MUC_INVITE).unwrap(),
+                EventSource::System("muc".into()),
+                EventPayload::MucInviteSendRequested {
+                    room: room_s.into_string(),
+                    to: Jid::new(jid).into_string(),
+                    reason: reason.map(str::to_string),
+                },
+            ));
+        }
+        #[cfg(not(feature = "native"))]
+        let _ = (jid, reason);
+
+        Ok(())
+    }
+
+    /// Ask the MUC `service` for one page of its public rooms. The page
+    /// arrives as `DiscoItemsReceived` from the service, and its
+    /// [`RsmResult`](waddle_core::rsm::RsmResult) gives the cursors for the
+    /// pages on either side.
+    pub fn browse_rooms(&self, service: &str, page: RsmQuery) {
+        #[cfg(feature = "native")]
+        {
+            let _ = self.event_bus.publish(Event::new(
+                Channel::new(channels::UI_DISCO_ITEMS).unwrap(),
+                EventSource::System("muc".into()),
+                EventPayload::DiscoItemsRequested {
+                    jid: Jid::new(service).into_string(),
+                    node: None,
+                    page,
+                },
+            ));
+        }
+        #[cfg(not(feature = "native"))]
+        let _ = (service, page);
+    }
+
+    /// Invitations not yet accepted or declined, newest first.
+    pub async fn pending_invites(&self) -> Result<Vec<MucInvite>, MessagingError> {
+        let rows: Vec<StoredInvite> = self
+            .db
+            .query(
+                "SELECT room_jid, from_jid, reason, password, mediated, received_at \
+                 FROM muc_invites ORDER BY received_at DESC, room_jid",
+                &[],
+            )
+            .await?;
+
+        Ok(rows.into_iter().map(|r| r.into_muc_invite()).collect())
+    }
+
+    /// Join the room of the pending invite to `room` as `nick`, using the
+    /// password the invite carried.
+    pub async fn accept_invite(&self, room: &str, nick: &str) -> Result<(), MessagingError> {
+        let invite = self.take_invite(room).await?;
+        self.request_join(&invite.room, nick, invite.password).await
+    }
+
+    /// Turn down the pending invite to `room`. Invites the room relayed are
+    /// declined back to the inviter; direct invites have no reply and are
+    /// just forgotten.
+    pub async fn decline_invite(
+        &self,
+        room: &str,
+        reason: Option<&str>,
+    ) -> Result<(), MessagingError> {
+        let invite = self.take_invite(room).await?;
+
+        #[cfg(feature = "native")]
+        if invite.mediated {
+            let _ = self.event_bus.publish(Event::new(
+                Channel::new(channels::UI_MUC_INVITE_DECLINE).unwrap(),
+                EventSource::System("muc".into()),
+                EventPayload::MucInviteDeclineRequested {
+                    room: invite.room,
+                    to: invite.from,
+                    reason: reason.map(str::to_string),
+                },
+            ));
+        }
+        #[cfg(not(feature = "native"))]
+        let _ = (invite, reason);
+
+        Ok(())
+    }
+
+    async fn take_invite(&self, room: &str) -> Result<MucInvite, MessagingError> {
+        let room_s = Jid::new(room);
+        let rows: Vec<StoredInvite> = self
+            .db
+            .query(
+                "SELECT room_jid, from_jid, reason, password, mediated, received_at \
+                 FROM muc_invites WHERE room_jid = ?1",
+                &[&room_s],
+            )
+            .await?;
+        let Some(invite) = rows.into_iter().next() else {
+            return Err(MessagingError::InviteNotFound(room.to_string()));
+        };
+        self.forget_invite(room).await?;
+        Ok(invite.into_muc_invite())
+    }
+
+    async fn forget_invite(&self, room: &str) -> Result<(), MessagingError> {
+        let room_s = Jid::new(room);
+        self.db
+            .execute("DELETE FROM muc_invites WHERE room_jid = ?1", &[&room_s])
+            .await?;
+        Ok(())
+    }
+
+    /// Keep `invite` until it is answered, replacing any earlier invite to
+    /// the same room. Invites to rooms we are in are ignored.
+    async fn store_invite(&self, invite: &MucInvite) -> Result<(), MessagingError> {
+        let room_s = Jid::new(&invite.room);
+        let joined = self
+            .get_joined_rooms()
+            .await?
+            .iter()
+            .any(|joined| joined.room_jid == room_s.as_str());
+        if joined {
+            return Ok(());
+        }
+
+        let from_s = Jid::new(&invite.from);
+        let mediated = i64::from(invite.mediated);
+        let received_at = invite.received_at.to_rfc3339();
+        self.db
+            .execute(
+                "INSERT OR REPLACE INTO muc_invites \
+                 (room_jid, from_jid, reason, password, mediated, received_at) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                &[
+                    &room_s,
+                    &from_s,
+                    &invite.reason,
+                    &invite.password,
+                    &mediated,
+                    &received_at,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// As a moderator, ask `room` to remove message `id` for everyone
+    /// (XEP-0425). The stored copy becomes a tombstone once the room
+    /// announces the removal.
+    pub async fn moderate_message(
+        &self,
+        room: &str,
+        id: &str,
+        reason: Option<&str>,
+    ) -> Result<(), MessagingError> {
+        let room_s = Jid::new(room);
+        let id_s = id.to_string();
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT stanza_id FROM messages \
+                 WHERE id = ?1 AND to_jid = ?2 AND message_type = 'groupchat'",
+                &[&id_s, &room_s],
+            )
+            .await?;
+        let Some(row) = rows.first() else {
+            return Err(MessagingError::MessageNotFound(id.to_string()));
+        };
+        let Some(SqlValue::Text(stanza_id)) = row.get(0) else {
+            return Err(MessagingError::NotRetractable(
+                "not yet confirmed by the room".to_string(),
+            ));
+        };
+
         #[cfg(feature = "native")]
         {
             let _ = self.event_bus.publish(Event::new(
-                Channel::new("ui.muc.send").unwrap(),
+                Channel::new(channels::UI_MUC_MODERATE).unwrap(),
                 EventSource::System("muc".into()),
-                EventPayload::MucSendRequested {
-                    room: room.to_string(),
-                    body: body.to_string(),
+                EventPayload::MessageModerateRequested {
+                    room: room_s.into_string(),
+                    id: stanza_id.clone(),
+                    reason: reason.map(str::to_string),
                 },
             ));
         }
+        #[cfg(not(feature = "native"))]
+        let _ = (stanza_id, reason);
 
         Ok(())
     }
@@ -1015,17 +2215,18 @@ impl<D: Database> MucManager<D> {
         limit: u32,
         before: Option<&str>,
     ) -> Result<Vec<ChatMessage>, MessagingError> {
-        let room_s = room.to_string();
+        let room_s = Jid::new(room);
         let limit_i = i64::from(limit);
 
         let rows: Vec<StoredMessage> = if let Some(before_ts) = before {
             let before_s = before_ts.to_string();
             self.db
                 .query(
-                    "SELECT id, from_jid, to_jid, body, timestamp, message_type, thread \
-                     FROM messages \
+                    "SELECT id, from_jid, to_jid, messages.body, timestamp, message_type, thread, embeds, \
+                     retracted_at, messages.lang, t.lang, t.body \
+                     FROM messages LEFT JOIN message_translations t ON t.message_id = messages.id \
                      WHERE to_jid = ?1 AND message_type = 'groupchat' AND timestamp < ?2 \
-                     ORDER BY timestamp DESC \
+                     ORDER BY timestamp DESC, id DESC \
                      LIMIT ?3",
                     &[&room_s, &before_s, &limit_i],
                 )
@@ -1033,10 +2234,11 @@ impl<D: Database> MucManager<D> {
         } else {
             self.db
                 .query(
-                    "SELECT id, from_jid, to_jid, body, timestamp, message_type, thread \
-                     FROM messages \
+                    "SELECT id, from_jid, to_jid, messages.body, timestamp, message_type, thread, embeds, \
+                     retracted_at, messages.lang, t.lang, t.body \
+                     FROM messages LEFT JOIN message_translations t ON t.message_id = messages.id \
                      WHERE to_jid = ?1 AND message_type = 'groupchat' \
-                     ORDER BY timestamp DESC \
+                     ORDER BY timestamp DESC, id DESC \
                      LIMIT ?2",
                     &[&room_s, &limit_i],
                 )
@@ -1048,51 +2250,26 @@ impl<D: Database> MucManager<D> {
 
     pub fn get_occupants(&self, room: &str) -> Vec<MucOccupant> {
         let occupants = self.occupants.read().unwrap();
-        match occupants.get(room) {
+        match occupants.get(&Jid::new(room)) {
             Some(map) => map.values().cloned().collect(),
             None => Vec::new(),
         }
     }
 
-    async fn persist_message(&self, message: &ChatMessage) -> Result<(), MessagingError> {
-        let id = message.id.clone();
-        let from = message.from.clone();
-        let to = message.to.clone();
-        let body = message.body.clone();
-        let ts = message.timestamp.to_rfc3339();
-        let mt = message_type_to_str(&message.message_type).to_string();
-        let thread = message.thread.clone();
-        let read = 0_i64;
-        
-        let embeds = if message.embeds.is_empty() {
-            None
-        } else {
-            Some(serde_json::to_string(&message.embeds).unwrap_or_default())
-        };
-
-        self.db
-            .execute(
-                "INSERT OR IGNORE INTO messages (id, from_jid, to_jid, body, timestamp, message_type, thread, read, embeds) \
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
-                &[&id, &from, &to, &body, &ts, &mt, &thread, &read, &embeds],
-            )
-            .await?;
-        Ok(())
-    }
-
+    /// Returns `false` if the message was already stored.
     async fn persist_room_message(
         &self,
         room: &str,
         message: &ChatMessage,
-    ) -> Result<(), MessagingError> {
+    ) -> Result<bool, MessagingError> {
         let mut normalized = message.clone();
         normalized.to = room.to_string();
         normalized.message_type = MessageType::Groupchat;
-        self.persist_message(&normalized).await
+        Ok(waddle_storage::store_message(self.db.as_ref(), &normalized).await?)
     }
 
     async fn mark_room_joined(&self, room: &str, nick: &str) -> Result<(), MessagingError> {
-        let room_s = room.to_string();
+        let room_s = Jid::new(room);
         let nick_s = nick.to_string();
         let joined = 1_i64;
         let subject: Option<String> = None;
@@ -1108,8 +2285,21 @@ impl<D: Database> MucManager<D> {
         Ok(())
     }
 
+    async fn rename_own_nick(&self, room: &str, new_nick: &str) -> Result<(), MessagingError> {
+        let room_s = Jid::new(room);
+        let nick_s = new_nick.to_string();
+
+        self.db
+            .execute(
+                "UPDATE muc_rooms SET nick = ?1 WHERE room_jid = ?2",
+                &[&nick_s, &room_s],
+            )
+            .await?;
+        Ok(())
+    }
+
     async fn mark_room_left(&self, room: &str) -> Result<(), MessagingError> {
-        let room_s = room.to_string();
+        let room_s = Jid::new(room);
         let joined = 0_i64;
 
         self.db
@@ -1119,13 +2309,36 @@ impl<D: Database> MucManager<D> {
             )
             .await?;
 
-        self.occupants.write().unwrap().remove(room);
+        self.occupants.write().unwrap().remove(&Jid::new(room));
         Ok(())
     }
 
-    async fn update_subject(&self, room: &str, subject: &str) -> Result<(), MessagingError> {
-        let room_s = room.to_string();
+    /// Subject changes recorded for `room`, oldest first.
+    pub async fn subject_history(
+        &self,
+        room: &str,
+    ) -> Result<Vec<MucSubjectChange>, MessagingError> {
+        let room_s = Jid::new(room);
+        let rows: Vec<MucSubjectChange> = self
+            .db
+            .query(
+                "SELECT room_jid, subject, set_by, changed_at FROM muc_subject_history \
+                 WHERE room_jid = ?1 ORDER BY id ASC",
+                &[&room_s],
+            )
+            .await?;
+        Ok(rows)
+    }
+
+    async fn update_subject(
+        &self,
+        room: &str,
+        subject: &str,
+        set_by: Option<&str>,
+    ) -> Result<(), MessagingError> {
+        let room_s = Jid::new(room);
         let subject_s = subject.to_string();
+        let set_by_s = set_by.map(|s| s.to_string());
 
         self.db
             .execute(
@@ -1133,12 +2346,50 @@ impl<D: Database> MucManager<D> {
                 &[&subject_s, &room_s],
             )
             .await?;
+
+        // Rooms replay the current subject on every join; only record it
+        // when it differs from the last entry we have.
+        let last: Vec<MucSubjectChange> = self
+            .db
+            .query(
+                "SELECT room_jid, subject, set_by, changed_at FROM muc_subject_history \
+                 WHERE room_jid = ?1 ORDER BY id DESC LIMIT 1",
+                &[&room_s],
+            )
+            .await?;
+        if let Some(last) = last.first()
+            && last.subject == subject_s
+            && last.set_by == set_by_s
+        {
+            return Ok(());
+        }
+
+        let changed_at = Utc::now().to_rfc3339();
+        self.db
+            .execute(
+                "INSERT INTO muc_subject_history (room_jid, subject, set_by, changed_at) \
+                 VALUES (?1, ?2, ?3, ?4)",
+                &[&room_s, &subject_s, &set_by_s, &changed_at],
+            )
+            .await?;
         Ok(())
     }
 
+    /// Move the occupant known as `old_nick` to `new_nick`, keeping their
+    /// role and affiliation until the room's next presence for them.
+    fn rename_occupant(&self, room: &str, old_nick: &str, new_nick: &str) {
+        let mut occupants = self.occupants.write().unwrap();
+        let room_occupants = occupants.entry(Jid::new(room)).or_default();
+
+        if let Some(mut occupant) = room_occupants.remove(old_nick) {
+            occupant.nick = new_nick.to_string();
+            room_occupants.insert(new_nick.to_string(), occupant);
+        }
+    }
+
     fn track_occupant(&self, room: &str, occupant: &MucOccupant) {
         let mut occupants = self.occupants.write().unwrap();
-        let room_occupants = occupants.entry(room.to_string()).or_default();
+        let room_occupants = occupants.entry(Jid::new(room)).or_default();
 
         if matches!(occupant.role, MucRole::None) {
             room_occupants.remove(&occupant.nick);
@@ -1150,17 +2401,49 @@ impl<D: Database> MucManager<D> {
     #[cfg(feature = "native")]
     pub async fn handle_event(&self, event: &Event) {
         match &event.payload {
+            EventPayload::ConnectionEstablished { .. } => {
+                if let Err(e) = self.rejoin_rooms().await {
+                    error!(error = %e, "failed to rejoin MUC rooms");
+                }
+            }
+            EventPayload::ConnectionLost { .. } => {
+                let pending: Vec<Jid> = self.rejoining.read().unwrap().iter().cloned().collect();
+                for room in pending {
+                    self.finish_rejoin(&room, MucRejoinStatus::Failed);
+                }
+            }
             EventPayload::MucJoined { room, nick } => {
                 debug!(room = %room, nick = %nick, "joined MUC room");
                 if let Err(e) = self.mark_room_joined(room, nick).await {
                     error!(error = %e, room = %room, "failed to persist room join");
                 }
+                if let Err(e) = self.forget_invite(room).await {
+                    error!(error = %e, room = %room, "failed to clear answered invite");
+                }
+                self.finish_rejoin(room, MucRejoinStatus::Rejoined);
+
+                // The join presence always says available.
+                let (show, status) = self.own_presence.read().unwrap().clone();
+                let differs = match show {
+                    PresenceShow::Available => status.is_some(),
+                    PresenceShow::Unavailable | PresenceShow::Invisible => false,
+                    _ => true,
+                };
+                if differs {
+                    self.send_room_presence(room, nick, show, status.as_deref());
+                }
+            }
+            EventPayload::OwnPresenceChanged { show, status } => {
+                if let Err(e) = self.update_room_presence(show, status.as_deref()).await {
+                    error!(error = %e, "failed to update presence in joined rooms");
+                }
             }
             EventPayload::MucLeft { room } => {
                 debug!(room = %room, "left MUC room");
                 if let Err(e) = self.mark_room_left(room).await {
                     error!(error = %e, room = %room, "failed to persist room leave");
                 }
+                self.finish_rejoin(room, MucRejoinStatus::Failed);
             }
             EventPayload::MucMessageReceived { room, message } => {
                 debug!(
@@ -1169,16 +2452,52 @@ impl<D: Database> MucManager<D> {
                     from = %message.from,
                     "MUC message received, persisting"
                 );
-                if let Err(e) = self.persist_room_message(room, message).await {
-                    error!(error = %e, room = %room, "failed to persist MUC message");
+                match self.persist_room_message(room, message).await {
+                    // Replayed history is already stored and never alerts twice.
+                    Ok(true) => {
+                        if let Err(e) = self.flag_mention(room, message).await {
+                            error!(error = %e, room = %room, "failed to flag MUC mention");
+                        }
+                    }
+                    Ok(false) => {}
+                    Err(e) => {
+                        error!(error = %e, room = %room, "failed to persist MUC message");
+                    }
                 }
             }
-            EventPayload::MucSubjectChanged { room, subject } => {
-                debug!(room = %room, subject = %subject, "MUC subject changed");
-                if let Err(e) = self.update_subject(room, subject).await {
+            EventPayload::MucSubjectChanged {
+                room,
+                subject,
+                set_by,
+            } => {
+                debug!(
+                    room = %room,
+                    subject = %subject,
+                    set_by = ?set_by,
+                    "MUC subject changed"
+                );
+                if let Err(e) = self.update_subject(room, subject, set_by.as_deref()).await {
                     error!(error = %e, room = %room, "failed to persist subject change");
                 }
             }
+            EventPayload::MucOccupantRenamed {
+                room,
+                old_nick,
+                new_nick,
+                own,
+            } => {
+                debug!(room = %room, old = %old_nick, new = %new_nick, "MUC occupant renamed");
+                self.rename_occupant(room, old_nick, new_nick);
+                if *own && let Err(e) = self.rename_own_nick(room, new_nick).await {
+                    error!(error = %e, room = %room, "failed to persist own nick change");
+                }
+            }
+            EventPayload::MucInviteReceived { invite } => {
+                debug!(room = %invite.room, from = %invite.from, "MUC invite received");
+                if let Err(e) = self.store_invite(invite).await {
+                    error!(error = %e, room = %invite.room, "failed to persist MUC invite");
+                }
+            }
             EventPayload::MucOccupantChanged { room, occupant } => {
                 debug!(
                     room = %room,
@@ -1195,7 +2514,7 @@ impl<D: Database> MucManager<D> {
     pub async fn run(self: Arc<Self>) -> Result<(), MessagingError> {
         let mut sub = self
             .event_bus
-            .subscribe("xmpp.muc.**")
+            .subscribe("{system,xmpp}.**")
             .map_err(|e| MessagingError::EventBus(e.to_string()))?;
 
         loop {
@@ -1262,6 +2581,11 @@ mod tests {
             message_type: MessageType::Chat,
             thread: None,
             embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
         }
     }
 
@@ -1415,6 +2739,11 @@ mod tests {
                 message_type: MessageType::Chat,
                 thread: None,
                 embeds: vec![],
+                origin_id: None,
+                stanza_id: None,
+                retracted_at: None,
+                lang: None,
+                translation: None,
             };
             manager.persist_message(&msg).await.unwrap();
         }
@@ -1442,6 +2771,11 @@ mod tests {
                 message_type: MessageType::Chat,
                 thread: None,
                 embeds: vec![],
+                origin_id: None,
+                stanza_id: None,
+                retracted_at: None,
+                lang: None,
+                translation: None,
             };
             manager.persist_message(&msg).await.unwrap();
         }
@@ -1517,6 +2851,321 @@ mod tests {
         manager.handle_event(&event).await;
     }
 
+    #[tokio::test]
+    async fn delivery_status_only_moves_forward() {
+        let (manager, event_bus, _dir) = setup().await;
+        let mut sends = event_bus.subscribe("ui.message.send").unwrap();
+        set_connection_online(&manager).await;
+
+        let message = manager
+            .send_message("bob@example.com", "hello")
+            .await
+            .unwrap();
+        assert!(matches!(
+            sends.recv().await.unwrap().payload,
+            EventPayload::MessageSendRequested {
+                request_receipt: true,
+                ..
+            }
+        ));
+        assert_eq!(
+            manager.get_message_status(&message.id).await.unwrap(),
+            Some(DeliveryStatus::Pending)
+        );
+
+        let mut sent = message.clone();
+        sent.to = "bob@example.com".to_string();
+        let sent_event = make_event(
+            "xmpp.message.sent",
+            EventPayload::MessageSent { message: sent },
+        );
+        manager.handle_event(&sent_event).await;
+        assert_eq!(
+            manager.get_message_status(&message.id).await.unwrap(),
+            Some(DeliveryStatus::Sent)
+        );
+
+        // A receipt from anyone but the recipient is ignored.
+        let spoofed = make_event(
+            "xmpp.message.delivered",
+            EventPayload::MessageDelivered {
+                id: message.id.clone(),
+                to: "mallory@example.com".to_string(),
+            },
+        );
+        manager.handle_event(&spoofed).await;
+        assert_eq!(
+            manager.get_message_status(&message.id).await.unwrap(),
+            Some(DeliveryStatus::Sent)
+        );
+
+        manager
+            .handle_event(&make_event(
+                "xmpp.message.displayed",
+                EventPayload::MessageDisplayed {
+                    id: message.id.clone(),
+                    to: "bob@example.com".to_string(),
+                },
+            ))
+            .await;
+        manager
+            .handle_event(&make_event(
+                "xmpp.message.delivered",
+                EventPayload::MessageDelivered {
+                    id: message.id.clone(),
+                    to: "bob@example.com".to_string(),
+                },
+            ))
+            .await;
+        manager.handle_event(&sent_event).await;
+        assert_eq!(
+            manager.get_message_status(&message.id).await.unwrap(),
+            Some(DeliveryStatus::Displayed)
+        );
+    }
+
+    #[tokio::test]
+    async fn incoming_messages_have_no_delivery_status() {
+        let (manager, _, _dir) = setup().await;
+        let message = make_chat_message("in-1", "bob@example.com", "alice@example.com", "hi");
+        manager
+            .handle_event(&make_event(
+                "xmpp.message.received",
+                EventPayload::MessageReceived { message },
+            ))
+            .await;
+        manager
+            .handle_event(&make_event(
+                "xmpp.message.delivered",
+                EventPayload::MessageDelivered {
+                    id: "in-1".to_string(),
+                    to: "alice@example.com".to_string(),
+                },
+            ))
+            .await;
+
+        assert_eq!(manager.get_message_status("in-1").await.unwrap(), None);
+        assert_eq!(manager.get_message_status("unknown").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn receipt_requests_are_acknowledged_unless_disabled() {
+        let (manager, event_bus, _dir) = setup().await;
+        let mut receipts = event_bus.subscribe("ui.receipt.send").unwrap();
+        let request = make_event(
+            "xmpp.message.receipt_requested",
+            EventPayload::ReceiptRequested {
+                id: "in-2".to_string(),
+                from: "bob@example.com/phone".to_string(),
+            },
+        );
+
+        manager.handle_event(&request).await;
+        let EventPayload::ReceiptSendRequested { to, id } = receipts.recv().await.unwrap().payload
+        else {
+            panic!("expected a receipt");
+        };
+        assert_eq!(to, "bob@example.com/phone");
+        assert_eq!(id, "in-2");
+
+        let db = waddle_storage::open_database(&_dir.path().join("quiet.db"))
+            .await
+            .unwrap();
+        let quiet =
+            MessageManager::new(Arc::new(db), event_bus.clone()).with_config(MessagingConfig {
+                request_receipts: false,
+                send_receipts: false,
+                ..Default::default()
+            });
+        quiet.handle_event(&request).await;
+        let next =
+            tokio::time::timeout(std::time::Duration::from_millis(20), receipts.recv()).await;
+        assert!(next.is_err(), "disabled receipts were sent: {next:?}");
+    }
+
+    #[tokio::test]
+    async fn displayed_marker_from_another_device_marks_earlier_messages_read() {
+        let (manager, event_bus, _dir) = setup().await;
+        let mut synced = event_bus
+            .subscribe("system.conversation.read_synced")
+            .unwrap();
+        for (id, seconds_ago) in [("in-1", 30), ("in-2", 20), ("in-3", 10)] {
+            let mut msg = make_chat_message(id, "bob@example.com", "me@example.com", id);
+            msg.timestamp = Utc::now() - chrono::Duration::seconds(seconds_ago);
+            manager
+                .handle_event(&make_event(
+                    "xmpp.message.received",
+                    EventPayload::MessageReceived { message: msg },
+                ))
+                .await;
+        }
+
+        let marker = |id: &str| {
+            make_event(
+                "xmpp.message.displayed_elsewhere",
+                EventPayload::MessageDisplayedElsewhere {
+                    conversation: "bob@example.com".to_string(),
+                    id: id.to_string(),
+                    timestamp: Utc::now(),
+                },
+            )
+        };
+        manager.handle_event(&marker("in-2")).await;
+        assert!(matches!(
+            synced.recv().await.unwrap().payload,
+            EventPayload::ReadStateSynced { ref conversation, unread_count: 1 }
+                if conversation == "bob@example.com"
+        ));
+
+        // An older marker arriving late from the archive changes nothing.
+        manager.handle_event(&marker("in-1")).await;
+        let next = tokio::time::timeout(std::time::Duration::from_millis(20), synced.recv()).await;
+        assert!(next.is_err(), "older marker was applied: {next:?}");
+
+        let rows: Vec<Row> = manager
+            .db
+            .query("SELECT id FROM messages WHERE read = 0", &[])
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get(0), Some(&SqlValue::Text("in-3".to_string())));
+    }
+
+    #[tokio::test]
+    async fn retracting_own_message_leaves_tombstone_and_sends_retraction() {
+        let (manager, event_bus, _dir) = setup().await;
+        let mut retractions = event_bus.subscribe("ui.message.retract").unwrap();
+        let mut retracted = event_bus.subscribe("system.message.retracted").unwrap();
+        set_connection_online(manager.as_ref()).await;
+
+        let sent = manager
+            .send_message("bob@example.com", "Wrong chat")
+            .await
+            .unwrap();
+        manager.retract_message(&sent.id).await.unwrap();
+
+        let EventPayload::MessageRetractRequested {
+            to,
+            id,
+            message_type,
+        } = retractions.recv().await.unwrap().payload
+        else {
+            panic!("expected a retraction");
+        };
+        assert_eq!(to, "bob@example.com");
+        assert_eq!(id, sent.id);
+        assert!(matches!(message_type, MessageType::Chat));
+        assert!(matches!(
+            retracted.recv().await.unwrap().payload,
+            EventPayload::MessageRetracted { ref id, ref conversation, .. }
+                if *id == sent.id && conversation == "bob@example.com"
+        ));
+
+        let messages = manager
+            .get_messages("bob@example.com", 50, None)
+            .await
+            .unwrap();
+        assert_eq!(messages[0].body, "");
+        assert!(messages[0].retracted_at.is_some());
+        assert!(matches!(
+            manager.retract_message(&sent.id).await,
+            Err(MessagingError::MessageNotFound(_))
+        ));
+
+        let theirs = make_chat_message("in-1", "bob@example.com", "me@example.com", "Hi");
+        manager
+            .handle_event(&make_event(
+                "xmpp.message.received",
+                EventPayload::MessageReceived { message: theirs },
+            ))
+            .await;
+        assert!(matches!(
+            manager.retract_message("in-1").await,
+            Err(MessagingError::NotRetractable(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn received_retractions_only_apply_to_the_senders_messages() {
+        let (manager, _, _dir) = setup().await;
+        let msg = make_chat_message("in-1", "bob@example.com", "me@example.com", "Oops");
+        manager
+            .handle_event(&make_event(
+                "xmpp.message.received",
+                EventPayload::MessageReceived { message: msg },
+            ))
+            .await;
+
+        let retraction = |from: &str| {
+            make_event(
+                "xmpp.message.retracted",
+                EventPayload::MessageRetractionReceived {
+                    conversation: from.to_string(),
+                    from: from.to_string(),
+                    id: "in-1".to_string(),
+                    moderated_by: None,
+                    reason: None,
+                },
+            )
+        };
+        manager
+            .handle_event(&retraction("mallory@example.com"))
+            .await;
+        let messages = manager
+            .get_messages("bob@example.com", 50, None)
+            .await
+            .unwrap();
+        assert_eq!(messages[0].body, "Oops");
+
+        manager.handle_event(&retraction("bob@example.com")).await;
+        let messages = manager
+            .get_messages("bob@example.com", 50, None)
+            .await
+            .unwrap();
+        assert_eq!(messages[0].body, "");
+        assert!(messages[0].retracted_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn room_moderation_removes_any_occupants_message() {
+        let (manager, _, _dir) = setup().await;
+        let mut msg = make_chat_message(
+            "gc-1",
+            "room@conference.example.com/mallory",
+            "room@conference.example.com",
+            "Buy now",
+        );
+        msg.message_type = MessageType::Groupchat;
+        msg.stanza_id = Some("archive-1".to_string());
+        waddle_storage::store_message(manager.db.as_ref(), &msg)
+            .await
+            .unwrap();
+
+        manager
+            .handle_event(&make_event(
+                "xmpp.muc.message.retracted",
+                EventPayload::MessageRetractionReceived {
+                    conversation: "room@conference.example.com".to_string(),
+                    from: "room@conference.example.com".to_string(),
+                    id: "archive-1".to_string(),
+                    moderated_by: Some("room@conference.example.com/mod".to_string()),
+                    reason: Some("Spam".to_string()),
+                },
+            ))
+            .await;
+
+        let rows: Vec<Row> = manager
+            .db
+            .query(
+                "SELECT body, retracted_at FROM messages WHERE id = 'gc-1'",
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows[0].get(0), Some(&SqlValue::Text(String::new())));
+        assert!(matches!(rows[0].get(1), Some(SqlValue::Text(_))));
+    }
+
     #[tokio::test]
     async fn handle_chat_state_received_does_not_error() {
         let (manager, _, _dir) = setup().await;
@@ -1544,6 +3193,11 @@ mod tests {
             message_type: MessageType::Chat,
             thread: Some("thread-123".to_string()),
             embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
         };
         manager.persist_message(&msg).await.unwrap();
 
@@ -1569,6 +3223,11 @@ mod tests {
             message_type: MessageType::Chat,
             thread: None,
             embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
         };
         manager.persist_message(&chat_msg).await.unwrap();
 
@@ -1581,6 +3240,11 @@ mod tests {
             message_type: MessageType::Groupchat,
             thread: None,
             embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
         };
         manager.persist_message(&gc_msg).await.unwrap();
 
@@ -1702,6 +3366,30 @@ mod tests {
         assert_eq!(stored[0].id, message.id);
     }
 
+    #[tokio::test]
+    async fn shutdown_checkpoints_offline_queue_into_database_file() {
+        let dir = TempDir::new().expect("failed to create temp dir");
+        let db = waddle_storage::open_native_database(&dir.path().join("test.db"))
+            .await
+            .expect("failed to open database");
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let manager = MessageManager::new(Arc::new(db), event_bus);
+        manager
+            .send_message("bob@example.com", "queued before exit")
+            .await
+            .unwrap();
+
+        manager.shutdown().await.unwrap();
+
+        let wal = std::fs::metadata(dir.path().join("test.db-wal")).map_or(0, |meta| meta.len());
+        assert_eq!(wal, 0);
+        let pending = manager
+            .load_offline_queue_by_status(OFFLINE_STATUS_PENDING)
+            .await
+            .unwrap();
+        assert_eq!(pending.len(), 1);
+    }
+
     #[tokio::test]
     async fn reconnect_drains_offline_queue_fifo_and_marks_sent() {
         let (manager, event_bus, _dir) = setup().await;
@@ -1775,6 +3463,61 @@ mod tests {
         assert_eq!(rows[1].get(0), Some(&SqlValue::Text("sent".to_string())));
     }
 
+    #[tokio::test]
+    async fn drained_items_are_marked_sent_as_they_are_published() {
+        let (manager, event_bus, _dir) = setup().await;
+
+        manager
+            .send_message("bob@example.com", "queued")
+            .await
+            .unwrap();
+        manager
+            .handle_event(&make_event(
+                "ui.presence.set",
+                EventPayload::PresenceSetRequested {
+                    show: PresenceShow::Away,
+                    status: None,
+                    priority: 0,
+                    idle_since: None,
+                },
+            ))
+            .await;
+
+        let mut sub = event_bus.subscribe("ui.**").unwrap();
+        set_connection_online(manager.as_ref()).await;
+        sub.recv().await.unwrap();
+        sub.recv().await.unwrap();
+
+        // Nothing has confirmed the message yet, but it is not pending any
+        // more, so a reconnect does not publish it twice.
+        let rows: Vec<Row> = manager
+            .db
+            .query("SELECT status FROM offline_queue ORDER BY id ASC", &[])
+            .await
+            .unwrap();
+        assert_eq!(rows[0].get(0), Some(&SqlValue::Text("sent".to_string())));
+        assert_eq!(
+            rows[1].get(0),
+            Some(&SqlValue::Text("confirmed".to_string()))
+        );
+
+        manager
+            .handle_event(&make_event(
+                "system.connection.lost",
+                EventPayload::ConnectionLost {
+                    reason: "test".to_string(),
+                    will_retry: true,
+                },
+            ))
+            .await;
+        set_connection_online(manager.as_ref()).await;
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(50), sub.recv())
+                .await
+                .is_err()
+        );
+    }
+
     #[tokio::test]
     async fn delivery_receipt_marks_queued_message_confirmed() {
         let (manager, _event_bus, _dir) = setup().await;
@@ -1820,61 +3563,301 @@ mod tests {
         assert_eq!(row.get(0), Some(&SqlValue::Text("confirmed".to_string())));
     }
 
+    /// Queues `bodies` to bob while offline, reconnects and reports each
+    /// one sent.
+    async fn queue_and_send_messages<D: Database>(
+        manager: &MessageManager<D>,
+        bodies: &[&str],
+    ) -> Vec<ChatMessage> {
+        let mut queued = Vec::new();
+        for body in bodies {
+            queued.push(manager.send_message("bob@example.com", body).await.unwrap());
+        }
+        set_connection_online(manager).await;
+        for message in &queued {
+            manager
+                .handle_event(&make_event(
+                    "xmpp.message.sent",
+                    EventPayload::MessageSent {
+                        message: make_chat_message(
+                            &message.id,
+                            "alice@example.com",
+                            "bob@example.com",
+                            &message.body,
+                        ),
+                    },
+                ))
+                .await;
+        }
+        queued
+    }
+
+    async fn receive_archived<D: Database>(manager: &MessageManager<D>, message: ChatMessage) {
+        manager
+            .handle_event(&make_event(
+                "xmpp.mam.result.received",
+                EventPayload::MamResultReceived {
+                    query_id: "q1".to_string(),
+                    messages: vec![message],
+                    complete: true,
+                },
+            ))
+            .await;
+    }
+
+    async fn queue_statuses<D: Database>(manager: &MessageManager<D>) -> Vec<String> {
+        let rows: Vec<Row> = manager
+            .db
+            .query("SELECT status FROM offline_queue ORDER BY id ASC", &[])
+            .await
+            .unwrap();
+        rows.iter()
+            .map(|row| match row.get(0) {
+                Some(SqlValue::Text(status)) => status.clone(),
+                other => panic!("unexpected status {other:?}"),
+            })
+            .collect()
+    }
+
     #[tokio::test]
-    async fn mam_result_reconciles_sent_queue_item_by_content() {
+    async fn mam_result_reconciles_sent_queue_item_by_origin_id() {
         let (manager, _event_bus, _dir) = setup().await;
 
-        let queued = manager
-            .send_message("bob@example.com", "reconcile me")
+        let queued = queue_and_send_messages(manager.as_ref(), &["ok", "ok"]).await;
+
+        let mut archived = make_chat_message(
+            "archive-id-42",
+            "alice@example.com",
+            "bob@example.com",
+            "ok",
+        );
+        archived.origin_id = Some(queued[0].id.clone());
+        receive_archived(manager.as_ref(), archived).await;
+
+        assert_eq!(
+            queue_statuses(manager.as_ref()).await,
+            ["confirmed", "sent"]
+        );
+    }
+
+    #[tokio::test]
+    async fn archived_copy_without_origin_id_needs_manual_retry() {
+        let (manager, event_bus, _dir) = setup().await;
+
+        let queued = queue_and_send_messages(manager.as_ref(), &["reconcile me"])
             .await
-            .unwrap();
-        set_connection_online(manager.as_ref()).await;
+            .remove(0);
+        receive_archived(
+            manager.as_ref(),
+            make_chat_message(
+                "archive-id-42",
+                "alice@example.com",
+                "bob@example.com",
+                "reconcile me",
+            ),
+        )
+        .await;
+
+        assert_eq!(queue_statuses(manager.as_ref()).await, ["ambiguous"]);
+        assert_eq!(
+            manager.ambiguous_queued_messages().await.unwrap(),
+            vec![queued.id.clone()]
+        );
+
+        let mut sub = event_bus.subscribe("ui.message.send").unwrap();
+        assert!(manager.retry_queued_message(&queued.id).await.unwrap());
+        let resent = tokio::time::timeout(std::time::Duration::from_millis(100), sub.recv())
+            .await
+            .expect("timed out waiting for retried message")
+            .expect("expected retried message");
+        assert_eq!(
+            resent.correlation_id.map(|id| id.to_string()),
+            Some(queued.id.clone())
+        );
+        assert!(!manager.retry_queued_message(&queued.id).await.unwrap());
+    }
 
+    const ROOM: &str = "lobby@conference.example.com";
+
+    /// Queues a groupchat send while offline for a room joined as `alice`,
+    /// reconnects and rejoins, and returns the drained event.
+    async fn queue_and_drain_room_message<D: Database>(
+        manager: &MessageManager<D>,
+        event_bus: &Arc<dyn EventBus>,
+    ) -> Event {
+        let room = ROOM.to_string();
+        let nick = "alice".to_string();
+        manager
+            .db
+            .execute(
+                "INSERT INTO muc_rooms (room_jid, nick, joined, subject) VALUES (?1, ?2, 0, NULL)",
+                &[&room, &nick],
+            )
+            .await
+            .unwrap();
         manager
             .handle_event(&make_event(
-                "xmpp.message.sent",
-                EventPayload::MessageSent {
-                    message: make_chat_message(
-                        &queued.id,
-                        "alice@example.com",
-                        "bob@example.com",
-                        "reconcile me",
-                    ),
+                "ui.muc.send",
+                EventPayload::MucSendRequested {
+                    room: room.clone(),
+                    body: "queued for the room".to_string(),
                 },
             ))
             .await;
 
-        let mam_message = ChatMessage {
-            id: "archive-id-42".to_string(),
-            from: "alice@example.com".to_string(),
-            to: "bob@example.com".to_string(),
-            body: "reconcile me".to_string(),
-            timestamp: Utc::now(),
-            message_type: MessageType::Chat,
-            thread: None,
-            embeds: vec![],
-        };
+        let mut sub = event_bus.subscribe("ui.muc.send").unwrap();
+        set_connection_online(manager).await;
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(50), sub.recv())
+                .await
+                .is_err(),
+            "room message must not be sent before the room is rejoined"
+        );
 
         manager
             .handle_event(&make_event(
-                "xmpp.mam.result.received",
-                EventPayload::MamResultReceived {
-                    query_id: "q1".to_string(),
-                    messages: vec![mam_message],
-                    complete: true,
-                },
+                "xmpp.muc.joined",
+                EventPayload::MucJoined { room, nick },
             ))
             .await;
+        tokio::time::timeout(std::time::Duration::from_millis(100), sub.recv())
+            .await
+            .expect("timed out waiting for drained room message")
+            .expect("expected drained room message")
+    }
+
+    #[tokio::test]
+    async fn offline_room_message_drains_after_rejoin() {
+        let (manager, event_bus, _dir) = setup().await;
+
+        let drained = queue_and_drain_room_message(manager.as_ref(), &event_bus).await;
+        assert!(matches!(
+            drained.payload,
+            EventPayload::MucSendRequested { ref room, ref body }
+                if room == ROOM && body == "queued for the room"
+        ));
+        let queued_id = drained
+            .correlation_id
+            .expect("queued room message has an id");
 
         let row: Row = manager
+            .db
+            .query_one("SELECT status FROM offline_queue", &[])
+            .await
+            .unwrap();
+        assert_eq!(row.get(0), Some(&SqlValue::Text("sent".to_string())));
+
+        let queued_id = queued_id.to_string();
+        let placeholder: Row = manager
             .db
             .query_one(
-                "SELECT status FROM offline_queue ORDER BY id ASC LIMIT 1",
-                &[],
+                "SELECT from_jid, to_jid, message_type FROM messages WHERE id = ?1",
+                &[&queued_id],
             )
             .await
             .unwrap();
+        assert_eq!(
+            placeholder.get(0),
+            Some(&SqlValue::Text(format!("{ROOM}/alice")))
+        );
+        assert_eq!(placeholder.get(1), Some(&SqlValue::Text(ROOM.to_string())));
+        assert_eq!(
+            placeholder.get(2),
+            Some(&SqlValue::Text("groupchat".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn reflected_room_message_confirms_queued_send() {
+        let (manager, event_bus, _dir) = setup().await;
+
+        let drained = queue_and_drain_room_message(manager.as_ref(), &event_bus).await;
+        let queued_id = drained.correlation_id.unwrap().to_string();
+
+        let mut reflected = make_chat_message(
+            "rewritten-by-room",
+            &format!("{ROOM}/alice"),
+            ROOM,
+            "queued for the room",
+        );
+        reflected.message_type = MessageType::Groupchat;
+        reflected.origin_id = Some(queued_id.clone());
+        manager
+            .handle_event(&make_event(
+                "xmpp.muc.message.received",
+                EventPayload::MucMessageReceived {
+                    room: ROOM.to_string(),
+                    message: reflected.clone(),
+                },
+            ))
+            .await;
+
+        let row: Row = manager
+            .db
+            .query_one("SELECT status FROM offline_queue", &[])
+            .await
+            .unwrap();
         assert_eq!(row.get(0), Some(&SqlValue::Text("confirmed".to_string())));
+
+        let stored = waddle_storage::store_message(manager.db.as_ref(), &reflected)
+            .await
+            .unwrap();
+        assert!(
+            !stored,
+            "the reflection is a copy of the queued placeholder"
+        );
+
+        let mut sub = event_bus.subscribe("ui.muc.send").unwrap();
+        manager
+            .handle_event(&make_event(
+                "system.connection.lost",
+                EventPayload::ConnectionLost {
+                    reason: "test".to_string(),
+                    will_retry: true,
+                },
+            ))
+            .await;
+        set_connection_online(manager.as_ref()).await;
+        manager
+            .handle_event(&make_event(
+                "xmpp.muc.joined",
+                EventPayload::MucJoined {
+                    room: ROOM.to_string(),
+                    nick: "alice".to_string(),
+                },
+            ))
+            .await;
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(50), sub.recv())
+                .await
+                .is_err(),
+            "confirmed room message must not be resent"
+        );
+    }
+
+    #[tokio::test]
+    async fn own_reflection_without_ids_marks_room_send_ambiguous() {
+        let (manager, event_bus, _dir) = setup().await;
+        queue_and_drain_room_message(manager.as_ref(), &event_bus).await;
+
+        let mut reflected = make_chat_message(
+            "rewritten-by-room",
+            &format!("{ROOM}/alice"),
+            ROOM,
+            "queued for the room",
+        );
+        reflected.message_type = MessageType::Groupchat;
+        manager
+            .handle_event(&make_event(
+                "xmpp.muc.message.received",
+                EventPayload::MucMessageReceived {
+                    room: ROOM.to_string(),
+                    message: reflected,
+                },
+            ))
+            .await;
+
+        assert_eq!(queue_statuses(manager.as_ref()).await, ["ambiguous"]);
     }
 }
 
@@ -1915,46 +3898,138 @@ mod muc_tests {
             message_type: MessageType::Groupchat,
             thread: None,
             embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
+        }
+    }
+
+    fn make_occupant(nick: &str, role: MucRole, affiliation: MucAffiliation) -> MucOccupant {
+        MucOccupant {
+            nick: nick.to_string(),
+            jid: None,
+            affiliation,
+            role,
         }
     }
 
-    fn make_occupant(nick: &str, role: MucRole, affiliation: MucAffiliation) -> MucOccupant {
-        MucOccupant {
-            nick: nick.to_string(),
-            jid: None,
-            affiliation,
-            role,
-        }
+    #[tokio::test]
+    async fn join_room_emits_event_and_persists() {
+        let (manager, event_bus, _dir) = setup_muc().await;
+        let mut sub = event_bus.subscribe("ui.**").unwrap();
+
+        manager
+            .join_room("room@conference.example.com", "Alice")
+            .await
+            .unwrap();
+
+        let received = tokio::time::timeout(std::time::Duration::from_millis(100), sub.recv())
+            .await
+            .expect("timed out")
+            .expect("should receive event");
+
+        assert!(matches!(
+            received.payload,
+            EventPayload::MucJoinRequested {
+                ref room,
+                ref nick,
+                history_since: None,
+                password: None,
+            } if room == "room@conference.example.com" && nick == "Alice"
+        ));
+
+        let rooms = manager.get_rooms().await.unwrap();
+        assert_eq!(rooms.len(), 1);
+        assert_eq!(rooms[0].room_jid, "room@conference.example.com");
+        assert_eq!(rooms[0].nick, "Alice");
+        assert!(!rooms[0].joined);
+    }
+
+    #[tokio::test]
+    async fn own_presence_change_is_sent_to_joined_rooms() {
+        let (manager, event_bus, _dir) = setup_muc().await;
+        for room in ["dev@conference.example.com", "ops@conference.example.com"] {
+            manager.join_room(room, "Alice").await.unwrap();
+        }
+        manager
+            .handle_event(&make_event(
+                "xmpp.muc.joined",
+                EventPayload::MucJoined {
+                    room: "dev@conference.example.com".to_string(),
+                    nick: "Alice".to_string(),
+                },
+            ))
+            .await;
+
+        let mut sub = event_bus.subscribe("ui.presence.directed").unwrap();
+        manager
+            .handle_event(&make_event(
+                "xmpp.presence.own_changed",
+                EventPayload::OwnPresenceChanged {
+                    show: PresenceShow::Away,
+                    status: Some("lunch".to_string()),
+                },
+            ))
+            .await;
+
+        let received = tokio::time::timeout(std::time::Duration::from_millis(100), sub.recv())
+            .await
+            .expect("timed out")
+            .expect("should receive event");
+        assert!(matches!(
+            received.payload,
+            EventPayload::DirectedPresenceRequested {
+                ref to,
+                show: PresenceShow::Away,
+                ref status,
+            } if to == "dev@conference.example.com/Alice" && status.as_deref() == Some("lunch")
+        ));
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(50), sub.recv())
+                .await
+                .is_err(),
+            "rooms not yet joined get no directed presence"
+        );
     }
 
     #[tokio::test]
-    async fn join_room_emits_event_and_persists() {
+    async fn room_join_repeats_current_status() {
         let (manager, event_bus, _dir) = setup_muc().await;
-        let mut sub = event_bus.subscribe("ui.**").unwrap();
+        manager
+            .handle_event(&make_event(
+                "xmpp.presence.own_changed",
+                EventPayload::OwnPresenceChanged {
+                    show: PresenceShow::Dnd,
+                    status: None,
+                },
+            ))
+            .await;
 
+        let mut sub = event_bus.subscribe("ui.presence.directed").unwrap();
         manager
-            .join_room("room@conference.example.com", "Alice")
-            .await
-            .unwrap();
+            .handle_event(&make_event(
+                "xmpp.muc.joined",
+                EventPayload::MucJoined {
+                    room: "dev@conference.example.com".to_string(),
+                    nick: "Alice".to_string(),
+                },
+            ))
+            .await;
 
         let received = tokio::time::timeout(std::time::Duration::from_millis(100), sub.recv())
             .await
             .expect("timed out")
             .expect("should receive event");
-
         assert!(matches!(
             received.payload,
-            EventPayload::MucJoinRequested {
-                ref room,
-                ref nick,
-            } if room == "room@conference.example.com" && nick == "Alice"
+            EventPayload::DirectedPresenceRequested {
+                ref to,
+                show: PresenceShow::Dnd,
+                status: None,
+            } if to == "dev@conference.example.com/Alice"
         ));
-
-        let rooms = manager.get_rooms().await.unwrap();
-        assert_eq!(rooms.len(), 1);
-        assert_eq!(rooms[0].room_jid, "room@conference.example.com");
-        assert_eq!(rooms[0].nick, "Alice");
-        assert!(!rooms[0].joined);
     }
 
     #[tokio::test]
@@ -2014,6 +4089,236 @@ mod muc_tests {
         assert!(!all_rooms[0].joined);
     }
 
+    #[tokio::test]
+    async fn browse_rooms_requests_a_page_of_the_service_items() {
+        let (manager, event_bus, _dir) = setup_muc().await;
+        let mut sub = event_bus.subscribe("ui.disco.items").unwrap();
+
+        manager.browse_rooms("conference.example.com", RsmQuery::after("room-20", 20));
+
+        let received = tokio::time::timeout(std::time::Duration::from_millis(100), sub.recv())
+            .await
+            .expect("timed out")
+            .expect("should receive event");
+        assert!(matches!(
+            received.payload,
+            EventPayload::DiscoItemsRequested { ref jid, node: None, ref page }
+                if jid == "conference.example.com" && *page == RsmQuery::after("room-20", 20)
+        ));
+    }
+
+    #[tokio::test]
+    async fn nick_changes_keep_the_occupant_and_the_room() {
+        let (manager, event_bus, _dir) = setup_muc().await;
+        let mut sub = event_bus.subscribe("ui.presence.directed").unwrap();
+        let room = "room@conference.example.com";
+
+        assert!(matches!(
+            manager.change_nick(room, "alicia").await,
+            Err(MessagingError::RoomNotJoined(_))
+        ));
+
+        manager
+            .handle_event(&make_event(
+                channels::XMPP_MUC_JOINED,
+                EventPayload::MucJoined {
+                    room: room.to_string(),
+                    nick: "alice".to_string(),
+                },
+            ))
+            .await;
+        for nick in ["alice", "bob"] {
+            manager
+                .handle_event(&make_event(
+                    channels::XMPP_MUC_OCCUPANT_CHANGED,
+                    EventPayload::MucOccupantChanged {
+                        room: room.to_string(),
+                        occupant: make_occupant(nick, MucRole::Moderator, MucAffiliation::Owner),
+                    },
+                ))
+                .await;
+        }
+
+        manager.change_nick(room, "alicia").await.unwrap();
+        let received = recv_within(&mut sub).await;
+        assert!(matches!(
+            received.payload,
+            EventPayload::DirectedPresenceRequested { ref to, .. }
+                if to == "room@conference.example.com/alicia"
+        ));
+
+        for (old_nick, new_nick, own) in [("alice", "alicia", true), ("bob", "robert", false)] {
+            manager
+                .handle_event(&make_event(
+                    channels::XMPP_MUC_OCCUPANT_RENAMED,
+                    EventPayload::MucOccupantRenamed {
+                        room: room.to_string(),
+                        old_nick: old_nick.to_string(),
+                        new_nick: new_nick.to_string(),
+                        own,
+                    },
+                ))
+                .await;
+        }
+
+        let mut occupants = manager.get_occupants(room);
+        occupants.sort_by(|a, b| a.nick.cmp(&b.nick));
+        let nicks: Vec<&str> = occupants.iter().map(|o| o.nick.as_str()).collect();
+        assert_eq!(nicks, ["alicia", "robert"]);
+        assert!(matches!(occupants[1].role, MucRole::Moderator));
+
+        let rooms = manager.get_joined_rooms().await.unwrap();
+        assert_eq!(rooms.len(), 1);
+        assert_eq!(rooms[0].nick, "alicia");
+    }
+
+    fn make_invite(room: &str, mediated: bool) -> MucInvite {
+        MucInvite {
+            room: room.to_string(),
+            from: "crone1@shakespeare.lit".to_string(),
+            reason: None,
+            password: Some("cauldronburn".to_string()),
+            mediated,
+            received_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn accepted_invites_join_with_their_password() {
+        let (manager, event_bus, _dir) = setup_muc().await;
+        let mut sub = event_bus.subscribe("ui.muc.**").unwrap();
+
+        for room in [
+            "coven@chat.shakespeare.lit",
+            "darkcave@chat.shakespeare.lit",
+        ] {
+            manager
+                .handle_event(&make_event(
+                    channels::XMPP_MUC_INVITE_RECEIVED,
+                    EventPayload::MucInviteReceived {
+                        invite: make_invite(room, false),
+                    },
+                ))
+                .await;
+        }
+        assert_eq!(manager.pending_invites().await.unwrap().len(), 2);
+
+        manager
+            .accept_invite("coven@chat.shakespeare.lit", "hecate")
+            .await
+            .unwrap();
+        let received = recv_within(&mut sub).await;
+        assert!(matches!(
+            received.payload,
+            EventPayload::MucJoinRequested { ref room, ref password, .. }
+                if room == "coven@chat.shakespeare.lit"
+                    && password.as_deref() == Some("cauldronburn")
+        ));
+        let pending = manager.pending_invites().await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].room, "darkcave@chat.shakespeare.lit");
+        assert!(matches!(
+            manager
+                .accept_invite("coven@chat.shakespeare.lit", "hecate")
+                .await,
+            Err(MessagingError::InviteNotFound(_))
+        ));
+
+        // Joining by other means answers the invite too.
+        manager
+            .handle_event(&make_event(
+                channels::XMPP_MUC_JOINED,
+                EventPayload::MucJoined {
+                    room: "darkcave@chat.shakespeare.lit".to_string(),
+                    nick: "hecate".to_string(),
+                },
+            ))
+            .await;
+        assert!(manager.pending_invites().await.unwrap().is_empty());
+
+        // Invites to rooms we are already in are not kept.
+        manager
+            .handle_event(&make_event(
+                channels::XMPP_MUC_INVITE_RECEIVED,
+                EventPayload::MucInviteReceived {
+                    invite: make_invite("darkcave@chat.shakespeare.lit", true),
+                },
+            ))
+            .await;
+        assert!(manager.pending_invites().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn only_mediated_invites_are_declined_to_the_inviter() {
+        let (manager, event_bus, _dir) = setup_muc().await;
+        let mut sub = event_bus.subscribe("ui.muc.**").unwrap();
+
+        for (room, mediated) in [
+            ("direct@chat.shakespeare.lit", false),
+            ("mediated@chat.shakespeare.lit", true),
+        ] {
+            manager
+                .handle_event(&make_event(
+                    channels::XMPP_MUC_INVITE_RECEIVED,
+                    EventPayload::MucInviteReceived {
+                        invite: make_invite(room, mediated),
+                    },
+                ))
+                .await;
+        }
+
+        manager
+            .decline_invite("direct@chat.shakespeare.lit", None)
+            .await
+            .unwrap();
+        manager
+            .decline_invite("mediated@chat.shakespeare.lit", Some("busy"))
+            .await
+            .unwrap();
+        let received = recv_within(&mut sub).await;
+        assert!(matches!(
+            received.payload,
+            EventPayload::MucInviteDeclineRequested { ref room, ref to, ref reason }
+                if room == "mediated@chat.shakespeare.lit"
+                    && to == "crone1@shakespeare.lit"
+                    && reason.as_deref() == Some("busy")
+        ));
+        assert!(manager.pending_invites().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn invites_are_sent_from_joined_rooms_only() {
+        let (manager, event_bus, _dir) = setup_muc().await;
+        let mut sub = event_bus.subscribe("ui.muc.invite").unwrap();
+
+        assert!(matches!(
+            manager
+                .send_invite("room@conference.example.com", "bob@example.com", None)
+                .await,
+            Err(MessagingError::RoomNotJoined(_))
+        ));
+
+        manager
+            .handle_event(&make_event(
+                channels::XMPP_MUC_JOINED,
+                EventPayload::MucJoined {
+                    room: "room@conference.example.com".to_string(),
+                    nick: "alice".to_string(),
+                },
+            ))
+            .await;
+        manager
+            .send_invite("room@conference.example.com", "Bob@Example.com", Some("hi"))
+            .await
+            .unwrap();
+        let received = recv_within(&mut sub).await;
+        assert!(matches!(
+            received.payload,
+            EventPayload::MucInviteSendRequested { ref room, ref to, .. }
+                if room == "room@conference.example.com" && to == "bob@example.com"
+        ));
+    }
+
     #[tokio::test]
     async fn leave_room_emits_event() {
         let (manager, event_bus, _dir) = setup_muc().await;
@@ -2105,6 +4410,11 @@ mod muc_tests {
             message_type: MessageType::Groupchat,
             thread: None,
             embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
         };
 
         let event = make_event(
@@ -2149,6 +4459,7 @@ mod muc_tests {
             EventPayload::MucSubjectChanged {
                 room: "room@conference.example.com".to_string(),
                 subject: "Sprint Planning - Week 7".to_string(),
+                set_by: Some("Alice".to_string()),
             },
         );
         manager.handle_event(&event).await;
@@ -2161,6 +4472,48 @@ mod muc_tests {
         );
     }
 
+    #[tokio::test]
+    async fn subject_history_records_setter_and_skips_replays() {
+        let (manager, _, _dir) = setup_muc().await;
+        let room = "room@conference.example.com";
+
+        manager.join_room(room, "Alice").await.unwrap();
+
+        for (subject, set_by) in [
+            ("Standup", Some("Alice")),
+            ("Standup", Some("Alice")),
+            ("Retro", Some("Bob")),
+            ("Retro", None),
+        ] {
+            let event = make_event(
+                "xmpp.muc.subject.changed",
+                EventPayload::MucSubjectChanged {
+                    room: room.to_string(),
+                    subject: subject.to_string(),
+                    set_by: set_by.map(|s| s.to_string()),
+                },
+            );
+            manager.handle_event(&event).await;
+        }
+
+        let history = manager.subject_history(room).await.unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].subject, "Standup");
+        assert_eq!(history[0].set_by.as_deref(), Some("Alice"));
+        assert_eq!(history[1].subject, "Retro");
+        assert_eq!(history[1].set_by.as_deref(), Some("Bob"));
+        assert_eq!(history[2].set_by, None);
+        assert!(history[0].changed_at <= history[2].changed_at);
+
+        assert!(
+            manager
+                .subject_history("other@conference.example.com")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
     #[tokio::test]
     async fn occupant_tracking_add_and_remove() {
         let (manager, _, _dir) = setup_muc().await;
@@ -2324,6 +4677,11 @@ mod muc_tests {
                 message_type: MessageType::Groupchat,
                 thread: None,
                 embeds: vec![],
+                origin_id: None,
+                stanza_id: None,
+                retracted_at: None,
+                lang: None,
+                translation: None,
             };
             let event = make_event(
                 "xmpp.muc.message.received",
@@ -2453,4 +4811,147 @@ mod muc_tests {
         assert!(matches!(occupants[0].role, MucRole::Moderator));
         assert!(matches!(occupants[0].affiliation, MucAffiliation::Admin));
     }
+
+    async fn recv_within(sub: &mut waddle_core::event::EventSubscription) -> Event {
+        tokio::time::timeout(std::time::Duration::from_millis(100), sub.recv())
+            .await
+            .expect("timed out")
+            .expect("should receive event")
+    }
+
+    #[tokio::test]
+    async fn reconnect_rejoins_joined_rooms_with_history_since_last_message() {
+        let (manager, event_bus, _dir) = setup_muc().await;
+        let room = "room@conference.example.com";
+
+        for (room, nick) in [(room, "Alice"), ("idle@conference.example.com", "Alice")] {
+            manager.join_room(room, nick).await.unwrap();
+        }
+        manager
+            .handle_event(&make_event(
+                "xmpp.muc.joined",
+                EventPayload::MucJoined {
+                    room: room.to_string(),
+                    nick: "Alice".to_string(),
+                },
+            ))
+            .await;
+        let last_seen = make_muc_message("m1", &format!("{room}/Bob"), room, "before the drop");
+        manager
+            .handle_event(&make_event(
+                "xmpp.muc.message.received",
+                EventPayload::MucMessageReceived {
+                    room: room.to_string(),
+                    message: last_seen.clone(),
+                },
+            ))
+            .await;
+
+        let mut joins = event_bus.subscribe("ui.muc.join").unwrap();
+        let mut statuses = event_bus.subscribe("system.muc.rejoin_status").unwrap();
+        manager
+            .handle_event(&make_event(
+                "system.connection.established",
+                EventPayload::ConnectionEstablished {
+                    jid: "alice@example.com".to_string(),
+                },
+            ))
+            .await;
+
+        let join = recv_within(&mut joins).await;
+        let EventPayload::MucJoinRequested {
+            room: join_room,
+            nick,
+            history_since,
+            ..
+        } = join.payload
+        else {
+            panic!("expected a join request");
+        };
+        assert_eq!(join_room, room);
+        assert_eq!(nick, "Alice");
+        assert_eq!(
+            history_since.map(|since| since.timestamp_micros()),
+            Some(last_seen.timestamp.timestamp_micros())
+        );
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(50), joins.recv())
+                .await
+                .is_err(),
+            "rooms that were never joined must not be rejoined"
+        );
+
+        assert!(matches!(
+            recv_within(&mut statuses).await.payload,
+            EventPayload::MucRejoinStatusChanged { ref room, status: MucRejoinStatus::Rejoining }
+                if room == "room@conference.example.com"
+        ));
+        manager
+            .handle_event(&make_event(
+                "xmpp.muc.joined",
+                EventPayload::MucJoined {
+                    room: room.to_string(),
+                    nick: "Alice".to_string(),
+                },
+            ))
+            .await;
+        assert!(matches!(
+            recv_within(&mut statuses).await.payload,
+            EventPayload::MucRejoinStatusChanged {
+                status: MucRejoinStatus::Rejoined,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn connection_lost_fails_pending_rejoins() {
+        let (manager, event_bus, _dir) = setup_muc().await;
+        let room = "room@conference.example.com";
+        manager
+            .handle_event(&make_event(
+                "xmpp.muc.joined",
+                EventPayload::MucJoined {
+                    room: room.to_string(),
+                    nick: "Alice".to_string(),
+                },
+            ))
+            .await;
+
+        let mut statuses = event_bus.subscribe("system.muc.rejoin_status").unwrap();
+        manager
+            .handle_event(&make_event(
+                "system.connection.established",
+                EventPayload::ConnectionEstablished {
+                    jid: "alice@example.com".to_string(),
+                },
+            ))
+            .await;
+        manager
+            .handle_event(&make_event(
+                "system.connection.lost",
+                EventPayload::ConnectionLost {
+                    reason: "network".to_string(),
+                    will_retry: true,
+                },
+            ))
+            .await;
+
+        assert!(matches!(
+            recv_within(&mut statuses).await.payload,
+            EventPayload::MucRejoinStatusChanged {
+                status: MucRejoinStatus::Rejoining,
+                ..
+            }
+        ));
+        assert!(matches!(
+            recv_within(&mut statuses).await.payload,
+            EventPayload::MucRejoinStatusChanged {
+                status: MucRejoinStatus::Failed,
+                ..
+            }
+        ));
+        let rooms = manager.get_joined_rooms().await.unwrap();
+        assert_eq!(rooms.len(), 1, "the room is retried on the next reconnect");
+    }
 }
@@ -0,0 +1,217 @@
+//! Mentions of the local user in group chats.
+//!
+//! A room message mentions us when it names our nick in that room, or one of
+//! the keywords set with [`MucManager::with_mention_keywords`], as a whole
+//! word and ignoring case. Mentions are flagged in storage and announced on
+//! `ui.notification.mention`, so a room can be muted while its mentions
+//! still get through.
+
+use waddle_core::event::ChatMessage;
+use waddle_storage::{Database, Row, SqlValue};
+
+#[cfg(feature = "native")]
+use waddle_core::event::{Channel, Event, EventPayload, EventSource, channels};
+
+use crate::{MessagingError, MucManager, StoredMessage};
+
+/// Whether `body` mentions `nick` or one of the lowercased `keywords`.
+fn is_mention(body: &str, nick: Option<&str>, keywords: &[String]) -> bool {
+    let body = body.to_lowercase();
+    nick.map(str::to_lowercase)
+        .iter()
+        .chain(keywords)
+        .filter(|term| !term.is_empty())
+        .any(|term| contains_word(&body, term))
+}
+
+/// Whether `word` occurs in `text` without letters or digits either side, so
+/// the nick "al" is not found in "also" but is in "@al:".
+fn contains_word(text: &str, word: &str) -> bool {
+    text.match_indices(word).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + word.len()..].chars().next();
+        !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+    })
+}
+
+impl<D: Database> MucManager<D> {
+    /// Flag a newly stored room message if it mentions us and announce it.
+    pub(crate) async fn flag_mention(
+        &self,
+        room: &str,
+        message: &ChatMessage,
+    ) -> Result<(), MessagingError> {
+        let room_s = room.to_string();
+        let rows: Vec<Row> = self
+            .db
+            .query("SELECT nick FROM muc_rooms WHERE room_jid = ?1", &[&room_s])
+            .await?;
+        let nick = rows.first().and_then(|row| match row.get(0) {
+            Some(SqlValue::Text(nick)) => Some(nick.clone()),
+            _ => None,
+        });
+
+        // The room reflects our own messages back to us.
+        let sender_nick = message.from.split_once('/').map(|(_, nick)| nick);
+        if sender_nick.is_some() && sender_nick == nick.as_deref() {
+            return Ok(());
+        }
+        if !is_mention(&message.body, nick.as_deref(), &self.mention_keywords) {
+            return Ok(());
+        }
+
+        let mentions = 1_i64;
+        self.db
+            .execute(
+                "UPDATE messages SET mentions = ?1 WHERE id = ?2",
+                &[&mentions, &message.id],
+            )
+            .await?;
+
+        #[cfg(feature = "native")]
+        {
+            let _ = self.event_bus.publish(Event::new(
+                Channel::new(channels::UI_NOTIFICATION_MENTION).unwrap(),
+                EventSource::System("muc".into()),
+                EventPayload::MucMentionReceived {
+                    room: room.to_string(),
+                    message: message.clone(),
+                },
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Messages in `room` that mentioned us, newest first.
+    pub async fn get_room_mentions(
+        &self,
+        room: &str,
+        limit: u32,
+    ) -> Result<Vec<ChatMessage>, MessagingError> {
+        let room_s = room.to_string();
+        let limit_i = i64::from(limit);
+        let rows: Vec<StoredMessage> = self
+            .db
+            .query(
+                "SELECT id, from_jid, to_jid, messages.body, timestamp, message_type, thread, embeds, \
+                 retracted_at, messages.lang, t.lang, t.body \
+                 FROM messages LEFT JOIN message_translations t ON t.message_id = messages.id \
+                 WHERE to_jid = ?1 AND message_type = 'groupchat' AND mentions = 1 \
+                 ORDER BY timestamp DESC, id DESC \
+                 LIMIT ?2",
+                &[&room_s, &limit_i],
+            )
+            .await?;
+
+        Ok(rows.into_iter().map(|r| r.into_chat_message()).collect())
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use chrono::Utc;
+    use tempfile::TempDir;
+    use waddle_core::event::{BroadcastEventBus, EventBus, MessageType};
+
+    const ROOM: &str = "dev@conference.example.com";
+
+    async fn setup(
+        keywords: &[String],
+    ) -> (Arc<MucManager<impl Database>>, Arc<dyn EventBus>, TempDir) {
+        let dir = TempDir::new().unwrap();
+        let db = waddle_storage::open_database(&dir.path().join("test.db"))
+            .await
+            .unwrap();
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let manager =
+            MucManager::new(Arc::new(db), event_bus.clone()).with_mention_keywords(keywords);
+        manager.join_room(ROOM, "Alice").await.unwrap();
+        (Arc::new(manager), event_bus, dir)
+    }
+
+    async fn receive(manager: &MucManager<impl Database>, id: &str, from_nick: &str, body: &str) {
+        let message = ChatMessage {
+            id: id.to_string(),
+            from: format!("{ROOM}/{from_nick}"),
+            to: ROOM.to_string(),
+            body: body.to_string(),
+            timestamp: Utc::now(),
+            message_type: MessageType::Groupchat,
+            thread: None,
+            embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
+        };
+        manager
+            .handle_event(&Event::new(
+                Channel::new("xmpp.muc.message.received").unwrap(),
+                EventSource::Xmpp,
+                EventPayload::MucMessageReceived {
+                    room: ROOM.to_string(),
+                    message,
+                },
+            ))
+            .await;
+    }
+
+    #[test]
+    fn mentions_match_whole_words_ignoring_case() {
+        let keywords = vec!["release".to_string()];
+        assert!(is_mention("hey @alice, look", Some("Alice"), &[]));
+        assert!(is_mention("ALICE: ping", Some("Alice"), &[]));
+        assert!(!is_mention("malice aforethought", Some("Alice"), &[]));
+        assert!(is_mention("the release is out", None, &keywords));
+        assert!(!is_mention("released yesterday", None, &keywords));
+        assert!(!is_mention("anything", Some(""), &[]));
+    }
+
+    #[tokio::test]
+    async fn mentions_are_flagged_and_announced() {
+        let (manager, event_bus, _dir) = setup(&["deploy".to_string()]).await;
+        let mut mentions = event_bus.subscribe("ui.notification.mention").unwrap();
+
+        receive(&manager, "m1", "bob", "morning all").await;
+        receive(&manager, "m2", "bob", "alice, can you review?").await;
+        receive(&manager, "m3", "carol", "Deploy is green").await;
+
+        for expected in ["m2", "m3"] {
+            let event = mentions.recv().await.unwrap();
+            assert!(matches!(
+                event.payload,
+                EventPayload::MucMentionReceived { ref room, ref message }
+                    if room == ROOM && message.id == expected
+            ));
+        }
+
+        let flagged = manager.get_room_mentions(ROOM, 10).await.unwrap();
+        let mut ids: Vec<_> = flagged.iter().map(|m| m.id.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["m2", "m3"]);
+    }
+
+    #[tokio::test]
+    async fn own_and_replayed_messages_do_not_alert() {
+        let (manager, event_bus, _dir) = setup(&[]).await;
+        let mut mentions = event_bus.subscribe("ui.notification.mention").unwrap();
+
+        receive(&manager, "m1", "Alice", "note to self, Alice").await;
+        receive(&manager, "m2", "bob", "thanks alice").await;
+        receive(&manager, "m2", "bob", "thanks alice").await;
+
+        let event = mentions.recv().await.unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::MucMentionReceived { ref message, .. } if message.id == "m2"
+        ));
+        let next =
+            tokio::time::timeout(std::time::Duration::from_millis(50), mentions.recv()).await;
+        assert!(next.is_err(), "unexpected mention {next:?}");
+    }
+}
@@ -0,0 +1,366 @@
+//! Maintenance pass that folds conversations whose JIDs differ only by case
+//! or resource into one, rewriting every table that refers to them.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+use tracing::info;
+
+use waddle_core::jid::Jid;
+use waddle_storage::{Database, Row, SqlValue, Transaction};
+
+use crate::MessagingError;
+
+/// Columns that hold a conversation JID, either bare or as the bare part of
+/// a full JID (MUC occupants, resources).
+const JID_COLUMNS: &[(&str, &str)] = &[
+    ("messages", "from_jid"),
+    ("messages", "to_jid"),
+    ("muc_subject_history", "room_jid"),
+];
+
+/// Tables keyed by a conversation JID. When both the variant and the
+/// canonical row exist, the canonical row is kept.
+const KEYED_TABLES: &[(&str, &str)] = &[
+    ("roster", "jid"),
+    ("pending_subscriptions", "jid"),
+    ("muc_rooms", "room_jid"),
+    ("mam_sync_state", "jid"),
+    ("reported_contacts", "jid"),
+    ("read_markers", "conversation"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergedConversation {
+    /// The surviving conversation JID.
+    pub jid: String,
+    /// Conversation JIDs folded into `jid`, in the order they sorted.
+    pub merged_from: Vec<String>,
+    pub messages_rewritten: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeReport {
+    pub merged: Vec<MergedConversation>,
+}
+
+impl MergeReport {
+    pub fn is_empty(&self) -> bool {
+        self.merged.is_empty()
+    }
+}
+
+/// Canonical conversation key: the bare JID with the localpart and domain
+/// case-folded. Resources are dropped because conversations are per bare JID.
+pub fn canonical_jid(jid: &str) -> String {
+    Jid::new(jid).bare().into_string()
+}
+
+struct ConversationRow {
+    jid: String,
+    kind: String,
+    last_message_id: Option<String>,
+    last_message_preview: Option<String>,
+    last_message_from: Option<String>,
+    last_activity: Option<String>,
+    unread_count: i64,
+    pinned: bool,
+    archived: bool,
+    muted: bool,
+    muted_until: Option<String>,
+}
+
+impl ConversationRow {
+    fn from_row(row: &Row) -> Option<Self> {
+        let text = |idx: usize| match row.get(idx) {
+            Some(SqlValue::Text(s)) => Some(s.clone()),
+            _ => None,
+        };
+        let integer = |idx: usize| match row.get(idx) {
+            Some(SqlValue::Integer(i)) => *i,
+            _ => 0,
+        };
+        Some(Self {
+            jid: text(0)?,
+            kind: text(1).unwrap_or_else(|| "chat".to_string()),
+            last_message_id: text(2),
+            last_message_preview: text(3),
+            last_message_from: text(4),
+            last_activity: text(5),
+            unread_count: integer(6),
+            pinned: integer(7) != 0,
+            archived: integer(8) != 0,
+            muted: integer(9) != 0,
+            muted_until: text(10),
+        })
+    }
+}
+
+/// Find conversations that collapse to the same [`canonical_jid`] and merge
+/// them. Variants are taken from the conversation list and from every JID
+/// column, so history stored under a variant with no conversation row of its
+/// own is folded in too. Every write commits in one transaction: a run either
+/// completes or leaves the database as it was.
+pub(crate) async fn merge_duplicate_conversations<D: Database>(
+    db: &D,
+) -> Result<MergeReport, MessagingError> {
+    let rows: Vec<Row> = db
+        .query(
+            "SELECT jid, kind, last_message_id, last_message_preview, last_message_from, \
+             last_activity, unread_count, pinned, archived, muted, muted_until \
+             FROM conversations ORDER BY jid",
+            &[],
+        )
+        .await?;
+
+    let mut groups: BTreeMap<String, Group> = BTreeMap::new();
+    for row in rows.iter().filter_map(ConversationRow::from_row) {
+        let canonical = canonical_jid(&row.jid);
+        let group = groups.entry(canonical.clone()).or_default();
+        if row.jid != canonical {
+            group.variants.insert(row.jid.clone());
+        }
+        group.members.push(row);
+    }
+    for jid in referenced_jids(db).await? {
+        // Full JIDs are rewritten through their bare part, keeping the
+        // resource or nick as stored.
+        let bare = jid.split('/').next().unwrap_or(&jid);
+        let canonical = canonical_jid(bare);
+        if bare != canonical {
+            groups
+                .entry(canonical)
+                .or_default()
+                .variants
+                .insert(bare.to_string());
+        }
+    }
+
+    let mut plans = Vec::new();
+    for (canonical, group) in groups {
+        if group.variants.is_empty() {
+            continue;
+        }
+        let variants: Vec<String> = group.variants.into_iter().collect();
+        let mut messages_rewritten = 0;
+        for variant in &variants {
+            messages_rewritten += count_messages(db, variant).await?;
+        }
+        let merged = (!group.members.is_empty()).then(|| merged_row(&canonical, &group.members));
+        plans.push(MergePlan {
+            canonical,
+            variants,
+            merged,
+            messages_rewritten,
+        });
+    }
+    if plans.is_empty() {
+        return Ok(MergeReport::default());
+    }
+
+    db.transaction(|tx| {
+        for plan in &plans {
+            plan.stage(tx);
+        }
+        Ok(())
+    })
+    .await?;
+
+    let merged = plans
+        .into_iter()
+        .map(|plan| {
+            info!(
+                jid = plan.canonical,
+                merged_from = ?plan.variants,
+                messages_rewritten = plan.messages_rewritten,
+                "merged duplicate conversations"
+            );
+            MergedConversation {
+                jid: plan.canonical,
+                merged_from: plan.variants,
+                messages_rewritten: plan.messages_rewritten,
+            }
+        })
+        .collect();
+    Ok(MergeReport { merged })
+}
+
+#[derive(Default)]
+struct Group {
+    members: Vec<ConversationRow>,
+    variants: BTreeSet<String>,
+}
+
+/// The writes for one canonical JID, worked out before the transaction
+/// opens.
+struct MergePlan {
+    canonical: String,
+    variants: Vec<String>,
+    /// The combined conversation row, if any member had one.
+    merged: Option<ConversationRow>,
+    messages_rewritten: u64,
+}
+
+impl MergePlan {
+    fn stage(&self, tx: &Transaction) {
+        for variant in &self.variants {
+            stage_rewrite(tx, variant, &self.canonical);
+        }
+
+        if let Some(merged) = &self.merged {
+            tx.execute(
+                "INSERT INTO conversations (jid, kind, last_message_id, last_message_preview, \
+                 last_message_from, last_activity, unread_count, pinned, archived, muted, \
+                 muted_until) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11) \
+                 ON CONFLICT (jid) DO UPDATE SET \
+                 kind = excluded.kind, \
+                 last_message_id = excluded.last_message_id, \
+                 last_message_preview = excluded.last_message_preview, \
+                 last_message_from = excluded.last_message_from, \
+                 last_activity = excluded.last_activity, \
+                 unread_count = excluded.unread_count, \
+                 pinned = excluded.pinned, \
+                 archived = excluded.archived, \
+                 muted = excluded.muted, \
+                 muted_until = excluded.muted_until",
+                &[
+                    &merged.jid,
+                    &merged.kind,
+                    &merged.last_message_id,
+                    &merged.last_message_preview,
+                    &merged.last_message_from,
+                    &merged.last_activity,
+                    &merged.unread_count,
+                    &merged.pinned,
+                    &merged.archived,
+                    &merged.muted,
+                    &merged.muted_until,
+                ],
+            );
+        }
+
+        for variant in &self.variants {
+            tx.execute("DELETE FROM conversations WHERE jid = ?1", &[variant]);
+        }
+    }
+}
+
+/// Every distinct value in the [`JID_COLUMNS`].
+async fn referenced_jids<D: Database>(db: &D) -> Result<Vec<String>, MessagingError> {
+    let sql = JID_COLUMNS
+        .iter()
+        .map(|(table, column)| format!("SELECT {column} FROM {table}"))
+        .collect::<Vec<_>>()
+        .join(" UNION ");
+    let rows: Vec<Row> = db.query(&sql, &[]).await?;
+    Ok(rows
+        .iter()
+        .filter_map(|row| match row.get(0) {
+            Some(SqlValue::Text(jid)) => Some(jid.clone()),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Messages that [`stage_rewrite`] will touch for `variant`.
+async fn count_messages<D: Database>(db: &D, variant: &str) -> Result<u64, MessagingError> {
+    let variant = variant.to_string();
+    let prefixed = i64::from(!variant.contains('/'));
+    let row: Row = db
+        .query_one(
+            "SELECT COUNT(*) FROM messages \
+             WHERE from_jid = ?1 OR to_jid = ?1 \
+             OR (?2 AND (substr(from_jid, 1, length(?1) + 1) = ?1 || '/' \
+             OR substr(to_jid, 1, length(?1) + 1) = ?1 || '/'))",
+            &[&variant, &prefixed],
+        )
+        .await?;
+    match row.get(0) {
+        Some(SqlValue::Integer(count)) => Ok(*count as u64),
+        _ => Ok(0),
+    }
+}
+
+/// Combine a group into one row: the latest activity wins the preview,
+/// unread counts add up, pinned if any member was pinned, archived only if
+/// every member was archived. Muted if any member was muted, for as long as
+/// the longest of those mutes.
+fn merged_row(canonical: &str, members: &[ConversationRow]) -> ConversationRow {
+    // Ties on activity resolve by JID so repeated runs pick the same preview.
+    let latest = members
+        .iter()
+        .max_by(|a, b| {
+            a.last_activity
+                .cmp(&b.last_activity)
+                .then_with(|| b.jid.cmp(&a.jid))
+        })
+        .expect("conversation group is never empty");
+    let kind = if members.iter().any(|member| member.kind == "groupchat") {
+        "groupchat"
+    } else {
+        "chat"
+    };
+
+    let muted: Vec<&ConversationRow> = members.iter().filter(|member| member.muted).collect();
+    // A mute without an end outlasts any other.
+    let muted_until = if muted.iter().any(|member| member.muted_until.is_none()) {
+        None
+    } else {
+        muted
+            .iter()
+            .filter_map(|member| member.muted_until.clone())
+            .max()
+    };
+
+    ConversationRow {
+        jid: canonical.to_string(),
+        kind: kind.to_string(),
+        last_message_id: latest.last_message_id.clone(),
+        last_message_preview: latest.last_message_preview.clone(),
+        last_message_from: latest.last_message_from.clone(),
+        last_activity: latest.last_activity.clone(),
+        unread_count: members.iter().map(|member| member.unread_count).sum(),
+        pinned: members.iter().any(|member| member.pinned),
+        archived: members.iter().all(|member| member.archived),
+        muted: !muted.is_empty(),
+        muted_until,
+    }
+}
+
+/// Stage the statements that point every reference to `variant` at
+/// `canonical`.
+fn stage_rewrite(tx: &Transaction, variant: &str, canonical: &str) {
+    let variant = variant.to_string();
+    let canonical = canonical.to_string();
+
+    for (table, column) in JID_COLUMNS {
+        tx.execute(
+            &format!("UPDATE {table} SET {column} = ?1 WHERE {column} = ?2"),
+            &[&canonical, &variant],
+        );
+        // Full JIDs under a bare variant keep their resource or nick.
+        if !variant.contains('/') {
+            tx.execute(
+                &format!(
+                    "UPDATE {table} SET {column} = ?1 || substr({column}, length(?2) + 1) \
+                     WHERE substr({column}, 1, length(?2) + 1) = ?2 || '/'"
+                ),
+                &[&canonical, &variant],
+            );
+        }
+    }
+
+    for (table, column) in KEYED_TABLES {
+        tx.execute(
+            &format!("UPDATE OR IGNORE {table} SET {column} = ?1 WHERE {column} = ?2"),
+            &[&canonical, &variant],
+        );
+        tx.execute(
+            &format!("DELETE FROM {table} WHERE {column} = ?1"),
+            &[&variant],
+        );
+    }
+}
@@ -0,0 +1,908 @@
+//! Preview cards for links in message bodies: the title, description and
+//! image a page declares in its OpenGraph tags, falling back to its
+//! `<title>` and description meta tag.
+//!
+//! Fetching a page tells its server that someone read the link, so only
+//! links the user sends or gets from roster contacts are fetched unless
+//! [`LinkPreviewConfig`] says otherwise. Domains can be allowed or denied
+//! there, and privacy mode only fetches links from messages the user sends.
+//! Previews are stored by URL and each page is fetched once; stored previews
+//! are shown even in privacy mode, since showing them makes no request.
+//!
+//! A link is only fetched from a public address, checked again on every
+//! redirect, so a message cannot make the client probe localhost or the
+//! user's network.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use tracing::{debug, warn};
+
+use waddle_core::config::LinkPreviewConfig;
+use waddle_core::error::EventBusError;
+use waddle_core::event::{
+    Channel, ChatMessage, Event, EventBus, EventPayload, EventSource, LinkPreview, channels,
+};
+use waddle_core::jid::bare_jid;
+use waddle_storage::{Database, Row, SqlValue, StorageError};
+
+use crate::MessagingError;
+use crate::downloads::{attachment_url, http_url};
+
+/// Links after this many in one message get no preview.
+const MAX_PREVIEWS_PER_MESSAGE: usize = 3;
+/// Metadata lives in `<head>`; the rest of a large page is not read.
+const MAX_PAGE_BYTES: usize = 512 * 1024;
+const MAX_TEXT_CHARS: usize = 500;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REDIRECTS: usize = 10;
+
+#[derive(Debug, thiserror::Error)]
+pub enum LinkPreviewError {
+    #[error("not an http(s) URL: {0}")]
+    InvalidUrl(String),
+
+    #[error("previews are not fetched from {0}")]
+    Blocked(String),
+
+    #[error("previews are not fetched from non-public address {0}")]
+    NotPublic(IpAddr),
+
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("server answered with status {0}")]
+    Status(u16),
+
+    #[error("storage error: {0}")]
+    Storage(#[from] StorageError),
+}
+
+/// The http(s) links in `body`, in order and without duplicates. Brackets
+/// and punctuation around a link are not part of it.
+pub fn link_urls(body: &str) -> Vec<Url> {
+    let mut urls: Vec<Url> = Vec::new();
+    for word in body.split_whitespace() {
+        let word = word
+            .trim_start_matches(['<', '(', '[', '"', '\''])
+            .trim_end_matches(['>', ')', ']', '"', '\'', '.', ',', ';', ':', '!', '?']);
+        if let Some(url) = http_url(word)
+            && !urls.contains(&url)
+        {
+            urls.push(url);
+        }
+    }
+    urls
+}
+
+pub struct LinkPreviewManager<D: Database> {
+    db: Arc<D>,
+    client: reqwest::Client,
+    config: LinkPreviewConfig,
+    event_bus: Arc<dyn EventBus>,
+    public_only: bool,
+}
+
+impl<D: Database> LinkPreviewManager<D> {
+    pub fn new(db: Arc<D>, config: LinkPreviewConfig, event_bus: Arc<dyn EventBus>) -> Self {
+        Self::build(db, config, event_bus, true)
+    }
+
+    /// `public_only` is only turned off by tests, whose pages are served
+    /// from loopback.
+    fn build(
+        db: Arc<D>,
+        config: LinkPreviewConfig,
+        event_bus: Arc<dyn EventBus>,
+        public_only: bool,
+    ) -> Self {
+        let client = if public_only {
+            public_client()
+        } else {
+            reqwest::Client::builder()
+                .timeout(FETCH_TIMEOUT)
+                .build()
+                .unwrap_or_default()
+        };
+        Self {
+            db,
+            client,
+            config,
+            event_bus,
+            public_only,
+        }
+    }
+
+    /// Whether the domain rules let previews be fetched from `url`.
+    pub fn allows(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let matches = |domain: &String| {
+            let domain = domain.trim_start_matches('.');
+            host.eq_ignore_ascii_case(domain)
+                || host
+                    .to_ascii_lowercase()
+                    .ends_with(&format!(".{}", domain.to_ascii_lowercase()))
+        };
+        !self.config.deny_domains.iter().any(matches)
+            && (self.config.allow_domains.is_empty()
+                || self.config.allow_domains.iter().any(matches))
+    }
+
+    /// The stored preview of `url`, if it was fetched before.
+    pub async fn stored(&self, url: &str) -> Result<Option<LinkPreview>, LinkPreviewError> {
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT url, title, description, image_url, site_name, fetched_at \
+                 FROM link_previews WHERE url = ?1",
+                &[&url.to_string()],
+            )
+            .await?;
+        Ok(rows.first().and_then(preview_from_row))
+    }
+
+    /// The preview of `url`, fetched and stored unless it was stored
+    /// before. `None` when the page is not HTML or declares nothing to show.
+    pub async fn preview(&self, url: &str) -> Result<Option<LinkPreview>, LinkPreviewError> {
+        let parsed = http_url(url).ok_or_else(|| LinkPreviewError::InvalidUrl(url.to_string()))?;
+        if let Some(preview) = self.stored(url).await? {
+            return Ok(Some(preview));
+        }
+        if !self.allows(&parsed) {
+            return Err(LinkPreviewError::Blocked(
+                parsed.host_str().unwrap_or_default().to_string(),
+            ));
+        }
+        // Names are checked when they resolve; addresses never do.
+        if let Some(address) = non_public_host(&parsed).filter(|_| self.public_only) {
+            return Err(LinkPreviewError::NotPublic(address));
+        }
+
+        let Some(preview) = self.fetch(url).await? else {
+            return Ok(None);
+        };
+        self.db
+            .execute(
+                "INSERT OR REPLACE INTO link_previews \
+                 (url, title, description, image_url, site_name, fetched_at) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                &[
+                    &preview.url,
+                    &preview.title,
+                    &preview.description,
+                    &preview.image_url,
+                    &preview.site_name,
+                    &preview.fetched_at.to_rfc3339(),
+                ],
+            )
+            .await?;
+        Ok(Some(preview))
+    }
+
+    /// Publish `ui.message.preview_ready` for each link in `message` that
+    /// has a preview, fetching the ones not stored yet when allowed.
+    /// `outgoing` marks messages the user sent.
+    pub async fn handle_message(&self, message: &ChatMessage, outgoing: bool) {
+        // Shared files are handled by the download manager.
+        if attachment_url(message).is_some() {
+            return;
+        }
+        let fetch = outgoing
+            || (!self.config.privacy_mode
+                && (self.config.fetch_from_strangers || self.is_contact(&message.from).await));
+        for url in link_urls(&message.body)
+            .into_iter()
+            .take(MAX_PREVIEWS_PER_MESSAGE)
+        {
+            let result = if fetch {
+                self.preview(url.as_str()).await
+            } else {
+                self.stored(url.as_str()).await
+            };
+            match result {
+                Ok(Some(preview)) => self.emit(
+                    channels::UI_MESSAGE_PREVIEW_READY,
+                    EventPayload::LinkPreviewReady {
+                        message_id: message.id.clone(),
+                        preview,
+                    },
+                ),
+                Ok(None) => {}
+                Err(LinkPreviewError::Blocked(domain)) => {
+                    debug!(domain, "link preview blocked by domain rules");
+                }
+                Err(LinkPreviewError::NotPublic(address)) => {
+                    debug!(%address, "link preview refused for non-public address");
+                }
+                Err(error) => warn!(url = %url, %error, "failed to build link preview"),
+            }
+        }
+    }
+
+    async fn is_contact(&self, jid: &str) -> bool {
+        match self
+            .db
+            .query::<Row>("SELECT 1 FROM roster WHERE jid = ?1", &[&bare_jid(jid)])
+            .await
+        {
+            Ok(rows) => !rows.is_empty(),
+            Err(error) => {
+                warn!(%error, "failed to look up roster for link preview");
+                false
+            }
+        }
+    }
+
+    /// Build previews for the links in sent and received messages until
+    /// the event bus closes.
+    pub async fn run(self: Arc<Self>) -> Result<(), MessagingError> {
+        let mut sub = self
+            .event_bus
+            .subscribe("xmpp.**")
+            .map_err(|e| MessagingError::EventBus(e.to_string()))?;
+
+        loop {
+            match sub.recv().await {
+                Ok(event) => {
+                    let (message, outgoing) = match event.payload {
+                        EventPayload::MessageReceived { message }
+                        | EventPayload::MucMessageReceived { message, .. } => (message, false),
+                        EventPayload::MessageSent { message } => (message, true),
+                        _ => continue,
+                    };
+                    // Pages are fetched one at a time; the subscription holds
+                    // the messages that arrive meanwhile.
+                    self.handle_message(&message, outgoing).await;
+                }
+                Err(EventBusError::ChannelClosed) => {
+                    debug!("event bus closed, link preview manager stopping");
+                    return Ok(());
+                }
+                Err(EventBusError::Lagged(count)) => {
+                    warn!(count, "link preview manager lagged, some messages missed");
+                }
+                Err(e) => return Err(MessagingError::EventBus(e.to_string())),
+            }
+        }
+    }
+
+    async fn fetch(&self, url: &str) -> Result<Option<LinkPreview>, LinkPreviewError> {
+        let mut response = self
+            .client
+            .get(url)
+            .header(ACCEPT, "text/html,application/xhtml+xml")
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(LinkPreviewError::Status(status.as_u16()));
+        }
+        let is_html = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.contains("html"));
+        if !is_html {
+            return Ok(None);
+        }
+
+        // Relative image URLs resolve against the page after redirects.
+        let base = response.url().clone();
+        let mut page = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            page.extend_from_slice(&chunk);
+            if page.len() >= MAX_PAGE_BYTES {
+                page.truncate(MAX_PAGE_BYTES);
+                break;
+            }
+        }
+        let metadata = PageMetadata::parse(&String::from_utf8_lossy(&page));
+        Ok(metadata.into_preview(url, &base))
+    }
+
+    fn emit(&self, channel: &str, payload: EventPayload) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channel).unwrap(),
+            EventSource::System("link_previews".into()),
+            payload,
+        ));
+    }
+}
+
+/// A client that only connects to public addresses: names resolve through
+/// [`PublicResolver`] and redirects to private address literals are refused.
+fn public_client() -> reqwest::Client {
+    let redirects = reqwest::redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if let Some(address) = non_public_host(attempt.url()) {
+            attempt.error(LinkPreviewError::NotPublic(address))
+        } else {
+            attempt.follow()
+        }
+    });
+    reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        // A proxy would resolve and connect on our behalf, past the resolver.
+        .no_proxy()
+        .redirect(redirects)
+        .dns_resolver(Arc::new(PublicResolver))
+        .build()
+        .unwrap_or_default()
+}
+
+/// Resolves page hosts, refusing any name with a non-public address among
+/// its records.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> =
+                tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            if let Some(address) = addresses.iter().find(|address| !is_public(address.ip())) {
+                return Err(Box::new(LinkPreviewError::NotPublic(address.ip())) as _);
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// The address `url` names directly, when it is not public.
+fn non_public_host(url: &Url) -> Option<IpAddr> {
+    let address: IpAddr = url
+        .host_str()?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()?;
+    (!is_public(address)).then_some(address)
+}
+
+/// Whether `address` is reachable on the internet at large, rather than
+/// being loopback, private, link-local or otherwise reserved.
+fn is_public(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => is_public_v4(address),
+        IpAddr::V6(address) => {
+            is_public_v6(address) && embedded_ipv4(address).into_iter().all(is_public_v4)
+        }
+    }
+}
+
+/// IPv4 addresses carried inside `address` by the transition mechanisms
+/// that route to them: IPv4-mapped and IPv4-compatible addresses, NAT64,
+/// 6to4, and both the server and the client of a Teredo address.
+fn embedded_ipv4(address: Ipv6Addr) -> Vec<Ipv4Addr> {
+    let segments = address.segments();
+    let octets = address.octets();
+    let last = Ipv4Addr::new(octets[12], octets[13], octets[14], octets[15]);
+    match segments {
+        // IPv4-compatible (deprecated) and IPv4-mapped.
+        [0, 0, 0, 0, 0, 0 | 0xffff, _, _] => vec![last],
+        // NAT64 well-known prefix.
+        [0x64, 0xff9b, 0, 0, 0, 0, _, _] => vec![last],
+        // 6to4.
+        [0x2002, ..] => vec![Ipv4Addr::new(octets[2], octets[3], octets[4], octets[5])],
+        // Teredo: the server in clear, the client with its bits inverted.
+        [0x2001, 0, ..] => vec![
+            Ipv4Addr::new(octets[4], octets[5], octets[6], octets[7]),
+            !last,
+        ],
+        _ => Vec::new(),
+    }
+}
+
+fn is_public_v4(address: Ipv4Addr) -> bool {
+    let [a, b, c, _] = address.octets();
+    !(address.is_unspecified()
+        || address.is_loopback()
+        || address.is_private()
+        || address.is_link_local()
+        || address.is_broadcast()
+        || address.is_documentation()
+        || address.is_multicast()
+        || a == 0
+        // Shared address space (carrier-grade NAT).
+        || (a == 100 && (64..128).contains(&b))
+        // IETF protocol assignments.
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking.
+        || (a == 198 && (18..20).contains(&b))
+        // Reserved.
+        || a >= 240)
+}
+
+fn is_public_v6(address: Ipv6Addr) -> bool {
+    let segments = address.segments();
+    !(address.is_unspecified()
+        || address.is_loopback()
+        || address.is_multicast()
+        // Unique local.
+        || (segments[0] & 0xfe00) == 0xfc00
+        // Link-local.
+        || (segments[0] & 0xffc0) == 0xfe80
+        // Documentation.
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8)
+        // Local-use NAT64, which translates to whatever the local network
+        // chooses.
+        || (segments[0] == 0x64 && segments[1] == 0xff9b && segments[2] == 1))
+}
+
+fn preview_from_row(row: &Row) -> Option<LinkPreview> {
+    let text = |index: usize| match row.get(index) {
+        Some(SqlValue::Text(value)) => Some(value.clone()),
+        _ => None,
+    };
+    Some(LinkPreview {
+        url: text(0)?,
+        title: text(1),
+        description: text(2),
+        image_url: text(3),
+        site_name: text(4),
+        fetched_at: DateTime::parse_from_rfc3339(&text(5)?)
+            .ok()?
+            .with_timezone(&Utc),
+    })
+}
+
+/// The metadata tags found in a page's `<head>`.
+#[derive(Debug, Default)]
+struct PageMetadata {
+    og_title: Option<String>,
+    og_description: Option<String>,
+    og_image: Option<String>,
+    og_site_name: Option<String>,
+    twitter_title: Option<String>,
+    twitter_description: Option<String>,
+    twitter_image: Option<String>,
+    description: Option<String>,
+    title: Option<String>,
+}
+
+impl PageMetadata {
+    fn parse(html: &str) -> Self {
+        let mut metadata = PageMetadata::default();
+        let lower = html.to_ascii_lowercase();
+        let mut position = 0;
+        while let Some(offset) = lower[position..].find('<') {
+            let start = position + offset + 1;
+            let Some(end) = lower[start..].find('>').map(|end| start + end) else {
+                break;
+            };
+            position = end + 1;
+            let tag = &html[start..end];
+            let name_end = tag
+                .find(|c: char| c.is_whitespace() || c == '/')
+                .unwrap_or(tag.len());
+            match &lower[start..start + name_end] {
+                "meta" => metadata.add_meta(&tag_attributes(&tag[name_end..])),
+                "title" if metadata.title.is_none() => {
+                    if let Some(close) = lower[position..].find("</title") {
+                        metadata.title = clean_text(&html[position..position + close]);
+                        position += close;
+                    }
+                }
+                "/head" | "body" => break,
+                _ => {}
+            }
+        }
+        metadata
+    }
+
+    fn add_meta(&mut self, attributes: &[(String, String)]) {
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let (Some(key), Some(content)) = (
+            attribute("property").or(attribute("name")),
+            attribute("content"),
+        ) else {
+            return;
+        };
+        let field = match key.to_ascii_lowercase().as_str() {
+            "og:title" => &mut self.og_title,
+            "og:description" => &mut self.og_description,
+            "og:image" | "og:image:url" => &mut self.og_image,
+            "og:site_name" => &mut self.og_site_name,
+            "twitter:title" => &mut self.twitter_title,
+            "twitter:description" => &mut self.twitter_description,
+            "twitter:image" => &mut self.twitter_image,
+            "description" => &mut self.description,
+            _ => return,
+        };
+        if field.is_none() {
+            *field = clean_text(content);
+        }
+    }
+
+    /// A preview when the page declares a title or description.
+    fn into_preview(self, url: &str, base: &Url) -> Option<LinkPreview> {
+        let title = self.og_title.or(self.twitter_title).or(self.title);
+        let description = self
+            .og_description
+            .or(self.twitter_description)
+            .or(self.description);
+        if title.is_none() && description.is_none() {
+            return None;
+        }
+        let image_url = self
+            .og_image
+            .or(self.twitter_image)
+            .and_then(|image| base.join(&image).ok())
+            .filter(|image| matches!(image.scheme(), "http" | "https"))
+            .map(String::from);
+        Some(LinkPreview {
+            url: url.to_string(),
+            title,
+            description,
+            image_url,
+            site_name: self.og_site_name,
+            fetched_at: Utc::now(),
+        })
+    }
+}
+
+/// The attributes of a tag, names lowercased and values unescaped.
+fn tag_attributes(tag: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = tag.trim_start();
+    while !rest.is_empty() {
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+        let mut value = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            let (raw, remaining) = match after.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let inner = &after[1..];
+                    let close = inner.find(quote).unwrap_or(inner.len());
+                    (&inner[..close], inner.get(close + 1..).unwrap_or_default())
+                }
+                _ => {
+                    let close = after.find(char::is_whitespace).unwrap_or(after.len());
+                    (&after[..close], &after[close..])
+                }
+            };
+            value = unescape(raw);
+            rest = remaining;
+        } else if name.is_empty() {
+            // A stray `/` or other separator.
+            rest = &rest[1.min(rest.len())..];
+        }
+        if !name.is_empty() {
+            attributes.push((name, value));
+        }
+        rest = rest.trim_start();
+    }
+    attributes
+}
+
+/// `text` unescaped, with runs of whitespace collapsed and cut to
+/// [`MAX_TEXT_CHARS`]. `None` when nothing is left.
+fn clean_text(text: &str) -> Option<String> {
+    let text = unescape(text)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if text.is_empty() {
+        return None;
+    }
+    Some(match text.char_indices().nth(MAX_TEXT_CHARS) {
+        Some((cut, _)) => format!("{}…", &text[..cut]),
+        None => text,
+    })
+}
+
+/// Replace the character references pages commonly use in metadata.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ => entity
+                    .strip_prefix("#x")
+                    .or_else(|| entity.strip_prefix("#X"))
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                    .and_then(char::from_u32),
+            }?;
+            Some((c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                unescaped.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use waddle_core::event::{BroadcastEventBus, MessageType};
+    use waddle_storage::NativeDatabase;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const PAGE: &str = "<!doctype html><html><head>\
+        <title>Fallback &amp; ignored</title>\
+        <meta property=\"og:title\" content=\"Waddle &amp; Friends\">\
+        <meta property='og:site_name' content='Waddle'/>\
+        <meta name=description content=\"A  chat\n client\">\
+        <meta property=\"og:image\" content=\"/cards/penguin.png\">\
+        </head><body><meta property=\"og:title\" content=\"Too late\"></body></html>";
+
+    fn message(id: &str, body: &str) -> ChatMessage {
+        ChatMessage {
+            id: id.to_string(),
+            from: "alice@example.com".to_string(),
+            to: "bob@example.com".to_string(),
+            body: body.to_string(),
+            timestamp: Utc::now(),
+            message_type: MessageType::Chat,
+            thread: None,
+            embeds: Vec::new(),
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
+        }
+    }
+
+    async fn setup(
+        config: LinkPreviewConfig,
+    ) -> (
+        LinkPreviewManager<NativeDatabase>,
+        Arc<dyn EventBus>,
+        TempDir,
+    ) {
+        let dir = TempDir::new().unwrap();
+        let db = waddle_storage::open_native_database(&dir.path().join("test.db"))
+            .await
+            .unwrap();
+        db.execute(
+            "INSERT INTO roster (jid, subscription) VALUES ('alice@example.com', 'both')",
+            &[],
+        )
+        .await
+        .unwrap();
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        // The mock server listens on loopback, which is otherwise refused.
+        let manager = LinkPreviewManager::build(Arc::new(db), config, event_bus.clone(), false);
+        (manager, event_bus, dir)
+    }
+
+    async fn serve_page(server: &MockServer, name: &str, expected_requests: u64) {
+        Mock::given(method("GET"))
+            .and(path(format!("/{name}")))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(PAGE, "text/html; charset=utf-8"))
+            .expect(expected_requests)
+            .mount(server)
+            .await;
+    }
+
+    #[test]
+    fn finds_links_and_reads_page_metadata() {
+        let urls: Vec<String> = link_urls(
+            "see <https://example.com/a>, (https://example.com/b). \
+             https://example.com/a again, ftp://example.com/c",
+        )
+        .into_iter()
+        .map(String::from)
+        .collect();
+        assert_eq!(urls, ["https://example.com/a", "https://example.com/b"]);
+
+        let base = Url::parse("https://waddle.social/blog/post").unwrap();
+        let preview = PageMetadata::parse(PAGE)
+            .into_preview("https://waddle.social/p", &base)
+            .unwrap();
+        assert_eq!(preview.title.as_deref(), Some("Waddle & Friends"));
+        assert_eq!(preview.description.as_deref(), Some("A chat client"));
+        assert_eq!(
+            preview.image_url.as_deref(),
+            Some("https://waddle.social/cards/penguin.png")
+        );
+        assert_eq!(preview.site_name.as_deref(), Some("Waddle"));
+
+        assert!(
+            PageMetadata::parse("<html><head></head><body>hi</body></html>")
+                .into_preview("https://example.com", &base)
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn fetches_once_and_announces_previews() {
+        let server = MockServer::start().await;
+        serve_page(&server, "post", 1).await;
+        let (manager, event_bus, _dir) = setup(LinkPreviewConfig::default()).await;
+        let mut ready = event_bus.subscribe("ui.message.preview_ready").unwrap();
+        let url = format!("{}/post", server.uri());
+
+        manager
+            .handle_message(&message("m1", &format!("look: {url}!")), false)
+            .await;
+        manager
+            .handle_message(&message("m2", &format!("again {url}")), false)
+            .await;
+
+        for id in ["m1", "m2"] {
+            match ready.recv().await.unwrap().payload {
+                EventPayload::LinkPreviewReady {
+                    message_id,
+                    preview,
+                } => {
+                    assert_eq!(message_id, id);
+                    assert_eq!(preview.url, url);
+                    assert_eq!(preview.title.as_deref(), Some("Waddle & Friends"));
+                }
+                other => panic!("unexpected payload: {other:?}"),
+            }
+        }
+        assert_eq!(manager.stored(&url).await.unwrap().unwrap().url, url);
+    }
+
+    #[tokio::test]
+    async fn privacy_mode_and_domain_rules_limit_fetching() {
+        let server = MockServer::start().await;
+        serve_page(&server, "post", 1).await;
+        let (manager, event_bus, _dir) = setup(LinkPreviewConfig {
+            privacy_mode: true,
+            ..LinkPreviewConfig::default()
+        })
+        .await;
+        let mut ready = event_bus.subscribe("ui.message.preview_ready").unwrap();
+        let url = format!("{}/post", server.uri());
+
+        manager
+            .handle_message(&message("in", &format!("theirs {url}")), false)
+            .await;
+        assert!(manager.stored(&url).await.unwrap().is_none());
+        manager
+            .handle_message(&message("out", &format!("mine {url}")), true)
+            .await;
+        manager
+            .handle_message(&message("in2", &format!("yours {url}")), false)
+            .await;
+
+        for id in ["out", "in2"] {
+            assert!(matches!(
+                ready.recv().await.unwrap().payload,
+                EventPayload::LinkPreviewReady { message_id, .. } if message_id == id
+            ));
+        }
+
+        let (denied, _, _dir) = setup(LinkPreviewConfig {
+            allow_domains: vec!["example.com".to_string()],
+            deny_domains: vec!["tracker.example.com".to_string()],
+            ..LinkPreviewConfig::default()
+        })
+        .await;
+        let allowed = |url: &str| denied.allows(&Url::parse(url).unwrap());
+        assert!(allowed("https://example.com/"));
+        assert!(allowed("https://news.Example.com/"));
+        assert!(!allowed("https://tracker.example.com/"));
+        assert!(!allowed("https://a.tracker.example.com/"));
+        assert!(!allowed("https://notexample.com/"));
+        assert!(matches!(
+            denied.preview("https://other.org/").await,
+            Err(LinkPreviewError::Blocked(domain)) if domain == "other.org"
+        ));
+    }
+
+    #[tokio::test]
+    async fn links_from_strangers_are_only_fetched_when_configured() {
+        let server = MockServer::start().await;
+        serve_page(&server, "post", 1).await;
+        let url = format!("{}/post", server.uri());
+        let mut stranger = message("m1", &format!("click {url}"));
+        stranger.from = "mallory@example.net/phone".to_string();
+
+        let (manager, _, _dir) = setup(LinkPreviewConfig::default()).await;
+        manager.handle_message(&stranger, false).await;
+        assert!(manager.stored(&url).await.unwrap().is_none());
+
+        let (manager, _, _dir) = setup(LinkPreviewConfig {
+            fetch_from_strangers: true,
+            ..LinkPreviewConfig::default()
+        })
+        .await;
+        manager.handle_message(&stranger, false).await;
+        assert!(manager.stored(&url).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn only_public_addresses_are_fetched() {
+        let server = MockServer::start().await;
+        serve_page(&server, "post", 0).await;
+        let dir = TempDir::new().unwrap();
+        let db = waddle_storage::open_native_database(&dir.path().join("test.db"))
+            .await
+            .unwrap();
+        let manager = LinkPreviewManager::new(
+            Arc::new(db),
+            LinkPreviewConfig::default(),
+            Arc::new(BroadcastEventBus::default()),
+        );
+
+        let port = server.address().port();
+        assert!(matches!(
+            manager.preview(&format!("{}/post", server.uri())).await,
+            Err(LinkPreviewError::NotPublic(address)) if address.is_loopback()
+        ));
+        assert!(matches!(
+            manager.preview(&format!("http://[::1]:{port}/post")).await,
+            Err(LinkPreviewError::NotPublic(_))
+        ));
+        assert!(matches!(
+            manager
+                .preview(&format!("http://localhost:{port}/post"))
+                .await,
+            Err(LinkPreviewError::Http(_))
+        ));
+
+        for address in [
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::ffff:127.0.0.1",
+            "::127.0.0.1",
+            "::1",
+            "64:ff9b::10.0.0.1",
+            "64:ff9b::7f00:1",
+            "64:ff9b:1::5db8:d822",
+            "2002:c0a8:101::1",
+            "2001:0:4136:e378:8000:63bf:3fff:fdd2",
+            "2001:0:a01:203::1",
+            "fd00::1",
+            "fe80::1",
+        ] {
+            assert!(!is_public(address.parse().unwrap()), "{address}");
+        }
+        for address in [
+            "93.184.216.34",
+            "2606:4700::1111",
+            "::ffff:93.184.216.34",
+            "64:ff9b::5db8:d822",
+            "2002:5db8:d822::1",
+        ] {
+            assert!(is_public(address.parse().unwrap()), "{address}");
+        }
+    }
+}
@@ -0,0 +1,349 @@
+//! Periodic pruning of local message history according to the configured
+//! [`RetentionConfig`]. Only the local store is affected; anything pruned can
+//! still be fetched again from the server archive.
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use tracing::{error, info};
+
+use waddle_core::config::{RetentionConfig, RetentionPolicy};
+use waddle_core::event::PrunedConversation;
+use waddle_storage::{Database, Row, SqlValue, ToSql};
+
+#[cfg(feature = "native")]
+use waddle_core::event::{Channel, Event, EventBus, EventPayload, EventSource, channels};
+
+use crate::MessagingError;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PruneReport {
+    /// Total messages deleted, including those outside any conversation.
+    pub messages_deleted: u64,
+    pub conversations: Vec<PrunedConversation>,
+}
+
+/// Deletes messages beyond the configured age and count limits, either
+/// every `prune_interval_secs` from [`RetentionManager::run`] or on demand
+/// with [`RetentionManager::prune_now`].
+pub struct RetentionManager<D: Database> {
+    db: Arc<D>,
+    #[cfg(feature = "native")]
+    event_bus: Arc<dyn EventBus>,
+    config: RetentionConfig,
+}
+
+impl<D: Database> RetentionManager<D> {
+    #[cfg(feature = "native")]
+    pub fn new(db: Arc<D>, event_bus: Arc<dyn EventBus>, config: RetentionConfig) -> Self {
+        Self {
+            db,
+            event_bus,
+            config,
+        }
+    }
+
+    /// Run one pruning pass now. Publishes `system.storage.pruned` when
+    /// anything was deleted.
+    pub async fn prune_now(&self) -> Result<PruneReport, MessagingError> {
+        let report = self.prune().await?;
+        if report.messages_deleted > 0 {
+            info!(
+                messages_deleted = report.messages_deleted,
+                conversations = report.conversations.len(),
+                "pruned message history"
+            );
+            self.emit_pruned(&report);
+        }
+        Ok(report)
+    }
+
+    async fn prune(&self) -> Result<PruneReport, MessagingError> {
+        let mut report = PruneReport::default();
+        let default_policy = RetentionPolicy {
+            max_age_days: self.config.max_age_days,
+            max_count: self.config.max_count,
+        };
+        if default_policy.is_unlimited()
+            && self
+                .config
+                .conversations
+                .values()
+                .all(RetentionPolicy::is_unlimited)
+        {
+            return Ok(report);
+        }
+
+        let mut jids: BTreeSet<String> = self.config.conversations.keys().cloned().collect();
+        let rows: Vec<Row> = self.db.query("SELECT jid FROM conversations", &[]).await?;
+        jids.extend(rows.iter().filter_map(|row| match row.get(0) {
+            Some(SqlValue::Text(jid)) => Some(jid.clone()),
+            _ => None,
+        }));
+
+        for jid in &jids {
+            let deleted = self
+                .prune_conversation(jid, self.config.policy_for(jid))
+                .await?;
+            if deleted > 0 {
+                report.messages_deleted += deleted;
+                report.conversations.push(PrunedConversation {
+                    jid: jid.clone(),
+                    messages_deleted: deleted,
+                });
+            }
+        }
+
+        if let Some(days) = default_policy.max_age_days {
+            report.messages_deleted += self.prune_unlisted(days).await?;
+        }
+
+        Ok(report)
+    }
+
+    async fn prune_conversation(
+        &self,
+        jid: &str,
+        policy: RetentionPolicy,
+    ) -> Result<u64, MessagingError> {
+        let jid = jid.to_string();
+        let mut deleted = 0;
+
+        if let Some(days) = policy.max_age_days {
+            let cutoff = cutoff(days);
+            deleted += self
+                .db
+                .execute(
+                    &format!(
+                        "DELETE FROM messages WHERE {} AND timestamp < ?2",
+                        belongs_to("?1")
+                    ),
+                    &[&jid, &cutoff],
+                )
+                .await?;
+        }
+
+        if let Some(max_count) = policy.max_count {
+            let keep = i64::from(max_count);
+            deleted += self
+                .db
+                .execute(
+                    &format!(
+                        "DELETE FROM messages WHERE id IN (SELECT id FROM messages WHERE {} \
+                         ORDER BY timestamp DESC LIMIT -1 OFFSET ?2)",
+                        belongs_to("?1")
+                    ),
+                    &[&jid, &keep],
+                )
+                .await?;
+        }
+
+        Ok(deleted)
+    }
+
+    /// Apply the global age limit to messages outside every known
+    /// conversation, such as archive results for contacts never chatted with.
+    async fn prune_unlisted(&self, max_age_days: u32) -> Result<u64, MessagingError> {
+        let cutoff = cutoff(max_age_days);
+        let overrides: Vec<String> = self.config.conversations.keys().cloned().collect();
+
+        let mut sql = format!(
+            "DELETE FROM messages WHERE timestamp < ?1 \
+             AND NOT EXISTS (SELECT 1 FROM conversations c WHERE {})",
+            belongs_to("c.jid")
+        );
+        let mut params: Vec<&dyn ToSql> = vec![&cutoff];
+        for (index, jid) in overrides.iter().enumerate() {
+            sql.push_str(&format!(
+                " AND NOT {}",
+                belongs_to(&format!("?{}", index + 2))
+            ));
+            params.push(jid);
+        }
+
+        Ok(self.db.execute(&sql, &params).await?)
+    }
+
+    #[cfg(feature = "native")]
+    fn emit_pruned(&self, report: &PruneReport) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channels::SYSTEM_STORAGE_PRUNED).unwrap(),
+            EventSource::System("retention".into()),
+            EventPayload::StoragePruned {
+                messages_deleted: report.messages_deleted,
+                conversations: report.conversations.clone(),
+            },
+        ));
+    }
+
+    #[cfg(not(feature = "native"))]
+    fn emit_pruned(&self, _report: &PruneReport) {}
+
+    /// Prune every `prune_interval_secs`, starting immediately.
+    #[cfg(feature = "native")]
+    pub async fn run(self: Arc<Self>) -> Result<(), MessagingError> {
+        let period = std::time::Duration::from_secs(self.config.prune_interval_secs.max(1));
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.prune_now().await {
+                error!(error = %e, "failed to prune message history");
+            }
+        }
+    }
+}
+
+/// SQL condition matching messages exchanged in the conversation whose bare
+/// JID is `jid_expr`, from either side and with or without a resource.
+fn belongs_to(jid_expr: &str) -> String {
+    format!(
+        "(from_jid = {jid_expr} OR to_jid = {jid_expr} \
+         OR substr(from_jid, 1, length({jid_expr}) + 1) = {jid_expr} || '/' \
+         OR substr(to_jid, 1, length({jid_expr}) + 1) = {jid_expr} || '/')"
+    )
+}
+
+fn cutoff(max_age_days: u32) -> String {
+    (Utc::now() - Duration::days(i64::from(max_age_days))).to_rfc3339()
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use tempfile::TempDir;
+    use waddle_core::event::{BroadcastEventBus, ChatMessage, MessageType};
+
+    const FRIEND: &str = "friend@example.com";
+    const COLLEAGUE: &str = "colleague@example.com";
+
+    async fn setup(
+        config: RetentionConfig,
+    ) -> (RetentionManager<impl Database>, Arc<dyn EventBus>, TempDir) {
+        let dir = TempDir::new().expect("failed to create temp dir");
+        let db = waddle_storage::open_database(&dir.path().join("test.db"))
+            .await
+            .expect("failed to open database");
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let manager = RetentionManager::new(Arc::new(db), event_bus.clone(), config);
+        (manager, event_bus, dir)
+    }
+
+    async fn insert(
+        manager: &RetentionManager<impl Database>,
+        id: &str,
+        from: &str,
+        days_ago: i64,
+    ) {
+        let message = ChatMessage {
+            id: id.to_string(),
+            from: format!("{from}/phone"),
+            to: "me@example.com".to_string(),
+            body: "hi".to_string(),
+            timestamp: Utc::now() - Duration::days(days_ago),
+            message_type: MessageType::Chat,
+            thread: None,
+            embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
+        };
+        waddle_storage::store_message(manager.db.as_ref(), &message)
+            .await
+            .unwrap();
+    }
+
+    async fn add_conversation(manager: &RetentionManager<impl Database>, jid: &str) {
+        manager
+            .db
+            .execute(
+                "INSERT INTO conversations (jid, kind) VALUES (?1, 'chat')",
+                &[&jid.to_string()],
+            )
+            .await
+            .unwrap();
+    }
+
+    async fn remaining(manager: &RetentionManager<impl Database>) -> Vec<String> {
+        let rows: Vec<Row> = manager
+            .db
+            .query("SELECT id FROM messages ORDER BY id", &[])
+            .await
+            .unwrap();
+        rows.iter()
+            .filter_map(|row| match row.get(0) {
+                Some(SqlValue::Text(id)) => Some(id.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn prunes_by_age_and_count_with_per_conversation_overrides() {
+        let config = RetentionConfig {
+            max_age_days: Some(30),
+            max_count: Some(2),
+            conversations: HashMap::from([(
+                COLLEAGUE.to_string(),
+                RetentionPolicy {
+                    max_age_days: None,
+                    max_count: None,
+                },
+            )]),
+            ..RetentionConfig::default()
+        };
+        let (manager, event_bus, _dir) = setup(config).await;
+        let mut sub = event_bus.subscribe("system.storage.*").unwrap();
+
+        add_conversation(&manager, FRIEND).await;
+        for (id, days_ago) in [("f1", 60), ("f2", 3), ("f3", 2), ("f4", 1)] {
+            insert(&manager, id, FRIEND, days_ago).await;
+        }
+        insert(&manager, "c1", COLLEAGUE, 400).await;
+        insert(&manager, "s1", "stranger@example.com", 90).await;
+        insert(&manager, "s2", "stranger@example.com", 1).await;
+
+        let report = manager.prune_now().await.unwrap();
+
+        assert_eq!(remaining(&manager).await, vec!["c1", "f3", "f4", "s2"]);
+        assert_eq!(report.messages_deleted, 3);
+        assert_eq!(
+            report.conversations,
+            vec![PrunedConversation {
+                jid: FRIEND.to_string(),
+                messages_deleted: 2,
+            }]
+        );
+
+        let event = tokio::time::timeout(std::time::Duration::from_millis(100), sub.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.channel.as_str(), "system.storage.pruned");
+        assert!(matches!(
+            event.payload,
+            EventPayload::StoragePruned {
+                messages_deleted: 3,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn nothing_is_pruned_without_limits() {
+        let (manager, _, _dir) = setup(RetentionConfig::default()).await;
+        add_conversation(&manager, FRIEND).await;
+        insert(&manager, "f1", FRIEND, 4000).await;
+
+        assert_eq!(manager.prune_now().await.unwrap(), PruneReport::default());
+        assert_eq!(remaining(&manager).await, vec!["f1"]);
+    }
+}
@@ -0,0 +1,652 @@
+//! Automation rules: built-in scripting for the common cases that do not
+//! warrant a plugin, such as answering messages while away.
+//!
+//! A [`Rule`] pairs a trigger, a message or a presence change, with
+//! actions: a templated reply, a forward, or an event for a plugin. Rules
+//! live in the `rules` table and are managed with [`RuleEngine::save_rule`]
+//! and [`RuleEngine::delete_rule`], or by publishing on `ui.rule.save` and
+//! `ui.rule.delete`. [`RuleEngine::run`] evaluates the enabled rules
+//! against what arrives on the bus.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::json;
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+use waddle_core::error::EventBusError;
+use waddle_core::event::{
+    Channel, Event, EventBus, EventPayload, EventSource, MessageType, PresenceShow, Rule,
+    RuleAction, RuleTrigger, channels,
+};
+use waddle_core::jid::Jid;
+use waddle_storage::{Database, Row, SqlValue};
+
+use crate::{MessageManager, MessagingError};
+
+/// What a trigger matched, as templates see it.
+#[derive(Debug, Clone, PartialEq)]
+struct RuleContext {
+    /// Bare JID of the contact.
+    jid: String,
+    body: Option<String>,
+    show: Option<PresenceShow>,
+    status: Option<String>,
+}
+
+impl RuleContext {
+    fn from_event(payload: &EventPayload) -> Option<Self> {
+        match payload {
+            EventPayload::MessageReceived { message }
+                if matches!(
+                    message.message_type,
+                    MessageType::Chat | MessageType::Normal
+                ) =>
+            {
+                Some(Self {
+                    jid: Jid::new(&message.from).bare().into_string(),
+                    body: Some(message.body.clone()),
+                    show: None,
+                    status: None,
+                })
+            }
+            EventPayload::PresenceChanged {
+                jid, show, status, ..
+            } => Some(Self {
+                jid: Jid::new(jid).bare().into_string(),
+                body: None,
+                show: Some(show.clone()),
+                status: status.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn show_str(&self) -> String {
+        self.show
+            .as_ref()
+            .and_then(|show| serde_json::to_value(show).ok())
+            .and_then(|show| show.as_str().map(String::from))
+            .unwrap_or_default()
+    }
+
+    fn render(&self, template: &str) -> String {
+        template
+            .replace("{from}", &self.jid)
+            .replace("{body}", self.body.as_deref().unwrap_or_default())
+            .replace("{show}", &self.show_str())
+            .replace("{status}", self.status.as_deref().unwrap_or_default())
+    }
+
+    /// The text a forward sends on.
+    fn summary(&self) -> String {
+        match (&self.body, &self.status) {
+            (Some(body), _) => format!("{}: {body}", self.jid),
+            (None, Some(status)) => format!("{} is now {} ({status})", self.jid, self.show_str()),
+            (None, None) => format!("{} is now {}", self.jid, self.show_str()),
+        }
+    }
+}
+
+/// Whether `trigger` matches what happened in `context`.
+fn trigger_matches(trigger: &RuleTrigger, context: &RuleContext) -> bool {
+    match trigger {
+        RuleTrigger::Message { from, keyword } => {
+            let Some(body) = &context.body else {
+                return false;
+            };
+            from.as_deref()
+                .is_none_or(|pattern| jid_matches(pattern, &context.jid))
+                && keyword
+                    .as_deref()
+                    .is_none_or(|keyword| body.to_lowercase().contains(&keyword.to_lowercase()))
+        }
+        RuleTrigger::PresenceChanged { from, show } => {
+            let Some(current) = &context.show else {
+                return false;
+            };
+            from.as_deref()
+                .is_none_or(|pattern| jid_matches(pattern, &context.jid))
+                && show.as_ref().is_none_or(|show| show == current)
+        }
+    }
+}
+
+/// Match `jid` against `pattern`, where `*` stands for any run of
+/// characters. Case is ignored, as in JIDs.
+fn jid_matches(pattern: &str, jid: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let jid = jid.to_lowercase();
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = jid.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+fn validate(rule: &Rule) -> Result<(), MessagingError> {
+    if rule.name.trim().is_empty() {
+        return Err(MessagingError::InvalidRule("a rule needs a name".into()));
+    }
+    if rule.actions.is_empty() {
+        return Err(MessagingError::InvalidRule(format!(
+            "rule `{}` has no actions",
+            rule.name
+        )));
+    }
+    for action in &rule.actions {
+        match action {
+            RuleAction::Forward { to } if !to.contains('@') => {
+                return Err(MessagingError::InvalidJid(to.clone()));
+            }
+            RuleAction::PluginHook { plugin_id, hook }
+                if plugin_id.is_empty() || hook.is_empty() =>
+            {
+                return Err(MessagingError::InvalidRule(format!(
+                    "rule `{}` names no plugin hook",
+                    rule.name
+                )));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn rule_from_row(row: &Row) -> Option<Rule> {
+    let text = |idx: usize| match row.get(idx) {
+        Some(SqlValue::Text(s)) => Some(s.clone()),
+        _ => None,
+    };
+    let integer = |idx: usize| match row.get(idx) {
+        Some(SqlValue::Integer(i)) => Some(*i),
+        _ => None,
+    };
+    Some(Rule {
+        id: text(0)?,
+        name: text(1)?,
+        enabled: integer(2)? != 0,
+        trigger: serde_json::from_str(&text(3)?).ok()?,
+        actions: serde_json::from_str(&text(4)?).ok()?,
+        cooldown_secs: u64::try_from(integer(5)?).unwrap_or_default(),
+    })
+}
+
+pub struct RuleEngine<D: Database> {
+    db: Arc<D>,
+    messages: Arc<MessageManager<D>>,
+    event_bus: Arc<dyn EventBus>,
+    /// The enabled rules, as last loaded from storage.
+    rules: RwLock<Vec<Rule>>,
+    /// When each rule last fired for each contact.
+    last_fired: Mutex<HashMap<(String, String), Instant>>,
+}
+
+impl<D: Database> RuleEngine<D> {
+    /// Replies and forwards go out through `messages`, like any message
+    /// the user sends.
+    pub fn new(db: Arc<D>, messages: Arc<MessageManager<D>>, event_bus: Arc<dyn EventBus>) -> Self {
+        Self {
+            db,
+            messages,
+            event_bus,
+            rules: RwLock::new(Vec::new()),
+            last_fired: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Every rule, enabled or not, oldest first.
+    pub async fn list_rules(&self) -> Result<Vec<Rule>, MessagingError> {
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT id, name, enabled, trigger_json, actions_json, cooldown_secs \
+                 FROM rules ORDER BY created_at, id",
+                &[],
+            )
+            .await?;
+        Ok(rows.iter().filter_map(rule_from_row).collect())
+    }
+
+    /// Store `rule`, replacing the rule with the same id. A rule without an
+    /// id is new and gets one.
+    pub async fn save_rule(&self, mut rule: Rule) -> Result<Rule, MessagingError> {
+        validate(&rule)?;
+        if rule.id.is_empty() {
+            rule.id = Uuid::new_v4().to_string();
+        }
+
+        // Serializing these types cannot fail.
+        let trigger = serde_json::to_string(&rule.trigger).unwrap_or_default();
+        let actions = serde_json::to_string(&rule.actions).unwrap_or_default();
+        let enabled = i64::from(rule.enabled);
+        let cooldown_secs = i64::try_from(rule.cooldown_secs).unwrap_or(i64::MAX);
+        let created_at = Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true);
+        self.db
+            .execute(
+                "INSERT INTO rules \
+                 (id, name, enabled, trigger_json, actions_json, cooldown_secs, created_at) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) \
+                 ON CONFLICT(id) DO UPDATE SET name = excluded.name, \
+                 enabled = excluded.enabled, trigger_json = excluded.trigger_json, \
+                 actions_json = excluded.actions_json, cooldown_secs = excluded.cooldown_secs",
+                &[
+                    &rule.id,
+                    &rule.name,
+                    &enabled,
+                    &trigger,
+                    &actions,
+                    &cooldown_secs,
+                    &created_at,
+                ],
+            )
+            .await?;
+        self.reload().await?;
+
+        self.emit(
+            channels::SYSTEM_RULE_SAVED,
+            EventPayload::RuleSaved { rule: rule.clone() },
+        );
+        Ok(rule)
+    }
+
+    /// Delete rule `id`. Returns `false` if there was no such rule.
+    pub async fn delete_rule(&self, id: &str) -> Result<bool, MessagingError> {
+        let id_s = id.to_string();
+        let deleted = self
+            .db
+            .execute("DELETE FROM rules WHERE id = ?1", &[&id_s])
+            .await?;
+        if deleted == 0 {
+            return Ok(false);
+        }
+        self.reload().await?;
+
+        self.emit(
+            channels::SYSTEM_RULE_DELETED,
+            EventPayload::RuleDeleted { id: id_s },
+        );
+        Ok(true)
+    }
+
+    async fn reload(&self) -> Result<(), MessagingError> {
+        let enabled = self
+            .list_rules()
+            .await?
+            .into_iter()
+            .filter(|rule| rule.enabled)
+            .collect();
+        *self.rules.write().unwrap() = enabled;
+        Ok(())
+    }
+
+    /// Run the actions of every enabled rule `payload` triggers.
+    async fn evaluate(&self, payload: &EventPayload) {
+        let Some(context) = RuleContext::from_event(payload) else {
+            return;
+        };
+        let matching: Vec<Rule> = self
+            .rules
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|rule| trigger_matches(&rule.trigger, &context))
+            .cloned()
+            .collect();
+
+        for rule in matching {
+            if !self.start_cooldown(&rule, &context.jid) {
+                debug!(rule = %rule.id, jid = %context.jid, "rule cooling down");
+                continue;
+            }
+            for action in &rule.actions {
+                self.run_action(&rule, action, &context).await;
+            }
+            self.emit(
+                channels::SYSTEM_RULE_FIRED,
+                EventPayload::RuleFired {
+                    rule_id: rule.id.clone(),
+                    jid: context.jid.clone(),
+                },
+            );
+        }
+    }
+
+    /// Record that `rule` fires for `jid` now, unless it already did within
+    /// its cooldown.
+    fn start_cooldown(&self, rule: &Rule, jid: &str) -> bool {
+        let now = Instant::now();
+        let cooldown = Duration::from_secs(rule.cooldown_secs);
+        let mut last_fired = self.last_fired.lock().unwrap();
+        let key = (rule.id.clone(), jid.to_string());
+        if let Some(at) = last_fired.get(&key)
+            && now.duration_since(*at) < cooldown
+        {
+            return false;
+        }
+        last_fired.insert(key, now);
+        true
+    }
+
+    async fn run_action(&self, rule: &Rule, action: &RuleAction, context: &RuleContext) {
+        let result = match action {
+            RuleAction::Reply { template } => self
+                .messages
+                .send_message(&context.jid, &context.render(template))
+                .await
+                .map(drop),
+            RuleAction::Forward { to } => self
+                .messages
+                .send_message(to, &context.summary())
+                .await
+                .map(drop),
+            RuleAction::PluginHook { plugin_id, hook } => {
+                self.emit(
+                    channels::SYSTEM_RULE_PLUGIN_HOOK,
+                    EventPayload::RulePluginHookRequested {
+                        rule_id: rule.id.clone(),
+                        plugin_id: plugin_id.clone(),
+                        hook: hook.clone(),
+                        data: json!({
+                            "from": context.jid,
+                            "body": context.body,
+                            "show": context.show,
+                            "status": context.status,
+                        }),
+                    },
+                );
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            warn!(rule = %rule.id, error = %e, "rule action failed");
+        }
+    }
+
+    fn emit(&self, channel: &str, payload: EventPayload) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channel).unwrap(),
+            EventSource::System("rules".into()),
+            payload,
+        ));
+    }
+
+    /// Evaluate rules against incoming messages and presence changes, and
+    /// carry out the save and delete requests frontends publish.
+    pub async fn run(self: Arc<Self>) -> Result<(), MessagingError> {
+        let mut sub = self
+            .event_bus
+            .subscribe("{xmpp,ui}.**")
+            .map_err(|e| MessagingError::EventBus(e.to_string()))?;
+        self.reload().await?;
+
+        loop {
+            match sub.recv().await {
+                Ok(event) => match event.payload {
+                    EventPayload::RuleSaveRequested { rule } => {
+                        if let Err(e) = self.save_rule(rule).await {
+                            error!(error = %e, "failed to save rule");
+                        }
+                    }
+                    EventPayload::RuleDeleteRequested { id } => {
+                        if let Err(e) = self.delete_rule(&id).await {
+                            error!(error = %e, "failed to delete rule");
+                        }
+                    }
+                    payload => self.evaluate(&payload).await,
+                },
+                Err(EventBusError::ChannelClosed) => {
+                    debug!("event bus closed, rule engine stopping");
+                    return Ok(());
+                }
+                Err(EventBusError::Lagged(count)) => {
+                    warn!(count, "rule engine lagged, some events dropped");
+                }
+                Err(e) => return Err(MessagingError::EventBus(e.to_string())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use waddle_core::event::{BroadcastEventBus, ChatMessage};
+    use waddle_storage::NativeDatabase;
+
+    async fn setup() -> (Arc<RuleEngine<NativeDatabase>>, Arc<dyn EventBus>, TempDir) {
+        let dir = TempDir::new().unwrap();
+        let db = Arc::new(
+            waddle_storage::open_native_database(&dir.path().join("test.db"))
+                .await
+                .unwrap(),
+        );
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let messages = Arc::new(MessageManager::new(db.clone(), event_bus.clone()));
+        let engine = Arc::new(RuleEngine::new(db, messages, event_bus.clone()));
+        (engine, event_bus, dir)
+    }
+
+    fn away_rule() -> Rule {
+        Rule {
+            id: String::new(),
+            name: "away".into(),
+            enabled: true,
+            trigger: RuleTrigger::Message {
+                from: Some("*@example.com".into()),
+                keyword: Some("urgent".into()),
+            },
+            actions: vec![
+                RuleAction::Reply {
+                    template: "Away until Monday; I will read \"{body}\" then.".into(),
+                },
+                RuleAction::PluginHook {
+                    plugin_id: "pager".into(),
+                    hook: "page".into(),
+                },
+            ],
+            cooldown_secs: 3600,
+        }
+    }
+
+    fn received(from: &str, body: &str) -> EventPayload {
+        EventPayload::MessageReceived {
+            message: ChatMessage {
+                id: Uuid::new_v4().to_string(),
+                from: from.into(),
+                to: "alice@example.com".into(),
+                body: body.into(),
+                timestamp: Utc::now(),
+                message_type: MessageType::Chat,
+                thread: None,
+                embeds: Vec::new(),
+                origin_id: None,
+                stanza_id: None,
+                retracted_at: None,
+                lang: None,
+                translation: None,
+            },
+        }
+    }
+
+    #[test]
+    fn sender_patterns_use_wildcards() {
+        assert!(jid_matches("*@example.com", "bob@example.com"));
+        assert!(jid_matches("Bob@Example.com", "bob@example.com"));
+        assert!(jid_matches("b*b@*.example.com", "bob@muc.example.com"));
+        assert!(jid_matches("*", "anyone@anywhere"));
+        assert!(!jid_matches("*@example.com", "bob@example.org"));
+        assert!(!jid_matches("bob@example.com", "bob@example.com.evil"));
+        assert!(!jid_matches("b*b@example.com", "bob@example.comb"));
+    }
+
+    #[tokio::test]
+    async fn rules_are_saved_listed_and_deleted() {
+        let (engine, event_bus, _dir) = setup().await;
+        let mut events = event_bus.subscribe("system.rule.*").unwrap();
+
+        let saved = engine.save_rule(away_rule()).await.unwrap();
+        assert!(!saved.id.is_empty());
+        assert!(matches!(
+            events.recv().await.unwrap().payload,
+            EventPayload::RuleSaved { ref rule } if *rule == saved
+        ));
+
+        let renamed = engine
+            .save_rule(Rule {
+                name: "holiday".into(),
+                enabled: false,
+                ..saved.clone()
+            })
+            .await
+            .unwrap();
+        assert_eq!(engine.list_rules().await.unwrap(), vec![renamed]);
+        assert!(engine.rules.read().unwrap().is_empty());
+
+        assert!(engine.delete_rule(&saved.id).await.unwrap());
+        assert!(!engine.delete_rule(&saved.id).await.unwrap());
+        assert!(engine.list_rules().await.unwrap().is_empty());
+
+        let invalid = Rule {
+            actions: vec![RuleAction::Forward {
+                to: "nobody".into(),
+            }],
+            ..away_rule()
+        };
+        assert!(engine.save_rule(invalid).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn matching_messages_fire_once_per_cooldown() {
+        let (engine, event_bus, _dir) = setup().await;
+        let mut hooks = event_bus.subscribe("system.rule.plugin_hook").unwrap();
+        let mut fired = event_bus.subscribe("system.rule.fired").unwrap();
+        let rule = engine.save_rule(away_rule()).await.unwrap();
+
+        engine
+            .evaluate(&received("bob@example.org", "urgent!"))
+            .await;
+        engine
+            .evaluate(&received("bob@example.com/phone", "hello"))
+            .await;
+        engine
+            .evaluate(&received("bob@example.com/phone", "URGENT: call me"))
+            .await;
+        engine
+            .evaluate(&received("bob@example.com/laptop", "urgent again"))
+            .await;
+
+        let event = fired.recv().await.unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::RuleFired { ref rule_id, ref jid }
+                if *rule_id == rule.id && jid == "bob@example.com"
+        ));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), fired.recv())
+                .await
+                .is_err()
+        );
+
+        let event = hooks.recv().await.unwrap();
+        let EventPayload::RulePluginHookRequested {
+            plugin_id, data, ..
+        } = event.payload
+        else {
+            panic!("expected RulePluginHookRequested");
+        };
+        assert_eq!(plugin_id, "pager");
+        assert_eq!(data["body"], "URGENT: call me");
+
+        let replies = engine
+            .messages
+            .get_messages("bob@example.com", 10, None)
+            .await
+            .unwrap();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(
+            replies[0].body,
+            "Away until Monday; I will read \"URGENT: call me\" then."
+        );
+    }
+
+    #[tokio::test]
+    async fn presence_changes_are_forwarded() {
+        let (engine, _event_bus, _dir) = setup().await;
+        engine
+            .save_rule(Rule {
+                id: String::new(),
+                name: "watch".into(),
+                enabled: true,
+                trigger: RuleTrigger::PresenceChanged {
+                    from: Some("boss@example.com".into()),
+                    show: Some(PresenceShow::Available),
+                },
+                actions: vec![RuleAction::Forward {
+                    to: "alice@example.net".into(),
+                }],
+                cooldown_secs: 0,
+            })
+            .await
+            .unwrap();
+
+        let presence = |show: PresenceShow| EventPayload::PresenceChanged {
+            jid: "boss@example.com/desk".into(),
+            show,
+            status: Some("in the office".into()),
+            priority: 0,
+            idle_since: None,
+        };
+        engine.evaluate(&presence(PresenceShow::Away)).await;
+        engine.evaluate(&presence(PresenceShow::Available)).await;
+
+        let forwarded = engine
+            .messages
+            .get_messages("alice@example.net", 10, None)
+            .await
+            .unwrap();
+        assert_eq!(forwarded.len(), 1);
+        assert_eq!(
+            forwarded[0].body,
+            "boss@example.com is now available (in the office)"
+        );
+    }
+
+    #[tokio::test]
+    async fn run_handles_requests_from_the_bus() {
+        let (engine, event_bus, _dir) = setup().await;
+        let mut saved = event_bus.subscribe("system.rule.saved").unwrap();
+        tokio::spawn(engine.clone().run());
+        tokio::task::yield_now().await;
+
+        event_bus
+            .publish(Event::new(
+                Channel::new(channels::UI_RULE_SAVE).unwrap(),
+                EventSource::Ui(waddle_core::event::UiTarget::Gui),
+                EventPayload::RuleSaveRequested { rule: away_rule() },
+            ))
+            .unwrap();
+
+        let event = tokio::time::timeout(Duration::from_secs(2), saved.recv())
+            .await
+            .expect("rule was not saved")
+            .unwrap();
+        assert!(matches!(event.payload, EventPayload::RuleSaved { .. }));
+        assert_eq!(engine.rules.read().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,384 @@
+//! Messages written now and sent later.
+//!
+//! Scheduled messages are kept in the `scheduled_messages` table until they
+//! fall due, so they survive restarts. [`MessageManager::run_scheduler`]
+//! sleeps until the earliest one and hands it to
+//! [`MessageManager::send_message`], which sends it right away when online
+//! and puts it in the offline queue otherwise.
+
+#[cfg(feature = "native")]
+use std::sync::Arc;
+
+use chrono::{DateTime, SecondsFormat, SubsecRound, Utc};
+use tracing::error;
+use uuid::Uuid;
+
+use waddle_core::event::{EventPayload, ScheduledMessage};
+use waddle_core::jid::Jid;
+use waddle_storage::{Database, Row, SqlValue};
+
+#[cfg(feature = "native")]
+use tracing::{debug, warn};
+#[cfg(feature = "native")]
+use waddle_core::error::EventBusError;
+#[cfg(feature = "native")]
+use waddle_core::event::{Channel, Event, EventSource, channels};
+
+use crate::{MessageManager, MessagingError};
+
+/// How long the scheduler waits before retrying after a storage error.
+#[cfg(feature = "native")]
+const RETRY_AFTER: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Fixed-width UTC timestamps, so `send_at` sorts and compares as text.
+fn to_sql_time(at: &DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn scheduled_from_row(row: &Row) -> Option<ScheduledMessage> {
+    let text = |idx: usize| match row.get(idx) {
+        Some(SqlValue::Text(s)) => Some(s.clone()),
+        _ => None,
+    };
+    let time = |idx: usize| {
+        text(idx)
+            .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
+            .map(|at| at.with_timezone(&Utc))
+    };
+    Some(ScheduledMessage {
+        id: text(0)?,
+        to: text(1)?,
+        body: text(2)?,
+        send_at: time(3)?,
+        created_at: time(4)?,
+    })
+}
+
+impl<D: Database> MessageManager<D> {
+    /// Hold a message to `to` until `send_at`. A time in the past sends it
+    /// on the scheduler's next pass.
+    pub async fn schedule_message(
+        &self,
+        to: &str,
+        body: &str,
+        send_at: DateTime<Utc>,
+    ) -> Result<ScheduledMessage, MessagingError> {
+        if !to.contains('@') {
+            return Err(MessagingError::InvalidJid(to.to_string()));
+        }
+
+        let scheduled = ScheduledMessage {
+            id: Uuid::new_v4().to_string(),
+            to: Jid::new(to).into_string(),
+            body: body.to_string(),
+            // Stored at microsecond precision; keep what we return in step.
+            send_at: send_at.trunc_subsecs(6),
+            created_at: Utc::now().trunc_subsecs(6),
+        };
+        self.insert_scheduled(&scheduled).await?;
+
+        self.emit_schedule_event(
+            channels::SYSTEM_MESSAGE_SCHEDULED,
+            EventPayload::MessageScheduled {
+                message: scheduled.clone(),
+            },
+        );
+        Ok(scheduled)
+    }
+
+    /// Cancel a scheduled message. Returns `false` if there was nothing to
+    /// cancel, for instance because it has already been sent.
+    pub async fn cancel_scheduled_message(&self, id: &str) -> Result<bool, MessagingError> {
+        let id_s = id.to_string();
+        let deleted = self
+            .db
+            .execute("DELETE FROM scheduled_messages WHERE id = ?1", &[&id_s])
+            .await?;
+        if deleted == 0 {
+            return Ok(false);
+        }
+
+        self.emit_schedule_event(
+            channels::SYSTEM_MESSAGE_SCHEDULE_CANCELLED,
+            EventPayload::ScheduledMessageCancelled { id: id_s },
+        );
+        Ok(true)
+    }
+
+    /// Messages still waiting to be sent, soonest first.
+    pub async fn list_scheduled_messages(&self) -> Result<Vec<ScheduledMessage>, MessagingError> {
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT id, to_jid, body, send_at, created_at FROM scheduled_messages \
+                 ORDER BY send_at, created_at",
+                &[],
+            )
+            .await?;
+        Ok(rows.iter().filter_map(scheduled_from_row).collect())
+    }
+
+    async fn insert_scheduled(&self, scheduled: &ScheduledMessage) -> Result<(), MessagingError> {
+        let send_at = to_sql_time(&scheduled.send_at);
+        let created_at = to_sql_time(&scheduled.created_at);
+        self.db
+            .execute(
+                "INSERT INTO scheduled_messages (id, to_jid, body, send_at, created_at) \
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                &[
+                    &scheduled.id,
+                    &scheduled.to,
+                    &scheduled.body,
+                    &send_at,
+                    &created_at,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Send every message due by `now`.
+    async fn dispatch_due(&self, now: DateTime<Utc>) -> Result<(), MessagingError> {
+        let now_s = to_sql_time(&now);
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT id, to_jid, body, send_at, created_at FROM scheduled_messages \
+                 WHERE send_at <= ?1 ORDER BY send_at, created_at",
+                &[&now_s],
+            )
+            .await?;
+
+        for scheduled in rows.iter().filter_map(scheduled_from_row) {
+            // Claim the message before sending it, so a cancel racing with
+            // the send either wins outright or finds nothing to cancel.
+            let deleted = self
+                .db
+                .execute(
+                    "DELETE FROM scheduled_messages WHERE id = ?1",
+                    &[&scheduled.id],
+                )
+                .await?;
+            if deleted == 0 {
+                continue;
+            }
+
+            match self.send_message(&scheduled.to, &scheduled.body).await {
+                Ok(message) => self.emit_schedule_event(
+                    channels::SYSTEM_MESSAGE_SCHEDULE_SENT,
+                    EventPayload::ScheduledMessageSent {
+                        id: scheduled.id,
+                        message_id: message.id,
+                    },
+                ),
+                Err(e) => {
+                    error!(id = %scheduled.id, error = %e, "failed to send scheduled message");
+                    self.insert_scheduled(&scheduled).await?;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn next_send_at(&self) -> Result<Option<DateTime<Utc>>, MessagingError> {
+        Ok(self
+            .list_scheduled_messages()
+            .await?
+            .first()
+            .map(|scheduled| scheduled.send_at))
+    }
+
+    #[cfg(feature = "native")]
+    fn emit_schedule_event(&self, channel: &str, payload: EventPayload) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channel).unwrap(),
+            EventSource::System("messaging".into()),
+            payload,
+        ));
+    }
+
+    #[cfg(not(feature = "native"))]
+    fn emit_schedule_event(&self, _channel: &str, _payload: EventPayload) {}
+
+    /// Send scheduled messages as they fall due. Scheduling and cancelling
+    /// publish events on `system.message.*`, which wake the loop to
+    /// recompute its deadline.
+    #[cfg(feature = "native")]
+    pub async fn run_scheduler(self: Arc<Self>) -> Result<(), MessagingError> {
+        let mut sub = self
+            .event_bus
+            .subscribe("system.message.**")
+            .map_err(|e| MessagingError::EventBus(e.to_string()))?;
+
+        loop {
+            let wait = match self.dispatch_due(Utc::now()).await {
+                Ok(()) => match self.next_send_at().await {
+                    Ok(next) => next.map(|at| (at - Utc::now()).to_std().unwrap_or_default()),
+                    Err(e) => {
+                        error!(error = %e, "failed to load scheduled messages");
+                        Some(RETRY_AFTER)
+                    }
+                },
+                Err(_) => Some(RETRY_AFTER),
+            };
+
+            tokio::select! {
+                event = sub.recv() => match event {
+                    Ok(_) => {}
+                    Err(EventBusError::ChannelClosed) => {
+                        debug!("event bus closed, message scheduler stopping");
+                        return Ok(());
+                    }
+                    Err(EventBusError::Lagged(count)) => {
+                        warn!(count, "message scheduler lagged, some events dropped");
+                    }
+                    Err(e) => {
+                        error!(error = %e, "message scheduler subscription error");
+                        return Err(MessagingError::EventBus(e.to_string()));
+                    }
+                },
+                _ = async {
+                    match wait {
+                        Some(wait) => tokio::time::sleep(wait).await,
+                        None => std::future::pending().await,
+                    }
+                } => {}
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use tempfile::TempDir;
+    use waddle_core::event::{BroadcastEventBus, EventBus};
+    use waddle_storage::NativeDatabase;
+
+    async fn setup() -> (
+        Arc<MessageManager<NativeDatabase>>,
+        Arc<dyn EventBus>,
+        TempDir,
+    ) {
+        let dir = TempDir::new().unwrap();
+        let db = waddle_storage::open_native_database(&dir.path().join("test.db"))
+            .await
+            .unwrap();
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let manager = Arc::new(MessageManager::new(Arc::new(db), event_bus.clone()));
+        (manager, event_bus, dir)
+    }
+
+    #[tokio::test]
+    async fn scheduled_messages_are_listed_soonest_first_until_cancelled() {
+        let (manager, event_bus, _dir) = setup().await;
+        let mut events = event_bus.subscribe("system.message.**").unwrap();
+        let now = Utc::now();
+
+        let later = manager
+            .schedule_message("bob@example.com", "later", now + Duration::hours(2))
+            .await
+            .unwrap();
+        let sooner = manager
+            .schedule_message("bob@example.com", "sooner", now + Duration::hours(1))
+            .await
+            .unwrap();
+        assert!(matches!(
+            events.recv().await.unwrap().payload,
+            EventPayload::MessageScheduled { ref message } if *message == later
+        ));
+
+        let listed = manager.list_scheduled_messages().await.unwrap();
+        assert_eq!(listed, vec![sooner.clone(), later.clone()]);
+
+        assert!(manager.cancel_scheduled_message(&sooner.id).await.unwrap());
+        assert!(!manager.cancel_scheduled_message(&sooner.id).await.unwrap());
+        assert_eq!(
+            manager.list_scheduled_messages().await.unwrap(),
+            vec![later]
+        );
+
+        assert!(
+            manager
+                .schedule_message("not-a-jid", "hi", now)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn due_messages_go_to_the_offline_queue_while_offline() {
+        let (manager, event_bus, _dir) = setup().await;
+        let mut events = event_bus.subscribe("system.message.schedule_sent").unwrap();
+        let now = Utc::now();
+
+        let due = manager
+            .schedule_message(
+                "bob@example.com",
+                "good morning",
+                now - Duration::seconds(1),
+            )
+            .await
+            .unwrap();
+        manager
+            .schedule_message("bob@example.com", "not yet", now + Duration::hours(1))
+            .await
+            .unwrap();
+
+        manager.dispatch_due(now).await.unwrap();
+
+        let Event {
+            payload: EventPayload::ScheduledMessageSent { id, message_id },
+            ..
+        } = events.recv().await.unwrap()
+        else {
+            panic!("expected ScheduledMessageSent");
+        };
+        assert_eq!(id, due.id);
+        let queued = manager
+            .load_offline_queue_by_status("pending")
+            .await
+            .unwrap();
+        assert_eq!(queued.len(), 1);
+
+        let history = manager
+            .get_messages("bob@example.com", 10, None)
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].id, message_id);
+        assert_eq!(history[0].body, "good morning");
+
+        let remaining = manager.list_scheduled_messages().await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].body, "not yet");
+    }
+
+    #[tokio::test]
+    async fn scheduler_sends_when_the_time_comes() {
+        let (manager, event_bus, _dir) = setup().await;
+        let mut sent = event_bus.subscribe("system.message.schedule_sent").unwrap();
+        tokio::spawn(manager.clone().run_scheduler());
+        tokio::task::yield_now().await;
+
+        let scheduled = manager
+            .schedule_message(
+                "bob@example.com",
+                "ping",
+                Utc::now() + Duration::milliseconds(50),
+            )
+            .await
+            .unwrap();
+
+        let event = tokio::time::timeout(std::time::Duration::from_secs(2), sent.recv())
+            .await
+            .expect("scheduled message was not sent")
+            .unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::ScheduledMessageSent { ref id, .. } if *id == scheduled.id
+        ));
+    }
+}
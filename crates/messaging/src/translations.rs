@@ -0,0 +1,377 @@
+//! Translations of received messages written in another language than the
+//! user's, shown beside the original body.
+//!
+//! Which messages need one is decided from the `xml:lang` they arrived with;
+//! turning text into another language is left to a [`Translator`] the host
+//! supplies. Without one, the manager asks on the bus with
+//! `ui.message.translation_requested`, and a plugin or the frontend answers
+//! with `ui.message.translation_provided`. Either way the translation is
+//! stored with the message and announced on `ui.message.translation_ready`.
+
+use std::pin::Pin;
+use std::sync::Arc;
+
+use tracing::{debug, warn};
+
+use waddle_core::error::EventBusError;
+use waddle_core::event::{
+    Channel, ChatMessage, Event, EventBus, EventPayload, EventSource, MessageTranslation, channels,
+};
+use waddle_storage::{Database, StorageError};
+
+use crate::MessagingError;
+
+pub type TranslationFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<String, TranslationError>> + Send + 'a>>;
+
+/// Turns text from one language into another, such as through a
+/// translation service.
+pub trait Translator: Send + Sync + 'static {
+    /// `text`, written in language `from`, in language `to`. Both are
+    /// language tags as in `xml:lang`.
+    fn translate<'a>(&'a self, text: &'a str, from: &'a str, to: &'a str) -> TranslationFuture<'a>;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TranslationError {
+    #[error("cannot translate from {from} to {to}")]
+    Unsupported { from: String, to: String },
+
+    #[error("translation failed: {0}")]
+    Failed(String),
+
+    #[error("storage error: {0}")]
+    Storage(#[from] StorageError),
+}
+
+/// Whether two language tags name the same language, whatever their region
+/// or script: `en-GB` and `en` do.
+pub fn same_language(a: &str, b: &str) -> bool {
+    let primary = |tag: &str| tag.split(['-', '_']).next().unwrap_or_default().to_string();
+    primary(a).eq_ignore_ascii_case(&primary(b))
+}
+
+pub struct TranslationManager<D: Database> {
+    db: Arc<D>,
+    target_language: String,
+    translator: Option<Arc<dyn Translator>>,
+    event_bus: Arc<dyn EventBus>,
+}
+
+impl<D: Database> TranslationManager<D> {
+    /// A manager translating into `target_language`, asking on the bus for
+    /// each translation until [`with_translator`](Self::with_translator)
+    /// sets a translator.
+    pub fn new(
+        db: Arc<D>,
+        target_language: impl Into<String>,
+        event_bus: Arc<dyn EventBus>,
+    ) -> Self {
+        Self {
+            db,
+            target_language: target_language.into(),
+            translator: None,
+            event_bus,
+        }
+    }
+
+    pub fn with_translator(mut self, translator: Arc<dyn Translator>) -> Self {
+        self.translator = Some(translator);
+        self
+    }
+
+    pub fn target_language(&self) -> &str {
+        &self.target_language
+    }
+
+    /// Whether `message` was written in another language than the user's.
+    /// Messages that declare no language are taken to be in the user's.
+    pub fn needs_translation(&self, message: &ChatMessage) -> bool {
+        message.retracted_at.is_none()
+            && message.translation.is_none()
+            && !message.body.trim().is_empty()
+            && message
+                .lang
+                .as_deref()
+                .is_some_and(|lang| !same_language(lang, &self.target_language))
+    }
+
+    /// Translate `message` if it needs it: with the translator, or by asking
+    /// on the bus when there is none. Returns the translation when the
+    /// translator made one.
+    pub async fn handle_message(
+        &self,
+        message: &ChatMessage,
+    ) -> Result<Option<MessageTranslation>, TranslationError> {
+        if !self.needs_translation(message) {
+            return Ok(None);
+        }
+        let from = message.lang.clone().unwrap_or_default();
+        let Some(translator) = &self.translator else {
+            self.emit(
+                channels::UI_MESSAGE_TRANSLATION_REQUESTED,
+                EventPayload::MessageTranslationRequested {
+                    message_id: message.id.clone(),
+                    body: message.body.clone(),
+                    from,
+                    to: self.target_language.clone(),
+                },
+            );
+            return Ok(None);
+        };
+
+        let body = translator
+            .translate(&message.body, &from, &self.target_language)
+            .await?;
+        let translation = MessageTranslation {
+            lang: self.target_language.clone(),
+            body,
+        };
+        self.attach(&message.id, translation.clone()).await?;
+        Ok(Some(translation))
+    }
+
+    /// Store `translation` with message `message_id` and announce it.
+    pub async fn attach(
+        &self,
+        message_id: &str,
+        translation: MessageTranslation,
+    ) -> Result<(), TranslationError> {
+        waddle_storage::store_translation(self.db.as_ref(), message_id, &translation).await?;
+        self.emit(
+            channels::UI_MESSAGE_TRANSLATION_READY,
+            EventPayload::MessageTranslated {
+                message_id: message_id.to_string(),
+                translation,
+            },
+        );
+        Ok(())
+    }
+
+    /// Translate received messages, and store the translations others
+    /// provide, until the event bus closes.
+    pub async fn run(self: Arc<Self>) -> Result<(), MessagingError> {
+        let mut sub = self
+            .event_bus
+            .subscribe("{xmpp.**,ui.message.translation_provided}")
+            .map_err(|e| MessagingError::EventBus(e.to_string()))?;
+
+        loop {
+            match sub.recv().await {
+                Ok(event) => {
+                    let result = match event.payload {
+                        EventPayload::MessageReceived { message }
+                        | EventPayload::MucMessageReceived { message, .. } => {
+                            self.handle_message(&message).await.map(|_| ())
+                        }
+                        EventPayload::MessageTranslationProvided {
+                            message_id,
+                            translation,
+                        } => self.attach(&message_id, translation).await,
+                        _ => continue,
+                    };
+                    if let Err(error) = result {
+                        warn!(%error, "failed to translate message");
+                    }
+                }
+                Err(EventBusError::ChannelClosed) => {
+                    debug!("event bus closed, translation manager stopping");
+                    return Ok(());
+                }
+                Err(EventBusError::Lagged(count)) => {
+                    warn!(count, "translation manager lagged, some messages missed");
+                }
+                Err(e) => return Err(MessagingError::EventBus(e.to_string())),
+            }
+        }
+    }
+
+    fn emit(&self, channel: &str, payload: EventPayload) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channel).unwrap(),
+            EventSource::System("translations".into()),
+            payload,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use tempfile::TempDir;
+    use tokio::time::timeout;
+    use waddle_core::event::{BroadcastEventBus, MessageType};
+    use waddle_storage::NativeDatabase;
+
+    use crate::MucManager;
+
+    /// Translates by tagging the text with both languages.
+    struct Tagger;
+
+    impl Translator for Tagger {
+        fn translate<'a>(
+            &'a self,
+            text: &'a str,
+            from: &'a str,
+            to: &'a str,
+        ) -> TranslationFuture<'a> {
+            Box::pin(async move { Ok(format!("[{from}>{to}] {text}")) })
+        }
+    }
+
+    struct Setup {
+        db: Arc<NativeDatabase>,
+        event_bus: Arc<dyn EventBus>,
+        _dir: TempDir,
+    }
+
+    async fn setup() -> Setup {
+        let dir = TempDir::new().unwrap();
+        let db = waddle_storage::open_native_database(&dir.path().join("test.db"))
+            .await
+            .unwrap();
+        Setup {
+            db: Arc::new(db),
+            event_bus: Arc::new(BroadcastEventBus::default()),
+            _dir: dir,
+        }
+    }
+
+    fn message(id: &str, lang: Option<&str>) -> ChatMessage {
+        ChatMessage {
+            id: id.to_string(),
+            from: "room@conference.example.com/hans".to_string(),
+            to: "room@conference.example.com".to_string(),
+            body: "Guten Morgen".to_string(),
+            timestamp: Utc::now(),
+            message_type: MessageType::Groupchat,
+            thread: None,
+            embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: lang.map(str::to_string),
+            translation: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn only_other_languages_need_translating() {
+        assert!(same_language("en-GB", "EN"));
+        assert!(same_language("zh_Hant", "zh-TW"));
+        assert!(!same_language("de", "en"));
+
+        let setup = setup().await;
+        let manager = TranslationManager::new(setup.db, "en-US", setup.event_bus);
+        assert!(manager.needs_translation(&message("1", Some("de"))));
+        assert!(!manager.needs_translation(&message("2", Some("en"))));
+        assert!(!manager.needs_translation(&message("3", None)));
+        let mut retracted = message("4", Some("de"));
+        retracted.retracted_at = Some(Utc::now());
+        assert!(!manager.needs_translation(&retracted));
+    }
+
+    #[tokio::test]
+    async fn translations_are_stored_with_room_history() {
+        let setup = setup().await;
+        let manager = TranslationManager::new(setup.db.clone(), "en", setup.event_bus.clone())
+            .with_translator(Arc::new(Tagger));
+        let mut ready = setup
+            .event_bus
+            .subscribe(channels::UI_MESSAGE_TRANSLATION_READY)
+            .unwrap();
+
+        let german = message("gc-1", Some("de"));
+        // Translated before the message manager has stored the message.
+        let translation = manager.handle_message(&german).await.unwrap().unwrap();
+        assert_eq!(translation.body, "[de>en] Guten Morgen");
+        assert!(matches!(
+            timeout(Duration::from_secs(1), ready.recv())
+                .await
+                .unwrap()
+                .unwrap()
+                .payload,
+            EventPayload::MessageTranslated { message_id, .. } if message_id == "gc-1"
+        ));
+
+        let muc = MucManager::new(setup.db.clone(), setup.event_bus.clone());
+        muc.handle_event(&Event::new(
+            Channel::new(channels::XMPP_MUC_MESSAGE_RECEIVED).unwrap(),
+            EventSource::Xmpp,
+            EventPayload::MucMessageReceived {
+                room: "room@conference.example.com".to_string(),
+                message: german,
+            },
+        ))
+        .await;
+        let history = muc
+            .get_room_messages("room@conference.example.com", 10, None)
+            .await
+            .unwrap();
+        assert_eq!(history[0].lang.as_deref(), Some("de"));
+        assert_eq!(history[0].translation, Some(translation));
+    }
+
+    #[tokio::test]
+    async fn without_a_translator_translations_are_requested() {
+        let setup = setup().await;
+        let manager = Arc::new(TranslationManager::new(
+            setup.db.clone(),
+            "en",
+            setup.event_bus.clone(),
+        ));
+        let mut events = setup.event_bus.subscribe("ui.message.**").unwrap();
+        tokio::spawn(manager.clone().run());
+        tokio::task::yield_now().await;
+
+        setup
+            .event_bus
+            .publish(Event::new(
+                Channel::new(channels::XMPP_MUC_MESSAGE_RECEIVED).unwrap(),
+                EventSource::Xmpp,
+                EventPayload::MucMessageReceived {
+                    room: "room@conference.example.com".to_string(),
+                    message: message("gc-2", Some("de")),
+                },
+            ))
+            .unwrap();
+        let request = timeout(Duration::from_secs(1), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            request.payload,
+            EventPayload::MessageTranslationRequested { ref from, ref to, .. }
+                if from == "de" && to == "en"
+        ));
+
+        setup
+            .event_bus
+            .publish(Event::new(
+                Channel::new(channels::UI_MESSAGE_TRANSLATION_PROVIDED).unwrap(),
+                EventSource::System("test".into()),
+                EventPayload::MessageTranslationProvided {
+                    message_id: "gc-2".to_string(),
+                    translation: MessageTranslation {
+                        lang: "en".to_string(),
+                        body: "Good morning".to_string(),
+                    },
+                },
+            ))
+            .unwrap();
+        let ready = timeout(Duration::from_secs(1), async {
+            loop {
+                let event = events.recv().await.unwrap();
+                if let EventPayload::MessageTranslated { translation, .. } = event.payload {
+                    return translation;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(ready.body, "Good morning");
+    }
+}
@@ -0,0 +1,321 @@
+//! Automatic XEP-0085 chat states driven by the user's typing.
+//!
+//! The UI reports keystrokes with [`TypingTracker::keystroke`], or by
+//! publishing [`EventPayload::ComposeStarted`] from out of process. The tracker
+//! sends `composing` when typing starts, `paused` once the input has been
+//! idle for [`PAUSED_AFTER`], and `active` when a message goes out to that
+//! conversation.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::time::{Instant, sleep_until};
+use tracing::{debug, error, warn};
+
+use waddle_core::error::EventBusError;
+use waddle_core::event::{
+    Channel, ChatState, Event, EventBus, EventPayload, EventSource, channels,
+};
+use waddle_core::jid::Jid;
+
+use crate::MessagingError;
+
+/// Idle time after the last keystroke before `paused` is sent.
+pub const PAUSED_AFTER: Duration = Duration::from_secs(3);
+
+/// Least time between two notifications to the same conversation. Only a
+/// renewed `composing` is held back; `paused` and `active` always go out so
+/// the peer never keeps showing a stale typing indicator.
+pub const MIN_NOTIFICATION_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Typing {
+    /// The state last sent to the peer.
+    state: ChatState,
+    last_keystroke: Instant,
+    last_sent: Instant,
+}
+
+pub struct TypingTracker {
+    event_bus: Arc<dyn EventBus>,
+    conversations: Mutex<HashMap<Jid, Typing>>,
+    changed: Notify,
+}
+
+impl TypingTracker {
+    pub fn new(event_bus: Arc<dyn EventBus>) -> Self {
+        Self {
+            event_bus,
+            conversations: Mutex::new(HashMap::new()),
+            changed: Notify::new(),
+        }
+    }
+
+    /// The user typed in the compose box of the conversation with `to`.
+    pub fn keystroke(&self, to: &str) {
+        let to = Jid::new(to);
+        let now = Instant::now();
+        {
+            let mut conversations = self.conversations.lock().unwrap();
+            match conversations.get_mut(&to) {
+                Some(typing) => {
+                    typing.last_keystroke = now;
+                    if matches!(typing.state, ChatState::Composing)
+                        || now.duration_since(typing.last_sent) < MIN_NOTIFICATION_INTERVAL
+                    {
+                        return;
+                    }
+                    typing.state = ChatState::Composing;
+                    typing.last_sent = now;
+                }
+                None => {
+                    conversations.insert(
+                        to.clone(),
+                        Typing {
+                            state: ChatState::Composing,
+                            last_keystroke: now,
+                            last_sent: now,
+                        },
+                    );
+                }
+            }
+        }
+        self.publish(&to, ChatState::Composing);
+        self.changed.notify_one();
+    }
+
+    /// A message was sent to `to`. Nothing is sent unless the user had been
+    /// typing there.
+    pub fn message_sent(&self, to: &str) {
+        let to = Jid::new(to);
+        {
+            let mut conversations = self.conversations.lock().unwrap();
+            let Some(typing) = conversations.get_mut(&to) else {
+                return;
+            };
+            if matches!(typing.state, ChatState::Active) {
+                return;
+            }
+            typing.state = ChatState::Active;
+            typing.last_sent = Instant::now();
+        }
+        self.publish(&to, ChatState::Active);
+    }
+
+    /// Send `paused` for conversations idle since `now - PAUSED_AFTER`, and
+    /// forget conversations that have settled back to `active`.
+    fn pause_idle(&self, now: Instant) {
+        let mut paused = Vec::new();
+        {
+            let mut conversations = self.conversations.lock().unwrap();
+            conversations.retain(|to, typing| match typing.state {
+                ChatState::Composing if now >= typing.last_keystroke + PAUSED_AFTER => {
+                    typing.state = ChatState::Paused;
+                    typing.last_sent = now;
+                    paused.push(to.clone());
+                    true
+                }
+                ChatState::Active => now < typing.last_sent + MIN_NOTIFICATION_INTERVAL,
+                _ => true,
+            });
+        }
+        for to in paused {
+            self.publish(&to, ChatState::Paused);
+        }
+    }
+
+    /// When `pause_idle` next has something to do.
+    fn next_deadline(&self) -> Option<Instant> {
+        self.conversations
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|typing| match typing.state {
+                ChatState::Composing => Some(typing.last_keystroke + PAUSED_AFTER),
+                ChatState::Active => Some(typing.last_sent + MIN_NOTIFICATION_INTERVAL),
+                _ => None,
+            })
+            .min()
+    }
+
+    fn publish(&self, to: &str, state: ChatState) {
+        debug!(to, ?state, "sending chat state");
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channels::UI_CHATSTATE_SEND).unwrap(),
+            EventSource::System("messaging".into()),
+            EventPayload::ChatStateSendRequested {
+                to: to.to_string(),
+                state,
+            },
+        ));
+    }
+
+    /// Drive the idle timers, take keystrokes reported on the bus and watch
+    /// outgoing messages for the switch back to `active`.
+    pub async fn run(self: Arc<Self>) -> Result<(), MessagingError> {
+        let mut sub = self
+            .event_bus
+            .subscribe("{ui.message.send,ui.compose.started}")
+            .map_err(|e| MessagingError::EventBus(e.to_string()))?;
+
+        loop {
+            let deadline = self.next_deadline();
+            tokio::select! {
+                event = sub.recv() => match event {
+                    Ok(Event {
+                        payload: EventPayload::MessageSendRequested { to, .. },
+                        ..
+                    }) => self.message_sent(&to),
+                    Ok(Event {
+                        payload: EventPayload::ComposeStarted { jid },
+                        ..
+                    }) => self.keystroke(&jid),
+                    Ok(_) => {}
+                    Err(EventBusError::ChannelClosed) => {
+                        debug!("event bus closed, typing tracker stopping");
+                        return Ok(());
+                    }
+                    Err(EventBusError::Lagged(count)) => {
+                        warn!(count, "typing tracker lagged, some events dropped");
+                    }
+                    Err(e) => {
+                        error!(error = %e, "typing tracker subscription error");
+                        return Err(MessagingError::EventBus(e.to_string()));
+                    }
+                },
+                _ = async {
+                    match deadline {
+                        Some(deadline) => sleep_until(deadline).await,
+                        None => std::future::pending().await,
+                    }
+                } => {}
+                _ = self.changed.notified() => {}
+            }
+            self.pause_idle(Instant::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use waddle_core::event::{BroadcastEventBus, EventSubscription, MessageType};
+
+    const FRIEND: &str = "friend@example.com";
+
+    fn setup() -> (Arc<TypingTracker>, Arc<dyn EventBus>, EventSubscription) {
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let states = event_bus.subscribe("ui.chatstate.send").unwrap();
+        let tracker = Arc::new(TypingTracker::new(event_bus.clone()));
+        tokio::spawn(tracker.clone().run());
+        (tracker, event_bus, states)
+    }
+
+    async fn next_state(states: &mut EventSubscription) -> ChatState {
+        match states.recv().await.unwrap().payload {
+            EventPayload::ChatStateSendRequested { to, state } => {
+                assert_eq!(to, FRIEND);
+                state
+            }
+            other => panic!("unexpected payload {other:?}"),
+        }
+    }
+
+    async fn assert_nothing_sent(states: &mut EventSubscription) {
+        let next = tokio::time::timeout(Duration::from_millis(10), states.recv()).await;
+        assert!(next.is_err(), "unexpected chat state {next:?}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn typing_sends_composing_once_then_paused_when_idle() {
+        let (tracker, _bus, mut states) = setup();
+
+        for _ in 0..5 {
+            tracker.keystroke(FRIEND);
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        assert!(matches!(
+            next_state(&mut states).await,
+            ChatState::Composing
+        ));
+        assert_nothing_sent(&mut states).await;
+
+        tokio::time::sleep(PAUSED_AFTER).await;
+        assert!(matches!(next_state(&mut states).await, ChatState::Paused));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sending_a_message_returns_to_active() {
+        let (tracker, bus, mut states) = setup();
+        tokio::task::yield_now().await;
+
+        tracker.keystroke(FRIEND);
+        assert!(matches!(
+            next_state(&mut states).await,
+            ChatState::Composing
+        ));
+
+        bus.publish(Event::new(
+            Channel::new("ui.message.send").unwrap(),
+            EventSource::System("test".into()),
+            EventPayload::MessageSendRequested {
+                to: FRIEND.to_string(),
+                body: "hi".to_string(),
+                message_type: MessageType::Chat,
+                request_receipt: false,
+            },
+        ))
+        .unwrap();
+        assert!(matches!(next_state(&mut states).await, ChatState::Active));
+
+        // No `paused` follows once the message is out.
+        tokio::time::sleep(PAUSED_AFTER * 2).await;
+        assert_nothing_sent(&mut states).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn compose_events_count_as_keystrokes() {
+        let (_tracker, bus, mut states) = setup();
+        tokio::task::yield_now().await;
+
+        bus.publish(Event::new(
+            Channel::new("ui.compose.started").unwrap(),
+            EventSource::Ui(waddle_core::event::UiTarget::Tui),
+            EventPayload::ComposeStarted {
+                jid: FRIEND.to_string(),
+            },
+        ))
+        .unwrap();
+        assert!(matches!(
+            next_state(&mut states).await,
+            ChatState::Composing
+        ));
+
+        tokio::time::sleep(PAUSED_AFTER).await;
+        assert!(matches!(next_state(&mut states).await, ChatState::Paused));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resumed_typing_is_rate_limited() {
+        let (tracker, _bus, mut states) = setup();
+
+        tracker.keystroke(FRIEND);
+        assert!(matches!(
+            next_state(&mut states).await,
+            ChatState::Composing
+        ));
+        tokio::time::sleep(PAUSED_AFTER).await;
+        assert!(matches!(next_state(&mut states).await, ChatState::Paused));
+
+        tracker.keystroke(FRIEND);
+        assert_nothing_sent(&mut states).await;
+
+        tokio::time::sleep(MIN_NOTIFICATION_INTERVAL).await;
+        tracker.keystroke(FRIEND);
+        assert!(matches!(
+            next_state(&mut states).await,
+            ChatState::Composing
+        ));
+    }
+}
@@ -1,10 +1,11 @@
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{HashMap, VecDeque};
 use std::sync::{
     Arc, Mutex, RwLock,
     atomic::{AtomicBool, Ordering},
 };
 use std::time::{Duration, Instant};
 
+use chrono::{DateTime, Utc};
 #[cfg(feature = "native")]
 use notify_rust::Notification;
 use tracing::error;
@@ -15,11 +16,25 @@ use waddle_core::config::Config;
 #[cfg(feature = "native")]
 use waddle_core::error::EventBusError;
 #[cfg(feature = "native")]
-use waddle_core::event::{Channel, EventBus, EventSource};
-use waddle_core::event::{ChatMessage, Event, EventPayload};
+use waddle_core::event::{Channel, EventBus, EventSource, channels};
+use waddle_core::event::{
+    ChatMessage, Event, EventPayload, NotificationLevel, NotificationPreference, PresenceShow,
+};
+use waddle_core::jid::Jid;
+use waddle_core::styling;
+#[cfg(feature = "native")]
+use waddle_storage::Database;
+use waddle_storage::StorageError;
+
+mod settings;
+
+pub use settings::NotificationSettings;
 
 const AGGREGATION_WINDOW: Duration = Duration::from_secs(2);
 const AGGREGATION_THRESHOLD: usize = 3;
+/// Messages from one conversation closer together than this share a
+/// notification.
+const SENDER_BATCH_WINDOW: Duration = Duration::from_secs(5);
 #[cfg(feature = "native")]
 const NOTIFICATION_SOURCE: &str = "notifications";
 
@@ -31,6 +46,9 @@ pub enum NotificationError {
     #[error("notification permission denied")]
     PermissionDenied,
 
+    #[error("storage error: {0}")]
+    Storage(#[from] StorageError),
+
     #[cfg(feature = "native")]
     #[error("event bus error: {0}")]
     EventBus(#[from] EventBusError),
@@ -42,6 +60,9 @@ struct NotificationRequest {
     body: String,
     event_id: Option<String>,
     conversation_jid: Option<String>,
+    /// Takes the place of the conversation's last notification rather than
+    /// showing another one.
+    replaces_earlier: bool,
 }
 
 impl NotificationRequest {
@@ -51,13 +72,21 @@ impl NotificationRequest {
             body: format!("{total} new messages"),
             event_id: None,
             conversation_jid: None,
+            replaces_earlier: false,
         }
     }
 }
 
+#[derive(Debug)]
+struct SenderBatch {
+    last_at: Instant,
+    count: usize,
+}
+
 #[derive(Debug, Default)]
 struct AggregationState {
     recent_notifications: VecDeque<Instant>,
+    senders: HashMap<String, SenderBatch>,
 }
 
 impl AggregationState {
@@ -72,41 +101,264 @@ impl AggregationState {
         }
         self.recent_notifications.len()
     }
+
+    /// Count a message from `conversation_jid` and return how many it has
+    /// sent without a [`SENDER_BATCH_WINDOW`] gap.
+    fn batch_sender(&mut self, conversation_jid: &str, now: Instant) -> usize {
+        self.senders
+            .retain(|_, batch| now.duration_since(batch.last_at) <= SENDER_BATCH_WINDOW);
+        let batch = self
+            .senders
+            .entry(conversation_jid.to_string())
+            .or_insert(SenderBatch {
+                last_at: now,
+                count: 0,
+            });
+        batch.last_at = now;
+        batch.count += 1;
+        batch.count
+    }
 }
 
 trait NotificationDispatcher: Send + Sync {
     fn dispatch(&self, request: NotificationRequest) -> Result<(), NotificationError>;
 }
 
+/// Shows notifications through the OS: D-Bus on Linux and the BSDs,
+/// Notification Center on macOS and toasts on Windows. Clicks are only
+/// reported back on D-Bus; the other backends give no way to observe them.
 #[cfg(feature = "native")]
+struct NativeNotificationDispatcher {
+    #[cfg_attr(not(all(unix, not(target_os = "macos"))), allow(dead_code))]
+    event_bus: Arc<dyn EventBus>,
+    #[cfg(all(unix, not(target_os = "macos")))]
+    shown: Arc<Mutex<ShownNotifications>>,
+    /// The running D-Bus signal listener, if any, which turns `true` once
+    /// its subscription is in place. Cleared when the listener stops, so the
+    /// next notification with an action starts it again.
+    #[cfg(all(unix, not(target_os = "macos")))]
+    listener: Arc<Mutex<Option<tokio::sync::watch::Receiver<bool>>>>,
+}
+
+#[cfg(feature = "native")]
+impl NativeNotificationDispatcher {
+    fn new(event_bus: Arc<dyn EventBus>) -> Self {
+        Self {
+            event_bus,
+            #[cfg(all(unix, not(target_os = "macos")))]
+            shown: Arc::new(Mutex::new(ShownNotifications::default())),
+            #[cfg(all(unix, not(target_os = "macos")))]
+            listener: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Start the one task that watches the notification server for clicks
+    /// and closes, unless it is already running, and return its readiness.
+    #[cfg(all(unix, not(target_os = "macos")))]
+    fn ensure_listening(&self) -> Option<tokio::sync::watch::Receiver<bool>> {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            warn!("no async runtime; notification clicks will not be reported");
+            return None;
+        };
+        let mut listener = self.listener.lock().unwrap();
+        if let Some(ready) = listener.as_ref() {
+            return Some(ready.clone());
+        }
+        let (ready_tx, ready) = tokio::sync::watch::channel(false);
+        *listener = Some(ready.clone());
+
+        let event_bus = self.event_bus.clone();
+        let shown = self.shown.clone();
+        let slot = self.listener.clone();
+        runtime.spawn(async move {
+            match listen_for_actions(event_bus, shown, ready_tx).await {
+                Ok(()) => debug!("notification server signals ended"),
+                Err(error) => warn!(%error, "notification action listener stopped"),
+            }
+            *slot.lock().unwrap() = None;
+        });
+        Some(ready)
+    }
+}
+
+/// Notifications we have on screen, keyed by their D-Bus id.
+#[cfg(all(feature = "native", unix, not(target_os = "macos")))]
 #[derive(Debug, Default)]
-struct NativeNotificationDispatcher;
+struct ShownNotifications {
+    /// The latest notification per conversation, so a batch can replace it
+    /// in place.
+    by_conversation: HashMap<String, u32>,
+    /// The event each notification opens when clicked.
+    clicks: HashMap<u32, String>,
+}
+
+#[cfg(all(feature = "native", unix, not(target_os = "macos")))]
+impl ShownNotifications {
+    fn record(&mut self, id: u32, conversation_jid: Option<String>, event_id: Option<String>) {
+        if let Some(jid) = conversation_jid {
+            self.by_conversation.insert(jid, id);
+        }
+        // A replaced notification keeps the event of the one it replaced,
+        // which still opens the same conversation.
+        if let Some(event_id) = event_id {
+            self.clicks.entry(id).or_insert(event_id);
+        }
+    }
+
+    fn clicked(&self, id: u32) -> Option<&str> {
+        self.clicks.get(&id).map(String::as_str)
+    }
+
+    fn forget(&mut self, id: u32) {
+        self.clicks.remove(&id);
+        self.by_conversation.retain(|_, shown| *shown != id);
+    }
+}
+
+/// Report clicks on our notifications and forget the ones the server
+/// closes, for as long as the session bus connection lasts.
+#[cfg(all(feature = "native", unix, not(target_os = "macos")))]
+async fn listen_for_actions(
+    event_bus: Arc<dyn EventBus>,
+    shown: Arc<Mutex<ShownNotifications>>,
+    ready: tokio::sync::watch::Sender<bool>,
+) -> zbus::Result<()> {
+    use futures::StreamExt;
+
+    let connection = zbus::Connection::session().await?;
+    let proxy = zbus::Proxy::new(
+        &connection,
+        "org.freedesktop.Notifications",
+        "/org/freedesktop/Notifications",
+        "org.freedesktop.Notifications",
+    )
+    .await?;
+    // One stream for both signals keeps a click ahead of the close that
+    // follows it.
+    let mut signals = proxy.receive_all_signals().await?;
+    ready.send_replace(true);
+    while let Some(message) = signals.next().await {
+        let header = message.header();
+        match header.member().map(|member| member.as_str()) {
+            Some("ActionInvoked") => {
+                let Ok((id, action)) = message.body().deserialize::<(u32, String)>() else {
+                    continue;
+                };
+                let event_id = shown.lock().unwrap().clicked(id).map(str::to_string);
+                if action == "default"
+                    && let Some(event_id) = event_id
+                    && let Err(error) = publish_notification_clicked(&event_bus, &event_id)
+                {
+                    warn!(%error, "failed to report notification click");
+                }
+            }
+            Some("NotificationClosed") => {
+                if let Ok((id, _reason)) = message.body().deserialize::<(u32, u32)>() {
+                    shown.lock().unwrap().forget(id);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
 
 #[cfg(feature = "native")]
 impl NotificationDispatcher for NativeNotificationDispatcher {
     fn dispatch(&self, request: NotificationRequest) -> Result<(), NotificationError> {
         let mut notification = Notification::new();
-        notification.summary(&request.title).body(&request.body);
+        notification
+            .appname("Waddle")
+            .summary(&request.title)
+            .body(&request.body);
 
         #[cfg(all(unix, not(target_os = "macos")))]
-        if request.event_id.is_some() {
-            notification.action("default", "Open");
+        {
+            let replaced = request
+                .conversation_jid
+                .as_ref()
+                .filter(|_| request.replaces_earlier)
+                .and_then(|jid| self.shown.lock().unwrap().by_conversation.get(jid).copied());
+            if let Some(id) = replaced {
+                notification.id(id);
+            }
+            if request.event_id.is_some() {
+                notification.action("default", "Open");
+                // A click before the listener subscribes would be lost, so
+                // the notification waits for it.
+                if let Some(mut ready) = self.ensure_listening()
+                    && !*ready.borrow()
+                {
+                    let shown = self.shown.clone();
+                    tokio::spawn(async move {
+                        if ready.wait_for(|ready| *ready).await.is_err() {
+                            warn!("notification listener failed; this click will not be reported");
+                        }
+                        let shown_request = tokio::task::spawn_blocking(move || {
+                            show_and_record(&notification, &shown, request)
+                        });
+                        if let Ok(Err(error)) = shown_request.await {
+                            error!(error = %error, "failed to dispatch notification");
+                        }
+                    });
+                    return Ok(());
+                }
+            }
+
+            show_and_record(&notification, &self.shown, request)?;
         }
 
+        #[cfg(not(all(unix, not(target_os = "macos"))))]
         notification
             .show()
             .map_err(|error| NotificationError::DispatchFailed(error.to_string()))?;
+
         Ok(())
     }
 }
 
+/// Show `notification` and remember it for clicks and replacement.
+#[cfg(all(feature = "native", unix, not(target_os = "macos")))]
+fn show_and_record(
+    notification: &Notification,
+    shown: &Mutex<ShownNotifications>,
+    request: NotificationRequest,
+) -> Result<(), NotificationError> {
+    let handle = notification
+        .show()
+        .map_err(|error| NotificationError::DispatchFailed(error.to_string()))?;
+    shown
+        .lock()
+        .unwrap()
+        .record(handle.id(), request.conversation_jid, request.event_id);
+    Ok(())
+}
+
+#[cfg(feature = "native")]
+fn publish_notification_clicked(
+    event_bus: &Arc<dyn EventBus>,
+    event_id: &str,
+) -> Result<(), NotificationError> {
+    event_bus.publish(Event::new(
+        Channel::new(channels::UI_NOTIFICATION_CLICKED).unwrap(),
+        EventSource::System(NOTIFICATION_SOURCE.to_string()),
+        EventPayload::NotificationClicked {
+            event_id: event_id.to_string(),
+        },
+    ))?;
+    Ok(())
+}
+
 pub struct NotificationManager {
     notifications_enabled: AtomicBool,
+    /// Our own presence is Do Not Disturb.
+    do_not_disturb: AtomicBool,
     focused_conversation: RwLock<Option<String>>,
-    muted_conversations: RwLock<HashSet<String>>,
-    highlight_keywords: RwLock<HashSet<String>>,
-    room_nicks: RwLock<HashMap<String, String>>,
-    account_localpart: RwLock<Option<String>>,
+    /// Conversations whose preference differs from the default, by bare JID.
+    preferences: RwLock<HashMap<String, NotificationPreference>>,
+    /// Conversations muted from the conversation list, with the end of the
+    /// mute if it has one. These silence whatever the preference says.
+    muted_conversations: RwLock<HashMap<String, Option<DateTime<Utc>>>>,
     aggregation: Mutex<AggregationState>,
     dispatcher: Arc<dyn NotificationDispatcher>,
     #[cfg(feature = "native")]
@@ -117,18 +369,21 @@ impl NotificationManager {
     #[cfg(feature = "native")]
     pub fn new(event_bus: Arc<dyn EventBus>, notifications_enabled: bool) -> Self {
         Self::with_dispatcher(
-            event_bus,
+            event_bus.clone(),
             notifications_enabled,
-            Arc::new(NativeNotificationDispatcher),
+            Arc::new(NativeNotificationDispatcher::new(event_bus)),
         )
     }
 
     #[cfg(feature = "native")]
-    pub async fn run(
+    pub async fn run<D: Database>(
         event_bus: Arc<dyn EventBus>,
         config: &Config,
+        settings: &NotificationSettings<D>,
     ) -> Result<(), NotificationError> {
         let manager = Arc::new(Self::new(event_bus, config.ui.notifications));
+        *manager.preferences.write().unwrap() = settings.list().await?;
+        *manager.muted_conversations.write().unwrap() = settings.muted_conversations().await?;
         manager.serve().await
     }
 
@@ -141,38 +396,70 @@ impl NotificationManager {
         *focused = jid.map(normalize_jid);
     }
 
-    pub fn set_conversation_muted(&self, jid: &str, muted: bool) {
+    /// Override the default for `jid`; `None` restores it. Use
+    /// [`NotificationSettings::set`] to make the change stick.
+    pub fn set_preference(&self, jid: &str, preference: Option<NotificationPreference>) {
         let normalized = normalize_jid(jid);
-        let mut muted_conversations = self.muted_conversations.write().unwrap();
-        if muted {
-            muted_conversations.insert(normalized);
-        } else {
-            muted_conversations.remove(&normalized);
+        let mut preferences = self.preferences.write().unwrap();
+        match preference {
+            Some(preference) => {
+                preferences.insert(normalized, preference);
+            }
+            None => {
+                preferences.remove(&normalized);
+            }
         }
     }
 
+    pub fn set_conversation_muted(&self, jid: &str, muted: bool) {
+        self.set_preference(
+            jid,
+            muted.then_some(NotificationPreference {
+                level: NotificationLevel::Muted,
+                muted_until: None,
+            }),
+        );
+    }
+
     pub fn is_conversation_muted(&self, jid: &str) -> bool {
-        let normalized = normalize_jid(jid);
-        self.muted_conversations
-            .read()
-            .unwrap()
-            .contains(&normalized)
+        matches!(
+            self.effective_level(&normalize_jid(jid), false),
+            NotificationLevel::Muted
+        )
     }
 
-    pub fn set_highlight_keywords(&self, keywords: &[String]) {
-        let normalized = keywords
-            .iter()
-            .map(|keyword| keyword.trim().to_ascii_lowercase())
-            .filter(|keyword| !keyword.is_empty())
-            .collect::<HashSet<_>>();
-        *self.highlight_keywords.write().unwrap() = normalized;
+    /// The level in force for `jid` now. Rooms default to mentions only and
+    /// direct chats to everything.
+    fn effective_level(&self, jid: &str, is_room: bool) -> NotificationLevel {
+        let default = if is_room {
+            NotificationLevel::MentionsOnly
+        } else {
+            NotificationLevel::All
+        };
+        if self
+            .muted_conversations
+            .read()
+            .unwrap()
+            .get(jid)
+            .is_some_and(|until| until.is_none_or(|until| until > Utc::now()))
+        {
+            return NotificationLevel::Muted;
+        }
+        match self.preferences.read().unwrap().get(jid) {
+            Some(preference)
+                if preference
+                    .muted_until
+                    .is_some_and(|until| until > Utc::now()) =>
+            {
+                NotificationLevel::Muted
+            }
+            Some(preference) => preference.level,
+            None => default,
+        }
     }
 
     pub fn handle_event(&self, event: &Event) {
         match &event.payload {
-            EventPayload::ConnectionEstablished { jid } => {
-                *self.account_localpart.write().unwrap() = extract_localpart(jid);
-            }
             EventPayload::ConversationOpened { jid } => {
                 self.set_focused_conversation(Some(jid));
             }
@@ -183,78 +470,122 @@ impl NotificationManager {
                     *focused = None;
                 }
             }
-            EventPayload::MucJoined { room, nick } => {
-                self.room_nicks
-                    .write()
-                    .unwrap()
-                    .insert(normalize_jid(room), nick.clone());
+            EventPayload::OwnPresenceChanged { show, .. } => {
+                self.do_not_disturb
+                    .store(matches!(show, PresenceShow::Dnd), Ordering::Relaxed);
+            }
+            EventPayload::NotificationPreferenceChanged { jid, preference } => {
+                self.set_preference(jid, preference.clone());
             }
-            EventPayload::MucLeft { room } => {
-                self.room_nicks
-                    .write()
-                    .unwrap()
-                    .remove(&normalize_jid(room));
+            EventPayload::ConversationMuteChanged {
+                jid,
+                muted,
+                muted_until,
+            } => {
+                let normalized = normalize_jid(jid);
+                let mut muted_conversations = self.muted_conversations.write().unwrap();
+                if *muted {
+                    muted_conversations.insert(normalized, *muted_until);
+                } else {
+                    muted_conversations.remove(&normalized);
+                }
             }
             EventPayload::MessageReceived { message } => {
                 self.maybe_notify_message(message);
             }
+            // A mention arrives as both events; each level listens to one.
             EventPayload::MucMessageReceived { room, message } => {
-                self.maybe_notify_muc_message(room, message);
+                self.maybe_notify_muc_message(room, message, NotificationLevel::All);
+            }
+            EventPayload::MucMentionReceived { room, message } => {
+                self.maybe_notify_muc_message(room, message, NotificationLevel::MentionsOnly);
             }
             EventPayload::SubscriptionRequest { from } => {
                 self.maybe_notify_subscription_request(from);
             }
+            EventPayload::ServerFeatureLost { server, feature } => {
+                self.maybe_notify_server_alert(
+                    "Server feature unavailable",
+                    format!("{server} stopped offering {}", feature.description()),
+                );
+            }
+            EventPayload::UploadQuotaReduced {
+                server,
+                previous,
+                current,
+            } => {
+                self.maybe_notify_server_alert(
+                    "Upload limit reduced",
+                    format!("{server} lowered the upload limit from {previous} to {current} bytes"),
+                );
+            }
             _ => {}
         }
     }
 
     #[cfg(feature = "native")]
     pub fn emit_notification_clicked(&self, event_id: &str) -> Result<(), NotificationError> {
-        self.event_bus.publish(Event::new(
-            Channel::new("ui.notification.clicked").unwrap(),
-            EventSource::System(NOTIFICATION_SOURCE.to_string()),
-            EventPayload::NotificationClicked {
-                event_id: event_id.to_string(),
-            },
-        ))?;
-        Ok(())
+        publish_notification_clicked(&self.event_bus, event_id)
     }
 
     fn maybe_notify_message(&self, message: &ChatMessage) {
         let conversation_jid = normalize_jid(&message.from);
-        if !self.should_notify_for_conversation(&conversation_jid) {
+        if !self.should_notify_for_conversation(&conversation_jid)
+            || matches!(
+                self.effective_level(&conversation_jid, false),
+                NotificationLevel::Muted
+            )
+        {
             return;
         }
 
-        self.dispatch_with_aggregation(NotificationRequest {
-            title: conversation_jid.clone(),
-            body: message.body.clone(),
-            event_id: Some(message.id.clone()),
-            conversation_jid: Some(conversation_jid),
-        });
+        self.notify_message(conversation_jid, message);
     }
 
-    fn maybe_notify_muc_message(&self, room: &str, message: &ChatMessage) {
+    /// Notify for a room message if the room's level is `level`.
+    fn maybe_notify_muc_message(
+        &self,
+        room: &str,
+        message: &ChatMessage,
+        level: NotificationLevel,
+    ) {
         let room_jid = normalize_jid(room);
-        if !self.should_notify_for_conversation(&room_jid) {
+        if !self.should_notify_for_conversation(&room_jid)
+            || self.effective_level(&room_jid, true) != level
+        {
             return;
         }
 
-        if !self.is_muc_highlight(&room_jid, message) {
-            return;
-        }
+        self.notify_message(room_jid, message);
+    }
+
+    /// Show `message`, folded into the conversation's previous notification
+    /// when it follows within [`SENDER_BATCH_WINDOW`].
+    fn notify_message(&self, conversation_jid: String, message: &ChatMessage) {
+        let batched = self
+            .aggregation
+            .lock()
+            .unwrap()
+            .batch_sender(&conversation_jid, Instant::now());
+        let body = if batched > 1 {
+            format!("{batched} new messages")
+        } else {
+            // Notification centers show plain text; the markers would be noise.
+            styling::plain_text(&message.body)
+        };
 
         self.dispatch_with_aggregation(NotificationRequest {
-            title: room_jid.clone(),
-            body: message.body.clone(),
+            title: conversation_jid.clone(),
+            body,
             event_id: Some(message.id.clone()),
-            conversation_jid: Some(room_jid),
+            conversation_jid: Some(conversation_jid),
+            replaces_earlier: batched > 1,
         });
     }
 
     fn maybe_notify_subscription_request(&self, from: &str) {
         let from_jid = normalize_jid(from);
-        if !self.notifications_enabled.load(Ordering::Relaxed) {
+        if self.is_quiet() {
             return;
         }
 
@@ -263,67 +594,69 @@ impl NotificationManager {
             body: format!("{from_jid} wants to subscribe to your presence"),
             event_id: None,
             conversation_jid: Some(from_jid),
+            replaces_earlier: false,
         });
     }
 
-    fn should_notify_for_conversation(&self, conversation_jid: &str) -> bool {
-        if !self.notifications_enabled.load(Ordering::Relaxed) {
-            return false;
+    fn maybe_notify_server_alert(&self, title: &str, body: String) {
+        if self.is_quiet() {
+            return;
         }
 
-        if self
-            .muted_conversations
-            .read()
-            .unwrap()
-            .contains(conversation_jid)
-        {
-            return false;
-        }
+        self.dispatch_with_aggregation(NotificationRequest {
+            title: title.to_string(),
+            body,
+            event_id: None,
+            conversation_jid: None,
+            replaces_earlier: false,
+        });
+    }
 
-        self.focused_conversation.read().unwrap().as_deref() != Some(conversation_jid)
+    /// Notifications are off, or we are set to Do Not Disturb.
+    fn is_quiet(&self) -> bool {
+        !self.notifications_enabled.load(Ordering::Relaxed)
+            || self.do_not_disturb.load(Ordering::Relaxed)
     }
 
-    fn is_muc_highlight(&self, room_jid: &str, message: &ChatMessage) -> bool {
-        let body = message.body.to_ascii_lowercase();
-        if body.is_empty() {
+    fn should_notify_for_conversation(&self, conversation_jid: &str) -> bool {
+        if self.is_quiet() {
             return false;
         }
 
-        let room_nick = self.room_nicks.read().unwrap().get(room_jid).cloned();
-        if let Some(nick) = room_nick {
-            let nick = nick.to_ascii_lowercase();
-            if body.contains(&format!("@{nick}")) {
-                return true;
-            }
-        }
-
-        let account_localpart = self.account_localpart.read().unwrap().clone();
-        if let Some(localpart) = account_localpart
-            && body.contains(&format!("@{localpart}"))
-        {
-            return true;
-        }
-
-        self.highlight_keywords
-            .read()
-            .unwrap()
-            .iter()
-            .any(|keyword| body.contains(keyword))
+        self.focused_conversation.read().unwrap().as_deref() != Some(conversation_jid)
     }
 
     fn dispatch_with_aggregation(&self, request: NotificationRequest) {
-        let count = self
-            .aggregation
-            .lock()
-            .unwrap()
-            .record_and_count(Instant::now());
-
-        let outgoing = if count > AGGREGATION_THRESHOLD {
-            NotificationRequest::summary(count)
-        } else {
+        // A replacement adds nothing to the screen, so it is not counted.
+        let outgoing = if request.replaces_earlier {
             request
+        } else {
+            let count = self
+                .aggregation
+                .lock()
+                .unwrap()
+                .record_and_count(Instant::now());
+            if count > AGGREGATION_THRESHOLD {
+                NotificationRequest::summary(count)
+            } else {
+                request
+            }
         };
 
+        #[cfg(feature = "native")]
+        {
+            let _ = self.event_bus.publish(Event::new(
+                Channel::new(channels::UI_NOTIFICATION_SHOW).unwrap(),
+                EventSource::System(NOTIFICATION_SOURCE.to_string()),
+                EventPayload::NotificationShowRequested {
+                    title: outgoing.title.clone(),
+                    body: outgoing.body.clone(),
+                    event_id: outgoing.event_id.clone(),
+                    conversation_jid: outgoing.conversation_jid.clone(),
+                },
+            ));
+        }
+
         if let Err(error) = self.dispatcher.dispatch(outgoing) {
             error!(error = %error, "failed to dispatch notification");
         }
@@ -360,11 +693,10 @@ impl NotificationManager {
     ) -> Self {
         Self {
             notifications_enabled: AtomicBool::new(notifications_enabled),
+            do_not_disturb: AtomicBool::new(false),
             focused_conversation: RwLock::new(None),
-            muted_conversations: RwLock::new(HashSet::new()),
-            highlight_keywords: RwLock::new(HashSet::new()),
-            room_nicks: RwLock::new(HashMap::new()),
-            account_localpart: RwLock::new(None),
+            preferences: RwLock::new(HashMap::new()),
+            muted_conversations: RwLock::new(HashMap::new()),
             aggregation: Mutex::new(AggregationState::default()),
             dispatcher,
             event_bus,
@@ -372,18 +704,9 @@ impl NotificationManager {
     }
 }
 
+/// Conversations are keyed by their normalized bare JID.
 fn normalize_jid(jid: &str) -> String {
-    jid.split('/').next().unwrap_or(jid).to_string()
-}
-
-fn extract_localpart(jid: &str) -> Option<String> {
-    let bare = normalize_jid(jid);
-    let (localpart, _) = bare.split_once('@')?;
-    if localpart.is_empty() {
-        None
-    } else {
-        Some(localpart.to_ascii_lowercase())
-    }
+    Jid::new(jid).bare().into_string()
 }
 
 #[cfg(all(test, feature = "native"))]
@@ -456,30 +779,58 @@ mod tests {
                     message_type: MessageType::Chat,
                     thread: None,
                     embeds: vec![],
+                    origin_id: None,
+                    stanza_id: None,
+                    retracted_at: None,
+                    lang: None,
+                    translation: None,
                 },
             },
         )
     }
 
+    fn make_muc_message(room: &str, body: &str, id: &str) -> ChatMessage {
+        ChatMessage {
+            id: id.to_string(),
+            from: format!("{room}/alice"),
+            to: room.to_string(),
+            body: body.to_string(),
+            timestamp: Utc::now(),
+            message_type: MessageType::Groupchat,
+            thread: None,
+            embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
+        }
+    }
+
     fn make_muc_message_event(room: &str, body: &str, id: &str) -> Event {
         make_event(
             "xmpp.muc.message.received",
             EventPayload::MucMessageReceived {
                 room: room.to_string(),
-                message: ChatMessage {
-                    id: id.to_string(),
-                    from: format!("{room}/alice"),
-                    to: room.to_string(),
-                    body: body.to_string(),
-                    timestamp: Utc::now(),
-                    message_type: MessageType::Groupchat,
-                    thread: None,
-                    embeds: vec![],
-                },
+                message: make_muc_message(room, body, id),
             },
         )
     }
 
+    /// A mention is reported as the plain room message followed by this.
+    fn make_muc_mention_events(room: &str, body: &str, id: &str) -> [Event; 2] {
+        [
+            make_muc_message_event(room, body, id),
+            make_event(
+                "ui.notification.mention",
+                EventPayload::MucMentionReceived {
+                    room: room.to_string(),
+                    message: make_muc_message(room, body, id),
+                },
+            ),
+        ]
+    }
+
     #[test]
     fn notifications_disabled_suppresses_message_notifications() {
         let (manager, dispatcher) = make_manager(false);
@@ -512,6 +863,37 @@ mod tests {
         assert!(dispatcher.notifications().is_empty());
     }
 
+    #[test]
+    fn conversations_muted_from_the_list_stay_quiet_until_the_mute_ends() {
+        let (manager, dispatcher) = make_manager(true);
+        let mute_changed = |muted, muted_until| {
+            make_event(
+                "system.conversation.mute_changed",
+                EventPayload::ConversationMuteChanged {
+                    jid: "alice@example.com".to_string(),
+                    muted,
+                    muted_until,
+                },
+            )
+        };
+
+        manager.handle_event(&mute_changed(true, None));
+        manager.handle_event(&make_message_event("alice@example.com", "hello", "m1"));
+        assert!(dispatcher.notifications().is_empty());
+
+        manager.handle_event(&mute_changed(
+            true,
+            Some(Utc::now() - chrono::Duration::minutes(1)),
+        ));
+        manager.handle_event(&make_message_event("alice@example.com", "hello", "m2"));
+        assert_eq!(dispatcher.notifications().len(), 1);
+
+        manager.handle_event(&mute_changed(true, None));
+        manager.handle_event(&mute_changed(false, None));
+        manager.handle_event(&make_message_event("alice@example.com", "again", "m3"));
+        assert_eq!(dispatcher.notifications().len(), 2);
+    }
+
     #[test]
     fn incoming_message_dispatches_notification() {
         let (manager, dispatcher) = make_manager(true);
@@ -523,6 +905,18 @@ mod tests {
         assert_eq!(notifications[0].body, "Hello!");
     }
 
+    #[test]
+    fn notification_body_drops_message_styling() {
+        let (manager, dispatcher) = make_manager(true);
+        manager.handle_event(&make_message_event(
+            "alice@example.com",
+            "> lunch?\n*yes* at `noon`",
+            "m1",
+        ));
+
+        assert_eq!(dispatcher.notifications()[0].body, "lunch?\nyes at noon");
+    }
+
     #[test]
     fn notification_burst_is_aggregated() {
         let (manager, dispatcher) = make_manager(true);
@@ -555,15 +949,29 @@ mod tests {
     }
 
     #[test]
-    fn muc_notifications_require_mention() {
+    fn server_feature_loss_dispatches_notification() {
         let (manager, dispatcher) = make_manager(true);
         manager.handle_event(&make_event(
-            "system.connection.established",
-            EventPayload::ConnectionEstablished {
-                jid: "user@example.com".to_string(),
+            "system.health.feature_lost",
+            EventPayload::ServerFeatureLost {
+                server: "example.com".to_string(),
+                feature: waddle_core::event::ServerFeature::MessageArchive,
             },
         ));
 
+        let notifications = dispatcher.notifications();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].title, "Server feature unavailable");
+        assert_eq!(
+            notifications[0].body,
+            "example.com stopped offering message archiving (XEP-0313)"
+        );
+    }
+
+    #[test]
+    fn muc_notifications_require_mention() {
+        let (manager, dispatcher) = make_manager(true);
+
         manager.handle_event(&make_muc_message_event(
             "dev@conference.example.com",
             "general room update",
@@ -571,11 +979,13 @@ mod tests {
         ));
         assert!(dispatcher.notifications().is_empty());
 
-        manager.handle_event(&make_muc_message_event(
+        for event in make_muc_mention_events(
             "dev@conference.example.com",
             "Hey @user, check this out",
             "m2",
-        ));
+        ) {
+            manager.handle_event(&event);
+        }
         assert_eq!(dispatcher.notifications().len(), 1);
         assert_eq!(
             dispatcher.notifications()[0].title,
@@ -583,6 +993,130 @@ mod tests {
         );
     }
 
+    #[test]
+    fn room_levels_follow_preferences() {
+        let room = "dev@conference.example.com";
+        let (manager, dispatcher) = make_manager(true);
+
+        manager.set_preference(
+            room,
+            Some(NotificationPreference {
+                level: NotificationLevel::All,
+                muted_until: None,
+            }),
+        );
+        manager.handle_event(&make_muc_message_event(room, "general room update", "m1"));
+        for event in make_muc_mention_events(room, "@user ping", "m2") {
+            manager.handle_event(&event);
+        }
+        assert_eq!(dispatcher.notifications().len(), 2);
+
+        manager.handle_event(&make_event(
+            "ui.notification.preference",
+            EventPayload::NotificationPreferenceChanged {
+                jid: room.to_string(),
+                preference: Some(NotificationPreference {
+                    level: NotificationLevel::Muted,
+                    muted_until: None,
+                }),
+            },
+        ));
+        for event in make_muc_mention_events(room, "@user ping", "m3") {
+            manager.handle_event(&event);
+        }
+        assert_eq!(dispatcher.notifications().len(), 2);
+    }
+
+    #[test]
+    fn snoozed_conversation_is_muted_until_the_time_passes() {
+        let (manager, dispatcher) = make_manager(true);
+        let snooze = |minutes| NotificationPreference {
+            level: NotificationLevel::All,
+            muted_until: Some(Utc::now() + chrono::Duration::minutes(minutes)),
+        };
+
+        manager.set_preference("alice@example.com", Some(snooze(30)));
+        assert!(manager.is_conversation_muted("alice@example.com"));
+        manager.handle_event(&make_message_event("alice@example.com", "hello", "m1"));
+        assert!(dispatcher.notifications().is_empty());
+
+        manager.set_preference("alice@example.com", Some(snooze(-1)));
+        manager.handle_event(&make_message_event("alice@example.com", "hello", "m2"));
+        assert_eq!(dispatcher.notifications().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn allowed_notifications_are_published_for_the_ui() {
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let mut shown = event_bus.subscribe("ui.notification.show").unwrap();
+        let manager = NotificationManager::with_dispatcher(
+            event_bus,
+            true,
+            Arc::new(TestDispatcher::default()),
+        );
+
+        manager.set_conversation_muted("bob@example.com", true);
+        manager.handle_event(&make_message_event("bob@example.com", "muted", "m1"));
+        manager.handle_event(&make_message_event("alice@example.com", "hello", "m2"));
+
+        let event = shown.recv().await.unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::NotificationShowRequested {
+                ref event_id,
+                ref conversation_jid,
+                ..
+            } if event_id.as_deref() == Some("m2")
+                && conversation_jid.as_deref() == Some("alice@example.com")
+        ));
+    }
+
+    #[test]
+    fn rapid_messages_from_one_sender_share_a_notification() {
+        let (manager, dispatcher) = make_manager(true);
+
+        manager.handle_event(&make_message_event("alice@example.com", "hi", "m1"));
+        manager.handle_event(&make_message_event("alice@example.com", "are you", "m2"));
+        manager.handle_event(&make_message_event("alice@example.com", "there?", "m3"));
+        manager.handle_event(&make_message_event("bob@example.com", "hello", "m4"));
+
+        let notifications = dispatcher.notifications();
+        assert_eq!(notifications.len(), 4);
+        assert_eq!(notifications[0].body, "hi");
+        assert!(!notifications[0].replaces_earlier);
+        assert_eq!(notifications[2].body, "3 new messages");
+        assert_eq!(notifications[2].event_id.as_deref(), Some("m3"));
+        assert!(notifications[2].replaces_earlier);
+        // Only two notifications are on screen, so no burst summary.
+        assert_eq!(notifications[3].body, "hello");
+    }
+
+    #[test]
+    fn do_not_disturb_suppresses_notifications() {
+        let (manager, dispatcher) = make_manager(true);
+        let own_presence = |show| {
+            make_event(
+                "xmpp.presence.own_changed",
+                EventPayload::OwnPresenceChanged { show, status: None },
+            )
+        };
+
+        manager.handle_event(&own_presence(PresenceShow::Dnd));
+        manager.handle_event(&make_message_event("alice@example.com", "hello", "m1"));
+        manager.handle_event(&make_event(
+            "xmpp.subscription.request",
+            EventPayload::SubscriptionRequest {
+                from: "bob@example.com".to_string(),
+            },
+        ));
+        assert!(dispatcher.notifications().is_empty());
+
+        manager.handle_event(&own_presence(PresenceShow::Away));
+        manager.handle_event(&make_message_event("alice@example.com", "hello", "m2"));
+        assert_eq!(dispatcher.notifications().len(), 1);
+        assert_eq!(dispatcher.notifications()[0].body, "hello");
+    }
+
     #[test]
     fn dispatch_failures_are_non_fatal() {
         let (manager, dispatcher) = make_manager(true);
@@ -595,4 +1129,25 @@ mod tests {
             "m2",
         ));
     }
+
+    #[cfg(all(feature = "native", unix, not(target_os = "macos")))]
+    #[test]
+    fn closed_notifications_are_forgotten() {
+        let mut shown = ShownNotifications::default();
+        shown.record(
+            7,
+            Some("alice@example.com".to_string()),
+            Some("m1".to_string()),
+        );
+        shown.record(
+            7,
+            Some("alice@example.com".to_string()),
+            Some("m2".to_string()),
+        );
+        assert_eq!(shown.clicked(7), Some("m1"));
+
+        shown.forget(7);
+        assert_eq!(shown.clicked(7), None);
+        assert!(shown.by_conversation.is_empty());
+    }
 }
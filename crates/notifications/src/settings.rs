@@ -0,0 +1,226 @@
+//! Per-conversation notification preferences, kept in storage.
+//!
+//! Changes are announced on `ui.notification.preference` so a running
+//! [`NotificationManager`](crate::NotificationManager) picks them up without
+//! reading the database on every message.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use waddle_core::event::{NotificationLevel, NotificationPreference};
+use waddle_storage::{Database, Row, SqlValue};
+
+#[cfg(feature = "native")]
+use waddle_core::event::{Channel, Event, EventBus, EventPayload, EventSource, channels};
+
+#[cfg(feature = "native")]
+use crate::NOTIFICATION_SOURCE;
+use crate::{NotificationError, normalize_jid};
+
+fn level_to_str(level: NotificationLevel) -> &'static str {
+    match level {
+        NotificationLevel::All => "all",
+        NotificationLevel::MentionsOnly => "mentions_only",
+        NotificationLevel::Muted => "muted",
+    }
+}
+
+fn level_from_str(level: &str) -> Option<NotificationLevel> {
+    match level {
+        "all" => Some(NotificationLevel::All),
+        "mentions_only" => Some(NotificationLevel::MentionsOnly),
+        "muted" => Some(NotificationLevel::Muted),
+        _ => None,
+    }
+}
+
+fn preference_from_row(row: &Row) -> Option<(String, NotificationPreference)> {
+    let jid = match row.get(0) {
+        Some(SqlValue::Text(jid)) => jid.clone(),
+        _ => return None,
+    };
+    let level = match row.get(1) {
+        Some(SqlValue::Text(level)) => level_from_str(level)?,
+        _ => return None,
+    };
+    let muted_until = match row.get(2) {
+        Some(SqlValue::Text(until)) => DateTime::parse_from_rfc3339(until)
+            .ok()
+            .map(|until| until.with_timezone(&Utc)),
+        _ => None,
+    };
+    Some((jid, NotificationPreference { level, muted_until }))
+}
+
+pub struct NotificationSettings<D: Database> {
+    db: Arc<D>,
+    #[cfg(feature = "native")]
+    event_bus: Arc<dyn EventBus>,
+}
+
+impl<D: Database> NotificationSettings<D> {
+    #[cfg(feature = "native")]
+    pub fn new(db: Arc<D>, event_bus: Arc<dyn EventBus>) -> Self {
+        Self { db, event_bus }
+    }
+
+    /// The preference set for `jid`, if it has one.
+    pub async fn get(
+        &self,
+        jid: &str,
+    ) -> Result<Option<NotificationPreference>, NotificationError> {
+        let jid_s = normalize_jid(jid);
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT jid, level, muted_until FROM notification_preferences WHERE jid = ?1",
+                &[&jid_s],
+            )
+            .await?;
+        Ok(rows
+            .first()
+            .and_then(preference_from_row)
+            .map(|(_, preference)| preference))
+    }
+
+    /// Every conversation with a preference of its own, by bare JID.
+    pub async fn list(&self) -> Result<HashMap<String, NotificationPreference>, NotificationError> {
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT jid, level, muted_until FROM notification_preferences",
+                &[],
+            )
+            .await?;
+        Ok(rows.iter().filter_map(preference_from_row).collect())
+    }
+
+    /// Conversations muted from the conversation list, with the end of
+    /// each mute if it has one.
+    pub async fn muted_conversations(
+        &self,
+    ) -> Result<HashMap<String, Option<DateTime<Utc>>>, NotificationError> {
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT jid, muted_until FROM conversations WHERE muted = 1",
+                &[],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .filter_map(|row| {
+                let jid = match row.get(0) {
+                    Some(SqlValue::Text(jid)) => jid.clone(),
+                    _ => return None,
+                };
+                let muted_until = match row.get(1) {
+                    Some(SqlValue::Text(until)) => DateTime::parse_from_rfc3339(until)
+                        .ok()
+                        .map(|until| until.with_timezone(&Utc)),
+                    _ => None,
+                };
+                Some((jid, muted_until))
+            })
+            .collect())
+    }
+
+    /// Set the preference for `jid`; `None` restores the default.
+    pub async fn set(
+        &self,
+        jid: &str,
+        preference: Option<NotificationPreference>,
+    ) -> Result<(), NotificationError> {
+        let jid_s = normalize_jid(jid);
+        match &preference {
+            Some(preference) => {
+                let level = level_to_str(preference.level).to_string();
+                let muted_until = preference.muted_until.map(|until| until.to_rfc3339());
+                self.db
+                    .execute(
+                        "INSERT OR REPLACE INTO notification_preferences (jid, level, muted_until) \
+                         VALUES (?1, ?2, ?3)",
+                        &[&jid_s, &level, &muted_until],
+                    )
+                    .await?;
+            }
+            None => {
+                self.db
+                    .execute(
+                        "DELETE FROM notification_preferences WHERE jid = ?1",
+                        &[&jid_s],
+                    )
+                    .await?;
+            }
+        }
+
+        #[cfg(feature = "native")]
+        self.event_bus.publish(Event::new(
+            Channel::new(channels::UI_NOTIFICATION_PREFERENCE).unwrap(),
+            EventSource::System(NOTIFICATION_SOURCE.to_string()),
+            EventPayload::NotificationPreferenceChanged {
+                jid: jid_s,
+                preference,
+            },
+        ))?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use tempfile::TempDir;
+    use waddle_core::event::BroadcastEventBus;
+
+    #[tokio::test]
+    async fn preferences_round_trip_and_announce_changes() {
+        let dir = TempDir::new().unwrap();
+        let db = waddle_storage::open_database(&dir.path().join("test.db"))
+            .await
+            .unwrap();
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let mut changes = event_bus.subscribe("ui.notification.preference").unwrap();
+        let settings = NotificationSettings::new(Arc::new(db), event_bus);
+
+        let snoozed = NotificationPreference {
+            level: NotificationLevel::All,
+            muted_until: Some(Utc::now() + Duration::hours(1)),
+        };
+        settings
+            .set("bob@example.com/phone", Some(snoozed.clone()))
+            .await
+            .unwrap();
+        settings
+            .set(
+                "dev@conference.example.com",
+                Some(NotificationPreference {
+                    level: NotificationLevel::Muted,
+                    muted_until: None,
+                }),
+            )
+            .await
+            .unwrap();
+
+        let stored = settings.get("bob@example.com").await.unwrap().unwrap();
+        assert_eq!(stored.level, NotificationLevel::All);
+        assert_eq!(
+            stored.muted_until.map(|until| until.timestamp_micros()),
+            snoozed.muted_until.map(|until| until.timestamp_micros())
+        );
+        assert_eq!(settings.list().await.unwrap().len(), 2);
+
+        let event = changes.recv().await.unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::NotificationPreferenceChanged { ref jid, preference: Some(_) }
+                if jid == "bob@example.com"
+        ));
+
+        settings.set("bob@example.com", None).await.unwrap();
+        assert!(settings.get("bob@example.com").await.unwrap().is_none());
+        assert_eq!(settings.list().await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,214 @@
+//! Curve25519 key pairs, their libsignal wire encoding, and the XEdDSA
+//! signatures that let an X25519 identity key sign pre-keys.
+
+use curve25519_dalek::constants::ED25519_BASEPOINT_TABLE;
+use curve25519_dalek::edwards::EdwardsPoint;
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use curve25519_dalek::scalar::{Scalar, clamp_integer};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::OmemoError;
+
+/// Type byte libsignal prepends to serialized Curve25519 public keys.
+const DJB_TYPE: u8 = 0x05;
+
+pub const SIGNATURE_LEN: usize = 64;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KeyPair {
+    private: [u8; 32],
+    public: [u8; 32],
+}
+
+impl KeyPair {
+    pub fn generate() -> Self {
+        let mut private = [0u8; 32];
+        OsRng.fill_bytes(&mut private);
+        Self::from_private(private)
+    }
+
+    pub fn from_private(private: [u8; 32]) -> Self {
+        // Stored clamped so the XEdDSA scalar matches the X25519 one.
+        let private = clamp_integer(private);
+        let public = PublicKey::from(&StaticSecret::from(private)).to_bytes();
+        Self { private, public }
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public
+    }
+
+    pub fn serialized_public(&self) -> Vec<u8> {
+        serialize_public(&self.public)
+    }
+
+    pub fn agree(&self, their_public: &[u8; 32]) -> [u8; 32] {
+        StaticSecret::from(self.private)
+            .diffie_hellman(&PublicKey::from(*their_public))
+            .to_bytes()
+    }
+
+    /// XEdDSA signature as produced by libsignal: the Edwards form of the
+    /// key signs, and its sign bit travels in the top bit of the signature.
+    pub fn sign(&self, message: &[u8]) -> [u8; SIGNATURE_LEN] {
+        let mut random = [0u8; 64];
+        OsRng.fill_bytes(&mut random);
+
+        let a = Scalar::from_bytes_mod_order(self.private);
+        let ed_public = (&a * ED25519_BASEPOINT_TABLE).compress();
+        let sign_bit = ed_public.as_bytes()[31] & 0x80;
+
+        let mut prefix = [0xFFu8; 32];
+        prefix[0] = 0xFE;
+        let r = hash_to_scalar(&[&prefix, &self.private, message, &random]);
+        let cap_r = (&r * ED25519_BASEPOINT_TABLE).compress();
+        let h = hash_to_scalar(&[cap_r.as_bytes(), ed_public.as_bytes(), message]);
+        let s = h * a + r;
+
+        let mut signature = [0u8; SIGNATURE_LEN];
+        signature[..32].copy_from_slice(cap_r.as_bytes());
+        signature[32..].copy_from_slice(s.as_bytes());
+        signature[63] &= 0x7F;
+        signature[63] |= sign_bit;
+        signature
+    }
+}
+
+impl std::fmt::Debug for KeyPair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyPair")
+            .field("public", &fingerprint(&self.public))
+            .finish_non_exhaustive()
+    }
+}
+
+pub fn verify_signature(public: &[u8; 32], message: &[u8], signature: &[u8]) -> bool {
+    let Ok(signature) = <[u8; SIGNATURE_LEN]>::try_from(signature) else {
+        return false;
+    };
+    let Some(ed_public) = MontgomeryPoint(*public).to_edwards(signature[63] >> 7) else {
+        return false;
+    };
+
+    let mut s = [0u8; 32];
+    s.copy_from_slice(&signature[32..]);
+    s[31] &= 0x7F;
+    if s[31] & 0xE0 != 0 {
+        return false;
+    }
+
+    let h = hash_to_scalar(&[&signature[..32], ed_public.compress().as_bytes(), message]);
+    let check = EdwardsPoint::vartime_double_scalar_mul_basepoint(
+        &h,
+        &-ed_public,
+        &Scalar::from_bytes_mod_order(s),
+    );
+    check.compress().as_bytes() == &signature[..32]
+}
+
+pub fn serialize_public(public: &[u8; 32]) -> Vec<u8> {
+    let mut serialized = Vec::with_capacity(33);
+    serialized.push(DJB_TYPE);
+    serialized.extend_from_slice(public);
+    serialized
+}
+
+pub fn deserialize_public(bytes: &[u8]) -> Result<[u8; 32], OmemoError> {
+    match bytes {
+        [DJB_TYPE, key @ ..] if key.len() == 32 => Ok(key.try_into().expect("length checked")),
+        _ => Err(OmemoError::InvalidKey(format!(
+            "expected a 33-byte Curve25519 public key, got {} bytes",
+            bytes.len()
+        ))),
+    }
+}
+
+/// Hex rendering of a public key in groups of eight, for comparing
+/// fingerprints out of band.
+pub fn fingerprint(public: &[u8; 32]) -> String {
+    public
+        .chunks(4)
+        .map(|chunk| chunk.iter().map(|byte| format!("{byte:02x}")).collect())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn hash_to_scalar(parts: &[&[u8]]) -> Scalar {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    Scalar::from_bytes_mod_order_wide(&hasher.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_agreement_is_symmetric() {
+        let alice = KeyPair::generate();
+        let bob = KeyPair::generate();
+        assert_eq!(
+            alice.agree(&bob.public_key()),
+            bob.agree(&alice.public_key())
+        );
+    }
+
+    #[test]
+    fn signatures_verify_and_reject_tampering() {
+        let identity = KeyPair::generate();
+        let signed_pre_key = KeyPair::generate().serialized_public();
+        let signature = identity.sign(&signed_pre_key);
+
+        assert!(verify_signature(
+            &identity.public_key(),
+            &signed_pre_key,
+            &signature
+        ));
+
+        let mut tampered = signed_pre_key.clone();
+        tampered[5] ^= 1;
+        assert!(!verify_signature(
+            &identity.public_key(),
+            &tampered,
+            &signature
+        ));
+        assert!(!verify_signature(
+            &KeyPair::generate().public_key(),
+            &signed_pre_key,
+            &signature
+        ));
+    }
+
+    #[test]
+    fn verifies_libsignal_bundle_signature() {
+        // Bundle from the XEP-0384 v0.3 example published by Conversations.
+        let identity = deserialize_public(&[
+            5, 1, 93, 218, 159, 206, 171, 222, 239, 0, 7, 75, 40, 13, 61, 14, 87, 18, 131, 76, 117,
+            197, 105, 246, 235, 74, 117, 141, 167, 178, 34, 16, 25,
+        ])
+        .unwrap();
+        let signed_pre_key = [
+            5, 128, 27, 0, 32, 53, 229, 179, 231, 247, 154, 123, 68, 96, 182, 93, 184, 16, 202, 92,
+            188, 105, 18, 146, 224, 22, 137, 250, 16, 252, 97, 184, 94,
+        ];
+        let signature = [
+            176, 130, 85, 52, 54, 98, 252, 216, 5, 178, 188, 184, 56, 23, 76, 249, 167, 114, 26,
+            219, 75, 17, 118, 212, 135, 248, 127, 229, 213, 78, 102, 247, 140, 131, 37, 104, 33,
+            216, 48, 5, 76, 234, 241, 104, 29, 219, 22, 19, 64, 200, 168, 55, 60, 24, 121, 180,
+            215, 251, 218, 116, 135, 215, 174, 140,
+        ];
+        assert!(verify_signature(&identity, &signed_pre_key, &signature));
+    }
+
+    #[test]
+    fn rejects_keys_without_type_byte() {
+        let key = KeyPair::generate().public_key();
+        assert!(deserialize_public(&key).is_err());
+        assert_eq!(deserialize_public(&serialize_public(&key)).unwrap(), key);
+    }
+}
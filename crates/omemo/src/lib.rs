@@ -0,0 +1,1187 @@
+//! OMEMO end-to-end encryption (XEP-0384, in the `eu.siacs.conversations.axolotl`
+//! namespace most deployed clients speak) for 1:1 chats.
+//!
+//! [`OmemoManager`] publishes this device and its key bundle over PEP, keeps
+//! the device lists of contacts, builds Signal sessions from their bundles,
+//! and turns `xmpp.omemo.message.received` events into ordinary
+//! `MessageReceived` events. Messages it cannot decrypt are reported as
+//! [`EventPayload::OmemoMessageUndecryptable`] so frontends can show a
+//! placeholder instead of dropping them.
+
+mod keys;
+mod payload;
+mod protocol;
+mod session;
+mod store;
+
+use std::sync::{Arc, RwLock};
+
+use rand_core::{OsRng, RngCore};
+use serde::Serialize;
+use tracing::{debug, info, warn};
+
+use waddle_core::event::{OmemoBundle, OmemoEnvelope, OmemoKeyElement, OmemoPreKey, OmemoTrust};
+use waddle_core::jid::bare_jid;
+use waddle_storage::{Database, StorageError};
+
+#[cfg(feature = "native")]
+use std::time::Duration;
+
+#[cfg(feature = "native")]
+use chrono::Utc;
+#[cfg(feature = "native")]
+use tracing::error;
+#[cfg(feature = "native")]
+use uuid::Uuid;
+
+#[cfg(feature = "native")]
+use waddle_core::event::{
+    Channel, ChatMessage, Event, EventBus, EventPayload, EventSource, EventSubscription,
+    MessageType, channels,
+};
+
+pub use keys::fingerprint;
+
+use keys::{KeyPair, deserialize_public};
+use protocol::PreKeySignalMessage;
+use session::{RemoteBundle, SessionState};
+use store::{KeyStore, LocalIdentity, SignedPreKey};
+
+/// Generate a random key for [`OmemoManager::new`].
+pub fn generate_storage_key() -> [u8; 32] {
+    let mut key = [0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    key
+}
+
+/// One-time pre-keys kept published; each one consumed is replaced.
+const PRE_KEY_COUNT: u32 = 100;
+const SIGNED_PRE_KEY_ID: u32 = 1;
+/// How long to wait for PEP to answer a device list or bundle fetch.
+#[cfg(feature = "native")]
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum OmemoError {
+    #[error("not connected, the account JID is not known yet")]
+    NotConnected,
+
+    #[error("invalid key: {0}")]
+    InvalidKey(String),
+
+    #[error("signed pre-key signature does not verify")]
+    InvalidSignature,
+
+    #[error("malformed OMEMO message: {0}")]
+    MalformedMessage(String),
+
+    #[error("message authentication failed")]
+    BadMac,
+
+    #[error("message was already decrypted")]
+    DuplicateMessage,
+
+    #[error("message is not encrypted for this device")]
+    NotForThisDevice,
+
+    #[error("no session with device {device_id} of {jid}")]
+    NoSession { jid: String, device_id: u32 },
+
+    #[error("unknown pre-key {0}")]
+    UnknownPreKey(u32),
+
+    #[error("unknown device {device_id} of {jid}")]
+    UnknownDevice { jid: String, device_id: u32 },
+
+    #[error("device {device_id} of {jid} is not trusted")]
+    Untrusted { jid: String, device_id: u32 },
+
+    #[error("no usable OMEMO devices for {0}")]
+    NoRecipients(String),
+
+    #[error("stored key material is corrupted: {0}")]
+    Corrupted(String),
+
+    #[error("storage error: {0}")]
+    Storage(#[from] StorageError),
+
+    #[error("event bus error: {0}")]
+    EventBus(String),
+}
+
+/// An OMEMO device of a contact or of our own account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OmemoDevice {
+    pub jid: String,
+    pub device_id: u32,
+    /// Identity key fingerprint; `None` until the device's bundle is fetched.
+    pub fingerprint: Option<String>,
+    pub trust: OmemoTrust,
+    /// Whether the device is in its owner's current device list.
+    pub active: bool,
+    pub has_session: bool,
+}
+
+pub struct OmemoManager<D: Database> {
+    store: KeyStore<D>,
+    own_jid: RwLock<Option<String>>,
+    #[cfg(feature = "native")]
+    event_bus: Arc<dyn EventBus>,
+    /// Serializes read-modify-write of sessions and pre-keys between the
+    /// event loop and callers of [`OmemoManager::send_message`].
+    #[cfg(feature = "native")]
+    state_lock: tokio::sync::Mutex<()>,
+}
+
+impl<D: Database> OmemoManager<D> {
+    /// `storage_key` encrypts private keys and sessions in the database; the
+    /// application keeps it outside the database, e.g. in the OS keyring.
+    #[cfg(feature = "native")]
+    pub fn new(db: Arc<D>, event_bus: Arc<dyn EventBus>, storage_key: [u8; 32]) -> Self {
+        Self {
+            store: KeyStore::new(db, &storage_key),
+            own_jid: RwLock::new(None),
+            event_bus,
+            state_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    fn account(&self) -> Result<String, OmemoError> {
+        self.own_jid
+            .read()
+            .unwrap()
+            .clone()
+            .ok_or(OmemoError::NotConnected)
+    }
+
+    pub async fn own_device_id(&self) -> Result<u32, OmemoError> {
+        let account = self.account()?;
+        Ok(self.identity(&account).await?.device_id)
+    }
+
+    pub async fn own_fingerprint(&self) -> Result<String, OmemoError> {
+        let account = self.account()?;
+        let identity = self.identity(&account).await?;
+        Ok(fingerprint(&identity.key_pair.public_key()))
+    }
+
+    /// Devices known for `jid`, including ones that left its device list.
+    pub async fn list_devices(&self, jid: &str) -> Result<Vec<OmemoDevice>, OmemoError> {
+        let account = self.account()?;
+        let jid = bare_jid(jid);
+        let mut devices = Vec::new();
+        for record in self.store.devices(&account, &jid).await? {
+            devices.push(OmemoDevice {
+                jid: jid.clone(),
+                device_id: record.device_id,
+                fingerprint: record.identity_key.as_ref().map(fingerprint),
+                trust: record.trust,
+                active: record.active,
+                has_session: self
+                    .store
+                    .session_exists(&account, &jid, record.device_id)
+                    .await?,
+            });
+        }
+        Ok(devices)
+    }
+
+    /// Load this account's identity, generating the device ID, identity key
+    /// and pre-keys on first use.
+    async fn identity(&self, account: &str) -> Result<LocalIdentity, OmemoError> {
+        if let Some(identity) = self.store.identity(account).await? {
+            return Ok(identity);
+        }
+
+        let identity = LocalIdentity {
+            device_id: (OsRng.next_u32() & 0x7FFF_FFFF).max(1),
+            key_pair: KeyPair::generate(),
+            next_pre_key_id: PRE_KEY_COUNT + 1,
+        };
+        let signed_pre_key = KeyPair::generate();
+        self.store
+            .save_signed_pre_key(
+                account,
+                &SignedPreKey {
+                    id: SIGNED_PRE_KEY_ID,
+                    signature: identity
+                        .key_pair
+                        .sign(&signed_pre_key.serialized_public())
+                        .to_vec(),
+                    key_pair: signed_pre_key,
+                },
+            )
+            .await?;
+        for id in 1..=PRE_KEY_COUNT {
+            self.store
+                .save_pre_key(account, id, &KeyPair::generate())
+                .await?;
+        }
+        // Written last so an interrupted setup is redone from scratch.
+        self.store.save_identity(account, &identity).await?;
+
+        info!(device_id = identity.device_id, "generated OMEMO identity");
+        Ok(identity)
+    }
+
+    async fn bundle(
+        &self,
+        account: &str,
+        identity: &LocalIdentity,
+    ) -> Result<OmemoBundle, OmemoError> {
+        let signed_pre_key = self
+            .store
+            .signed_pre_key(account, None)
+            .await?
+            .ok_or_else(|| OmemoError::Corrupted("no signed pre-key".to_string()))?;
+        Ok(OmemoBundle {
+            identity_key: identity.key_pair.serialized_public(),
+            signed_pre_key_id: signed_pre_key.id,
+            signed_pre_key: signed_pre_key.key_pair.serialized_public(),
+            signed_pre_key_signature: signed_pre_key.signature,
+            pre_keys: self
+                .store
+                .pre_keys(account)
+                .await?
+                .into_iter()
+                .map(|(id, key_pair)| OmemoPreKey {
+                    id,
+                    public_key: key_pair.serialized_public(),
+                })
+                .collect(),
+        })
+    }
+
+    /// Record the identity key a device presented. A device whose key
+    /// changed is treated as a new device: its trust goes back to undecided
+    /// and the old session is dropped. Returns the device's trust.
+    async fn record_identity(
+        &self,
+        account: &str,
+        jid: &str,
+        device_id: u32,
+        identity_key: &[u8; 32],
+    ) -> Result<OmemoTrust, OmemoError> {
+        let known = self.store.device(account, jid, device_id).await?;
+        match known.as_ref().and_then(|device| device.identity_key) {
+            Some(key) if key == *identity_key => {
+                Ok(known.map(|device| device.trust).unwrap_or_default())
+            }
+            Some(_) => {
+                warn!(jid, device_id, "OMEMO identity key changed");
+                self.store
+                    .set_identity_key(account, jid, device_id, identity_key, OmemoTrust::Undecided)
+                    .await?;
+                self.store.delete_session(account, jid, device_id).await?;
+                #[cfg(feature = "native")]
+                self.publish(
+                    channels::SYSTEM_OMEMO_TRUST_CHANGED,
+                    EventPayload::OmemoTrustChanged {
+                        jid: jid.to_string(),
+                        device_id,
+                        trust: OmemoTrust::Undecided,
+                    },
+                );
+                Ok(OmemoTrust::Undecided)
+            }
+            None => {
+                let trust = known.map(|device| device.trust).unwrap_or_default();
+                self.store
+                    .set_identity_key(account, jid, device_id, identity_key, trust)
+                    .await?;
+                Ok(trust)
+            }
+        }
+    }
+
+    /// Decrypt the key addressed to this device and, unless this is a key
+    /// transport message, the payload. Sessions advance even if the payload
+    /// turns out to be corrupt, as in libsignal.
+    async fn decrypt_envelope(
+        &self,
+        account: &str,
+        identity: &LocalIdentity,
+        from: &str,
+        envelope: &OmemoEnvelope,
+    ) -> Result<(Option<String>, Option<u32>), OmemoError> {
+        let key = envelope
+            .keys
+            .iter()
+            .find(|key| key.recipient_device_id == identity.device_id)
+            .ok_or(OmemoError::NotForThisDevice)?;
+        let sender = envelope.sender_device_id;
+
+        let (session, key_material, consumed_pre_key) = if key.prekey {
+            let message = PreKeySignalMessage::decode(&key.data)?;
+            let trust = self
+                .record_identity(account, from, sender, &message.identity_key)
+                .await?;
+            ensure_trusted(from, sender, trust)?;
+
+            // A sender keeps sending pre-key messages until it hears back;
+            // those decrypt with the session the first one created.
+            let existing = self
+                .store
+                .session(account, from, sender)
+                .await?
+                .filter(|session| {
+                    session.base_key() == message.base_key
+                        && session.remote_identity() == message.identity_key
+                });
+            match existing {
+                Some(mut session) => {
+                    let key_material = session.decrypt(&message.message)?;
+                    (session, key_material, None)
+                }
+                None => {
+                    let signed_pre_key = self
+                        .store
+                        .signed_pre_key(account, Some(message.signed_pre_key_id))
+                        .await?
+                        .ok_or(OmemoError::UnknownPreKey(message.signed_pre_key_id))?;
+                    let one_time = match message.pre_key_id {
+                        Some(id) => Some(
+                            self.store
+                                .pre_key(account, id)
+                                .await?
+                                .ok_or(OmemoError::UnknownPreKey(id))?,
+                        ),
+                        None => None,
+                    };
+                    let mut session = SessionState::respond(
+                        &identity.key_pair,
+                        identity.device_id,
+                        &signed_pre_key.key_pair,
+                        one_time.as_ref(),
+                        &message,
+                    );
+                    let key_material = session.decrypt(&message.message)?;
+                    (session, key_material, message.pre_key_id)
+                }
+            }
+        } else {
+            let known = self.store.device(account, from, sender).await?;
+            ensure_trusted(
+                from,
+                sender,
+                known.map(|device| device.trust).unwrap_or_default(),
+            )?;
+            let mut session = self
+                .store
+                .session(account, from, sender)
+                .await?
+                .ok_or_else(|| OmemoError::NoSession {
+                    jid: from.to_string(),
+                    device_id: sender,
+                })?;
+            let key_material = session.decrypt(&key.data)?;
+            (session, key_material, None)
+        };
+        self.store
+            .save_session(account, from, sender, &session)
+            .await?;
+
+        let Some(payload) = &envelope.payload else {
+            return Ok((None, consumed_pre_key));
+        };
+        let plaintext = payload::open(&key_material, &envelope.iv, payload)?;
+        let body = String::from_utf8(plaintext)
+            .map_err(|_| OmemoError::MalformedMessage("payload is not UTF-8".to_string()))?;
+        Ok((Some(body), consumed_pre_key))
+    }
+
+    /// Replace a consumed one-time pre-key so the published bundle keeps
+    /// [`PRE_KEY_COUNT`] of them.
+    async fn replace_pre_key(
+        &self,
+        account: &str,
+        mut identity: LocalIdentity,
+        consumed: u32,
+    ) -> Result<LocalIdentity, OmemoError> {
+        self.store.remove_pre_key(account, consumed).await?;
+        self.store
+            .save_pre_key(account, identity.next_pre_key_id, &KeyPair::generate())
+            .await?;
+        identity.next_pre_key_id += 1;
+        self.store.save_identity(account, &identity).await?;
+        Ok(identity)
+    }
+
+    /// Encrypt `body` for every usable device in `devices`, which must
+    /// already have sessions. Devices without one are skipped.
+    async fn encrypt_for(
+        &self,
+        account: &str,
+        identity: &LocalIdentity,
+        devices: &[(String, u32)],
+        body: &str,
+    ) -> Result<OmemoEnvelope, OmemoError> {
+        let sealed = payload::seal(body.as_bytes());
+        let mut keys = Vec::new();
+        for (jid, device_id) in devices {
+            let Some(mut session) = self.store.session(account, jid, *device_id).await? else {
+                debug!(jid = %jid, device_id, "no OMEMO session, device skipped");
+                continue;
+            };
+            let encrypted = session.encrypt(&sealed.key_material);
+            self.store
+                .save_session(account, jid, *device_id, &session)
+                .await?;
+            keys.push(OmemoKeyElement {
+                recipient_device_id: *device_id,
+                prekey: encrypted.prekey,
+                data: encrypted.data,
+            });
+        }
+        Ok(OmemoEnvelope {
+            sender_device_id: identity.device_id,
+            keys,
+            iv: sealed.iv,
+            payload: Some(sealed.ciphertext),
+        })
+    }
+
+    /// Active, not distrusted devices of `jid`, without our own device.
+    async fn recipient_devices(
+        &self,
+        account: &str,
+        identity: &LocalIdentity,
+        jid: &str,
+    ) -> Result<Vec<(String, u32)>, OmemoError> {
+        Ok(self
+            .store
+            .devices(account, jid)
+            .await?
+            .into_iter()
+            .filter(|device| device.active && device.trust != OmemoTrust::Untrusted)
+            .filter(|device| !(jid == account && device.device_id == identity.device_id))
+            .map(|device| (jid.to_string(), device.device_id))
+            .collect())
+    }
+
+    /// Store a fetched bundle and start a session with the device if there
+    /// is none yet.
+    async fn process_bundle(
+        &self,
+        account: &str,
+        identity: &LocalIdentity,
+        jid: &str,
+        device_id: u32,
+        bundle: &OmemoBundle,
+    ) -> Result<(), OmemoError> {
+        let remote = remote_bundle(bundle)?;
+        let trust = self
+            .record_identity(account, jid, device_id, &remote.identity_key)
+            .await?;
+        if trust == OmemoTrust::Untrusted
+            || self.store.session_exists(account, jid, device_id).await?
+        {
+            return Ok(());
+        }
+        let session = SessionState::initiate(&identity.key_pair, identity.device_id, &remote)?;
+        self.store
+            .save_session(account, jid, device_id, &session)
+            .await?;
+        debug!(jid, device_id, "OMEMO session started");
+        Ok(())
+    }
+}
+
+#[cfg(feature = "native")]
+impl<D: Database> OmemoManager<D> {
+    /// Set the trust of a device and announce the change.
+    pub async fn trust_device(
+        &self,
+        jid: &str,
+        device_id: u32,
+        trust: OmemoTrust,
+    ) -> Result<(), OmemoError> {
+        let account = self.account()?;
+        let jid = bare_jid(jid);
+        if !self
+            .store
+            .set_trust(&account, &jid, device_id, trust)
+            .await?
+        {
+            return Err(OmemoError::UnknownDevice { jid, device_id });
+        }
+        info!(jid = %jid, device_id, trust = trust.as_str(), "OMEMO device trust changed");
+        self.publish(
+            channels::SYSTEM_OMEMO_TRUST_CHANGED,
+            EventPayload::OmemoTrustChanged {
+                jid,
+                device_id,
+                trust,
+            },
+        );
+        Ok(())
+    }
+
+    /// Encrypt `body` for `to` and our other devices and send it, fetching
+    /// device lists and bundles that are not known yet. Emits
+    /// `MessageSent` with the plaintext for local history.
+    pub async fn send_message(&self, to: &str, body: &str) -> Result<ChatMessage, OmemoError> {
+        let account = self.account()?;
+        let identity = self.identity(&account).await?;
+        let to = bare_jid(to);
+
+        let mut sub = self
+            .event_bus
+            .subscribe("xmpp.omemo.**")
+            .map_err(|e| OmemoError::EventBus(e.to_string()))?;
+        if self.store.devices(&account, &to).await?.is_empty() {
+            self.fetch_device_list(&mut sub, &account, &identity, &to)
+                .await?;
+        }
+
+        let mut devices = self.recipient_devices(&account, &identity, &to).await?;
+        devices.extend(
+            self.recipient_devices(&account, &identity, &account)
+                .await?,
+        );
+        self.fetch_missing_bundles(&mut sub, &account, &identity, &devices)
+            .await?;
+
+        let envelope = {
+            let _guard = self.state_lock.lock().await;
+            self.encrypt_for(&account, &identity, &devices, body)
+                .await?
+        };
+        let contact_devices: Vec<u32> = devices
+            .iter()
+            .filter(|(jid, _)| *jid == to)
+            .map(|(_, device_id)| *device_id)
+            .collect();
+        if !envelope
+            .keys
+            .iter()
+            .any(|key| contact_devices.contains(&key.recipient_device_id))
+        {
+            return Err(OmemoError::NoRecipients(to));
+        }
+
+        let message = ChatMessage {
+            id: Uuid::new_v4().to_string(),
+            from: account,
+            to: to.clone(),
+            body: body.to_string(),
+            timestamp: Utc::now(),
+            message_type: MessageType::Chat,
+            thread: None,
+            embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
+        };
+        self.event_bus
+            .publish(Event::new(
+                Channel::new(channels::UI_OMEMO_MESSAGE_SEND).unwrap(),
+                EventSource::System("omemo".into()),
+                EventPayload::OmemoMessageSendRequested {
+                    to,
+                    id: message.id.clone(),
+                    envelope,
+                },
+            ))
+            .map_err(|e| OmemoError::EventBus(e.to_string()))?;
+        self.publish(
+            channels::XMPP_MESSAGE_SENT,
+            EventPayload::MessageSent {
+                message: message.clone(),
+            },
+        );
+        Ok(message)
+    }
+
+    async fn fetch_device_list(
+        &self,
+        sub: &mut EventSubscription,
+        account: &str,
+        identity: &LocalIdentity,
+        jid: &str,
+    ) -> Result<(), OmemoError> {
+        self.publish(
+            channels::UI_OMEMO_DEVICELIST_FETCH,
+            EventPayload::OmemoDeviceListFetchRequested {
+                jid: jid.to_string(),
+            },
+        );
+        let deadline = tokio::time::Instant::now() + FETCH_TIMEOUT;
+        while let Ok(received) = tokio::time::timeout_at(deadline, sub.recv()).await {
+            let event = received.map_err(|e| OmemoError::EventBus(e.to_string()))?;
+            if let EventPayload::OmemoDeviceListReceived {
+                jid: list_jid,
+                devices,
+            } = &event.payload
+                && pep_jid(account, list_jid) == jid
+            {
+                return self
+                    .process_device_list(account, identity, jid, devices)
+                    .await;
+            }
+        }
+        debug!(jid, "no OMEMO device list received");
+        Ok(())
+    }
+
+    async fn fetch_missing_bundles(
+        &self,
+        sub: &mut EventSubscription,
+        account: &str,
+        identity: &LocalIdentity,
+        devices: &[(String, u32)],
+    ) -> Result<(), OmemoError> {
+        let mut missing = Vec::new();
+        for (jid, device_id) in devices {
+            if !self.store.session_exists(account, jid, *device_id).await? {
+                self.publish(
+                    channels::UI_OMEMO_BUNDLE_FETCH,
+                    EventPayload::OmemoBundleFetchRequested {
+                        jid: jid.clone(),
+                        device_id: *device_id,
+                    },
+                );
+                missing.push((jid.clone(), *device_id));
+            }
+        }
+
+        let deadline = tokio::time::Instant::now() + FETCH_TIMEOUT;
+        while !missing.is_empty() {
+            let Ok(received) = tokio::time::timeout_at(deadline, sub.recv()).await else {
+                debug!(count = missing.len(), "OMEMO bundles not received in time");
+                break;
+            };
+            let event = received.map_err(|e| OmemoError::EventBus(e.to_string()))?;
+            if let EventPayload::OmemoBundleReceived {
+                jid,
+                device_id,
+                bundle,
+            } = &event.payload
+            {
+                let jid = pep_jid(account, jid);
+                let Some(position) = missing
+                    .iter()
+                    .position(|(missing_jid, id)| *missing_jid == jid && id == device_id)
+                else {
+                    continue;
+                };
+                missing.remove(position);
+                let _guard = self.state_lock.lock().await;
+                if let Err(e) = self
+                    .process_bundle(account, identity, &jid, *device_id, bundle)
+                    .await
+                {
+                    warn!(jid = %jid, device_id, error = %e, "rejected OMEMO bundle");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Make this device known: publish its bundle, then add it to our device
+    /// list. The list is fetched first so other devices are kept; if PEP has
+    /// no list yet the fetch goes unanswered and a new one is published.
+    async fn announce_device(&self, account: &str) -> Result<(), OmemoError> {
+        let identity = self.identity(account).await?;
+        self.publish(
+            channels::UI_OMEMO_BUNDLE_PUBLISH,
+            EventPayload::OmemoBundlePublishRequested {
+                device_id: identity.device_id,
+                bundle: self.bundle(account, &identity).await?,
+            },
+        );
+
+        let mut sub = self
+            .event_bus
+            .subscribe("xmpp.omemo.**")
+            .map_err(|e| OmemoError::EventBus(e.to_string()))?;
+        self.fetch_device_list(&mut sub, account, &identity, account)
+            .await?;
+        if self.store.devices(account, account).await?.is_empty() {
+            self.publish(
+                channels::UI_OMEMO_DEVICELIST_PUBLISH,
+                EventPayload::OmemoDeviceListPublishRequested {
+                    devices: vec![identity.device_id],
+                },
+            );
+        }
+        Ok(())
+    }
+
+    async fn process_device_list(
+        &self,
+        account: &str,
+        identity: &LocalIdentity,
+        jid: &str,
+        devices: &[u32],
+    ) -> Result<(), OmemoError> {
+        self.store.set_device_list(account, jid, devices).await?;
+        if jid == account && !devices.contains(&identity.device_id) {
+            let mut devices = devices.to_vec();
+            devices.push(identity.device_id);
+            info!(
+                device_id = identity.device_id,
+                "adding this device to the OMEMO device list"
+            );
+            self.publish(
+                channels::UI_OMEMO_DEVICELIST_PUBLISH,
+                EventPayload::OmemoDeviceListPublishRequested { devices },
+            );
+        }
+        Ok(())
+    }
+
+    async fn handle_encrypted_message(
+        &self,
+        id: &str,
+        from: &str,
+        to: &str,
+        message_type: &MessageType,
+        envelope: &OmemoEnvelope,
+    ) -> Result<(), OmemoError> {
+        let account = self.account()?;
+        let identity = self.identity(&account).await?;
+        let from = bare_jid(from);
+
+        let body = {
+            let _guard = self.state_lock.lock().await;
+            let (body, consumed_pre_key) = self
+                .decrypt_envelope(&account, &identity, &from, envelope)
+                .await?;
+            if let Some(pre_key_id) = consumed_pre_key {
+                let identity = self.replace_pre_key(&account, identity, pre_key_id).await?;
+                self.publish(
+                    channels::UI_OMEMO_BUNDLE_PUBLISH,
+                    EventPayload::OmemoBundlePublishRequested {
+                        device_id: identity.device_id,
+                        bundle: self.bundle(&account, &identity).await?,
+                    },
+                );
+            }
+            body
+        };
+
+        let Some(body) = body else {
+            debug!(from = %from, "OMEMO key transport message processed");
+            return Ok(());
+        };
+        self.publish(
+            channels::XMPP_MESSAGE_RECEIVED,
+            EventPayload::MessageReceived {
+                message: ChatMessage {
+                    id: id.to_string(),
+                    from,
+                    to: bare_jid(to),
+                    body,
+                    timestamp: Utc::now(),
+                    message_type: message_type.clone(),
+                    thread: None,
+                    embeds: vec![],
+                    origin_id: None,
+                    stanza_id: None,
+                    retracted_at: None,
+                    lang: None,
+                    translation: None,
+                },
+            },
+        );
+        Ok(())
+    }
+
+    pub async fn handle_event(&self, event: &Event) {
+        match &event.payload {
+            EventPayload::ConnectionEstablished { jid } => {
+                let account = bare_jid(jid);
+                *self.own_jid.write().unwrap() = Some(account.clone());
+                if let Err(e) = self.announce_device(&account).await {
+                    error!(error = %e, "failed to announce OMEMO device");
+                }
+            }
+            EventPayload::OmemoDeviceListReceived { jid, devices } => {
+                let result = async {
+                    let account = self.account()?;
+                    let identity = self.identity(&account).await?;
+                    self.process_device_list(&account, &identity, &pep_jid(&account, jid), devices)
+                        .await
+                };
+                if let Err(e) = result.await {
+                    warn!(jid = %jid, error = %e, "failed to store OMEMO device list");
+                }
+            }
+            EventPayload::OmemoBundleReceived {
+                jid,
+                device_id,
+                bundle,
+            } => {
+                let result = async {
+                    let account = self.account()?;
+                    let identity = self.identity(&account).await?;
+                    let _guard = self.state_lock.lock().await;
+                    self.process_bundle(
+                        &account,
+                        &identity,
+                        &pep_jid(&account, jid),
+                        *device_id,
+                        bundle,
+                    )
+                    .await
+                };
+                if let Err(e) = result.await {
+                    warn!(jid = %jid, device_id, error = %e, "rejected OMEMO bundle");
+                }
+            }
+            EventPayload::OmemoMessageReceived {
+                id,
+                from,
+                to,
+                message_type,
+                envelope,
+            } => {
+                if let Err(e) = self
+                    .handle_encrypted_message(id, from, to, message_type, envelope)
+                    .await
+                {
+                    warn!(from = %from, id = %id, error = %e, "undecryptable OMEMO message");
+                    self.publish(
+                        channels::SYSTEM_OMEMO_MESSAGE_UNDECRYPTABLE,
+                        EventPayload::OmemoMessageUndecryptable {
+                            id: id.clone(),
+                            from: bare_jid(from),
+                            sender_device_id: envelope.sender_device_id,
+                            reason: e.to_string(),
+                        },
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn publish(&self, channel: &str, payload: EventPayload) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channel).unwrap(),
+            EventSource::System("omemo".into()),
+            payload,
+        ));
+    }
+
+    pub async fn run(self: Arc<Self>) -> Result<(), OmemoError> {
+        let mut sub = self
+            .event_bus
+            .subscribe("{system,xmpp}.**")
+            .map_err(|e| OmemoError::EventBus(e.to_string()))?;
+
+        loop {
+            match sub.recv().await {
+                Ok(event) => {
+                    self.handle_event(&event).await;
+                }
+                Err(waddle_core::error::EventBusError::ChannelClosed) => {
+                    debug!("event bus closed, OMEMO manager stopping");
+                    return Ok(());
+                }
+                Err(waddle_core::error::EventBusError::Lagged(count)) => {
+                    warn!(count, "OMEMO manager lagged, some events dropped");
+                }
+                Err(e) => {
+                    error!(error = %e, "OMEMO manager subscription error");
+                    return Err(OmemoError::EventBus(e.to_string()));
+                }
+            }
+        }
+    }
+}
+
+fn ensure_trusted(jid: &str, device_id: u32, trust: OmemoTrust) -> Result<(), OmemoError> {
+    if trust == OmemoTrust::Untrusted {
+        return Err(OmemoError::Untrusted {
+            jid: jid.to_string(),
+            device_id,
+        });
+    }
+    Ok(())
+}
+
+fn remote_bundle(bundle: &OmemoBundle) -> Result<RemoteBundle, OmemoError> {
+    Ok(RemoteBundle {
+        identity_key: deserialize_public(&bundle.identity_key)?,
+        signed_pre_key_id: bundle.signed_pre_key_id,
+        signed_pre_key: deserialize_public(&bundle.signed_pre_key)?,
+        signed_pre_key_signature: bundle.signed_pre_key_signature.clone(),
+        pre_keys: bundle
+            .pre_keys
+            .iter()
+            .filter_map(|pre_key| {
+                deserialize_public(&pre_key.public_key)
+                    .ok()
+                    .map(|key| (pre_key.id, key))
+            })
+            .collect(),
+    })
+}
+
+/// The owner of a PEP item; servers may omit `from` on our own nodes.
+#[cfg(feature = "native")]
+fn pep_jid(account: &str, jid: &str) -> String {
+    if jid.is_empty() {
+        account.to_string()
+    } else {
+        bare_jid(jid)
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use waddle_core::event::BroadcastEventBus;
+
+    struct Peer<D: Database> {
+        manager: OmemoManager<D>,
+        event_bus: Arc<dyn EventBus>,
+        _dir: TempDir,
+    }
+
+    async fn peer(jid: &str) -> Peer<impl Database> {
+        let dir = TempDir::new().expect("failed to create temp dir");
+        let db = waddle_storage::open_database(&dir.path().join("test.db"))
+            .await
+            .expect("failed to open database");
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let manager = OmemoManager::new(Arc::new(db), event_bus.clone(), [7u8; 32]);
+        *manager.own_jid.write().unwrap() = Some(jid.to_string());
+        Peer {
+            manager,
+            event_bus,
+            _dir: dir,
+        }
+    }
+
+    fn event(channel: &str, payload: EventPayload) -> Event {
+        Event::new(Channel::new(channel).unwrap(), EventSource::Xmpp, payload)
+    }
+
+    /// Give `from` the device list and bundle of `to`, as PEP would.
+    async fn introduce(from: &Peer<impl Database>, to: &Peer<impl Database>, to_jid: &str) {
+        let account = to.manager.account().unwrap();
+        let identity = to.manager.identity(&account).await.unwrap();
+        let bundle = to.manager.bundle(&account, &identity).await.unwrap();
+        from.manager
+            .handle_event(&event(
+                "xmpp.omemo.devicelist.received",
+                EventPayload::OmemoDeviceListReceived {
+                    jid: to_jid.to_string(),
+                    devices: vec![identity.device_id],
+                },
+            ))
+            .await;
+        from.manager
+            .handle_event(&event(
+                "xmpp.omemo.bundle.received",
+                EventPayload::OmemoBundleReceived {
+                    jid: to_jid.to_string(),
+                    device_id: identity.device_id,
+                    bundle,
+                },
+            ))
+            .await;
+    }
+
+    /// Send `body` from `from` and deliver the resulting stanza to `to`.
+    async fn deliver(
+        from: &Peer<impl Database>,
+        to: &Peer<impl Database>,
+        to_jid: &str,
+        body: &str,
+    ) -> Event {
+        let mut outbox = from.event_bus.subscribe("ui.omemo.message.send").unwrap();
+        let mut inbox = to.event_bus.subscribe("{system,xmpp}.**").unwrap();
+        from.manager.send_message(to_jid, body).await.unwrap();
+
+        let EventPayload::OmemoMessageSendRequested { id, envelope, .. } =
+            outbox.recv().await.unwrap().payload
+        else {
+            panic!("expected an OMEMO send request");
+        };
+        to.manager
+            .handle_event(&event(
+                "xmpp.omemo.message.received",
+                EventPayload::OmemoMessageReceived {
+                    id,
+                    from: format!("{}/phone", from.manager.account().unwrap()),
+                    to: to_jid.to_string(),
+                    message_type: MessageType::Chat,
+                    envelope,
+                },
+            ))
+            .await;
+        loop {
+            let received = inbox.recv().await.unwrap();
+            if matches!(
+                received.payload,
+                EventPayload::MessageReceived { .. }
+                    | EventPayload::OmemoMessageUndecryptable { .. }
+            ) {
+                return received;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_decrypt_in_both_directions() {
+        let alice = peer("alice@example.com").await;
+        let bob = peer("bob@example.com").await;
+        introduce(&alice, &bob, "bob@example.com").await;
+
+        let received = deliver(&alice, &bob, "bob@example.com", "hello bob").await;
+        let EventPayload::MessageReceived { message } = received.payload else {
+            panic!("expected MessageReceived, got {:?}", received.payload);
+        };
+        assert_eq!(message.body, "hello bob");
+        assert_eq!(message.from, "alice@example.com");
+
+        // Bob replies over the session Alice's pre-key message created.
+        let received = deliver(&bob, &alice, "alice@example.com", "hi alice").await;
+        let EventPayload::MessageReceived { message } = received.payload else {
+            panic!("expected MessageReceived, got {:?}", received.payload);
+        };
+        assert_eq!(message.body, "hi alice");
+    }
+
+    #[tokio::test]
+    async fn consumed_pre_key_is_replaced() {
+        let alice = peer("alice@example.com").await;
+        let bob = peer("bob@example.com").await;
+        introduce(&alice, &bob, "bob@example.com").await;
+
+        deliver(&alice, &bob, "bob@example.com", "hello").await;
+        let pre_keys = bob.manager.store.pre_keys("bob@example.com").await.unwrap();
+        assert_eq!(pre_keys.len(), PRE_KEY_COUNT as usize);
+        assert!(pre_keys.iter().any(|(id, _)| *id == PRE_KEY_COUNT + 1));
+    }
+
+    #[tokio::test]
+    async fn message_for_another_device_is_reported_undecryptable() {
+        let alice = peer("alice@example.com").await;
+        let bob = peer("bob@example.com").await;
+        let carol = peer("bob@example.com").await;
+        introduce(&alice, &carol, "bob@example.com").await;
+
+        let received = deliver(&alice, &bob, "bob@example.com", "hello").await;
+        assert_eq!(
+            received.channel.as_str(),
+            "system.omemo.message.undecryptable"
+        );
+        let EventPayload::OmemoMessageUndecryptable { from, reason, .. } = received.payload else {
+            panic!("expected OmemoMessageUndecryptable");
+        };
+        assert_eq!(from, "alice@example.com");
+        assert_eq!(reason, OmemoError::NotForThisDevice.to_string());
+    }
+
+    #[tokio::test]
+    async fn trust_is_tracked_per_device_and_reset_on_new_identity() {
+        let alice = peer("alice@example.com").await;
+        let bob = peer("bob@example.com").await;
+        introduce(&alice, &bob, "bob@example.com").await;
+        let bob_device = bob.manager.own_device_id().await.unwrap();
+
+        alice
+            .manager
+            .trust_device("bob@example.com", bob_device, OmemoTrust::Trusted)
+            .await
+            .unwrap();
+        let devices = alice.manager.list_devices("bob@example.com").await.unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].trust, OmemoTrust::Trusted);
+        assert_eq!(
+            devices[0].fingerprint.as_deref(),
+            Some(bob.manager.own_fingerprint().await.unwrap().as_str())
+        );
+        assert!(devices[0].has_session);
+
+        // The same device ID shows up with a different identity key.
+        let mut bundle = bob
+            .manager
+            .bundle(
+                "bob@example.com",
+                &bob.manager.identity("bob@example.com").await.unwrap(),
+            )
+            .await
+            .unwrap();
+        let impostor = KeyPair::generate();
+        bundle.identity_key = impostor.serialized_public();
+        bundle.signed_pre_key_signature = impostor.sign(&bundle.signed_pre_key).to_vec();
+        alice
+            .manager
+            .handle_event(&event(
+                "xmpp.omemo.bundle.received",
+                EventPayload::OmemoBundleReceived {
+                    jid: "bob@example.com".to_string(),
+                    device_id: bob_device,
+                    bundle,
+                },
+            ))
+            .await;
+        let devices = alice.manager.list_devices("bob@example.com").await.unwrap();
+        assert_eq!(devices[0].trust, OmemoTrust::Undecided);
+        assert_eq!(
+            devices[0].fingerprint,
+            Some(fingerprint(&impostor.public_key()))
+        );
+
+        assert!(matches!(
+            alice
+                .manager
+                .trust_device("bob@example.com", 42, OmemoTrust::Trusted)
+                .await,
+            Err(OmemoError::UnknownDevice { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn untrusted_devices_are_not_encrypted_for() {
+        let alice = peer("alice@example.com").await;
+        let bob = peer("bob@example.com").await;
+        introduce(&alice, &bob, "bob@example.com").await;
+        let bob_device = bob.manager.own_device_id().await.unwrap();
+        alice
+            .manager
+            .trust_device("bob@example.com", bob_device, OmemoTrust::Untrusted)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            alice.manager.send_message("bob@example.com", "hi").await,
+            Err(OmemoError::NoRecipients(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn own_device_list_without_this_device_is_extended() {
+        let alice = peer("alice@example.com").await;
+        let own_device = alice.manager.own_device_id().await.unwrap();
+        let mut sub = alice.event_bus.subscribe("ui.omemo.**").unwrap();
+
+        alice
+            .manager
+            .handle_event(&event(
+                "xmpp.omemo.devicelist.received",
+                EventPayload::OmemoDeviceListReceived {
+                    jid: "alice@example.com".to_string(),
+                    devices: vec![1111],
+                },
+            ))
+            .await;
+
+        let EventPayload::OmemoDeviceListPublishRequested { devices } =
+            sub.recv().await.unwrap().payload
+        else {
+            panic!("expected a device list publish request");
+        };
+        assert_eq!(devices, vec![1111, own_device]);
+    }
+}
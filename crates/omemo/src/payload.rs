@@ -0,0 +1,93 @@
+//! Message payload encryption for OMEMO 0.3 (`eu.siacs.conversations.axolotl`).
+//!
+//! The body is sealed with AES-128-GCM under a fresh key. The key and the
+//! GCM tag are then encrypted for each recipient device through its Signal
+//! session, so the `<payload/>` carries the ciphertext without its tag.
+
+use aes::Aes128;
+use aes_gcm::aead::consts::{U12, U16};
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{AesGcm, Nonce};
+use rand_core::{OsRng, RngCore};
+
+use crate::OmemoError;
+
+const KEY_LEN: usize = 16;
+const TAG_LEN: usize = 16;
+const IV_LEN: usize = 12;
+
+pub struct SealedPayload {
+    pub iv: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    /// Key and tag, to be encrypted for each recipient device.
+    pub key_material: Vec<u8>,
+}
+
+pub fn seal(plaintext: &[u8]) -> SealedPayload {
+    let mut key = [0u8; KEY_LEN];
+    let mut iv = [0u8; IV_LEN];
+    OsRng.fill_bytes(&mut key);
+    OsRng.fill_bytes(&mut iv);
+
+    let mut ciphertext = AesGcm::<Aes128, U12>::new(&key.into())
+        .encrypt(Nonce::<U12>::from_slice(&iv), plaintext)
+        .expect("AES-GCM encryption of an in-memory buffer cannot fail");
+    let tag = ciphertext.split_off(ciphertext.len() - TAG_LEN);
+
+    let mut key_material = key.to_vec();
+    key_material.extend_from_slice(&tag);
+    SealedPayload {
+        iv: iv.to_vec(),
+        ciphertext,
+        key_material,
+    }
+}
+
+/// Open a payload with key material decrypted from the sender's session.
+/// Older clients sent a bare 16-byte key with the tag left on the payload,
+/// and some use 16-byte IVs; both are accepted.
+pub fn open(key_material: &[u8], iv: &[u8], payload: &[u8]) -> Result<Vec<u8>, OmemoError> {
+    if key_material.len() < KEY_LEN {
+        return Err(OmemoError::MalformedMessage(
+            "payload key is too short".to_string(),
+        ));
+    }
+    let (key, tag) = key_material.split_at(KEY_LEN);
+    let mut sealed = payload.to_vec();
+    sealed.extend_from_slice(tag);
+
+    let opened = match iv.len() {
+        12 => AesGcm::<Aes128, U12>::new(key.into())
+            .decrypt(Nonce::<U12>::from_slice(iv), &sealed[..]),
+        16 => AesGcm::<Aes128, U16>::new(key.into())
+            .decrypt(Nonce::<U16>::from_slice(iv), &sealed[..]),
+        len => {
+            return Err(OmemoError::MalformedMessage(format!(
+                "unsupported IV length {len}"
+            )));
+        }
+    };
+    opened.map_err(|_| OmemoError::BadMac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_payload_opens_with_its_key_material() {
+        let sealed = seal("héllo 🐧".as_bytes());
+        assert_eq!(sealed.key_material.len(), KEY_LEN + TAG_LEN);
+        assert_eq!(
+            open(&sealed.key_material, &sealed.iv, &sealed.ciphertext).unwrap(),
+            "héllo 🐧".as_bytes()
+        );
+
+        let mut wrong_tag = sealed.key_material.clone();
+        wrong_tag[KEY_LEN] ^= 1;
+        assert!(matches!(
+            open(&wrong_tag, &sealed.iv, &sealed.ciphertext),
+            Err(OmemoError::BadMac)
+        ));
+    }
+}
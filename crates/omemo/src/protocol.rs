@@ -0,0 +1,339 @@
+//! libsignal v3 wire messages: `SignalMessage` and `PreKeySignalMessage`,
+//! encoded as the version byte followed by a protobuf body.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::OmemoError;
+use crate::keys::{deserialize_public, serialize_public};
+
+/// Current and minimum supported version, packed into one byte.
+const VERSION_BYTE: u8 = (3 << 4) | 3;
+const MAC_LEN: usize = 8;
+
+const WIRE_VARINT: u8 = 0;
+const WIRE_LEN: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignalMessage {
+    pub ratchet_key: [u8; 32],
+    pub counter: u32,
+    pub previous_counter: u32,
+    pub ciphertext: Vec<u8>,
+}
+
+impl SignalMessage {
+    /// Serialize, appending a MAC over both identities and the message.
+    pub fn encode(
+        &self,
+        mac_key: &[u8; 32],
+        sender_identity: &[u8; 32],
+        receiver_identity: &[u8; 32],
+    ) -> Vec<u8> {
+        let mut encoded = vec![VERSION_BYTE];
+        put_bytes(&mut encoded, 1, &serialize_public(&self.ratchet_key));
+        put_uint(&mut encoded, 2, self.counter);
+        put_uint(&mut encoded, 3, self.previous_counter);
+        put_bytes(&mut encoded, 4, &self.ciphertext);
+        let mac = compute_mac(mac_key, sender_identity, receiver_identity, &encoded);
+        encoded.extend_from_slice(&mac);
+        encoded
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, OmemoError> {
+        if bytes.len() < 1 + MAC_LEN {
+            return Err(malformed("signal message is truncated"));
+        }
+        check_version(bytes[0])?;
+
+        let mut ratchet_key = None;
+        let mut counter = None;
+        let mut previous_counter = 0;
+        let mut ciphertext = None;
+        for field in Fields::new(&bytes[1..bytes.len() - MAC_LEN]) {
+            match field? {
+                (1, Value::Bytes(value)) => ratchet_key = Some(deserialize_public(value)?),
+                (2, Value::Uint(value)) => counter = Some(value),
+                (3, Value::Uint(value)) => previous_counter = value,
+                (4, Value::Bytes(value)) => ciphertext = Some(value.to_vec()),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            ratchet_key: ratchet_key.ok_or_else(|| malformed("missing ratchet key"))?,
+            counter: counter.ok_or_else(|| malformed("missing counter"))?,
+            previous_counter,
+            ciphertext: ciphertext.ok_or_else(|| malformed("missing ciphertext"))?,
+        })
+    }
+
+    /// Check the MAC of a serialized message in constant time.
+    pub fn verify_mac(
+        bytes: &[u8],
+        mac_key: &[u8; 32],
+        sender_identity: &[u8; 32],
+        receiver_identity: &[u8; 32],
+    ) -> bool {
+        if bytes.len() < 1 + MAC_LEN {
+            return false;
+        }
+        let (body, mac) = bytes.split_at(bytes.len() - MAC_LEN);
+        mac_for(mac_key, sender_identity, receiver_identity, body)
+            .verify_truncated_left(mac)
+            .is_ok()
+    }
+}
+
+/// First message of a session, carrying what the receiver needs to run the
+/// X3DH agreement before decrypting the inner [`SignalMessage`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreKeySignalMessage {
+    pub registration_id: u32,
+    pub pre_key_id: Option<u32>,
+    pub signed_pre_key_id: u32,
+    pub base_key: [u8; 32],
+    pub identity_key: [u8; 32],
+    pub message: Vec<u8>,
+}
+
+impl PreKeySignalMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = vec![VERSION_BYTE];
+        put_uint(&mut encoded, 5, self.registration_id);
+        if let Some(pre_key_id) = self.pre_key_id {
+            put_uint(&mut encoded, 1, pre_key_id);
+        }
+        put_uint(&mut encoded, 6, self.signed_pre_key_id);
+        put_bytes(&mut encoded, 2, &serialize_public(&self.base_key));
+        put_bytes(&mut encoded, 3, &serialize_public(&self.identity_key));
+        put_bytes(&mut encoded, 4, &self.message);
+        encoded
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, OmemoError> {
+        let (&version, body) = bytes
+            .split_first()
+            .ok_or_else(|| malformed("pre-key message is empty"))?;
+        check_version(version)?;
+
+        let mut registration_id = 0;
+        let mut pre_key_id = None;
+        let mut signed_pre_key_id = None;
+        let mut base_key = None;
+        let mut identity_key = None;
+        let mut message = None;
+        for field in Fields::new(body) {
+            match field? {
+                (5, Value::Uint(value)) => registration_id = value,
+                (1, Value::Uint(value)) => pre_key_id = Some(value),
+                (6, Value::Uint(value)) => signed_pre_key_id = Some(value),
+                (2, Value::Bytes(value)) => base_key = Some(deserialize_public(value)?),
+                (3, Value::Bytes(value)) => identity_key = Some(deserialize_public(value)?),
+                (4, Value::Bytes(value)) => message = Some(value.to_vec()),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            registration_id,
+            pre_key_id,
+            signed_pre_key_id: signed_pre_key_id
+                .ok_or_else(|| malformed("missing signed pre-key id"))?,
+            base_key: base_key.ok_or_else(|| malformed("missing base key"))?,
+            identity_key: identity_key.ok_or_else(|| malformed("missing identity key"))?,
+            message: message.ok_or_else(|| malformed("missing inner message"))?,
+        })
+    }
+}
+
+fn compute_mac(
+    mac_key: &[u8; 32],
+    sender_identity: &[u8; 32],
+    receiver_identity: &[u8; 32],
+    body: &[u8],
+) -> [u8; MAC_LEN] {
+    let full = mac_for(mac_key, sender_identity, receiver_identity, body)
+        .finalize()
+        .into_bytes();
+    let mut mac = [0u8; MAC_LEN];
+    mac.copy_from_slice(&full[..MAC_LEN]);
+    mac
+}
+
+fn mac_for(
+    mac_key: &[u8; 32],
+    sender_identity: &[u8; 32],
+    receiver_identity: &[u8; 32],
+    body: &[u8],
+) -> Hmac<Sha256> {
+    let mut hmac = Hmac::<Sha256>::new_from_slice(mac_key).expect("HMAC accepts any key length");
+    hmac.update(&serialize_public(sender_identity));
+    hmac.update(&serialize_public(receiver_identity));
+    hmac.update(body);
+    hmac
+}
+
+fn check_version(byte: u8) -> Result<(), OmemoError> {
+    if byte >> 4 == VERSION_BYTE >> 4 {
+        Ok(())
+    } else {
+        Err(malformed(&format!(
+            "unsupported message version {}",
+            byte >> 4
+        )))
+    }
+}
+
+fn malformed(reason: &str) -> OmemoError {
+    OmemoError::MalformedMessage(reason.to_string())
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_uint(out: &mut Vec<u8>, field: u8, value: u32) {
+    out.push((field << 3) | WIRE_VARINT);
+    put_varint(out, u64::from(value));
+}
+
+fn put_bytes(out: &mut Vec<u8>, field: u8, value: &[u8]) {
+    out.push((field << 3) | WIRE_LEN);
+    put_varint(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+enum Value<'a> {
+    Uint(u32),
+    Bytes(&'a [u8]),
+}
+
+/// Iterator over the fields of a protobuf message. Only the varint and
+/// length-delimited wire types occur in these messages.
+struct Fields<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn varint(&mut self) -> Result<u64, OmemoError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self
+                .bytes
+                .split_first()
+                .ok_or_else(|| malformed("truncated varint"))?;
+            self.bytes = rest;
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(malformed("varint is too long"))
+    }
+
+    fn field(&mut self) -> Result<(u64, Value<'a>), OmemoError> {
+        let key = self.varint()?;
+        let value = match (key & 0x07) as u8 {
+            WIRE_VARINT => Value::Uint(
+                u32::try_from(self.varint()?).map_err(|_| malformed("integer out of range"))?,
+            ),
+            WIRE_LEN => {
+                let len = usize::try_from(self.varint()?)
+                    .ok()
+                    .filter(|len| *len <= self.bytes.len())
+                    .ok_or_else(|| malformed("field length exceeds message"))?;
+                let (value, rest) = self.bytes.split_at(len);
+                self.bytes = rest;
+                Value::Bytes(value)
+            }
+            other => return Err(malformed(&format!("unsupported wire type {other}"))),
+        };
+        Ok((key >> 3, value))
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u64, Value<'a>), OmemoError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        let field = self.field();
+        if field.is_err() {
+            self.bytes = &[];
+        }
+        Some(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::KeyPair;
+
+    #[test]
+    fn signal_message_round_trips_and_authenticates() {
+        let sender = KeyPair::generate().public_key();
+        let receiver = KeyPair::generate().public_key();
+        let mac_key = [7u8; 32];
+        let message = SignalMessage {
+            ratchet_key: KeyPair::generate().public_key(),
+            counter: 300,
+            previous_counter: 2,
+            ciphertext: vec![1, 2, 3, 4],
+        };
+
+        let encoded = message.encode(&mac_key, &sender, &receiver);
+        assert_eq!(SignalMessage::decode(&encoded).unwrap(), message);
+        assert!(SignalMessage::verify_mac(
+            &encoded, &mac_key, &sender, &receiver
+        ));
+        assert!(!SignalMessage::verify_mac(
+            &encoded, &mac_key, &receiver, &sender
+        ));
+    }
+
+    #[test]
+    fn pre_key_message_round_trips_without_one_time_pre_key() {
+        let message = PreKeySignalMessage {
+            registration_id: 1234,
+            pre_key_id: None,
+            signed_pre_key_id: 1,
+            base_key: KeyPair::generate().public_key(),
+            identity_key: KeyPair::generate().public_key(),
+            message: vec![0x33, 1, 2],
+        };
+        assert_eq!(
+            PreKeySignalMessage::decode(&message.encode()).unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_fields() {
+        let mut encoded = PreKeySignalMessage {
+            registration_id: 1,
+            pre_key_id: Some(4),
+            signed_pre_key_id: 1,
+            base_key: [9; 32],
+            identity_key: [9; 32],
+            message: vec![0; 40],
+        }
+        .encode();
+        encoded.truncate(encoded.len() - 10);
+        assert!(matches!(
+            PreKeySignalMessage::decode(&encoded),
+            Err(OmemoError::MalformedMessage(_))
+        ));
+    }
+}
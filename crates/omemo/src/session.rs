@@ -0,0 +1,488 @@
+//! Signal sessions: X3DH agreement from a published bundle and the Double
+//! Ratchet, following libsignal so sessions interoperate with other OMEMO
+//! clients.
+
+use aes::Aes256;
+use cbc::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit, block_padding::Pkcs7};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::OmemoError;
+use crate::keys::{KeyPair, verify_signature};
+use crate::protocol::{PreKeySignalMessage, SignalMessage};
+
+/// Receiving chains kept for out-of-order messages from earlier ratchet steps.
+const MAX_RECEIVER_CHAINS: usize = 5;
+/// Skipped message keys kept per receiving chain.
+const MAX_SKIPPED_KEYS: usize = 2000;
+/// How far ahead of the chain a single message may jump.
+const MAX_FORWARD_JUMP: u32 = 25_000;
+
+const MESSAGE_KEY_SEED: u8 = 0x01;
+const CHAIN_KEY_SEED: u8 = 0x02;
+
+/// A remote device's bundle with its keys decoded.
+#[derive(Debug, Clone)]
+pub struct RemoteBundle {
+    pub identity_key: [u8; 32],
+    pub signed_pre_key_id: u32,
+    pub signed_pre_key: [u8; 32],
+    pub signed_pre_key_signature: Vec<u8>,
+    pub pre_keys: Vec<(u32, [u8; 32])>,
+}
+
+/// Output of [`SessionState::encrypt`].
+pub struct EncryptedKey {
+    /// Whether `data` is a [`PreKeySignalMessage`].
+    pub prekey: bool,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ChainKey {
+    key: [u8; 32],
+    index: u32,
+}
+
+impl ChainKey {
+    fn seed(&self, constant: u8) -> [u8; 32] {
+        let mut hmac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        hmac.update(&[constant]);
+        hmac.finalize().into_bytes().into()
+    }
+
+    fn message_keys(&self) -> MessageKeys {
+        let mut okm = [0u8; 80];
+        Hkdf::<Sha256>::new(None, &self.seed(MESSAGE_KEY_SEED))
+            .expand(b"WhisperMessageKeys", &mut okm)
+            .expect("80 bytes is a valid HKDF output length");
+        MessageKeys {
+            counter: self.index,
+            cipher_key: okm[..32].try_into().expect("slice is 32 bytes"),
+            mac_key: okm[32..64].try_into().expect("slice is 32 bytes"),
+            iv: okm[64..].try_into().expect("slice is 16 bytes"),
+        }
+    }
+
+    fn next(&self) -> ChainKey {
+        ChainKey {
+            key: self.seed(CHAIN_KEY_SEED),
+            index: self.index + 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MessageKeys {
+    counter: u32,
+    cipher_key: [u8; 32],
+    mac_key: [u8; 32],
+    iv: [u8; 16],
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ReceiverChain {
+    ratchet_key: [u8; 32],
+    chain_key: ChainKey,
+    skipped: Vec<MessageKeys>,
+}
+
+/// Set while we have not heard back from the other side, so every message
+/// is sent as a pre-key message they can build the session from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PendingPreKey {
+    pre_key_id: Option<u32>,
+    signed_pre_key_id: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionState {
+    local_identity: [u8; 32],
+    remote_identity: [u8; 32],
+    local_registration_id: u32,
+    /// Base key of the X3DH agreement that created the session.
+    base_key: [u8; 32],
+    root_key: [u8; 32],
+    sender_ratchet: KeyPair,
+    sender_chain: ChainKey,
+    previous_counter: u32,
+    receiver_chains: Vec<ReceiverChain>,
+    pending_pre_key: Option<PendingPreKey>,
+}
+
+impl SessionState {
+    /// Start a session with a device from its bundle, as the initiator.
+    pub fn initiate(
+        local_identity: &KeyPair,
+        local_registration_id: u32,
+        bundle: &RemoteBundle,
+    ) -> Result<Self, OmemoError> {
+        let signed = crate::keys::serialize_public(&bundle.signed_pre_key);
+        if !verify_signature(
+            &bundle.identity_key,
+            &signed,
+            &bundle.signed_pre_key_signature,
+        ) {
+            return Err(OmemoError::InvalidSignature);
+        }
+
+        let one_time = match bundle.pre_keys.len() {
+            0 => None,
+            len => Some(bundle.pre_keys[(OsRng.next_u32() as usize) % len]),
+        };
+        let base_key = KeyPair::generate();
+
+        let mut secrets = vec![0xFFu8; 32];
+        secrets.extend_from_slice(&local_identity.agree(&bundle.signed_pre_key));
+        secrets.extend_from_slice(&base_key.agree(&bundle.identity_key));
+        secrets.extend_from_slice(&base_key.agree(&bundle.signed_pre_key));
+        if let Some((_, one_time_key)) = one_time {
+            secrets.extend_from_slice(&base_key.agree(&one_time_key));
+        }
+        let (root_key, receiver_chain_key) = derive_initial_keys(&secrets);
+
+        let sender_ratchet = KeyPair::generate();
+        let (root_key, sender_chain) =
+            create_chain(&root_key, &bundle.signed_pre_key, &sender_ratchet);
+
+        Ok(Self {
+            local_identity: local_identity.public_key(),
+            remote_identity: bundle.identity_key,
+            local_registration_id,
+            base_key: base_key.public_key(),
+            root_key,
+            sender_ratchet,
+            sender_chain,
+            previous_counter: 0,
+            receiver_chains: vec![ReceiverChain {
+                ratchet_key: bundle.signed_pre_key,
+                chain_key: receiver_chain_key,
+                skipped: Vec::new(),
+            }],
+            pending_pre_key: Some(PendingPreKey {
+                pre_key_id: one_time.map(|(id, _)| id),
+                signed_pre_key_id: bundle.signed_pre_key_id,
+            }),
+        })
+    }
+
+    /// Build the responder's side of a session from a pre-key message and
+    /// the local pre-keys it names.
+    pub fn respond(
+        local_identity: &KeyPair,
+        local_registration_id: u32,
+        signed_pre_key: &KeyPair,
+        one_time_pre_key: Option<&KeyPair>,
+        message: &PreKeySignalMessage,
+    ) -> Self {
+        let mut secrets = vec![0xFFu8; 32];
+        secrets.extend_from_slice(&signed_pre_key.agree(&message.identity_key));
+        secrets.extend_from_slice(&local_identity.agree(&message.base_key));
+        secrets.extend_from_slice(&signed_pre_key.agree(&message.base_key));
+        if let Some(one_time) = one_time_pre_key {
+            secrets.extend_from_slice(&one_time.agree(&message.base_key));
+        }
+        let (root_key, sender_chain) = derive_initial_keys(&secrets);
+
+        Self {
+            local_identity: local_identity.public_key(),
+            remote_identity: message.identity_key,
+            local_registration_id,
+            base_key: message.base_key,
+            root_key,
+            sender_ratchet: signed_pre_key.clone(),
+            sender_chain,
+            previous_counter: 0,
+            receiver_chains: Vec::new(),
+            pending_pre_key: None,
+        }
+    }
+
+    pub fn remote_identity(&self) -> [u8; 32] {
+        self.remote_identity
+    }
+
+    pub fn base_key(&self) -> [u8; 32] {
+        self.base_key
+    }
+
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> EncryptedKey {
+        let keys = self.sender_chain.message_keys();
+        let ciphertext = cbc::Encryptor::<Aes256>::new(&keys.cipher_key.into(), &keys.iv.into())
+            .encrypt_padded_vec_mut::<Pkcs7>(plaintext);
+        let message = SignalMessage {
+            ratchet_key: self.sender_ratchet.public_key(),
+            counter: self.sender_chain.index,
+            previous_counter: self.previous_counter,
+            ciphertext,
+        }
+        .encode(&keys.mac_key, &self.local_identity, &self.remote_identity);
+        self.sender_chain = self.sender_chain.next();
+
+        match &self.pending_pre_key {
+            Some(pending) => EncryptedKey {
+                prekey: true,
+                data: PreKeySignalMessage {
+                    registration_id: self.local_registration_id,
+                    pre_key_id: pending.pre_key_id,
+                    signed_pre_key_id: pending.signed_pre_key_id,
+                    base_key: self.base_key,
+                    identity_key: self.local_identity,
+                    message,
+                }
+                .encode(),
+            },
+            None => EncryptedKey {
+                prekey: false,
+                data: message,
+            },
+        }
+    }
+
+    /// Decrypt a serialized [`SignalMessage`]. The session is only changed
+    /// when decryption succeeds.
+    pub fn decrypt(&mut self, bytes: &[u8]) -> Result<Vec<u8>, OmemoError> {
+        let message = SignalMessage::decode(bytes)?;
+        let mut next = self.clone();
+        let keys = next.message_keys_for(&message)?;
+
+        if !SignalMessage::verify_mac(
+            bytes,
+            &keys.mac_key,
+            &next.remote_identity,
+            &next.local_identity,
+        ) {
+            return Err(OmemoError::BadMac);
+        }
+        let plaintext = cbc::Decryptor::<Aes256>::new(&keys.cipher_key.into(), &keys.iv.into())
+            .decrypt_padded_vec_mut::<Pkcs7>(&message.ciphertext)
+            .map_err(|_| OmemoError::MalformedMessage("invalid padding".to_string()))?;
+
+        next.pending_pre_key = None;
+        *self = next;
+        Ok(plaintext)
+    }
+
+    fn message_keys_for(&mut self, message: &SignalMessage) -> Result<MessageKeys, OmemoError> {
+        let position = match self
+            .receiver_chains
+            .iter()
+            .position(|chain| chain.ratchet_key == message.ratchet_key)
+        {
+            Some(position) => position,
+            None => self.ratchet_step(&message.ratchet_key),
+        };
+        let chain = &mut self.receiver_chains[position];
+
+        if chain.chain_key.index > message.counter {
+            let skipped = chain
+                .skipped
+                .iter()
+                .position(|keys| keys.counter == message.counter)
+                .ok_or(OmemoError::DuplicateMessage)?;
+            return Ok(chain.skipped.remove(skipped));
+        }
+        if message.counter - chain.chain_key.index > MAX_FORWARD_JUMP {
+            return Err(OmemoError::MalformedMessage(format!(
+                "message counter {} is too far ahead",
+                message.counter
+            )));
+        }
+
+        while chain.chain_key.index < message.counter {
+            chain.skipped.push(chain.chain_key.message_keys());
+            chain.chain_key = chain.chain_key.next();
+        }
+        if chain.skipped.len() > MAX_SKIPPED_KEYS {
+            let excess = chain.skipped.len() - MAX_SKIPPED_KEYS;
+            chain.skipped.drain(..excess);
+        }
+        let keys = chain.chain_key.message_keys();
+        chain.chain_key = chain.chain_key.next();
+        Ok(keys)
+    }
+
+    /// Advance the root chain for a new ratchet key from the other side and
+    /// return the index of the receiving chain it created.
+    fn ratchet_step(&mut self, their_ratchet_key: &[u8; 32]) -> usize {
+        let (root_key, receiver_chain) =
+            create_chain(&self.root_key, their_ratchet_key, &self.sender_ratchet);
+        let sender_ratchet = KeyPair::generate();
+        let (root_key, sender_chain) = create_chain(&root_key, their_ratchet_key, &sender_ratchet);
+
+        self.root_key = root_key;
+        self.previous_counter = self.sender_chain.index.saturating_sub(1);
+        self.sender_ratchet = sender_ratchet;
+        self.sender_chain = sender_chain;
+
+        self.receiver_chains.push(ReceiverChain {
+            ratchet_key: *their_ratchet_key,
+            chain_key: receiver_chain,
+            skipped: Vec::new(),
+        });
+        if self.receiver_chains.len() > MAX_RECEIVER_CHAINS {
+            self.receiver_chains.remove(0);
+        }
+        self.receiver_chains.len() - 1
+    }
+}
+
+fn derive_initial_keys(secrets: &[u8]) -> ([u8; 32], ChainKey) {
+    let mut okm = [0u8; 64];
+    Hkdf::<Sha256>::new(None, secrets)
+        .expand(b"WhisperText", &mut okm)
+        .expect("64 bytes is a valid HKDF output length");
+    split_root_and_chain(&okm)
+}
+
+fn create_chain(
+    root_key: &[u8; 32],
+    their_ratchet_key: &[u8; 32],
+    our_ratchet: &KeyPair,
+) -> ([u8; 32], ChainKey) {
+    let mut okm = [0u8; 64];
+    Hkdf::<Sha256>::new(Some(root_key), &our_ratchet.agree(their_ratchet_key))
+        .expand(b"WhisperRatchet", &mut okm)
+        .expect("64 bytes is a valid HKDF output length");
+    split_root_and_chain(&okm)
+}
+
+fn split_root_and_chain(okm: &[u8; 64]) -> ([u8; 32], ChainKey) {
+    (
+        okm[..32].try_into().expect("slice is 32 bytes"),
+        ChainKey {
+            key: okm[32..].try_into().expect("slice is 32 bytes"),
+            index: 0,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Device {
+        identity: KeyPair,
+        signed_pre_key: KeyPair,
+        pre_key: KeyPair,
+    }
+
+    impl Device {
+        fn new() -> Self {
+            Self {
+                identity: KeyPair::generate(),
+                signed_pre_key: KeyPair::generate(),
+                pre_key: KeyPair::generate(),
+            }
+        }
+
+        fn bundle(&self) -> RemoteBundle {
+            RemoteBundle {
+                identity_key: self.identity.public_key(),
+                signed_pre_key_id: 1,
+                signed_pre_key: self.signed_pre_key.public_key(),
+                signed_pre_key_signature: self
+                    .identity
+                    .sign(&self.signed_pre_key.serialized_public())
+                    .to_vec(),
+                pre_keys: vec![(7, self.pre_key.public_key())],
+            }
+        }
+
+        fn accept(&self, data: &[u8]) -> (SessionState, Vec<u8>) {
+            let message = PreKeySignalMessage::decode(data).unwrap();
+            assert_eq!(message.pre_key_id, Some(7));
+            let mut session = SessionState::respond(
+                &self.identity,
+                2,
+                &self.signed_pre_key,
+                Some(&self.pre_key),
+                &message,
+            );
+            let plaintext = session.decrypt(&message.message).unwrap();
+            (session, plaintext)
+        }
+    }
+
+    fn established() -> (SessionState, SessionState) {
+        let alice = Device::new();
+        let bob = Device::new();
+        let mut alice_session = SessionState::initiate(&alice.identity, 1, &bob.bundle()).unwrap();
+        let first = alice_session.encrypt(b"hello bob");
+        assert!(first.prekey);
+        let (bob_session, plaintext) = bob.accept(&first.data);
+        assert_eq!(plaintext, b"hello bob");
+        (alice_session, bob_session)
+    }
+
+    #[test]
+    fn rejects_bundle_with_forged_signature() {
+        let mut bundle = Device::new().bundle();
+        bundle.signed_pre_key = KeyPair::generate().public_key();
+        assert!(matches!(
+            SessionState::initiate(&KeyPair::generate(), 1, &bundle),
+            Err(OmemoError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn conversation_ratchets_in_both_directions() {
+        let (mut alice, mut bob) = established();
+
+        let reply = bob.encrypt(b"hi alice");
+        assert!(!reply.prekey);
+        assert_eq!(alice.decrypt(&reply.data).unwrap(), b"hi alice");
+
+        // Alice heard back, so she stops sending pre-key messages.
+        let next = alice.encrypt(b"how are you?");
+        assert!(!next.prekey);
+        assert_eq!(bob.decrypt(&next.data).unwrap(), b"how are you?");
+
+        for round in 0..3 {
+            let text = format!("round {round}");
+            let message = bob.encrypt(text.as_bytes());
+            assert_eq!(alice.decrypt(&message.data).unwrap(), text.as_bytes());
+        }
+    }
+
+    #[test]
+    fn decrypts_out_of_order_and_rejects_replays() {
+        let (mut alice, mut bob) = established();
+        let first = bob.encrypt(b"one");
+        let second = bob.encrypt(b"two");
+        let third = bob.encrypt(b"three");
+
+        assert_eq!(alice.decrypt(&third.data).unwrap(), b"three");
+        assert_eq!(alice.decrypt(&first.data).unwrap(), b"one");
+        assert_eq!(alice.decrypt(&second.data).unwrap(), b"two");
+        assert!(matches!(
+            alice.decrypt(&second.data),
+            Err(OmemoError::DuplicateMessage)
+        ));
+    }
+
+    #[test]
+    fn tampered_message_leaves_session_usable() {
+        let (mut alice, mut bob) = established();
+        let message = bob.encrypt(b"secret");
+        let mut tampered = message.data.clone();
+        let last = tampered.len() - 12;
+        tampered[last] ^= 0x01;
+
+        assert!(alice.decrypt(&tampered).is_err());
+        assert_eq!(alice.decrypt(&message.data).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn session_state_survives_serialization() {
+        let (alice, mut bob) = established();
+        let mut alice: SessionState =
+            serde_json::from_slice(&serde_json::to_vec(&alice).unwrap()).unwrap();
+        let message = bob.encrypt(b"persisted");
+        assert_eq!(alice.decrypt(&message.data).unwrap(), b"persisted");
+    }
+}
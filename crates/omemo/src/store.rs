@@ -0,0 +1,488 @@
+//! OMEMO state in waddle-storage. Private keys and session state are sealed
+//! with AES-256-GCM under a storage key kept outside the database, and each
+//! sealed value is bound to the row it was written to.
+
+use std::sync::Arc;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use chrono::Utc;
+use rand_core::{OsRng, RngCore};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use waddle_core::event::OmemoTrust;
+use waddle_storage::{Database, Row, SqlValue};
+
+use crate::OmemoError;
+use crate::keys::KeyPair;
+use crate::session::SessionState;
+
+const NONCE_LEN: usize = 12;
+
+pub(crate) struct LocalIdentity {
+    pub device_id: u32,
+    pub key_pair: KeyPair,
+    pub next_pre_key_id: u32,
+}
+
+pub(crate) struct SignedPreKey {
+    pub id: u32,
+    pub key_pair: KeyPair,
+    pub signature: Vec<u8>,
+}
+
+pub(crate) struct DeviceRecord {
+    pub device_id: u32,
+    pub identity_key: Option<[u8; 32]>,
+    pub trust: OmemoTrust,
+    pub active: bool,
+}
+
+pub(crate) struct KeyStore<D: Database> {
+    db: Arc<D>,
+    cipher: Aes256Gcm,
+}
+
+impl<D: Database> KeyStore<D> {
+    pub fn new(db: Arc<D>, storage_key: &[u8; 32]) -> Self {
+        Self {
+            db,
+            cipher: Aes256Gcm::new(storage_key.into()),
+        }
+    }
+
+    pub async fn identity(&self, account: &str) -> Result<Option<LocalIdentity>, OmemoError> {
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT device_id, key_pair, next_pre_key_id FROM omemo_identity \
+                 WHERE account_jid = ?1",
+                &[&account.to_string()],
+            )
+            .await?;
+        let Some(row) = rows.first() else {
+            return Ok(None);
+        };
+        Ok(Some(LocalIdentity {
+            device_id: integer(row, 0)?,
+            key_pair: self.open(&format!("identity:{account}"), blob(row, 1)?)?,
+            next_pre_key_id: integer(row, 2)?,
+        }))
+    }
+
+    pub async fn save_identity(
+        &self,
+        account: &str,
+        identity: &LocalIdentity,
+    ) -> Result<(), OmemoError> {
+        let sealed = self.seal(&format!("identity:{account}"), &identity.key_pair)?;
+        self.db
+            .execute(
+                "INSERT INTO omemo_identity (account_jid, device_id, key_pair, next_pre_key_id, \
+                 created_at) VALUES (?1, ?2, ?3, ?4, ?5) \
+                 ON CONFLICT (account_jid) DO UPDATE SET \
+                 device_id = excluded.device_id, \
+                 key_pair = excluded.key_pair, \
+                 next_pre_key_id = excluded.next_pre_key_id",
+                &[
+                    &account.to_string(),
+                    &identity.device_id,
+                    &sealed,
+                    &identity.next_pre_key_id,
+                    &Utc::now().to_rfc3339(),
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// The signed pre-key with `id`, or the newest one when `id` is `None`.
+    pub async fn signed_pre_key(
+        &self,
+        account: &str,
+        id: Option<u32>,
+    ) -> Result<Option<SignedPreKey>, OmemoError> {
+        let rows: Vec<Row> = match id {
+            Some(id) => {
+                self.db
+                    .query(
+                        "SELECT id, key_pair, signature FROM omemo_signed_pre_keys \
+                         WHERE account_jid = ?1 AND id = ?2",
+                        &[&account.to_string(), &id],
+                    )
+                    .await?
+            }
+            None => {
+                self.db
+                    .query(
+                        "SELECT id, key_pair, signature FROM omemo_signed_pre_keys \
+                         WHERE account_jid = ?1 ORDER BY id DESC LIMIT 1",
+                        &[&account.to_string()],
+                    )
+                    .await?
+            }
+        };
+        let Some(row) = rows.first() else {
+            return Ok(None);
+        };
+        let id: u32 = integer(row, 0)?;
+        Ok(Some(SignedPreKey {
+            id,
+            key_pair: self.open(&format!("signed_pre_key:{account}:{id}"), blob(row, 1)?)?,
+            signature: blob(row, 2)?.to_vec(),
+        }))
+    }
+
+    pub async fn save_signed_pre_key(
+        &self,
+        account: &str,
+        signed_pre_key: &SignedPreKey,
+    ) -> Result<(), OmemoError> {
+        let id = signed_pre_key.id;
+        let sealed = self.seal(
+            &format!("signed_pre_key:{account}:{id}"),
+            &signed_pre_key.key_pair,
+        )?;
+        self.db
+            .execute(
+                "INSERT OR REPLACE INTO omemo_signed_pre_keys \
+                 (account_jid, id, key_pair, signature, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                &[
+                    &account.to_string(),
+                    &id,
+                    &sealed,
+                    &signed_pre_key.signature,
+                    &Utc::now().to_rfc3339(),
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn pre_keys(&self, account: &str) -> Result<Vec<(u32, KeyPair)>, OmemoError> {
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT id, key_pair FROM omemo_pre_keys WHERE account_jid = ?1 ORDER BY id",
+                &[&account.to_string()],
+            )
+            .await?;
+        rows.iter()
+            .map(|row| {
+                let id: u32 = integer(row, 0)?;
+                let key_pair = self.open(&format!("pre_key:{account}:{id}"), blob(row, 1)?)?;
+                Ok((id, key_pair))
+            })
+            .collect()
+    }
+
+    pub async fn pre_key(&self, account: &str, id: u32) -> Result<Option<KeyPair>, OmemoError> {
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT key_pair FROM omemo_pre_keys WHERE account_jid = ?1 AND id = ?2",
+                &[&account.to_string(), &id],
+            )
+            .await?;
+        rows.first()
+            .map(|row| self.open(&format!("pre_key:{account}:{id}"), blob(row, 0)?))
+            .transpose()
+    }
+
+    pub async fn save_pre_key(
+        &self,
+        account: &str,
+        id: u32,
+        key_pair: &KeyPair,
+    ) -> Result<(), OmemoError> {
+        let sealed = self.seal(&format!("pre_key:{account}:{id}"), key_pair)?;
+        self.db
+            .execute(
+                "INSERT OR REPLACE INTO omemo_pre_keys (account_jid, id, key_pair) \
+                 VALUES (?1, ?2, ?3)",
+                &[&account.to_string(), &id, &sealed],
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn remove_pre_key(&self, account: &str, id: u32) -> Result<(), OmemoError> {
+        self.db
+            .execute(
+                "DELETE FROM omemo_pre_keys WHERE account_jid = ?1 AND id = ?2",
+                &[&account.to_string(), &id],
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn devices(&self, account: &str, jid: &str) -> Result<Vec<DeviceRecord>, OmemoError> {
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT device_id, identity_key, trust, active FROM omemo_devices \
+                 WHERE account_jid = ?1 AND jid = ?2 ORDER BY device_id",
+                &[&account.to_string(), &jid.to_string()],
+            )
+            .await?;
+        rows.iter().map(device_from_row).collect()
+    }
+
+    pub async fn device(
+        &self,
+        account: &str,
+        jid: &str,
+        device_id: u32,
+    ) -> Result<Option<DeviceRecord>, OmemoError> {
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT device_id, identity_key, trust, active FROM omemo_devices \
+                 WHERE account_jid = ?1 AND jid = ?2 AND device_id = ?3",
+                &[&account.to_string(), &jid.to_string(), &device_id],
+            )
+            .await?;
+        rows.first().map(device_from_row).transpose()
+    }
+
+    /// Record `jid`'s published device list. Devices missing from it stay
+    /// known, with their trust, but are no longer encrypted to.
+    pub async fn set_device_list(
+        &self,
+        account: &str,
+        jid: &str,
+        devices: &[u32],
+    ) -> Result<(), OmemoError> {
+        self.db
+            .execute(
+                "UPDATE omemo_devices SET active = 0 WHERE account_jid = ?1 AND jid = ?2",
+                &[&account.to_string(), &jid.to_string()],
+            )
+            .await?;
+        let now = Utc::now().to_rfc3339();
+        for device_id in devices {
+            self.db
+                .execute(
+                    "INSERT INTO omemo_devices (account_jid, jid, device_id, active, last_seen_at) \
+                     VALUES (?1, ?2, ?3, 1, ?4) \
+                     ON CONFLICT (account_jid, jid, device_id) DO UPDATE SET \
+                     active = 1, last_seen_at = excluded.last_seen_at",
+                    &[&account.to_string(), &jid.to_string(), device_id, &now],
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn set_identity_key(
+        &self,
+        account: &str,
+        jid: &str,
+        device_id: u32,
+        identity_key: &[u8; 32],
+        trust: OmemoTrust,
+    ) -> Result<(), OmemoError> {
+        self.db
+            .execute(
+                "INSERT INTO omemo_devices \
+                 (account_jid, jid, device_id, identity_key, trust, active, last_seen_at) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, 1, ?6) \
+                 ON CONFLICT (account_jid, jid, device_id) DO UPDATE SET \
+                 identity_key = excluded.identity_key, trust = excluded.trust",
+                &[
+                    &account.to_string(),
+                    &jid.to_string(),
+                    &device_id,
+                    &identity_key.to_vec(),
+                    &trust.as_str().to_string(),
+                    &Utc::now().to_rfc3339(),
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Returns false if the device is unknown.
+    pub async fn set_trust(
+        &self,
+        account: &str,
+        jid: &str,
+        device_id: u32,
+        trust: OmemoTrust,
+    ) -> Result<bool, OmemoError> {
+        let updated = self
+            .db
+            .execute(
+                "UPDATE omemo_devices SET trust = ?4 \
+                 WHERE account_jid = ?1 AND jid = ?2 AND device_id = ?3",
+                &[
+                    &account.to_string(),
+                    &jid.to_string(),
+                    &device_id,
+                    &trust.as_str().to_string(),
+                ],
+            )
+            .await?;
+        Ok(updated > 0)
+    }
+
+    pub async fn session(
+        &self,
+        account: &str,
+        jid: &str,
+        device_id: u32,
+    ) -> Result<Option<SessionState>, OmemoError> {
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT state FROM omemo_sessions \
+                 WHERE account_jid = ?1 AND jid = ?2 AND device_id = ?3",
+                &[&account.to_string(), &jid.to_string(), &device_id],
+            )
+            .await?;
+        rows.first()
+            .map(|row| {
+                self.open(
+                    &format!("session:{account}:{jid}:{device_id}"),
+                    blob(row, 0)?,
+                )
+            })
+            .transpose()
+    }
+
+    pub async fn session_exists(
+        &self,
+        account: &str,
+        jid: &str,
+        device_id: u32,
+    ) -> Result<bool, OmemoError> {
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT 1 FROM omemo_sessions \
+                 WHERE account_jid = ?1 AND jid = ?2 AND device_id = ?3",
+                &[&account.to_string(), &jid.to_string(), &device_id],
+            )
+            .await?;
+        Ok(!rows.is_empty())
+    }
+
+    pub async fn save_session(
+        &self,
+        account: &str,
+        jid: &str,
+        device_id: u32,
+        session: &SessionState,
+    ) -> Result<(), OmemoError> {
+        let sealed = self.seal(&format!("session:{account}:{jid}:{device_id}"), session)?;
+        self.db
+            .execute(
+                "INSERT OR REPLACE INTO omemo_sessions \
+                 (account_jid, jid, device_id, state, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                &[
+                    &account.to_string(),
+                    &jid.to_string(),
+                    &device_id,
+                    &sealed,
+                    &Utc::now().to_rfc3339(),
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_session(
+        &self,
+        account: &str,
+        jid: &str,
+        device_id: u32,
+    ) -> Result<(), OmemoError> {
+        self.db
+            .execute(
+                "DELETE FROM omemo_sessions WHERE account_jid = ?1 AND jid = ?2 AND device_id = ?3",
+                &[&account.to_string(), &jid.to_string(), &device_id],
+            )
+            .await?;
+        Ok(())
+    }
+
+    fn seal<T: Serialize>(&self, label: &str, value: &T) -> Result<Vec<u8>, OmemoError> {
+        let plaintext =
+            serde_json::to_vec(value).map_err(|error| OmemoError::Corrupted(error.to_string()))?;
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &plaintext,
+                    aad: label.as_bytes(),
+                },
+            )
+            .map_err(|_| OmemoError::Corrupted(format!("failed to seal {label}")))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    fn open<T: DeserializeOwned>(&self, label: &str, sealed: &[u8]) -> Result<T, OmemoError> {
+        if sealed.len() < NONCE_LEN {
+            return Err(OmemoError::Corrupted(format!("{label} is truncated")));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: label.as_bytes(),
+                },
+            )
+            .map_err(|_| OmemoError::Corrupted(format!("{label} failed authentication")))?;
+        serde_json::from_slice(&plaintext).map_err(|error| OmemoError::Corrupted(error.to_string()))
+    }
+}
+
+fn device_from_row(row: &Row) -> Result<DeviceRecord, OmemoError> {
+    let identity_key = match row.get(1) {
+        Some(SqlValue::Blob(bytes)) => Some(
+            bytes
+                .as_slice()
+                .try_into()
+                .map_err(|_| OmemoError::Corrupted("stored identity key length".to_string()))?,
+        ),
+        _ => None,
+    };
+    let trust = match row.get(2) {
+        Some(SqlValue::Text(trust)) => trust.parse().unwrap_or_default(),
+        _ => OmemoTrust::default(),
+    };
+    Ok(DeviceRecord {
+        device_id: integer(row, 0)?,
+        identity_key,
+        trust,
+        active: integer::<i64>(row, 3)? != 0,
+    })
+}
+
+fn integer<T: TryFrom<i64>>(row: &Row, index: usize) -> Result<T, OmemoError> {
+    match row.get(index) {
+        Some(SqlValue::Integer(value)) => T::try_from(*value)
+            .map_err(|_| OmemoError::Corrupted(format!("column {index} is out of range"))),
+        _ => Err(OmemoError::Corrupted(format!(
+            "column {index} is not an integer"
+        ))),
+    }
+}
+
+fn blob(row: &Row, index: usize) -> Result<&[u8], OmemoError> {
+    match row.get(index) {
+        Some(SqlValue::Blob(bytes)) => Ok(bytes),
+        _ => Err(OmemoError::Corrupted(format!(
+            "column {index} is not a blob"
+        ))),
+    }
+}
@@ -0,0 +1,237 @@
+//! Development mode for plugin authors: a plugin directory is watched and
+//! every new build is reinstalled and hot-reloaded into the running app.
+//!
+//! The directory is polled rather than watched through OS notifications.
+//! Builds write `plugin.wasm` in several steps, so a change is only picked
+//! up once the files have stayed the same for a whole poll interval.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::Mutex;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+use waddle_storage::Database;
+
+use crate::registry::{PluginRegistry, RegistryError};
+use crate::runtime::{PluginError, PluginHandle, PluginRuntime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, thiserror::Error)]
+pub enum DevWatchError {
+    #[error(transparent)]
+    Registry(#[from] RegistryError),
+
+    #[error(transparent)]
+    Plugin(#[from] PluginError),
+
+    #[error("failed to read plugin files: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Size and modification time of every file that goes into an install.
+type Fingerprint = Vec<(PathBuf, u64, Option<SystemTime>)>;
+
+pub struct DevWatcher<D: Database> {
+    source_dir: PathBuf,
+    registry: Arc<PluginRegistry>,
+    runtime: Arc<Mutex<PluginRuntime<D>>>,
+}
+
+impl<D: Database> DevWatcher<D> {
+    pub fn new(
+        source_dir: impl Into<PathBuf>,
+        registry: Arc<PluginRegistry>,
+        runtime: Arc<Mutex<PluginRuntime<D>>>,
+    ) -> Self {
+        Self {
+            source_dir: source_dir.into(),
+            registry,
+            runtime,
+        }
+    }
+
+    pub fn source_dir(&self) -> &Path {
+        &self.source_dir
+    }
+
+    /// Install the current build and load it, replacing the running
+    /// instance if there is one. A build that fails to load leaves the
+    /// previous one running.
+    pub async fn reinstall(&self) -> Result<PluginHandle, DevWatchError> {
+        let installed = self.registry.install_local(&self.source_dir).await?;
+        let files = self.registry.get_plugin_files(&installed.id)?;
+        let wasm_bytes = tokio::fs::read(&files.wasm_path).await?;
+
+        let mut runtime = self.runtime.lock().await;
+        let handle = if runtime.get_plugin(&installed.id).is_some() {
+            runtime
+                .reload_plugin(&installed.id, files.manifest, &wasm_bytes)
+                .await?
+        } else {
+            runtime.load_plugin(files.manifest, &wasm_bytes).await?
+        };
+        Ok(handle)
+    }
+
+    pub async fn run(self: Arc<Self>) -> Result<(), DevWatchError> {
+        let mut ticker = tokio::time::interval(POLL_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut installed: Option<Fingerprint> = None;
+        let mut previous: Option<Fingerprint> = None;
+
+        loop {
+            ticker.tick().await;
+            let current = match fingerprint(&self.source_dir) {
+                Ok(current) => current,
+                Err(error) => {
+                    warn!(source = %self.source_dir.display(), %error, "cannot read plugin directory");
+                    previous = None;
+                    continue;
+                }
+            };
+            let settled = previous.as_ref() == Some(&current);
+            previous = Some(current.clone());
+            if !settled || installed.as_ref() == Some(&current) {
+                continue;
+            }
+
+            match self.reinstall().await {
+                Ok(handle) => info!(
+                    plugin_id = %handle.id,
+                    version = %handle.version,
+                    "reloaded development plugin"
+                ),
+                Err(error) => warn!(
+                    source = %self.source_dir.display(),
+                    %error,
+                    "failed to reload development plugin"
+                ),
+            }
+            // A broken build is not retried until it changes again.
+            installed = Some(current);
+        }
+    }
+}
+
+fn fingerprint(source_dir: &Path) -> std::io::Result<Fingerprint> {
+    let mut files = Vec::new();
+    for name in ["manifest.toml", "plugin.wasm"] {
+        add_file(&source_dir.join(name), &mut files)?;
+    }
+    for name in ["vue", "assets"] {
+        let dir = source_dir.join(name);
+        if dir.is_dir() {
+            add_dir(&dir, &mut files)?;
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn add_dir(dir: &Path, files: &mut Fingerprint) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            add_dir(&path, files)?;
+        } else {
+            add_file(&path, files)?;
+        }
+    }
+    Ok(())
+}
+
+fn add_file(path: &Path, files: &mut Fingerprint) -> std::io::Result<()> {
+    let metadata = std::fs::metadata(path)?;
+    files.push((path.to_path_buf(), metadata.len(), metadata.modified().ok()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::RegistryConfig;
+    use crate::runtime::PluginRuntimeConfig;
+    use waddle_core::event::BroadcastEventBus;
+
+    const MANIFEST: &str = r#"
+[plugin]
+id = "com.test.dev"
+name = "Dev Plugin"
+version = "VERSION"
+description = "A plugin under development"
+
+[permissions]
+
+[hooks]
+"#;
+
+    const WASM: &str = r#"
+        (module
+          (func (export "plugin_init") (result i32)
+            i32.const 0)
+          (func (export "plugin_shutdown")))
+    "#;
+
+    fn write_build(dir: &Path, version: &str) {
+        std::fs::write(
+            dir.join("manifest.toml"),
+            MANIFEST.replace("VERSION", version),
+        )
+        .unwrap();
+        std::fs::write(dir.join("plugin.wasm"), WASM).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reinstalls_and_reloads_changed_builds() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_dir = dir.path().join("source");
+        std::fs::create_dir_all(&source_dir).unwrap();
+        write_build(&source_dir, "0.1.0");
+
+        let db = waddle_storage::open_native_database(&dir.path().join("waddle.db"))
+            .await
+            .unwrap();
+        let registry = Arc::new(
+            PluginRegistry::new(RegistryConfig::default(), dir.path().join("data")).unwrap(),
+        );
+        let runtime = Arc::new(Mutex::new(PluginRuntime::new(
+            PluginRuntimeConfig::default(),
+            Arc::new(BroadcastEventBus::new(64)),
+            Arc::new(db),
+        )));
+        let watcher = Arc::new(DevWatcher::new(
+            &source_dir,
+            registry.clone(),
+            runtime.clone(),
+        ));
+        tokio::spawn(watcher.run());
+
+        let version = || async {
+            runtime
+                .lock()
+                .await
+                .get_plugin("com.test.dev")
+                .map(|plugin| plugin.version.clone())
+        };
+        let wait_for = |expected: &'static str| async move {
+            tokio::time::timeout(Duration::from_secs(10), async {
+                while version().await.as_deref() != Some(expected) {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+            })
+            .await
+            .unwrap_or_else(|_| panic!("plugin never reached version {expected}"));
+        };
+
+        wait_for("0.1.0").await;
+        write_build(&source_dir, "0.2.0");
+        wait_for("0.2.0").await;
+
+        let installed = registry.list_installed().unwrap();
+        assert_eq!(installed.len(), 1);
+        assert_eq!(installed[0].version, "0.2.0");
+    }
+}
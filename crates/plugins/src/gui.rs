@@ -0,0 +1,163 @@
+//! What a plugin adds to the Vue frontend. Plugins with the `gui_metadata`
+//! hook export `plugin_gui_component_info() -> i32` and leave a JSON
+//! [`GuiComponentManifest`] in their result buffer; every component it names
+//! must be one of the `.vue` files declared under `[gui]` in the plugin
+//! manifest, which the frontend loads from the plugin's `vue` directory.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GuiComponentManifest {
+    #[serde(default)]
+    pub routes: Vec<GuiRoute>,
+    #[serde(default)]
+    pub panels: Vec<GuiPanel>,
+    #[serde(default)]
+    pub settings: Vec<GuiSettingsPage>,
+}
+
+/// A full page, mounted under the plugin's own route prefix.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GuiRoute {
+    /// Starts with `/`.
+    pub path: String,
+    pub component: String,
+    pub title: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GuiPanel {
+    /// Unique within the plugin.
+    pub id: String,
+    pub location: GuiPanelLocation,
+    pub component: String,
+    pub title: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GuiPanelLocation {
+    Sidebar,
+    Conversation,
+    Roster,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GuiSettingsPage {
+    pub component: String,
+    pub title: String,
+}
+
+impl GuiComponentManifest {
+    /// Parse the JSON a plugin returned and check it against the components
+    /// its manifest declares.
+    pub fn parse(json: &str, declared_components: &[String]) -> Result<Self, String> {
+        let manifest: Self =
+            serde_json::from_str(json).map_err(|error| format!("invalid JSON: {error}"))?;
+        manifest.validate(declared_components)?;
+        Ok(manifest)
+    }
+
+    fn validate(&self, declared_components: &[String]) -> Result<(), String> {
+        let components = self
+            .routes
+            .iter()
+            .map(|route| &route.component)
+            .chain(self.panels.iter().map(|panel| &panel.component))
+            .chain(self.settings.iter().map(|page| &page.component));
+        for component in components {
+            if !declared_components.contains(component) {
+                return Err(format!(
+                    "component '{component}' is not declared in [gui] components"
+                ));
+            }
+        }
+
+        for route in &self.routes {
+            if !route.path.starts_with('/') || route.path.split('/').any(|part| part == "..") {
+                return Err(format!("invalid route path '{}'", route.path));
+            }
+        }
+
+        for (index, panel) in self.panels.iter().enumerate() {
+            if panel.id.trim().is_empty() {
+                return Err("panel ids must not be empty".to_string());
+            }
+            if self.panels[..index]
+                .iter()
+                .any(|other| other.id == panel.id)
+            {
+                return Err(format!("duplicate panel id '{}'", panel.id));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty() && self.panels.is_empty() && self.settings.is_empty()
+    }
+}
+
+/// One installed plugin's part of the frontend.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginUiContribution {
+    pub plugin_id: String,
+    pub name: String,
+    pub version: String,
+    pub vue_dir: Option<PathBuf>,
+    pub assets_dir: Option<PathBuf>,
+    /// Whether the plugin is running. Only running plugins can describe
+    /// their components, so the manifest of any other is empty.
+    pub active: bool,
+    pub components: GuiComponentManifest,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn declared() -> Vec<String> {
+        vec!["Settings.vue".to_string(), "Panel.vue".to_string()]
+    }
+
+    #[test]
+    fn parses_declared_components() {
+        let json = r#"{
+            "routes": [{"path": "/stats", "component": "Panel.vue", "title": "Stats"}],
+            "panels": [{"id": "stats", "location": "sidebar", "component": "Panel.vue", "title": "Stats"}],
+            "settings": [{"component": "Settings.vue", "title": "Stats"}]
+        }"#;
+        let manifest = GuiComponentManifest::parse(json, &declared()).unwrap();
+        assert_eq!(manifest.routes[0].path, "/stats");
+        assert_eq!(manifest.panels[0].location, GuiPanelLocation::Sidebar);
+        assert_eq!(manifest.settings.len(), 1);
+        assert!(GuiComponentManifest::parse("{}", &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_undeclared_components_and_bad_routes() {
+        for json in [
+            r#"{"settings": [{"component": "Other.vue", "title": "x"}]}"#,
+            r#"{"routes": [{"path": "stats", "component": "Panel.vue", "title": "x"}]}"#,
+            r#"{"routes": [{"path": "/a/../b", "component": "Panel.vue", "title": "x"}]}"#,
+            r#"{"panels": [
+                {"id": "a", "location": "roster", "component": "Panel.vue", "title": "x"},
+                {"id": "a", "location": "sidebar", "component": "Panel.vue", "title": "y"}
+            ]}"#,
+            r#"{"panels": [{"id": "a", "location": "toolbar", "component": "Panel.vue", "title": "x"}]}"#,
+        ] {
+            assert!(
+                GuiComponentManifest::parse(json, &declared()).is_err(),
+                "accepted {json}"
+            );
+        }
+    }
+}
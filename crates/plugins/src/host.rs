@@ -0,0 +1,28 @@
+//! Application state that plugins can read through host functions.
+//!
+//! The runtime cannot depend on the roster or presence crates, so the
+//! application supplies a [`PluginHostApi`] implementation with
+//! [`crate::PluginRuntime::set_host_api`]. The guest-facing contract for all
+//! host functions is defined in `wit/host.wit`.
+
+use serde::Serialize;
+use waddle_core::event::{PresenceShow, RosterItem};
+
+/// Read-only view of client state served to plugins. Calls are made
+/// synchronously from inside a guest invocation and must not block on I/O.
+pub trait PluginHostApi: Send + Sync {
+    fn roster(&self) -> Vec<RosterItem>;
+
+    /// Current presence of a contact, or `None` if nothing is known.
+    fn presence(&self, jid: &str) -> Option<HostPresence>;
+}
+
+/// A contact's presence as returned by `host-roster.get-presence`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostPresence {
+    pub jid: String,
+    pub show: PresenceShow,
+    pub status: Option<String>,
+    pub priority: i8,
+}
@@ -1,5 +1,11 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "native")]
+use waddle_core::error::EventBusError;
+#[cfg(feature = "native")]
+use waddle_core::event::{Channel, Event, EventBus, EventPayload, EventSource, EventSubscription};
 use waddle_storage::{Database, Row, SqlValue, StorageError};
 
 fn escape_like_prefix(prefix: &str) -> String {
@@ -47,12 +53,60 @@ pub enum KvError {
 
     #[error("storage error: {0}")]
     Storage(#[from] StorageError),
+
+    #[error("cannot watch kv changes: {0}")]
+    WatchFailed(String),
+}
+
+/// A plugin's KV entries, as written by [`PluginKvStore::export`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KvExport {
+    /// The plugin the entries were exported from.
+    pub plugin_id: String,
+    pub entries: BTreeMap<String, Vec<u8>>,
+}
+
+/// A write or deletion seen through [`PluginKvStore::watch`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KvChange {
+    pub key: String,
+    pub deleted: bool,
+}
+
+/// Changes to one plugin's keys under a prefix.
+#[cfg(feature = "native")]
+pub struct KvWatch {
+    plugin_id: String,
+    prefix: String,
+    subscription: EventSubscription,
+}
+
+#[cfg(feature = "native")]
+impl KvWatch {
+    pub async fn recv(&mut self) -> Result<KvChange, EventBusError> {
+        loop {
+            let event = self.subscription.recv().await?;
+            if let EventPayload::PluginKvChanged {
+                plugin_id,
+                key,
+                deleted,
+            } = event.payload
+                && plugin_id == self.plugin_id
+                && key.starts_with(&self.prefix)
+            {
+                return Ok(KvChange { key, deleted });
+            }
+        }
+    }
 }
 
 pub struct PluginKvStore<D: Database> {
     plugin_id: String,
     db: Arc<D>,
     quota: KvQuota,
+    #[cfg(feature = "native")]
+    event_bus: Option<Arc<dyn EventBus>>,
 }
 
 impl<D: Database> PluginKvStore<D> {
@@ -61,6 +115,56 @@ impl<D: Database> PluginKvStore<D> {
             plugin_id,
             db,
             quota,
+            #[cfg(feature = "native")]
+            event_bus: None,
+        }
+    }
+
+    /// Publish each change on `plugin.<id>.kv.changed`.
+    #[cfg(feature = "native")]
+    pub fn with_event_bus(mut self, event_bus: Arc<dyn EventBus>) -> Self {
+        self.event_bus = Some(event_bus);
+        self
+    }
+
+    /// Follow changes to keys starting with `prefix`, made through any store
+    /// for this plugin that has an event bus.
+    #[cfg(feature = "native")]
+    pub fn watch(&self, prefix: &str) -> Result<KvWatch, KvError> {
+        let event_bus = self
+            .event_bus
+            .as_ref()
+            .ok_or_else(|| KvError::WatchFailed("store has no event bus".to_string()))?;
+        let subscription = event_bus
+            .subscribe(&changed_channel(&self.plugin_id))
+            .map_err(|error| KvError::WatchFailed(error.to_string()))?;
+        Ok(KvWatch {
+            plugin_id: self.plugin_id.clone(),
+            prefix: prefix.to_string(),
+            subscription,
+        })
+    }
+
+    fn notify(&self, key: &str, deleted: bool) {
+        #[cfg(feature = "native")]
+        if let Some(event_bus) = &self.event_bus {
+            let Ok(channel) = Channel::new(changed_channel(&self.plugin_id)) else {
+                return;
+            };
+            let _ = event_bus.publish(Event::new(
+                channel,
+                EventSource::System("plugins".to_string()),
+                EventPayload::PluginKvChanged {
+                    plugin_id: self.plugin_id.clone(),
+                    key: key.to_string(),
+                    deleted,
+                },
+            ));
+        }
+
+        #[cfg(not(feature = "native"))]
+        {
+            let _ = (key, deleted);
         }
     }
 
@@ -130,18 +234,23 @@ impl<D: Database> PluginKvStore<D> {
             });
         }
 
+        self.notify(key, false);
         Ok(())
     }
 
     pub async fn delete(&self, key: &str) -> Result<(), KvError> {
         let pid = self.plugin_id.clone();
         let k = key.to_string();
-        self.db
+        let deleted = self
+            .db
             .execute(
                 "DELETE FROM plugin_kv WHERE plugin_id = ?1 AND key = ?2",
                 &[&pid, &k],
             )
             .await?;
+        if deleted > 0 {
+            self.notify(key, true);
+        }
         Ok(())
     }
 
@@ -171,6 +280,72 @@ impl<D: Database> PluginKvStore<D> {
             .collect())
     }
 
+    /// Every key and value stored by this plugin.
+    pub async fn entries(&self) -> Result<Vec<(String, Vec<u8>)>, KvError> {
+        let pid = self.plugin_id.clone();
+        let rows: Vec<Row> = self
+            .db
+            .query(
+                "SELECT key, value FROM plugin_kv WHERE plugin_id = ?1",
+                &[&pid],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .filter_map(|row| match (row.get(0), row.get(1)) {
+                (Some(SqlValue::Text(key)), Some(SqlValue::Blob(value))) => {
+                    Some((key.clone(), value.clone()))
+                }
+                _ => None,
+            })
+            .collect())
+    }
+
+    pub async fn export(&self) -> Result<KvExport, KvError> {
+        Ok(KvExport {
+            plugin_id: self.plugin_id.clone(),
+            entries: self.entries().await?.into_iter().collect(),
+        })
+    }
+
+    /// Write every entry of `data` into this plugin's namespace, replacing
+    /// keys it already has and keeping the rest. Nothing is written unless
+    /// all of it fits the quota. The source plugin is not checked, so a new
+    /// plugin ID can take over the data of an old one.
+    pub async fn import(&self, data: &KvExport) -> Result<(), KvError> {
+        if let Some(size) = data
+            .entries
+            .values()
+            .map(|value| value.len() as u64)
+            .find(|size| *size > self.quota.max_value_bytes)
+        {
+            return Err(KvError::ValueTooLarge {
+                size,
+                limit: self.quota.max_value_bytes,
+            });
+        }
+
+        let existing = self.list_keys("").await?;
+        let new_keys = data
+            .entries
+            .keys()
+            .filter(|key| !existing.contains(key))
+            .count() as u64;
+        let key_count = existing.len() as u64 + new_keys;
+        if key_count > self.quota.max_keys {
+            return Err(KvError::QuotaExceeded {
+                current: key_count,
+                limit: self.quota.max_keys,
+            });
+        }
+
+        for (key, value) in &data.entries {
+            self.set(key, value).await?;
+        }
+        Ok(())
+    }
+
     pub async fn usage(&self) -> Result<KvUsage, KvError> {
         let pid = self.plugin_id.clone();
         let row: Row = self
@@ -198,13 +373,30 @@ impl<D: Database> PluginKvStore<D> {
 
     pub async fn clear_all(&self) -> Result<(), KvError> {
         let pid = self.plugin_id.clone();
+        #[cfg(feature = "native")]
+        let keys = match self.event_bus {
+            Some(_) => self.list_keys("").await?,
+            None => Vec::new(),
+        };
+        #[cfg(not(feature = "native"))]
+        let keys: Vec<String> = Vec::new();
+
         self.db
             .execute("DELETE FROM plugin_kv WHERE plugin_id = ?1", &[&pid])
             .await?;
+        for key in keys {
+            self.notify(&key, true);
+        }
         Ok(())
     }
 }
 
+/// Plugin IDs may contain `-`, which channel names may not.
+#[cfg(feature = "native")]
+fn changed_channel(plugin_id: &str) -> String {
+    format!("plugin.{}.kv.changed", plugin_id.replace('-', "_"))
+}
+
 #[cfg(all(test, feature = "native"))]
 mod tests {
     use super::*;
@@ -604,4 +796,106 @@ mod tests {
         let value = store.get("empty").await.expect("get failed");
         assert_eq!(value, Some(vec![]));
     }
+
+    // ---- Export and import ----
+
+    #[tokio::test]
+    async fn export_and_import_round_trip() {
+        let (old, _old_dir) = open_temp_store("test-plugin").await;
+        old.set("a", b"1").await.expect("set failed");
+        old.set("b", b"22").await.expect("set failed");
+        let data = old.export().await.expect("export failed");
+        assert_eq!(data.plugin_id, "test-plugin");
+        assert_eq!(data.entries.len(), 2);
+
+        let (new, _new_dir) = open_temp_store("test-plugin").await;
+        new.set("b", b"old").await.expect("set failed");
+        new.set("c", b"3").await.expect("set failed");
+        new.import(&data).await.expect("import failed");
+        assert_eq!(new.get("a").await.expect("get failed"), Some(b"1".to_vec()));
+        assert_eq!(
+            new.get("b").await.expect("get failed"),
+            Some(b"22".to_vec())
+        );
+        assert_eq!(new.get("c").await.expect("get failed"), Some(b"3".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn import_over_quota_writes_nothing() {
+        let quota = KvQuota {
+            max_keys: 2,
+            max_value_bytes: 4,
+        };
+        let (store, _dir) = open_temp_store_with_quota("test-plugin", quota).await;
+        store.set("a", b"1").await.expect("set failed");
+
+        let mut data = KvExport::default();
+        data.entries.insert("b".to_string(), b"2".to_vec());
+        data.entries.insert("c".to_string(), b"3".to_vec());
+        assert!(matches!(
+            store.import(&data).await,
+            Err(KvError::QuotaExceeded {
+                current: 3,
+                limit: 2
+            })
+        ));
+        data.entries.remove("c");
+        data.entries.insert("a".to_string(), b"12345".to_vec());
+        assert!(matches!(
+            store.import(&data).await,
+            Err(KvError::ValueTooLarge { size: 5, .. })
+        ));
+        assert_eq!(store.list_keys("").await.expect("list failed"), vec!["a"]);
+    }
+
+    // ---- Change notifications ----
+
+    #[tokio::test]
+    async fn watch_reports_changes_under_prefix() {
+        use std::time::Duration;
+        use waddle_core::event::BroadcastEventBus;
+
+        let (store, _dir) = open_temp_store("test-plugin").await;
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        assert!(matches!(store.watch(""), Err(KvError::WatchFailed(_))));
+        let store = store.with_event_bus(event_bus.clone());
+        let mut watch = store.watch("settings.").expect("watch failed");
+        let mut all = event_bus
+            .subscribe("plugin.test_plugin.kv.changed")
+            .expect("subscribe failed");
+
+        store.set("cache.a", b"1").await.expect("set failed");
+        store
+            .set("settings.theme", b"dark")
+            .await
+            .expect("set failed");
+        store
+            .delete("settings.missing")
+            .await
+            .expect("delete failed");
+        store.clear_all().await.expect("clear failed");
+
+        let mut next = async || {
+            tokio::time::timeout(Duration::from_secs(5), watch.recv())
+                .await
+                .expect("timed out waiting for change")
+                .expect("recv failed")
+        };
+        let change = |key: &str, deleted| KvChange {
+            key: key.to_string(),
+            deleted,
+        };
+        assert_eq!(next().await, change("settings.theme", false));
+        assert_eq!(next().await, change("settings.theme", true));
+
+        // Deleting a missing key changes nothing, so only four events.
+        let mut published = 0;
+        while tokio::time::timeout(Duration::from_millis(100), all.recv())
+            .await
+            .is_ok()
+        {
+            published += 1;
+        }
+        assert_eq!(published, 4);
+    }
 }
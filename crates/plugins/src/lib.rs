@@ -1,16 +1,31 @@
+#[cfg(feature = "native")]
+pub mod dev;
+pub mod gui;
+pub mod host;
 pub mod kv;
 pub mod registry;
 pub mod runtime;
+#[cfg(feature = "native")]
+pub mod signature;
 
-pub use kv::{KvError, KvQuota, KvUsage, PluginKvStore};
+#[cfg(feature = "native")]
+pub use dev::{DevWatchError, DevWatcher};
+pub use gui::{
+    GuiComponentManifest, GuiPanel, GuiPanelLocation, GuiRoute, GuiSettingsPage,
+    PluginUiContribution,
+};
+pub use host::{HostPresence, PluginHostApi};
+#[cfg(feature = "native")]
+pub use kv::KvWatch;
+pub use kv::{KvChange, KvError, KvExport, KvQuota, KvUsage, PluginKvStore};
 pub use registry::{
     GrantedPermissions, InstalledPlugin, ManifestCapability, ManifestError, PermissionGrant,
     PermissionPolicy, PermissionPolicyConfig, PermissionPolicyError, PluginAssets, PluginFiles,
-    PluginGui, PluginHooks, PluginManifest, PluginMetadata, PluginPermissions, PluginRegistry,
-    PluginSummary, RegistryConfig, RegistryError,
+    PluginGui, PluginHooks, PluginManifest, PluginMetadata, PluginPermission, PluginPermissions,
+    PluginRegistry, PluginSummary, RegistryConfig, RegistryCredentials, RegistryError,
 };
-pub use waddle_core::event::MessageEmbed;
+pub use waddle_core::event::{MessageEmbed, PluginUsage};
 pub use runtime::{
-    PluginCapability, PluginError, PluginHandle, PluginHook, PluginInfo, PluginRuntime,
-    PluginRuntimeConfig, PluginStatus,
+    EventQuota, PluginCapability, PluginError, PluginHandle, PluginHook, PluginInfo, PluginLimits,
+    PluginRuntime, PluginRuntimeConfig, PluginStatus, StanzaVerdict,
 };
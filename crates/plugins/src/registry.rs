@@ -3,15 +3,27 @@ use std::path::{Path, PathBuf};
 use std::sync::RwLock;
 
 use chrono::Utc;
+#[cfg(feature = "native")]
+use docker_credential::DockerCredential;
 use glob::Pattern;
 #[cfg(feature = "native")]
 use oci_distribution::Reference;
 #[cfg(feature = "native")]
 use oci_distribution::client::{Client, ClientConfig};
 #[cfg(feature = "native")]
-use oci_distribution::manifest::OciImageManifest;
+use oci_distribution::errors::{OciDistributionError, OciErrorCode};
+#[cfg(feature = "native")]
+use oci_distribution::manifest::{
+    IMAGE_MANIFEST_MEDIA_TYPE, OCI_IMAGE_MEDIA_TYPE, OciImageManifest,
+};
 #[cfg(feature = "native")]
 use oci_distribution::secrets::RegistryAuth;
+
+#[cfg(feature = "native")]
+use crate::signature::{
+    ArtifactSignature, COSIGN_SIGNATURE_ANNOTATION, COSIGN_SIGNATURE_MEDIA_TYPE, SignaturePolicy,
+    TrustedKey, signature_tag, verify_artifact,
+};
 use semver::Version;
 use sha2::{Digest, Sha256};
 use tracing::{debug, info, warn};
@@ -27,11 +39,32 @@ const MEDIA_TYPE_VUE: &str = "application/vnd.waddle.plugin.vue.v1+tar";
 #[cfg(feature = "native")]
 const MEDIA_TYPE_ASSETS: &str = "application/vnd.waddle.plugin.assets.v1+tar";
 
+#[cfg(feature = "native")]
+const MEDIA_TYPE_INDEX: &str = "application/vnd.waddle.plugin.index.v1+json";
+
+/// Repository in a registry namespace that holds its plugin index.
+#[cfg(feature = "native")]
+const INDEX_REPOSITORY: &str = "plugin-index";
+
+/// How long a downloaded plugin index is searched before it is fetched again.
+#[cfg(feature = "native")]
+pub const INDEX_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Username that goes with a bare token, as Docker sends identity tokens.
+#[cfg(feature = "native")]
+const TOKEN_USERNAME: &str = "<token>";
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RegistryConfig {
     pub default_registry: String,
     pub check_updates_on_startup: bool,
+    /// `enforce`, `warn` or `disabled`.
     pub signature_policy: String,
+    /// PEM public keys whose cosign signatures are accepted on artifacts.
+    pub trusted_keys: Vec<String>,
+    /// Keyed by registry host, such as `ghcr.io`. Registries not listed are
+    /// accessed anonymously.
+    pub credentials: BTreeMap<String, RegistryCredentials>,
 }
 
 impl Default for RegistryConfig {
@@ -40,10 +73,26 @@ impl Default for RegistryConfig {
             default_registry: "ghcr.io/waddle-social".to_string(),
             check_updates_on_startup: true,
             signature_policy: "warn".to_string(),
+            trusted_keys: Vec::new(),
+            credentials: BTreeMap::new(),
         }
     }
 }
 
+/// How to sign in to a registry that does not allow anonymous pulls.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistryCredentials {
+    Basic {
+        username: String,
+        password: String,
+    },
+    /// A personal access or robot token.
+    Token(String),
+    /// Whatever `docker login` stored for the registry, including through a
+    /// credential helper named in the Docker config.
+    Docker,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct PluginManifest {
     pub plugin: PluginMetadata,
@@ -198,6 +247,65 @@ pub struct PluginPermissions {
     /// Hosts this plugin is allowed to contact via host-http (e.g. `["api.github.com"]`).
     #[serde(default)]
     pub http_hosts: Vec<String>,
+    /// Plugin may read the roster and contact presence via host-roster.
+    #[serde(default)]
+    pub roster_access: bool,
+    /// Plugin may send chat messages via host-xmpp.
+    #[serde(default)]
+    pub send_messages: bool,
+}
+
+impl PluginPermissions {
+    /// Whether the manifest declares `permission`. Event subscriptions must
+    /// match a declared pattern exactly.
+    pub fn allows(&self, permission: &PluginPermission) -> bool {
+        match permission {
+            PluginPermission::StanzaAccess => self.stanza_access,
+            PluginPermission::KvStorage => self.kv_storage,
+            PluginPermission::RosterAccess => self.roster_access,
+            PluginPermission::SendMessages => self.send_messages,
+            PluginPermission::Network { host } => self.http_hosts.iter().any(|h| h == host),
+            PluginPermission::EventSubscription { pattern } => self
+                .event_subscriptions
+                .iter()
+                .any(|declared| declared == pattern),
+        }
+    }
+}
+
+/// A single access right a plugin exercises at runtime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PluginPermission {
+    StanzaAccess,
+    KvStorage,
+    RosterAccess,
+    SendMessages,
+    Network { host: String },
+    EventSubscription { pattern: String },
+}
+
+impl PluginPermission {
+    /// The manifest field under `[permissions]` that grants this permission.
+    pub fn manifest_field(&self) -> &'static str {
+        match self {
+            Self::StanzaAccess => "stanza_access",
+            Self::KvStorage => "kv_storage",
+            Self::RosterAccess => "roster_access",
+            Self::SendMessages => "send_messages",
+            Self::Network { .. } => "http_hosts",
+            Self::EventSubscription { .. } => "event_subscriptions",
+        }
+    }
+}
+
+impl std::fmt::Display for PluginPermission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Network { host } => write!(f, "http_hosts ({host})"),
+            Self::EventSubscription { pattern } => write!(f, "event_subscriptions ({pattern})"),
+            _ => f.write_str(self.manifest_field()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, Default)]
@@ -218,6 +326,9 @@ pub struct PluginHooks {
     /// Plugin can render embeds for the GUI.
     #[serde(default)]
     pub gui_renderer: bool,
+    /// Plugin runs the hooks named by rules' plugin-hook actions.
+    #[serde(default)]
+    pub rule_hook: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize, Default)]
@@ -324,14 +435,25 @@ pub struct InstalledPlugin {
     pub installed_at: String,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// One entry of a registry's plugin index.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct PluginSummary {
+    /// Where to install the plugin from, without a tag.
     pub reference: String,
     pub name: String,
+    #[serde(default)]
     pub description: String,
     pub latest_version: String,
 }
 
+/// The JSON document in a registry's plugin index artifact.
+#[cfg(feature = "native")]
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct RegistryIndex {
+    #[serde(default)]
+    plugins: Vec<PluginSummary>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PluginFiles {
     pub manifest: PluginManifest,
@@ -429,7 +551,7 @@ impl PluginRegistry {
 
     pub async fn install(&self, reference: &str) -> Result<InstalledPlugin, RegistryError> {
         if Path::new(reference).is_dir() {
-            return self.install_from_local(reference).await;
+            return self.install_from_local(Path::new(reference), false).await;
         }
 
         self.install_from_oci(reference, false, None).await
@@ -448,16 +570,12 @@ impl PluginRegistry {
         info!(reference = %ref_str, "installing plugin from OCI registry");
 
         let client = Client::new(ClientConfig::default());
-        let auth = RegistryAuth::Anonymous;
+        let auth = self.registry_auth(&oci_ref).await?;
 
-        let (manifest, digest) =
-            client
-                .pull_image_manifest(&oci_ref, &auth)
-                .await
-                .map_err(|err| RegistryError::PullFailed {
-                    reference: ref_str.clone(),
-                    reason: err.to_string(),
-                })?;
+        let (manifest, digest) = pull_verified_manifest(&client, &oci_ref, &auth).await?;
+
+        self.verify_signature(&client, &oci_ref, &auth, &digest)
+            .await?;
 
         let artifact = self.pull_layers(&client, &oci_ref, &manifest).await?;
         let plugin_manifest = &artifact.manifest;
@@ -528,8 +646,23 @@ impl PluginRegistry {
         ))
     }
 
-    async fn install_from_local(&self, path: &str) -> Result<InstalledPlugin, RegistryError> {
-        let source_dir = Path::new(path);
+    /// Install the plugin in `path`, a directory holding `manifest.toml`,
+    /// `plugin.wasm` and optionally `vue/` and `assets/`, without going
+    /// through a registry. An installed copy of the same plugin is replaced,
+    /// so plugin authors can reinstall each new build.
+    pub async fn install_local(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<InstalledPlugin, RegistryError> {
+        self.install_from_local(path.as_ref(), true).await
+    }
+
+    async fn install_from_local(
+        &self,
+        source_dir: &Path,
+        allow_replace: bool,
+    ) -> Result<InstalledPlugin, RegistryError> {
+        let path = source_dir.display().to_string();
         let manifest_path = source_dir.join("manifest.toml");
         let wasm_path = source_dir.join("plugin.wasm");
 
@@ -549,7 +682,9 @@ impl PluginRegistry {
                 .installed
                 .read()
                 .map_err(|_| RegistryError::Io(std::io::Error::other("lock poisoned")))?;
-            if let Some(existing) = index.plugins.iter().find(|p| p.id == plugin_id) {
+            if let Some(existing) = index.plugins.iter().find(|p| p.id == plugin_id)
+                && !allow_replace
+            {
                 return Err(RegistryError::AlreadyInstalled {
                     id: plugin_id,
                     version: existing.version.clone(),
@@ -558,6 +693,10 @@ impl PluginRegistry {
         }
 
         let dest_dir = self.installed_dir().join(&plugin_id);
+        // Files dropped from the source must not linger in the install.
+        if dest_dir.exists() {
+            std::fs::remove_dir_all(&dest_dir)?;
+        }
         std::fs::create_dir_all(&dest_dir)?;
 
         std::fs::copy(&manifest_path, dest_dir.join("manifest.toml"))?;
@@ -577,12 +716,16 @@ impl PluginRegistry {
             id: plugin_id.clone(),
             name: plugin_manifest.name().to_string(),
             version: plugin_manifest.version().to_string(),
-            source: path.to_string(),
+            source: path.clone(),
             digest: None,
             installed_at: Utc::now().to_rfc3339(),
         };
 
-        self.add_to_index(entry.clone())?;
+        if allow_replace {
+            self.upsert_index(entry.clone())?;
+        } else {
+            self.add_to_index(entry.clone())?;
+        }
 
         info!(plugin_id = %plugin_id, source = %path, "plugin installed from local directory");
         Ok(entry)
@@ -677,53 +820,115 @@ impl PluginRegistry {
         ))
     }
 
+    /// Plugins in the index published under `registry`, a namespace such
+    /// as `ghcr.io/waddle-social`, or under the default registry when it is
+    /// empty. Entries match when `query` appears in their name, description
+    /// or reference, ignoring case; an empty query matches everything.
+    ///
+    /// The index is cached for [`INDEX_MAX_AGE`], and a stale copy is used
+    /// when the registry cannot be reached.
     #[cfg(feature = "native")]
     pub async fn search(
         &self,
         registry: &str,
         query: &str,
     ) -> Result<Vec<PluginSummary>, RegistryError> {
-        let ref_str = if registry.contains('/') {
-            registry.to_string()
-        } else {
-            format!("{}/{query}", self.config.default_registry)
+        let namespace = match registry.trim_end_matches('/') {
+            "" => self.config.default_registry.as_str(),
+            namespace => namespace,
         };
+        let query = query.trim().to_lowercase();
 
-        let oci_ref: Reference = ref_str
-            .parse()
-            .map_err(
-                |err: oci_distribution::ParseError| RegistryError::ResolveFailed {
-                    reference: ref_str.clone(),
-                    reason: err.to_string(),
-                },
-            )?;
+        let mut summaries: Vec<PluginSummary> = self
+            .registry_index(namespace)
+            .await?
+            .into_iter()
+            .filter(|summary| {
+                query.is_empty()
+                    || [&summary.name, &summary.description, &summary.reference]
+                        .iter()
+                        .any(|field| field.to_lowercase().contains(&query))
+            })
+            .collect();
+        summaries.sort_by(|a, b| {
+            a.name
+                .cmp(&b.name)
+                .then_with(|| a.reference.cmp(&b.reference))
+        });
+        Ok(summaries)
+    }
 
-        let client = Client::new(ClientConfig::default());
-        let auth = RegistryAuth::Anonymous;
+    #[cfg(feature = "native")]
+    async fn registry_index(&self, namespace: &str) -> Result<Vec<PluginSummary>, RegistryError> {
+        let cache_path = self
+            .cache_dir()
+            .join(format!("index-{}.json", namespace.replace(['/', ':'], "_")));
+        let cached_age = std::fs::metadata(&cache_path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .map(|modified| modified.elapsed().unwrap_or_default());
+        if cached_age.is_some_and(|age| age < INDEX_MAX_AGE)
+            && let Ok(summaries) = read_index(&cache_path)
+        {
+            return Ok(summaries);
+        }
 
-        let tag_response = client
-            .list_tags(&oci_ref, &auth, None, None)
-            .await
-            .map_err(|err| RegistryError::ResolveFailed {
-                reference: ref_str.clone(),
-                reason: err.to_string(),
-            })?;
+        let error = match self.pull_index(namespace).await {
+            Ok(data) => match parse_index(&data) {
+                Ok(summaries) => {
+                    std::fs::write(&cache_path, &data)?;
+                    return Ok(summaries);
+                }
+                Err(reason) => RegistryError::ResolveFailed {
+                    reference: format!("{namespace}/{INDEX_REPOSITORY}"),
+                    reason: format!("invalid plugin index: {reason}"),
+                },
+            },
+            Err(error) => error,
+        };
 
-        let mut summaries = Vec::new();
-        for tag in &tag_response.tags {
-            if !query.is_empty() && !tag.contains(query) && !tag_response.name.contains(query) {
-                continue;
+        match read_index(&cache_path) {
+            Ok(summaries) => {
+                warn!(%namespace, %error, "using cached plugin index");
+                Ok(summaries)
             }
-
-            summaries.push(PluginSummary {
-                reference: format!("{}:{tag}", ref_str),
-                name: tag_response.name.clone(),
-                description: String::new(),
-                latest_version: tag.clone(),
-            });
+            Err(_) => Err(error),
         }
+    }
 
-        Ok(summaries)
+    #[cfg(feature = "native")]
+    async fn pull_index(&self, namespace: &str) -> Result<Vec<u8>, RegistryError> {
+        let oci_ref = self.resolve_reference(&format!("{namespace}/{INDEX_REPOSITORY}:latest"))?;
+        let ref_str = oci_ref.whole();
+        let pull_failed = |reason| RegistryError::PullFailed {
+            reference: ref_str.clone(),
+            reason,
+        };
+
+        let client = Client::new(ClientConfig::default());
+        let auth = self.registry_auth(&oci_ref).await?;
+        let (manifest, _) = client
+            .pull_image_manifest(&oci_ref, &auth)
+            .await
+            .map_err(|err| oci_error(&oci_ref, err, pull_failed))?;
+        let layer = manifest
+            .layers
+            .iter()
+            .find(|layer| layer.media_type == MEDIA_TYPE_INDEX)
+            .ok_or_else(|| pull_failed("artifact has no plugin index layer".to_string()))?;
+
+        let mut data = Vec::new();
+        client
+            .pull_blob(&oci_ref, layer, &mut data)
+            .await
+            .map_err(|err| oci_error(&oci_ref, err, pull_failed))?;
+        if format!("sha256:{:x}", Sha256::digest(&data)) != layer.digest {
+            return Err(pull_failed(format!(
+                "digest mismatch for plugin index {}",
+                layer.digest
+            )));
+        }
+        Ok(data)
     }
 
     #[cfg(not(feature = "native"))]
@@ -793,14 +998,16 @@ impl PluginRegistry {
         let ref_str = oci_ref.whole();
 
         let client = Client::new(ClientConfig::default());
-        let auth = RegistryAuth::Anonymous;
+        let auth = self.registry_auth(&oci_ref).await?;
 
         let tag_response = client
             .list_tags(&oci_ref, &auth, None, None)
             .await
-            .map_err(|err| RegistryError::ResolveFailed {
-                reference: ref_str.clone(),
-                reason: err.to_string(),
+            .map_err(|err| {
+                oci_error(&oci_ref, err, |reason| RegistryError::ResolveFailed {
+                    reference: ref_str.clone(),
+                    reason,
+                })
             })?;
 
         let mut versions: Vec<String> = tag_response
@@ -841,6 +1048,138 @@ impl PluginRegistry {
         )
     }
 
+    /// Credentials for the registry `oci_ref` is hosted on.
+    #[cfg(feature = "native")]
+    async fn registry_auth(&self, oci_ref: &Reference) -> Result<RegistryAuth, RegistryError> {
+        let registry = oci_ref.registry().to_string();
+        let auth = match self.config.credentials.get(&registry) {
+            None => RegistryAuth::Anonymous,
+            Some(RegistryCredentials::Basic { username, password }) => {
+                RegistryAuth::Basic(username.clone(), password.clone())
+            }
+            Some(RegistryCredentials::Token(token)) => {
+                RegistryAuth::Basic(TOKEN_USERNAME.to_string(), token.clone())
+            }
+            Some(RegistryCredentials::Docker) => {
+                let failed = |reason: String| RegistryError::AuthenticationFailed {
+                    registry: registry.clone(),
+                    reason,
+                };
+                // Credential helpers are external programs.
+                let server = registry.clone();
+                let credential =
+                    tokio::task::spawn_blocking(move || docker_credential::get_credential(&server))
+                        .await
+                        .map_err(|error| failed(error.to_string()))?
+                        .map_err(|error| failed(format!("no Docker credentials: {error}")))?;
+                match credential {
+                    DockerCredential::UsernamePassword(username, password) => {
+                        RegistryAuth::Basic(username, password)
+                    }
+                    DockerCredential::IdentityToken(token) => {
+                        RegistryAuth::Basic(TOKEN_USERNAME.to_string(), token)
+                    }
+                }
+            }
+        };
+        Ok(auth)
+    }
+
+    /// Check the cosign signatures published for the artifact manifest
+    /// `digest` against the trusted keys, as the signature policy demands.
+    #[cfg(feature = "native")]
+    async fn verify_signature(
+        &self,
+        client: &Client,
+        oci_ref: &Reference,
+        auth: &RegistryAuth,
+        digest: &str,
+    ) -> Result<(), RegistryError> {
+        let policy = SignaturePolicy::from_config(&self.config.signature_policy);
+        if policy == SignaturePolicy::Disabled {
+            return Ok(());
+        }
+
+        match self.check_signature(client, oci_ref, auth, digest).await {
+            Ok(()) => {
+                debug!(reference = %oci_ref.whole(), "plugin signature verified");
+                Ok(())
+            }
+            Err(reason) if policy == SignaturePolicy::Warn => {
+                warn!(
+                    reference = %oci_ref.whole(),
+                    %reason,
+                    "installing plugin without a verified signature"
+                );
+                Ok(())
+            }
+            Err(reason) => Err(RegistryError::SignatureVerificationFailed {
+                reference: oci_ref.whole(),
+                reason,
+            }),
+        }
+    }
+
+    #[cfg(feature = "native")]
+    async fn check_signature(
+        &self,
+        client: &Client,
+        oci_ref: &Reference,
+        auth: &RegistryAuth,
+        digest: &str,
+    ) -> Result<(), String> {
+        let trusted_keys = self
+            .config
+            .trusted_keys
+            .iter()
+            .map(|key| TrustedKey::from_pem(key))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let signature_ref = Reference::with_tag(
+            oci_ref.registry().to_string(),
+            oci_ref.repository().to_string(),
+            signature_tag(digest),
+        );
+        let (manifest, _) = client
+            .pull_image_manifest(&signature_ref, auth)
+            .await
+            .map_err(|err| format!("no signature found: {err}"))?;
+
+        let mut signatures = Vec::new();
+        for layer in manifest
+            .layers
+            .iter()
+            .filter(|layer| layer.media_type == COSIGN_SIGNATURE_MEDIA_TYPE)
+        {
+            let Some(signature) = layer
+                .annotations
+                .as_ref()
+                .and_then(|annotations| annotations.get(COSIGN_SIGNATURE_ANNOTATION))
+            else {
+                continue;
+            };
+
+            let mut payload = Vec::new();
+            client
+                .pull_blob(&signature_ref, layer, &mut payload)
+                .await
+                .map_err(|err| format!("failed to pull signature payload: {err}"))?;
+            if format!("sha256:{:x}", Sha256::digest(&payload)) != layer.digest {
+                return Err(format!(
+                    "digest mismatch for signature payload {}",
+                    layer.digest
+                ));
+            }
+
+            signatures.push(ArtifactSignature {
+                payload,
+                signature: signature.clone(),
+            });
+        }
+
+        verify_artifact(digest, &signatures, &trusted_keys)
+    }
+
     #[cfg(feature = "native")]
     async fn pull_layers(
         &self,
@@ -1019,6 +1358,89 @@ impl PluginRegistry {
     }
 }
 
+/// Pull the image manifest of `oci_ref` and return it with the digest of the
+/// bytes received. The registry's `Docker-Content-Digest` header is not
+/// trusted, since a registry could otherwise serve any manifest under the
+/// digest of a signed one.
+#[cfg(feature = "native")]
+async fn pull_verified_manifest(
+    client: &Client,
+    oci_ref: &Reference,
+    auth: &RegistryAuth,
+) -> Result<(OciImageManifest, String), RegistryError> {
+    let pull_failed = |reason| RegistryError::PullFailed {
+        reference: oci_ref.whole(),
+        reason,
+    };
+
+    let (body, claimed) = client
+        .pull_manifest_raw(
+            oci_ref,
+            auth,
+            &[OCI_IMAGE_MEDIA_TYPE, IMAGE_MANIFEST_MEDIA_TYPE],
+        )
+        .await
+        .map_err(|err| oci_error(oci_ref, err, pull_failed))?;
+    let digest = format!("sha256:{:x}", Sha256::digest(&body));
+    for expected in [Some(claimed.as_str()), oci_ref.digest()]
+        .into_iter()
+        .flatten()
+    {
+        if expected != digest {
+            return Err(pull_failed(format!(
+                "manifest digest mismatch: expected {expected}, got {digest}"
+            )));
+        }
+    }
+
+    let manifest = serde_json::from_slice(&body)
+        .map_err(|err| pull_failed(format!("invalid image manifest: {err}")))?;
+    Ok((manifest, digest))
+}
+
+/// Rejected credentials name the registry; other failures become `other`.
+#[cfg(feature = "native")]
+fn oci_error(
+    oci_ref: &Reference,
+    error: OciDistributionError,
+    other: impl FnOnce(String) -> RegistryError,
+) -> RegistryError {
+    let denied = match &error {
+        OciDistributionError::AuthenticationFailure(_)
+        | OciDistributionError::UnauthorizedError { .. } => true,
+        OciDistributionError::RegistryError { envelope, .. } => {
+            envelope.errors.iter().any(|error| {
+                matches!(
+                    error.code,
+                    OciErrorCode::Unauthorized | OciErrorCode::Denied
+                )
+            })
+        }
+        _ => false,
+    };
+    if denied {
+        RegistryError::AuthenticationFailed {
+            registry: oci_ref.registry().to_string(),
+            reason: error.to_string(),
+        }
+    } else {
+        other(error.to_string())
+    }
+}
+
+#[cfg(feature = "native")]
+fn parse_index(data: &[u8]) -> Result<Vec<PluginSummary>, String> {
+    serde_json::from_slice::<RegistryIndex>(data)
+        .map(|index| index.plugins)
+        .map_err(|error| error.to_string())
+}
+
+#[cfg(feature = "native")]
+fn read_index(path: &Path) -> Result<Vec<PluginSummary>, String> {
+    let data = std::fs::read(path).map_err(|error| error.to_string())?;
+    parse_index(&data)
+}
+
 fn load_index(plugins_dir: &Path) -> PluginIndex {
     let index_path = plugins_dir.join("index.toml");
     if !index_path.exists() {
@@ -1407,6 +1829,7 @@ fn resolve_event_subscriptions(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Duration;
 
     const VALID_MANIFEST: &str = r#"
 [plugin]
@@ -1465,6 +1888,28 @@ gui_metadata = false
         assert!(matches!(error, ManifestError::InvalidCapability { .. }));
     }
 
+    #[test]
+    fn permissions_allow_only_declared_access() {
+        let manifest = PluginManifest::from_toml_str(VALID_MANIFEST).unwrap();
+        let permissions = &manifest.permissions;
+
+        assert!(permissions.allows(&PluginPermission::StanzaAccess));
+        assert!(permissions.allows(&PluginPermission::KvStorage));
+        assert!(permissions.allows(&PluginPermission::EventSubscription {
+            pattern: "xmpp.message.*".to_string(),
+        }));
+        assert!(!permissions.allows(&PluginPermission::EventSubscription {
+            pattern: "xmpp.**".to_string(),
+        }));
+        let network = PluginPermission::Network {
+            host: "api.github.com".to_string(),
+        };
+        assert!(!permissions.allows(&network));
+        assert_eq!(network.to_string(), "http_hosts (api.github.com)");
+        assert!(!permissions.allows(&PluginPermission::RosterAccess));
+        assert!(!permissions.allows(&PluginPermission::SendMessages));
+    }
+
     #[test]
     fn manifest_rejects_event_subscriptions_without_event_handler_hook() {
         let manifest = VALID_MANIFEST.replace("event_handler = true", "event_handler = false");
@@ -1836,6 +2281,47 @@ description = "A duplicate test"
         assert!(matches!(err, RegistryError::AlreadyInstalled { .. }));
     }
 
+    #[tokio::test]
+    async fn install_local_replaces_previous_build() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().join("data");
+        let source_dir = dir.path().join("source");
+        std::fs::create_dir_all(source_dir.join("assets")).unwrap();
+
+        let manifest_toml = r#"
+[plugin]
+id = "com.test.rebuild"
+name = "Rebuilt Plugin"
+version = "0.1.0"
+description = "Installed over and over"
+
+[permissions]
+
+[hooks]
+"#;
+        std::fs::write(source_dir.join("manifest.toml"), manifest_toml).unwrap();
+        std::fs::write(source_dir.join("plugin.wasm"), b"first").unwrap();
+        std::fs::write(source_dir.join("assets/icon.svg"), b"<svg/>").unwrap();
+
+        let registry = PluginRegistry::new(RegistryConfig::default(), data_dir).unwrap();
+        registry.install_local(&source_dir).await.unwrap();
+
+        std::fs::write(
+            source_dir.join("manifest.toml"),
+            manifest_toml.replace("0.1.0", "0.2.0"),
+        )
+        .unwrap();
+        std::fs::write(source_dir.join("plugin.wasm"), b"second").unwrap();
+        std::fs::remove_dir_all(source_dir.join("assets")).unwrap();
+        let installed = registry.install_local(&source_dir).await.unwrap();
+
+        assert_eq!(installed.version, "0.2.0");
+        assert_eq!(registry.list_installed().unwrap(), vec![installed]);
+        let files = registry.get_plugin_files("com.test.rebuild").unwrap();
+        assert_eq!(std::fs::read(&files.wasm_path).unwrap(), b"second");
+        assert!(files.assets_dir.is_none());
+    }
+
     #[test]
     fn get_plugin_files_not_installed_returns_error() {
         let dir = tempfile::tempdir().unwrap();
@@ -1872,6 +2358,186 @@ description = "A duplicate test"
         assert_eq!(oci_ref.tag(), Some("2.0.0"));
     }
 
+    #[tokio::test]
+    async fn registry_auth_uses_configured_credentials() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = RegistryConfig {
+            credentials: BTreeMap::from([
+                (
+                    "harbor.example.com".to_string(),
+                    RegistryCredentials::Basic {
+                        username: "robot$waddle".to_string(),
+                        password: "secret".to_string(),
+                    },
+                ),
+                (
+                    "ghcr.io".to_string(),
+                    RegistryCredentials::Token("ghp_token".to_string()),
+                ),
+            ]),
+            ..RegistryConfig::default()
+        };
+        let registry = PluginRegistry::new(config, dir.path().to_path_buf()).unwrap();
+
+        for (reference, expected) in [
+            (
+                "harbor.example.com/internal/plugin:1.0.0",
+                RegistryAuth::Basic("robot$waddle".to_string(), "secret".to_string()),
+            ),
+            (
+                "plugin:1.0.0",
+                RegistryAuth::Basic(TOKEN_USERNAME.to_string(), "ghp_token".to_string()),
+            ),
+            ("docker.io/myorg/plugin:1.0.0", RegistryAuth::Anonymous),
+        ] {
+            let oci_ref = registry.resolve_reference(reference).unwrap();
+            assert_eq!(
+                registry.registry_auth(&oci_ref).await.unwrap(),
+                expected,
+                "{reference}"
+            );
+        }
+    }
+
+    fn write_cached_index(registry: &PluginRegistry, namespace: &str, age: Duration) {
+        let index = RegistryIndex {
+            plugins: vec![
+                PluginSummary {
+                    reference: format!("{namespace}/omemo"),
+                    name: "OMEMO Encryption".to_string(),
+                    description: "End-to-end encryption".to_string(),
+                    latest_version: "1.2.0".to_string(),
+                },
+                PluginSummary {
+                    reference: format!("{namespace}/embeds"),
+                    name: "Link Embeds".to_string(),
+                    description: String::new(),
+                    latest_version: "0.3.0".to_string(),
+                },
+            ],
+        };
+        let path = registry
+            .cache_dir()
+            .join(format!("index-{}.json", namespace.replace(['/', ':'], "_")));
+        std::fs::write(&path, serde_json::to_vec(&index).unwrap()).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - age)
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn search_filters_the_cached_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry =
+            PluginRegistry::new(RegistryConfig::default(), dir.path().to_path_buf()).unwrap();
+        write_cached_index(&registry, "ghcr.io/waddle-social", Duration::ZERO);
+
+        let all = registry.search("", "").await.unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].name, "Link Embeds");
+
+        let found = registry.search("", "ENCRYPTION").await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].reference, "ghcr.io/waddle-social/omemo");
+        assert_eq!(found[0].latest_version, "1.2.0");
+        assert!(registry.search("", "weather").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn search_falls_back_to_a_stale_index_offline() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry =
+            PluginRegistry::new(RegistryConfig::default(), dir.path().to_path_buf()).unwrap();
+        // Nothing listens on the discard port.
+        let namespace = "127.0.0.1:9/waddle";
+
+        assert!(registry.search(namespace, "").await.is_err());
+
+        write_cached_index(&registry, namespace, INDEX_MAX_AGE * 2);
+        let found = registry.search(namespace, "embeds").await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].reference, "127.0.0.1:9/waddle/embeds");
+    }
+
+    #[test]
+    fn rejected_credentials_name_the_registry() {
+        let oci_ref: Reference = "harbor.example.com/internal/plugin:1.0.0".parse().unwrap();
+        let pull_failed = |reason| RegistryError::PullFailed {
+            reference: oci_ref.whole(),
+            reason,
+        };
+
+        let unauthorized = OciDistributionError::UnauthorizedError {
+            url: "https://harbor.example.com/v2/".to_string(),
+        };
+        assert!(matches!(
+            oci_error(&oci_ref, unauthorized, pull_failed),
+            RegistryError::AuthenticationFailed { ref registry, .. }
+                if registry == "harbor.example.com"
+        ));
+
+        let missing = OciDistributionError::ImageManifestNotFoundError("1.0.0".to_string());
+        assert!(matches!(
+            oci_error(&oci_ref, missing, pull_failed),
+            RegistryError::PullFailed { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn manifest_digest_is_computed_not_taken_from_the_registry() {
+        use oci_distribution::client::ClientProtocol;
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let body = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": OCI_IMAGE_MEDIA_TYPE,
+            "config": {
+                "mediaType": "application/vnd.oci.empty.v1+json",
+                "digest": "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a",
+                "size": 2
+            },
+            "layers": []
+        })
+        .to_string();
+        let digest = format!("sha256:{:x}", Sha256::digest(body.as_bytes()));
+        let signed = format!("sha256:{:x}", Sha256::digest(b"a signed manifest"));
+        for (tag, claimed) in [("honest", &digest), ("lying", &signed)] {
+            Mock::given(method("GET"))
+                .and(path(format!("/v2/waddle/plugin/manifests/{tag}")))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header("Content-Type", OCI_IMAGE_MEDIA_TYPE)
+                        .insert_header("Docker-Content-Digest", claimed.as_str())
+                        .set_body_string(body.clone()),
+                )
+                .mount(&server)
+                .await;
+        }
+
+        let client = Client::new(ClientConfig {
+            protocol: ClientProtocol::Http,
+            ..ClientConfig::default()
+        });
+        let host = server.address().to_string();
+        let pull = |tag: &str| {
+            let oci_ref: Reference = format!("{host}/waddle/plugin:{tag}").parse().unwrap();
+            let client = &client;
+            async move { pull_verified_manifest(client, &oci_ref, &RegistryAuth::Anonymous).await }
+        };
+
+        let (_, pulled) = pull("honest").await.unwrap();
+        assert_eq!(pulled, digest);
+        assert!(matches!(
+            pull("lying").await,
+            Err(RegistryError::PullFailed { ref reason, .. }) if reason.contains(&signed)
+        ));
+    }
+
     #[test]
     fn plugin_index_serializes_roundtrip() {
         let index = PluginIndex {
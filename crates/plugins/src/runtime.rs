@@ -15,9 +15,16 @@ use std::time::{Duration, Instant};
 
 #[cfg(feature = "native")]
 use glob::Pattern;
+#[cfg(feature = "native")]
+use tracing::{debug, warn};
+#[cfg(feature = "native")]
+use waddle_core::error::EventBusError;
 use waddle_core::event::Event;
 #[cfg(feature = "native")]
-use waddle_core::event::{Channel, EventBus, EventPayload, EventSource};
+use waddle_core::event::{
+    Channel, EventBus, EventPayload, EventSource, EventSubscription, MessageType, PluginUsage,
+    channels,
+};
 use waddle_storage::Database;
 
 #[cfg(feature = "native")]
@@ -26,16 +33,31 @@ use wasmtime::{
     TypedFunc,
 };
 
-use crate::registry::{ManifestCapability, PluginManifest};
-
+use crate::gui::GuiComponentManifest;
 #[cfg(feature = "native")]
-const AUTO_DISABLE_ERROR_THRESHOLD: usize = 5;
+use crate::gui::PluginUiContribution;
+use crate::host::PluginHostApi;
+use crate::kv::{KvError, KvExport, KvQuota, PluginKvStore};
+use crate::registry::{ManifestCapability, PluginManifest, PluginPermission, PluginPermissions};
 #[cfg(feature = "native")]
-const ERROR_WINDOW: Duration = Duration::from_secs(60);
+use crate::registry::{PluginRegistry, RegistryError};
+
 #[cfg(feature = "native")]
 const BLOCKING_POOL_THREADS: usize = 2;
 #[cfg(feature = "native")]
 const VALID_EVENT_DOMAINS: &[&str] = &["system", "xmpp", "ui", "plugin"];
+/// Channels announcing that the set of loaded plugins has changed.
+#[cfg(feature = "native")]
+const PLUGIN_LIFECYCLE_PATTERN: &str = "plugin.*.{loaded,unloaded,error}";
+/// Event types under a plugin's namespace that only the runtime publishes.
+#[cfg(feature = "native")]
+const RESERVED_EVENT_TYPES: &[&str] = &["loaded", "unloaded", "error", "kv.changed"];
+#[cfg(feature = "native")]
+const EVENT_RATE_WINDOW: Duration = Duration::from_secs(1);
+/// Fuel charged per host call on top of the guest's own instructions, plus
+/// one unit per byte passed across the boundary.
+#[cfg(feature = "native")]
+const HOST_CALL_FUEL: u64 = 1_000;
 
 #[cfg(feature = "native")]
 type BlockingTask = Box<dyn FnOnce() + Send + 'static>;
@@ -46,6 +68,15 @@ pub struct PluginRuntimeConfig {
     pub fuel_per_render: u64,
     pub epoch_timeout_ms: u64,
     pub max_memory_bytes: u64,
+    /// Limits on what each plugin may keep in KV storage.
+    pub kv_quota: KvQuota,
+    /// Limits on the custom events each plugin may publish.
+    pub event_quota: EventQuota,
+    /// Overrides of the limits above for individual plugins, keyed by ID.
+    pub plugin_limits: BTreeMap<String, PluginLimits>,
+    /// Failures within `error_window_secs` after which a plugin is disabled.
+    pub error_threshold: u32,
+    pub error_window_secs: u64,
 }
 
 impl Default for PluginRuntimeConfig {
@@ -55,6 +86,61 @@ impl Default for PluginRuntimeConfig {
             fuel_per_render: 5_000_000,
             epoch_timeout_ms: 5_000,
             max_memory_bytes: 16_777_216,
+            kv_quota: KvQuota::default(),
+            event_quota: EventQuota::default(),
+            plugin_limits: BTreeMap::new(),
+            error_threshold: 5,
+            error_window_secs: 60,
+        }
+    }
+}
+
+impl PluginRuntimeConfig {
+    pub fn fuel_per_invocation_for(&self, plugin_id: &str) -> u64 {
+        self.plugin_limits
+            .get(plugin_id)
+            .and_then(|limits| limits.fuel_per_invocation)
+            .unwrap_or(self.fuel_per_invocation)
+    }
+
+    pub fn fuel_per_render_for(&self, plugin_id: &str) -> u64 {
+        self.plugin_limits
+            .get(plugin_id)
+            .and_then(|limits| limits.fuel_per_render)
+            .unwrap_or(self.fuel_per_render)
+    }
+
+    pub fn max_memory_bytes_for(&self, plugin_id: &str) -> u64 {
+        self.plugin_limits
+            .get(plugin_id)
+            .and_then(|limits| limits.max_memory_bytes)
+            .unwrap_or(self.max_memory_bytes)
+    }
+}
+
+/// Sandbox limits for one plugin. Each one set replaces the matching
+/// global limit in [`PluginRuntimeConfig`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PluginLimits {
+    pub fuel_per_invocation: Option<u64>,
+    pub fuel_per_render: Option<u64>,
+    pub max_memory_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventQuota {
+    /// Largest JSON payload accepted, in bytes.
+    pub max_payload_bytes: u64,
+    /// Events accepted from one plugin in any one-second window; the rest
+    /// are refused.
+    pub max_per_second: u32,
+}
+
+impl Default for EventQuota {
+    fn default() -> Self {
+        Self {
+            max_payload_bytes: 65_536,
+            max_per_second: 50,
         }
     }
 }
@@ -85,6 +171,13 @@ pub enum PluginError {
     #[error("plugin {id} fuel exhausted")]
     FuelExhausted { id: String },
 
+    #[error("plugin {id} denied {permission}: {reason}")]
+    PermissionDenied {
+        id: String,
+        permission: String,
+        reason: String,
+    },
+
     #[error("plugin {id} epoch timeout")]
     EpochTimeout { id: String },
 
@@ -105,6 +198,12 @@ pub enum PluginError {
 
     #[error("failed to publish plugin event for {id}: {reason}")]
     EventPublishFailed { id: String, reason: String },
+
+    #[error("failed to subscribe plugins to {pattern}: {reason}")]
+    EventSubscriptionFailed { pattern: String, reason: String },
+
+    #[error("plugin {id} storage failed: {reason}")]
+    StorageFailed { id: String, reason: String },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -147,15 +246,46 @@ pub enum PluginHook {
     Event(Box<Event>),
     InboundStanza(String),
     OutboundStanza(String),
-    TuiRender { width: u16, height: u16 },
+    TuiRender {
+        width: u16,
+        height: u16,
+    },
+    /// Describe the plugin's Vue components. Returns a JSON
+    /// [`GuiComponentManifest`].
     GuiGetComponentInfo,
     /// Transform a message body: detect URLs, produce embed descriptors.
     /// Returns JSON: `{"embeds":[{"namespace":"...","data":{...}}]}`
-    MessageTransform { body: String },
+    MessageTransform {
+        body: String,
+    },
     /// Render an embed for the TUI. Returns JSON array of styled spans.
-    RenderTui { embed_json: String, width: u16 },
+    RenderTui {
+        embed_json: String,
+        width: u16,
+    },
     /// Render an embed for the GUI. Returns an HTML fragment string.
-    RenderGui { embed_json: String },
+    RenderGui {
+        embed_json: String,
+    },
+    /// Run `hook` on `plugin_id` for a rule's plugin-hook action. The guest
+    /// gets `{"ruleId", "hook", "data"}` as JSON.
+    Rule {
+        plugin_id: String,
+        rule_id: String,
+        hook: String,
+        data: serde_json::Value,
+    },
+}
+
+/// Result of handing a stanza to a stanza processor plugin. Guests export
+/// `plugin_process_inbound(ptr, len) -> i32` (and the outbound equivalent)
+/// returning 0 to pass the stanza through, 1 to replace it with the result
+/// buffer, or 2 to consume it. Exports taking no arguments only observe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StanzaVerdict {
+    PassThrough,
+    Modify(String),
+    Consume,
 }
 
 #[cfg(feature = "native")]
@@ -163,15 +293,126 @@ struct PluginStoreState {
     plugin_id: String,
     limits: StoreLimits,
     event_bus: Arc<dyn EventBus>,
-    declared_event_subscriptions: Vec<String>,
+    /// Permissions declared in the plugin manifest.
+    permissions: PluginPermissions,
     event_subscription_patterns: Vec<String>,
     event_subscriptions: Vec<Pattern>,
-    /// Allowed HTTP hosts for host-http.fetch calls.
-    http_hosts: Vec<String>,
     /// Buffer for last host-http response body.
     http_response_body: Vec<u8>,
     /// Status code of last host-http response.
     http_response_status: i32,
+    host_api: Option<Arc<dyn PluginHostApi>>,
+    /// Output of the last host-roster or host-kv call, copied out with
+    /// host-result.read.
+    host_result: Vec<u8>,
+    /// The plugin's KV entries. Writes land here at once and are queued in
+    /// `kv_writes` until the runtime flushes them to storage.
+    kv: BTreeMap<String, Vec<u8>>,
+    kv_writes: Vec<(String, Vec<u8>)>,
+    kv_quota: KvQuota,
+    event_quota: EventQuota,
+    /// When recently accepted custom events were published, oldest first.
+    published_events: VecDeque<Instant>,
+    /// Start and fuel budget of the invocation in progress, until its usage
+    /// is recorded.
+    invocation: Option<(Instant, u64)>,
+    usage: UsageCounters,
+}
+
+/// Resources a plugin has consumed across all its invocations.
+#[cfg(feature = "native")]
+#[derive(Debug, Clone, Copy, Default)]
+struct UsageCounters {
+    invocations: u64,
+    fuel_consumed: u64,
+    wall_time: Duration,
+    memory_high_water_bytes: u64,
+}
+
+#[cfg(feature = "native")]
+impl UsageCounters {
+    fn add(&mut self, other: &Self) {
+        self.invocations += other.invocations;
+        self.fuel_consumed += other.fuel_consumed;
+        self.wall_time += other.wall_time;
+        self.memory_high_water_bytes = self
+            .memory_high_water_bytes
+            .max(other.memory_high_water_bytes);
+    }
+}
+
+#[cfg(feature = "native")]
+impl PluginStoreState {
+    /// Refuse `permission` unless the manifest declares it. Denials are
+    /// published on `plugin.permission.denied`.
+    fn require(&self, permission: PluginPermission, action: &str) -> Result<(), PluginError> {
+        if self.permissions.allows(&permission) {
+            return Ok(());
+        }
+
+        let reason = format!("{action} requires {permission} in the manifest");
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channels::PLUGIN_PERMISSION_DENIED).unwrap(),
+            EventSource::System("plugins".to_string()),
+            EventPayload::PluginPermissionDenied {
+                plugin_id: self.plugin_id.clone(),
+                permission: permission.to_string(),
+                detail: reason.clone(),
+            },
+        ));
+        Err(PluginError::PermissionDenied {
+            id: self.plugin_id.clone(),
+            permission: permission.manifest_field().to_string(),
+            reason,
+        })
+    }
+
+    /// Count a custom event against the rate limit, or refuse it when the
+    /// plugin has used up the current window.
+    fn admit_event(&mut self) -> Result<(), String> {
+        let now = Instant::now();
+        while self
+            .published_events
+            .front()
+            .is_some_and(|published| now.duration_since(*published) >= EVENT_RATE_WINDOW)
+        {
+            self.published_events.pop_front();
+        }
+
+        let limit = self.event_quota.max_per_second;
+        if self.published_events.len() >= limit as usize {
+            return Err(format!("rate limit of {limit} events per second exceeded"));
+        }
+        self.published_events.push_back(now);
+        Ok(())
+    }
+}
+
+/// Failure of a host function called by a guest. Permission denials trap the
+/// guest; other failures are reported to it as a status code.
+#[cfg(feature = "native")]
+enum HostCallError {
+    Failed(String),
+    Denied(PluginError),
+}
+
+#[cfg(feature = "native")]
+impl From<String> for HostCallError {
+    fn from(reason: String) -> Self {
+        Self::Failed(reason)
+    }
+}
+
+#[cfg(feature = "native")]
+fn host_status(result: Result<i32, HostCallError>, failure_status: i32) -> wasmtime::Result<i32> {
+    match result {
+        Ok(status) => Ok(status),
+        Err(HostCallError::Failed(reason)) => {
+            debug!(%reason, "plugin host call failed");
+            Ok(failure_status)
+        }
+        Err(HostCallError::Denied(error)) => Err(wasmtime::Error::new(error)),
+    }
 }
 
 #[cfg(feature = "native")]
@@ -193,17 +434,28 @@ enum RuntimeHook {
     Status(TypedFunc<(), i32>),
 }
 
+#[cfg(feature = "native")]
+#[derive(Clone)]
+enum StanzaHook {
+    Observe(RuntimeHook),
+    Process(TypedFunc<(i32, i32), i32>),
+}
+
 #[cfg(feature = "native")]
 struct LoadedPlugin {
     store: Store<PluginStoreState>,
     instance: Instance,
     shutdown: LifecycleShutdown,
     event_handler: Option<RuntimeHook>,
-    process_inbound: Option<RuntimeHook>,
-    process_outbound: Option<RuntimeHook>,
+    process_inbound: Option<StanzaHook>,
+    process_outbound: Option<StanzaHook>,
     message_transform: Option<TypedFunc<(i32, i32), i32>>,
     render_tui: Option<TypedFunc<(i32, i32, i32), i32>>,
     render_gui: Option<TypedFunc<(i32, i32), i32>>,
+    gui_component_info: Option<TypedFunc<(), i32>>,
+    rule_hook: Option<TypedFunc<(i32, i32), i32>>,
+    /// The `.vue` files listed under `[gui]` in the manifest.
+    gui_components: Vec<String>,
     /// guest_alloc(size) -> ptr — plugin-exported allocator for passing data in.
     guest_alloc: Option<TypedFunc<i32, i32>>,
 }
@@ -234,6 +486,29 @@ impl LoadedPlugin {
         }
     }
 
+    /// Replace the instance's view of its KV entries, dropping writes not
+    /// yet flushed.
+    fn replace_kv(&mut self, kv: BTreeMap<String, Vec<u8>>) {
+        let state = self.store.data_mut();
+        state.kv = kv;
+        state.kv_writes.clear();
+    }
+
+    fn take_kv_writes(&mut self) -> Vec<(String, Vec<u8>)> {
+        std::mem::take(&mut self.store.data_mut().kv_writes)
+    }
+
+    /// Apply writes made by another instance of this plugin, except for keys
+    /// this instance has since written itself.
+    fn adopt_kv_writes(&mut self, writes: &[(String, Vec<u8>)]) {
+        let state = self.store.data_mut();
+        for (key, value) in writes {
+            if !state.kv_writes.iter().any(|(written, _)| written == key) {
+                state.kv.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
     fn matches_event_subscription(&self, channel: &str) -> bool {
         self.store
             .data()
@@ -242,6 +517,27 @@ impl LoadedPlugin {
             .any(|pattern| pattern.matches(channel))
     }
 
+    fn event_subscription_patterns(&self) -> &[String] {
+        &self.store.data().event_subscription_patterns
+    }
+
+    fn record_usage(&mut self) {
+        record_usage(&mut self.store, &self.instance);
+    }
+
+    fn usage(&self) -> PluginUsage {
+        let state = self.store.data();
+        PluginUsage {
+            plugin_id: state.plugin_id.clone(),
+            invocations: state.usage.invocations,
+            fuel_consumed: state.usage.fuel_consumed,
+            wall_time_micros: u64::try_from(state.usage.wall_time.as_micros()).unwrap_or(u64::MAX),
+            memory_high_water_bytes: state.usage.memory_high_water_bytes,
+            kv_keys: state.kv.len() as u64,
+            kv_bytes: state.kv.values().map(|value| value.len() as u64).sum(),
+        }
+    }
+
     fn invoke_event_handler(&mut self, fuel_per_invocation: u64) -> Result<(), PluginError> {
         self.invoke_hook(
             "event handler",
@@ -250,22 +546,78 @@ impl LoadedPlugin {
         )
     }
 
-    fn invoke_inbound_stanza(&mut self, fuel_per_invocation: u64) -> Result<(), PluginError> {
-        self.invoke_hook(
+    fn invoke_inbound_stanza(
+        &mut self,
+        xml: &str,
+        fuel_per_invocation: u64,
+    ) -> Result<StanzaVerdict, PluginError> {
+        self.invoke_stanza_hook(
             "stanza inbound processor",
             self.process_inbound.clone(),
+            xml,
             fuel_per_invocation,
         )
     }
 
-    fn invoke_outbound_stanza(&mut self, fuel_per_invocation: u64) -> Result<(), PluginError> {
-        self.invoke_hook(
+    fn invoke_outbound_stanza(
+        &mut self,
+        xml: &str,
+        fuel_per_invocation: u64,
+    ) -> Result<StanzaVerdict, PluginError> {
+        self.invoke_stanza_hook(
             "stanza outbound processor",
             self.process_outbound.clone(),
+            xml,
             fuel_per_invocation,
         )
     }
 
+    fn invoke_stanza_hook(
+        &mut self,
+        hook_name: &str,
+        hook: Option<StanzaHook>,
+        xml: &str,
+        fuel_per_invocation: u64,
+    ) -> Result<StanzaVerdict, PluginError> {
+        let func = match hook {
+            None => return Ok(StanzaVerdict::PassThrough),
+            Some(StanzaHook::Observe(hook)) => {
+                self.store
+                    .data()
+                    .require(PluginPermission::StanzaAccess, hook_name)?;
+                return self
+                    .invoke_hook(hook_name, Some(hook), fuel_per_invocation)
+                    .map(|_| StanzaVerdict::PassThrough);
+            }
+            Some(StanzaHook::Process(func)) => func,
+        };
+
+        self.store
+            .data()
+            .require(PluginPermission::StanzaAccess, hook_name)?;
+        let plugin_id = self.store.data().plugin_id.clone();
+        let (ptr, len) = self.write_guest_bytes(xml.as_bytes())?;
+        prepare_invocation(&mut self.store, &plugin_id, fuel_per_invocation)?;
+        let status = func
+            .call(&mut self.store, (ptr, len))
+            .map_err(|error| classify_invocation_error(&plugin_id, error))?;
+        match status {
+            0 => Ok(StanzaVerdict::PassThrough),
+            1 => match self.read_guest_result()? {
+                Some(xml) => Ok(StanzaVerdict::Modify(xml)),
+                None => Err(PluginError::InvocationFailed {
+                    id: plugin_id,
+                    reason: format!("{hook_name} modified the stanza without a result"),
+                }),
+            },
+            2 => Ok(StanzaVerdict::Consume),
+            status => Err(PluginError::InvocationFailed {
+                id: plugin_id,
+                reason: format!("non-zero {hook_name} status: {status}"),
+            }),
+        }
+    }
+
     fn invoke_hook(
         &mut self,
         hook_name: &str,
@@ -331,12 +683,13 @@ impl LoadedPlugin {
             id: plugin_id.clone(),
             reason: "write_guest_bytes: negative pointer from guest_alloc".to_string(),
         })?;
-        let end = start.checked_add(data.len()).ok_or_else(|| {
-            PluginError::MemoryLimitExceeded {
-                id: plugin_id.clone(),
-                reason: "write_guest_bytes: pointer + length overflow".to_string(),
-            }
-        })?;
+        let end =
+            start
+                .checked_add(data.len())
+                .ok_or_else(|| PluginError::MemoryLimitExceeded {
+                    id: plugin_id.clone(),
+                    reason: "write_guest_bytes: pointer + length overflow".to_string(),
+                })?;
         if end > mem_data.len() {
             return Err(PluginError::MemoryLimitExceeded {
                 id: plugin_id,
@@ -384,23 +737,25 @@ impl LoadedPlugin {
         // so the `as usize` casts are value-preserving.  Use checked_add
         // to prevent overflow from a malicious guest returning huge values.
         let start = ptr as usize;
-        let end = start.checked_add(len as usize).ok_or_else(|| {
-            PluginError::MemoryLimitExceeded {
-                id: plugin_id.clone(),
-                reason: "read_guest_result: pointer + length overflow".to_string(),
-            }
-        })?;
+        let end =
+            start
+                .checked_add(len as usize)
+                .ok_or_else(|| PluginError::MemoryLimitExceeded {
+                    id: plugin_id.clone(),
+                    reason: "read_guest_result: pointer + length overflow".to_string(),
+                })?;
         if end > data.len() {
             return Err(PluginError::MemoryLimitExceeded {
                 id: plugin_id,
                 reason: "read_guest_result: out of bounds".to_string(),
             });
         }
-        let result = std::str::from_utf8(&data[start..end])
-            .map_err(|error| PluginError::InvocationFailed {
+        let result = std::str::from_utf8(&data[start..end]).map_err(|error| {
+            PluginError::InvocationFailed {
                 id: plugin_id,
                 reason: format!("result is not valid UTF-8: {error}"),
-            })?;
+            }
+        })?;
         Ok(Some(result.to_string()))
     }
 
@@ -413,6 +768,9 @@ impl LoadedPlugin {
         let Some(func) = self.message_transform.clone() else {
             return Ok(None);
         };
+        self.store
+            .data()
+            .require(PluginPermission::StanzaAccess, "message transformer")?;
         let plugin_id = self.store.data().plugin_id.clone();
         let (ptr, len) = self.write_guest_bytes(body.as_bytes())?;
         prepare_invocation(&mut self.store, &plugin_id, fuel)?;
@@ -453,6 +811,45 @@ impl LoadedPlugin {
         self.read_guest_result()
     }
 
+    /// Invoke gui_component_info: call plugin_gui_component_info, read result.
+    fn invoke_gui_component_info(&mut self, fuel: u64) -> Result<Option<String>, PluginError> {
+        let Some(func) = self.gui_component_info.clone() else {
+            return Ok(None);
+        };
+        let plugin_id = self.store.data().plugin_id.clone();
+        prepare_invocation(&mut self.store, &plugin_id, fuel)?;
+        let status = func
+            .call(&mut self.store, ())
+            .map_err(|error| classify_invocation_error(&plugin_id, error))?;
+        if status != 0 {
+            return Err(PluginError::InvocationFailed {
+                id: plugin_id,
+                reason: format!("non-zero gui_component_info status: {status}"),
+            });
+        }
+        self.read_guest_result()
+    }
+
+    /// Invoke rule_hook: write the request JSON to guest, call plugin_rule_hook.
+    fn invoke_rule_hook(&mut self, request: &str, fuel: u64) -> Result<(), PluginError> {
+        let Some(func) = self.rule_hook.clone() else {
+            return Ok(());
+        };
+        let plugin_id = self.store.data().plugin_id.clone();
+        let (ptr, len) = self.write_guest_bytes(request.as_bytes())?;
+        prepare_invocation(&mut self.store, &plugin_id, fuel)?;
+        let status = func
+            .call(&mut self.store, (ptr, len))
+            .map_err(|error| classify_invocation_error(&plugin_id, error))?;
+        if status != 0 {
+            return Err(PluginError::InvocationFailed {
+                id: plugin_id,
+                reason: format!("non-zero rule_hook status: {status}"),
+            });
+        }
+        Ok(())
+    }
+
     /// Invoke render_gui: write embed JSON to guest, call plugin_render_gui, read result.
     fn invoke_render_gui(
         &mut self,
@@ -593,6 +990,8 @@ pub struct PluginRuntime<D: Database> {
     #[cfg(feature = "native")]
     blocking_pool: BlockingPool,
     #[cfg(feature = "native")]
+    host_api: Option<Arc<dyn PluginHostApi>>,
+    #[cfg(feature = "native")]
     _epoch_ticker: EpochTicker,
 }
 
@@ -621,6 +1020,7 @@ impl<D: Database> PluginRuntime<D> {
             error_windows: BTreeMap::new(),
             disabled_plugins: BTreeSet::new(),
             blocking_pool,
+            host_api: None,
             _epoch_ticker: epoch_ticker,
         }
     }
@@ -647,6 +1047,23 @@ impl<D: Database> PluginRuntime<D> {
         &self.db
     }
 
+    /// Serve host-roster calls from `api`, for plugins already loaded and
+    /// those loaded later.
+    pub fn set_host_api(&mut self, api: Arc<dyn PluginHostApi>) {
+        #[cfg(feature = "native")]
+        {
+            for plugin in self.runtime_plugins.values_mut() {
+                plugin.store.data_mut().host_api = Some(Arc::clone(&api));
+            }
+            self.host_api = Some(api);
+        }
+
+        #[cfg(not(feature = "native"))]
+        {
+            let _ = api;
+        }
+    }
+
     pub async fn load_plugin(
         &mut self,
         manifest: PluginManifest,
@@ -686,21 +1103,35 @@ impl<D: Database> PluginRuntime<D> {
                 },
             );
 
-            let engine = self.engine.clone();
-            let config = self.config.clone();
-            let manifest_for_task = manifest.clone();
-            let event_bus = Arc::clone(&self.event_bus);
-            let wasm = wasm_bytes.to_vec();
-            let load_result = self
-                .run_blocking_task(plugin_id.clone(), move || {
-                    compile_and_init_plugin(engine, config, event_bus, manifest_for_task, wasm)
-                })
-                .await;
+            let load_result = match self.load_kv_entries(&manifest).await {
+                Ok(kv) => {
+                    let engine = self.engine.clone();
+                    let config = self.config.clone();
+                    let manifest_for_task = manifest.clone();
+                    let event_bus = Arc::clone(&self.event_bus);
+                    let host_api = self.host_api.clone();
+                    let wasm = wasm_bytes.to_vec();
+                    self.run_blocking_task(plugin_id.clone(), move || {
+                        compile_and_init_plugin(
+                            engine,
+                            config,
+                            event_bus,
+                            host_api,
+                            kv,
+                            manifest_for_task,
+                            wasm,
+                        )
+                    })
+                    .await
+                }
+                Err(error) => Err(error),
+            };
 
             match load_result {
                 Ok(loaded_plugin) => {
                     self.runtime_plugins
                         .insert(plugin_id.clone(), loaded_plugin);
+                    self.flush_kv_writes(&plugin_id).await;
                     self.error_windows.remove(&plugin_id);
                     self.disabled_plugins.remove(&plugin_id);
 
@@ -760,15 +1191,16 @@ impl<D: Database> PluginRuntime<D> {
             let task_plugin_id = plugin_id.clone();
             let shutdown_result = self
                 .run_blocking_task(plugin_id.clone(), move || {
-                    let _ = &task_plugin_id;
-                    loaded_plugin.shutdown(config.fuel_per_invocation)
+                    loaded_plugin.shutdown(config.fuel_per_invocation_for(&task_plugin_id))?;
+                    Ok(loaded_plugin.take_kv_writes())
                 })
                 .await;
 
             self.plugins.remove(&plugin_id);
 
             match shutdown_result {
-                Ok(()) => {
+                Ok(kv_writes) => {
+                    self.write_kv_entries(&plugin_id, kv_writes).await;
                     let _ = self.emit_plugin_unloaded(&plugin_id);
                     Ok(())
                 }
@@ -796,6 +1228,132 @@ impl<D: Database> PluginRuntime<D> {
         }
     }
 
+    /// Swap a loaded plugin's wasm module for a new build in place.
+    ///
+    /// The new module is compiled and initialised before the old instance is
+    /// shut down, so a build that fails to load leaves the running one in
+    /// service; such failures are reported but do not count towards
+    /// auto-disabling it. Hook invocations borrow the runtime mutably, which means none
+    /// are in flight while the swap happens. KV data is keyed by plugin ID
+    /// and carries over untouched.
+    pub async fn reload_plugin(
+        &mut self,
+        plugin_id: &str,
+        manifest: PluginManifest,
+        wasm_bytes: &[u8],
+    ) -> Result<PluginHandle, PluginError> {
+        #[cfg(feature = "native")]
+        {
+            let plugin_id = plugin_id.to_string();
+            if manifest.id() != plugin_id {
+                return Err(PluginError::InvalidManifest {
+                    id: plugin_id,
+                    reason: format!("manifest is for plugin {}", manifest.id()),
+                });
+            }
+            manifest
+                .validate()
+                .map_err(|error| PluginError::InvalidManifest {
+                    id: plugin_id.clone(),
+                    reason: error.to_string(),
+                })?;
+
+            if !self.runtime_plugins.contains_key(&plugin_id) {
+                return Err(PluginError::NotFound { id: plugin_id });
+            }
+
+            let plugin_name = manifest.name().to_string();
+            let plugin_version = manifest.version().to_string();
+            let capabilities = map_capabilities(&manifest);
+
+            let load_result = match self.load_kv_entries(&manifest).await {
+                Ok(kv) => {
+                    let engine = self.engine.clone();
+                    let config = self.config.clone();
+                    let event_bus = Arc::clone(&self.event_bus);
+                    let host_api = self.host_api.clone();
+                    let wasm = wasm_bytes.to_vec();
+                    self.run_blocking_task(plugin_id.clone(), move || {
+                        compile_and_init_plugin(
+                            engine, config, event_bus, host_api, kv, manifest, wasm,
+                        )
+                    })
+                    .await
+                }
+                Err(error) => Err(error),
+            };
+            let replacement = match load_result {
+                Ok(replacement) => replacement,
+                Err(error) => {
+                    let _ = self.emit_plugin_error(&plugin_id, &error.to_string());
+                    return Err(error);
+                }
+            };
+
+            let Some(mut previous) = self.runtime_plugins.insert(plugin_id.clone(), replacement)
+            else {
+                return Err(PluginError::NotFound { id: plugin_id });
+            };
+            // Usage is counted per plugin, not per build.
+            if let Some(plugin) = self.runtime_plugins.get_mut(&plugin_id) {
+                plugin
+                    .store
+                    .data_mut()
+                    .usage
+                    .add(&previous.store.data().usage);
+            }
+            let fuel = self.config.fuel_per_invocation_for(&plugin_id);
+            let shutdown_result = self
+                .run_blocking_task(plugin_id.clone(), move || {
+                    previous.shutdown(fuel)?;
+                    Ok(previous.take_kv_writes())
+                })
+                .await;
+            match shutdown_result {
+                Ok(kv_writes) => {
+                    // The replacement read its KV entries before the old
+                    // instance shut down; carry over what it wrote since.
+                    if let Some(plugin) = self.runtime_plugins.get_mut(&plugin_id) {
+                        plugin.adopt_kv_writes(&kv_writes);
+                    }
+                    self.write_kv_entries(&plugin_id, kv_writes).await;
+                }
+                Err(error) => {
+                    let _ = self.emit_plugin_error(&plugin_id, &error.to_string());
+                }
+            }
+            self.flush_kv_writes(&plugin_id).await;
+            let _ = self.emit_plugin_unloaded(&plugin_id);
+
+            self.error_windows.remove(&plugin_id);
+            self.disabled_plugins.remove(&plugin_id);
+            self.plugins.insert(
+                plugin_id.clone(),
+                PluginInfo {
+                    id: plugin_id.clone(),
+                    name: plugin_name.clone(),
+                    version: plugin_version.clone(),
+                    status: PluginStatus::Active,
+                    capabilities,
+                    error_count: 0,
+                },
+            );
+            let _ = self.emit_plugin_loaded(&plugin_id, &plugin_version);
+
+            Ok(PluginHandle {
+                id: plugin_id,
+                name: plugin_name,
+                version: plugin_version,
+            })
+        }
+
+        #[cfg(not(feature = "native"))]
+        {
+            let _ = (plugin_id, manifest, wasm_bytes);
+            Err(PluginError::NotImplemented)
+        }
+    }
+
     pub fn list_plugins(&self) -> Vec<PluginInfo> {
         self.plugins.values().cloned().collect()
     }
@@ -804,25 +1362,188 @@ impl<D: Database> PluginRuntime<D> {
         self.plugins.get(plugin_id)
     }
 
-    /// Invoke a hook on all matching plugins. Fire-and-forget hooks return `None`.
-    /// Bidirectional hooks (`MessageTransform`, `RenderTui`, `RenderGui`) return
-    /// the result from the **first** plugin that produces output.
-    pub async fn invoke_hook(
-        &mut self,
-        hook: PluginHook,
-    ) -> Result<Option<String>, PluginError> {
+    /// Whether `plugin_id` has been disabled for failing too often. A
+    /// disabled plugin receives no hooks until it is re-enabled or reloaded.
+    pub fn is_disabled(&self, plugin_id: &str) -> bool {
         #[cfg(feature = "native")]
         {
-            if self.runtime_plugins.is_empty() {
-                return Ok(None);
-            }
-
-            let plugin_ids: Vec<String> = self.runtime_plugins.keys().cloned().collect();
-            let mut failures = Vec::new();
-            let mut result: Option<String> = None;
+            self.disabled_plugins.contains(plugin_id)
+        }
 
-            for plugin_id in plugin_ids {
-                let invocation_result: Result<Option<String>, PluginError> = match &hook {
+        #[cfg(not(feature = "native"))]
+        {
+            let _ = plugin_id;
+            false
+        }
+    }
+
+    /// Resume dispatching hooks to a plugin disabled for failing too often,
+    /// with its error count reset. A plugin disabled while loading is only
+    /// allowed to load again.
+    pub fn re_enable(&mut self, plugin_id: &str) -> Result<(), PluginError> {
+        #[cfg(feature = "native")]
+        {
+            if !self.disabled_plugins.remove(plugin_id) {
+                return if self.plugins.contains_key(plugin_id) {
+                    Ok(())
+                } else {
+                    Err(PluginError::NotFound {
+                        id: plugin_id.to_string(),
+                    })
+                };
+            }
+            self.error_windows.remove(plugin_id);
+
+            if let Some(plugin_info) = self.plugins.get_mut(plugin_id) {
+                plugin_info.status = PluginStatus::Active;
+                plugin_info.error_count = 0;
+                let version = plugin_info.version.clone();
+                // Back in service, as far as subscribers are concerned.
+                let _ = self.emit_plugin_loaded(plugin_id, &version);
+            }
+            Ok(())
+        }
+
+        #[cfg(not(feature = "native"))]
+        {
+            let _ = plugin_id;
+            Err(PluginError::NotImplemented)
+        }
+    }
+
+    /// Replace the limit overrides for `plugin_id`. Fuel limits apply from
+    /// its next invocation and the memory limit from its next allocation.
+    pub fn set_plugin_limits(&mut self, plugin_id: &str, limits: PluginLimits) {
+        if limits == PluginLimits::default() {
+            self.config.plugin_limits.remove(plugin_id);
+        } else {
+            self.config
+                .plugin_limits
+                .insert(plugin_id.to_string(), limits);
+        }
+
+        #[cfg(feature = "native")]
+        if let Some(plugin) = self.runtime_plugins.get_mut(plugin_id) {
+            plugin.store.data_mut().limits =
+                store_limits(self.config.max_memory_bytes_for(plugin_id));
+        }
+    }
+
+    /// What `plugin_id` has consumed since it was loaded, or `None` when it
+    /// is not loaded.
+    #[cfg(feature = "native")]
+    pub fn usage(&self, plugin_id: &str) -> Option<PluginUsage> {
+        self.runtime_plugins.get(plugin_id).map(LoadedPlugin::usage)
+    }
+
+    /// Usage of every loaded plugin.
+    #[cfg(feature = "native")]
+    pub fn usage_snapshot(&self) -> Vec<PluginUsage> {
+        self.runtime_plugins
+            .values()
+            .map(LoadedPlugin::usage)
+            .collect()
+    }
+
+    /// Publish [`PluginRuntime::usage_snapshot`] on `plugin.usage.snapshot`
+    /// every `interval`.
+    #[cfg(feature = "native")]
+    pub async fn report_usage(
+        runtime: Arc<tokio::sync::Mutex<Self>>,
+        interval: Duration,
+    ) -> Result<(), PluginError> {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let guard = runtime.lock().await;
+            let published = guard.event_bus.publish(Event::new(
+                Channel::new(channels::PLUGIN_USAGE_SNAPSHOT).unwrap(),
+                EventSource::System("plugins".to_string()),
+                EventPayload::PluginUsageSnapshot {
+                    plugins: guard.usage_snapshot(),
+                },
+            ));
+            if let Err(EventBusError::ChannelClosed) = published {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Everything `plugin_id` has stored, including writes not yet flushed,
+    /// so it can be backed up before the plugin is uninstalled.
+    pub async fn export_plugin_data(&mut self, plugin_id: &str) -> Result<KvExport, PluginError> {
+        #[cfg(feature = "native")]
+        self.flush_kv_writes(plugin_id).await;
+        self.kv_store(plugin_id)
+            .export()
+            .await
+            .map_err(|error| kv_failed(plugin_id, error))
+    }
+
+    /// Restore data from [`PluginRuntime::export_plugin_data`], or data a
+    /// plugin author migrated from an older version. A loaded plugin sees it
+    /// from its next invocation.
+    pub async fn import_plugin_data(
+        &mut self,
+        plugin_id: &str,
+        data: &KvExport,
+    ) -> Result<(), PluginError> {
+        #[cfg(feature = "native")]
+        self.flush_kv_writes(plugin_id).await;
+        let store = self.kv_store(plugin_id);
+        store
+            .import(data)
+            .await
+            .map_err(|error| kv_failed(plugin_id, error))?;
+
+        #[cfg(feature = "native")]
+        if self.runtime_plugins.contains_key(plugin_id) {
+            let entries = store
+                .entries()
+                .await
+                .map_err(|error| kv_failed(plugin_id, error))?;
+            if let Some(plugin) = self.runtime_plugins.get_mut(plugin_id) {
+                plugin.replace_kv(entries.into_iter().collect());
+            }
+        }
+        Ok(())
+    }
+
+    /// Wipe the KV namespace of `plugin_id`, including what a loaded
+    /// instance holds in memory.
+    pub async fn reset_plugin_data(&mut self, plugin_id: &str) -> Result<(), PluginError> {
+        #[cfg(feature = "native")]
+        if let Some(plugin) = self.runtime_plugins.get_mut(plugin_id) {
+            plugin.replace_kv(BTreeMap::new());
+        }
+        self.kv_store(plugin_id)
+            .clear_all()
+            .await
+            .map_err(|error| kv_failed(plugin_id, error))
+    }
+
+    /// Invoke a hook on all matching plugins. Fire-and-forget hooks return `None`.
+    /// Bidirectional hooks (`MessageTransform`, `RenderTui`, `RenderGui`) return
+    /// the result from the **first** plugin that produces output.
+    pub async fn invoke_hook(&mut self, hook: PluginHook) -> Result<Option<String>, PluginError> {
+        #[cfg(feature = "native")]
+        {
+            if self.runtime_plugins.is_empty() {
+                return Ok(None);
+            }
+
+            let plugin_ids: Vec<String> = self
+                .runtime_plugins
+                .keys()
+                .filter(|plugin_id| !self.disabled_plugins.contains(*plugin_id))
+                .cloned()
+                .collect();
+            let mut failures = Vec::new();
+            let mut result: Option<String> = None;
+
+            for plugin_id in plugin_ids {
+                let invocation_result: Result<Option<String>, PluginError> = match &hook {
                     PluginHook::Event(event) => {
                         let Some(plugin) = self.runtime_plugins.get_mut(&plugin_id) else {
                             continue;
@@ -831,45 +1552,96 @@ impl<D: Database> PluginRuntime<D> {
                             continue;
                         }
                         plugin
-                            .invoke_event_handler(self.config.fuel_per_invocation)
+                            .invoke_event_handler(self.config.fuel_per_invocation_for(&plugin_id))
                             .map(|_| None)
                     }
-                    PluginHook::InboundStanza(_xml) => {
+                    PluginHook::InboundStanza(xml) => {
                         let Some(plugin) = self.runtime_plugins.get_mut(&plugin_id) else {
                             continue;
                         };
                         plugin
-                            .invoke_inbound_stanza(self.config.fuel_per_invocation)
+                            .invoke_inbound_stanza(
+                                xml,
+                                self.config.fuel_per_invocation_for(&plugin_id),
+                            )
                             .map(|_| None)
                     }
-                    PluginHook::OutboundStanza(_xml) => {
+                    PluginHook::OutboundStanza(xml) => {
                         let Some(plugin) = self.runtime_plugins.get_mut(&plugin_id) else {
                             continue;
                         };
                         plugin
-                            .invoke_outbound_stanza(self.config.fuel_per_invocation)
+                            .invoke_outbound_stanza(
+                                xml,
+                                self.config.fuel_per_invocation_for(&plugin_id),
+                            )
                             .map(|_| None)
                     }
-                    PluginHook::TuiRender { .. } | PluginHook::GuiGetComponentInfo => Ok(None),
+                    PluginHook::TuiRender { .. } => Ok(None),
+                    PluginHook::GuiGetComponentInfo => {
+                        let Some(plugin) = self.runtime_plugins.get_mut(&plugin_id) else {
+                            continue;
+                        };
+                        plugin.invoke_gui_component_info(
+                            self.config.fuel_per_invocation_for(&plugin_id),
+                        )
+                    }
                     PluginHook::MessageTransform { body } => {
                         let Some(plugin) = self.runtime_plugins.get_mut(&plugin_id) else {
                             continue;
                         };
-                        plugin.invoke_message_transform(body, self.config.fuel_per_invocation)
+                        plugin.invoke_message_transform(
+                            body,
+                            self.config.fuel_per_invocation_for(&plugin_id),
+                        )
                     }
                     PluginHook::RenderTui { embed_json, width } => {
                         let Some(plugin) = self.runtime_plugins.get_mut(&plugin_id) else {
                             continue;
                         };
-                        plugin.invoke_render_tui(embed_json, *width, self.config.fuel_per_render)
+                        plugin.invoke_render_tui(
+                            embed_json,
+                            *width,
+                            self.config.fuel_per_render_for(&plugin_id),
+                        )
                     }
                     PluginHook::RenderGui { embed_json } => {
                         let Some(plugin) = self.runtime_plugins.get_mut(&plugin_id) else {
                             continue;
                         };
-                        plugin.invoke_render_gui(embed_json, self.config.fuel_per_render)
+                        plugin.invoke_render_gui(
+                            embed_json,
+                            self.config.fuel_per_render_for(&plugin_id),
+                        )
+                    }
+                    PluginHook::Rule {
+                        plugin_id: target,
+                        rule_id,
+                        hook,
+                        data,
+                    } => {
+                        if *target != plugin_id {
+                            continue;
+                        }
+                        let Some(plugin) = self.runtime_plugins.get_mut(&plugin_id) else {
+                            continue;
+                        };
+                        let request = serde_json::json!({
+                            "ruleId": rule_id,
+                            "hook": hook,
+                            "data": data,
+                        });
+                        plugin
+                            .invoke_rule_hook(
+                                &request.to_string(),
+                                self.config.fuel_per_invocation_for(&plugin_id),
+                            )
+                            .map(|_| None)
                     }
                 };
+                if let Some(plugin) = self.runtime_plugins.get_mut(&plugin_id) {
+                    plugin.record_usage();
+                }
 
                 match invocation_result {
                     Ok(Some(output)) if result.is_none() => {
@@ -882,14 +1654,11 @@ impl<D: Database> PluginRuntime<D> {
                 }
             }
 
+            for plugin_id in self.runtime_plugins.keys().cloned().collect::<Vec<_>>() {
+                self.flush_kv_writes(&plugin_id).await;
+            }
             for (plugin_id, error) in failures {
-                let reason = error.to_string();
-                let auto_disabled = self.record_plugin_error(&plugin_id, &reason);
-                let _ = self.emit_plugin_error(&plugin_id, &reason);
-
-                if auto_disabled {
-                    let _ = self.emit_plugin_error(&plugin_id, "auto-disabled: too many errors");
-                }
+                self.report_plugin_failure(&plugin_id, &error);
             }
 
             Ok(result)
@@ -902,6 +1671,242 @@ impl<D: Database> PluginRuntime<D> {
         }
     }
 
+    /// Every channel pattern a loaded plugin has subscribed to.
+    #[cfg(feature = "native")]
+    pub fn event_filters(&self) -> BTreeSet<String> {
+        self.runtime_plugins
+            .iter()
+            .filter(|(plugin_id, _)| !self.disabled_plugins.contains(*plugin_id))
+            .flat_map(|(_, plugin)| plugin.event_subscription_patterns().iter().cloned())
+            .collect()
+    }
+
+    /// Hand events from the bus to the plugins subscribed to them. A single
+    /// subscription covers the union of [`PluginRuntime::event_filters`], so
+    /// events no plugin asked for never wake the runtime; it is renewed
+    /// whenever a plugin is loaded, unloaded or fails. Rules' plugin-hook
+    /// requests go to the plugin they name, subscribed or not.
+    #[cfg(feature = "native")]
+    pub async fn dispatch_events(
+        runtime: Arc<tokio::sync::Mutex<Self>>,
+    ) -> Result<(), PluginError> {
+        let event_bus = Arc::clone(runtime.lock().await.event_bus());
+        let subscribe = |pattern: &str| {
+            event_bus
+                .subscribe(pattern)
+                .map_err(|error| PluginError::EventSubscriptionFailed {
+                    pattern: pattern.to_string(),
+                    reason: error.to_string(),
+                })
+        };
+        // Subscribed before the filters are first read so no change is missed.
+        let mut lifecycle = subscribe(PLUGIN_LIFECYCLE_PATTERN)?;
+        let mut rule_hooks = subscribe(channels::SYSTEM_RULE_PLUGIN_HOOK)?;
+        let mut filters = BTreeSet::new();
+        let mut events = None;
+
+        loop {
+            let current = runtime.lock().await.event_filters();
+            if current != filters {
+                events = union_pattern(&current)
+                    .map(|pattern| subscribe(&pattern))
+                    .transpose()?;
+                debug!(filters = ?current, "plugin event filters changed");
+                filters = current;
+            }
+
+            loop {
+                tokio::select! {
+                    received = lifecycle.recv() => match received {
+                        Err(EventBusError::ChannelClosed) => return Ok(()),
+                        // Lagging may have hidden a change, so re-read either way.
+                        _ => break,
+                    },
+                    received = rule_hooks.recv() => match received {
+                        Ok(Event {
+                            payload:
+                                EventPayload::RulePluginHookRequested {
+                                    rule_id,
+                                    plugin_id,
+                                    hook,
+                                    data,
+                                },
+                            ..
+                        }) => {
+                            let hook = PluginHook::Rule {
+                                plugin_id,
+                                rule_id,
+                                hook,
+                                data,
+                            };
+                            if let Err(error) = runtime.lock().await.invoke_hook(hook).await {
+                                warn!(%error, "failed to run rule plugin hook");
+                            }
+                        }
+                        Err(EventBusError::ChannelClosed) => return Ok(()),
+                        _ => {}
+                    },
+                    received = recv_subscribed(&mut events) => match received {
+                        Ok(event) => {
+                            let hook = PluginHook::Event(Box::new(event));
+                            if let Err(error) = runtime.lock().await.invoke_hook(hook).await {
+                                warn!(%error, "failed to dispatch event to plugins");
+                            }
+                        }
+                        Err(EventBusError::ChannelClosed) => return Ok(()),
+                        Err(_) => {}
+                    },
+                }
+            }
+        }
+    }
+
+    /// Loaded plugins with the stanza processor capability and their
+    /// priority, lowest priority first.
+    pub fn stanza_processors(&self) -> Vec<(String, i32)> {
+        #[cfg(feature = "native")]
+        {
+            let mut processors: Vec<(String, i32)> = self
+                .runtime_plugins
+                .keys()
+                .filter(|plugin_id| !self.disabled_plugins.contains(*plugin_id))
+                .filter_map(|plugin_id| {
+                    let info = self.plugins.get(plugin_id)?;
+                    info.capabilities
+                        .iter()
+                        .find_map(|capability| match capability {
+                            PluginCapability::StanzaProcessor { priority } => {
+                                Some((plugin_id.clone(), *priority))
+                            }
+                            _ => None,
+                        })
+                })
+                .collect();
+            processors.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+            processors
+        }
+
+        #[cfg(not(feature = "native"))]
+        {
+            Vec::new()
+        }
+    }
+
+    /// Pass a stanza hook (`InboundStanza` or `OutboundStanza`) to a single
+    /// plugin under the per-invocation fuel limit. Failures count towards
+    /// auto-disabling the plugin, like those of [`Self::invoke_hook`].
+    pub async fn invoke_stanza_processor(
+        &mut self,
+        plugin_id: &str,
+        hook: &PluginHook,
+    ) -> Result<StanzaVerdict, PluginError> {
+        #[cfg(feature = "native")]
+        {
+            let fuel = self.config.fuel_per_invocation_for(plugin_id);
+            if self.disabled_plugins.contains(plugin_id) {
+                return Ok(StanzaVerdict::PassThrough);
+            }
+            let Some(plugin) = self.runtime_plugins.get_mut(plugin_id) else {
+                return Ok(StanzaVerdict::PassThrough);
+            };
+            let result = match hook {
+                PluginHook::InboundStanza(xml) => plugin.invoke_inbound_stanza(xml, fuel),
+                PluginHook::OutboundStanza(xml) => plugin.invoke_outbound_stanza(xml, fuel),
+                _ => Ok(StanzaVerdict::PassThrough),
+            };
+            plugin.record_usage();
+
+            self.flush_kv_writes(plugin_id).await;
+            if let Err(error) = &result {
+                self.report_plugin_failure(plugin_id, error);
+            }
+            result
+        }
+
+        #[cfg(not(feature = "native"))]
+        {
+            let _ = (plugin_id, hook);
+            Err(PluginError::NotImplemented)
+        }
+    }
+
+    /// The Vue components `plugin_id` contributes, or `None` when it is not
+    /// running or has no `gui_metadata` hook. A manifest naming components
+    /// the plugin did not declare counts as a failed invocation.
+    pub async fn gui_components(
+        &mut self,
+        plugin_id: &str,
+    ) -> Result<Option<GuiComponentManifest>, PluginError> {
+        #[cfg(feature = "native")]
+        {
+            let fuel = self.config.fuel_per_invocation_for(plugin_id);
+            if self.disabled_plugins.contains(plugin_id) {
+                return Ok(None);
+            }
+            let Some(plugin) = self.runtime_plugins.get_mut(plugin_id) else {
+                return Ok(None);
+            };
+            let result = plugin.invoke_gui_component_info(fuel).and_then(|json| {
+                json.map(|json| GuiComponentManifest::parse(&json, &plugin.gui_components))
+                    .transpose()
+                    .map_err(|reason| PluginError::InvocationFailed {
+                        id: plugin_id.to_string(),
+                        reason: format!("invalid gui component manifest: {reason}"),
+                    })
+            });
+            plugin.record_usage();
+
+            self.flush_kv_writes(plugin_id).await;
+            if let Err(error) = &result {
+                self.report_plugin_failure(plugin_id, error);
+            }
+            result
+        }
+
+        #[cfg(not(feature = "native"))]
+        {
+            let _ = plugin_id;
+            Err(PluginError::NotImplemented)
+        }
+    }
+
+    /// The frontend contributions of every installed plugin with the
+    /// `gui_metadata` hook. A running plugin that fails to describe its
+    /// components is listed without any.
+    #[cfg(feature = "native")]
+    pub async fn ui_contributions(
+        &mut self,
+        registry: &PluginRegistry,
+    ) -> Result<Vec<PluginUiContribution>, RegistryError> {
+        let mut contributions = Vec::new();
+        for installed in registry.list_installed()? {
+            let files = registry.get_plugin_files(&installed.id)?;
+            if !files.manifest.hooks.gui_metadata {
+                continue;
+            }
+
+            let active = self.runtime_plugins.contains_key(&installed.id)
+                && !self.disabled_plugins.contains(&installed.id);
+            let components = match self.gui_components(&installed.id).await {
+                Ok(components) => components,
+                Err(error) => {
+                    warn!(plugin_id = %installed.id, %error, "failed to get plugin gui components");
+                    None
+                }
+            };
+            contributions.push(PluginUiContribution {
+                plugin_id: installed.id,
+                name: installed.name,
+                version: installed.version,
+                vue_dir: files.vue_dir,
+                assets_dir: files.assets_dir,
+                active,
+                components: components.unwrap_or_default(),
+            });
+        }
+        Ok(contributions)
+    }
+
     #[cfg(feature = "native")]
     async fn run_blocking_task<T, F>(&self, plugin_id: String, task: F) -> Result<T, PluginError>
     where
@@ -924,14 +1929,79 @@ impl<D: Database> PluginRuntime<D> {
         })?
     }
 
+    fn kv_store(&self, plugin_id: &str) -> PluginKvStore<D> {
+        let store = PluginKvStore::new(
+            plugin_id.to_string(),
+            Arc::clone(&self.db),
+            self.config.kv_quota.clone(),
+        );
+        #[cfg(feature = "native")]
+        let store = store.with_event_bus(Arc::clone(&self.event_bus));
+        store
+    }
+
+    /// The stored KV entries of a plugin that declares `kv_storage`.
+    #[cfg(feature = "native")]
+    async fn load_kv_entries(
+        &self,
+        manifest: &PluginManifest,
+    ) -> Result<BTreeMap<String, Vec<u8>>, PluginError> {
+        if !manifest.permissions.kv_storage {
+            return Ok(BTreeMap::new());
+        }
+
+        let entries = self
+            .kv_store(manifest.id())
+            .entries()
+            .await
+            .map_err(|error| kv_failed(manifest.id(), error))?;
+        Ok(entries.into_iter().collect())
+    }
+
+    /// Persist the KV writes a plugin made during its last invocation.
+    #[cfg(feature = "native")]
+    async fn flush_kv_writes(&mut self, plugin_id: &str) {
+        let Some(plugin) = self.runtime_plugins.get_mut(plugin_id) else {
+            return;
+        };
+        let writes = plugin.take_kv_writes();
+        self.write_kv_entries(plugin_id, writes).await;
+    }
+
+    #[cfg(feature = "native")]
+    async fn write_kv_entries(&self, plugin_id: &str, writes: Vec<(String, Vec<u8>)>) {
+        if writes.is_empty() {
+            return;
+        }
+
+        let store = self.kv_store(plugin_id);
+        for (key, value) in writes {
+            if let Err(error) = store.set(&key, &value).await {
+                warn!(plugin_id, key, %error, "failed to persist plugin kv write");
+            }
+        }
+    }
+
+    #[cfg(feature = "native")]
+    fn report_plugin_failure(&mut self, plugin_id: &str, error: &PluginError) {
+        let reason = error.to_string();
+        let auto_disabled = self.record_plugin_error(plugin_id, &reason);
+        let _ = self.emit_plugin_error(plugin_id, &reason);
+
+        if auto_disabled {
+            let _ = self.emit_plugin_error(plugin_id, "auto-disabled: too many errors");
+        }
+    }
+
     #[cfg(feature = "native")]
     fn record_plugin_error(&mut self, plugin_id: &str, reason: &str) -> bool {
         let now = Instant::now();
+        let window_length = Duration::from_secs(self.config.error_window_secs);
         let window = self.error_windows.entry(plugin_id.to_string()).or_default();
         window.push_back(now);
 
         while let Some(timestamp) = window.front().copied() {
-            if now.duration_since(timestamp) > ERROR_WINDOW {
+            if now.duration_since(timestamp) > window_length {
                 window.pop_front();
             } else {
                 break;
@@ -944,11 +2014,10 @@ impl<D: Database> PluginRuntime<D> {
             plugin_info.status = PluginStatus::Error(reason.to_string());
         }
 
-        if window.len() >= AUTO_DISABLE_ERROR_THRESHOLD {
-            self.disabled_plugins.insert(plugin_id.to_string());
-            self.runtime_plugins.remove(plugin_id);
-            self.plugins.remove(plugin_id);
-            return true;
+        // A disabled plugin stays loaded, with its last error as its status,
+        // so it can be inspected and re-enabled without reinstalling.
+        if error_count >= self.config.error_threshold {
+            return self.disabled_plugins.insert(plugin_id.to_string());
         }
 
         false
@@ -1030,6 +2099,8 @@ fn compile_and_init_plugin(
     engine: Engine,
     config: PluginRuntimeConfig,
     event_bus: Arc<dyn EventBus>,
+    host_api: Option<Arc<dyn PluginHostApi>>,
+    kv: BTreeMap<String, Vec<u8>>,
     manifest: PluginManifest,
     wasm_bytes: Vec<u8>,
 ) -> Result<LoadedPlugin, PluginError> {
@@ -1040,23 +2111,27 @@ fn compile_and_init_plugin(
             reason: error.to_string(),
         })?;
 
-    let memory_limit = usize::try_from(config.max_memory_bytes).unwrap_or(usize::MAX);
-    let limits = StoreLimitsBuilder::new()
-        .memory_size(memory_limit)
-        .trap_on_grow_failure(true)
-        .build();
+    let limits = store_limits(config.max_memory_bytes_for(&plugin_id));
     let mut store = Store::new(
         &engine,
         PluginStoreState {
             plugin_id: plugin_id.clone(),
             limits,
             event_bus,
-            declared_event_subscriptions: manifest.permissions.event_subscriptions.clone(),
+            permissions: manifest.permissions.clone(),
             event_subscription_patterns: Vec::new(),
             event_subscriptions: Vec::new(),
-            http_hosts: manifest.permissions.http_hosts.clone(),
             http_response_body: Vec::new(),
             http_response_status: 0,
+            host_api,
+            host_result: Vec::new(),
+            kv,
+            kv_writes: Vec::new(),
+            kv_quota: config.kv_quota.clone(),
+            event_quota: config.event_quota.clone(),
+            published_events: VecDeque::new(),
+            invocation: None,
+            usage: UsageCounters::default(),
         },
     );
     store.limiter(|state| &mut state.limits);
@@ -1065,12 +2140,19 @@ fn compile_and_init_plugin(
     let mut linker = Linker::new(&engine);
     bind_host_events(&mut linker, &plugin_id)?;
     bind_host_http(&mut linker, &plugin_id)?;
+    bind_host_api(&mut linker, &plugin_id)?;
     let instance = linker
         .instantiate(&mut store, &module)
         .map_err(|error| map_instantiation_error(&plugin_id, error.to_string()))?;
 
     let init = resolve_init(&mut store, &instance, &plugin_id)?;
-    invoke_init(&mut store, &init, &plugin_id, config.fuel_per_invocation)?;
+    invoke_init(
+        &mut store,
+        &init,
+        &plugin_id,
+        config.fuel_per_invocation_for(&plugin_id),
+    )?;
+    record_usage(&mut store, &instance);
 
     let shutdown = resolve_shutdown(&mut store, &instance, &plugin_id)?;
     let event_handler = if manifest.hooks.event_handler {
@@ -1085,7 +2167,7 @@ fn compile_and_init_plugin(
         None
     };
     let process_inbound = if manifest.hooks.stanza_processor {
-        Some(resolve_runtime_hook(
+        Some(resolve_stanza_hook(
             &mut store,
             &instance,
             &plugin_id,
@@ -1096,7 +2178,7 @@ fn compile_and_init_plugin(
         None
     };
     let process_outbound = if manifest.hooks.stanza_processor {
-        Some(resolve_runtime_hook(
+        Some(resolve_stanza_hook(
             &mut store,
             &instance,
             &plugin_id,
@@ -1131,6 +2213,26 @@ fn compile_and_init_plugin(
         None
     };
 
+    let gui_component_info = if manifest.hooks.gui_metadata {
+        instance
+            .get_typed_func::<(), i32>(&mut store, "plugin_gui_component_info")
+            .ok()
+    } else {
+        None
+    };
+    let rule_hook = if manifest.hooks.rule_hook {
+        instance
+            .get_typed_func::<(i32, i32), i32>(&mut store, "plugin_rule_hook")
+            .ok()
+    } else {
+        None
+    };
+    let gui_components = manifest
+        .gui
+        .as_ref()
+        .map(|gui| gui.components.clone())
+        .unwrap_or_default();
+
     let guest_alloc = instance
         .get_typed_func::<i32, i32>(&mut store, "guest_alloc")
         .ok();
@@ -1145,6 +2247,9 @@ fn compile_and_init_plugin(
         message_transform,
         render_tui,
         render_gui,
+        gui_component_info,
+        rule_hook,
+        gui_components,
         guest_alloc,
     })
 }
@@ -1217,6 +2322,24 @@ fn resolve_runtime_hook(
     })
 }
 
+#[cfg(feature = "native")]
+fn resolve_stanza_hook(
+    store: &mut Store<PluginStoreState>,
+    instance: &Instance,
+    plugin_id: &str,
+    export_names: &[&str],
+    hook_name: &str,
+) -> Result<StanzaHook, PluginError> {
+    for export_name in export_names {
+        if let Ok(func) = instance.get_typed_func::<(i32, i32), i32>(&mut *store, export_name) {
+            return Ok(StanzaHook::Process(func));
+        }
+    }
+
+    resolve_runtime_hook(store, instance, plugin_id, export_names, hook_name)
+        .map(StanzaHook::Observe)
+}
+
 #[cfg(feature = "native")]
 fn bind_host_events(
     linker: &mut Linker<PluginStoreState>,
@@ -1231,8 +2354,9 @@ fn bind_host_events(
              channel_len: i32,
              payload_ptr: i32,
              payload_len: i32|
-             -> i32 {
-                host_publish_event(
+             -> wasmtime::Result<i32> {
+                charge_host_fuel(&mut caller, &[channel_len, payload_len])?;
+                Ok(host_publish_event(
                     &mut caller,
                     channel_ptr,
                     channel_len,
@@ -1240,7 +2364,7 @@ fn bind_host_events(
                     payload_len,
                 )
                 .map(|_| 0)
-                .unwrap_or(1)
+                .unwrap_or(1))
             },
         )
         .map_err(|error| PluginError::InstantiationFailed {
@@ -1252,10 +2376,15 @@ fn bind_host_events(
         .func_wrap(
             "host-events",
             "subscribe",
-            |mut caller: Caller<'_, PluginStoreState>, pattern_ptr: i32, pattern_len: i32| -> i32 {
-                host_subscribe(&mut caller, pattern_ptr, pattern_len)
-                    .map(|_| 0)
-                    .unwrap_or(1)
+            |mut caller: Caller<'_, PluginStoreState>,
+             pattern_ptr: i32,
+             pattern_len: i32|
+             -> wasmtime::Result<i32> {
+                charge_host_fuel(&mut caller, &[pattern_len])?;
+                host_status(
+                    host_subscribe(&mut caller, pattern_ptr, pattern_len).map(|_| 0),
+                    1,
+                )
             },
         )
         .map_err(|error| PluginError::InstantiationFailed {
@@ -1270,7 +2399,8 @@ fn bind_host_events(
 #[cfg(feature = "native")]
 const HOST_HTTP_MAX_RESPONSE_BYTES: usize = 65_536;
 
-/// Default timeout for host-http.fetch requests (5 seconds).
+/// Longest a host-http.fetch request may take (5 seconds), whatever
+/// timeout the plugin asks for.
 #[cfg(feature = "native")]
 const HOST_HTTP_TIMEOUT_MS: u64 = 5_000;
 
@@ -1287,9 +2417,13 @@ fn bind_host_http(
             |mut caller: Caller<'_, PluginStoreState>,
              url_ptr: i32,
              url_len: i32,
-             _timeout_ms: i32|
-             -> i32 {
-                host_http_fetch(&mut caller, url_ptr, url_len).unwrap_or(-1)
+             timeout_ms: i32|
+             -> wasmtime::Result<i32> {
+                charge_host_fuel(&mut caller, &[url_len])?;
+                host_status(
+                    host_http_fetch(&mut caller, url_ptr, url_len, timeout_ms),
+                    -1,
+                )
             },
         )
         .map_err(|error| PluginError::InstantiationFailed {
@@ -1297,84 +2431,84 @@ fn bind_host_http(
             reason: error.to_string(),
         })?;
 
-    // host-http.response_ptr() -> i32
-    linker
-        .func_wrap(
-            "host-http",
-            "response_ptr",
-            |mut caller: Caller<'_, PluginStoreState>| -> i32 {
-                let state = caller.data();
-                let body = &state.http_response_body;
-                if body.is_empty() {
-                    return 0;
-                }
-                // Write response into guest memory via guest_alloc if available
-                let plugin_id = state.plugin_id.clone();
-                let body_clone = body.clone();
-                let instance = match caller.get_export("guest_alloc") {
-                    Some(export) => export,
-                    None => return 0,
-                };
-                let alloc = match instance.into_func() {
-                    Some(func) => func,
-                    None => return 0,
-                };
-                let alloc = match alloc.typed::<i32, i32>(&caller) {
-                    Ok(f) => f,
-                    Err(_) => return 0,
-                };
-                let len = body_clone.len() as i32;
-                let ptr = match alloc.call(&mut caller, len) {
-                    Ok(p) => p,
-                    Err(_) => return 0,
-                };
-                // Write body into guest memory
-                // Validate pointer before writing into guest memory
-                let Ok(start) = usize::try_from(ptr) else {
-                    return 0;
-                };
-                let Some(end) = start.checked_add(body_clone.len()) else {
-                    return 0;
-                };
-                if let Some(memory) = caller.get_export("memory").and_then(|e| e.into_memory()) {
-                    let data = memory.data_mut(&mut caller);
-                    if end <= data.len() {
-                        data[start..end].copy_from_slice(&body_clone);
-                    }
-                }
-                let _ = plugin_id;
-                ptr
-            },
-        )
-        .map_err(|error| PluginError::InstantiationFailed {
-            id: plugin_id.to_string(),
-            reason: error.to_string(),
-        })?;
-
-    // host-http.response_len() -> i32
-    linker
-        .func_wrap(
-            "host-http",
-            "response_len",
-            |caller: Caller<'_, PluginStoreState>| -> i32 {
-                caller.data().http_response_body.len() as i32
-            },
-        )
-        .map_err(|error| PluginError::InstantiationFailed {
-            id: plugin_id.to_string(),
-            reason: error.to_string(),
-        })?;
+    // host-http.response-ptr() -> i32
+    let response_ptr = |mut caller: Caller<'_, PluginStoreState>| -> wasmtime::Result<i32> {
+        let len = caller.data().http_response_body.len();
+        charge_host_fuel(&mut caller, &[i32::try_from(len).unwrap_or(i32::MAX)])?;
+        Ok(host_http_response_ptr(&mut caller))
+    };
+    // host-http.response-len() -> i32
+    let response_len = |mut caller: Caller<'_, PluginStoreState>| -> wasmtime::Result<i32> {
+        charge_host_fuel(&mut caller, &[])?;
+        Ok(caller.data().http_response_body.len() as i32)
+    };
+    // Plugins built before the WIT contract import the snake_case names.
+    for name in ["response-ptr", "response_ptr"] {
+        linker
+            .func_wrap("host-http", name, response_ptr)
+            .map_err(|error| PluginError::InstantiationFailed {
+                id: plugin_id.to_string(),
+                reason: error.to_string(),
+            })?;
+    }
+    for name in ["response-len", "response_len"] {
+        linker
+            .func_wrap("host-http", name, response_len)
+            .map_err(|error| PluginError::InstantiationFailed {
+                id: plugin_id.to_string(),
+                reason: error.to_string(),
+            })?;
+    }
 
     Ok(())
 }
 
+/// Copy the last response body into guest memory via `guest_alloc`,
+/// returning where it was written or 0 when there is nothing to copy.
+#[cfg(feature = "native")]
+fn host_http_response_ptr(caller: &mut Caller<'_, PluginStoreState>) -> i32 {
+    let body = caller.data().http_response_body.clone();
+    if body.is_empty() {
+        return 0;
+    }
+    let Some(alloc) = caller
+        .get_export("guest_alloc")
+        .and_then(|export| export.into_func())
+        .and_then(|func| func.typed::<i32, i32>(&*caller).ok())
+    else {
+        return 0;
+    };
+    let Ok(ptr) = alloc.call(&mut *caller, body.len() as i32) else {
+        return 0;
+    };
+    // Validate the pointer before writing into guest memory.
+    let Ok(start) = usize::try_from(ptr) else {
+        return 0;
+    };
+    let Some(end) = start.checked_add(body.len()) else {
+        return 0;
+    };
+    if let Some(memory) = caller.get_export("memory").and_then(|e| e.into_memory()) {
+        let data = memory.data_mut(&mut *caller);
+        if end <= data.len() {
+            data[start..end].copy_from_slice(&body);
+        }
+    }
+    ptr
+}
+
 #[cfg(feature = "native")]
 fn host_http_fetch(
     caller: &mut Caller<'_, PluginStoreState>,
     url_ptr: i32,
     url_len: i32,
-) -> Result<i32, String> {
+    timeout_ms: i32,
+) -> Result<i32, HostCallError> {
     let url = read_guest_string(caller, url_ptr, url_len)?;
+    let timeout_ms = u64::try_from(timeout_ms)
+        .ok()
+        .filter(|ms| *ms > 0)
+        .map_or(HOST_HTTP_TIMEOUT_MS, |ms| ms.min(HOST_HTTP_TIMEOUT_MS));
 
     // Parse URL and validate against allowed hosts
     let parsed = url
@@ -1389,17 +2523,20 @@ fn host_http_fetch(
         .next()
         .unwrap_or("");
 
-    let state = caller.data();
-    if !state.http_hosts.iter().any(|h| h == host) {
-        return Err(format!(
-            "host '{host}' is not in the allowed http_hosts list"
-        ));
-    }
+    caller
+        .data()
+        .require(
+            PluginPermission::Network {
+                host: host.to_string(),
+            },
+            "host-http.fetch",
+        )
+        .map_err(HostCallError::Denied)?;
 
     // Perform synchronous HTTP GET (we're already on the blocking pool)
     let agent = ureq::Agent::new_with_config(
         ureq::config::Config::builder()
-            .timeout_global(Some(std::time::Duration::from_millis(HOST_HTTP_TIMEOUT_MS)))
+            .timeout_global(Some(std::time::Duration::from_millis(timeout_ms)))
             .build(),
     );
     let response = agent.get(&url).call();
@@ -1433,6 +2570,279 @@ fn host_http_fetch(
     }
 }
 
+#[cfg(feature = "native")]
+fn bind_host_api(
+    linker: &mut Linker<PluginStoreState>,
+    plugin_id: &str,
+) -> Result<(), PluginError> {
+    let link_error = |error: wasmtime::Error| PluginError::InstantiationFailed {
+        id: plugin_id.to_string(),
+        reason: error.to_string(),
+    };
+
+    // host-xmpp.send-message(jid_ptr, jid_len, body_ptr, body_len) -> 0 | 1
+    linker
+        .func_wrap(
+            "host-xmpp",
+            "send-message",
+            |mut caller: Caller<'_, PluginStoreState>,
+             jid_ptr: i32,
+             jid_len: i32,
+             body_ptr: i32,
+             body_len: i32|
+             -> wasmtime::Result<i32> {
+                charge_host_fuel(&mut caller, &[jid_len, body_len])?;
+                host_status(
+                    host_send_message(&mut caller, jid_ptr, jid_len, body_ptr, body_len).map(|_| 0),
+                    1,
+                )
+            },
+        )
+        .map_err(link_error)?;
+
+    // host-roster.get-roster() -> result length | -1
+    linker
+        .func_wrap(
+            "host-roster",
+            "get-roster",
+            |mut caller: Caller<'_, PluginStoreState>| -> wasmtime::Result<i32> {
+                charge_host_fuel(&mut caller, &[])?;
+                host_status(host_get_roster(&mut caller), -1)
+            },
+        )
+        .map_err(link_error)?;
+
+    // host-roster.get-presence(jid_ptr, jid_len) -> result length | -1
+    linker
+        .func_wrap(
+            "host-roster",
+            "get-presence",
+            |mut caller: Caller<'_, PluginStoreState>,
+             jid_ptr: i32,
+             jid_len: i32|
+             -> wasmtime::Result<i32> {
+                charge_host_fuel(&mut caller, &[jid_len])?;
+                host_status(host_get_presence(&mut caller, jid_ptr, jid_len), -1)
+            },
+        )
+        .map_err(link_error)?;
+
+    // host-kv.get(key_ptr, key_len) -> result length | -1 when absent
+    linker
+        .func_wrap(
+            "host-kv",
+            "get",
+            |mut caller: Caller<'_, PluginStoreState>,
+             key_ptr: i32,
+             key_len: i32|
+             -> wasmtime::Result<i32> {
+                charge_host_fuel(&mut caller, &[key_len])?;
+                host_status(host_kv_get(&mut caller, key_ptr, key_len), -1)
+            },
+        )
+        .map_err(link_error)?;
+
+    // host-kv.set(key_ptr, key_len, value_ptr, value_len) -> 0 | 1
+    linker
+        .func_wrap(
+            "host-kv",
+            "set",
+            |mut caller: Caller<'_, PluginStoreState>,
+             key_ptr: i32,
+             key_len: i32,
+             value_ptr: i32,
+             value_len: i32|
+             -> wasmtime::Result<i32> {
+                charge_host_fuel(&mut caller, &[key_len, value_len])?;
+                host_status(
+                    host_kv_set(&mut caller, key_ptr, key_len, value_ptr, value_len).map(|_| 0),
+                    1,
+                )
+            },
+        )
+        .map_err(link_error)?;
+
+    // host-result.read(ptr, len) -> bytes copied | -1
+    linker
+        .func_wrap(
+            "host-result",
+            "read",
+            |mut caller: Caller<'_, PluginStoreState>,
+             ptr: i32,
+             len: i32|
+             -> wasmtime::Result<i32> {
+                charge_host_fuel(&mut caller, &[len])?;
+                host_status(host_read_result(&mut caller, ptr, len), -1)
+            },
+        )
+        .map_err(link_error)?;
+
+    Ok(())
+}
+
+/// Deduct the cost of a host call from the invocation's fuel, trapping the
+/// guest with `OutOfFuel` when it cannot pay.
+#[cfg(feature = "native")]
+fn charge_host_fuel(
+    caller: &mut Caller<'_, PluginStoreState>,
+    lengths: &[i32],
+) -> wasmtime::Result<()> {
+    let bytes: u64 = lengths
+        .iter()
+        .map(|len| u64::try_from(*len).unwrap_or(0))
+        .sum();
+    let cost = HOST_CALL_FUEL.saturating_add(bytes);
+    let remaining = caller.get_fuel()?;
+    if remaining < cost {
+        caller.set_fuel(0)?;
+        return Err(wasmtime::Trap::OutOfFuel.into());
+    }
+    caller.set_fuel(remaining - cost)
+}
+
+#[cfg(feature = "native")]
+fn host_send_message(
+    caller: &mut Caller<'_, PluginStoreState>,
+    jid_ptr: i32,
+    jid_len: i32,
+    body_ptr: i32,
+    body_len: i32,
+) -> Result<(), HostCallError> {
+    caller
+        .data()
+        .require(PluginPermission::SendMessages, "host-xmpp.send-message")
+        .map_err(HostCallError::Denied)?;
+    let to = read_guest_string(caller, jid_ptr, jid_len)?;
+    let body = read_guest_string(caller, body_ptr, body_len)?;
+    if to.is_empty() || !to.contains('@') || to.contains(char::is_whitespace) {
+        return Err(format!("'{to}' is not a valid recipient JID").into());
+    }
+
+    let state = caller.data();
+    let event = Event::new(
+        Channel::new(channels::UI_MESSAGE_SEND).map_err(|error| error.to_string())?,
+        EventSource::Plugin(state.plugin_id.clone()),
+        EventPayload::MessageSendRequested {
+            to,
+            body,
+            message_type: MessageType::Chat,
+            request_receipt: false,
+        },
+    );
+    state
+        .event_bus
+        .publish(event)
+        .map_err(|error| HostCallError::Failed(error.to_string()))
+}
+
+#[cfg(feature = "native")]
+fn host_get_roster(caller: &mut Caller<'_, PluginStoreState>) -> Result<i32, HostCallError> {
+    caller
+        .data()
+        .require(PluginPermission::RosterAccess, "host-roster.get-roster")
+        .map_err(HostCallError::Denied)?;
+    let api = host_api(caller)?;
+    let json = serde_json::to_vec(&api.roster()).map_err(|error| error.to_string())?;
+    Ok(set_host_result(caller, json))
+}
+
+#[cfg(feature = "native")]
+fn host_get_presence(
+    caller: &mut Caller<'_, PluginStoreState>,
+    jid_ptr: i32,
+    jid_len: i32,
+) -> Result<i32, HostCallError> {
+    caller
+        .data()
+        .require(PluginPermission::RosterAccess, "host-roster.get-presence")
+        .map_err(HostCallError::Denied)?;
+    let jid = read_guest_string(caller, jid_ptr, jid_len)?;
+    let api = host_api(caller)?;
+    let json = serde_json::to_vec(&api.presence(&jid)).map_err(|error| error.to_string())?;
+    Ok(set_host_result(caller, json))
+}
+
+#[cfg(feature = "native")]
+fn host_kv_get(
+    caller: &mut Caller<'_, PluginStoreState>,
+    key_ptr: i32,
+    key_len: i32,
+) -> Result<i32, HostCallError> {
+    caller
+        .data()
+        .require(PluginPermission::KvStorage, "host-kv.get")
+        .map_err(HostCallError::Denied)?;
+    let key = read_guest_string(caller, key_ptr, key_len)?;
+    let Some(value) = caller.data().kv.get(&key).cloned() else {
+        return Err(format!("no value stored under '{key}'").into());
+    };
+    Ok(set_host_result(caller, value))
+}
+
+#[cfg(feature = "native")]
+fn host_kv_set(
+    caller: &mut Caller<'_, PluginStoreState>,
+    key_ptr: i32,
+    key_len: i32,
+    value_ptr: i32,
+    value_len: i32,
+) -> Result<(), HostCallError> {
+    caller
+        .data()
+        .require(PluginPermission::KvStorage, "host-kv.set")
+        .map_err(HostCallError::Denied)?;
+    let key = read_guest_string(caller, key_ptr, key_len)?;
+    let value = read_guest_bytes(caller, value_ptr, value_len)?;
+
+    let state = caller.data_mut();
+    if value.len() as u64 > state.kv_quota.max_value_bytes {
+        return Err(format!(
+            "value of {} bytes exceeds the limit of {} bytes",
+            value.len(),
+            state.kv_quota.max_value_bytes
+        )
+        .into());
+    }
+    if !state.kv.contains_key(&key) && state.kv.len() as u64 >= state.kv_quota.max_keys {
+        return Err(format!("quota of {} keys reached", state.kv_quota.max_keys).into());
+    }
+
+    state.kv.insert(key.clone(), value.clone());
+    state.kv_writes.retain(|(written, _)| written != &key);
+    state.kv_writes.push((key, value));
+    Ok(())
+}
+
+#[cfg(feature = "native")]
+fn host_read_result(
+    caller: &mut Caller<'_, PluginStoreState>,
+    ptr: i32,
+    len: i32,
+) -> Result<i32, HostCallError> {
+    let len = usize::try_from(len).map_err(|_| "length must be non-negative".to_string())?;
+    let result = caller.data().host_result.clone();
+    let copied = result.len().min(len);
+    write_guest_memory(caller, ptr, &result[..copied])?;
+    Ok(copied as i32)
+}
+
+#[cfg(feature = "native")]
+fn host_api(caller: &Caller<'_, PluginStoreState>) -> Result<Arc<dyn PluginHostApi>, String> {
+    caller
+        .data()
+        .host_api
+        .clone()
+        .ok_or_else(|| "the host does not provide roster data".to_string())
+}
+
+/// Stage `result` for host-result.read and return its length.
+#[cfg(feature = "native")]
+fn set_host_result(caller: &mut Caller<'_, PluginStoreState>, result: Vec<u8>) -> i32 {
+    let len = i32::try_from(result.len()).unwrap_or(i32::MAX);
+    caller.data_mut().host_result = result;
+    len
+}
+
 #[cfg(feature = "native")]
 fn host_publish_event(
     caller: &mut Caller<'_, PluginStoreState>,
@@ -1441,26 +2851,37 @@ fn host_publish_event(
     payload_ptr: i32,
     payload_len: i32,
 ) -> Result<(), String> {
+    let limit = caller.data().event_quota.max_payload_bytes;
+    if u64::try_from(payload_len).is_ok_and(|len| len > limit) {
+        return Err(format!(
+            "event payload of {payload_len} bytes exceeds the {limit} byte limit"
+        ));
+    }
     let channel_name = read_guest_string(caller, channel_ptr, channel_len)?;
     let payload = read_guest_string(caller, payload_ptr, payload_len)?;
 
-    let state = caller.data();
-    let prefix = plugin_event_prefix(&state.plugin_id);
-    if !channel_name.starts_with(&prefix) {
+    let prefix = plugin_event_prefix(&caller.data().plugin_id);
+    let Some(event_type) = channel_name.strip_prefix(&prefix) else {
         return Err(format!(
             "plugins may only publish to channels under '{prefix}'"
         ));
+    };
+    // Subscribers matching on the channel could not tell these apart from
+    // the runtime's own lifecycle events.
+    if RESERVED_EVENT_TYPES.contains(&event_type) {
+        return Err(format!(
+            "'{channel_name}' is reserved for the plugin runtime"
+        ));
     }
+    let event_type = event_type.to_string();
 
     let channel = Channel::new(channel_name.clone()).map_err(|error| error.to_string())?;
     let data: serde_json::Value = serde_json::from_str(&payload)
         .map_err(|error| format!("failed to parse plugin event payload: {error}"))?;
 
+    let state = caller.data_mut();
+    state.admit_event()?;
     let plugin_id = state.plugin_id.clone();
-    let event_type = channel_name
-        .strip_prefix(&prefix)
-        .unwrap_or("event")
-        .to_string();
     let event = Event::new(
         channel,
         EventSource::Plugin(plugin_id.clone()),
@@ -1482,20 +2903,19 @@ fn host_subscribe(
     caller: &mut Caller<'_, PluginStoreState>,
     pattern_ptr: i32,
     pattern_len: i32,
-) -> Result<(), String> {
+) -> Result<(), HostCallError> {
     let pattern = read_guest_string(caller, pattern_ptr, pattern_len)?;
     let compiled = validate_subscription_pattern(&pattern)?;
 
     let state = caller.data_mut();
-    if !state
-        .declared_event_subscriptions
-        .iter()
-        .any(|declared| declared == &pattern)
-    {
-        return Err(format!(
-            "subscription pattern '{pattern}' is not declared in permissions.event_subscriptions"
-        ));
-    }
+    state
+        .require(
+            PluginPermission::EventSubscription {
+                pattern: pattern.clone(),
+            },
+            "host-events.subscribe",
+        )
+        .map_err(HostCallError::Denied)?;
 
     if state
         .event_subscription_patterns
@@ -1541,6 +2961,16 @@ fn read_guest_string(
     ptr: i32,
     len: i32,
 ) -> Result<String, String> {
+    let bytes = read_guest_bytes(caller, ptr, len)?;
+    String::from_utf8(bytes).map_err(|error| format!("guest string is not valid utf-8: {error}"))
+}
+
+#[cfg(feature = "native")]
+fn read_guest_bytes(
+    caller: &mut Caller<'_, PluginStoreState>,
+    ptr: i32,
+    len: i32,
+) -> Result<Vec<u8>, String> {
     if ptr < 0 || len < 0 {
         return Err("pointer and length must be non-negative".to_string());
     }
@@ -1563,9 +2993,32 @@ fn read_guest_string(
         return Err("guest memory access out of bounds".to_string());
     }
 
-    std::str::from_utf8(&data[ptr..end])
-        .map(|value| value.to_string())
-        .map_err(|error| format!("guest string is not valid utf-8: {error}"))
+    Ok(data[ptr..end].to_vec())
+}
+
+#[cfg(feature = "native")]
+fn write_guest_memory(
+    caller: &mut Caller<'_, PluginStoreState>,
+    ptr: i32,
+    bytes: &[u8],
+) -> Result<(), String> {
+    let ptr = usize::try_from(ptr).map_err(|_| "pointer must be non-negative".to_string())?;
+    let Some(memory) = caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+    else {
+        return Err("guest module does not export memory".to_string());
+    };
+
+    let data = memory.data_mut(caller);
+    let end = ptr
+        .checked_add(bytes.len())
+        .ok_or_else(|| "memory range overflow".to_string())?;
+    if end > data.len() {
+        return Err("guest memory access out of bounds".to_string());
+    }
+    data[ptr..end].copy_from_slice(bytes);
+    Ok(())
 }
 
 #[cfg(feature = "native")]
@@ -1610,11 +3063,59 @@ fn prepare_invocation(
             reason: error.to_string(),
         })?;
     store.set_epoch_deadline(1);
+    store.data_mut().invocation = Some((Instant::now(), fuel_per_invocation));
     Ok(())
 }
 
+/// Add the invocation started by [`prepare_invocation`] to the plugin's
+/// usage. Failed invocations count too.
+#[cfg(feature = "native")]
+fn record_usage(store: &mut Store<PluginStoreState>, instance: &Instance) {
+    let Some((started, budget)) = store.data_mut().invocation.take() else {
+        return;
+    };
+    let remaining = store.get_fuel().unwrap_or(0);
+    let memory_bytes = instance
+        .get_memory(&mut *store, "memory")
+        .map_or(0, |memory| memory.data_size(&*store) as u64);
+
+    let usage = &mut store.data_mut().usage;
+    usage.invocations += 1;
+    usage.fuel_consumed += budget.saturating_sub(remaining);
+    usage.wall_time += started.elapsed();
+    usage.memory_high_water_bytes = usage.memory_high_water_bytes.max(memory_bytes);
+}
+
+#[cfg(feature = "native")]
+fn store_limits(max_memory_bytes: u64) -> StoreLimits {
+    StoreLimitsBuilder::new()
+        .memory_size(usize::try_from(max_memory_bytes).unwrap_or(usize::MAX))
+        .trap_on_grow_failure(true)
+        .build()
+}
+
+fn kv_failed(plugin_id: &str, error: KvError) -> PluginError {
+    PluginError::StorageFailed {
+        id: plugin_id.to_string(),
+        reason: error.to_string(),
+    }
+}
+
 #[cfg(feature = "native")]
 fn classify_invocation_error(plugin_id: &str, error: wasmtime::Error) -> PluginError {
+    if let Some(PluginError::PermissionDenied {
+        id,
+        permission,
+        reason,
+    }) = error.downcast_ref::<PluginError>()
+    {
+        return PluginError::PermissionDenied {
+            id: id.clone(),
+            permission: permission.clone(),
+            reason: reason.clone(),
+        };
+    }
+
     if let Some(trap) = error.downcast_ref::<wasmtime::Trap>() {
         match trap {
             wasmtime::Trap::OutOfFuel => {
@@ -1699,6 +3200,29 @@ fn plugin_channel(plugin_id: &str, action: &str) -> Result<Channel, PluginError>
     })
 }
 
+/// One glob matching any of `filters`, or `None` when there are none.
+#[cfg(feature = "native")]
+fn union_pattern(filters: &BTreeSet<String>) -> Option<String> {
+    match filters.len() {
+        0 => None,
+        1 => filters.first().cloned(),
+        _ => Some(format!(
+            "{{{}}}",
+            filters.iter().cloned().collect::<Vec<_>>().join(",")
+        )),
+    }
+}
+
+#[cfg(feature = "native")]
+async fn recv_subscribed(
+    subscription: &mut Option<EventSubscription>,
+) -> Result<Event, EventBusError> {
+    match subscription {
+        Some(subscription) => subscription.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
 #[cfg(feature = "native")]
 fn plugin_event_prefix(plugin_id: &str) -> String {
     let safe_plugin_id = plugin_id.replace('-', "_");
@@ -1721,10 +3245,11 @@ mod tests {
     use std::path::Path;
 
     use tokio::time::{Duration, timeout};
-    use waddle_core::event::BroadcastEventBus;
+    use waddle_core::event::{BroadcastEventBus, RosterItem, Subscription};
     use waddle_storage::open_database;
 
     use super::*;
+    use crate::host::HostPresence;
 
     fn test_manifest(plugin_id: &str) -> PluginManifest {
         test_manifest_with(plugin_id, false, &[], false, false)
@@ -1819,48 +3344,304 @@ gui_metadata = false
     }
 
     #[tokio::test]
-    async fn host_publish_event_enforces_plugin_namespace() {
+    async fn reload_swaps_module_and_keeps_kv_data() {
         let (mut runtime, _dir) = open_runtime(PluginRuntimeConfig::default()).await;
-        let manifest = test_manifest("com.waddle.runtime.publisher");
+        let plugin_id = "com.waddle.runtime.reload";
+        let mut lifecycle = runtime
+            .event_bus()
+            .subscribe("plugin.com.waddle.runtime.reload.*")
+            .expect("event bus subscription should succeed");
+        let kv = PluginKvStore::new(
+            plugin_id.to_string(),
+            Arc::clone(runtime.database()),
+            KvQuota::default(),
+        );
         let wasm = r#"
             (module
-              (import "host-events" "publish-event" (func $publish_event (param i32 i32 i32 i32) (result i32)))
-              (memory (export "memory") 1)
-              (data (i32.const 0) "xmpp.message.sent")
-              (data (i32.const 64) "{}")
               (func (export "plugin_init") (result i32)
-                i32.const 0
-                i32.const 17
-                i32.const 64
-                i32.const 2
-                call $publish_event)
+                i32.const 0)
               (func (export "plugin_shutdown")))
         "#;
 
-        let result = runtime.load_plugin(manifest, wasm.as_bytes()).await;
-        assert!(
-            matches!(
-                result,
-                Err(PluginError::InitFailed { ref id, .. }) if id == "com.waddle.runtime.publisher"
-            ),
-            "unexpected result: {result:?}"
+        runtime
+            .load_plugin(test_manifest(plugin_id), wasm.as_bytes())
+            .await
+            .expect("load should succeed");
+        kv.set("greeting", b"hello").await.expect("kv set");
+        let loaded = lifecycle.recv().await.expect("loaded event");
+        assert!(matches!(loaded.payload, EventPayload::PluginLoaded { .. }));
+
+        let broken = r#"
+            (module
+              (func (export "plugin_init") (result i32)
+                i32.const 1)
+              (func (export "plugin_shutdown")))
+        "#;
+        let failed = runtime
+            .reload_plugin(plugin_id, test_manifest(plugin_id), broken.as_bytes())
+            .await;
+        assert!(matches!(failed, Err(PluginError::InitFailed { .. })));
+        assert!(matches!(
+            runtime.get_plugin(plugin_id).map(|info| &info.status),
+            Some(PluginStatus::Active)
+        ));
+        let error = lifecycle.recv().await.expect("error event");
+        assert!(matches!(error.payload, EventPayload::PluginError { .. }));
+
+        let mismatched = runtime
+            .reload_plugin(
+                plugin_id,
+                test_manifest("com.waddle.other"),
+                wasm.as_bytes(),
+            )
+            .await;
+        assert!(matches!(
+            mismatched,
+            Err(PluginError::InvalidManifest { .. })
+        ));
+
+        runtime
+            .reload_plugin(plugin_id, test_manifest(plugin_id), wasm.as_bytes())
+            .await
+            .expect("reload should succeed");
+        let unloaded = lifecycle.recv().await.expect("unloaded event");
+        assert!(matches!(
+            unloaded.payload,
+            EventPayload::PluginUnloaded { .. }
+        ));
+        let reloaded = lifecycle.recv().await.expect("loaded event");
+        assert!(matches!(
+            reloaded.payload,
+            EventPayload::PluginLoaded { .. }
+        ));
+        assert_eq!(runtime.list_plugins().len(), 1);
+        assert_eq!(
+            kv.get("greeting").await.expect("kv get").as_deref(),
+            Some(&b"hello"[..])
         );
+
+        let missing = runtime
+            .reload_plugin(
+                "com.waddle.absent",
+                test_manifest("com.waddle.absent"),
+                wasm.as_bytes(),
+            )
+            .await;
+        assert!(matches!(missing, Err(PluginError::NotFound { .. })));
+    }
+
+    struct StubHost;
+
+    impl PluginHostApi for StubHost {
+        fn roster(&self) -> Vec<RosterItem> {
+            vec![RosterItem {
+                jid: "alice@example.com".to_string(),
+                name: Some("Alice".to_string()),
+                subscription: Subscription::Both,
+                groups: Vec::new(),
+            }]
+        }
+
+        fn presence(&self, _jid: &str) -> Option<HostPresence> {
+            None
+        }
     }
 
     #[tokio::test]
-    async fn host_subscribe_enforces_declared_permissions() {
+    async fn host_api_sends_messages_reads_roster_and_persists_kv() {
         let (mut runtime, _dir) = open_runtime(PluginRuntimeConfig::default()).await;
-        let manifest = test_manifest_with("com.waddle.runtime.subscriber", false, &[], false, true);
-        let wasm = r#"
+        runtime.set_host_api(Arc::new(StubHost));
+        let mut sent = runtime
+            .event_bus()
+            .subscribe("ui.message.send")
+            .expect("event bus subscription should succeed");
+        let mut manifest = test_manifest("com.waddle.runtime.host");
+        manifest.permissions.kv_storage = true;
+        manifest.permissions.roster_access = true;
+        manifest.permissions.send_messages = true;
+
+        // Stores a KV value, then messages Alice the roster JSON.
+        let writer = r#"
             (module
-              (import "host-events" "subscribe" (func $subscribe (param i32 i32) (result i32)))
+              (import "host-kv" "set" (func $kv_set (param i32 i32 i32 i32) (result i32)))
+              (import "host-roster" "get-roster" (func $get_roster (result i32)))
+              (import "host-result" "read" (func $read (param i32 i32) (result i32)))
+              (import "host-xmpp" "send-message" (func $send (param i32 i32 i32 i32) (result i32)))
               (memory (export "memory") 1)
-              (data (i32.const 0) "xmpp.message.*")
+              (data (i32.const 0) "visits")
+              (data (i32.const 16) "1")
+              (data (i32.const 32) "alice@example.com")
               (func (export "plugin_init") (result i32)
-                i32.const 0
-                i32.const 14
-                call $subscribe)
-              (func (export "plugin_handle_event"))
+                (local $len i32)
+                (drop (call $kv_set (i32.const 0) (i32.const 6) (i32.const 16) (i32.const 1)))
+                (local.set $len (call $get_roster))
+                (drop (call $read (i32.const 256) (local.get $len)))
+                (call $send (i32.const 32) (i32.const 17) (i32.const 256) (local.get $len)))
+              (func (export "plugin_shutdown")))
+        "#;
+        runtime
+            .load_plugin(manifest.clone(), writer.as_bytes())
+            .await
+            .expect("load should succeed");
+
+        let message = timeout(Duration::from_secs(1), sent.recv())
+            .await
+            .expect("timed out waiting for message")
+            .expect("message should be published");
+        let roster_json = serde_json::to_string(&StubHost.roster()).unwrap();
+        assert!(matches!(
+            message.payload,
+            EventPayload::MessageSendRequested { ref to, ref body, .. }
+                if to == "alice@example.com" && body == &roster_json
+        ));
+        assert!(matches!(
+            message.source,
+            EventSource::Plugin(ref id) if id == "com.waddle.runtime.host"
+        ));
+        let kv = PluginKvStore::new(
+            "com.waddle.runtime.host".to_string(),
+            Arc::clone(runtime.database()),
+            KvQuota::default(),
+        );
+        assert_eq!(kv.get("visits").await.unwrap().as_deref(), Some(&b"1"[..]));
+
+        // A fresh instance sees the stored value.
+        let reader = r#"
+            (module
+              (import "host-kv" "get" (func $kv_get (param i32 i32) (result i32)))
+              (import "host-result" "read" (func $read (param i32 i32) (result i32)))
+              (import "host-xmpp" "send-message" (func $send (param i32 i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "visits")
+              (data (i32.const 32) "alice@example.com")
+              (func (export "plugin_init") (result i32)
+                (local $len i32)
+                (local.set $len (call $kv_get (i32.const 0) (i32.const 6)))
+                (drop (call $read (i32.const 256) (local.get $len)))
+                (call $send (i32.const 32) (i32.const 17) (i32.const 256) (local.get $len)))
+              (func (export "plugin_shutdown")))
+        "#;
+        runtime
+            .reload_plugin("com.waddle.runtime.host", manifest, reader.as_bytes())
+            .await
+            .expect("reload should succeed");
+        let message = timeout(Duration::from_secs(1), sent.recv())
+            .await
+            .expect("timed out waiting for message")
+            .expect("message should be published");
+        assert!(matches!(
+            message.payload,
+            EventPayload::MessageSendRequested { ref body, .. } if body == "1"
+        ));
+    }
+
+    #[tokio::test]
+    async fn plugin_data_can_be_exported_reset_and_imported() {
+        let (mut runtime, _dir) = open_runtime(PluginRuntimeConfig::default()).await;
+        let plugin_id = "com.waddle.runtime.backup";
+        let mut changes = runtime
+            .event_bus()
+            .subscribe("plugin.com.waddle.runtime.backup.kv.changed")
+            .expect("event bus subscription should succeed");
+        let mut manifest = test_manifest(plugin_id);
+        manifest.permissions.kv_storage = true;
+        let wasm = r#"
+            (module
+              (import "host-kv" "set" (func $kv_set (param i32 i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "visits")
+              (data (i32.const 16) "1")
+              (func (export "plugin_init") (result i32)
+                (drop (call $kv_set (i32.const 0) (i32.const 6) (i32.const 16) (i32.const 1)))
+                i32.const 0)
+              (func (export "plugin_shutdown")))
+        "#;
+        runtime
+            .load_plugin(manifest, wasm.as_bytes())
+            .await
+            .expect("load should succeed");
+
+        let backup = runtime
+            .export_plugin_data(plugin_id)
+            .await
+            .expect("export should succeed");
+        assert_eq!(
+            backup.entries.get("visits").map(Vec::as_slice),
+            Some(&b"1"[..])
+        );
+
+        runtime
+            .reset_plugin_data(plugin_id)
+            .await
+            .expect("reset should succeed");
+        let empty = runtime
+            .export_plugin_data(plugin_id)
+            .await
+            .expect("export should succeed");
+        assert!(empty.entries.is_empty());
+
+        runtime
+            .import_plugin_data(plugin_id, &backup)
+            .await
+            .expect("import should succeed");
+        let restored = runtime
+            .export_plugin_data(plugin_id)
+            .await
+            .expect("export should succeed");
+        assert_eq!(restored, backup);
+
+        let mut seen = Vec::new();
+        for _ in 0..3 {
+            let event = timeout(Duration::from_secs(1), changes.recv())
+                .await
+                .expect("timed out waiting for kv change")
+                .expect("change should be published");
+            if let EventPayload::PluginKvChanged { deleted, .. } = event.payload {
+                seen.push(deleted);
+            }
+        }
+        assert_eq!(seen, [false, true, false]);
+    }
+
+    #[tokio::test]
+    async fn host_api_requires_declared_permissions() {
+        let (mut runtime, _dir) = open_runtime(PluginRuntimeConfig::default()).await;
+        runtime.set_host_api(Arc::new(StubHost));
+        let wasm = r#"
+            (module
+              (import "host-roster" "get-roster" (func $get_roster (result i32)))
+              (func (export "plugin_init") (result i32)
+                (drop (call $get_roster))
+                i32.const 0)
+              (func (export "plugin_shutdown")))
+        "#;
+
+        let result = runtime
+            .load_plugin(test_manifest("com.waddle.runtime.snoop"), wasm.as_bytes())
+            .await;
+        assert!(matches!(
+            result,
+            Err(PluginError::PermissionDenied { ref permission, .. })
+                if permission == "roster_access"
+        ));
+    }
+
+    #[tokio::test]
+    async fn host_publish_event_enforces_plugin_namespace() {
+        let (mut runtime, _dir) = open_runtime(PluginRuntimeConfig::default()).await;
+        let manifest = test_manifest("com.waddle.runtime.publisher");
+        let wasm = r#"
+            (module
+              (import "host-events" "publish-event" (func $publish_event (param i32 i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "xmpp.message.sent")
+              (data (i32.const 64) "{}")
+              (func (export "plugin_init") (result i32)
+                i32.const 0
+                i32.const 17
+                i32.const 64
+                i32.const 2
+                call $publish_event)
               (func (export "plugin_shutdown")))
         "#;
 
@@ -1868,12 +3649,211 @@ gui_metadata = false
         assert!(
             matches!(
                 result,
-                Err(PluginError::InitFailed { ref id, .. }) if id == "com.waddle.runtime.subscriber"
+                Err(PluginError::InitFailed { ref id, .. }) if id == "com.waddle.runtime.publisher"
             ),
             "unexpected result: {result:?}"
         );
     }
 
+    #[tokio::test]
+    async fn host_publish_event_refuses_reserved_and_oversized_events() {
+        let config = PluginRuntimeConfig {
+            event_quota: EventQuota {
+                max_payload_bytes: 8,
+                ..EventQuota::default()
+            },
+            ..PluginRuntimeConfig::default()
+        };
+        let (mut runtime, _dir) = open_runtime(config).await;
+        for (plugin_id, channel, payload) in [
+            ("com.waddle.liar", "plugin.com.waddle.liar.loaded", "{}"),
+            (
+                "com.waddle.chatty",
+                "plugin.com.waddle.chatty.note",
+                "[1,2,3,4]",
+            ),
+        ] {
+            let wasm = format!(
+                r#"
+                (module
+                  (import "host-events" "publish-event" (func $publish_event (param i32 i32 i32 i32) (result i32)))
+                  (memory (export "memory") 1)
+                  (data (i32.const 0) "{channel}")
+                  (data (i32.const 64) "{payload}")
+                  (func (export "plugin_init") (result i32)
+                    i32.const 0
+                    i32.const {channel_len}
+                    i32.const 64
+                    i32.const {payload_len}
+                    call $publish_event)
+                  (func (export "plugin_shutdown")))
+                "#,
+                channel_len = channel.len(),
+                payload_len = payload.len(),
+            );
+
+            let result = runtime
+                .load_plugin(test_manifest(plugin_id), wasm.as_bytes())
+                .await;
+            assert!(
+                matches!(result, Err(PluginError::InitFailed { ref id, .. }) if id == plugin_id),
+                "unexpected result for {plugin_id}: {result:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn host_publish_event_is_rate_limited_per_plugin() {
+        let config = PluginRuntimeConfig {
+            event_quota: EventQuota {
+                max_per_second: 2,
+                ..EventQuota::default()
+            },
+            ..PluginRuntimeConfig::default()
+        };
+        let (mut runtime, _dir) = open_runtime(config).await;
+        let mut ticks = runtime
+            .event_bus()
+            .subscribe("plugin.com.waddle.flood.tick")
+            .expect("event bus subscription should succeed");
+        // The third event inside one second is refused and fails init.
+        let wasm = r#"
+            (module
+              (import "host-events" "publish-event" (func $publish_event (param i32 i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "plugin.com.waddle.flood.tick")
+              (data (i32.const 64) "{}")
+              (func $tick (result i32)
+                i32.const 0
+                i32.const 28
+                i32.const 64
+                i32.const 2
+                call $publish_event)
+              (func (export "plugin_init") (result i32)
+                call $tick
+                drop
+                call $tick
+                drop
+                call $tick)
+              (func (export "plugin_shutdown")))
+        "#;
+
+        let result = runtime
+            .load_plugin(test_manifest("com.waddle.flood"), wasm.as_bytes())
+            .await;
+        assert!(
+            matches!(result, Err(PluginError::InitFailed { .. })),
+            "unexpected result: {result:?}"
+        );
+        for _ in 0..2 {
+            timeout(Duration::from_secs(1), ticks.recv())
+                .await
+                .expect("timed out waiting for tick")
+                .expect("tick should be published");
+        }
+        assert!(
+            timeout(Duration::from_millis(100), ticks.recv())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn host_subscribe_enforces_declared_permissions() {
+        let (mut runtime, _dir) = open_runtime(PluginRuntimeConfig::default()).await;
+        let manifest = test_manifest_with("com.waddle.runtime.subscriber", false, &[], false, true);
+        let wasm = r#"
+            (module
+              (import "host-events" "subscribe" (func $subscribe (param i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "xmpp.message.*")
+              (func (export "plugin_init") (result i32)
+                i32.const 0
+                i32.const 14
+                call $subscribe)
+              (func (export "plugin_handle_event"))
+              (func (export "plugin_shutdown")))
+        "#;
+
+        let mut denials = runtime
+            .event_bus()
+            .subscribe("plugin.permission.denied")
+            .expect("event bus subscription should succeed");
+
+        let result = runtime.load_plugin(manifest, wasm.as_bytes()).await;
+        assert!(
+            matches!(
+                result,
+                Err(PluginError::PermissionDenied { ref id, ref permission, .. })
+                    if id == "com.waddle.runtime.subscriber" && permission == "event_subscriptions"
+            ),
+            "unexpected result: {result:?}"
+        );
+
+        let denial = timeout(Duration::from_secs(1), denials.recv())
+            .await
+            .expect("timed out waiting for denial event")
+            .expect("denial event should exist");
+        assert!(matches!(
+            denial.payload,
+            EventPayload::PluginPermissionDenied { ref plugin_id, ref permission, .. }
+                if plugin_id == "com.waddle.runtime.subscriber"
+                    && permission == "event_subscriptions (xmpp.message.*)"
+        ));
+    }
+
+    #[tokio::test]
+    async fn host_http_fetch_refuses_undeclared_hosts() {
+        let (mut runtime, _dir) = open_runtime(PluginRuntimeConfig::default()).await;
+        let mut manifest =
+            test_manifest_with("com.waddle.runtime.fetcher", true, &[], false, false);
+        manifest.hooks.message_transformer = true;
+        manifest.permissions.http_hosts = vec!["api.github.com".to_string()];
+        let wasm = r#"
+            (module
+              (import "host-http" "fetch" (func $fetch (param i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "https://attacker.example/steal")
+              (func (export "plugin_init"))
+              (func (export "guest_alloc") (param i32) (result i32)
+                i32.const 1024)
+              (func (export "plugin_transform_message") (param i32 i32) (result i32)
+                i32.const 0
+                i32.const 30
+                i32.const 1000
+                call $fetch
+                drop
+                i32.const 0)
+              (func (export "plugin_shutdown")))
+        "#;
+
+        runtime
+            .load_plugin(manifest, wasm.as_bytes())
+            .await
+            .expect("plugin load should succeed");
+        let mut errors = runtime
+            .event_bus()
+            .subscribe("plugin.com.waddle.runtime.fetcher.error")
+            .expect("event bus subscription should succeed");
+
+        let result = runtime
+            .invoke_hook(PluginHook::MessageTransform {
+                body: "see https://attacker.example".to_string(),
+            })
+            .await
+            .expect("hook dispatch should succeed");
+        assert_eq!(result, None);
+
+        let error = timeout(Duration::from_secs(1), errors.recv())
+            .await
+            .expect("timed out waiting for error event")
+            .expect("error event should exist");
+        assert!(matches!(
+            error.payload,
+            EventPayload::PluginError { ref error, .. } if error.contains("denied http_hosts")
+        ));
+    }
+
     #[tokio::test]
     async fn invoke_event_hook_dispatches_to_subscribed_plugins() {
         let (mut runtime, _dir) = open_runtime(PluginRuntimeConfig::default()).await;
@@ -1888,60 +3868,306 @@ gui_metadata = false
             .event_bus()
             .subscribe("plugin.com.waddle.eh.event")
             .expect("event bus subscription should succeed");
-
+
+        let wasm = r#"
+            (module
+              (import "host-events" "subscribe" (func $subscribe (param i32 i32) (result i32)))
+              (import "host-events" "publish-event" (func $publish_event (param i32 i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "xmpp.message.received")
+              (data (i32.const 64) "plugin.com.waddle.eh.event")
+              (data (i32.const 128) "{\"ok\":true}")
+              (func (export "plugin_init") (result i32)
+                i32.const 0
+                i32.const 21
+                call $subscribe)
+              (func (export "plugin_handle_event") (result i32)
+                i32.const 64
+                i32.const 26
+                i32.const 128
+                i32.const 11
+                call $publish_event)
+              (func (export "plugin_shutdown")))
+        "#;
+
+        runtime
+            .load_plugin(manifest, wasm.as_bytes())
+            .await
+            .expect("plugin load should succeed");
+
+        let event = Event::new(
+            Channel::new("xmpp.message.received").expect("channel should be valid"),
+            EventSource::Xmpp,
+            EventPayload::RawStanzaReceived {
+                stanza: "<message/>".to_string(),
+            },
+        );
+        runtime
+            .invoke_hook(PluginHook::Event(Box::new(event)))
+            .await
+            .expect("hook invocation should succeed");
+
+        let published = timeout(Duration::from_secs(1), custom_events.recv())
+            .await
+            .expect("timed out waiting for custom event")
+            .expect("custom event should be published");
+        assert_eq!(published.channel.as_str(), "plugin.com.waddle.eh.event");
+        assert!(matches!(
+            published.payload,
+            EventPayload::PluginCustomEvent {
+                ref plugin_id,
+                ref event_type,
+                ref data
+            } if plugin_id == "com.waddle.eh"
+                && event_type == "event"
+                && data.get("ok").and_then(|value| value.as_bool()) == Some(true)
+        ));
+    }
+
+    #[tokio::test]
+    async fn rule_hook_runs_only_on_the_named_plugin() {
+        let (mut runtime, _dir) = open_runtime(PluginRuntimeConfig::default()).await;
+        let mut manifest = test_manifest_with("com.waddle.pager", false, &[], false, false);
+        manifest.hooks.rule_hook = true;
+        let mut paged = runtime
+            .event_bus()
+            .subscribe("plugin.com.waddle.pager.paged")
+            .expect("event bus subscription should succeed");
+
+        let wasm = r#"
+            (module
+              (import "host-events" "publish-event" (func $publish_event (param i32 i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "plugin.com.waddle.pager.paged")
+              (func (export "plugin_init") (result i32)
+                i32.const 0)
+              (func (export "guest_alloc") (param i32) (result i32)
+                i32.const 1024)
+              (func (export "plugin_rule_hook") (param i32 i32) (result i32)
+                i32.const 0
+                i32.const 29
+                local.get 0
+                local.get 1
+                call $publish_event)
+              (func (export "plugin_shutdown")))
+        "#;
+        runtime
+            .load_plugin(manifest, wasm.as_bytes())
+            .await
+            .expect("plugin load should succeed");
+
+        let hook = |plugin_id: &str| PluginHook::Rule {
+            plugin_id: plugin_id.to_string(),
+            rule_id: "r1".to_string(),
+            hook: "page".to_string(),
+            data: serde_json::json!({ "from": "bob@example.com" }),
+        };
+        runtime
+            .invoke_hook(hook("com.waddle.other"))
+            .await
+            .expect("hook invocation should succeed");
+        assert!(
+            timeout(Duration::from_millis(50), paged.recv())
+                .await
+                .is_err()
+        );
+
+        runtime
+            .invoke_hook(hook("com.waddle.pager"))
+            .await
+            .expect("hook invocation should succeed");
+        let published = timeout(Duration::from_secs(1), paged.recv())
+            .await
+            .expect("timed out waiting for the hook to run")
+            .expect("custom event should be published");
+        let EventPayload::PluginCustomEvent { data, .. } = published.payload else {
+            panic!("unexpected event {published:?}");
+        };
+        assert_eq!(data["ruleId"], "r1");
+        assert_eq!(data["hook"], "page");
+        assert_eq!(data["data"]["from"], "bob@example.com");
+    }
+
+    #[tokio::test]
+    async fn usage_is_tracked_and_limits_apply_per_plugin() {
+        let (mut runtime, _dir) = open_runtime(PluginRuntimeConfig::default()).await;
+        let manifest = test_manifest_with(
+            "com.waddle.usage",
+            false,
+            &["xmpp.message.received"],
+            false,
+            true,
+        );
+        let wasm = r#"
+            (module
+              (import "host-events" "subscribe" (func $subscribe (param i32 i32) (result i32)))
+              (memory (export "memory") 2)
+              (data (i32.const 0) "xmpp.message.received")
+              (func (export "plugin_init") (result i32)
+                i32.const 0
+                i32.const 21
+                call $subscribe)
+              (func (export "plugin_handle_event")
+                (local i32)
+                i32.const 1000
+                local.set 0
+                (block
+                (loop
+                  local.get 0
+                  i32.eqz
+                  br_if 1
+                  local.get 0
+                  i32.const 1
+                  i32.sub
+                  local.set 0
+                  br 0)))
+              (func (export "plugin_shutdown")))
+        "#;
+        runtime
+            .load_plugin(manifest, wasm.as_bytes())
+            .await
+            .expect("plugin load should succeed");
+
+        let event = || {
+            PluginHook::Event(Box::new(Event::new(
+                Channel::new("xmpp.message.received").expect("channel should be valid"),
+                EventSource::Xmpp,
+                EventPayload::RawStanzaReceived {
+                    stanza: "<message/>".to_string(),
+                },
+            )))
+        };
+        for _ in 0..2 {
+            runtime.invoke_hook(event()).await.unwrap();
+        }
+
+        let usage = runtime.usage("com.waddle.usage").unwrap();
+        assert_eq!(usage.invocations, 3);
+        assert!(usage.fuel_consumed > 2 * 1000);
+        assert_eq!(usage.memory_high_water_bytes, 2 * 65_536);
+        assert_eq!(runtime.usage_snapshot(), vec![usage.clone()]);
+
+        // Too little fuel for the loop, for this plugin only.
+        runtime.set_plugin_limits(
+            "com.waddle.usage",
+            PluginLimits {
+                fuel_per_invocation: Some(500),
+                ..PluginLimits::default()
+            },
+        );
+        runtime.invoke_hook(event()).await.unwrap();
+        let limited = runtime.usage("com.waddle.usage").unwrap();
+        assert_eq!(limited.invocations, 4);
+        assert_eq!(limited.fuel_consumed, usage.fuel_consumed + 500);
+        assert_eq!(
+            runtime.get_plugin("com.waddle.usage").unwrap().error_count,
+            1
+        );
+        assert_eq!(
+            runtime.config().fuel_per_invocation_for("com.other.plugin"),
+            1_000_000
+        );
+    }
+
+    #[tokio::test]
+    async fn dispatcher_delivers_only_subscribed_channels() {
+        let (mut runtime, _dir) = open_runtime(PluginRuntimeConfig::default()).await;
+        let event_bus = Arc::clone(runtime.event_bus());
+        let mut custom_events = event_bus
+            .subscribe("plugin.com.waddle.eh.event")
+            .expect("event bus subscription should succeed");
         let wasm = r#"
             (module
               (import "host-events" "subscribe" (func $subscribe (param i32 i32) (result i32)))
               (import "host-events" "publish-event" (func $publish_event (param i32 i32 i32 i32) (result i32)))
               (memory (export "memory") 1)
-              (data (i32.const 0) "xmpp.message.received")
+              (data (i32.const 0) "xmpp.message.*")
               (data (i32.const 64) "plugin.com.waddle.eh.event")
-              (data (i32.const 128) "{\"ok\":true}")
+              (data (i32.const 128) "{}")
               (func (export "plugin_init") (result i32)
                 i32.const 0
-                i32.const 21
+                i32.const 14
                 call $subscribe)
               (func (export "plugin_handle_event") (result i32)
                 i32.const 64
                 i32.const 26
                 i32.const 128
-                i32.const 11
+                i32.const 2
                 call $publish_event)
               (func (export "plugin_shutdown")))
         "#;
-
         runtime
-            .load_plugin(manifest, wasm.as_bytes())
+            .load_plugin(
+                test_manifest_with("com.waddle.eh", false, &["xmpp.message.*"], false, true),
+                wasm.as_bytes(),
+            )
             .await
             .expect("plugin load should succeed");
-
-        let event = Event::new(
-            Channel::new("xmpp.message.received").expect("channel should be valid"),
-            EventSource::Xmpp,
-            EventPayload::RawStanzaReceived {
-                stanza: "<message/>".to_string(),
-            },
+        assert_eq!(
+            runtime.event_filters(),
+            BTreeSet::from(["xmpp.message.*".to_string()])
         );
-        runtime
-            .invoke_hook(PluginHook::Event(Box::new(event)))
-            .await
-            .expect("hook invocation should succeed");
 
-        let published = timeout(Duration::from_secs(1), custom_events.recv())
-            .await
-            .expect("timed out waiting for custom event")
-            .expect("custom event should be published");
-        assert_eq!(published.channel.as_str(), "plugin.com.waddle.eh.event");
-        assert!(matches!(
-            published.payload,
-            EventPayload::PluginCustomEvent {
-                ref plugin_id,
-                ref event_type,
-                ref data
-            } if plugin_id == "com.waddle.eh"
-                && event_type == "event"
-                && data.get("ok").and_then(|value| value.as_bool()) == Some(true)
-        ));
+        let runtime = Arc::new(tokio::sync::Mutex::new(runtime));
+        let publish = |channel: &str, payload| {
+            event_bus
+                .publish(Event::new(
+                    Channel::new(channel).expect("channel should be valid"),
+                    EventSource::Xmpp,
+                    payload,
+                ))
+                .expect("publish should succeed");
+        };
+        let checks = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+
+            publish(
+                "xmpp.presence.received",
+                EventPayload::RawStanzaReceived {
+                    stanza: "<presence/>".to_string(),
+                },
+            );
+            let unmatched = timeout(Duration::from_millis(200), custom_events.recv()).await;
+            assert!(
+                unmatched.is_err(),
+                "plugin woke for an unsubscribed channel"
+            );
+
+            publish(
+                "xmpp.message.delivered",
+                EventPayload::MessageDelivered {
+                    id: "m1".to_string(),
+                    to: "bob@example.com".to_string(),
+                },
+            );
+            timeout(Duration::from_secs(1), custom_events.recv())
+                .await
+                .expect("timed out waiting for custom event")
+                .expect("custom event should be published");
+
+            runtime
+                .lock()
+                .await
+                .unload_plugin("com.waddle.eh")
+                .await
+                .expect("unload should succeed");
+            assert!(runtime.lock().await.event_filters().is_empty());
+        };
+
+        tokio::select! {
+            result = PluginRuntime::dispatch_events(Arc::clone(&runtime)) => {
+                panic!("dispatcher stopped: {result:?}")
+            }
+            () = checks => {}
+        }
+    }
+
+    #[test]
+    fn union_pattern_matches_any_filter() {
+        assert_eq!(union_pattern(&BTreeSet::new()), None);
+        let filters = BTreeSet::from(["system.*".to_string(), "xmpp.message.*".to_string()]);
+        let pattern = union_pattern(&filters).expect("filters should combine");
+        assert_eq!(pattern, "{system.*,xmpp.message.*}");
     }
 
     #[tokio::test]
@@ -2010,6 +4236,155 @@ gui_metadata = false
         );
     }
 
+    #[tokio::test]
+    async fn stanza_processors_modify_and_consume_in_priority_order() {
+        let (mut runtime, _dir) = open_runtime(PluginRuntimeConfig::default()).await;
+        let mut rewriter = test_manifest_with("com.waddle.rewriter", true, &[], true, false);
+        rewriter.hooks.stanza_priority = 20;
+        let mut observer = test_manifest_with("com.waddle.observer", true, &[], true, false);
+        observer.hooks.stanza_priority = -5;
+
+        let rewriter_wasm = r#"
+            (module
+              (memory (export "memory") 1)
+              (data (i32.const 1024) "<message xmlns='jabber:client'><body>rewritten</body></message>")
+              (func (export "plugin_init") (result i32)
+                i32.const 0)
+              (func (export "guest_alloc") (param i32) (result i32)
+                i32.const 0)
+              (func (export "get_result_ptr") (result i32)
+                i32.const 1024)
+              (func (export "get_result_len") (result i32)
+                i32.const 63)
+              (func (export "plugin_process_inbound") (param i32 i32) (result i32)
+                local.get 0
+                i32.load8_u offset=1
+                i32.const 109
+                i32.eq)
+              (func (export "plugin_process_outbound") (param i32 i32) (result i32)
+                i32.const 2)
+              (func (export "plugin_shutdown")))
+        "#;
+        let observer_wasm = r#"
+            (module
+              (func (export "plugin_init"))
+              (func (export "plugin_process_inbound"))
+              (func (export "plugin_process_outbound"))
+              (func (export "plugin_shutdown")))
+        "#;
+
+        runtime
+            .load_plugin(rewriter, rewriter_wasm.as_bytes())
+            .await
+            .expect("rewriter load should succeed");
+        runtime
+            .load_plugin(observer, observer_wasm.as_bytes())
+            .await
+            .expect("observer load should succeed");
+
+        assert_eq!(
+            runtime.stanza_processors(),
+            vec![
+                ("com.waddle.observer".to_string(), -5),
+                ("com.waddle.rewriter".to_string(), 20),
+            ]
+        );
+
+        let message = PluginHook::InboundStanza("<message/>".to_string());
+        assert_eq!(
+            runtime
+                .invoke_stanza_processor("com.waddle.observer", &message)
+                .await
+                .expect("observer should succeed"),
+            StanzaVerdict::PassThrough
+        );
+        assert_eq!(
+            runtime
+                .invoke_stanza_processor("com.waddle.rewriter", &message)
+                .await
+                .expect("rewriter should succeed"),
+            StanzaVerdict::Modify(
+                "<message xmlns='jabber:client'><body>rewritten</body></message>".to_string()
+            )
+        );
+        assert_eq!(
+            runtime
+                .invoke_stanza_processor(
+                    "com.waddle.rewriter",
+                    &PluginHook::InboundStanza("<presence/>".to_string())
+                )
+                .await
+                .expect("rewriter should succeed"),
+            StanzaVerdict::PassThrough
+        );
+        assert_eq!(
+            runtime
+                .invoke_stanza_processor(
+                    "com.waddle.rewriter",
+                    &PluginHook::OutboundStanza("<message/>".to_string())
+                )
+                .await
+                .expect("rewriter should succeed"),
+            StanzaVerdict::Consume
+        );
+    }
+
+    #[tokio::test]
+    async fn stanza_processor_fuel_exhaustion_is_reported() {
+        let config = PluginRuntimeConfig {
+            fuel_per_invocation: 10_000,
+            ..PluginRuntimeConfig::default()
+        };
+        let (mut runtime, _dir) = open_runtime(config).await;
+        let manifest = test_manifest_with("com.waddle.spinner", true, &[], true, false);
+        let mut errors = runtime
+            .event_bus()
+            .subscribe("plugin.com.waddle.spinner.error")
+            .expect("event bus subscription should succeed");
+
+        let wasm = r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "plugin_init"))
+              (func (export "guest_alloc") (param i32) (result i32)
+                i32.const 0)
+              (func (export "plugin_process_inbound") (param i32 i32) (result i32)
+                (loop
+                  br 0)
+                i32.const 0)
+              (func (export "plugin_process_outbound") (param i32 i32) (result i32)
+                i32.const 0)
+              (func (export "plugin_shutdown")))
+        "#;
+        runtime
+            .load_plugin(manifest, wasm.as_bytes())
+            .await
+            .expect("plugin load should succeed");
+
+        let result = runtime
+            .invoke_stanza_processor(
+                "com.waddle.spinner",
+                &PluginHook::InboundStanza("<message/>".to_string()),
+            )
+            .await;
+        assert!(
+            matches!(result, Err(PluginError::FuelExhausted { ref id }) if id == "com.waddle.spinner"),
+            "unexpected result: {result:?}"
+        );
+
+        let error = timeout(Duration::from_secs(1), errors.recv())
+            .await
+            .expect("timed out waiting for error event")
+            .expect("error event should exist");
+        assert!(matches!(error.payload, EventPayload::PluginError { .. }));
+        assert_eq!(
+            runtime
+                .get_plugin("com.waddle.spinner")
+                .map(|info| info.error_count),
+            Some(1)
+        );
+    }
+
     #[tokio::test]
     async fn auto_disables_plugin_after_five_errors() {
         let config = PluginRuntimeConfig {
@@ -2049,6 +4424,168 @@ gui_metadata = false
         ));
     }
 
+    #[tokio::test]
+    async fn failing_plugin_is_disabled_until_re_enabled() {
+        let config = PluginRuntimeConfig {
+            error_threshold: 2,
+            ..PluginRuntimeConfig::default()
+        };
+        let (mut runtime, _dir) = open_runtime(config).await;
+        let plugin_id = "com.waddle.runtime.crashy";
+        let manifest =
+            test_manifest_with(plugin_id, false, &["xmpp.message.received"], false, true);
+        let wasm = r#"
+            (module
+              (import "host-events" "subscribe" (func $subscribe (param i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "xmpp.message.received")
+              (func (export "plugin_init") (result i32)
+                i32.const 0
+                i32.const 21
+                call $subscribe)
+              (func (export "plugin_handle_event")
+                unreachable)
+              (func (export "plugin_shutdown")))
+        "#;
+        runtime
+            .load_plugin(manifest, wasm.as_bytes())
+            .await
+            .expect("plugin load should succeed");
+        let mut errors = runtime
+            .event_bus()
+            .subscribe("plugin.com.waddle.runtime.crashy.error")
+            .unwrap();
+        let event = || {
+            PluginHook::Event(Box::new(Event::new(
+                Channel::new("xmpp.message.received").expect("channel should be valid"),
+                EventSource::Xmpp,
+                EventPayload::RawStanzaReceived {
+                    stanza: "<message/>".to_string(),
+                },
+            )))
+        };
+
+        runtime.invoke_hook(event()).await.unwrap();
+        assert!(!runtime.is_disabled(plugin_id));
+        runtime.invoke_hook(event()).await.unwrap();
+        assert!(runtime.is_disabled(plugin_id));
+        let info = runtime.get_plugin(plugin_id).unwrap();
+        assert!(matches!(info.status, PluginStatus::Error(_)));
+        assert_eq!(info.error_count, 2);
+        assert!(runtime.event_filters().is_empty());
+
+        let mut reasons = Vec::new();
+        while let Ok(Ok(event)) = timeout(Duration::from_millis(100), errors.recv()).await {
+            if let EventPayload::PluginError { error, .. } = event.payload {
+                reasons.push(error);
+            }
+        }
+        assert_eq!(reasons.len(), 3);
+        assert_eq!(reasons[2], "auto-disabled: too many errors");
+
+        // No longer called, so no longer failing.
+        let invocations = runtime.usage(plugin_id).unwrap().invocations;
+        runtime.invoke_hook(event()).await.unwrap();
+        assert_eq!(runtime.usage(plugin_id).unwrap().invocations, invocations);
+
+        runtime.re_enable(plugin_id).unwrap();
+        assert!(!runtime.is_disabled(plugin_id));
+        let info = runtime.get_plugin(plugin_id).unwrap();
+        assert_eq!(info.status, PluginStatus::Active);
+        assert_eq!(info.error_count, 0);
+        assert!(runtime.event_filters().contains("xmpp.message.received"));
+        runtime.invoke_hook(event()).await.unwrap();
+        assert_eq!(
+            runtime.usage(plugin_id).unwrap().invocations,
+            invocations + 1
+        );
+
+        assert!(matches!(
+            runtime.re_enable("com.waddle.runtime.missing"),
+            Err(PluginError::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn gui_metadata_hook_describes_installed_components() {
+        let (mut runtime, dir) = open_runtime(PluginRuntimeConfig::default()).await;
+        let source_dir = dir.path().join("source");
+        std::fs::create_dir_all(source_dir.join("vue")).unwrap();
+        std::fs::write(
+            source_dir.join("manifest.toml"),
+            r#"
+[plugin]
+id = "com.waddle.runtime.gui"
+name = "GUI Plugin"
+version = "1.0.0"
+description = "Adds a panel"
+
+[permissions]
+
+[hooks]
+gui_metadata = true
+
+[gui]
+components = ["Panel.vue"]
+"#,
+        )
+        .unwrap();
+        std::fs::write(source_dir.join("vue/Panel.vue"), "<template></template>").unwrap();
+        let json = r#"{"panels":[{"id":"stats","location":"sidebar","component":"Panel.vue","title":"Stats"}]}"#;
+        let wasm = format!(
+            r#"
+            (module
+              (memory (export "memory") 1)
+              (data (i32.const 0) "{}")
+              (func (export "plugin_init") (result i32)
+                i32.const 0)
+              (func (export "plugin_gui_component_info") (result i32)
+                i32.const 0)
+              (func (export "get_result_ptr") (result i32)
+                i32.const 0)
+              (func (export "get_result_len") (result i32)
+                i32.const {})
+              (func (export "plugin_shutdown")))
+            "#,
+            json.replace('"', "\\\""),
+            json.len()
+        );
+        std::fs::write(source_dir.join("plugin.wasm"), &wasm).unwrap();
+
+        let registry = PluginRegistry::new(
+            crate::registry::RegistryConfig::default(),
+            dir.path().join("data"),
+        )
+        .unwrap();
+        registry.install_local(&source_dir).await.unwrap();
+        let contributions = runtime.ui_contributions(&registry).await.unwrap();
+        assert_eq!(contributions.len(), 1);
+        assert!(!contributions[0].active);
+        assert!(contributions[0].components.is_empty());
+
+        let files = registry.get_plugin_files("com.waddle.runtime.gui").unwrap();
+        runtime
+            .load_plugin(files.manifest, wasm.as_bytes())
+            .await
+            .expect("plugin load should succeed");
+        assert_eq!(
+            runtime
+                .invoke_hook(PluginHook::GuiGetComponentInfo)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some(json)
+        );
+
+        let contributions = runtime.ui_contributions(&registry).await.unwrap();
+        let contribution = &contributions[0];
+        assert!(contribution.active);
+        let vue_dir = contribution.vue_dir.as_ref().unwrap();
+        assert!(vue_dir.join("Panel.vue").exists());
+        assert_eq!(contribution.components.panels.len(), 1);
+        assert_eq!(contribution.components.panels[0].id, "stats");
+    }
+
     #[tokio::test]
     async fn memory_limit_is_enforced_for_init() {
         let config = PluginRuntimeConfig {
@@ -2077,6 +4614,116 @@ gui_metadata = false
         );
     }
 
+    /// Core wasm imports for every function in `wit/host.wit`, spelled as
+    /// the contract spells them.
+    fn wit_host_imports() -> Vec<String> {
+        let core_types = |wit_type: &str| match wit_type.trim() {
+            "string" | "list<u8>" => "i32 i32",
+            "s32" => "i32",
+            other => panic!("unmapped WIT type {other}"),
+        };
+        let mut interface = "";
+        let mut imports = Vec::new();
+        for line in include_str!("../wit/host.wit").lines().map(str::trim) {
+            if let Some(name) = line.strip_prefix("interface ") {
+                interface = name.trim_end_matches('{').trim();
+                continue;
+            }
+            let Some((name, signature)) = line.split_once(": func(") else {
+                continue;
+            };
+            let (params, result) = signature.split_once(')').unwrap();
+            let params: Vec<&str> = params
+                .split(',')
+                .filter_map(|param| param.split_once(':'))
+                .map(|(_, wit_type)| core_types(wit_type))
+                .collect();
+            let result = match result.trim().trim_end_matches(';').strip_prefix("->") {
+                Some(wit_type) => format!(" (result {})", core_types(wit_type)),
+                None => String::new(),
+            };
+            imports.push(format!(
+                "(import \"{interface}\" \"{name}\" (func (param {}){result}))",
+                params.join(" ")
+            ));
+        }
+        imports
+    }
+
+    #[tokio::test]
+    async fn modules_importing_the_wit_contract_instantiate() {
+        let (mut runtime, _dir) = open_runtime(PluginRuntimeConfig::default()).await;
+        let imports = wit_host_imports();
+        assert!(imports.len() >= 11, "parsed imports: {imports:?}");
+        let wasm = format!(
+            r#"
+            (module
+              {}
+              (memory (export "memory") 1)
+              (func (export "plugin_init"))
+              (func (export "plugin_shutdown")))
+            "#,
+            imports.join("\n              ")
+        );
+
+        runtime
+            .load_plugin(test_manifest("com.waddle.runtime.wit"), wasm.as_bytes())
+            .await
+            .expect("a module importing the WIT contract should instantiate");
+    }
+
+    #[tokio::test]
+    async fn subscribing_is_charged_against_fuel() {
+        let config = PluginRuntimeConfig {
+            fuel_per_invocation: 5_000,
+            ..PluginRuntimeConfig::default()
+        };
+        let (mut runtime, _dir) = open_runtime(config).await;
+        let manifest = test_manifest_with(
+            "com.waddle.runtime.subfuel",
+            false,
+            &["xmpp.message.received"],
+            false,
+            true,
+        );
+        let wasm = r#"
+            (module
+              (import "host-events" "subscribe" (func $subscribe (param i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "xmpp.message.received")
+              (func (export "plugin_init")
+                (local i32)
+                i32.const 10
+                local.set 0
+                (block
+                (loop
+                  local.get 0
+                  i32.eqz
+                  br_if 1
+                  i32.const 0
+                  i32.const 21
+                  call $subscribe
+                  drop
+                  local.get 0
+                  i32.const 1
+                  i32.sub
+                  local.set 0
+                  br 0)))
+              (func (export "plugin_handle_event") (param i32 i32) (result i32)
+                i32.const 0)
+              (func (export "plugin_shutdown")))
+        "#;
+
+        let result = runtime.load_plugin(manifest, wasm.as_bytes()).await;
+        assert!(
+            matches!(
+                result,
+                Err(PluginError::FuelExhausted { ref id }) if id == "com.waddle.runtime.subfuel"
+            ),
+            "unexpected result: {result:?}"
+        );
+    }
+
     #[tokio::test]
     async fn fuel_limit_is_enforced_for_init() {
         let config = PluginRuntimeConfig {
@@ -0,0 +1,267 @@
+//! Verification of cosign-style signatures on plugin artifacts.
+//!
+//! `cosign sign` stores signatures for an artifact in the same repository
+//! under the tag `sha256-<hex>.sig`. Each layer of that signature manifest is
+//! a "simple signing" JSON payload naming the signed manifest digest, with
+//! the base64 signature over the payload in the
+//! `dev.cosignproject.cosign/signature` annotation. ECDSA P-256 (cosign's
+//! default) and Ed25519 keys are supported.
+
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+use ring::signature::{ECDSA_P256_SHA256_ASN1, ED25519, UnparsedPublicKey};
+
+pub const COSIGN_SIGNATURE_MEDIA_TYPE: &str = "application/vnd.dev.cosign.simplesigning.v1+json";
+pub const COSIGN_SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+
+/// DER prefix of an Ed25519 SubjectPublicKeyInfo, followed by the 32-byte key.
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// DER prefix of a P-256 SubjectPublicKeyInfo, followed by the 65-byte
+/// uncompressed point.
+const P256_SPKI_PREFIX: [u8; 26] = [
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a,
+    0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignaturePolicy {
+    /// Refuse to install artifacts without a valid signature.
+    Enforce,
+    /// Install anyway, logging a warning.
+    Warn,
+    Disabled,
+}
+
+impl SignaturePolicy {
+    /// Parse a `signature_policy` setting. Unknown values fail closed.
+    pub fn from_config(value: &str) -> Self {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "warn" => Self::Warn,
+            "disabled" | "off" | "none" => Self::Disabled,
+            _ => Self::Enforce,
+        }
+    }
+}
+
+/// One layer of a cosign signature manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactSignature {
+    pub payload: Vec<u8>,
+    /// Base64 signature from the layer annotation.
+    pub signature: String,
+}
+
+/// A public key trusted to sign plugin artifacts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrustedKey {
+    EcdsaP256(Vec<u8>),
+    Ed25519(Vec<u8>),
+}
+
+impl TrustedKey {
+    /// Parse a PEM (or bare base64) SubjectPublicKeyInfo, as written by
+    /// `cosign generate-key-pair`.
+    pub fn from_pem(pem: &str) -> Result<Self, String> {
+        let body: String = pem
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .map(str::trim)
+            .collect();
+        let der = STANDARD
+            .decode(body)
+            .map_err(|error| format!("public key is not valid base64: {error}"))?;
+
+        if let Some(point) = der.strip_prefix(&P256_SPKI_PREFIX[..])
+            && point.len() == 65
+        {
+            return Ok(Self::EcdsaP256(point.to_vec()));
+        }
+        if let Some(key) = der.strip_prefix(&ED25519_SPKI_PREFIX[..])
+            && key.len() == 32
+        {
+            return Ok(Self::Ed25519(key.to_vec()));
+        }
+        Err("only ECDSA P-256 and Ed25519 public keys are supported".to_string())
+    }
+
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        let result = match self {
+            Self::EcdsaP256(point) => {
+                UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, point).verify(message, signature)
+            }
+            Self::Ed25519(key) => UnparsedPublicKey::new(&ED25519, key).verify(message, signature),
+        };
+        result.is_ok()
+    }
+}
+
+/// Tag under which cosign publishes signatures for `manifest_digest`.
+pub fn signature_tag(manifest_digest: &str) -> String {
+    format!("{}.sig", manifest_digest.replacen(':', "-", 1))
+}
+
+/// Check that at least one signature is a valid signature by one of
+/// `trusted_keys` over a payload naming `manifest_digest`.
+pub fn verify_artifact(
+    manifest_digest: &str,
+    signatures: &[ArtifactSignature],
+    trusted_keys: &[TrustedKey],
+) -> Result<(), String> {
+    if trusted_keys.is_empty() {
+        return Err("no trusted signing keys are configured".to_string());
+    }
+    if signatures.is_empty() {
+        return Err("artifact is not signed".to_string());
+    }
+
+    let mut last_error = String::new();
+    for signature in signatures {
+        match verify_one(manifest_digest, signature, trusted_keys) {
+            Ok(()) => return Ok(()),
+            Err(error) => last_error = error,
+        }
+    }
+    Err(last_error)
+}
+
+fn verify_one(
+    manifest_digest: &str,
+    signature: &ArtifactSignature,
+    trusted_keys: &[TrustedKey],
+) -> Result<(), String> {
+    let payload: serde_json::Value = serde_json::from_slice(&signature.payload)
+        .map_err(|error| format!("signature payload is not JSON: {error}"))?;
+    let signed_digest = payload
+        .pointer("/critical/image/docker-manifest-digest")
+        .and_then(|digest| digest.as_str())
+        .ok_or_else(|| "signature payload names no manifest digest".to_string())?;
+    if signed_digest != manifest_digest {
+        return Err(format!(
+            "signature is for {signed_digest}, not {manifest_digest}"
+        ));
+    }
+
+    let raw = STANDARD
+        .decode(signature.signature.trim())
+        .map_err(|error| format!("signature is not valid base64: {error}"))?;
+    if trusted_keys
+        .iter()
+        .any(|key| key.verify(&signature.payload, &raw))
+    {
+        Ok(())
+    } else {
+        Err("signature does not match any trusted key".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::rand::SystemRandom;
+    use ring::signature::{
+        ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair, Ed25519KeyPair, KeyPair as _,
+    };
+
+    use super::*;
+
+    const DIGEST: &str = "sha256:4d3c2b1a";
+
+    fn payload(digest: &str) -> Vec<u8> {
+        format!(
+            r#"{{"critical":{{"identity":{{"docker-reference":"ghcr.io/waddle-social/example"}},"image":{{"docker-manifest-digest":"{digest}"}},"type":"cosign container image signature"}},"optional":null}}"#
+        )
+        .into_bytes()
+    }
+
+    fn pem(prefix: &[u8], key: &[u8]) -> String {
+        let mut der = prefix.to_vec();
+        der.extend_from_slice(key);
+        format!(
+            "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n",
+            STANDARD.encode(der)
+        )
+    }
+
+    /// A P-256 key as cosign generates it, and its signature over the
+    /// payload for `digest`.
+    fn ecdsa_signed(digest: &str) -> (TrustedKey, ArtifactSignature) {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+        let payload = payload(digest);
+        let signature = pair.sign(&rng, &payload).unwrap();
+        let key =
+            TrustedKey::from_pem(&pem(&P256_SPKI_PREFIX, pair.public_key().as_ref())).unwrap();
+        (
+            key,
+            ArtifactSignature {
+                payload,
+                signature: STANDARD.encode(signature.as_ref()),
+            },
+        )
+    }
+
+    #[test]
+    fn accepts_signature_by_trusted_key_for_the_manifest() {
+        let (key, signature) = ecdsa_signed(DIGEST);
+        assert_eq!(verify_artifact(DIGEST, &[signature], &[key]), Ok(()));
+    }
+
+    #[test]
+    fn accepts_ed25519_signatures() {
+        let rng = SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let key =
+            TrustedKey::from_pem(&pem(&ED25519_SPKI_PREFIX, pair.public_key().as_ref())).unwrap();
+        let payload = payload(DIGEST);
+        let signature = ArtifactSignature {
+            signature: STANDARD.encode(pair.sign(&payload).as_ref()),
+            payload,
+        };
+
+        assert_eq!(verify_artifact(DIGEST, &[signature], &[key]), Ok(()));
+    }
+
+    #[test]
+    fn rejects_untrusted_keys_and_other_digests() {
+        let (_, signature) = ecdsa_signed(DIGEST);
+        let (other_key, _) = ecdsa_signed(DIGEST);
+        assert!(verify_artifact(DIGEST, &[signature], &[other_key]).is_err());
+
+        let (key, signature) = ecdsa_signed("sha256:ffff");
+        let error = verify_artifact(DIGEST, &[signature], std::slice::from_ref(&key)).unwrap_err();
+        assert!(error.contains("not sha256:4d3c2b1a"));
+
+        assert!(verify_artifact(DIGEST, &[], &[key]).is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_key_types() {
+        assert!(
+            TrustedKey::from_pem("-----BEGIN PUBLIC KEY-----\nAAAA\n-----END PUBLIC KEY-----")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn unknown_policies_fail_closed() {
+        assert_eq!(SignaturePolicy::from_config("warn"), SignaturePolicy::Warn);
+        assert_eq!(
+            SignaturePolicy::from_config("disabled"),
+            SignaturePolicy::Disabled
+        );
+        assert_eq!(
+            SignaturePolicy::from_config("Enforce"),
+            SignaturePolicy::Enforce
+        );
+        assert_eq!(
+            SignaturePolicy::from_config("strict"),
+            SignaturePolicy::Enforce
+        );
+        assert_eq!(signature_tag("sha256:abcd"), "sha256-abcd.sig");
+    }
+}
@@ -0,0 +1,344 @@
+//! Automatic away. The operating system is polled for the time since the
+//! last keyboard or pointer input; past the configured thresholds our show
+//! moves to away and then extended away, and whatever the user had chosen
+//! comes back as soon as they touch the machine again.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+use waddle_core::config::PresenceConfig;
+use waddle_core::event::PresenceShow;
+
+use crate::{PresenceError, PresenceManager};
+
+/// How often the system idle time is sampled.
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+pub type IdleFuture<'a> = Pin<Box<dyn Future<Output = Option<Duration>> + Send + 'a>>;
+
+/// Reports how long the user has been inactive.
+pub trait IdleSource: Send + Sync {
+    /// Time since the last input, or `None` when it cannot be determined.
+    fn idle_time(&self) -> IdleFuture<'_>;
+}
+
+/// Idle time as the desktop sees it: the GNOME or freedesktop screensaver
+/// D-Bus interfaces on Linux, `IOHIDSystem` on macOS and `GetLastInputInfo`
+/// on Windows. Other platforms never report idle.
+#[derive(Default)]
+pub struct SystemIdleSource {
+    #[cfg(target_os = "linux")]
+    session_bus: tokio::sync::Mutex<Option<zbus::Connection>>,
+}
+
+impl IdleSource for SystemIdleSource {
+    fn idle_time(&self) -> IdleFuture<'_> {
+        Box::pin(self.query())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl SystemIdleSource {
+    async fn query(&self) -> Option<Duration> {
+        let connection = {
+            let mut bus = self.session_bus.lock().await;
+            if bus.is_none() {
+                *bus = zbus::Connection::session().await.ok();
+            }
+            bus.clone()?
+        };
+
+        // Mutter answers in milliseconds.
+        if let Ok(reply) = connection
+            .call_method(
+                Some("org.gnome.Mutter.IdleMonitor"),
+                "/org/gnome/Mutter/IdleMonitor/Core",
+                Some("org.gnome.Mutter.IdleMonitor"),
+                "GetIdletime",
+                &(),
+            )
+            .await
+            && let Ok(millis) = reply.body().deserialize::<u64>()
+        {
+            return Some(Duration::from_millis(millis));
+        }
+
+        // KDE and other freedesktop screensavers, also in milliseconds.
+        let reply = connection
+            .call_method(
+                Some("org.freedesktop.ScreenSaver"),
+                "/org/freedesktop/ScreenSaver",
+                Some("org.freedesktop.ScreenSaver"),
+                "GetSessionIdleTime",
+                &(),
+            )
+            .await
+            .ok()?;
+        let millis = reply.body().deserialize::<u32>().ok()?;
+        Some(Duration::from_millis(millis.into()))
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl SystemIdleSource {
+    async fn query(&self) -> Option<Duration> {
+        let output = tokio::process::Command::new("ioreg")
+            .args(["-c", "IOHIDSystem", "-d", "4"])
+            .output()
+            .await
+            .ok()?;
+        parse_hid_idle_time(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+#[cfg(windows)]
+impl SystemIdleSource {
+    async fn query(&self) -> Option<Duration> {
+        use windows_sys::Win32::System::SystemInformation::GetTickCount;
+        use windows_sys::Win32::UI::Input::KeyboardAndMouse::{GetLastInputInfo, LASTINPUTINFO};
+
+        let mut info = LASTINPUTINFO {
+            cbSize: size_of::<LASTINPUTINFO>() as u32,
+            dwTime: 0,
+        };
+        // SAFETY: `info` is a valid LASTINPUTINFO with `cbSize` set, as the
+        // call requires.
+        if unsafe { GetLastInputInfo(&mut info) } == 0 {
+            return None;
+        }
+        // Both are millisecond tick counts that wrap after 49.7 days.
+        let idle = unsafe { GetTickCount() }.wrapping_sub(info.dwTime);
+        Some(Duration::from_millis(idle.into()))
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+impl SystemIdleSource {
+    async fn query(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// The `"HIDIdleTime" = <nanoseconds>` entry of `ioreg` output.
+#[cfg(any(target_os = "macos", test))]
+fn parse_hid_idle_time(output: &str) -> Option<Duration> {
+    let line = output
+        .lines()
+        .find(|line| line.contains("\"HIDIdleTime\""))?;
+    let nanos = line.rsplit('=').next()?.trim().parse::<u64>().ok()?;
+    Some(Duration::from_nanos(nanos))
+}
+
+/// A presence change made by the monitor, kept so it can be undone.
+struct AutoAway {
+    /// The show and status the user had chosen.
+    restore: (PresenceShow, Option<String>),
+    /// The show the monitor last applied.
+    applied: PresenceShow,
+}
+
+pub struct AutoAwayMonitor {
+    presence: Arc<PresenceManager>,
+    source: Box<dyn IdleSource>,
+    away_after: Duration,
+    xa_after: Option<Duration>,
+    state: Mutex<Option<AutoAway>>,
+}
+
+impl AutoAwayMonitor {
+    pub fn new(presence: Arc<PresenceManager>, config: &PresenceConfig) -> Self {
+        let minutes = |mins: u64| Duration::from_secs(mins * 60);
+        Self {
+            presence,
+            source: Box::new(SystemIdleSource::default()),
+            away_after: minutes(config.away_after_mins),
+            xa_after: (config.xa_after_mins > 0).then(|| minutes(config.xa_after_mins)),
+            state: Mutex::new(None),
+        }
+    }
+
+    /// Read idle time from `source` instead of the operating system.
+    pub fn with_idle_source(mut self, source: impl IdleSource + 'static) -> Self {
+        self.source = Box::new(source);
+        self
+    }
+
+    /// Sample the idle time once and adjust our presence to it.
+    pub async fn check(&self) {
+        if let Some(idle) = self.source.idle_time().await {
+            self.apply(idle);
+        }
+    }
+
+    fn apply(&self, idle: Duration) {
+        let own = self.presence.own_presence();
+        let mut state = self.state.lock().unwrap();
+
+        // Our presence changed under us, by hand or through a disconnect;
+        // that choice now wins and is not undone later.
+        if state
+            .as_ref()
+            .is_some_and(|auto| !same_show(&auto.applied, &own.show))
+        {
+            *state = None;
+        }
+
+        if idle < self.away_after {
+            if let Some(AutoAway {
+                restore: (show, status),
+                ..
+            }) = state.take()
+            {
+                info!(?show, "input resumed, restoring presence");
+                if let Err(e) = self
+                    .presence
+                    .set_own_presence(show, status.as_deref(), None)
+                {
+                    warn!(error = %e, "failed to restore presence after auto-away");
+                }
+            }
+            return;
+        }
+
+        let target = match self.xa_after {
+            Some(xa_after) if idle >= xa_after => PresenceShow::Xa,
+            _ => PresenceShow::Away,
+        };
+        match state.as_mut() {
+            // Only a user who shows as available is moved; away, busy and
+            // invisible are deliberate choices.
+            None if matches!(own.show, PresenceShow::Available | PresenceShow::Chat) => {
+                *state = Some(AutoAway {
+                    restore: (own.show, own.status),
+                    applied: target.clone(),
+                });
+            }
+            Some(auto) if !same_show(&auto.applied, &target) => auto.applied = target.clone(),
+            _ => return,
+        }
+
+        info!(
+            ?target,
+            idle_secs = idle.as_secs(),
+            "no recent input, changing presence"
+        );
+        let since = Utc::now() - chrono::Duration::from_std(idle).unwrap_or_default();
+        self.presence.set_auto_away(target, since);
+    }
+
+    pub async fn run(self: Arc<Self>) -> Result<(), PresenceError> {
+        let mut ticker = tokio::time::interval(POLL_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            self.check().await;
+        }
+    }
+}
+
+fn same_show(a: &PresenceShow, b: &PresenceShow) -> bool {
+    std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use waddle_core::event::{BroadcastEventBus, EventBus};
+
+    #[derive(Clone, Default)]
+    struct FakeIdle(Arc<Mutex<Duration>>);
+
+    impl FakeIdle {
+        fn set_minutes(&self, minutes: u64) {
+            *self.0.lock().unwrap() = Duration::from_secs(minutes * 60);
+        }
+    }
+
+    impl IdleSource for FakeIdle {
+        fn idle_time(&self) -> IdleFuture<'_> {
+            let idle = *self.0.lock().unwrap();
+            Box::pin(async move { Some(idle) })
+        }
+    }
+
+    fn make_monitor() -> (AutoAwayMonitor, Arc<PresenceManager>, FakeIdle) {
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let presence = Arc::new(PresenceManager::new(event_bus));
+        let idle = FakeIdle::default();
+        let monitor = AutoAwayMonitor::new(presence.clone(), &PresenceConfig::default())
+            .with_idle_source(idle.clone());
+        (monitor, presence, idle)
+    }
+
+    #[tokio::test]
+    async fn goes_away_then_xa_and_restores_on_input() {
+        let (monitor, presence, idle) = make_monitor();
+        presence
+            .set_own_presence(PresenceShow::Chat, Some("around"), None)
+            .unwrap();
+
+        idle.set_minutes(6);
+        monitor.check().await;
+        let own = presence.own_presence();
+        assert!(matches!(own.show, PresenceShow::Away));
+        assert_eq!(own.status.as_deref(), Some("around"));
+        assert!(own.idle_since.is_some());
+
+        idle.set_minutes(31);
+        monitor.check().await;
+        assert!(matches!(presence.own_presence().show, PresenceShow::Xa));
+
+        idle.set_minutes(0);
+        monitor.check().await;
+        let own = presence.own_presence();
+        assert!(matches!(own.show, PresenceShow::Chat));
+        assert_eq!(own.status.as_deref(), Some("around"));
+        assert_eq!(own.idle_since, None);
+    }
+
+    #[tokio::test]
+    async fn a_manual_change_while_away_is_kept() {
+        let (monitor, presence, idle) = make_monitor();
+        presence
+            .set_own_presence(PresenceShow::Available, None, None)
+            .unwrap();
+
+        idle.set_minutes(10);
+        monitor.check().await;
+        assert!(matches!(presence.own_presence().show, PresenceShow::Away));
+
+        presence
+            .set_own_presence(PresenceShow::Dnd, Some("focus"), None)
+            .unwrap();
+        idle.set_minutes(0);
+        monitor.check().await;
+        assert!(matches!(presence.own_presence().show, PresenceShow::Dnd));
+    }
+
+    #[tokio::test]
+    async fn deliberate_shows_are_left_alone() {
+        let (monitor, presence, idle) = make_monitor();
+        for show in [PresenceShow::Dnd, PresenceShow::Invisible] {
+            presence.set_own_presence(show.clone(), None, None).unwrap();
+            idle.set_minutes(60);
+            monitor.check().await;
+            assert!(same_show(&presence.own_presence().show, &show));
+        }
+    }
+
+    #[test]
+    fn parses_ioreg_idle_time() {
+        let output = "    | |   \"HIDIdleTime\" = 1500000000\n    | |   \"HIDKind\" = 0";
+        assert_eq!(
+            parse_hid_idle_time(output),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(parse_hid_idle_time("\"HIDKind\" = 0"), None);
+    }
+}
@@ -0,0 +1,676 @@
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "native")]
+use std::sync::Mutex;
+use std::sync::RwLock;
+
+use serde::Serialize;
+
+use waddle_core::jid::{Jid, bare_jid};
+
+#[cfg(feature = "native")]
+use std::sync::Arc;
+
+#[cfg(feature = "native")]
+use tracing::{debug, error, warn};
+
+#[cfg(feature = "native")]
+use waddle_core::event::{
+    Channel, Event, EventBus, EventPayload, EventSource, PresenceShow, channels,
+};
+
+#[cfg(feature = "native")]
+use crate::PresenceError;
+#[cfg(feature = "native")]
+use crate::upload::{SEARCH_TIMEOUT, UploadDiscovery, query_channel};
+
+const NS_RECEIPTS: &str = "urn:xmpp:receipts";
+const NS_CORRECTION: &str = "urn:xmpp:message-correct:0";
+const NS_REACTIONS: &str = "urn:xmpp:reactions:0";
+/// OMEMO device list notifications, legacy (0.3) and current (XEP-0384 v2).
+const NS_OMEMO_DEVICES: [&str; 2] = [
+    "eu.siacs.conversations.axolotl.devicelist+notify",
+    "urn:xmpp:omemo:2:devices+notify",
+];
+
+/// What a peer can handle, for deciding which affordances to offer in a
+/// conversation. A feature is only reported as supported when every known
+/// online resource advertises it, since a message reaches all of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContactCapabilities {
+    pub receipts: bool,
+    pub corrections: bool,
+    pub reactions: bool,
+    pub omemo: bool,
+    /// XEP-0363 upload limit of the peer's server, in bytes.
+    pub upload_max_size: Option<u64>,
+}
+
+/// Caches XEP-0115 entity capabilities and disco#info results, requesting
+/// disco#info for verification strings it has not seen yet. A result is only
+/// cached under a verification string it actually hashes to, so one peer
+/// cannot misreport the features of every client sharing that string.
+pub struct CapabilitiesManager {
+    /// Caps verification string -> advertised features.
+    caps_features: RwLock<HashMap<String, Vec<String>>>,
+    /// Full JID -> caps verification string.
+    resource_caps: RwLock<HashMap<Jid, String>>,
+    /// Disco#info already requested: caps node for clients, domain for
+    /// servers.
+    requested: RwLock<HashSet<String>>,
+    /// Server domain -> upload limit of its upload service, once found.
+    upload_limits: RwLock<HashMap<String, Option<u64>>>,
+    #[cfg(feature = "native")]
+    uploads: Mutex<UploadDiscovery>,
+    #[cfg(feature = "native")]
+    event_bus: Arc<dyn EventBus>,
+}
+
+impl CapabilitiesManager {
+    #[cfg(feature = "native")]
+    pub fn new(event_bus: Arc<dyn EventBus>) -> Self {
+        Self {
+            caps_features: RwLock::new(HashMap::new()),
+            resource_caps: RwLock::new(HashMap::new()),
+            requested: RwLock::new(HashSet::new()),
+            upload_limits: RwLock::new(HashMap::new()),
+            uploads: Mutex::new(UploadDiscovery::default()),
+            event_bus,
+        }
+    }
+
+    /// Capability summary for a conversation peer. Unknown peers support
+    /// nothing, so frontends fall back to the plain-text baseline.
+    pub fn capabilities(&self, jid: &str) -> ContactCapabilities {
+        let bare = bare_jid(jid);
+        let features = self.resource_features(&bare);
+
+        ContactCapabilities {
+            receipts: all_advertise(&features, &[NS_RECEIPTS]),
+            corrections: all_advertise(&features, &[NS_CORRECTION]),
+            reactions: all_advertise(&features, &[NS_REACTIONS]),
+            omemo: all_advertise(&features, &NS_OMEMO_DEVICES),
+            upload_max_size: self.upload_limit(domain_part(&bare)),
+        }
+    }
+
+    /// Feature lists of every online resource of `bare` with a resolved
+    /// caps hash.
+    fn resource_features(&self, bare: &str) -> Vec<Vec<String>> {
+        let resources = self.resource_caps.read().unwrap();
+        let caps = self.caps_features.read().unwrap();
+        resources
+            .iter()
+            .filter(|(full, _)| full.bare() == bare)
+            .filter_map(|(_, ver)| caps.get(ver).cloned())
+            .collect()
+    }
+
+    fn upload_limit(&self, domain: &str) -> Option<u64> {
+        self.upload_limits
+            .read()
+            .unwrap()
+            .get(domain)
+            .copied()
+            .flatten()
+    }
+
+    #[cfg(feature = "native")]
+    pub async fn handle_event(&self, event: &Event) {
+        match &event.payload {
+            EventPayload::EntityCapsReceived { jid, node, ver } => {
+                let previous = self
+                    .resource_caps
+                    .write()
+                    .unwrap()
+                    .insert(Jid::new(jid), ver.clone());
+                let known = self.caps_features.read().unwrap().contains_key(ver);
+                if known {
+                    if previous.as_deref() != Some(ver) {
+                        self.emit_changed(&bare_jid(jid));
+                    }
+                } else {
+                    self.request_info(jid, format!("{node}#{ver}"));
+                }
+                self.discover_upload(domain_part(jid));
+            }
+            EventPayload::DiscoInfoReceived {
+                jid,
+                node,
+                features,
+                upload_max_size,
+                verification,
+            } => {
+                if let Some(ver) = node
+                    .as_deref()
+                    .and_then(|n| n.rsplit_once('#'))
+                    .map(|(_, v)| v)
+                {
+                    if verification.as_deref() != Some(ver) {
+                        warn!(jid = %jid, ver, ?verification, "caps hash does not match disco#info, ignoring");
+                        return;
+                    }
+                    debug!(jid = %jid, ver, "caps features resolved");
+                    self.caps_features
+                        .write()
+                        .unwrap()
+                        .insert(ver.to_string(), features.clone());
+                    for bare in self.bare_jids_with_ver(ver) {
+                        self.emit_changed(&bare);
+                    }
+                } else if node.is_none() {
+                    self.uploads
+                        .lock()
+                        .unwrap()
+                        .info_received(jid, features, *upload_max_size);
+                    self.record_uploads();
+                }
+            }
+            EventPayload::DiscoItemsReceived {
+                jid,
+                node: None,
+                items,
+                ..
+            } => {
+                let queries = self.uploads.lock().unwrap().items_received(jid, items);
+                for query in queries {
+                    self.request(query);
+                }
+                self.record_uploads();
+            }
+            EventPayload::DiscoInfoFailed {
+                jid, node: None, ..
+            } => {
+                self.uploads.lock().unwrap().info_failed(jid);
+                self.record_uploads();
+            }
+            EventPayload::DiscoItemsFailed {
+                jid, node: None, ..
+            } => {
+                self.uploads.lock().unwrap().items_failed(jid);
+                self.record_uploads();
+            }
+            EventPayload::PresenceChanged {
+                jid,
+                show: PresenceShow::Unavailable,
+                ..
+            } => self.forget_resource(jid),
+            EventPayload::ConnectionLost { .. } => {
+                self.resource_caps.write().unwrap().clear();
+                self.requested.write().unwrap().clear();
+                self.uploads.lock().unwrap().clear();
+            }
+            _ => {}
+        }
+    }
+
+    #[cfg(feature = "native")]
+    fn forget_resource(&self, jid: &str) {
+        if self
+            .resource_caps
+            .write()
+            .unwrap()
+            .remove(&Jid::new(jid))
+            .is_some()
+        {
+            self.emit_changed(&bare_jid(jid));
+        }
+    }
+
+    #[cfg(feature = "native")]
+    fn bare_jids_with_ver(&self, ver: &str) -> HashSet<String> {
+        self.resource_caps
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, v)| v.as_str() == ver)
+            .map(|(full, _)| full.bare().into_string())
+            .collect()
+    }
+
+    /// Query disco#info once per caps hash until the connection drops,
+    /// whichever resource happened to advertise it first.
+    #[cfg(feature = "native")]
+    fn request_info(&self, jid: &str, node: String) {
+        if !self.requested.write().unwrap().insert(node.clone()) {
+            return;
+        }
+        self.request(EventPayload::DiscoInfoRequested {
+            jid: jid.to_string(),
+            node: Some(node),
+        });
+    }
+
+    /// Look for the upload service of a peer's server, once per domain until
+    /// the connection drops.
+    #[cfg(feature = "native")]
+    fn discover_upload(&self, domain: &str) {
+        if !self.requested.write().unwrap().insert(domain.to_string()) {
+            return;
+        }
+        let queries = self.uploads.lock().unwrap().start(domain);
+        for query in queries {
+            self.request(query);
+        }
+    }
+
+    #[cfg(feature = "native")]
+    fn record_uploads(&self) {
+        let finished = self.uploads.lock().unwrap().take_finished();
+        let mut limits = self.upload_limits.write().unwrap();
+        for (domain, service) in finished {
+            debug!(domain = %domain, ?service, "upload service discovered");
+            limits.insert(domain, service.and_then(|service| service.max_size));
+        }
+    }
+
+    /// Give up on upload searches that went unanswered for too long. A
+    /// search that found nothing releases its domain, so the next presence
+    /// from that server tries again.
+    #[cfg(feature = "native")]
+    fn expire_uploads(&self) {
+        let expired = self.uploads.lock().unwrap().take_timed_out();
+        for (domain, service) in expired {
+            debug!(domain = %domain, ?service, "upload service search timed out");
+            match service {
+                Some(service) => {
+                    self.upload_limits
+                        .write()
+                        .unwrap()
+                        .insert(domain, service.max_size);
+                }
+                None => {
+                    self.requested.write().unwrap().remove(&domain);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "native")]
+    fn request(&self, query: EventPayload) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(query_channel(&query)).unwrap(),
+            EventSource::System("capabilities".into()),
+            query,
+        ));
+    }
+
+    #[cfg(feature = "native")]
+    fn emit_changed(&self, jid: &str) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channels::SYSTEM_CAPABILITIES_CHANGED).unwrap(),
+            EventSource::System("capabilities".into()),
+            EventPayload::CapabilitiesChanged {
+                jid: jid.to_string(),
+            },
+        ));
+    }
+
+    #[cfg(feature = "native")]
+    pub async fn run(self: Arc<Self>) -> Result<(), PresenceError> {
+        let mut sub = self
+            .event_bus
+            .subscribe("{system,xmpp}.**")
+            .map_err(|e| PresenceError::EventBus(e.to_string()))?;
+        let mut ticker =
+            tokio::time::interval_at(tokio::time::Instant::now() + SEARCH_TIMEOUT, SEARCH_TIMEOUT);
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => self.expire_uploads(),
+                received = sub.recv() => match received {
+                    Ok(event) => {
+                        self.handle_event(&event).await;
+                    }
+                    Err(waddle_core::error::EventBusError::ChannelClosed) => {
+                        debug!("event bus closed, capabilities manager stopping");
+                        return Ok(());
+                    }
+                    Err(waddle_core::error::EventBusError::Lagged(count)) => {
+                        warn!(count, "capabilities manager lagged, some events dropped");
+                    }
+                    Err(e) => {
+                        error!(error = %e, "capabilities manager subscription error");
+                        return Err(PresenceError::EventBus(e.to_string()));
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// True if there is at least one resource and each advertises one of `vars`.
+fn all_advertise(resources: &[Vec<String>], vars: &[&str]) -> bool {
+    !resources.is_empty()
+        && resources
+            .iter()
+            .all(|features| features.iter().any(|f| vars.contains(&f.as_str())))
+}
+
+fn domain_part(jid: &str) -> &str {
+    let without_resource = jid.split('/').next().unwrap_or(jid);
+    match without_resource.rsplit_once('@') {
+        Some((_, domain)) => domain,
+        None => without_resource,
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use waddle_core::event::{BroadcastEventBus, DiscoItem};
+
+    fn make_manager() -> (Arc<CapabilitiesManager>, Arc<dyn EventBus>) {
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let manager = Arc::new(CapabilitiesManager::new(event_bus.clone()));
+        (manager, event_bus)
+    }
+
+    fn make_event(channel: &str, payload: EventPayload) -> Event {
+        Event::new(Channel::new(channel).unwrap(), EventSource::Xmpp, payload)
+    }
+
+    fn caps(jid: &str, ver: &str) -> Event {
+        make_event(
+            "xmpp.caps.received",
+            EventPayload::EntityCapsReceived {
+                jid: jid.to_string(),
+                node: "https://example.org/client".to_string(),
+                ver: ver.to_string(),
+            },
+        )
+    }
+
+    fn info(jid: &str, node: Option<&str>, features: &[&str], upload: Option<u64>) -> Event {
+        make_event(
+            "xmpp.disco.info.received",
+            EventPayload::DiscoInfoReceived {
+                jid: jid.to_string(),
+                node: node.map(String::from),
+                features: features.iter().map(|f| f.to_string()).collect(),
+                upload_max_size: upload,
+                // As if every client reported its capabilities truthfully.
+                verification: node
+                    .and_then(|node| node.rsplit_once('#'))
+                    .map(|(_, ver)| ver.to_string()),
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn unknown_caps_hash_requests_disco_info_once() {
+        let (manager, event_bus) = make_manager();
+        let mut sub = event_bus.subscribe("ui.disco.**").unwrap();
+
+        manager
+            .handle_event(&caps("alice@example.com/phone", "v1"))
+            .await;
+        manager
+            .handle_event(&caps("bob@example.com/pc", "v1"))
+            .await;
+
+        let first = sub.recv().await.unwrap();
+        assert!(matches!(
+            first.payload,
+            EventPayload::DiscoInfoRequested { ref jid, node: Some(ref node) }
+                if jid == "alice@example.com/phone" && node == "https://example.org/client#v1"
+        ));
+        let second = sub.recv().await.unwrap();
+        assert!(matches!(
+            second.payload,
+            EventPayload::DiscoInfoRequested { ref jid, node: None } if jid == "example.com"
+        ));
+        let third = sub.recv().await.unwrap();
+        assert!(matches!(
+            third.payload,
+            EventPayload::DiscoItemsRequested { ref jid, node: None, .. } if jid == "example.com"
+        ));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), sub.recv())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn feature_requires_support_on_every_resource() {
+        let (manager, event_bus) = make_manager();
+        let mut sub = event_bus.subscribe("system.capabilities.**").unwrap();
+
+        manager
+            .handle_event(&caps("alice@example.com/phone", "v1"))
+            .await;
+        manager
+            .handle_event(&caps("alice@example.com/pc", "v2"))
+            .await;
+        manager
+            .handle_event(&info(
+                "alice@example.com/phone",
+                Some("https://example.org/client#v1"),
+                &[NS_RECEIPTS, NS_REACTIONS, NS_OMEMO_DEVICES[0]],
+                None,
+            ))
+            .await;
+        manager
+            .handle_event(&info(
+                "alice@example.com/pc",
+                Some("https://example.org/client#v2"),
+                &[NS_RECEIPTS, NS_CORRECTION],
+                None,
+            ))
+            .await;
+
+        let event = sub.recv().await.unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::CapabilitiesChanged { ref jid } if jid == "alice@example.com"
+        ));
+
+        let summary = manager.capabilities("alice@example.com");
+        assert!(summary.receipts);
+        assert!(!summary.corrections);
+        assert!(!summary.reactions);
+        assert!(!summary.omemo);
+
+        manager
+            .handle_event(&make_event(
+                "xmpp.presence.changed",
+                EventPayload::PresenceChanged {
+                    jid: "alice@example.com/pc".to_string(),
+                    show: PresenceShow::Unavailable,
+                    status: None,
+                    priority: 0,
+                    idle_since: None,
+                },
+            ))
+            .await;
+        let summary = manager.capabilities("alice@example.com");
+        assert!(summary.reactions);
+        assert!(summary.omemo);
+    }
+
+    #[tokio::test]
+    async fn unknown_peer_supports_nothing() {
+        let (manager, _) = make_manager();
+        assert_eq!(
+            manager.capabilities("nobody@example.com"),
+            ContactCapabilities::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn caps_result_with_wrong_hash_is_not_cached() {
+        let (manager, _) = make_manager();
+        manager
+            .handle_event(&caps("alice@example.com/phone", "v1"))
+            .await;
+        manager
+            .handle_event(&make_event(
+                "xmpp.disco.info.received",
+                EventPayload::DiscoInfoReceived {
+                    jid: "alice@example.com/phone".to_string(),
+                    node: Some("https://example.org/client#v1".to_string()),
+                    features: vec![NS_RECEIPTS.to_string()],
+                    upload_max_size: None,
+                    verification: Some("forged".to_string()),
+                },
+            ))
+            .await;
+
+        assert!(!manager.capabilities("alice@example.com").receipts);
+    }
+
+    fn items(jid: &str, components: &[&str]) -> Event {
+        make_event(
+            "xmpp.disco.items.received",
+            EventPayload::DiscoItemsReceived {
+                jid: jid.to_string(),
+                node: None,
+                items: components
+                    .iter()
+                    .map(|component| DiscoItem {
+                        jid: component.to_string(),
+                        node: None,
+                        name: None,
+                    })
+                    .collect(),
+                page: None,
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn upload_limit_comes_from_component_listed_in_disco_items() {
+        let (manager, event_bus) = make_manager();
+        let mut sub = event_bus.subscribe("ui.disco.**").unwrap();
+        manager
+            .handle_event(&caps("alice@example.com/phone", "v1"))
+            .await;
+        manager
+            .handle_event(&info("example.com", None, &[], None))
+            .await;
+        manager
+            .handle_event(&items(
+                "example.com",
+                &["conference.example.com", "files.example.com"],
+            ))
+            .await;
+
+        let mut asked = Vec::new();
+        while let Ok(Ok(event)) = tokio::time::timeout(Duration::from_millis(50), sub.recv()).await
+        {
+            if let EventPayload::DiscoInfoRequested { jid, node: None } = event.payload {
+                asked.push(jid);
+            }
+        }
+        assert_eq!(
+            asked,
+            ["example.com", "conference.example.com", "files.example.com"]
+        );
+
+        manager
+            .handle_event(&info(
+                "files.example.com",
+                None,
+                &["urn:xmpp:http:upload:0"],
+                Some(10_485_760),
+            ))
+            .await;
+        // Still waiting on the conference component.
+        assert_eq!(
+            manager.capabilities("alice@example.com").upload_max_size,
+            None
+        );
+        manager
+            .handle_event(&info("conference.example.com", None, &[], None))
+            .await;
+
+        assert_eq!(
+            manager.capabilities("alice@example.com").upload_max_size,
+            Some(10_485_760)
+        );
+        assert_eq!(
+            manager.capabilities("carol@elsewhere.org").upload_max_size,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn erroring_component_does_not_block_upload_discovery() {
+        let (manager, _) = make_manager();
+        manager
+            .handle_event(&caps("alice@example.com/phone", "v1"))
+            .await;
+        manager
+            .handle_event(&info("example.com", None, &[], None))
+            .await;
+        manager
+            .handle_event(&items(
+                "example.com",
+                &["broken.example.com", "files.example.com"],
+            ))
+            .await;
+        manager
+            .handle_event(&info(
+                "files.example.com",
+                None,
+                &["urn:xmpp:http:upload:0"],
+                Some(1024),
+            ))
+            .await;
+        assert_eq!(
+            manager.capabilities("alice@example.com").upload_max_size,
+            None
+        );
+
+        manager
+            .handle_event(&make_event(
+                "xmpp.disco.info.failed",
+                EventPayload::DiscoInfoFailed {
+                    jid: "broken.example.com".to_string(),
+                    node: None,
+                    error: "service-unavailable".to_string(),
+                },
+            ))
+            .await;
+
+        assert_eq!(
+            manager.capabilities("alice@example.com").upload_max_size,
+            Some(1024)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unanswered_upload_search_is_retried_after_the_timeout() {
+        let (manager, event_bus) = make_manager();
+        let mut sub = event_bus.subscribe("ui.disco.info").unwrap();
+        manager
+            .handle_event(&caps("alice@example.com/phone", "v1"))
+            .await;
+        manager
+            .handle_event(&caps("bob@example.com/pc", "v1"))
+            .await;
+        let mut server_queries = 0;
+        while let Ok(Ok(event)) = tokio::time::timeout(Duration::from_millis(50), sub.recv()).await
+        {
+            if matches!(event.payload, EventPayload::DiscoInfoRequested { ref jid, .. } if jid == "example.com")
+            {
+                server_queries += 1;
+            }
+        }
+        assert_eq!(server_queries, 1);
+
+        tokio::time::advance(SEARCH_TIMEOUT).await;
+        manager.expire_uploads();
+        manager
+            .handle_event(&caps("carol@example.com/pc", "v1"))
+            .await;
+
+        let event = sub.recv().await.unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::DiscoInfoRequested { ref jid, node: None } if jid == "example.com"
+        ));
+    }
+}
@@ -0,0 +1,837 @@
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn};
+
+use waddle_core::event::{
+    Channel, DiscoItem, Event, EventBus, EventPayload, EventSource, ServerFeature, channels,
+};
+use waddle_core::jid::bare_jid;
+use waddle_storage::{Database, FromRow, Row, SqlValue, StorageError};
+
+use crate::PresenceError;
+use crate::upload::{UploadDiscovery, UploadService, query_channel};
+
+const NS_MAM: &str = "urn:xmpp:mam:2";
+const NS_CARBONS: &str = "urn:xmpp:carbons:2";
+
+/// How often the server is re-probed while connected.
+pub const PROBE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(6 * 60 * 60);
+
+/// How long a round of checks waits for its disco answers. An upload search
+/// still waiting by then counts what it found so far; a server or account
+/// that never answered leaves nothing to compare, so the round is dropped.
+const PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Last known state of one monitored feature.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeatureStatus {
+    pub feature: ServerFeature,
+    pub available: bool,
+    /// When the feature was last seen advertised; `None` if never.
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub checked_at: DateTime<Utc>,
+}
+
+/// Snapshot of what the account's server offers, for support requests.
+/// `Display` renders it as plain text suitable for pasting into a bug report.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthReport {
+    pub account: String,
+    pub server: String,
+    pub features: Vec<FeatureStatus>,
+    pub upload_max_size: Option<u64>,
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Waddle server health report")?;
+        writeln!(f, "Account: {}", self.account)?;
+        writeln!(f, "Server: {}", self.server)?;
+        if self.features.is_empty() {
+            return writeln!(f, "No checks have completed yet.");
+        }
+        for status in &self.features {
+            let state = match (status.available, status.last_seen_at) {
+                (true, _) => "available".to_string(),
+                (false, Some(seen)) => format!("MISSING (last seen {})", seen.to_rfc3339()),
+                (false, None) => "not offered".to_string(),
+            };
+            writeln!(
+                f,
+                "{}: {} [checked {}]",
+                status.feature.description(),
+                state,
+                status.checked_at.to_rfc3339()
+            )?;
+        }
+        match self.upload_max_size {
+            Some(limit) => writeln!(f, "Upload limit: {limit} bytes"),
+            None => writeln!(f, "Upload limit: unknown"),
+        }
+    }
+}
+
+struct FeatureRow {
+    feature: ServerFeature,
+    present: bool,
+    upload_max_size: Option<u64>,
+    last_seen_at: Option<DateTime<Utc>>,
+    checked_at: DateTime<Utc>,
+}
+
+impl FromRow for FeatureRow {
+    fn from_row(row: &Row) -> Result<Self, StorageError> {
+        let text = |idx: usize| match row.get(idx) {
+            Some(SqlValue::Text(s)) => Some(s.clone()),
+            _ => None,
+        };
+        let timestamp = |idx: usize| {
+            text(idx).and_then(|s| {
+                DateTime::parse_from_rfc3339(&s)
+                    .ok()
+                    .map(|dt| dt.with_timezone(&Utc))
+            })
+        };
+
+        let feature = text(0)
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| StorageError::QueryFailed("invalid feature column".to_string()))?;
+        Ok(FeatureRow {
+            feature,
+            present: matches!(row.get(1), Some(SqlValue::Integer(v)) if *v != 0),
+            upload_max_size: match row.get(2) {
+                Some(SqlValue::Integer(v)) => u64::try_from(*v).ok(),
+                _ => None,
+            },
+            last_seen_at: timestamp(3),
+            checked_at: timestamp(4).unwrap_or_else(Utc::now),
+        })
+    }
+}
+
+/// disco results collected for one round of checks.
+struct Probe {
+    account: String,
+    server: String,
+    server_features: Option<Vec<String>>,
+    account_features: Option<Vec<String>>,
+    uploads: UploadDiscovery,
+    /// The server's upload service, once discovery has finished.
+    upload: Option<Option<UploadService>>,
+    deadline: Instant,
+}
+
+impl Probe {
+    fn is_complete(&self) -> bool {
+        self.server_features.is_some() && self.account_features.is_some() && self.upload.is_some()
+    }
+
+    fn record_uploads(&mut self) {
+        for (domain, service) in self.uploads.take_finished() {
+            if domain == self.server {
+                self.upload = Some(service);
+            }
+        }
+    }
+}
+
+/// Re-probes the account's server on connect and every [`PROBE_INTERVAL`],
+/// compares the result against what was seen before and raises
+/// `ServerFeatureLost` / `UploadQuotaReduced` when something regressed.
+pub struct ServerHealthMonitor<D: Database> {
+    db: Arc<D>,
+    account: RwLock<Option<String>>,
+    probe: Mutex<Option<Probe>>,
+    event_bus: Arc<dyn EventBus>,
+}
+
+impl<D: Database> ServerHealthMonitor<D> {
+    pub fn new(db: Arc<D>, event_bus: Arc<dyn EventBus>) -> Self {
+        Self {
+            db,
+            account: RwLock::new(None),
+            probe: Mutex::new(None),
+            event_bus,
+        }
+    }
+
+    /// Start a round of checks now. Does nothing while disconnected.
+    pub fn probe(&self) {
+        let Some(account) = self.account.read().unwrap().clone() else {
+            return;
+        };
+        let server = server_of(&account).to_string();
+        debug!(server = %server, "probing server features");
+
+        let mut uploads = UploadDiscovery::default();
+        let mut queries = uploads.start(&server);
+        queries.push(EventPayload::DiscoInfoRequested {
+            jid: account.clone(),
+            node: None,
+        });
+        *self.probe.lock().unwrap() = Some(Probe {
+            account,
+            server,
+            server_features: None,
+            account_features: None,
+            uploads,
+            upload: None,
+            deadline: Instant::now() + PROBE_TIMEOUT,
+        });
+        self.request(queries);
+    }
+
+    /// Troubleshooting report built from the most recent checks of
+    /// `account_jid`. Available offline.
+    pub async fn report(&self, account_jid: &str) -> Result<HealthReport, PresenceError> {
+        let account = bare_jid(account_jid);
+        let rows = self.load(&account).await?;
+        let upload_max_size = rows
+            .iter()
+            .find(|row| row.feature == ServerFeature::FileUpload && row.present)
+            .and_then(|row| row.upload_max_size);
+
+        Ok(HealthReport {
+            server: server_of(&account).to_string(),
+            account,
+            features: rows
+                .into_iter()
+                .map(|row| FeatureStatus {
+                    feature: row.feature,
+                    available: row.present,
+                    last_seen_at: row.last_seen_at,
+                    checked_at: row.checked_at,
+                })
+                .collect(),
+            upload_max_size,
+        })
+    }
+
+    pub async fn handle_event(&self, event: &Event) {
+        match &event.payload {
+            EventPayload::ConnectionEstablished { jid } => {
+                *self.account.write().unwrap() = Some(bare_jid(jid));
+                self.probe();
+            }
+            EventPayload::ConnectionLost { .. } => {
+                *self.account.write().unwrap() = None;
+                *self.probe.lock().unwrap() = None;
+            }
+            EventPayload::DiscoInfoReceived {
+                jid,
+                node: None,
+                features,
+                upload_max_size,
+                ..
+            } => {
+                let probe = self.record_info(jid, features, *upload_max_size);
+                self.finish(probe).await;
+            }
+            EventPayload::DiscoItemsReceived {
+                jid,
+                node: None,
+                items,
+                ..
+            } => {
+                let probe = self.record_items(jid, items);
+                self.finish(probe).await;
+            }
+            // A refusal is an answer: whoever sent it offers nothing.
+            EventPayload::DiscoInfoFailed {
+                jid, node: None, ..
+            } => {
+                let probe = self.record_info(jid, &[], None);
+                self.finish(probe).await;
+            }
+            EventPayload::DiscoItemsFailed {
+                jid, node: None, ..
+            } => {
+                let probe = self.record_items_failed(jid);
+                self.finish(probe).await;
+            }
+            _ => {}
+        }
+    }
+
+    async fn finish(&self, probe: Option<Probe>) {
+        let Some(probe) = probe else {
+            return;
+        };
+        if let Err(e) = self.evaluate(probe).await {
+            error!(error = %e, "failed to record server feature check");
+        }
+    }
+
+    /// Store a disco#info result in the current probe and hand the probe
+    /// back once the server, the account and the upload search have all
+    /// answered.
+    fn record_info(&self, jid: &str, features: &[String], upload: Option<u64>) -> Option<Probe> {
+        let mut guard = self.probe.lock().unwrap();
+        let probe = guard.as_mut()?;
+        if jid == probe.server {
+            probe.server_features = Some(features.to_vec());
+        } else if jid == probe.account {
+            probe.account_features = Some(features.to_vec());
+        }
+        probe.uploads.info_received(jid, features, upload);
+        probe.record_uploads();
+        if probe.is_complete() {
+            guard.take()
+        } else {
+            None
+        }
+    }
+
+    /// Store the server's disco#items result in the current probe, asking
+    /// the components it lists whether they host the upload service.
+    fn record_items(&self, jid: &str, items: &[DiscoItem]) -> Option<Probe> {
+        let mut guard = self.probe.lock().unwrap();
+        let probe = guard.as_mut()?;
+        let queries = probe.uploads.items_received(jid, items);
+        probe.record_uploads();
+        self.request(queries);
+        if probe.is_complete() {
+            guard.take()
+        } else {
+            None
+        }
+    }
+
+    /// Note that the server refused to list its items, which leaves only
+    /// the server itself to host the upload service.
+    fn record_items_failed(&self, jid: &str) -> Option<Probe> {
+        let mut guard = self.probe.lock().unwrap();
+        let probe = guard.as_mut()?;
+        probe.uploads.items_failed(jid);
+        probe.record_uploads();
+        if probe.is_complete() {
+            guard.take()
+        } else {
+            None
+        }
+    }
+
+    /// End the current probe if it passed [`PROBE_TIMEOUT`], handing it back
+    /// when the server and account answered so it can still be evaluated.
+    fn expire(&self) -> Option<Probe> {
+        let mut guard = self.probe.lock().unwrap();
+        if guard.as_ref()?.deadline > Instant::now() {
+            return None;
+        }
+        let mut probe = guard.take()?;
+        if probe.upload.is_none() {
+            probe.upload = Some(
+                probe
+                    .uploads
+                    .take_timed_out()
+                    .into_iter()
+                    .find(|(domain, _)| *domain == probe.server)
+                    .and_then(|(_, service)| service),
+            );
+        }
+        if probe.server_features.is_none() || probe.account_features.is_none() {
+            warn!(server = %probe.server, "server feature check timed out");
+            return None;
+        }
+        Some(probe)
+    }
+
+    fn request(&self, queries: Vec<EventPayload>) {
+        for query in queries {
+            self.publish(query_channel(&query), query);
+        }
+    }
+
+    async fn evaluate(&self, probe: Probe) -> Result<(), PresenceError> {
+        let server_features = probe.server_features.unwrap_or_default();
+        let account_features = probe.account_features.unwrap_or_default();
+        let upload = probe.upload.flatten();
+        let advertised = |var: &str| {
+            server_features.iter().any(|f| f == var) || account_features.iter().any(|f| f == var)
+        };
+
+        let previous = self.load(&probe.account).await?;
+        let now = Utc::now().to_rfc3339();
+
+        for feature in ServerFeature::ALL {
+            let present = match feature {
+                ServerFeature::MessageArchive => advertised(NS_MAM),
+                ServerFeature::Carbons => advertised(NS_CARBONS),
+                ServerFeature::FileUpload => upload.is_some(),
+            };
+            let upload_max_size = match feature {
+                ServerFeature::FileUpload => upload.as_ref().and_then(|service| service.max_size),
+                _ => None,
+            };
+
+            if let Some(before) = previous.iter().find(|row| row.feature == feature) {
+                if before.present && !present {
+                    warn!(server = %probe.server, feature = feature.as_str(), "server feature disappeared");
+                    self.publish(
+                        channels::SYSTEM_HEALTH_FEATURE_LOST,
+                        EventPayload::ServerFeatureLost {
+                            server: probe.server.clone(),
+                            feature,
+                        },
+                    );
+                }
+                if let (Some(previous), Some(current)) = (before.upload_max_size, upload_max_size)
+                    && current < previous
+                {
+                    warn!(server = %probe.server, previous, current, "upload quota reduced");
+                    self.publish(
+                        channels::SYSTEM_HEALTH_UPLOAD_QUOTA_REDUCED,
+                        EventPayload::UploadQuotaReduced {
+                            server: probe.server.clone(),
+                            previous,
+                            current,
+                        },
+                    );
+                }
+            }
+
+            let last_seen = present.then(|| now.clone());
+            self.db
+                .execute(
+                    "INSERT INTO server_features \
+                     (account_jid, feature, present, upload_max_size, last_seen_at, checked_at) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6) \
+                     ON CONFLICT (account_jid, feature) DO UPDATE SET \
+                     present = excluded.present, \
+                     upload_max_size = excluded.upload_max_size, \
+                     last_seen_at = IFNULL(excluded.last_seen_at, last_seen_at), \
+                     checked_at = excluded.checked_at",
+                    &[
+                        &probe.account,
+                        &feature.as_str().to_string(),
+                        &present,
+                        &upload_max_size,
+                        &last_seen,
+                        &now,
+                    ],
+                )
+                .await?;
+        }
+
+        info!(server = %probe.server, "server feature check complete");
+        Ok(())
+    }
+
+    async fn load(&self, account: &str) -> Result<Vec<FeatureRow>, PresenceError> {
+        let mut rows: Vec<FeatureRow> = self
+            .db
+            .query(
+                "SELECT feature, present, upload_max_size, last_seen_at, checked_at \
+                 FROM server_features WHERE account_jid = ?1",
+                &[&account.to_string()],
+            )
+            .await?;
+        rows.sort_by_key(|row| ServerFeature::ALL.iter().position(|f| *f == row.feature));
+        Ok(rows)
+    }
+
+    fn publish(&self, channel: &str, payload: EventPayload) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channel).unwrap(),
+            EventSource::System("health".into()),
+            payload,
+        ));
+    }
+
+    pub async fn run(self: Arc<Self>) -> Result<(), PresenceError> {
+        let mut sub = self
+            .event_bus
+            .subscribe("{system,xmpp}.**")
+            .map_err(|e| PresenceError::EventBus(e.to_string()))?;
+        let mut ticker =
+            tokio::time::interval_at(tokio::time::Instant::now() + PROBE_INTERVAL, PROBE_INTERVAL);
+        let mut expiry =
+            tokio::time::interval_at(tokio::time::Instant::now() + PROBE_TIMEOUT, PROBE_TIMEOUT);
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => self.probe(),
+                _ = expiry.tick() => {
+                    let probe = self.expire();
+                    self.finish(probe).await;
+                }
+                received = sub.recv() => match received {
+                    Ok(event) => {
+                        self.handle_event(&event).await;
+                    }
+                    Err(waddle_core::error::EventBusError::ChannelClosed) => {
+                        debug!("event bus closed, health monitor stopping");
+                        return Ok(());
+                    }
+                    Err(waddle_core::error::EventBusError::Lagged(count)) => {
+                        warn!(count, "health monitor lagged, some events dropped");
+                    }
+                    Err(e) => {
+                        error!(error = %e, "health monitor subscription error");
+                        return Err(PresenceError::EventBus(e.to_string()));
+                    }
+                },
+            }
+        }
+    }
+}
+
+fn server_of(bare: &str) -> &str {
+    bare.rsplit_once('@').map_or(bare, |(_, domain)| domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::upload::NS_HTTP_UPLOAD;
+    use std::time::Duration;
+    use tempfile::TempDir;
+    use waddle_core::event::BroadcastEventBus;
+
+    async fn make_monitor() -> (
+        Arc<ServerHealthMonitor<impl Database>>,
+        Arc<dyn EventBus>,
+        TempDir,
+    ) {
+        let dir = TempDir::new().unwrap();
+        let db = waddle_storage::open_database(&dir.path().join("test.db"))
+            .await
+            .unwrap();
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let monitor = Arc::new(ServerHealthMonitor::new(Arc::new(db), event_bus.clone()));
+        (monitor, event_bus, dir)
+    }
+
+    fn make_event(channel: &str, payload: EventPayload) -> Event {
+        Event::new(Channel::new(channel).unwrap(), EventSource::Xmpp, payload)
+    }
+
+    fn connected() -> Event {
+        make_event(
+            "system.connection.established",
+            EventPayload::ConnectionEstablished {
+                jid: "alice@example.com/desktop".to_string(),
+            },
+        )
+    }
+
+    fn info(jid: &str, features: &[&str], upload: Option<u64>) -> Event {
+        make_event(
+            "xmpp.disco.info.received",
+            EventPayload::DiscoInfoReceived {
+                jid: jid.to_string(),
+                node: None,
+                features: features.iter().map(|f| f.to_string()).collect(),
+                upload_max_size: upload,
+                verification: None,
+            },
+        )
+    }
+
+    fn items(jid: &str, components: &[&str]) -> Event {
+        make_event(
+            "xmpp.disco.items.received",
+            EventPayload::DiscoItemsReceived {
+                jid: jid.to_string(),
+                node: None,
+                items: components
+                    .iter()
+                    .map(|component| DiscoItem {
+                        jid: component.to_string(),
+                        node: None,
+                        name: None,
+                    })
+                    .collect(),
+                page: None,
+            },
+        )
+    }
+
+    fn info_failed(jid: &str) -> Event {
+        make_event(
+            "xmpp.disco.info.failed",
+            EventPayload::DiscoInfoFailed {
+                jid: jid.to_string(),
+                node: None,
+                error: "service-unavailable".to_string(),
+            },
+        )
+    }
+
+    async fn check<D: Database>(
+        monitor: &ServerHealthMonitor<D>,
+        server: &[&str],
+        upload: Option<u64>,
+        account: &[&str],
+    ) {
+        monitor.handle_event(&connected()).await;
+        monitor
+            .handle_event(&info("example.com", server, upload))
+            .await;
+        monitor.handle_event(&items("example.com", &[])).await;
+        monitor
+            .handle_event(&info("alice@example.com", account, None))
+            .await;
+    }
+
+    #[tokio::test]
+    async fn connect_probes_server_and_account() {
+        let (monitor, event_bus, _dir) = make_monitor().await;
+        let mut sub = event_bus.subscribe("ui.disco.**").unwrap();
+
+        monitor.handle_event(&connected()).await;
+
+        let mut targets = Vec::new();
+        for _ in 0..3 {
+            match sub.recv().await.unwrap().payload {
+                EventPayload::DiscoInfoRequested { jid, node: None } => {
+                    targets.push(format!("info {jid}"))
+                }
+                EventPayload::DiscoItemsRequested {
+                    jid, node: None, ..
+                } => targets.push(format!("items {jid}")),
+                other => panic!("unexpected payload: {other:?}"),
+            }
+        }
+        assert_eq!(
+            targets,
+            vec![
+                "info example.com",
+                "items example.com",
+                "info alice@example.com"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn upload_component_listed_in_disco_items_counts_as_file_upload() {
+        let (monitor, event_bus, _dir) = make_monitor().await;
+        let mut sub = event_bus.subscribe("ui.disco.info").unwrap();
+
+        monitor.handle_event(&connected()).await;
+        monitor
+            .handle_event(&info("example.com", &[NS_CARBONS], None))
+            .await;
+        monitor
+            .handle_event(&info("alice@example.com", &[NS_MAM], None))
+            .await;
+        monitor
+            .handle_event(&items("example.com", &["upload.example.com"]))
+            .await;
+        let mut asked = Vec::new();
+        while let Ok(Ok(event)) = tokio::time::timeout(Duration::from_millis(50), sub.recv()).await
+        {
+            if let EventPayload::DiscoInfoRequested { jid, .. } = event.payload {
+                asked.push(jid);
+            }
+        }
+        assert_eq!(asked.last().map(String::as_str), Some("upload.example.com"));
+        assert!(
+            monitor
+                .report("alice@example.com")
+                .await
+                .unwrap()
+                .features
+                .is_empty(),
+            "the check waits for the upload component"
+        );
+
+        monitor
+            .handle_event(&info(
+                "upload.example.com",
+                &[NS_HTTP_UPLOAD],
+                Some(50_000_000),
+            ))
+            .await;
+
+        let report = monitor.report("alice@example.com").await.unwrap();
+        let upload = report
+            .features
+            .iter()
+            .find(|status| status.feature == ServerFeature::FileUpload)
+            .unwrap();
+        assert!(upload.available);
+        assert_eq!(report.upload_max_size, Some(50_000_000));
+    }
+
+    #[tokio::test]
+    async fn lost_feature_and_reduced_quota_raise_alerts() {
+        let (monitor, event_bus, _dir) = make_monitor().await;
+        let mut sub = event_bus.subscribe("system.health.**").unwrap();
+
+        check(
+            &monitor,
+            &[NS_CARBONS, NS_HTTP_UPLOAD],
+            Some(100_000_000),
+            &[NS_MAM],
+        )
+        .await;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), sub.recv())
+                .await
+                .is_err(),
+            "first check only records a baseline"
+        );
+
+        check(
+            &monitor,
+            &[NS_CARBONS, NS_HTTP_UPLOAD],
+            Some(10_000_000),
+            &[],
+        )
+        .await;
+
+        let lost = sub.recv().await.unwrap();
+        assert!(matches!(
+            lost.payload,
+            EventPayload::ServerFeatureLost {
+                ref server,
+                feature: ServerFeature::MessageArchive,
+            } if server == "example.com"
+        ));
+        let quota = sub.recv().await.unwrap();
+        assert!(matches!(
+            quota.payload,
+            EventPayload::UploadQuotaReduced {
+                previous: 100_000_000,
+                current: 10_000_000,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn report_lists_missing_features_with_last_sighting() {
+        let (monitor, _event_bus, _dir) = make_monitor().await;
+        check(&monitor, &[NS_CARBONS], None, &[NS_MAM]).await;
+        check(&monitor, &[NS_CARBONS], None, &[]).await;
+
+        let report = monitor.report("alice@example.com/desktop").await.unwrap();
+        assert_eq!(report.server, "example.com");
+        assert_eq!(report.features.len(), 3);
+
+        let mam = &report.features[0];
+        assert_eq!(mam.feature, ServerFeature::MessageArchive);
+        assert!(!mam.available);
+        assert!(mam.last_seen_at.is_some());
+        assert!(report.features[1].available);
+        assert!(report.features[2].last_seen_at.is_none());
+
+        let text = report.to_string();
+        assert!(text.contains("message archiving (XEP-0313): MISSING"));
+        assert!(text.contains("file upload (XEP-0363): not offered"));
+    }
+
+    #[tokio::test]
+    async fn unrelated_disco_results_are_ignored() {
+        let (monitor, _event_bus, _dir) = make_monitor().await;
+        monitor.handle_event(&connected()).await;
+        monitor
+            .handle_event(&info("conference.example.com", &[NS_MAM], None))
+            .await;
+
+        assert!(
+            monitor
+                .report("alice@example.com")
+                .await
+                .unwrap()
+                .features
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn error_replies_count_as_answers() {
+        let (monitor, _event_bus, _dir) = make_monitor().await;
+        monitor.handle_event(&connected()).await;
+        monitor.handle_event(&info_failed("example.com")).await;
+        monitor
+            .handle_event(&make_event(
+                "xmpp.disco.items.failed",
+                EventPayload::DiscoItemsFailed {
+                    jid: "example.com".to_string(),
+                    node: None,
+                    error: "feature-not-implemented".to_string(),
+                },
+            ))
+            .await;
+        monitor
+            .handle_event(&info("alice@example.com", &[NS_MAM], None))
+            .await;
+
+        let report = monitor.report("alice@example.com").await.unwrap();
+        let available: Vec<_> = report
+            .features
+            .iter()
+            .filter(|status| status.available)
+            .map(|status| status.feature)
+            .collect();
+        assert_eq!(available, [ServerFeature::MessageArchive]);
+    }
+
+    #[tokio::test]
+    async fn erroring_upload_component_completes_the_check() {
+        let (monitor, _event_bus, _dir) = make_monitor().await;
+        monitor.handle_event(&connected()).await;
+        monitor
+            .handle_event(&info("example.com", &[NS_CARBONS], None))
+            .await;
+        monitor
+            .handle_event(&info("alice@example.com", &[NS_MAM], None))
+            .await;
+        monitor
+            .handle_event(&items("example.com", &["broken.example.com"]))
+            .await;
+        monitor
+            .handle_event(&info_failed("broken.example.com"))
+            .await;
+
+        let report = monitor.report("alice@example.com").await.unwrap();
+        assert_eq!(report.features.len(), 3);
+        assert_eq!(report.upload_max_size, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn probe_gives_up_on_silent_entities_at_the_deadline() {
+        let (monitor, _event_bus, _dir) = make_monitor().await;
+        monitor.handle_event(&connected()).await;
+        monitor
+            .handle_event(&info("example.com", &[NS_CARBONS], None))
+            .await;
+        monitor
+            .handle_event(&info("alice@example.com", &[NS_MAM], None))
+            .await;
+        monitor
+            .handle_event(&items("example.com", &["silent.example.com"]))
+            .await;
+        assert!(monitor.expire().is_none(), "still within the deadline");
+
+        tokio::time::advance(PROBE_TIMEOUT).await;
+        let probe = monitor.expire();
+        assert!(probe.is_some());
+        monitor.finish(probe).await;
+
+        let report = monitor.report("alice@example.com").await.unwrap();
+        assert_eq!(report.features.len(), 3);
+        assert!(
+            !report
+                .features
+                .iter()
+                .find(|status| status.feature == ServerFeature::FileUpload)
+                .unwrap()
+                .available
+        );
+
+        // A server that never answers leaves nothing to record.
+        monitor.probe();
+        tokio::time::advance(PROBE_TIMEOUT).await;
+        assert!(monitor.expire().is_none());
+        assert!(monitor.probe.lock().unwrap().is_none());
+    }
+}
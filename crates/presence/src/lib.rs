@@ -5,6 +5,7 @@ use chrono::{DateTime, Utc};
 use tracing::{debug, error, warn};
 
 use waddle_core::event::{Event, EventPayload, PresenceShow};
+use waddle_core::jid::{bare_jid, resource_part};
 
 #[cfg(feature = "native")]
 use std::sync::Arc;
@@ -12,7 +13,27 @@ use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 
 #[cfg(feature = "native")]
-use waddle_core::event::{Channel, EventBus, EventSource};
+use waddle_core::WaddleError;
+#[cfg(feature = "native")]
+use waddle_core::event::{Channel, EventBus, EventSource, channels};
+#[cfg(feature = "native")]
+use waddle_core::jid::JidParts;
+#[cfg(feature = "native")]
+use waddle_core::shutdown::{Manager, ShutdownFuture};
+
+#[cfg(feature = "native")]
+mod auto_away;
+mod capabilities;
+#[cfg(feature = "native")]
+mod health;
+#[cfg(feature = "native")]
+mod upload;
+
+#[cfg(feature = "native")]
+pub use auto_away::{AutoAwayMonitor, IdleFuture, IdleSource, SystemIdleSource};
+pub use capabilities::{CapabilitiesManager, ContactCapabilities};
+#[cfg(feature = "native")]
+pub use health::{FeatureStatus, HealthReport, PROBE_INTERVAL, ServerHealthMonitor};
 
 #[derive(Debug, thiserror::Error)]
 pub enum PresenceError {
@@ -22,6 +43,12 @@ pub enum PresenceError {
     #[error("invalid priority value: {0} (must be -128..127)")]
     InvalidPriority(i16),
 
+    #[error("invalid JID: {0}")]
+    InvalidJid(String),
+
+    #[error("storage error: {0}")]
+    Storage(#[from] waddle_storage::StorageError),
+
     #[error("event bus error: {0}")]
     EventBus(String),
 }
@@ -32,6 +59,8 @@ pub struct PresenceInfo {
     pub show: PresenceShow,
     pub status: Option<String>,
     pub priority: i8,
+    /// XEP-0319 idle timestamp: when the user last interacted with their client.
+    pub idle_since: Option<DateTime<Utc>>,
     pub last_updated: DateTime<Utc>,
 }
 
@@ -42,9 +71,15 @@ impl PresenceInfo {
             show: PresenceShow::Unavailable,
             status: None,
             priority: 0,
+            idle_since: None,
             last_updated: Utc::now(),
         }
     }
+
+    /// Whether this presence advertises the user as idle.
+    pub fn is_idle(&self) -> bool {
+        self.idle_since.is_some() && !matches!(self.show, PresenceShow::Unavailable)
+    }
 }
 
 /// Per-resource presence map for a single bare JID.
@@ -56,10 +91,22 @@ pub struct PresenceManager {
     contacts: RwLock<HashMap<String, ResourceMap>>,
     #[cfg(feature = "native")]
     awaiting_initial_presence: AtomicBool,
+    /// The user chose to appear offline; kept across reconnects.
+    #[cfg(feature = "native")]
+    appear_offline: AtomicBool,
+    /// The server advertises XEP-0186 invisibility.
+    #[cfg(feature = "native")]
+    invisibility_supported: AtomicBool,
+    /// XEP-0186 invisibility is active for the current session.
+    #[cfg(feature = "native")]
+    server_invisible: AtomicBool,
     #[cfg(feature = "native")]
     event_bus: Arc<dyn EventBus>,
 }
 
+#[cfg(feature = "native")]
+const NS_INVISIBLE: &str = "urn:xmpp:invisible:0";
+
 impl PresenceManager {
     #[cfg(feature = "native")]
     pub fn new(event_bus: Arc<dyn EventBus>) -> Self {
@@ -69,10 +116,14 @@ impl PresenceManager {
                 show: PresenceShow::Unavailable,
                 status: None,
                 priority: 0,
+                idle_since: None,
                 last_updated: Utc::now(),
             }),
             contacts: RwLock::new(HashMap::new()),
             awaiting_initial_presence: AtomicBool::new(false),
+            appear_offline: AtomicBool::new(false),
+            invisibility_supported: AtomicBool::new(false),
+            server_invisible: AtomicBool::new(false),
             event_bus,
         }
     }
@@ -92,6 +143,24 @@ impl PresenceManager {
         }
     }
 
+    /// The presence of each online resource of a JID, by resource name.
+    pub fn get_resources(&self, jid: &str) -> Vec<(String, PresenceInfo)> {
+        let contacts = self.contacts.read().unwrap();
+        let mut resources: Vec<(String, PresenceInfo)> = contacts
+            .get(&bare_jid(jid))
+            .map(|resources| {
+                resources
+                    .iter()
+                    .map(|(resource, info)| (resource.clone(), info.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        resources.sort_by(|a, b| a.0.cmp(&b.0));
+        resources
+    }
+
+    /// Set our broadcast presence. [`PresenceShow::Invisible`] appears
+    /// offline to contacts while the session keeps receiving messages.
     #[cfg(feature = "native")]
     pub fn set_own_presence(
         &self,
@@ -99,24 +168,124 @@ impl PresenceManager {
         status: Option<&str>,
         priority: Option<i8>,
     ) -> Result<(), PresenceError> {
-        {
+        self.appear_offline
+            .store(matches!(show, PresenceShow::Invisible), Ordering::Relaxed);
+        let priority = {
             let mut own = self.own_presence.write().unwrap();
             own.show = show.clone();
             own.status = status.map(String::from);
             if let Some(p) = priority {
                 own.priority = p;
             }
+            own.idle_since = None;
             own.last_updated = Utc::now();
+            own.priority
+        };
+
+        self.request_presence(show, status.map(String::from), priority, None);
+        Ok(())
+    }
+
+    /// Change show on behalf of [`AutoAwayMonitor`], keeping the status and
+    /// advertising when input stopped (XEP-0319) in the same presence.
+    #[cfg(feature = "native")]
+    pub(crate) fn set_auto_away(&self, show: PresenceShow, idle_since: DateTime<Utc>) {
+        let (status, priority) = {
+            let mut own = self.own_presence.write().unwrap();
+            own.show = show.clone();
+            own.idle_since = Some(idle_since);
+            own.last_updated = Utc::now();
+            (own.status.clone(), own.priority)
+        };
+        self.request_presence(show, status, priority, Some(idle_since));
+    }
+
+    /// Publish a broadcast presence change. Appearing offline goes through
+    /// XEP-0186 when the server offers it, which keeps the session available
+    /// for message routing; otherwise the outbound router falls back to an
+    /// unavailable presence, after which only messages addressed to this
+    /// resource's full JID reach us.
+    #[cfg(feature = "native")]
+    fn request_presence(
+        &self,
+        show: PresenceShow,
+        status: Option<String>,
+        priority: i8,
+        idle_since: Option<DateTime<Utc>>,
+    ) {
+        let hide = matches!(show, PresenceShow::Invisible)
+            && self.invisibility_supported.load(Ordering::Relaxed);
+        if self.server_invisible.swap(hide, Ordering::Relaxed) != hide {
+            debug!(invisible = hide, "changing XEP-0186 invisibility");
+            let _ = self.event_bus.publish(Event::new(
+                Channel::new(channels::UI_PRESENCE_INVISIBILITY).unwrap(),
+                EventSource::System("presence".into()),
+                EventPayload::InvisibilitySetRequested { invisible: hide },
+            ));
         }
 
         let _ = self.event_bus.publish(Event::new(
-            Channel::new("ui.presence.set").unwrap(),
+            Channel::new(channels::UI_PRESENCE_SET).unwrap(),
             EventSource::System("presence".into()),
             EventPayload::PresenceSetRequested {
                 show,
-                status: status.map(String::from),
+                status,
+                priority,
+                idle_since,
             },
         ));
+    }
+
+    /// Advertise (or clear) local user inactivity via XEP-0319. The current
+    /// show and status are re-sent with an `<idle/>` element when `since` is
+    /// set. Does nothing while our own presence is unavailable or invisible.
+    #[cfg(feature = "native")]
+    pub fn set_idle(&self, since: Option<DateTime<Utc>>) -> Result<(), PresenceError> {
+        let (show, status, priority) = {
+            let mut own = self.own_presence.write().unwrap();
+            if matches!(
+                own.show,
+                PresenceShow::Unavailable | PresenceShow::Invisible
+            ) || own.idle_since == since
+            {
+                return Ok(());
+            }
+            own.idle_since = since;
+            own.last_updated = Utc::now();
+            (own.show.clone(), own.status.clone(), own.priority)
+        };
+
+        debug!(?since, "advertising own idle state");
+        self.request_presence(show, status, priority, since);
+        Ok(())
+    }
+
+    /// Send presence to `jid` alone, e.g. a status shown only inside one MUC
+    /// room (addressed to `room@service/nick`). Our broadcast presence and
+    /// local state are left untouched.
+    #[cfg(feature = "native")]
+    pub fn send_directed(
+        &self,
+        jid: &str,
+        show: PresenceShow,
+        status: Option<&str>,
+    ) -> Result<(), PresenceError> {
+        if !waddle_core::jid::is_valid(jid) {
+            return Err(PresenceError::InvalidJid(jid.to_string()));
+        }
+
+        debug!(to = %jid, ?show, "sending directed presence");
+        self.event_bus
+            .publish(Event::new(
+                Channel::new(channels::UI_PRESENCE_DIRECTED).unwrap(),
+                EventSource::System("presence".into()),
+                EventPayload::DirectedPresenceRequested {
+                    to: jid.to_string(),
+                    show,
+                    status: status.map(String::from),
+                },
+            ))
+            .map_err(|e| PresenceError::EventBus(e.to_string()))?;
 
         Ok(())
     }
@@ -132,13 +301,18 @@ impl PresenceManager {
                     own.show = PresenceShow::Unavailable;
                     own.status = None;
                     own.priority = 0;
+                    own.idle_since = None;
                     own.last_updated = Utc::now();
                 }
                 self.contacts.write().unwrap().clear();
                 self.awaiting_initial_presence
                     .store(true, Ordering::Relaxed);
+                self.invisibility_supported.store(false, Ordering::Relaxed);
+                self.server_invisible.store(false, Ordering::Relaxed);
             }
-            EventPayload::RosterReceived { .. } => {
+            // Versioned fetches may confirm the stored roster instead of
+            // sending it again.
+            EventPayload::RosterReceived { .. } | EventPayload::RosterUnchanged => {
                 if !self
                     .awaiting_initial_presence
                     .swap(false, Ordering::Relaxed)
@@ -149,7 +323,11 @@ impl PresenceManager {
                 debug!("roster received, sending initial presence");
                 {
                     let mut own = self.own_presence.write().unwrap();
-                    own.show = PresenceShow::Available;
+                    own.show = if self.appear_offline.load(Ordering::Relaxed) {
+                        PresenceShow::Invisible
+                    } else {
+                        PresenceShow::Available
+                    };
                     own.status = None;
                     own.priority = 0;
                     own.last_updated = Utc::now();
@@ -162,10 +340,12 @@ impl PresenceManager {
                     .store(false, Ordering::Relaxed);
                 self.send_unavailable_presence();
                 self.contacts.write().unwrap().clear();
+                self.server_invisible.store(false, Ordering::Relaxed);
                 {
                     let mut own = self.own_presence.write().unwrap();
                     own.show = PresenceShow::Unavailable;
                     own.status = None;
+                    own.idle_since = None;
                     own.last_updated = Utc::now();
                 }
             }
@@ -174,8 +354,9 @@ impl PresenceManager {
                 show,
                 status,
                 priority,
+                idle_since,
             } => {
-                debug!(jid = %jid, ?show, priority, "contact presence changed");
+                debug!(jid = %jid, ?show, priority, ?idle_since, "contact presence changed");
                 let bare = bare_jid(jid);
                 let resource = resource_part(jid);
                 let info = PresenceInfo {
@@ -183,14 +364,24 @@ impl PresenceManager {
                     show: show.clone(),
                     status: status.clone(),
                     priority: *priority,
+                    idle_since: *idle_since,
                     last_updated: Utc::now(),
                 };
-                let mut contacts = self.contacts.write().unwrap();
-                let resources = contacts.entry(bare).or_default();
-                if matches!(show, PresenceShow::Unavailable) {
-                    resources.remove(&resource);
-                } else {
-                    resources.insert(resource, info);
+                let (before, after) = {
+                    let mut contacts = self.contacts.write().unwrap();
+                    let resources = contacts.entry(bare.clone()).or_default();
+                    let before = best_presence(&bare, resources);
+                    if matches!(show, PresenceShow::Unavailable) {
+                        resources.remove(&resource);
+                    } else {
+                        resources.insert(resource, info);
+                    }
+                    (before, best_presence(&bare, resources))
+                };
+                if !matches!(after.show, PresenceShow::Unavailable)
+                    && before.is_idle() != after.is_idle()
+                {
+                    self.emit_idle_changed(&bare, after.idle_since);
                 }
             }
             EventPayload::OwnPresenceChanged { show, status } => {
@@ -200,30 +391,65 @@ impl PresenceManager {
                 own.status = status.clone();
                 own.last_updated = Utc::now();
             }
+            EventPayload::DiscoInfoReceived {
+                jid,
+                node: None,
+                features,
+                ..
+            } => self.record_server_features(jid, features),
             _ => {}
         }
     }
 
     #[cfg(feature = "native")]
-    fn send_initial_presence(&self) {
+    fn emit_idle_changed(&self, jid: &str, idle_since: Option<DateTime<Utc>>) {
+        debug!(jid = %jid, ?idle_since, "contact idle state changed");
         let _ = self.event_bus.publish(Event::new(
-            Channel::new("ui.presence.set").unwrap(),
+            Channel::new(channels::SYSTEM_PRESENCE_IDLE_CHANGED).unwrap(),
             EventSource::System("presence".into()),
-            EventPayload::PresenceSetRequested {
-                show: PresenceShow::Available,
-                status: None,
+            EventPayload::ContactIdleChanged {
+                jid: jid.to_string(),
+                idle_since,
             },
         ));
     }
 
+    /// Note whether our server offers XEP-0186. If we already appear offline
+    /// through the unavailable fallback, switch to real invisibility.
+    #[cfg(feature = "native")]
+    fn record_server_features(&self, jid: &str, features: &[String]) {
+        let own = self.own_presence();
+        let is_own_server = JidParts::parse(&own.jid).is_ok_and(|parts| parts.domain == jid);
+        if !is_own_server {
+            return;
+        }
+
+        let supported = features.iter().any(|feature| feature == NS_INVISIBLE);
+        let was_supported = self
+            .invisibility_supported
+            .swap(supported, Ordering::Relaxed);
+        if supported && !was_supported && matches!(own.show, PresenceShow::Invisible) {
+            debug!("server supports invisibility, leaving unavailable fallback");
+            self.request_presence(own.show, own.status, own.priority, None);
+        }
+    }
+
+    #[cfg(feature = "native")]
+    fn send_initial_presence(&self) {
+        let own = self.own_presence();
+        self.request_presence(own.show, None, own.priority, None);
+    }
+
     #[cfg(feature = "native")]
     fn send_unavailable_presence(&self) {
         let _ = self.event_bus.publish(Event::new(
-            Channel::new("ui.presence.set").unwrap(),
+            Channel::new(channels::UI_PRESENCE_SET).unwrap(),
             EventSource::System("presence".into()),
             EventPayload::PresenceSetRequested {
                 show: PresenceShow::Unavailable,
                 status: None,
+                priority: 0,
+                idle_since: None,
             },
         ));
     }
@@ -256,6 +482,46 @@ impl PresenceManager {
     }
 }
 
+/// Sends unavailable presence on shutdown and waits until it is on the wire.
+#[cfg(feature = "native")]
+impl Manager for PresenceManager {
+    fn name(&self) -> &'static str {
+        "presence"
+    }
+
+    fn shutdown(&self) -> ShutdownFuture<'_> {
+        Box::pin(async move {
+            if matches!(self.own_presence().show, PresenceShow::Unavailable) {
+                return Ok(());
+            }
+
+            let mut sub = self
+                .event_bus
+                .subscribe(channels::XMPP_PRESENCE_OWN_CHANGED)?;
+            self.set_own_presence(PresenceShow::Unavailable, None, None)
+                .map_err(|e| WaddleError::Xmpp(e.to_string()))?;
+
+            loop {
+                match sub.recv().await {
+                    Ok(Event {
+                        payload:
+                            EventPayload::OwnPresenceChanged {
+                                show: PresenceShow::Unavailable,
+                                ..
+                            },
+                        ..
+                    }) => return Ok(()),
+                    Ok(_) => {}
+                    Err(waddle_core::error::EventBusError::Lagged(count)) => {
+                        warn!(count, "presence shutdown lagged, some events dropped");
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        })
+    }
+}
+
 /// Select the highest-priority resource's presence. Ties broken by most
 /// recent update. Returns Unavailable if the resource map is empty.
 fn best_presence(bare: &str, resources: &ResourceMap) -> PresenceInfo {
@@ -270,20 +536,6 @@ fn best_presence(bare: &str, resources: &ResourceMap) -> PresenceInfo {
         .unwrap_or_else(|| PresenceInfo::unavailable(bare))
 }
 
-fn bare_jid(jid: &str) -> String {
-    match jid.find('/') {
-        Some(pos) => jid[..pos].to_string(),
-        None => jid.to_string(),
-    }
-}
-
-fn resource_part(jid: &str) -> String {
-    match jid.find('/') {
-        Some(pos) => jid[pos + 1..].to_string(),
-        None => String::new(),
-    }
-}
-
 #[cfg(all(test, feature = "native"))]
 mod tests {
     use super::*;
@@ -316,6 +568,7 @@ mod tests {
             show,
             status: status.map(String::from),
             priority,
+            idle_since: None,
         }
     }
 
@@ -374,6 +627,8 @@ mod tests {
             EventPayload::PresenceSetRequested {
                 show: PresenceShow::Available,
                 status: None,
+                priority: 0,
+                idle_since: None,
             }
         ));
     }
@@ -434,6 +689,8 @@ mod tests {
             EventPayload::PresenceSetRequested {
                 show: PresenceShow::Unavailable,
                 status: None,
+                priority: 0,
+                idle_since: None,
             }
         ));
     }
@@ -506,6 +763,11 @@ mod tests {
         assert!(matches!(info.show, PresenceShow::Away));
         assert_eq!(info.status, Some("on phone".to_string()));
         assert_eq!(info.priority, 10);
+
+        let resources = manager.get_resources("alice@example.com");
+        let names: Vec<&str> = resources.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["desktop", "mobile"]);
+        assert!(matches!(resources[0].1.show, PresenceShow::Available));
     }
 
     #[tokio::test]
@@ -627,7 +889,7 @@ mod tests {
         let mut sub = event_bus.subscribe("ui.**").unwrap();
 
         manager
-            .set_own_presence(PresenceShow::Away, Some("lunch"), None)
+            .set_own_presence(PresenceShow::Away, Some("lunch"), Some(10))
             .unwrap();
 
         let received = tokio::time::timeout(Duration::from_millis(100), sub.recv())
@@ -639,6 +901,166 @@ mod tests {
             received.payload,
             EventPayload::PresenceSetRequested {
                 show: PresenceShow::Away,
+                priority: 10,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_directed_targets_one_jid_without_touching_own_presence() {
+        let (manager, event_bus) = make_manager();
+        let mut sub = event_bus.subscribe("ui.**").unwrap();
+
+        manager
+            .send_directed(
+                "dev@conference.example.com/alice",
+                PresenceShow::Dnd,
+                Some("heads down"),
+            )
+            .unwrap();
+
+        let received = tokio::time::timeout(Duration::from_millis(100), sub.recv())
+            .await
+            .expect("timed out")
+            .expect("should receive event");
+        assert_eq!(received.channel.as_str(), "ui.presence.directed");
+        match received.payload {
+            EventPayload::DirectedPresenceRequested { to, show, status } => {
+                assert_eq!(to, "dev@conference.example.com/alice");
+                assert!(matches!(show, PresenceShow::Dnd));
+                assert_eq!(status.as_deref(), Some("heads down"));
+            }
+            other => panic!("expected DirectedPresenceRequested, got {other:?}"),
+        }
+        assert!(matches!(
+            manager.own_presence().show,
+            PresenceShow::Unavailable
+        ));
+
+        assert!(matches!(
+            manager.send_directed("not a jid", PresenceShow::Away, None),
+            Err(PresenceError::InvalidJid(_))
+        ));
+    }
+
+    async fn next_ui_payload(sub: &mut waddle_core::event::EventSubscription) -> EventPayload {
+        tokio::time::timeout(Duration::from_millis(100), sub.recv())
+            .await
+            .expect("timed out")
+            .expect("should receive event")
+            .payload
+    }
+
+    async fn connect(manager: &PresenceManager, server_features: &[&str]) {
+        manager
+            .handle_event(&make_event(
+                "system.connection.established",
+                EventPayload::ConnectionEstablished {
+                    jid: "alice@example.com/desktop".to_string(),
+                },
+            ))
+            .await;
+        manager
+            .handle_event(&make_event(
+                "xmpp.disco.info_received",
+                EventPayload::DiscoInfoReceived {
+                    jid: "example.com".to_string(),
+                    node: None,
+                    features: server_features.iter().map(|f| f.to_string()).collect(),
+                    upload_max_size: None,
+                    verification: None,
+                },
+            ))
+            .await;
+    }
+
+    #[tokio::test]
+    async fn appearing_offline_without_server_support_sends_invisible_presence() {
+        let (manager, event_bus) = make_manager();
+        connect(&manager, &[]).await;
+        let mut sub = event_bus.subscribe("ui.**").unwrap();
+
+        manager
+            .set_own_presence(PresenceShow::Invisible, None, None)
+            .unwrap();
+
+        assert!(matches!(
+            next_ui_payload(&mut sub).await,
+            EventPayload::PresenceSetRequested {
+                show: PresenceShow::Invisible,
+                ..
+            }
+        ));
+        assert!(matches!(
+            manager.own_presence().show,
+            PresenceShow::Invisible
+        ));
+        manager.set_idle(Some(Utc::now())).unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), sub.recv())
+                .await
+                .is_err(),
+            "idle state is not advertised while invisible"
+        );
+    }
+
+    #[tokio::test]
+    async fn appearing_offline_uses_server_invisibility_when_offered() {
+        let (manager, event_bus) = make_manager();
+        connect(&manager, &[NS_INVISIBLE]).await;
+        let mut sub = event_bus.subscribe("ui.**").unwrap();
+
+        manager
+            .set_own_presence(PresenceShow::Invisible, None, None)
+            .unwrap();
+        assert!(matches!(
+            next_ui_payload(&mut sub).await,
+            EventPayload::InvisibilitySetRequested { invisible: true }
+        ));
+        assert!(matches!(
+            next_ui_payload(&mut sub).await,
+            EventPayload::PresenceSetRequested {
+                show: PresenceShow::Invisible,
+                ..
+            }
+        ));
+
+        manager
+            .set_own_presence(PresenceShow::Away, None, None)
+            .unwrap();
+        assert!(matches!(
+            next_ui_payload(&mut sub).await,
+            EventPayload::InvisibilitySetRequested { invisible: false }
+        ));
+        assert!(matches!(
+            next_ui_payload(&mut sub).await,
+            EventPayload::PresenceSetRequested {
+                show: PresenceShow::Away,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn appearing_offline_survives_reconnect() {
+        let (manager, event_bus) = make_manager();
+        manager
+            .set_own_presence(PresenceShow::Invisible, None, None)
+            .unwrap();
+        connect(&manager, &[]).await;
+        let mut sub = event_bus.subscribe("ui.**").unwrap();
+
+        manager
+            .handle_event(&make_event(
+                "xmpp.roster.received",
+                EventPayload::RosterReceived { items: Vec::new() },
+            ))
+            .await;
+        assert!(matches!(
+            next_ui_payload(&mut sub).await,
+            EventPayload::PresenceSetRequested {
+                show: PresenceShow::Invisible,
                 ..
             }
         ));
@@ -720,6 +1142,167 @@ mod tests {
         assert_eq!(info.status, Some("stepped out".to_string()));
     }
 
+    #[tokio::test]
+    async fn contact_idle_since_is_stored() {
+        let (manager, _) = make_manager();
+        let since = Utc::now() - chrono::Duration::hours(2);
+
+        let event = make_event(
+            "xmpp.presence.changed",
+            EventPayload::PresenceChanged {
+                jid: "alice@example.com/desktop".to_string(),
+                show: PresenceShow::Away,
+                status: None,
+                priority: 0,
+                idle_since: Some(since),
+            },
+        );
+        manager.handle_event(&event).await;
+
+        let info = manager.get_presence("alice@example.com");
+        assert_eq!(info.idle_since, Some(since));
+        assert!(info.is_idle());
+    }
+
+    #[tokio::test]
+    async fn idle_transitions_emit_events() {
+        let (manager, event_bus) = make_manager();
+        let mut sub = event_bus.subscribe("system.presence.idle_changed").unwrap();
+        let since = Utc::now() - chrono::Duration::minutes(10);
+
+        let active = make_event(
+            "xmpp.presence.changed",
+            presence_changed(
+                "alice@example.com/desktop",
+                PresenceShow::Available,
+                None,
+                0,
+            ),
+        );
+        manager.handle_event(&active).await;
+
+        let idle = make_event(
+            "xmpp.presence.changed",
+            EventPayload::PresenceChanged {
+                jid: "alice@example.com/desktop".to_string(),
+                show: PresenceShow::Away,
+                status: None,
+                priority: 0,
+                idle_since: Some(since),
+            },
+        );
+        manager.handle_event(&idle).await;
+
+        let received = tokio::time::timeout(Duration::from_millis(100), sub.recv())
+            .await
+            .expect("timed out")
+            .expect("should receive event");
+        match received.payload {
+            EventPayload::ContactIdleChanged { jid, idle_since } => {
+                assert_eq!(jid, "alice@example.com");
+                assert_eq!(idle_since, Some(since));
+            }
+            other => panic!("expected ContactIdleChanged, got {other:?}"),
+        }
+
+        // Repeating the same idle presence is not a transition
+        manager.handle_event(&idle).await;
+        manager.handle_event(&active).await;
+
+        let received = tokio::time::timeout(Duration::from_millis(100), sub.recv())
+            .await
+            .expect("timed out")
+            .expect("should receive event");
+        assert!(matches!(
+            received.payload,
+            EventPayload::ContactIdleChanged {
+                idle_since: None,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn going_offline_while_idle_emits_no_idle_event() {
+        let (manager, event_bus) = make_manager();
+        let mut sub = event_bus.subscribe("system.presence.idle_changed").unwrap();
+
+        let idle = make_event(
+            "xmpp.presence.changed",
+            EventPayload::PresenceChanged {
+                jid: "alice@example.com/desktop".to_string(),
+                show: PresenceShow::Xa,
+                status: None,
+                priority: 0,
+                idle_since: Some(Utc::now()),
+            },
+        );
+        manager.handle_event(&idle).await;
+        let _ = sub.recv().await.unwrap();
+
+        let offline = make_event(
+            "xmpp.presence.changed",
+            presence_changed(
+                "alice@example.com/desktop",
+                PresenceShow::Unavailable,
+                None,
+                0,
+            ),
+        );
+        manager.handle_event(&offline).await;
+
+        let received = tokio::time::timeout(Duration::from_millis(100), sub.recv()).await;
+        assert!(received.is_err(), "offline is not an active transition");
+    }
+
+    #[tokio::test]
+    async fn set_idle_republishes_presence_with_idle() {
+        let (manager, event_bus) = make_manager();
+        manager
+            .set_own_presence(PresenceShow::Away, Some("lunch"), None)
+            .unwrap();
+        let mut sub = event_bus.subscribe("ui.presence.set").unwrap();
+        let since = Utc::now();
+
+        manager.set_idle(Some(since)).unwrap();
+
+        let received = tokio::time::timeout(Duration::from_millis(100), sub.recv())
+            .await
+            .expect("timed out")
+            .expect("should receive event");
+        match received.payload {
+            EventPayload::PresenceSetRequested {
+                show,
+                status,
+                idle_since,
+                ..
+            } => {
+                assert!(matches!(show, PresenceShow::Away));
+                assert_eq!(status.as_deref(), Some("lunch"));
+                assert_eq!(idle_since, Some(since));
+            }
+            other => panic!("expected PresenceSetRequested, got {other:?}"),
+        }
+        assert_eq!(manager.own_presence().idle_since, Some(since));
+
+        // Setting the same idle state again is a no-op
+        manager.set_idle(Some(since)).unwrap();
+        let received = tokio::time::timeout(Duration::from_millis(100), sub.recv()).await;
+        assert!(received.is_err());
+    }
+
+    #[tokio::test]
+    async fn set_idle_ignored_while_unavailable() {
+        let (manager, event_bus) = make_manager();
+        let mut sub = event_bus.subscribe("ui.**").unwrap();
+
+        manager.set_idle(Some(Utc::now())).unwrap();
+
+        let received = tokio::time::timeout(Duration::from_millis(100), sub.recv()).await;
+        assert!(received.is_err());
+        assert!(manager.own_presence().idle_since.is_none());
+    }
+
     #[tokio::test]
     async fn run_loop_processes_events() {
         let (manager, event_bus) = make_manager();
@@ -739,6 +1322,7 @@ mod tests {
                     show: PresenceShow::Chat,
                     status: Some("free to chat".to_string()),
                     priority: 0,
+                    idle_since: None,
                 },
             ))
             .unwrap();
@@ -752,18 +1336,59 @@ mod tests {
         handle.abort();
     }
 
-    #[test]
-    fn bare_jid_strips_resource() {
-        assert_eq!(bare_jid("user@example.com/resource"), "user@example.com");
-        assert_eq!(bare_jid("user@example.com"), "user@example.com");
-        assert_eq!(bare_jid("user@example.com/res/extra"), "user@example.com");
+    #[tokio::test]
+    async fn shutdown_waits_for_unavailable_to_be_sent() {
+        let (manager, event_bus) = make_manager();
+        manager
+            .set_own_presence(PresenceShow::Available, None, None)
+            .unwrap();
+        let mut sub = event_bus.subscribe("ui.presence.set").unwrap();
+
+        let shutdown = tokio::spawn({
+            let manager = manager.clone();
+            async move { manager.shutdown().await }
+        });
+
+        let event = tokio::time::timeout(Duration::from_millis(200), sub.recv())
+            .await
+            .expect("timed out")
+            .unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::PresenceSetRequested {
+                show: PresenceShow::Unavailable,
+                ..
+            }
+        ));
+        assert!(!shutdown.is_finished());
+
+        event_bus
+            .publish(Event::new(
+                Channel::new("xmpp.presence.own_changed").unwrap(),
+                EventSource::Xmpp,
+                EventPayload::OwnPresenceChanged {
+                    show: PresenceShow::Unavailable,
+                    status: None,
+                },
+            ))
+            .unwrap();
+
+        tokio::time::timeout(Duration::from_millis(200), shutdown)
+            .await
+            .expect("timed out")
+            .unwrap()
+            .unwrap();
     }
 
-    #[test]
-    fn resource_part_extracts_resource() {
-        assert_eq!(resource_part("user@example.com/desktop"), "desktop");
-        assert_eq!(resource_part("user@example.com"), "");
-        assert_eq!(resource_part("user@example.com/res/extra"), "res/extra");
+    #[tokio::test]
+    async fn shutdown_while_offline_sends_nothing() {
+        let (manager, event_bus) = make_manager();
+        let mut sub = event_bus.subscribe("ui.**").unwrap();
+
+        manager.shutdown().await.unwrap();
+
+        let result = tokio::time::timeout(Duration::from_millis(50), sub.recv()).await;
+        assert!(result.is_err(), "no presence should be sent");
     }
 
     #[test]
@@ -776,6 +1401,7 @@ mod tests {
                 show: PresenceShow::Available,
                 status: None,
                 priority: 5,
+                idle_since: None,
                 last_updated: Utc::now(),
             },
         );
@@ -786,6 +1412,7 @@ mod tests {
                 show: PresenceShow::Away,
                 status: Some("on phone".to_string()),
                 priority: 10,
+                idle_since: None,
                 last_updated: Utc::now(),
             },
         );
@@ -0,0 +1,315 @@
+//! XEP-0363 upload service discovery. The service is the server itself or,
+//! more often, one of the components its disco#items lists, such as
+//! `upload.example.com`, so finding it takes a disco#info query to the
+//! server and one to every component.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use tokio::time::Instant;
+use waddle_core::event::{DiscoItem, EventPayload, channels};
+use waddle_core::rsm::RsmQuery;
+
+pub(crate) const NS_HTTP_UPLOAD: &str = "urn:xmpp:http:upload:0";
+
+/// How long a search waits for disco answers before it gives up on the
+/// entities that have not answered.
+pub(crate) const SEARCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where a server's upload service lives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct UploadService {
+    pub jid: String,
+    /// Largest file it accepts, in bytes, if it says.
+    pub max_size: Option<u64>,
+}
+
+#[derive(Debug)]
+struct Search {
+    server_answered: bool,
+    items_answered: bool,
+    /// Components asked for disco#info that have not answered yet.
+    pending: HashSet<String>,
+    found: Option<UploadService>,
+    deadline: Instant,
+}
+
+impl Search {
+    fn is_finished(&self) -> bool {
+        self.server_answered && self.items_answered && self.pending.is_empty()
+    }
+}
+
+/// Follows the disco answers that locate each searched domain's upload
+/// service. Callers publish the queries it returns and feed it the answers.
+#[derive(Debug, Default)]
+pub(crate) struct UploadDiscovery {
+    searches: HashMap<String, Search>,
+}
+
+impl UploadDiscovery {
+    /// Start looking on `domain`, replacing any search already under way,
+    /// and return the queries to send.
+    pub fn start(&mut self, domain: &str) -> Vec<EventPayload> {
+        self.searches.insert(
+            domain.to_string(),
+            Search {
+                server_answered: false,
+                items_answered: false,
+                pending: HashSet::new(),
+                found: None,
+                deadline: Instant::now() + SEARCH_TIMEOUT,
+            },
+        );
+        vec![
+            EventPayload::DiscoInfoRequested {
+                jid: domain.to_string(),
+                node: None,
+            },
+            EventPayload::DiscoItemsRequested {
+                jid: domain.to_string(),
+                node: None,
+                page: RsmQuery::default(),
+            },
+        ]
+    }
+
+    /// Record a disco#info answer from a searched domain or one of its
+    /// components.
+    pub fn info_received(&mut self, jid: &str, features: &[String], max_size: Option<u64>) {
+        let offers_upload = features.iter().any(|f| f == NS_HTTP_UPLOAD);
+        for (domain, search) in &mut self.searches {
+            let asked = if jid == domain {
+                search.server_answered = true;
+                true
+            } else {
+                search.pending.remove(jid)
+            };
+            if asked && offers_upload && search.found.is_none() {
+                search.found = Some(UploadService {
+                    jid: jid.to_string(),
+                    max_size,
+                });
+            }
+        }
+    }
+
+    /// Record a disco#info error from a searched domain or one of its
+    /// components: whoever refused the query does not host the service.
+    pub fn info_failed(&mut self, jid: &str) {
+        self.info_received(jid, &[], None);
+    }
+
+    /// Record a searched domain's refusal to list its items, leaving only
+    /// the domain itself to host the service.
+    pub fn items_failed(&mut self, jid: &str) {
+        if let Some(search) = self.searches.get_mut(jid) {
+            search.items_answered = true;
+        }
+    }
+
+    /// Record a searched domain's disco#items answer and return the
+    /// disco#info queries for the components it lists.
+    pub fn items_received(&mut self, jid: &str, items: &[DiscoItem]) -> Vec<EventPayload> {
+        let Some(search) = self.searches.get_mut(jid) else {
+            return Vec::new();
+        };
+        if search.items_answered {
+            return Vec::new();
+        }
+        search.items_answered = true;
+        items
+            .iter()
+            // Components are bare domains; items with a node are not
+            // separate entities.
+            .filter(|item| item.node.is_none() && !item.jid.contains(['@', '/']) && item.jid != jid)
+            .filter(|item| search.pending.insert(item.jid.clone()))
+            .map(|item| EventPayload::DiscoInfoRequested {
+                jid: item.jid.clone(),
+                node: None,
+            })
+            .collect()
+    }
+
+    /// Remove and return the searches every answer has come in for, with
+    /// the upload service each found, if any.
+    pub fn take_finished(&mut self) -> Vec<(String, Option<UploadService>)> {
+        let finished: Vec<String> = self
+            .searches
+            .iter()
+            .filter(|(_, search)| search.is_finished())
+            .map(|(domain, _)| domain.clone())
+            .collect();
+        finished
+            .into_iter()
+            .filter_map(|domain| {
+                let search = self.searches.remove(&domain)?;
+                Some((domain, search.found))
+            })
+            .collect()
+    }
+
+    /// Remove and return the searches that passed [`SEARCH_TIMEOUT`] with
+    /// answers still missing, with the upload service found so far, if any.
+    pub fn take_timed_out(&mut self) -> Vec<(String, Option<UploadService>)> {
+        let now = Instant::now();
+        let expired: Vec<String> = self
+            .searches
+            .iter()
+            .filter(|(_, search)| search.deadline <= now)
+            .map(|(domain, _)| domain.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|domain| {
+                let search = self.searches.remove(&domain)?;
+                Some((domain, search.found))
+            })
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.searches.clear();
+    }
+}
+
+/// The channel to publish a disco query returned by [`UploadDiscovery`] on.
+pub(crate) fn query_channel(query: &EventPayload) -> &'static str {
+    match query {
+        EventPayload::DiscoItemsRequested { .. } => channels::UI_DISCO_ITEMS,
+        _ => channels::UI_DISCO_INFO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(jid: &str) -> DiscoItem {
+        DiscoItem {
+            jid: jid.to_string(),
+            node: None,
+            name: None,
+        }
+    }
+
+    fn info_requests(payloads: &[EventPayload]) -> Vec<&str> {
+        payloads
+            .iter()
+            .filter_map(|payload| match payload {
+                EventPayload::DiscoInfoRequested { jid, .. } => Some(jid.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_the_upload_component_among_disco_items() {
+        let mut discovery = UploadDiscovery::default();
+        assert_eq!(
+            info_requests(&discovery.start("example.com")),
+            ["example.com"]
+        );
+
+        discovery.info_received("example.com", &["urn:xmpp:mam:2".to_string()], None);
+        let queries = discovery.items_received(
+            "example.com",
+            &[
+                item("conference.example.com"),
+                item("files.example.com"),
+                item("alice@example.com"),
+            ],
+        );
+        assert_eq!(
+            info_requests(&queries),
+            ["conference.example.com", "files.example.com"]
+        );
+
+        discovery.info_received(
+            "files.example.com",
+            &[NS_HTTP_UPLOAD.to_string()],
+            Some(1024),
+        );
+        assert!(discovery.take_finished().is_empty());
+
+        discovery.info_received("conference.example.com", &[], None);
+        assert_eq!(
+            discovery.take_finished(),
+            [(
+                "example.com".to_string(),
+                Some(UploadService {
+                    jid: "files.example.com".to_string(),
+                    max_size: Some(1024),
+                })
+            )]
+        );
+        assert!(discovery.take_finished().is_empty());
+    }
+
+    #[test]
+    fn finishes_without_a_service_when_none_offers_upload() {
+        let mut discovery = UploadDiscovery::default();
+        discovery.start("example.com");
+        discovery.items_received("example.com", &[]);
+        discovery.info_received("example.com", &[], None);
+        assert_eq!(
+            discovery.take_finished(),
+            [("example.com".to_string(), None)]
+        );
+    }
+
+    #[test]
+    fn a_refusing_component_does_not_hold_up_the_search() {
+        let mut discovery = UploadDiscovery::default();
+        discovery.start("example.com");
+        discovery.info_received("example.com", &[], None);
+        discovery.items_received(
+            "example.com",
+            &[item("files.example.com"), item("broken.example.com")],
+        );
+        discovery.info_received("files.example.com", &[NS_HTTP_UPLOAD.to_string()], None);
+        assert!(discovery.take_finished().is_empty());
+
+        discovery.info_failed("broken.example.com");
+        assert_eq!(
+            discovery.take_finished(),
+            [(
+                "example.com".to_string(),
+                Some(UploadService {
+                    jid: "files.example.com".to_string(),
+                    max_size: None,
+                })
+            )]
+        );
+    }
+
+    #[test]
+    fn a_server_refusing_disco_items_finishes_the_search() {
+        let mut discovery = UploadDiscovery::default();
+        discovery.start("example.com");
+        discovery.info_failed("example.com");
+        discovery.items_failed("example.com");
+        assert_eq!(
+            discovery.take_finished(),
+            [("example.com".to_string(), None)]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_on_silent_components_after_the_timeout() {
+        let mut discovery = UploadDiscovery::default();
+        discovery.start("example.com");
+        discovery.info_received("example.com", &[], None);
+        discovery.items_received("example.com", &[item("silent.example.com")]);
+
+        tokio::time::advance(SEARCH_TIMEOUT / 2).await;
+        assert!(discovery.take_timed_out().is_empty());
+
+        tokio::time::advance(SEARCH_TIMEOUT).await;
+        assert_eq!(
+            discovery.take_timed_out(),
+            [("example.com".to_string(), None)]
+        );
+        assert!(discovery.take_finished().is_empty());
+    }
+}
@@ -1,13 +1,25 @@
-use std::sync::Arc;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
+use chrono::{DateTime, Utc};
 use tracing::{debug, error, warn};
 
-use waddle_core::event::{Channel, Event, EventPayload, EventSource, RosterItem, Subscription};
-use waddle_storage::{Database, FromRow, Row, SqlValue, StorageError};
+use waddle_core::config::RosterConfig;
+use waddle_core::event::{
+    Channel, Event, EventPayload, EventSource, RosterItem, SpamReason, Subscription, channels,
+};
+use waddle_core::jid::Jid;
+use waddle_storage::{Database, FromRow, Query, Row, SqlValue, StorageError, ToSql};
+use waddle_xmpp::{AVATAR_METADATA_NODE, avatar_hash};
 
 #[cfg(feature = "native")]
 use waddle_core::event::EventBus;
 
+mod profile;
+
+pub use profile::{Profile, ProfileManager};
+
 #[derive(Debug, thiserror::Error)]
 pub enum RosterError {
     #[error("roster fetch failed: {0}")]
@@ -66,6 +78,98 @@ impl FromRow for StoredRosterItem {
     }
 }
 
+/// An inbound subscription request awaiting approval or denial.
+#[derive(Debug, Clone)]
+pub struct PendingSubscription {
+    pub jid: String,
+    pub received_at: DateTime<Utc>,
+}
+
+impl FromRow for PendingSubscription {
+    fn from_row(row: &Row) -> Result<Self, StorageError> {
+        let jid = match row.get(0) {
+            Some(SqlValue::Text(s)) => s.clone(),
+            _ => return Err(StorageError::QueryFailed("missing jid column".to_string())),
+        };
+        let received_at = match row.get(1) {
+            Some(SqlValue::Text(s)) => DateTime::parse_from_rfc3339(s)
+                .map(|dt| dt.with_timezone(&Utc))
+                .map_err(|e| StorageError::QueryFailed(format!("invalid received_at: {e}")))?,
+            _ => {
+                return Err(StorageError::QueryFailed(
+                    "missing received_at column".to_string(),
+                ));
+            }
+        };
+        Ok(PendingSubscription { jid, received_at })
+    }
+}
+
+/// A sender the user reported for spam or abuse.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportedContact {
+    pub jid: String,
+    pub reason: SpamReason,
+    pub reported_at: DateTime<Utc>,
+}
+
+fn reason_to_str(reason: SpamReason) -> &'static str {
+    match reason {
+        SpamReason::Spam => "spam",
+        SpamReason::Abuse => "abuse",
+    }
+}
+
+impl FromRow for ReportedContact {
+    fn from_row(row: &Row) -> Result<Self, StorageError> {
+        let jid = match row.get(0) {
+            Some(SqlValue::Text(s)) => s.clone(),
+            _ => return Err(StorageError::QueryFailed("missing jid column".to_string())),
+        };
+        let reason = match row.get(1) {
+            Some(SqlValue::Text(s)) if s == "abuse" => SpamReason::Abuse,
+            Some(SqlValue::Text(_)) => SpamReason::Spam,
+            _ => {
+                return Err(StorageError::QueryFailed(
+                    "missing reason column".to_string(),
+                ));
+            }
+        };
+        let reported_at = match row.get(2) {
+            Some(SqlValue::Text(s)) => DateTime::parse_from_rfc3339(s)
+                .map(|dt| dt.with_timezone(&Utc))
+                .map_err(|e| StorageError::QueryFailed(format!("invalid reported_at: {e}")))?,
+            _ => {
+                return Err(StorageError::QueryFailed(
+                    "missing reported_at column".to_string(),
+                ));
+            }
+        };
+        Ok(ReportedContact {
+            jid,
+            reason,
+            reported_at,
+        })
+    }
+}
+
+/// What the roster knows about one contact, for a profile view.
+#[derive(Debug, Clone)]
+pub struct ContactSummary {
+    pub jid: String,
+    /// The roster entry, or `None` for someone not on the roster.
+    pub item: Option<RosterItem>,
+    /// Our subscription state with the contact; [`Subscription::None`] off
+    /// the roster.
+    pub subscription: Subscription,
+    /// The contact asked to see our presence and awaits an answer.
+    pub subscription_pending: bool,
+    /// Hash of the contact's XEP-0084 avatar, as last announced.
+    pub avatar_hash: Option<String>,
+    /// When a message was last exchanged with the contact.
+    pub last_message_at: Option<DateTime<Utc>>,
+}
+
 impl StoredRosterItem {
     fn into_roster_item(self) -> RosterItem {
         let groups: Vec<String> = self
@@ -84,6 +188,17 @@ impl StoredRosterItem {
 
 pub struct RosterManager<D: Database> {
     db: Arc<D>,
+    /// Whether approving a request also subscribes to the requester.
+    mutual_subscription: AtomicBool,
+    /// Lowercased domains whose requests are approved without asking.
+    auto_accept_domains: Vec<String>,
+    /// Approve requests from contacts we already have a `to` subscription to.
+    auto_accept_subscribed: bool,
+    /// Roster state from before each local change the server has not
+    /// confirmed yet, keyed by JID. `None` means the contact did not exist.
+    unconfirmed: Mutex<HashMap<Jid, Option<RosterItem>>>,
+    /// Avatar hash each contact last announced, keyed by JID.
+    avatar_hashes: Mutex<HashMap<Jid, String>>,
     #[cfg(feature = "native")]
     event_bus: Arc<dyn EventBus>,
 }
@@ -91,7 +206,47 @@ pub struct RosterManager<D: Database> {
 impl<D: Database> RosterManager<D> {
     #[cfg(feature = "native")]
     pub fn new(db: Arc<D>, event_bus: Arc<dyn EventBus>) -> Self {
-        Self { db, event_bus }
+        Self {
+            db,
+            mutual_subscription: AtomicBool::new(true),
+            auto_accept_domains: Vec::new(),
+            auto_accept_subscribed: true,
+            unconfirmed: Mutex::new(HashMap::new()),
+            avatar_hashes: Mutex::new(HashMap::new()),
+            event_bus,
+        }
+    }
+
+    /// Apply the subscription rules from the `[roster]` config section.
+    pub fn with_config(mut self, config: &RosterConfig) -> Self {
+        self.mutual_subscription = AtomicBool::new(config.mutual_subscription);
+        self.auto_accept_domains = config
+            .auto_accept_domains
+            .iter()
+            .map(|domain| domain.trim().to_lowercase())
+            .filter(|domain| !domain.is_empty())
+            .collect();
+        self.auto_accept_subscribed = config.auto_accept_subscribed;
+        self
+    }
+
+    /// Enable or disable sending a reciprocal subscription request when an
+    /// inbound request is approved. Enabled by default.
+    pub fn set_mutual_subscription(&self, enabled: bool) {
+        self.mutual_subscription.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Inbound subscription requests that have not been approved or denied
+    /// yet, oldest first. Persisted across restarts.
+    pub async fn pending_subscriptions(&self) -> Result<Vec<PendingSubscription>, RosterError> {
+        let rows: Vec<PendingSubscription> = self
+            .db
+            .query(
+                "SELECT jid, received_at FROM pending_subscriptions ORDER BY received_at, jid",
+                &[],
+            )
+            .await?;
+        Ok(rows)
     }
 
     pub async fn get_roster(&self) -> Result<Vec<RosterItem>, RosterError> {
@@ -105,6 +260,56 @@ impl<D: Database> RosterManager<D> {
         Ok(rows.into_iter().map(|r| r.into_roster_item()).collect())
     }
 
+    /// Compose what the roster knows about `jid` for a profile view: the
+    /// roster entry and subscription, avatar and the last message exchanged.
+    pub async fn get_contact_summary(&self, jid: &str) -> Result<ContactSummary, RosterError> {
+        let key = roster_key(jid);
+        let item = self.get_contact(jid).await?;
+        let subscription_pending =
+            Query::new("SELECT jid, received_at FROM pending_subscriptions WHERE jid = :jid")
+                .bind("jid", &key)
+                .fetch_optional::<PendingSubscription, _>(self.db.as_ref())
+                .await?
+                .is_some();
+
+        let last_message: Option<Row> = Query::new(
+            "SELECT MAX(timestamp) FROM messages WHERE from_jid = :jid OR to_jid = :jid",
+        )
+        .bind("jid", &key)
+        .fetch_optional(self.db.as_ref())
+        .await?;
+        let last_message_at = match last_message.as_ref().and_then(|row| row.get(0)) {
+            Some(SqlValue::Text(timestamp)) => DateTime::parse_from_rfc3339(timestamp)
+                .map(|at| Some(at.with_timezone(&Utc)))
+                .map_err(|e| StorageError::QueryFailed(format!("invalid timestamp: {e}")))?,
+            _ => None,
+        };
+
+        Ok(ContactSummary {
+            jid: key.as_str().to_string(),
+            subscription: item
+                .as_ref()
+                .map_or(Subscription::None, |item| item.subscription.clone()),
+            item,
+            subscription_pending,
+            avatar_hash: self.avatar_hashes.lock().unwrap().get(&key).cloned(),
+            last_message_at,
+        })
+    }
+
+    /// The roster version (RFC 6121 §2.6) the stored roster is current as
+    /// of, if the server supports versioning and no local change is
+    /// unconfirmed.
+    pub async fn roster_version(&self) -> Result<Option<String>, RosterError> {
+        let row: Option<Row> = Query::new("SELECT ver FROM roster_version")
+            .fetch_optional(self.db.as_ref())
+            .await?;
+        Ok(row.and_then(|row| match row.get(0) {
+            Some(SqlValue::Text(ver)) => Some(ver.clone()),
+            _ => None,
+        }))
+    }
+
     pub async fn add_contact(
         &self,
         jid: &str,
@@ -115,8 +320,9 @@ impl<D: Database> RosterManager<D> {
             jid: jid.to_string(),
             reason: e.to_string(),
         })?;
+        let previous = self.get_contact(jid).await?;
         let sub = Subscription::None.as_str().to_string();
-        let jid_s = jid.to_string();
+        let jid_s = roster_key(jid);
         let name_s = name.map(|s| s.to_string());
         self.db
             .execute(
@@ -124,11 +330,12 @@ impl<D: Database> RosterManager<D> {
                 &[&jid_s, &name_s, &sub, &groups_json],
             )
             .await?;
+        self.remember_previous(jid, previous).await?;
 
         #[cfg(feature = "native")]
         {
             let _ = self.event_bus.publish(Event::new(
-                Channel::new("ui.roster.add").unwrap(),
+                Channel::new(channels::UI_ROSTER_ADD).unwrap(),
                 EventSource::System("roster".into()),
                 EventPayload::RosterAddRequested {
                     jid: jid.to_string(),
@@ -141,20 +348,57 @@ impl<D: Database> RosterManager<D> {
         Ok(())
     }
 
-    pub async fn remove_contact(&self, jid: &str) -> Result<(), RosterError> {
-        let jid_s = jid.to_string();
-        let affected = self
-            .db
-            .execute("DELETE FROM roster WHERE jid = ?1", &[&jid_s])
-            .await?;
-        if affected == 0 {
-            return Err(RosterError::ContactNotFound(jid.to_string()));
+    /// Add a contact and pre-approve their subscription to us (RFC 6121
+    /// §3.4), so their request back needs no confirmation, then subscribe to
+    /// them. A `preauth` token the contact shared out of band (XEP-0379)
+    /// lets their server approve our request without asking them either.
+    pub async fn add_contact_preapproved(
+        &self,
+        jid: &str,
+        name: Option<&str>,
+        groups: &[String],
+        preauth: Option<&str>,
+    ) -> Result<(), RosterError> {
+        self.add_contact(jid, name, groups).await?;
+
+        #[cfg(feature = "native")]
+        {
+            let _ = self.event_bus.publish(Event::new(
+                Channel::new(channels::UI_SUBSCRIPTION_RESPOND).unwrap(),
+                EventSource::System("roster".into()),
+                EventPayload::SubscriptionRespondRequested {
+                    jid: jid.to_string(),
+                    accept: true,
+                },
+            ));
+            let _ = self.event_bus.publish(Event::new(
+                Channel::new(channels::UI_SUBSCRIPTION_SEND).unwrap(),
+                EventSource::System("roster".into()),
+                EventPayload::SubscriptionSendRequested {
+                    jid: jid.to_string(),
+                    subscribe: true,
+                    preauth: preauth.map(String::from),
+                },
+            ));
         }
+        #[cfg(not(feature = "native"))]
+        let _ = preauth;
+
+        Ok(())
+    }
+
+    pub async fn remove_contact(&self, jid: &str) -> Result<(), RosterError> {
+        let previous = self
+            .get_contact(jid)
+            .await?
+            .ok_or_else(|| RosterError::ContactNotFound(jid.to_string()))?;
+        self.delete_item(jid).await?;
+        self.remember_previous(jid, Some(previous)).await?;
 
         #[cfg(feature = "native")]
         {
             let _ = self.event_bus.publish(Event::new(
-                Channel::new("ui.roster.remove").unwrap(),
+                Channel::new(channels::UI_ROSTER_REMOVE).unwrap(),
                 EventSource::System("roster".into()),
                 EventPayload::RosterRemoveRequested {
                     jid: jid.to_string(),
@@ -171,56 +415,102 @@ impl<D: Database> RosterManager<D> {
         name: Option<&str>,
         groups: &[String],
     ) -> Result<(), RosterError> {
-        let jid_s = jid.to_string();
+        let previous = self.require_contact(jid).await?;
+        let updated = RosterItem {
+            name: name.map(String::from),
+            groups: groups.to_vec(),
+            ..previous.clone()
+        };
+        self.apply_update(previous, updated).await
+    }
 
-        let existing: Result<StoredRosterItem, StorageError> = self
-            .db
-            .query_one(
-                "SELECT jid, name, subscription, groups FROM roster WHERE jid = ?1",
-                &[&jid_s],
-            )
-            .await;
+    /// Change the display name of a contact. `None` clears it.
+    pub async fn rename_contact(&self, jid: &str, name: Option<&str>) -> Result<(), RosterError> {
+        let previous = self.require_contact(jid).await?;
+        let updated = RosterItem {
+            name: name.map(String::from),
+            ..previous.clone()
+        };
+        self.apply_update(previous, updated).await
+    }
 
-        if let Err(error) = existing {
-            return match error {
-                StorageError::NotFound => Err(RosterError::ContactNotFound(jid.to_string())),
-                other => Err(RosterError::Storage(other)),
-            };
+    /// Replace the groups of a contact. Duplicate group names are dropped.
+    pub async fn set_groups(&self, jid: &str, groups: &[String]) -> Result<(), RosterError> {
+        let previous = self.require_contact(jid).await?;
+        let mut deduped: Vec<String> = Vec::with_capacity(groups.len());
+        for group in groups {
+            if !deduped.contains(group) {
+                deduped.push(group.clone());
+            }
         }
+        let updated = RosterItem {
+            groups: deduped,
+            ..previous.clone()
+        };
+        self.apply_update(previous, updated).await
+    }
 
-        let groups_json = serde_json::to_string(groups).map_err(|e| RosterError::SetFailed {
-            jid: jid.to_string(),
-            reason: e.to_string(),
-        })?;
-        let name_s = name.map(|s| s.to_string());
-        self.db
-            .execute(
-                "UPDATE roster SET name = ?1, groups = ?2 WHERE jid = ?3",
-                &[&name_s, &groups_json, &jid_s],
-            )
-            .await?;
-
-        #[cfg(feature = "native")]
-        {
-            let _ = self.event_bus.publish(Event::new(
-                Channel::new("ui.roster.update").unwrap(),
-                EventSource::System("roster".into()),
-                EventPayload::RosterUpdateRequested {
-                    jid: jid.to_string(),
-                    name: name.map(String::from),
-                    groups: groups.to_vec(),
-                },
-            ));
+    /// Move a contact from group `from` to group `to`, keeping its other
+    /// groups. If the contact is not in `from` it is simply added to `to`.
+    pub async fn move_to_group(&self, jid: &str, from: &str, to: &str) -> Result<(), RosterError> {
+        let previous = self.require_contact(jid).await?;
+        let mut groups: Vec<String> = previous
+            .groups
+            .iter()
+            .filter(|group| group.as_str() != from)
+            .cloned()
+            .collect();
+        if !groups.iter().any(|group| group == to) {
+            groups.push(to.to_string());
         }
+        let updated = RosterItem {
+            groups,
+            ..previous.clone()
+        };
+        self.apply_update(previous, updated).await
+    }
 
-        Ok(())
+    /// All group names used in the roster, sorted and deduplicated.
+    pub async fn list_groups(&self) -> Result<Vec<String>, RosterError> {
+        let groups: BTreeSet<String> = self
+            .get_roster()
+            .await?
+            .into_iter()
+            .flat_map(|item| item.groups)
+            .collect();
+        Ok(groups.into_iter().collect())
     }
 
+    /// Contacts matching `query` by name, JID or group, best match first,
+    /// for the quick-switcher. Matching is case-insensitive; a blank query
+    /// matches nothing.
+    pub async fn search(&self, query: &str) -> Result<Vec<RosterItem>, RosterError> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut ranked: Vec<(u8, String, RosterItem)> = self
+            .get_roster()
+            .await?
+            .into_iter()
+            .filter_map(|item| {
+                let rank = match_rank(&item, &query)?;
+                let label = item.name.as_deref().unwrap_or(&item.jid).to_lowercase();
+                Some((rank, label, item))
+            })
+            .collect();
+        ranked.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        Ok(ranked.into_iter().map(|(_, _, item)| item).collect())
+    }
+
+    /// Approve an inbound subscription request. Clears it from the pending
+    /// list, makes sure the contact is in the roster and, when mutual
+    /// subscription is enabled, subscribes back to the requester.
     pub async fn approve_subscription(&self, jid: &str) -> Result<(), RosterError> {
         #[cfg(feature = "native")]
         {
             let _ = self.event_bus.publish(Event::new(
-                Channel::new("ui.subscription.respond").unwrap(),
+                Channel::new(channels::UI_SUBSCRIPTION_RESPOND).unwrap(),
                 EventSource::System("roster".into()),
                 EventPayload::SubscriptionRespondRequested {
                     jid: jid.to_string(),
@@ -228,14 +518,33 @@ impl<D: Database> RosterManager<D> {
                 },
             ));
         }
+
+        self.clear_pending(jid).await?;
+
+        let existing = self.get_contact(jid).await?;
+        let already_subscribed = existing
+            .as_ref()
+            .is_some_and(|item| matches!(item.subscription, Subscription::To | Subscription::Both));
+
+        if self.mutual_subscription.load(Ordering::Relaxed) && !already_subscribed {
+            self.request_subscription(jid).await?;
+        }
+
+        if existing.is_none() {
+            self.add_contact(jid, None, &[]).await?;
+        }
+
         Ok(())
     }
 
+    /// Deny an inbound subscription request and clear it from the pending list.
     pub async fn deny_subscription(&self, jid: &str) -> Result<(), RosterError> {
+        self.clear_pending(jid).await?;
+
         #[cfg(feature = "native")]
         {
             let _ = self.event_bus.publish(Event::new(
-                Channel::new("ui.subscription.respond").unwrap(),
+                Channel::new(channels::UI_SUBSCRIPTION_RESPOND).unwrap(),
                 EventSource::System("roster".into()),
                 EventPayload::SubscriptionRespondRequested {
                     jid: jid.to_string(),
@@ -250,11 +559,12 @@ impl<D: Database> RosterManager<D> {
         #[cfg(feature = "native")]
         {
             let _ = self.event_bus.publish(Event::new(
-                Channel::new("ui.subscription.send").unwrap(),
+                Channel::new(channels::UI_SUBSCRIPTION_SEND).unwrap(),
                 EventSource::System("roster".into()),
                 EventPayload::SubscriptionSendRequested {
                     jid: jid.to_string(),
                     subscribe: true,
+                    preauth: None,
                 },
             ));
         }
@@ -265,55 +575,291 @@ impl<D: Database> RosterManager<D> {
         #[cfg(feature = "native")]
         {
             let _ = self.event_bus.publish(Event::new(
-                Channel::new("ui.subscription.send").unwrap(),
+                Channel::new(channels::UI_SUBSCRIPTION_SEND).unwrap(),
                 EventSource::System("roster".into()),
                 EventPayload::SubscriptionSendRequested {
                     jid: jid.to_string(),
                     subscribe: false,
+                    preauth: None,
                 },
             ));
         }
         Ok(())
     }
 
-    async fn upsert_item(&self, item: &RosterItem) -> Result<(), RosterError> {
-        let groups_json =
-            serde_json::to_string(&item.groups).map_err(|e| RosterError::SetFailed {
-                jid: item.jid.clone(),
-                reason: e.to_string(),
-            })?;
-        let sub = item.subscription.as_str().to_string();
-        self.db
-            .execute(
-                "INSERT OR REPLACE INTO roster (jid, name, subscription, groups) VALUES (?1, ?2, ?3, ?4)",
-                &[&item.jid, &item.name, &sub, &groups_json],
+    /// Block `jid` on the server (XEP-0191).
+    pub async fn block(&self, jid: &str) -> Result<(), RosterError> {
+        self.request_block(jid, None);
+        Ok(())
+    }
+
+    /// Unblock `jid` on the server. A sender reported earlier is no longer
+    /// marked as reported, so the UI shows them again.
+    pub async fn unblock(&self, jid: &str) -> Result<(), RosterError> {
+        Query::new("DELETE FROM reported_contacts WHERE jid = :jid")
+            .bind("jid", &roster_key(jid))
+            .execute(self.db.as_ref())
+            .await?;
+
+        #[cfg(feature = "native")]
+        {
+            let _ = self.event_bus.publish(Event::new(
+                Channel::new(channels::UI_BLOCKING_UNBLOCK).unwrap(),
+                EventSource::System("roster".into()),
+                EventPayload::UnblockRequested {
+                    jid: jid.to_string(),
+                },
+            ));
+        }
+        Ok(())
+    }
+
+    /// Block `jid` and report them to the server's operators (XEP-0377).
+    /// They are remembered as reported so the UI can hide them.
+    pub async fn report_spam(&self, jid: &str, reason: SpamReason) -> Result<(), RosterError> {
+        Query::new(
+            "INSERT OR REPLACE INTO reported_contacts (jid, reason, reported_at) \
+             VALUES (:jid, :reason, :reported_at)",
+        )
+        .bind("jid", &roster_key(jid))
+        .bind("reason", &reason_to_str(reason).to_string())
+        .bind("reported_at", &Utc::now().to_rfc3339())
+        .execute(self.db.as_ref())
+        .await?;
+        self.request_block(jid, Some(reason));
+        Ok(())
+    }
+
+    /// Senders the user reported, most recent first.
+    pub async fn reported_contacts(&self) -> Result<Vec<ReportedContact>, RosterError> {
+        let rows: Vec<ReportedContact> = self
+            .db
+            .query(
+                "SELECT jid, reason, reported_at FROM reported_contacts \
+                 ORDER BY reported_at DESC, jid",
+                &[],
             )
             .await?;
+        Ok(rows)
+    }
+
+    pub async fn is_reported(&self, jid: &str) -> Result<bool, RosterError> {
+        let reported: Option<ReportedContact> =
+            Query::new("SELECT jid, reason, reported_at FROM reported_contacts WHERE jid = :jid")
+                .bind("jid", &roster_key(jid))
+                .fetch_optional(self.db.as_ref())
+                .await?;
+        Ok(reported.is_some())
+    }
+
+    fn request_block(&self, jid: &str, report: Option<SpamReason>) {
+        #[cfg(feature = "native")]
+        {
+            let _ = self.event_bus.publish(Event::new(
+                Channel::new(channels::UI_BLOCKING_BLOCK).unwrap(),
+                EventSource::System("roster".into()),
+                EventPayload::BlockRequested {
+                    jid: jid.to_string(),
+                    report,
+                },
+            ));
+        }
+        #[cfg(not(feature = "native"))]
+        let _ = (jid, report);
+    }
+
+    /// Whether a request from `jid` is approved without asking the user.
+    async fn should_auto_accept(&self, jid: &str) -> Result<bool, RosterError> {
+        let bare = roster_key(jid);
+        let domain = bare.rsplit('@').next().unwrap_or(&bare);
+        if self.auto_accept_domains.iter().any(|d| d == domain) {
+            return Ok(true);
+        }
+        if !self.auto_accept_subscribed {
+            return Ok(false);
+        }
+        Ok(self
+            .get_contact(&bare)
+            .await?
+            .is_some_and(|item| matches!(item.subscription, Subscription::To)))
+    }
+
+    async fn get_contact(&self, jid: &str) -> Result<Option<RosterItem>, RosterError> {
+        let item: Option<StoredRosterItem> =
+            Query::new("SELECT jid, name, subscription, groups FROM roster WHERE jid = :jid")
+                .bind("jid", &roster_key(jid))
+                .fetch_optional(self.db.as_ref())
+                .await?;
+        Ok(item.map(StoredRosterItem::into_roster_item))
+    }
+
+    async fn require_contact(&self, jid: &str) -> Result<RosterItem, RosterError> {
+        self.get_contact(jid)
+            .await?
+            .ok_or_else(|| RosterError::ContactNotFound(jid.to_string()))
+    }
+
+    /// Store `updated` locally right away and ask the server to apply it.
+    /// The previous state is kept until the server confirms or rejects it.
+    async fn apply_update(
+        &self,
+        previous: RosterItem,
+        updated: RosterItem,
+    ) -> Result<(), RosterError> {
+        self.upsert_item(&updated).await?;
+        self.remember_previous(&updated.jid, Some(previous)).await?;
+
+        #[cfg(feature = "native")]
+        {
+            let _ = self.event_bus.publish(Event::new(
+                Channel::new(channels::UI_ROSTER_UPDATE).unwrap(),
+                EventSource::System("roster".into()),
+                EventPayload::RosterUpdateRequested {
+                    jid: updated.jid.clone(),
+                    name: updated.name.clone(),
+                    groups: updated.groups.clone(),
+                },
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Keep the oldest unconfirmed state so that several changes in a row
+    /// roll back to what the server last confirmed. The stored roster no
+    /// longer matches any server version, so the next fetch is a full one.
+    async fn remember_previous(
+        &self,
+        jid: &str,
+        previous: Option<RosterItem>,
+    ) -> Result<(), RosterError> {
+        self.unconfirmed
+            .lock()
+            .unwrap()
+            .entry(roster_key(jid))
+            .or_insert(previous);
+        self.set_roster_version(None).await
+    }
+
+    fn confirm(&self, jid: &str) {
+        self.unconfirmed.lock().unwrap().remove(&roster_key(jid));
+    }
+
+    async fn rollback(&self, jid: &str) -> Result<(), RosterError> {
+        let Some(previous) = self.unconfirmed.lock().unwrap().remove(&roster_key(jid)) else {
+            return Ok(());
+        };
+        match previous {
+            Some(item) => self.upsert_item(&item).await,
+            None => self.delete_item(jid).await,
+        }
+    }
+
+    async fn store_pending(&self, jid: &str) -> Result<PendingSubscription, RosterError> {
+        let jid = roster_key(jid);
+        Query::new(
+            "INSERT OR IGNORE INTO pending_subscriptions (jid, received_at) \
+             VALUES (:jid, :received_at)",
+        )
+        .bind("jid", &jid)
+        .bind("received_at", &Utc::now().to_rfc3339())
+        .execute(self.db.as_ref())
+        .await?;
+
+        let stored: PendingSubscription =
+            Query::new("SELECT jid, received_at FROM pending_subscriptions WHERE jid = :jid")
+                .bind("jid", &jid)
+                .fetch_one(self.db.as_ref())
+                .await?;
+        Ok(stored)
+    }
+
+    async fn clear_pending(&self, jid: &str) -> Result<(), RosterError> {
+        Query::new("DELETE FROM pending_subscriptions WHERE jid = :jid")
+            .bind("jid", &roster_key(jid))
+            .execute(self.db.as_ref())
+            .await?;
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    fn emit_subscription_pending(&self, pending: &PendingSubscription) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channels::UI_ROSTER_SUBSCRIPTION_PENDING).unwrap(),
+            EventSource::System("roster".into()),
+            EventPayload::SubscriptionPending {
+                jid: pending.jid.clone(),
+                received_at: pending.received_at,
+            },
+        ));
+    }
+
+    async fn upsert_item(&self, item: &RosterItem) -> Result<(), RosterError> {
+        let row = RosterRow::new(item)?;
+        self.db.execute(UPSERT_ROSTER_ITEM, &row.params()).await?;
         Ok(())
     }
 
     async fn delete_item(&self, jid: &str) -> Result<(), RosterError> {
-        let jid_s = jid.to_string();
-        self.db
-            .execute("DELETE FROM roster WHERE jid = ?1", &[&jid_s])
+        Query::new("DELETE FROM roster WHERE jid = :jid")
+            .bind("jid", &roster_key(jid))
+            .execute(self.db.as_ref())
             .await?;
         Ok(())
     }
 
+    /// Replace the stored roster with `items` in one transaction, so a
+    /// failure part way never leaves a partial roster behind.
     async fn replace_all(&self, items: &[RosterItem]) -> Result<(), RosterError> {
-        self.db.execute("DELETE FROM roster", &[]).await?;
-        for item in items {
-            self.upsert_item(item).await?;
+        let rows = items
+            .iter()
+            .map(RosterRow::new)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.db
+            .transaction(|tx| {
+                tx.execute("DELETE FROM roster", &[]);
+                for row in &rows {
+                    tx.execute(UPSERT_ROSTER_ITEM, &row.params());
+                }
+                Ok(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn set_roster_version(&self, ver: Option<&str>) -> Result<(), RosterError> {
+        match ver {
+            Some(ver) => {
+                Query::new("INSERT OR REPLACE INTO roster_version (id, ver) VALUES (1, :ver)")
+                    .bind("ver", ver)
+                    .execute(self.db.as_ref())
+                    .await?;
+            }
+            None => {
+                self.db.execute("DELETE FROM roster_version", &[]).await?;
+            }
         }
         Ok(())
     }
 
+    /// Ask for the changes since the stored roster version, or for the full
+    /// roster and its version when there is none. Servers without roster
+    /// versioning ignore the version and send the full roster.
     #[cfg(feature = "native")]
-    fn request_roster_fetch(&self) {
+    async fn request_roster_fetch(&self) {
+        let ver = match self.roster_version().await {
+            Ok(ver) => ver,
+            Err(e) => {
+                error!(error = %e, "failed to load roster version");
+                None
+            }
+        };
         let _ = self.event_bus.publish(Event::new(
-            Channel::new("ui.roster.fetch").unwrap(),
+            Channel::new(channels::UI_ROSTER_FETCH).unwrap(),
             EventSource::System("roster".into()),
-            EventPayload::RosterFetchRequested,
+            EventPayload::RosterFetchRequested {
+                ver: Some(ver.unwrap_or_default()),
+            },
         ));
     }
 
@@ -322,51 +868,121 @@ impl<D: Database> RosterManager<D> {
         match &event.payload {
             EventPayload::ConnectionEstablished { .. } => {
                 debug!("connection established, requesting roster fetch");
-                self.request_roster_fetch();
+                self.request_roster_fetch().await;
+
+                match self.pending_subscriptions().await {
+                    Ok(pending) => {
+                        for request in &pending {
+                            self.emit_subscription_pending(request);
+                        }
+                    }
+                    Err(e) => {
+                        error!(error = %e, "failed to load pending subscription requests");
+                    }
+                }
             }
             EventPayload::RosterReceived { items } => {
                 debug!(count = items.len(), "full roster received, persisting");
+                self.unconfirmed.lock().unwrap().clear();
                 if let Err(e) = self.replace_all(items).await {
                     error!(error = %e, "failed to persist roster");
                 }
             }
+            EventPayload::RosterUnchanged => {
+                debug!("stored roster is current, awaiting pushed changes");
+            }
+            EventPayload::RosterVersionChanged { ver } => {
+                // A version only vouches for the stored roster once every
+                // local change has been confirmed.
+                if !self.unconfirmed.lock().unwrap().is_empty() {
+                    debug!("roster changes unconfirmed, not storing roster version");
+                    return;
+                }
+                if let Err(e) = self.set_roster_version(ver.as_deref()).await {
+                    error!(error = %e, "failed to store roster version");
+                }
+            }
             EventPayload::RosterUpdated { item } => {
                 debug!(jid = %item.jid, "roster item updated, persisting");
+                self.confirm(&item.jid);
                 if let Err(e) = self.upsert_item(item).await {
                     error!(error = %e, jid = %item.jid, "failed to persist roster update");
                 }
+
+                // Approved from another client: the request is no longer pending.
+                if matches!(item.subscription, Subscription::From | Subscription::Both)
+                    && let Err(e) = self.clear_pending(&item.jid).await
+                {
+                    error!(error = %e, jid = %item.jid, "failed to clear pending subscription");
+                }
             }
             EventPayload::RosterRemoved { jid } => {
                 debug!(jid = %jid, "roster item removed, deleting from storage");
+                self.confirm(jid);
                 if let Err(e) = self.delete_item(jid).await {
                     error!(error = %e, jid = %jid, "failed to delete roster item");
                 }
             }
-            EventPayload::SubscriptionRequest { from } => {
-                debug!(from = %from, "inbound subscription request received, auto-approving");
-
-                // Auto-approve the incoming subscription request
-                if let Err(e) = self.approve_subscription(from).await {
-                    error!(error = %e, from = %from, "failed to approve subscription");
+            EventPayload::RosterSetFailed { jid, reason } => {
+                warn!(jid = %jid, reason = %reason, "roster change rejected, rolling back");
+                // No confirmed state means the contact was new.
+                let was_add = matches!(
+                    self.unconfirmed.lock().unwrap().get(&roster_key(jid)),
+                    Some(None)
+                );
+                if let Err(e) = self.rollback(jid).await {
+                    error!(error = %e, jid = %jid, "failed to roll back roster change");
+                    return;
                 }
-
-                // Reciprocally subscribe so both sides see each other
-                if let Err(e) = self.request_subscription(from).await {
-                    error!(error = %e, from = %from, "failed to send reciprocal subscription");
+                if was_add {
+                    let _ = self.event_bus.publish(Event::new(
+                        Channel::new(channels::UI_ROSTER_ADD_FAILED).unwrap(),
+                        EventSource::System("roster".into()),
+                        EventPayload::RosterAddFailed {
+                            jid: roster_key(jid).into_string(),
+                            reason: reason.clone(),
+                        },
+                    ));
+                }
+            }
+            EventPayload::SubscriptionRequest { from } => {
+                match self.should_auto_accept(from).await {
+                    Ok(true) => {
+                        debug!(from = %from, "subscription request matches an auto-accept rule");
+                        if let Err(e) = self.approve_subscription(from).await {
+                            error!(error = %e, from = %from, "failed to auto-approve subscription");
+                        }
+                        return;
+                    }
+                    Ok(false) => {}
+                    Err(e) => {
+                        error!(error = %e, from = %from, "failed to check auto-accept rules");
+                    }
                 }
 
-                // Ensure the contact exists in local storage
-                let existing: Result<StoredRosterItem, StorageError> = self
-                    .db
-                    .query_one(
-                        "SELECT jid, name, subscription, groups FROM roster WHERE jid = ?1",
-                        &[&from.to_string()],
-                    )
-                    .await;
-
-                if existing.is_err() {
-                    if let Err(e) = self.add_contact(from, None, &[]).await {
-                        error!(error = %e, from = %from, "failed to add contact from subscription");
+                debug!(from = %from, "inbound subscription request received, awaiting approval");
+                match self.store_pending(from).await {
+                    Ok(pending) => self.emit_subscription_pending(&pending),
+                    Err(e) => {
+                        error!(error = %e, from = %from, "failed to persist subscription request");
+                    }
+                }
+            }
+            EventPayload::PubSubItemsReceived { from, node, items }
+                if node == AVATAR_METADATA_NODE =>
+            {
+                // The node keeps one item: the current avatar.
+                let Some(item) = items.last() else {
+                    return;
+                };
+                let mut hashes = self.avatar_hashes.lock().unwrap();
+                match avatar_hash(item) {
+                    Some(hash) => {
+                        debug!(jid = %from, hash = %hash, "contact avatar announced");
+                        hashes.insert(roster_key(from), hash);
+                    }
+                    None => {
+                        hashes.remove(&roster_key(from));
                     }
                 }
             }
@@ -411,17 +1027,94 @@ impl<D: Database> RosterManager<D> {
     }
 }
 
+/// Roster entries and subscription requests are per bare JID.
+fn roster_key(jid: &str) -> Jid {
+    Jid::new(jid).bare()
+}
+
+const UPSERT_ROSTER_ITEM: &str =
+    "INSERT OR REPLACE INTO roster (jid, name, subscription, groups) VALUES (?1, ?2, ?3, ?4)";
+
+/// Column values for storing a roster item.
+struct RosterRow<'a> {
+    jid: Jid,
+    name: &'a Option<String>,
+    subscription: String,
+    groups: String,
+}
+
+impl<'a> RosterRow<'a> {
+    fn new(item: &'a RosterItem) -> Result<Self, RosterError> {
+        let groups = serde_json::to_string(&item.groups).map_err(|e| RosterError::SetFailed {
+            jid: item.jid.clone(),
+            reason: e.to_string(),
+        })?;
+        Ok(Self {
+            jid: roster_key(&item.jid),
+            name: &item.name,
+            subscription: item.subscription.as_str().to_string(),
+            groups,
+        })
+    }
+
+    fn params(&self) -> [&dyn ToSql; 4] {
+        [&self.jid, self.name, &self.subscription, &self.groups]
+    }
+}
+
+/// How well `item` matches a lowercased `query`, higher is better, or
+/// `None` when it does not match at all.
+fn match_rank(item: &RosterItem, query: &str) -> Option<u8> {
+    let jid = item.jid.to_lowercase();
+    let name = item.name.as_deref().map(str::to_lowercase);
+    let name = name.as_deref().unwrap_or_default();
+
+    let rank = if jid == query || name == query {
+        6
+    } else if name.starts_with(query) {
+        5
+    } else if jid.starts_with(query) {
+        4
+    } else if name
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| word.starts_with(query))
+    {
+        3
+    } else if name.contains(query) || jid.contains(query) {
+        2
+    } else if item
+        .groups
+        .iter()
+        .any(|group| group.to_lowercase().contains(query))
+    {
+        1
+    } else {
+        return None;
+    };
+    Some(rank)
+}
+
 #[cfg(all(test, feature = "native"))]
 mod tests {
     use super::*;
     use std::sync::Arc;
     use tempfile::TempDir;
-    use waddle_core::event::{BroadcastEventBus, EventBus};
+    use waddle_core::event::{BroadcastEventBus, EventBus, PubSubItem};
 
     async fn setup() -> (
         Arc<RosterManager<impl Database>>,
         Arc<dyn EventBus>,
         TempDir,
+    ) {
+        setup_with_config(RosterConfig::default()).await
+    }
+
+    async fn setup_with_config(
+        config: RosterConfig,
+    ) -> (
+        Arc<RosterManager<impl Database>>,
+        Arc<dyn EventBus>,
+        TempDir,
     ) {
         let dir = TempDir::new().expect("failed to create temp dir");
         let db_path = dir.path().join("test.db");
@@ -430,10 +1123,93 @@ mod tests {
             .expect("failed to open database");
         let db = Arc::new(db);
         let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
-        let manager = Arc::new(RosterManager::new(db, event_bus.clone()));
+        let manager = Arc::new(RosterManager::new(db, event_bus.clone()).with_config(&config));
         (manager, event_bus, dir)
     }
 
+    #[tokio::test]
+    async fn contact_summary_composes_roster_avatar_and_messages() {
+        let dir = TempDir::new().expect("failed to create temp dir");
+        let db = Arc::new(
+            waddle_storage::open_database(&dir.path().join("test.db"))
+                .await
+                .expect("failed to open database"),
+        );
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let manager = RosterManager::new(db.clone(), event_bus.clone());
+
+        let bob = RosterItem {
+            jid: "bob@example.com".to_string(),
+            name: Some("Bob".to_string()),
+            subscription: Subscription::Both,
+            groups: vec!["Friends".to_string()],
+        };
+        manager
+            .handle_event(&xmpp_event(
+                "xmpp.roster.received",
+                EventPayload::RosterReceived {
+                    items: vec![bob.clone()],
+                },
+            ))
+            .await;
+        manager
+            .handle_event(&xmpp_event(
+                "xmpp.pubsub.items",
+                EventPayload::PubSubItemsReceived {
+                    from: "bob@example.com".to_string(),
+                    node: "urn:xmpp:avatar:metadata".to_string(),
+                    items: vec![PubSubItem {
+                        id: Some("a1b2".to_string()),
+                        publisher: None,
+                        payload: Some(
+                            "<metadata xmlns='urn:xmpp:avatar:metadata'>\
+                                <info id='a1b2' bytes='10' type='image/png'/>\
+                            </metadata>"
+                                .to_string(),
+                        ),
+                    }],
+                },
+            ))
+            .await;
+        db.execute(
+            "INSERT INTO messages (id, from_jid, to_jid, body, timestamp, message_type) \
+             VALUES ('m1', 'bob@example.com', 'alice@example.com', 'hi', \
+                     '2026-10-17T09:30:00+00:00', 'chat')",
+            &[],
+        )
+        .await
+        .unwrap();
+
+        let summary = manager
+            .get_contact_summary("Bob@Example.com")
+            .await
+            .unwrap();
+        assert_eq!(summary.jid, "bob@example.com");
+        let item = summary.item.expect("bob is on the roster");
+        assert_eq!(item.name, bob.name);
+        assert_eq!(item.groups, bob.groups);
+        assert_eq!(summary.subscription, Subscription::Both);
+        assert!(!summary.subscription_pending);
+        assert_eq!(summary.avatar_hash.as_deref(), Some("a1b2"));
+        assert_eq!(
+            summary.last_message_at,
+            Some("2026-10-17T09:30:00Z".parse().unwrap())
+        );
+
+        // Someone off the roster asking to subscribe.
+        manager
+            .handle_event(&subscription_request("carol@example.com"))
+            .await;
+        let summary = manager
+            .get_contact_summary("carol@example.com")
+            .await
+            .unwrap();
+        assert!(summary.item.is_none());
+        assert_eq!(summary.subscription, Subscription::None);
+        assert!(summary.subscription_pending);
+        assert_eq!(summary.last_message_at, None);
+    }
+
     #[tokio::test]
     async fn get_roster_empty() {
         let (manager, _, _dir) = setup().await;
@@ -539,6 +1315,172 @@ mod tests {
         ));
     }
 
+    fn xmpp_event(channel: &str, payload: EventPayload) -> Event {
+        Event::new(Channel::new(channel).unwrap(), EventSource::Xmpp, payload)
+    }
+
+    fn set_failed(jid: &str) -> Event {
+        xmpp_event(
+            "xmpp.roster.set_failed",
+            EventPayload::RosterSetFailed {
+                jid: jid.to_string(),
+                reason: "NotAllowed".to_string(),
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn rename_contact_updates_locally_and_requests_set() {
+        let (manager, event_bus, _dir) = setup().await;
+        manager
+            .add_contact("alice@example.com", Some("Alice"), &["Friends".to_string()])
+            .await
+            .unwrap();
+        let mut sub = event_bus.subscribe("ui.roster.update").unwrap();
+
+        manager
+            .rename_contact("alice@example.com", Some("Alice W"))
+            .await
+            .unwrap();
+
+        let items = manager.get_roster().await.unwrap();
+        assert_eq!(items[0].name.as_deref(), Some("Alice W"));
+        assert_eq!(items[0].groups, vec!["Friends"]);
+
+        let event = sub.recv().await.unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::RosterUpdateRequested { ref name, ref groups, .. }
+                if name.as_deref() == Some("Alice W") && groups == &["Friends"]
+        ));
+    }
+
+    #[tokio::test]
+    async fn group_management_round_trip() {
+        let (manager, _, _dir) = setup().await;
+        manager
+            .add_contact("alice@example.com", None, &["Friends".to_string()])
+            .await
+            .unwrap();
+        manager
+            .add_contact("bob@example.com", None, &["Work".to_string()])
+            .await
+            .unwrap();
+
+        manager
+            .set_groups(
+                "alice@example.com",
+                &[
+                    "Friends".to_string(),
+                    "Climbing".to_string(),
+                    "Friends".to_string(),
+                ],
+            )
+            .await
+            .unwrap();
+        manager
+            .move_to_group("alice@example.com", "Friends", "Family")
+            .await
+            .unwrap();
+
+        let alice = manager.require_contact("alice@example.com").await.unwrap();
+        assert_eq!(alice.groups, vec!["Climbing", "Family"]);
+        assert_eq!(
+            manager.list_groups().await.unwrap(),
+            vec!["Climbing", "Family", "Work"]
+        );
+
+        let result = manager.rename_contact("nobody@example.com", None).await;
+        assert!(matches!(result, Err(RosterError::ContactNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn rejected_change_rolls_back_to_confirmed_state() {
+        let (manager, _, _dir) = setup().await;
+        let confirmed = RosterItem {
+            jid: "alice@example.com".to_string(),
+            name: Some("Alice".to_string()),
+            subscription: Subscription::Both,
+            groups: vec!["Friends".to_string()],
+        };
+        manager
+            .handle_event(&xmpp_event(
+                "xmpp.roster.updated",
+                EventPayload::RosterUpdated { item: confirmed },
+            ))
+            .await;
+
+        manager
+            .rename_contact("alice@example.com", Some("Al"))
+            .await
+            .unwrap();
+        manager
+            .move_to_group("alice@example.com", "Friends", "Work")
+            .await
+            .unwrap();
+        manager.handle_event(&set_failed("alice@example.com")).await;
+
+        let alice = manager.require_contact("alice@example.com").await.unwrap();
+        assert_eq!(alice.name.as_deref(), Some("Alice"));
+        assert_eq!(alice.groups, vec!["Friends"]);
+        assert!(matches!(alice.subscription, Subscription::Both));
+    }
+
+    #[tokio::test]
+    async fn rejected_add_removes_contact_and_confirmed_change_sticks() {
+        let (manager, event_bus, _dir) = setup().await;
+        let mut failed_sub = event_bus.subscribe("ui.roster.add_failed").unwrap();
+        manager
+            .add_contact("mallory@example.com", None, &[])
+            .await
+            .unwrap();
+        manager
+            .handle_event(&set_failed("mallory@example.com"))
+            .await;
+        assert!(
+            manager
+                .get_contact("mallory@example.com")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let failed = failed_sub.recv().await.unwrap();
+        assert!(matches!(
+            failed.payload,
+            EventPayload::RosterAddFailed { ref jid, ref reason }
+                if jid == "mallory@example.com" && reason == "NotAllowed"
+        ));
+
+        manager
+            .add_contact("bob@example.com", Some("Bob"), &[])
+            .await
+            .unwrap();
+        manager
+            .handle_event(&xmpp_event(
+                "xmpp.roster.updated",
+                EventPayload::RosterUpdated {
+                    item: RosterItem {
+                        jid: "bob@example.com".to_string(),
+                        name: Some("Bob".to_string()),
+                        subscription: Subscription::None,
+                        groups: vec![],
+                    },
+                },
+            ))
+            .await;
+        manager.handle_event(&set_failed("bob@example.com")).await;
+        assert!(
+            manager
+                .get_contact("bob@example.com")
+                .await
+                .unwrap()
+                .is_some()
+        );
+        let no_failure =
+            tokio::time::timeout(std::time::Duration::from_millis(50), failed_sub.recv()).await;
+        assert!(no_failure.is_err(), "a confirmed contact was not added");
+    }
+
     #[tokio::test]
     async fn handle_roster_received_persists_items() {
         let (manager, _, _dir) = setup().await;
@@ -667,10 +1609,90 @@ mod tests {
             .expect("timed out")
             .expect("should receive event");
 
+        // No stored version: bootstrap versioning with an empty one.
         assert!(matches!(
             received.payload,
-            EventPayload::RosterFetchRequested
+            EventPayload::RosterFetchRequested { ver: Some(ref ver) } if ver.is_empty()
+        ));
+    }
+
+    #[tokio::test]
+    async fn roster_version_is_stored_while_every_change_is_confirmed() {
+        let (manager, event_bus, _dir) = setup().await;
+        let alice = RosterItem {
+            jid: "alice@example.com".to_string(),
+            name: Some("Alice".to_string()),
+            subscription: Subscription::Both,
+            groups: vec![],
+        };
+        let version = |ver: Option<&str>| {
+            xmpp_event(
+                "xmpp.roster.version_changed",
+                EventPayload::RosterVersionChanged {
+                    ver: ver.map(String::from),
+                },
+            )
+        };
+
+        manager
+            .handle_event(&xmpp_event(
+                "xmpp.roster.received",
+                EventPayload::RosterReceived {
+                    items: vec![alice.clone()],
+                },
+            ))
+            .await;
+        manager.handle_event(&version(Some("v1"))).await;
+        assert_eq!(
+            manager.roster_version().await.unwrap().as_deref(),
+            Some("v1")
+        );
+
+        let mut sub = event_bus.subscribe("ui.roster.fetch").unwrap();
+        manager
+            .handle_event(&Event::new(
+                Channel::new("system.connection.established").unwrap(),
+                EventSource::System("connection".into()),
+                EventPayload::ConnectionEstablished {
+                    jid: "user@example.com".to_string(),
+                },
+            ))
+            .await;
+        assert!(matches!(
+            sub.recv().await.unwrap().payload,
+            EventPayload::RosterFetchRequested { ver: Some(ref ver) } if ver == "v1"
         ));
+
+        // A local change leaves the stored roster ahead of any version.
+        manager
+            .rename_contact("alice@example.com", Some("Alice W"))
+            .await
+            .unwrap();
+        assert_eq!(manager.roster_version().await.unwrap(), None);
+        manager.handle_event(&version(Some("v2"))).await;
+        assert_eq!(manager.roster_version().await.unwrap(), None);
+
+        // The server's push confirms it.
+        manager
+            .handle_event(&xmpp_event(
+                "xmpp.roster.updated",
+                EventPayload::RosterUpdated {
+                    item: RosterItem {
+                        name: Some("Alice W".to_string()),
+                        ..alice
+                    },
+                },
+            ))
+            .await;
+        manager.handle_event(&version(Some("v3"))).await;
+        assert_eq!(
+            manager.roster_version().await.unwrap().as_deref(),
+            Some("v3")
+        );
+
+        // A full roster without a version: versioning is not supported.
+        manager.handle_event(&version(None)).await;
+        assert_eq!(manager.roster_version().await.unwrap(), None);
     }
 
     #[tokio::test]
@@ -743,6 +1765,7 @@ mod tests {
             EventPayload::SubscriptionSendRequested {
                 ref jid,
                 subscribe: true,
+                ..
             } if jid == "carol@example.com"
         ));
     }
@@ -764,6 +1787,7 @@ mod tests {
             EventPayload::SubscriptionSendRequested {
                 ref jid,
                 subscribe: false,
+                ..
             } if jid == "carol@example.com"
         ));
     }
@@ -781,4 +1805,388 @@ mod tests {
         );
         manager.handle_event(&event).await;
     }
+
+    fn subscription_request(from: &str) -> Event {
+        Event::new(
+            Channel::new("xmpp.subscription.request").unwrap(),
+            EventSource::Xmpp,
+            EventPayload::SubscriptionRequest {
+                from: from.to_string(),
+            },
+        )
+    }
+
+    async fn next_ui_event(sub: &mut waddle_core::event::EventSubscription) -> EventPayload {
+        tokio::time::timeout(std::time::Duration::from_millis(100), sub.recv())
+            .await
+            .expect("timed out")
+            .expect("should receive event")
+            .payload
+    }
+
+    #[tokio::test]
+    async fn subscription_request_is_persisted_and_announced() {
+        let (manager, event_bus, _dir) = setup().await;
+        let mut sub = event_bus.subscribe("ui.**").unwrap();
+
+        manager
+            .handle_event(&subscription_request("carol@example.com"))
+            .await;
+
+        assert!(matches!(
+            next_ui_event(&mut sub).await,
+            EventPayload::SubscriptionPending { ref jid, .. } if jid == "carol@example.com"
+        ));
+
+        let pending = manager.pending_subscriptions().await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].jid, "carol@example.com");
+
+        // Nothing is approved or added until the user decides
+        assert!(manager.get_roster().await.unwrap().is_empty());
+        let no_more = tokio::time::timeout(std::time::Duration::from_millis(50), sub.recv()).await;
+        assert!(no_more.is_err());
+    }
+
+    #[tokio::test]
+    async fn repeated_request_keeps_original_timestamp() {
+        let (manager, _, _dir) = setup().await;
+
+        manager
+            .handle_event(&subscription_request("carol@example.com"))
+            .await;
+        let first = manager.pending_subscriptions().await.unwrap();
+        manager
+            .handle_event(&subscription_request("carol@example.com"))
+            .await;
+        let second = manager.pending_subscriptions().await.unwrap();
+
+        assert_eq!(second.len(), 1);
+        assert_eq!(first[0].received_at, second[0].received_at);
+    }
+
+    #[tokio::test]
+    async fn approve_subscription_clears_pending_and_subscribes_back() {
+        let (manager, event_bus, _dir) = setup().await;
+        manager
+            .handle_event(&subscription_request("carol@example.com"))
+            .await;
+        let mut sub = event_bus.subscribe("ui.**").unwrap();
+
+        manager
+            .approve_subscription("carol@example.com")
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            next_ui_event(&mut sub).await,
+            EventPayload::SubscriptionRespondRequested { ref jid, accept: true }
+                if jid == "carol@example.com"
+        ));
+        assert!(matches!(
+            next_ui_event(&mut sub).await,
+            EventPayload::SubscriptionSendRequested { ref jid, subscribe: true, .. }
+                if jid == "carol@example.com"
+        ));
+        assert!(matches!(
+            next_ui_event(&mut sub).await,
+            EventPayload::RosterAddRequested { ref jid, .. } if jid == "carol@example.com"
+        ));
+
+        assert!(manager.pending_subscriptions().await.unwrap().is_empty());
+        let roster = manager.get_roster().await.unwrap();
+        assert_eq!(roster.len(), 1);
+        assert_eq!(roster[0].jid, "carol@example.com");
+    }
+
+    #[tokio::test]
+    async fn approve_without_mutual_subscription_only_responds() {
+        let (manager, event_bus, _dir) = setup().await;
+        manager.set_mutual_subscription(false);
+        manager
+            .add_contact("carol@example.com", None, &[])
+            .await
+            .unwrap();
+        let mut sub = event_bus.subscribe("ui.**").unwrap();
+
+        manager
+            .approve_subscription("carol@example.com")
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            next_ui_event(&mut sub).await,
+            EventPayload::SubscriptionRespondRequested { accept: true, .. }
+        ));
+        let no_more = tokio::time::timeout(std::time::Duration::from_millis(50), sub.recv()).await;
+        assert!(
+            no_more.is_err(),
+            "no reciprocal subscription or roster add expected"
+        );
+    }
+
+    #[tokio::test]
+    async fn deny_subscription_clears_pending() {
+        let (manager, event_bus, _dir) = setup().await;
+        manager
+            .handle_event(&subscription_request("mallory@example.com"))
+            .await;
+        let mut sub = event_bus.subscribe("ui.**").unwrap();
+
+        manager
+            .deny_subscription("mallory@example.com")
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            next_ui_event(&mut sub).await,
+            EventPayload::SubscriptionRespondRequested { accept: false, .. }
+        ));
+        assert!(manager.pending_subscriptions().await.unwrap().is_empty());
+        assert!(manager.get_roster().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reporting_spam_blocks_and_remembers_the_sender() {
+        let (manager, event_bus, _dir) = setup().await;
+        let mut sub = event_bus.subscribe("ui.blocking.*").unwrap();
+
+        manager
+            .report_spam("Spammer@example.com/bot", SpamReason::Spam)
+            .await
+            .unwrap();
+        manager.block("pest@example.com").await.unwrap();
+
+        assert!(matches!(
+            next_ui_event(&mut sub).await,
+            EventPayload::BlockRequested { ref jid, report: Some(SpamReason::Spam) }
+                if jid == "Spammer@example.com/bot"
+        ));
+        assert!(matches!(
+            next_ui_event(&mut sub).await,
+            EventPayload::BlockRequested { report: None, .. }
+        ));
+        assert!(manager.is_reported("spammer@example.com").await.unwrap());
+        assert!(!manager.is_reported("pest@example.com").await.unwrap());
+        let reported = manager.reported_contacts().await.unwrap();
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].jid, "spammer@example.com");
+        assert_eq!(reported[0].reason, SpamReason::Spam);
+
+        manager.unblock("spammer@example.com").await.unwrap();
+        assert!(matches!(
+            next_ui_event(&mut sub).await,
+            EventPayload::UnblockRequested { .. }
+        ));
+        assert!(manager.reported_contacts().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn pending_requests_are_reannounced_on_connection() {
+        let (manager, event_bus, _dir) = setup().await;
+        manager
+            .handle_event(&subscription_request("carol@example.com"))
+            .await;
+        let mut sub = event_bus
+            .subscribe("ui.roster.subscription_pending")
+            .unwrap();
+
+        let connected = Event::new(
+            Channel::new("system.connection.established").unwrap(),
+            EventSource::Xmpp,
+            EventPayload::ConnectionEstablished {
+                jid: "me@example.com".to_string(),
+            },
+        );
+        manager.handle_event(&connected).await;
+
+        assert!(matches!(
+            next_ui_event(&mut sub).await,
+            EventPayload::SubscriptionPending { ref jid, .. } if jid == "carol@example.com"
+        ));
+    }
+
+    #[tokio::test]
+    async fn roster_push_with_from_subscription_clears_pending() {
+        let (manager, _, _dir) = setup().await;
+        manager
+            .handle_event(&subscription_request("carol@example.com"))
+            .await;
+
+        let push = Event::new(
+            Channel::new("xmpp.roster.updated").unwrap(),
+            EventSource::Xmpp,
+            EventPayload::RosterUpdated {
+                item: RosterItem {
+                    jid: "carol@example.com".to_string(),
+                    name: None,
+                    subscription: Subscription::From,
+                    groups: vec![],
+                },
+            },
+        );
+        manager.handle_event(&push).await;
+
+        assert!(manager.pending_subscriptions().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn requests_from_auto_accept_domains_are_approved() {
+        let config = RosterConfig {
+            auto_accept_domains: vec!["Example.COM".to_string()],
+            ..RosterConfig::default()
+        };
+        let (manager, event_bus, _dir) = setup_with_config(config).await;
+        let mut sub = event_bus.subscribe("ui.**").unwrap();
+
+        manager
+            .handle_event(&subscription_request("carol@example.com/phone"))
+            .await;
+
+        assert!(matches!(
+            next_ui_event(&mut sub).await,
+            EventPayload::SubscriptionRespondRequested { ref jid, accept: true }
+                if jid == "carol@example.com/phone"
+        ));
+        assert!(manager.pending_subscriptions().await.unwrap().is_empty());
+
+        manager
+            .handle_event(&subscription_request("mallory@example.org"))
+            .await;
+        let pending = manager.pending_subscriptions().await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].jid, "mallory@example.org");
+    }
+
+    #[tokio::test]
+    async fn requests_from_subscribed_contacts_are_approved_unless_disabled() {
+        let bob = RosterItem {
+            jid: "bob@example.com".to_string(),
+            name: None,
+            subscription: Subscription::To,
+            groups: vec![],
+        };
+
+        let (manager, event_bus, _dir) = setup().await;
+        manager.upsert_item(&bob).await.unwrap();
+        let mut sub = event_bus.subscribe("ui.**").unwrap();
+        manager
+            .handle_event(&subscription_request("bob@example.com"))
+            .await;
+        assert!(matches!(
+            next_ui_event(&mut sub).await,
+            EventPayload::SubscriptionRespondRequested { accept: true, .. }
+        ));
+        // Already subscribed to bob, so nothing is sent back.
+        let no_more = tokio::time::timeout(std::time::Duration::from_millis(50), sub.recv()).await;
+        assert!(no_more.is_err());
+
+        let config = RosterConfig {
+            auto_accept_subscribed: false,
+            ..RosterConfig::default()
+        };
+        let (manager, _, _dir) = setup_with_config(config).await;
+        manager.upsert_item(&bob).await.unwrap();
+        manager
+            .handle_event(&subscription_request("bob@example.com"))
+            .await;
+        assert_eq!(manager.pending_subscriptions().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn preapproved_add_approves_then_subscribes_with_token() {
+        let (manager, event_bus, _dir) = setup().await;
+        let mut sub = event_bus.subscribe("ui.**").unwrap();
+
+        manager
+            .add_contact_preapproved("dave@example.com", Some("Dave"), &[], Some("ae7d5a"))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            next_ui_event(&mut sub).await,
+            EventPayload::RosterAddRequested { ref jid, .. } if jid == "dave@example.com"
+        ));
+        assert!(matches!(
+            next_ui_event(&mut sub).await,
+            EventPayload::SubscriptionRespondRequested { ref jid, accept: true }
+                if jid == "dave@example.com"
+        ));
+        assert!(matches!(
+            next_ui_event(&mut sub).await,
+            EventPayload::SubscriptionSendRequested { subscribe: true, ref preauth, .. }
+                if preauth.as_deref() == Some("ae7d5a")
+        ));
+    }
+
+    #[tokio::test]
+    async fn search_ranks_name_jid_and_group_matches() {
+        let (manager, _, _dir) = setup().await;
+        for (jid, name, groups) in [
+            ("zed@example.com", Some("Zed Annie"), vec![]),
+            ("anna@example.com", None, vec![]),
+            ("bob@example.com", Some("Anna"), vec![]),
+            (
+                "carol@example.com",
+                Some("Carol"),
+                vec!["Annual Planning".to_string()],
+            ),
+            ("dave@hanna.example", Some("Dave"), vec![]),
+            ("erin@example.com", Some("Erin"), vec![]),
+        ] {
+            manager
+                .upsert_item(&RosterItem {
+                    jid: jid.to_string(),
+                    name: name.map(String::from),
+                    subscription: Subscription::Both,
+                    groups,
+                })
+                .await
+                .unwrap();
+        }
+
+        let jids: Vec<String> = manager
+            .search("  ANN ")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| item.jid)
+            .collect();
+        assert_eq!(
+            jids,
+            vec![
+                "bob@example.com",
+                "anna@example.com",
+                "zed@example.com",
+                "dave@hanna.example",
+                "carol@example.com",
+            ]
+        );
+
+        let exact = manager.search("erin@example.com").await.unwrap();
+        assert_eq!(exact[0].jid, "erin@example.com");
+        assert!(manager.search("   ").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn contacts_are_keyed_by_normalized_bare_jid() {
+        let (manager, _, _dir) = setup().await;
+
+        manager
+            .add_contact("Bob@Example.COM", Some("Bob"), &[])
+            .await
+            .unwrap();
+        manager
+            .rename_contact("bob@example.com/laptop", Some("Robert"))
+            .await
+            .unwrap();
+
+        let roster = manager.get_roster().await.unwrap();
+        assert_eq!(roster.len(), 1);
+        assert_eq!(roster[0].jid, "bob@example.com");
+        assert_eq!(roster[0].name.as_deref(), Some("Robert"));
+
+        manager.remove_contact("BOB@example.com").await.unwrap();
+        assert!(manager.get_roster().await.unwrap().is_empty());
+    }
 }
@@ -0,0 +1,359 @@
+//! Our own profile: the nickname (XEP-0172), avatar (XEP-0084) and vCard
+//! (XEP-0292) that contacts and our other devices read from our PEP
+//! service. The profile as last set is kept in storage, so it shows while
+//! offline, and is published again on the next connection when setting it
+//! did not reach the server.
+
+use std::sync::Arc;
+#[cfg(feature = "native")]
+use std::sync::RwLock;
+#[cfg(feature = "native")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+#[cfg(feature = "native")]
+use tracing::{debug, error, warn};
+
+use waddle_storage::{Database, FromRow, Query, Row, SqlValue, StorageError};
+use waddle_xmpp::avatar_id;
+
+#[cfg(feature = "native")]
+use waddle_core::event::{Channel, Event, EventBus, EventPayload, EventSource, channels};
+#[cfg(feature = "native")]
+use waddle_xmpp::{
+    AVATAR_DATA_NODE, AVATAR_METADATA_NODE, NICK_NODE, PROFILE_OPTIONS, PubSubError, PubSubManager,
+    VCARD4_ITEM_ID, VCARD4_NODE, avatar_data_element, avatar_metadata_element, nick_element,
+    vcard4_element,
+};
+
+use crate::RosterError;
+
+/// Our own profile as last set on this device.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    pub nickname: Option<String>,
+    pub about: Option<String>,
+    pub avatar: Option<Vec<u8>>,
+    /// SHA-1 of `avatar`, the id contacts know it by.
+    pub avatar_hash: Option<String>,
+    /// Whether the server has accepted this version of the profile.
+    pub published: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl FromRow for Profile {
+    fn from_row(row: &Row) -> Result<Self, StorageError> {
+        let text = |index: usize| match row.get(index) {
+            Some(SqlValue::Text(s)) => Some(s.clone()),
+            _ => None,
+        };
+        let avatar = match row.get(2) {
+            Some(SqlValue::Blob(bytes)) => Some(bytes.clone()),
+            _ => None,
+        };
+        let published = matches!(
+            row.get(4),
+            Some(SqlValue::Integer(1) | SqlValue::Boolean(true))
+        );
+        let updated_at = match row.get(5) {
+            Some(SqlValue::Text(s)) => DateTime::parse_from_rfc3339(s)
+                .map(|dt| dt.with_timezone(&Utc))
+                .map_err(|e| StorageError::QueryFailed(format!("invalid updated_at: {e}")))?,
+            _ => {
+                return Err(StorageError::QueryFailed(
+                    "missing updated_at column".to_string(),
+                ));
+            }
+        };
+        Ok(Profile {
+            nickname: text(0),
+            about: text(1),
+            avatar,
+            avatar_hash: text(3),
+            published,
+            updated_at,
+        })
+    }
+}
+
+/// Edits our own profile and publishes it to our PEP service.
+pub struct ProfileManager<D: Database> {
+    db: Arc<D>,
+    #[cfg(feature = "native")]
+    event_bus: Arc<dyn EventBus>,
+    /// Publishes the profile to our PEP service, once set.
+    #[cfg(feature = "native")]
+    pubsub: RwLock<Option<Arc<PubSubManager>>>,
+    #[cfg(feature = "native")]
+    connected: AtomicBool,
+}
+
+impl<D: Database> ProfileManager<D> {
+    #[cfg(feature = "native")]
+    pub fn new(db: Arc<D>, event_bus: Arc<dyn EventBus>) -> Self {
+        Self {
+            db,
+            event_bus,
+            pubsub: RwLock::new(None),
+            connected: AtomicBool::new(false),
+        }
+    }
+
+    /// Publish the profile to our PEP service through `pubsub`.
+    #[cfg(feature = "native")]
+    pub fn set_pubsub(&self, pubsub: Arc<PubSubManager>) {
+        *self.pubsub.write().unwrap() = Some(pubsub);
+    }
+
+    /// The profile as last set here, or `None` if it never was.
+    pub async fn get_profile(&self) -> Result<Option<Profile>, RosterError> {
+        Ok(Query::new(
+            "SELECT nickname, about, avatar, avatar_hash, published, updated_at \
+             FROM own_profile",
+        )
+        .fetch_optional(self.db.as_ref())
+        .await?)
+    }
+
+    /// Replace our profile. `None`, or blank text, clears that part of it,
+    /// so pass the current avatar to keep it. The profile is stored and
+    /// announced on `ui.profile.updated` first; publishing it then happens
+    /// right away while connected, or on the next connection otherwise.
+    pub async fn set_profile(
+        &self,
+        nickname: Option<&str>,
+        avatar: Option<&[u8]>,
+        about: Option<&str>,
+    ) -> Result<Profile, RosterError> {
+        let text = |value: Option<&str>| {
+            value
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(String::from)
+        };
+        let mut profile = Profile {
+            nickname: text(nickname),
+            about: text(about),
+            avatar: avatar.map(<[u8]>::to_vec),
+            avatar_hash: avatar.map(avatar_id),
+            published: false,
+            updated_at: Utc::now(),
+        };
+        Query::new(
+            "INSERT OR REPLACE INTO own_profile \
+                 (id, nickname, about, avatar, avatar_hash, published, updated_at) \
+             VALUES (1, :nickname, :about, :avatar, :avatar_hash, 0, :updated_at)",
+        )
+        .bind("nickname", &profile.nickname)
+        .bind("about", &profile.about)
+        .bind("avatar", &profile.avatar)
+        .bind("avatar_hash", &profile.avatar_hash)
+        .bind("updated_at", &profile.updated_at.to_rfc3339())
+        .execute(self.db.as_ref())
+        .await?;
+        self.emit_updated(&profile);
+
+        profile.published = self.publish(&profile).await?;
+        Ok(profile)
+    }
+
+    #[cfg(feature = "native")]
+    fn emit_updated(&self, profile: &Profile) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channels::UI_PROFILE_UPDATED).unwrap(),
+            EventSource::System("profile".into()),
+            EventPayload::ProfileUpdated {
+                nickname: profile.nickname.clone(),
+                about: profile.about.clone(),
+                avatar_hash: profile.avatar_hash.clone(),
+            },
+        ));
+    }
+
+    #[cfg(not(feature = "native"))]
+    fn emit_updated(&self, _profile: &Profile) {}
+
+    /// Publish `profile` if connected, and record that the server has it.
+    /// A failure is logged and left for the next connection to retry.
+    #[cfg(feature = "native")]
+    async fn publish(&self, profile: &Profile) -> Result<bool, RosterError> {
+        let pubsub = self.pubsub.read().unwrap().clone();
+        let Some(pubsub) = pubsub else {
+            return Ok(false);
+        };
+        if !self.connected.load(Ordering::SeqCst) {
+            return Ok(false);
+        }
+        if let Err(e) = publish_profile(&pubsub, profile).await {
+            warn!(error = %e, "failed to publish own profile");
+            return Ok(false);
+        }
+        // Only mark this version: another may have been set meanwhile.
+        Query::new("UPDATE own_profile SET published = 1 WHERE updated_at = :updated_at")
+            .bind("updated_at", &profile.updated_at.to_rfc3339())
+            .execute(self.db.as_ref())
+            .await?;
+        Ok(true)
+    }
+
+    #[cfg(not(feature = "native"))]
+    async fn publish(&self, _profile: &Profile) -> Result<bool, RosterError> {
+        Ok(false)
+    }
+
+    /// Publish the stored profile if the server does not have it yet.
+    #[cfg(feature = "native")]
+    async fn publish_pending(&self) -> Result<(), RosterError> {
+        if let Some(profile) = self.get_profile().await?
+            && !profile.published
+        {
+            self.publish(&profile).await?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "native")]
+    pub async fn handle_event(&self, event: &Event) {
+        let result = match &event.payload {
+            EventPayload::ConnectionEstablished { .. } => {
+                self.connected.store(true, Ordering::SeqCst);
+                self.publish_pending().await
+            }
+            EventPayload::ConnectionLost { .. } => {
+                self.connected.store(false, Ordering::SeqCst);
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+
+        if let Err(e) = result {
+            error!(error = %e, channel = %event.channel, "failed to publish own profile");
+        }
+    }
+
+    #[cfg(feature = "native")]
+    pub async fn run(self: Arc<Self>) -> Result<(), RosterError> {
+        let mut sub = self
+            .event_bus
+            .subscribe("system.connection.*")
+            .map_err(|e| RosterError::EventBus(e.to_string()))?;
+
+        loop {
+            match sub.recv().await {
+                Ok(event) => {
+                    self.handle_event(&event).await;
+                }
+                Err(waddle_core::error::EventBusError::ChannelClosed) => {
+                    debug!("event bus closed, profile manager stopping");
+                    return Ok(());
+                }
+                Err(waddle_core::error::EventBusError::Lagged(count)) => {
+                    warn!(count, "profile manager lagged, some events dropped");
+                }
+                Err(e) => {
+                    error!(error = %e, "profile manager subscription error");
+                    return Err(RosterError::EventBus(e.to_string()));
+                }
+            }
+        }
+    }
+}
+
+/// XEP-0084 has the image published before the metadata announcing it, so
+/// contacts never look up an avatar that is not there yet.
+#[cfg(feature = "native")]
+async fn publish_profile(pubsub: &PubSubManager, profile: &Profile) -> Result<(), PubSubError> {
+    if let (Some(avatar), Some(hash)) = (&profile.avatar, &profile.avatar_hash) {
+        pubsub
+            .publish(
+                None,
+                AVATAR_DATA_NODE,
+                Some(hash),
+                avatar_data_element(avatar),
+                PROFILE_OPTIONS,
+            )
+            .await?;
+    }
+    pubsub
+        .publish(
+            None,
+            AVATAR_METADATA_NODE,
+            profile.avatar_hash.as_deref(),
+            avatar_metadata_element(profile.avatar.as_deref()),
+            PROFILE_OPTIONS,
+        )
+        .await?;
+    pubsub
+        .publish(
+            None,
+            NICK_NODE,
+            None,
+            nick_element(profile.nickname.as_deref()),
+            PROFILE_OPTIONS,
+        )
+        .await?;
+    pubsub
+        .publish(
+            None,
+            VCARD4_NODE,
+            Some(VCARD4_ITEM_ID),
+            vcard4_element(profile.nickname.as_deref(), profile.about.as_deref()),
+            PROFILE_OPTIONS,
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use waddle_core::event::BroadcastEventBus;
+
+    #[tokio::test]
+    async fn set_profile_is_stored_and_announced_until_published() {
+        let dir = TempDir::new().expect("failed to create temp dir");
+        let db = Arc::new(
+            waddle_storage::open_database(&dir.path().join("test.db"))
+                .await
+                .expect("failed to open database"),
+        );
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let manager = ProfileManager::new(db, event_bus.clone());
+        let mut sub = event_bus.subscribe("ui.profile.updated").unwrap();
+
+        assert_eq!(manager.get_profile().await.unwrap(), None);
+
+        let avatar = b"\x89PNG\r\n\x1a\navatar";
+        let profile = manager
+            .set_profile(Some(" Alice "), Some(avatar), Some(""))
+            .await
+            .unwrap();
+        assert_eq!(profile.nickname.as_deref(), Some("Alice"));
+        assert_eq!(profile.about, None);
+        assert_eq!(profile.avatar_hash, Some(avatar_id(avatar)));
+        // Not connected, so it waits for the next connection.
+        assert!(!profile.published);
+        assert_eq!(manager.get_profile().await.unwrap(), Some(profile.clone()));
+
+        let event = sub.recv().await.unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::ProfileUpdated { ref nickname, ref about, ref avatar_hash }
+                if nickname.as_deref() == Some("Alice")
+                    && about.is_none()
+                    && *avatar_hash == profile.avatar_hash
+        ));
+
+        let cleared = manager
+            .set_profile(Some("Alice"), None, Some("Out sailing"))
+            .await
+            .unwrap();
+        assert_eq!(cleared.avatar, None);
+        assert_eq!(cleared.avatar_hash, None);
+        let stored = manager.get_profile().await.unwrap().unwrap();
+        assert_eq!(stored.about.as_deref(), Some("Out sailing"));
+    }
+}
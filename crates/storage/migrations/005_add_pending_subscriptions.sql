@@ -0,0 +1,5 @@
+-- Migration: Persist inbound subscription requests awaiting a user decision
+CREATE TABLE IF NOT EXISTS pending_subscriptions (
+    jid TEXT PRIMARY KEY,
+    received_at TEXT NOT NULL
+);
@@ -0,0 +1,10 @@
+-- Migration: Keep every MUC subject change with its setter and time
+CREATE TABLE IF NOT EXISTS muc_subject_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    room_jid TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    set_by TEXT,
+    changed_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_muc_subject_history_room ON muc_subject_history(room_jid, id);
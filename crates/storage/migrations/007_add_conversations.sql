@@ -0,0 +1,14 @@
+-- Migration: Conversation list state for 1:1 chats and MUC rooms
+CREATE TABLE IF NOT EXISTS conversations (
+    jid TEXT PRIMARY KEY,
+    kind TEXT NOT NULL,
+    last_message_id TEXT,
+    last_message_preview TEXT,
+    last_message_from TEXT,
+    last_activity TEXT,
+    unread_count INTEGER NOT NULL DEFAULT 0,
+    pinned INTEGER NOT NULL DEFAULT 0,
+    archived INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS idx_conversations_activity ON conversations(pinned, last_activity);
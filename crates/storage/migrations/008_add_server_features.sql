@@ -0,0 +1,10 @@
+-- Migration: Last known server features per account, for regression alerts
+CREATE TABLE IF NOT EXISTS server_features (
+    account_jid TEXT NOT NULL,
+    feature TEXT NOT NULL,
+    present INTEGER NOT NULL,
+    upload_max_size INTEGER,
+    last_seen_at TEXT,
+    checked_at TEXT NOT NULL,
+    PRIMARY KEY (account_jid, feature)
+);
@@ -0,0 +1,46 @@
+-- Migration: OMEMO (XEP-0384) identity, pre-keys, known devices and sessions.
+-- Private keys and session state are encrypted by waddle-omemo before they
+-- are written; public keys are stored as-is.
+CREATE TABLE IF NOT EXISTS omemo_identity (
+    account_jid TEXT PRIMARY KEY,
+    device_id INTEGER NOT NULL,
+    key_pair BLOB NOT NULL,
+    next_pre_key_id INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS omemo_signed_pre_keys (
+    account_jid TEXT NOT NULL,
+    id INTEGER NOT NULL,
+    key_pair BLOB NOT NULL,
+    signature BLOB NOT NULL,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (account_jid, id)
+);
+
+CREATE TABLE IF NOT EXISTS omemo_pre_keys (
+    account_jid TEXT NOT NULL,
+    id INTEGER NOT NULL,
+    key_pair BLOB NOT NULL,
+    PRIMARY KEY (account_jid, id)
+);
+
+CREATE TABLE IF NOT EXISTS omemo_devices (
+    account_jid TEXT NOT NULL,
+    jid TEXT NOT NULL,
+    device_id INTEGER NOT NULL,
+    identity_key BLOB,
+    trust TEXT NOT NULL DEFAULT 'undecided',
+    active INTEGER NOT NULL DEFAULT 1,
+    last_seen_at TEXT NOT NULL,
+    PRIMARY KEY (account_jid, jid, device_id)
+);
+
+CREATE TABLE IF NOT EXISTS omemo_sessions (
+    account_jid TEXT NOT NULL,
+    jid TEXT NOT NULL,
+    device_id INTEGER NOT NULL,
+    state BLOB NOT NULL,
+    updated_at TEXT NOT NULL,
+    PRIMARY KEY (account_jid, jid, device_id)
+);
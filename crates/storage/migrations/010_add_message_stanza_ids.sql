@@ -0,0 +1,6 @@
+-- Migration: XEP-0359 origin and stanza ids for duplicate suppression
+ALTER TABLE messages ADD COLUMN origin_id TEXT;
+ALTER TABLE messages ADD COLUMN stanza_id TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_messages_origin_id ON messages(origin_id);
+CREATE INDEX IF NOT EXISTS idx_messages_stanza_id ON messages(stanza_id);
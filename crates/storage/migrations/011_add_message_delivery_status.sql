@@ -0,0 +1,2 @@
+-- Migration: delivery state of outgoing messages (pending, sent, delivered, displayed)
+ALTER TABLE messages ADD COLUMN delivery_status TEXT;
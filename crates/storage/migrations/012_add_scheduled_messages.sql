@@ -0,0 +1,10 @@
+-- Migration: outgoing messages held back until their scheduled send time
+CREATE TABLE IF NOT EXISTS scheduled_messages (
+    id TEXT PRIMARY KEY,
+    to_jid TEXT NOT NULL,
+    body TEXT NOT NULL,
+    send_at TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_scheduled_messages_send_at ON scheduled_messages(send_at);
@@ -0,0 +1,2 @@
+-- Migration: flag room messages that mention the local user
+ALTER TABLE messages ADD COLUMN mentions INTEGER NOT NULL DEFAULT 0;
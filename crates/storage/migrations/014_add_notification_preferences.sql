@@ -0,0 +1,6 @@
+-- Migration: per-conversation notification preferences
+CREATE TABLE IF NOT EXISTS notification_preferences (
+    jid TEXT PRIMARY KEY,
+    level TEXT NOT NULL,
+    muted_until TEXT
+);
@@ -0,0 +1,167 @@
+-- Migration: case-fold the localpart and domainpart of every stored JID so
+-- that rows written before normalization match the keys used now. SQLite's
+-- lower() only folds ASCII; anything it misses is left for the
+-- merge-duplicates maintenance pass. Resources keep their case.
+
+-- Plain references: rewrite in place.
+UPDATE messages SET from_jid = CASE WHEN instr(from_jid, '/') > 0
+        THEN lower(substr(from_jid, 1, instr(from_jid, '/') - 1)) || substr(from_jid, instr(from_jid, '/'))
+        ELSE lower(from_jid) END
+WHERE from_jid IS NOT NULL AND from_jid != lower(from_jid);
+
+UPDATE messages SET to_jid = CASE WHEN instr(to_jid, '/') > 0
+        THEN lower(substr(to_jid, 1, instr(to_jid, '/') - 1)) || substr(to_jid, instr(to_jid, '/'))
+        ELSE lower(to_jid) END
+WHERE to_jid IS NOT NULL AND to_jid != lower(to_jid);
+
+UPDATE muc_subject_history SET room_jid = CASE WHEN instr(room_jid, '/') > 0
+        THEN lower(substr(room_jid, 1, instr(room_jid, '/') - 1)) || substr(room_jid, instr(room_jid, '/'))
+        ELSE lower(room_jid) END
+WHERE room_jid IS NOT NULL AND room_jid != lower(room_jid);
+
+UPDATE conversations SET last_message_from = CASE WHEN instr(last_message_from, '/') > 0
+        THEN lower(substr(last_message_from, 1, instr(last_message_from, '/') - 1)) || substr(last_message_from, instr(last_message_from, '/'))
+        ELSE lower(last_message_from) END
+WHERE last_message_from IS NOT NULL AND last_message_from != lower(last_message_from);
+
+UPDATE scheduled_messages SET to_jid = CASE WHEN instr(to_jid, '/') > 0
+        THEN lower(substr(to_jid, 1, instr(to_jid, '/') - 1)) || substr(to_jid, instr(to_jid, '/'))
+        ELSE lower(to_jid) END
+WHERE to_jid IS NOT NULL AND to_jid != lower(to_jid);
+
+-- Conversations: make sure a canonical row exists for every variant, taking
+-- the preview from the most recent one, then fold unread counts and flags
+-- into it the same way merge-duplicates does.
+INSERT OR IGNORE INTO conversations
+    (jid, kind, last_message_id, last_message_preview, last_message_from,
+     last_activity, unread_count, pinned, archived)
+SELECT lower(jid), kind, last_message_id, last_message_preview, last_message_from,
+       last_activity, 0, 0, 1
+FROM conversations
+WHERE jid != lower(jid)
+ORDER BY last_activity DESC;
+
+UPDATE conversations SET
+    unread_count = unread_count + (
+        SELECT SUM(v.unread_count) FROM conversations v
+        WHERE lower(v.jid) = conversations.jid AND v.jid != conversations.jid),
+    pinned = MAX(pinned, (
+        SELECT MAX(v.pinned) FROM conversations v
+        WHERE lower(v.jid) = conversations.jid AND v.jid != conversations.jid)),
+    archived = MIN(archived, (
+        SELECT MIN(v.archived) FROM conversations v
+        WHERE lower(v.jid) = conversations.jid AND v.jid != conversations.jid)),
+    kind = CASE WHEN EXISTS (
+        SELECT 1 FROM conversations v
+        WHERE lower(v.jid) = conversations.jid AND v.kind = 'groupchat')
+        THEN 'groupchat' ELSE kind END
+WHERE jid = lower(jid) AND EXISTS (
+    SELECT 1 FROM conversations v
+    WHERE lower(v.jid) = conversations.jid AND v.jid != conversations.jid);
+
+DELETE FROM conversations WHERE jid != lower(jid);
+
+-- Keyed tables: when the folded key already exists, that row is kept.
+UPDATE OR IGNORE roster SET jid = CASE WHEN instr(jid, '/') > 0
+        THEN lower(substr(jid, 1, instr(jid, '/') - 1)) || substr(jid, instr(jid, '/'))
+        ELSE lower(jid) END
+WHERE jid != lower(jid);
+DELETE FROM roster WHERE jid != CASE WHEN instr(jid, '/') > 0
+        THEN lower(substr(jid, 1, instr(jid, '/') - 1)) || substr(jid, instr(jid, '/'))
+        ELSE lower(jid) END;
+
+UPDATE OR IGNORE pending_subscriptions SET jid = CASE WHEN instr(jid, '/') > 0
+        THEN lower(substr(jid, 1, instr(jid, '/') - 1)) || substr(jid, instr(jid, '/'))
+        ELSE lower(jid) END
+WHERE jid != lower(jid);
+DELETE FROM pending_subscriptions WHERE jid != CASE WHEN instr(jid, '/') > 0
+        THEN lower(substr(jid, 1, instr(jid, '/') - 1)) || substr(jid, instr(jid, '/'))
+        ELSE lower(jid) END;
+
+UPDATE OR IGNORE muc_rooms SET room_jid = CASE WHEN instr(room_jid, '/') > 0
+        THEN lower(substr(room_jid, 1, instr(room_jid, '/') - 1)) || substr(room_jid, instr(room_jid, '/'))
+        ELSE lower(room_jid) END
+WHERE room_jid != lower(room_jid);
+DELETE FROM muc_rooms WHERE room_jid != CASE WHEN instr(room_jid, '/') > 0
+        THEN lower(substr(room_jid, 1, instr(room_jid, '/') - 1)) || substr(room_jid, instr(room_jid, '/'))
+        ELSE lower(room_jid) END;
+
+UPDATE OR IGNORE mam_sync_state SET jid = CASE WHEN instr(jid, '/') > 0
+        THEN lower(substr(jid, 1, instr(jid, '/') - 1)) || substr(jid, instr(jid, '/'))
+        ELSE lower(jid) END
+WHERE jid != lower(jid);
+DELETE FROM mam_sync_state WHERE jid != CASE WHEN instr(jid, '/') > 0
+        THEN lower(substr(jid, 1, instr(jid, '/') - 1)) || substr(jid, instr(jid, '/'))
+        ELSE lower(jid) END;
+
+UPDATE OR IGNORE notification_preferences SET jid = CASE WHEN instr(jid, '/') > 0
+        THEN lower(substr(jid, 1, instr(jid, '/') - 1)) || substr(jid, instr(jid, '/'))
+        ELSE lower(jid) END
+WHERE jid != lower(jid);
+DELETE FROM notification_preferences WHERE jid != CASE WHEN instr(jid, '/') > 0
+        THEN lower(substr(jid, 1, instr(jid, '/') - 1)) || substr(jid, instr(jid, '/'))
+        ELSE lower(jid) END;
+
+UPDATE OR IGNORE server_features SET account_jid = CASE WHEN instr(account_jid, '/') > 0
+        THEN lower(substr(account_jid, 1, instr(account_jid, '/') - 1)) || substr(account_jid, instr(account_jid, '/'))
+        ELSE lower(account_jid) END
+WHERE account_jid != lower(account_jid);
+DELETE FROM server_features WHERE account_jid != CASE WHEN instr(account_jid, '/') > 0
+        THEN lower(substr(account_jid, 1, instr(account_jid, '/') - 1)) || substr(account_jid, instr(account_jid, '/'))
+        ELSE lower(account_jid) END;
+
+UPDATE OR IGNORE omemo_identity SET account_jid = CASE WHEN instr(account_jid, '/') > 0
+        THEN lower(substr(account_jid, 1, instr(account_jid, '/') - 1)) || substr(account_jid, instr(account_jid, '/'))
+        ELSE lower(account_jid) END
+WHERE account_jid != lower(account_jid);
+DELETE FROM omemo_identity WHERE account_jid != CASE WHEN instr(account_jid, '/') > 0
+        THEN lower(substr(account_jid, 1, instr(account_jid, '/') - 1)) || substr(account_jid, instr(account_jid, '/'))
+        ELSE lower(account_jid) END;
+
+UPDATE OR IGNORE omemo_signed_pre_keys SET account_jid = CASE WHEN instr(account_jid, '/') > 0
+        THEN lower(substr(account_jid, 1, instr(account_jid, '/') - 1)) || substr(account_jid, instr(account_jid, '/'))
+        ELSE lower(account_jid) END
+WHERE account_jid != lower(account_jid);
+DELETE FROM omemo_signed_pre_keys WHERE account_jid != CASE WHEN instr(account_jid, '/') > 0
+        THEN lower(substr(account_jid, 1, instr(account_jid, '/') - 1)) || substr(account_jid, instr(account_jid, '/'))
+        ELSE lower(account_jid) END;
+
+UPDATE OR IGNORE omemo_pre_keys SET account_jid = CASE WHEN instr(account_jid, '/') > 0
+        THEN lower(substr(account_jid, 1, instr(account_jid, '/') - 1)) || substr(account_jid, instr(account_jid, '/'))
+        ELSE lower(account_jid) END
+WHERE account_jid != lower(account_jid);
+DELETE FROM omemo_pre_keys WHERE account_jid != CASE WHEN instr(account_jid, '/') > 0
+        THEN lower(substr(account_jid, 1, instr(account_jid, '/') - 1)) || substr(account_jid, instr(account_jid, '/'))
+        ELSE lower(account_jid) END;
+
+UPDATE OR IGNORE omemo_devices SET account_jid = CASE WHEN instr(account_jid, '/') > 0
+        THEN lower(substr(account_jid, 1, instr(account_jid, '/') - 1)) || substr(account_jid, instr(account_jid, '/'))
+        ELSE lower(account_jid) END
+WHERE account_jid != lower(account_jid);
+DELETE FROM omemo_devices WHERE account_jid != CASE WHEN instr(account_jid, '/') > 0
+        THEN lower(substr(account_jid, 1, instr(account_jid, '/') - 1)) || substr(account_jid, instr(account_jid, '/'))
+        ELSE lower(account_jid) END;
+
+UPDATE OR IGNORE omemo_devices SET jid = CASE WHEN instr(jid, '/') > 0
+        THEN lower(substr(jid, 1, instr(jid, '/') - 1)) || substr(jid, instr(jid, '/'))
+        ELSE lower(jid) END
+WHERE jid != lower(jid);
+DELETE FROM omemo_devices WHERE jid != CASE WHEN instr(jid, '/') > 0
+        THEN lower(substr(jid, 1, instr(jid, '/') - 1)) || substr(jid, instr(jid, '/'))
+        ELSE lower(jid) END;
+
+UPDATE OR IGNORE omemo_sessions SET account_jid = CASE WHEN instr(account_jid, '/') > 0
+        THEN lower(substr(account_jid, 1, instr(account_jid, '/') - 1)) || substr(account_jid, instr(account_jid, '/'))
+        ELSE lower(account_jid) END
+WHERE account_jid != lower(account_jid);
+DELETE FROM omemo_sessions WHERE account_jid != CASE WHEN instr(account_jid, '/') > 0
+        THEN lower(substr(account_jid, 1, instr(account_jid, '/') - 1)) || substr(account_jid, instr(account_jid, '/'))
+        ELSE lower(account_jid) END;
+
+UPDATE OR IGNORE omemo_sessions SET jid = CASE WHEN instr(jid, '/') > 0
+        THEN lower(substr(jid, 1, instr(jid, '/') - 1)) || substr(jid, instr(jid, '/'))
+        ELSE lower(jid) END
+WHERE jid != lower(jid);
+DELETE FROM omemo_sessions WHERE jid != CASE WHEN instr(jid, '/') > 0
+        THEN lower(substr(jid, 1, instr(jid, '/') - 1)) || substr(jid, instr(jid, '/'))
+        ELSE lower(jid) END;
@@ -0,0 +1,9 @@
+-- Migration: preview cards for links shared in messages, fetched once per URL
+CREATE TABLE IF NOT EXISTS link_previews (
+    url TEXT PRIMARY KEY,
+    title TEXT,
+    description TEXT,
+    image_url TEXT,
+    site_name TEXT,
+    fetched_at TEXT NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Migration: senders reported for spam or abuse (XEP-0377), hidden by the UI
+CREATE TABLE IF NOT EXISTS reported_contacts (
+    jid TEXT PRIMARY KEY,
+    reason TEXT NOT NULL,
+    reported_at TEXT NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Migration: tombstones for retracted and moderated messages (XEP-0424, XEP-0425)
+ALTER TABLE messages ADD COLUMN retracted_at TEXT;
@@ -0,0 +1,8 @@
+-- Migration: the newest displayed marker (XEP-0333) per chat, from any of
+-- our devices. read_up_to is the timestamp of the marked message.
+CREATE TABLE IF NOT EXISTS read_markers (
+    conversation TEXT PRIMARY KEY,
+    message_id TEXT NOT NULL,
+    read_up_to TEXT NOT NULL,
+    marked_at TEXT NOT NULL
+);
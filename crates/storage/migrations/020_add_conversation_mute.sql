@@ -0,0 +1,4 @@
+-- Migration: muted conversations. muted_until is NULL for a mute that lasts
+-- until the user lifts it.
+ALTER TABLE conversations ADD COLUMN muted INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE conversations ADD COLUMN muted_until TEXT;
@@ -0,0 +1,11 @@
+-- Migration: user-defined automation rules. The trigger and actions are
+-- kept as the JSON the event bus carries.
+CREATE TABLE IF NOT EXISTS rules (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    enabled INTEGER NOT NULL DEFAULT 1,
+    trigger_json TEXT NOT NULL,
+    actions_json TEXT NOT NULL,
+    cooldown_secs INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL
+);
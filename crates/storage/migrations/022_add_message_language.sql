@@ -0,0 +1,9 @@
+-- Migration: the language of each message body, and translations of bodies
+-- into the user's language. A translation may be stored before its message.
+ALTER TABLE messages ADD COLUMN lang TEXT;
+
+CREATE TABLE IF NOT EXISTS message_translations (
+    message_id TEXT PRIMARY KEY,
+    lang TEXT NOT NULL,
+    body TEXT NOT NULL
+);
@@ -0,0 +1,9 @@
+-- Migration: Keep MUC invitations until the user accepts or declines them
+CREATE TABLE IF NOT EXISTS muc_invites (
+    room_jid TEXT PRIMARY KEY,
+    from_jid TEXT NOT NULL,
+    reason TEXT,
+    password TEXT,
+    mediated INTEGER NOT NULL DEFAULT 0,
+    received_at TEXT NOT NULL
+);
@@ -0,0 +1,10 @@
+-- Migration: spans of each archive stored without holes, keyed like
+-- mam_sync_state. Two ranges for one jid leave a gap between them.
+CREATE TABLE IF NOT EXISTS mam_sync_ranges (
+    jid TEXT NOT NULL,
+    first_id TEXT NOT NULL,
+    first_at TEXT NOT NULL,
+    last_id TEXT NOT NULL,
+    last_at TEXT NOT NULL,
+    PRIMARY KEY (jid, first_id)
+);
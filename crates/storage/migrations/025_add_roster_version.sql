@@ -0,0 +1,6 @@
+-- Migration: RFC 6121 roster version the stored roster is current as of.
+-- At most one row; no row means the next fetch asks for the full roster.
+CREATE TABLE IF NOT EXISTS roster_version (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    ver TEXT NOT NULL
+);
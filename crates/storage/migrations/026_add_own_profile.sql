@@ -0,0 +1,12 @@
+-- Migration: our own profile as last set, shown while offline and
+-- published again when setting it did not reach the server.
+-- At most one row; no row means the profile was never set here.
+CREATE TABLE IF NOT EXISTS own_profile (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    nickname TEXT,
+    about TEXT,
+    avatar BLOB,
+    avatar_hash TEXT,
+    published INTEGER NOT NULL DEFAULT 0,
+    updated_at TEXT NOT NULL
+);
@@ -0,0 +1,375 @@
+//! Online snapshots of the native database, their rotation, and restoring
+//! from them.
+//!
+//! Snapshots use SQLite's backup API, so they are consistent while the
+//! application keeps writing. Each one is written under a temporary name and
+//! renamed into place, so an interrupted backup never leaves a truncated
+//! snapshot among the rotations.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rusqlite::Connection;
+use rusqlite::backup::Backup;
+use tokio::{sync::oneshot, task};
+use tracing::{error, info};
+
+use waddle_core::config::BackupConfig;
+use waddle_core::event::{Channel, Event, EventBus, EventPayload, EventSource, channels};
+
+use crate::{
+    ConnectionOptions, NativeDatabase, StorageError, WriteCommand, open_keyed_connection,
+    run_migrations,
+};
+
+const BACKUP_PREFIX: &str = "waddle-";
+const BACKUP_SUFFIX: &str = ".db";
+const BACKUP_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+/// Pages copied per backup step. Writers can interleave between steps.
+const PAGES_PER_STEP: i32 = 256;
+const STEP_PAUSE: Duration = Duration::from_millis(5);
+
+impl NativeDatabase {
+    /// Write a consistent snapshot of the database to `destination`,
+    /// replacing any file already there. Returns the snapshot size in bytes.
+    /// Snapshots of an encrypted database are encrypted with the same key.
+    pub async fn backup_to(&self, destination: &Path) -> Result<u64, StorageError> {
+        let source = self.path.clone();
+        let options = self.options.clone();
+        let target = destination.to_path_buf();
+
+        task::spawn_blocking(move || snapshot(&source, &target, &options))
+            .await
+            .map_err(|error| {
+                backup_failed(destination, format!("failed to join backup task: {error}"))
+            })?
+    }
+
+    /// Replace the database contents with the snapshot at `source`, then
+    /// apply any migrations the snapshot predates. The restore runs on the
+    /// writer, so no other write interleaves with it.
+    pub async fn restore_from_backup(&self, source: &Path) -> Result<(), StorageError> {
+        let (response_tx, response_rx) = oneshot::channel();
+        let command = WriteCommand::Restore {
+            source: source.to_path_buf(),
+            response: response_tx,
+        };
+
+        self.writer.send(command).map_err(|_| {
+            StorageError::QueryFailed("storage writer task is unavailable".to_string())
+        })?;
+
+        response_rx.await.map_err(|_| {
+            StorageError::QueryFailed(
+                "storage writer task terminated before responding".to_string(),
+            )
+        })?
+    }
+}
+
+fn snapshot(
+    source: &Path,
+    destination: &Path,
+    options: &ConnectionOptions,
+) -> Result<u64, StorageError> {
+    let partial = destination.with_extension("partial");
+    let source = open_keyed_connection(source, options)?;
+    {
+        let mut target = open_keyed_connection(&partial, options)?;
+        copy_database(&source, &mut target).map_err(|error| backup_failed(destination, error))?;
+    }
+
+    std::fs::rename(&partial, destination)
+        .and_then(|()| std::fs::metadata(destination))
+        .map(|metadata| metadata.len())
+        .map_err(|error| backup_failed(destination, error.to_string()))
+}
+
+/// Copy the snapshot at `source` over the writer's connection.
+pub(crate) fn restore_into(
+    connection: &mut Connection,
+    source: &Path,
+    options: &ConnectionOptions,
+) -> Result<(), StorageError> {
+    if !source.is_file() {
+        return Err(backup_failed(source, "no backup exists at this path"));
+    }
+
+    let backup = open_keyed_connection(source, options)?;
+    let check: String = backup
+        .query_row("PRAGMA quick_check", [], |row| row.get(0))
+        .map_err(|error| backup_failed(source, error.to_string()))?;
+    if check != "ok" {
+        return Err(backup_failed(source, format!("backup is corrupt: {check}")));
+    }
+
+    copy_database(&backup, connection).map_err(|error| backup_failed(source, error))?;
+    info!(path = %source.display(), "restored database from backup");
+    run_migrations(connection)
+}
+
+fn copy_database(source: &Connection, target: &mut Connection) -> Result<(), String> {
+    Backup::new(source, target)
+        .and_then(|backup| backup.run_to_completion(PAGES_PER_STEP, STEP_PAUSE, None))
+        .map_err(|error| error.to_string())
+}
+
+fn backup_failed(path: &Path, reason: impl Into<String>) -> StorageError {
+    StorageError::BackupFailed {
+        path: path.to_path_buf(),
+        reason: reason.into(),
+    }
+}
+
+/// Takes a snapshot every `interval_secs` into the backup directory and
+/// keeps the newest `keep` of them.
+pub struct BackupManager {
+    db: Arc<NativeDatabase>,
+    event_bus: Arc<dyn EventBus>,
+    directory: PathBuf,
+    interval: Duration,
+    keep: usize,
+}
+
+impl BackupManager {
+    pub fn new(
+        db: Arc<NativeDatabase>,
+        event_bus: Arc<dyn EventBus>,
+        directory: PathBuf,
+        config: &BackupConfig,
+    ) -> Self {
+        Self {
+            db,
+            event_bus,
+            directory,
+            interval: Duration::from_secs(config.interval_secs),
+            keep: config.keep.max(1),
+        }
+    }
+
+    /// Snapshot the database now and delete rotations beyond `keep`.
+    /// Publishes `system.backup.completed` and returns the new snapshot.
+    pub async fn backup_now(&self) -> Result<PathBuf, StorageError> {
+        let name = format!(
+            "{BACKUP_PREFIX}{}{BACKUP_SUFFIX}",
+            Utc::now().format(BACKUP_TIMESTAMP_FORMAT)
+        );
+        let path = self.directory.join(name);
+        let size_bytes = self.db.backup_to(&path).await?;
+        let removed = self.rotate()?;
+
+        info!(
+            path = %path.display(),
+            size_bytes,
+            removed = removed.len(),
+            "database backup completed"
+        );
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channels::SYSTEM_BACKUP_COMPLETED).unwrap(),
+            EventSource::System("backup".into()),
+            EventPayload::BackupCompleted {
+                path: path.display().to_string(),
+                size_bytes,
+                removed: removed
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect(),
+            },
+        ));
+        Ok(path)
+    }
+
+    /// Restore the database from one of [`BackupManager::list_backups`] (or
+    /// any other snapshot). See [`NativeDatabase::restore_from_backup`].
+    pub async fn restore_from_backup(&self, path: &Path) -> Result<(), StorageError> {
+        self.db.restore_from_backup(path).await
+    }
+
+    /// Snapshots in the backup directory, newest first.
+    pub fn list_backups(&self) -> Result<Vec<PathBuf>, StorageError> {
+        let entries = match std::fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(backup_failed(&self.directory, error.to_string())),
+        };
+
+        let mut backups: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| backup_time(path).is_some())
+            .collect();
+        // The timestamp format sorts lexically in time order.
+        backups.sort_by(|a, b| b.cmp(a));
+        Ok(backups)
+    }
+
+    fn rotate(&self) -> Result<Vec<PathBuf>, StorageError> {
+        let expired: Vec<PathBuf> = self.list_backups()?.into_iter().skip(self.keep).collect();
+        for path in &expired {
+            std::fs::remove_file(path).map_err(|error| backup_failed(path, error.to_string()))?;
+        }
+        Ok(expired)
+    }
+
+    /// Time until the next snapshot is due, counted from the newest existing
+    /// one so restarts do not postpone backups indefinitely.
+    fn time_until_due(&self) -> Duration {
+        let latest = self
+            .list_backups()
+            .ok()
+            .and_then(|backups| backups.first().and_then(|path| backup_time(path)));
+        let Some(latest) = latest else {
+            return Duration::ZERO;
+        };
+
+        let interval = chrono::Duration::from_std(self.interval).unwrap_or(chrono::Duration::MAX);
+        (latest + interval - Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO)
+    }
+
+    pub async fn run(self: Arc<Self>) -> Result<(), StorageError> {
+        loop {
+            tokio::time::sleep(self.time_until_due()).await;
+            if let Err(e) = self.backup_now().await {
+                error!(error = %e, "database backup failed");
+                let _ = self.event_bus.publish(Event::new(
+                    Channel::new(channels::SYSTEM_ERROR_OCCURRED).unwrap(),
+                    EventSource::System("backup".into()),
+                    EventPayload::ErrorOccurred {
+                        component: "backup".to_string(),
+                        message: e.to_string(),
+                        recoverable: true,
+                    },
+                ));
+                tokio::time::sleep(self.interval).await;
+            }
+        }
+    }
+}
+
+fn backup_time(path: &Path) -> Option<DateTime<Utc>> {
+    let timestamp = path
+        .file_name()?
+        .to_str()?
+        .strip_prefix(BACKUP_PREFIX)?
+        .strip_suffix(BACKUP_SUFFIX)?;
+    NaiveDateTime::parse_from_str(timestamp, BACKUP_TIMESTAMP_FORMAT)
+        .ok()
+        .map(|time| time.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Database, Row, SqlValue};
+    use tempfile::TempDir;
+    use waddle_core::event::BroadcastEventBus;
+
+    async fn open_temp_db(dir: &TempDir) -> Arc<NativeDatabase> {
+        Arc::new(
+            crate::open_native_database(&dir.path().join("waddle.db"))
+                .await
+                .expect("failed to open database"),
+        )
+    }
+
+    async fn roster_jids(db: &NativeDatabase) -> Vec<String> {
+        let rows: Vec<Row> = db
+            .query("SELECT jid FROM roster ORDER BY jid", &[])
+            .await
+            .unwrap();
+        rows.iter()
+            .filter_map(|row| match row.get(0) {
+                Some(SqlValue::Text(jid)) => Some(jid.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    async fn add_contact(db: &NativeDatabase, jid: &str) {
+        db.execute(
+            "INSERT INTO roster (jid, subscription) VALUES (?1, 'both')",
+            &[&jid.to_string()],
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn restore_brings_back_the_snapshot_contents() {
+        let dir = TempDir::new().unwrap();
+        let db = open_temp_db(&dir).await;
+        add_contact(&db, "alice@example.com").await;
+
+        let snapshot = dir.path().join("snapshot.db");
+        assert!(db.backup_to(&snapshot).await.unwrap() > 0);
+
+        add_contact(&db, "bob@example.com").await;
+        db.restore_from_backup(&snapshot).await.unwrap();
+
+        assert_eq!(roster_jids(&db).await, vec!["alice@example.com"]);
+        add_contact(&db, "carol@example.com").await;
+        assert_eq!(roster_jids(&db).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn restore_rejects_missing_and_corrupt_backups() {
+        let dir = TempDir::new().unwrap();
+        let db = open_temp_db(&dir).await;
+        add_contact(&db, "alice@example.com").await;
+
+        let missing = db.restore_from_backup(&dir.path().join("missing.db")).await;
+        assert!(matches!(missing, Err(StorageError::BackupFailed { .. })));
+
+        let garbage = dir.path().join("garbage.db");
+        std::fs::write(&garbage, vec![0x42; 4096]).unwrap();
+        assert!(db.restore_from_backup(&garbage).await.is_err());
+
+        assert_eq!(roster_jids(&db).await, vec!["alice@example.com"]);
+    }
+
+    #[tokio::test]
+    async fn backups_rotate_and_announce_completion() {
+        let dir = TempDir::new().unwrap();
+        let db = open_temp_db(&dir).await;
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let mut sub = event_bus.subscribe("system.backup.*").unwrap();
+        let config = BackupConfig {
+            enabled: true,
+            keep: 2,
+            ..BackupConfig::default()
+        };
+        let manager = BackupManager::new(db, event_bus, dir.path().join("backups"), &config);
+        assert_eq!(manager.time_until_due(), Duration::ZERO);
+
+        let mut created = Vec::new();
+        for _ in 0..3 {
+            created.push(manager.backup_now().await.unwrap());
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        assert_eq!(
+            manager.list_backups().unwrap(),
+            vec![created[2].clone(), created[1].clone()]
+        );
+        assert!(manager.time_until_due() > Duration::from_secs(86_000));
+
+        let mut last = None;
+        while let Ok(Ok(event)) = tokio::time::timeout(Duration::from_millis(50), sub.recv()).await
+        {
+            last = Some(event);
+        }
+        let event = last.expect("expected backup events");
+        assert_eq!(event.channel.as_str(), "system.backup.completed");
+        match event.payload {
+            EventPayload::BackupCompleted { path, removed, .. } => {
+                assert_eq!(path, created[2].display().to_string());
+                assert_eq!(removed, vec![created[0].display().to_string()]);
+            }
+            other => panic!("expected BackupCompleted, got {other:?}"),
+        }
+    }
+}
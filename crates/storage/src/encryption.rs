@@ -0,0 +1,235 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rusqlite::{Connection, params};
+use tokio::task;
+use tracing::info;
+
+use crate::{ConnectionOptions, NativeDatabase, StorageError, open_connection};
+
+const PLAINTEXT_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+/// Key material for an SQLCipher database.
+///
+/// Passphrases are stretched by SQLCipher itself (PBKDF2-HMAC-SHA512 with a
+/// per-database salt). Raw keys skip derivation and are meant for random keys
+/// held in an OS keyring.
+#[derive(Clone)]
+pub enum DatabaseKey {
+    Passphrase(String),
+    Raw([u8; 32]),
+}
+
+impl DatabaseKey {
+    /// The value SQLCipher expects in `PRAGMA key` and `ATTACH ... KEY`.
+    fn sqlcipher_value(&self) -> String {
+        match self {
+            Self::Passphrase(passphrase) => passphrase.clone(),
+            Self::Raw(bytes) => {
+                let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+                format!("x'{hex}'")
+            }
+        }
+    }
+}
+
+impl fmt::Debug for DatabaseKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Passphrase(_) => f.write_str("DatabaseKey::Passphrase(..)"),
+            Self::Raw(_) => f.write_str("DatabaseKey::Raw(..)"),
+        }
+    }
+}
+
+/// Open an SQLCipher-encrypted database, creating it if missing. An existing
+/// plaintext database at `path` is encrypted in place first, once.
+pub async fn open_database_encrypted(
+    path: &Path,
+    key: DatabaseKey,
+) -> Result<NativeDatabase, StorageError> {
+    let key = Arc::new(key);
+    let setup_path = path.to_path_buf();
+    let setup_key = key.clone();
+
+    task::spawn_blocking(move || {
+        if is_plaintext(&setup_path)? {
+            encrypt_in_place(&setup_path, &setup_key)?;
+        }
+        Ok::<_, StorageError>(())
+    })
+    .await
+    .map_err(|error| StorageError::ConnectionFailed {
+        path: path.to_path_buf(),
+        reason: format!("failed to join encryption setup task: {error}"),
+    })??;
+
+    NativeDatabase::open_with(path, ConnectionOptions { key: Some(key) }).await
+}
+
+/// Key a freshly opened connection and check that the key decrypts the file.
+pub(crate) fn apply_key(
+    connection: &Connection,
+    path: &Path,
+    key: &DatabaseKey,
+) -> Result<(), StorageError> {
+    connection
+        .pragma_update(None, "key", key.sqlcipher_value())
+        .map_err(|error| StorageError::ConnectionFailed {
+            path: path.to_path_buf(),
+            reason: error.to_string(),
+        })?;
+
+    connection
+        .query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))
+        .map_err(|_| StorageError::KeyRejected {
+            path: path.to_path_buf(),
+        })
+}
+
+fn is_plaintext(path: &Path) -> Result<bool, StorageError> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(error) => {
+            return Err(StorageError::ConnectionFailed {
+                path: path.to_path_buf(),
+                reason: error.to_string(),
+            });
+        }
+    };
+    let mut header = [0u8; 16];
+    Ok(file.read_exact(&mut header).is_ok() && &header == PLAINTEXT_HEADER)
+}
+
+/// Export a plaintext database into an encrypted copy and swap it in. The
+/// original file is only replaced once the export has fully succeeded.
+fn encrypt_in_place(path: &Path, key: &DatabaseKey) -> Result<(), StorageError> {
+    let failed = |reason: String| StorageError::ConnectionFailed {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let staging = sibling(path, "encrypting");
+    let _ = std::fs::remove_file(&staging);
+
+    {
+        let connection = open_connection(path)?;
+        connection
+            .execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")
+            .map_err(|error| failed(format!("failed to checkpoint before encrypting: {error}")))?;
+        connection
+            .execute(
+                "ATTACH DATABASE ?1 AS encrypted KEY ?2",
+                params![staging.to_string_lossy(), key.sqlcipher_value()],
+            )
+            .map_err(|error| failed(format!("failed to create encrypted copy: {error}")))?;
+        connection
+            .query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(()))
+            .map_err(|error| failed(format!("failed to export into encrypted copy: {error}")))?;
+        connection
+            .execute_batch("DETACH DATABASE encrypted;")
+            .map_err(|error| failed(format!("failed to detach encrypted copy: {error}")))?;
+    }
+
+    for suffix in ["wal", "shm"] {
+        let _ = std::fs::remove_file(sibling(path, suffix));
+    }
+    std::fs::rename(&staging, path)
+        .map_err(|error| failed(format!("failed to replace plaintext database: {error}")))?;
+
+    info!(path = %path.display(), "encrypted existing plaintext database");
+    Ok(())
+}
+
+/// `waddle.db` -> `waddle.db-{suffix}`, matching SQLite's WAL file naming.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!("-{suffix}"));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Database, Row, SqlValue};
+    use tempfile::TempDir;
+
+    fn passphrase() -> DatabaseKey {
+        DatabaseKey::Passphrase("correct horse battery staple".to_string())
+    }
+
+    async fn roster_jids(db: &NativeDatabase) -> Vec<String> {
+        let rows: Vec<Row> = db
+            .query("SELECT jid FROM roster ORDER BY jid", &[])
+            .await
+            .unwrap();
+        rows.iter()
+            .filter_map(|row| match row.get(0) {
+                Some(SqlValue::Text(jid)) => Some(jid.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    async fn insert_contact(db: &NativeDatabase, jid: &str) {
+        db.execute(
+            "INSERT INTO roster (jid, subscription) VALUES (?1, 'both')",
+            &[&jid.to_string()],
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn encrypted_database_round_trips_and_hides_plaintext() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("waddle.db");
+
+        let db = open_database_encrypted(&path, passphrase()).await.unwrap();
+        insert_contact(&db, "alice@example.com").await;
+        assert_eq!(roster_jids(&db).await, vec!["alice@example.com"]);
+        drop(db);
+
+        assert!(!is_plaintext(&path).unwrap());
+        let reopened = open_database_encrypted(&path, passphrase()).await.unwrap();
+        assert_eq!(roster_jids(&reopened).await, vec!["alice@example.com"]);
+    }
+
+    #[tokio::test]
+    async fn wrong_key_is_rejected() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("waddle.db");
+        drop(open_database_encrypted(&path, passphrase()).await.unwrap());
+
+        let error = open_database_encrypted(&path, DatabaseKey::Raw([7; 32]))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, StorageError::KeyRejected { .. }));
+    }
+
+    #[tokio::test]
+    async fn existing_plaintext_database_is_migrated() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("waddle.db");
+
+        let plain = NativeDatabase::open(&path).await.unwrap();
+        insert_contact(&plain, "bob@example.com").await;
+        drop(plain);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(is_plaintext(&path).unwrap());
+
+        let key = DatabaseKey::Raw([42; 32]);
+        let db = open_database_encrypted(&path, key.clone()).await.unwrap();
+        assert_eq!(roster_jids(&db).await, vec!["bob@example.com"]);
+        assert!(!is_plaintext(&path).unwrap());
+        assert!(!sibling(&path, "encrypting").exists());
+    }
+
+    #[test]
+    fn debug_output_redacts_key_material() {
+        assert_eq!(format!("{:?}", passphrase()), "DatabaseKey::Passphrase(..)");
+    }
+}
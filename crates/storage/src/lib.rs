@@ -1,15 +1,22 @@
+use std::cell::RefCell;
 use std::path::{Path, PathBuf};
 
+use serde::Serialize;
+use waddle_core::jid::Jid;
+
 #[cfg(feature = "native")]
 use std::{
-    sync::mpsc::{self, Receiver, Sender},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
     thread,
     time::Duration,
 };
 
 #[cfg(feature = "native")]
 use rusqlite::{
-    Connection, params, params_from_iter,
+    Connection, OptionalExtension, params, params_from_iter,
     types::{Value, ValueRef},
 };
 
@@ -19,6 +26,46 @@ use tokio::{sync::oneshot, task};
 #[cfg(feature = "native")]
 use tracing::info;
 
+#[cfg(feature = "native")]
+mod backup;
+
+#[cfg(feature = "native")]
+mod maintenance;
+
+#[cfg(feature = "native")]
+mod spill;
+
+#[cfg(feature = "encryption")]
+mod encryption;
+
+// Holds JS handles, so only for the single-threaded browser targets.
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+mod web;
+
+mod messages;
+mod query;
+mod stats;
+mod wipe;
+
+pub use messages::{retract_message, store_message, store_messages, store_translation};
+pub use query::Query;
+pub use stats::StorageStats;
+
+#[cfg(feature = "native")]
+pub use backup::BackupManager;
+
+#[cfg(feature = "native")]
+pub use maintenance::MaintenanceScheduler;
+
+#[cfg(feature = "native")]
+pub use spill::SpillFile;
+
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+pub use web::{WEB_DATABASE_NAME, WebDatabase};
+
+#[cfg(feature = "encryption")]
+pub use encryption::{DatabaseKey, open_database_encrypted};
+
 #[derive(Debug, thiserror::Error)]
 pub enum StorageError {
     #[error("failed to open database at {path}: {reason}")]
@@ -27,6 +74,12 @@ pub enum StorageError {
     #[error("migration {version} failed: {reason}")]
     MigrationFailed { version: u32, reason: String },
 
+    #[error("migration {version} was interrupted and left the database in an unknown state")]
+    MigrationDirty { version: u32 },
+
+    #[error("database schema version {found} is newer than this build supports ({supported})")]
+    SchemaTooNew { found: u32, supported: u32 },
+
     #[error("query failed: {0}")]
     QueryFailed(String),
 
@@ -35,6 +88,15 @@ pub enum StorageError {
 
     #[error("transaction rolled back: {0}")]
     TransactionFailed(String),
+
+    #[error("database key rejected for {path}")]
+    KeyRejected { path: PathBuf },
+
+    #[error("backup at {path} failed: {reason}")]
+    BackupFailed { path: PathBuf, reason: String },
+
+    #[error("database maintenance failed: {0}")]
+    MaintenanceFailed(String),
 }
 
 #[derive(Debug, Clone, PartialEq, Default)]
@@ -108,6 +170,12 @@ impl ToSql for str {
     }
 }
 
+impl ToSql for Jid {
+    fn to_sql_value(&self) -> SqlValue {
+        SqlValue::Text(self.to_string())
+    }
+}
+
 impl ToSql for Vec<u8> {
     fn to_sql_value(&self) -> SqlValue {
         SqlValue::Blob(self.clone())
@@ -165,9 +233,45 @@ impl FromRow for Row {
     }
 }
 
+/// Writes staged by a [`Database::transaction`] closure. They run in order
+/// once the closure returns `Ok` and commit together: either every statement
+/// takes effect or none does. Reads go through the database as usual, before
+/// the transaction is opened.
 #[derive(Debug, Default)]
 pub struct Transaction {
-    _private: (),
+    statements: RefCell<Vec<StagedStatement>>,
+}
+
+#[derive(Debug)]
+struct StagedStatement {
+    sql: String,
+    params: Vec<SqlValue>,
+}
+
+impl Transaction {
+    /// Stage `sql` to run when the transaction commits.
+    pub fn execute(&self, sql: &str, params: &[&dyn ToSql]) {
+        self.statements.borrow_mut().push(StagedStatement {
+            sql: sql.to_string(),
+            params: params.iter().map(|param| param.to_sql_value()).collect(),
+        });
+    }
+
+    fn into_statements(self) -> Vec<StagedStatement> {
+        self.statements.into_inner()
+    }
+}
+
+/// Outcome of [`Database::maintenance`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MaintenanceReport {
+    /// What `PRAGMA integrity_check` found; empty for a sound database.
+    pub problems: Vec<String>,
+    /// How much smaller the database got on disk.
+    pub reclaimed_bytes: u64,
+    /// Size on disk afterwards.
+    pub size_bytes: u64,
 }
 
 #[allow(async_fn_in_trait)]
@@ -189,13 +293,75 @@ pub trait Database: Send + Sync + 'static {
     async fn transaction<F, R>(&self, f: F) -> Result<R, StorageError>
     where
         F: FnOnce(&Transaction) -> Result<R, StorageError> + Send;
+
+    /// Check the database for corruption, return free pages to the file
+    /// system and fold the write-ahead log back into the database. A
+    /// database that fails the check is not vacuumed.
+    async fn maintenance(&self) -> Result<MaintenanceReport, StorageError>;
+
+    /// Row counts and approximate sizes of every table.
+    async fn stats(&self) -> Result<StorageStats, StorageError> {
+        stats::collect(self).await
+    }
+
+    /// Delete everything stored for the account: messages, contacts, rooms,
+    /// encryption state and sync progress. Plugin data is kept. Returns the
+    /// tables emptied.
+    async fn wipe_account_data(&self) -> Result<Vec<String>, StorageError> {
+        wipe::wipe(self).await
+    }
 }
 
 #[cfg(feature = "native")]
 #[derive(Debug)]
 pub struct NativeDatabase {
     path: PathBuf,
+    options: ConnectionOptions,
     writer: Sender<WriteCommand>,
+    readers: Arc<ReaderPool>,
+}
+
+/// Prepared statements kept per connection, keyed by SQL text. Managers
+/// issue a small fixed set of statements, so this holds all the hot ones.
+#[cfg(feature = "native")]
+const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+/// Most idle read connections kept open for reuse.
+#[cfg(feature = "native")]
+const MAX_IDLE_READERS: usize = 4;
+
+/// Read connections returned after use, so that queries reuse both the
+/// connection and the statements it has already prepared instead of
+/// opening the file and parsing the SQL again each time.
+#[cfg(feature = "native")]
+#[derive(Debug, Default)]
+struct ReaderPool {
+    idle: Mutex<Vec<Connection>>,
+}
+
+#[cfg(feature = "native")]
+impl ReaderPool {
+    fn take(&self, path: &Path, options: &ConnectionOptions) -> Result<Connection, StorageError> {
+        match self.idle.lock().unwrap().pop() {
+            Some(connection) => Ok(connection),
+            None => open_native_connection(path, options),
+        }
+    }
+
+    fn put(&self, connection: Connection) {
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < MAX_IDLE_READERS {
+            idle.push(connection);
+        }
+    }
+}
+
+/// Settings applied to every connection opened for one database.
+#[cfg(feature = "native")]
+#[derive(Debug, Default, Clone)]
+struct ConnectionOptions {
+    #[cfg(feature = "encryption")]
+    key: Option<std::sync::Arc<DatabaseKey>>,
 }
 
 #[cfg(feature = "native")]
@@ -205,6 +371,17 @@ enum WriteCommand {
         params: Vec<SqlValue>,
         response: oneshot::Sender<Result<u64, StorageError>>,
     },
+    Transaction {
+        statements: Vec<StagedStatement>,
+        response: oneshot::Sender<Result<(), StorageError>>,
+    },
+    Restore {
+        source: PathBuf,
+        response: oneshot::Sender<Result<(), StorageError>>,
+    },
+    Maintenance {
+        response: oneshot::Sender<Result<MaintenanceReport, StorageError>>,
+    },
 }
 
 #[cfg(feature = "native")]
@@ -263,6 +440,14 @@ fn open_connection(path: &Path) -> Result<Connection, StorageError> {
 
 #[cfg(feature = "native")]
 fn configure_native_connection(connection: &Connection, path: &Path) -> Result<(), StorageError> {
+    // Only honoured while the file is still empty, so before the switch to
+    // WAL; older databases change over during their first maintenance run.
+    connection
+        .pragma_update(None, "auto_vacuum", "INCREMENTAL")
+        .map_err(|error| StorageError::ConnectionFailed {
+            path: path.to_path_buf(),
+            reason: error.to_string(),
+        })?;
     connection
         .pragma_update(None, "journal_mode", "WAL")
         .map_err(|error| StorageError::ConnectionFailed {
@@ -281,16 +466,36 @@ fn configure_native_connection(connection: &Connection, path: &Path) -> Result<(
             path: path.to_path_buf(),
             reason: error.to_string(),
         })?;
+    connection.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
     Ok(())
 }
 
 #[cfg(feature = "native")]
-fn open_native_connection(path: &Path) -> Result<Connection, StorageError> {
-    let connection = open_connection(path)?;
+fn open_native_connection(
+    path: &Path,
+    options: &ConnectionOptions,
+) -> Result<Connection, StorageError> {
+    let connection = open_keyed_connection(path, options)?;
     configure_native_connection(&connection, path)?;
     Ok(connection)
 }
 
+/// Open `path` with the database key applied but no other configuration.
+#[cfg(feature = "native")]
+#[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+fn open_keyed_connection(
+    path: &Path,
+    options: &ConnectionOptions,
+) -> Result<Connection, StorageError> {
+    let connection = open_connection(path)?;
+    // SQLCipher requires the key before any other statement touches the file.
+    #[cfg(feature = "encryption")]
+    if let Some(key) = &options.key {
+        encryption::apply_key(&connection, path, key)?;
+    }
+    Ok(connection)
+}
+
 #[cfg(feature = "native")]
 fn execute_statement(
     connection: &Connection,
@@ -300,11 +505,28 @@ fn execute_statement(
     let values = sql_values_to_rusqlite_values(params);
 
     connection
-        .execute(sql, params_from_iter(values.iter()))
+        .prepare_cached(sql)
+        .and_then(|mut statement| statement.execute(params_from_iter(values.iter())))
         .map(|rows_affected| rows_affected as u64)
         .map_err(|error| StorageError::QueryFailed(error.to_string()))
 }
 
+/// Run `statements` in one transaction, rolling all of them back if any
+/// fails.
+#[cfg(feature = "native")]
+fn execute_transaction(
+    connection: &Connection,
+    statements: &[StagedStatement],
+) -> Result<(), StorageError> {
+    let failed = |error: rusqlite::Error| StorageError::TransactionFailed(error.to_string());
+    let tx = connection.unchecked_transaction().map_err(failed)?;
+    for statement in statements {
+        execute_statement(&tx, &statement.sql, &statement.params)
+            .map_err(|error| StorageError::TransactionFailed(error.to_string()))?;
+    }
+    tx.commit().map_err(failed)
+}
+
 #[cfg(feature = "native")]
 fn query_rows(
     connection: &Connection,
@@ -312,7 +534,7 @@ fn query_rows(
     params: &[SqlValue],
 ) -> Result<Vec<Row>, StorageError> {
     let mut statement = connection
-        .prepare(sql)
+        .prepare_cached(sql)
         .map_err(|error| StorageError::QueryFailed(error.to_string()))?;
     let values = sql_values_to_rusqlite_values(params);
     let column_count = statement.column_count();
@@ -338,47 +560,190 @@ fn query_rows(
     Ok(output)
 }
 
-#[cfg(feature = "native")]
+/// A single forward-only schema change. Steps run inside a transaction
+/// together with the bookkeeping row in `_migrations`.
+#[cfg(any(feature = "native", feature = "web"))]
+enum MigrationStep {
+    Sql(&'static str),
+    /// For changes that need to inspect or rewrite existing data. No shipped
+    /// migration needs one yet. Native only: the web backend rejects these.
+    #[cfg(feature = "native")]
+    #[allow(dead_code)]
+    Rust(fn(&Connection) -> rusqlite::Result<()>),
+}
+
+#[cfg(any(feature = "native", feature = "web"))]
 struct Migration {
     version: u32,
-    sql: &'static str,
+    step: MigrationStep,
 }
 
-#[cfg(feature = "native")]
+/// Migrations in application order. Versions must be strictly increasing;
+/// append new entries at the end and never edit one that has shipped.
+#[cfg(any(feature = "native", feature = "web"))]
 const MIGRATIONS: &[Migration] = &[
     Migration {
         version: 1,
-        sql: include_str!("../migrations/001_initial.sql"),
+        step: MigrationStep::Sql(include_str!("../migrations/001_initial.sql")),
     },
     Migration {
         version: 2,
-        sql: include_str!("../migrations/002_add_mam_sync_state.sql"),
+        step: MigrationStep::Sql(include_str!("../migrations/002_add_mam_sync_state.sql")),
     },
     Migration {
         version: 3,
-        sql: include_str!("../migrations/003_add_offline_queue.sql"),
+        step: MigrationStep::Sql(include_str!("../migrations/003_add_offline_queue.sql")),
     },
     Migration {
         version: 4,
-        sql: include_str!("../migrations/004_add_embeds_column.sql"),
+        step: MigrationStep::Sql(include_str!("../migrations/004_add_embeds_column.sql")),
+    },
+    Migration {
+        version: 5,
+        step: MigrationStep::Sql(include_str!(
+            "../migrations/005_add_pending_subscriptions.sql"
+        )),
+    },
+    Migration {
+        version: 6,
+        step: MigrationStep::Sql(include_str!(
+            "../migrations/006_add_muc_subject_history.sql"
+        )),
+    },
+    Migration {
+        version: 7,
+        step: MigrationStep::Sql(include_str!("../migrations/007_add_conversations.sql")),
+    },
+    Migration {
+        version: 8,
+        step: MigrationStep::Sql(include_str!("../migrations/008_add_server_features.sql")),
+    },
+    Migration {
+        version: 9,
+        step: MigrationStep::Sql(include_str!("../migrations/009_add_omemo.sql")),
+    },
+    Migration {
+        version: 10,
+        step: MigrationStep::Sql(include_str!("../migrations/010_add_message_stanza_ids.sql")),
+    },
+    Migration {
+        version: 11,
+        step: MigrationStep::Sql(include_str!(
+            "../migrations/011_add_message_delivery_status.sql"
+        )),
+    },
+    Migration {
+        version: 12,
+        step: MigrationStep::Sql(include_str!("../migrations/012_add_scheduled_messages.sql")),
+    },
+    Migration {
+        version: 13,
+        step: MigrationStep::Sql(include_str!("../migrations/013_add_message_mentions.sql")),
+    },
+    Migration {
+        version: 14,
+        step: MigrationStep::Sql(include_str!(
+            "../migrations/014_add_notification_preferences.sql"
+        )),
+    },
+    Migration {
+        version: 15,
+        step: MigrationStep::Sql(include_str!("../migrations/015_normalize_jids.sql")),
+    },
+    Migration {
+        version: 16,
+        step: MigrationStep::Sql(include_str!("../migrations/016_add_link_previews.sql")),
+    },
+    Migration {
+        version: 17,
+        step: MigrationStep::Sql(include_str!("../migrations/017_add_reported_contacts.sql")),
+    },
+    Migration {
+        version: 18,
+        step: MigrationStep::Sql(include_str!("../migrations/018_add_message_retraction.sql")),
+    },
+    Migration {
+        version: 19,
+        step: MigrationStep::Sql(include_str!("../migrations/019_add_read_markers.sql")),
+    },
+    Migration {
+        version: 20,
+        step: MigrationStep::Sql(include_str!("../migrations/020_add_conversation_mute.sql")),
+    },
+    Migration {
+        version: 21,
+        step: MigrationStep::Sql(include_str!("../migrations/021_add_rules.sql")),
+    },
+    Migration {
+        version: 22,
+        step: MigrationStep::Sql(include_str!("../migrations/022_add_message_language.sql")),
+    },
+    Migration {
+        version: 23,
+        step: MigrationStep::Sql(include_str!("../migrations/023_add_muc_invites.sql")),
+    },
+    Migration {
+        version: 24,
+        step: MigrationStep::Sql(include_str!("../migrations/024_add_mam_sync_ranges.sql")),
+    },
+    Migration {
+        version: 25,
+        step: MigrationStep::Sql(include_str!("../migrations/025_add_roster_version.sql")),
+    },
+    Migration {
+        version: 26,
+        step: MigrationStep::Sql(include_str!("../migrations/026_add_own_profile.sql")),
     },
 ];
 
 #[cfg(feature = "native")]
 fn run_migrations(connection: &Connection) -> Result<(), StorageError> {
-    connection
-        .execute_batch(
-            "CREATE TABLE IF NOT EXISTS _migrations (
-                version INTEGER PRIMARY KEY,
-                applied_at TEXT NOT NULL DEFAULT (datetime('now'))
-            );",
+    apply_migrations(connection, MIGRATIONS)
+}
+
+/// Bring the schema up to date. A migration's `_migrations` row is written
+/// dirty and cleared in the same transaction as its changes, so a marker
+/// that survives means a step committed part of its work and then died. The
+/// database is refused until it is repaired by hand rather than running the
+/// step again over a half-applied schema.
+#[cfg(feature = "native")]
+fn apply_migrations(connection: &Connection, migrations: &[Migration]) -> Result<(), StorageError> {
+    prepare_migrations_table(connection)?;
+
+    let dirty: Option<u32> = connection
+        .query_row(
+            "SELECT version FROM _migrations WHERE dirty != 0 ORDER BY version LIMIT 1",
+            [],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|error| StorageError::MigrationFailed {
+            version: 0,
+            reason: format!("failed to query migration state: {error}"),
+        })?;
+    if let Some(version) = dirty {
+        return Err(StorageError::MigrationDirty { version });
+    }
+
+    let applied: u32 = connection
+        .query_row(
+            "SELECT IFNULL(MAX(version), 0) FROM _migrations",
+            [],
+            |row| row.get(0),
         )
         .map_err(|error| StorageError::MigrationFailed {
             version: 0,
-            reason: format!("failed to create _migrations table: {error}"),
+            reason: format!("failed to query migration state: {error}"),
         })?;
+    let supported = migrations.last().map_or(0, |migration| migration.version);
+    if applied > supported {
+        return Err(StorageError::SchemaTooNew {
+            found: applied,
+            supported,
+        });
+    }
 
-    for migration in MIGRATIONS {
+    for migration in migrations {
         let is_applied: i64 = connection
             .query_row(
                 "SELECT EXISTS(SELECT 1 FROM _migrations WHERE version = ?1)",
@@ -394,43 +759,93 @@ fn run_migrations(connection: &Connection) -> Result<(), StorageError> {
             continue;
         }
 
-        let tx =
-            connection
-                .unchecked_transaction()
-                .map_err(|error| StorageError::MigrationFailed {
-                    version: migration.version,
-                    reason: format!("failed to begin transaction: {error}"),
-                })?;
+        apply_migration(connection, migration)?;
+        info!(version = migration.version, "applied migration");
+    }
 
-        tx.execute_batch(migration.sql)
-            .map_err(|error| StorageError::MigrationFailed {
-                version: migration.version,
-                reason: error.to_string(),
-            })?;
+    Ok(())
+}
 
-        tx.execute(
-            "INSERT INTO _migrations (version) VALUES (?1)",
-            params![migration.version],
-        )
+#[cfg(feature = "native")]
+fn apply_migration(connection: &Connection, migration: &Migration) -> Result<(), StorageError> {
+    let tx = connection
+        .unchecked_transaction()
         .map_err(|error| StorageError::MigrationFailed {
             version: migration.version,
-            reason: format!("failed to record migration: {error}"),
+            reason: format!("failed to begin transaction: {error}"),
         })?;
 
-        tx.commit().map_err(|error| StorageError::MigrationFailed {
-            version: migration.version,
-            reason: format!("failed to commit migration: {error}"),
-        })?;
+    tx.execute(
+        "INSERT INTO _migrations (version, dirty) VALUES (?1, 1)",
+        params![migration.version],
+    )
+    .map_err(|error| StorageError::MigrationFailed {
+        version: migration.version,
+        reason: format!("failed to mark migration as started: {error}"),
+    })?;
+
+    let result = match migration.step {
+        MigrationStep::Sql(sql) => tx.execute_batch(sql),
+        MigrationStep::Rust(step) => step(&tx),
+    };
+    result.map_err(|error| StorageError::MigrationFailed {
+        version: migration.version,
+        reason: error.to_string(),
+    })?;
+
+    tx.execute(
+        "UPDATE _migrations SET dirty = 0, applied_at = datetime('now') WHERE version = ?1",
+        params![migration.version],
+    )
+    .map_err(|error| StorageError::MigrationFailed {
+        version: migration.version,
+        reason: format!("failed to record migration: {error}"),
+    })?;
+
+    tx.commit().map_err(|error| StorageError::MigrationFailed {
+        version: migration.version,
+        reason: format!("failed to commit migration: {error}"),
+    })
+}
 
-        info!(version = migration.version, "applied migration");
+/// Create `_migrations`, adding the `dirty` column to databases created
+/// before dirty-state tracking existed.
+#[cfg(feature = "native")]
+fn prepare_migrations_table(connection: &Connection) -> Result<(), StorageError> {
+    let bootstrap_failed = |error: rusqlite::Error| StorageError::MigrationFailed {
+        version: 0,
+        reason: format!("failed to prepare _migrations table: {error}"),
+    };
+
+    connection
+        .execute_batch(
+            "CREATE TABLE IF NOT EXISTS _migrations (
+                version INTEGER PRIMARY KEY,
+                applied_at TEXT NOT NULL DEFAULT (datetime('now')),
+                dirty INTEGER NOT NULL DEFAULT 0
+            );",
+        )
+        .map_err(bootstrap_failed)?;
+
+    let has_dirty: i64 = connection
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM pragma_table_info('_migrations') WHERE name = 'dirty')",
+            [],
+            |row| row.get(0),
+        )
+        .map_err(bootstrap_failed)?;
+    if has_dirty == 0 {
+        connection
+            .execute_batch("ALTER TABLE _migrations ADD COLUMN dirty INTEGER NOT NULL DEFAULT 0;")
+            .map_err(bootstrap_failed)?;
     }
 
     Ok(())
 }
 
 #[cfg(feature = "native")]
-fn run_writer(path: PathBuf, receiver: Receiver<WriteCommand>) {
-    let mut state = match open_native_connection(&path) {
+fn run_writer(path: PathBuf, options: ConnectionOptions, receiver: Receiver<WriteCommand>) {
+    let mut state = match open_native_connection(&path, &options) {
         Ok(connection) => WriterState::Ready(connection),
         Err(error) => WriterState::Failed(error.to_string()),
     };
@@ -450,6 +865,44 @@ fn run_writer(path: PathBuf, receiver: Receiver<WriteCommand>) {
                     }),
                 };
 
+                let _ = response.send(result);
+            }
+            WriteCommand::Transaction {
+                statements,
+                response,
+            } => {
+                let result = match &mut state {
+                    WriterState::Ready(connection) => execute_transaction(connection, &statements),
+                    WriterState::Failed(reason) => Err(StorageError::ConnectionFailed {
+                        path: path.clone(),
+                        reason: reason.clone(),
+                    }),
+                };
+
+                let _ = response.send(result);
+            }
+            WriteCommand::Restore { source, response } => {
+                let result = match &mut state {
+                    WriterState::Ready(connection) => {
+                        backup::restore_into(connection, &source, &options)
+                    }
+                    WriterState::Failed(reason) => Err(StorageError::ConnectionFailed {
+                        path: path.clone(),
+                        reason: reason.clone(),
+                    }),
+                };
+
+                let _ = response.send(result);
+            }
+            WriteCommand::Maintenance { response } => {
+                let result = match &mut state {
+                    WriterState::Ready(connection) => maintenance::run(connection, &path),
+                    WriterState::Failed(reason) => Err(StorageError::ConnectionFailed {
+                        path: path.clone(),
+                        reason: reason.clone(),
+                    }),
+                };
+
                 let _ = response.send(result);
             }
         }
@@ -459,11 +912,16 @@ fn run_writer(path: PathBuf, receiver: Receiver<WriteCommand>) {
 #[cfg(feature = "native")]
 impl NativeDatabase {
     async fn open(path: &Path) -> Result<Self, StorageError> {
+        Self::open_with(path, ConnectionOptions::default()).await
+    }
+
+    async fn open_with(path: &Path, options: ConnectionOptions) -> Result<Self, StorageError> {
         let path = path.to_path_buf();
         let setup_path = path.clone();
+        let setup_options = options.clone();
 
         task::spawn_blocking(move || {
-            let connection = open_native_connection(&setup_path)?;
+            let connection = open_native_connection(&setup_path, &setup_options)?;
             run_migrations(&connection)?;
             Ok(())
         })
@@ -475,16 +933,22 @@ impl NativeDatabase {
 
         let (writer, receiver) = mpsc::channel();
         let writer_path = path.clone();
+        let writer_options = options.clone();
 
         thread::Builder::new()
             .name("storage_writer".to_string())
-            .spawn(move || run_writer(writer_path, receiver))
+            .spawn(move || run_writer(writer_path, writer_options, receiver))
             .map_err(|error| StorageError::ConnectionFailed {
                 path: path.clone(),
                 reason: format!("failed to spawn storage_writer task: {error}"),
             })?;
 
-        Ok(Self { path, writer })
+        Ok(Self {
+            path,
+            options,
+            writer,
+            readers: Arc::default(),
+        })
     }
 }
 
@@ -517,9 +981,13 @@ impl Database for NativeDatabase {
         let sql = sql.to_string();
         let params = collect_params(params);
         let path = self.path.clone();
+        let options = self.options.clone();
+        let readers = self.readers.clone();
         let rows = task::spawn_blocking(move || {
-            let connection = open_native_connection(&path)?;
-            query_rows(&connection, &sql, &params)
+            let connection = readers.take(&path, &options)?;
+            let rows = query_rows(&connection, &sql, &params);
+            readers.put(connection);
+            rows
         })
         .await
         .map_err(|error| {
@@ -547,86 +1015,46 @@ impl Database for NativeDatabase {
         F: FnOnce(&Transaction) -> Result<R, StorageError> + Send,
     {
         let transaction = Transaction::default();
-        f(&transaction).map_err(|error| StorageError::TransactionFailed(error.to_string()))
-    }
-}
-
-/// Hardcoded database name for the web backend. On web targets, the `storage.path`
-/// config setting is ignored; this name is used with OPFS or IndexedDB backing.
-#[cfg(feature = "web")]
-pub const WEB_DATABASE_NAME: &str = "waddle";
-
-/// Web storage backend backed by wa-sqlite (compiled to WASM) with OPFS persistence
-/// (preferred) or IndexedDB fallback. Uses single-connection mode (no WAL, no pooling).
-///
-/// This is currently a compile-safe placeholder. The full implementation will:
-/// - Load wa-sqlite as a WASM module alongside the application
-/// - Use OPFS (Origin Private File System) when available, falling back to IndexedDB
-/// - Share the same SQL dialect and migration system as the native backend
-/// - Run in single-connection mode (all reads and writes serialised)
-#[cfg(feature = "web")]
-#[derive(Debug)]
-pub struct WebDatabase {
-    name: String,
-}
+        let result =
+            f(&transaction).map_err(|error| StorageError::TransactionFailed(error.to_string()))?;
+        let statements = transaction.into_statements();
+        if statements.is_empty() {
+            return Ok(result);
+        }
 
-#[cfg(feature = "web")]
-impl WebDatabase {
-    /// Open (or create) a web database with the given logical name.
-    ///
-    /// In the future this will initialise wa-sqlite with OPFS/IndexedDB backing
-    /// and run pending migrations. Currently returns a stub that errors on all
-    /// operations.
-    pub async fn open(_path: &Path) -> Result<Self, StorageError> {
-        Ok(Self {
-            name: WEB_DATABASE_NAME.to_string(),
-        })
-    }
+        let (response_tx, response_rx) = oneshot::channel();
+        let command = WriteCommand::Transaction {
+            statements,
+            response: response_tx,
+        };
 
-    pub fn name(&self) -> &str {
-        &self.name
-    }
-}
+        self.writer.send(command).map_err(|_| {
+            StorageError::QueryFailed("storage writer task is unavailable".to_string())
+        })?;
 
-#[cfg(feature = "web")]
-impl Database for WebDatabase {
-    async fn execute(&self, sql: &str, params: &[&dyn ToSql]) -> Result<u64, StorageError> {
-        let _ = (sql, params);
-        Err(StorageError::QueryFailed(
-            "web storage backend not yet implemented (wa-sqlite)".to_string(),
-        ))
+        response_rx.await.map_err(|_| {
+            StorageError::QueryFailed(
+                "storage writer task terminated before responding".to_string(),
+            )
+        })??;
+        Ok(result)
     }
 
-    async fn query<T: FromRow>(
-        &self,
-        sql: &str,
-        params: &[&dyn ToSql],
-    ) -> Result<Vec<T>, StorageError> {
-        let _ = (sql, params);
-        Err(StorageError::QueryFailed(
-            "web storage backend not yet implemented (wa-sqlite)".to_string(),
-        ))
-    }
+    async fn maintenance(&self) -> Result<MaintenanceReport, StorageError> {
+        let (response_tx, response_rx) = oneshot::channel();
+        let command = WriteCommand::Maintenance {
+            response: response_tx,
+        };
 
-    async fn query_one<T: FromRow>(
-        &self,
-        sql: &str,
-        params: &[&dyn ToSql],
-    ) -> Result<T, StorageError> {
-        let _ = (sql, params);
-        Err(StorageError::QueryFailed(
-            "web storage backend not yet implemented (wa-sqlite)".to_string(),
-        ))
-    }
+        self.writer.send(command).map_err(|_| {
+            StorageError::QueryFailed("storage writer task is unavailable".to_string())
+        })?;
 
-    async fn transaction<F, R>(&self, f: F) -> Result<R, StorageError>
-    where
-        F: FnOnce(&Transaction) -> Result<R, StorageError> + Send,
-    {
-        let _ = f;
-        Err(StorageError::TransactionFailed(
-            "web storage backend not yet implemented (wa-sqlite)".to_string(),
-        ))
+        response_rx.await.map_err(|_| {
+            StorageError::QueryFailed(
+                "storage writer task terminated before responding".to_string(),
+            )
+        })?
     }
 }
 
@@ -640,7 +1068,7 @@ pub async fn open_native_database(path: &Path) -> Result<NativeDatabase, Storage
     NativeDatabase::open(path).await
 }
 
-#[cfg(all(not(feature = "native"), feature = "web"))]
+#[cfg(all(not(feature = "native"), feature = "web", target_arch = "wasm32"))]
 pub async fn open_database(path: &Path) -> Result<impl Database, StorageError> {
     WebDatabase::open(path).await
 }
@@ -729,7 +1157,7 @@ mod tests {
             })
             .collect();
 
-        assert_eq!(versions, vec![1, 2, 3]);
+        assert_eq!(versions, (1..=26).collect::<Vec<i64>>());
     }
 
     #[tokio::test]
@@ -763,11 +1191,207 @@ mod tests {
 
         assert_eq!(
             versions,
-            vec![1, 2, 3],
+            (1..=26).collect::<Vec<i64>>(),
             "migrations should not duplicate on re-open"
         );
     }
 
+    fn create_notes(connection: &Connection) -> rusqlite::Result<()> {
+        connection.execute_batch("CREATE TABLE notes (body TEXT NOT NULL);")?;
+        connection.execute("INSERT INTO notes (body) VALUES ('seeded')", [])?;
+        Ok(())
+    }
+
+    fn test_migrations() -> Vec<Migration> {
+        vec![
+            Migration {
+                version: 1,
+                step: MigrationStep::Sql("CREATE TABLE items (id INTEGER PRIMARY KEY);"),
+            },
+            Migration {
+                version: 2,
+                step: MigrationStep::Rust(create_notes),
+            },
+        ]
+    }
+
+    fn recorded_versions(connection: &Connection) -> Vec<(u32, i64)> {
+        let mut stmt = connection
+            .prepare("SELECT version, dirty FROM _migrations ORDER BY version")
+            .unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn migration_versions_are_strictly_increasing() {
+        assert!(
+            MIGRATIONS
+                .windows(2)
+                .all(|pair| pair[0].version < pair[1].version)
+        );
+    }
+
+    #[test]
+    fn sql_and_rust_steps_apply_in_order() {
+        let connection = Connection::open_in_memory().unwrap();
+        apply_migrations(&connection, &test_migrations()).unwrap();
+
+        assert_eq!(recorded_versions(&connection), vec![(1, 0), (2, 0)]);
+        let body: String = connection
+            .query_row("SELECT body FROM notes", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(body, "seeded");
+    }
+
+    #[test]
+    fn failed_migration_rolls_back_and_can_be_retried() {
+        let connection = Connection::open_in_memory().unwrap();
+        let mut migrations = test_migrations();
+        migrations.push(Migration {
+            version: 3,
+            step: MigrationStep::Sql("CREATE TABLE extra (id INTEGER); SELECT * FROM missing;"),
+        });
+
+        let error = apply_migrations(&connection, &migrations).unwrap_err();
+        assert!(matches!(
+            error,
+            StorageError::MigrationFailed { version: 3, .. }
+        ));
+        assert_eq!(recorded_versions(&connection), vec![(1, 0), (2, 0)]);
+
+        migrations[2].step = MigrationStep::Sql("CREATE TABLE extra (id INTEGER);");
+        apply_migrations(&connection, &migrations).unwrap();
+        assert_eq!(recorded_versions(&connection), vec![(1, 0), (2, 0), (3, 0)]);
+    }
+
+    #[test]
+    fn interrupted_migration_is_reported_dirty() {
+        let connection = Connection::open_in_memory().unwrap();
+        apply_migrations(&connection, &test_migrations()[..1]).unwrap();
+        connection
+            .execute("INSERT INTO _migrations (version, dirty) VALUES (2, 1)", [])
+            .unwrap();
+
+        let error = apply_migrations(&connection, &test_migrations()).unwrap_err();
+        assert!(matches!(error, StorageError::MigrationDirty { version: 2 }));
+        assert_eq!(recorded_versions(&connection), vec![(1, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn newer_schema_is_refused() {
+        let connection = Connection::open_in_memory().unwrap();
+        apply_migrations(&connection, &test_migrations()).unwrap();
+
+        let error = apply_migrations(&connection, &test_migrations()[..1]).unwrap_err();
+        assert!(matches!(
+            error,
+            StorageError::SchemaTooNew {
+                found: 2,
+                supported: 1
+            }
+        ));
+    }
+
+    #[test]
+    fn legacy_migrations_table_gains_dirty_column() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE _migrations (
+                    version INTEGER PRIMARY KEY,
+                    applied_at TEXT NOT NULL DEFAULT (datetime('now'))
+                );
+                CREATE TABLE items (id INTEGER PRIMARY KEY);
+                INSERT INTO _migrations (version) VALUES (1);",
+            )
+            .unwrap();
+
+        apply_migrations(&connection, &test_migrations()).unwrap();
+        assert_eq!(recorded_versions(&connection), vec![(1, 0), (2, 0)]);
+    }
+
+    #[test]
+    fn normalize_jids_migration_folds_existing_rows() {
+        let connection = Connection::open_in_memory().unwrap();
+        apply_migrations(&connection, &MIGRATIONS[..14]).unwrap();
+        connection
+            .execute_batch(
+                "INSERT INTO messages (id, from_jid, to_jid, body, timestamp, message_type)
+                 VALUES ('m1', 'Bob@Example.COM/Phone', 'me@example.com', 'hi', '2026-01-01T00:00:00Z', 'chat');
+                 INSERT INTO roster (jid, subscription) VALUES ('bob@example.com', 'both');
+                 INSERT INTO roster (jid, subscription) VALUES ('Bob@Example.com', 'none');
+                 INSERT INTO roster (jid, subscription) VALUES ('Carol@Example.com', 'to');
+                 INSERT INTO conversations (jid, kind, last_activity, unread_count, pinned, archived)
+                 VALUES ('bob@example.com', 'chat', '2026-01-01T00:00:00Z', 2, 0, 1);
+                 INSERT INTO conversations (jid, kind, last_activity, unread_count, pinned, archived)
+                 VALUES ('BOB@example.com', 'chat', '2026-01-02T00:00:00Z', 3, 1, 0);
+                 INSERT INTO conversations (jid, kind, last_message_preview, unread_count)
+                 VALUES ('Dave@Example.com', 'chat', 'yo', 1);",
+            )
+            .unwrap();
+
+        apply_migrations(&connection, MIGRATIONS).unwrap();
+
+        let from: String = connection
+            .query_row("SELECT from_jid FROM messages WHERE id = 'm1'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(from, "bob@example.com/Phone");
+
+        let mut stmt = connection
+            .prepare("SELECT jid, subscription FROM roster ORDER BY jid")
+            .unwrap();
+        let roster: Vec<(String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            roster,
+            vec![
+                ("bob@example.com".to_string(), "both".to_string()),
+                ("carol@example.com".to_string(), "to".to_string()),
+            ]
+        );
+
+        let mut stmt = connection
+            .prepare(
+                "SELECT jid, unread_count, pinned, archived, last_message_preview \
+                 FROM conversations ORDER BY jid",
+            )
+            .unwrap();
+        let conversations: Vec<(String, i64, i64, i64, Option<String>)> = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            conversations,
+            vec![
+                ("bob@example.com".to_string(), 5, 1, 0, None),
+                (
+                    "dave@example.com".to_string(),
+                    1,
+                    0,
+                    0,
+                    Some("yo".to_string())
+                ),
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn migrations_create_expected_indices() {
         let (db, _dir) = open_temp_db().await;
@@ -1046,6 +1670,46 @@ mod tests {
         assert!(matches!(result, Err(StorageError::TransactionFailed(_))));
     }
 
+    #[tokio::test]
+    async fn transaction_commits_staged_writes_together() {
+        let (db, _dir) = open_temp_db().await;
+
+        db.transaction(|tx| {
+            for jid in ["alice@example.com", "bob@example.com"] {
+                tx.execute(
+                    "INSERT INTO roster (jid, subscription, groups) VALUES (?1, 'both', '[]')",
+                    &[&jid.to_string()],
+                );
+            }
+            Ok(())
+        })
+        .await
+        .expect("transaction failed");
+
+        let rows: Vec<Row> = db.query("SELECT jid FROM roster", &[]).await.unwrap();
+        assert_eq!(rows.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_statement_rolls_back_the_whole_transaction() {
+        let (db, _dir) = open_temp_db().await;
+
+        let result = db
+            .transaction(|tx| {
+                tx.execute(
+                    "INSERT INTO roster (jid, subscription, groups) VALUES ('alice@example.com', 'both', '[]')",
+                    &[],
+                );
+                tx.execute("INSERT INTO no_such_table VALUES (1)", &[]);
+                Ok(())
+            })
+            .await;
+        assert!(matches!(result, Err(StorageError::TransactionFailed(_))));
+
+        let rows: Vec<Row> = db.query("SELECT jid FROM roster", &[]).await.unwrap();
+        assert!(rows.is_empty());
+    }
+
     #[tokio::test]
     async fn null_values_round_trip_correctly() {
         let (db, _dir) = open_temp_db().await;
@@ -0,0 +1,340 @@
+//! Database upkeep: an integrity check, an incremental vacuum and a WAL
+//! checkpoint, run on the writer so nothing interleaves with them, and the
+//! scheduler that runs them while the app is inactive.
+//!
+//! Messages are deleted by retention and pruning all the time, and SQLite
+//! only reuses the pages they free; the file itself never shrinks unless it
+//! is vacuumed. Incremental auto-vacuum hands free pages back without
+//! rewriting the whole file, so regular runs stay cheap.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rusqlite::Connection;
+use tracing::{error, info, warn};
+
+use waddle_core::config::MaintenanceConfig;
+use waddle_core::error::EventBusError;
+use waddle_core::event::{Channel, Event, EventBus, EventPayload, EventSource, channels};
+
+use crate::{Database, MaintenanceReport, NativeDatabase, StorageError, StorageStats};
+
+/// `PRAGMA auto_vacuum` value for incremental mode.
+const AUTO_VACUUM_INCREMENTAL: i64 = 2;
+
+/// Run maintenance on the writer's connection.
+pub(crate) fn run(connection: &Connection, path: &Path) -> Result<MaintenanceReport, StorageError> {
+    let failed = |error: rusqlite::Error| StorageError::MaintenanceFailed(error.to_string());
+    let size_before = size_on_disk(path);
+
+    let mut problems: Vec<String> = connection
+        .prepare("PRAGMA integrity_check")
+        .and_then(|mut statement| {
+            statement
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<_, _>>()
+        })
+        .map_err(failed)?;
+    problems.retain(|line| line != "ok");
+
+    if problems.is_empty() {
+        let auto_vacuum: i64 = connection
+            .pragma_query_value(None, "auto_vacuum", |row| row.get(0))
+            .map_err(failed)?;
+        if auto_vacuum == AUTO_VACUUM_INCREMENTAL {
+            connection
+                .execute_batch("PRAGMA incremental_vacuum")
+                .map_err(failed)?;
+        } else {
+            // A database from before incremental mode; one full vacuum
+            // switches it over.
+            connection
+                .pragma_update(None, "auto_vacuum", "INCREMENTAL")
+                .and_then(|()| connection.execute_batch("VACUUM"))
+                .map_err(failed)?;
+        }
+    } else {
+        warn!(
+            count = problems.len(),
+            "database integrity check found problems"
+        );
+    }
+
+    // TRUNCATE also shrinks the WAL file back to nothing. Readers still
+    // holding old snapshots leave part of it in place until next time.
+    connection
+        .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+        .map_err(failed)?;
+
+    let size_bytes = size_on_disk(path);
+    Ok(MaintenanceReport {
+        problems,
+        reclaimed_bytes: size_before.saturating_sub(size_bytes),
+        size_bytes,
+    })
+}
+
+/// The database file and its write-ahead log together.
+fn size_on_disk(path: &Path) -> u64 {
+    let mut wal = path.as_os_str().to_owned();
+    wal.push("-wal");
+    [path, Path::new(&wal)]
+        .iter()
+        .filter_map(|file| std::fs::metadata(file).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Runs [`Database::maintenance`] at most every `interval_secs`, once the
+/// app has been inactive for `idle_delay_secs`, and reports storage usage
+/// after each run and on request.
+pub struct MaintenanceScheduler {
+    db: Arc<NativeDatabase>,
+    event_bus: Arc<dyn EventBus>,
+    interval: Duration,
+    idle_delay: Duration,
+}
+
+impl MaintenanceScheduler {
+    pub fn new(
+        db: Arc<NativeDatabase>,
+        event_bus: Arc<dyn EventBus>,
+        config: &MaintenanceConfig,
+    ) -> Self {
+        Self {
+            db,
+            event_bus,
+            interval: Duration::from_secs(config.interval_secs),
+            idle_delay: Duration::from_secs(config.idle_delay_secs),
+        }
+    }
+
+    /// Run maintenance now. Publishes
+    /// `system.storage.maintenance_completed`.
+    pub async fn maintain_now(&self) -> Result<MaintenanceReport, StorageError> {
+        let started = Instant::now();
+        let report = self.db.maintenance().await?;
+
+        info!(
+            problems = report.problems.len(),
+            reclaimed_bytes = report.reclaimed_bytes,
+            size_bytes = report.size_bytes,
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "database maintenance completed"
+        );
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channels::SYSTEM_STORAGE_MAINTENANCE_COMPLETED).unwrap(),
+            EventSource::System("maintenance".into()),
+            EventPayload::StorageMaintenanceCompleted {
+                problems: report.problems.clone(),
+                reclaimed_bytes: report.reclaimed_bytes,
+                size_bytes: report.size_bytes,
+            },
+        ));
+        Ok(report)
+    }
+
+    /// Measure each table now. Publishes `system.storage.stats`.
+    pub async fn report_stats(&self) -> Result<StorageStats, StorageError> {
+        let stats = self.db.stats().await?;
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channels::SYSTEM_STORAGE_STATS).unwrap(),
+            EventSource::System("maintenance".into()),
+            EventPayload::StorageStats {
+                tables: stats.tables.clone(),
+                total_bytes: stats.total_bytes,
+            },
+        ));
+        Ok(stats)
+    }
+
+    pub async fn run(self: Arc<Self>) -> Result<(), StorageError> {
+        let mut subscription = self
+            .event_bus
+            .subscribe(channels::UI_CLIENT_ACTIVITY)
+            .map_err(|error| StorageError::MaintenanceFailed(error.to_string()))?;
+        let mut inactive = false;
+        let mut last_run: Option<Instant> = None;
+
+        loop {
+            // The idle delay restarts whenever the app is shown again.
+            let until_due = last_run
+                .map(|at| self.interval.saturating_sub(at.elapsed()))
+                .unwrap_or_default();
+            let wait = until_due.max(self.idle_delay);
+
+            tokio::select! {
+                received = subscription.recv() => match received {
+                    Ok(event) => {
+                        if let EventPayload::ClientActivityChanged { active } = event.payload {
+                            inactive = !active;
+                        }
+                    }
+                    Err(EventBusError::ChannelClosed) => return Ok(()),
+                    Err(_) => {}
+                },
+                () = tokio::time::sleep(wait), if inactive => {
+                    last_run = Some(Instant::now());
+                    let result = match self.maintain_now().await {
+                        Ok(_) => self.report_stats().await.map(|_| ()),
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = result {
+                        error!(error = %e, "database maintenance failed");
+                        let _ = self.event_bus.publish(Event::new(
+                            Channel::new(channels::SYSTEM_ERROR_OCCURRED).unwrap(),
+                            EventSource::System("maintenance".into()),
+                            EventPayload::ErrorOccurred {
+                                component: "maintenance".to_string(),
+                                message: e.to_string(),
+                                recoverable: true,
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Row, SqlValue};
+    use tempfile::TempDir;
+    use waddle_core::event::BroadcastEventBus;
+
+    async fn open_temp_db(dir: &TempDir) -> Arc<NativeDatabase> {
+        Arc::new(
+            crate::open_native_database(&dir.path().join("waddle.db"))
+                .await
+                .expect("failed to open database"),
+        )
+    }
+
+    async fn fill_and_empty_roster(db: &NativeDatabase) {
+        let name = "x".repeat(4096);
+        for i in 0..200 {
+            db.execute(
+                "INSERT INTO roster (jid, name, subscription) VALUES (?1, ?2, 'both')",
+                &[&format!("contact{i}@example.com"), &name],
+            )
+            .await
+            .unwrap();
+        }
+        db.execute("DELETE FROM roster", &[]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn new_databases_vacuum_incrementally() {
+        let dir = TempDir::new().unwrap();
+        let db = open_temp_db(&dir).await;
+        let rows: Vec<Row> = db.query("PRAGMA auto_vacuum", &[]).await.unwrap();
+        assert_eq!(
+            rows[0].get(0),
+            Some(&SqlValue::Integer(AUTO_VACUUM_INCREMENTAL))
+        );
+
+        fill_and_empty_roster(&db).await;
+        let report = db.maintenance().await.unwrap();
+        assert!(report.problems.is_empty());
+        assert!(report.reclaimed_bytes > 0);
+        assert_eq!(
+            report.size_bytes,
+            size_on_disk(&dir.path().join("waddle.db"))
+        );
+    }
+
+    #[tokio::test]
+    async fn older_databases_switch_to_incremental_vacuum() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("waddle.db");
+        {
+            let connection = Connection::open(&path).unwrap();
+            connection
+                .execute_batch("PRAGMA auto_vacuum = NONE; CREATE TABLE legacy (id INTEGER);")
+                .unwrap();
+        }
+        let db = crate::open_native_database(&path).await.unwrap();
+        let before: Vec<Row> = db.query("PRAGMA auto_vacuum", &[]).await.unwrap();
+        assert_eq!(before[0].get(0), Some(&SqlValue::Integer(0)));
+
+        db.maintenance().await.unwrap();
+        // Open connections keep the mode they started with.
+        let after: i64 = Connection::open(&path)
+            .unwrap()
+            .pragma_query_value(None, "auto_vacuum", |row| row.get(0))
+            .unwrap();
+        assert_eq!(after, AUTO_VACUUM_INCREMENTAL);
+    }
+
+    #[tokio::test]
+    async fn runs_once_the_app_goes_inactive() {
+        let dir = TempDir::new().unwrap();
+        let db = open_temp_db(&dir).await;
+        fill_and_empty_roster(&db).await;
+
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let scheduler = Arc::new(MaintenanceScheduler::new(
+            db,
+            event_bus.clone(),
+            &MaintenanceConfig {
+                enabled: true,
+                interval_secs: 3600,
+                idle_delay_secs: 0,
+            },
+        ));
+        let mut completed = event_bus
+            .subscribe("system.storage.maintenance_completed")
+            .unwrap();
+        let mut stats = event_bus.subscribe("system.storage.stats").unwrap();
+        tokio::spawn(scheduler.run());
+        tokio::task::yield_now().await;
+
+        let set_active = |active| {
+            event_bus
+                .publish(Event::new(
+                    Channel::new("ui.client.activity").unwrap(),
+                    EventSource::System("test".into()),
+                    EventPayload::ClientActivityChanged { active },
+                ))
+                .unwrap();
+        };
+
+        set_active(true);
+        let early = tokio::time::timeout(Duration::from_millis(100), completed.recv()).await;
+        assert!(early.is_err(), "maintenance ran while the app was active");
+
+        set_active(false);
+        let event = tokio::time::timeout(Duration::from_secs(5), completed.recv())
+            .await
+            .expect("timed out waiting for maintenance")
+            .unwrap();
+        match event.payload {
+            EventPayload::StorageMaintenanceCompleted {
+                problems,
+                reclaimed_bytes,
+                ..
+            } => {
+                assert!(problems.is_empty());
+                assert!(reclaimed_bytes > 0);
+            }
+            other => panic!("expected StorageMaintenanceCompleted, got {other:?}"),
+        }
+        let event = tokio::time::timeout(Duration::from_secs(5), stats.recv())
+            .await
+            .expect("timed out waiting for storage stats")
+            .unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::StorageStats { ref tables, .. } if !tables.is_empty()
+        ));
+
+        // Not due again until the interval has passed.
+        set_active(true);
+        set_active(false);
+        let again = tokio::time::timeout(Duration::from_millis(100), completed.recv()).await;
+        assert!(again.is_err(), "maintenance ran again before it was due");
+    }
+}
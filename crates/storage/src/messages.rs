@@ -0,0 +1,496 @@
+//! Message persistence shared by the messaging and MAM managers.
+//!
+//! The same logical message can reach the store several times: live, as a
+//! room reflection, through a carbon, and again from an archive, with each
+//! copy possibly carrying a different stanza `id`. The XEP-0359 ids tie the
+//! copies together: the sender's origin id is stable across every path, and
+//! the archive's stanza id is shared by the live copy and the archived one.
+
+use chrono::{DateTime, Utc};
+use waddle_core::event::{ChatMessage, MessageTranslation, MessageType};
+use waddle_core::jid::Jid;
+
+use crate::{Database, Row, SqlValue, StorageError, ToSql};
+
+/// Rows holding a copy of the message bound as by [`NewMessageRow::params`]:
+/// from the same sender, with its id, or with its origin or stanza id in
+/// either id column. Origin ids are picked by the sender, so another
+/// sender's message never matches on them.
+macro_rules! stored_copy {
+    () => {
+        "(from_jid = ?13 OR substr(from_jid, 1, length(?13) + 1) = ?13 || '/') \
+         AND (id = ?1 \
+              OR (?11 IS NOT NULL AND (stanza_id = ?11 OR id = ?11)) \
+              OR (?10 IS NOT NULL AND (origin_id = ?10 OR id = ?10)))"
+    };
+}
+
+/// Inserts the message unless a copy is stored. The check and the insert
+/// are one statement, so a copy stored concurrently cannot slip in between.
+const INSERT_MESSAGE: &str = concat!(
+    "INSERT OR IGNORE INTO messages \
+     (id, from_jid, to_jid, body, timestamp, message_type, thread, read, embeds, origin_id, stanza_id, lang) \
+     SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12 \
+     WHERE NOT EXISTS (SELECT 1 FROM messages WHERE ",
+    stored_copy!(),
+    ")"
+);
+
+/// Records the origin and stanza id on a stored copy that was missing them.
+const MERGE_MESSAGE_IDS: &str = concat!(
+    "UPDATE messages SET origin_id = COALESCE(origin_id, ?10), \
+     stanza_id = COALESCE(stanza_id, ?11) \
+     WHERE id = (SELECT id FROM messages WHERE ",
+    stored_copy!(),
+    " LIMIT 1)"
+);
+
+const FIND_STORED_COPY: &str =
+    concat!("SELECT id FROM messages WHERE ", stored_copy!(), " LIMIT 1");
+
+/// Store `message` unless a copy of it is already stored. Returns `false` for
+/// a duplicate, after recording any origin or stanza id the stored copy was
+/// missing so later copies match it too. Sender and recipient are stored
+/// normalized.
+pub async fn store_message<D: Database>(
+    db: &D,
+    message: &ChatMessage,
+) -> Result<bool, StorageError> {
+    let row = NewMessageRow::new(message);
+    db.execute(MERGE_MESSAGE_IDS, &row.params()).await?;
+    let inserted = db.execute(INSERT_MESSAGE, &row.params()).await?;
+    Ok(inserted > 0)
+}
+
+/// Store a batch of messages, such as a page of archive results, in one
+/// transaction. Each is deduplicated against the store as by
+/// [`store_message`], and copies of one message within the batch are stored
+/// once. Returns how many messages were new.
+pub async fn store_messages<D: Database>(
+    db: &D,
+    messages: &[ChatMessage],
+) -> Result<usize, StorageError> {
+    let mut rows = Vec::new();
+    let mut new = 0;
+    for (index, message) in messages.iter().enumerate() {
+        if messages[..index]
+            .iter()
+            .any(|earlier| is_copy_of(message, earlier))
+        {
+            continue;
+        }
+        let row = NewMessageRow::new(message);
+        if find_stored_copy(db, &row).await?.is_none() {
+            new += 1;
+        }
+        rows.push(row);
+    }
+
+    // The statements check for stored copies again as they run, so the
+    // count may be off if another writer stores a copy meanwhile, but the
+    // rows never are.
+    db.transaction(|tx| {
+        for row in &rows {
+            tx.execute(MERGE_MESSAGE_IDS, &row.params());
+            tx.execute(INSERT_MESSAGE, &row.params());
+        }
+        Ok(())
+    })
+    .await?;
+    Ok(new)
+}
+
+/// Turn the stored message `id` into a tombstone: its body and embeds are
+/// cleared and `retracted_at` is set. `id` may be the stored id, the origin
+/// id or the stanza id. With `sender`, only a message it sent matches: a
+/// bare JID matches each of its resources, so a room's JID matches every
+/// occupant. Returns the stored id of the message, or `None` when no
+/// matching message is stored or it was retracted already.
+pub async fn retract_message<D: Database>(
+    db: &D,
+    id: &str,
+    sender: Option<&str>,
+    retracted_at: DateTime<Utc>,
+) -> Result<Option<String>, StorageError> {
+    let id_s = id.to_string();
+    let sender = sender.map(Jid::new);
+    let rows: Vec<Row> = db
+        .query(
+            "SELECT id FROM messages \
+             WHERE (id = ?1 OR origin_id = ?1 OR stanza_id = ?1) \
+               AND (?2 IS NULL OR from_jid = ?2 OR substr(from_jid, 1, length(?2) + 1) = ?2 || '/') \
+               AND retracted_at IS NULL \
+             LIMIT 1",
+            &[&id_s, &sender],
+        )
+        .await?;
+    let Some(stored_id) = rows.first().and_then(|row| match row.get(0) {
+        Some(SqlValue::Text(id)) => Some(id.clone()),
+        _ => None,
+    }) else {
+        return Ok(None);
+    };
+
+    db.transaction(|tx| {
+        tx.execute(
+            "UPDATE messages SET body = '', embeds = NULL, retracted_at = ?1 WHERE id = ?2",
+            &[&retracted_at.to_rfc3339(), &stored_id],
+        );
+        tx.execute(
+            "DELETE FROM message_translations WHERE message_id = ?1",
+            &[&stored_id],
+        );
+        Ok(())
+    })
+    .await?;
+    Ok(Some(stored_id))
+}
+
+/// Store `translation` of message `id`, replacing any earlier one. The
+/// message itself may not be stored yet.
+pub async fn store_translation<D: Database>(
+    db: &D,
+    id: &str,
+    translation: &MessageTranslation,
+) -> Result<(), StorageError> {
+    db.execute(
+        "INSERT OR REPLACE INTO message_translations (message_id, lang, body) \
+         VALUES (?1, ?2, ?3)",
+        &[&id.to_string(), &translation.lang, &translation.body],
+    )
+    .await?;
+    Ok(())
+}
+
+/// Column values for a message about to be inserted.
+struct NewMessageRow<'a> {
+    message: &'a ChatMessage,
+    from: Jid,
+    sender: Jid,
+    to: Jid,
+    timestamp: String,
+    message_type: String,
+    embeds: Option<String>,
+}
+
+impl<'a> NewMessageRow<'a> {
+    fn new(message: &'a ChatMessage) -> Self {
+        let from = Jid::new(&message.from);
+        Self {
+            message,
+            sender: from.bare(),
+            from,
+            to: Jid::new(&message.to),
+            timestamp: message.timestamp.to_rfc3339(),
+            message_type: message_type_to_str(&message.message_type).to_string(),
+            embeds: if message.embeds.is_empty() {
+                None
+            } else {
+                Some(serde_json::to_string(&message.embeds).unwrap_or_default())
+            },
+        }
+    }
+
+    /// Parameters for [`INSERT_MESSAGE`], followed by the sender's bare JID
+    /// for [`stored_copy!`]. New messages start unread.
+    fn params(&self) -> [&dyn ToSql; 13] {
+        [
+            &self.message.id,
+            &self.from,
+            &self.to,
+            &self.message.body,
+            &self.timestamp,
+            &self.message_type,
+            &self.message.thread,
+            &0_i64,
+            &self.embeds,
+            &self.message.origin_id,
+            &self.message.stanza_id,
+            &self.message.lang,
+            &self.sender,
+        ]
+    }
+}
+
+/// Whether `message` would be matched to `other` by [`stored_copy!`] had
+/// `other` been stored.
+fn is_copy_of(message: &ChatMessage, other: &ChatMessage) -> bool {
+    let matches_id = |id: &Option<String>, other_id: &Option<String>| {
+        id.as_ref()
+            .is_some_and(|id| other_id.as_ref() == Some(id) || other.id == *id)
+    };
+    Jid::new(&message.from).bare() == Jid::new(&other.from).bare()
+        && (message.id == other.id
+            || matches_id(&message.stanza_id, &other.stanza_id)
+            || matches_id(&message.origin_id, &other.origin_id))
+}
+
+/// The id of a stored copy of `row`'s message. Our own sent messages are
+/// stored under the id that later comes back as their origin id, and
+/// archived messages under their stanza id, so both are also matched
+/// against `id`.
+async fn find_stored_copy<D: Database>(
+    db: &D,
+    row: &NewMessageRow<'_>,
+) -> Result<Option<String>, StorageError> {
+    let rows: Vec<Row> = db.query(FIND_STORED_COPY, &row.params()).await?;
+    Ok(rows.first().and_then(|row| match row.get(0) {
+        Some(SqlValue::Text(id)) => Some(id.clone()),
+        _ => None,
+    }))
+}
+
+fn message_type_to_str(mt: &MessageType) -> &'static str {
+    match mt {
+        MessageType::Chat => "chat",
+        MessageType::Groupchat => "groupchat",
+        MessageType::Normal => "normal",
+        MessageType::Headline => "headline",
+        MessageType::Error => "error",
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use chrono::Utc;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::NativeDatabase;
+
+    async fn open_temp_db() -> (NativeDatabase, TempDir) {
+        let dir = TempDir::new().expect("failed to create temp dir");
+        let db = NativeDatabase::open(&dir.path().join("test.db"))
+            .await
+            .expect("failed to open database");
+        (db, dir)
+    }
+
+    fn message(id: &str, origin_id: Option<&str>, stanza_id: Option<&str>) -> ChatMessage {
+        ChatMessage {
+            id: id.to_string(),
+            from: "alice@example.com/phone".to_string(),
+            to: "bob@example.com".to_string(),
+            body: "Hello".to_string(),
+            timestamp: Utc::now(),
+            message_type: MessageType::Chat,
+            thread: None,
+            embeds: vec![],
+            origin_id: origin_id.map(str::to_string),
+            stanza_id: stanza_id.map(str::to_string),
+            retracted_at: None,
+            lang: None,
+            translation: None,
+        }
+    }
+
+    async fn stored(db: &NativeDatabase) -> Vec<Row> {
+        db.query("SELECT id, origin_id, stanza_id FROM messages", &[])
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn archived_copy_of_live_message_is_stored_once() {
+        let (db, _dir) = open_temp_db().await;
+
+        let live = message("live-1", Some("origin-1"), Some("archive-1"));
+        assert!(store_message(&db, &live).await.unwrap());
+
+        // MAM results are keyed by the archive id rather than the stanza id.
+        let archived = message("archive-1", Some("origin-1"), Some("archive-1"));
+        assert!(!store_message(&db, &archived).await.unwrap());
+
+        assert_eq!(stored(&db).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn sent_message_is_matched_by_origin_id_and_learns_stanza_id() {
+        let (db, _dir) = open_temp_db().await;
+
+        let sent = message("msg-1", Some("msg-1"), None);
+        assert!(store_message(&db, &sent).await.unwrap());
+
+        let reflected = message("server-id", Some("msg-1"), Some("archive-9"));
+        assert!(!store_message(&db, &reflected).await.unwrap());
+
+        let rows = stored(&db).await;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get(0), Some(&SqlValue::Text("msg-1".to_string())));
+        assert_eq!(
+            rows[0].get(2),
+            Some(&SqlValue::Text("archive-9".to_string()))
+        );
+
+        // A later archive copy only carries the stanza id.
+        let archived = message("archive-9", None, Some("archive-9"));
+        assert!(!store_message(&db, &archived).await.unwrap());
+        assert_eq!(stored(&db).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn distinct_messages_are_both_stored() {
+        let (db, _dir) = open_temp_db().await;
+
+        assert!(
+            store_message(&db, &message("a", Some("o-a"), None))
+                .await
+                .unwrap()
+        );
+        assert!(
+            store_message(&db, &message("b", Some("o-b"), None))
+                .await
+                .unwrap()
+        );
+        assert_eq!(stored(&db).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn batch_stores_new_messages_once() {
+        let (db, _dir) = open_temp_db().await;
+
+        let live = message("live-1", Some("origin-1"), None);
+        store_message(&db, &live).await.unwrap();
+
+        let page = [
+            message("archive-1", Some("origin-1"), Some("archive-1")),
+            message("archive-2", Some("origin-2"), Some("archive-2")),
+            message("archive-2", Some("origin-2"), Some("archive-2")),
+            message("archive-3", None, Some("archive-3")),
+        ];
+        assert_eq!(store_messages(&db, &page).await.unwrap(), 2);
+
+        let rows: Vec<Row> = db
+            .query("SELECT stanza_id FROM messages WHERE id = 'live-1'", &[])
+            .await
+            .unwrap();
+        assert_eq!(
+            rows[0].get(0),
+            Some(&SqlValue::Text("archive-1".to_string()))
+        );
+        assert_eq!(stored(&db).await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn retraction_leaves_a_tombstone_of_the_senders_message_only() {
+        let (db, _dir) = open_temp_db().await;
+        store_message(&db, &message("live-1", Some("origin-1"), Some("archive-1")))
+            .await
+            .unwrap();
+
+        let now = Utc::now();
+        assert_eq!(
+            retract_message(&db, "origin-1", Some("mallory@example.com"), now)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            retract_message(&db, "origin-1", Some("Alice@example.com"), now)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("live-1")
+        );
+        // A second retraction of the same message changes nothing.
+        assert_eq!(
+            retract_message(&db, "archive-1", None, now).await.unwrap(),
+            None
+        );
+
+        let rows: Vec<Row> = db
+            .query("SELECT body, retracted_at FROM messages", &[])
+            .await
+            .unwrap();
+        assert_eq!(rows[0].get(0), Some(&SqlValue::Text(String::new())));
+        assert_eq!(rows[0].get(1), Some(&SqlValue::Text(now.to_rfc3339())));
+    }
+
+    #[tokio::test]
+    async fn language_and_translation_are_stored() {
+        let (db, _dir) = open_temp_db().await;
+        let mut german = message("de-1", None, None);
+        german.lang = Some("de".to_string());
+        store_message(&db, &german).await.unwrap();
+
+        let translation = |body: &str| MessageTranslation {
+            lang: "en".to_string(),
+            body: body.to_string(),
+        };
+        store_translation(&db, "de-1", &translation("Hi"))
+            .await
+            .unwrap();
+        store_translation(&db, "de-1", &translation("Hello"))
+            .await
+            .unwrap();
+
+        let rows: Vec<Row> = db
+            .query(
+                "SELECT messages.lang, t.body FROM messages \
+                 JOIN message_translations t ON t.message_id = messages.id",
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get(0), Some(&SqlValue::Text("de".to_string())));
+        assert_eq!(rows[0].get(1), Some(&SqlValue::Text("Hello".to_string())));
+
+        // A retracted message takes its translation with it.
+        retract_message(&db, "de-1", None, Utc::now())
+            .await
+            .unwrap();
+        let rows: Vec<Row> = db
+            .query("SELECT * FROM message_translations", &[])
+            .await
+            .unwrap();
+        assert!(rows.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sender_and_recipient_are_stored_normalized() {
+        let (db, _dir) = open_temp_db().await;
+
+        let mut mixed = message("m", None, None);
+        mixed.from = "Alice@Example.COM/Phone".to_string();
+        mixed.to = "BOB@example.com".to_string();
+        store_message(&db, &mixed).await.unwrap();
+
+        let rows: Vec<Row> = db
+            .query("SELECT from_jid, to_jid FROM messages", &[])
+            .await
+            .unwrap();
+        assert_eq!(
+            rows[0].get(0),
+            Some(&SqlValue::Text("alice@example.com/Phone".to_string()))
+        );
+        assert_eq!(
+            rows[0].get(1),
+            Some(&SqlValue::Text("bob@example.com".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn origin_id_of_another_sender_is_not_a_copy() {
+        let (db, _dir) = open_temp_db().await;
+        assert!(
+            store_message(&db, &message("a", Some("shared"), None))
+                .await
+                .unwrap()
+        );
+
+        let mut forged = message("b", Some("shared"), Some("archive-b"));
+        forged.from = "mallory@example.com/laptop".to_string();
+        assert!(store_message(&db, &forged).await.unwrap());
+
+        let mut rows = stored(&db).await;
+        rows.sort_by_key(|row| format!("{:?}", row.get(0)));
+        assert_eq!(rows.len(), 2);
+        // The forged copy did not lend its stanza id to Alice's message.
+        assert_eq!(rows[0].get(2), Some(&SqlValue::Null));
+
+        let page = [forged.clone(), message("a", Some("shared"), None)];
+        assert_eq!(store_messages(&db, &page).await.unwrap(), 0);
+        assert_eq!(stored(&db).await.len(), 2);
+    }
+}
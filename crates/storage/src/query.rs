@@ -0,0 +1,180 @@
+//! Queries with named parameters. SQL refers to values as `:name` and they
+//! are bound by that name instead of by position, so a statement and its
+//! bindings can be read side by side and reordering a clause cannot shift
+//! a value into the wrong column. SQLite numbers named parameters in order
+//! of first appearance, which is the order the positional [`Database`] API
+//! receives them in here.
+
+use std::collections::HashMap;
+
+use crate::{Database, FromRow, SqlValue, StorageError, ToSql};
+
+/// A statement with named parameters, bound with [`Query::bind`] and run
+/// against any [`Database`].
+#[derive(Debug, Clone)]
+pub struct Query<'a> {
+    sql: &'a str,
+    names: Vec<&'a str>,
+    values: HashMap<&'a str, SqlValue>,
+}
+
+impl<'a> Query<'a> {
+    pub fn new(sql: &'a str) -> Self {
+        Self {
+            sql,
+            names: parameter_names(sql),
+            values: HashMap::new(),
+        }
+    }
+
+    /// Bind `value` to every occurrence of `:name`.
+    pub fn bind(mut self, name: &'a str, value: &(impl ToSql + ?Sized)) -> Self {
+        self.values.insert(name, value.to_sql_value());
+        self
+    }
+
+    pub async fn execute<D: Database>(&self, db: &D) -> Result<u64, StorageError> {
+        let params = self.positional()?;
+        db.execute(self.sql, &as_params(&params)).await
+    }
+
+    pub async fn fetch_all<T: FromRow, D: Database>(&self, db: &D) -> Result<Vec<T>, StorageError> {
+        let params = self.positional()?;
+        db.query(self.sql, &as_params(&params)).await
+    }
+
+    pub async fn fetch_one<T: FromRow, D: Database>(&self, db: &D) -> Result<T, StorageError> {
+        let params = self.positional()?;
+        db.query_one(self.sql, &as_params(&params)).await
+    }
+
+    /// Like [`Query::fetch_one`], with no rows reported as `None`.
+    pub async fn fetch_optional<T: FromRow, D: Database>(
+        &self,
+        db: &D,
+    ) -> Result<Option<T>, StorageError> {
+        match self.fetch_one(db).await {
+            Ok(row) => Ok(Some(row)),
+            Err(StorageError::NotFound) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn positional(&self) -> Result<Vec<SqlValue>, StorageError> {
+        self.names
+            .iter()
+            .map(|name| {
+                self.values
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| StorageError::QueryFailed(format!("no value bound for :{name}")))
+            })
+            .collect()
+    }
+}
+
+impl ToSql for SqlValue {
+    fn to_sql_value(&self) -> SqlValue {
+        self.clone()
+    }
+}
+
+fn as_params(values: &[SqlValue]) -> Vec<&dyn ToSql> {
+    values.iter().map(|value| value as &dyn ToSql).collect()
+}
+
+/// Distinct parameter names in `sql`, in order of first appearance. Quoted
+/// strings and identifiers are skipped.
+fn parameter_names(sql: &str) -> Vec<&str> {
+    let bytes = sql.as_bytes();
+    let mut names = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            quote @ (b'\'' | b'"') => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    i += 1;
+                }
+                i += 1;
+            }
+            b':' => {
+                let start = i + 1;
+                let mut end = start;
+                while end < bytes.len()
+                    && (bytes[end].is_ascii_alphanumeric() || bytes[end] == b'_')
+                {
+                    end += 1;
+                }
+                let name = &sql[start..end];
+                if !name.is_empty() && !names.contains(&name) {
+                    names.push(name);
+                }
+                i = end;
+            }
+            _ => i += 1,
+        }
+    }
+    names
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{NativeDatabase, Row};
+
+    #[test]
+    fn names_are_numbered_by_first_appearance() {
+        assert_eq!(
+            parameter_names("SELECT * FROM t WHERE a = :b OR c = :a OR d = :b"),
+            ["b", "a"]
+        );
+        assert_eq!(
+            parameter_names("SELECT ':quoted', \"col:x\" FROM t WHERE a = :real"),
+            ["real"]
+        );
+    }
+
+    #[tokio::test]
+    async fn binds_by_name_and_reports_missing_values() {
+        let dir = TempDir::new().expect("failed to create temp dir");
+        let db = NativeDatabase::open(&dir.path().join("test.db"))
+            .await
+            .expect("failed to open database");
+
+        Query::new(
+            "INSERT INTO roster (jid, name, subscription, groups) \
+             VALUES (:jid, :name, :subscription, '[]')",
+        )
+        .bind("subscription", "both")
+        .bind("name", "Alice")
+        .bind("jid", "alice@example.com")
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let row: Option<Row> = Query::new("SELECT name FROM roster WHERE jid = :jid")
+            .bind("jid", "alice@example.com")
+            .fetch_optional(&db)
+            .await
+            .unwrap();
+        assert_eq!(
+            row.unwrap().get(0),
+            Some(&SqlValue::Text("Alice".to_string()))
+        );
+
+        let missing: Option<Row> = Query::new("SELECT name FROM roster WHERE jid = :jid")
+            .bind("jid", "bob@example.com")
+            .fetch_optional(&db)
+            .await
+            .unwrap();
+        assert!(missing.is_none());
+
+        let unbound = Query::new("SELECT name FROM roster WHERE jid = :jid")
+            .fetch_all::<Row, _>(&db)
+            .await;
+        assert!(matches!(unbound, Err(StorageError::QueryFailed(_))));
+    }
+}
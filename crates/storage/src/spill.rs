@@ -0,0 +1,204 @@
+//! An append-only file of events for subscribers that spill to disk when
+//! they fall behind.
+//!
+//! Events are stored one JSON document per line. Acknowledging a read batch
+//! advances an offset through the file, and the file is truncated once
+//! everything in it has been acknowledged, so it only grows while a
+//! subscriber is behind. The offset is not saved, so after a restart events
+//! are read again from the start of the file.
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use waddle_core::error::EventBusError;
+use waddle_core::event::{Event, SpillStore};
+
+use crate::StorageError;
+
+struct SpillState {
+    file: File,
+    read_offset: u64,
+    /// Byte length of each line returned by the last `peek_batch`.
+    peeked: VecDeque<u64>,
+}
+
+pub struct SpillFile {
+    path: PathBuf,
+    state: Mutex<SpillState>,
+}
+
+impl SpillFile {
+    /// Open the spill file at `path`, creating it if needed. Events left over
+    /// from a previous run are read back first.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)
+            .map_err(|error| StorageError::ConnectionFailed {
+                path: path.clone(),
+                reason: error.to_string(),
+            })?;
+
+        Ok(Self {
+            path,
+            state: Mutex::new(SpillState {
+                file,
+                read_offset: 0,
+                peeked: VecDeque::new(),
+            }),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn spill_error(&self, error: impl std::fmt::Display) -> EventBusError {
+        EventBusError::Spill(format!("{}: {error}", self.path.display()))
+    }
+}
+
+impl SpillStore for SpillFile {
+    fn push(&self, event: &Event) -> Result<(), EventBusError> {
+        let mut line = serde_json::to_vec(event).map_err(|error| self.spill_error(error))?;
+        line.push(b'\n');
+
+        let mut state = self.state.lock().unwrap();
+        state
+            .file
+            .write_all(&line)
+            .map_err(|error| self.spill_error(error))
+    }
+
+    fn peek_batch(&self, max: usize) -> Result<Vec<Event>, EventBusError> {
+        let mut state = self.state.lock().unwrap();
+        let read_offset = state.read_offset;
+        state
+            .file
+            .seek(SeekFrom::Start(read_offset))
+            .map_err(|error| self.spill_error(error))?;
+
+        let mut reader = BufReader::new(&state.file);
+        let mut events = Vec::new();
+        let mut peeked = VecDeque::new();
+        let mut line = String::new();
+        while events.len() < max {
+            line.clear();
+            let read = reader
+                .read_line(&mut line)
+                .map_err(|error| self.spill_error(error))?;
+            // A line without its newline is a write still in progress or
+            // cut short by a crash; leave it for the next read.
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            peeked.push_back(read as u64);
+            events.push(serde_json::from_str(&line).map_err(|error| self.spill_error(error))?);
+        }
+        drop(reader);
+        state.peeked = peeked;
+        Ok(events)
+    }
+
+    fn ack(&self, count: usize) -> Result<(), EventBusError> {
+        let mut state = self.state.lock().unwrap();
+        let count = count.min(state.peeked.len());
+        let acked: u64 = state.peeked.drain(..count).sum();
+        state.read_offset += acked;
+
+        let len = state
+            .file
+            .metadata()
+            .map_err(|error| self.spill_error(error))?
+            .len();
+        if state.read_offset >= len {
+            state
+                .file
+                .set_len(0)
+                .map_err(|error| self.spill_error(error))?;
+            state.read_offset = 0;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use waddle_core::event::{Channel, EventPayload, EventSource};
+
+    fn sync_completed(count: u64) -> Event {
+        Event::new(
+            Channel::new("system.sync.completed").unwrap(),
+            EventSource::System("test".into()),
+            EventPayload::SyncCompleted {
+                messages_synced: count,
+            },
+        )
+    }
+
+    fn synced(events: &[Event]) -> Vec<u64> {
+        events
+            .iter()
+            .map(|event| match event.payload {
+                EventPayload::SyncCompleted { messages_synced } => messages_synced,
+                _ => panic!("unexpected event {event:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn events_come_back_in_order_and_file_is_truncated_when_drained() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.spill");
+        let spill = SpillFile::open(&path).unwrap();
+
+        for count in 0..5 {
+            spill.push(&sync_completed(count)).unwrap();
+        }
+        assert_eq!(synced(&spill.peek_batch(3).unwrap()), vec![0, 1, 2]);
+        spill.ack(3).unwrap();
+
+        spill.push(&sync_completed(5)).unwrap();
+        assert_eq!(synced(&spill.peek_batch(10).unwrap()), vec![3, 4, 5]);
+        spill.ack(3).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+        assert!(spill.peek_batch(10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unacknowledged_events_are_read_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.spill");
+        let spill = SpillFile::open(&path).unwrap();
+
+        for count in 0..3 {
+            spill.push(&sync_completed(count)).unwrap();
+        }
+        assert_eq!(synced(&spill.peek_batch(2).unwrap()), vec![0, 1]);
+        assert_eq!(synced(&spill.peek_batch(2).unwrap()), vec![0, 1]);
+        spill.ack(1).unwrap();
+        assert_eq!(synced(&spill.peek_batch(10).unwrap()), vec![1, 2]);
+        assert_ne!(std::fs::metadata(&path).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn unread_events_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.spill");
+        {
+            let spill = SpillFile::open(&path).unwrap();
+            spill.push(&sync_completed(1)).unwrap();
+            spill.push(&sync_completed(2)).unwrap();
+        }
+
+        let spill = SpillFile::open(&path).unwrap();
+        assert_eq!(synced(&spill.peek_batch(10).unwrap()), vec![1, 2]);
+    }
+}
@@ -0,0 +1,546 @@
+//! Web storage backend: SQLite compiled to WebAssembly (sql.js), with the
+//! database image persisted to IndexedDB.
+//!
+//! The page must load sql.js so that `initSqlJs` is a global function before
+//! the database is opened. The database is held in memory; its image is
+//! exported and stored in IndexedDB under the database name, and loaded back
+//! from there on open. Exporting copies the whole database, so single writes
+//! are coalesced into one store shortly after the first of them, while
+//! transactions are stored as soon as they commit. The native
+//! migrations are applied unchanged, so managers run against the same schema
+//! and SQL dialect in the browser as on the desktop.
+
+use std::cell::Cell;
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+
+use js_sys::{Array, BigInt, Function, Object, Promise, Reflect, Uint8Array};
+use tracing::{info, warn};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::prelude::wasm_bindgen;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::{JsFuture, spawn_local};
+use web_sys::{IdbDatabase, IdbFactory, IdbRequest, IdbTransactionMode};
+
+use crate::{
+    Database, FromRow, MIGRATIONS, MaintenanceReport, MigrationStep, Row, SqlValue, StorageError,
+    ToSql, Transaction,
+};
+
+/// Hardcoded database name for the web backend. On web targets, the `storage.path`
+/// config setting is ignored; this name keys the image in IndexedDB.
+pub const WEB_DATABASE_NAME: &str = "waddle";
+
+const IDB_NAME: &str = "waddle-storage";
+const IDB_VERSION: u32 = 1;
+const IDB_STORE: &str = "databases";
+
+/// How long after a write outside a transaction the image is stored, so
+/// that a burst of writes costs one export.
+const PERSIST_DELAY_MS: i32 = 500;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_name = initSqlJs, catch)]
+    fn init_sql_js() -> Result<Promise, JsValue>;
+
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(handler: &Function, timeout: i32) -> JsValue;
+
+    /// A sql.js `Database`.
+    #[derive(Clone)]
+    type SqlJsDatabase;
+
+    #[wasm_bindgen(method, catch)]
+    fn exec(this: &SqlJsDatabase, sql: &str) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(method, catch)]
+    fn run(this: &SqlJsDatabase, sql: &str, params: &Array) -> Result<JsValue, JsValue>;
+
+    #[wasm_bindgen(method, catch)]
+    fn prepare(this: &SqlJsDatabase, sql: &str) -> Result<SqlJsStatement, JsValue>;
+
+    #[wasm_bindgen(method, js_name = getRowsModified)]
+    fn get_rows_modified(this: &SqlJsDatabase) -> f64;
+
+    #[wasm_bindgen(method)]
+    fn export(this: &SqlJsDatabase) -> Uint8Array;
+
+    /// A sql.js prepared `Statement`.
+    type SqlJsStatement;
+
+    #[wasm_bindgen(method, catch)]
+    fn bind(this: &SqlJsStatement, params: &Array) -> Result<bool, JsValue>;
+
+    #[wasm_bindgen(method, catch)]
+    fn step(this: &SqlJsStatement) -> Result<bool, JsValue>;
+
+    #[wasm_bindgen(method)]
+    fn get(this: &SqlJsStatement, params: &JsValue, config: &Object) -> Array;
+
+    #[wasm_bindgen(method)]
+    fn free(this: &SqlJsStatement) -> bool;
+}
+
+/// Web storage backend backed by sql.js with IndexedDB persistence. Runs in
+/// single-connection mode: every statement executes synchronously on the
+/// in-memory database, so reads and writes are naturally serialised.
+pub struct WebDatabase {
+    name: String,
+    db: SqlJsDatabase,
+    idb: IdbDatabase,
+    persistence: Rc<Persistence>,
+}
+
+/// Whether the in-memory database has changes IndexedDB does not have yet,
+/// and whether a store is already scheduled to pick them up.
+#[derive(Default)]
+struct Persistence {
+    dirty: Cell<bool>,
+    scheduled: Cell<bool>,
+}
+
+// SAFETY: this module only builds for wasm32, which runs single-threaded, so
+// the JS handles it holds can never be observed from another thread.
+unsafe impl Send for WebDatabase {}
+unsafe impl Sync for WebDatabase {}
+
+impl fmt::Debug for WebDatabase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebDatabase")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WebDatabase {
+    /// Open (or create) the web database, loading its last persisted image
+    /// from IndexedDB and running pending migrations.
+    pub async fn open(_path: &Path) -> Result<Self, StorageError> {
+        let failed = |reason: String| StorageError::ConnectionFailed {
+            path: WEB_DATABASE_NAME.into(),
+            reason,
+        };
+
+        let loader = init_sql_js().map_err(|error| {
+            failed(format!(
+                "sql.js is not loaded: {}",
+                js_error_message(&error)
+            ))
+        })?;
+        let sql = JsFuture::from(loader)
+            .await
+            .map_err(|error| failed(js_error_message(&error)))?;
+        let idb = open_idb().await?;
+        let image = load_image(&idb, WEB_DATABASE_NAME).await?;
+
+        let constructor: Function = Reflect::get(&sql, &JsValue::from_str("Database"))
+            .and_then(JsCast::dyn_into)
+            .map_err(|error| failed(js_error_message(&error)))?;
+        let args = Array::new();
+        if let Some(image) = &image {
+            args.push(image);
+        }
+        let db = Reflect::construct(&constructor, &args)
+            .map_err(|error| failed(js_error_message(&error)))?
+            .unchecked_into::<SqlJsDatabase>();
+
+        let database = Self {
+            name: WEB_DATABASE_NAME.to_string(),
+            db,
+            idb,
+            persistence: Rc::default(),
+        };
+        database.run_migrations()?;
+        database.persist().await?;
+        Ok(database)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Store any changes not yet in IndexedDB now rather than after the
+    /// persist delay, for instance before the page unloads.
+    pub async fn flush(&self) -> Result<(), StorageError> {
+        if self.persistence.dirty.get() {
+            self.persist().await?;
+        }
+        Ok(())
+    }
+
+    /// Apply pending migrations. Each runs in one transaction with its
+    /// bookkeeping row; the image is only persisted once all have succeeded.
+    fn run_migrations(&self) -> Result<(), StorageError> {
+        let bootstrap_failed = |error: JsValue| StorageError::MigrationFailed {
+            version: 0,
+            reason: format!(
+                "failed to prepare _migrations table: {}",
+                js_error_message(&error)
+            ),
+        };
+        self.db
+            .exec(
+                "CREATE TABLE IF NOT EXISTS _migrations (
+                    version INTEGER PRIMARY KEY,
+                    applied_at TEXT NOT NULL DEFAULT (datetime('now')),
+                    dirty INTEGER NOT NULL DEFAULT 0
+                );",
+            )
+            .map_err(bootstrap_failed)?;
+
+        let has_dirty = self.query_rows(
+            "SELECT EXISTS(SELECT 1 FROM pragma_table_info('_migrations') WHERE name = 'dirty')",
+            &[],
+        )?;
+        if !matches!(
+            has_dirty.first().and_then(|row| row.get(0)),
+            Some(SqlValue::Integer(1))
+        ) {
+            self.db
+                .exec("ALTER TABLE _migrations ADD COLUMN dirty INTEGER NOT NULL DEFAULT 0;")
+                .map_err(bootstrap_failed)?;
+        }
+
+        // As on native: a surviving marker means the schema may be half
+        // applied, so refuse it rather than run the migration again.
+        let dirty = self.query_rows(
+            "SELECT version FROM _migrations WHERE dirty != 0 ORDER BY version LIMIT 1",
+            &[],
+        )?;
+        if let Some(SqlValue::Integer(version)) = dirty.first().and_then(|row| row.get(0)) {
+            return Err(StorageError::MigrationDirty {
+                version: u32::try_from(*version).unwrap_or(u32::MAX),
+            });
+        }
+
+        let applied: Vec<u32> = self
+            .query_rows("SELECT version FROM _migrations ORDER BY version", &[])?
+            .iter()
+            .filter_map(|row| match row.get(0) {
+                Some(SqlValue::Integer(version)) => u32::try_from(*version).ok(),
+                _ => None,
+            })
+            .collect();
+        let supported = MIGRATIONS.last().map_or(0, |migration| migration.version);
+        if let Some(&found) = applied.last()
+            && found > supported
+        {
+            return Err(StorageError::SchemaTooNew { found, supported });
+        }
+
+        for migration in MIGRATIONS {
+            if applied.contains(&migration.version) {
+                continue;
+            }
+
+            #[cfg_attr(not(feature = "native"), allow(clippy::infallible_destructuring_match))]
+            let sql = match migration.step {
+                MigrationStep::Sql(sql) => sql,
+                #[cfg(feature = "native")]
+                MigrationStep::Rust(_) => {
+                    return Err(StorageError::MigrationFailed {
+                        version: migration.version,
+                        reason: "Rust migrations are not supported by the web backend".to_string(),
+                    });
+                }
+            };
+            let version = migration.version;
+            let batch = format!(
+                "BEGIN;\n\
+                 INSERT INTO _migrations (version, dirty) VALUES ({version}, 1);\n\
+                 {sql}\n\
+                 UPDATE _migrations SET dirty = 0, applied_at = datetime('now') WHERE version = {version};\n\
+                 COMMIT;"
+            );
+            if let Err(error) = self.db.exec(&batch) {
+                let _ = self.db.exec("ROLLBACK;");
+                return Err(StorageError::MigrationFailed {
+                    version: migration.version,
+                    reason: js_error_message(&error),
+                });
+            }
+            info!(version = migration.version, "applied migration");
+        }
+
+        Ok(())
+    }
+
+    fn query_rows(&self, sql: &str, params: &[&dyn ToSql]) -> Result<Vec<Row>, StorageError> {
+        let statement = self.db.prepare(sql).map_err(query_failed)?;
+        let rows = read_rows(&statement, &bind_params(params));
+        statement.free();
+        rows
+    }
+
+    /// Store the current database image in IndexedDB now.
+    async fn persist(&self) -> Result<(), StorageError> {
+        self.persistence.dirty.set(false);
+        let stored = store_image(&self.idb, &self.db, &self.name).await;
+        if stored.is_err() {
+            self.persistence.dirty.set(true);
+        }
+        stored
+    }
+
+    /// Mark the image as changed and store it `PERSIST_DELAY_MS` from now,
+    /// together with every other change made by then.
+    fn schedule_persist(&self) {
+        let persistence = &self.persistence;
+        persistence.dirty.set(true);
+        if persistence.scheduled.replace(true) {
+            return;
+        }
+
+        let persistence = Rc::clone(persistence);
+        let (idb, db, name) = (self.idb.clone(), self.db.clone(), self.name.clone());
+        spawn_local(async move {
+            let delay = Promise::new(&mut |resolve, _| {
+                set_timeout(&resolve, PERSIST_DELAY_MS);
+            });
+            let _ = JsFuture::from(delay).await;
+            persistence.scheduled.set(false);
+            // A transaction or flush may have stored everything meanwhile.
+            if !persistence.dirty.replace(false) {
+                return;
+            }
+            if let Err(error) = store_image(&idb, &db, &name).await {
+                warn!(%error, "failed to store database image");
+                persistence.dirty.set(true);
+            }
+        });
+    }
+}
+
+impl Database for WebDatabase {
+    async fn execute(&self, sql: &str, params: &[&dyn ToSql]) -> Result<u64, StorageError> {
+        self.db
+            .run(sql, &bind_params(params))
+            .map_err(query_failed)?;
+        let changed = self.db.get_rows_modified() as u64;
+        self.schedule_persist();
+        Ok(changed)
+    }
+
+    async fn query<T: FromRow>(
+        &self,
+        sql: &str,
+        params: &[&dyn ToSql],
+    ) -> Result<Vec<T>, StorageError> {
+        self.query_rows(sql, params)?
+            .iter()
+            .map(T::from_row)
+            .collect()
+    }
+
+    async fn query_one<T: FromRow>(
+        &self,
+        sql: &str,
+        params: &[&dyn ToSql],
+    ) -> Result<T, StorageError> {
+        let mut rows = self.query(sql, params).await?;
+        if rows.is_empty() {
+            return Err(StorageError::NotFound);
+        }
+
+        Ok(rows.remove(0))
+    }
+
+    async fn transaction<F, R>(&self, f: F) -> Result<R, StorageError>
+    where
+        F: FnOnce(&Transaction) -> Result<R, StorageError> + Send,
+    {
+        let transaction = Transaction::default();
+        let result =
+            f(&transaction).map_err(|error| StorageError::TransactionFailed(error.to_string()))?;
+        let statements = transaction.into_statements();
+        if statements.is_empty() {
+            return Ok(result);
+        }
+
+        let failed = |error: JsValue| StorageError::TransactionFailed(js_error_message(&error));
+        self.db.exec("BEGIN;").map_err(failed)?;
+        for statement in &statements {
+            let params: Array = statement.params.iter().map(sql_value_to_js).collect();
+            if let Err(error) = self.db.run(&statement.sql, &params) {
+                let _ = self.db.exec("ROLLBACK;");
+                return Err(failed(error));
+            }
+        }
+        self.db.exec("COMMIT;").map_err(failed)?;
+        self.persist().await?;
+        Ok(result)
+    }
+
+    /// The database lives in memory and there is no write-ahead log, so a
+    /// full `VACUUM` is cheap; sizes are those of the stored image.
+    async fn maintenance(&self) -> Result<MaintenanceReport, StorageError> {
+        let size_before = u64::from(self.db.export().length());
+        let problems: Vec<String> = self
+            .query_rows("PRAGMA integrity_check", &[])?
+            .iter()
+            .filter_map(|row| match row.get(0) {
+                Some(SqlValue::Text(line)) if line != "ok" => Some(line.clone()),
+                _ => None,
+            })
+            .collect();
+        if problems.is_empty() {
+            self.db
+                .exec("VACUUM;")
+                .map_err(|error| StorageError::MaintenanceFailed(js_error_message(&error)))?;
+        }
+
+        self.persist().await?;
+        let size_bytes = u64::from(self.db.export().length());
+        Ok(MaintenanceReport {
+            problems,
+            reclaimed_bytes: size_before.saturating_sub(size_bytes),
+            size_bytes,
+        })
+    }
+}
+
+fn read_rows(statement: &SqlJsStatement, params: &Array) -> Result<Vec<Row>, StorageError> {
+    statement.bind(params).map_err(query_failed)?;
+
+    // Return integers as BigInt so they stay distinguishable from reals.
+    let config = Object::new();
+    Reflect::set(&config, &JsValue::from_str("useBigInt"), &JsValue::TRUE).map_err(query_failed)?;
+
+    let mut rows = Vec::new();
+    while statement.step().map_err(query_failed)? {
+        let values = statement.get(&JsValue::UNDEFINED, &config);
+        rows.push(Row::new(values.iter().map(js_to_sql_value).collect()));
+    }
+    Ok(rows)
+}
+
+fn bind_params(params: &[&dyn ToSql]) -> Array {
+    params
+        .iter()
+        .map(|param| sql_value_to_js(&param.to_sql_value()))
+        .collect()
+}
+
+/// Integers are always bound as BigInt: sql.js binds a JS number that does
+/// not fit in 32 bits as a REAL.
+fn sql_value_to_js(value: &SqlValue) -> JsValue {
+    match value {
+        SqlValue::Null => JsValue::NULL,
+        SqlValue::Integer(integer) => BigInt::from(*integer).into(),
+        SqlValue::Real(real) => JsValue::from_f64(*real),
+        SqlValue::Text(text) => JsValue::from_str(text),
+        SqlValue::Blob(bytes) => Uint8Array::from(bytes.as_slice()).into(),
+        SqlValue::Boolean(boolean) => BigInt::from(i64::from(*boolean)).into(),
+    }
+}
+
+fn js_to_sql_value(value: JsValue) -> SqlValue {
+    if value.is_null() || value.is_undefined() {
+        SqlValue::Null
+    } else if let Some(text) = value.as_string() {
+        SqlValue::Text(text)
+    } else if value.is_bigint() {
+        i64::try_from(value).map_or(SqlValue::Null, SqlValue::Integer)
+    } else if let Some(real) = value.as_f64() {
+        SqlValue::Real(real)
+    } else if let Some(bytes) = value.dyn_ref::<Uint8Array>() {
+        SqlValue::Blob(bytes.to_vec())
+    } else {
+        SqlValue::Null
+    }
+}
+
+async fn open_idb() -> Result<IdbDatabase, StorageError> {
+    let failed = |reason: String| StorageError::ConnectionFailed {
+        path: IDB_NAME.into(),
+        reason,
+    };
+
+    let factory: IdbFactory = Reflect::get(&js_sys::global(), &JsValue::from_str("indexedDB"))
+        .ok()
+        .and_then(|factory| factory.dyn_into().ok())
+        .ok_or_else(|| failed("IndexedDB is not available".to_string()))?;
+    let request = factory
+        .open_with_u32(IDB_NAME, IDB_VERSION)
+        .map_err(|error| failed(js_error_message(&error)))?;
+
+    let upgrade_request = request.clone();
+    let on_upgrade = Closure::<dyn FnMut()>::new(move || {
+        if let Ok(idb) = upgrade_request
+            .result()
+            .and_then(JsCast::dyn_into::<IdbDatabase>)
+            && !idb.object_store_names().contains(IDB_STORE)
+        {
+            let _ = idb.create_object_store(IDB_STORE);
+        }
+    });
+    request.set_onupgradeneeded(Some(on_upgrade.as_ref().unchecked_ref()));
+
+    let result = request_result(&request).await;
+    request.set_onupgradeneeded(None);
+    drop(on_upgrade);
+
+    result?
+        .dyn_into()
+        .map_err(|error| failed(js_error_message(&error)))
+}
+
+/// Store the image of `db` in IndexedDB under `name`.
+async fn store_image(
+    idb: &IdbDatabase,
+    db: &SqlJsDatabase,
+    name: &str,
+) -> Result<(), StorageError> {
+    let image = db.export();
+    let transaction = idb
+        .transaction_with_str_and_mode(IDB_STORE, IdbTransactionMode::Readwrite)
+        .map_err(query_failed)?;
+    let request = transaction
+        .object_store(IDB_STORE)
+        .and_then(|store| store.put_with_key(&image, &JsValue::from_str(name)))
+        .map_err(query_failed)?;
+    request_result(&request).await.map(|_| ())
+}
+
+async fn load_image(idb: &IdbDatabase, name: &str) -> Result<Option<Uint8Array>, StorageError> {
+    let request = idb
+        .transaction_with_str(IDB_STORE)
+        .and_then(|transaction| transaction.object_store(IDB_STORE))
+        .and_then(|store| store.get(&JsValue::from_str(name)))
+        .map_err(query_failed)?;
+    Ok(request_result(&request).await?.dyn_into().ok())
+}
+
+/// Wait for an IndexedDB request to finish and return its result.
+async fn request_result(request: &IdbRequest) -> Result<JsValue, StorageError> {
+    let promise = Promise::new(&mut |resolve, reject| {
+        request.set_onsuccess(Some(&resolve));
+        request.set_onerror(Some(&reject));
+    });
+    let outcome = JsFuture::from(promise).await;
+    request.set_onsuccess(None);
+    request.set_onerror(None);
+
+    outcome.map_err(|_| {
+        let reason = request.error().ok().flatten().map_or_else(
+            || "IndexedDB request failed".to_string(),
+            |error| error.message(),
+        );
+        StorageError::QueryFailed(reason)
+    })?;
+    request.result().map_err(query_failed)
+}
+
+fn query_failed(error: JsValue) -> StorageError {
+    StorageError::QueryFailed(js_error_message(&error))
+}
+
+fn js_error_message(error: &JsValue) -> String {
+    error
+        .dyn_ref::<js_sys::Error>()
+        .map(|error| String::from(error.message()))
+        .or_else(|| error.as_string())
+        .unwrap_or_else(|| format!("{error:?}"))
+}
@@ -0,0 +1,72 @@
+//! Removing everything stored for the account, as when the account has been
+//! deleted from the server. Plugin data belongs to the installation rather
+//! than the account and is kept, as is the schema.
+
+use crate::stats::{quote_identifier, text};
+use crate::{Database, Row, StorageError};
+
+/// Tables kept by [`Database::wipe_account_data`].
+const KEPT_TABLES: &[&str] = &["_migrations", "plugin_kv"];
+
+pub(crate) async fn wipe<D: Database + ?Sized>(db: &D) -> Result<Vec<String>, StorageError> {
+    let names: Vec<Row> = db
+        .query(
+            "SELECT name FROM sqlite_master \
+             WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+            &[],
+        )
+        .await?;
+    let tables: Vec<String> = names
+        .iter()
+        .filter_map(|row| text(row.get(0)))
+        .filter(|name| !KEPT_TABLES.contains(&name.as_str()))
+        .collect();
+
+    db.transaction(|transaction| {
+        for table in &tables {
+            transaction.execute(&format!("DELETE FROM {}", quote_identifier(table)), &[]);
+        }
+        Ok(())
+    })
+    .await?;
+    Ok(tables)
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use crate::{NativeDatabase, SqlValue};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn wipes_account_tables_and_keeps_plugin_data() {
+        let dir = TempDir::new().unwrap();
+        let db = NativeDatabase::open(&dir.path().join("test.db"))
+            .await
+            .unwrap();
+        db.execute(
+            "INSERT INTO roster (jid, name, subscription) VALUES ('bob@example.com', 'Bob', 'both')",
+            &[],
+        )
+        .await
+        .unwrap();
+        db.execute(
+            "INSERT INTO plugin_kv (plugin_id, key, value) VALUES ('demo', 'theme', 'dark')",
+            &[],
+        )
+        .await
+        .unwrap();
+
+        let wiped = db.wipe_account_data().await.unwrap();
+        assert!(wiped.iter().any(|table| table == "roster"));
+        assert!(!wiped.iter().any(|table| table == "plugin_kv"));
+
+        for (table, expected) in [("roster", 0), ("plugin_kv", 1)] {
+            let row = db
+                .query_one::<Row>(&format!("SELECT COUNT(*) FROM {table}"), &[])
+                .await
+                .unwrap();
+            assert_eq!(row.get(0), Some(&SqlValue::Integer(expected)), "{table}");
+        }
+    }
+}
@@ -5,12 +5,13 @@ use futures::StreamExt;
 use ratatui::DefaultTerminal;
 use tokio::select;
 
-use waddle_core::config::Config;
+use waddle_core::config::{self, Config};
 use waddle_core::event::{
-    Channel, ChatMessage, Event, EventBus, EventPayload, EventSource, PresenceShow, UiTarget,
+    Channel, ChatMessage, Event, EventBus, EventPayload, EventSource, MucRole, PresenceShow,
+    ScrollDirection, UiTarget, channels,
 };
 use waddle_core::i18n::I18n;
-use waddle_core::theme::ThemeManager;
+use waddle_core::theme::{ThemeError, ThemeManager};
 
 use crate::error::TuiError;
 use crate::input::{self, Action};
@@ -23,23 +24,20 @@ impl TuiApp {
     pub async fn run(event_bus: Arc<dyn EventBus>, config: &Config) -> Result<(), TuiError> {
         let mut terminal =
             ratatui::try_init().map_err(|e| TuiError::TerminalInit(e.to_string()))?;
-        let state = initial_state(config)?;
+        let state = initial_state(config, &event_bus);
         let result = run_loop(&mut terminal, event_bus, state).await;
         ratatui::restore();
         result
     }
 }
 
-fn initial_state(config: &Config) -> Result<AppState, TuiError> {
+fn initial_state(config: &Config, event_bus: &Arc<dyn EventBus>) -> AppState {
     let i18n = I18n::new(config.ui.locale.as_deref(), &["en-US"]);
-    let theme = ThemeManager::load(&config.theme).map_err(TuiError::Theme)?;
+    let theme_manager =
+        ThemeManager::open(&config::data_dir(), &config.theme).with_event_bus(event_bus.clone());
+    let theme = theme_manager.current().clone();
 
-    let mut theme_manager = ThemeManager::new();
-    if ThemeManager::builtin(&theme.name).is_none() {
-        theme_manager.register_custom(theme.clone());
-    }
-
-    Ok(AppState::new(i18n, theme_manager, theme))
+    AppState::new(i18n, theme_manager, theme)
 }
 
 async fn run_loop(
@@ -97,10 +95,35 @@ fn handle_action(
         Action::None => {}
         Action::Quit => {}
         Action::OpenConversation(jid) => {
+            // With nothing stored locally, the first page comes from the
+            // archive.
+            let request_history = state
+                .conversations
+                .get_mut(&jid)
+                .filter(|conversation| {
+                    conversation.messages.is_empty()
+                        && !conversation.loading_history
+                        && !conversation.history_complete
+                })
+                .map(|conversation| conversation.loading_history = true)
+                .is_some();
+            publish(
+                event_bus,
+                channels::UI_CONVERSATION_OPENED,
+                EventPayload::ConversationOpened { jid: jid.clone() },
+            )?;
+            if request_history {
+                request_older_history(event_bus, jid)?;
+            }
+        }
+        Action::LoadHistory(jid) => {
+            request_older_history(event_bus, jid)?;
+        }
+        Action::Compose { jid } => {
             publish(
                 event_bus,
-                "ui.conversation.opened",
-                EventPayload::ConversationOpened { jid },
+                channels::UI_COMPOSE_STARTED,
+                EventPayload::ComposeStarted { jid },
             )?;
         }
         Action::SendMessage { to, body } => {
@@ -108,17 +131,18 @@ fn handle_action(
             if is_room {
                 publish(
                     event_bus,
-                    "ui.message.send",
+                    channels::UI_MUC_SEND,
                     EventPayload::MucSendRequested { room: to, body },
                 )?;
             } else {
                 publish(
                     event_bus,
-                    "ui.message.send",
+                    channels::UI_MESSAGE_SEND,
                     EventPayload::MessageSendRequested {
                         to,
                         body,
                         message_type: waddle_core::event::MessageType::Chat,
+                        request_receipt: false,
                     },
                 )?;
             }
@@ -130,6 +154,17 @@ fn handle_action(
     Ok(())
 }
 
+fn request_older_history(event_bus: &Arc<dyn EventBus>, jid: String) -> Result<(), TuiError> {
+    publish(
+        event_bus,
+        channels::UI_SCROLL_REQUESTED,
+        EventPayload::ScrollRequested {
+            jid,
+            direction: ScrollDirection::Up,
+        },
+    )
+}
+
 fn handle_command(
     event_bus: &Arc<dyn EventBus>,
     state: &mut AppState,
@@ -146,10 +181,12 @@ fn handle_command(
     if let Some(show) = parse_presence_show(&head) {
         publish(
             event_bus,
-            "ui.presence.set",
+            channels::UI_PRESENCE_SET,
             EventPayload::PresenceSetRequested {
                 show: show.clone(),
                 status: non_empty_string(tail),
+                priority: 0,
+                idle_since: None,
             },
         )?;
 
@@ -180,10 +217,12 @@ fn handle_command(
 
             publish(
                 event_bus,
-                "ui.presence.set",
+                channels::UI_PRESENCE_SET,
                 EventPayload::PresenceSetRequested {
                     show: show.clone(),
                     status: non_empty_string(status_tail),
+                    priority: 0,
+                    idle_since: None,
                 },
             )?;
 
@@ -206,10 +245,12 @@ fn handle_command(
 
             publish(
                 event_bus,
-                "ui.muc.join",
+                channels::UI_MUC_JOIN,
                 EventPayload::MucJoinRequested {
                     room: room.to_string(),
                     nick,
+                    history_since: None,
+                    password: None,
                 },
             )?;
 
@@ -234,7 +275,7 @@ fn handle_command(
 
             publish(
                 event_bus,
-                "ui.muc.leave",
+                channels::UI_MUC_LEAVE,
                 EventPayload::MucLeaveRequested { room: room.clone() },
             )?;
 
@@ -248,15 +289,22 @@ fn handle_command(
                 return Ok(());
             }
 
-            let Some(theme) = state.theme_manager.get(theme_id) else {
-                let prefix = state.i18n.t("command-theme-not-found", None);
-                state.command_feedback = Some(format!("{prefix} {theme_id}"));
-                return Ok(());
+            let theme = match state.theme_manager.set(theme_id) {
+                Ok(theme) => theme,
+                Err(ThemeError::NotFound(_)) => {
+                    let prefix = state.i18n.t("command-theme-not-found", None);
+                    state.command_feedback = Some(format!("{prefix} {theme_id}"));
+                    return Ok(());
+                }
+                Err(error) => {
+                    state.command_feedback = Some(error.to_string());
+                    return Ok(());
+                }
             };
 
             publish(
                 event_bus,
-                "ui.theme.changed",
+                channels::UI_THEME_CHANGED,
                 EventPayload::ThemeChanged {
                     theme_id: theme_id.to_string(),
                 },
@@ -282,6 +330,7 @@ fn parse_presence_show(value: &str) -> Option<PresenceShow> {
         "dnd" => Some(PresenceShow::Dnd),
         "xa" => Some(PresenceShow::Xa),
         "chat" => Some(PresenceShow::Chat),
+        "invisible" => Some(PresenceShow::Invisible),
         _ => None,
     }
 }
@@ -292,6 +341,7 @@ fn presence_message_id(show: &PresenceShow) -> &'static str {
         PresenceShow::Away => "status-away",
         PresenceShow::Xa => "status-xa",
         PresenceShow::Dnd => "status-dnd",
+        PresenceShow::Invisible => "status-invisible",
         PresenceShow::Unavailable => "status-unavailable",
     }
 }
@@ -326,12 +376,13 @@ fn command_help_text(state: &AppState) -> String {
     let status_usage = state.i18n.t("command-presence-usage", None);
 
     format!(
-        ":help ({}) | :quit ({}) | :status ({status_usage}) | :join ({}) | :leave ({}) | :theme ({})",
+        ":help ({}) | :quit ({}) | :status ({status_usage}) | :join ({}) | :leave ({}) | :theme ({}) | Ctrl+P ({})",
         state.i18n.t("cmd-help", None),
         state.i18n.t("cmd-quit", None),
         state.i18n.t("cmd-join", None),
         state.i18n.t("cmd-leave", None),
         state.i18n.t("cmd-theme", None),
+        state.i18n.t("palette-title", None),
     )
 }
 
@@ -400,17 +451,53 @@ fn handle_bus_event(state: &mut AppState, event: Event) {
         EventPayload::MucMessageReceived { room, message } => {
             add_message(state, &room, message);
         }
-        EventPayload::MucJoined { room, .. } => {
-            if !state.rooms.iter().any(|r| r.jid == room) {
-                let name = room.split('@').next().unwrap_or(&room).to_string();
-                state.rooms.push(MucRoom {
-                    jid: room,
-                    name,
-                    unread: 0,
-                });
+        EventPayload::MucJoined { room, .. } if !state.rooms.iter().any(|r| r.jid == room) => {
+            let name = room.split('@').next().unwrap_or(&room).to_string();
+            state.rooms.push(MucRoom {
+                jid: room,
+                name,
+                unread: 0,
+            });
+        }
+        EventPayload::MucOccupantChanged { room, occupant } => {
+            let occupants = state.occupants.entry(room).or_default();
+            occupants.retain(|o| o.nick != occupant.nick);
+            if !matches!(occupant.role, MucRole::None) {
+                occupants.push(occupant);
+            }
+        }
+        EventPayload::MucOccupantRenamed {
+            room,
+            old_nick,
+            new_nick,
+            ..
+        } => {
+            if let Some(occupant) = state
+                .occupants
+                .get_mut(&room)
+                .and_then(|occupants| occupants.iter_mut().find(|o| o.nick == old_nick))
+            {
+                occupant.nick = new_nick;
             }
         }
+        EventPayload::HistoryPageLoaded {
+            jid,
+            messages,
+            has_more,
+            ..
+        } => {
+            let conv = state.ensure_conversation(&jid);
+            conv.loading_history = false;
+            conv.history_complete = !has_more;
+            let mut older: Vec<ChatMessage> = messages
+                .into_iter()
+                .filter(|message| !conv.messages.iter().any(|m| m.id == message.id))
+                .collect();
+            older.append(&mut conv.messages);
+            conv.messages = older;
+        }
         EventPayload::MucLeft { room } => {
+            state.occupants.remove(&room);
             state.rooms.retain(|r| r.jid != room);
             if state.active_conversation.as_deref() == Some(&room) {
                 state.active_conversation = None;
@@ -419,7 +506,7 @@ fn handle_bus_event(state: &mut AppState, event: Event) {
                 state.sidebar_index -= 1;
             }
         }
-        EventPayload::MucSubjectChanged { room, subject } => {
+        EventPayload::MucSubjectChanged { room, subject, .. } => {
             if let Some(r) = state.rooms.iter_mut().find(|r| r.jid == room) {
                 r.name = if subject.is_empty() {
                     room.split('@').next().unwrap_or(&room).to_string()
@@ -441,7 +528,7 @@ fn handle_bus_event(state: &mut AppState, event: Event) {
         EventPayload::SyncStarted => {
             state.connection_status = ConnectionStatus::Syncing;
         }
-        EventPayload::SyncCompleted { .. } => {
+        EventPayload::SyncCompleted { .. } | EventPayload::SyncFailed { .. } => {
             if let ConnectionStatus::Syncing = state.connection_status {
                 state.connection_status = match state.connected_jid.clone() {
                     Some(jid) => ConnectionStatus::Connected { jid },
@@ -496,7 +583,9 @@ mod tests {
 
     use tokio::time::timeout;
     use waddle_core::config::ThemeConfig;
-    use waddle_core::event::{BroadcastEventBus, EventBus};
+    use waddle_core::event::{
+        BroadcastEventBus, EventBus, MessageType, MucAffiliation, MucOccupant,
+    };
 
     fn test_state() -> AppState {
         let i18n = I18n::new(Some("en-US"), &["en-US"]);
@@ -531,6 +620,7 @@ mod tests {
             EventPayload::PresenceSetRequested {
                 show: PresenceShow::Away,
                 status: Some(status),
+                ..
             } if status == "in a meeting"
         ));
     }
@@ -575,7 +665,7 @@ mod tests {
 
         assert!(matches!(
             event.payload,
-            EventPayload::MucJoinRequested { room, nick }
+            EventPayload::MucJoinRequested { room, nick, .. }
                 if room == "general@conference.example.com" && nick == "Alice"
         ));
     }
@@ -606,4 +696,164 @@ mod tests {
             EventPayload::MucLeaveRequested { room: payload_room } if payload_room == room
         ));
     }
+
+    fn message(id: &str, from: &str) -> ChatMessage {
+        ChatMessage {
+            id: id.to_string(),
+            from: from.to_string(),
+            to: "me@example.com".to_string(),
+            body: format!("message {id}"),
+            timestamp: chrono::Utc::now(),
+            message_type: MessageType::Chat,
+            thread: None,
+            embeds: vec![],
+            origin_id: None,
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
+        }
+    }
+
+    fn bus_event(channel: &str, payload: EventPayload) -> Event {
+        Event::new(
+            Channel::new(channel).unwrap(),
+            EventSource::System("test".into()),
+            payload,
+        )
+    }
+
+    #[tokio::test]
+    async fn scrolling_past_the_top_requests_older_history_once() {
+        let event_bus = test_event_bus();
+        let mut sub = event_bus.subscribe("ui.scroll.requested").unwrap();
+        let mut state = test_state();
+
+        let jid = "alice@example.com";
+        state.ensure_conversation(jid).messages = vec![message("3", jid)];
+        state.active_conversation = Some(jid.to_string());
+        state.scroll_offset = 1;
+
+        let jid = state.take_history_request().unwrap();
+        handle_action(&event_bus, &mut state, Action::LoadHistory(jid)).unwrap();
+        assert!(state.take_history_request().is_none());
+
+        let event = timeout(Duration::from_millis(100), sub.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            event.payload,
+            EventPayload::ScrollRequested {
+                jid,
+                direction: ScrollDirection::Up,
+            } if jid == "alice@example.com"
+        ));
+
+        handle_bus_event(
+            &mut state,
+            bus_event(
+                "ui.history.page_loaded",
+                EventPayload::HistoryPageLoaded {
+                    jid: "alice@example.com".to_string(),
+                    messages: vec![
+                        message("1", "alice@example.com"),
+                        message("3", "alice@example.com"),
+                    ],
+                    before: Some("3".to_string()),
+                    has_more: false,
+                },
+            ),
+        );
+
+        let conversation = &state.conversations["alice@example.com"];
+        let ids: Vec<&str> = conversation
+            .messages
+            .iter()
+            .map(|m| m.id.as_str())
+            .collect();
+        assert_eq!(ids, ["1", "3"]);
+        assert!(!conversation.loading_history);
+        assert!(conversation.history_complete);
+        assert!(state.take_history_request().is_none());
+    }
+
+    #[test]
+    fn occupants_follow_presence_and_leaving() {
+        let mut state = test_state();
+        let room = "general@conference.example.com";
+        let occupant = |nick: &str, role: MucRole| MucOccupant {
+            nick: nick.to_string(),
+            jid: None,
+            affiliation: MucAffiliation::None,
+            role,
+        };
+        let changed = |occupant: MucOccupant| {
+            bus_event(
+                "xmpp.muc.occupant.changed",
+                EventPayload::MucOccupantChanged {
+                    room: room.to_string(),
+                    occupant,
+                },
+            )
+        };
+
+        handle_bus_event(&mut state, changed(occupant("alice", MucRole::Participant)));
+        handle_bus_event(&mut state, changed(occupant("bob", MucRole::Visitor)));
+        handle_bus_event(&mut state, changed(occupant("bob", MucRole::Moderator)));
+        let nicks: Vec<&str> = state.occupants[room]
+            .iter()
+            .map(|o| o.nick.as_str())
+            .collect();
+        assert_eq!(nicks, ["alice", "bob"]);
+        assert!(matches!(state.occupants[room][1].role, MucRole::Moderator));
+
+        // A rename keeps the occupant in place rather than leaving and
+        // rejoining.
+        handle_bus_event(
+            &mut state,
+            bus_event(
+                "xmpp.muc.occupant.renamed",
+                EventPayload::MucOccupantRenamed {
+                    room: room.to_string(),
+                    old_nick: "bob".to_string(),
+                    new_nick: "robert".to_string(),
+                    own: false,
+                },
+            ),
+        );
+        handle_bus_event(&mut state, changed(occupant("robert", MucRole::Moderator)));
+        let nicks: Vec<&str> = state.occupants[room]
+            .iter()
+            .map(|o| o.nick.as_str())
+            .collect();
+        assert_eq!(nicks, ["alice", "robert"]);
+
+        handle_bus_event(&mut state, changed(occupant("alice", MucRole::None)));
+        assert_eq!(state.occupants[room].len(), 1);
+
+        handle_bus_event(
+            &mut state,
+            bus_event(
+                "xmpp.muc.left",
+                EventPayload::MucLeft {
+                    room: room.to_string(),
+                },
+            ),
+        );
+        assert!(!state.occupants.contains_key(room));
+    }
+
+    #[test]
+    fn drafts_survive_switching_conversations() {
+        let mut state = test_state();
+        state.open_conversation("alice@example.com");
+        state.input_buffer = "half a thought".to_string();
+
+        state.open_conversation("bob@example.com");
+        assert!(state.input_buffer.is_empty());
+
+        state.open_conversation("alice@example.com");
+        assert_eq!(state.input_buffer, "half a thought");
+    }
 }
@@ -1,12 +1,25 @@
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
+use crate::palette;
 use crate::state::{AppState, InputMode, Panel};
 
+/// Lines moved by Page Up and Page Down.
+const PAGE_LINES: u16 = 10;
+
 pub enum Action {
     None,
-    SendMessage { to: String, body: String },
+    SendMessage {
+        to: String,
+        body: String,
+    },
+    /// The user typed in the compose box of the conversation with `jid`.
+    Compose {
+        jid: String,
+    },
     ExecuteCommand(String),
     OpenConversation(String),
+    /// Fetch the page of history before the oldest loaded message.
+    LoadHistory(String),
     Quit,
 }
 
@@ -20,10 +33,18 @@ pub fn handle_key(state: &mut AppState, key: KeyEvent) -> Action {
         InputMode::Normal => handle_normal_mode(state, key),
         InputMode::Insert => handle_insert_mode(state, key),
         InputMode::Command => handle_command_mode(state, key),
+        InputMode::Palette => handle_palette_mode(state, key),
     }
 }
 
 fn handle_normal_mode(state: &mut AppState, key: KeyEvent) -> Action {
+    if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('p') {
+        state.input_mode = InputMode::Palette;
+        state.stash_draft();
+        state.palette_index = 0;
+        return Action::None;
+    }
+
     match key.code {
         KeyCode::Char('q') => {
             state.should_quit = true;
@@ -35,7 +56,7 @@ fn handle_normal_mode(state: &mut AppState, key: KeyEvent) -> Action {
         }
         KeyCode::Char(':') => {
             state.input_mode = InputMode::Command;
-            state.input_buffer.clear();
+            state.stash_draft();
             Action::None
         }
         KeyCode::Tab => {
@@ -62,19 +83,23 @@ fn handle_normal_mode(state: &mut AppState, key: KeyEvent) -> Action {
                     state.sidebar_index -= 1;
                 }
             } else {
-                state.scroll_offset = state.scroll_offset.saturating_add(1);
+                return scroll_up(state, 1);
             }
             Action::None
         }
+        KeyCode::PageUp if state.focused_panel == Panel::Conversation => {
+            scroll_up(state, PAGE_LINES)
+        }
+        KeyCode::PageDown if state.focused_panel == Panel::Conversation => {
+            state.scroll_offset = state.scroll_offset.saturating_sub(PAGE_LINES);
+            Action::None
+        }
         KeyCode::Enter => {
-            if state.focused_panel == Panel::Sidebar {
-                if let Some(jid) = state.selected_jid() {
-                    state.active_conversation = Some(jid.clone());
-                    state.scroll_offset = 0;
-                    state.ensure_conversation(&jid);
-                    state.mark_conversation_read(&jid);
-                    return Action::OpenConversation(jid);
-                }
+            if state.focused_panel == Panel::Sidebar
+                && let Some(jid) = state.selected_jid()
+            {
+                state.open_conversation(&jid);
+                return Action::OpenConversation(jid);
             }
             Action::None
         }
@@ -105,22 +130,35 @@ fn handle_insert_mode(state: &mut AppState, key: KeyEvent) -> Action {
         }
         KeyCode::Char(c) => {
             state.input_buffer.push(c);
-            Action::None
+            // Rooms get no typing notifications.
+            match state.active_conversation.clone() {
+                Some(jid) if !state.is_room(&jid) => Action::Compose { jid },
+                _ => Action::None,
+            }
         }
         _ => Action::None,
     }
 }
 
+fn scroll_up(state: &mut AppState, lines: u16) -> Action {
+    state.scroll_offset = state.scroll_offset.saturating_add(lines);
+    match state.take_history_request() {
+        Some(jid) => Action::LoadHistory(jid),
+        None => Action::None,
+    }
+}
+
 fn handle_command_mode(state: &mut AppState, key: KeyEvent) -> Action {
     match key.code {
         KeyCode::Esc => {
             state.input_mode = InputMode::Normal;
-            state.input_buffer.clear();
+            state.restore_draft();
             Action::None
         }
         KeyCode::Enter => {
             let cmd = state.input_buffer.drain(..).collect::<String>();
             state.input_mode = InputMode::Normal;
+            state.restore_draft();
             if cmd.is_empty() {
                 return Action::None;
             }
@@ -130,6 +168,7 @@ fn handle_command_mode(state: &mut AppState, key: KeyEvent) -> Action {
             state.input_buffer.pop();
             if state.input_buffer.is_empty() {
                 state.input_mode = InputMode::Normal;
+                state.restore_draft();
             }
             Action::None
         }
@@ -140,3 +179,50 @@ fn handle_command_mode(state: &mut AppState, key: KeyEvent) -> Action {
         _ => Action::None,
     }
 }
+
+fn handle_palette_mode(state: &mut AppState, key: KeyEvent) -> Action {
+    match key.code {
+        KeyCode::Esc => {
+            state.input_mode = InputMode::Normal;
+            state.restore_draft();
+            Action::None
+        }
+        KeyCode::Up => {
+            state.palette_index = state.palette_index.saturating_sub(1);
+            Action::None
+        }
+        KeyCode::Down => {
+            let count = palette::matching(&state.input_buffer).len();
+            if state.palette_index + 1 < count {
+                state.palette_index += 1;
+            }
+            Action::None
+        }
+        KeyCode::Enter => {
+            let matches = palette::matching(&state.input_buffer);
+            let Some(command) = matches.get(state.palette_index) else {
+                return Action::None;
+            };
+            if command.takes_args {
+                state.input_mode = InputMode::Command;
+                state.input_buffer = format!("{} ", command.name);
+                Action::None
+            } else {
+                state.input_mode = InputMode::Normal;
+                state.restore_draft();
+                Action::ExecuteCommand(command.name.to_string())
+            }
+        }
+        KeyCode::Backspace => {
+            state.input_buffer.pop();
+            state.palette_index = 0;
+            Action::None
+        }
+        KeyCode::Char(c) => {
+            state.input_buffer.push(c);
+            state.palette_index = 0;
+            Action::None
+        }
+        _ => Action::None,
+    }
+}
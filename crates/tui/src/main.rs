@@ -1,13 +1,14 @@
 mod app;
 mod error;
 mod input;
+mod palette;
 mod state;
 mod ui;
 
 use std::sync::Arc;
 
 use waddle_core::config;
-use waddle_core::event::BroadcastEventBus;
+use waddle_core::event::{BroadcastEventBus, channels};
 
 #[tokio::main]
 async fn main() {
@@ -21,6 +22,11 @@ async fn main() {
         }
     };
 
+    if let Err(e) = channels::validate() {
+        eprintln!("Invalid channel registry: {e}");
+        std::process::exit(1);
+    }
+
     let event_bus = Arc::new(BroadcastEventBus::new(config.event_bus.channel_capacity));
 
     if let Err(e) = app::TuiApp::run(event_bus, &config).await {
@@ -0,0 +1,54 @@
+//! The command palette: the `:` commands, listed and filtered as the user
+//! types, for when the name of one has slipped their mind.
+
+pub struct PaletteCommand {
+    pub name: &'static str,
+    /// Message id of the one-line description.
+    pub description: &'static str,
+    /// Whether choosing the command leaves the user to type its arguments.
+    pub takes_args: bool,
+}
+
+pub const COMMANDS: &[PaletteCommand] = &[
+    PaletteCommand {
+        name: "help",
+        description: "cmd-help",
+        takes_args: false,
+    },
+    PaletteCommand {
+        name: "status",
+        description: "cmd-status",
+        takes_args: true,
+    },
+    PaletteCommand {
+        name: "join",
+        description: "cmd-join",
+        takes_args: true,
+    },
+    PaletteCommand {
+        name: "leave",
+        description: "cmd-leave",
+        takes_args: false,
+    },
+    PaletteCommand {
+        name: "theme",
+        description: "cmd-theme",
+        takes_args: true,
+    },
+    PaletteCommand {
+        name: "quit",
+        description: "cmd-quit",
+        takes_args: false,
+    },
+];
+
+/// The commands whose name contains `filter`, those starting with it first.
+pub fn matching(filter: &str) -> Vec<&'static PaletteCommand> {
+    let filter = filter.trim().to_ascii_lowercase();
+    let mut matches: Vec<&PaletteCommand> = COMMANDS
+        .iter()
+        .filter(|command| command.name.contains(&filter))
+        .collect();
+    matches.sort_by_key(|command| !command.name.starts_with(&filter));
+    matches
+}
@@ -1,6 +1,6 @@
 use std::collections::{HashMap, HashSet};
 
-use waddle_core::event::{ChatMessage, ChatState, PresenceShow, RosterItem};
+use waddle_core::event::{ChatMessage, ChatState, MucOccupant, PresenceShow, RosterItem};
 use waddle_core::i18n::I18n;
 use waddle_core::theme::{Theme, ThemeManager};
 
@@ -40,6 +40,10 @@ pub struct MucRoom {
 pub struct Conversation {
     pub messages: Vec<ChatMessage>,
     pub remote_chat_state: Option<ChatState>,
+    /// An older page of history has been requested and not yet arrived.
+    pub loading_history: bool,
+    /// The archive has no messages older than the first one here.
+    pub history_complete: bool,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -47,6 +51,7 @@ pub enum InputMode {
     Normal,
     Insert,
     Command,
+    Palette,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -65,6 +70,12 @@ pub struct AppState {
     pub connection_status: ConnectionStatus,
     pub input_mode: InputMode,
     pub input_buffer: String,
+    /// Unsent compose text of the conversations not currently open.
+    pub drafts: HashMap<String, String>,
+    /// Occupants of each joined room, in the order they arrived.
+    pub occupants: HashMap<String, Vec<MucOccupant>>,
+    /// The highlighted entry of the command palette.
+    pub palette_index: usize,
     pub focused_panel: Panel,
     pub sidebar_index: usize,
     pub scroll_offset: u16,
@@ -87,6 +98,9 @@ impl AppState {
             connection_status: ConnectionStatus::Disconnected,
             input_mode: InputMode::Normal,
             input_buffer: String::new(),
+            drafts: HashMap::new(),
+            occupants: HashMap::new(),
+            palette_index: 0,
             focused_panel: Panel::Sidebar,
             sidebar_index: 0,
             scroll_offset: 0,
@@ -121,6 +135,62 @@ impl AppState {
             .and_then(|jid| self.conversations.get(jid))
     }
 
+    pub fn is_room(&self, jid: &str) -> bool {
+        self.rooms.iter().any(|room| room.jid == jid)
+    }
+
+    /// Switch to the conversation with `jid`, keeping what was typed in the
+    /// previous one as its draft and restoring this one's.
+    pub fn open_conversation(&mut self, jid: &str) {
+        if self.active_conversation.as_deref() != Some(jid) {
+            self.stash_draft();
+            self.active_conversation = Some(jid.to_string());
+            self.restore_draft();
+        }
+        self.scroll_offset = 0;
+        self.ensure_conversation(jid);
+        self.mark_conversation_read(jid);
+    }
+
+    /// Move the compose text out of the input line into the active
+    /// conversation's draft, freeing the line for a command.
+    pub fn stash_draft(&mut self) {
+        let draft = std::mem::take(&mut self.input_buffer);
+        if let Some(jid) = &self.active_conversation
+            && !draft.is_empty()
+        {
+            self.drafts.insert(jid.clone(), draft);
+        }
+    }
+
+    /// Put the active conversation's draft back in the input line.
+    pub fn restore_draft(&mut self) {
+        self.input_buffer = self
+            .active_conversation
+            .as_ref()
+            .and_then(|jid| self.drafts.remove(jid))
+            .unwrap_or_default();
+    }
+
+    /// The active conversation, if its view has scrolled up to the oldest
+    /// loaded message and older history may exist. Marks the page as
+    /// requested so it is asked for once.
+    pub fn take_history_request(&mut self) -> Option<String> {
+        let jid = self.active_conversation.clone()?;
+        let scroll_offset = usize::from(self.scroll_offset);
+        let conversation = self.conversations.get_mut(&jid)?;
+        // Every message takes at least one line, so this is reached at the
+        // latest when the first message scrolls into view.
+        if conversation.loading_history
+            || conversation.history_complete
+            || scroll_offset < conversation.messages.len()
+        {
+            return None;
+        }
+        conversation.loading_history = true;
+        Some(jid)
+    }
+
     pub fn ensure_conversation(&mut self, jid: &str) -> &mut Conversation {
         self.conversations.entry(jid.to_string()).or_default()
     }
@@ -3,11 +3,15 @@ use ratatui::{
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, Paragraph, Wrap},
+    widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Wrap},
 };
 
+use crate::palette;
 use crate::state::{AppState, ConnectionStatus, InputMode, Panel};
-use waddle_core::event::PresenceShow;
+use waddle_core::color::{self, ColorPalette};
+use waddle_core::event::{ChatMessage, MessageType, MucOccupant, MucRole, PresenceShow};
+use waddle_core::jid::Jid;
+use waddle_core::styling::{self, InlineStyle};
 use waddle_core::theme::Theme;
 
 struct Palette {
@@ -24,6 +28,7 @@ struct Palette {
     status_bar_bg: Color,
     input_border: Color,
     unread_badge: Color,
+    nicks: ColorPalette,
 }
 
 impl Palette {
@@ -81,8 +86,24 @@ impl Palette {
             status_bar_bg,
             input_border,
             unread_badge,
+            nicks: ColorPalette::for_theme(theme),
         }
     }
+
+    /// The XEP-0392 color of `msg`'s sender: its nick in rooms, its bare
+    /// JID otherwise.
+    fn sender_color(&self, msg: &ChatMessage) -> Color {
+        let jid = Jid::new(&msg.from);
+        let identifier = match (&msg.message_type, jid.resource()) {
+            (MessageType::Groupchat, Some(nick)) => nick.to_string(),
+            _ => jid.bare().into_string(),
+        };
+        if identifier.is_empty() {
+            return self.accent;
+        }
+        let rgb = color::consistent_color_with(&identifier, &self.nicks);
+        Color::Rgb(rgb.r, rgb.g, rgb.b)
+    }
 }
 
 fn parse_theme_override(value: Option<&str>, fallback: Color) -> Color {
@@ -139,9 +160,132 @@ pub fn draw(frame: &mut Frame, state: &AppState) {
         .split(chunks[0]);
 
     draw_sidebar(frame, state, main_chunks[0], &palette);
-    draw_conversation(frame, state, main_chunks[1], &palette);
+    let occupants = state
+        .active_conversation
+        .as_ref()
+        .filter(|jid| state.is_room(jid))
+        .map(|jid| {
+            state
+                .occupants
+                .get(jid)
+                .map(Vec::as_slice)
+                .unwrap_or_default()
+        });
+    match occupants {
+        Some(occupants) => {
+            let room_chunks = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Min(20), Constraint::Length(OCCUPANTS_WIDTH)])
+                .split(main_chunks[1]);
+            draw_conversation(frame, state, room_chunks[0], &palette);
+            draw_occupants(frame, state, occupants, room_chunks[1], &palette);
+        }
+        None => draw_conversation(frame, state, main_chunks[1], &palette),
+    }
     draw_status_bar(frame, state, chunks[1], &palette);
     draw_input(frame, state, chunks[2], &palette);
+    if state.input_mode == InputMode::Palette {
+        draw_palette(frame, state, chunks[0], &palette);
+    }
+}
+
+/// Width of the occupant list beside a room's messages.
+const OCCUPANTS_WIDTH: u16 = 24;
+
+fn draw_occupants(
+    frame: &mut Frame,
+    state: &AppState,
+    occupants: &[MucOccupant],
+    area: Rect,
+    palette: &Palette,
+) {
+    let block = Block::default()
+        .title(format!(
+            " {} ({}) ",
+            state.i18n.t("occupants-title", None),
+            occupants.len()
+        ))
+        .borders(Borders::ALL)
+        .style(Style::default().bg(palette.surface).fg(palette.foreground))
+        .border_style(Style::default().fg(palette.border));
+
+    // Moderators first, then participants, then visitors.
+    let mut sorted: Vec<&MucOccupant> = occupants.iter().collect();
+    sorted.sort_by_key(|occupant| match occupant.role {
+        MucRole::Moderator => 0,
+        MucRole::Participant => 1,
+        MucRole::Visitor | MucRole::None => 2,
+    });
+
+    let items: Vec<ListItem> = sorted
+        .into_iter()
+        .map(|occupant| {
+            let marker = match occupant.role {
+                MucRole::Moderator => "@",
+                MucRole::Participant => " ",
+                MucRole::Visitor | MucRole::None => "-",
+            };
+            let rgb = color::consistent_color_with(&occupant.nick, &palette.nicks);
+            ListItem::new(Line::from(vec![
+                Span::styled(marker, Style::default().fg(palette.muted)),
+                Span::styled(
+                    occupant.nick.clone(),
+                    Style::default().fg(Color::Rgb(rgb.r, rgb.g, rgb.b)),
+                ),
+            ]))
+        })
+        .collect();
+
+    frame.render_widget(List::new(items).block(block), area);
+}
+
+/// The command palette, over the bottom of `area`.
+fn draw_palette(frame: &mut Frame, state: &AppState, area: Rect, palette: &Palette) {
+    let matches = palette::matching(&state.input_buffer);
+    let height = (matches.len().max(1) as u16 + 2).min(area.height);
+    let popup = Rect {
+        x: area.x + area.width / 4,
+        y: area.y + area.height - height,
+        width: area.width / 2,
+        height,
+    };
+
+    let block = Block::default()
+        .title(format!(" {} ", state.i18n.t("palette-title", None)))
+        .borders(Borders::ALL)
+        .style(Style::default().bg(palette.surface).fg(palette.foreground))
+        .border_style(Style::default().fg(palette.accent));
+
+    let mut items: Vec<ListItem> = matches
+        .iter()
+        .enumerate()
+        .map(|(i, command)| {
+            let item = ListItem::new(Line::from(vec![
+                Span::styled(
+                    format!("{:<8}", command.name),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ),
+                Span::styled(
+                    state.i18n.t(command.description, None),
+                    Style::default().fg(palette.muted),
+                ),
+            ]));
+            if i == state.palette_index {
+                item.style(Style::default().bg(palette.roster_highlight))
+            } else {
+                item
+            }
+        })
+        .collect();
+    if items.is_empty() {
+        items.push(ListItem::new(Span::styled(
+            state.i18n.t("palette-empty", None),
+            Style::default().fg(palette.muted),
+        )));
+    }
+
+    frame.render_widget(Clear, popup);
+    frame.render_widget(List::new(items).block(block), popup);
 }
 
 fn presence_indicator(show: &PresenceShow, palette: &Palette) -> (&'static str, Color) {
@@ -150,6 +294,7 @@ fn presence_indicator(show: &PresenceShow, palette: &Palette) -> (&'static str,
         PresenceShow::Away => ("●", palette.warning),
         PresenceShow::Xa => ("●", palette.warning),
         PresenceShow::Dnd => ("●", palette.error),
+        PresenceShow::Invisible => ("◌", palette.muted),
         PresenceShow::Unavailable => ("○", palette.muted),
     }
 }
@@ -289,31 +434,57 @@ fn draw_conversation(frame: &mut Frame, state: &AppState, area: Rect, palette: &
     match conv {
         Some(conversation) if !conversation.messages.is_empty() => {
             let mut lines: Vec<Line> = Vec::new();
+            let history_note = if conversation.loading_history {
+                Some("conversation-loading-history")
+            } else if conversation.history_complete {
+                Some("conversation-history-start")
+            } else {
+                None
+            };
+            if let Some(note) = history_note {
+                lines.push(Line::from(Span::styled(
+                    state.i18n.t(note, None),
+                    Style::default()
+                        .fg(palette.muted)
+                        .add_modifier(Modifier::ITALIC),
+                )));
+            }
 
             for msg in &conversation.messages {
                 let time = msg.timestamp.format("%H:%M");
                 let sender = msg.from.split('@').next().unwrap_or(&msg.from);
                 let sender = if sender.is_empty() { "you" } else { sender };
 
-                let mut spans = vec![
+                let mut body_lines = Vec::new();
+                push_body_lines(&mut body_lines, &msg.styled_body(), "", palette);
+                let mut body_lines = body_lines.into_iter();
+
+                let mut message_lines = vec![vec![
                     Span::styled(format!("{time} "), Style::default().fg(palette.muted)),
                     Span::styled(
                         format!("{sender}: "),
                         Style::default()
-                            .fg(palette.accent)
+                            .fg(palette.sender_color(msg))
                             .add_modifier(Modifier::BOLD),
                     ),
-                    Span::raw(&msg.body),
-                ];
-
-                if state.delivered_message_ids.contains(&msg.id) {
-                    spans.push(Span::styled(
+                ]];
+                message_lines[0].extend(body_lines.next().unwrap_or_default());
+                message_lines.extend(body_lines.map(|line| {
+                    let mut indented = vec![Span::raw("  ")];
+                    indented.extend(line);
+                    indented
+                }));
+
+                if state.delivered_message_ids.contains(&msg.id)
+                    && let Some(last) = message_lines.last_mut()
+                {
+                    last.push(Span::styled(
                         format!(" [{}]", state.i18n.t("message-delivered", None)),
                         Style::default().fg(palette.muted),
                     ));
                 }
 
-                lines.push(Line::from(spans));
+                lines.extend(message_lines.into_iter().map(Line::from));
 
                 // Render plugin embeds as inline cards below the message
                 for embed in &msg.embeds {
@@ -330,15 +501,15 @@ fn draw_conversation(frame: &mut Frame, state: &AppState, area: Rect, palette: &
             let visible_lines: Vec<Line> = lines.into_iter().skip(skip).collect();
 
             let mut typing_lines = visible_lines;
-            if let Some(chat_state) = &conversation.remote_chat_state {
-                if matches!(chat_state, waddle_core::event::ChatState::Composing) {
-                    typing_lines.push(Line::from(Span::styled(
-                        state.i18n.t("chatstate-typing", None),
-                        Style::default()
-                            .fg(palette.muted)
-                            .add_modifier(Modifier::ITALIC),
-                    )));
-                }
+            if let Some(chat_state) = &conversation.remote_chat_state
+                && matches!(chat_state, waddle_core::event::ChatState::Composing)
+            {
+                typing_lines.push(Line::from(Span::styled(
+                    state.i18n.t("chatstate-typing", None),
+                    Style::default()
+                        .fg(palette.muted)
+                        .add_modifier(Modifier::ITALIC),
+                )));
             }
 
             let paragraph = Paragraph::new(typing_lines)
@@ -411,6 +582,7 @@ fn draw_input(frame: &mut Frame, state: &AppState, area: Rect, palette: &Palette
         InputMode::Normal => (state.i18n.t("mode-normal", None), palette.input_border),
         InputMode::Insert => (state.i18n.t("mode-insert", None), palette.success),
         InputMode::Command => (state.i18n.t("mode-command", None), palette.warning),
+        InputMode::Palette => (state.i18n.t("mode-palette", None), palette.accent),
     };
 
     let prefix = match state.input_mode {
@@ -444,6 +616,65 @@ const MAX_EMBED_LINES: usize = 20;
 /// If a plugin runtime is available and has a `render_tui` export, it would
 /// be called here. For now we render a built-in card for known namespaces
 /// and a generic fallback for unknown ones.
+/// Append the lines of a message body with its XEP-0393 styling applied.
+/// Directives are hidden, quotes get a bar per level and preformatted text
+/// is muted.
+fn push_body_lines(
+    lines: &mut Vec<Vec<Span<'static>>>,
+    blocks: &[styling::Block],
+    prefix: &str,
+    palette: &Palette,
+) {
+    let quote_bar = || Span::styled(prefix.to_string(), Style::default().fg(palette.muted));
+    for block in blocks {
+        match block {
+            styling::Block::Line { spans } => {
+                let mut line = vec![quote_bar()];
+                for span in spans {
+                    push_styled_spans(&mut line, span, Style::default(), palette);
+                }
+                lines.push(line);
+            }
+            styling::Block::Preformatted { text } => {
+                for text_line in text.split('\n') {
+                    lines.push(vec![
+                        quote_bar(),
+                        Span::styled(text_line.to_string(), Style::default().fg(palette.muted)),
+                    ]);
+                }
+            }
+            styling::Block::Quote { blocks } => {
+                push_body_lines(lines, blocks, &format!("{prefix}│ "), palette);
+            }
+        }
+    }
+}
+
+fn push_styled_spans(
+    line: &mut Vec<Span<'static>>,
+    span: &styling::Span,
+    style: Style,
+    palette: &Palette,
+) {
+    match span {
+        styling::Span::Text { text } => line.push(Span::styled(text.clone(), style)),
+        styling::Span::Styled {
+            style: inline,
+            children,
+        } => {
+            let style = match inline {
+                InlineStyle::Strong => style.add_modifier(Modifier::BOLD),
+                InlineStyle::Emphasis => style.add_modifier(Modifier::ITALIC),
+                InlineStyle::Strike => style.add_modifier(Modifier::CROSSED_OUT),
+                InlineStyle::Preformatted => style.fg(palette.muted),
+            };
+            for child in children {
+                push_styled_spans(line, child, style, palette);
+            }
+        }
+    }
+}
+
 fn render_embed_lines(
     lines: &mut Vec<Line<'_>>,
     embed: &waddle_core::event::MessageEmbed,
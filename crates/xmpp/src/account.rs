@@ -0,0 +1,310 @@
+//! Changing the password of, and deleting, the logged-in account
+//! (XEP-0077 §3.2 and §3.3).
+//!
+//! Requests are written straight to the wire instead of going through the
+//! stanza pipeline, so a new password never reaches plugin hooks or the
+//! stanza debugger. The server's answer comes back through
+//! [`AccountProcessor`](crate::AccountProcessor) as `xmpp.account.answered`.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use uuid::Uuid;
+use waddle_core::credentials::CredentialStore;
+use waddle_core::error::EventBusError;
+use waddle_core::event::{Channel, Event, EventBus, EventPayload, EventSource, channels};
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::BareJid;
+use xmpp_parsers::minidom::Element;
+
+use crate::error::ConnectionError;
+use crate::outbound::StanzaSender;
+use crate::processors::ACCOUNT_IQ_ID_PREFIX;
+use crate::registration::{REGISTER_NS, registration_error};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct AccountManager {
+    jid: String,
+    credentials: Arc<dyn CredentialStore>,
+    event_bus: Arc<dyn EventBus>,
+    wire_sender: StanzaSender,
+    request_timeout: Duration,
+}
+
+impl AccountManager {
+    pub fn new(
+        jid: impl Into<String>,
+        credentials: Arc<dyn CredentialStore>,
+        event_bus: Arc<dyn EventBus>,
+        wire_sender: StanzaSender,
+    ) -> Self {
+        Self {
+            jid: jid.into(),
+            credentials,
+            event_bus,
+            wire_sender,
+            request_timeout: REQUEST_TIMEOUT,
+        }
+    }
+
+    /// Change the password on the server, then in the credential store.
+    /// Publishes `system.account.password_changed`.
+    pub async fn change_password(&self, new_password: &str) -> Result<(), ConnectionError> {
+        let jid = self.bare_jid()?;
+        let username = jid.node().map(|node| node.to_string()).unwrap_or_default();
+        let query = Element::builder("query", REGISTER_NS)
+            .append(Element::builder("username", REGISTER_NS).append(username))
+            .append(Element::builder("password", REGISTER_NS).append(new_password))
+            .build();
+        self.request(&jid, query).await?;
+
+        let credentials = self.credentials.clone();
+        let account = self.jid.clone();
+        let secret = new_password.to_string();
+        tokio::task::spawn_blocking(move || credentials.set(&account, &secret))
+            .await
+            .map_err(|error| ConnectionError::CredentialUnavailable(error.to_string()))?
+            .map_err(|error| ConnectionError::CredentialUnavailable(error.to_string()))?;
+
+        self.emit(
+            channels::SYSTEM_ACCOUNT_PASSWORD_CHANGED,
+            EventPayload::AccountPasswordChanged {
+                jid: self.jid.clone(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Delete the account from the server. The session is then closed and
+    /// the password removed from the credential store; local data is left
+    /// for the caller to wipe. Publishes `system.account.deleted`.
+    pub async fn delete_account(&self) -> Result<(), ConnectionError> {
+        let jid = self.bare_jid()?;
+        let query = Element::builder("query", REGISTER_NS)
+            .append(Element::builder("remove", REGISTER_NS))
+            .build();
+        self.request(&jid, query).await?;
+
+        let credentials = self.credentials.clone();
+        let account = self.jid.clone();
+        // The account is already gone; a password left behind is harmless.
+        let _ = tokio::task::spawn_blocking(move || credentials.delete(&account)).await;
+
+        self.emit(
+            channels::SYSTEM_ACCOUNT_DELETED,
+            EventPayload::AccountDeleted {
+                jid: self.jid.clone(),
+            },
+        );
+        // The server closes the stream after a deletion; disconnecting
+        // first keeps that from being treated as a lost connection.
+        self.emit(channels::SYSTEM_GOING_OFFLINE, EventPayload::GoingOffline);
+        Ok(())
+    }
+
+    fn bare_jid(&self) -> Result<BareJid, ConnectionError> {
+        self.jid.parse().map_err(|error| {
+            ConnectionError::TransportError(format!(
+                "invalid JID '{}' in config: {error}",
+                self.jid
+            ))
+        })
+    }
+
+    async fn request(&self, jid: &BareJid, query: Element) -> Result<(), ConnectionError> {
+        let request_id = format!("{ACCOUNT_IQ_ID_PREFIX}{}", Uuid::new_v4());
+        // Subscribed before sending so the answer cannot be missed.
+        let mut answers = self
+            .event_bus
+            .subscribe(channels::XMPP_ACCOUNT_ANSWERED)
+            .map_err(|error| ConnectionError::TransportError(error.to_string()))?;
+
+        let mut stanza = Vec::new();
+        // Writing to a Vec cannot fail.
+        let _ = Element::from(Iq::Set {
+            from: None,
+            to: jid.domain().as_str().parse().ok(),
+            id: request_id.clone(),
+            payload: query,
+        })
+        .write_to(&mut stanza);
+        self.wire_sender
+            .send(stanza)
+            .await
+            .map_err(|_| ConnectionError::TransportError("not connected".to_string()))?;
+
+        let answer = tokio::time::timeout(self.request_timeout, async {
+            loop {
+                match answers.recv().await {
+                    Ok(Event {
+                        payload:
+                            EventPayload::AccountRequestAnswered {
+                                request_id: id,
+                                error,
+                            },
+                        ..
+                    }) if id == request_id => return Ok(error),
+                    Ok(_) | Err(EventBusError::Lagged(_)) => {}
+                    Err(error) => return Err(ConnectionError::TransportError(error.to_string())),
+                }
+            }
+        })
+        .await
+        .map_err(|_| ConnectionError::Timeout)??;
+
+        match answer {
+            Some(reason) => Err(registration_error(reason)),
+            None => Ok(()),
+        }
+    }
+
+    fn emit(&self, channel: &str, payload: EventPayload) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channel).unwrap(),
+            EventSource::Xmpp,
+            payload,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use waddle_core::credentials::InMemoryCredentialStore;
+    use waddle_core::event::BroadcastEventBus;
+
+    use super::*;
+    use crate::outbound::{StanzaReceiver, stanza_channel};
+    use crate::pipeline::{ProcessorContext, StanzaDirection, StanzaProcessor};
+    use crate::processors::AccountProcessor;
+    use crate::stanza::Stanza;
+
+    /// Answer the next request on the wire through an `AccountProcessor`,
+    /// with `error` as the stanza error condition if given. Returns the
+    /// request.
+    fn serve_one(
+        mut wire: StanzaReceiver,
+        event_bus: Arc<dyn EventBus>,
+        error: Option<&'static str>,
+    ) -> tokio::task::JoinHandle<Element> {
+        tokio::spawn(async move {
+            let bytes = wire.recv().await.unwrap();
+            let request = Element::from_str(std::str::from_utf8(&bytes).unwrap()).unwrap();
+            let id = request.attr("id").unwrap();
+            let reply = match error {
+                None => format!("<iq xmlns='jabber:client' type='result' id='{id}'/>"),
+                Some(condition) => format!(
+                    "<iq xmlns='jabber:client' type='error' id='{id}'>\
+                        <error type='cancel'>\
+                            <{condition} xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/>\
+                        </error>\
+                    </iq>"
+                ),
+            };
+            let mut stanza = Stanza::parse(reply.as_bytes()).unwrap();
+            AccountProcessor::new(event_bus).process_inbound(
+                &mut stanza,
+                &ProcessorContext {
+                    direction: StanzaDirection::Inbound,
+                },
+            );
+            request
+        })
+    }
+
+    fn manager() -> (
+        AccountManager,
+        Arc<InMemoryCredentialStore>,
+        Arc<dyn EventBus>,
+        StanzaReceiver,
+    ) {
+        let credentials = Arc::new(InMemoryCredentialStore::with_secret(
+            "alice@example.com",
+            "old",
+        ));
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let (wire_sender, wire) = stanza_channel(8);
+        let manager = AccountManager::new(
+            "alice@example.com",
+            credentials.clone(),
+            event_bus.clone(),
+            wire_sender,
+        );
+        (manager, credentials, event_bus, wire)
+    }
+
+    #[tokio::test]
+    async fn change_password_updates_the_credential_store() {
+        let (manager, credentials, event_bus, wire) = manager();
+        let mut events = event_bus.subscribe("system.account.**").unwrap();
+        let server = serve_one(wire, event_bus.clone(), None);
+
+        manager.change_password("new").await.unwrap();
+
+        let request = server.await.unwrap();
+        assert_eq!(request.attr("to"), Some("example.com"));
+        let query = request.get_child("query", REGISTER_NS).unwrap();
+        assert_eq!(
+            query.get_child("username", REGISTER_NS).unwrap().text(),
+            "alice"
+        );
+        assert_eq!(
+            query.get_child("password", REGISTER_NS).unwrap().text(),
+            "new"
+        );
+        assert_eq!(credentials.get("alice@example.com").unwrap(), "new");
+        assert!(matches!(
+            events.recv().await.unwrap().payload,
+            EventPayload::AccountPasswordChanged { jid } if jid == "alice@example.com"
+        ));
+    }
+
+    #[tokio::test]
+    async fn refused_deletion_keeps_the_session_and_password() {
+        let (manager, credentials, event_bus, wire) = manager();
+        let server = serve_one(wire, event_bus.clone(), Some("not-allowed"));
+
+        let error = manager.delete_account().await.unwrap_err();
+
+        assert!(
+            matches!(error, ConnectionError::RegistrationRejected(reason) if reason == "not-allowed")
+        );
+        let request = server.await.unwrap();
+        let query = request.get_child("query", REGISTER_NS).unwrap();
+        assert!(query.get_child("remove", REGISTER_NS).is_some());
+        assert_eq!(credentials.get("alice@example.com").unwrap(), "old");
+    }
+
+    #[tokio::test]
+    async fn deletion_removes_the_password_and_goes_offline() {
+        let (manager, credentials, event_bus, wire) = manager();
+        let mut events = event_bus.subscribe("system.**").unwrap();
+        let server = serve_one(wire, event_bus.clone(), None);
+
+        manager.delete_account().await.unwrap();
+        server.await.unwrap();
+
+        assert!(credentials.get("alice@example.com").is_err());
+        assert!(matches!(
+            events.recv().await.unwrap().payload,
+            EventPayload::AccountDeleted { .. }
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap().payload,
+            EventPayload::GoingOffline
+        ));
+    }
+
+    #[tokio::test]
+    async fn unanswered_requests_time_out() {
+        let (mut manager, _credentials, _event_bus, _wire) = manager();
+        manager.request_timeout = Duration::from_millis(20);
+
+        assert!(matches!(
+            manager.change_password("new").await,
+            Err(ConnectionError::Timeout)
+        ));
+    }
+}
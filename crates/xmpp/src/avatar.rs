@@ -0,0 +1,123 @@
+//! XEP-0084 avatars. Contacts announce their avatar in a PEP node, naming
+//! it by the SHA-1 hash of the image, and publish the image itself in
+//! another.
+
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+use sha1::{Digest, Sha1};
+use waddle_core::event::PubSubItem;
+use xmpp_parsers::minidom::Element;
+use xmpp_parsers::minidom::rxml::xml_ncname;
+
+use crate::pubsub::item_payload;
+
+/// PEP node announcing the avatar, and the namespace of its payloads.
+pub const AVATAR_METADATA_NODE: &str = "urn:xmpp:avatar:metadata";
+
+/// PEP node holding the image, and the namespace of its payloads.
+pub const AVATAR_DATA_NODE: &str = "urn:xmpp:avatar:data";
+
+/// The id an avatar is published under: the hex SHA-1 of the image.
+pub fn avatar_id(image: &[u8]) -> String {
+    format!("{:x}", Sha1::digest(image))
+}
+
+/// The MIME type of an image, going by its first bytes. Anything else is
+/// announced as PNG, the one type every client must support.
+pub fn image_type(image: &[u8]) -> &'static str {
+    if image.starts_with(&[0xff, 0xd8, 0xff]) {
+        "image/jpeg"
+    } else if image.starts_with(b"GIF8") {
+        "image/gif"
+    } else if image.starts_with(b"RIFF") && image.get(8..12) == Some(b"WEBP") {
+        "image/webp"
+    } else {
+        "image/png"
+    }
+}
+
+/// The data item carrying `image`, published under [`avatar_id`].
+pub fn avatar_data_element(image: &[u8]) -> Element {
+    Element::builder("data", AVATAR_DATA_NODE)
+        .append(STANDARD.encode(image))
+        .build()
+}
+
+/// The metadata item announcing `image`, published under [`avatar_id`]
+/// once its data item is. `None` announces that there is no avatar.
+pub fn avatar_metadata_element(image: Option<&[u8]>) -> Element {
+    let metadata = Element::builder("metadata", AVATAR_METADATA_NODE);
+    let Some(image) = image else {
+        return metadata.build();
+    };
+    let info = Element::builder("info", AVATAR_METADATA_NODE)
+        .attr(xml_ncname!("id").to_owned(), avatar_id(image))
+        .attr(xml_ncname!("bytes").to_owned(), image.len().to_string())
+        .attr(xml_ncname!("type").to_owned(), image_type(image))
+        .build();
+    metadata.append(info).build()
+}
+
+/// The hash of the avatar a metadata item announces, or `None` when it
+/// announces none: an empty `<metadata/>` disables the avatar.
+pub fn avatar_hash(item: &PubSubItem) -> Option<String> {
+    let metadata = item_payload(item)?;
+    if !metadata.is("metadata", AVATAR_METADATA_NODE) {
+        return None;
+    }
+    metadata
+        .get_child("info", AVATAR_METADATA_NODE)?
+        .attr("id")
+        .map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(payload: &str) -> PubSubItem {
+        PubSubItem {
+            id: Some("111f4b3c50d7b0df729d299bc6f8e9ef9066971f".to_string()),
+            publisher: None,
+            payload: Some(payload.to_string()),
+        }
+    }
+
+    #[test]
+    fn reads_the_hash_of_the_announced_avatar() {
+        let announced = item(
+            "<metadata xmlns='urn:xmpp:avatar:metadata'>\
+                <info id='111f4b3c50d7b0df729d299bc6f8e9ef9066971f' bytes='12345' \
+                    type='image/png' height='64' width='64'/>\
+            </metadata>",
+        );
+        assert_eq!(
+            avatar_hash(&announced).as_deref(),
+            Some("111f4b3c50d7b0df729d299bc6f8e9ef9066971f")
+        );
+
+        let disabled = item("<metadata xmlns='urn:xmpp:avatar:metadata'/>");
+        assert_eq!(avatar_hash(&disabled), None);
+    }
+
+    #[test]
+    fn published_metadata_names_the_image_by_its_hash() {
+        let image = b"\x89PNG\r\n\x1a\nnot really an image";
+        let metadata = avatar_metadata_element(Some(image));
+        let info = metadata.get_child("info", AVATAR_METADATA_NODE).unwrap();
+        assert_eq!(info.attr("bytes"), Some(image.len().to_string().as_str()));
+        assert_eq!(info.attr("type"), Some("image/png"));
+        assert_eq!(
+            avatar_hash(&item(&String::from(&metadata))),
+            Some(avatar_id(image))
+        );
+        assert_eq!(
+            avatar_hash(&item(&String::from(&avatar_metadata_element(None)))),
+            None
+        );
+
+        let data = avatar_data_element(image);
+        assert_eq!(STANDARD.decode(data.text()).unwrap(), image);
+        assert_eq!(image_type(&[0xff, 0xd8, 0xff, 0xe0]), "image/jpeg");
+    }
+}
@@ -1,13 +1,15 @@
+use std::sync::Arc;
 use std::time::Duration;
 
-#[cfg(feature = "native")]
-use std::sync::Arc;
+use waddle_core::credentials::CredentialStore;
+use waddle_core::event::ConnectionHealth;
 
 pub use crate::transport::ConnectionConfig;
 use crate::{
     carbons::{CarbonsManager, CarbonsState, is_carbons_iq_response},
     csi::{ClientState, CsiManager},
     error::ConnectionError,
+    health::HealthMonitor,
     stream_management::{
         StreamManagementAction, StreamManagementState, StreamManager, decode_nonza, encode_nonza,
     },
@@ -15,16 +17,12 @@ use crate::{
 };
 
 #[cfg(feature = "native")]
-use waddle_core::event::{Channel, Event, EventBus, EventPayload, EventSource};
+use waddle_core::event::{Channel, Event, EventBus, EventPayload, EventSource, channels};
 
 #[cfg(not(any(feature = "native", feature = "web")))]
 compile_error!("waddle-xmpp requires either the `native` or `web` feature.");
 
-#[cfg(feature = "native")]
-type DefaultTransport = crate::transport::NativeTcpTransport;
-
-#[cfg(all(feature = "web", not(feature = "native")))]
-type DefaultTransport = crate::transport::WebSocketTransport;
+type DefaultTransport = crate::transport::FallbackTransport;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ConnectionState {
@@ -40,10 +38,12 @@ where
 {
     state: ConnectionState,
     config: ConnectionConfig,
+    credentials: Arc<dyn CredentialStore>,
     transport: Option<T>,
     stream_manager: StreamManager,
     carbons_manager: CarbonsManager,
     csi_manager: CsiManager,
+    health_monitor: HealthMonitor,
     #[cfg(feature = "native")]
     event_bus: Option<Arc<dyn EventBus>>,
 }
@@ -55,28 +55,36 @@ where
     const INITIAL_RECONNECT_DELAY_SECONDS: u64 = 1;
     const MAX_RECONNECT_DELAY_SECONDS: u64 = 60;
 
-    pub fn new(config: ConnectionConfig) -> Self {
+    pub fn new(config: ConnectionConfig, credentials: Arc<dyn CredentialStore>) -> Self {
         Self {
             state: ConnectionState::Disconnected,
             config,
+            credentials,
             transport: None,
             stream_manager: StreamManager::new(),
             carbons_manager: CarbonsManager::new(),
             csi_manager: CsiManager::new(),
+            health_monitor: HealthMonitor::new(),
             #[cfg(feature = "native")]
             event_bus: None,
         }
     }
 
     #[cfg(feature = "native")]
-    pub fn with_event_bus(config: ConnectionConfig, event_bus: Arc<dyn EventBus>) -> Self {
+    pub fn with_event_bus(
+        config: ConnectionConfig,
+        credentials: Arc<dyn CredentialStore>,
+        event_bus: Arc<dyn EventBus>,
+    ) -> Self {
         Self {
             state: ConnectionState::Disconnected,
             config,
+            credentials,
             transport: None,
             stream_manager: StreamManager::new(),
             carbons_manager: CarbonsManager::new(),
             csi_manager: CsiManager::new(),
+            health_monitor: HealthMonitor::new(),
             event_bus: Some(event_bus),
         }
     }
@@ -90,7 +98,17 @@ where
         let mut reconnect_attempt = 0_u32;
 
         loop {
-            match T::connect(&self.config).await {
+            let password = match self.fetch_password().await {
+                Ok(password) => password,
+                Err(error) => {
+                    reconnect_attempt = self
+                        .handle_connect_failure(error, reconnect_attempt)
+                        .await?;
+                    continue;
+                }
+            };
+
+            match T::connect(&self.config, &password).await {
                 Ok(mut transport) => {
                     if transport.supports_stream_management() {
                         if let Err(error) = self.bootstrap_stream_management(&mut transport).await {
@@ -134,7 +152,11 @@ where
         };
 
         match tokio::time::timeout(timeout_duration, transport.recv()).await {
-            Ok(result) => result.map(Some),
+            Ok(result) => {
+                let frame = result?;
+                self.health_monitor.record_received(frame.len());
+                Ok(Some(frame))
+            }
             Err(_) => Ok(None),
         }
     }
@@ -151,6 +173,35 @@ where
         self.carbons_manager.state()
     }
 
+    pub fn health(&self) -> ConnectionHealth {
+        self.health_monitor.snapshot()
+    }
+
+    /// Publish a `system.connection.health` snapshot and ping the server for
+    /// the next one's round trip. Meant to run every
+    /// [`HEALTH_INTERVAL`](crate::health::HEALTH_INTERVAL) while connected.
+    pub async fn check_health(&mut self) -> Result<(), ConnectionError> {
+        if !matches!(self.state, ConnectionState::Connected) {
+            return Ok(());
+        }
+
+        #[cfg(feature = "native")]
+        self.emit_health();
+
+        let Some((_, domain)) = self.config.jid.split_once('@') else {
+            return Ok(());
+        };
+        let domain = domain.split('/').next().unwrap_or(domain).to_string();
+        let ping = self.health_monitor.ping(&domain);
+        self.send_raw(&ping, false).await
+    }
+
+    /// Whether `stanza` answered the health ping; such answers need no
+    /// further processing.
+    pub fn handle_ping_response(&mut self, stanza: &[u8]) -> bool {
+        self.health_monitor.handle_ping_response(stanza)
+    }
+
     pub fn csi_state(&self) -> ClientState {
         self.csi_manager.state()
     }
@@ -160,21 +211,21 @@ where
     }
 
     pub async fn enable_carbons(&mut self) -> Result<(), ConnectionError> {
-        if let Some(iq) = self.carbons_manager.enable() {
-            if let Err(error) = self.send_raw(&iq, false).await {
-                self.carbons_manager.on_enable_result(false);
-                return Err(error);
-            }
+        if let Some(iq) = self.carbons_manager.enable()
+            && let Err(error) = self.send_raw(&iq, false).await
+        {
+            self.carbons_manager.on_enable_result(false);
+            return Err(error);
         }
         Ok(())
     }
 
     pub async fn disable_carbons(&mut self) -> Result<(), ConnectionError> {
-        if let Some(iq) = self.carbons_manager.disable() {
-            if let Err(error) = self.send_raw(&iq, false).await {
-                self.carbons_manager.on_disable_result(false);
-                return Err(error);
-            }
+        if let Some(iq) = self.carbons_manager.disable()
+            && let Err(error) = self.send_raw(&iq, false).await
+        {
+            self.carbons_manager.on_disable_result(false);
+            return Err(error);
         }
         Ok(())
     }
@@ -193,21 +244,21 @@ where
     }
 
     pub async fn set_csi_inactive(&mut self) -> Result<(), ConnectionError> {
-        if let Some(stanza) = self.csi_manager.set_inactive() {
-            if let Err(error) = self.send_raw(&stanza, false).await {
-                let _ = self.csi_manager.set_active();
-                return Err(error);
-            }
+        if let Some(stanza) = self.csi_manager.set_inactive()
+            && let Err(error) = self.send_raw(&stanza, false).await
+        {
+            let _ = self.csi_manager.set_active();
+            return Err(error);
         }
         Ok(())
     }
 
     pub async fn set_csi_active(&mut self) -> Result<(), ConnectionError> {
-        if let Some(stanza) = self.csi_manager.set_active() {
-            if let Err(error) = self.send_raw(&stanza, false).await {
-                let _ = self.csi_manager.set_inactive();
-                return Err(error);
-            }
+        if let Some(stanza) = self.csi_manager.set_active()
+            && let Err(error) = self.send_raw(&stanza, false).await
+        {
+            let _ = self.csi_manager.set_inactive();
+            return Err(error);
         }
         Ok(())
     }
@@ -238,6 +289,7 @@ where
         self.state = ConnectionState::Disconnected;
         self.stream_manager.prepare_for_reconnect();
         self.carbons_manager.reset();
+        self.health_monitor.reset_pending();
 
         #[cfg(feature = "native")]
         self.emit_connection_lost(reason, will_retry);
@@ -246,19 +298,19 @@ where
     }
 
     pub async fn disconnect(&mut self) -> Result<(), ConnectionError> {
-        if let Some(mut transport) = self.transport.take() {
-            if let Err(error) = transport.close().await {
-                self.state = ConnectionState::Disconnected;
-                self.stream_manager.reset();
-                self.carbons_manager.reset();
-                self.csi_manager.reset();
-                #[cfg(feature = "native")]
-                {
-                    self.emit_connection_lost(error.to_string(), false);
-                    self.emit_connection_error(&error);
-                }
-                return Err(error);
+        if let Some(mut transport) = self.transport.take()
+            && let Err(error) = transport.close().await
+        {
+            self.state = ConnectionState::Disconnected;
+            self.stream_manager.reset();
+            self.carbons_manager.reset();
+            self.csi_manager.reset();
+            #[cfg(feature = "native")]
+            {
+                self.emit_connection_lost(error.to_string(), false);
+                self.emit_connection_error(&error);
             }
+            return Err(error);
         }
 
         if !matches!(self.state, ConnectionState::Disconnected) {
@@ -284,6 +336,7 @@ where
         if let Some(nonza) = self.stream_manager.on_stream_started() {
             let request = encode_nonza(nonza)?;
             transport.send(&request).await?;
+            self.health_monitor.record_sent(request.len());
         }
         Ok(())
     }
@@ -294,6 +347,31 @@ where
         }
     }
 
+    /// Look the password up for this attempt only; it is dropped as soon as
+    /// the transport has authenticated.
+    async fn fetch_password(&self) -> Result<String, ConnectionError> {
+        let credentials = self.credentials.clone();
+        let account = self.config.jid.clone();
+        let lookup = move || {
+            credentials
+                .get(&account)
+                .map_err(|error| ConnectionError::CredentialUnavailable(error.to_string()))
+        };
+
+        #[cfg(feature = "native")]
+        {
+            tokio::task::spawn_blocking(lookup).await.map_err(|error| {
+                ConnectionError::CredentialUnavailable(format!(
+                    "credential lookup task failed: {error}"
+                ))
+            })?
+        }
+        #[cfg(not(feature = "native"))]
+        {
+            lookup()
+        }
+    }
+
     async fn handle_connect_failure(
         &mut self,
         error: ConnectionError,
@@ -317,6 +395,7 @@ where
         self.state = ConnectionState::Reconnecting {
             attempt: next_attempt,
         };
+        self.health_monitor.record_reconnect();
         #[cfg(feature = "native")]
         self.emit_connection_reconnecting(next_attempt);
 
@@ -354,6 +433,7 @@ where
             ConnectionError::TransportError("cannot send data while disconnected".to_string())
         })?;
         transport.send(data).await?;
+        self.health_monitor.record_sent(data.len());
 
         if track_for_resumption {
             self.stream_manager.track_outbound_stanza(data);
@@ -378,7 +458,7 @@ where
     #[cfg(feature = "native")]
     fn emit_connection_established(&self) {
         self.emit_event(
-            "system.connection.established",
+            channels::SYSTEM_CONNECTION_ESTABLISHED,
             EventPayload::ConnectionEstablished {
                 jid: self.config.jid.clone(),
             },
@@ -388,7 +468,7 @@ where
     #[cfg(feature = "native")]
     fn emit_connection_lost(&self, reason: String, will_retry: bool) {
         self.emit_event(
-            "system.connection.lost",
+            channels::SYSTEM_CONNECTION_LOST,
             EventPayload::ConnectionLost { reason, will_retry },
         );
     }
@@ -396,15 +476,25 @@ where
     #[cfg(feature = "native")]
     fn emit_connection_reconnecting(&self, attempt: u32) {
         self.emit_event(
-            "system.connection.reconnecting",
+            channels::SYSTEM_CONNECTION_RECONNECTING,
             EventPayload::ConnectionReconnecting { attempt },
         );
     }
 
+    #[cfg(feature = "native")]
+    fn emit_health(&self) {
+        self.emit_event(
+            channels::SYSTEM_CONNECTION_HEALTH,
+            EventPayload::ConnectionHealth {
+                health: self.health_monitor.snapshot(),
+            },
+        );
+    }
+
     #[cfg(feature = "native")]
     fn emit_connection_error(&self, error: &ConnectionError) {
         self.emit_event(
-            "system.error.occurred",
+            channels::SYSTEM_ERROR_OCCURRED,
             EventPayload::ErrorOccurred {
                 component: "connection".to_string(),
                 message: error.to_string(),
@@ -431,11 +521,15 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use waddle_core::credentials::InMemoryCredentialStore;
 
     struct DummyTransport;
 
     impl XmppTransport for DummyTransport {
-        async fn connect(_config: &ConnectionConfig) -> Result<Self, ConnectionError> {
+        async fn connect(
+            _config: &ConnectionConfig,
+            _password: &str,
+        ) -> Result<Self, ConnectionError> {
             Ok(Self)
         }
 
@@ -491,17 +585,26 @@ mod tests {
     fn config(max_reconnect_attempts: u32) -> ConnectionConfig {
         ConnectionConfig {
             jid: "alice@example.com".to_string(),
-            password: "password".to_string(),
             server: Some("xmpp.example.com".to_string()),
             port: Some(5222),
             timeout_seconds: 30,
             max_reconnect_attempts,
+            transports: Vec::new(),
+            websocket_url: None,
+            bosh_url: None,
         }
     }
 
+    fn credentials() -> Arc<dyn CredentialStore> {
+        Arc::new(InMemoryCredentialStore::with_secret(
+            "alice@example.com",
+            "password",
+        ))
+    }
+
     #[tokio::test(flavor = "current_thread")]
     async fn enable_carbons_while_disconnected_rolls_back_state() {
-        let mut manager = ConnectionManager::<DummyTransport>::new(config(0));
+        let mut manager = ConnectionManager::<DummyTransport>::new(config(0), credentials());
 
         let result = manager.enable_carbons().await;
         assert!(matches!(result, Err(ConnectionError::TransportError(_))));
@@ -510,7 +613,7 @@ mod tests {
 
     #[tokio::test(flavor = "current_thread")]
     async fn set_csi_inactive_while_disconnected_rolls_back_state() {
-        let mut manager = ConnectionManager::<DummyTransport>::new(config(0));
+        let mut manager = ConnectionManager::<DummyTransport>::new(config(0), credentials());
         manager.set_csi_server_support(true);
 
         let result = manager.set_csi_inactive().await;
@@ -527,6 +630,7 @@ mod native_tests {
     };
 
     use tokio::{sync::Mutex as AsyncMutex, time};
+    use waddle_core::credentials::InMemoryCredentialStore;
     use waddle_core::event::{BroadcastEventBus, EventPayload};
     use xmpp_parsers::sm::Nonza;
 
@@ -592,18 +696,30 @@ mod native_tests {
     fn config(max_reconnect_attempts: u32) -> ConnectionConfig {
         ConnectionConfig {
             jid: "alice@example.com".to_string(),
-            password: "password".to_string(),
             server: Some("xmpp.example.com".to_string()),
             port: Some(5222),
             timeout_seconds: 30,
             max_reconnect_attempts,
+            transports: Vec::new(),
+            websocket_url: None,
+            bosh_url: None,
         }
     }
 
+    fn credentials() -> Arc<dyn CredentialStore> {
+        Arc::new(InMemoryCredentialStore::with_secret(
+            "alice@example.com",
+            "password",
+        ))
+    }
+
     struct TestTransport;
 
     impl XmppTransport for TestTransport {
-        async fn connect(_config: &ConnectionConfig) -> Result<Self, ConnectionError> {
+        async fn connect(
+            _config: &ConnectionConfig,
+            _password: &str,
+        ) -> Result<Self, ConnectionError> {
             let mut state = transport_state()
                 .lock()
                 .expect("failed to lock transport state");
@@ -651,8 +767,11 @@ mod native_tests {
             .subscribe("system.connection.established")
             .expect("failed to subscribe established events");
 
-        let mut manager =
-            ConnectionManager::<TestTransport>::with_event_bus(config(0), event_bus.clone());
+        let mut manager = ConnectionManager::<TestTransport>::with_event_bus(
+            config(0),
+            credentials(),
+            event_bus.clone(),
+        );
         manager.connect().await.expect("connect should succeed");
 
         assert_eq!(manager.state(), ConnectionState::Connected);
@@ -693,8 +812,11 @@ mod native_tests {
             .subscribe("system.error.occurred")
             .expect("failed to subscribe error events");
 
-        let mut manager =
-            ConnectionManager::<TestTransport>::with_event_bus(config(10), event_bus.clone());
+        let mut manager = ConnectionManager::<TestTransport>::with_event_bus(
+            config(10),
+            credentials(),
+            event_bus.clone(),
+        );
         let result = manager.connect().await;
 
         assert!(matches!(
@@ -729,6 +851,43 @@ mod native_tests {
         ));
     }
 
+    #[tokio::test(flavor = "current_thread")]
+    async fn missing_credential_fails_before_connecting() {
+        let _guard = test_lock().lock().await;
+        configure_transport(vec![Ok(())]);
+
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::new(16));
+        let mut lost = event_bus
+            .subscribe("system.connection.lost")
+            .expect("failed to subscribe lost events");
+
+        let mut manager = ConnectionManager::<TestTransport>::with_event_bus(
+            config(10),
+            Arc::new(InMemoryCredentialStore::new()),
+            event_bus.clone(),
+        );
+        let result = manager.connect().await;
+
+        assert!(matches!(
+            result,
+            Err(ConnectionError::CredentialUnavailable(_))
+        ));
+        assert_eq!(manager.state(), ConnectionState::Disconnected);
+        assert_eq!(connect_calls(), 0);
+
+        let lost_event = time::timeout(Duration::from_millis(100), lost.recv())
+            .await
+            .expect("timed out waiting for lost event")
+            .expect("failed to receive lost event");
+        assert!(matches!(
+            lost_event.payload,
+            EventPayload::ConnectionLost {
+                will_retry: false,
+                ..
+            }
+        ));
+    }
+
     #[tokio::test(flavor = "current_thread", start_paused = true)]
     async fn retryable_errors_emit_reconnecting_and_retry() {
         let _guard = test_lock().lock().await;
@@ -745,8 +904,11 @@ mod native_tests {
             .subscribe("system.connection.established")
             .expect("failed to subscribe established events");
 
-        let manager =
-            ConnectionManager::<TestTransport>::with_event_bus(config(3), event_bus.clone());
+        let manager = ConnectionManager::<TestTransport>::with_event_bus(
+            config(3),
+            credentials(),
+            event_bus.clone(),
+        );
         let connect_task = tokio::spawn(async move {
             let mut manager = manager;
             let result = manager.connect().await;
@@ -789,14 +951,63 @@ mod native_tests {
         ));
     }
 
+    #[tokio::test(flavor = "current_thread")]
+    async fn check_health_publishes_snapshot_and_pings_server() {
+        let _guard = test_lock().lock().await;
+        configure_transport(vec![Ok(())]);
+
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::new(16));
+        let mut health = event_bus
+            .subscribe("system.connection.health")
+            .expect("failed to subscribe health events");
+
+        let mut manager = ConnectionManager::<TestTransport>::with_event_bus(
+            config(0),
+            credentials(),
+            event_bus.clone(),
+        );
+        manager.connect().await.expect("connect should succeed");
+        manager
+            .check_health()
+            .await
+            .expect("health check should succeed");
+
+        let event = time::timeout(Duration::from_millis(100), health.recv())
+            .await
+            .expect("timed out waiting for health event")
+            .expect("failed to receive health event");
+        let EventPayload::ConnectionHealth { health: snapshot } = event.payload else {
+            panic!("expected a connection health payload");
+        };
+        assert!(snapshot.bytes_sent > 0);
+        assert_eq!(snapshot.rtt_ms, None);
+
+        let ping = sent_payloads()
+            .into_iter()
+            .find(|payload| payload.contains("urn:xmpp:ping"))
+            .expect("expected a ping to the server");
+        assert!(ping.contains("to='example.com'"));
+        let id = ping
+            .split("id='")
+            .nth(1)
+            .and_then(|rest| rest.split('\'').next())
+            .expect("ping should carry an id");
+        let reply = format!("<iq xmlns='jabber:client' type='result' id='{id}'/>");
+        assert!(manager.handle_ping_response(reply.as_bytes()));
+        assert!(manager.health().rtt_ms.is_some());
+    }
+
     #[tokio::test(flavor = "current_thread")]
     async fn network_interruption_uses_stream_resumption_and_replays_unacked() {
         let _guard = test_lock().lock().await;
         configure_transport(vec![Ok(()), Ok(())]);
 
         let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::new(16));
-        let mut manager =
-            ConnectionManager::<TestTransport>::with_event_bus(config(0), event_bus.clone());
+        let mut manager = ConnectionManager::<TestTransport>::with_event_bus(
+            config(0),
+            credentials(),
+            event_bus.clone(),
+        );
         manager.connect().await.expect("connect should succeed");
         manager
             .handle_stream_management_frame(
@@ -865,8 +1076,11 @@ mod native_tests {
             .subscribe("system.connection.lost")
             .expect("failed to subscribe lost events");
 
-        let mut manager =
-            ConnectionManager::<TestTransport>::with_event_bus(config(0), event_bus.clone());
+        let mut manager = ConnectionManager::<TestTransport>::with_event_bus(
+            config(0),
+            credentials(),
+            event_bus.clone(),
+        );
         manager.connect().await.expect("connect should succeed");
         manager
             .disconnect()
@@ -0,0 +1,87 @@
+//! Conversation flags kept in a private PEP node, so archiving and muting
+//! a conversation on one device applies on the others. Each conversation is
+//! one item, with the conversation's bare JID as the item id.
+
+use chrono::{DateTime, Utc};
+use waddle_core::event::ConversationFlags;
+use xmpp_parsers::minidom::Element;
+use xmpp_parsers::minidom::rxml::xml_ncname;
+
+use crate::error::PipelineError;
+
+/// PEP node holding the flags, and the namespace of its payloads.
+pub const CONVERSATION_FLAGS_NODE: &str = "urn:waddle:conversation-flags:0";
+
+/// Publish options that keep the node to our own account.
+pub const CONVERSATION_FLAGS_OPTIONS: &[(&str, &str)] = &[
+    ("pubsub#access_model", "whitelist"),
+    ("pubsub#persist_items", "true"),
+    ("pubsub#max_items", "max"),
+];
+
+pub fn flags_to_element(flags: &ConversationFlags) -> Element {
+    let mut element = Element::builder("conversation", CONVERSATION_FLAGS_NODE)
+        .attr(
+            xml_ncname!("archived").to_owned(),
+            flags.archived.to_string(),
+        )
+        .attr(xml_ncname!("muted").to_owned(), flags.muted.to_string());
+    if let Some(until) = flags.muted_until {
+        element = element.attr(xml_ncname!("muted-until").to_owned(), until.to_rfc3339());
+    }
+    element.build()
+}
+
+pub fn flags_from_element(element: &Element) -> Result<ConversationFlags, PipelineError> {
+    if !element.is("conversation", CONVERSATION_FLAGS_NODE) {
+        return Err(PipelineError::ParseFailed(
+            "not a conversation flags payload".to_string(),
+        ));
+    }
+    let flag = |name: &str| matches!(element.attr(name), Some("true" | "1"));
+    let muted_until = element
+        .attr("muted-until")
+        .map(|until| {
+            DateTime::parse_from_rfc3339(until)
+                .map(|until| until.with_timezone(&Utc))
+                .map_err(|error| {
+                    PipelineError::ParseFailed(format!("invalid muted-until: {error}"))
+                })
+        })
+        .transpose()?;
+    Ok(ConversationFlags {
+        archived: flag("archived"),
+        muted: flag("muted"),
+        muted_until,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_round_trip() {
+        let flags = ConversationFlags {
+            archived: true,
+            muted: true,
+            muted_until: Some("2026-10-18T08:00:00Z".parse().unwrap()),
+        };
+        assert_eq!(
+            flags_from_element(&flags_to_element(&flags)).unwrap(),
+            flags
+        );
+
+        let element: Element = "<conversation xmlns='urn:waddle:conversation-flags:0' muted='1'/>"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            flags_from_element(&element).unwrap(),
+            ConversationFlags {
+                archived: false,
+                muted: true,
+                muted_until: None,
+            }
+        );
+    }
+}
@@ -0,0 +1,345 @@
+//! Capture of the raw XML stream for protocol debugging.
+//!
+//! [`DebugProcessor`](crate::DebugProcessor) hands every stanza to a
+//! [`StanzaDebugger`], which keeps the most recent ones in memory, and
+//! optionally in a file, while capture is turned on. SASL exchanges,
+//! passwords and message bodies are redacted before anything is kept,
+//! according to [`DebugConfig`].
+
+use std::collections::VecDeque;
+#[cfg(feature = "native")]
+use std::fs::{File, OpenOptions};
+#[cfg(feature = "native")]
+use std::io::{self, Write};
+#[cfg(feature = "native")]
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "native")]
+use tracing::warn;
+
+use waddle_core::config::DebugConfig;
+
+use crate::pipeline::StanzaDirection;
+
+const REDACTED: &str = "[redacted]";
+
+/// Elements whose text carries credentials: SASL exchanges (RFC 6120) and
+/// the passwords of in-band registration and legacy auth.
+const AUTH_ELEMENTS: &[&str] = &["auth", "challenge", "response", "success", "password"];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CapturedStanza {
+    pub timestamp: DateTime<Utc>,
+    pub direction: StanzaDirection,
+    /// `message`, `presence`, `iq` or another top-level element.
+    pub name: String,
+    pub xml: String,
+}
+
+/// Which captured stanzas [`StanzaDebugger::entries`] returns. Empty fields
+/// match everything.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StanzaFilter {
+    pub direction: Option<StanzaDirection>,
+    pub name: Option<String>,
+    /// Text the XML must contain, ignoring case.
+    pub contains: Option<String>,
+    /// Only the latest this many matches.
+    pub limit: Option<usize>,
+}
+
+impl StanzaFilter {
+    fn matches(&self, stanza: &CapturedStanza) -> bool {
+        self.direction
+            .is_none_or(|direction| direction == stanza.direction)
+            && self.name.as_ref().is_none_or(|name| *name == stanza.name)
+            && self
+                .contains
+                .as_ref()
+                .is_none_or(|text| stanza.xml.to_lowercase().contains(&text.to_lowercase()))
+    }
+}
+
+pub struct StanzaDebugger {
+    enabled: AtomicBool,
+    capacity: usize,
+    redact_auth: bool,
+    redact_bodies: bool,
+    buffer: Mutex<VecDeque<CapturedStanza>>,
+    #[cfg(feature = "native")]
+    file: Mutex<Option<File>>,
+}
+
+impl StanzaDebugger {
+    pub fn new(config: &DebugConfig) -> Self {
+        Self {
+            enabled: AtomicBool::new(config.capture_stanzas),
+            capacity: config.buffer_size.max(1),
+            redact_auth: config.redact_auth,
+            redact_bodies: config.redact_bodies,
+            buffer: Mutex::new(VecDeque::new()),
+            #[cfg(feature = "native")]
+            file: Mutex::new(None),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Start or stop capturing. What was captured so far is kept.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Append captured stanzas to `path` as well, or stop writing to a file
+    /// with `None`.
+    #[cfg(feature = "native")]
+    pub fn set_capture_file(&self, path: Option<&Path>) -> io::Result<()> {
+        let file = match path {
+            Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+            None => None,
+        };
+        *self.file.lock().unwrap() = file;
+        Ok(())
+    }
+
+    /// Capture a stanza if capture is on, returning it as kept, redactions
+    /// applied.
+    pub fn record(
+        &self,
+        direction: StanzaDirection,
+        name: &str,
+        xml: &str,
+    ) -> Option<CapturedStanza> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let mut xml = xml.to_string();
+        if self.redact_auth {
+            for element in AUTH_ELEMENTS {
+                xml = redact_element(&xml, element);
+            }
+        }
+        if self.redact_bodies {
+            xml = redact_element(&xml, "body");
+        }
+        let captured = CapturedStanza {
+            timestamp: Utc::now(),
+            direction,
+            name: name.to_string(),
+            xml,
+        };
+
+        #[cfg(feature = "native")]
+        self.write_to_file(&captured);
+
+        let mut buffer = self.buffer.lock().unwrap();
+        if buffer.len() == self.capacity {
+            buffer.pop_front();
+        }
+        buffer.push_back(captured.clone());
+        Some(captured)
+    }
+
+    #[cfg(feature = "native")]
+    fn write_to_file(&self, captured: &CapturedStanza) {
+        let mut file = self.file.lock().unwrap();
+        let Some(writer) = file.as_mut() else {
+            return;
+        };
+        let arrow = match captured.direction {
+            StanzaDirection::Inbound => "<<",
+            StanzaDirection::Outbound => ">>",
+        };
+        if let Err(error) = writeln!(
+            writer,
+            "{} {arrow} {}",
+            captured.timestamp.to_rfc3339(),
+            captured.xml
+        ) {
+            warn!(%error, "failed to write stanza capture file, closing it");
+            *file = None;
+        }
+    }
+
+    /// Captured stanzas matching `filter`, oldest first.
+    pub fn entries(&self, filter: &StanzaFilter) -> Vec<CapturedStanza> {
+        let buffer = self.buffer.lock().unwrap();
+        let mut entries: Vec<CapturedStanza> = buffer
+            .iter()
+            .rev()
+            .filter(|stanza| filter.matches(stanza))
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect();
+        entries.reverse();
+        entries
+    }
+
+    pub fn clear(&self) {
+        self.buffer.lock().unwrap().clear();
+    }
+}
+
+/// Replace the content of every `name` element in `xml`, whatever its
+/// namespace prefix, with a placeholder. Empty elements are left alone.
+fn redact_element(xml: &str, name: &str) -> String {
+    let mut out = String::with_capacity(xml.len());
+    let mut rest = xml;
+
+    while let Some((start, open_end)) = find_open_tag(rest, name) {
+        let empty = rest[start..open_end].ends_with("/>");
+        out.push_str(&rest[..open_end]);
+        rest = &rest[open_end..];
+        if empty {
+            continue;
+        }
+        match find_close_tag(rest, name) {
+            Some(close) => {
+                out.push_str(REDACTED);
+                rest = &rest[close..];
+            }
+            None => break,
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The start of the next `<name ...>` or `<prefix:name ...>` tag in `xml`
+/// and the index just past it.
+fn find_open_tag(xml: &str, name: &str) -> Option<(usize, usize)> {
+    let mut from = 0;
+    while let Some(offset) = xml[from..].find('<') {
+        let start = from + offset;
+        let tag = &xml[start + 1..];
+        let end = start + 1 + tag.find('>')? + 1;
+        let tag_name = tag
+            .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .next()
+            .unwrap_or_default();
+        if tag_name.rsplit(':').next() == Some(name) {
+            return Some((start, end));
+        }
+        from = end;
+    }
+    None
+}
+
+/// The start of the next `</name>` or `</prefix:name>` in `xml`.
+fn find_close_tag(xml: &str, name: &str) -> Option<usize> {
+    let mut from = 0;
+    while let Some(offset) = xml[from..].find("</") {
+        let start = from + offset;
+        let tag = &xml[start + 2..];
+        let tag_name = tag.split('>').next()?.trim_end();
+        if tag_name.rsplit(':').next() == Some(name) {
+            return Some(start);
+        }
+        from = start + 2;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debugger(redact_bodies: bool) -> StanzaDebugger {
+        StanzaDebugger::new(&DebugConfig {
+            capture_stanzas: true,
+            buffer_size: 3,
+            redact_bodies,
+            ..DebugConfig::default()
+        })
+    }
+
+    #[test]
+    fn credentials_and_bodies_are_redacted() {
+        let sasl = "<auth xmlns='urn:ietf:params:xml:ns:xmpp-sasl' mechanism='PLAIN'>\
+            AGFsaWNlAHNlY3JldA==</auth>";
+        assert_eq!(
+            redact_element(sasl, "auth"),
+            "<auth xmlns='urn:ietf:params:xml:ns:xmpp-sasl' mechanism='PLAIN'>[redacted]</auth>"
+        );
+        assert_eq!(
+            redact_element("<x:password>hunter2</x:password><password/>", "password"),
+            "<x:password>[redacted]</x:password><password/>"
+        );
+        // `bodyguard` is not a `body`.
+        assert_eq!(
+            redact_element(
+                "<message><body>hi</body><bodyguard>x</bodyguard><body>again</body></message>",
+                "body"
+            ),
+            "<message><body>[redacted]</body><bodyguard>x</bodyguard>\
+             <body>[redacted]</body></message>"
+        );
+
+        let message = "<message><body>secret plans</body></message>";
+        let kept = debugger(true)
+            .record(StanzaDirection::Inbound, "message", message)
+            .unwrap();
+        assert_eq!(kept.xml, "<message><body>[redacted]</body></message>");
+        let kept = debugger(false)
+            .record(StanzaDirection::Inbound, "message", message)
+            .unwrap();
+        assert_eq!(kept.xml, message);
+    }
+
+    #[test]
+    fn capture_can_be_toggled_and_filtered() {
+        let debugger = debugger(false);
+        debugger.set_enabled(false);
+        assert!(
+            debugger
+                .record(StanzaDirection::Inbound, "iq", "<iq id='0'/>")
+                .is_none()
+        );
+
+        debugger.set_enabled(true);
+        debugger.record(StanzaDirection::Outbound, "iq", "<iq id='1'/>");
+        debugger.record(StanzaDirection::Inbound, "presence", "<presence/>");
+        debugger.record(StanzaDirection::Inbound, "iq", "<iq id='2'/>");
+        debugger.record(StanzaDirection::Outbound, "iq", "<iq id='3'/>");
+
+        let xml = |entries: Vec<CapturedStanza>| {
+            entries
+                .into_iter()
+                .map(|stanza| stanza.xml)
+                .collect::<Vec<_>>()
+        };
+        // The buffer holds three, so the first is gone.
+        assert_eq!(
+            xml(debugger.entries(&StanzaFilter::default())),
+            ["<presence/>", "<iq id='2'/>", "<iq id='3'/>"]
+        );
+        assert_eq!(
+            xml(debugger.entries(&StanzaFilter {
+                name: Some("iq".to_string()),
+                limit: Some(1),
+                ..StanzaFilter::default()
+            })),
+            ["<iq id='3'/>"]
+        );
+        assert_eq!(
+            xml(debugger.entries(&StanzaFilter {
+                direction: Some(StanzaDirection::Inbound),
+                contains: Some("ID='2'".to_string()),
+                ..StanzaFilter::default()
+            })),
+            ["<iq id='2'/>"]
+        );
+
+        debugger.clear();
+        assert!(debugger.entries(&StanzaFilter::default()).is_empty());
+    }
+}
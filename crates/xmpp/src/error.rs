@@ -19,11 +19,30 @@ pub enum ConnectionError {
 
     #[error("transport error: {0}")]
     TransportError(String),
+
+    #[error("credential unavailable: {0}")]
+    CredentialUnavailable(String),
+
+    #[error("server does not allow in-band registration: {0}")]
+    RegistrationUnsupported(String),
+
+    #[error("username is already taken: {0}")]
+    RegistrationConflict(String),
+
+    #[error("registration rejected: {0}")]
+    RegistrationRejected(String),
 }
 
 impl ConnectionError {
     pub fn is_retryable(&self) -> bool {
-        !matches!(self, ConnectionError::AuthenticationFailed(_))
+        !matches!(
+            self,
+            ConnectionError::AuthenticationFailed(_)
+                | ConnectionError::CredentialUnavailable(_)
+                | ConnectionError::RegistrationUnsupported(_)
+                | ConnectionError::RegistrationConflict(_)
+                | ConnectionError::RegistrationRejected(_)
+        )
     }
 }
 
@@ -38,3 +57,27 @@ pub enum PipelineError {
     #[error("plugin hook timed out: {0}")]
     PluginTimeout(String),
 }
+
+#[derive(Debug, Error)]
+pub enum PubSubError {
+    #[error("invalid pubsub service JID: {0}")]
+    InvalidJid(String),
+
+    #[error("pubsub request refused: {0}")]
+    Rejected(String),
+
+    #[error("service sent no configuration for node {0}")]
+    MissingConfig(String),
+
+    #[error("pubsub request timed out")]
+    Timeout,
+
+    #[error("not connected")]
+    NotConnected,
+
+    #[error("stanza pipeline failed: {0}")]
+    Pipeline(String),
+
+    #[error("event bus error: {0}")]
+    EventBus(String),
+}
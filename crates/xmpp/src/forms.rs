@@ -0,0 +1,201 @@
+//! Conversion between the shared [`waddle_core::form::DataForm`] model and
+//! XEP-0004 `<x xmlns='jabber:x:data'/>` elements.
+
+use waddle_core::form::{
+    DataForm, FieldOption, FieldType, FormField, FormMedia, FormType, MediaUri,
+};
+use xmpp_parsers::data_forms;
+use xmpp_parsers::media_element::{MediaElement, Uri};
+use xmpp_parsers::minidom::Element;
+
+use crate::error::PipelineError;
+
+/// Parse an `<x xmlns='jabber:x:data'/>` element.
+pub fn form_from_element(element: Element) -> Result<DataForm, PipelineError> {
+    let form = data_forms::DataForm::try_from(element)
+        .map_err(|error| PipelineError::ParseFailed(format!("invalid data form: {error}")))?;
+    Ok(from_parsed(form))
+}
+
+/// Serialize a form for embedding in an outgoing stanza payload.
+pub fn form_to_element(form: &DataForm) -> Element {
+    to_parsed(form).into()
+}
+
+fn from_parsed(form: data_forms::DataForm) -> DataForm {
+    DataForm {
+        form_type: match form.type_ {
+            data_forms::DataFormType::Form => FormType::Form,
+            data_forms::DataFormType::Submit => FormType::Submit,
+            data_forms::DataFormType::Cancel => FormType::Cancel,
+            data_forms::DataFormType::Result_ => FormType::Result,
+        },
+        title: form.title,
+        instructions: form.instructions,
+        fields: form.fields.into_iter().map(field_from_parsed).collect(),
+    }
+}
+
+fn field_from_parsed(field: data_forms::Field) -> FormField {
+    FormField {
+        var: field.var,
+        field_type: match field.type_ {
+            data_forms::FieldType::Boolean => FieldType::Boolean,
+            data_forms::FieldType::Fixed => FieldType::Fixed,
+            data_forms::FieldType::Hidden => FieldType::Hidden,
+            data_forms::FieldType::JidMulti => FieldType::JidMulti,
+            data_forms::FieldType::JidSingle => FieldType::JidSingle,
+            data_forms::FieldType::ListMulti => FieldType::ListMulti,
+            data_forms::FieldType::ListSingle => FieldType::ListSingle,
+            data_forms::FieldType::TextMulti => FieldType::TextMulti,
+            data_forms::FieldType::TextPrivate => FieldType::TextPrivate,
+            data_forms::FieldType::TextSingle => FieldType::TextSingle,
+        },
+        label: field.label,
+        desc: field.desc,
+        required: field.required,
+        values: field.values,
+        options: field
+            .options
+            .into_iter()
+            .map(|option| FieldOption {
+                label: option.label,
+                value: option.value,
+            })
+            .collect(),
+        media: field
+            .media
+            .into_iter()
+            .map(|media| FormMedia {
+                width: media.width.and_then(|w| u32::try_from(w).ok()),
+                height: media.height.and_then(|h| u32::try_from(h).ok()),
+                uris: media
+                    .uris
+                    .into_iter()
+                    .map(|uri| MediaUri {
+                        mime_type: uri.type_,
+                        uri: uri.uri,
+                    })
+                    .collect(),
+            })
+            .collect(),
+    }
+}
+
+fn to_parsed(form: &DataForm) -> data_forms::DataForm {
+    data_forms::DataForm {
+        type_: match form.form_type {
+            FormType::Form => data_forms::DataFormType::Form,
+            FormType::Submit => data_forms::DataFormType::Submit,
+            FormType::Cancel => data_forms::DataFormType::Cancel,
+            FormType::Result => data_forms::DataFormType::Result_,
+        },
+        title: form.title.clone(),
+        instructions: form.instructions.clone(),
+        fields: form.fields.iter().map(field_to_parsed).collect(),
+    }
+}
+
+fn field_to_parsed(field: &FormField) -> data_forms::Field {
+    data_forms::Field {
+        var: field.var.clone(),
+        type_: match field.field_type {
+            FieldType::Boolean => data_forms::FieldType::Boolean,
+            FieldType::Fixed => data_forms::FieldType::Fixed,
+            FieldType::Hidden => data_forms::FieldType::Hidden,
+            FieldType::JidMulti => data_forms::FieldType::JidMulti,
+            FieldType::JidSingle => data_forms::FieldType::JidSingle,
+            FieldType::ListMulti => data_forms::FieldType::ListMulti,
+            FieldType::ListSingle => data_forms::FieldType::ListSingle,
+            FieldType::TextMulti => data_forms::FieldType::TextMulti,
+            FieldType::TextPrivate => data_forms::FieldType::TextPrivate,
+            FieldType::TextSingle => data_forms::FieldType::TextSingle,
+        },
+        label: field.label.clone(),
+        required: field.required,
+        desc: field.desc.clone(),
+        options: field
+            .options
+            .iter()
+            .map(|option| data_forms::Option_ {
+                label: option.label.clone(),
+                value: option.value.clone(),
+            })
+            .collect(),
+        values: field.values.clone(),
+        media: field
+            .media
+            .iter()
+            .map(|media| MediaElement {
+                width: media.width.map(|w| w as usize),
+                height: media.height.map(|h| h as usize),
+                uris: media
+                    .uris
+                    .iter()
+                    .map(|uri| Uri {
+                        type_: uri.mime_type.clone(),
+                        uri: uri.uri.clone(),
+                    })
+                    .collect(),
+            })
+            .collect(),
+        validate: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAPTCHA_XML: &str = "<x xmlns='jabber:x:data' type='form'>\
+        <title>Verify you are human</title>\
+        <instructions>Type the characters shown</instructions>\
+        <field var='FORM_TYPE' type='hidden'><value>urn:xmpp:captcha</value></field>\
+        <field var='from' type='hidden'><value>room@muc.example.com</value></field>\
+        <field var='ocr' label='Enter the text you see'>\
+            <required/>\
+            <media xmlns='urn:xmpp:media-element' height='80' width='290'>\
+                <uri type='image/png'>cid:sha1+f24030b8d91d233bac14777be5ab531ca3b9f102@bob.xmpp.org</uri>\
+            </media>\
+        </field>\
+    </x>";
+
+    #[test]
+    fn parses_captcha_form_with_media() {
+        let form = form_from_element(CAPTCHA_XML.parse().unwrap()).unwrap();
+
+        assert_eq!(form.form_type, FormType::Form);
+        assert_eq!(form.title.as_deref(), Some("Verify you are human"));
+        assert_eq!(form.schema(), Some("urn:xmpp:captcha"));
+
+        let ocr = form.field("ocr").unwrap();
+        assert!(ocr.required);
+        assert_eq!(ocr.field_type, FieldType::TextSingle);
+        assert_eq!(ocr.media[0].width, Some(290));
+        assert_eq!(ocr.media[0].uris[0].mime_type, "image/png");
+    }
+
+    #[test]
+    fn submission_round_trips_through_xml() {
+        let mut form = form_from_element(CAPTCHA_XML.parse().unwrap()).unwrap();
+        form.set_values("ocr", vec!["7nHL3".to_string()]);
+        let submission = form.to_submission();
+
+        let element = form_to_element(&submission);
+        assert!(element.is("x", "jabber:x:data"));
+        assert_eq!(element.attr("type"), Some("submit"));
+
+        let parsed = form_from_element(element).unwrap();
+        assert_eq!(parsed, submission);
+        assert_eq!(parsed.value("ocr"), Some("7nHL3"));
+    }
+
+    #[test]
+    fn rejects_elements_that_are_not_forms() {
+        let element: Element = "<query xmlns='jabber:iq:roster'/>".parse().unwrap();
+        assert!(matches!(
+            form_from_element(element),
+            Err(PipelineError::ParseFailed(_))
+        ));
+    }
+}
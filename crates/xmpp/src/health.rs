@@ -0,0 +1,167 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use waddle_core::event::ConnectionHealth;
+use xmpp_parsers::minidom::Element;
+
+/// How often a health snapshot is published and the server pinged.
+pub const HEALTH_INTERVAL: Duration = Duration::from_secs(30);
+
+const PING_NS: &str = "urn:xmpp:ping";
+const PING_ID_PREFIX: &str = "health-ping-";
+
+/// Tracks traffic and XEP-0199 ping round trips for the current account so
+/// the UI can show connection quality.
+#[derive(Debug, Default)]
+pub struct HealthMonitor {
+    bytes_sent: u64,
+    bytes_received: u64,
+    last_inbound_at: Option<DateTime<Utc>>,
+    rtt_ms: Option<u64>,
+    pending_ping: Option<(String, DateTime<Utc>)>,
+    next_ping_id: u64,
+    reconnect_count: u32,
+}
+
+impl HealthMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_sent(&mut self, bytes: usize) {
+        self.bytes_sent = self.bytes_sent.saturating_add(bytes as u64);
+    }
+
+    pub fn record_received(&mut self, bytes: usize) {
+        self.bytes_received = self.bytes_received.saturating_add(bytes as u64);
+        self.last_inbound_at = Some(Utc::now());
+    }
+
+    pub fn record_reconnect(&mut self) {
+        self.reconnect_count = self.reconnect_count.saturating_add(1);
+    }
+
+    /// Forget the ping in flight; its answer cannot arrive on a new stream.
+    pub fn reset_pending(&mut self) {
+        self.pending_ping = None;
+    }
+
+    /// Build a ping to `server` and start timing it. A ping still awaiting
+    /// its answer is superseded, and the round trip becomes unknown.
+    pub fn ping(&mut self, server: &str) -> Vec<u8> {
+        if self.pending_ping.is_some() {
+            self.rtt_ms = None;
+        }
+        self.next_ping_id += 1;
+        let id = format!("{PING_ID_PREFIX}{}", self.next_ping_id);
+        let stanza = format!(
+            "<iq xmlns='jabber:client' type='get' to='{server}' id='{id}'>\
+             <ping xmlns='{PING_NS}'/>\
+             </iq>"
+        );
+        self.pending_ping = Some((id, Utc::now()));
+        stanza.into_bytes()
+    }
+
+    /// Record the round trip if `stanza` answers the pending ping. Error
+    /// replies count too: servers without XEP-0199 still answer promptly.
+    pub fn handle_ping_response(&mut self, stanza: &[u8]) -> bool {
+        let Some((pending_id, sent_at)) = &self.pending_ping else {
+            return false;
+        };
+        let Some(xml) = std::str::from_utf8(stanza).ok().map(str::trim) else {
+            return false;
+        };
+        let Ok(element) = Element::from_str(xml) else {
+            return false;
+        };
+        if element.name() != "iq"
+            || element.attr("id") != Some(pending_id.as_str())
+            || !matches!(element.attr("type"), Some("result" | "error"))
+        {
+            return false;
+        }
+
+        let rtt = (Utc::now() - *sent_at).num_milliseconds().max(0);
+        self.rtt_ms = Some(rtt as u64);
+        self.pending_ping = None;
+        true
+    }
+
+    pub fn snapshot(&self) -> ConnectionHealth {
+        self.snapshot_at(Utc::now())
+    }
+
+    fn snapshot_at(&self, now: DateTime<Utc>) -> ConnectionHealth {
+        ConnectionHealth {
+            rtt_ms: self.rtt_ms,
+            bytes_sent: self.bytes_sent,
+            bytes_received: self.bytes_received,
+            since_last_inbound_ms: self
+                .last_inbound_at
+                .map(|at| (now - at).num_milliseconds().max(0) as u64),
+            reconnect_count: self.reconnect_count,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ping_id(stanza: &[u8]) -> String {
+        let element = Element::from_str(std::str::from_utf8(stanza).unwrap()).unwrap();
+        assert!(element.has_child("ping", PING_NS));
+        element.attr("id").unwrap().to_string()
+    }
+
+    #[test]
+    fn ping_result_records_round_trip() {
+        let mut monitor = HealthMonitor::new();
+        let id = ping_id(&monitor.ping("example.com"));
+
+        let other = b"<iq xmlns='jabber:client' type='result' id='carbons-enable'/>";
+        assert!(!monitor.handle_ping_response(other));
+        assert_eq!(monitor.snapshot().rtt_ms, None);
+
+        let reply = format!("<iq xmlns='jabber:client' type='result' id='{id}'/>");
+        assert!(monitor.handle_ping_response(reply.as_bytes()));
+        assert!(monitor.snapshot().rtt_ms.is_some());
+
+        // The same answer is not counted twice.
+        assert!(!monitor.handle_ping_response(reply.as_bytes()));
+    }
+
+    #[test]
+    fn newer_ping_supersedes_unanswered_one() {
+        let mut monitor = HealthMonitor::new();
+        let stale = ping_id(&monitor.ping("example.com"));
+        let current = ping_id(&monitor.ping("example.com"));
+        assert_ne!(stale, current);
+
+        assert_eq!(monitor.snapshot().rtt_ms, None);
+
+        let late = format!("<iq xmlns='jabber:client' type='error' id='{stale}'/>");
+        assert!(!monitor.handle_ping_response(late.as_bytes()));
+        let reply = format!("<iq xmlns='jabber:client' type='error' id='{current}'/>");
+        assert!(monitor.handle_ping_response(reply.as_bytes()));
+    }
+
+    #[test]
+    fn snapshot_reports_traffic_and_idle_time() {
+        let mut monitor = HealthMonitor::new();
+        assert_eq!(monitor.snapshot(), ConnectionHealth::default());
+
+        monitor.record_sent(100);
+        monitor.record_received(40);
+        monitor.record_reconnect();
+        let received_at = monitor.last_inbound_at.unwrap();
+
+        let health = monitor.snapshot_at(received_at + chrono::Duration::seconds(5));
+        assert_eq!(health.bytes_sent, 100);
+        assert_eq!(health.bytes_received, 40);
+        assert_eq!(health.since_last_inbound_ms, Some(5000));
+        assert_eq!(health.reconnect_count, 1);
+    }
+}
@@ -0,0 +1,403 @@
+//! Conversion between the call types in [`waddle_core::event`] and Jingle
+//! `<jingle/>` elements: XEP-0166 sessions carrying XEP-0167 RTP audio
+//! over XEP-0176 ICE-UDP, secured with XEP-0320 DTLS fingerprints.
+//!
+//! Only the actions a voice call needs are understood; anything else is
+//! reported as [`JingleAction::Unsupported`] so it can be refused.
+
+use waddle_core::event::{
+    CallContent, CallEndReason, DtlsFingerprint, IceCandidate, IceTransport, JingleAction,
+    RtpDescription, RtpPayloadType,
+};
+use xmpp_parsers::minidom::Element;
+use xmpp_parsers::minidom::rxml::xml_ncname;
+use xmpp_parsers::ns;
+
+use crate::error::PipelineError;
+
+/// Ids of the Jingle iqs we send start with this, so their answers can be
+/// told apart from other iq results.
+pub const JINGLE_IQ_ID_PREFIX: &str = "jingle-";
+
+const RTP_INFO_NS: &str = "urn:xmpp:jingle:apps:rtp:info:1";
+
+pub fn jingle_to_element(sid: &str, action: &JingleAction) -> Element {
+    let (name, contents): (&str, &[CallContent]) = match action {
+        JingleAction::SessionInitiate { contents } => ("session-initiate", contents),
+        JingleAction::SessionAccept { contents } => ("session-accept", contents),
+        JingleAction::Ringing => ("session-info", &[]),
+        JingleAction::TransportInfo { contents } => ("transport-info", contents),
+        JingleAction::SessionTerminate { .. } => ("session-terminate", &[]),
+        JingleAction::Unsupported { name } => (name.as_str(), &[]),
+    };
+    let mut jingle = Element::builder("jingle", ns::JINGLE)
+        .attr(xml_ncname!("action").to_owned(), name)
+        .attr(xml_ncname!("sid").to_owned(), sid)
+        .append_all(contents.iter().map(content_to_element))
+        .build();
+    match action {
+        JingleAction::Ringing => {
+            jingle.append_child(Element::builder("ringing", RTP_INFO_NS).build());
+        }
+        JingleAction::SessionTerminate { reason } => {
+            jingle.append_child(
+                Element::builder("reason", ns::JINGLE)
+                    .append(Element::builder(reason.as_str(), ns::JINGLE))
+                    .build(),
+            );
+        }
+        _ => {}
+    }
+    jingle
+}
+
+/// The session ID and action of a `<jingle/>` element.
+pub fn jingle_from_element(element: &Element) -> Result<(String, JingleAction), PipelineError> {
+    if !element.is("jingle", ns::JINGLE) {
+        return Err(PipelineError::ParseFailed("not a jingle element".into()));
+    }
+    let sid = required_attr(element, "sid")?.to_string();
+    let contents = || {
+        element
+            .children()
+            .filter(|child| child.is("content", ns::JINGLE))
+            .map(content_from_element)
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let action = match required_attr(element, "action")? {
+        "session-initiate" => JingleAction::SessionInitiate {
+            contents: contents()?,
+        },
+        "session-accept" => JingleAction::SessionAccept {
+            contents: contents()?,
+        },
+        "transport-info" => JingleAction::TransportInfo {
+            contents: contents()?,
+        },
+        "session-terminate" => JingleAction::SessionTerminate {
+            reason: element
+                .get_child("reason", ns::JINGLE)
+                .and_then(|reason| reason.children().find(|child| child.ns() == ns::JINGLE))
+                .map_or(CallEndReason::Success, |condition| {
+                    CallEndReason::from_name(condition.name())
+                }),
+        },
+        "session-info" if element.get_child("ringing", RTP_INFO_NS).is_some() => {
+            JingleAction::Ringing
+        }
+        name => JingleAction::Unsupported {
+            name: name.to_string(),
+        },
+    };
+    Ok((sid, action))
+}
+
+fn content_to_element(content: &CallContent) -> Element {
+    let mut element = Element::builder("content", ns::JINGLE)
+        .attr(xml_ncname!("creator").to_owned(), "initiator")
+        .attr(xml_ncname!("name").to_owned(), content.name.as_str())
+        .build();
+    if let Some(description) = &content.description {
+        element.append_child(description_to_element(description));
+    }
+    element.append_child(transport_to_element(&content.transport));
+    element
+}
+
+fn content_from_element(element: &Element) -> Result<CallContent, PipelineError> {
+    let transport = element
+        .get_child("transport", ns::JINGLE_ICE_UDP)
+        .ok_or_else(|| PipelineError::ParseFailed("content has no ICE-UDP transport".into()))?;
+    Ok(CallContent {
+        name: required_attr(element, "name")?.to_string(),
+        description: element
+            .get_child("description", ns::JINGLE_RTP)
+            .map(description_from_element)
+            .transpose()?,
+        transport: transport_from_element(transport)?,
+    })
+}
+
+fn description_to_element(description: &RtpDescription) -> Element {
+    let mut element = Element::builder("description", ns::JINGLE_RTP)
+        .attr(xml_ncname!("media").to_owned(), description.media.as_str())
+        .append_all(description.payload_types.iter().map(|payload_type| {
+            Element::builder("payload-type", ns::JINGLE_RTP)
+                .attr(xml_ncname!("id").to_owned(), payload_type.id)
+                .attr(xml_ncname!("name").to_owned(), payload_type.name.as_deref())
+                .attr(xml_ncname!("clockrate").to_owned(), payload_type.clockrate)
+                .attr(xml_ncname!("channels").to_owned(), payload_type.channels)
+                .append_all(payload_type.parameters.iter().map(|(name, value)| {
+                    Element::builder("parameter", ns::JINGLE_RTP)
+                        .attr(xml_ncname!("name").to_owned(), name.as_str())
+                        .attr(xml_ncname!("value").to_owned(), value.as_str())
+                }))
+        }))
+        .build();
+    if description.rtcp_mux {
+        element.append_child(Element::builder("rtcp-mux", ns::JINGLE_RTP).build());
+    }
+    element
+}
+
+fn description_from_element(element: &Element) -> Result<RtpDescription, PipelineError> {
+    let payload_types = element
+        .children()
+        .filter(|child| child.is("payload-type", ns::JINGLE_RTP))
+        .map(|payload_type| {
+            Ok(RtpPayloadType {
+                id: parse_attr(payload_type, "id")?,
+                name: payload_type.attr("name").map(String::from),
+                clockrate: payload_type
+                    .attr("clockrate")
+                    .map(|_| parse_attr(payload_type, "clockrate"))
+                    .transpose()?,
+                channels: payload_type
+                    .attr("channels")
+                    .map_or(Ok(1), |_| parse_attr(payload_type, "channels"))?,
+                parameters: payload_type
+                    .children()
+                    .filter(|child| child.is("parameter", ns::JINGLE_RTP))
+                    .filter_map(|parameter| {
+                        Some((
+                            parameter.attr("name")?.to_string(),
+                            parameter.attr("value")?.to_string(),
+                        ))
+                    })
+                    .collect(),
+            })
+        })
+        .collect::<Result<Vec<_>, PipelineError>>()?;
+    Ok(RtpDescription {
+        media: required_attr(element, "media")?.to_string(),
+        payload_types,
+        rtcp_mux: element.has_child("rtcp-mux", ns::JINGLE_RTP),
+    })
+}
+
+fn transport_to_element(transport: &IceTransport) -> Element {
+    let mut element = Element::builder("transport", ns::JINGLE_ICE_UDP)
+        .attr(xml_ncname!("ufrag").to_owned(), transport.ufrag.as_deref())
+        .attr(xml_ncname!("pwd").to_owned(), transport.pwd.as_deref())
+        .build();
+    if let Some(fingerprint) = &transport.fingerprint {
+        element.append_child(
+            Element::builder("fingerprint", ns::JINGLE_DTLS)
+                .attr(xml_ncname!("hash").to_owned(), fingerprint.hash.as_str())
+                .attr(xml_ncname!("setup").to_owned(), fingerprint.setup.as_str())
+                .append(fingerprint.value.as_str())
+                .build(),
+        );
+    }
+    for candidate in &transport.candidates {
+        element.append_child(
+            Element::builder("candidate", ns::JINGLE_ICE_UDP)
+                .attr(xml_ncname!("id").to_owned(), candidate.id.as_str())
+                .attr(
+                    xml_ncname!("foundation").to_owned(),
+                    candidate.foundation.as_str(),
+                )
+                .attr(xml_ncname!("component").to_owned(), candidate.component)
+                .attr(xml_ncname!("generation").to_owned(), candidate.generation)
+                .attr(
+                    xml_ncname!("protocol").to_owned(),
+                    candidate.protocol.as_str(),
+                )
+                .attr(xml_ncname!("priority").to_owned(), candidate.priority)
+                .attr(xml_ncname!("ip").to_owned(), candidate.ip.as_str())
+                .attr(xml_ncname!("port").to_owned(), candidate.port)
+                .attr(xml_ncname!("type").to_owned(), candidate.kind.as_str())
+                .attr(
+                    xml_ncname!("rel-addr").to_owned(),
+                    candidate.rel_addr.as_deref(),
+                )
+                .attr(xml_ncname!("rel-port").to_owned(), candidate.rel_port)
+                .build(),
+        );
+    }
+    element
+}
+
+fn transport_from_element(element: &Element) -> Result<IceTransport, PipelineError> {
+    let fingerprint = element
+        .get_child("fingerprint", ns::JINGLE_DTLS)
+        .map(|fingerprint| {
+            Ok::<_, PipelineError>(DtlsFingerprint {
+                hash: required_attr(fingerprint, "hash")?.to_string(),
+                setup: required_attr(fingerprint, "setup")?.to_string(),
+                value: fingerprint.text().trim().to_string(),
+            })
+        })
+        .transpose()?;
+    let candidates = element
+        .children()
+        .filter(|child| child.is("candidate", ns::JINGLE_ICE_UDP))
+        .map(|candidate| {
+            Ok(IceCandidate {
+                id: required_attr(candidate, "id")?.to_string(),
+                foundation: required_attr(candidate, "foundation")?.to_string(),
+                component: parse_attr(candidate, "component")?,
+                generation: parse_attr(candidate, "generation")?,
+                protocol: required_attr(candidate, "protocol")?.to_string(),
+                priority: parse_attr(candidate, "priority")?,
+                ip: required_attr(candidate, "ip")?.to_string(),
+                port: parse_attr(candidate, "port")?,
+                kind: required_attr(candidate, "type")?.to_string(),
+                rel_addr: candidate.attr("rel-addr").map(String::from),
+                rel_port: candidate
+                    .attr("rel-port")
+                    .map(|_| parse_attr(candidate, "rel-port"))
+                    .transpose()?,
+            })
+        })
+        .collect::<Result<Vec<_>, PipelineError>>()?;
+    Ok(IceTransport {
+        ufrag: element.attr("ufrag").map(String::from),
+        pwd: element.attr("pwd").map(String::from),
+        fingerprint,
+        candidates,
+    })
+}
+
+fn required_attr<'a>(element: &'a Element, name: &'a str) -> Result<&'a str, PipelineError> {
+    element
+        .attr(name)
+        .ok_or_else(|| PipelineError::ParseFailed(format!("<{}/> has no '{name}'", element.name())))
+}
+
+fn parse_attr<T: std::str::FromStr>(element: &Element, name: &str) -> Result<T, PipelineError> {
+    required_attr(element, name)?.parse().map_err(|_| {
+        PipelineError::ParseFailed(format!("<{}/> has an invalid '{name}'", element.name()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audio_content() -> CallContent {
+        CallContent {
+            name: "0".to_string(),
+            description: Some(RtpDescription {
+                media: "audio".to_string(),
+                payload_types: vec![RtpPayloadType {
+                    id: 111,
+                    name: Some("opus".to_string()),
+                    clockrate: Some(48000),
+                    channels: 2,
+                    parameters: vec![("useinbandfec".to_string(), "1".to_string())],
+                }],
+                rtcp_mux: true,
+            }),
+            transport: IceTransport {
+                ufrag: Some("8hhy".to_string()),
+                pwd: Some("asd88fgpdd777uzjYhagZg".to_string()),
+                fingerprint: Some(DtlsFingerprint {
+                    hash: "sha-256".to_string(),
+                    setup: "actpass".to_string(),
+                    value: "02:1A:CC:54".to_string(),
+                }),
+                candidates: vec![IceCandidate {
+                    id: "el0747fg11".to_string(),
+                    foundation: "1".to_string(),
+                    component: 1,
+                    generation: 0,
+                    protocol: "udp".to_string(),
+                    priority: 2130706431,
+                    ip: "10.0.1.1".to_string(),
+                    port: 8998,
+                    kind: "host".to_string(),
+                    rel_addr: None,
+                    rel_port: None,
+                }],
+            },
+        }
+    }
+
+    #[test]
+    fn round_trips_call_actions() {
+        let actions = [
+            JingleAction::SessionInitiate {
+                contents: vec![audio_content()],
+            },
+            JingleAction::SessionAccept {
+                contents: vec![audio_content()],
+            },
+            JingleAction::Ringing,
+            JingleAction::TransportInfo {
+                contents: vec![CallContent {
+                    description: None,
+                    ..audio_content()
+                }],
+            },
+            JingleAction::SessionTerminate {
+                reason: CallEndReason::Busy,
+            },
+        ];
+        for action in actions {
+            let element = jingle_to_element("a73sjjvkla37jfea", &action);
+            assert_eq!(
+                jingle_from_element(&element).unwrap(),
+                ("a73sjjvkla37jfea".to_string(), action)
+            );
+        }
+    }
+
+    #[test]
+    fn parses_peer_offers() {
+        let element: Element = "<jingle xmlns='urn:xmpp:jingle:1' action='session-initiate' \
+                initiator='romeo@montague.lit/orchard' sid='a73sjjvkla37jfea'>\
+              <content creator='initiator' name='voice'>\
+                <description xmlns='urn:xmpp:jingle:apps:rtp:1' media='audio'>\
+                  <payload-type id='0' name='PCMU' clockrate='8000'/>\
+                </description>\
+                <transport xmlns='urn:xmpp:jingle:transports:ice-udp:1' pwd='p' ufrag='u'>\
+                  <candidate component='1' foundation='2' generation='0' id='y3s2b30v3r' \
+                    ip='192.0.2.3' network='1' port='45664' priority='1694498815' \
+                    protocol='udp' rel-addr='10.0.1.1' rel-port='8998' type='srflx'/>\
+                </transport>\
+              </content>\
+            </jingle>"
+            .parse()
+            .unwrap();
+
+        let (sid, action) = jingle_from_element(&element).unwrap();
+
+        assert_eq!(sid, "a73sjjvkla37jfea");
+        let JingleAction::SessionInitiate { contents } = action else {
+            panic!("unexpected action: {action:?}");
+        };
+        let description = contents[0].description.as_ref().unwrap();
+        assert_eq!(description.payload_types[0].channels, 1);
+        assert!(!description.rtcp_mux);
+        let candidate = &contents[0].transport.candidates[0];
+        assert_eq!(candidate.kind, "srflx");
+        assert_eq!(candidate.rel_port, Some(8998));
+    }
+
+    #[test]
+    fn reports_other_actions_as_unsupported() {
+        let element: Element = "<jingle xmlns='urn:xmpp:jingle:1' action='content-add' sid='s1'/>"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            jingle_from_element(&element).unwrap().1,
+            JingleAction::Unsupported {
+                name: "content-add".to_string()
+            }
+        );
+
+        let unknown_reason: Element = "<jingle xmlns='urn:xmpp:jingle:1' \
+                action='session-terminate' sid='s1'>\
+              <reason><alternative-session><sid>s2</sid></alternative-session></reason>\
+            </jingle>"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            jingle_from_element(&unknown_reason).unwrap().1,
+            JingleAction::SessionTerminate {
+                reason: CallEndReason::GeneralError
+            }
+        );
+    }
+}
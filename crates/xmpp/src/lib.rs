@@ -1,34 +1,72 @@
+#[cfg(feature = "native")]
+pub mod account;
+pub mod avatar;
 pub mod carbons;
 pub mod connection;
+pub mod conversation_flags;
 pub mod csi;
+pub mod debugger;
 pub mod error;
+pub mod forms;
+pub mod health;
+pub mod jingle;
+pub mod omemo;
 pub mod outbound;
 pub mod pipeline;
 pub mod processors;
+pub mod profile;
+pub mod pubsub;
+pub mod registration;
+pub mod rsm;
 pub mod sasl;
 pub mod stanza;
 pub mod stream_management;
 pub mod transport;
 
+#[cfg(feature = "native")]
+pub use account::AccountManager;
+pub use avatar::{
+    AVATAR_DATA_NODE, AVATAR_METADATA_NODE, avatar_data_element, avatar_hash, avatar_id,
+    avatar_metadata_element, image_type,
+};
 pub use carbons::{CarbonDirection, CarbonsManager, CarbonsState, UnwrappedCarbon};
 pub use connection::{ConnectionConfig, ConnectionManager, ConnectionState};
+pub use conversation_flags::{
+    CONVERSATION_FLAGS_NODE, CONVERSATION_FLAGS_OPTIONS, flags_from_element, flags_to_element,
+};
 pub use csi::{ClientState, CsiManager};
-pub use error::{ConnectionError, PipelineError};
+pub use debugger::{CapturedStanza, StanzaDebugger, StanzaFilter};
+pub use error::{ConnectionError, PipelineError, PubSubError};
+pub use forms::{form_from_element, form_to_element};
+pub use health::{HEALTH_INTERVAL, HealthMonitor};
+pub use jingle::{JINGLE_IQ_ID_PREFIX, jingle_from_element, jingle_to_element};
 pub use outbound::{OutboundRouter, OutboundRouterError};
 #[cfg(feature = "native")]
 pub use outbound::{StanzaReceiver, StanzaSender, stanza_channel};
 pub use pipeline::{
-    ProcessorContext, ProcessorResult, StanzaDirection, StanzaPipeline, StanzaProcessor,
+    PluginHookFuture, PluginStanzaHost, PluginStanzaProcessor, ProcessorContext, ProcessorResult,
+    StanzaDirection, StanzaHookOutcome, StanzaPipeline, StanzaProcessor,
 };
-#[cfg(debug_assertions)]
-pub use processors::DebugProcessor;
 pub use processors::{
-    ChatStateProcessor, MamProcessor, MessageProcessor, MucProcessor, PresenceProcessor,
-    RosterProcessor,
+    AccountProcessor, ChatStateProcessor, DebugProcessor, DiscoProcessor, JingleProcessor,
+    MamProcessor, MessageProcessor, MucProcessor, OmemoProcessor, PresenceProcessor,
+    PubSubProcessor, RosterProcessor,
 };
+pub use profile::{
+    NICK_NODE, PROFILE_OPTIONS, VCARD4_ITEM_ID, VCARD4_NODE, VCARD4_NS, nick_element,
+    vcard4_element,
+};
+#[cfg(feature = "native")]
+pub use pubsub::PubSubManager;
+pub use pubsub::item_payload;
+pub use registration::{RegistrationForm, submitted_credentials};
+#[cfg(feature = "native")]
+pub use registration::{RegistrationManager, RegistrationTransport};
 pub use sasl::SelectedMechanism;
 pub use stanza::{Stanza, parse_stanza, serialize_stanza};
 pub use stream_management::{
     StreamManagementAction, StreamManagementState, StreamManager, decode_nonza, encode_nonza,
 };
-pub use transport::XmppTransport;
+pub use transport::{
+    BoshTransport, FallbackTransport, TransportKind, WebSocketTransport, XmppTransport,
+};
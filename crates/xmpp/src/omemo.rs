@@ -0,0 +1,203 @@
+//! Conversion between the OMEMO types in [`waddle_core::event`] and the
+//! `eu.siacs.conversations.axolotl` elements: device lists, key bundles and
+//! `<encrypted/>` message payloads.
+
+use waddle_core::event::{OmemoBundle, OmemoEnvelope, OmemoKeyElement, OmemoPreKey};
+use xmpp_parsers::legacy_omemo;
+use xmpp_parsers::minidom::Element;
+use xmpp_parsers::ns;
+
+use crate::error::PipelineError;
+
+/// PEP node holding an account's device list.
+pub const DEVICE_LIST_NODE: &str = ns::LEGACY_OMEMO_DEVICELIST;
+
+/// PEP node holding the bundle of one device.
+pub fn bundle_node(device_id: u32) -> String {
+    format!("{}:{device_id}", ns::LEGACY_OMEMO_BUNDLES)
+}
+
+/// The device ID a bundle node belongs to, if `node` is one.
+pub fn bundle_node_device(node: &str) -> Option<u32> {
+    node.strip_prefix(ns::LEGACY_OMEMO_BUNDLES)?
+        .strip_prefix(':')?
+        .parse()
+        .ok()
+}
+
+pub fn device_list_from_element(element: Element) -> Result<Vec<u32>, PipelineError> {
+    let list = legacy_omemo::DeviceList::try_from(element)
+        .map_err(|error| PipelineError::ParseFailed(format!("invalid device list: {error}")))?;
+    Ok(list.devices.into_iter().map(|device| device.id).collect())
+}
+
+pub fn device_list_to_element(devices: &[u32]) -> Element {
+    legacy_omemo::DeviceList {
+        devices: devices
+            .iter()
+            .map(|id| legacy_omemo::Device { id: *id })
+            .collect(),
+    }
+    .into()
+}
+
+/// Parse a published bundle. Bundles missing the identity key or signed
+/// pre-key cannot be used to start a session and are rejected.
+pub fn bundle_from_element(element: Element) -> Result<OmemoBundle, PipelineError> {
+    let bundle = legacy_omemo::Bundle::try_from(element)
+        .map_err(|error| PipelineError::ParseFailed(format!("invalid bundle: {error}")))?;
+    let incomplete = |part: &str| PipelineError::ParseFailed(format!("bundle has no {part}"));
+
+    let signed_pre_key = bundle
+        .signed_pre_key_public
+        .ok_or_else(|| incomplete("signed pre-key"))?;
+    Ok(OmemoBundle {
+        identity_key: bundle
+            .identity_key
+            .ok_or_else(|| incomplete("identity key"))?
+            .data,
+        signed_pre_key_id: signed_pre_key.signed_pre_key_id.unwrap_or_default(),
+        signed_pre_key: signed_pre_key.data,
+        signed_pre_key_signature: bundle
+            .signed_pre_key_signature
+            .ok_or_else(|| incomplete("signed pre-key signature"))?
+            .data,
+        pre_keys: bundle
+            .prekeys
+            .map(|prekeys| prekeys.keys)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|key| OmemoPreKey {
+                id: key.pre_key_id,
+                public_key: key.data,
+            })
+            .collect(),
+    })
+}
+
+pub fn bundle_to_element(bundle: &OmemoBundle) -> Element {
+    legacy_omemo::Bundle {
+        signed_pre_key_public: Some(legacy_omemo::SignedPreKeyPublic {
+            signed_pre_key_id: Some(bundle.signed_pre_key_id),
+            data: bundle.signed_pre_key.clone(),
+        }),
+        signed_pre_key_signature: Some(legacy_omemo::SignedPreKeySignature {
+            data: bundle.signed_pre_key_signature.clone(),
+        }),
+        identity_key: Some(legacy_omemo::IdentityKey {
+            data: bundle.identity_key.clone(),
+        }),
+        prekeys: Some(legacy_omemo::Prekeys {
+            keys: bundle
+                .pre_keys
+                .iter()
+                .map(|key| legacy_omemo::PreKeyPublic {
+                    pre_key_id: key.id,
+                    data: key.public_key.clone(),
+                })
+                .collect(),
+        }),
+    }
+    .into()
+}
+
+pub fn envelope_from_encrypted(encrypted: legacy_omemo::Encrypted) -> OmemoEnvelope {
+    OmemoEnvelope {
+        sender_device_id: encrypted.header.sid,
+        keys: encrypted
+            .header
+            .keys
+            .into_iter()
+            .map(|key| OmemoKeyElement {
+                recipient_device_id: key.rid,
+                prekey: key.prekey,
+                data: key.data,
+            })
+            .collect(),
+        iv: encrypted.header.iv.data,
+        payload: encrypted.payload.map(|payload| payload.data),
+    }
+}
+
+pub fn envelope_to_element(envelope: &OmemoEnvelope) -> Element {
+    legacy_omemo::Encrypted {
+        header: legacy_omemo::Header {
+            sid: envelope.sender_device_id,
+            keys: envelope
+                .keys
+                .iter()
+                .map(|key| legacy_omemo::Key {
+                    rid: key.recipient_device_id,
+                    prekey: key.prekey,
+                    data: key.data.clone(),
+                })
+                .collect(),
+            iv: legacy_omemo::IV {
+                data: envelope.iv.clone(),
+            },
+        },
+        payload: envelope
+            .payload
+            .as_ref()
+            .map(|data| legacy_omemo::Payload { data: data.clone() }),
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundle_nodes_carry_the_device_id() {
+        let node = bundle_node(31415);
+        assert_eq!(node, "eu.siacs.conversations.axolotl.bundles:31415");
+        assert_eq!(bundle_node_device(&node), Some(31415));
+        assert_eq!(bundle_node_device(DEVICE_LIST_NODE), None);
+    }
+
+    #[test]
+    fn bundle_round_trips() {
+        let bundle = OmemoBundle {
+            identity_key: vec![5; 33],
+            signed_pre_key_id: 1,
+            signed_pre_key: vec![5, 1, 2],
+            signed_pre_key_signature: vec![9; 64],
+            pre_keys: vec![OmemoPreKey {
+                id: 7,
+                public_key: vec![5, 3],
+            }],
+        };
+        assert_eq!(
+            bundle_from_element(bundle_to_element(&bundle)).unwrap(),
+            bundle
+        );
+    }
+
+    #[test]
+    fn envelope_round_trips() {
+        let envelope = OmemoEnvelope {
+            sender_device_id: 42,
+            keys: vec![OmemoKeyElement {
+                recipient_device_id: 7,
+                prekey: true,
+                data: vec![1, 2, 3],
+            }],
+            iv: vec![0; 12],
+            payload: Some(vec![4, 5]),
+        };
+        let encrypted = legacy_omemo::Encrypted::try_from(envelope_to_element(&envelope)).unwrap();
+        assert_eq!(envelope_from_encrypted(encrypted), envelope);
+    }
+
+    #[test]
+    fn bundle_without_identity_key_is_rejected() {
+        let element: Element = "<bundle xmlns='eu.siacs.conversations.axolotl'>\
+            <signedPreKeyPublic signedPreKeyId='1'>BQ==</signedPreKeyPublic>\
+            <signedPreKeySignature>AA==</signedPreKeySignature>\
+            </bundle>"
+            .parse()
+            .unwrap();
+        assert!(bundle_from_element(element).is_err());
+    }
+}
@@ -9,24 +9,44 @@ use tracing::{debug, error, warn};
 use uuid::Uuid;
 use xmpp_parsers::chatstates::ChatState as XmppChatState;
 use xmpp_parsers::data_forms::{DataForm, DataFormType, Field};
+use xmpp_parsers::disco::{DiscoInfoQuery, DiscoItemsQuery};
+use xmpp_parsers::eme::ExplicitMessageEncryption;
+use xmpp_parsers::idle::Idle;
 use xmpp_parsers::iq::Iq;
 use xmpp_parsers::jid;
 use xmpp_parsers::mam;
 use xmpp_parsers::message::{Lang, Message, MessageType as XmppMessageType};
+use xmpp_parsers::minidom::Element;
+use xmpp_parsers::minidom::rxml::xml_ncname;
 use xmpp_parsers::muc::Muc;
+use xmpp_parsers::muc::muc::History;
+use xmpp_parsers::ns;
 use xmpp_parsers::presence::{Presence, Show, Type as PresenceType};
+use xmpp_parsers::pubsub::pubsub::{Item, Items, Publish, PublishOptions};
+use xmpp_parsers::pubsub::{ItemId, NodeName, PubSub};
+use xmpp_parsers::receipts;
 use xmpp_parsers::roster;
-use xmpp_parsers::rsm;
+use xmpp_parsers::stanza_error::{DefinedCondition, ErrorType, StanzaError};
+use xmpp_parsers::stanza_id::OriginId;
 
 use waddle_core::event::{
-    ChatMessage, ChatState as CoreChatState, Event, EventPayload, EventSource,
-    MessageType as CoreMessageType, PresenceShow as CorePresenceShow,
+    ChatMessage, ChatState as CoreChatState, Event, EventPayload, EventSource, JingleAction,
+    MessageType as CoreMessageType, OmemoEnvelope, PresenceShow as CorePresenceShow, SpamReason,
 };
+use waddle_core::rsm::RsmQuery;
 
 #[cfg(feature = "native")]
-use waddle_core::event::{Channel, EventBus};
+use waddle_core::event::{Channel, EventBus, channels};
 
+use crate::jingle::jingle_to_element;
+use crate::omemo::{
+    DEVICE_LIST_NODE, bundle_node, bundle_to_element, device_list_to_element, envelope_to_element,
+};
 use crate::pipeline::StanzaPipeline;
+use crate::processors::{
+    DISCO_INFO_IQ_ID_PREFIX, DISCO_ITEMS_IQ_ID_PREFIX, NS_MODERATE, NS_RETRACT,
+};
+use crate::rsm;
 use crate::stanza::Stanza;
 
 #[cfg(feature = "native")]
@@ -43,6 +63,12 @@ pub fn stanza_channel(buffer: usize) -> (StanzaSender, StanzaReceiver) {
 #[cfg(feature = "native")]
 const OFFLINE_DRAIN_SOURCE: &str = "offline";
 
+const NS_INVISIBLE: &str = "urn:xmpp:invisible:0";
+const NS_BLOCKING: &str = "urn:xmpp:blocking";
+const NS_REPORTING: &str = "urn:xmpp:reporting:1";
+const NS_FALLBACK: &str = "urn:xmpp:fallback:0";
+const NS_CONFERENCE: &str = "jabber:x:conference";
+
 pub struct OutboundRouter {
     #[cfg(feature = "native")]
     event_bus: Arc<dyn EventBus>,
@@ -51,6 +77,9 @@ pub struct OutboundRouter {
     wire_sender: StanzaSender,
     #[cfg(feature = "native")]
     is_online: AtomicBool,
+    /// XEP-0186 invisibility is active for this session.
+    #[cfg(feature = "native")]
+    invisible: AtomicBool,
 }
 
 impl OutboundRouter {
@@ -65,6 +94,7 @@ impl OutboundRouter {
             pipeline,
             wire_sender,
             is_online: AtomicBool::new(false),
+            invisible: AtomicBool::new(false),
         }
     }
 
@@ -104,7 +134,12 @@ impl OutboundRouter {
     #[cfg(feature = "native")]
     async fn handle_event(&self, event: &Event) -> Result<(), OutboundRouterError> {
         match &event.payload {
-            EventPayload::ConnectionEstablished { .. } | EventPayload::ComingOnline => {
+            EventPayload::ConnectionEstablished { .. } => {
+                self.is_online.store(true, Ordering::Relaxed);
+                self.invisible.store(false, Ordering::Relaxed);
+                return Ok(());
+            }
+            EventPayload::ComingOnline => {
                 self.is_online.store(true, Ordering::Relaxed);
                 return Ok(());
             }
@@ -130,21 +165,50 @@ impl OutboundRouter {
                 to,
                 body,
                 message_type,
+                request_receipt,
             } => {
                 let message_id = event
                     .correlation_id
                     .map(|id| id.to_string())
                     .unwrap_or_else(|| Uuid::new_v4().to_string());
-                let stanza =
+                let mut stanza =
                     build_message_stanza(to, body, message_type, Some(message_id.as_str()))?;
+                if *request_receipt && let Stanza::Message(msg) = &mut stanza {
+                    msg.payloads.push(receipts::Request.into());
+                }
                 message_sent = Some((message_id, to.clone(), body.clone(), message_type.clone()));
                 Some(stanza)
             }
-            EventPayload::PresenceSetRequested { show, status } => {
-                let stanza = build_presence_stanza(show, status.as_deref());
+            EventPayload::PresenceSetRequested {
+                show,
+                status,
+                priority,
+                idle_since,
+            } => {
+                // While the server hides us (XEP-0186) we stay available to
+                // it; otherwise appearing offline means going unavailable.
+                let wire_show = match show {
+                    CorePresenceShow::Invisible if self.invisible.load(Ordering::Relaxed) => {
+                        &CorePresenceShow::Available
+                    }
+                    show => show,
+                };
+                let stanza = build_presence_stanza(
+                    wire_show,
+                    status.as_deref(),
+                    *priority,
+                    idle_since.as_ref(),
+                );
                 own_presence_changed = Some((show.clone(), status.clone()));
                 Some(stanza)
             }
+            EventPayload::InvisibilitySetRequested { invisible } => {
+                self.invisible.store(*invisible, Ordering::Relaxed);
+                Some(build_invisibility_stanza(*invisible))
+            }
+            EventPayload::DirectedPresenceRequested { to, show, status } => {
+                Some(build_directed_presence_stanza(to, show, status.as_deref())?)
+            }
             EventPayload::RosterAddRequested { jid, name, groups } => {
                 Some(build_roster_add_stanza(jid, name.as_deref(), groups)?)
             }
@@ -152,32 +216,102 @@ impl OutboundRouter {
                 Some(build_roster_add_stanza(jid, name.as_deref(), groups)?)
             }
             EventPayload::RosterRemoveRequested { jid } => Some(build_roster_remove_stanza(jid)?),
-            EventPayload::RosterFetchRequested => Some(build_roster_get_stanza()),
+            EventPayload::RosterFetchRequested { ver } => {
+                Some(build_roster_get_stanza(ver.as_deref()))
+            }
+            EventPayload::BlockRequested { jid, report } => {
+                Some(build_block_stanza("block", jid, *report)?)
+            }
+            EventPayload::UnblockRequested { jid } => {
+                Some(build_block_stanza("unblock", jid, None)?)
+            }
             EventPayload::SubscriptionRespondRequested { jid, accept } => {
                 Some(build_subscription_response_stanza(jid, *accept)?)
             }
-            EventPayload::SubscriptionSendRequested { jid, subscribe } => {
-                Some(build_subscription_send_stanza(jid, *subscribe)?)
+            EventPayload::SubscriptionSendRequested {
+                jid,
+                subscribe,
+                preauth,
+            } => Some(build_subscription_send_stanza(
+                jid,
+                *subscribe,
+                preauth.as_deref(),
+            )?),
+            EventPayload::MucJoinRequested {
+                room,
+                nick,
+                history_since,
+                password,
+            } => Some(build_muc_join_stanza(
+                room,
+                nick,
+                history_since.as_ref(),
+                password.as_deref(),
+            )?),
+            EventPayload::MucLeaveRequested { room } => Some(build_muc_leave_stanza(room)?),
+            EventPayload::MucInviteSendRequested { room, to, reason } => {
+                Some(build_direct_invite_stanza(room, to, reason.as_deref())?)
             }
-            EventPayload::MucJoinRequested { room, nick } => {
-                Some(build_muc_join_stanza(room, nick)?)
+            EventPayload::MucInviteDeclineRequested { room, to, reason } => {
+                Some(build_invite_decline_stanza(room, to, reason.as_deref())?)
             }
-            EventPayload::MucLeaveRequested { room } => Some(build_muc_leave_stanza(room)?),
             EventPayload::MucSendRequested { room, body } => {
-                Some(build_muc_message_stanza(room, body)?)
+                let message_id = event.correlation_id.map(|id| id.to_string());
+                Some(build_muc_message_stanza(room, body, message_id.as_deref())?)
+            }
+            EventPayload::MessageRetractRequested {
+                to,
+                id,
+                message_type,
+            } => Some(build_retract_stanza(to, id, message_type)?),
+            EventPayload::MessageModerateRequested { room, id, reason } => {
+                Some(build_moderate_stanza(room, id, reason.as_deref())?)
             }
             EventPayload::ChatStateSendRequested { to, state } => {
                 Some(build_chat_state_stanza(to, state)?)
             }
+            EventPayload::ReceiptSendRequested { to, id } => Some(build_receipt_stanza(to, id)?),
             EventPayload::MamQueryRequested {
                 query_id,
                 with_jid,
-                after,
-                before,
-                max,
+                page,
+                archive,
             } => Some(build_mam_query_stanza(
-                query_id, with_jid, after, before, *max,
-            )),
+                query_id,
+                with_jid,
+                page,
+                archive.as_deref(),
+            )?),
+            EventPayload::DiscoInfoRequested { jid, node } => {
+                Some(build_disco_info_stanza(jid, node.as_deref())?)
+            }
+            EventPayload::DiscoItemsRequested { jid, node, page } => {
+                Some(build_disco_items_stanza(jid, node.as_deref(), page)?)
+            }
+            EventPayload::OmemoDeviceListPublishRequested { devices } => Some(
+                build_pep_publish_stanza(DEVICE_LIST_NODE, device_list_to_element(devices)),
+            ),
+            EventPayload::OmemoBundlePublishRequested { device_id, bundle } => Some(
+                build_pep_publish_stanza(&bundle_node(*device_id), bundle_to_element(bundle)),
+            ),
+            EventPayload::OmemoDeviceListFetchRequested { jid } => {
+                Some(build_pep_items_stanza(jid, DEVICE_LIST_NODE)?)
+            }
+            EventPayload::OmemoBundleFetchRequested { jid, device_id } => {
+                Some(build_pep_items_stanza(jid, &bundle_node(*device_id))?)
+            }
+            EventPayload::OmemoMessageSendRequested { to, id, envelope } => {
+                Some(build_omemo_message_stanza(to, id, envelope)?)
+            }
+            EventPayload::JingleSendRequested {
+                to,
+                iq_id,
+                sid,
+                action,
+            } => Some(build_jingle_stanza(to, iq_id, sid, action)?),
+            EventPayload::JingleAnswerRequested { to, iq_id, error } => {
+                Some(build_jingle_answer_stanza(to, iq_id, error.as_deref())?)
+            }
             _ => None,
         };
 
@@ -214,7 +348,7 @@ impl OutboundRouter {
         body: &str,
         message_type: &CoreMessageType,
     ) {
-        let channel = match Channel::new("xmpp.message.sent") {
+        let channel = match Channel::new(channels::XMPP_MESSAGE_SENT) {
             Ok(c) => c,
             Err(_) => return,
         };
@@ -233,6 +367,11 @@ impl OutboundRouter {
             message_type: message_type.clone(),
             thread: None,
             embeds: vec![],
+            origin_id: Some(message_id.to_string()),
+            stanza_id: None,
+            retracted_at: None,
+            lang: None,
+            translation: None,
         };
 
         let sent_event = if let Some(corr) = event.correlation_id {
@@ -255,7 +394,7 @@ impl OutboundRouter {
 
     #[cfg(feature = "native")]
     fn emit_own_presence_changed(&self, show: &CorePresenceShow, status: Option<&str>) {
-        let channel = match Channel::new("xmpp.presence.own_changed") {
+        let channel = match Channel::new(channels::XMPP_PRESENCE_OWN_CHANGED) {
             Ok(c) => c,
             Err(_) => return,
         };
@@ -289,21 +428,26 @@ fn build_message_stanza(
     };
 
     let mut msg = Message::new_with_type(xmpp_type, Some(to_jid));
-    msg.id = Some(xmpp_parsers::message::Id(
-        message_id
-            .map(ToOwned::to_owned)
-            .unwrap_or_else(|| Uuid::new_v4().to_string()),
-    ));
+    let id = message_id
+        .map(ToOwned::to_owned)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    msg.id = Some(xmpp_parsers::message::Id(id.clone()));
     msg.bodies.insert(Lang::new(), body.to_string());
+    msg.payloads.push(OriginId { id }.into());
 
     Ok(Stanza::Message(Box::new(msg)))
 }
 
-fn build_presence_stanza(show: &CorePresenceShow, status: Option<&str>) -> Stanza {
-    let mut presence = Presence::new(PresenceType::None);
+fn build_presence_stanza(
+    show: &CorePresenceShow,
+    status: Option<&str>,
+    priority: i8,
+    idle_since: Option<&chrono::DateTime<chrono::Utc>>,
+) -> Stanza {
+    let mut presence = Presence::new(PresenceType::None).with_priority(priority);
 
     match show {
-        CorePresenceShow::Unavailable => {
+        CorePresenceShow::Unavailable | CorePresenceShow::Invisible => {
             presence.type_ = PresenceType::Unavailable;
         }
         CorePresenceShow::Available => {}
@@ -325,9 +469,51 @@ fn build_presence_stanza(show: &CorePresenceShow, status: Option<&str>) -> Stanz
         presence.statuses.insert(Lang::new(), text.to_string());
     }
 
+    if let Some(since) = idle_since
+        && !matches!(
+            show,
+            CorePresenceShow::Unavailable | CorePresenceShow::Invisible
+        )
+    {
+        presence.add_payload(Idle {
+            since: xmpp_parsers::date::DateTime(since.fixed_offset()),
+        });
+    }
+
     Stanza::Presence(Box::new(presence))
 }
 
+/// Directed presence carries show and status only; priority and idle time
+/// describe the resource to the server and the roster, not to one peer.
+fn build_directed_presence_stanza(
+    to: &str,
+    show: &CorePresenceShow,
+    status: Option<&str>,
+) -> Result<Stanza, OutboundRouterError> {
+    let to_jid: jid::Jid = to
+        .parse()
+        .map_err(|_| OutboundRouterError::InvalidJid(to.to_string()))?;
+
+    let mut stanza = build_presence_stanza(show, status, 0, None);
+    if let Stanza::Presence(presence) = &mut stanza {
+        presence.to = Some(to_jid);
+    }
+    Ok(stanza)
+}
+
+/// XEP-0186: while invisible the server withholds our presence from
+/// contacts but keeps routing messages to this session.
+fn build_invisibility_stanza(invisible: bool) -> Stanza {
+    let command = if invisible { "invisible" } else { "visible" };
+    let iq = Iq::Set {
+        from: None,
+        to: None,
+        id: Uuid::new_v4().to_string(),
+        payload: Element::builder(command, NS_INVISIBLE).build(),
+    };
+    Stanza::Iq(Box::new(iq))
+}
+
 fn build_roster_add_stanza(
     jid_str: &str,
     name: Option<&str>,
@@ -354,9 +540,9 @@ fn build_roster_add_stanza(
     Ok(Stanza::Iq(Box::new(iq)))
 }
 
-fn build_roster_get_stanza() -> Stanza {
+fn build_roster_get_stanza(ver: Option<&str>) -> Stanza {
     let query = roster::Roster {
-        ver: None,
+        ver: ver.map(String::from),
         items: vec![],
     };
     let iq = Iq::from_get(Uuid::new_v4().to_string(), query);
@@ -385,6 +571,34 @@ fn build_roster_remove_stanza(jid_str: &str) -> Result<Stanza, OutboundRouterErr
     Ok(Stanza::Iq(Box::new(iq)))
 }
 
+/// A XEP-0191 `block` or `unblock` command for `jid`, carrying a XEP-0377
+/// report when one is given.
+fn build_block_stanza(
+    command: &str,
+    jid_str: &str,
+    report: Option<SpamReason>,
+) -> Result<Stanza, OutboundRouterError> {
+    let contact_jid: jid::Jid = jid_str
+        .parse()
+        .map_err(|_| OutboundRouterError::InvalidJid(jid_str.to_string()))?;
+
+    let mut item =
+        Element::builder("item", NS_BLOCKING).attr(xml_ncname!("jid").to_owned(), contact_jid);
+    if let Some(reason) = report {
+        item = item.append(
+            Element::builder("report", NS_REPORTING)
+                .attr(xml_ncname!("reason").to_owned(), reason.uri()),
+        );
+    }
+    let iq = Iq::Set {
+        from: None,
+        to: None,
+        id: Uuid::new_v4().to_string(),
+        payload: Element::builder(command, NS_BLOCKING).append(item).build(),
+    };
+    Ok(Stanza::Iq(Box::new(iq)))
+}
+
 fn build_subscription_response_stanza(
     jid_str: &str,
     accept: bool,
@@ -406,6 +620,7 @@ fn build_subscription_response_stanza(
 fn build_subscription_send_stanza(
     jid_str: &str,
     subscribe: bool,
+    preauth: Option<&str>,
 ) -> Result<Stanza, OutboundRouterError> {
     let to_jid: jid::Jid = jid_str
         .parse()
@@ -417,11 +632,23 @@ fn build_subscription_send_stanza(
         PresenceType::Unsubscribe
     });
     presence.to = Some(to_jid);
+    if subscribe && let Some(token) = preauth {
+        presence.payloads.push(
+            Element::builder("preauth", "urn:xmpp:pars:0")
+                .attr(xml_ncname!("token").to_owned(), token)
+                .build(),
+        );
+    }
 
     Ok(Stanza::Presence(Box::new(presence)))
 }
 
-fn build_muc_join_stanza(room: &str, nick: &str) -> Result<Stanza, OutboundRouterError> {
+fn build_muc_join_stanza(
+    room: &str,
+    nick: &str,
+    history_since: Option<&chrono::DateTime<chrono::Utc>>,
+    password: Option<&str>,
+) -> Result<Stanza, OutboundRouterError> {
     let room_jid: jid::Jid = format!("{room}/{nick}")
         .parse()
         .map_err(|_| OutboundRouterError::InvalidJid(format!("{room}/{nick}")))?;
@@ -429,7 +656,15 @@ fn build_muc_join_stanza(room: &str, nick: &str) -> Result<Stanza, OutboundRoute
     let mut presence = Presence::new(PresenceType::None);
     presence.to = Some(room_jid);
 
-    let muc = Muc::new();
+    let mut muc = Muc::new();
+    if let Some(since) = history_since {
+        muc = muc.with_history(
+            History::new().with_since(xmpp_parsers::date::DateTime(since.fixed_offset())),
+        );
+    }
+    if let Some(password) = password {
+        muc = muc.with_password(password.to_string());
+    }
     let muc_element: xmpp_parsers::minidom::Element = muc.into();
     presence.payloads.push(muc_element);
 
@@ -447,31 +682,152 @@ fn build_muc_leave_stanza(room: &str) -> Result<Stanza, OutboundRouterError> {
     Ok(Stanza::Presence(Box::new(presence)))
 }
 
-fn build_muc_message_stanza(room: &str, body: &str) -> Result<Stanza, OutboundRouterError> {
+/// A XEP-0249 direct invitation to `room`, sent to the invitee.
+fn build_direct_invite_stanza(
+    room: &str,
+    to: &str,
+    reason: Option<&str>,
+) -> Result<Stanza, OutboundRouterError> {
+    let to_jid: jid::Jid = to
+        .parse()
+        .map_err(|_| OutboundRouterError::InvalidJid(to.to_string()))?;
+    let room_jid: jid::BareJid = room
+        .parse()
+        .map_err(|_| OutboundRouterError::InvalidJid(room.to_string()))?;
+
+    let mut invite = Element::builder("x", NS_CONFERENCE)
+        .attr(xml_ncname!("jid").to_owned(), room_jid.to_string());
+    if let Some(reason) = reason {
+        invite = invite.attr(xml_ncname!("reason").to_owned(), reason);
+    }
+    let mut msg = Message::new(Some(to_jid));
+    msg.id = Some(xmpp_parsers::message::Id(Uuid::new_v4().to_string()));
+    msg.payloads.push(invite.build());
+
+    Ok(Stanza::Message(Box::new(msg)))
+}
+
+/// A XEP-0045 decline of an invitation to `room`, which the room passes on
+/// to the inviter `to`.
+fn build_invite_decline_stanza(
+    room: &str,
+    to: &str,
+    reason: Option<&str>,
+) -> Result<Stanza, OutboundRouterError> {
     let room_jid: jid::Jid = room
         .parse()
         .map_err(|_| OutboundRouterError::InvalidJid(room.to_string()))?;
+    let inviter: jid::BareJid = to
+        .parse()
+        .map_err(|_| OutboundRouterError::InvalidJid(to.to_string()))?;
 
-    let mut msg = Message::new_with_type(XmppMessageType::Groupchat, Some(room_jid));
+    let mut decline = Element::builder("decline", ns::MUC_USER)
+        .attr(xml_ncname!("to").to_owned(), inviter.to_string());
+    if let Some(reason) = reason {
+        decline = decline.append(Element::builder("reason", ns::MUC_USER).append(reason));
+    }
+    let mut msg = Message::new(Some(room_jid));
     msg.id = Some(xmpp_parsers::message::Id(Uuid::new_v4().to_string()));
+    msg.payloads
+        .push(Element::builder("x", ns::MUC_USER).append(decline).build());
+
+    Ok(Stanza::Message(Box::new(msg)))
+}
+
+/// Build a groupchat message. Queued sends pass their correlation ID as `id`
+/// so the room's reflection of the message can be matched to the queue. The
+/// id is repeated as the XEP-0359 origin id, which survives rooms that
+/// rewrite the stanza's own id.
+fn build_muc_message_stanza(
+    room: &str,
+    body: &str,
+    id: Option<&str>,
+) -> Result<Stanza, OutboundRouterError> {
+    let room_jid: jid::Jid = room
+        .parse()
+        .map_err(|_| OutboundRouterError::InvalidJid(room.to_string()))?;
+
+    let mut msg = Message::new_with_type(XmppMessageType::Groupchat, Some(room_jid));
+    let id = id.map_or_else(|| Uuid::new_v4().to_string(), str::to_string);
+    msg.id = Some(xmpp_parsers::message::Id(id.clone()));
     msg.bodies.insert(Lang::new(), body.to_string());
+    msg.payloads.push(OriginId { id }.into());
+
+    Ok(Stanza::Message(Box::new(msg)))
+}
+
+/// A XEP-0424 retraction of message `id`, with a fallback body for clients
+/// that do not support it.
+fn build_retract_stanza(
+    to: &str,
+    id: &str,
+    message_type: &CoreMessageType,
+) -> Result<Stanza, OutboundRouterError> {
+    let to_jid: jid::Jid = to
+        .parse()
+        .map_err(|_| OutboundRouterError::InvalidJid(to.to_string()))?;
+
+    let xmpp_type = match message_type {
+        CoreMessageType::Groupchat => XmppMessageType::Groupchat,
+        _ => XmppMessageType::Chat,
+    };
+    let mut msg = Message::new_with_type(xmpp_type, Some(to_jid));
+    msg.id = Some(xmpp_parsers::message::Id(Uuid::new_v4().to_string()));
+    msg.bodies.insert(
+        Lang::new(),
+        "This person attempted to retract a previous message, but it's unsupported by your client."
+            .to_string(),
+    );
+    msg.payloads.push(
+        Element::builder("retract", NS_RETRACT)
+            .attr(xml_ncname!("id").to_owned(), id)
+            .build(),
+    );
+    msg.payloads.push(
+        Element::builder("fallback", NS_FALLBACK)
+            .attr(xml_ncname!("for").to_owned(), NS_RETRACT)
+            .build(),
+    );
+    msg.payloads
+        .push(Element::builder("store", "urn:xmpp:hints").build());
 
     Ok(Stanza::Message(Box::new(msg)))
 }
 
+/// A XEP-0425 request asking `room` to retract message `id` for everyone.
+fn build_moderate_stanza(
+    room: &str,
+    id: &str,
+    reason: Option<&str>,
+) -> Result<Stanza, OutboundRouterError> {
+    let room_jid: jid::Jid = room
+        .parse()
+        .map_err(|_| OutboundRouterError::InvalidJid(room.to_string()))?;
+
+    let mut moderate = Element::builder("moderate", NS_MODERATE)
+        .attr(xml_ncname!("id").to_owned(), id)
+        .append(Element::builder("retract", NS_RETRACT));
+    if let Some(reason) = reason {
+        moderate = moderate.append(Element::builder("reason", NS_MODERATE).append(reason));
+    }
+    let iq = Iq::Set {
+        from: None,
+        to: Some(room_jid),
+        id: Uuid::new_v4().to_string(),
+        payload: moderate.build(),
+    };
+    Ok(Stanza::Iq(Box::new(iq)))
+}
+
+/// Build a MAM query. `archive` addresses a room archive; without it the
+/// query goes to the user's own archive.
 fn build_mam_query_stanza(
     query_id: &str,
     with_jid: &Option<String>,
-    after: &Option<String>,
-    before: &Option<String>,
-    max: u32,
-) -> Stanza {
-    let set = rsm::SetQuery {
-        max: Some(max as usize),
-        after: after.clone(),
-        before: before.clone(),
-        index: None,
-    };
+    page: &RsmQuery,
+    archive: Option<&str>,
+) -> Result<Stanza, OutboundRouterError> {
+    let set = rsm::set_query(page);
 
     let form = with_jid.as_ref().map(|jid| {
         DataForm::new(
@@ -489,10 +845,120 @@ fn build_mam_query_stanza(
         flip_page: false,
     };
 
-    let iq = Iq::from_set(query_id.to_string(), query);
+    let mut iq = Iq::from_set(query_id.to_string(), query);
+    if let Some(archive) = archive {
+        let archive_jid: jid::Jid = archive
+            .parse()
+            .map_err(|_| OutboundRouterError::InvalidJid(archive.to_string()))?;
+        iq = iq.with_to(archive_jid);
+    }
+    Ok(Stanza::Iq(Box::new(iq)))
+}
+
+fn build_disco_info_stanza(
+    jid_str: &str,
+    node: Option<&str>,
+) -> Result<Stanza, OutboundRouterError> {
+    let to_jid: jid::Jid = jid_str
+        .parse()
+        .map_err(|_| OutboundRouterError::InvalidJid(jid_str.to_string()))?;
+
+    let query = DiscoInfoQuery {
+        node: node.map(String::from),
+    };
+
+    let id = format!("{DISCO_INFO_IQ_ID_PREFIX}{}", Uuid::new_v4());
+    let iq = Iq::from_get(id, query).with_to(to_jid);
+    Ok(Stanza::Iq(Box::new(iq)))
+}
+
+/// A disco#items query for one page of `jid`'s items, such as the rooms of
+/// a MUC service.
+fn build_disco_items_stanza(
+    jid_str: &str,
+    node: Option<&str>,
+    page: &RsmQuery,
+) -> Result<Stanza, OutboundRouterError> {
+    let to_jid: jid::Jid = jid_str
+        .parse()
+        .map_err(|_| OutboundRouterError::InvalidJid(jid_str.to_string()))?;
+
+    let query = DiscoItemsQuery {
+        node: node.map(String::from),
+        rsm: Some(rsm::set_query(page)),
+    };
+
+    let id = format!("{DISCO_ITEMS_IQ_ID_PREFIX}{}", Uuid::new_v4());
+    let iq = Iq::from_get(id, query).with_to(to_jid);
+    Ok(Stanza::Iq(Box::new(iq)))
+}
+
+/// Publish `payload` as the single `current` item of one of our PEP nodes.
+/// OMEMO nodes must be readable by contacts not subscribed to our presence,
+/// hence the open access model.
+fn build_pep_publish_stanza(node: &str, payload: Element) -> Stanza {
+    let pubsub = PubSub::Publish {
+        publish: Publish {
+            node: NodeName(node.to_string()),
+            items: vec![Item {
+                id: Some(ItemId("current".to_string())),
+                publisher: None,
+                payload: Some(payload),
+            }],
+        },
+        publish_options: Some(PublishOptions {
+            form: Some(DataForm::new(
+                DataFormType::Submit,
+                "http://jabber.org/protocol/pubsub#publish-options",
+                vec![Field::text_single("pubsub#access_model", "open")],
+            )),
+        }),
+    };
+
+    let iq = Iq::from_set(Uuid::new_v4().to_string(), pubsub);
     Stanza::Iq(Box::new(iq))
 }
 
+fn build_pep_items_stanza(jid_str: &str, node: &str) -> Result<Stanza, OutboundRouterError> {
+    let to_jid: jid::Jid = jid_str
+        .parse()
+        .map_err(|_| OutboundRouterError::InvalidJid(jid_str.to_string()))?;
+
+    let iq =
+        Iq::from_get(Uuid::new_v4().to_string(), PubSub::Items(Items::new(node))).with_to(to_jid);
+    Ok(Stanza::Iq(Box::new(iq)))
+}
+
+fn build_omemo_message_stanza(
+    to: &str,
+    message_id: &str,
+    envelope: &OmemoEnvelope,
+) -> Result<Stanza, OutboundRouterError> {
+    let to_jid: jid::Jid = to
+        .parse()
+        .map_err(|_| OutboundRouterError::InvalidJid(to.to_string()))?;
+
+    let mut msg = Message::new_with_type(XmppMessageType::Chat, Some(to_jid));
+    msg.id = Some(xmpp_parsers::message::Id(message_id.to_string()));
+    msg.bodies.insert(
+        Lang::new(),
+        "I sent you an OMEMO encrypted message but your client doesn't seem to support that."
+            .to_string(),
+    );
+    msg.payloads.push(envelope_to_element(envelope));
+    msg.payloads.push(
+        ExplicitMessageEncryption {
+            namespace: ns::LEGACY_OMEMO.to_string(),
+            name: Some("OMEMO".to_string()),
+        }
+        .into(),
+    );
+    msg.payloads
+        .push(Element::builder("store", "urn:xmpp:hints").build());
+
+    Ok(Stanza::Message(Box::new(msg)))
+}
+
 fn build_chat_state_stanza(to: &str, state: &CoreChatState) -> Result<Stanza, OutboundRouterError> {
     let to_jid: jid::Jid = to
         .parse()
@@ -514,6 +980,87 @@ fn build_chat_state_stanza(to: &str, state: &CoreChatState) -> Result<Stanza, Ou
     Ok(Stanza::Message(Box::new(msg)))
 }
 
+fn build_receipt_stanza(to: &str, id: &str) -> Result<Stanza, OutboundRouterError> {
+    let to_jid: jid::Jid = to
+        .parse()
+        .map_err(|_| OutboundRouterError::InvalidJid(to.to_string()))?;
+
+    let mut msg = Message::new(Some(to_jid));
+    msg.type_ = XmppMessageType::Chat;
+    msg.payloads
+        .push(receipts::Received { id: id.to_string() }.into());
+
+    Ok(Stanza::Message(Box::new(msg)))
+}
+
+fn build_jingle_stanza(
+    to: &str,
+    iq_id: &str,
+    sid: &str,
+    action: &JingleAction,
+) -> Result<Stanza, OutboundRouterError> {
+    let to_jid: jid::Jid = to
+        .parse()
+        .map_err(|_| OutboundRouterError::InvalidJid(to.to_string()))?;
+
+    let iq = Iq::Set {
+        from: None,
+        to: Some(to_jid),
+        id: iq_id.to_string(),
+        payload: jingle_to_element(sid, action),
+    };
+    Ok(Stanza::Iq(Box::new(iq)))
+}
+
+/// Acknowledge a Jingle request, or refuse it with the stanza error
+/// `condition` and the XEP-0166 error that goes with it.
+fn build_jingle_answer_stanza(
+    to: &str,
+    iq_id: &str,
+    condition: Option<&str>,
+) -> Result<Stanza, OutboundRouterError> {
+    let to_jid: jid::Jid = to
+        .parse()
+        .map_err(|_| OutboundRouterError::InvalidJid(to.to_string()))?;
+
+    let Some(condition) = condition else {
+        return Ok(Stanza::Iq(Box::new(Iq::Result {
+            from: None,
+            to: Some(to_jid),
+            id: iq_id.to_string(),
+            payload: None,
+        })));
+    };
+    let defined_condition =
+        DefinedCondition::try_from(Element::builder(condition, ns::XMPP_STANZAS).build())
+            .unwrap_or(DefinedCondition::UndefinedCondition);
+    let jingle_condition = match condition {
+        "item-not-found" => Some("unknown-session"),
+        "unexpected-request" => Some("out-of-order"),
+        "feature-not-implemented" => Some("unsupported-info"),
+        _ => None,
+    };
+    let error = StanzaError {
+        type_: if condition == "bad-request" {
+            ErrorType::Modify
+        } else {
+            ErrorType::Cancel
+        },
+        by: None,
+        defined_condition,
+        texts: Default::default(),
+        other: jingle_condition
+            .map(|name| Element::builder(name, "urn:xmpp:jingle:errors:1").build()),
+    };
+    Ok(Stanza::Iq(Box::new(Iq::Error {
+        from: None,
+        to: Some(to_jid),
+        id: iq_id.to_string(),
+        payload: None,
+        error,
+    })))
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum OutboundRouterError {
     #[error("failed to subscribe to events: {0}")]
@@ -573,41 +1120,196 @@ mod tests {
 
     #[test]
     fn builds_available_presence() {
-        let stanza = build_presence_stanza(&CorePresenceShow::Available, None);
+        let stanza = build_presence_stanza(&CorePresenceShow::Available, None, 0, None);
+        let Stanza::Presence(p) = &stanza else {
+            panic!("expected presence stanza");
+        };
+        assert_eq!(p.type_, PresenceType::None);
+        assert!(p.show.is_none());
+    }
+
+    #[test]
+    fn builds_away_presence_with_status() {
+        let stanza = build_presence_stanza(&CorePresenceShow::Away, Some("brb"), 0, None);
+        let Stanza::Presence(p) = &stanza else {
+            panic!("expected presence stanza");
+        };
+        assert_eq!(p.show, Some(Show::Away));
+        assert_eq!(p.statuses.get("").map(String::as_str), Some("brb"));
+    }
+
+    #[test]
+    fn builds_unavailable_presence() {
+        let stanza = build_presence_stanza(&CorePresenceShow::Unavailable, None, 0, None);
+        let Stanza::Presence(p) = &stanza else {
+            panic!("expected presence stanza");
+        };
+        assert_eq!(p.type_, PresenceType::Unavailable);
+    }
+
+    #[test]
+    fn builds_presence_with_idle() {
+        let since = chrono::Utc::now();
+        let stanza = build_presence_stanza(&CorePresenceShow::Away, None, 0, Some(&since));
+        let Stanza::Presence(p) = &stanza else {
+            panic!("expected presence stanza");
+        };
+        let idle = p
+            .payloads
+            .iter()
+            .find_map(|el| Idle::try_from(el.clone()).ok())
+            .expect("idle payload");
+        assert_eq!(idle.since.0.timestamp(), since.timestamp());
+    }
+
+    #[test]
+    fn unavailable_presence_omits_idle() {
+        let since = chrono::Utc::now();
+        let stanza = build_presence_stanza(&CorePresenceShow::Unavailable, None, 0, Some(&since));
+        let Stanza::Presence(p) = &stanza else {
+            panic!("expected presence stanza");
+        };
+        assert!(p.payloads.is_empty());
+    }
+
+    #[test]
+    fn builds_dnd_presence() {
+        let stanza = build_presence_stanza(&CorePresenceShow::Dnd, Some("busy"), 0, None);
+        let Stanza::Presence(p) = &stanza else {
+            panic!("expected presence stanza");
+        };
+        assert_eq!(p.show, Some(Show::Dnd));
+        assert_eq!(p.statuses.get("").map(String::as_str), Some("busy"));
+    }
+
+    #[test]
+    fn invisible_presence_falls_back_to_unavailable() {
+        let since = chrono::Utc::now();
+        let stanza = build_presence_stanza(&CorePresenceShow::Invisible, None, 0, Some(&since));
         let Stanza::Presence(p) = &stanza else {
             panic!("expected presence stanza");
         };
-        assert_eq!(p.type_, PresenceType::None);
-        assert!(p.show.is_none());
+        assert_eq!(p.type_, PresenceType::Unavailable);
+        assert!(p.payloads.is_empty());
+    }
+
+    #[test]
+    fn builds_invisibility_commands() {
+        for (invisible, command) in [(true, "invisible"), (false, "visible")] {
+            let Stanza::Iq(iq) = build_invisibility_stanza(invisible) else {
+                panic!("expected iq stanza");
+            };
+            let Iq::Set { payload, .. } = iq.as_ref() else {
+                panic!("expected iq set");
+            };
+            assert!(payload.is(command, NS_INVISIBLE));
+        }
+    }
+
+    #[test]
+    fn builds_block_commands_with_reports() {
+        let Stanza::Iq(iq) =
+            build_block_stanza("block", "spammer@example.com", Some(SpamReason::Spam)).unwrap()
+        else {
+            panic!("expected iq stanza");
+        };
+        let Iq::Set { payload, .. } = iq.as_ref() else {
+            panic!("expected iq set");
+        };
+        assert!(payload.is("block", NS_BLOCKING));
+        let item = payload.get_child("item", NS_BLOCKING).unwrap();
+        assert_eq!(item.attr("jid"), Some("spammer@example.com"));
+        assert_eq!(
+            item.get_child("report", NS_REPORTING)
+                .and_then(|report| report.attr("reason")),
+            Some("urn:xmpp:reporting:spam")
+        );
+
+        let Stanza::Iq(iq) = build_block_stanza("unblock", "friend@example.com", None).unwrap()
+        else {
+            panic!("expected iq stanza");
+        };
+        let Iq::Set { payload, .. } = iq.as_ref() else {
+            panic!("expected iq set");
+        };
+        assert!(payload.is("unblock", NS_BLOCKING));
+        assert!(
+            payload
+                .get_child("item", NS_BLOCKING)
+                .unwrap()
+                .get_child("report", NS_REPORTING)
+                .is_none()
+        );
+        assert!(build_block_stanza("block", "not a jid@", None).is_err());
+    }
+
+    #[test]
+    fn builds_retractions_and_moderation_requests() {
+        let Stanza::Message(msg) =
+            build_retract_stanza("bob@example.com", "origin-1", &CoreMessageType::Chat).unwrap()
+        else {
+            panic!("expected message stanza");
+        };
+        assert_eq!(msg.type_, XmppMessageType::Chat);
+        let retract = msg
+            .payloads
+            .iter()
+            .find(|payload| payload.is("retract", NS_RETRACT))
+            .unwrap();
+        assert_eq!(retract.attr("id"), Some("origin-1"));
+        assert!(msg.get_best_body(vec![]).is_some());
+
+        let Stanza::Iq(iq) =
+            build_moderate_stanza("room@conference.example.com", "stanza-1", Some("Spam")).unwrap()
+        else {
+            panic!("expected iq stanza");
+        };
+        let Iq::Set { to, payload, .. } = iq.as_ref() else {
+            panic!("expected iq set");
+        };
+        assert_eq!(
+            to.as_ref().map(|jid| jid.to_string()).as_deref(),
+            Some("room@conference.example.com")
+        );
+        assert!(payload.is("moderate", NS_MODERATE));
+        assert_eq!(payload.attr("id"), Some("stanza-1"));
+        assert!(payload.get_child("retract", NS_RETRACT).is_some());
+        assert_eq!(
+            payload
+                .get_child("reason", NS_MODERATE)
+                .map(|reason| reason.text()),
+            Some("Spam".to_string())
+        );
     }
 
     #[test]
-    fn builds_away_presence_with_status() {
-        let stanza = build_presence_stanza(&CorePresenceShow::Away, Some("brb"));
+    fn builds_directed_presence() {
+        let stanza = build_directed_presence_stanza(
+            "dev@conference.example.com/alice",
+            &CorePresenceShow::Away,
+            Some("brb"),
+        )
+        .unwrap();
         let Stanza::Presence(p) = &stanza else {
             panic!("expected presence stanza");
         };
+        assert_eq!(
+            p.to.as_ref().map(|to| to.to_string()).as_deref(),
+            Some("dev@conference.example.com/alice")
+        );
         assert_eq!(p.show, Some(Show::Away));
         assert_eq!(p.statuses.get("").map(String::as_str), Some("brb"));
     }
 
     #[test]
-    fn builds_unavailable_presence() {
-        let stanza = build_presence_stanza(&CorePresenceShow::Unavailable, None);
-        let Stanza::Presence(p) = &stanza else {
-            panic!("expected presence stanza");
-        };
-        assert_eq!(p.type_, PresenceType::Unavailable);
-    }
-
-    #[test]
-    fn builds_dnd_presence() {
-        let stanza = build_presence_stanza(&CorePresenceShow::Dnd, Some("busy"));
+    fn builds_presence_with_priority() {
+        let stanza = build_presence_stanza(&CorePresenceShow::Available, None, 5, None);
         let Stanza::Presence(p) = &stanza else {
             panic!("expected presence stanza");
         };
-        assert_eq!(p.show, Some(Show::Dnd));
-        assert_eq!(p.statuses.get("").map(String::as_str), Some("busy"));
+        let el = Element::from((**p).clone());
+        let priority = el.get_child("priority", "jabber:client").expect("priority");
+        assert_eq!(priority.text(), "5");
     }
 
     #[test]
@@ -645,6 +1347,70 @@ mod tests {
         }
     }
 
+    #[test]
+    fn builds_roster_get_with_version() {
+        for (ver, expected) in [
+            (Some("v42"), Some("v42")),
+            (Some(""), Some("")),
+            (None, None),
+        ] {
+            let Stanza::Iq(iq) = build_roster_get_stanza(ver) else {
+                panic!("expected iq stanza");
+            };
+            let Iq::Get { payload, .. } = *iq else {
+                panic!("expected IQ get");
+            };
+            assert_eq!(payload.attr("ver"), expected);
+        }
+    }
+
+    #[test]
+    fn builds_disco_info_query_with_node() {
+        let stanza =
+            build_disco_info_stanza("alice@example.com/desktop", Some("https://example.org#ver"))
+                .unwrap();
+        let Stanza::Iq(iq) = &stanza else {
+            panic!("expected iq stanza");
+        };
+        match iq.as_ref() {
+            Iq::Get { to, payload, .. } => {
+                assert_eq!(
+                    to.as_ref().map(|j| j.to_string()).as_deref(),
+                    Some("alice@example.com/desktop")
+                );
+                let query = DiscoInfoQuery::try_from(payload.clone()).unwrap();
+                assert_eq!(query.node.as_deref(), Some("https://example.org#ver"));
+            }
+            _ => panic!("expected IQ get"),
+        }
+    }
+
+    #[test]
+    fn builds_paged_disco_items_query() {
+        let stanza = build_disco_items_stanza(
+            "conference.example.com",
+            None,
+            &RsmQuery::after("room-20", 20),
+        )
+        .unwrap();
+        let Stanza::Iq(iq) = &stanza else {
+            panic!("expected iq stanza");
+        };
+        match iq.as_ref() {
+            Iq::Get { to, payload, .. } => {
+                assert_eq!(
+                    to.as_ref().map(|j| j.to_string()).as_deref(),
+                    Some("conference.example.com")
+                );
+                let query = DiscoItemsQuery::try_from(payload.clone()).unwrap();
+                let set = query.rsm.expect("disco#items query should be paged");
+                assert_eq!(set.max, Some(20));
+                assert_eq!(set.after.as_deref(), Some("room-20"));
+            }
+            _ => panic!("expected IQ get"),
+        }
+    }
+
     #[test]
     fn builds_subscription_accept() {
         let stanza = build_subscription_response_stanza("carol@example.com", true).unwrap();
@@ -669,7 +1435,7 @@ mod tests {
 
     #[test]
     fn builds_subscription_subscribe() {
-        let stanza = build_subscription_send_stanza("carol@example.com", true).unwrap();
+        let stanza = build_subscription_send_stanza("carol@example.com", true, None).unwrap();
         let Stanza::Presence(p) = &stanza else {
             panic!("expected presence stanza");
         };
@@ -682,7 +1448,7 @@ mod tests {
 
     #[test]
     fn builds_subscription_unsubscribe() {
-        let stanza = build_subscription_send_stanza("carol@example.com", false).unwrap();
+        let stanza = build_subscription_send_stanza("carol@example.com", false, None).unwrap();
         let Stanza::Presence(p) = &stanza else {
             panic!("expected presence stanza");
         };
@@ -693,9 +1459,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn subscribe_carries_preauth_token() {
+        let stanza =
+            build_subscription_send_stanza("carol@example.com", true, Some("ae7d5a")).unwrap();
+        let Stanza::Presence(p) = &stanza else {
+            panic!("expected presence stanza");
+        };
+        let preauth = p
+            .payloads
+            .iter()
+            .find(|el| el.is("preauth", "urn:xmpp:pars:0"))
+            .expect("preauth element");
+        assert_eq!(preauth.attr("token"), Some("ae7d5a"));
+    }
+
     #[test]
     fn builds_muc_join_stanza_test() {
-        let stanza = build_muc_join_stanza("room@conference.example.com", "mynick").unwrap();
+        let stanza =
+            build_muc_join_stanza("room@conference.example.com", "mynick", None, None).unwrap();
         let Stanza::Presence(p) = &stanza else {
             panic!("expected presence stanza");
         };
@@ -710,6 +1492,94 @@ mod tests {
         assert!(has_muc, "MUC join presence should contain <x/> element");
     }
 
+    #[test]
+    fn muc_join_stanza_requests_history_since_last_seen() {
+        let since = chrono::DateTime::parse_from_rfc3339("2026-03-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let stanza =
+            build_muc_join_stanza("room@conference.example.com", "mynick", Some(&since), None)
+                .unwrap();
+        let Stanza::Presence(p) = &stanza else {
+            panic!("expected presence stanza");
+        };
+        let muc = p
+            .payloads
+            .iter()
+            .find_map(|el| Muc::try_from(el.clone()).ok())
+            .expect("join presence should contain <x/> element");
+        let history = muc.history.expect("history should be requested");
+        assert_eq!(history.since.map(|date| date.0), Some(since.fixed_offset()));
+    }
+
+    #[test]
+    fn invites_are_sent_directly_and_declined_through_the_room() {
+        let stanza = build_muc_join_stanza(
+            "room@conference.example.com",
+            "mynick",
+            None,
+            Some("cauldronburn"),
+        )
+        .unwrap();
+        let Stanza::Presence(p) = &stanza else {
+            panic!("expected presence stanza");
+        };
+        let muc = p
+            .payloads
+            .iter()
+            .find_map(|el| Muc::try_from(el.clone()).ok())
+            .expect("join presence should contain <x/> element");
+        assert_eq!(muc.password.as_deref(), Some("cauldronburn"));
+
+        let stanza = build_direct_invite_stanza(
+            "room@conference.example.com",
+            "bob@example.com",
+            Some("come along"),
+        )
+        .unwrap();
+        let Stanza::Message(msg) = &stanza else {
+            panic!("expected message stanza");
+        };
+        assert_eq!(
+            msg.to.as_ref().map(|j| j.to_string()),
+            Some("bob@example.com".to_string())
+        );
+        let invite = msg
+            .payloads
+            .iter()
+            .find(|el| el.is("x", NS_CONFERENCE))
+            .expect("direct invite element");
+        assert_eq!(invite.attr("jid"), Some("room@conference.example.com"));
+        assert_eq!(invite.attr("reason"), Some("come along"));
+
+        let stanza = build_invite_decline_stanza(
+            "room@conference.example.com",
+            "bob@example.com",
+            Some("busy"),
+        )
+        .unwrap();
+        let Stanza::Message(msg) = &stanza else {
+            panic!("expected message stanza");
+        };
+        assert_eq!(
+            msg.to.as_ref().map(|j| j.to_string()),
+            Some("room@conference.example.com".to_string())
+        );
+        let decline = msg
+            .payloads
+            .iter()
+            .find(|el| el.is("x", ns::MUC_USER))
+            .and_then(|x| x.get_child("decline", ns::MUC_USER))
+            .expect("decline element");
+        assert_eq!(decline.attr("to"), Some("bob@example.com"));
+        assert_eq!(
+            decline
+                .get_child("reason", ns::MUC_USER)
+                .map(|reason| reason.text()),
+            Some("busy".to_string())
+        );
+    }
+
     #[test]
     fn builds_muc_leave_stanza_test() {
         let stanza = build_muc_leave_stanza("room@conference.example.com").unwrap();
@@ -726,7 +1596,7 @@ mod tests {
     #[test]
     fn builds_muc_message_stanza_test() {
         let stanza =
-            build_muc_message_stanza("room@conference.example.com", "Hello room!").unwrap();
+            build_muc_message_stanza("room@conference.example.com", "Hello room!", None).unwrap();
         let Stanza::Message(msg) = &stanza else {
             panic!("expected message stanza");
         };
@@ -736,6 +1606,14 @@ mod tests {
             Some("room@conference.example.com".to_string())
         );
         assert_eq!(msg.bodies.get("").map(String::as_str), Some("Hello room!"));
+
+        let stanza =
+            build_muc_message_stanza("room@conference.example.com", "again", Some("queued-1"))
+                .unwrap();
+        let Stanza::Message(msg) = &stanza else {
+            panic!("expected message stanza");
+        };
+        assert_eq!(msg.id.as_ref().map(|id| id.0.as_str()), Some("queued-1"));
     }
 
     #[test]
@@ -743,10 +1621,10 @@ mod tests {
         let stanza = build_mam_query_stanza(
             "query-123",
             &Some("bob@example.com".to_string()),
-            &Some("after-1".to_string()),
-            &Some("before-1".to_string()),
-            25,
-        );
+            &RsmQuery::after("after-1", 25),
+            None,
+        )
+        .unwrap();
         let Stanza::Iq(iq) = &stanza else {
             panic!("expected iq stanza");
         };
@@ -767,7 +1645,7 @@ mod tests {
         let set = query.set.expect("MAM query should have RSM set");
         assert_eq!(set.max, Some(25));
         assert_eq!(set.after.as_deref(), Some("after-1"));
-        assert_eq!(set.before.as_deref(), Some("before-1"));
+        assert_eq!(set.before, None);
 
         let form = query.form.expect("MAM query should include form filter");
         let with_field = form
@@ -781,6 +1659,161 @@ mod tests {
         );
     }
 
+    #[test]
+    fn mam_query_for_room_archive_is_addressed_to_the_room() {
+        let stanza = build_mam_query_stanza(
+            "room-q",
+            &None,
+            &RsmQuery::after("stanza-9", 50),
+            Some("lobby@conference.example.com"),
+        )
+        .unwrap();
+        let Stanza::Iq(iq) = &stanza else {
+            panic!("expected iq stanza");
+        };
+        assert_eq!(
+            iq.to().map(|jid| jid.to_string()),
+            Some("lobby@conference.example.com".to_string())
+        );
+
+        let personal = build_mam_query_stanza("own-q", &None, &RsmQuery::first(50), None).unwrap();
+        let Stanza::Iq(iq) = &personal else {
+            panic!("expected iq stanza");
+        };
+        assert!(iq.to().is_none());
+    }
+
+    #[test]
+    fn builds_omemo_bundle_publish_with_open_access() {
+        let bundle = waddle_core::event::OmemoBundle {
+            identity_key: vec![5; 33],
+            signed_pre_key_id: 1,
+            signed_pre_key: vec![5; 33],
+            signed_pre_key_signature: vec![0; 64],
+            pre_keys: vec![],
+        };
+        let stanza = build_pep_publish_stanza(&bundle_node(4223), bundle_to_element(&bundle));
+        let Stanza::Iq(iq) = &stanza else {
+            panic!("expected iq stanza");
+        };
+        let Iq::Set { payload, .. } = iq.as_ref() else {
+            panic!("expected IQ set");
+        };
+
+        let Ok(PubSub::Publish {
+            publish,
+            publish_options,
+        }) = PubSub::try_from(payload.clone())
+        else {
+            panic!("expected pubsub publish");
+        };
+        assert_eq!(
+            publish.node.0,
+            "eu.siacs.conversations.axolotl.bundles:4223"
+        );
+        assert_eq!(
+            publish.items[0].id.as_ref().map(|id| id.0.as_str()),
+            Some("current")
+        );
+        let form = publish_options.and_then(|options| options.form).unwrap();
+        assert!(form.fields.iter().any(|field| {
+            field.var.as_deref() == Some("pubsub#access_model") && field.values == ["open"]
+        }));
+    }
+
+    #[test]
+    fn builds_omemo_device_list_fetch() {
+        let stanza = build_pep_items_stanza("bob@example.com", DEVICE_LIST_NODE).unwrap();
+        let Stanza::Iq(iq) = &stanza else {
+            panic!("expected iq stanza");
+        };
+        let Iq::Get { to, payload, .. } = iq.as_ref() else {
+            panic!("expected IQ get");
+        };
+        assert_eq!(
+            to.as_ref().map(|j| j.to_string()),
+            Some("bob@example.com".to_string())
+        );
+        let Ok(PubSub::Items(items)) = PubSub::try_from(payload.clone()) else {
+            panic!("expected pubsub items");
+        };
+        assert_eq!(items.node.0, DEVICE_LIST_NODE);
+    }
+
+    #[test]
+    fn builds_omemo_message_with_eme_and_fallback_body() {
+        let envelope = OmemoEnvelope {
+            sender_device_id: 42,
+            keys: vec![],
+            iv: vec![0; 12],
+            payload: Some(vec![1]),
+        };
+        let stanza = build_omemo_message_stanza("bob@example.com", "msg-1", &envelope).unwrap();
+        let Stanza::Message(msg) = &stanza else {
+            panic!("expected message stanza");
+        };
+        assert_eq!(msg.id.as_ref().map(|id| id.0.as_str()), Some("msg-1"));
+        assert!(msg.bodies.contains_key(""));
+        assert!(
+            msg.payloads
+                .iter()
+                .any(|el| xmpp_parsers::legacy_omemo::Encrypted::try_from(el.clone()).is_ok())
+        );
+        let eme = msg
+            .payloads
+            .iter()
+            .find_map(|el| ExplicitMessageEncryption::try_from(el.clone()).ok())
+            .expect("OMEMO message should carry an EME hint");
+        assert_eq!(eme.namespace, ns::LEGACY_OMEMO);
+    }
+
+    #[test]
+    fn builds_receipt_for_message_id() {
+        let stanza = build_receipt_stanza("alice@example.com/phone", "msg-7").unwrap();
+        let Stanza::Message(msg) = &stanza else {
+            panic!("expected message stanza");
+        };
+        assert!(msg.bodies.is_empty());
+        let received = msg
+            .payloads
+            .iter()
+            .find_map(|el| receipts::Received::try_from(el.clone()).ok())
+            .expect("receipt should carry <received/>");
+        assert_eq!(received.id, "msg-7");
+    }
+
+    #[test]
+    fn builds_jingle_requests_and_answers() {
+        let stanza = build_jingle_stanza(
+            "romeo@montague.lit/orchard",
+            "jingle-1",
+            "s1",
+            &JingleAction::Ringing,
+        )
+        .unwrap();
+        let Stanza::Iq(iq) = &stanza else {
+            panic!("expected iq stanza");
+        };
+        let Iq::Set { id, payload, .. } = iq.as_ref() else {
+            panic!("expected iq set");
+        };
+        assert_eq!(id, "jingle-1");
+        assert_eq!(payload.attr("action"), Some("session-info"));
+
+        let stanza =
+            build_jingle_answer_stanza("romeo@montague.lit/orchard", "j2", Some("item-not-found"))
+                .unwrap();
+        let Stanza::Iq(iq) = &stanza else {
+            panic!("expected iq stanza");
+        };
+        let Iq::Error { id, error, .. } = iq.as_ref() else {
+            panic!("expected iq error");
+        };
+        assert_eq!(id, "j2");
+        assert_eq!(error.defined_condition, DefinedCondition::ItemNotFound);
+        assert_eq!(error.other.as_ref().unwrap().name(), "unknown-session");
+    }
+
     #[test]
     fn builds_chat_state_composing() {
         let stanza = build_chat_state_stanza("bob@example.com", &CoreChatState::Composing).unwrap();
@@ -825,18 +1858,22 @@ mod tests {
     fn all_stanzas_serialize_to_valid_xml() {
         let stanzas = vec![
             build_message_stanza("bob@example.com", "test", &CoreMessageType::Chat, None).unwrap(),
-            build_presence_stanza(&CorePresenceShow::Available, None),
-            build_presence_stanza(&CorePresenceShow::Away, Some("brb")),
-            build_presence_stanza(&CorePresenceShow::Unavailable, None),
+            build_presence_stanza(&CorePresenceShow::Available, None, 0, None),
+            build_presence_stanza(&CorePresenceShow::Away, Some("brb"), 0, None),
+            build_presence_stanza(&CorePresenceShow::Unavailable, None, 0, None),
             build_roster_add_stanza("alice@example.com", Some("Alice"), &[]).unwrap(),
             build_roster_remove_stanza("alice@example.com").unwrap(),
             build_subscription_response_stanza("carol@example.com", true).unwrap(),
             build_subscription_response_stanza("carol@example.com", false).unwrap(),
-            build_subscription_send_stanza("carol@example.com", true).unwrap(),
-            build_subscription_send_stanza("carol@example.com", false).unwrap(),
-            build_muc_join_stanza("room@conference.example.com", "nick").unwrap(),
+            build_subscription_send_stanza("carol@example.com", true, None).unwrap(),
+            build_subscription_send_stanza("carol@example.com", false, None).unwrap(),
+            build_muc_join_stanza("room@conference.example.com", "nick", None, None).unwrap(),
             build_muc_leave_stanza("room@conference.example.com").unwrap(),
-            build_muc_message_stanza("room@conference.example.com", "hi").unwrap(),
+            build_direct_invite_stanza("room@conference.example.com", "bob@example.com", None)
+                .unwrap(),
+            build_invite_decline_stanza("room@conference.example.com", "bob@example.com", None)
+                .unwrap(),
+            build_muc_message_stanza("room@conference.example.com", "hi", None).unwrap(),
             build_chat_state_stanza("bob@example.com", &CoreChatState::Composing).unwrap(),
         ];
 
@@ -906,6 +1943,50 @@ mod integration_tests {
         tokio::time::sleep(Duration::from_millis(10)).await;
     }
 
+    #[tokio::test]
+    async fn invisible_presence_stays_available_while_server_hides_us() {
+        let (router, mut rx, event_bus) = make_router();
+
+        let _handle = tokio::spawn(async move { router.run().await });
+        yield_to_router().await;
+        publish_connection_established(&event_bus).await;
+
+        let invisible = || EventPayload::PresenceSetRequested {
+            show: CorePresenceShow::Invisible,
+            status: None,
+            priority: 0,
+            idle_since: None,
+        };
+        let next_stanza = async |rx: &mut StanzaReceiver| {
+            let bytes = timeout(Duration::from_millis(200), rx.recv())
+                .await
+                .expect("timed out waiting for wire bytes")
+                .expect("channel should not be closed");
+            Stanza::parse(&bytes).expect("wire bytes should parse as stanza")
+        };
+
+        publish_ui_event(&event_bus, "ui.presence.set", invisible());
+        let Stanza::Presence(p) = next_stanza(&mut rx).await else {
+            panic!("expected presence stanza");
+        };
+        assert_eq!(p.type_, PresenceType::Unavailable);
+
+        publish_ui_event(
+            &event_bus,
+            "ui.presence.invisibility",
+            EventPayload::InvisibilitySetRequested { invisible: true },
+        );
+        assert_eq!(next_stanza(&mut rx).await.name(), "iq");
+
+        publish_ui_event(&event_bus, "ui.presence.set", invisible());
+        let Stanza::Presence(p) = next_stanza(&mut rx).await else {
+            panic!("expected presence stanza");
+        };
+        assert_eq!(p.type_, PresenceType::None);
+
+        _handle.abort();
+    }
+
     #[tokio::test]
     async fn message_send_reaches_wire() {
         let (router, mut rx, event_bus) = make_router();
@@ -921,6 +2002,7 @@ mod integration_tests {
                 to: "bob@example.com".to_string(),
                 body: "Hello Bob!".to_string(),
                 message_type: CoreMessageType::Chat,
+                request_receipt: true,
             },
         );
 
@@ -935,6 +2017,11 @@ mod integration_tests {
             panic!("expected message stanza");
         };
         assert_eq!(msg.bodies.get("").map(String::as_str), Some("Hello Bob!"));
+        assert!(
+            msg.payloads
+                .iter()
+                .any(|el| receipts::Request::try_from(el.clone()).is_ok())
+        );
 
         _handle.abort();
     }
@@ -953,6 +2040,8 @@ mod integration_tests {
             EventPayload::PresenceSetRequested {
                 show: CorePresenceShow::Away,
                 status: Some("brb".to_string()),
+                priority: 0,
+                idle_since: None,
             },
         );
 
@@ -1010,6 +2099,8 @@ mod integration_tests {
             EventPayload::MucJoinRequested {
                 room: "room@conference.example.com".to_string(),
                 nick: "mynick".to_string(),
+                history_since: None,
+                password: None,
             },
         );
 
@@ -1052,6 +2143,51 @@ mod integration_tests {
         _handle.abort();
     }
 
+    #[tokio::test]
+    async fn omemo_message_send_reaches_wire_without_message_sent() {
+        let (router, mut rx, event_bus) = make_router();
+        let mut sent_sub = event_bus
+            .subscribe("xmpp.message.sent")
+            .expect("subscribe should succeed");
+
+        let _handle = tokio::spawn(async move { router.run().await });
+        yield_to_router().await;
+        publish_connection_established(&event_bus).await;
+
+        publish_ui_event(
+            &event_bus,
+            "ui.omemo.message.send",
+            EventPayload::OmemoMessageSendRequested {
+                to: "bob@example.com".to_string(),
+                id: "omemo-1".to_string(),
+                envelope: OmemoEnvelope {
+                    sender_device_id: 42,
+                    keys: vec![],
+                    iv: vec![0; 12],
+                    payload: Some(vec![1, 2, 3]),
+                },
+            },
+        );
+
+        let bytes = timeout(Duration::from_millis(200), rx.recv())
+            .await
+            .expect("timed out waiting for wire bytes")
+            .expect("channel should not be closed");
+        let Stanza::Message(msg) = Stanza::parse(&bytes).expect("should parse") else {
+            panic!("expected message stanza");
+        };
+        assert_eq!(msg.id.as_ref().map(|id| id.0.as_str()), Some("omemo-1"));
+
+        // The OMEMO manager reports the plaintext itself.
+        assert!(
+            timeout(Duration::from_millis(50), sent_sub.recv())
+                .await
+                .is_err()
+        );
+
+        _handle.abort();
+    }
+
     #[tokio::test]
     async fn message_sent_event_emitted_on_outbound_message() {
         let (router, mut rx, event_bus) = make_router();
@@ -1071,6 +2207,7 @@ mod integration_tests {
                 to: "bob@example.com".to_string(),
                 body: "Test".to_string(),
                 message_type: CoreMessageType::Chat,
+                request_receipt: false,
             },
         );
 
@@ -1108,6 +2245,7 @@ mod integration_tests {
                 to: "bob@example.com".to_string(),
                 body: "Correlated".to_string(),
                 message_type: CoreMessageType::Chat,
+                request_receipt: false,
             },
             correlation_id,
         );
@@ -1179,6 +2317,7 @@ mod integration_tests {
                 to: "bob@example.com".to_string(),
                 body: "offline".to_string(),
                 message_type: CoreMessageType::Chat,
+                request_receipt: false,
             },
         );
 
@@ -1206,6 +2345,7 @@ mod integration_tests {
                     to: "bob@example.com".to_string(),
                     body: "replay".to_string(),
                     message_type: CoreMessageType::Chat,
+                    request_receipt: false,
                 },
                 Uuid::new_v4(),
             ))
@@ -1242,6 +2382,7 @@ mod integration_tests {
                 to: "bob@example.com".to_string(),
                 body: "test".to_string(),
                 message_type: CoreMessageType::Chat,
+                request_receipt: false,
             },
         );
 
@@ -1276,6 +2417,7 @@ mod integration_tests {
                 to: "bob@example.com".to_string(),
                 body: "test".to_string(),
                 message_type: CoreMessageType::Chat,
+                request_receipt: false,
             },
         );
 
@@ -1311,6 +2453,8 @@ mod integration_tests {
             EventPayload::PresenceSetRequested {
                 show: CorePresenceShow::Away,
                 status: Some("brb".to_string()),
+                priority: 0,
+                idle_since: None,
             },
         );
 
@@ -1339,6 +2483,7 @@ mod integration_tests {
                     to: "bob@example.com".to_string(),
                     body: "hi".to_string(),
                     message_type: CoreMessageType::Chat,
+                    request_receipt: false,
                 },
             ),
             (
@@ -1346,6 +2491,16 @@ mod integration_tests {
                 EventPayload::PresenceSetRequested {
                     show: CorePresenceShow::Dnd,
                     status: None,
+                    priority: 0,
+                    idle_since: None,
+                },
+            ),
+            (
+                "ui.presence.directed",
+                EventPayload::DirectedPresenceRequested {
+                    to: "room@conference.example.com/nick".to_string(),
+                    show: CorePresenceShow::Away,
+                    status: None,
                 },
             ),
             (
@@ -1374,6 +2529,7 @@ mod integration_tests {
                 EventPayload::SubscriptionSendRequested {
                     jid: "carol@example.com".to_string(),
                     subscribe: true,
+                    preauth: None,
                 },
             ),
             (
@@ -1381,6 +2537,8 @@ mod integration_tests {
                 EventPayload::MucJoinRequested {
                     room: "room@conference.example.com".to_string(),
                     nick: "nick".to_string(),
+                    history_since: None,
+                    password: None,
                 },
             ),
             (
@@ -1408,9 +2566,8 @@ mod integration_tests {
                 EventPayload::MamQueryRequested {
                     query_id: "mam-q1".to_string(),
                     with_jid: Some("bob@example.com".to_string()),
-                    after: Some("a1".to_string()),
-                    before: None,
-                    max: 25,
+                    page: RsmQuery::after("a1", 25),
+                    archive: None,
                 },
             ),
         ];
@@ -1,3 +1,8 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
 use tracing::{debug, warn};
 
 use crate::{error::PipelineError, stanza::Stanza};
@@ -12,7 +17,8 @@ pub struct ProcessorContext {
     pub direction: StanzaDirection,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum StanzaDirection {
     Inbound,
     Outbound,
@@ -28,17 +34,62 @@ pub trait StanzaProcessor: Send + Sync + 'static {
     fn priority(&self) -> i32;
 }
 
+/// What a plugin stanza processor did with the raw stanza it was handed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StanzaHookOutcome {
+    PassThrough,
+    Modify(String),
+    Consume,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginStanzaProcessor {
+    pub plugin_id: String,
+    pub priority: i32,
+}
+
+pub type PluginHookFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Dispatches raw stanzas to plugins registered as stanza processors. The
+/// pipeline slots each plugin between the built-in processors by priority.
+pub trait PluginStanzaHost: Send + Sync + 'static {
+    fn stanza_processors(&self) -> PluginHookFuture<'_, Vec<PluginStanzaProcessor>>;
+
+    fn process_stanza<'a>(
+        &'a self,
+        plugin_id: &'a str,
+        direction: StanzaDirection,
+        xml: String,
+    ) -> PluginHookFuture<'a, Result<StanzaHookOutcome, PipelineError>>;
+}
+
+enum Stage<'a> {
+    Builtin(&'a dyn StanzaProcessor),
+    Plugin(PluginStanzaProcessor),
+}
+
+enum StageResult {
+    Continue,
+    Stop(String),
+}
+
 pub struct StanzaPipeline {
     processors: Vec<Box<dyn StanzaProcessor>>,
+    plugin_host: Option<Arc<dyn PluginStanzaHost>>,
 }
 
 impl StanzaPipeline {
     pub fn new() -> Self {
         Self {
             processors: Vec::new(),
+            plugin_host: None,
         }
     }
 
+    pub fn set_plugin_host(&mut self, host: Arc<dyn PluginStanzaHost>) {
+        self.plugin_host = Some(host);
+    }
+
     pub fn register(&mut self, processor: Box<dyn StanzaProcessor>) {
         self.processors.push(processor);
         self.processors.sort_by_key(|p| p.priority());
@@ -60,35 +111,17 @@ impl StanzaPipeline {
             direction: StanzaDirection::Inbound,
         };
 
-        for processor in &self.processors {
-            match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                processor.process_inbound(&mut stanza, &ctx)
-            })) {
-                Ok(ProcessorResult::Continue) => {}
-                Ok(ProcessorResult::Drop) => {
+        for stage in self.stages().await {
+            match self.run_stage(&stage, &mut stanza, &ctx).await {
+                StageResult::Continue => {}
+                StageResult::Stop(name) => {
                     debug!(
-                        processor = processor.name(),
+                        processor = %name,
                         stanza_type = stanza.name(),
                         "inbound stanza dropped by processor"
                     );
                     return Ok(());
                 }
-                Ok(ProcessorResult::Replace(replacement)) => {
-                    debug!(
-                        processor = processor.name(),
-                        old_type = stanza.name(),
-                        new_type = replacement.name(),
-                        "inbound stanza replaced by processor"
-                    );
-                    stanza = *replacement;
-                }
-                Err(_) => {
-                    warn!(
-                        processor = processor.name(),
-                        stanza_type = stanza.name(),
-                        "processor panicked during inbound processing, skipping"
-                    );
-                }
             }
         }
 
@@ -105,42 +138,162 @@ impl StanzaPipeline {
             direction: StanzaDirection::Outbound,
         };
 
-        for processor in &self.processors {
-            match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                processor.process_outbound(&mut stanza, &ctx)
-            })) {
-                Ok(ProcessorResult::Continue) => {}
-                Ok(ProcessorResult::Drop) => {
+        for stage in self.stages().await {
+            match self.run_stage(&stage, &mut stanza, &ctx).await {
+                StageResult::Continue => {}
+                StageResult::Stop(name) => {
                     debug!(
-                        processor = processor.name(),
+                        processor = %name,
                         stanza_type = stanza.name(),
                         "outbound stanza dropped by processor"
                     );
                     return Err(PipelineError::ProcessorFailed(format!(
-                        "outbound stanza dropped by processor '{}'",
-                        processor.name()
+                        "outbound stanza dropped by processor '{name}'"
                     )));
                 }
-                Ok(ProcessorResult::Replace(replacement)) => {
+            }
+        }
+
+        stanza.to_bytes()
+    }
+
+    /// Built-in processors merged with the plugin stanza processors by
+    /// priority. A plugin runs after built-in processors of equal priority.
+    async fn stages(&self) -> Vec<Stage<'_>> {
+        let mut plugins = match &self.plugin_host {
+            Some(host) => host.stanza_processors().await,
+            None => Vec::new(),
+        };
+        plugins.sort_by(|a, b| {
+            a.priority
+                .cmp(&b.priority)
+                .then_with(|| a.plugin_id.cmp(&b.plugin_id))
+        });
+
+        let mut stages = Vec::with_capacity(self.processors.len() + plugins.len());
+        let mut plugins = plugins.into_iter().peekable();
+        for processor in &self.processors {
+            while let Some(plugin) = plugins.next_if(|p| p.priority < processor.priority()) {
+                stages.push(Stage::Plugin(plugin));
+            }
+            stages.push(Stage::Builtin(processor.as_ref()));
+        }
+        stages.extend(plugins.map(Stage::Plugin));
+        stages
+    }
+
+    async fn run_stage(
+        &self,
+        stage: &Stage<'_>,
+        stanza: &mut Stanza,
+        ctx: &ProcessorContext,
+    ) -> StageResult {
+        match stage {
+            Stage::Builtin(processor) => self.run_processor(*processor, stanza, ctx),
+            Stage::Plugin(plugin) => self.run_plugin(plugin, stanza, ctx).await,
+        }
+    }
+
+    fn run_processor(
+        &self,
+        processor: &dyn StanzaProcessor,
+        stanza: &mut Stanza,
+        ctx: &ProcessorContext,
+    ) -> StageResult {
+        let result =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| match ctx.direction {
+                StanzaDirection::Inbound => processor.process_inbound(stanza, ctx),
+                StanzaDirection::Outbound => processor.process_outbound(stanza, ctx),
+            }));
+
+        match result {
+            Ok(ProcessorResult::Continue) => {}
+            Ok(ProcessorResult::Drop) => return StageResult::Stop(processor.name().to_string()),
+            Ok(ProcessorResult::Replace(replacement)) => {
+                debug!(
+                    processor = processor.name(),
+                    direction = ?ctx.direction,
+                    old_type = stanza.name(),
+                    new_type = replacement.name(),
+                    "stanza replaced by processor"
+                );
+                *stanza = *replacement;
+            }
+            Err(_) => {
+                warn!(
+                    processor = processor.name(),
+                    direction = ?ctx.direction,
+                    stanza_type = stanza.name(),
+                    "processor panicked, skipping"
+                );
+            }
+        }
+
+        StageResult::Continue
+    }
+
+    /// Hand the serialized stanza to a plugin. Plugin failures, including
+    /// running out of fuel, and unparseable replacements leave the stanza
+    /// untouched.
+    async fn run_plugin(
+        &self,
+        plugin: &PluginStanzaProcessor,
+        stanza: &mut Stanza,
+        ctx: &ProcessorContext,
+    ) -> StageResult {
+        let Some(host) = &self.plugin_host else {
+            return StageResult::Continue;
+        };
+        let xml = match stanza.to_bytes().map(String::from_utf8) {
+            Ok(Ok(xml)) => xml,
+            _ => {
+                warn!(
+                    plugin_id = %plugin.plugin_id,
+                    stanza_type = stanza.name(),
+                    "stanza could not be serialized for plugin, skipping"
+                );
+                return StageResult::Continue;
+            }
+        };
+
+        match host
+            .process_stanza(&plugin.plugin_id, ctx.direction, xml)
+            .await
+        {
+            Ok(StanzaHookOutcome::PassThrough) => {}
+            Ok(StanzaHookOutcome::Consume) => {
+                return StageResult::Stop(format!("plugin:{}", plugin.plugin_id));
+            }
+            Ok(StanzaHookOutcome::Modify(xml)) => match Stanza::parse(xml.as_bytes()) {
+                Ok(replacement) => {
                     debug!(
-                        processor = processor.name(),
+                        plugin_id = %plugin.plugin_id,
+                        direction = ?ctx.direction,
                         old_type = stanza.name(),
                         new_type = replacement.name(),
-                        "outbound stanza replaced by processor"
+                        "stanza modified by plugin"
                     );
-                    stanza = *replacement;
+                    *stanza = replacement;
                 }
-                Err(_) => {
+                Err(error) => {
                     warn!(
-                        processor = processor.name(),
-                        stanza_type = stanza.name(),
-                        "processor panicked during outbound processing, skipping"
+                        plugin_id = %plugin.plugin_id,
+                        %error,
+                        "plugin returned an invalid stanza, ignoring modification"
                     );
                 }
+            },
+            Err(error) => {
+                warn!(
+                    plugin_id = %plugin.plugin_id,
+                    direction = ?ctx.direction,
+                    %error,
+                    "plugin stanza processor failed, skipping"
+                );
             }
         }
 
-        stanza.to_bytes()
+        StageResult::Continue
     }
 }
 
@@ -152,6 +305,8 @@ impl Default for StanzaPipeline {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
     use std::sync::atomic::{AtomicU32, Ordering};
 
     use super::*;
@@ -499,4 +654,212 @@ mod tests {
         let round_tripped = Stanza::parse(&bytes).expect("should re-parse");
         assert!(matches!(round_tripped, Stanza::Message(_)));
     }
+
+    struct FakePluginHost {
+        processors: Vec<PluginStanzaProcessor>,
+        outcomes: HashMap<&'static str, Result<StanzaHookOutcome, PipelineError>>,
+        calls: Mutex<Vec<(String, String)>>,
+    }
+
+    impl FakePluginHost {
+        fn new(
+            plugins: &[(&'static str, i32, Result<StanzaHookOutcome, PipelineError>)],
+        ) -> Arc<Self> {
+            let mut outcomes = HashMap::new();
+            let processors = plugins
+                .iter()
+                .map(|(id, priority, outcome)| {
+                    let outcome = match outcome {
+                        Ok(outcome) => Ok(outcome.clone()),
+                        Err(error) => Err(PipelineError::ProcessorFailed(error.to_string())),
+                    };
+                    outcomes.insert(*id, outcome);
+                    PluginStanzaProcessor {
+                        plugin_id: id.to_string(),
+                        priority: *priority,
+                    }
+                })
+                .collect();
+            Arc::new(Self {
+                processors,
+                outcomes,
+                calls: Mutex::new(Vec::new()),
+            })
+        }
+
+        fn calls(&self) -> Vec<(String, String)> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    impl PluginStanzaHost for FakePluginHost {
+        fn stanza_processors(&self) -> PluginHookFuture<'_, Vec<PluginStanzaProcessor>> {
+            Box::pin(async move { self.processors.clone() })
+        }
+
+        fn process_stanza<'a>(
+            &'a self,
+            plugin_id: &'a str,
+            _direction: StanzaDirection,
+            xml: String,
+        ) -> PluginHookFuture<'a, Result<StanzaHookOutcome, PipelineError>> {
+            Box::pin(async move {
+                self.calls
+                    .lock()
+                    .unwrap()
+                    .push((plugin_id.to_string(), xml));
+                match &self.outcomes[plugin_id] {
+                    Ok(outcome) => Ok(outcome.clone()),
+                    Err(error) => Err(PipelineError::ProcessorFailed(error.to_string())),
+                }
+            })
+        }
+    }
+
+    struct NamedProcessor {
+        prio: i32,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl StanzaProcessor for NamedProcessor {
+        fn name(&self) -> &str {
+            "named"
+        }
+
+        fn process_inbound(
+            &self,
+            _stanza: &mut Stanza,
+            _ctx: &ProcessorContext,
+        ) -> ProcessorResult {
+            self.log.lock().unwrap().push(format!("core:{}", self.prio));
+            ProcessorResult::Continue
+        }
+
+        fn process_outbound(
+            &self,
+            _stanza: &mut Stanza,
+            _ctx: &ProcessorContext,
+        ) -> ProcessorResult {
+            ProcessorResult::Continue
+        }
+
+        fn priority(&self) -> i32 {
+            self.prio
+        }
+    }
+
+    #[tokio::test]
+    async fn plugin_processors_interleave_with_core_by_priority() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let host = FakePluginHost::new(&[
+            ("late", 60, Ok(StanzaHookOutcome::PassThrough)),
+            ("early", 5, Ok(StanzaHookOutcome::PassThrough)),
+            ("tied", 10, Ok(StanzaHookOutcome::PassThrough)),
+        ]);
+
+        let mut pipeline = StanzaPipeline::new();
+        pipeline.register(Box::new(NamedProcessor {
+            prio: 10,
+            log: log.clone(),
+        }));
+        pipeline.set_plugin_host(host.clone());
+
+        pipeline
+            .process_inbound(MESSAGE_XML)
+            .await
+            .expect("inbound should succeed");
+
+        let stages: Vec<String> = host.calls().into_iter().map(|(id, _)| id).collect();
+        assert_eq!(stages, vec!["early", "tied", "late"]);
+        assert_eq!(*log.lock().unwrap(), vec!["core:10"]);
+
+        let stages = pipeline.stages().await;
+        let order: Vec<String> = stages
+            .iter()
+            .map(|stage| match stage {
+                Stage::Builtin(processor) => format!("core:{}", processor.priority()),
+                Stage::Plugin(plugin) => plugin.plugin_id.clone(),
+            })
+            .collect();
+        assert_eq!(order, vec!["early", "core:10", "tied", "late"]);
+    }
+
+    #[tokio::test]
+    async fn plugin_modification_is_seen_by_later_stages() {
+        let presence = String::from_utf8(PRESENCE_XML.to_vec()).unwrap();
+        let host = FakePluginHost::new(&[
+            ("rewriter", 1, Ok(StanzaHookOutcome::Modify(presence))),
+            ("observer", 2, Ok(StanzaHookOutcome::PassThrough)),
+        ]);
+        let mut pipeline = StanzaPipeline::new();
+        pipeline.set_plugin_host(host.clone());
+
+        let bytes = pipeline
+            .process_outbound(Stanza::parse(MESSAGE_XML).unwrap())
+            .await
+            .expect("outbound should succeed");
+
+        let calls = host.calls();
+        assert!(calls[0].1.starts_with("<message"));
+        assert!(calls[1].1.starts_with("<presence"));
+        assert!(matches!(
+            Stanza::parse(&bytes).unwrap(),
+            Stanza::Presence(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn plugin_consume_stops_the_pipeline() {
+        static AFTER_INBOUND: AtomicU32 = AtomicU32::new(0);
+        static AFTER_OUTBOUND: AtomicU32 = AtomicU32::new(0);
+        AFTER_INBOUND.store(0, Ordering::SeqCst);
+        AFTER_OUTBOUND.store(0, Ordering::SeqCst);
+
+        let host = FakePluginHost::new(&[("spam-filter", 1, Ok(StanzaHookOutcome::Consume))]);
+        let mut pipeline = StanzaPipeline::new();
+        pipeline.register(Box::new(TrackingProcessor {
+            prio: 10,
+            inbound_counter: &AFTER_INBOUND,
+            outbound_counter: &AFTER_OUTBOUND,
+        }));
+        pipeline.set_plugin_host(host);
+
+        pipeline
+            .process_inbound(MESSAGE_XML)
+            .await
+            .expect("consumed inbound stanza is not an error");
+        assert_eq!(AFTER_INBOUND.load(Ordering::SeqCst), 0);
+
+        let result = pipeline
+            .process_outbound(Stanza::parse(MESSAGE_XML).unwrap())
+            .await;
+        assert!(matches!(result, Err(PipelineError::ProcessorFailed(_))));
+        assert_eq!(AFTER_OUTBOUND.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn failing_or_invalid_plugins_pass_the_stanza_through() {
+        let host = FakePluginHost::new(&[
+            (
+                "out-of-fuel",
+                1,
+                Err(PipelineError::ProcessorFailed("fuel exhausted".to_string())),
+            ),
+            (
+                "garbage",
+                2,
+                Ok(StanzaHookOutcome::Modify("not a stanza<<<".to_string())),
+            ),
+        ]);
+        let mut pipeline = StanzaPipeline::new();
+        pipeline.set_plugin_host(host.clone());
+
+        let bytes = pipeline
+            .process_outbound(Stanza::parse(MESSAGE_XML).unwrap())
+            .await
+            .expect("outbound should succeed");
+
+        assert_eq!(host.calls().len(), 2);
+        assert!(matches!(Stanza::parse(&bytes).unwrap(), Stanza::Message(_)));
+    }
 }
@@ -0,0 +1,120 @@
+use tracing::debug;
+use xmpp_parsers::iq::Iq;
+
+#[cfg(feature = "native")]
+use std::sync::Arc;
+
+#[cfg(feature = "native")]
+use waddle_core::event::{Channel, Event, EventBus, EventPayload, EventSource, channels};
+#[cfg(feature = "native")]
+use xmpp_parsers::minidom::Element;
+
+use crate::pipeline::{ProcessorContext, ProcessorResult, StanzaProcessor};
+use crate::stanza::Stanza;
+
+/// Ids of the iqs sent for password changes and account deletion start with
+/// this, so their answers can be told apart from other iq results.
+pub(crate) const ACCOUNT_IQ_ID_PREFIX: &str = "account-";
+
+/// Reports the server's answers to `AccountManager` requests.
+pub struct AccountProcessor {
+    #[cfg(feature = "native")]
+    event_bus: Arc<dyn EventBus>,
+}
+
+impl AccountProcessor {
+    #[cfg(feature = "native")]
+    pub fn new(event_bus: Arc<dyn EventBus>) -> Self {
+        Self { event_bus }
+    }
+}
+
+impl StanzaProcessor for AccountProcessor {
+    fn name(&self) -> &str {
+        "account"
+    }
+
+    fn process_inbound(&self, stanza: &mut Stanza, _ctx: &ProcessorContext) -> ProcessorResult {
+        let Stanza::Iq(iq) = stanza else {
+            return ProcessorResult::Continue;
+        };
+        let (id, error) = match iq.as_ref() {
+            Iq::Result { id, .. } => (id, None),
+            Iq::Error { id, error, .. } => (id, Some(error)),
+            _ => return ProcessorResult::Continue,
+        };
+        if !id.starts_with(ACCOUNT_IQ_ID_PREFIX) {
+            return ProcessorResult::Continue;
+        }
+        debug!(id = %id, refused = error.is_some(), "account request answered");
+
+        #[cfg(feature = "native")]
+        {
+            let error = error.map(|error| {
+                crate::registration::error_reason(Some(&Element::from(error.clone())))
+            });
+            let _ = self.event_bus.publish(Event::new(
+                Channel::new(channels::XMPP_ACCOUNT_ANSWERED).unwrap(),
+                EventSource::Xmpp,
+                EventPayload::AccountRequestAnswered {
+                    request_id: id.clone(),
+                    error,
+                },
+            ));
+        }
+
+        ProcessorResult::Continue
+    }
+
+    fn process_outbound(&self, _stanza: &mut Stanza, _ctx: &ProcessorContext) -> ProcessorResult {
+        ProcessorResult::Continue
+    }
+
+    fn priority(&self) -> i32 {
+        10
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use crate::pipeline::StanzaDirection;
+    use waddle_core::event::BroadcastEventBus;
+
+    fn answer(processor: &AccountProcessor, xml: &[u8]) {
+        let mut stanza = Stanza::parse(xml).unwrap();
+        let ctx = ProcessorContext {
+            direction: StanzaDirection::Inbound,
+        };
+        processor.process_inbound(&mut stanza, &ctx);
+    }
+
+    #[tokio::test]
+    async fn publishes_answers_to_account_requests_only() {
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let processor = AccountProcessor::new(event_bus.clone());
+        let mut sub = event_bus.subscribe("xmpp.account.answered").unwrap();
+
+        answer(
+            &processor,
+            b"<iq xmlns='jabber:client' type='result' id='roster-1'/>",
+        );
+        answer(
+            &processor,
+            b"<iq xmlns='jabber:client' type='error' id='account-2'>\
+                <error type='cancel'>\
+                    <not-allowed xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/>\
+                </error>\
+            </iq>",
+        );
+
+        let event = sub.recv().await.unwrap();
+        match event.payload {
+            EventPayload::AccountRequestAnswered { request_id, error } => {
+                assert_eq!(request_id, "account-2");
+                assert_eq!(error.as_deref(), Some("not-allowed"));
+            }
+            other => panic!("unexpected payload: {other:?}"),
+        }
+    }
+}
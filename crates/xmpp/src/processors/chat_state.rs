@@ -4,7 +4,9 @@ use tracing::debug;
 use xmpp_parsers::chatstates::ChatState as XmppChatState;
 use xmpp_parsers::message::MessageType;
 
-use waddle_core::event::{Channel, ChatState as CoreChatState, Event, EventPayload, EventSource};
+use waddle_core::event::{
+    Channel, ChatState as CoreChatState, Event, EventPayload, EventSource, channels,
+};
 
 #[cfg(feature = "native")]
 use waddle_core::event::EventBus;
@@ -61,7 +63,7 @@ impl StanzaProcessor for ChatStateProcessor {
         #[cfg(feature = "native")]
         {
             let _ = self.event_bus.publish(Event::new(
-                Channel::new("xmpp.chatstate.received").unwrap(),
+                Channel::new(channels::XMPP_CHATSTATE_RECEIVED).unwrap(),
                 EventSource::Xmpp,
                 EventPayload::ChatStateReceived {
                     from,
@@ -2,23 +2,30 @@ use std::sync::Arc;
 
 use tracing::debug;
 
-use waddle_core::event::{Channel, Event, EventPayload, EventSource};
+use waddle_core::event::{Channel, Event, EventPayload, EventSource, channels};
 
 #[cfg(feature = "native")]
 use waddle_core::event::EventBus;
 
-use crate::pipeline::{ProcessorContext, ProcessorResult, StanzaProcessor};
+use crate::debugger::StanzaDebugger;
+use crate::pipeline::{ProcessorContext, ProcessorResult, StanzaDirection, StanzaProcessor};
 use crate::stanza::Stanza;
 
+/// Feeds the stanza stream to a [`StanzaDebugger`] and, while it is
+/// capturing, publishes each stanza as it was kept on `xmpp.debug.stanza.*`.
 pub struct DebugProcessor {
     #[cfg(feature = "native")]
     event_bus: Arc<dyn EventBus>,
+    debugger: Arc<StanzaDebugger>,
 }
 
 impl DebugProcessor {
     #[cfg(feature = "native")]
-    pub fn new(event_bus: Arc<dyn EventBus>) -> Self {
-        Self { event_bus }
+    pub fn new(event_bus: Arc<dyn EventBus>, debugger: Arc<StanzaDebugger>) -> Self {
+        Self {
+            event_bus,
+            debugger,
+        }
     }
 }
 
@@ -28,7 +35,13 @@ impl StanzaProcessor for DebugProcessor {
     }
 
     fn process_inbound(&self, stanza: &mut Stanza, _ctx: &ProcessorContext) -> ProcessorResult {
-        let xml = stanza_to_string(stanza);
+        let Some(captured) = self.debugger.record(
+            StanzaDirection::Inbound,
+            stanza.name(),
+            &stanza_to_string(stanza),
+        ) else {
+            return ProcessorResult::Continue;
+        };
         debug!(
             direction = "inbound",
             stanza_type = stanza.name(),
@@ -37,16 +50,24 @@ impl StanzaProcessor for DebugProcessor {
         #[cfg(feature = "native")]
         {
             let _ = self.event_bus.publish(Event::new(
-                Channel::new("xmpp.debug.stanza.received").unwrap(),
+                Channel::new(channels::XMPP_DEBUG_STANZA_RECEIVED).unwrap(),
                 EventSource::Xmpp,
-                EventPayload::RawStanzaReceived { stanza: xml },
+                EventPayload::RawStanzaReceived {
+                    stanza: captured.xml,
+                },
             ));
         }
         ProcessorResult::Continue
     }
 
     fn process_outbound(&self, stanza: &mut Stanza, _ctx: &ProcessorContext) -> ProcessorResult {
-        let xml = stanza_to_string(stanza);
+        let Some(captured) = self.debugger.record(
+            StanzaDirection::Outbound,
+            stanza.name(),
+            &stanza_to_string(stanza),
+        ) else {
+            return ProcessorResult::Continue;
+        };
         debug!(
             direction = "outbound",
             stanza_type = stanza.name(),
@@ -55,9 +76,11 @@ impl StanzaProcessor for DebugProcessor {
         #[cfg(feature = "native")]
         {
             let _ = self.event_bus.publish(Event::new(
-                Channel::new("xmpp.debug.stanza.sent").unwrap(),
+                Channel::new(channels::XMPP_DEBUG_STANZA_SENT).unwrap(),
                 EventSource::Xmpp,
-                EventPayload::RawStanzaSent { stanza: xml },
+                EventPayload::RawStanzaSent {
+                    stanza: captured.xml,
+                },
             ));
         }
         ProcessorResult::Continue
@@ -96,10 +119,42 @@ mod tests {
     fn debug_processor_has_priority_100() {
         #[cfg(feature = "native")]
         {
+            use waddle_core::config::DebugConfig;
             use waddle_core::event::BroadcastEventBus;
             let bus = Arc::new(BroadcastEventBus::default());
-            let processor = DebugProcessor::new(bus);
+            let debugger = Arc::new(StanzaDebugger::new(&DebugConfig::default()));
+            let processor = DebugProcessor::new(bus, debugger);
             assert_eq!(processor.priority(), 100);
         }
     }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn stanzas_are_published_only_while_capturing() {
+        use waddle_core::config::DebugConfig;
+        use waddle_core::event::{BroadcastEventBus, EventBus};
+
+        let bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let mut published = bus.subscribe("xmpp.debug.stanza.*").unwrap();
+        let debugger = Arc::new(StanzaDebugger::new(&DebugConfig::default()));
+        let processor = DebugProcessor::new(bus, debugger.clone());
+        let ctx = ProcessorContext {
+            direction: StanzaDirection::Inbound,
+        };
+
+        let mut stanza = Stanza::parse(MESSAGE_XML).unwrap();
+        processor.process_inbound(&mut stanza, &ctx);
+        debugger.set_enabled(true);
+        processor.process_inbound(&mut stanza, &ctx);
+
+        let event = published.recv().await.unwrap();
+        let EventPayload::RawStanzaReceived { stanza: xml } = event.payload else {
+            panic!("expected RawStanzaReceived");
+        };
+        assert!(xml.contains("[redacted]"));
+        assert!(!xml.contains("<body>test</body>"));
+        let next =
+            tokio::time::timeout(std::time::Duration::from_millis(50), published.recv()).await;
+        assert!(next.is_err(), "unexpected stanza {next:?}");
+    }
 }
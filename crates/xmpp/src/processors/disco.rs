@@ -0,0 +1,378 @@
+use std::sync::Arc;
+
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+use sha1::{Digest, Sha1};
+use tracing::{debug, warn};
+use xmpp_parsers::caps;
+use xmpp_parsers::disco::{DiscoInfoResult, DiscoItemsResult};
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::minidom::Element;
+use xmpp_parsers::ns;
+
+use waddle_core::event::{Channel, DiscoItem, Event, EventPayload, EventSource, channels};
+
+#[cfg(feature = "native")]
+use waddle_core::event::EventBus;
+
+use crate::pipeline::{ProcessorContext, ProcessorResult, StanzaProcessor};
+use crate::stanza::Stanza;
+
+/// Ids of the disco queries we send start with these, so an error answer,
+/// which need not echo the query, can still be told apart by kind.
+pub(crate) const DISCO_INFO_IQ_ID_PREFIX: &str = "disco-info-";
+pub(crate) const DISCO_ITEMS_IQ_ID_PREFIX: &str = "disco-items-";
+
+pub struct DiscoProcessor {
+    #[cfg(feature = "native")]
+    event_bus: Arc<dyn EventBus>,
+}
+
+impl DiscoProcessor {
+    #[cfg(feature = "native")]
+    pub fn new(event_bus: Arc<dyn EventBus>) -> Self {
+        Self { event_bus }
+    }
+
+    fn info_received(&self, jid: String, payload: &Element) {
+        let Ok(info) = DiscoInfoResult::try_from(payload.clone()) else {
+            warn!("failed to parse disco#info result payload");
+            return;
+        };
+
+        let features: Vec<String> = info.features.iter().map(|f| f.var.clone()).collect();
+        let upload_max_size = upload_max_size(&info);
+        let verification = info.node.is_some().then(|| caps_verification(&info));
+        debug!(jid = %jid, node = ?info.node, count = features.len(), "disco#info received");
+
+        #[cfg(feature = "native")]
+        {
+            let _ = self.event_bus.publish(Event::new(
+                Channel::new(channels::XMPP_DISCO_INFO_RECEIVED).unwrap(),
+                EventSource::Xmpp,
+                EventPayload::DiscoInfoReceived {
+                    jid,
+                    node: info.node.clone(),
+                    features,
+                    upload_max_size,
+                    verification,
+                },
+            ));
+        }
+    }
+
+    fn items_received(&self, jid: String, payload: &Element) {
+        let Ok(result) = DiscoItemsResult::try_from(payload.clone()) else {
+            warn!("failed to parse disco#items result payload");
+            return;
+        };
+
+        let items: Vec<DiscoItem> = result
+            .items
+            .into_iter()
+            .map(|item| DiscoItem {
+                jid: item.jid.to_string(),
+                node: item.node,
+                name: item.name,
+            })
+            .collect();
+        let page = result.rsm.as_ref().map(crate::rsm::rsm_result);
+        debug!(jid = %jid, node = ?result.node, count = items.len(), "disco#items received");
+
+        #[cfg(feature = "native")]
+        {
+            let _ = self.event_bus.publish(Event::new(
+                Channel::new(channels::XMPP_DISCO_ITEMS_RECEIVED).unwrap(),
+                EventSource::Xmpp,
+                EventPayload::DiscoItemsReceived {
+                    jid,
+                    node: result.node,
+                    items,
+                    page,
+                },
+            ));
+        }
+    }
+
+    /// Report a disco query refused with a stanza error, so whoever sent it
+    /// stops waiting for the answer.
+    fn query_failed(&self, id: &str, jid: String, node: Option<String>, error: String) {
+        debug!(id = %id, jid = %jid, ?node, error = %error, "disco query refused");
+        #[cfg(feature = "native")]
+        if id.starts_with(DISCO_INFO_IQ_ID_PREFIX) {
+            self.emit(
+                channels::XMPP_DISCO_INFO_FAILED,
+                EventPayload::DiscoInfoFailed { jid, node, error },
+            );
+        } else {
+            self.emit(
+                channels::XMPP_DISCO_ITEMS_FAILED,
+                EventPayload::DiscoItemsFailed { jid, node, error },
+            );
+        }
+    }
+
+    #[cfg(feature = "native")]
+    fn emit(&self, channel: &str, payload: EventPayload) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channel).unwrap(),
+            EventSource::Xmpp,
+            payload,
+        ));
+    }
+}
+
+impl StanzaProcessor for DiscoProcessor {
+    fn name(&self) -> &str {
+        "disco"
+    }
+
+    fn process_inbound(&self, stanza: &mut Stanza, _ctx: &ProcessorContext) -> ProcessorResult {
+        let Stanza::Iq(iq) = stanza else {
+            return ProcessorResult::Continue;
+        };
+        match iq.as_ref() {
+            Iq::Result {
+                from,
+                payload: Some(payload),
+                ..
+            } => {
+                let jid = from.as_ref().map(|j| j.to_string()).unwrap_or_default();
+                if payload.is("query", ns::DISCO_INFO) {
+                    self.info_received(jid, payload);
+                } else if payload.is("query", ns::DISCO_ITEMS) {
+                    self.items_received(jid, payload);
+                }
+            }
+            Iq::Error {
+                from,
+                id,
+                payload,
+                error,
+                ..
+            } if id.starts_with(DISCO_INFO_IQ_ID_PREFIX)
+                || id.starts_with(DISCO_ITEMS_IQ_ID_PREFIX) =>
+            {
+                let jid = from.as_ref().map(|j| j.to_string()).unwrap_or_default();
+                let node = payload
+                    .as_ref()
+                    .and_then(|query| query.attr("node"))
+                    .map(String::from);
+                let error = crate::registration::error_reason(Some(&Element::from(error.clone())));
+                self.query_failed(id, jid, node, error);
+            }
+            _ => {}
+        }
+
+        ProcessorResult::Continue
+    }
+
+    fn process_outbound(&self, _stanza: &mut Stanza, _ctx: &ProcessorContext) -> ProcessorResult {
+        ProcessorResult::Continue
+    }
+
+    fn priority(&self) -> i32 {
+        10
+    }
+}
+
+/// XEP-0363 §4: upload services publish their limit in an extension form.
+fn upload_max_size(info: &DiscoInfoResult) -> Option<u64> {
+    info.extensions
+        .iter()
+        .filter(|form| form.form_type() == Some(ns::HTTP_UPLOAD))
+        .flat_map(|form| form.fields.iter())
+        .find(|field| field.var.as_deref() == Some("max-file-size"))
+        .and_then(|field| field.values.first())
+        .and_then(|value| value.parse().ok())
+}
+
+/// XEP-0115 §5.1: the SHA-1 verification string of a disco#info result.
+fn caps_verification(info: &DiscoInfoResult) -> String {
+    let hash = Sha1::digest(caps::compute_disco(info));
+    STANDARD.encode(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UPLOAD_INFO_XML: &[u8] = b"<iq xmlns='jabber:client' type='result' id='disco-1' \
+        from='upload.example.com'>\
+        <query xmlns='http://jabber.org/protocol/disco#info'>\
+            <identity category='store' type='file' name='HTTP File Upload'/>\
+            <feature var='urn:xmpp:http:upload:0'/>\
+            <x type='result' xmlns='jabber:x:data'>\
+                <field var='FORM_TYPE' type='hidden'><value>urn:xmpp:http:upload:0</value></field>\
+                <field var='max-file-size'><value>5242880</value></field>\
+            </x>\
+        </query>\
+    </iq>";
+
+    const CLIENT_INFO_XML: &[u8] = b"<iq xmlns='jabber:client' type='result' id='disco-2' \
+        from='alice@example.com/desktop'>\
+        <query xmlns='http://jabber.org/protocol/disco#info' \
+            node='https://example.org/client#QgayPKawpkPSDYmwT/WM94uAlu0='>\
+            <identity category='client' type='pc'/>\
+            <feature var='urn:xmpp:receipts'/>\
+            <feature var='urn:xmpp:reactions:0'/>\
+        </query>\
+    </iq>";
+
+    /// The example from XEP-0115 §5.2.
+    const CAPS_EXAMPLE_XML: &[u8] = b"<iq xmlns='jabber:client' type='result' id='disco-4' \
+        from='romeo@montague.lit/orchard'>\
+        <query xmlns='http://jabber.org/protocol/disco#info' \
+            node='http://code.google.com/p/exodus#QgayPKawpkPSDYmwT/WM94uAlu0='>\
+            <identity category='client' name='Exodus 0.9.1' type='pc'/>\
+            <feature var='http://jabber.org/protocol/caps'/>\
+            <feature var='http://jabber.org/protocol/disco#info'/>\
+            <feature var='http://jabber.org/protocol/disco#items'/>\
+            <feature var='http://jabber.org/protocol/muc'/>\
+        </query>\
+    </iq>";
+
+    const ROOM_ITEMS_XML: &[u8] = b"<iq xmlns='jabber:client' type='result' id='disco-3' \
+        from='conference.example.com'>\
+        <query xmlns='http://jabber.org/protocol/disco#items'>\
+            <item jid='lobby@conference.example.com' name='Lobby'/>\
+            <item jid='dev@conference.example.com' name='Development'/>\
+            <set xmlns='http://jabber.org/protocol/rsm'>\
+                <first index='0'>lobby@conference.example.com</first>\
+                <last>dev@conference.example.com</last>\
+                <count>30</count>\
+            </set>\
+        </query>\
+    </iq>";
+
+    const UPLOAD_INFO_ERROR_XML: &[u8] = b"<iq xmlns='jabber:client' type='error' \
+        id='disco-info-7' from='upload.example.com'>\
+        <error type='cancel'>\
+            <service-unavailable xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/>\
+        </error>\
+    </iq>";
+
+    fn parse_info(xml: &[u8]) -> DiscoInfoResult {
+        let Stanza::Iq(iq) = Stanza::parse(xml).unwrap() else {
+            panic!("expected iq");
+        };
+        let Iq::Result {
+            payload: Some(payload),
+            ..
+        } = *iq
+        else {
+            panic!("expected result payload");
+        };
+        DiscoInfoResult::try_from(payload).unwrap()
+    }
+
+    #[test]
+    fn reads_upload_limit_from_extension_form() {
+        assert_eq!(
+            upload_max_size(&parse_info(UPLOAD_INFO_XML)),
+            Some(5_242_880)
+        );
+    }
+
+    #[test]
+    fn client_info_has_features_and_no_upload_limit() {
+        let info = parse_info(CLIENT_INFO_XML);
+        assert_eq!(upload_max_size(&info), None);
+        assert_eq!(info.features.len(), 2);
+        assert_eq!(
+            info.node.as_deref(),
+            Some("https://example.org/client#QgayPKawpkPSDYmwT/WM94uAlu0=")
+        );
+    }
+
+    #[test]
+    fn computes_caps_verification_string() {
+        let info = parse_info(CAPS_EXAMPLE_XML);
+        assert_eq!(caps_verification(&info), "QgayPKawpkPSDYmwT/WM94uAlu0=");
+        assert_ne!(
+            caps_verification(&parse_info(CLIENT_INFO_XML)),
+            "QgayPKawpkPSDYmwT/WM94uAlu0="
+        );
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn publishes_disco_info_received() {
+        let event_bus: Arc<dyn EventBus> =
+            Arc::new(waddle_core::event::BroadcastEventBus::default());
+        let processor = DiscoProcessor::new(event_bus.clone());
+        let mut sub = event_bus.subscribe("xmpp.disco.**").unwrap();
+
+        let mut stanza = Stanza::parse(CLIENT_INFO_XML).unwrap();
+        let ctx = ProcessorContext {
+            direction: crate::pipeline::StanzaDirection::Inbound,
+        };
+        processor.process_inbound(&mut stanza, &ctx);
+
+        let event = sub.recv().await.unwrap();
+        match event.payload {
+            EventPayload::DiscoInfoReceived { jid, features, .. } => {
+                assert_eq!(jid, "alice@example.com/desktop");
+                assert!(features.contains(&"urn:xmpp:receipts".to_string()));
+            }
+            other => panic!("unexpected payload: {other:?}"),
+        }
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn publishes_a_page_of_disco_items() {
+        let event_bus: Arc<dyn EventBus> =
+            Arc::new(waddle_core::event::BroadcastEventBus::default());
+        let processor = DiscoProcessor::new(event_bus.clone());
+        let mut sub = event_bus.subscribe("xmpp.disco.**").unwrap();
+
+        let mut stanza = Stanza::parse(ROOM_ITEMS_XML).unwrap();
+        let ctx = ProcessorContext {
+            direction: crate::pipeline::StanzaDirection::Inbound,
+        };
+        processor.process_inbound(&mut stanza, &ctx);
+
+        let event = sub.recv().await.unwrap();
+        assert_eq!(event.channel.as_str(), "xmpp.disco.items.received");
+        match event.payload {
+            EventPayload::DiscoItemsReceived {
+                jid, items, page, ..
+            } => {
+                assert_eq!(jid, "conference.example.com");
+                assert_eq!(items.len(), 2);
+                assert_eq!(items[1].name.as_deref(), Some("Development"));
+                let page = page.expect("the reply carried a result set");
+                assert_eq!(page.last.as_deref(), Some("dev@conference.example.com"));
+                assert_eq!(page.remaining(), Some(30));
+            }
+            other => panic!("unexpected payload: {other:?}"),
+        }
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn publishes_disco_info_failed_for_an_error_answer() {
+        let event_bus: Arc<dyn EventBus> =
+            Arc::new(waddle_core::event::BroadcastEventBus::default());
+        let processor = DiscoProcessor::new(event_bus.clone());
+        let mut sub = event_bus.subscribe("xmpp.disco.**").unwrap();
+
+        let mut stanza = Stanza::parse(UPLOAD_INFO_ERROR_XML).unwrap();
+        let ctx = ProcessorContext {
+            direction: crate::pipeline::StanzaDirection::Inbound,
+        };
+        processor.process_inbound(&mut stanza, &ctx);
+
+        let event = sub.recv().await.unwrap();
+        assert_eq!(event.channel.as_str(), "xmpp.disco.info.failed");
+        match event.payload {
+            EventPayload::DiscoInfoFailed { jid, node, error } => {
+                assert_eq!(jid, "upload.example.com");
+                assert_eq!(node, None);
+                assert!(error.starts_with("service-unavailable"), "{error}");
+            }
+            other => panic!("unexpected payload: {other:?}"),
+        }
+    }
+}
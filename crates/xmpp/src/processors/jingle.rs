@@ -0,0 +1,203 @@
+use tracing::{debug, warn};
+use xmpp_parsers::iq::Iq;
+
+#[cfg(feature = "native")]
+use std::sync::Arc;
+
+#[cfg(feature = "native")]
+use waddle_core::event::{Channel, Event, EventBus, EventPayload, EventSource, channels};
+#[cfg(feature = "native")]
+use xmpp_parsers::minidom::Element;
+
+use crate::jingle::{JINGLE_IQ_ID_PREFIX, jingle_from_element};
+use crate::pipeline::{ProcessorContext, ProcessorResult, StanzaProcessor};
+use crate::stanza::Stanza;
+
+/// Reports Jingle requests from peers, and peers' answers to ours, for the
+/// call manager.
+pub struct JingleProcessor {
+    #[cfg(feature = "native")]
+    event_bus: Arc<dyn EventBus>,
+}
+
+impl JingleProcessor {
+    #[cfg(feature = "native")]
+    pub fn new(event_bus: Arc<dyn EventBus>) -> Self {
+        Self { event_bus }
+    }
+
+    #[cfg(feature = "native")]
+    fn emit(&self, channel: &str, payload: EventPayload) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channel).unwrap(),
+            EventSource::Xmpp,
+            payload,
+        ));
+    }
+}
+
+impl StanzaProcessor for JingleProcessor {
+    fn name(&self) -> &str {
+        "jingle"
+    }
+
+    fn process_inbound(&self, stanza: &mut Stanza, _ctx: &ProcessorContext) -> ProcessorResult {
+        let Stanza::Iq(iq) = stanza else {
+            return ProcessorResult::Continue;
+        };
+
+        match iq.as_ref() {
+            Iq::Set {
+                from, id, payload, ..
+            } if payload.is("jingle", xmpp_parsers::ns::JINGLE) => {
+                let from = from.as_ref().map(|jid| jid.to_string()).unwrap_or_default();
+                match jingle_from_element(payload) {
+                    Ok((sid, action)) => {
+                        debug!(from = %from, sid = %sid, "jingle request received");
+                        #[cfg(feature = "native")]
+                        self.emit(
+                            channels::XMPP_JINGLE_RECEIVED,
+                            EventPayload::JingleReceived {
+                                from,
+                                iq_id: id.clone(),
+                                sid,
+                                action,
+                            },
+                        );
+                    }
+                    Err(error) => {
+                        warn!(from = %from, %error, "refusing malformed jingle request");
+                        #[cfg(feature = "native")]
+                        self.emit(
+                            channels::UI_JINGLE_ANSWER,
+                            EventPayload::JingleAnswerRequested {
+                                to: from,
+                                iq_id: id.clone(),
+                                error: Some("bad-request".to_string()),
+                            },
+                        );
+                    }
+                }
+            }
+            Iq::Result { id, .. } if id.starts_with(JINGLE_IQ_ID_PREFIX) => {
+                #[cfg(feature = "native")]
+                self.emit(
+                    channels::XMPP_JINGLE_ANSWERED,
+                    EventPayload::JingleAnswered {
+                        iq_id: id.clone(),
+                        error: None,
+                    },
+                );
+            }
+            Iq::Error { id, error, .. } if id.starts_with(JINGLE_IQ_ID_PREFIX) => {
+                debug!(id = %id, "jingle request refused");
+                #[cfg(feature = "native")]
+                self.emit(
+                    channels::XMPP_JINGLE_ANSWERED,
+                    EventPayload::JingleAnswered {
+                        iq_id: id.clone(),
+                        error: Some(crate::registration::error_reason(Some(&Element::from(
+                            error.clone(),
+                        )))),
+                    },
+                );
+            }
+            _ => {}
+        }
+
+        ProcessorResult::Continue
+    }
+
+    fn process_outbound(&self, _stanza: &mut Stanza, _ctx: &ProcessorContext) -> ProcessorResult {
+        ProcessorResult::Continue
+    }
+
+    fn priority(&self) -> i32 {
+        10
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use crate::pipeline::StanzaDirection;
+    use waddle_core::event::{BroadcastEventBus, JingleAction};
+
+    fn receive(processor: &JingleProcessor, xml: &str) {
+        let mut stanza = Stanza::parse(xml.as_bytes()).unwrap();
+        let ctx = ProcessorContext {
+            direction: StanzaDirection::Inbound,
+        };
+        processor.process_inbound(&mut stanza, &ctx);
+    }
+
+    #[tokio::test]
+    async fn publishes_requests_and_answers() {
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let processor = JingleProcessor::new(event_bus.clone());
+        let mut received = event_bus.subscribe("xmpp.jingle.**").unwrap();
+
+        receive(
+            &processor,
+            "<iq xmlns='jabber:client' type='set' id='j1' from='romeo@montague.lit/orchard'>\
+               <jingle xmlns='urn:xmpp:jingle:1' action='session-info' sid='s1'>\
+                 <ringing xmlns='urn:xmpp:jingle:apps:rtp:info:1'/>\
+               </jingle>\
+             </iq>",
+        );
+        receive(
+            &processor,
+            "<iq xmlns='jabber:client' type='result' id='roster-1'/>",
+        );
+        receive(
+            &processor,
+            "<iq xmlns='jabber:client' type='error' id='jingle-2'>\
+               <error type='cancel'>\
+                 <service-unavailable xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/>\
+               </error>\
+             </iq>",
+        );
+
+        match received.recv().await.unwrap().payload {
+            EventPayload::JingleReceived {
+                from,
+                iq_id,
+                sid,
+                action,
+            } => {
+                assert_eq!(from, "romeo@montague.lit/orchard");
+                assert_eq!(iq_id, "j1");
+                assert_eq!(sid, "s1");
+                assert_eq!(action, JingleAction::Ringing);
+            }
+            other => panic!("unexpected payload: {other:?}"),
+        }
+        match received.recv().await.unwrap().payload {
+            EventPayload::JingleAnswered { iq_id, error } => {
+                assert_eq!(iq_id, "jingle-2");
+                assert_eq!(error.as_deref(), Some("service-unavailable"));
+            }
+            other => panic!("unexpected payload: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_malformed_requests() {
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let processor = JingleProcessor::new(event_bus.clone());
+        let mut answers = event_bus.subscribe("ui.jingle.answer").unwrap();
+
+        receive(
+            &processor,
+            "<iq xmlns='jabber:client' type='set' id='j1' from='romeo@montague.lit/orchard'>\
+               <jingle xmlns='urn:xmpp:jingle:1' action='session-initiate'/>\
+             </iq>",
+        );
+
+        assert!(matches!(
+            answers.recv().await.unwrap().payload,
+            EventPayload::JingleAnswerRequested { iq_id, error: Some(error), .. }
+                if iq_id == "j1" && error == "bad-request"
+        ));
+    }
+}
@@ -1,23 +1,32 @@
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
 
 use chrono::Utc;
-use tracing::debug;
+use tracing::{debug, warn};
 use xmpp_parsers::iq::Iq;
 use xmpp_parsers::mam;
+use xmpp_parsers::minidom::Element;
+use xmpp_parsers::ns;
+use xmpp_parsers::stanza_id::OriginId;
 
 use waddle_core::event::{
     Channel, ChatMessage, Event, EventPayload, EventSource, MessageType as CoreMessageType,
+    channels,
 };
 
-use super::message::parse_embeds_from_payloads;
+use super::message::{body_lang, parse_embeds_from_payloads, try_extract_displayed};
 
 #[cfg(feature = "native")]
 use waddle_core::event::EventBus;
 
 use crate::pipeline::{ProcessorContext, ProcessorResult, StanzaProcessor};
+use crate::registration::error_reason;
 use crate::stanza::Stanza;
 
 pub struct MamProcessor {
+    /// Ids of the queries we sent that the server has not answered yet,
+    /// so their errors can be told apart from other IQ errors.
+    pending_queries: Mutex<HashSet<String>>,
     #[cfg(feature = "native")]
     event_bus: Arc<dyn EventBus>,
 }
@@ -25,7 +34,10 @@ pub struct MamProcessor {
 impl MamProcessor {
     #[cfg(feature = "native")]
     pub fn new(event_bus: Arc<dyn EventBus>) -> Self {
-        Self { event_bus }
+        Self {
+            pending_queries: Mutex::new(HashSet::new()),
+            event_bus,
+        }
     }
 }
 
@@ -54,12 +66,17 @@ impl StanzaProcessor for MamProcessor {
                     .map(|d| d.stamp.0.to_utc())
                     .unwrap_or_else(Utc::now);
 
-                let body = forwarded_msg
+                let (lang, body) = forwarded_msg
                     .get_best_body(vec![])
-                    .map(|(_, b)| b.clone())
+                    .map(|(lang, body)| (body_lang(&lang), body.clone()))
                     .unwrap_or_default();
 
                 let embeds = parse_embeds_from_payloads(&forwarded_msg.payloads);
+                let origin_id = forwarded_msg
+                    .payloads
+                    .iter()
+                    .find_map(|el| OriginId::try_from(el.clone()).ok())
+                    .map(|origin| origin.id);
 
                 let chat_message = ChatMessage {
                     id: result.id.clone(),
@@ -84,8 +101,26 @@ impl StanzaProcessor for MamProcessor {
                     },
                     thread: forwarded_msg.thread.as_ref().map(|t| t.id.clone()),
                     embeds,
+                    origin_id,
+                    // The result id is the archive's stanza id for the message.
+                    stanza_id: Some(result.id.clone()),
+                    retracted_at: None,
+                    lang,
+                    translation: None,
                 };
 
+                // A marker we sent from any device: the chat it went to has
+                // been read up to the marked message.
+                let own_marker = try_extract_displayed(forwarded_msg).filter(|_| {
+                    forwarded_msg.from.as_ref().map(|j| j.to_bare())
+                        == msg.to.as_ref().map(|j| j.to_bare())
+                });
+                let marked_conversation = forwarded_msg
+                    .to
+                    .as_ref()
+                    .map(|j| j.to_bare().to_string())
+                    .unwrap_or_default();
+
                 let query_id = result
                     .queryid
                     .as_ref()
@@ -96,7 +131,7 @@ impl StanzaProcessor for MamProcessor {
                 #[cfg(feature = "native")]
                 {
                     let _ = self.event_bus.publish(Event::new(
-                        Channel::new("xmpp.mam.result.received").unwrap(),
+                        Channel::new(channels::XMPP_MAM_RESULT_RECEIVED).unwrap(),
                         EventSource::Xmpp,
                         EventPayload::MamResultReceived {
                             query_id,
@@ -104,34 +139,60 @@ impl StanzaProcessor for MamProcessor {
                             complete: false,
                         },
                     ));
+                    if let Some(id) = own_marker {
+                        let _ = self.event_bus.publish(Event::new(
+                            Channel::new(channels::XMPP_MESSAGE_DISPLAYED_ELSEWHERE).unwrap(),
+                            EventSource::Xmpp,
+                            EventPayload::MessageDisplayedElsewhere {
+                                conversation: marked_conversation,
+                                id,
+                                timestamp,
+                            },
+                        ));
+                    }
                 }
             }
             Stanza::Iq(iq) => {
-                if let Iq::Result {
+                if let Iq::Error { id, error, .. } = iq.as_ref()
+                    && self.pending_queries.lock().unwrap().remove(id)
+                {
+                    let error = error_reason(Some(&Element::from(error.clone())));
+                    warn!(iq_id = %id, error = %error, "MAM query refused");
+                    #[cfg(feature = "native")]
+                    {
+                        let _ = self.event_bus.publish(Event::new(
+                            Channel::new(channels::XMPP_MAM_QUERY_FAILED).unwrap(),
+                            EventSource::Xmpp,
+                            EventPayload::MamQueryFailed {
+                                iq_id: id.clone(),
+                                error,
+                            },
+                        ));
+                    }
+                } else if let Iq::Result {
                     id,
                     payload: Some(payload),
                     ..
                 } = iq.as_ref()
+                    && let Ok(fin) = mam::Fin::try_from(payload.clone())
                 {
-                    if let Ok(fin) = mam::Fin::try_from(payload.clone()) {
-                        let last_id = fin.set.last.clone();
-                        debug!(
-                            complete = fin.complete,
-                            last_id = ?last_id,
-                            "MAM query finished"
-                        );
-                        #[cfg(feature = "native")]
-                        {
-                            let _ = self.event_bus.publish(Event::new(
-                                Channel::new("xmpp.mam.fin.received").unwrap(),
-                                EventSource::Xmpp,
-                                EventPayload::MamFinReceived {
-                                    iq_id: id.clone(),
-                                    complete: fin.complete,
-                                    last_id,
-                                },
-                            ));
-                        }
+                    self.pending_queries.lock().unwrap().remove(id);
+                    debug!(
+                        complete = fin.complete,
+                        last_id = ?fin.set.last,
+                        "MAM query finished"
+                    );
+                    #[cfg(feature = "native")]
+                    {
+                        let _ = self.event_bus.publish(Event::new(
+                            Channel::new(channels::XMPP_MAM_FIN_RECEIVED).unwrap(),
+                            EventSource::Xmpp,
+                            EventPayload::MamFinReceived {
+                                iq_id: id.clone(),
+                                complete: fin.complete,
+                                page: crate::rsm::rsm_result(&fin.set),
+                            },
+                        ));
                     }
                 }
             }
@@ -141,7 +202,13 @@ impl StanzaProcessor for MamProcessor {
         ProcessorResult::Continue
     }
 
-    fn process_outbound(&self, _stanza: &mut Stanza, _ctx: &ProcessorContext) -> ProcessorResult {
+    fn process_outbound(&self, stanza: &mut Stanza, _ctx: &ProcessorContext) -> ProcessorResult {
+        if let Stanza::Iq(iq) = stanza
+            && let Iq::Set { id, payload, .. } = iq.as_ref()
+            && payload.is("query", ns::MAM)
+        {
+            self.pending_queries.lock().unwrap().insert(id.clone());
+        }
         ProcessorResult::Continue
     }
 
@@ -181,4 +248,49 @@ mod tests {
         assert_eq!(result.id, "archive-id-1");
         assert_eq!(result.queryid.as_ref().map(|q| q.0.as_str()), Some("q1"));
     }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn refused_queries_are_reported_and_other_errors_ignored() {
+        let event_bus: Arc<dyn EventBus> =
+            Arc::new(waddle_core::event::BroadcastEventBus::default());
+        let processor = MamProcessor::new(event_bus.clone());
+        let mut sub = event_bus.subscribe("xmpp.mam.**").unwrap();
+        let outbound = ProcessorContext {
+            direction: crate::pipeline::StanzaDirection::Outbound,
+        };
+        let inbound = ProcessorContext {
+            direction: crate::pipeline::StanzaDirection::Inbound,
+        };
+
+        let mut query = Stanza::parse(
+            b"<iq xmlns='jabber:client' type='set' id='q7'>\
+                <query xmlns='urn:xmpp:mam:2' queryid='q7'/>\
+            </iq>",
+        )
+        .unwrap();
+        processor.process_outbound(&mut query, &outbound);
+
+        for id in ["other", "q7"] {
+            let xml = format!(
+                "<iq xmlns='jabber:client' type='error' id='{id}'>\
+                    <error type='cancel'>\
+                        <item-not-found xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/>\
+                    </error>\
+                </iq>"
+            );
+            let mut error = Stanza::parse(xml.as_bytes()).unwrap();
+            processor.process_inbound(&mut error, &inbound);
+        }
+
+        let event = sub.recv().await.unwrap();
+        match event.payload {
+            EventPayload::MamQueryFailed { iq_id, error } => {
+                assert_eq!(iq_id, "q7");
+                assert_eq!(error, "item-not-found");
+            }
+            other => panic!("unexpected payload: {other:?}"),
+        }
+        assert!(processor.pending_queries.lock().unwrap().is_empty());
+    }
 }
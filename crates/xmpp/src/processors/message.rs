@@ -2,12 +2,14 @@ use std::sync::Arc;
 
 use chrono::Utc;
 use tracing::debug;
+use xmpp_parsers::carbons;
 use xmpp_parsers::message::MessageType;
 use xmpp_parsers::receipts;
+use xmpp_parsers::stanza_id::{OriginId, StanzaId};
 
 use waddle_core::event::{
     Channel, ChatMessage, Event, EventPayload, EventSource, MessageEmbed,
-    MessageType as CoreMessageType,
+    MessageType as CoreMessageType, channels,
 };
 
 #[cfg(feature = "native")]
@@ -42,6 +44,23 @@ impl StanzaProcessor for MessageProcessor {
             return ProcessorResult::Continue;
         }
 
+        if let Some((conversation, id)) = try_extract_own_displayed(msg) {
+            debug!(id = %id, conversation = %conversation, "displayed on another device");
+            #[cfg(feature = "native")]
+            {
+                let _ = self.event_bus.publish(Event::new(
+                    Channel::new(channels::XMPP_MESSAGE_DISPLAYED_ELSEWHERE).unwrap(),
+                    EventSource::Xmpp,
+                    EventPayload::MessageDisplayedElsewhere {
+                        conversation,
+                        id,
+                        timestamp: Utc::now(),
+                    },
+                ));
+            }
+            return ProcessorResult::Continue;
+        }
+
         if let Some(received) = try_extract_receipt(msg) {
             debug!(id = %received.id, "delivery receipt received");
             #[cfg(feature = "native")]
@@ -52,7 +71,7 @@ impl StanzaProcessor for MessageProcessor {
                     .map(|j| j.to_bare().to_string())
                     .unwrap_or_default();
                 let _ = self.event_bus.publish(Event::new(
-                    Channel::new("xmpp.message.delivered").unwrap(),
+                    Channel::new(channels::XMPP_MESSAGE_DELIVERED).unwrap(),
                     EventSource::Xmpp,
                     EventPayload::MessageDelivered {
                         id: received.id,
@@ -63,14 +82,71 @@ impl StanzaProcessor for MessageProcessor {
             return ProcessorResult::Continue;
         }
 
-        let body = match msg.get_best_body(vec![]) {
-            Some((_, body)) => body.clone(),
+        if let Some(id) = try_extract_displayed(msg) {
+            debug!(id = %id, "displayed marker received");
+            #[cfg(feature = "native")]
+            {
+                let to = msg
+                    .from
+                    .as_ref()
+                    .map(|j| j.to_bare().to_string())
+                    .unwrap_or_default();
+                let _ = self.event_bus.publish(Event::new(
+                    Channel::new(channels::XMPP_MESSAGE_DISPLAYED).unwrap(),
+                    EventSource::Xmpp,
+                    EventPayload::MessageDisplayed { id, to },
+                ));
+            }
+            return ProcessorResult::Continue;
+        }
+
+        // The fallback body of a retraction is not a message of its own.
+        if let Some(retraction) = parse_retraction(&msg.payloads) {
+            debug!(id = %retraction.id, "message retraction received");
+            #[cfg(feature = "native")]
+            {
+                let from = msg
+                    .from
+                    .as_ref()
+                    .map(|j| j.to_bare().to_string())
+                    .unwrap_or_default();
+                let _ = self.event_bus.publish(Event::new(
+                    Channel::new(channels::XMPP_MESSAGE_RETRACTED).unwrap(),
+                    EventSource::Xmpp,
+                    EventPayload::MessageRetractionReceived {
+                        conversation: from.clone(),
+                        from,
+                        id: retraction.id,
+                        moderated_by: retraction.moderated_by,
+                        reason: retraction.reason,
+                    },
+                ));
+            }
+            return ProcessorResult::Continue;
+        }
+
+        // Nor is the fallback body of a room invite; the MUC processor
+        // announces the invite itself.
+        if super::muc::parse_invite(msg).is_some() {
+            return ProcessorResult::Continue;
+        }
+
+        let (lang, body) = match msg.get_best_body(vec![]) {
+            Some((lang, body)) => (body_lang(&lang), body.clone()),
             None => return ProcessorResult::Continue,
         };
 
         // Parse plugin embeds from stanza payloads
         let embeds = parse_embeds_from_payloads(&msg.payloads);
 
+        // Our server archives one-to-one messages under our bare JID.
+        let archive = msg
+            .to
+            .as_ref()
+            .map(|j| j.to_bare().to_string())
+            .unwrap_or_default();
+        let (origin_id, stanza_id) = parse_stanza_ids(&msg.payloads, &archive);
+
         let chat_message = ChatMessage {
             id: msg.id.as_ref().map(|id| id.0.clone()).unwrap_or_default(),
             from: msg
@@ -94,6 +170,11 @@ impl StanzaProcessor for MessageProcessor {
             },
             thread: msg.thread.as_ref().map(|t| t.id.clone()),
             embeds,
+            origin_id,
+            stanza_id,
+            retracted_at: None,
+            lang,
+            translation: None,
         };
 
         debug!(
@@ -105,12 +186,24 @@ impl StanzaProcessor for MessageProcessor {
         #[cfg(feature = "native")]
         {
             let _ = self.event_bus.publish(Event::new(
-                Channel::new("xmpp.message.received").unwrap(),
+                Channel::new(channels::XMPP_MESSAGE_RECEIVED).unwrap(),
                 EventSource::Xmpp,
                 EventPayload::MessageReceived {
                     message: chat_message,
                 },
             ));
+
+            // Announced after the message so it is stored before it is acked.
+            if let (Some(id), Some(from)) = (receipt_request_id(msg), msg.from.as_ref()) {
+                let _ = self.event_bus.publish(Event::new(
+                    Channel::new(channels::XMPP_MESSAGE_RECEIPT_REQUESTED).unwrap(),
+                    EventSource::Xmpp,
+                    EventPayload::ReceiptRequested {
+                        id,
+                        from: from.to_string(),
+                    },
+                ));
+            }
         }
 
         ProcessorResult::Continue
@@ -125,6 +218,64 @@ impl StanzaProcessor for MessageProcessor {
     }
 }
 
+/// The language of a body, from the key `get_best_body` returned it under:
+/// its own `xml:lang`, or the stanza's. Empty when neither declared one.
+pub(crate) fn body_lang(lang: &xmpp_parsers::message::Lang) -> Option<String> {
+    (!lang.is_empty()).then(|| lang.to_string())
+}
+
+/// XEP-0359 ids of a message: the sender's `<origin-id/>`, and the
+/// `<stanza-id/>` added by `archive`. Stanza ids claimed by any other entity
+/// are ignored, as senders can attach them too.
+pub(crate) fn parse_stanza_ids(
+    payloads: &[xmpp_parsers::minidom::Element],
+    archive: &str,
+) -> (Option<String>, Option<String>) {
+    let origin_id = payloads
+        .iter()
+        .find_map(|el| OriginId::try_from(el.clone()).ok())
+        .map(|origin| origin.id);
+    let stanza_id = payloads
+        .iter()
+        .filter_map(|el| StanzaId::try_from(el.clone()).ok())
+        .find(|stanza_id| stanza_id.by.to_string() == archive)
+        .map(|stanza_id| stanza_id.id);
+    (origin_id, stanza_id)
+}
+
+const NS_CHAT_MARKERS: &str = "urn:xmpp:chat-markers:0";
+
+pub(crate) const NS_RETRACT: &str = "urn:xmpp:message-retract:1";
+pub(crate) const NS_MODERATE: &str = "urn:xmpp:message-moderate:1";
+
+/// A XEP-0424 `<retract/>`, or a XEP-0425 moderation when `moderated_by` is
+/// set.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Retraction {
+    pub id: String,
+    pub moderated_by: Option<String>,
+    pub reason: Option<String>,
+}
+
+pub(crate) fn parse_retraction(payloads: &[xmpp_parsers::minidom::Element]) -> Option<Retraction> {
+    let retract = payloads
+        .iter()
+        .find(|payload| payload.is("retract", NS_RETRACT))?;
+    let id = retract.attr("id")?.to_string();
+    let moderated_by = retract
+        .get_child("moderated", NS_MODERATE)
+        .map(|moderated| moderated.attr("by").unwrap_or_default().to_string());
+    let reason = retract
+        .get_child("reason", NS_RETRACT)
+        .map(|reason| reason.text())
+        .filter(|reason| !reason.is_empty());
+    Some(Retraction {
+        id,
+        moderated_by,
+        reason,
+    })
+}
+
 /// Known embed namespace for GitHub metadata.
 const NS_WADDLE_GITHUB: &str = "urn:waddle:github:0";
 
@@ -262,6 +413,42 @@ pub(crate) fn parse_embeds_from_payloads(payloads: &[xmpp_parsers::minidom::Elem
     embeds
 }
 
+/// XEP-0184 §5.4: a receipt can only refer to a message with an id.
+fn receipt_request_id(msg: &xmpp_parsers::message::Message) -> Option<String> {
+    let id = msg.id.as_ref()?;
+    msg.payloads
+        .iter()
+        .any(|payload| receipts::Request::try_from(payload.clone()).is_ok())
+        .then(|| id.0.clone())
+}
+
+/// Id of the message a XEP-0333 `<displayed/>` marker refers to.
+pub(crate) fn try_extract_displayed(msg: &xmpp_parsers::message::Message) -> Option<String> {
+    msg.payloads
+        .iter()
+        .find(|payload| payload.is("displayed", NS_CHAT_MARKERS))
+        .and_then(|payload| payload.attr("id"))
+        .map(str::to_string)
+}
+
+/// A displayed marker another of our devices sent, as the chat it was sent
+/// to and the message id. It reaches us as a XEP-0280 sent carbon, which is
+/// only trusted from our own bare JID.
+fn try_extract_own_displayed(msg: &xmpp_parsers::message::Message) -> Option<(String, String)> {
+    let from = msg.from.as_ref()?;
+    if from.resource().is_some() || Some(from.to_bare()) != msg.to.as_ref().map(|j| j.to_bare()) {
+        return None;
+    }
+    let sent = msg
+        .payloads
+        .iter()
+        .find_map(|payload| carbons::Sent::try_from(payload.clone()).ok())?;
+    let forwarded = &sent.forwarded.message;
+    let id = try_extract_displayed(forwarded)?;
+    let conversation = forwarded.to.as_ref()?.to_bare().to_string();
+    Some((conversation, id))
+}
+
 fn try_extract_receipt(msg: &xmpp_parsers::message::Message) -> Option<receipts::Received> {
     for payload in &msg.payloads {
         if let Ok(received) = receipts::Received::try_from(payload.clone()) {
@@ -307,6 +494,124 @@ mod tests {
         assert_eq!(receipt.unwrap().id, "msg-1");
     }
 
+    #[test]
+    fn receipt_request_needs_a_message_id() {
+        let with_id = Stanza::parse(
+            b"<message xmlns='jabber:client' type='chat' from='alice@example.com/phone' id='msg-2'>\
+              <body>ping</body><request xmlns='urn:xmpp:receipts'/></message>",
+        )
+        .unwrap();
+        let without_id = Stanza::parse(
+            b"<message xmlns='jabber:client' type='chat' from='alice@example.com/phone'>\
+              <body>ping</body><request xmlns='urn:xmpp:receipts'/></message>",
+        )
+        .unwrap();
+        let (Stanza::Message(with_id), Stanza::Message(without_id)) = (&with_id, &without_id)
+        else {
+            panic!("expected messages");
+        };
+
+        assert_eq!(receipt_request_id(with_id).as_deref(), Some("msg-2"));
+        assert_eq!(receipt_request_id(without_id), None);
+        let Stanza::Message(plain) = Stanza::parse(CHAT_MESSAGE_XML).unwrap() else {
+            panic!("expected message");
+        };
+        assert_eq!(receipt_request_id(&plain), None);
+    }
+
+    #[test]
+    fn parses_displayed_marker() {
+        let stanza = Stanza::parse(
+            b"<message xmlns='jabber:client' type='chat' from='bob@example.com/laptop'>\
+              <displayed xmlns='urn:xmpp:chat-markers:0' id='msg-1'/></message>",
+        )
+        .unwrap();
+        let Stanza::Message(msg) = &stanza else {
+            panic!("expected message");
+        };
+        assert_eq!(try_extract_displayed(msg).as_deref(), Some("msg-1"));
+    }
+
+    #[test]
+    fn parses_displayed_marker_from_own_device() {
+        let carbon = |from: &str| {
+            format!(
+                "<message xmlns='jabber:client' from='{from}' to='alice@example.com/laptop'>\
+                  <sent xmlns='urn:xmpp:carbons:2'>\
+                    <forwarded xmlns='urn:xmpp:forward:0'>\
+                      <message xmlns='jabber:client' type='chat' \
+                        from='alice@example.com/phone' to='bob@example.com'>\
+                        <displayed xmlns='urn:xmpp:chat-markers:0' id='msg-7'/>\
+                      </message>\
+                    </forwarded>\
+                  </sent>\
+                </message>"
+            )
+        };
+
+        let stanza = Stanza::parse(carbon("alice@example.com").as_bytes()).unwrap();
+        let Stanza::Message(msg) = &stanza else {
+            panic!("expected message");
+        };
+        assert_eq!(
+            try_extract_own_displayed(msg),
+            Some(("bob@example.com".to_string(), "msg-7".to_string()))
+        );
+
+        let stanza = Stanza::parse(carbon("mallory@example.com").as_bytes()).unwrap();
+        let Stanza::Message(msg) = &stanza else {
+            panic!("expected message");
+        };
+        assert_eq!(try_extract_own_displayed(msg), None);
+    }
+
+    #[test]
+    fn parses_retraction_instead_of_fallback_body() {
+        let xml = b"<message xmlns='jabber:client' type='chat' \
+            from='alice@example.com/phone' to='bob@example.com' id='r-1'>\
+            <retract xmlns='urn:xmpp:message-retract:1' id='origin-1'/>\
+            <fallback xmlns='urn:xmpp:fallback:0' for='urn:xmpp:message-retract:1'/>\
+            <body>This person attempted to retract a previous message.</body>\
+        </message>";
+        let stanza = Stanza::parse(xml).unwrap();
+        let Stanza::Message(msg) = &stanza else {
+            panic!("expected message");
+        };
+        assert_eq!(
+            parse_retraction(&msg.payloads),
+            Some(Retraction {
+                id: "origin-1".to_string(),
+                moderated_by: None,
+                reason: None,
+            })
+        );
+    }
+
+    #[test]
+    fn body_language_comes_from_the_body_or_the_stanza() {
+        let lang = |xml: &[u8]| {
+            let Stanza::Message(msg) = Stanza::parse(xml).unwrap() else {
+                panic!("expected message");
+            };
+            let (lang, _) = msg.get_best_body(vec![]).unwrap();
+            body_lang(&lang)
+        };
+
+        assert_eq!(lang(CHAT_MESSAGE_XML), None);
+        assert_eq!(
+            lang(b"<message xmlns='jabber:client' type='groupchat' xml:lang='de'>\
+                   <body>Guten Morgen</body></message>")
+            .as_deref(),
+            Some("de")
+        );
+        assert_eq!(
+            lang(b"<message xmlns='jabber:client' type='chat' xml:lang='de'>\
+                   <body xml:lang='fr'>Bonjour</body></message>")
+            .as_deref(),
+            Some("fr")
+        );
+    }
+
     #[test]
     fn skips_groupchat() {
         let stanza = Stanza::parse(GROUPCHAT_XML).unwrap();
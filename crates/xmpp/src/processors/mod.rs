@@ -1,15 +1,29 @@
+mod account;
 mod chat_state;
 mod debug;
+mod disco;
+mod jingle;
 mod mam;
 mod message;
 mod muc;
+mod omemo;
 mod presence;
+mod pubsub;
 mod roster;
 
+#[cfg(feature = "native")]
+pub(crate) use account::ACCOUNT_IQ_ID_PREFIX;
+pub use account::AccountProcessor;
 pub use chat_state::ChatStateProcessor;
 pub use debug::DebugProcessor;
+pub use disco::DiscoProcessor;
+pub(crate) use disco::{DISCO_INFO_IQ_ID_PREFIX, DISCO_ITEMS_IQ_ID_PREFIX};
+pub use jingle::JingleProcessor;
 pub use mam::MamProcessor;
 pub use message::MessageProcessor;
+pub(crate) use message::{NS_MODERATE, NS_RETRACT};
 pub use muc::MucProcessor;
+pub use omemo::OmemoProcessor;
 pub use presence::PresenceProcessor;
+pub use pubsub::PubSubProcessor;
 pub use roster::RosterProcessor;
@@ -1,18 +1,22 @@
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
 
 use chrono::Utc;
 use tracing::debug;
-use xmpp_parsers::message::MessageType;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::message::{Message, MessageType};
 use xmpp_parsers::muc::user::{MucUser, Status};
+use xmpp_parsers::ns;
 use xmpp_parsers::presence::Type as PresenceType;
 
 use waddle_core::event::{
     Channel, ChatMessage, Event, EventPayload, EventSource, MessageType as CoreMessageType,
-    MucAffiliation as CoreAffiliation, MucOccupant as CoreOccupant, MucRole as CoreRole,
+    MucAffiliation as CoreAffiliation, MucInvite, MucOccupant as CoreOccupant, MucRole as CoreRole,
+    channels,
 };
 
-// Re-use the embed parser from the message processor
-use super::message::parse_embeds_from_payloads;
+// Re-use the embed and stanza id parsers from the message processor
+use super::message::{body_lang, parse_embeds_from_payloads, parse_retraction, parse_stanza_ids};
 
 #[cfg(feature = "native")]
 use waddle_core::event::EventBus;
@@ -23,12 +27,18 @@ use crate::stanza::Stanza;
 pub struct MucProcessor {
     #[cfg(feature = "native")]
     event_bus: Arc<dyn EventBus>,
+    /// Occupant JIDs we are being renamed to. The room confirms the new nick
+    /// with a self-presence that is not a fresh join.
+    renaming: Mutex<HashSet<String>>,
 }
 
 impl MucProcessor {
     #[cfg(feature = "native")]
     pub fn new(event_bus: Arc<dyn EventBus>) -> Self {
-        Self { event_bus }
+        Self {
+            event_bus,
+            renaming: Mutex::new(HashSet::new()),
+        }
     }
 }
 
@@ -41,6 +51,17 @@ impl StanzaProcessor for MucProcessor {
         match stanza {
             Stanza::Message(msg) => {
                 if msg.type_ != MessageType::Groupchat {
+                    if let Some(invite) = parse_invite(msg) {
+                        debug!(room = %invite.room, from = %invite.from, "MUC invite received");
+                        #[cfg(feature = "native")]
+                        {
+                            let _ = self.event_bus.publish(Event::new(
+                                Channel::new(channels::XMPP_MUC_INVITE_RECEIVED).unwrap(),
+                                EventSource::Xmpp,
+                                EventPayload::MucInviteReceived { invite },
+                            ));
+                        }
+                    }
                     return ProcessorResult::Continue;
                 }
 
@@ -50,23 +71,54 @@ impl StanzaProcessor for MucProcessor {
                         .as_ref()
                         .map(|j| j.to_bare().to_string())
                         .unwrap_or_default();
-                    debug!(room = %room, "MUC subject changed");
+                    let set_by = msg
+                        .from
+                        .as_ref()
+                        .and_then(|j| j.resource().map(|r| r.to_string()));
+                    debug!(room = %room, set_by = ?set_by, "MUC subject changed");
                     #[cfg(feature = "native")]
                     {
                         let _ = self.event_bus.publish(Event::new(
-                            Channel::new("xmpp.muc.subject.changed").unwrap(),
+                            Channel::new(channels::XMPP_MUC_SUBJECT_CHANGED).unwrap(),
                             EventSource::Xmpp,
                             EventPayload::MucSubjectChanged {
                                 room,
                                 subject: subject.clone(),
+                                set_by,
                             },
                         ));
                     }
                     return ProcessorResult::Continue;
                 }
 
-                let body = match msg.get_best_body(vec![]) {
-                    Some((_, body)) => body.clone(),
+                // Retractions by an occupant come from their occupant JID,
+                // moderations from the room itself.
+                if let Some(retraction) = parse_retraction(&msg.payloads) {
+                    let room = msg
+                        .from
+                        .as_ref()
+                        .map(|j| j.to_bare().to_string())
+                        .unwrap_or_default();
+                    debug!(room = %room, id = %retraction.id, "MUC message retracted");
+                    #[cfg(feature = "native")]
+                    {
+                        let _ = self.event_bus.publish(Event::new(
+                            Channel::new(channels::XMPP_MUC_MESSAGE_RETRACTED).unwrap(),
+                            EventSource::Xmpp,
+                            EventPayload::MessageRetractionReceived {
+                                conversation: room,
+                                from: msg.from.as_ref().map(|j| j.to_string()).unwrap_or_default(),
+                                id: retraction.id,
+                                moderated_by: retraction.moderated_by,
+                                reason: retraction.reason,
+                            },
+                        ));
+                    }
+                    return ProcessorResult::Continue;
+                }
+
+                let (lang, body) = match msg.get_best_body(vec![]) {
+                    Some((lang, body)) => (body_lang(&lang), body.clone()),
                     None => return ProcessorResult::Continue,
                 };
 
@@ -77,6 +129,7 @@ impl StanzaProcessor for MucProcessor {
                     .unwrap_or_default();
 
                 let embeds = parse_embeds_from_payloads(&msg.payloads);
+                let (origin_id, stanza_id) = parse_stanza_ids(&msg.payloads, &room);
 
                 let chat_message = ChatMessage {
                     id: msg.id.as_ref().map(|id| id.0.clone()).unwrap_or_default(),
@@ -87,13 +140,18 @@ impl StanzaProcessor for MucProcessor {
                     message_type: CoreMessageType::Groupchat,
                     thread: msg.thread.as_ref().map(|t| t.id.clone()),
                     embeds,
+                    origin_id,
+                    stanza_id,
+                    retracted_at: None,
+                    lang,
+                    translation: None,
                 };
 
                 debug!(room = %room, "MUC message received");
                 #[cfg(feature = "native")]
                 {
                     let _ = self.event_bus.publish(Event::new(
-                        Channel::new("xmpp.muc.message.received").unwrap(),
+                        Channel::new(channels::XMPP_MUC_MESSAGE_RECEIVED).unwrap(),
                         EventSource::Xmpp,
                         EventPayload::MucMessageReceived {
                             room,
@@ -126,13 +184,42 @@ impl StanzaProcessor for MucProcessor {
 
                 let is_self = muc_user.status.contains(&Status::SelfPresence);
 
-                if presence.type_ == PresenceType::Unavailable {
+                let new_nick = muc_user
+                    .status
+                    .contains(&Status::NewNick)
+                    .then(|| muc_user.items.first().and_then(|item| item.nick.clone()))
+                    .flatten();
+
+                if presence.type_ == PresenceType::Unavailable
+                    && let Some(new_nick) = new_nick
+                {
+                    debug!(room = %room, old = %nick, new = %new_nick, "MUC occupant renamed");
+                    if is_self {
+                        self.renaming
+                            .lock()
+                            .unwrap()
+                            .insert(format!("{room}/{new_nick}"));
+                    }
+                    #[cfg(feature = "native")]
+                    {
+                        let _ = self.event_bus.publish(Event::new(
+                            Channel::new(channels::XMPP_MUC_OCCUPANT_RENAMED).unwrap(),
+                            EventSource::Xmpp,
+                            EventPayload::MucOccupantRenamed {
+                                room,
+                                old_nick: nick,
+                                new_nick,
+                                own: is_self,
+                            },
+                        ));
+                    }
+                } else if presence.type_ == PresenceType::Unavailable {
                     if is_self {
                         debug!(room = %room, "left MUC room");
                         #[cfg(feature = "native")]
                         {
                             let _ = self.event_bus.publish(Event::new(
-                                Channel::new("xmpp.muc.left").unwrap(),
+                                Channel::new(channels::XMPP_MUC_LEFT).unwrap(),
                                 EventSource::Xmpp,
                                 EventPayload::MucLeft { room },
                             ));
@@ -147,12 +234,18 @@ impl StanzaProcessor for MucProcessor {
                         );
                     }
                 } else {
-                    if is_self {
+                    let renamed = is_self
+                        && self
+                            .renaming
+                            .lock()
+                            .unwrap()
+                            .remove(&format!("{room}/{nick}"));
+                    if is_self && !renamed {
                         debug!(room = %room, nick = %nick, "joined MUC room");
                         #[cfg(feature = "native")]
                         {
                             let _ = self.event_bus.publish(Event::new(
-                                Channel::new("xmpp.muc.joined").unwrap(),
+                                Channel::new(channels::XMPP_MUC_JOINED).unwrap(),
                                 EventSource::Xmpp,
                                 EventPayload::MucJoined {
                                     room: room.clone(),
@@ -185,6 +278,47 @@ impl StanzaProcessor for MucProcessor {
     }
 }
 
+const NS_CONFERENCE: &str = "jabber:x:conference";
+
+/// The room invitation a non-groupchat message carries: a XEP-0249 direct
+/// invite from a contact, or a XEP-0045 invite the room relays for one of
+/// its occupants.
+pub(crate) fn parse_invite(msg: &Message) -> Option<MucInvite> {
+    let sender = msg.from.as_ref()?.to_bare();
+
+    if let Some(direct) = msg.payloads.iter().find(|el| el.is("x", NS_CONFERENCE)) {
+        let room = direct.attr("jid")?.parse::<Jid>().ok()?.to_bare();
+        return Some(MucInvite {
+            room: room.to_string(),
+            from: sender.to_string(),
+            reason: direct
+                .attr("reason")
+                .filter(|reason| !reason.is_empty())
+                .map(str::to_string),
+            password: direct.attr("password").map(str::to_string),
+            mediated: false,
+            received_at: Utc::now(),
+        });
+    }
+
+    let muc_user = msg.payloads.iter().find(|el| el.is("x", ns::MUC_USER))?;
+    let invite = muc_user.get_child("invite", ns::MUC_USER)?;
+    let inviter = invite.attr("from")?.parse::<Jid>().ok()?.to_bare();
+    Some(MucInvite {
+        room: sender.to_string(),
+        from: inviter.to_string(),
+        reason: invite
+            .get_child("reason", ns::MUC_USER)
+            .map(|reason| reason.text())
+            .filter(|reason| !reason.is_empty()),
+        password: muc_user
+            .get_child("password", ns::MUC_USER)
+            .map(|password| password.text()),
+        mediated: true,
+        received_at: Utc::now(),
+    })
+}
+
 fn emit_occupant_changed(
     room: &str,
     nick: &str,
@@ -214,7 +348,7 @@ fn emit_occupant_changed(
         #[cfg(feature = "native")]
         {
             let _ = event_bus.publish(Event::new(
-                Channel::new("xmpp.muc.occupant.changed").unwrap(),
+                Channel::new(channels::XMPP_MUC_OCCUPANT_CHANGED).unwrap(),
                 EventSource::Xmpp,
                 EventPayload::MucOccupantChanged {
                     room: room.to_string(),
@@ -256,6 +390,31 @@ mod tests {
         assert_eq!(msg.type_, MessageType::Groupchat);
     }
 
+    #[test]
+    fn parses_moderation_by_the_room() {
+        let stanza = Stanza::parse(
+            b"<message xmlns='jabber:client' type='groupchat' \
+              from='room@conference.example.com' to='bob@example.com' id='mod-1'>\
+              <retract xmlns='urn:xmpp:message-retract:1' id='stanza-1'>\
+                <moderated xmlns='urn:xmpp:message-moderate:1' \
+                  by='room@conference.example.com/macbeth'/>\
+                <reason>Spam</reason>\
+              </retract>\
+            </message>",
+        )
+        .unwrap();
+        let Stanza::Message(msg) = &stanza else {
+            panic!("expected message");
+        };
+        let retraction = parse_retraction(&msg.payloads).unwrap();
+        assert_eq!(retraction.id, "stanza-1");
+        assert_eq!(
+            retraction.moderated_by.as_deref(),
+            Some("room@conference.example.com/macbeth")
+        );
+        assert_eq!(retraction.reason.as_deref(), Some("Spam"));
+    }
+
     #[test]
     fn parses_muc_subject() {
         let stanza = Stanza::parse(MUC_SUBJECT_XML).unwrap();
@@ -265,6 +424,102 @@ mod tests {
         assert!(msg.get_best_subject(vec![]).is_some());
     }
 
+    #[test]
+    fn parses_direct_and_mediated_invites() {
+        let stanza = Stanza::parse(
+            b"<message xmlns='jabber:client' from='crone1@shakespeare.lit/desktop' \
+              to='hecate@shakespeare.lit'>\
+              <x xmlns='jabber:x:conference' jid='darkcave@macbeth.shakespeare.lit' \
+                password='cauldronburn' reason='Hey Hecate, this is the place for all good witches!'/>\
+            </message>",
+        )
+        .unwrap();
+        let Stanza::Message(msg) = &stanza else {
+            panic!("expected message");
+        };
+        let invite = parse_invite(msg).unwrap();
+        assert_eq!(invite.room, "darkcave@macbeth.shakespeare.lit");
+        assert_eq!(invite.from, "crone1@shakespeare.lit");
+        assert_eq!(invite.password.as_deref(), Some("cauldronburn"));
+        assert!(!invite.mediated);
+
+        let stanza = Stanza::parse(
+            b"<message xmlns='jabber:client' from='coven@chat.shakespeare.lit' \
+              to='hecate@shakespeare.lit' id='nzd143v8'>\
+              <x xmlns='http://jabber.org/protocol/muc#user'>\
+                <invite from='crone1@shakespeare.lit/desktop'>\
+                  <reason>Hey Hecate, this is the place for all good witches!</reason>\
+                </invite>\
+                <password>cauldronburn</password>\
+              </x>\
+            </message>",
+        )
+        .unwrap();
+        let Stanza::Message(msg) = &stanza else {
+            panic!("expected message");
+        };
+        let invite = parse_invite(msg).unwrap();
+        assert_eq!(invite.room, "coven@chat.shakespeare.lit");
+        assert_eq!(invite.from, "crone1@shakespeare.lit");
+        assert_eq!(
+            invite.reason.as_deref(),
+            Some("Hey Hecate, this is the place for all good witches!")
+        );
+        assert_eq!(invite.password.as_deref(), Some("cauldronburn"));
+        assert!(invite.mediated);
+
+        let Stanza::Message(msg) = &Stanza::parse(MUC_MESSAGE_XML).unwrap() else {
+            panic!("expected message");
+        };
+        assert!(parse_invite(msg).is_none());
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn own_rename_is_neither_a_leave_nor_a_join() {
+        use crate::pipeline::StanzaDirection;
+        use waddle_core::event::BroadcastEventBus;
+
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let processor = MucProcessor::new(event_bus.clone());
+        let mut events = event_bus.subscribe("xmpp.muc.**").unwrap();
+        let ctx = ProcessorContext {
+            direction: StanzaDirection::Inbound,
+        };
+        for xml in [
+            "<presence xmlns='jabber:client' type='unavailable' \
+               from='coven@chat.shakespeare.lit/thirdwitch'>\
+               <x xmlns='http://jabber.org/protocol/muc#user'>\
+                 <item affiliation='member' nick='oldhag' role='participant'/>\
+                 <status code='303'/>\
+                 <status code='110'/>\
+               </x>\
+             </presence>",
+            "<presence xmlns='jabber:client' from='coven@chat.shakespeare.lit/oldhag'>\
+               <x xmlns='http://jabber.org/protocol/muc#user'>\
+                 <item affiliation='member' role='participant'/>\
+                 <status code='110'/>\
+               </x>\
+             </presence>",
+        ] {
+            let mut stanza = Stanza::parse(xml.as_bytes()).unwrap();
+            processor.process_inbound(&mut stanza, &ctx);
+        }
+
+        assert!(matches!(
+            events.recv().await.unwrap().payload,
+            EventPayload::MucOccupantRenamed { room, old_nick, new_nick, own: true }
+                if room == "coven@chat.shakespeare.lit"
+                    && old_nick == "thirdwitch"
+                    && new_nick == "oldhag"
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap().payload,
+            EventPayload::MucOccupantChanged { occupant, .. } if occupant.nick == "oldhag"
+        ));
+        assert!(processor.renaming.lock().unwrap().is_empty());
+    }
+
     #[test]
     fn parses_muc_presence() {
         let stanza = Stanza::parse(MUC_PRESENCE_XML).unwrap();
@@ -0,0 +1,314 @@
+use std::sync::Arc;
+
+use tracing::{debug, warn};
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::legacy_omemo;
+use xmpp_parsers::message::MessageType;
+use xmpp_parsers::minidom::Element;
+use xmpp_parsers::pubsub::{self, PubSub};
+
+use waddle_core::event::{
+    Channel, Event, EventPayload, EventSource, MessageType as CoreMessageType, channels,
+};
+
+#[cfg(feature = "native")]
+use waddle_core::event::EventBus;
+
+use crate::omemo::{
+    DEVICE_LIST_NODE, bundle_from_element, bundle_node_device, device_list_from_element,
+    envelope_from_encrypted,
+};
+use crate::pipeline::{ProcessorContext, ProcessorResult, StanzaProcessor};
+use crate::stanza::Stanza;
+
+/// Picks OMEMO device lists and bundles out of PEP notifications and fetch
+/// results, and `<encrypted/>` messages out of the inbound stream. Runs
+/// before the message processor, which would otherwise surface the
+/// fallback body of encrypted messages as plaintext.
+pub struct OmemoProcessor {
+    #[cfg(feature = "native")]
+    event_bus: Arc<dyn EventBus>,
+}
+
+impl OmemoProcessor {
+    #[cfg(feature = "native")]
+    pub fn new(event_bus: Arc<dyn EventBus>) -> Self {
+        Self { event_bus }
+    }
+
+    /// Publish the event for a device list or bundle item of `jid`'s `node`.
+    /// `jid` is empty for our own nodes when the server omits `from`.
+    fn process_item(&self, jid: &str, node: &str, payload: Option<Element>) {
+        let Some(payload) = payload else {
+            return;
+        };
+        let (channel, payload) = if node == DEVICE_LIST_NODE {
+            match device_list_from_element(payload) {
+                Ok(devices) => (
+                    channels::XMPP_OMEMO_DEVICELIST_RECEIVED,
+                    EventPayload::OmemoDeviceListReceived {
+                        jid: jid.to_string(),
+                        devices,
+                    },
+                ),
+                Err(e) => {
+                    warn!(jid, error = %e, "ignoring OMEMO device list");
+                    return;
+                }
+            }
+        } else if let Some(device_id) = bundle_node_device(node) {
+            match bundle_from_element(payload) {
+                Ok(bundle) => (
+                    channels::XMPP_OMEMO_BUNDLE_RECEIVED,
+                    EventPayload::OmemoBundleReceived {
+                        jid: jid.to_string(),
+                        device_id,
+                        bundle,
+                    },
+                ),
+                Err(e) => {
+                    warn!(jid, device_id, error = %e, "ignoring OMEMO bundle");
+                    return;
+                }
+            }
+        } else {
+            return;
+        };
+        debug!(jid, node, "OMEMO PEP item received");
+
+        #[cfg(feature = "native")]
+        {
+            let _ = self.event_bus.publish(Event::new(
+                Channel::new(channel).unwrap(),
+                EventSource::Xmpp,
+                payload,
+            ));
+        }
+    }
+}
+
+impl StanzaProcessor for OmemoProcessor {
+    fn name(&self) -> &str {
+        "omemo"
+    }
+
+    fn process_inbound(&self, stanza: &mut Stanza, _ctx: &ProcessorContext) -> ProcessorResult {
+        match stanza {
+            Stanza::Message(msg) => {
+                let from = msg
+                    .from
+                    .as_ref()
+                    .map(|j| j.to_bare().to_string())
+                    .unwrap_or_default();
+
+                if let Some(event) = msg
+                    .payloads
+                    .iter()
+                    .find_map(|el| pubsub::Event::try_from(el.clone()).ok())
+                    && let pubsub::event::Payload::Items {
+                        node, published, ..
+                    } = event.payload
+                {
+                    for item in published {
+                        self.process_item(&from, &node.0, item.payload);
+                    }
+                    return ProcessorResult::Continue;
+                }
+
+                let Some(encrypted) = msg
+                    .payloads
+                    .iter()
+                    .find_map(|el| legacy_omemo::Encrypted::try_from(el.clone()).ok())
+                else {
+                    return ProcessorResult::Continue;
+                };
+                let id = msg.id.as_ref().map(|id| id.0.clone()).unwrap_or_default();
+                debug!(from = %from, id = %id, "OMEMO message received");
+
+                #[cfg(feature = "native")]
+                {
+                    let _ = self.event_bus.publish(Event::new(
+                        Channel::new(channels::XMPP_OMEMO_MESSAGE_RECEIVED).unwrap(),
+                        EventSource::Xmpp,
+                        EventPayload::OmemoMessageReceived {
+                            id,
+                            from,
+                            to: msg
+                                .to
+                                .as_ref()
+                                .map(|j| j.to_bare().to_string())
+                                .unwrap_or_default(),
+                            message_type: match msg.type_ {
+                                MessageType::Chat => CoreMessageType::Chat,
+                                MessageType::Groupchat => CoreMessageType::Groupchat,
+                                MessageType::Normal => CoreMessageType::Normal,
+                                MessageType::Headline => CoreMessageType::Headline,
+                                MessageType::Error => CoreMessageType::Error,
+                            },
+                            envelope: envelope_from_encrypted(encrypted),
+                        },
+                    ));
+                }
+
+                // The body only says the message is encrypted.
+                msg.bodies.clear();
+            }
+            Stanza::Iq(iq) => {
+                if let Iq::Result {
+                    from,
+                    payload: Some(payload),
+                    ..
+                } = iq.as_ref()
+                    && let Ok(PubSub::Items(items)) = PubSub::try_from(payload.clone())
+                {
+                    let from = from
+                        .as_ref()
+                        .map(|j| j.to_bare().to_string())
+                        .unwrap_or_default();
+                    for item in items.items {
+                        self.process_item(&from, &items.node.0, item.payload.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        ProcessorResult::Continue
+    }
+
+    fn process_outbound(&self, _stanza: &mut Stanza, _ctx: &ProcessorContext) -> ProcessorResult {
+        ProcessorResult::Continue
+    }
+
+    fn priority(&self) -> i32 {
+        5
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENCRYPTED_XML: &[u8] = b"<message xmlns='jabber:client' type='chat' id='m1' \
+        from='bob@example.com/phone' to='alice@example.com'>\
+        <body>I sent you an OMEMO encrypted message.</body>\
+        <encrypted xmlns='eu.siacs.conversations.axolotl'>\
+            <header sid='27183'>\
+                <key rid='31415' prekey='true'>AQID</key>\
+                <iv>AAAAAAAAAAAAAAAA</iv>\
+            </header>\
+            <payload>BAU=</payload>\
+        </encrypted>\
+    </message>";
+
+    const DEVICE_LIST_EVENT_XML: &[u8] = b"<message xmlns='jabber:client' \
+        from='bob@example.com' to='alice@example.com/desktop'>\
+        <event xmlns='http://jabber.org/protocol/pubsub#event'>\
+            <items node='eu.siacs.conversations.axolotl.devicelist'>\
+                <item id='current'>\
+                    <list xmlns='eu.siacs.conversations.axolotl'>\
+                        <device id='12345'/><device id='4223'/>\
+                    </list>\
+                </item>\
+            </items>\
+        </event>\
+    </message>";
+
+    const BUNDLE_RESULT_XML: &[u8] = b"<iq xmlns='jabber:client' type='result' id='b1' \
+        from='bob@example.com'>\
+        <pubsub xmlns='http://jabber.org/protocol/pubsub'>\
+            <items node='eu.siacs.conversations.axolotl.bundles:4223'>\
+                <item id='current'>\
+                    <bundle xmlns='eu.siacs.conversations.axolotl'>\
+                        <signedPreKeyPublic signedPreKeyId='1'>BQE=</signedPreKeyPublic>\
+                        <signedPreKeySignature>AAE=</signedPreKeySignature>\
+                        <identityKey>BQI=</identityKey>\
+                        <prekeys><preKeyPublic preKeyId='1'>BQM=</preKeyPublic></prekeys>\
+                    </bundle>\
+                </item>\
+            </items>\
+        </pubsub>\
+    </iq>";
+
+    fn inbound() -> ProcessorContext {
+        ProcessorContext {
+            direction: crate::pipeline::StanzaDirection::Inbound,
+        }
+    }
+
+    #[cfg(feature = "native")]
+    fn processor() -> (OmemoProcessor, waddle_core::event::EventSubscription) {
+        let event_bus: Arc<dyn EventBus> =
+            Arc::new(waddle_core::event::BroadcastEventBus::default());
+        let sub = event_bus.subscribe("xmpp.omemo.**").unwrap();
+        (OmemoProcessor::new(event_bus), sub)
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn encrypted_message_is_published_and_fallback_body_removed() {
+        let (processor, mut sub) = processor();
+        let mut stanza = Stanza::parse(ENCRYPTED_XML).unwrap();
+        processor.process_inbound(&mut stanza, &inbound());
+
+        let Stanza::Message(msg) = &stanza else {
+            panic!("expected message");
+        };
+        assert!(msg.bodies.is_empty());
+
+        let event = sub.recv().await.unwrap();
+        match event.payload {
+            EventPayload::OmemoMessageReceived {
+                id, from, envelope, ..
+            } => {
+                assert_eq!(id, "m1");
+                assert_eq!(from, "bob@example.com");
+                assert_eq!(envelope.sender_device_id, 27183);
+                assert_eq!(envelope.keys[0].recipient_device_id, 31415);
+                assert!(envelope.keys[0].prekey);
+                assert_eq!(envelope.payload, Some(vec![4, 5]));
+            }
+            other => panic!("unexpected payload: {other:?}"),
+        }
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn device_list_notification_is_published() {
+        let (processor, mut sub) = processor();
+        let mut stanza = Stanza::parse(DEVICE_LIST_EVENT_XML).unwrap();
+        processor.process_inbound(&mut stanza, &inbound());
+
+        let event = sub.recv().await.unwrap();
+        match event.payload {
+            EventPayload::OmemoDeviceListReceived { jid, devices } => {
+                assert_eq!(jid, "bob@example.com");
+                assert_eq!(devices, vec![12345, 4223]);
+            }
+            other => panic!("unexpected payload: {other:?}"),
+        }
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn fetched_bundle_is_published() {
+        let (processor, mut sub) = processor();
+        let mut stanza = Stanza::parse(BUNDLE_RESULT_XML).unwrap();
+        processor.process_inbound(&mut stanza, &inbound());
+
+        let event = sub.recv().await.unwrap();
+        match event.payload {
+            EventPayload::OmemoBundleReceived {
+                jid,
+                device_id,
+                bundle,
+            } => {
+                assert_eq!(jid, "bob@example.com");
+                assert_eq!(device_id, 4223);
+                assert_eq!(bundle.identity_key, vec![5, 2]);
+                assert_eq!(bundle.pre_keys.len(), 1);
+            }
+            other => panic!("unexpected payload: {other:?}"),
+        }
+    }
+}
@@ -1,11 +1,14 @@
 use std::sync::Arc;
 
+use chrono::{DateTime, Utc};
 use tracing::debug;
+use xmpp_parsers::idle::Idle;
 use xmpp_parsers::muc::Muc;
+use xmpp_parsers::ns;
 use xmpp_parsers::presence::{Presence, Priority, Show, Type as PresenceType};
 
 use waddle_core::event::{
-    Channel, Event, EventPayload, EventSource, PresenceShow as CorePresenceShow,
+    Channel, Event, EventPayload, EventSource, PresenceShow as CorePresenceShow, channels,
 };
 
 #[cfg(feature = "native")]
@@ -51,7 +54,7 @@ impl StanzaProcessor for PresenceProcessor {
                 #[cfg(feature = "native")]
                 {
                     let _ = self.event_bus.publish(Event::new(
-                        Channel::new("xmpp.subscription.request").unwrap(),
+                        Channel::new(channels::XMPP_SUBSCRIPTION_REQUEST).unwrap(),
                         EventSource::Xmpp,
                         EventPayload::SubscriptionRequest { from },
                     ));
@@ -67,7 +70,7 @@ impl StanzaProcessor for PresenceProcessor {
                 #[cfg(feature = "native")]
                 {
                     let _ = self.event_bus.publish(Event::new(
-                        Channel::new("xmpp.subscription.approved").unwrap(),
+                        Channel::new(channels::XMPP_SUBSCRIPTION_APPROVED).unwrap(),
                         EventSource::Xmpp,
                         EventPayload::SubscriptionApproved { jid },
                     ));
@@ -83,7 +86,7 @@ impl StanzaProcessor for PresenceProcessor {
                 #[cfg(feature = "native")]
                 {
                     let _ = self.event_bus.publish(Event::new(
-                        Channel::new("xmpp.subscription.revoked").unwrap(),
+                        Channel::new(channels::XMPP_SUBSCRIPTION_REVOKED).unwrap(),
                         EventSource::Xmpp,
                         EventPayload::SubscriptionRevoked { jid },
                     ));
@@ -98,19 +101,29 @@ impl StanzaProcessor for PresenceProcessor {
                 let show = convert_show(presence);
                 let status = presence.statuses.get("").cloned();
                 let priority = extract_priority(&presence.priority);
-                debug!(jid = %jid, ?show, priority, "presence changed");
+                let idle_since = extract_idle_since(presence);
+                let caps = extract_caps(presence);
+                debug!(jid = %jid, ?show, priority, ?idle_since, ?caps, "presence changed");
                 #[cfg(feature = "native")]
                 {
                     let _ = self.event_bus.publish(Event::new(
-                        Channel::new("xmpp.presence.changed").unwrap(),
+                        Channel::new(channels::XMPP_PRESENCE_CHANGED).unwrap(),
                         EventSource::Xmpp,
                         EventPayload::PresenceChanged {
-                            jid,
+                            jid: jid.clone(),
                             show,
                             status,
                             priority,
+                            idle_since,
                         },
                     ));
+                    if let Some((node, ver)) = caps {
+                        let _ = self.event_bus.publish(Event::new(
+                            Channel::new(channels::XMPP_CAPS_RECEIVED).unwrap(),
+                            EventSource::Xmpp,
+                            EventPayload::EntityCapsReceived { jid, node, ver },
+                        ));
+                    }
                 }
             }
             _ => {}
@@ -157,6 +170,31 @@ fn extract_priority(priority: &Priority) -> i8 {
     el.text().parse::<i8>().unwrap_or(0)
 }
 
+fn extract_idle_since(presence: &Presence) -> Option<DateTime<Utc>> {
+    presence
+        .payloads
+        .iter()
+        .find_map(|el| Idle::try_from(el.clone()).ok())
+        .map(|idle| idle.since.0.with_timezone(&Utc))
+}
+
+/// XEP-0115 `node` and `ver` of an available presence. The hash is passed
+/// through as advertised and not verified against the disco#info result.
+fn extract_caps(presence: &Presence) -> Option<(String, String)> {
+    if presence.type_ == PresenceType::Unavailable {
+        return None;
+    }
+    let caps = presence.payloads.iter().find(|el| el.is("c", ns::CAPS))?;
+    // Only SHA-1 strings can be checked against the disco#info they stand for.
+    if caps.attr("hash") != Some("sha-1") {
+        return None;
+    }
+    Some((
+        caps.attr("node")?.to_string(),
+        caps.attr("ver")?.to_string(),
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -172,6 +210,16 @@ mod tests {
     const UNAVAILABLE_XML: &[u8] = b"<presence xmlns='jabber:client' \
         from='alice@example.com/mobile' type='unavailable'/>";
 
+    const IDLE_XML: &[u8] = b"<presence xmlns='jabber:client' from='alice@example.com/desktop'>\
+        <show>away</show>\
+        <idle xmlns='urn:xmpp:idle:1' since='2024-05-01T10:00:00Z'/>\
+    </presence>";
+
+    const CAPS_XML: &[u8] = b"<presence xmlns='jabber:client' from='alice@example.com/desktop'>\
+        <c xmlns='http://jabber.org/protocol/caps' hash='sha-1' \
+            node='https://example.org/client' ver='QgayPKawpkPSDYmwT/WM94uAlu0='/>\
+    </presence>";
+
     const SUBSCRIBE_XML: &[u8] = b"<presence xmlns='jabber:client' \
         from='carol@example.com' type='subscribe'/>";
 
@@ -214,4 +262,40 @@ mod tests {
         };
         assert_eq!(p.type_, PresenceType::Subscribe);
     }
+
+    #[test]
+    fn extracts_idle_since() {
+        let stanza = Stanza::parse(IDLE_XML).unwrap();
+        let Stanza::Presence(p) = &stanza else {
+            panic!("expected presence");
+        };
+        let since = extract_idle_since(p).expect("idle element should be parsed");
+        assert_eq!(since.to_rfc3339(), "2024-05-01T10:00:00+00:00");
+    }
+
+    #[test]
+    fn presence_without_idle_has_no_idle_since() {
+        let stanza = Stanza::parse(AWAY_XML).unwrap();
+        let Stanza::Presence(p) = &stanza else {
+            panic!("expected presence");
+        };
+        assert!(extract_idle_since(p).is_none());
+    }
+
+    #[test]
+    fn extracts_entity_caps() {
+        let stanza = Stanza::parse(CAPS_XML).unwrap();
+        let Stanza::Presence(p) = &stanza else {
+            panic!("expected presence");
+        };
+        let (node, ver) = extract_caps(p).expect("caps element should be parsed");
+        assert_eq!(node, "https://example.org/client");
+        assert_eq!(ver, "QgayPKawpkPSDYmwT/WM94uAlu0=");
+
+        let stanza = Stanza::parse(AWAY_XML).unwrap();
+        let Stanza::Presence(p) = &stanza else {
+            panic!("expected presence");
+        };
+        assert!(extract_caps(p).is_none());
+    }
 }
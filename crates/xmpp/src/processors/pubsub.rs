@@ -0,0 +1,267 @@
+use tracing::debug;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::minidom::Element;
+
+#[cfg(feature = "native")]
+use std::sync::Arc;
+
+#[cfg(feature = "native")]
+use waddle_core::event::{Channel, Event, EventBus, EventPayload, EventSource, channels};
+
+use crate::pipeline::{ProcessorContext, ProcessorResult, StanzaProcessor};
+use crate::pubsub::{
+    PUBSUB_EVENT_NS, PUBSUB_IQ_ID_PREFIX, config_from_answer, item_from_element, items_from_answer,
+};
+use crate::stanza::Stanza;
+
+/// Reports the answers to `PubSubManager` requests and routes pubsub
+/// notifications (PEP included) onto the bus for the managers of each node.
+pub struct PubSubProcessor {
+    #[cfg(feature = "native")]
+    event_bus: Arc<dyn EventBus>,
+}
+
+impl PubSubProcessor {
+    #[cfg(feature = "native")]
+    pub fn new(event_bus: Arc<dyn EventBus>) -> Self {
+        Self { event_bus }
+    }
+
+    /// Publish the events for the `<event/>` of a notification from `from`.
+    fn process_notification(&self, from: &str, event: &Element) {
+        for child in event.children() {
+            let Some(node) = child.attr("node") else {
+                continue;
+            };
+            match child.name() {
+                "items" => {
+                    let items: Vec<_> = child
+                        .children()
+                        .filter(|item| item.is("item", PUBSUB_EVENT_NS))
+                        .map(item_from_element)
+                        .collect();
+                    let ids: Vec<String> = child
+                        .children()
+                        .filter(|retract| retract.is("retract", PUBSUB_EVENT_NS))
+                        .filter_map(|retract| retract.attr("id").map(String::from))
+                        .collect();
+                    debug!(
+                        from,
+                        node,
+                        published = items.len(),
+                        retracted = ids.len(),
+                        "pubsub notification"
+                    );
+                    #[cfg(feature = "native")]
+                    {
+                        if !items.is_empty() {
+                            self.emit(
+                                channels::XMPP_PUBSUB_ITEMS,
+                                EventPayload::PubSubItemsReceived {
+                                    from: from.to_string(),
+                                    node: node.to_string(),
+                                    items,
+                                },
+                            );
+                        }
+                        if !ids.is_empty() {
+                            self.emit(
+                                channels::XMPP_PUBSUB_RETRACTED,
+                                EventPayload::PubSubItemsRetracted {
+                                    from: from.to_string(),
+                                    node: node.to_string(),
+                                    ids,
+                                },
+                            );
+                        }
+                    }
+                }
+                "delete" => {
+                    debug!(from, node, "pubsub node deleted");
+                    #[cfg(feature = "native")]
+                    self.emit(
+                        channels::XMPP_PUBSUB_NODE_DELETED,
+                        EventPayload::PubSubNodeDeleted {
+                            from: from.to_string(),
+                            node: node.to_string(),
+                        },
+                    );
+                }
+                "purge" => {
+                    debug!(from, node, "pubsub node purged");
+                    #[cfg(feature = "native")]
+                    self.emit(
+                        channels::XMPP_PUBSUB_NODE_PURGED,
+                        EventPayload::PubSubNodePurged {
+                            from: from.to_string(),
+                            node: node.to_string(),
+                        },
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[cfg(feature = "native")]
+    fn emit(&self, channel: &str, payload: EventPayload) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channel).unwrap(),
+            EventSource::Xmpp,
+            payload,
+        ));
+    }
+}
+
+impl StanzaProcessor for PubSubProcessor {
+    fn name(&self) -> &str {
+        "pubsub"
+    }
+
+    fn process_inbound(&self, stanza: &mut Stanza, _ctx: &ProcessorContext) -> ProcessorResult {
+        match stanza {
+            Stanza::Message(msg) => {
+                if let Some(event) = msg
+                    .payloads
+                    .iter()
+                    .find(|payload| payload.is("event", PUBSUB_EVENT_NS))
+                {
+                    let from = msg
+                        .from
+                        .as_ref()
+                        .map(|jid| jid.to_bare().to_string())
+                        .unwrap_or_default();
+                    self.process_notification(&from, event);
+                }
+            }
+            Stanza::Iq(iq) => {
+                let (id, payload, error) = match iq.as_ref() {
+                    Iq::Result { id, payload, .. } => (id, payload.as_ref(), None),
+                    Iq::Error { id, error, .. } => (id, None, Some(error)),
+                    _ => return ProcessorResult::Continue,
+                };
+                if !id.starts_with(PUBSUB_IQ_ID_PREFIX) {
+                    return ProcessorResult::Continue;
+                }
+                debug!(id = %id, refused = error.is_some(), "pubsub request answered");
+
+                #[cfg(feature = "native")]
+                self.emit(
+                    channels::XMPP_PUBSUB_ANSWERED,
+                    EventPayload::PubSubRequestAnswered {
+                        request_id: id.clone(),
+                        error: error.map(|error| {
+                            crate::registration::error_reason(Some(&Element::from(error.clone())))
+                        }),
+                        items: payload.map(items_from_answer).unwrap_or_default(),
+                        config: payload.and_then(config_from_answer),
+                    },
+                );
+            }
+            _ => {}
+        }
+
+        ProcessorResult::Continue
+    }
+
+    fn process_outbound(&self, _stanza: &mut Stanza, _ctx: &ProcessorContext) -> ProcessorResult {
+        ProcessorResult::Continue
+    }
+
+    fn priority(&self) -> i32 {
+        10
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use crate::pipeline::StanzaDirection;
+    use waddle_core::event::BroadcastEventBus;
+
+    fn receive(processor: &PubSubProcessor, xml: &str) {
+        let mut stanza = Stanza::parse(xml.as_bytes()).unwrap();
+        let ctx = ProcessorContext {
+            direction: StanzaDirection::Inbound,
+        };
+        processor.process_inbound(&mut stanza, &ctx);
+    }
+
+    #[tokio::test]
+    async fn routes_notifications_by_node() {
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let processor = PubSubProcessor::new(event_bus.clone());
+        let mut events = event_bus.subscribe("xmpp.pubsub.**").unwrap();
+
+        receive(
+            &processor,
+            "<message xmlns='jabber:client' from='bob@example.com' to='alice@example.com/desktop'>\
+                <event xmlns='http://jabber.org/protocol/pubsub#event'>\
+                    <items node='urn:xmpp:avatar:metadata'>\
+                        <item id='111f4b3c' publisher='bob@example.com'>\
+                            <metadata xmlns='urn:xmpp:avatar:metadata'/>\
+                        </item>\
+                        <retract id='0a1b2c3d'/>\
+                    </items>\
+                </event>\
+            </message>",
+        );
+        receive(
+            &processor,
+            "<message xmlns='jabber:client' from='bob@example.com'>\
+                <event xmlns='http://jabber.org/protocol/pubsub#event'>\
+                    <delete node='urn:xmpp:bookmarks:1'/>\
+                </event>\
+            </message>",
+        );
+
+        match events.recv().await.unwrap().payload {
+            EventPayload::PubSubItemsReceived { from, node, items } => {
+                assert_eq!(from, "bob@example.com");
+                assert_eq!(node, "urn:xmpp:avatar:metadata");
+                assert_eq!(items[0].id.as_deref(), Some("111f4b3c"));
+                assert_eq!(items[0].publisher.as_deref(), Some("bob@example.com"));
+                assert!(items[0].payload.as_deref().unwrap().contains("metadata"));
+            }
+            other => panic!("unexpected payload: {other:?}"),
+        }
+        assert!(matches!(
+            events.recv().await.unwrap().payload,
+            EventPayload::PubSubItemsRetracted { ids, .. } if ids == ["0a1b2c3d"]
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap().payload,
+            EventPayload::PubSubNodeDeleted { node, .. } if node == "urn:xmpp:bookmarks:1"
+        ));
+    }
+
+    #[tokio::test]
+    async fn publishes_answers_to_pubsub_requests_only() {
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let processor = PubSubProcessor::new(event_bus.clone());
+        let mut answers = event_bus.subscribe("xmpp.pubsub.answered").unwrap();
+
+        receive(
+            &processor,
+            "<iq xmlns='jabber:client' type='result' id='account-1'/>",
+        );
+        receive(
+            &processor,
+            "<iq xmlns='jabber:client' type='error' id='pubsub-2'>\
+                <error type='auth'>\
+                    <forbidden xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/>\
+                </error>\
+            </iq>",
+        );
+
+        match answers.recv().await.unwrap().payload {
+            EventPayload::PubSubRequestAnswered {
+                request_id, error, ..
+            } => {
+                assert_eq!(request_id, "pubsub-2");
+                assert_eq!(error.as_deref(), Some("forbidden"));
+            }
+            other => panic!("unexpected payload: {other:?}"),
+        }
+    }
+}
@@ -1,10 +1,12 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
 use tracing::{debug, warn};
 use xmpp_parsers::{iq::Iq, ns, roster::Roster};
 
 use waddle_core::event::{
     Channel, Event, EventPayload, EventSource, RosterItem, Subscription as CoreSubscription,
+    channels,
 };
 
 #[cfg(feature = "native")]
@@ -14,6 +16,10 @@ use crate::pipeline::{ProcessorContext, ProcessorResult, StanzaProcessor};
 use crate::stanza::Stanza;
 
 pub struct RosterProcessor {
+    /// Outbound roster set ids awaiting a result, mapped to the contact JID.
+    pending_sets: Mutex<HashMap<String, String>>,
+    /// Id of the roster get awaiting a result.
+    pending_fetch: Mutex<Option<String>>,
     #[cfg(feature = "native")]
     event_bus: Arc<dyn EventBus>,
 }
@@ -21,7 +27,11 @@ pub struct RosterProcessor {
 impl RosterProcessor {
     #[cfg(feature = "native")]
     pub fn new(event_bus: Arc<dyn EventBus>) -> Self {
-        Self { event_bus }
+        Self {
+            pending_sets: Mutex::new(HashMap::new()),
+            pending_fetch: Mutex::new(None),
+            event_bus,
+        }
     }
 }
 
@@ -36,6 +46,40 @@ impl StanzaProcessor for RosterProcessor {
         };
 
         match iq.as_ref() {
+            Iq::Result {
+                id, payload: None, ..
+            } => {
+                let mut pending_fetch = self.pending_fetch.lock().unwrap();
+                if pending_fetch.as_ref() == Some(id) {
+                    *pending_fetch = None;
+                    debug!("roster unchanged since the cached version");
+                    #[cfg(feature = "native")]
+                    {
+                        let _ = self.event_bus.publish(Event::new(
+                            Channel::new(channels::XMPP_ROSTER_UNCHANGED).unwrap(),
+                            EventSource::Xmpp,
+                            EventPayload::RosterUnchanged,
+                        ));
+                    }
+                } else if let Some(jid) = self.pending_sets.lock().unwrap().remove(id) {
+                    debug!(jid = %jid, "roster set acknowledged");
+                }
+            }
+            Iq::Error { id, error, .. } => {
+                let Some(jid) = self.pending_sets.lock().unwrap().remove(id) else {
+                    return ProcessorResult::Continue;
+                };
+                let reason = format!("{:?}", error.defined_condition);
+                warn!(jid = %jid, reason = %reason, "roster set rejected by server");
+                #[cfg(feature = "native")]
+                {
+                    let _ = self.event_bus.publish(Event::new(
+                        Channel::new(channels::XMPP_ROSTER_SET_FAILED).unwrap(),
+                        EventSource::Xmpp,
+                        EventPayload::RosterSetFailed { jid, reason },
+                    ));
+                }
+            }
             Iq::Result {
                 payload: Some(payload),
                 ..
@@ -49,13 +93,15 @@ impl StanzaProcessor for RosterProcessor {
                 };
                 let items: Vec<RosterItem> = roster.items.iter().map(convert_roster_item).collect();
                 debug!(count = items.len(), "roster result received");
+                *self.pending_fetch.lock().unwrap() = None;
                 #[cfg(feature = "native")]
                 {
                     let _ = self.event_bus.publish(Event::new(
-                        Channel::new("xmpp.roster.received").unwrap(),
+                        Channel::new(channels::XMPP_ROSTER_RECEIVED).unwrap(),
                         EventSource::Xmpp,
                         EventPayload::RosterReceived { items },
                     ));
+                    self.publish_version(roster.ver);
                 }
             }
             Iq::Set { payload, .. } => {
@@ -76,7 +122,7 @@ impl StanzaProcessor for RosterProcessor {
                         #[cfg(feature = "native")]
                         {
                             let _ = self.event_bus.publish(Event::new(
-                                Channel::new("xmpp.roster.removed").unwrap(),
+                                Channel::new(channels::XMPP_ROSTER_REMOVED).unwrap(),
                                 EventSource::Xmpp,
                                 EventPayload::RosterRemoved {
                                     jid: item.jid.to_string(),
@@ -88,13 +134,17 @@ impl StanzaProcessor for RosterProcessor {
                         #[cfg(feature = "native")]
                         {
                             let _ = self.event_bus.publish(Event::new(
-                                Channel::new("xmpp.roster.updated").unwrap(),
+                                Channel::new(channels::XMPP_ROSTER_UPDATED).unwrap(),
                                 EventSource::Xmpp,
                                 EventPayload::RosterUpdated { item: core_item },
                             ));
                         }
                     }
                 }
+                #[cfg(feature = "native")]
+                if roster.ver.is_some() {
+                    self.publish_version(roster.ver);
+                }
             }
             _ => {}
         }
@@ -102,7 +152,26 @@ impl StanzaProcessor for RosterProcessor {
         ProcessorResult::Continue
     }
 
-    fn process_outbound(&self, _stanza: &mut Stanza, _ctx: &ProcessorContext) -> ProcessorResult {
+    fn process_outbound(&self, stanza: &mut Stanza, _ctx: &ProcessorContext) -> ProcessorResult {
+        let Stanza::Iq(iq) = stanza else {
+            return ProcessorResult::Continue;
+        };
+        let (Iq::Get { id, payload, .. } | Iq::Set { id, payload, .. }) = iq.as_ref() else {
+            return ProcessorResult::Continue;
+        };
+        if !payload.is("query", ns::ROSTER) {
+            return ProcessorResult::Continue;
+        }
+        if matches!(iq.as_ref(), Iq::Get { .. }) {
+            *self.pending_fetch.lock().unwrap() = Some(id.clone());
+        } else if let Ok(roster) = Roster::try_from(payload.clone())
+            && let Some(item) = roster.items.first()
+        {
+            self.pending_sets
+                .lock()
+                .unwrap()
+                .insert(id.clone(), item.jid.to_string());
+        }
         ProcessorResult::Continue
     }
 
@@ -111,6 +180,21 @@ impl StanzaProcessor for RosterProcessor {
     }
 }
 
+#[cfg(feature = "native")]
+impl RosterProcessor {
+    /// Announce the roster version a full roster or push brought the cache
+    /// to. An empty version counts as none.
+    fn publish_version(&self, ver: Option<String>) {
+        let _ = self.event_bus.publish(Event::new(
+            Channel::new(channels::XMPP_ROSTER_VERSION_CHANGED).unwrap(),
+            EventSource::Xmpp,
+            EventPayload::RosterVersionChanged {
+                ver: ver.filter(|ver| !ver.is_empty()),
+            },
+        ));
+    }
+}
+
 fn convert_roster_item(item: &xmpp_parsers::roster::Item) -> RosterItem {
     RosterItem {
         jid: item.jid.to_string(),
@@ -180,6 +264,141 @@ mod tests {
         assert!(matches!(stanza, Stanza::Iq(_)));
     }
 
+    #[cfg(feature = "native")]
+    const ROSTER_SET_XML: &[u8] = b"<iq xmlns='jabber:client' type='set' id='set-1'>\
+        <query xmlns='jabber:iq:roster'>\
+            <item jid='erin@example.com' name='Erin'/>\
+        </query>\
+    </iq>";
+
+    #[cfg(feature = "native")]
+    const ROSTER_SET_ERROR_XML: &[u8] = b"<iq xmlns='jabber:client' type='error' id='set-1'>\
+        <error type='cancel'>\
+            <not-allowed xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/>\
+        </error>\
+    </iq>";
+
+    #[cfg(feature = "native")]
+    const ROSTER_SET_RESULT_XML: &[u8] = b"<iq xmlns='jabber:client' type='result' id='set-1'/>";
+
+    #[cfg(feature = "native")]
+    fn processor() -> (RosterProcessor, Arc<dyn EventBus>) {
+        let event_bus: Arc<dyn EventBus> =
+            Arc::new(waddle_core::event::BroadcastEventBus::default());
+        (RosterProcessor::new(event_bus.clone()), event_bus)
+    }
+
+    #[cfg(feature = "native")]
+    fn outbound_ctx() -> ProcessorContext {
+        ProcessorContext {
+            direction: crate::pipeline::StanzaDirection::Outbound,
+        }
+    }
+
+    #[cfg(feature = "native")]
+    fn inbound_ctx() -> ProcessorContext {
+        ProcessorContext {
+            direction: crate::pipeline::StanzaDirection::Inbound,
+        }
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn rejected_roster_set_emits_failure_for_contact() {
+        let (processor, event_bus) = processor();
+        let mut sub = event_bus.subscribe("xmpp.roster.set_failed").unwrap();
+
+        let mut set = Stanza::parse(ROSTER_SET_XML).unwrap();
+        processor.process_outbound(&mut set, &outbound_ctx());
+        let mut error = Stanza::parse(ROSTER_SET_ERROR_XML).unwrap();
+        processor.process_inbound(&mut error, &inbound_ctx());
+
+        let event = sub.recv().await.unwrap();
+        match event.payload {
+            EventPayload::RosterSetFailed { jid, reason } => {
+                assert_eq!(jid, "erin@example.com");
+                assert_eq!(reason, "NotAllowed");
+            }
+            other => panic!("unexpected payload: {other:?}"),
+        }
+        assert!(processor.pending_sets.lock().unwrap().is_empty());
+    }
+
+    #[cfg(feature = "native")]
+    #[test]
+    fn acknowledged_roster_set_is_forgotten() {
+        let (processor, _event_bus) = processor();
+
+        let mut set = Stanza::parse(ROSTER_SET_XML).unwrap();
+        processor.process_outbound(&mut set, &outbound_ctx());
+        assert_eq!(processor.pending_sets.lock().unwrap().len(), 1);
+
+        let mut result = Stanza::parse(ROSTER_SET_RESULT_XML).unwrap();
+        processor.process_inbound(&mut result, &inbound_ctx());
+        assert!(processor.pending_sets.lock().unwrap().is_empty());
+    }
+
+    #[cfg(feature = "native")]
+    const ROSTER_GET_XML: &[u8] = b"<iq xmlns='jabber:client' type='get' id='get-1'>\
+        <query xmlns='jabber:iq:roster' ver='ver14'/>\
+    </iq>";
+
+    #[cfg(feature = "native")]
+    const ROSTER_GET_RESULT_XML: &[u8] = b"<iq xmlns='jabber:client' type='result' id='get-1'/>";
+
+    #[cfg(feature = "native")]
+    const VERSIONED_PUSH_XML: &[u8] = b"<iq xmlns='jabber:client' type='set' id='push-3'>\
+        <query xmlns='jabber:iq:roster' ver='ver15'>\
+            <item jid='carol@example.com' name='Carol' subscription='both'/>\
+        </query>\
+    </iq>";
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn empty_fetch_result_reports_unchanged_roster_and_pushes_carry_version() {
+        let (processor, event_bus) = processor();
+        let mut sub = event_bus.subscribe("xmpp.roster.**").unwrap();
+
+        let mut get = Stanza::parse(ROSTER_GET_XML).unwrap();
+        processor.process_outbound(&mut get, &outbound_ctx());
+        let mut result = Stanza::parse(ROSTER_GET_RESULT_XML).unwrap();
+        processor.process_inbound(&mut result, &inbound_ctx());
+        assert!(matches!(
+            sub.recv().await.unwrap().payload,
+            EventPayload::RosterUnchanged
+        ));
+
+        let mut push = Stanza::parse(VERSIONED_PUSH_XML).unwrap();
+        processor.process_inbound(&mut push, &inbound_ctx());
+        assert!(matches!(
+            sub.recv().await.unwrap().payload,
+            EventPayload::RosterUpdated { item } if item.jid == "carol@example.com"
+        ));
+        assert!(matches!(
+            sub.recv().await.unwrap().payload,
+            EventPayload::RosterVersionChanged { ver: Some(ver) } if ver == "ver15"
+        ));
+    }
+
+    #[cfg(feature = "native")]
+    #[tokio::test]
+    async fn full_roster_result_reports_its_version() {
+        let (processor, event_bus) = processor();
+        let mut sub = event_bus.subscribe("xmpp.roster.**").unwrap();
+
+        let mut result = Stanza::parse(ROSTER_RESULT_XML).unwrap();
+        processor.process_inbound(&mut result, &inbound_ctx());
+        assert!(matches!(
+            sub.recv().await.unwrap().payload,
+            EventPayload::RosterReceived { items } if items.len() == 2
+        ));
+        // No version: the server does not support roster versioning.
+        assert!(matches!(
+            sub.recv().await.unwrap().payload,
+            EventPayload::RosterVersionChanged { ver: None }
+        ));
+    }
+
     #[test]
     fn roster_processor_parses_remove() {
         let stanza = Stanza::parse(ROSTER_REMOVE_XML).unwrap();
@@ -0,0 +1,75 @@
+//! Our own profile as other clients read it from PEP: the XEP-0172
+//! nickname and the XEP-0292 vCard. The avatar is published separately,
+//! see [`crate::avatar`].
+
+use xmpp_parsers::minidom::Element;
+
+/// PEP node holding the nickname, and the namespace of its payloads.
+pub const NICK_NODE: &str = "http://jabber.org/protocol/nick";
+
+/// PEP node holding the vCard.
+pub const VCARD4_NODE: &str = "urn:xmpp:vcard4";
+
+/// Namespace of the vCard payload.
+pub const VCARD4_NS: &str = "urn:ietf:params:xml:ns:vcard-4.0";
+
+/// Item id of the vCard, which is replaced rather than added to.
+pub const VCARD4_ITEM_ID: &str = "current";
+
+/// Publish options that let anyone read the profile, as contacts and room
+/// occupants look it up without a presence subscription.
+pub const PROFILE_OPTIONS: &[(&str, &str)] = &[
+    ("pubsub#access_model", "open"),
+    ("pubsub#persist_items", "true"),
+];
+
+/// The nickname item. An empty `<nick/>` withdraws a nickname set before.
+pub fn nick_element(nickname: Option<&str>) -> Element {
+    let nick = Element::builder("nick", NICK_NODE);
+    match nickname {
+        Some(nickname) => nick.append(nickname).build(),
+        None => nick.build(),
+    }
+}
+
+/// The vCard item, with the nickname and the free-form `about` text as its
+/// note.
+pub fn vcard4_element(nickname: Option<&str>, about: Option<&str>) -> Element {
+    let property = |name: &str, value: &str| {
+        Element::builder(name, VCARD4_NS)
+            .append(Element::builder("text", VCARD4_NS).append(value).build())
+            .build()
+    };
+    let mut vcard = Element::builder("vcard", VCARD4_NS);
+    if let Some(nickname) = nickname {
+        vcard = vcard.append(property("nickname", nickname));
+    }
+    if let Some(about) = about {
+        vcard = vcard.append(property("note", about));
+    }
+    vcard.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vcard_carries_nickname_and_note() {
+        let vcard = vcard4_element(Some("Alice"), Some("Gone fishing"));
+        let text = |name: &str| {
+            vcard
+                .get_child(name, VCARD4_NS)
+                .and_then(|property| property.get_child("text", VCARD4_NS))
+                .map(Element::text)
+        };
+        assert_eq!(text("nickname").as_deref(), Some("Alice"));
+        assert_eq!(text("note").as_deref(), Some("Gone fishing"));
+
+        let empty = vcard4_element(None, None);
+        assert!(empty.children().next().is_none());
+
+        assert_eq!(nick_element(Some("Alice")).text(), "Alice");
+        assert_eq!(nick_element(None).text(), "");
+    }
+}
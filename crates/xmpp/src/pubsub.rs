@@ -0,0 +1,616 @@
+//! Publish-subscribe (XEP-0060), including the personal eventing service
+//! every account has (PEP, XEP-0163).
+//!
+//! [`PubSubManager`] sends node requests and waits for the service to
+//! answer, so features stored in PEP, such as avatars, bookmarks or OMEMO
+//! device lists, do not each write their own iqs. Notifications reach them
+//! through [`PubSubProcessor`](crate::PubSubProcessor) as `xmpp.pubsub.*`
+//! events, which they filter by node.
+
+use std::str::FromStr;
+
+use waddle_core::event::PubSubItem;
+use waddle_core::form::{DataForm, FORM_TYPE_VAR, FieldType, FormField, FormType};
+use xmpp_parsers::minidom::Element;
+use xmpp_parsers::minidom::rxml::xml_ncname;
+
+use crate::forms::{form_from_element, form_to_element};
+
+#[cfg(feature = "native")]
+pub use native::PubSubManager;
+
+pub const PUBSUB_NS: &str = "http://jabber.org/protocol/pubsub";
+pub const PUBSUB_OWNER_NS: &str = "http://jabber.org/protocol/pubsub#owner";
+pub const PUBSUB_EVENT_NS: &str = "http://jabber.org/protocol/pubsub#event";
+const PUBLISH_OPTIONS_FORM_TYPE: &str = "http://jabber.org/protocol/pubsub#publish-options";
+const NODE_CONFIG_FORM_TYPE: &str = "http://jabber.org/protocol/pubsub#node_config";
+
+/// Ids of the iqs `PubSubManager` sends start with this, so their answers
+/// can be told apart from other iq results.
+pub(crate) const PUBSUB_IQ_ID_PREFIX: &str = "pubsub-";
+
+/// Parse the payload of `item` back into an element.
+pub fn item_payload(item: &PubSubItem) -> Option<Element> {
+    Element::from_str(item.payload.as_deref()?).ok()
+}
+
+/// Read an `<item/>` of any pubsub namespace.
+pub(crate) fn item_from_element(item: &Element) -> PubSubItem {
+    PubSubItem {
+        id: item.attr("id").map(String::from),
+        publisher: item.attr("publisher").map(String::from),
+        payload: item.children().next().map(|payload| {
+            let mut xml = Vec::new();
+            // Writing to a Vec cannot fail.
+            let _ = payload.write_to(&mut xml);
+            String::from_utf8_lossy(&xml).into_owned()
+        }),
+    }
+}
+
+/// The items in the answer to a retrieve or publish request.
+pub(crate) fn items_from_answer(payload: &Element) -> Vec<PubSubItem> {
+    if !payload.is("pubsub", PUBSUB_NS) {
+        return Vec::new();
+    }
+    payload
+        .children()
+        .filter(|child| child.is("items", PUBSUB_NS) || child.is("publish", PUBSUB_NS))
+        .flat_map(|child| child.children().filter(|item| item.is("item", PUBSUB_NS)))
+        .map(item_from_element)
+        .collect()
+}
+
+/// The node configuration form in the answer to a configure request.
+pub(crate) fn config_from_answer(payload: &Element) -> Option<DataForm> {
+    if !payload.is("pubsub", PUBSUB_OWNER_NS) {
+        return None;
+    }
+    let form = payload
+        .get_child("configure", PUBSUB_OWNER_NS)?
+        .get_child("x", "jabber:x:data")?;
+    form_from_element(form.clone()).ok()
+}
+
+fn pubsub(ns: &str, child: Element) -> Element {
+    Element::builder("pubsub", ns).append(child).build()
+}
+
+fn node_element(name: &str, ns: &str, node: &str) -> Element {
+    Element::builder(name, ns)
+        .attr(xml_ncname!("node").to_owned(), node)
+        .build()
+}
+
+/// A submitted form of `form_type` setting each of `fields`.
+fn options_form(form_type: &str, fields: &[(&str, &str)]) -> Element {
+    let fields =
+        std::iter::once(FormField::new(FORM_TYPE_VAR, FieldType::Hidden).with_value(form_type))
+            .chain(
+                fields.iter().map(|(var, value)| {
+                    FormField::new(var, FieldType::TextSingle).with_value(value)
+                }),
+            )
+            .collect();
+    form_to_element(&DataForm::new(FormType::Submit, fields))
+}
+
+pub(crate) fn publish_request(
+    node: &str,
+    item_id: Option<&str>,
+    payload: Element,
+    options: &[(&str, &str)],
+) -> Element {
+    let mut item = Element::builder("item", PUBSUB_NS).append(payload);
+    if let Some(item_id) = item_id {
+        item = item.attr(xml_ncname!("id").to_owned(), item_id);
+    }
+    let mut request = Element::builder("pubsub", PUBSUB_NS).append(
+        Element::builder("publish", PUBSUB_NS)
+            .attr(xml_ncname!("node").to_owned(), node)
+            .append(item),
+    );
+    if !options.is_empty() {
+        request = request.append(
+            Element::builder("publish-options", PUBSUB_NS)
+                .append(options_form(PUBLISH_OPTIONS_FORM_TYPE, options)),
+        );
+    }
+    request.build()
+}
+
+pub(crate) fn items_request(node: &str, max_items: Option<u32>) -> Element {
+    let mut items = Element::builder("items", PUBSUB_NS).attr(xml_ncname!("node").to_owned(), node);
+    if let Some(max_items) = max_items {
+        items = items.attr(xml_ncname!("max_items").to_owned(), max_items.to_string());
+    }
+    pubsub(PUBSUB_NS, items.build())
+}
+
+pub(crate) fn retract_request(node: &str, item_id: &str) -> Element {
+    let retract = Element::builder("retract", PUBSUB_NS)
+        .attr(xml_ncname!("node").to_owned(), node)
+        .attr(xml_ncname!("notify").to_owned(), "true")
+        .append(Element::builder("item", PUBSUB_NS).attr(xml_ncname!("id").to_owned(), item_id))
+        .build();
+    pubsub(PUBSUB_NS, retract)
+}
+
+pub(crate) fn subscription_request(node: &str, jid: &str, subscribe: bool) -> Element {
+    let name = if subscribe {
+        "subscribe"
+    } else {
+        "unsubscribe"
+    };
+    let request = Element::builder(name, PUBSUB_NS)
+        .attr(xml_ncname!("node").to_owned(), node)
+        .attr(xml_ncname!("jid").to_owned(), jid)
+        .build();
+    pubsub(PUBSUB_NS, request)
+}
+
+pub(crate) fn create_request(node: &str, config: &[(&str, &str)]) -> Element {
+    let mut request =
+        Element::builder("pubsub", PUBSUB_NS).append(node_element("create", PUBSUB_NS, node));
+    if !config.is_empty() {
+        request = request.append(
+            Element::builder("configure", PUBSUB_NS)
+                .append(options_form(NODE_CONFIG_FORM_TYPE, config)),
+        );
+    }
+    request.build()
+}
+
+/// Ask for the configuration form of `node`, or submit `form` for it.
+pub(crate) fn configure_request(node: &str, form: Option<&DataForm>) -> Element {
+    let mut configure = node_element("configure", PUBSUB_OWNER_NS, node);
+    if let Some(form) = form {
+        configure.append_child(form_to_element(&form.to_submission()));
+    }
+    pubsub(PUBSUB_OWNER_NS, configure)
+}
+
+pub(crate) fn delete_request(node: &str) -> Element {
+    pubsub(
+        PUBSUB_OWNER_NS,
+        node_element("delete", PUBSUB_OWNER_NS, node),
+    )
+}
+
+#[cfg(feature = "native")]
+mod native {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tracing::debug;
+    use uuid::Uuid;
+    use waddle_core::error::EventBusError;
+    use waddle_core::event::{Event, EventBus, EventPayload, PubSubItem, channels};
+    use waddle_core::form::DataForm;
+    use xmpp_parsers::iq::Iq;
+    use xmpp_parsers::jid::Jid;
+    use xmpp_parsers::minidom::Element;
+
+    use super::*;
+    use crate::error::PubSubError;
+    use crate::outbound::StanzaSender;
+    use crate::pipeline::StanzaPipeline;
+    use crate::stanza::Stanza;
+
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Sends XEP-0060 node requests and waits for their answers. `service`
+    /// is the pubsub service's JID, or a contact's bare JID for their PEP
+    /// nodes; `None` addresses our own PEP service.
+    pub struct PubSubManager {
+        jid: String,
+        event_bus: Arc<dyn EventBus>,
+        pipeline: Arc<StanzaPipeline>,
+        wire_sender: StanzaSender,
+        request_timeout: Duration,
+    }
+
+    struct Answer {
+        items: Vec<PubSubItem>,
+        config: Option<DataForm>,
+    }
+
+    impl PubSubManager {
+        pub fn new(
+            jid: impl Into<String>,
+            event_bus: Arc<dyn EventBus>,
+            pipeline: Arc<StanzaPipeline>,
+            wire_sender: StanzaSender,
+        ) -> Self {
+            Self {
+                jid: jid.into(),
+                event_bus,
+                pipeline,
+                wire_sender,
+                request_timeout: REQUEST_TIMEOUT,
+            }
+        }
+
+        /// Publish `payload` to `node`, creating the node if the service
+        /// auto-creates them as PEP does. `options` are XEP-0060
+        /// publish-options preconditions such as `pubsub#access_model`.
+        /// Returns the item's id, which the service picks if `item_id` is
+        /// `None`.
+        pub async fn publish(
+            &self,
+            service: Option<&str>,
+            node: &str,
+            item_id: Option<&str>,
+            payload: Element,
+            options: &[(&str, &str)],
+        ) -> Result<Option<String>, PubSubError> {
+            let answer = self
+                .set(service, publish_request(node, item_id, payload, options))
+                .await?;
+            Ok(answer
+                .items
+                .into_iter()
+                .find_map(|item| item.id)
+                .or_else(|| item_id.map(String::from)))
+        }
+
+        /// Fetch the items of `node`, at most the `max_items` newest.
+        pub async fn retrieve(
+            &self,
+            service: Option<&str>,
+            node: &str,
+            max_items: Option<u32>,
+        ) -> Result<Vec<PubSubItem>, PubSubError> {
+            let answer = self
+                .request(service, items_request(node, max_items), false)
+                .await?;
+            Ok(answer.items)
+        }
+
+        /// Delete one item of `node`, notifying subscribers.
+        pub async fn retract(
+            &self,
+            service: Option<&str>,
+            node: &str,
+            item_id: &str,
+        ) -> Result<(), PubSubError> {
+            self.set(service, retract_request(node, item_id)).await?;
+            Ok(())
+        }
+
+        /// Create `node` with `config` overriding the service's default
+        /// configuration.
+        pub async fn create_node(
+            &self,
+            service: Option<&str>,
+            node: &str,
+            config: &[(&str, &str)],
+        ) -> Result<(), PubSubError> {
+            self.set(service, create_request(node, config)).await?;
+            Ok(())
+        }
+
+        pub async fn delete_node(
+            &self,
+            service: Option<&str>,
+            node: &str,
+        ) -> Result<(), PubSubError> {
+            self.set(service, delete_request(node)).await?;
+            Ok(())
+        }
+
+        /// The configuration form of one of our nodes.
+        pub async fn node_config(
+            &self,
+            service: Option<&str>,
+            node: &str,
+        ) -> Result<DataForm, PubSubError> {
+            self.request(service, configure_request(node, None), false)
+                .await?
+                .config
+                .ok_or_else(|| PubSubError::MissingConfig(node.to_string()))
+        }
+
+        /// Submit a configuration form returned by
+        /// [`node_config`](Self::node_config) with the values changed.
+        pub async fn configure_node(
+            &self,
+            service: Option<&str>,
+            node: &str,
+            form: &DataForm,
+        ) -> Result<(), PubSubError> {
+            self.set(service, configure_request(node, Some(form)))
+                .await?;
+            Ok(())
+        }
+
+        /// Subscribe our bare JID to `node`; its notifications then arrive
+        /// as `xmpp.pubsub.*` events.
+        pub async fn subscribe(
+            &self,
+            service: Option<&str>,
+            node: &str,
+        ) -> Result<(), PubSubError> {
+            self.set(service, subscription_request(node, &self.bare_jid(), true))
+                .await?;
+            Ok(())
+        }
+
+        pub async fn unsubscribe(
+            &self,
+            service: Option<&str>,
+            node: &str,
+        ) -> Result<(), PubSubError> {
+            self.set(service, subscription_request(node, &self.bare_jid(), false))
+                .await?;
+            Ok(())
+        }
+
+        fn bare_jid(&self) -> String {
+            waddle_core::jid::bare_jid(&self.jid)
+        }
+
+        async fn set(
+            &self,
+            service: Option<&str>,
+            payload: Element,
+        ) -> Result<Answer, PubSubError> {
+            self.request(service, payload, true).await
+        }
+
+        async fn request(
+            &self,
+            service: Option<&str>,
+            payload: Element,
+            set: bool,
+        ) -> Result<Answer, PubSubError> {
+            let to = service
+                .map(|service| {
+                    service
+                        .parse::<Jid>()
+                        .map_err(|_| PubSubError::InvalidJid(service.to_string()))
+                })
+                .transpose()?;
+            let request_id = format!("{PUBSUB_IQ_ID_PREFIX}{}", Uuid::new_v4());
+            // Subscribed before sending so the answer cannot be missed.
+            let mut answers = self
+                .event_bus
+                .subscribe(channels::XMPP_PUBSUB_ANSWERED)
+                .map_err(|error| PubSubError::EventBus(error.to_string()))?;
+
+            let iq = if set {
+                Iq::Set {
+                    from: None,
+                    to,
+                    id: request_id.clone(),
+                    payload,
+                }
+            } else {
+                Iq::Get {
+                    from: None,
+                    to,
+                    id: request_id.clone(),
+                    payload,
+                }
+            };
+            let bytes = self
+                .pipeline
+                .process_outbound(Stanza::Iq(Box::new(iq)))
+                .await
+                .map_err(|error| PubSubError::Pipeline(error.to_string()))?;
+            self.wire_sender
+                .send(bytes)
+                .await
+                .map_err(|_| PubSubError::NotConnected)?;
+            debug!(id = %request_id, "pubsub request sent");
+
+            tokio::time::timeout(self.request_timeout, async {
+                loop {
+                    match answers.recv().await {
+                        Ok(Event {
+                            payload:
+                                EventPayload::PubSubRequestAnswered {
+                                    request_id: id,
+                                    error,
+                                    items,
+                                    config,
+                                },
+                            ..
+                        }) if id == request_id => {
+                            return match error {
+                                Some(reason) => Err(PubSubError::Rejected(reason)),
+                                None => Ok(Answer { items, config }),
+                            };
+                        }
+                        Ok(_) | Err(EventBusError::Lagged(_)) => {}
+                        Err(error) => return Err(PubSubError::EventBus(error.to_string())),
+                    }
+                }
+            })
+            .await
+            .map_err(|_| PubSubError::Timeout)?
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use waddle_core::event::BroadcastEventBus;
+
+        use super::*;
+        use crate::outbound::{StanzaReceiver, stanza_channel};
+        use crate::pipeline::{ProcessorContext, StanzaDirection, StanzaProcessor};
+        use crate::processors::PubSubProcessor;
+
+        /// Answer the next request on the wire through a `PubSubProcessor`
+        /// with `reply`, an iq whose id is filled in. Returns the request.
+        fn serve_one(
+            mut wire: StanzaReceiver,
+            event_bus: Arc<dyn EventBus>,
+            reply: &'static str,
+        ) -> tokio::task::JoinHandle<Element> {
+            tokio::spawn(async move {
+                let bytes = wire.recv().await.unwrap();
+                let request = Element::from_str(std::str::from_utf8(&bytes).unwrap()).unwrap();
+                let reply = reply.replace("{id}", request.attr("id").unwrap());
+                let mut stanza = Stanza::parse(reply.as_bytes()).unwrap();
+                PubSubProcessor::new(event_bus).process_inbound(
+                    &mut stanza,
+                    &ProcessorContext {
+                        direction: StanzaDirection::Inbound,
+                    },
+                );
+                request
+            })
+        }
+
+        fn manager() -> (PubSubManager, Arc<dyn EventBus>, StanzaReceiver) {
+            let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+            let (wire_sender, wire) = stanza_channel(8);
+            let manager = PubSubManager::new(
+                "alice@example.com/desktop",
+                event_bus.clone(),
+                Arc::new(StanzaPipeline::new()),
+                wire_sender,
+            );
+            (manager, event_bus, wire)
+        }
+
+        #[tokio::test]
+        async fn publish_returns_the_item_id_the_service_picked() {
+            let (manager, event_bus, wire) = manager();
+            let server = serve_one(
+                wire,
+                event_bus,
+                "<iq xmlns='jabber:client' type='result' id='{id}'>\
+                    <pubsub xmlns='http://jabber.org/protocol/pubsub'>\
+                        <publish node='urn:xmpp:bookmarks:1'><item id='ae890ac52d'/></publish>\
+                    </pubsub>\
+                </iq>",
+            );
+
+            let id = manager
+                .publish(
+                    None,
+                    "urn:xmpp:bookmarks:1",
+                    None,
+                    Element::builder("conference", "urn:xmpp:bookmarks:1").build(),
+                    &[("pubsub#access_model", "whitelist")],
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(id.as_deref(), Some("ae890ac52d"));
+            let request = server.await.unwrap();
+            assert_eq!(request.attr("type"), Some("set"));
+            assert_eq!(request.attr("to"), None);
+            let pubsub = request.get_child("pubsub", PUBSUB_NS).unwrap();
+            let publish = pubsub.get_child("publish", PUBSUB_NS).unwrap();
+            assert_eq!(publish.attr("node"), Some("urn:xmpp:bookmarks:1"));
+            let options = pubsub.get_child("publish-options", PUBSUB_NS).unwrap();
+            let form = form_from_element(options.children().next().unwrap().clone()).unwrap();
+            assert_eq!(form.schema(), Some(PUBLISH_OPTIONS_FORM_TYPE));
+            assert_eq!(form.value("pubsub#access_model"), Some("whitelist"));
+        }
+
+        #[tokio::test]
+        async fn retrieve_returns_the_node_items() {
+            let (manager, event_bus, wire) = manager();
+            let server = serve_one(
+                wire,
+                event_bus,
+                "<iq xmlns='jabber:client' type='result' id='{id}' from='bob@example.com'>\
+                    <pubsub xmlns='http://jabber.org/protocol/pubsub'>\
+                        <items node='urn:xmpp:avatar:metadata'>\
+                            <item id='111f4b3c'><metadata xmlns='urn:xmpp:avatar:metadata'/></item>\
+                        </items>\
+                    </pubsub>\
+                </iq>",
+            );
+
+            let items = manager
+                .retrieve(Some("bob@example.com"), "urn:xmpp:avatar:metadata", Some(1))
+                .await
+                .unwrap();
+
+            assert_eq!(items.len(), 1);
+            assert_eq!(items[0].id.as_deref(), Some("111f4b3c"));
+            assert!(
+                item_payload(&items[0])
+                    .unwrap()
+                    .is("metadata", "urn:xmpp:avatar:metadata")
+            );
+            let request = server.await.unwrap();
+            assert_eq!(request.attr("type"), Some("get"));
+            assert_eq!(request.attr("to"), Some("bob@example.com"));
+            let items = request
+                .get_child("pubsub", PUBSUB_NS)
+                .and_then(|pubsub| pubsub.get_child("items", PUBSUB_NS))
+                .unwrap();
+            assert_eq!(items.attr("max_items"), Some("1"));
+        }
+
+        #[tokio::test]
+        async fn node_config_reads_the_owner_form() {
+            let (manager, event_bus, wire) = manager();
+            serve_one(
+                wire,
+                event_bus,
+                "<iq xmlns='jabber:client' type='result' id='{id}'>\
+                    <pubsub xmlns='http://jabber.org/protocol/pubsub#owner'>\
+                        <configure node='urn:xmpp:bookmarks:1'>\
+                            <x xmlns='jabber:x:data' type='form'>\
+                                <field var='FORM_TYPE' type='hidden'>\
+                                    <value>http://jabber.org/protocol/pubsub#node_config</value>\
+                                </field>\
+                                <field var='pubsub#max_items' type='text-single'><value>1</value></field>\
+                            </x>\
+                        </configure>\
+                    </pubsub>\
+                </iq>",
+            );
+
+            let form = manager
+                .node_config(None, "urn:xmpp:bookmarks:1")
+                .await
+                .unwrap();
+
+            assert_eq!(form.value("pubsub#max_items"), Some("1"));
+        }
+
+        #[tokio::test]
+        async fn refused_requests_fail_with_the_condition() {
+            let (manager, event_bus, wire) = manager();
+            let server = serve_one(
+                wire,
+                event_bus,
+                "<iq xmlns='jabber:client' type='error' id='{id}'>\
+                    <error type='cancel'>\
+                        <item-not-found xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/>\
+                    </error>\
+                </iq>",
+            );
+
+            assert!(matches!(
+                manager.delete_node(None, "urn:xmpp:bookmarks:1").await,
+                Err(PubSubError::Rejected(reason)) if reason == "item-not-found"
+            ));
+            let request = server.await.unwrap();
+            assert!(
+                request
+                    .get_child("pubsub", PUBSUB_OWNER_NS)
+                    .and_then(|pubsub| pubsub.get_child("delete", PUBSUB_OWNER_NS))
+                    .is_some()
+            );
+        }
+
+        #[tokio::test]
+        async fn unanswered_requests_time_out() {
+            let (mut manager, _event_bus, _wire) = manager();
+            manager.request_timeout = Duration::from_millis(20);
+            assert!(matches!(
+                manager.subscribe(Some("pubsub.example.com"), "news").await,
+                Err(PubSubError::Timeout)
+            ));
+        }
+    }
+}
@@ -0,0 +1,716 @@
+//! In-band registration (XEP-0077): asking a server for its registration
+//! form over a stream that has not authenticated, and submitting it to
+//! create an account.
+//!
+//! Servers describe what they need either as a XEP-0004 data form, which is
+//! also how CAPTCHA challenges (XEP-0158) arrive, or as the older fixed
+//! fields such as `<username/>` and `<password/>`. Both reach the user as a
+//! [`DataForm`] and are sent back in the shape the server used.
+
+use std::str::FromStr;
+
+use waddle_core::form::{DataForm, FORM_TYPE_VAR, FieldType, FormField, FormType};
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::minidom::Element;
+
+use crate::error::ConnectionError;
+use crate::forms::{form_from_element, form_to_element};
+use crate::transport::XmppTransport;
+
+#[cfg(feature = "native")]
+pub use native::{RegistrationManager, RegistrationTransport};
+
+pub const REGISTER_NS: &str = "jabber:iq:register";
+const DATA_FORMS_NS: &str = "jabber:x:data";
+const STANZAS_NS: &str = "urn:ietf:params:xml:ns:xmpp-stanzas";
+const CLIENT_NS: &str = "jabber:client";
+
+const REGISTER_FORM_IQ_ID: &str = "register-form";
+const REGISTER_SUBMIT_IQ_ID: &str = "register-submit";
+
+/// What a server asks for before it creates an account.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegistrationForm {
+    pub form: DataForm,
+    /// The server listed fixed fields instead of sending a data form; the
+    /// answer goes back as fields too.
+    pub legacy: bool,
+}
+
+/// The username and password from a filled-in registration form.
+pub fn submitted_credentials(form: &DataForm) -> Option<(String, String)> {
+    let username = form.value("username").filter(|value| !value.is_empty())?;
+    let password = form.value("password").filter(|value| !value.is_empty())?;
+    Some((username.to_string(), password.to_string()))
+}
+
+pub fn build_form_request(server: &str) -> Vec<u8> {
+    let query = Element::builder("query", REGISTER_NS).build();
+    serialize_iq(Iq::Get {
+        from: None,
+        to: server.parse().ok(),
+        id: REGISTER_FORM_IQ_ID.to_string(),
+        payload: query,
+    })
+}
+
+/// The `<iq type='set'/>` answering `form` with the user's `submitted` copy.
+pub fn build_submission(server: &str, form: &RegistrationForm, submitted: &DataForm) -> Vec<u8> {
+    let submission = submitted.to_submission();
+    let query = if form.legacy {
+        Element::builder("query", REGISTER_NS)
+            .append_all(
+                submission
+                    .fields
+                    .iter()
+                    .filter_map(|field| Some((field.var.as_deref()?, field)))
+                    .filter(|(var, _)| *var != FORM_TYPE_VAR)
+                    .map(|(var, field)| {
+                        Element::builder(var, REGISTER_NS)
+                            .append(field.values.first().cloned().unwrap_or_default())
+                    }),
+            )
+            .build()
+    } else {
+        Element::builder("query", REGISTER_NS)
+            .append(form_to_element(&submission))
+            .build()
+    };
+    serialize_iq(Iq::Set {
+        from: None,
+        to: server.parse().ok(),
+        id: REGISTER_SUBMIT_IQ_ID.to_string(),
+        payload: query,
+    })
+}
+
+fn serialize_iq(iq: Iq) -> Vec<u8> {
+    let mut payload = Vec::new();
+    // Writing to a Vec cannot fail.
+    let _ = Element::from(iq).write_to(&mut payload);
+    payload
+}
+
+/// Parse the reply to [`build_form_request`].
+pub fn parse_form_response(stanza: &[u8]) -> Result<RegistrationForm, ConnectionError> {
+    let reply = parse_reply(stanza)?;
+    let query = reply
+        .get_child("query", REGISTER_NS)
+        .ok_or_else(|| unsupported("server sent no registration form"))?;
+
+    if let Some(x) = query.get_child("x", DATA_FORMS_NS) {
+        let form = form_from_element(x.clone())
+            .map_err(|error| ConnectionError::StreamError(error.to_string()))?;
+        return Ok(RegistrationForm {
+            form,
+            legacy: false,
+        });
+    }
+
+    let mut form = DataForm::new(FormType::Form, Vec::new());
+    for child in query.children().filter(|child| child.ns() == REGISTER_NS) {
+        match child.name() {
+            "instructions" => form.instructions = Some(child.text()),
+            // Only sent to an account that is already logged in.
+            "registered" | "remove" => {}
+            name => {
+                let field_type = if name == "password" {
+                    FieldType::TextPrivate
+                } else {
+                    FieldType::TextSingle
+                };
+                let mut field = FormField::new(name, field_type).required();
+                let value = child.text();
+                if !value.is_empty() {
+                    field = field.with_value(&value);
+                }
+                form.fields.push(field);
+            }
+        }
+    }
+    if form.fields.is_empty() {
+        return Err(unsupported("registration form has no fields"));
+    }
+    Ok(RegistrationForm { form, legacy: true })
+}
+
+/// Parse the reply to [`build_submission`].
+pub fn parse_submission_response(stanza: &[u8]) -> Result<(), ConnectionError> {
+    parse_reply(stanza).map(|_| ())
+}
+
+/// The `result` iq, or the error it carries mapped into a
+/// [`ConnectionError`].
+fn parse_reply(stanza: &[u8]) -> Result<Element, ConnectionError> {
+    let element = std::str::from_utf8(stanza)
+        .ok()
+        .and_then(|xml| Element::from_str(xml.trim()).ok())
+        .filter(|element| element.name() == "iq")
+        .ok_or_else(|| ConnectionError::StreamError("invalid registration reply".to_string()))?;
+
+    match element.attr("type") {
+        Some("result") => Ok(element),
+        Some("error") => Err(registration_error(error_reason(
+            element.get_child("error", CLIENT_NS),
+        ))),
+        other => Err(ConnectionError::StreamError(format!(
+            "unexpected registration reply type {other:?}"
+        ))),
+    }
+}
+
+/// `condition`, or `condition: text`, for a stanza `<error/>`.
+pub(crate) fn error_reason(error: Option<&Element>) -> String {
+    let children = || {
+        error
+            .into_iter()
+            .flat_map(Element::children)
+            .filter(|child| child.ns() == STANZAS_NS)
+    };
+    let condition = children()
+        .find(|child| child.name() != "text")
+        .map(|child| child.name().to_string())
+        .unwrap_or_else(|| "undefined-condition".to_string());
+    match children().find(|child| child.name() == "text") {
+        Some(text) => format!("{condition}: {}", text.text()),
+        None => condition,
+    }
+}
+
+/// Map a reason from [`error_reason`] into a [`ConnectionError`].
+pub(crate) fn registration_error(reason: String) -> ConnectionError {
+    match reason.split(':').next().unwrap_or_default() {
+        "conflict" => ConnectionError::RegistrationConflict(reason),
+        "service-unavailable" | "feature-not-implemented" => {
+            ConnectionError::RegistrationUnsupported(reason)
+        }
+        _ => ConnectionError::RegistrationRejected(reason),
+    }
+}
+
+fn unsupported(reason: &str) -> ConnectionError {
+    ConnectionError::RegistrationUnsupported(reason.to_string())
+}
+
+/// Ask for the registration form of `server` on a stream that has not
+/// authenticated.
+pub async fn fetch_form<T: XmppTransport>(
+    transport: &mut T,
+    server: &str,
+) -> Result<RegistrationForm, ConnectionError> {
+    transport.send(&build_form_request(server)).await?;
+    let reply = await_reply(transport, REGISTER_FORM_IQ_ID).await?;
+    parse_form_response(&reply)
+}
+
+/// Submit a filled-in copy of `form`. Succeeds once the account exists.
+pub async fn submit<T: XmppTransport>(
+    transport: &mut T,
+    server: &str,
+    form: &RegistrationForm,
+    submitted: &DataForm,
+) -> Result<(), ConnectionError> {
+    transport
+        .send(&build_submission(server, form, submitted))
+        .await?;
+    let reply = await_reply(transport, REGISTER_SUBMIT_IQ_ID).await?;
+    parse_submission_response(&reply)
+}
+
+async fn await_reply<T: XmppTransport>(
+    transport: &mut T,
+    id: &str,
+) -> Result<Vec<u8>, ConnectionError> {
+    loop {
+        let stanza = transport.recv().await?;
+        let matches = std::str::from_utf8(&stanza)
+            .ok()
+            .and_then(|xml| Element::from_str(xml.trim()).ok())
+            .is_some_and(|element| element.name() == "iq" && element.attr("id") == Some(id));
+        if matches {
+            return Ok(stanza);
+        }
+    }
+}
+
+#[cfg(feature = "native")]
+mod native {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use tokio::sync::Mutex;
+    use uuid::Uuid;
+    use waddle_core::credentials::CredentialStore;
+    use waddle_core::error::EventBusError;
+    use waddle_core::event::{Channel, Event, EventBus, EventPayload, EventSource, channels};
+    use waddle_core::form::{DataForm, FormType};
+
+    use super::{RegistrationForm, fetch_form, submit, submitted_credentials};
+    use crate::error::ConnectionError;
+    use crate::transport::{ConnectionConfig, NativeTcpTransport, XmppTransport};
+
+    const REGISTRATION_TIMEOUT_SECONDS: u32 = 30;
+
+    /// A transport that can open a stream before any account exists.
+    pub trait RegistrationTransport: XmppTransport + Sized {
+        fn connect_unauthenticated(
+            config: &ConnectionConfig,
+        ) -> impl Future<Output = Result<Self, ConnectionError>>;
+    }
+
+    impl RegistrationTransport for NativeTcpTransport {
+        async fn connect_unauthenticated(
+            config: &ConnectionConfig,
+        ) -> Result<Self, ConnectionError> {
+            NativeTcpTransport::connect_unauthenticated(config).await
+        }
+    }
+
+    struct PendingRegistration<T> {
+        server: String,
+        form: RegistrationForm,
+        transport: T,
+    }
+
+    /// Drives account creation for the onboarding UI. A
+    /// `ui.account.register` request opens a stream to the server and sends
+    /// its form out as `xmpp.form.requested`; the matching
+    /// `ui.form.submitted` registers the account, saves its password to the
+    /// credential store and publishes `system.account.registered`. Failures
+    /// end in `system.account.registration_failed`.
+    pub struct RegistrationManager<T = NativeTcpTransport> {
+        credentials: Arc<dyn CredentialStore>,
+        event_bus: Arc<dyn EventBus>,
+        pending: Mutex<HashMap<String, PendingRegistration<T>>>,
+    }
+
+    impl<T: RegistrationTransport> RegistrationManager<T> {
+        pub fn new(credentials: Arc<dyn CredentialStore>, event_bus: Arc<dyn EventBus>) -> Self {
+            Self {
+                credentials,
+                event_bus,
+                pending: Mutex::new(HashMap::new()),
+            }
+        }
+
+        pub async fn run(self: Arc<Self>) -> Result<(), EventBusError> {
+            let mut requests = self.event_bus.subscribe(channels::UI_ACCOUNT_REGISTER)?;
+            let mut submissions = self.event_bus.subscribe(channels::UI_FORM_SUBMITTED)?;
+
+            loop {
+                let received = tokio::select! {
+                    received = requests.recv() => received,
+                    received = submissions.recv() => received,
+                };
+                match received {
+                    Ok(event) => match event.payload {
+                        EventPayload::AccountRegistrationRequested { server } => {
+                            self.start(server).await;
+                        }
+                        EventPayload::FormSubmitted { form_id, form } => {
+                            self.finish(&form_id, form).await;
+                        }
+                        _ => {}
+                    },
+                    Err(EventBusError::ChannelClosed) => return Ok(()),
+                    Err(_) => {}
+                }
+            }
+        }
+
+        async fn start(&self, server: String) {
+            let config = ConnectionConfig {
+                jid: server.clone(),
+                server: None,
+                port: None,
+                timeout_seconds: REGISTRATION_TIMEOUT_SECONDS,
+                max_reconnect_attempts: 0,
+                transports: Vec::new(),
+                websocket_url: None,
+                bosh_url: None,
+            };
+            let mut transport = match T::connect_unauthenticated(&config).await {
+                Ok(transport) => transport,
+                Err(error) => return self.emit_failed(&server, &error),
+            };
+            let form = match fetch_form(&mut transport, &server).await {
+                Ok(form) => form,
+                Err(error) => {
+                    let _ = transport.close().await;
+                    return self.emit_failed(&server, &error);
+                }
+            };
+
+            let form_id = Uuid::new_v4().to_string();
+            self.emit(
+                channels::XMPP_FORM_REQUESTED,
+                EventPayload::FormRequested {
+                    form_id: form_id.clone(),
+                    from: server.clone(),
+                    form: form.form.clone(),
+                },
+            );
+            let mut pending = self.pending.lock().await;
+            // Asking again abandons the earlier form for that server.
+            pending.retain(|_, earlier| earlier.server != server);
+            pending.insert(
+                form_id,
+                PendingRegistration {
+                    server,
+                    form,
+                    transport,
+                },
+            );
+        }
+
+        async fn finish(&self, form_id: &str, submitted: DataForm) {
+            // Forms from other features are answered on the same channel.
+            let Some(mut pending) = self.pending.lock().await.remove(form_id) else {
+                return;
+            };
+            if submitted.form_type == FormType::Cancel {
+                let _ = pending.transport.close().await;
+                return;
+            }
+
+            let result = self.register(&mut pending, &submitted).await;
+            let _ = pending.transport.close().await;
+            match result {
+                Ok(jid) => self.emit(
+                    channels::SYSTEM_ACCOUNT_REGISTERED,
+                    EventPayload::AccountRegistered { jid },
+                ),
+                Err(error) => self.emit_failed(&pending.server, &error),
+            }
+        }
+
+        async fn register(
+            &self,
+            pending: &mut PendingRegistration<T>,
+            submitted: &DataForm,
+        ) -> Result<String, ConnectionError> {
+            let (username, password) = submitted_credentials(submitted).ok_or_else(|| {
+                ConnectionError::RegistrationRejected(
+                    "a username and password are required".to_string(),
+                )
+            })?;
+            submit(
+                &mut pending.transport,
+                &pending.server,
+                &pending.form,
+                submitted,
+            )
+            .await?;
+
+            let jid = format!("{username}@{}", pending.server);
+            let credentials = self.credentials.clone();
+            let account = jid.clone();
+            tokio::task::spawn_blocking(move || credentials.set(&account, &password))
+                .await
+                .map_err(|error| ConnectionError::CredentialUnavailable(error.to_string()))?
+                .map_err(|error| ConnectionError::CredentialUnavailable(error.to_string()))?;
+            Ok(jid)
+        }
+
+        fn emit_failed(&self, server: &str, error: &ConnectionError) {
+            self.emit(
+                channels::SYSTEM_ACCOUNT_REGISTRATION_FAILED,
+                EventPayload::AccountRegistrationFailed {
+                    server: server.to_string(),
+                    reason: error.to_string(),
+                },
+            );
+        }
+
+        fn emit(&self, channel: &str, payload: EventPayload) {
+            let _ = self.event_bus.publish(Event::new(
+                Channel::new(channel).unwrap(),
+                EventSource::Xmpp,
+                payload,
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(xml: &str) -> Vec<u8> {
+        xml.as_bytes().to_vec()
+    }
+
+    #[test]
+    fn legacy_fields_become_a_form_and_are_sent_back_as_fields() {
+        let form = parse_form_response(&reply(
+            "<iq xmlns='jabber:client' type='result' id='register-form'>\
+             <query xmlns='jabber:iq:register'>\
+             <instructions>Choose a username and password.</instructions>\
+             <username/><password/><email/>\
+             </query></iq>",
+        ))
+        .unwrap();
+        assert!(form.legacy);
+        assert_eq!(
+            form.form.instructions.as_deref(),
+            Some("Choose a username and password.")
+        );
+        assert_eq!(form.form.fields.len(), 3);
+        assert_eq!(
+            form.form.field("password").unwrap().field_type,
+            FieldType::TextPrivate
+        );
+
+        let mut filled = form.form.clone();
+        filled.set_values("username", vec!["juliet".to_string()]);
+        filled.set_values("password", vec!["R0m30".to_string()]);
+        assert_eq!(
+            submitted_credentials(&filled),
+            Some(("juliet".to_string(), "R0m30".to_string()))
+        );
+
+        let stanza = String::from_utf8(build_submission("example.com", &form, &filled)).unwrap();
+        assert!(stanza.contains("<username>juliet</username>"), "{stanza}");
+        assert!(stanza.contains("<password>R0m30</password>"), "{stanza}");
+        assert!(!stanza.contains(DATA_FORMS_NS), "{stanza}");
+    }
+
+    #[test]
+    fn data_forms_carry_captcha_fields_through() {
+        let form = parse_form_response(&reply(
+            "<iq xmlns='jabber:client' type='result' id='register-form'>\
+             <query xmlns='jabber:iq:register'>\
+             <x xmlns='jabber:x:data' type='form'>\
+             <field var='FORM_TYPE' type='hidden'><value>urn:xmpp:captcha</value></field>\
+             <field var='username' type='text-single'><required/></field>\
+             <field var='password' type='text-private'><required/></field>\
+             <field var='challenge' type='hidden'><value>F3A6292C</value></field>\
+             <field var='ocr' label='Enter the text you see'><required/></field>\
+             </x></query></iq>",
+        ))
+        .unwrap();
+        assert!(!form.legacy);
+        assert_eq!(form.form.schema(), Some("urn:xmpp:captcha"));
+
+        let mut filled = form.form.clone();
+        filled.set_values("username", vec!["juliet".to_string()]);
+        filled.set_values("password", vec!["R0m30".to_string()]);
+        filled.set_values("ocr", vec!["7nHL3".to_string()]);
+        let stanza = String::from_utf8(build_submission("example.com", &form, &filled)).unwrap();
+        assert!(stanza.contains("type='submit'"), "{stanza}");
+        assert!(stanza.contains("F3A6292C"), "{stanza}");
+        assert!(stanza.contains("7nHL3"), "{stanza}");
+    }
+
+    #[test]
+    fn stanza_errors_map_into_connection_errors() {
+        let error = |condition: &str| {
+            parse_submission_response(&reply(&format!(
+                "<iq xmlns='jabber:client' type='error' id='register-submit'>\
+                 <error type='cancel'><{condition} xmlns='{STANZAS_NS}'/>\
+                 <text xmlns='{STANZAS_NS}'>nope</text></error></iq>"
+            )))
+            .unwrap_err()
+        };
+        assert!(matches!(
+            error("conflict"),
+            ConnectionError::RegistrationConflict(reason) if reason == "conflict: nope"
+        ));
+        assert!(matches!(
+            error("service-unavailable"),
+            ConnectionError::RegistrationUnsupported(_)
+        ));
+        assert!(matches!(
+            error("not-acceptable"),
+            ConnectionError::RegistrationRejected(_)
+        ));
+        assert!(!error("not-allowed").is_retryable());
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod native_tests {
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::{Arc, Mutex, OnceLock};
+    use std::time::Duration;
+
+    use waddle_core::credentials::{CredentialStore, InMemoryCredentialStore};
+    use waddle_core::event::{
+        BroadcastEventBus, Channel, Event, EventBus, EventPayload, EventSource,
+    };
+
+    use super::*;
+    use crate::transport::ConnectionConfig;
+
+    #[derive(Default)]
+    struct ServerScript {
+        replies: VecDeque<Vec<u8>>,
+        sent: Vec<String>,
+    }
+
+    /// Replies scripted per server, so tests running in parallel do not
+    /// share them.
+    fn servers() -> &'static Mutex<HashMap<String, ServerScript>> {
+        static SERVERS: OnceLock<Mutex<HashMap<String, ServerScript>>> = OnceLock::new();
+        SERVERS.get_or_init(Default::default)
+    }
+
+    struct ScriptedTransport {
+        server: String,
+    }
+
+    impl XmppTransport for ScriptedTransport {
+        async fn connect(
+            _config: &ConnectionConfig,
+            _password: &str,
+        ) -> Result<Self, ConnectionError> {
+            unreachable!("registration never authenticates")
+        }
+
+        async fn send(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
+            let mut servers = servers().lock().unwrap();
+            let script = servers.get_mut(&self.server).unwrap();
+            script.sent.push(String::from_utf8_lossy(data).into_owned());
+            Ok(())
+        }
+
+        async fn recv(&mut self) -> Result<Vec<u8>, ConnectionError> {
+            let mut servers = servers().lock().unwrap();
+            servers
+                .get_mut(&self.server)
+                .and_then(|script| script.replies.pop_front())
+                .ok_or_else(|| ConnectionError::TransportError("closed".to_string()))
+        }
+
+        async fn close(&mut self) -> Result<(), ConnectionError> {
+            Ok(())
+        }
+
+        fn supports_stream_management(&self) -> bool {
+            false
+        }
+    }
+
+    impl RegistrationTransport for ScriptedTransport {
+        async fn connect_unauthenticated(
+            config: &ConnectionConfig,
+        ) -> Result<Self, ConnectionError> {
+            Ok(Self {
+                server: config.jid.clone(),
+            })
+        }
+    }
+
+    const FORM: &str = "<iq xmlns='jabber:client' type='result' id='register-form'>\
+        <query xmlns='jabber:iq:register'><username/><password/></query></iq>";
+
+    fn script(server: &str, replies: &[&str]) {
+        servers().lock().unwrap().insert(
+            server.to_string(),
+            ServerScript {
+                replies: replies
+                    .iter()
+                    .map(|reply| reply.as_bytes().to_vec())
+                    .collect(),
+                sent: Vec::new(),
+            },
+        );
+    }
+
+    async fn register(server: &str, credentials: Arc<dyn CredentialStore>) -> EventPayload {
+        let event_bus: Arc<dyn EventBus> = Arc::new(BroadcastEventBus::default());
+        let mut forms = event_bus.subscribe("xmpp.form.requested").unwrap();
+        let mut outcomes = event_bus.subscribe("system.account.*").unwrap();
+        let manager = Arc::new(RegistrationManager::<ScriptedTransport>::new(
+            credentials,
+            event_bus.clone(),
+        ));
+        tokio::spawn(manager.run());
+        tokio::task::yield_now().await;
+
+        let publish = |channel: &str, payload| {
+            event_bus
+                .publish(Event::new(
+                    Channel::new(channel).unwrap(),
+                    EventSource::System("test".into()),
+                    payload,
+                ))
+                .unwrap();
+        };
+        publish(
+            "ui.account.register",
+            EventPayload::AccountRegistrationRequested {
+                server: server.to_string(),
+            },
+        );
+        let event = tokio::time::timeout(Duration::from_secs(5), forms.recv())
+            .await
+            .expect("timed out waiting for the form")
+            .unwrap();
+        let EventPayload::FormRequested { form_id, form, .. } = event.payload else {
+            panic!("expected FormRequested, got {:?}", event.payload);
+        };
+
+        let mut filled = form;
+        filled.set_values("username", vec!["juliet".to_string()]);
+        filled.set_values("password", vec!["R0m30".to_string()]);
+        publish(
+            "ui.form.submitted",
+            EventPayload::FormSubmitted {
+                form_id,
+                form: filled.to_submission(),
+            },
+        );
+        tokio::time::timeout(Duration::from_secs(5), outcomes.recv())
+            .await
+            .expect("timed out waiting for the registration outcome")
+            .unwrap()
+            .payload
+    }
+
+    #[tokio::test]
+    async fn registers_the_account_and_saves_its_password() {
+        script(
+            "ibr-ok.example",
+            &[
+                FORM,
+                "<iq xmlns='jabber:client' type='result' id='register-submit'/>",
+            ],
+        );
+        let credentials = Arc::new(InMemoryCredentialStore::new());
+
+        let outcome = register("ibr-ok.example", credentials.clone()).await;
+        assert!(matches!(
+            outcome,
+            EventPayload::AccountRegistered { ref jid } if jid == "juliet@ibr-ok.example"
+        ));
+        assert_eq!(credentials.get("juliet@ibr-ok.example").unwrap(), "R0m30");
+        let sent = servers().lock().unwrap()["ibr-ok.example"].sent.clone();
+        assert_eq!(sent.len(), 2);
+        assert!(sent[1].contains("<username>juliet</username>"));
+    }
+
+    #[tokio::test]
+    async fn reports_a_taken_username() {
+        script(
+            "ibr-conflict.example",
+            &[
+                FORM,
+                "<iq xmlns='jabber:client' type='error' id='register-submit'>\
+                 <error type='cancel'>\
+                 <conflict xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/></error></iq>",
+            ],
+        );
+        let credentials = Arc::new(InMemoryCredentialStore::new());
+
+        let outcome = register("ibr-conflict.example", credentials.clone()).await;
+        match outcome {
+            EventPayload::AccountRegistrationFailed { server, reason } => {
+                assert_eq!(server, "ibr-conflict.example");
+                assert!(reason.contains("already taken"), "{reason}");
+            }
+            other => panic!("expected AccountRegistrationFailed, got {other:?}"),
+        }
+        assert!(credentials.get("juliet@ibr-conflict.example").is_err());
+    }
+}
@@ -0,0 +1,77 @@
+//! Conversion between the shared [`waddle_core::rsm`] paging model and
+//! XEP-0059 `<set xmlns='http://jabber.org/protocol/rsm'/>` elements.
+
+use waddle_core::rsm::{RsmCursor, RsmQuery, RsmResult};
+use xmpp_parsers::rsm::{SetQuery, SetResult};
+
+/// The `<set/>` asking for `query`'s page. The last page is requested with
+/// an empty `<before/>` (XEP-0059 §2.5).
+pub fn set_query(query: &RsmQuery) -> SetQuery {
+    let (after, before) = match &query.cursor {
+        RsmCursor::Start => (None, None),
+        RsmCursor::After(id) => (Some(id.clone()), None),
+        RsmCursor::Before(id) => (None, Some(id.clone())),
+        RsmCursor::End => (None, Some(String::new())),
+    };
+    SetQuery {
+        max: query.max.map(|max| max as usize),
+        after,
+        before,
+        index: None,
+    }
+}
+
+/// What a reply's `<set/>` says about the page it carries.
+pub fn rsm_result(set: &SetResult) -> RsmResult {
+    RsmResult {
+        first: set.first.as_ref().map(|first| first.item.clone()),
+        first_index: set
+            .first
+            .as_ref()
+            .and_then(|first| first.index)
+            .map(|index| index as u64),
+        last: set.last.clone(),
+        count: set.count.map(|count| count as u64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xmpp_parsers::minidom::Element;
+
+    #[test]
+    fn cursors_map_onto_after_and_before() {
+        let element: Element = set_query(&RsmQuery::last(20)).into();
+        let before = element
+            .get_child("before", xmpp_parsers::ns::RSM)
+            .expect("the last page is asked for with an empty <before/>");
+        assert_eq!(before.text(), "");
+
+        let set = set_query(&RsmQuery::after("item-9", 10));
+        assert_eq!(set.after.as_deref(), Some("item-9"));
+        assert_eq!(set.before, None);
+
+        let set = set_query(&RsmQuery::default());
+        assert_eq!((set.max, set.after, set.before), (None, None, None));
+    }
+
+    #[test]
+    fn reads_a_reply_page() {
+        let element: Element = "<set xmlns='http://jabber.org/protocol/rsm'>\
+            <first index='20'>item-21</first>\
+            <last>item-30</last>\
+            <count>95</count>\
+        </set>"
+            .parse()
+            .unwrap();
+        let page = rsm_result(&SetResult::try_from(element).unwrap());
+
+        assert_eq!(page.first.as_deref(), Some("item-21"));
+        assert_eq!(page.first_index, Some(20));
+        assert_eq!(page.remaining(), Some(75));
+        assert_eq!(page.next(10), Some(RsmQuery::after("item-30", 10)));
+        assert_eq!(page.previous(10), Some(RsmQuery::before("item-21", 10)));
+        assert_eq!(RsmResult::default().next(10), None);
+    }
+}
@@ -1,15 +1,20 @@
 use std::collections::HashSet;
+use std::str::FromStr;
 
-#[cfg(any(feature = "native", test))]
 use sasl::client::Mechanism;
-#[cfg(any(feature = "native", test))]
 use sasl::client::mechanisms::{Plain, Scram};
-#[cfg(any(feature = "native", test))]
-use sasl::common::Credentials;
-#[cfg(any(feature = "native", test))]
 use sasl::common::scram::{Sha1, Sha256};
+use sasl::common::{ChannelBinding, Credentials};
+use tracing::debug;
+use xmpp_parsers::{
+    bind::BindQuery,
+    iq::Iq,
+    minidom::Element,
+    ns,
+    sasl::{Auth, Challenge, Failure, Mechanism as SaslMechanism, Response, Success},
+    stream_features::StreamFeatures,
+};
 
-#[cfg(any(feature = "native", test))]
 use crate::error::ConnectionError;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -48,8 +53,7 @@ pub fn select_mechanism(server_mechanisms: &HashSet<String>) -> Option<SelectedM
         .copied()
 }
 
-#[cfg(any(feature = "native", test))]
-fn build_mechanism(
+pub(crate) fn build_mechanism(
     selected: SelectedMechanism,
     credentials: &Credentials,
 ) -> Result<Box<dyn Mechanism + Send>, ConnectionError> {
@@ -76,6 +80,127 @@ fn build_mechanism(
     }
 }
 
+/// Outcome of feeding a server element to [`SaslNegotiation::handle`].
+pub(crate) enum SaslStep {
+    Respond(Element),
+    Succeeded,
+}
+
+/// SASL over already-parsed elements, for transports that frame the stream
+/// themselves (WebSocket, BOSH) instead of going through tokio-xmpp.
+pub(crate) struct SaslNegotiation {
+    mechanism: Box<dyn Mechanism + Send>,
+}
+
+impl SaslNegotiation {
+    /// Pick a mechanism from `features` and build the initial `<auth/>`.
+    pub(crate) fn start(
+        features: &StreamFeatures,
+        username: &str,
+        password: &str,
+    ) -> Result<(Self, Element), ConnectionError> {
+        let server_mechanisms: HashSet<String> = features
+            .sasl_mechanisms
+            .mechanisms
+            .iter()
+            .cloned()
+            .collect();
+        if server_mechanisms.is_empty() {
+            return Err(ConnectionError::AuthenticationFailed(
+                "server did not advertise any SASL mechanisms".to_string(),
+            ));
+        }
+
+        let selected = select_mechanism(&server_mechanisms).ok_or_else(|| {
+            ConnectionError::AuthenticationFailed(format!(
+                "no supported SASL mechanism found; server offers: {}",
+                server_mechanisms
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        })?;
+        debug!(mechanism = %selected, "selected SASL mechanism");
+
+        let credentials = Credentials::default()
+            .with_username(username)
+            .with_password(password)
+            .with_channel_binding(ChannelBinding::Unsupported);
+        let mut mechanism = build_mechanism(selected, &credentials)?;
+        let mechanism_name = SaslMechanism::from_str(selected.name()).map_err(|e| {
+            ConnectionError::AuthenticationFailed(format!("invalid SASL mechanism name: {e}"))
+        })?;
+
+        let auth = Auth {
+            mechanism: mechanism_name,
+            data: mechanism.initial(),
+        };
+        Ok((Self { mechanism }, auth.into()))
+    }
+
+    /// Process one element from the server. Returns `None` for elements that
+    /// are not part of the SASL exchange.
+    pub(crate) fn handle(&mut self, element: Element) -> Result<Option<SaslStep>, ConnectionError> {
+        if element.is("challenge", ns::SASL) {
+            let challenge = Challenge::try_from(element).map_err(|e| {
+                ConnectionError::StreamError(format!("invalid SASL challenge: {e}"))
+            })?;
+            let data = self.mechanism.response(&challenge.data).map_err(|e| {
+                ConnectionError::AuthenticationFailed(format!(
+                    "SASL challenge-response failed: {e:?}"
+                ))
+            })?;
+            Ok(Some(SaslStep::Respond(Response { data }.into())))
+        } else if element.is("success", ns::SASL) {
+            let success = Success::try_from(element)
+                .map_err(|e| ConnectionError::StreamError(format!("invalid SASL success: {e}")))?;
+            self.mechanism.success(&success.data).map_err(|e| {
+                ConnectionError::AuthenticationFailed(format!(
+                    "server signature verification failed: {e:?}"
+                ))
+            })?;
+            Ok(Some(SaslStep::Succeeded))
+        } else if element.is("failure", ns::SASL) {
+            let failure = Failure::try_from(element)
+                .map_err(|e| ConnectionError::StreamError(format!("invalid SASL failure: {e}")))?;
+            Err(map_failure(&failure))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+fn map_failure(failure: &Failure) -> ConnectionError {
+    let condition = format!("{:?}", failure.defined_condition);
+    match failure.texts.values().next() {
+        Some(text) if !text.is_empty() => {
+            ConnectionError::AuthenticationFailed(format!("{condition}: {text}"))
+        }
+        _ => ConnectionError::AuthenticationFailed(condition),
+    }
+}
+
+const BIND_REQUEST_ID: &str = "resource-bind";
+
+pub(crate) fn bind_request(resource: Option<String>) -> Element {
+    Iq::from_set(BIND_REQUEST_ID, BindQuery::new(resource)).into()
+}
+
+/// The outcome of resource binding if `element` answers [`bind_request`].
+pub(crate) fn bind_result(element: Element) -> Option<Result<(), ConnectionError>> {
+    let iq = Iq::try_from(element).ok()?;
+    if iq.id() != BIND_REQUEST_ID {
+        return None;
+    }
+    Some(match iq {
+        Iq::Result { .. } => Ok(()),
+        _ => Err(ConnectionError::StreamError(
+            "invalid response to resource binding".to_string(),
+        )),
+    })
+}
+
 #[cfg(feature = "native")]
 mod native {
     use std::collections::HashSet;
@@ -94,11 +219,9 @@ mod native {
     use tokio_xmpp::xmpp_stream::XMPPStream;
     use tracing::{debug, warn};
 
-    use super::{build_mechanism, select_mechanism};
+    use super::{BIND_REQUEST_ID, build_mechanism, select_mechanism};
     use crate::error::ConnectionError;
 
-    const BIND_REQUEST_ID: &str = "resource-bind";
-
     pub struct AuthenticatedStream<S> {
         pub stream: S,
         pub stream_management_supported: bool,
@@ -390,6 +513,61 @@ mod tests {
         assert!(!error.is_retryable());
     }
 
+    fn plain_only_features() -> StreamFeatures {
+        let element = Element::from_str(
+            "<features xmlns='http://etherx.jabber.org/streams'>\
+             <mechanisms xmlns='urn:ietf:params:xml:ns:xmpp-sasl'>\
+             <mechanism>PLAIN</mechanism></mechanisms></features>",
+        )
+        .unwrap();
+        StreamFeatures::try_from(element).unwrap()
+    }
+
+    #[test]
+    fn negotiation_sends_auth_and_reports_success() {
+        let (mut sasl, auth) =
+            SaslNegotiation::start(&plain_only_features(), "alice", "secret").unwrap();
+        assert!(auth.is("auth", ns::SASL));
+        assert_eq!(auth.attr("mechanism"), Some("PLAIN"));
+
+        let unrelated = Element::from_str("<presence xmlns='jabber:client'/>").unwrap();
+        assert!(sasl.handle(unrelated).unwrap().is_none());
+
+        let success =
+            Element::from_str("<success xmlns='urn:ietf:params:xml:ns:xmpp-sasl'/>").unwrap();
+        assert!(matches!(
+            sasl.handle(success).unwrap(),
+            Some(SaslStep::Succeeded)
+        ));
+    }
+
+    #[test]
+    fn negotiation_failure_is_authentication_failed() {
+        let (mut sasl, _) =
+            SaslNegotiation::start(&plain_only_features(), "alice", "wrong").unwrap();
+        let failure = Element::from_str(
+            "<failure xmlns='urn:ietf:params:xml:ns:xmpp-sasl'><not-authorized/></failure>",
+        )
+        .unwrap();
+        assert!(matches!(
+            sasl.handle(failure),
+            Err(ConnectionError::AuthenticationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn bind_result_ignores_other_iqs() {
+        let other =
+            Element::from_str("<iq xmlns='jabber:client' type='result' id='ping-1'/>").unwrap();
+        assert!(bind_result(other).is_none());
+
+        let bound = Element::from_str(&format!(
+            "<iq xmlns='jabber:client' type='result' id='{BIND_REQUEST_ID}'/>"
+        ))
+        .unwrap();
+        assert!(matches!(bind_result(bound), Some(Ok(()))));
+    }
+
     #[test]
     fn selected_mechanism_display() {
         assert_eq!(SelectedMechanism::ScramSha256.to_string(), "SCRAM-SHA-256");
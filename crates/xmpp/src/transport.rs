@@ -1,22 +1,74 @@
 use crate::error::ConnectionError;
 
+pub use waddle_core::config::TransportKind;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ConnectionConfig {
     pub jid: String,
-    pub password: String,
+    /// Host for the `tcp` and `tls` transports. When unset, the JID's domain
+    /// is resolved through `_xmpp-client` / `_xmpps-client` SRV records.
     pub server: Option<String>,
+    /// Port for the `tcp` and `tls` transports; 5222 and 5223 when unset.
     pub port: Option<u16>,
     pub timeout_seconds: u32,
     pub max_reconnect_attempts: u32,
+    /// Transports tried by [`FallbackTransport`], in order. Empty uses
+    /// [`DEFAULT_TRANSPORT_ORDER`].
+    pub transports: Vec<TransportKind>,
+    /// WebSocket endpoint. When unset it is discovered through XEP-0156 in
+    /// browsers, or else `wss://<domain>/xmpp-websocket` is used.
+    pub websocket_url: Option<String>,
+    /// BOSH endpoint, discovered the same way; `https://<domain>/http-bind`
+    /// otherwise.
+    pub bosh_url: Option<String>,
+}
+
+#[cfg(feature = "native")]
+pub const DEFAULT_TRANSPORT_ORDER: &[TransportKind] = &[
+    TransportKind::Tcp,
+    TransportKind::Tls,
+    TransportKind::WebSocket,
+    TransportKind::Bosh,
+];
+
+#[cfg(not(feature = "native"))]
+pub const DEFAULT_TRANSPORT_ORDER: &[TransportKind] =
+    &[TransportKind::WebSocket, TransportKind::Bosh];
+
+impl ConnectionConfig {
+    /// The transports to try, in order, without duplicates.
+    pub fn transport_order(&self) -> Vec<TransportKind> {
+        let configured = if self.transports.is_empty() {
+            DEFAULT_TRANSPORT_ORDER
+        } else {
+            self.transports.as_slice()
+        };
+
+        let mut order = Vec::with_capacity(configured.len());
+        for kind in configured {
+            if !order.contains(kind) {
+                order.push(*kind);
+            }
+        }
+        order
+    }
 }
 
 /// Platform-abstracted XMPP transport.
 ///
 /// Feature-gated implementations provide the concrete transport:
 /// - `NativeTcpTransport` (native feature): TCP/TLS via tokio-xmpp + rustls
-/// - `WebSocketTransport` (web feature): WebSocket via tokio-tungstenite / web-sys
+/// - `WebSocketTransport`: RFC 7395 via tokio-tungstenite, or web-sys in browsers
+/// - `BoshTransport`: XEP-0206 long-polling over HTTPS via reqwest
+/// - `FallbackTransport`: tries the configured transports in order
+///
+/// The password is passed per connection attempt rather than stored in
+/// [`ConnectionConfig`], so it only lives in memory while authenticating.
 pub trait XmppTransport: Send + 'static {
-    fn connect(config: &ConnectionConfig) -> impl Future<Output = Result<Self, ConnectionError>>
+    fn connect(
+        config: &ConnectionConfig,
+        password: &str,
+    ) -> impl Future<Output = Result<Self, ConnectionError>>
     where
         Self: Sized;
 
@@ -29,18 +81,139 @@ pub trait XmppTransport: Send + 'static {
     fn supports_stream_management(&self) -> bool;
 }
 
+enum Connected {
+    #[cfg(feature = "native")]
+    Native(Box<NativeTcpTransport>),
+    WebSocket(Box<WebSocketTransport>),
+    Bosh(BoshTransport),
+}
+
+/// Connects with each transport from [`ConnectionConfig::transport_order`]
+/// until one succeeds. Authentication and credential failures stop the
+/// search, since every other transport would fail the same way.
+pub struct FallbackTransport {
+    kind: TransportKind,
+    inner: Connected,
+}
+
+impl FallbackTransport {
+    /// The transport that connected.
+    pub fn kind(&self) -> TransportKind {
+        self.kind
+    }
+
+    async fn connect_with(
+        kind: TransportKind,
+        config: &ConnectionConfig,
+        password: &str,
+    ) -> Result<Connected, ConnectionError> {
+        match kind {
+            #[cfg(feature = "native")]
+            TransportKind::Tcp => NativeTcpTransport::connect(config, password)
+                .await
+                .map(|transport| Connected::Native(Box::new(transport))),
+            #[cfg(feature = "native")]
+            TransportKind::Tls => NativeTcpTransport::connect_direct_tls(config, password)
+                .await
+                .map(|transport| Connected::Native(Box::new(transport))),
+            #[cfg(not(feature = "native"))]
+            TransportKind::Tcp | TransportKind::Tls => Err(ConnectionError::TransportError(
+                format!("the {kind} transport is not available in this build"),
+            )),
+            TransportKind::WebSocket => WebSocketTransport::connect(config, password)
+                .await
+                .map(|transport| Connected::WebSocket(Box::new(transport))),
+            TransportKind::Bosh => BoshTransport::connect(config, password)
+                .await
+                .map(Connected::Bosh),
+        }
+    }
+}
+
+impl XmppTransport for FallbackTransport {
+    async fn connect(config: &ConnectionConfig, password: &str) -> Result<Self, ConnectionError> {
+        let mut last_error = None;
+
+        for kind in config.transport_order() {
+            match Self::connect_with(kind, config, password).await {
+                Ok(inner) => {
+                    tracing::debug!(transport = %kind, "XMPP transport connected");
+                    return Ok(Self { kind, inner });
+                }
+                Err(error) if !error.is_retryable() => return Err(error),
+                Err(error) => {
+                    tracing::warn!(
+                        transport = %kind,
+                        %error,
+                        "XMPP transport failed; trying the next one"
+                    );
+                    last_error = Some(error);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            ConnectionError::TransportError("no XMPP transports configured".to_string())
+        }))
+    }
+
+    async fn send(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
+        match &mut self.inner {
+            #[cfg(feature = "native")]
+            Connected::Native(transport) => transport.send(data).await,
+            Connected::WebSocket(transport) => transport.send(data).await,
+            Connected::Bosh(transport) => transport.send(data).await,
+        }
+    }
+
+    async fn recv(&mut self) -> Result<Vec<u8>, ConnectionError> {
+        match &mut self.inner {
+            #[cfg(feature = "native")]
+            Connected::Native(transport) => transport.recv().await,
+            Connected::WebSocket(transport) => transport.recv().await,
+            Connected::Bosh(transport) => transport.recv().await,
+        }
+    }
+
+    async fn close(&mut self) -> Result<(), ConnectionError> {
+        match &mut self.inner {
+            #[cfg(feature = "native")]
+            Connected::Native(transport) => transport.close().await,
+            Connected::WebSocket(transport) => transport.close().await,
+            Connected::Bosh(transport) => transport.close().await,
+        }
+    }
+
+    fn supports_stream_management(&self) -> bool {
+        match &self.inner {
+            #[cfg(feature = "native")]
+            Connected::Native(transport) => transport.supports_stream_management(),
+            Connected::WebSocket(transport) => transport.supports_stream_management(),
+            Connected::Bosh(transport) => transport.supports_stream_management(),
+        }
+    }
+}
+
 #[cfg(feature = "native")]
 mod native {
     use super::*;
     use bytes::BytesMut;
     use std::{
-        sync::atomic::{AtomicBool, Ordering},
+        sync::{
+            Arc,
+            atomic::{AtomicBool, Ordering},
+        },
         time::Duration,
     };
     use tokio::{
         io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
         time::timeout,
     };
+    use tokio_rustls::{
+        TlsConnector,
+        rustls::{ClientConfig, RootCertStore, pki_types::ServerName},
+    };
     use tokio_util::codec::Decoder;
     use tokio_xmpp::{
         connect::{AsyncReadAndWrite, ServerConnector},
@@ -53,6 +226,9 @@ mod native {
     use tracing::warn;
 
     const DEFAULT_XMPP_PORT: u16 = 5222;
+    const DEFAULT_XMPPS_PORT: u16 = 5223;
+    /// ALPN protocol id for client connections over direct TLS (XEP-0368).
+    const XMPP_CLIENT_ALPN: &[u8] = b"xmpp-client";
     const INSECURE_TCP_ENV: &str = "WADDLE_XMPP_INSECURE_TCP";
     const MIN_TIMEOUT_SECONDS: u64 = 1;
     const RECV_BUFFER_SIZE: usize = 16 * 1024;
@@ -236,21 +412,55 @@ mod native {
         config: &ConnectionConfig,
         jid: &Jid,
         username: &str,
+        password: &str,
         io_timeout: Duration,
     ) -> Result<(Box<dyn AsyncReadAndWrite>, bool), ConnectionError> {
-        let server_config = to_server_config(config);
+        let connect_to = async |server_config: ServerConfig| {
+            let xmpp_stream = timeout(io_timeout, server_config.connect(jid, ns::JABBER_CLIENT))
+                .await
+                .map_err(|_| ConnectionError::Timeout)?
+                .map_err(map_starttls_error)?;
+
+            authenticate_stream(xmpp_stream, username, password, io_timeout).await
+        };
+
+        match to_server_config(config) {
+            ServerConfig::UseSrv => {
+                srv::connect_any(
+                    jid.domain().as_str(),
+                    srv::XMPP_CLIENT_SERVICE,
+                    DEFAULT_XMPP_PORT,
+                    async |target| {
+                        connect_to(ServerConfig::Manual {
+                            host: target.host.clone(),
+                            port: target.port,
+                        })
+                        .await
+                    },
+                )
+                .await
+            }
+            server_config => connect_to(server_config).await,
+        }
+    }
+
+    async fn open_starttls(
+        server_config: ServerConfig,
+        jid: &Jid,
+        io_timeout: Duration,
+    ) -> Result<Box<dyn AsyncReadAndWrite>, ConnectionError> {
         let xmpp_stream = timeout(io_timeout, server_config.connect(jid, ns::JABBER_CLIENT))
             .await
             .map_err(|_| ConnectionError::Timeout)?
             .map_err(map_starttls_error)?;
-
-        authenticate_stream(xmpp_stream, username, &config.password, io_timeout).await
+        Ok(Box::new(xmpp_stream.into_inner()))
     }
 
     async fn connect_via_insecure_tcp(
         config: &ConnectionConfig,
         jid: &Jid,
         username: &str,
+        password: &str,
         io_timeout: Duration,
     ) -> Result<(Box<dyn AsyncReadAndWrite>, bool), ConnectionError> {
         let address = insecure_tcp_target(config, jid);
@@ -260,20 +470,165 @@ mod native {
             .map_err(|_| ConnectionError::Timeout)?
             .map_err(map_tcp_error)?;
 
-        authenticate_stream(xmpp_stream, username, &config.password, io_timeout).await
+        authenticate_stream(xmpp_stream, username, password, io_timeout).await
+    }
+
+    async fn connect_via_direct_tls(
+        config: &ConnectionConfig,
+        jid: &Jid,
+        username: &str,
+        password: &str,
+        io_timeout: Duration,
+    ) -> Result<(Box<dyn AsyncReadAndWrite>, bool), ConnectionError> {
+        match &config.server {
+            Some(host) => {
+                let port = config.port.unwrap_or(DEFAULT_XMPPS_PORT);
+                connect_direct_tls_to(host, port, jid, username, password, io_timeout).await
+            }
+            None => {
+                srv::connect_any(
+                    jid.domain().as_str(),
+                    srv::XMPPS_CLIENT_SERVICE,
+                    DEFAULT_XMPPS_PORT,
+                    async |target| {
+                        connect_direct_tls_to(
+                            &target.host,
+                            target.port,
+                            jid,
+                            username,
+                            password,
+                            io_timeout,
+                        )
+                        .await
+                    },
+                )
+                .await
+            }
+        }
+    }
+
+    async fn connect_direct_tls_to(
+        host: &str,
+        port: u16,
+        jid: &Jid,
+        username: &str,
+        password: &str,
+        io_timeout: Duration,
+    ) -> Result<(Box<dyn AsyncReadAndWrite>, bool), ConnectionError> {
+        let tcp_stream = timeout(io_timeout, TcpStream::connect((host, port)))
+            .await
+            .map_err(|_| ConnectionError::Timeout)?
+            .map_err(map_io_error)?;
+
+        let mut tls_config = ClientConfig::builder()
+            .with_root_certificates(RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.into(),
+            })
+            .with_no_client_auth();
+        tls_config.alpn_protocols = vec![XMPP_CLIENT_ALPN.to_vec()];
+
+        // The certificate must match the XMPP domain, not the host dialled.
+        let server_name = ServerName::try_from(jid.domain().to_string())
+            .map_err(|error| ConnectionError::TlsHandshakeFailed(error.to_string()))?;
+        let tls_stream = timeout(
+            io_timeout,
+            TlsConnector::from(Arc::new(tls_config)).connect(server_name, tcp_stream),
+        )
+        .await
+        .map_err(|_| ConnectionError::Timeout)?
+        .map_err(|error| ConnectionError::TlsHandshakeFailed(error.to_string()))?;
+
+        let xmpp_stream = timeout(
+            io_timeout,
+            XMPPStream::start(tls_stream, jid.clone(), ns::JABBER_CLIENT.to_string()),
+        )
+        .await
+        .map_err(|_| ConnectionError::Timeout)?
+        .map_err(|error| ConnectionError::StreamError(error.to_string()))?;
+
+        authenticate_stream(xmpp_stream, username, password, io_timeout).await
+    }
+
+    fn sasl_username(config: &ConnectionConfig, jid: &Jid) -> Result<String, ConnectionError> {
+        jid.node().map(|node| node.to_string()).ok_or_else(|| {
+            ConnectionError::AuthenticationFailed(format!(
+                "JID '{}' has no local part for SASL authentication",
+                config.jid
+            ))
+        })
+    }
+
+    impl NativeTcpTransport {
+        fn from_authenticated(
+            stream: Box<dyn AsyncReadAndWrite>,
+            io_timeout: Duration,
+            stream_management_supported: bool,
+        ) -> Self {
+            Self {
+                stream,
+                io_timeout,
+                stream_management_supported,
+                inbound_codec: prime_inbound_codec(),
+                inbound_buffer: BytesMut::with_capacity(RECV_BUFFER_SIZE),
+            }
+        }
+
+        /// Connect with TLS from the first byte (XEP-0368) rather than
+        /// upgrading with STARTTLS. Uses `config.server` and `config.port`
+        /// (5223 when unset), or the `_xmpps-client` SRV targets without a
+        /// server.
+        pub async fn connect_direct_tls(
+            config: &ConnectionConfig,
+            password: &str,
+        ) -> Result<Self, ConnectionError> {
+            let jid = parse_jid(&config.jid)?;
+            let io_timeout = connect_timeout(config);
+            let username = sasl_username(config, &jid)?;
+
+            let (stream, stream_management_supported) =
+                connect_via_direct_tls(config, &jid, &username, password, io_timeout).await?;
+            Ok(Self::from_authenticated(
+                stream,
+                io_timeout,
+                stream_management_supported,
+            ))
+        }
+
+        /// Open a stream without authenticating, for in-band registration
+        /// (XEP-0077). `config.jid` may be a bare domain. The stream is
+        /// upgraded with STARTTLS unless `WADDLE_XMPP_INSECURE_TCP` is set.
+        pub async fn connect_unauthenticated(
+            config: &ConnectionConfig,
+        ) -> Result<Self, ConnectionError> {
+            let jid = parse_jid(&config.jid)?;
+            let io_timeout = connect_timeout(config);
+
+            let stream: Box<dyn AsyncReadAndWrite> = if insecure_tcp_env_override() == Some(true) {
+                let connector = TcpServerConnector::new(insecure_tcp_target(config, &jid));
+                let xmpp_stream = timeout(io_timeout, connector.connect(&jid, ns::JABBER_CLIENT))
+                    .await
+                    .map_err(|_| ConnectionError::Timeout)?
+                    .map_err(map_tcp_error)?;
+                Box::new(xmpp_stream.into_inner())
+            } else {
+                // Without a configured server tokio-xmpp resolves the SRV
+                // records itself.
+                open_starttls(to_server_config(config), &jid, io_timeout).await?
+            };
+
+            Ok(Self::from_authenticated(stream, io_timeout, false))
+        }
     }
 
     impl XmppTransport for NativeTcpTransport {
-        async fn connect(config: &ConnectionConfig) -> Result<Self, ConnectionError> {
+        async fn connect(
+            config: &ConnectionConfig,
+            password: &str,
+        ) -> Result<Self, ConnectionError> {
             let jid = parse_jid(&config.jid)?;
             let io_timeout = connect_timeout(config);
 
-            let username = jid.node().ok_or_else(|| {
-                ConnectionError::AuthenticationFailed(format!(
-                    "JID '{}' has no local part for SASL authentication",
-                    config.jid
-                ))
-            })?;
+            let username = sasl_username(config, &jid)?;
 
             let insecure_override = insecure_tcp_env_override();
             let loopback_target = is_local_loopback_target(config, &jid);
@@ -284,9 +639,10 @@ mod native {
 
             let (stream, stream_management_supported): (Box<dyn AsyncReadAndWrite>, bool) =
                 if prefer_insecure {
-                    connect_via_insecure_tcp(config, &jid, username.as_str(), io_timeout).await?
+                    connect_via_insecure_tcp(config, &jid, &username, password, io_timeout).await?
                 } else {
-                    match connect_via_starttls(config, &jid, username.as_str(), io_timeout).await {
+                    match connect_via_starttls(config, &jid, &username, password, io_timeout).await
+                    {
                         Ok(result) => {
                             if loopback_target {
                                 LOOPBACK_TLS_FAILED.store(false, Ordering::Relaxed);
@@ -304,20 +660,18 @@ mod native {
                                 env = INSECURE_TCP_ENV,
                                 "TLS failed against loopback target; retrying with insecure TCP"
                             );
-                            connect_via_insecure_tcp(config, &jid, username.as_str(), io_timeout)
+                            connect_via_insecure_tcp(config, &jid, &username, password, io_timeout)
                                 .await?
                         }
                         Err(error) => return Err(error),
                     }
                 };
 
-            Ok(Self {
+            Ok(Self::from_authenticated(
                 stream,
                 io_timeout,
                 stream_management_supported,
-                inbound_codec: prime_inbound_codec(),
-                inbound_buffer: BytesMut::with_capacity(RECV_BUFFER_SIZE),
-            })
+            ))
         }
 
         async fn send(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
@@ -344,10 +698,9 @@ mod native {
                     .inbound_codec
                     .decode(&mut self.inbound_buffer)
                     .map_err(|error| ConnectionError::TransportError(error.to_string()))?
+                    && let Some(payload) = serialize_packet(packet)?
                 {
-                    if let Some(payload) = serialize_packet(packet)? {
-                        return Ok(payload);
-                    }
+                    return Ok(payload);
                 }
 
                 let mut chunk = vec![0_u8; RECV_BUFFER_SIZE];
@@ -380,65 +733,46 @@ mod native {
     }
 }
 
-#[cfg(feature = "web")]
-mod web {
-    use super::*;
-    use std::time::Duration;
-    use tokio::time::timeout;
-    use xmpp_parsers::jid::Jid;
+const MIN_TIMEOUT_SECONDS: u64 = 1;
 
-    const DEFAULT_WEBSOCKET_PORT: u16 = 443;
-    const MIN_TIMEOUT_SECONDS: u64 = 1;
-    #[cfg(any(test, target_arch = "wasm32"))]
-    const XEP_0156_WEBSOCKET_REL: &str = "urn:xmpp:alt-connections:websocket";
-
-    fn connect_timeout(config: &ConnectionConfig) -> Duration {
-        Duration::from_secs(u64::from(config.timeout_seconds).max(MIN_TIMEOUT_SECONDS))
-    }
-
-    fn jid_domain(jid: &str) -> Result<String, ConnectionError> {
-        jid.parse::<Jid>()
-            .map(|parsed| parsed.domain().to_string())
-            .map_err(|error| {
-                ConnectionError::TransportError(format!("invalid JID '{jid}' in config: {error}"))
-            })
-    }
-
-    fn server_to_websocket_url(server: &str, default_port: u16) -> Result<String, ConnectionError> {
-        if server.starts_with("ws://") || server.starts_with("wss://") {
-            return Ok(server.to_string());
-        }
+fn io_timeout(config: &ConnectionConfig) -> std::time::Duration {
+    std::time::Duration::from_secs(u64::from(config.timeout_seconds).max(MIN_TIMEOUT_SECONDS))
+}
 
-        if server.contains("://") {
-            return Err(ConnectionError::TransportError(format!(
-                "unsupported WebSocket scheme for server '{server}'"
-            )));
-        }
+fn parse_config_jid(jid: &str) -> Result<xmpp_parsers::jid::Jid, ConnectionError> {
+    jid.parse().map_err(|error| {
+        ConnectionError::TransportError(format!("invalid JID '{jid}' in config: {error}"))
+    })
+}
 
-        let host_or_path = server.trim_matches('/');
-        if host_or_path.is_empty() {
-            return Err(ConnectionError::TransportError(
-                "server value cannot be empty".to_string(),
-            ));
-        }
+#[cfg(not(target_arch = "wasm32"))]
+async fn with_timeout<R>(
+    io_timeout: std::time::Duration,
+    operation: impl Future<Output = Result<R, ConnectionError>>,
+) -> Result<R, ConnectionError> {
+    tokio::time::timeout(io_timeout, operation)
+        .await
+        .map_err(|_| ConnectionError::Timeout)?
+}
 
-        if host_or_path.contains('/') {
-            return Ok(format!("wss://{host_or_path}"));
-        }
+// Browsers give tokio no timer; the socket's or request's own error events
+// end a stalled exchange instead.
+#[cfg(target_arch = "wasm32")]
+async fn with_timeout<R>(
+    _io_timeout: std::time::Duration,
+    operation: impl Future<Output = Result<R, ConnectionError>>,
+) -> Result<R, ConnectionError> {
+    operation.await
+}
 
-        let has_explicit_port = host_or_path
-            .rsplit_once(':')
-            .map(|(_, suffix)| suffix.chars().all(|character| character.is_ascii_digit()))
-            .unwrap_or(false);
+/// XEP-0156 lookup of WebSocket and BOSH endpoints through the domain's
+/// `host-meta` document. Only browsers can fetch it; elsewhere nothing is
+/// discovered.
+mod discovery {
+    use crate::error::ConnectionError;
 
-        if has_explicit_port {
-            Ok(format!("wss://{host_or_path}/xmpp-websocket"))
-        } else {
-            Ok(format!(
-                "wss://{host_or_path}:{default_port}/xmpp-websocket"
-            ))
-        }
-    }
+    pub(super) const WEBSOCKET_REL: &str = "urn:xmpp:alt-connections:websocket";
+    pub(super) const BOSH_REL: &str = "urn:xmpp:alt-connections:xbosh";
 
     #[cfg(any(test, target_arch = "wasm32"))]
     fn extract_xml_attribute(tag: &str, attribute: &str) -> Option<String> {
@@ -455,7 +789,7 @@ mod web {
     }
 
     #[cfg(any(test, target_arch = "wasm32"))]
-    fn parse_host_meta_websocket_endpoint(host_meta: &str) -> Option<String> {
+    pub(super) fn parse_host_meta_endpoint(host_meta: &str, link_rel: &str) -> Option<String> {
         host_meta.split('<').skip(1).find_map(|segment| {
             let trimmed = segment.trim_start();
             let lower = trimmed.to_ascii_lowercase();
@@ -464,7 +798,7 @@ mod web {
             }
 
             let rel = extract_xml_attribute(trimmed, "rel")?;
-            if rel != XEP_0156_WEBSOCKET_REL {
+            if rel != link_rel {
                 return None;
             }
 
@@ -473,7 +807,10 @@ mod web {
     }
 
     #[cfg(target_arch = "wasm32")]
-    async fn discover_xep0156_endpoint(domain: &str) -> Result<Option<String>, ConnectionError> {
+    pub(super) async fn discover_xep0156_endpoint(
+        domain: &str,
+        link_rel: &str,
+    ) -> Result<Option<String>, ConnectionError> {
         use wasm_bindgen::JsCast;
         use wasm_bindgen_futures::JsFuture;
         use web_sys::Response;
@@ -514,92 +851,312 @@ mod web {
             )
         })?;
 
-        Ok(parse_host_meta_websocket_endpoint(&host_meta))
+        Ok(parse_host_meta_endpoint(&host_meta, link_rel))
     }
 
     #[cfg(not(target_arch = "wasm32"))]
-    async fn discover_xep0156_endpoint(_domain: &str) -> Result<Option<String>, ConnectionError> {
+    pub(super) async fn discover_xep0156_endpoint(
+        _domain: &str,
+        _link_rel: &str,
+    ) -> Result<Option<String>, ConnectionError> {
         Ok(None)
     }
 
-    async fn resolve_websocket_url(config: &ConnectionConfig) -> Result<String, ConnectionError> {
-        let default_port = config.port.unwrap_or(DEFAULT_WEBSOCKET_PORT);
-        if let Some(server) = config.server.as_deref() {
-            return server_to_websocket_url(server, default_port);
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn host_meta_parser_finds_requested_link() {
+            let host_meta = r#"<?xml version='1.0'?>
+<XRD xmlns='http://docs.oasis-open.org/ns/xri/xrd-1.0'>
+    <Link rel='urn:xmpp:alt-connections:xbosh' href='https://xmpp.example.com/http-bind'/>
+    <Link rel='urn:xmpp:alt-connections:websocket' href='wss://xmpp.example.com/ws'/>
+</XRD>"#;
+
+            assert_eq!(
+                parse_host_meta_endpoint(host_meta, WEBSOCKET_REL),
+                Some("wss://xmpp.example.com/ws".to_string())
+            );
+            assert_eq!(
+                parse_host_meta_endpoint(host_meta, BOSH_REL),
+                Some("https://xmpp.example.com/http-bind".to_string())
+            );
         }
+    }
+}
 
-        let domain = jid_domain(&config.jid)?;
-        if let Some(discovered_url) = discover_xep0156_endpoint(&domain).await? {
-            return Ok(discovered_url);
+mod bosh;
+#[cfg(feature = "native")]
+mod srv;
+
+mod websocket {
+    use super::*;
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use tracing::debug;
+    use xmpp_parsers::{jid::Jid, minidom::Element, ns, stream_features::StreamFeatures};
+
+    use crate::sasl::{SaslNegotiation, SaslStep, bind_request, bind_result};
+
+    const DEFAULT_WEBSOCKET_PORT: u16 = 443;
+    const FRAMING_NS: &str = "urn:ietf:params:xml:ns:xmpp-framing";
+    /// The `Sec-WebSocket-Protocol` value registered by RFC 7395.
+    const XMPP_SUBPROTOCOL: &str = "xmpp";
+
+    fn server_to_websocket_url(server: &str, default_port: u16) -> Result<String, ConnectionError> {
+        if server.starts_with("ws://") || server.starts_with("wss://") {
+            return Ok(server.to_string());
+        }
+
+        if server.contains("://") {
+            return Err(ConnectionError::TransportError(format!(
+                "unsupported WebSocket scheme for server '{server}'"
+            )));
         }
 
-        Ok(format!("wss://{domain}:{default_port}/xmpp-websocket"))
+        let host_or_path = server.trim_matches('/');
+        if host_or_path.is_empty() {
+            return Err(ConnectionError::TransportError(
+                "server value cannot be empty".to_string(),
+            ));
+        }
+
+        if host_or_path.contains('/') {
+            return Ok(format!("wss://{host_or_path}"));
+        }
+
+        let has_explicit_port = host_or_path
+            .rsplit_once(':')
+            .map(|(_, suffix)| suffix.chars().all(|character| character.is_ascii_digit()))
+            .unwrap_or(false);
+
+        if has_explicit_port {
+            Ok(format!("wss://{host_or_path}/xmpp-websocket"))
+        } else {
+            Ok(format!(
+                "wss://{host_or_path}:{default_port}/xmpp-websocket"
+            ))
+        }
     }
 
-    #[cfg(not(target_arch = "wasm32"))]
-    type BlockingWebSocket = tokio_tungstenite::tungstenite::WebSocket<
-        tokio_tungstenite::tungstenite::stream::MaybeTlsStream<std::net::TcpStream>,
-    >;
+    async fn resolve_websocket_url(config: &ConnectionConfig) -> Result<String, ConnectionError> {
+        if let Some(url) = config.websocket_url.as_deref() {
+            return server_to_websocket_url(url, DEFAULT_WEBSOCKET_PORT);
+        }
 
-    #[cfg(not(target_arch = "wasm32"))]
-    fn map_websocket_error(error: tokio_tungstenite::tungstenite::Error) -> ConnectionError {
-        let message = error.to_string();
-        let lower = message.to_ascii_lowercase();
-        if lower.contains("dns")
-            || lower.contains("resolve")
-            || lower.contains("unable to connect")
-            || lower.contains("failed to lookup")
+        // `server` and `port` describe the TCP endpoint unless WebSocket is
+        // the only transport or the server is already a WebSocket URL.
+        if let Some(server) = config.server.as_deref()
+            && (server.starts_with("ws://")
+                || server.starts_with("wss://")
+                || config.transport_order() == [TransportKind::WebSocket])
         {
-            ConnectionError::DnsResolutionFailed(message)
-        } else if lower.contains("tls")
-            || lower.contains("certificate")
-            || lower.contains("handshake")
-            || lower.contains("tlsfeaturenotenabled")
+            return server_to_websocket_url(server, config.port.unwrap_or(DEFAULT_WEBSOCKET_PORT));
+        }
+
+        let domain = parse_config_jid(&config.jid)?.domain().to_string();
+        if let Some(discovered_url) =
+            discovery::discover_xep0156_endpoint(&domain, discovery::WEBSOCKET_REL).await?
         {
-            ConnectionError::TlsHandshakeFailed(message)
+            return Ok(discovered_url);
+        }
+
+        Ok(format!(
+            "wss://{domain}:{DEFAULT_WEBSOCKET_PORT}/xmpp-websocket"
+        ))
+    }
+
+    /// Whether the server agreed to the `xmpp` subprotocol; RFC 7395 §3.3
+    /// requires closing the connection otherwise.
+    fn subprotocol_accepted(protocol: Option<&str>) -> bool {
+        protocol.is_some_and(|protocol| protocol.trim().eq_ignore_ascii_case(XMPP_SUBPROTOCOL))
+    }
+
+    /// `domain` comes from a parsed JID, so it needs no attribute escaping.
+    fn open_frame(domain: &str) -> String {
+        format!("<open xmlns='{FRAMING_NS}' to='{domain}' version='1.0'/>")
+    }
+
+    fn close_frame() -> String {
+        format!("<close xmlns='{FRAMING_NS}'/>")
+    }
+
+    /// One RFC 7395 message: each carries exactly one complete element.
+    #[derive(Debug)]
+    enum Frame {
+        Open,
+        Close { see_other_uri: Option<String> },
+        Element(Element),
+    }
+
+    fn parse_frame(text: &str) -> Result<Frame, ConnectionError> {
+        let element = Element::from_str(text.trim()).map_err(|error| {
+            ConnectionError::StreamError(format!("invalid XMPP-over-WebSocket frame: {error}"))
+        })?;
+
+        if element.is("open", FRAMING_NS) {
+            Ok(Frame::Open)
+        } else if element.is("close", FRAMING_NS) {
+            Ok(Frame::Close {
+                see_other_uri: element.attr("see-other-uri").map(str::to_string),
+            })
         } else {
-            ConnectionError::TransportError(message)
+            Ok(Frame::Element(element))
         }
     }
 
-    #[cfg(not(target_arch = "wasm32"))]
-    async fn run_blocking_with_timeout<R, F>(
-        io_timeout: Duration,
-        operation: F,
-    ) -> Result<R, ConnectionError>
-    where
-        R: Send + 'static,
-        F: FnOnce() -> Result<R, ConnectionError> + Send + 'static,
-    {
-        timeout(io_timeout, tokio::task::spawn_blocking(operation))
-            .await
-            .map_err(|_| ConnectionError::Timeout)?
-            .map_err(|error| ConnectionError::TransportError(error.to_string()))?
+    fn closed_by_server(see_other_uri: Option<String>) -> ConnectionError {
+        match see_other_uri {
+            Some(uri) => ConnectionError::TransportError(format!(
+                "XMPP stream closed by server; reconnect to '{uri}'"
+            )),
+            None => ConnectionError::TransportError("XMPP stream closed by server".to_string()),
+        }
     }
 
-    #[cfg(not(target_arch = "wasm32"))]
+    /// XMPP over WebSocket (RFC 7395): the stream is opened with framed
+    /// `<open/>` elements, authenticated with SASL, bound, and closed with a
+    /// `<close/>` exchange before the WebSocket itself is closed.
     pub struct WebSocketTransport {
-        socket: std::sync::Arc<std::sync::Mutex<BlockingWebSocket>>,
+        socket: WebSocketChannel,
+        domain: String,
         io_timeout: Duration,
+        stream_management_supported: bool,
+        closed: bool,
+    }
+
+    impl WebSocketTransport {
+        async fn send_text(&mut self, text: String) -> Result<(), ConnectionError> {
+            with_timeout(self.io_timeout, self.socket.send_text(text)).await
+        }
+
+        async fn send_element(&mut self, element: Element) -> Result<(), ConnectionError> {
+            self.send_text(String::from(&element)).await
+        }
+
+        async fn next_element(&mut self) -> Result<Element, ConnectionError> {
+            loop {
+                let text = self.socket.recv_text().await?.ok_or_else(|| {
+                    ConnectionError::TransportError("websocket closed by peer".to_string())
+                })?;
+                match parse_frame(&text)? {
+                    Frame::Element(element) if element.is("error", ns::STREAM) => {
+                        return Err(ConnectionError::StreamError(format!(
+                            "server sent stream error: {}",
+                            String::from(&element)
+                        )));
+                    }
+                    Frame::Element(element) => return Ok(element),
+                    Frame::Open => {}
+                    Frame::Close { see_other_uri } => {
+                        self.closed = true;
+                        return Err(closed_by_server(see_other_uri));
+                    }
+                }
+            }
+        }
+
+        async fn open_stream(&mut self) -> Result<StreamFeatures, ConnectionError> {
+            self.send_text(open_frame(&self.domain)).await?;
+            loop {
+                let element = self.next_element().await?;
+                if element.is("features", ns::STREAM) {
+                    return StreamFeatures::try_from(element).map_err(|error| {
+                        ConnectionError::StreamError(format!("invalid stream features: {error}"))
+                    });
+                }
+            }
+        }
+
+        async fn authenticate(
+            &mut self,
+            features: &StreamFeatures,
+            username: &str,
+            password: &str,
+        ) -> Result<(), ConnectionError> {
+            let (mut sasl, auth) = SaslNegotiation::start(features, username, password)?;
+            self.send_element(auth).await?;
+
+            loop {
+                let element = self.next_element().await?;
+                match sasl.handle(element)? {
+                    Some(SaslStep::Respond(response)) => self.send_element(response).await?,
+                    Some(SaslStep::Succeeded) => return Ok(()),
+                    None => {}
+                }
+            }
+        }
+
+        async fn bind(&mut self, resource: Option<String>) -> Result<(), ConnectionError> {
+            self.send_element(bind_request(resource)).await?;
+
+            loop {
+                let element = self.next_element().await?;
+                if let Some(result) = bind_result(element) {
+                    return result;
+                }
+            }
+        }
+
+        async fn negotiate(&mut self, jid: &Jid, password: &str) -> Result<(), ConnectionError> {
+            let username = jid.node().ok_or_else(|| {
+                ConnectionError::AuthenticationFailed(format!(
+                    "JID '{jid}' has no local part for SASL authentication"
+                ))
+            })?;
+
+            let features = self.open_stream().await?;
+            self.authenticate(&features, username.as_str(), password)
+                .await?;
+
+            // SASL success restarts the stream with a fresh <open/>.
+            let features = self.open_stream().await?;
+            self.stream_management_supported = features.stream_management.is_some();
+            if features.can_bind() {
+                self.bind(jid.resource().map(|resource| resource.to_string()))
+                    .await?;
+            }
+            Ok(())
+        }
+
+        /// Wait for the server's `<close/>` or for the socket to drop.
+        async fn await_close(&mut self) -> Result<(), ConnectionError> {
+            while let Some(text) = self.socket.recv_text().await? {
+                if matches!(parse_frame(&text), Ok(Frame::Close { .. })) {
+                    break;
+                }
+            }
+            Ok(())
+        }
     }
 
-    #[cfg(not(target_arch = "wasm32"))]
     impl XmppTransport for WebSocketTransport {
-        async fn connect(config: &ConnectionConfig) -> Result<Self, ConnectionError> {
+        async fn connect(
+            config: &ConnectionConfig,
+            password: &str,
+        ) -> Result<Self, ConnectionError> {
+            let jid = parse_config_jid(&config.jid)?;
             let url = resolve_websocket_url(config).await?;
-            let io_timeout = connect_timeout(config);
-
-            let websocket = run_blocking_with_timeout(io_timeout, move || {
-                tokio_tungstenite::tungstenite::connect(url.as_str())
-                    .map(|(socket, _response)| socket)
-                    .map_err(map_websocket_error)
-            })
-            .await?;
+            let io_timeout = io_timeout(config);
 
-            Ok(Self {
-                socket: std::sync::Arc::new(std::sync::Mutex::new(websocket)),
+            let socket = with_timeout(io_timeout, WebSocketChannel::open(&url)).await?;
+            let mut transport = Self {
+                socket,
+                domain: jid.domain().to_string(),
                 io_timeout,
-            })
+                stream_management_supported: false,
+                closed: false,
+            };
+
+            if let Err(error) = with_timeout(io_timeout, transport.negotiate(&jid, password)).await
+            {
+                let _ = transport.socket.close().await;
+                return Err(error);
+            }
+            debug!(%url, "XMPP-over-WebSocket stream established");
+            Ok(transport)
         }
 
         async fn send(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
@@ -612,202 +1169,315 @@ mod web {
                     "RFC 7395 requires UTF-8 text frames; invalid payload: {error}"
                 ))
             })?;
-            let message = tokio_tungstenite::tungstenite::Message::Text(text.to_string().into());
-            let socket = std::sync::Arc::clone(&self.socket);
-
-            run_blocking_with_timeout(self.io_timeout, move || {
-                let mut websocket = socket.lock().map_err(|_| {
-                    ConnectionError::TransportError("websocket state mutex poisoned".to_string())
-                })?;
-                websocket.send(message).map_err(map_websocket_error)
-            })
-            .await
+            self.send_text(text.to_string()).await
         }
 
         async fn recv(&mut self) -> Result<Vec<u8>, ConnectionError> {
-            let socket = std::sync::Arc::clone(&self.socket);
-
-            run_blocking_with_timeout(self.io_timeout, move || {
-                loop {
-                    let message = {
-                        let mut websocket = socket.lock().map_err(|_| {
-                            ConnectionError::TransportError(
-                                "websocket state mutex poisoned".to_string(),
-                            )
-                        })?;
-                        websocket.read().map_err(map_websocket_error)?
-                    };
-
-                    match message {
-                        tokio_tungstenite::tungstenite::Message::Text(text) => {
-                            return Ok(text.to_string().into_bytes());
-                        }
-                        tokio_tungstenite::tungstenite::Message::Binary(bytes) => {
-                            return Ok(bytes.to_vec());
-                        }
-                        tokio_tungstenite::tungstenite::Message::Close(_) => {
-                            return Err(ConnectionError::TransportError(
-                                "websocket closed by peer".to_string(),
-                            ));
-                        }
-                        tokio_tungstenite::tungstenite::Message::Ping(_)
-                        | tokio_tungstenite::tungstenite::Message::Pong(_)
-                        | tokio_tungstenite::tungstenite::Message::Frame(_) => {}
+            loop {
+                let text = self.socket.recv_text().await?.ok_or_else(|| {
+                    ConnectionError::TransportError("websocket closed by peer".to_string())
+                })?;
+                match parse_frame(&text)? {
+                    Frame::Element(_) => return Ok(text.into_bytes()),
+                    Frame::Open => {}
+                    Frame::Close { see_other_uri } => {
+                        // Acknowledge the server's <close/> before it drops
+                        // the WebSocket (RFC 7395 §3.6).
+                        self.closed = true;
+                        let _ = self.send_text(close_frame()).await;
+                        let _ = self.socket.close().await;
+                        return Err(closed_by_server(see_other_uri));
                     }
                 }
-            })
-            .await
+            }
         }
 
         async fn close(&mut self) -> Result<(), ConnectionError> {
-            let socket = std::sync::Arc::clone(&self.socket);
-
-            run_blocking_with_timeout(self.io_timeout, move || {
-                let mut websocket = socket.lock().map_err(|_| {
-                    ConnectionError::TransportError("websocket state mutex poisoned".to_string())
-                })?;
-                websocket.close(None).map_err(map_websocket_error)
-            })
-            .await
+            if !self.closed {
+                self.closed = true;
+                self.send_text(close_frame()).await?;
+                if let Err(error) = with_timeout(self.io_timeout, self.await_close()).await {
+                    debug!(%error, "server did not acknowledge XMPP stream close");
+                }
+            }
+            self.socket.close().await
         }
 
         fn supports_stream_management(&self) -> bool {
-            false
+            self.stream_management_supported
         }
     }
 
-    #[cfg(target_arch = "wasm32")]
-    enum WebSocketCommand {
-        Send(String),
-        Close,
-    }
+    #[cfg(not(target_arch = "wasm32"))]
+    use self::tungstenite_channel::WebSocketChannel;
 
-    #[cfg(target_arch = "wasm32")]
-    pub struct WebSocketTransport {
-        outbound: tokio::sync::mpsc::UnboundedSender<WebSocketCommand>,
-        inbound: tokio::sync::mpsc::UnboundedReceiver<Result<Vec<u8>, ConnectionError>>,
-    }
+    #[cfg(not(target_arch = "wasm32"))]
+    mod tungstenite_channel {
+        use futures::{SinkExt, StreamExt};
+        use tokio_tungstenite::{
+            MaybeTlsStream, WebSocketStream,
+            tungstenite::{
+                Error as WebSocketError, Message,
+                client::IntoClientRequest,
+                http::{HeaderValue, header::SEC_WEBSOCKET_PROTOCOL},
+            },
+        };
 
-    #[cfg(target_arch = "wasm32")]
-    async fn run_browser_websocket(
-        socket: web_sys::WebSocket,
-        mut outbound: tokio::sync::mpsc::UnboundedReceiver<WebSocketCommand>,
-        inbound: tokio::sync::mpsc::UnboundedSender<Result<Vec<u8>, ConnectionError>>,
-    ) {
-        use wasm_bindgen::{JsCast, closure::Closure};
-        use web_sys::{CloseEvent, ErrorEvent, MessageEvent};
-
-        let inbound_for_message = inbound.clone();
-        let onmessage = Closure::wrap(Box::new(move |event: MessageEvent| {
-            if let Some(text) = event.data().as_string() {
-                let _ = inbound_for_message.send(Ok(text.into_bytes()));
-                return;
-            }
-
-            let _ = inbound_for_message.send(Err(ConnectionError::TransportError(
-                "received non-text WebSocket frame".to_string(),
-            )));
-        }) as Box<dyn FnMut(MessageEvent)>);
-        socket.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
-        onmessage.forget();
-
-        let inbound_for_error = inbound.clone();
-        let onerror = Closure::wrap(Box::new(move |event: ErrorEvent| {
-            let _ = inbound_for_error.send(Err(ConnectionError::TransportError(format!(
-                "websocket error: {}",
-                event.message()
-            ))));
-        }) as Box<dyn FnMut(ErrorEvent)>);
-        socket.set_onerror(Some(onerror.as_ref().unchecked_ref()));
-        onerror.forget();
-
-        let inbound_for_close = inbound.clone();
-        let onclose = Closure::wrap(Box::new(move |event: CloseEvent| {
-            let _ = inbound_for_close.send(Err(ConnectionError::TransportError(format!(
-                "websocket closed: code={} reason={}",
-                event.code(),
-                event.reason()
-            ))));
-        }) as Box<dyn FnMut(CloseEvent)>);
-        socket.set_onclose(Some(onclose.as_ref().unchecked_ref()));
-        onclose.forget();
-
-        while let Some(command) = outbound.recv().await {
-            match command {
-                WebSocketCommand::Send(message) => {
-                    if socket.ready_state() != web_sys::WebSocket::OPEN {
-                        let _ = inbound.send(Err(ConnectionError::TransportError(
-                            "websocket is not open".to_string(),
-                        )));
-                        continue;
-                    }
-                    if let Err(error) = socket.send_with_str(&message) {
-                        let _ = inbound.send(Err(ConnectionError::TransportError(format!(
-                            "failed to send websocket frame: {error:?}"
-                        ))));
+        use super::{XMPP_SUBPROTOCOL, subprotocol_accepted};
+        use crate::error::ConnectionError;
+
+        fn map_websocket_error(error: WebSocketError) -> ConnectionError {
+            let message = error.to_string();
+            let lower = message.to_ascii_lowercase();
+            if lower.contains("dns")
+                || lower.contains("resolve")
+                || lower.contains("unable to connect")
+                || lower.contains("failed to lookup")
+            {
+                ConnectionError::DnsResolutionFailed(message)
+            } else if lower.contains("tls")
+                || lower.contains("certificate")
+                || lower.contains("handshake")
+                || lower.contains("tlsfeaturenotenabled")
+            {
+                ConnectionError::TlsHandshakeFailed(message)
+            } else {
+                ConnectionError::TransportError(message)
+            }
+        }
+
+        pub(super) struct WebSocketChannel {
+            socket: WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>,
+        }
+
+        impl WebSocketChannel {
+            pub(super) async fn open(url: &str) -> Result<Self, ConnectionError> {
+                let mut request = url.into_client_request().map_err(map_websocket_error)?;
+                request.headers_mut().insert(
+                    SEC_WEBSOCKET_PROTOCOL,
+                    HeaderValue::from_static(XMPP_SUBPROTOCOL),
+                );
+
+                let (socket, response) = tokio_tungstenite::connect_async(request)
+                    .await
+                    .map_err(map_websocket_error)?;
+                let mut channel = Self { socket };
+
+                let protocol = response
+                    .headers()
+                    .get(SEC_WEBSOCKET_PROTOCOL)
+                    .and_then(|value| value.to_str().ok());
+                if !subprotocol_accepted(protocol) {
+                    let _ = channel.close().await;
+                    return Err(ConnectionError::TransportError(format!(
+                        "'{url}' did not accept the '{XMPP_SUBPROTOCOL}' WebSocket subprotocol"
+                    )));
+                }
+                Ok(channel)
+            }
+
+            pub(super) async fn send_text(&mut self, text: String) -> Result<(), ConnectionError> {
+                self.socket
+                    .send(Message::Text(text.into()))
+                    .await
+                    .map_err(map_websocket_error)
+            }
+
+            /// The next text frame, or `None` once the WebSocket has closed.
+            pub(super) async fn recv_text(&mut self) -> Result<Option<String>, ConnectionError> {
+                loop {
+                    match self.socket.next().await {
+                        Some(Ok(Message::Text(text))) => return Ok(Some(text.to_string())),
+                        Some(Ok(Message::Binary(_))) => {
+                            return Err(ConnectionError::TransportError(
+                                "RFC 7395 forbids binary WebSocket frames".to_string(),
+                            ));
+                        }
+                        Some(Ok(Message::Close(_))) | None => return Ok(None),
+                        Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => {}
+                        Some(Err(
+                            WebSocketError::ConnectionClosed | WebSocketError::AlreadyClosed,
+                        )) => return Ok(None),
+                        Some(Err(error)) => return Err(map_websocket_error(error)),
                     }
                 }
-                WebSocketCommand::Close => {
-                    let _ = socket.close();
-                    break;
+            }
+
+            pub(super) async fn close(&mut self) -> Result<(), ConnectionError> {
+                match self.socket.close(None).await {
+                    Ok(())
+                    | Err(WebSocketError::ConnectionClosed | WebSocketError::AlreadyClosed) => {
+                        Ok(())
+                    }
+                    Err(error) => Err(map_websocket_error(error)),
                 }
             }
         }
     }
 
     #[cfg(target_arch = "wasm32")]
-    impl XmppTransport for WebSocketTransport {
-        async fn connect(config: &ConnectionConfig) -> Result<Self, ConnectionError> {
-            let url = resolve_websocket_url(config).await?;
-            let socket = web_sys::WebSocket::new(url.as_str()).map_err(|error| {
-                ConnectionError::TransportError(format!(
-                    "failed to open browser websocket connection: {error:?}"
-                ))
-            })?;
+    use self::browser_channel::WebSocketChannel;
 
-            let (outbound_tx, outbound_rx) = tokio::sync::mpsc::unbounded_channel();
-            let (inbound_tx, inbound_rx) = tokio::sync::mpsc::unbounded_channel();
+    #[cfg(target_arch = "wasm32")]
+    mod browser_channel {
+        use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
 
-            wasm_bindgen_futures::spawn_local(run_browser_websocket(
-                socket,
-                outbound_rx,
-                inbound_tx,
-            ));
+        use super::{XMPP_SUBPROTOCOL, subprotocol_accepted};
+        use crate::error::ConnectionError;
 
-            Ok(Self {
-                outbound: outbound_tx,
-                inbound: inbound_rx,
-            })
+        enum Command {
+            Send(String),
+            Close,
         }
 
-        async fn send(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
-            let text = std::str::from_utf8(data).map_err(|error| {
-                ConnectionError::TransportError(format!(
-                    "RFC 7395 requires UTF-8 text frames; invalid payload: {error}"
-                ))
-            })?;
-            self.outbound
-                .send(WebSocketCommand::Send(text.to_string()))
-                .map_err(|error| ConnectionError::TransportError(error.to_string()))
+        enum SocketEvent {
+            Open { protocol: String },
+            Text(String),
+            Failed(ConnectionError),
+            Closed,
         }
 
-        async fn recv(&mut self) -> Result<Vec<u8>, ConnectionError> {
-            self.inbound.recv().await.unwrap_or_else(|| {
-                Err(ConnectionError::TransportError(
-                    "websocket receive channel closed".to_string(),
-                ))
-            })
+        /// `web_sys::WebSocket` is not `Send`, so it lives in a local task
+        /// and is driven through channels.
+        pub(super) struct WebSocketChannel {
+            commands: UnboundedSender<Command>,
+            events: UnboundedReceiver<SocketEvent>,
         }
 
-        async fn close(&mut self) -> Result<(), ConnectionError> {
-            self.outbound
-                .send(WebSocketCommand::Close)
-                .map_err(|error| ConnectionError::TransportError(error.to_string()))?;
-            Ok(())
+        async fn run_browser_websocket(
+            socket: web_sys::WebSocket,
+            mut commands: UnboundedReceiver<Command>,
+            events: UnboundedSender<SocketEvent>,
+        ) {
+            use wasm_bindgen::{JsCast, closure::Closure};
+            use web_sys::{CloseEvent, ErrorEvent, Event, MessageEvent};
+
+            let events_for_open = events.clone();
+            let socket_for_open = socket.clone();
+            let onopen = Closure::wrap(Box::new(move |_event: Event| {
+                let _ = events_for_open.send(SocketEvent::Open {
+                    protocol: socket_for_open.protocol(),
+                });
+            }) as Box<dyn FnMut(Event)>);
+            socket.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+            onopen.forget();
+
+            let events_for_message = events.clone();
+            let onmessage = Closure::wrap(Box::new(move |event: MessageEvent| {
+                let event = match event.data().as_string() {
+                    Some(text) => SocketEvent::Text(text),
+                    None => SocketEvent::Failed(ConnectionError::TransportError(
+                        "RFC 7395 forbids binary WebSocket frames".to_string(),
+                    )),
+                };
+                let _ = events_for_message.send(event);
+            }) as Box<dyn FnMut(MessageEvent)>);
+            socket.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+            onmessage.forget();
+
+            let events_for_error = events.clone();
+            let onerror = Closure::wrap(Box::new(move |event: ErrorEvent| {
+                let _ =
+                    events_for_error.send(SocketEvent::Failed(ConnectionError::TransportError(
+                        format!("websocket error: {}", event.message()),
+                    )));
+            }) as Box<dyn FnMut(ErrorEvent)>);
+            socket.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+            onerror.forget();
+
+            let events_for_close = events.clone();
+            let onclose = Closure::wrap(Box::new(move |event: CloseEvent| {
+                tracing::debug!(
+                    code = event.code(),
+                    reason = %event.reason(),
+                    "browser websocket closed"
+                );
+                let _ = events_for_close.send(SocketEvent::Closed);
+            }) as Box<dyn FnMut(CloseEvent)>);
+            socket.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+            onclose.forget();
+
+            while let Some(command) = commands.recv().await {
+                match command {
+                    Command::Send(message) => {
+                        if let Err(error) = socket.send_with_str(&message) {
+                            let _ =
+                                events.send(SocketEvent::Failed(ConnectionError::TransportError(
+                                    format!("failed to send websocket frame: {error:?}"),
+                                )));
+                        }
+                    }
+                    Command::Close => {
+                        let _ = socket.close();
+                        break;
+                    }
+                }
+            }
         }
 
-        fn supports_stream_management(&self) -> bool {
-            false
+        impl WebSocketChannel {
+            pub(super) async fn open(url: &str) -> Result<Self, ConnectionError> {
+                let socket =
+                    web_sys::WebSocket::new_with_str(url, XMPP_SUBPROTOCOL).map_err(|error| {
+                        ConnectionError::TransportError(format!(
+                            "failed to open browser websocket connection: {error:?}"
+                        ))
+                    })?;
+
+                let (commands_tx, commands_rx) = unbounded_channel();
+                let (events_tx, events_rx) = unbounded_channel();
+                wasm_bindgen_futures::spawn_local(run_browser_websocket(
+                    socket,
+                    commands_rx,
+                    events_tx,
+                ));
+
+                let mut channel = Self {
+                    commands: commands_tx,
+                    events: events_rx,
+                };
+                match channel.events.recv().await {
+                    Some(SocketEvent::Open { protocol })
+                        if subprotocol_accepted(Some(&protocol)) =>
+                    {
+                        Ok(channel)
+                    }
+                    Some(SocketEvent::Open { .. }) => {
+                        let _ = channel.close().await;
+                        Err(ConnectionError::TransportError(format!(
+                            "'{url}' did not accept the '{XMPP_SUBPROTOCOL}' WebSocket subprotocol"
+                        )))
+                    }
+                    Some(SocketEvent::Failed(error)) => Err(error),
+                    _ => Err(ConnectionError::TransportError(format!(
+                        "websocket to '{url}' closed before opening"
+                    ))),
+                }
+            }
+
+            pub(super) async fn send_text(&mut self, text: String) -> Result<(), ConnectionError> {
+                self.commands
+                    .send(Command::Send(text))
+                    .map_err(|_| ConnectionError::TransportError("websocket is closed".to_string()))
+            }
+
+            /// The next text frame, or `None` once the WebSocket has closed.
+            pub(super) async fn recv_text(&mut self) -> Result<Option<String>, ConnectionError> {
+                loop {
+                    match self.events.recv().await {
+                        Some(SocketEvent::Text(text)) => return Ok(Some(text)),
+                        Some(SocketEvent::Failed(error)) => return Err(error),
+                        Some(SocketEvent::Closed) | None => return Ok(None),
+                        Some(SocketEvent::Open { .. }) => {}
+                    }
+                }
+            }
+
+            pub(super) async fn close(&mut self) -> Result<(), ConnectionError> {
+                // The socket task may already have stopped after a close.
+                let _ = self.commands.send(Command::Close);
+                Ok(())
+            }
         }
     }
 
@@ -827,15 +1497,82 @@ mod web {
             assert_eq!(url, "wss://xmpp.example.com/ws");
         }
 
+        fn config(server: Option<&str>, transports: Vec<TransportKind>) -> ConnectionConfig {
+            ConnectionConfig {
+                jid: "alice@example.com".to_string(),
+                server: server.map(str::to_string),
+                port: Some(5222),
+                timeout_seconds: 30,
+                max_reconnect_attempts: 0,
+                transports,
+                websocket_url: None,
+                bosh_url: None,
+            }
+        }
+
+        #[tokio::test]
+        async fn websocket_url_ignores_tcp_server_when_other_transports_are_enabled() {
+            let fallback = config(Some("xmpp.example.com"), vec![]);
+            assert_eq!(
+                resolve_websocket_url(&fallback).await.unwrap(),
+                "wss://example.com:443/xmpp-websocket"
+            );
+
+            let websocket_only = config(Some("ws.example.com"), vec![TransportKind::WebSocket]);
+            assert_eq!(
+                resolve_websocket_url(&websocket_only).await.unwrap(),
+                "wss://ws.example.com:5222/xmpp-websocket"
+            );
+
+            let explicit = ConnectionConfig {
+                websocket_url: Some("wss://chat.example.com/ws".to_string()),
+                ..fallback
+            };
+            assert_eq!(
+                resolve_websocket_url(&explicit).await.unwrap(),
+                "wss://chat.example.com/ws"
+            );
+        }
+
         #[test]
-        fn xep0156_host_meta_parser_finds_websocket_link() {
-            let host_meta = r#"<?xml version='1.0'?>
-<XRD xmlns='http://docs.oasis-open.org/ns/xri/xrd-1.0'>
-    <Link rel='urn:xmpp:alt-connections:websocket' href='wss://xmpp.example.com/ws'/>
-</XRD>"#;
+        fn open_and_close_frames_use_the_framing_namespace() {
+            let open = open_frame("example.com");
+            assert!(matches!(parse_frame(&open), Ok(Frame::Open)));
+            assert!(open.contains("to='example.com'"));
+            assert!(open.contains("version='1.0'"));
+
+            assert!(matches!(
+                parse_frame(&close_frame()),
+                Ok(Frame::Close {
+                    see_other_uri: None
+                })
+            ));
+        }
+
+        #[test]
+        fn parses_server_close_with_redirect_and_stanzas() {
+            let close = r#"<close xmlns="urn:ietf:params:xml:ns:xmpp-framing" see-other-uri="wss://other.example.com/ws"/>"#;
+            match parse_frame(close).unwrap() {
+                Frame::Close { see_other_uri } => {
+                    assert_eq!(see_other_uri.as_deref(), Some("wss://other.example.com/ws"))
+                }
+                other => panic!("expected close frame, got {other:?}"),
+            }
 
-            let discovered = parse_host_meta_websocket_endpoint(host_meta);
-            assert_eq!(discovered, Some("wss://xmpp.example.com/ws".to_string()));
+            let message = r#"<message xmlns="jabber:client" to="alice@example.com"><body>hi</body></message>"#;
+            assert!(matches!(
+                parse_frame(message),
+                Ok(Frame::Element(element)) if element.name() == "message"
+            ));
+            assert!(parse_frame("<open").is_err());
+        }
+
+        #[test]
+        fn subprotocol_must_be_xmpp() {
+            assert!(subprotocol_accepted(Some("xmpp")));
+            assert!(subprotocol_accepted(Some("XMPP")));
+            assert!(!subprotocol_accepted(Some("")));
+            assert!(!subprotocol_accepted(None));
         }
     }
 }
@@ -843,5 +1580,39 @@ mod web {
 #[cfg(feature = "native")]
 pub use native::NativeTcpTransport;
 
-#[cfg(feature = "web")]
-pub use web::WebSocketTransport;
+pub use bosh::BoshTransport;
+pub use websocket::WebSocketTransport;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(transports: Vec<TransportKind>) -> ConnectionConfig {
+        ConnectionConfig {
+            jid: "alice@example.com".to_string(),
+            server: None,
+            port: None,
+            timeout_seconds: 30,
+            max_reconnect_attempts: 0,
+            transports,
+            websocket_url: None,
+            bosh_url: None,
+        }
+    }
+
+    #[test]
+    fn empty_transport_list_uses_default_order() {
+        assert_eq!(config(vec![]).transport_order(), DEFAULT_TRANSPORT_ORDER);
+    }
+
+    #[test]
+    fn transport_order_keeps_first_occurrence() {
+        let order = config(vec![
+            TransportKind::WebSocket,
+            TransportKind::Tcp,
+            TransportKind::WebSocket,
+        ])
+        .transport_order();
+        assert_eq!(order, vec![TransportKind::WebSocket, TransportKind::Tcp]);
+    }
+}
@@ -0,0 +1,634 @@
+//! XMPP over BOSH (XEP-0124 with the XEP-0206 bindings).
+//!
+//! Each HTTP request carries a `<body/>` wrapper with the next request id
+//! (`rid`) and the session id (`sid`) the server assigned. The server holds
+//! one request open until it has stanzas to deliver or `wait` expires, so a
+//! background task keeps a long-poll parked at all times and uses the
+//! remaining request slots for outgoing stanzas.
+
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::time::Duration;
+
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use tokio::sync::{mpsc, oneshot};
+use tracing::debug;
+use xmpp_parsers::{jid::Jid, minidom::Element, ns, stream_features::StreamFeatures};
+
+use super::{
+    ConnectionConfig, TransportKind, XmppTransport, discovery, io_timeout, parse_config_jid,
+    with_timeout,
+};
+use crate::error::ConnectionError;
+use crate::sasl::{SaslNegotiation, SaslStep, bind_request, bind_result};
+
+const HTTPBIND_NS: &str = "http://jabber.org/protocol/httpbind";
+const XBOSH_NS: &str = "urn:xmpp:xbosh";
+const BOSH_VERSION: &str = "1.11";
+/// Longest time, in seconds, the server may hold a request open.
+const WAIT_SECONDS: u64 = 60;
+/// Requests the server may keep waiting at once; one is enough for pushes.
+const HOLD: usize = 1;
+const DEFAULT_HTTP_BIND_PATH: &str = "http-bind";
+
+fn server_to_bosh_url(server: &str) -> Result<String, ConnectionError> {
+    if server.starts_with("http://") || server.starts_with("https://") {
+        return Ok(server.to_string());
+    }
+
+    if server.contains("://") {
+        return Err(ConnectionError::TransportError(format!(
+            "unsupported BOSH scheme for server '{server}'"
+        )));
+    }
+
+    let host_or_path = server.trim_matches('/');
+    if host_or_path.is_empty() {
+        return Err(ConnectionError::TransportError(
+            "server value cannot be empty".to_string(),
+        ));
+    }
+
+    if host_or_path.contains('/') {
+        Ok(format!("https://{host_or_path}"))
+    } else {
+        Ok(format!("https://{host_or_path}/{DEFAULT_HTTP_BIND_PATH}"))
+    }
+}
+
+async fn resolve_bosh_url(config: &ConnectionConfig) -> Result<String, ConnectionError> {
+    if let Some(url) = config.bosh_url.as_deref() {
+        return server_to_bosh_url(url);
+    }
+
+    // As with WebSocket, `server` names the TCP endpoint unless it is
+    // already an HTTP URL or BOSH is the only transport.
+    if let Some(server) = config.server.as_deref()
+        && (server.starts_with("http://")
+            || server.starts_with("https://")
+            || config.transport_order() == [TransportKind::Bosh])
+    {
+        return server_to_bosh_url(server);
+    }
+
+    let domain = parse_config_jid(&config.jid)?.domain().to_string();
+    if let Some(discovered_url) =
+        discovery::discover_xep0156_endpoint(&domain, discovery::BOSH_REL).await?
+    {
+        return Ok(discovered_url);
+    }
+
+    Ok(format!("https://{domain}/{DEFAULT_HTTP_BIND_PATH}"))
+}
+
+/// A random starting `rid`, small enough that it never nears 2^53 over the
+/// lifetime of the session as XEP-0124 §14.1 requires.
+fn initial_rid() -> u64 {
+    u64::from(uuid::Uuid::new_v4().as_fields().0) + 1
+}
+
+/// `domain` comes from a parsed JID, so it needs no attribute escaping.
+fn session_request(rid: u64, domain: &str) -> String {
+    format!(
+        "<body content='text/xml; charset=utf-8' hold='{HOLD}' rid='{rid}' to='{domain}' \
+         ver='{BOSH_VERSION}' wait='{WAIT_SECONDS}' xml:lang='en' xmpp:version='1.0' \
+         xmlns='{HTTPBIND_NS}' xmlns:xmpp='{XBOSH_NS}'/>"
+    )
+}
+
+fn payload_body(rid: u64, sid: &str, payload: &str) -> String {
+    let sid = escape_attribute(sid);
+    if payload.is_empty() {
+        format!("<body rid='{rid}' sid='{sid}' xmlns='{HTTPBIND_NS}'/>")
+    } else {
+        format!("<body rid='{rid}' sid='{sid}' xmlns='{HTTPBIND_NS}'>{payload}</body>")
+    }
+}
+
+/// Asks the connection manager to restart the stream after SASL (XEP-0206 §5).
+fn restart_body(rid: u64, sid: &str, domain: &str) -> String {
+    let sid = escape_attribute(sid);
+    format!(
+        "<body rid='{rid}' sid='{sid}' to='{domain}' xml:lang='en' xmpp:restart='true' \
+         xmlns='{HTTPBIND_NS}' xmlns:xmpp='{XBOSH_NS}'/>"
+    )
+}
+
+fn terminate_body(rid: u64, sid: &str, payload: &str) -> String {
+    let sid = escape_attribute(sid);
+    format!(
+        "<body rid='{rid}' sid='{sid}' type='terminate' xmlns='{HTTPBIND_NS}'>{payload}\
+         <presence type='unavailable' xmlns='jabber:client'/></body>"
+    )
+}
+
+/// The `sid` is chosen by the server, so it is escaped before being echoed.
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('\'', "&apos;")
+        .replace('<', "&lt;")
+}
+
+/// Parse a response `<body/>`, turning a server-side termination into an
+/// error.
+fn parse_body(text: &str) -> Result<Element, ConnectionError> {
+    let body = Element::from_str(text.trim()).map_err(|error| {
+        ConnectionError::StreamError(format!("invalid BOSH response body: {error}"))
+    })?;
+    if !body.is("body", HTTPBIND_NS) {
+        return Err(ConnectionError::StreamError(format!(
+            "expected BOSH <body/>, got <{}/>",
+            body.name()
+        )));
+    }
+
+    if body.attr("type") == Some("terminate") {
+        let condition = body.attr("condition").unwrap_or("unspecified");
+        if let Some(error) = body.children().find(|child| child.is("error", ns::STREAM)) {
+            return Err(ConnectionError::StreamError(format!(
+                "server sent stream error: {}",
+                String::from(error)
+            )));
+        }
+        return Err(ConnectionError::TransportError(format!(
+            "BOSH session terminated by server: {condition}"
+        )));
+    }
+    Ok(body)
+}
+
+/// Attributes of the session creation response that shape later requests.
+#[derive(Debug, PartialEq, Eq)]
+struct SessionParams {
+    sid: String,
+    requests: usize,
+    wait: u64,
+}
+
+fn session_params(body: &Element) -> Result<SessionParams, ConnectionError> {
+    let sid = body.attr("sid").ok_or_else(|| {
+        ConnectionError::StreamError("BOSH session response has no sid".to_string())
+    })?;
+    // XEP-0124 §7.1: without a `requests` attribute, allow one more request
+    // than the hold.
+    let requests = body
+        .attr("requests")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(HOLD + 1)
+        .max(1);
+    let wait = body
+        .attr("wait")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(WAIT_SECONDS)
+        .min(WAIT_SECONDS);
+    Ok(SessionParams {
+        sid: sid.to_string(),
+        requests,
+        wait,
+    })
+}
+
+fn map_http_error(error: reqwest::Error) -> ConnectionError {
+    if error.is_timeout() {
+        ConnectionError::Timeout
+    } else {
+        ConnectionError::TransportError(format!("BOSH request failed: {error}"))
+    }
+}
+
+/// POST one `<body/>`; owned arguments keep the future `'static` so it can
+/// sit in the driver's in-flight set.
+async fn post(
+    client: reqwest::Client,
+    url: String,
+    body: String,
+    timeout: Duration,
+) -> Result<Element, ConnectionError> {
+    let request = client
+        .post(&url)
+        .header(reqwest::header::CONTENT_TYPE, "text/xml; charset=utf-8")
+        .body(body);
+    #[cfg(not(target_arch = "wasm32"))]
+    let request = request.timeout(timeout);
+    #[cfg(target_arch = "wasm32")]
+    let _ = timeout;
+
+    let response = request.send().await.map_err(map_http_error)?;
+    let status = response.status();
+    if !status.is_success() {
+        // XEP-0124 §17: 400, 403 and 404 all end the session.
+        return Err(ConnectionError::TransportError(format!(
+            "BOSH request to '{url}' failed with HTTP {status}"
+        )));
+    }
+    let text = response.text().await.map_err(map_http_error)?;
+    parse_body(&text)
+}
+
+struct Session {
+    client: reqwest::Client,
+    url: String,
+    domain: String,
+    sid: String,
+    rid: u64,
+    requests: usize,
+    wait: Duration,
+    io_timeout: Duration,
+    pending: VecDeque<Element>,
+}
+
+impl Session {
+    async fn create(
+        url: String,
+        domain: String,
+        io_timeout: Duration,
+    ) -> Result<Self, ConnectionError> {
+        let client = reqwest::Client::new();
+        let rid = initial_rid();
+        let body = post(
+            client.clone(),
+            url.clone(),
+            session_request(rid, &domain),
+            io_timeout,
+        )
+        .await?;
+        let params = session_params(&body)?;
+
+        Ok(Self {
+            client,
+            url,
+            domain,
+            sid: params.sid,
+            rid: rid + 1,
+            requests: params.requests,
+            wait: Duration::from_secs(params.wait),
+            io_timeout,
+            pending: body.children().cloned().collect(),
+        })
+    }
+
+    fn next_rid(&mut self) -> u64 {
+        let rid = self.rid;
+        self.rid += 1;
+        rid
+    }
+
+    /// Long-polls may legitimately take the whole `wait`.
+    fn request_timeout(&self) -> Duration {
+        self.wait + self.io_timeout
+    }
+
+    fn post(&self, body: String) -> impl Future<Output = Result<Element, ConnectionError>> + use<> {
+        post(
+            self.client.clone(),
+            self.url.clone(),
+            body,
+            self.request_timeout(),
+        )
+    }
+
+    async fn exchange(&mut self, body: String) -> Result<(), ConnectionError> {
+        let response = self.post(body).await?;
+        self.pending.extend(response.children().cloned());
+        Ok(())
+    }
+
+    async fn send_element(&mut self, element: Element) -> Result<(), ConnectionError> {
+        let rid = self.next_rid();
+        let body = payload_body(rid, &self.sid, &String::from(&element));
+        self.exchange(body).await
+    }
+
+    async fn next_element(&mut self) -> Result<Element, ConnectionError> {
+        loop {
+            if let Some(element) = self.pending.pop_front() {
+                return Ok(element);
+            }
+            let rid = self.next_rid();
+            let body = payload_body(rid, &self.sid, "");
+            self.exchange(body).await?;
+        }
+    }
+
+    async fn next_features(&mut self) -> Result<StreamFeatures, ConnectionError> {
+        loop {
+            let element = self.next_element().await?;
+            if element.is("features", ns::STREAM) {
+                return StreamFeatures::try_from(element).map_err(|error| {
+                    ConnectionError::StreamError(format!("invalid stream features: {error}"))
+                });
+            }
+        }
+    }
+
+    async fn restart(&mut self) -> Result<StreamFeatures, ConnectionError> {
+        let rid = self.next_rid();
+        let body = restart_body(rid, &self.sid, &self.domain);
+        self.exchange(body).await?;
+        self.next_features().await
+    }
+
+    async fn authenticate(
+        &mut self,
+        features: &StreamFeatures,
+        username: &str,
+        password: &str,
+    ) -> Result<(), ConnectionError> {
+        let (mut sasl, auth) = SaslNegotiation::start(features, username, password)?;
+        self.send_element(auth).await?;
+
+        loop {
+            let element = self.next_element().await?;
+            match sasl.handle(element)? {
+                Some(SaslStep::Respond(response)) => self.send_element(response).await?,
+                Some(SaslStep::Succeeded) => return Ok(()),
+                None => {}
+            }
+        }
+    }
+
+    async fn bind(&mut self, resource: Option<String>) -> Result<(), ConnectionError> {
+        self.send_element(bind_request(resource)).await?;
+
+        loop {
+            let element = self.next_element().await?;
+            if let Some(result) = bind_result(element) {
+                return result;
+            }
+        }
+    }
+
+    /// Authenticate and bind; returns whether stream management is offered.
+    async fn negotiate(&mut self, jid: &Jid, password: &str) -> Result<bool, ConnectionError> {
+        let username = jid.node().ok_or_else(|| {
+            ConnectionError::AuthenticationFailed(format!(
+                "JID '{jid}' has no local part for SASL authentication"
+            ))
+        })?;
+
+        let features = self.next_features().await?;
+        self.authenticate(&features, username.as_str(), password)
+            .await?;
+
+        let features = self.restart().await?;
+        if features.can_bind() {
+            self.bind(jid.resource().map(|resource| resource.to_string()))
+                .await?;
+        }
+        Ok(features.stream_management.is_some())
+    }
+
+    /// End the session, flushing any stanzas not yet sent.
+    async fn terminate(&mut self, payload: &str) {
+        let rid = self.next_rid();
+        let body = terminate_body(rid, &self.sid, payload);
+        let request = post(self.client.clone(), self.url.clone(), body, self.io_timeout);
+        if let Err(error) = request.await {
+            debug!(%error, "BOSH session terminate request failed");
+        }
+    }
+}
+
+enum Command {
+    Send(String),
+    Close(oneshot::Sender<()>),
+}
+
+type Inbound = Result<Vec<u8>, ConnectionError>;
+
+/// Owns the session once it is established: keeps one request parked at the
+/// server for it to answer with stanzas, and uses the other slots to send
+/// buffered stanzas as soon as they are queued.
+async fn drive(
+    mut session: Session,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+    inbound: mpsc::UnboundedSender<Inbound>,
+) {
+    for element in session.pending.drain(..) {
+        let _ = inbound.send(Ok(String::from(&element).into_bytes()));
+    }
+
+    let mut outbox = String::new();
+    let mut in_flight = FuturesUnordered::new();
+    loop {
+        if in_flight.is_empty() || (!outbox.is_empty() && in_flight.len() < session.requests) {
+            let rid = session.next_rid();
+            let body = payload_body(rid, &session.sid, &std::mem::take(&mut outbox));
+            in_flight.push(session.post(body));
+        }
+
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(Command::Send(payload)) => outbox.push_str(&payload),
+                Some(Command::Close(done)) => {
+                    session.terminate(&outbox).await;
+                    let _ = done.send(());
+                    return;
+                }
+                None => {
+                    session.terminate(&outbox).await;
+                    return;
+                }
+            },
+            Some(response) = in_flight.next() => match response {
+                Ok(body) => {
+                    for element in body.children() {
+                        if inbound.send(Ok(String::from(element).into_bytes())).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(error) => {
+                    let _ = inbound.send(Err(error));
+                    return;
+                }
+            },
+        }
+    }
+}
+
+/// XMPP over BOSH (XEP-0206): used when neither TCP nor WebSocket can reach
+/// the server, typically behind HTTP-only proxies.
+pub struct BoshTransport {
+    commands: mpsc::UnboundedSender<Command>,
+    inbound: mpsc::UnboundedReceiver<Inbound>,
+    io_timeout: Duration,
+    stream_management_supported: bool,
+    closed: bool,
+}
+
+impl XmppTransport for BoshTransport {
+    async fn connect(config: &ConnectionConfig, password: &str) -> Result<Self, ConnectionError> {
+        let jid = parse_config_jid(&config.jid)?;
+        let url = resolve_bosh_url(config).await?;
+        let io_timeout = io_timeout(config);
+
+        let mut session =
+            Session::create(url.clone(), jid.domain().to_string(), io_timeout).await?;
+        let stream_management_supported =
+            match with_timeout(io_timeout, session.negotiate(&jid, password)).await {
+                Ok(supported) => supported,
+                Err(error) => {
+                    session.terminate("").await;
+                    return Err(error);
+                }
+            };
+        debug!(%url, sid = %session.sid, "BOSH session established");
+
+        let (commands_tx, commands_rx) = mpsc::unbounded_channel();
+        let (inbound_tx, inbound_rx) = mpsc::unbounded_channel();
+        #[cfg(not(target_arch = "wasm32"))]
+        tokio::spawn(drive(session, commands_rx, inbound_tx));
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(drive(session, commands_rx, inbound_tx));
+
+        Ok(Self {
+            commands: commands_tx,
+            inbound: inbound_rx,
+            io_timeout,
+            stream_management_supported,
+            closed: false,
+        })
+    }
+
+    async fn send(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let payload = std::str::from_utf8(data).map_err(|error| {
+            ConnectionError::TransportError(format!("BOSH payload is not UTF-8: {error}"))
+        })?;
+        self.commands
+            .send(Command::Send(payload.to_string()))
+            .map_err(|_| ConnectionError::TransportError("BOSH session ended".to_string()))
+    }
+
+    async fn recv(&mut self) -> Result<Vec<u8>, ConnectionError> {
+        self.inbound.recv().await.unwrap_or_else(|| {
+            Err(ConnectionError::TransportError(
+                "BOSH session ended".to_string(),
+            ))
+        })
+    }
+
+    async fn close(&mut self) -> Result<(), ConnectionError> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.commands.send(Command::Close(done_tx)).is_err() {
+            return Ok(());
+        }
+        with_timeout(self.io_timeout, async {
+            done_rx.await.map_err(|_| {
+                ConnectionError::TransportError("BOSH session ended before closing".to_string())
+            })
+        })
+        .await
+    }
+
+    fn supports_stream_management(&self) -> bool {
+        self.stream_management_supported
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(server: Option<&str>, transports: Vec<TransportKind>) -> ConnectionConfig {
+        ConnectionConfig {
+            jid: "alice@example.com".to_string(),
+            server: server.map(str::to_string),
+            port: Some(5222),
+            timeout_seconds: 30,
+            max_reconnect_attempts: 0,
+            transports,
+            websocket_url: None,
+            bosh_url: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn bosh_url_prefers_explicit_endpoint_then_default_path() {
+        let fallback = config(Some("xmpp.example.com"), vec![]);
+        assert_eq!(
+            resolve_bosh_url(&fallback).await.unwrap(),
+            "https://example.com/http-bind"
+        );
+
+        let bosh_only = config(Some("bosh.example.com"), vec![TransportKind::Bosh]);
+        assert_eq!(
+            resolve_bosh_url(&bosh_only).await.unwrap(),
+            "https://bosh.example.com/http-bind"
+        );
+
+        let explicit = ConnectionConfig {
+            bosh_url: Some("https://chat.example.com/bosh".to_string()),
+            ..fallback
+        };
+        assert_eq!(
+            resolve_bosh_url(&explicit).await.unwrap(),
+            "https://chat.example.com/bosh"
+        );
+    }
+
+    #[test]
+    fn session_request_declares_xbosh_version() {
+        let body = Element::from_str(&session_request(42, "example.com")).unwrap();
+        assert!(body.is("body", HTTPBIND_NS));
+        assert_eq!(body.attr("rid"), Some("42"));
+        assert_eq!(body.attr("to"), Some("example.com"));
+        assert_eq!(body.attr("hold"), Some("1"));
+        assert_eq!(body.attr("ver"), Some(BOSH_VERSION));
+    }
+
+    #[test]
+    fn payload_body_wraps_stanzas_and_escapes_sid() {
+        let body = Element::from_str(&payload_body(7, "a'b", "<presence xmlns='jabber:client'/>"))
+            .unwrap();
+        assert_eq!(body.attr("sid"), Some("a'b"));
+        assert_eq!(body.attr("rid"), Some("7"));
+        assert!(body.has_child("presence", "jabber:client"));
+
+        let poll = Element::from_str(&payload_body(8, "sid", "")).unwrap();
+        assert_eq!(poll.children().count(), 0);
+    }
+
+    #[test]
+    fn session_params_fall_back_to_hold_plus_one_requests() {
+        let body = parse_body(&format!(
+            "<body sid='s1' wait='300' xmlns='{HTTPBIND_NS}'>\
+             <features xmlns='{}'/></body>",
+            ns::STREAM
+        ))
+        .unwrap();
+        assert_eq!(
+            session_params(&body).unwrap(),
+            SessionParams {
+                sid: "s1".to_string(),
+                requests: 2,
+                wait: WAIT_SECONDS,
+            }
+        );
+        assert_eq!(body.children().count(), 1);
+    }
+
+    #[test]
+    fn terminate_response_is_an_error() {
+        let error = parse_body(&format!(
+            "<body type='terminate' condition='item-not-found' xmlns='{HTTPBIND_NS}'/>"
+        ))
+        .unwrap_err();
+        assert!(
+            matches!(error, ConnectionError::TransportError(message) if message.contains("item-not-found"))
+        );
+
+        assert!(parse_body("<presence xmlns='jabber:client'/>").is_err());
+    }
+}
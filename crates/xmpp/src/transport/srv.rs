@@ -0,0 +1,264 @@
+//! DNS SRV resolution of client connection targets (RFC 6120 §3.2).
+//!
+//! `_xmpp-client._tcp` records point at STARTTLS endpoints and
+//! `_xmpps-client._tcp` at direct TLS ones (XEP-0368). Targets are tried in
+//! priority order, weighted at random within a priority as RFC 2782
+//! describes, and the last target that worked for a domain is tried first
+//! on the next connect.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{LazyLock, Mutex};
+
+use hickory_resolver::TokioAsyncResolver;
+use tracing::debug;
+
+use crate::error::ConnectionError;
+
+pub(super) const XMPP_CLIENT_SERVICE: &str = "_xmpp-client._tcp";
+pub(super) const XMPPS_CLIENT_SERVICE: &str = "_xmpps-client._tcp";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Target {
+    pub(super) host: String,
+    pub(super) port: u16,
+}
+
+#[derive(Debug, Clone)]
+struct SrvRecord {
+    priority: u16,
+    weight: u16,
+    target: Target,
+}
+
+/// Last target that completed a connection, keyed by domain and service.
+static LAST_GOOD: LazyLock<Mutex<HashMap<(String, &'static str), Target>>> =
+    LazyLock::new(Default::default);
+
+fn last_good(domain: &str, service: &'static str) -> Option<Target> {
+    LAST_GOOD
+        .lock()
+        .ok()?
+        .get(&(domain.to_string(), service))
+        .cloned()
+}
+
+fn remember(domain: &str, service: &'static str, target: Target) {
+    if let Ok(mut cache) = LAST_GOOD.lock() {
+        cache.insert((domain.to_string(), service), target);
+    }
+}
+
+/// A uniformly random value in `0..=max`.
+fn random_up_to(max: u32) -> u32 {
+    let random = uuid::Uuid::new_v4().as_fields().0;
+    match max.checked_add(1) {
+        Some(bound) => random % bound,
+        None => random,
+    }
+}
+
+/// Sort by ascending priority, then order each priority's records with the
+/// RFC 2782 weighted selection, drawing from `random(total_weight)`.
+fn order_records(mut records: Vec<SrvRecord>, mut random: impl FnMut(u32) -> u32) -> Vec<Target> {
+    // Zero-weight records go first so they are only picked when the draw is 0.
+    records.sort_by_key(|record| (record.priority, record.weight != 0));
+
+    let mut ordered = Vec::with_capacity(records.len());
+    let mut remaining = records.as_slice();
+    while let Some(first) = remaining.first() {
+        let same_priority = remaining
+            .iter()
+            .take_while(|record| record.priority == first.priority)
+            .count();
+        let (group, rest) = remaining.split_at(same_priority);
+        remaining = rest;
+
+        let mut group = group.to_vec();
+        while !group.is_empty() {
+            let total: u32 = group.iter().map(|record| u32::from(record.weight)).sum();
+            let draw = random(total);
+            let mut running = 0;
+            let index = group
+                .iter()
+                .position(|record| {
+                    running += u32::from(record.weight);
+                    running >= draw
+                })
+                .unwrap_or(group.len() - 1);
+            ordered.push(group.remove(index).target);
+        }
+    }
+    ordered
+}
+
+/// `Ok(None)` when the lookup finds nothing or fails, in which case RFC 6120
+/// §3.2.2 has the client fall back to the domain itself.
+async fn lookup(domain: &str, service: &str) -> Result<Option<Vec<SrvRecord>>, ConnectionError> {
+    let resolver = TokioAsyncResolver::tokio_from_system_conf().map_err(|error| {
+        ConnectionError::DnsResolutionFailed(format!("failed to load resolver config: {error}"))
+    })?;
+
+    let name = format!("{service}.{domain}.");
+    match resolver.srv_lookup(name.as_str()).await {
+        Ok(lookup) => Ok(Some(
+            lookup
+                .iter()
+                .map(|srv| SrvRecord {
+                    priority: srv.priority(),
+                    weight: srv.weight(),
+                    target: Target {
+                        host: srv.target().to_ascii().trim_end_matches('.').to_string(),
+                        port: srv.port(),
+                    },
+                })
+                .collect(),
+        )),
+        Err(error) => {
+            debug!(%name, %error, "no SRV records; falling back to the domain");
+            Ok(None)
+        }
+    }
+}
+
+/// Connection targets for `service` at `domain`, most preferred first.
+pub(super) async fn resolve_targets(
+    domain: &str,
+    service: &'static str,
+    fallback_port: u16,
+) -> Result<Vec<Target>, ConnectionError> {
+    let fallback = Target {
+        host: domain.to_string(),
+        port: fallback_port,
+    };
+    if domain.parse::<IpAddr>().is_ok() {
+        return Ok(vec![fallback]);
+    }
+
+    let mut targets = match lookup(domain, service).await? {
+        None => vec![fallback],
+        // A single "." target means the service is decidedly not offered.
+        Some(records) if records.len() == 1 && records[0].target.host.is_empty() => {
+            return Err(ConnectionError::DnsResolutionFailed(format!(
+                "{domain} does not offer {service}"
+            )));
+        }
+        Some(records) => order_records(records, random_up_to),
+    };
+
+    if let Some(cached) = last_good(domain, service)
+        && let Some(index) = targets.iter().position(|target| *target == cached)
+    {
+        let cached = targets.remove(index);
+        targets.insert(0, cached);
+    }
+    Ok(targets)
+}
+
+/// Try each target of `service` in turn until `attempt` succeeds. Errors that
+/// retrying cannot fix, such as rejected credentials, end the search early.
+pub(super) async fn connect_any<T>(
+    domain: &str,
+    service: &'static str,
+    fallback_port: u16,
+    mut attempt: impl AsyncFnMut(&Target) -> Result<T, ConnectionError>,
+) -> Result<T, ConnectionError> {
+    let targets = resolve_targets(domain, service, fallback_port).await?;
+
+    let mut last_error = None;
+    for target in targets {
+        debug!(host = %target.host, port = target.port, service, "connecting to target");
+        match attempt(&target).await {
+            Ok(connected) => {
+                remember(domain, service, target);
+                return Ok(connected);
+            }
+            Err(error) if !error.is_retryable() => return Err(error),
+            Err(error) => {
+                debug!(host = %target.host, port = target.port, %error, "target failed");
+                last_error = Some(error);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        ConnectionError::DnsResolutionFailed(format!("no {service} targets for {domain}"))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(priority: u16, weight: u16, host: &str) -> SrvRecord {
+        SrvRecord {
+            priority,
+            weight,
+            target: Target {
+                host: host.to_string(),
+                port: 5222,
+            },
+        }
+    }
+
+    fn hosts(targets: &[Target]) -> Vec<&str> {
+        targets.iter().map(|target| target.host.as_str()).collect()
+    }
+
+    #[test]
+    fn lower_priority_values_come_first() {
+        let records = vec![
+            record(20, 0, "backup"),
+            record(10, 0, "primary"),
+            record(30, 0, "last"),
+        ];
+        let ordered = order_records(records, |_| 0);
+        assert_eq!(hosts(&ordered), vec!["primary", "backup", "last"]);
+    }
+
+    #[test]
+    fn weight_decides_order_within_a_priority() {
+        let records = vec![
+            record(10, 10, "light"),
+            record(10, 90, "heavy"),
+            record(10, 0, "zero"),
+        ];
+
+        // A draw of 0 picks the zero-weight record, which is sorted first.
+        let ordered = order_records(records.clone(), |_| 0);
+        assert_eq!(ordered[0].host, "zero");
+
+        // Running sums are zero=0, light=10, heavy=100.
+        let ordered = order_records(records, |total| total);
+        assert_eq!(hosts(&ordered), vec!["heavy", "light", "zero"]);
+    }
+
+    #[tokio::test]
+    async fn ip_literal_is_its_own_target() {
+        let targets = resolve_targets("127.0.0.1", XMPP_CLIENT_SERVICE, 5222)
+            .await
+            .unwrap();
+        assert_eq!(
+            targets,
+            vec![Target {
+                host: "127.0.0.1".to_string(),
+                port: 5222,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn credentials_rejection_stops_trying_targets() {
+        let mut attempts = 0;
+        let result: Result<(), _> =
+            connect_any("127.0.0.1", XMPP_CLIENT_SERVICE, 5222, async |_| {
+                attempts += 1;
+                Err(ConnectionError::AuthenticationFailed("bad".to_string()))
+            })
+            .await;
+        assert!(matches!(
+            result,
+            Err(ConnectionError::AuthenticationFailed(_))
+        ));
+        assert_eq!(attempts, 1);
+    }
+}
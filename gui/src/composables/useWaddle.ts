@@ -54,6 +54,7 @@ export type PluginAction =
   | { action: 'install'; reference: string }
   | { action: 'uninstall'; pluginId: string }
   | { action: 'update'; pluginId: string }
+  | { action: 'reload'; pluginId: string }
   | { action: 'get'; pluginId: string };
 
 export type UnlistenFn = () => void;